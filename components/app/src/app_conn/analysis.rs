@@ -0,0 +1,74 @@
+use proto::crypto::{InvoiceId, PublicKey, Uid};
+
+use proto::app_server::messages::{
+    AppRequest, ReportSubscription, RequestBalanceHistory, RequestReceipts,
+};
+use proto::funder::messages::{Currency, ExportChannelProof};
+
+/// Request the worst case credit exposure to friends, with respect to in-flight requests.
+/// The response is sent back as `AppServerToApp::ResponseExposure`, with a matching `request_id`.
+pub fn request_exposure(request_id: Uid) -> AppRequest {
+    AppRequest::RequestExposure(request_id)
+}
+
+/// Request the complete current state of one friend, without subscribing to reports.
+/// The response is sent back as `AppServerToApp::ResponseFriendDetail`, with a matching
+/// `friend_public_key`.
+pub fn request_friend_detail(friend_public_key: PublicKey) -> AppRequest {
+    AppRequest::RequestFriendDetail(friend_public_key)
+}
+
+/// Choose the report mutations sent to this app connection.
+/// For example, an app may ask only for changes to the balances of a few friends.
+pub fn set_report_subscription(report_subscription: ReportSubscription) -> AppRequest {
+    AppRequest::SetReportSubscription(report_subscription)
+}
+
+/// Export the latest signed state of the channel with a friend, as a portable blob.
+/// The response is sent back as `AppServerToApp::ResponseChannelProof`, with a matching
+/// `request_id`. The blob can be verified offline using `verify::verify_channel_proof_blob`.
+pub fn export_channel_proof(request_id: Uid, friend_public_key: PublicKey) -> AppRequest {
+    AppRequest::ExportChannelProof(ExportChannelProof {
+        request_id,
+        friend_public_key,
+    })
+}
+
+/// Request the balance snapshots taken with a friend in `currency`, between `from_tick` and
+/// `to_tick` (inclusive). The response is sent back as `AppServerToApp::ResponseBalanceHistory`,
+/// with a matching `request_id`.
+pub fn request_balance_history(
+    request_id: Uid,
+    friend_public_key: PublicKey,
+    currency: Currency,
+    from_tick: u64,
+    to_tick: u64,
+) -> AppRequest {
+    AppRequest::RequestBalanceHistory(RequestBalanceHistory {
+        request_id,
+        friend_public_key,
+        currency,
+        from_tick,
+        to_tick,
+    })
+}
+
+/// Request the receipts and the issued invoices archived between `from_tick` and `to_tick`
+/// (inclusive). Only receipts of payments to `opt_counterparty` are returned if it is given, and
+/// only entries for `opt_invoice_id` if it is given. The response is sent back as one or more
+/// `AppServerToApp::ResponseReceipts`, with a matching `request_id`.
+pub fn request_receipts(
+    request_id: Uid,
+    from_tick: u64,
+    to_tick: u64,
+    opt_counterparty: Option<PublicKey>,
+    opt_invoice_id: Option<InvoiceId>,
+) -> AppRequest {
+    AppRequest::RequestReceipts(RequestReceipts {
+        request_id,
+        from_tick,
+        to_tick,
+        opt_counterparty,
+        opt_invoice_id,
+    })
+}
@@ -2,7 +2,8 @@ use proto::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
 
 use proto::app_server::messages::AppRequest;
 use proto::funder::messages::{
-    AckClosePayment, CreatePayment, CreateTransaction, Currency, FriendsRoute,
+    AckClosePayment, CreateExchangeTransaction, CreatePayment, CreateTransaction, Currency,
+    FriendsRoute, QuotePayment, Rate, Rebalance,
 };
 
 pub fn create_payment(
@@ -11,6 +12,26 @@ pub fn create_payment(
     currency: Currency,
     total_dest_payment: u128,
     dest_public_key: PublicKey,
+) -> AppRequest {
+    create_payment_with_fee_cap(
+        payment_id,
+        invoice_id,
+        currency,
+        total_dest_payment,
+        dest_public_key,
+        None,
+    )
+}
+
+/// Like `create_payment`, but the node rejects transactions of this payment whose fees exceed
+/// `opt_max_fee_per_hop` for every node that forwards the transaction.
+pub fn create_payment_with_fee_cap(
+    payment_id: PaymentId,
+    invoice_id: InvoiceId,
+    currency: Currency,
+    total_dest_payment: u128,
+    dest_public_key: PublicKey,
+    opt_max_fee_per_hop: Option<u128>,
 ) -> AppRequest {
     let create_payment = CreatePayment {
         payment_id,
@@ -18,6 +39,7 @@ pub fn create_payment(
         currency,
         total_dest_payment,
         dest_public_key,
+        opt_max_fee_per_hop,
     };
 
     AppRequest::CreatePayment(create_payment)
@@ -36,11 +58,88 @@ pub fn create_transaction(
         route,
         dest_payment,
         fees,
+        opt_refund_ticks: None,
     };
 
     AppRequest::CreateTransaction(create_transaction)
 }
 
+/// Create a transaction that is refunded automatically if the destination does not reveal its
+/// lock within `refund_ticks` ticks.
+pub fn create_refundable_transaction(
+    payment_id: PaymentId,
+    request_id: Uid,
+    route: FriendsRoute,
+    dest_payment: u128,
+    fees: u128,
+    refund_ticks: u64,
+) -> AppRequest {
+    let create_transaction = CreateTransaction {
+        payment_id,
+        request_id,
+        route,
+        dest_payment,
+        fees,
+        opt_refund_ticks: Some(refund_ticks),
+    };
+
+    AppRequest::CreateTransaction(create_transaction)
+}
+
+/// Create a transaction through a route that exchanges currencies on the way.
+/// `exchanged_payment` and `fees` are sent to the first friend on the route in `currency`. The
+/// exchanging node gives `dest_payment` credits (In the currency of the payment) to the
+/// destination.
+pub fn create_exchange_transaction(
+    payment_id: PaymentId,
+    request_id: Uid,
+    route: FriendsRoute,
+    currency: Currency,
+    exchanged_payment: u128,
+    dest_payment: u128,
+    fees: u128,
+    refund_ticks: u64,
+) -> AppRequest {
+    let create_exchange_transaction = CreateExchangeTransaction {
+        payment_id,
+        request_id,
+        route,
+        currency,
+        exchanged_payment,
+        dest_payment,
+        fees,
+        refund_ticks,
+    };
+
+    AppRequest::CreateExchangeTransaction(create_exchange_transaction)
+}
+
+/// Move `amount` credits between two friends by paying ourselves along a cycle `route`, which
+/// starts and ends with the local public key. The node commits the invoice of the self payment by
+/// itself, and the payment is closed like any other payment, using `payment_id`.
+/// Suggested rebalancing is found in `FunderReport::rebalance_suggestions`.
+pub fn rebalance(
+    payment_id: PaymentId,
+    invoice_id: InvoiceId,
+    request_id: Uid,
+    currency: Currency,
+    route: FriendsRoute,
+    amount: u128,
+    fees: u128,
+) -> AppRequest {
+    let rebalance = Rebalance {
+        payment_id,
+        invoice_id,
+        request_id,
+        currency,
+        route,
+        amount,
+        fees,
+    };
+
+    AppRequest::Rebalance(rebalance)
+}
+
 pub fn request_close_payment(payment_id: PaymentId) -> AppRequest {
     AppRequest::RequestClosePayment(payment_id)
 }
@@ -53,3 +152,27 @@ pub fn ack_close_payment(payment_id: PaymentId, ack_uid: Uid) -> AppRequest {
 
     AppRequest::AckClosePayment(ack_close_payment)
 }
+
+/// Quote a payment of `dest_payment` credits through `route`, without sending it.
+/// `mediator_rates` contains the rate of every node on the route, except for the first and the
+/// last. The response is sent back as `AppServerToApp::ResponseQuotePayment`, with a matching
+/// `request_id`.
+pub fn quote_payment(
+    request_id: Uid,
+    currency: Currency,
+    route: FriendsRoute,
+    mediator_rates: Vec<Rate>,
+    dest_payment: u128,
+    opt_max_fee_per_hop: Option<u128>,
+) -> AppRequest {
+    let quote_payment = QuotePayment {
+        request_id,
+        currency,
+        route,
+        mediator_rates,
+        dest_payment,
+        opt_max_fee_per_hop,
+    };
+
+    AppRequest::QuotePayment(quote_payment)
+}
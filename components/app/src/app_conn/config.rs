@@ -1,13 +1,15 @@
-use proto::crypto::{PublicKey, Signature};
+use proto::crypto::{PublicKey, Signature, Uid};
 
 use proto::app_server::messages::{
-    AppRequest, CloseFriendCurrency, NamedRelayAddress, OpenFriendCurrency, RelayAddress,
+    AppRequest, AppToAppServer, CloseFriendCurrency, NamedRelayAddress, OpenFriendCurrency,
+    RelayAddress, SendFriendProposal, SetNodeConfig,
 };
 use proto::funder::messages::{
-    AddFriend, Currency, Rate, RemoveFriendCurrency, ResetFriendChannel, SetFriendCurrencyMaxDebt,
-    SetFriendCurrencyRate, SetFriendName, SetFriendRelays,
+    AddFriend, Currency, ExchangeRate, KeyRotation, MaxOutflow, Rate, RemoveFriendCurrency,
+    ResetFriendChannel, SetExchangeRate, SetFriendCurrencyMaxDebt, SetFriendCurrencyRate,
+    SetFriendMaxOutflow, SetFriendName, SetFriendRelays,
 };
-use proto::index_server::messages::NamedIndexServerAddress;
+use proto::index_server::messages::{FriendProposal, NamedIndexServerAddress};
 
 pub fn add_relay(named_relay_address: NamedRelayAddress) -> AppRequest {
     AppRequest::AddRelay(named_relay_address)
@@ -58,6 +60,11 @@ pub fn disable_friend(friend_public_key: PublicKey) -> AppRequest {
     AppRequest::DisableFriend(friend_public_key)
 }
 
+/// Close the channel with a friend gracefully. See `DrainStatusReport` for the progress.
+pub fn drain_friend(friend_public_key: PublicKey) -> AppRequest {
+    AppRequest::DrainFriend(friend_public_key)
+}
+
 pub fn remove_friend_currency(friend_public_key: PublicKey, currency: Currency) -> AppRequest {
     AppRequest::RemoveFriendCurrency(RemoveFriendCurrency {
         friend_public_key,
@@ -92,6 +99,21 @@ pub fn set_friend_currency_max_debt(
     AppRequest::SetFriendCurrencyMaxDebt(set_friend_currency_max_debt)
 }
 
+/// Limit the credits sent to a friend during a window of `window_ticks` timer ticks.
+/// `opt_max_outflow = None` removes the limit.
+pub fn set_friend_max_outflow(
+    friend_public_key: PublicKey,
+    currency: Currency,
+    opt_max_outflow: Option<MaxOutflow>,
+) -> AppRequest {
+    let set_friend_max_outflow = SetFriendMaxOutflow {
+        friend_public_key,
+        currency,
+        opt_max_outflow,
+    };
+    AppRequest::SetFriendMaxOutflow(set_friend_max_outflow)
+}
+
 pub fn set_friend_currency_rate(
     friend_public_key: PublicKey,
     currency: Currency,
@@ -105,6 +127,21 @@ pub fn set_friend_currency_rate(
     AppRequest::SetFriendCurrencyRate(set_friend_currency_rate)
 }
 
+/// Exchange credits received in `from_currency` to credits sent in `to_currency`, for requests
+/// forwarded through this node. `opt_rate = None` stops exchanging between the two currencies.
+pub fn set_exchange_rate(
+    from_currency: Currency,
+    to_currency: Currency,
+    opt_rate: Option<ExchangeRate>,
+) -> AppRequest {
+    let set_exchange_rate = SetExchangeRate {
+        from_currency,
+        to_currency,
+        opt_rate,
+    };
+    AppRequest::SetExchangeRate(set_exchange_rate)
+}
+
 pub fn reset_friend_channel(friend_public_key: PublicKey, reset_token: Signature) -> AppRequest {
     // TODO: Check if a reset confusion attack is possible here.
     // Maybe we (locally) should be the ones generating the reset token.
@@ -124,3 +161,67 @@ pub fn add_index_server(named_index_server: NamedIndexServerAddress) -> AppReque
 pub fn remove_index_server(index_public_key: PublicKey) -> AppRequest {
     AppRequest::RemoveIndexServer(index_public_key)
 }
+
+pub fn set_node_config(set_node_config: SetNodeConfig) -> AppRequest {
+    AppRequest::SetNodeConfig(set_node_config)
+}
+
+/// Propose a credit line to a node we are not friends with yet.
+/// `relays` are the relays the destination node can reach us through.
+pub fn send_friend_proposal(
+    dest_public_key: PublicKey,
+    relays: Vec<RelayAddress>,
+    currency: Currency,
+    proposed_max_debt: u128,
+) -> AppRequest {
+    AppRequest::SendFriendProposal(SendFriendProposal {
+        dest_public_key,
+        relays,
+        currency,
+        proposed_max_debt,
+    })
+}
+
+/// Reject a received friend proposal, or dismiss it after it was accepted.
+pub fn remove_friend_proposal(friend_public_key: PublicKey) -> AppRequest {
+    AppRequest::RemoveFriendProposal(friend_public_key)
+}
+
+/// Announce a new identity key to all friends.
+/// The node starts using the new key after it is restarted with the new identity.
+pub fn rotate_key(key_rotation: KeyRotation) -> AppRequest {
+    AppRequest::RotateKey(key_rotation)
+}
+
+/// Send multiple requests together, given with their request ids.
+/// A single acknowledgement is received for the whole batch.
+pub fn batch(app_requests: Vec<(Uid, AppRequest)>) -> AppRequest {
+    AppRequest::Batch(
+        app_requests
+            .into_iter()
+            .map(|(app_request_id, app_request)| AppToAppServer::new(app_request_id, app_request))
+            .collect(),
+    )
+}
+
+/// The requests that accept a received friend proposal, in the order they should be sent:
+/// Add the proposing node as a friend, allow it the proposed max debt, open the proposed
+/// currency, enable the friend and finally remove the proposal from the inbox.
+pub fn accept_friend_proposal(friend_proposal: &FriendProposal, name: String) -> Vec<AppRequest> {
+    let friend_public_key = friend_proposal.src_public_key.clone();
+    vec![
+        add_friend(
+            friend_public_key.clone(),
+            friend_proposal.relays.clone(),
+            name,
+        ),
+        set_friend_currency_max_debt(
+            friend_public_key.clone(),
+            friend_proposal.currency.clone(),
+            friend_proposal.proposed_max_debt,
+        ),
+        open_friend_currency(friend_public_key.clone(), friend_proposal.currency.clone()),
+        enable_friend(friend_public_key.clone()),
+        remove_friend_proposal(friend_public_key),
+    ]
+}
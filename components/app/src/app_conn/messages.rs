@@ -0,0 +1,27 @@
+use proto::crypto::PublicKey;
+
+use proto::app_server::messages::{AppRequest, AppTopic, PublishAppMessage};
+
+/// Publish a message to all the apps connected to the node that are subscribed to the topic
+/// `topic_name` of this app. Requires the `publish` permission.
+pub fn publish_app_message(topic_name: String, data: Vec<u8>) -> AppRequest {
+    AppRequest::PublishAppMessage(PublishAppMessage { topic_name, data })
+}
+
+/// Receive messages published by the app with public key `app_public_key` under the topic
+/// `topic_name`. Messages are sent back as `AppServerToApp::AppMessage`.
+pub fn subscribe_app_topic(app_public_key: PublicKey, topic_name: String) -> AppRequest {
+    AppRequest::SubscribeAppTopic(AppTopic {
+        app_public_key,
+        name: topic_name,
+    })
+}
+
+/// Stop receiving messages published by the app with public key `app_public_key` under the
+/// topic `topic_name`.
+pub fn unsubscribe_app_topic(app_public_key: PublicKey, topic_name: String) -> AppRequest {
+    AppRequest::UnsubscribeAppTopic(AppTopic {
+        app_public_key,
+        name: topic_name,
+    })
+}
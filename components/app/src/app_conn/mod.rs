@@ -1,4 +1,6 @@
+pub mod analysis;
 pub mod buyer;
 pub mod config;
+pub mod messages;
 pub mod routes;
 pub mod seller;
@@ -2,7 +2,7 @@ use proto::crypto::{PublicKey, Uid};
 
 use proto::app_server::messages::AppRequest;
 use proto::funder::messages::Currency;
-use proto::index_server::messages::{Edge, RequestRoutes};
+use proto::index_server::messages::{Edge, RequestRoutes, RouteConstraints, RouteRanking};
 
 pub fn request_routes(
     request_routes_id: Uid,
@@ -11,6 +11,53 @@ pub fn request_routes(
     source: PublicKey,
     destination: PublicKey,
     opt_exclude: Option<(PublicKey, PublicKey)>,
+) -> AppRequest {
+    request_routes_with_constraints(
+        request_routes_id,
+        currency,
+        capacity,
+        source,
+        destination,
+        opt_exclude,
+        RouteConstraints::default(),
+    )
+}
+
+/// Like `request_routes`, but all the returned routes must also satisfy `constraints`
+/// (For example, avoid mediators that failed previous payments).
+pub fn request_routes_with_constraints(
+    request_routes_id: Uid,
+    currency: Currency,
+    capacity: u128,
+    source: PublicKey,
+    destination: PublicKey,
+    opt_exclude: Option<(PublicKey, PublicKey)>,
+    constraints: RouteConstraints,
+) -> AppRequest {
+    request_routes_with_ranking(
+        request_routes_id,
+        currency,
+        capacity,
+        source,
+        destination,
+        opt_exclude,
+        constraints,
+        RouteRanking::ShortestPath,
+    )
+}
+
+/// Like `request_routes_with_constraints`, but the returned routes are ranked according to
+/// `ranking`. For example, `RouteRanking::Capacity(k)` asks for the top `k` routes with the most
+/// residual capacity.
+pub fn request_routes_with_ranking(
+    request_routes_id: Uid,
+    currency: Currency,
+    capacity: u128,
+    source: PublicKey,
+    destination: PublicKey,
+    opt_exclude: Option<(PublicKey, PublicKey)>,
+    constraints: RouteConstraints,
+    ranking: RouteRanking,
 ) -> AppRequest {
     let opt_exclude = opt_exclude.map(|(from_public_key, to_public_key)| Edge {
         from_public_key,
@@ -24,6 +71,8 @@ pub fn request_routes(
         source,
         destination,
         opt_exclude,
+        constraints,
+        ranking,
     };
 
     AppRequest::RequestRoutes(request_routes)
@@ -2,7 +2,7 @@ use std::time::Duration;
 
 use futures::task::Spawn;
 
-use common::conn::ConnPair;
+use common::conn::{ConnPair, ConnPairVec, FutTransform};
 use common::int_convert::usize_to_u64;
 
 use proto::app_server::messages::{AppPermissions, AppServerToApp, AppToAppServer, NodeReport};
@@ -15,7 +15,7 @@ use crypto::rand::system_random;
 
 use identity::IdentityClient;
 use net::TcpConnector;
-use timer::create_timer;
+use timer::{create_timer, TimerClient};
 
 use app_client::app_connect_to_node;
 use connection::create_secure_connector;
@@ -28,13 +28,20 @@ pub type AppConnTuple = (AppPermissions, NodeReport, ConnPairApp);
 #[derive(Debug)]
 pub struct ConnectError;
 
-/// Connect to a remote offst-node.
-pub async fn connect<S>(
-    node_public_key: PublicKey,
-    node_net_address: NetAddress,
+/// Create a timer client, and a connector that opens secure connections to remote nodes.
+pub(crate) fn create_app_connector<S>(
     app_identity_client: IdentityClient,
     spawner: S,
-) -> Result<AppConnTuple, ConnectError>
+) -> Result<
+    (
+        TimerClient,
+        impl FutTransform<Input = (PublicKey, NetAddress), Output = Option<ConnPairVec>>
+            + Clone
+            + Send
+            + 'static,
+    ),
+    ConnectError,
+>
 where
     S: Spawn + Clone + Send + 'static,
 {
@@ -50,12 +57,28 @@ where
 
     let secure_connector = create_secure_connector(
         tcp_connector,
-        timer_client,
+        timer_client.clone(),
         app_identity_client,
         rng,
-        spawner.clone(),
+        spawner,
     );
 
+    Ok((timer_client, secure_connector))
+}
+
+/// Connect to a remote offst-node.
+pub async fn connect<S>(
+    node_public_key: PublicKey,
+    node_net_address: NetAddress,
+    app_identity_client: IdentityClient,
+    spawner: S,
+) -> Result<AppConnTuple, ConnectError>
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (_timer_client, secure_connector) =
+        create_app_connector(app_identity_client, spawner.clone())?;
+
     app_connect_to_node(secure_connector, node_public_key, node_net_address, spawner)
         .await
         .map_err(|_| ConnectError)
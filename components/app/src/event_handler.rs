@@ -0,0 +1,203 @@
+use std::collections::BTreeMap;
+
+use futures::{Stream, StreamExt};
+
+use proto::app_server::messages::{AppServerToApp, NodeReport, NodeReportMutation};
+use proto::crypto::{PaymentId, PublicKey};
+use proto::funder::messages::{Currency, PaymentStatus, Receipt};
+use proto::report::messages::{ChannelStatusReport, FriendLivenessReport, FunderReportMutation};
+
+/// Callbacks for the events of a connection to a node.
+///
+/// Every callback has an empty default implementation, so an application only implements the
+/// events it is interested in. Callbacks are called by `AppEventDispatcher`, after the node
+/// report was updated with the event.
+pub trait AppEventHandler {
+    /// A friend became online
+    fn on_friend_online(&mut self, _friend_public_key: &PublicKey) {}
+
+    /// A friend became offline (Or was removed while online)
+    fn on_friend_offline(&mut self, _friend_public_key: &PublicKey) {}
+
+    /// The balance with a friend in a currency has changed.
+    /// `balance` is the amount of credits the friend owes us. A negative balance means that we
+    /// owe the friend credits.
+    fn on_balance_change(
+        &mut self,
+        _friend_public_key: &PublicKey,
+        _currency: &Currency,
+        _balance: i128,
+    ) {
+    }
+
+    /// A payment was closed successfully. The payment must still be acknowledged using
+    /// `buyer::ack_close_payment`, otherwise the node keeps the receipt.
+    fn on_receipt(&mut self, _payment_id: &PaymentId, _receipt: &Receipt) {}
+
+    /// Any message from the node other than report mutations, for example responses to requests
+    /// sent by the application. Called after the more specific callbacks.
+    fn on_message(&mut self, _message: &AppServerToApp) {}
+}
+
+#[derive(Debug)]
+pub enum AppEventError {
+    /// A report mutation could not be applied. The node report is out of sync with the node.
+    ReportMutationError,
+}
+
+/// The state of a friend that events are derived from
+#[derive(Debug, Default, PartialEq, Eq)]
+struct FriendSnapshot {
+    is_online: bool,
+    balances: BTreeMap<Currency, i128>,
+}
+
+fn friend_snapshot(node_report: &NodeReport, friend_public_key: &PublicKey) -> FriendSnapshot {
+    let friend_report = match node_report.funder_report.friends.get(friend_public_key) {
+        Some(friend_report) => friend_report,
+        None => return FriendSnapshot::default(),
+    };
+    let balances = match &friend_report.channel_status {
+        ChannelStatusReport::Consistent(channel_consistent_report) => channel_consistent_report
+            .currency_reports
+            .iter()
+            .map(|currency_report| {
+                (
+                    currency_report.currency.clone(),
+                    currency_report.balance.balance,
+                )
+            })
+            .collect(),
+        // Balances are unknown until the channel is reset:
+        ChannelStatusReport::Inconsistent(_) => BTreeMap::new(),
+    };
+    FriendSnapshot {
+        is_online: friend_report.liveness == FriendLivenessReport::Online,
+        balances,
+    }
+}
+
+/// The friend a report mutation is about.
+/// Returns None for mutations that do not concern a specific friend.
+fn mutation_friend(mutation: &NodeReportMutation) -> Option<&PublicKey> {
+    match mutation {
+        NodeReportMutation::Funder(FunderReportMutation::AddFriend(add_friend_report)) => {
+            Some(&add_friend_report.friend_public_key)
+        }
+        NodeReportMutation::Funder(FunderReportMutation::RemoveFriend(friend_public_key))
+        | NodeReportMutation::Funder(FunderReportMutation::PkFriendReportMutation((
+            friend_public_key,
+            _,
+        ))) => Some(friend_public_key),
+        _ => None,
+    }
+}
+
+/// Keeps the node report up to date, and turns the messages of the node into calls to an
+/// `AppEventHandler`.
+pub struct AppEventDispatcher<H> {
+    node_report: NodeReport,
+    handler: H,
+}
+
+impl<H> AppEventDispatcher<H>
+where
+    H: AppEventHandler,
+{
+    /// `node_report` is the report received when connecting to the node.
+    pub fn new(node_report: NodeReport, handler: H) -> Self {
+        AppEventDispatcher {
+            node_report,
+            handler,
+        }
+    }
+
+    /// The node report, including all the messages dispatched so far
+    pub fn node_report(&self) -> &NodeReport {
+        &self.node_report
+    }
+
+    pub fn handler(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    pub fn into_handler(self) -> H {
+        self.handler
+    }
+
+    /// Apply a message from the node to the node report, and call the matching callbacks
+    pub fn dispatch(&mut self, message: AppServerToApp) -> Result<(), AppEventError> {
+        match message {
+            AppServerToApp::ReportMutations(report_mutations) => {
+                for mutation in &report_mutations.mutations {
+                    self.dispatch_mutation(mutation)?;
+                }
+                return Ok(());
+            }
+            AppServerToApp::ResponseClosePayment(ref response_close_payment) => {
+                if let PaymentStatus::Success(payment_status_success) =
+                    &response_close_payment.status
+                {
+                    self.handler.on_receipt(
+                        &response_close_payment.payment_id,
+                        &payment_status_success.receipt,
+                    );
+                }
+            }
+            _ => {}
+        }
+        self.handler.on_message(&message);
+        Ok(())
+    }
+
+    fn dispatch_mutation(&mut self, mutation: &NodeReportMutation) -> Result<(), AppEventError> {
+        let opt_friend_public_key = mutation_friend(mutation).cloned();
+        let opt_before = opt_friend_public_key
+            .as_ref()
+            .map(|friend_public_key| friend_snapshot(&self.node_report, friend_public_key));
+
+        self.node_report
+            .mutate(mutation)
+            .map_err(|_| AppEventError::ReportMutationError)?;
+
+        let (friend_public_key, before) = match (opt_friend_public_key, opt_before) {
+            (Some(friend_public_key), Some(before)) => (friend_public_key, before),
+            _ => return Ok(()),
+        };
+        let after = friend_snapshot(&self.node_report, &friend_public_key);
+
+        if !before.is_online && after.is_online {
+            self.handler.on_friend_online(&friend_public_key);
+        } else if before.is_online && !after.is_online {
+            self.handler.on_friend_offline(&friend_public_key);
+        }
+
+        for (currency, balance) in &after.balances {
+            if before.balances.get(currency).cloned().unwrap_or(0) != *balance {
+                self.handler
+                    .on_balance_change(&friend_public_key, currency, *balance);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Dispatch all the messages received from the node to `handler`, until the connection is
+/// closed. `node_report` is the report received when connecting to the node.
+///
+/// Returns the handler once the connection is closed.
+pub async fn run_event_handler<R, H>(
+    node_report: NodeReport,
+    mut receiver: R,
+    handler: H,
+) -> Result<H, AppEventError>
+where
+    R: Stream<Item = AppServerToApp> + Unpin,
+    H: AppEventHandler,
+{
+    let mut dispatcher = AppEventDispatcher::new(node_report, handler);
+    while let Some(message) = receiver.next().await {
+        dispatcher.dispatch(message)?;
+    }
+    Ok(dispatcher.into_handler())
+}
@@ -11,7 +11,11 @@ extern crate log;
 
 mod app_conn;
 mod connect;
+mod event_handler;
 mod identity;
+mod payment_client;
+mod reconnect;
+mod routes_client;
 mod types;
 
 /// Utils for random generation of types
@@ -32,10 +36,12 @@ pub mod common {
         Signature, Uid,
     };
     pub use proto::funder::messages::{
-        Commit, Currency, FriendsRoute, PaymentStatus, PaymentStatusSuccess, Rate, Receipt,
+        Commit, Currency, ExchangeRate, FriendsRoute, KeyRotation, MaxOutflow, PaymentStatus,
+        PaymentStatusSuccess, Rate, Receipt,
     };
     pub use proto::index_server::messages::{
-        MultiRoute, NamedIndexServerAddress, RouteCapacityRate,
+        FriendProposal, MultiRoute, NamedIndexServerAddress, RouteCapacityRate, RouteConstraints,
+        RouteRanking,
     };
     pub use proto::net::messages::NetAddress;
 }
@@ -45,13 +51,29 @@ pub use proto::file;
 
 /// Offst connection
 pub mod conn {
-    pub use super::app_conn::{buyer, config, routes, seller};
+    pub use super::app_conn::{analysis, buyer, config, messages, routes, seller};
     pub use super::connect::{connect, AppConnTuple, ConnPairApp, ConnectError};
+    pub use super::event_handler::{
+        run_event_handler, AppEventDispatcher, AppEventError, AppEventHandler,
+    };
     pub use super::identity::{identity_from_file, IdentityFromFileError};
+    pub use super::payment_client::{PaymentClient, PaymentClientError};
+    pub use super::reconnect::{
+        connect_reconnect, AppConnEvent, AppSession, ConnPairReconnect, RECONNECT_BACKOFF_TICKS,
+    };
+    pub use super::routes_client::{multi_route_fees, AppRoutes, AppRoutesError};
     pub use proto::app_server::messages::{
-        AppPermissions, AppRequest, AppServerToApp, AppToAppServer,
+        AppMessage, AppPermissions, AppRequest, AppServerToApp, AppSubscription, AppToAppServer,
+        AppTopic, ArchivedInvoice, ArchivedReceipt, BalanceDelta, FriendDetail, FriendDetailResult,
+        FriendsFilter, NodeFeature, ReportSubscription, ResponseBalanceHistory,
+        ResponseFriendDetail, ResponseReceipts, ServerHello, SetNodeConfig,
+    };
+    pub use proto::funder::messages::{
+        ChannelProofResult, CurrencyExposure, ExportChannelProof, FriendCurrencyExposure,
+        FriendExposure, HopFreeze, QuotePayment, QuoteRejection, RequestError, RequestErrorCode,
+        RequestResult, ResponseChannelProof, ResponseClosePayment, ResponseExposure,
+        ResponseQuotePayment, TransactionRejection,
     };
-    pub use proto::funder::messages::{RequestResult, ResponseClosePayment};
     pub use proto::index_client::messages::{ClientResponseRoutes, ResponseRoutesResult};
 }
 
@@ -59,10 +81,11 @@ pub mod conn {
 /// Report related types
 pub mod report {
     pub use proto::report::messages::{
-        AddFriendReport, ChannelConsistentReport, ChannelInconsistentReport, ChannelStatusReport,
-        CurrencyConfigReport, CurrencyReport, FriendLivenessReport, FriendReport,
-        FriendStatusReport, FunderReport, McBalanceReport, MoveTokenHashedReport,
-        RequestsStatusReport, ResetTermsReport,
+        AddFriendReport, ChannelConsistentReport, ChannelInconsistentReport, ChannelProof,
+        ChannelStatusReport, CurrencyConfigReport, CurrencyReport, DisputeEvidence,
+        DisputedTransaction, FriendLivenessReport, FriendReport, FriendStatusReport, FunderReport,
+        McBalanceReport, MoveTokenHashedReport, RebalanceSuggestion, RequestsStatusReport,
+        ResetTermsReport,
     };
 
     pub use proto::funder::messages::{
@@ -75,5 +98,17 @@ pub mod report {
 
 /// Verification functions
 pub mod verify {
-    pub use signature::verify::{verify_commit, verify_move_token_hashed_report, verify_receipt};
+    pub use signature::channel_proof::{
+        latest_token_info, verify_channel_proof, verify_channel_proof_blob, ChannelProofError,
+    };
+    pub use signature::receipt::{verify_receipt, ReceiptError};
+    pub use signature::verify::{verify_commit, verify_move_token_hashed_report};
+}
+
+/// Mock services with scriptable behaviors, for deterministic tests
+#[cfg(feature = "testing")]
+pub mod testing {
+    pub use common::testing::{MockAction, MockScript};
+    pub use identity::testing::{create_mock_identity, MockBackend};
+    pub use timer::testing::{create_mock_timer, MockTimer, MockTimerError};
 }
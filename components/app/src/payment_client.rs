@@ -0,0 +1,281 @@
+use std::collections::HashSet;
+
+use futures::{SinkExt, StreamExt};
+
+use common::conn::FutTransform;
+
+use proto::app_server::messages::{AppRequest, AppServerToApp, AppToAppServer};
+use proto::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
+use proto::funder::messages::{
+    Commit, Currency, PaymentStatus, PaymentStatusSuccess, Receipt, RequestResult,
+    TransactionRejection,
+};
+use proto::index_server::messages::MultiRoute;
+
+use route::choose_multi_route;
+
+use signature::receipt::{verify_receipt, ReceiptError};
+
+use crate::app_conn::{buyer, routes};
+use crate::connect::ConnPairApp;
+use crate::gen::{gen_payment_id, gen_uid};
+use crate::routes_client::{request_multi_routes, AppRoutesError};
+
+/// Maximum amount of routes a single payment may be split between, in case no single route has
+/// enough capacity.
+const MAX_PAYMENT_ROUTES: usize = 4;
+
+#[derive(Debug)]
+pub enum PaymentClientError {
+    /// Sending a request to the node failed
+    SendError,
+    /// The connection to the node was closed
+    ConnectionClosed,
+    /// The index servers could not provide routes to the destination
+    RoutesUnavailable,
+    /// None of the routes has enough capacity for the payment
+    NoSuitableRoute,
+    /// The chosen routes do not exist, or do not sum up to the payment
+    InvalidRouteChoice,
+    FeesOverflow,
+    /// The commit could not be handed to the seller
+    SendCommitError,
+    /// The payment was canceled. No credits were paid.
+    PaymentCanceled,
+    /// The node rejected a transaction of the payment (For example, because its fees exceed the
+    /// fee cap). The payment was canceled, and no credits were paid.
+    TransactionRejected(TransactionRejection),
+    PaymentNotFound,
+    /// The payment succeeded, but the receipt does not prove the payment of the invoice
+    InvalidReceipt(ReceiptError),
+}
+
+/// A high level interface for paying invoices.
+///
+/// `PaymentClient` requests routes, creates the payment and its transactions, hands the resulting
+/// commit to the seller (Using `commit_sender`) and waits for the receipt.
+/// `commit_sender` should deliver the commit to the seller (For example, over a connection
+/// to the seller's application), and resolve to true if the commit was delivered.
+pub struct PaymentClient<CS> {
+    conn_pair: ConnPairApp,
+    local_public_key: PublicKey,
+    commit_sender: CS,
+    opt_max_fee_per_hop: Option<u128>,
+}
+
+impl<CS> PaymentClient<CS>
+where
+    CS: FutTransform<Input = Commit, Output = bool>,
+{
+    pub fn new(conn_pair: ConnPairApp, local_public_key: PublicKey, commit_sender: CS) -> Self {
+        PaymentClient {
+            conn_pair,
+            local_public_key,
+            commit_sender,
+            opt_max_fee_per_hop: None,
+        }
+    }
+
+    /// Limit the fees of future payments to `opt_max_fee_per_hop` credits for every node that
+    /// forwards a payment. None removes the limit.
+    pub fn set_max_fee_per_hop(&mut self, opt_max_fee_per_hop: Option<u128>) {
+        self.opt_max_fee_per_hop = opt_max_fee_per_hop;
+    }
+
+    /// Pay an invoice of `dest_payment` credits (Not including fees) to `dest_public_key`.
+    /// Resolves once the payment is closed. On success, returns a receipt that was verified to be
+    /// signed by the seller.
+    pub async fn pay(
+        &mut self,
+        currency: Currency,
+        dest_public_key: PublicKey,
+        dest_payment: u128,
+        invoice_id: InvoiceId,
+    ) -> Result<Receipt, PaymentClientError> {
+        let multi_routes = self
+            .request_routes(currency.clone(), dest_public_key.clone(), dest_payment)
+            .await?;
+
+        let (route_index, multi_route_choice) =
+            choose_multi_route(&multi_routes, dest_payment, MAX_PAYMENT_ROUTES)
+                .ok_or(PaymentClientError::NoSuitableRoute)?;
+
+        self.pay_with_route(
+            currency,
+            dest_public_key,
+            dest_payment,
+            invoice_id,
+            &multi_routes[route_index],
+            &multi_route_choice,
+        )
+        .await
+    }
+
+    /// Like `pay`, but the payment is sent along an explicitly chosen route.
+    /// `multi_route` is usually one of the candidates returned by `AppRoutes`, and
+    /// `multi_route_choice` is a list of pairs of route index and credits to push through that
+    /// route. The credits must sum up to `dest_payment`.
+    pub async fn pay_with_route(
+        &mut self,
+        currency: Currency,
+        dest_public_key: PublicKey,
+        dest_payment: u128,
+        invoice_id: InvoiceId,
+        multi_route: &MultiRoute,
+        multi_route_choice: &[(usize, u128)],
+    ) -> Result<Receipt, PaymentClientError> {
+        let mut total_choice = 0u128;
+        for (route_index, route_dest_payment) in multi_route_choice {
+            if *route_index >= multi_route.routes.len() {
+                return Err(PaymentClientError::InvalidRouteChoice);
+            }
+            total_choice = total_choice
+                .checked_add(*route_dest_payment)
+                .ok_or(PaymentClientError::InvalidRouteChoice)?;
+        }
+        if total_choice != dest_payment {
+            return Err(PaymentClientError::InvalidRouteChoice);
+        }
+
+        let payment_id = gen_payment_id();
+        self.send_request(buyer::create_payment_with_fee_cap(
+            payment_id.clone(),
+            invoice_id.clone(),
+            currency,
+            dest_payment,
+            dest_public_key.clone(),
+            self.opt_max_fee_per_hop,
+        ))
+        .await?;
+
+        // One transaction for every chosen route:
+        let mut pending_requests = HashSet::new();
+        for (route_index, route_dest_payment) in multi_route_choice {
+            let route = &multi_route.routes[*route_index];
+            let fees = route
+                .rate
+                .calc_fee(*route_dest_payment)
+                .ok_or(PaymentClientError::FeesOverflow)?;
+
+            let request_id = gen_uid();
+            pending_requests.insert(request_id.clone());
+            self.send_request(buyer::create_transaction(
+                payment_id.clone(),
+                request_id,
+                route.route.clone(),
+                *route_dest_payment,
+                fees,
+            ))
+            .await?;
+        }
+
+        // No new transactions will be created. The node will report the status of the payment
+        // once all the transactions are done:
+        self.send_request(buyer::request_close_payment(payment_id.clone()))
+            .await?;
+
+        let mut opt_rejection = None;
+        let payment_status = self
+            .wait_payment_status(&payment_id, pending_requests, &mut opt_rejection)
+            .await?;
+
+        let (receipt, ack_uid) = match payment_status {
+            PaymentStatus::PaymentNotFound => return Err(PaymentClientError::PaymentNotFound),
+            PaymentStatus::Canceled(ack_uid) => {
+                self.send_request(buyer::ack_close_payment(payment_id, ack_uid))
+                    .await?;
+                return Err(match opt_rejection {
+                    Some(rejection) => PaymentClientError::TransactionRejected(rejection),
+                    None => PaymentClientError::PaymentCanceled,
+                });
+            }
+            PaymentStatus::Success(PaymentStatusSuccess { receipt, ack_uid }) => (receipt, ack_uid),
+        };
+
+        // The node discards the receipt once we ack it:
+        self.send_request(buyer::ack_close_payment(payment_id, ack_uid))
+            .await?;
+
+        verify_receipt(&receipt, &invoice_id, dest_payment, &dest_public_key)
+            .map_err(PaymentClientError::InvalidReceipt)?;
+
+        Ok(receipt)
+    }
+
+    async fn send_request(&mut self, app_request: AppRequest) -> Result<(), PaymentClientError> {
+        // We wait on the ids inside the requests, so `app_request_id` is never used:
+        let app_to_app_server = AppToAppServer {
+            app_request_id: gen_uid(),
+            app_request,
+        };
+        self.conn_pair
+            .sender
+            .send(app_to_app_server)
+            .await
+            .map_err(|_| PaymentClientError::SendError)
+    }
+
+    async fn request_routes(
+        &mut self,
+        currency: Currency,
+        dest_public_key: PublicKey,
+        dest_payment: u128,
+    ) -> Result<Vec<MultiRoute>, PaymentClientError> {
+        let request_routes_id = gen_uid();
+        let app_request = routes::request_routes(
+            request_routes_id.clone(),
+            currency,
+            dest_payment,
+            self.local_public_key.clone(),
+            dest_public_key,
+            None,
+        );
+
+        request_multi_routes(&mut self.conn_pair, request_routes_id, app_request)
+            .await
+            .map_err(|e| match e {
+                AppRoutesError::SendError => PaymentClientError::SendError,
+                AppRoutesError::ConnectionClosed => PaymentClientError::ConnectionClosed,
+                AppRoutesError::RoutesUnavailable => PaymentClientError::RoutesUnavailable,
+            })
+    }
+
+    /// Wait until the payment is closed.
+    /// Hands the commit to the seller if any of the transactions completes the payment.
+    /// If the node rejects any of the transactions, the reason is stored in `opt_rejection`.
+    async fn wait_payment_status(
+        &mut self,
+        payment_id: &PaymentId,
+        mut pending_requests: HashSet<Uid>,
+        opt_rejection: &mut Option<TransactionRejection>,
+    ) -> Result<PaymentStatus, PaymentClientError> {
+        while let Some(app_server_to_app) = self.conn_pair.receiver.next().await {
+            match app_server_to_app {
+                AppServerToApp::TransactionResult(transaction_result) => {
+                    if !pending_requests.remove(&transaction_result.request_id) {
+                        // Not one of our transactions:
+                        continue;
+                    }
+                    // A failed transaction cancels the payment. We will be notified through
+                    // `ResponseClosePayment`.
+                    match transaction_result.result {
+                        RequestResult::Complete(commit) => {
+                            if !self.commit_sender.transform(commit).await {
+                                return Err(PaymentClientError::SendCommitError);
+                            }
+                        }
+                        RequestResult::Rejected(rejection) => *opt_rejection = Some(rejection),
+                        RequestResult::Success | RequestResult::Failure => {}
+                    }
+                }
+                AppServerToApp::ResponseClosePayment(response_close_payment) => {
+                    if &response_close_payment.payment_id == payment_id {
+                        return Ok(response_close_payment.status);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(PaymentClientError::ConnectionClosed)
+    }
+}
@@ -0,0 +1,238 @@
+use futures::channel::mpsc;
+use futures::task::{Spawn, SpawnExt};
+use futures::{future, stream, SinkExt, StreamExt};
+
+use log::warn;
+
+use common::conn::{BoxStream, ConnPair, ConnPairVec, FutTransform};
+use common::select_streams::select_streams;
+
+use proto::app_server::messages::{
+    AppHello, AppPermissions, AppServerToApp, AppSubscription, AppToAppServer, NodeReport,
+    ServerHello,
+};
+use proto::crypto::PublicKey;
+use proto::net::messages::NetAddress;
+
+use identity::IdentityClient;
+use timer::TimerClient;
+
+use app_client::app_connect_to_node_with_hello;
+
+use crate::connect::{create_app_connector, ConnectError};
+
+/// Amount of ticks to wait after losing the connection to the node (Or failing to connect),
+/// before attempting to connect again.
+pub const RECONNECT_BACKOFF_TICKS: usize = 0x10;
+
+/// A session of an App with a Node, established on every successful (re)connection.
+#[derive(Debug)]
+pub struct AppSession {
+    pub app_permissions: AppPermissions,
+    /// Features supported by the node
+    pub server_hello: ServerHello,
+    /// A fresh report of the node. Report mutations received during this session apply to this
+    /// report.
+    pub node_report: NodeReport,
+}
+
+/// An event of a connection of an App to a Node, that reconnects automatically.
+#[derive(Debug)]
+pub enum AppConnEvent {
+    /// A session with the node was established. Any previously received node report is stale,
+    /// and should be replaced with the report of the new session.
+    Connected(AppSession),
+    /// A message from the node, received during the current session
+    Message(AppServerToApp),
+    /// The session with the node was lost. Requests sent by the app until the next `Connected`
+    /// event are discarded, and responses to requests of the lost session might never arrive.
+    Disconnected,
+}
+
+/// A connection of an App to a Node, that reconnects automatically
+pub type ConnPairReconnect = ConnPair<AppToAppServer, AppConnEvent>;
+
+#[derive(Debug)]
+enum ReconnectError {
+    RequestTimerStreamError,
+    TimerClosed,
+}
+
+#[derive(Debug)]
+enum ReconnectEvent {
+    User(AppToAppServer),
+    UserClosed,
+    Node(AppServerToApp),
+    NodeClosed,
+    TimerTick,
+    TimerClosed,
+}
+
+async fn reconnect_loop<C, S>(
+    connector: C,
+    node_public_key: PublicKey,
+    node_net_address: NetAddress,
+    app_hello: AppHello,
+    mut timer_client: TimerClient,
+    backoff_ticks: usize,
+    mut from_user: mpsc::Receiver<AppToAppServer>,
+    mut to_user: mpsc::Sender<AppConnEvent>,
+    spawner: S,
+) -> Result<(), ReconnectError>
+where
+    C: FutTransform<Input = (PublicKey, NetAddress), Output = Option<ConnPairVec>>
+        + Clone
+        + Send
+        + 'static,
+    S: Spawn + Clone + Send + 'static,
+{
+    let mut timer_stream = timer_client
+        .request_timer_stream()
+        .await
+        .map_err(|_| ReconnectError::RequestTimerStreamError)?;
+
+    loop {
+        match app_connect_to_node_with_hello(
+            connector.clone(),
+            node_public_key.clone(),
+            node_net_address.clone(),
+            app_hello.clone(),
+            spawner.clone(),
+        )
+        .await
+        {
+            Ok((server_hello, (app_permissions, node_report, conn_pair))) => {
+                let app_session = AppSession {
+                    app_permissions,
+                    server_hello,
+                    node_report,
+                };
+                if to_user
+                    .send(AppConnEvent::Connected(app_session))
+                    .await
+                    .is_err()
+                {
+                    return Ok(());
+                }
+
+                let (mut sender, receiver) = conn_pair.split();
+                let receiver = receiver
+                    .map(ReconnectEvent::Node)
+                    .chain(stream::once(future::ready(ReconnectEvent::NodeClosed)));
+                let user = from_user
+                    .by_ref()
+                    .map(ReconnectEvent::User)
+                    .chain(stream::once(future::ready(ReconnectEvent::UserClosed)));
+                // Ticks are not needed during a session, but we still have to consume them:
+                let timer = timer_stream
+                    .by_ref()
+                    .map(|_| ReconnectEvent::TimerTick)
+                    .chain(stream::once(future::ready(ReconnectEvent::TimerClosed)));
+
+                let mut events = select_streams![receiver, user, timer];
+                while let Some(event) = events.next().await {
+                    match event {
+                        ReconnectEvent::User(app_to_app_server) => {
+                            if sender.send(app_to_app_server).await.is_err() {
+                                warn!("reconnect_loop: Failed to send a message to the node");
+                                break;
+                            }
+                        }
+                        ReconnectEvent::UserClosed => return Ok(()),
+                        ReconnectEvent::Node(app_server_to_app) => {
+                            if to_user
+                                .send(AppConnEvent::Message(app_server_to_app))
+                                .await
+                                .is_err()
+                            {
+                                return Ok(());
+                            }
+                        }
+                        ReconnectEvent::NodeClosed => break,
+                        ReconnectEvent::TimerTick => {}
+                        ReconnectEvent::TimerClosed => return Err(ReconnectError::TimerClosed),
+                    }
+                }
+
+                if to_user.send(AppConnEvent::Disconnected).await.is_err() {
+                    return Ok(());
+                }
+            }
+            Err(e) => warn!("reconnect_loop: Failed to connect to node: {:?}", e),
+        }
+
+        // Backoff before reconnecting, so that we don't flood the node with connection attempts:
+        let user = from_user
+            .by_ref()
+            .map(ReconnectEvent::User)
+            .chain(stream::once(future::ready(ReconnectEvent::UserClosed)));
+        let timer = timer_stream
+            .by_ref()
+            .map(|_| ReconnectEvent::TimerTick)
+            .chain(stream::once(future::ready(ReconnectEvent::TimerClosed)));
+
+        let mut events = select_streams![user, timer];
+        let mut ticks_left = backoff_ticks;
+        while ticks_left > 0 {
+            match events.next().await {
+                Some(ReconnectEvent::User(app_to_app_server)) => warn!(
+                    "reconnect_loop: Not connected. Discarding message: {:?}",
+                    app_to_app_server
+                ),
+                Some(ReconnectEvent::UserClosed) => return Ok(()),
+                Some(ReconnectEvent::TimerTick) => ticks_left -= 1,
+                Some(ReconnectEvent::TimerClosed) | None => {
+                    return Err(ReconnectError::TimerClosed)
+                }
+                Some(ReconnectEvent::Node(_)) | Some(ReconnectEvent::NodeClosed) => unreachable!(),
+            }
+        }
+    }
+}
+
+/// Connect to a remote offst-node, and keep reconnecting whenever the connection is lost (For
+/// example, when the node restarts).
+///
+/// Every connection establishes a new secure session, goes through the permissions handshake and
+/// obtains a fresh node report, reported to the app as an `AppConnEvent::Connected` event. The
+/// app is subscribed to all report mutations on every session.
+pub fn connect_reconnect<S>(
+    node_public_key: PublicKey,
+    node_net_address: NetAddress,
+    app_identity_client: IdentityClient,
+    spawner: S,
+) -> Result<ConnPairReconnect, ConnectError>
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (timer_client, secure_connector) =
+        create_app_connector(app_identity_client, spawner.clone())?;
+
+    let app_hello = AppHello {
+        subscriptions: AppSubscription::all(),
+    };
+
+    let (user_sender, from_user) = mpsc::channel(0);
+    let (to_user, user_receiver) = mpsc::channel(1);
+
+    let loop_fut = reconnect_loop(
+        secure_connector,
+        node_public_key,
+        node_net_address,
+        app_hello,
+        timer_client,
+        RECONNECT_BACKOFF_TICKS,
+        from_user,
+        to_user,
+        spawner.clone(),
+    );
+    spawner
+        .spawn(async move {
+            if let Err(e) = loop_fut.await {
+                warn!("connect_reconnect: reconnect_loop error: {:?}", e);
+            }
+        })
+        .map_err(|_| ConnectError)?;
+
+    Ok(ConnPair::from_raw(user_sender, user_receiver))
+}
@@ -0,0 +1,138 @@
+use futures::{SinkExt, StreamExt};
+
+use proto::app_server::messages::{AppRequest, AppServerToApp, AppToAppServer};
+use proto::crypto::{PublicKey, Uid};
+use proto::funder::messages::Currency;
+use proto::index_client::messages::ResponseRoutesResult;
+use proto::index_server::messages::{MultiRoute, RouteConstraints, RouteRanking};
+
+use crate::app_conn::routes;
+use crate::connect::ConnPairApp;
+use crate::gen::gen_uid;
+
+#[derive(Debug)]
+pub enum AppRoutesError {
+    /// Sending a request to the node failed
+    SendError,
+    /// The connection to the node was closed
+    ConnectionClosed,
+    /// The index servers could not provide routes to the destination
+    RoutesUnavailable,
+}
+
+/// A high level interface for route discovery.
+///
+/// `AppRoutes` asks the node's index client for candidate routes to a destination.
+/// Every returned route carries its capacity and its rate, so that the application may inspect
+/// the candidates and choose a route explicitly (See `PaymentClient::pay_with_route`),
+/// instead of relying on automatic routing.
+pub struct AppRoutes {
+    conn_pair: ConnPairApp,
+    local_public_key: PublicKey,
+}
+
+impl AppRoutes {
+    pub fn new(conn_pair: ConnPairApp, local_public_key: PublicKey) -> Self {
+        AppRoutes {
+            conn_pair,
+            local_public_key,
+        }
+    }
+
+    /// Get back the connection to the node
+    pub fn into_conn_pair(self) -> ConnPairApp {
+        self.conn_pair
+    }
+
+    /// Request candidate routes from the local node to `dest_public_key` that may carry
+    /// `capacity` credits.
+    pub async fn request_routes(
+        &mut self,
+        currency: Currency,
+        dest_public_key: PublicKey,
+        capacity: u128,
+    ) -> Result<Vec<MultiRoute>, AppRoutesError> {
+        self.request_routes_with_ranking(
+            currency,
+            dest_public_key,
+            capacity,
+            RouteConstraints::default(),
+            RouteRanking::ShortestPath,
+        )
+        .await
+    }
+
+    /// Like `request_routes`, but all the returned routes must satisfy `constraints`, and are
+    /// ranked according to `ranking`.
+    pub async fn request_routes_with_ranking(
+        &mut self,
+        currency: Currency,
+        dest_public_key: PublicKey,
+        capacity: u128,
+        constraints: RouteConstraints,
+        ranking: RouteRanking,
+    ) -> Result<Vec<MultiRoute>, AppRoutesError> {
+        let request_routes_id = gen_uid();
+        let app_request = routes::request_routes_with_ranking(
+            request_routes_id.clone(),
+            currency,
+            capacity,
+            self.local_public_key.clone(),
+            dest_public_key,
+            None,
+            constraints,
+            ranking,
+        );
+        request_multi_routes(&mut self.conn_pair, request_routes_id, app_request).await
+    }
+}
+
+/// Calculate the total fees of sending credits along `multi_route`, split according to
+/// `multi_route_choice` (Pairs of route index and credits to push through that route).
+/// Returns None if an overflow occurred.
+pub fn multi_route_fees(
+    multi_route: &MultiRoute,
+    multi_route_choice: &[(usize, u128)],
+) -> Option<u128> {
+    let mut total_fees = 0u128;
+    for (route_index, dest_payment) in multi_route_choice {
+        let fee = multi_route
+            .routes
+            .get(*route_index)?
+            .rate
+            .calc_fee(*dest_payment)?;
+        total_fees = total_fees.checked_add(fee)?;
+    }
+    Some(total_fees)
+}
+
+/// Send a routes request to the node, and wait for the matching response.
+/// Messages that are not related to the request are discarded.
+pub(crate) async fn request_multi_routes(
+    conn_pair: &mut ConnPairApp,
+    request_routes_id: Uid,
+    app_request: AppRequest,
+) -> Result<Vec<MultiRoute>, AppRoutesError> {
+    // We wait on the id inside the request, so `app_request_id` is never used:
+    let app_to_app_server = AppToAppServer {
+        app_request_id: gen_uid(),
+        app_request,
+    };
+    conn_pair
+        .sender
+        .send(app_to_app_server)
+        .await
+        .map_err(|_| AppRoutesError::SendError)?;
+
+    while let Some(app_server_to_app) = conn_pair.receiver.next().await {
+        if let AppServerToApp::ResponseRoutes(client_response_routes) = app_server_to_app {
+            if client_response_routes.request_id == request_routes_id {
+                return match client_response_routes.result {
+                    ResponseRoutesResult::Success(multi_routes) => Ok(multi_routes),
+                    ResponseRoutesResult::Failure => Err(AppRoutesError::RoutesUnavailable),
+                };
+            }
+        }
+    }
+    Err(AppRoutesError::ConnectionClosed)
+}
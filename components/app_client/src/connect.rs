@@ -4,7 +4,10 @@ use futures::{SinkExt, StreamExt};
 
 use common::conn::{ConnPair, ConnPairVec, FutTransform};
 
-use proto::app_server::messages::{AppPermissions, AppServerToApp, AppToAppServer, NodeReport};
+use proto::app_server::messages::{
+    AppHello, AppPermissions, AppServerToApp, AppSubscription, AppToAppServer, NodeReport,
+    ServerHello,
+};
 use proto::crypto::PublicKey;
 use proto::net::messages::NetAddress;
 use proto::proto_ser::{ProtoDeserialize, ProtoSerialize};
@@ -19,6 +22,9 @@ pub enum SetupConnectionError {
     EncryptSetupError,
     RecvAppPermissionsError,
     DeserializeAppPermissionsError,
+    RecvServerHelloError,
+    DeserializeServerHelloError,
+    SendAppHelloError,
     RecvNodeReportError,
     DeserializeNodeReportError,
     ClosedBeforeNodeReport,
@@ -28,8 +34,9 @@ pub enum SetupConnectionError {
 /// Connect to an offst-node
 async fn setup_connection<S>(
     conn_pair: ConnPairVec,
+    app_hello: AppHello,
     spawner: S,
-) -> Result<AppConnTuple, SetupConnectionError>
+) -> Result<(ServerHello, AppConnTuple), SetupConnectionError>
 where
     S: Spawn + Clone + Send + 'static,
 {
@@ -43,6 +50,20 @@ where
     let app_permissions = AppPermissions::proto_deserialize(&app_permissions_data)
         .map_err(|_| SetupConnectionError::DeserializeAppPermissionsError)?;
 
+    // Get ServerHello:
+    let server_hello_data = receiver
+        .next()
+        .await
+        .ok_or(SetupConnectionError::RecvServerHelloError)?;
+    let server_hello = ServerHello::proto_deserialize(&server_hello_data)
+        .map_err(|_| SetupConnectionError::DeserializeServerHelloError)?;
+
+    // Tell the node what we want to receive:
+    sender
+        .send(app_hello.proto_serialize())
+        .await
+        .map_err(|_| SetupConnectionError::SendAppHelloError)?;
+
     // Get NodeReport:
     let node_report_data = receiver
         .next()
@@ -83,9 +104,12 @@ where
     });
 
     Ok((
-        app_permissions,
-        node_report,
-        ConnPair::from_raw(user_sender, user_receiver),
+        server_hello,
+        (
+            app_permissions,
+            node_report,
+            ConnPair::from_raw(user_sender, user_receiver),
+        ),
     ))
 }
 
@@ -96,13 +120,17 @@ pub enum AppConnectError {
     SetupConnectionError(SetupConnectionError),
 }
 
-/// Connect to an offst node as an app
-pub async fn app_connect_to_node<C, S>(
+/// Connect to an offst node as an app.
+/// `app_hello` lists the report mutations the app wishes to receive.
+/// Returns the features supported by the node, allowing the app to avoid requests the node can
+/// not handle.
+pub async fn app_connect_to_node_with_hello<C, S>(
     mut connector: C,
     node_public_key: PublicKey,
     node_net_address: NetAddress,
+    app_hello: AppHello,
     spawner: S,
-) -> Result<AppConnTuple, AppConnectError>
+) -> Result<(ServerHello, AppConnTuple), AppConnectError>
 where
     C: FutTransform<Input = (PublicKey, NetAddress), Output = Option<ConnPairVec>>,
     S: Spawn + Send + Clone + 'static,
@@ -112,7 +140,32 @@ where
         .await
         .ok_or(AppConnectError::ConnectorError)?;
 
-    setup_connection(conn_pair, spawner.clone())
+    setup_connection(conn_pair, app_hello, spawner.clone())
         .await
         .map_err(AppConnectError::SetupConnectionError)
 }
+
+/// Connect to an offst node as an app, subscribing to all report mutations.
+pub async fn app_connect_to_node<C, S>(
+    connector: C,
+    node_public_key: PublicKey,
+    node_net_address: NetAddress,
+    spawner: S,
+) -> Result<AppConnTuple, AppConnectError>
+where
+    C: FutTransform<Input = (PublicKey, NetAddress), Output = Option<ConnPairVec>>,
+    S: Spawn + Send + Clone + 'static,
+{
+    let app_hello = AppHello {
+        subscriptions: AppSubscription::all(),
+    };
+    let (_server_hello, app_conn_tuple) = app_connect_to_node_with_hello(
+        connector,
+        node_public_key,
+        node_net_address,
+        app_hello,
+        spawner,
+    )
+    .await?;
+    Ok(app_conn_tuple)
+}
@@ -10,4 +10,4 @@
 
 mod connect;
 
-pub use self::connect::{app_connect_to_node, AppConnectError};
+pub use self::connect::{app_connect_to_node, app_connect_to_node_with_hello, AppConnectError};
@@ -0,0 +1,131 @@
+use std::collections::{HashMap, VecDeque};
+
+use proto::app_server::messages::BalanceDelta;
+use proto::crypto::PublicKey;
+use proto::funder::messages::Currency;
+use proto::report::messages::{ChannelStatusReport, FunderReport};
+
+/// Configuration for the periodic balance snapshots taken with every friend.
+#[derive(Debug, Clone)]
+pub struct BalanceHistoryConfig {
+    /// Amount of ticks between two balance snapshots. 0 disables balance snapshots.
+    pub snapshot_ticks: usize,
+    /// Maximum amount of snapshots kept for every friend.
+    /// When the limit is reached, the oldest snapshot is discarded.
+    pub max_snapshots: usize,
+}
+
+/// The balances with a friend at a certain tick
+#[derive(Debug)]
+struct BalanceSnapshot {
+    tick: u64,
+    balance_deltas: HashMap<Currency, BalanceDelta>,
+}
+
+/// Keeps a bounded history of balance snapshots for every friend.
+///
+/// History is kept in memory only, and begins when the node starts. The first snapshot taken
+/// with a friend has a zero delta. A currency missing from the previous snapshot of a friend is
+/// considered to have had a zero balance.
+#[derive(Debug)]
+pub struct BalanceHistory {
+    config: BalanceHistoryConfig,
+    current_tick: u64,
+    snapshots: HashMap<PublicKey, VecDeque<BalanceSnapshot>>,
+}
+
+impl BalanceHistory {
+    pub fn new(config: BalanceHistoryConfig) -> Self {
+        BalanceHistory {
+            config,
+            current_tick: 0,
+            snapshots: HashMap::new(),
+        }
+    }
+
+    pub fn current_tick(&self) -> u64 {
+        self.current_tick
+    }
+
+    /// A time tick has passed. Takes a snapshot of all balances in `funder_report` if it is
+    /// time to do so.
+    pub fn tick<B>(&mut self, funder_report: &FunderReport<B>) {
+        self.current_tick = self.current_tick.wrapping_add(1);
+        if self.config.snapshot_ticks == 0 || self.config.max_snapshots == 0 {
+            return;
+        }
+        if self.current_tick % (self.config.snapshot_ticks as u64) != 0 {
+            return;
+        }
+
+        // Forget removed friends:
+        self.snapshots
+            .retain(|friend_public_key, _| funder_report.friends.contains_key(friend_public_key));
+
+        for (friend_public_key, friend_report) in &funder_report.friends {
+            let channel_consistent = match &friend_report.channel_status {
+                ChannelStatusReport::Consistent(channel_consistent) => channel_consistent,
+                // There are no agreed balances while the channel is inconsistent:
+                ChannelStatusReport::Inconsistent(_) => continue,
+            };
+
+            let friend_snapshots = self
+                .snapshots
+                .entry(friend_public_key.clone())
+                .or_insert_with(VecDeque::new);
+
+            let opt_prev_snapshot = friend_snapshots.back();
+            let mut balance_deltas = HashMap::new();
+            for currency_report in &channel_consistent.currency_reports {
+                let balance = currency_report.balance.balance;
+                let delta = match opt_prev_snapshot {
+                    Some(prev_snapshot) => {
+                        let prev_balance = prev_snapshot
+                            .balance_deltas
+                            .get(&currency_report.currency)
+                            .map(|balance_delta| balance_delta.balance)
+                            .unwrap_or(0);
+                        balance.wrapping_sub(prev_balance)
+                    }
+                    None => 0,
+                };
+                balance_deltas.insert(
+                    currency_report.currency.clone(),
+                    BalanceDelta {
+                        tick: self.current_tick,
+                        balance,
+                        delta,
+                    },
+                );
+            }
+
+            friend_snapshots.push_back(BalanceSnapshot {
+                tick: self.current_tick,
+                balance_deltas,
+            });
+            while friend_snapshots.len() > self.config.max_snapshots {
+                friend_snapshots.pop_front();
+            }
+        }
+    }
+
+    /// Snapshots of the balance with `friend_public_key` in `currency`, taken between
+    /// `from_tick` and `to_tick` (inclusive), ordered by tick.
+    pub fn balance_deltas(
+        &self,
+        friend_public_key: &PublicKey,
+        currency: &Currency,
+        from_tick: u64,
+        to_tick: u64,
+    ) -> Vec<BalanceDelta> {
+        let friend_snapshots = match self.snapshots.get(friend_public_key) {
+            Some(friend_snapshots) => friend_snapshots,
+            None => return Vec::new(),
+        };
+        friend_snapshots
+            .iter()
+            .filter(|snapshot| snapshot.tick >= from_tick && snapshot.tick <= to_tick)
+            .filter_map(|snapshot| snapshot.balance_deltas.get(currency).cloned())
+            .collect()
+    }
+}
@@ -11,12 +11,24 @@
 #[macro_use]
 extern crate log;
 
+#[macro_use]
+extern crate serde;
+
+#[macro_use]
+extern crate quickcheck_derive;
+
 #[macro_use]
 extern crate common;
 
+mod balance_history;
+mod receipt_archive;
 mod server;
 
 #[cfg(test)]
 mod tests;
 
-pub use self::server::{app_server_loop, AppServerError, ConnPairServer, IncomingAppConnection};
+pub use self::balance_history::BalanceHistoryConfig;
+pub use self::receipt_archive::{PendingPayment, ReceiptArchive, ReceiptArchiveMutation};
+pub use self::server::{
+    app_server_loop, server_hello, AppServerError, ConnPairServer, IncomingAppConnection,
+};
@@ -0,0 +1,184 @@
+use common::mutable_state::MutableState;
+use common::never::Never;
+use common::ser_utils::ser_b64;
+
+use proto::app_server::messages::{
+    ArchivedInvoice, ArchivedReceipt, RequestReceipts, ResponseReceipts,
+};
+use proto::crypto::{InvoiceId, PaymentId, PublicKey};
+use proto::funder::messages::Commit;
+
+/// Maximum amount of entries (Receipts and invoices) sent in one `ResponseReceipts` message.
+/// Larger results are split over multiple messages.
+pub const MAX_ARCHIVE_ENTRIES_IN_RESPONSE: usize = 0x100;
+
+/// A payment created by this node, that did not produce a receipt yet.
+/// Kept to remember the seller of the payment, which is not part of the receipt.
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingPayment {
+    #[serde(with = "ser_b64")]
+    pub payment_id: PaymentId,
+    #[serde(with = "ser_b64")]
+    pub dest_public_key: PublicKey,
+}
+
+/// All the receipts obtained and the invoices issued by the node.
+///
+/// Entries are stamped with archive ticks. The archive ticks continue from the tick of the latest
+/// entry when the node restarts, so that the entries are always ordered by tick.
+#[derive(Arbitrary, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReceiptArchive {
+    /// Tick of the latest entry
+    pub last_tick: u64,
+    pub pending_payments: Vec<PendingPayment>,
+    pub receipts: Vec<ArchivedReceipt>,
+    pub invoices: Vec<ArchivedInvoice>,
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq)]
+pub enum ReceiptArchiveMutation {
+    AddPendingPayment(PendingPayment),
+    RemovePendingPayment(PaymentId),
+    /// Add a receipt, and remove the pending payment it belongs to
+    AddReceipt(ArchivedReceipt),
+    AddInvoice(ArchivedInvoice),
+    /// Attach a commitment to the issued invoice with the same invoice id
+    CommitInvoice(Commit),
+}
+
+impl ReceiptArchive {
+    pub fn new() -> Self {
+        ReceiptArchive::default()
+    }
+
+    /// The seller of a payment that did not produce a receipt yet
+    pub fn pending_dest(&self, payment_id: &PaymentId) -> Option<&PublicKey> {
+        self.pending_payments
+            .iter()
+            .find(|pending_payment| &pending_payment.payment_id == payment_id)
+            .map(|pending_payment| &pending_payment.dest_public_key)
+    }
+
+    pub fn invoice(&self, invoice_id: &InvoiceId) -> Option<&ArchivedInvoice> {
+        self.invoices
+            .iter()
+            .find(|archived_invoice| &archived_invoice.invoice_id == invoice_id)
+    }
+
+    /// The entries matching `request_receipts`, ordered by tick.
+    /// Always returns at least one response. Only the last response has `is_last` set.
+    pub fn query(
+        &self,
+        request_receipts: &RequestReceipts,
+        current_tick: u64,
+    ) -> Vec<ResponseReceipts> {
+        let in_range =
+            |tick: u64| tick >= request_receipts.from_tick && tick <= request_receipts.to_tick;
+        let receipts = self
+            .receipts
+            .iter()
+            .filter(|archived_receipt| in_range(archived_receipt.tick))
+            .filter(|archived_receipt| {
+                request_receipts
+                    .opt_counterparty
+                    .as_ref()
+                    .map_or(true, |counterparty| {
+                        &archived_receipt.dest_public_key == counterparty
+                    })
+            })
+            .filter(|archived_receipt| {
+                request_receipts
+                    .opt_invoice_id
+                    .as_ref()
+                    .map_or(true, |invoice_id| {
+                        &archived_receipt.receipt.invoice_id == invoice_id
+                    })
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // Issued invoices have no counterparty:
+        let invoices = if request_receipts.opt_counterparty.is_some() {
+            Vec::new()
+        } else {
+            self.invoices
+                .iter()
+                .filter(|archived_invoice| in_range(archived_invoice.tick))
+                .filter(|archived_invoice| {
+                    request_receipts
+                        .opt_invoice_id
+                        .as_ref()
+                        .map_or(true, |invoice_id| {
+                            &archived_invoice.invoice_id == invoice_id
+                        })
+                })
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        let mut chunks = Vec::new();
+        for receipts_chunk in receipts.chunks(MAX_ARCHIVE_ENTRIES_IN_RESPONSE) {
+            chunks.push((receipts_chunk.to_vec(), Vec::new()));
+        }
+        for invoices_chunk in invoices.chunks(MAX_ARCHIVE_ENTRIES_IN_RESPONSE) {
+            chunks.push((Vec::new(), invoices_chunk.to_vec()));
+        }
+        if chunks.is_empty() {
+            chunks.push((Vec::new(), Vec::new()));
+        }
+
+        let num_chunks = chunks.len();
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, (receipts, invoices))| ResponseReceipts {
+                request_id: request_receipts.request_id.clone(),
+                current_tick,
+                receipts,
+                invoices,
+                is_last: index + 1 == num_chunks,
+            })
+            .collect()
+    }
+}
+
+impl MutableState for ReceiptArchive {
+    type Mutation = ReceiptArchiveMutation;
+    type MutateError = Never;
+
+    fn mutate(&mut self, mutation: &Self::Mutation) -> Result<(), Self::MutateError> {
+        match mutation {
+            ReceiptArchiveMutation::AddPendingPayment(pending_payment) => {
+                // Remove first, to avoid duplicates:
+                self.pending_payments
+                    .retain(|cur| cur.payment_id != pending_payment.payment_id);
+                self.pending_payments.push(pending_payment.clone());
+            }
+            ReceiptArchiveMutation::RemovePendingPayment(payment_id) => {
+                self.pending_payments
+                    .retain(|pending_payment| &pending_payment.payment_id != payment_id);
+            }
+            ReceiptArchiveMutation::AddReceipt(archived_receipt) => {
+                self.pending_payments.retain(|pending_payment| {
+                    pending_payment.payment_id != archived_receipt.payment_id
+                });
+                self.last_tick = std::cmp::max(self.last_tick, archived_receipt.tick);
+                self.receipts.push(archived_receipt.clone());
+            }
+            ReceiptArchiveMutation::AddInvoice(archived_invoice) => {
+                self.last_tick = std::cmp::max(self.last_tick, archived_invoice.tick);
+                self.invoices.push(archived_invoice.clone());
+            }
+            ReceiptArchiveMutation::CommitInvoice(commit) => {
+                if let Some(archived_invoice) = self
+                    .invoices
+                    .iter_mut()
+                    .find(|archived_invoice| archived_invoice.invoice_id == commit.invoice_id)
+                {
+                    archived_invoice.opt_commit = Some(commit.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::marker::Unpin;
 
@@ -7,23 +8,36 @@ use futures::task::{Spawn, SpawnExt};
 use futures::{future, stream, Sink, SinkExt, Stream, StreamExt};
 
 use common::conn::{sink_to_sender, BoxStream, ConnPair};
+use common::mutable_state::MutableState;
 use common::select_streams::select_streams;
-// use common::mutable_state::MutableState;
-use proto::crypto::{PaymentId, Uid};
+use proto::consts::{MAX_APP_TOPIC_LEN, MAX_APP_TOPIC_SUBSCRIPTIONS};
+use proto::crypto::{PaymentId, PublicKey, Uid};
 
 use proto::funder::messages::{
-    FriendStatus, FunderControl, FunderIncomingControl, FunderOutgoingControl, RequestsStatus,
-    SetFriendCurrencyRequestsStatus, SetFriendStatus,
+    AddInvoice, Commit, CreatePayment, FriendStatus, FriendsRoute, FunderControl,
+    FunderIncomingControl, FunderOutgoingControl, PaymentStatus, RequestAlternativeRoute,
+    RequestError, RequestErrorCode, RequestsStatus, ResponseClosePayment, RetryTransaction,
+    SetFriendCurrencyRequestsStatus, SetFriendStatus, SetFunderConfig,
 };
 use proto::report::convert::funder_report_mutation_to_index_mutation;
 
 use proto::app_server::messages::{
-    AppPermissions, AppRequest, AppServerToApp, AppToAppServer, NodeReport, NodeReportMutation,
-    ReportMutations,
+    AppMessage, AppPermission, AppPermissions, AppRequest, AppServerToApp, AppSubscription,
+    AppToAppServer, AppTopic, ArchivedInvoice, ArchivedReceipt, FriendDetail, FriendDetailResult,
+    FriendsFilter, NodeFeature, NodeReport, NodeReportMutation, PermissionDenied,
+    PublishAppMessage, ReportMutations, ReportSubscription, ResponseBalanceHistory,
+    ResponseFriendDetail, ServerHello,
 };
 use proto::index_client::messages::{
-    AppServerToIndexClient, IndexClientRequest, IndexClientToAppServer,
+    AppServerToIndexClient, IndexClientRequest, IndexClientToAppServer, ResponseRoutesResult,
 };
+use proto::index_server::messages::{Edge, MultiRoute, RouteConstraints, RouteRanking};
+use proto::report::messages::{FriendReportMutation, FunderReport, FunderReportMutation};
+
+use database::DatabaseClient;
+
+use crate::balance_history::{BalanceHistory, BalanceHistoryConfig};
+use crate::receipt_archive::{PendingPayment, ReceiptArchive, ReceiptArchiveMutation};
 
 pub type ConnPairServer<B> = ConnPair<AppServerToApp<B>, AppToAppServer<B>>;
 
@@ -36,7 +50,11 @@ pub type IncomingAppConnection<B> = (
 
 #[derive(Debug)]
 pub struct IncomingAppConnection<B> {
+    /// Messages published by the app are published under topics scoped by this public key
+    pub app_public_key: PublicKey,
     pub app_permissions: AppPermissions,
+    /// Kinds of report mutations the app wishes to receive
+    pub app_subscriptions: Vec<AppSubscription>,
     // The server has to send the `NodeReport` first. Only then communication with the App becomes
     // possible.
     pub report_sender: oneshot::Sender<(NodeReport<B>, oneshot::Sender<ConnPairServer<B>>)>,
@@ -52,6 +70,7 @@ pub enum AppServerError {
     AllAppsClosed,
     ObtainConnPairError,
     SendNodeReportError,
+    DatabaseError,
 }
 
 // TODO: Possibly remove Clone annotation here?
@@ -65,23 +84,207 @@ pub enum AppServerEvent<B: Clone> {
     FromIndexClient(IndexClientToAppServer<B>),
     IndexClientClosed,
     FromApp((u128, Option<AppToAppServer<B>>)), // None means that app was closed
+    TimerTick,
 }
 
 // TODO: Possibly remove Clone annotation here?
 pub struct App<B: Clone> {
+    public_key: PublicKey,
     permissions: AppPermissions,
+    subscriptions: Vec<AppSubscription>,
+    friends: FriendsFilter,
+    /// Topics of messages published by other apps, this app wishes to receive
+    topics: HashSet<AppTopic>,
     opt_sender: Option<mpsc::Sender<AppServerToApp<B>>>,
+    /// Report mutations held back while a batch of requests from this app is handled.
+    /// They are sent together with the acknowledgement of the batch.
+    opt_held_reports: Option<Vec<ReportMutations<B>>>,
+}
+
+/// A batch of requests from an app, not all of them were acknowledged yet.
+struct PendingBatch {
+    app_id: u128,
+    pending_requests: HashSet<Uid>,
+}
+
+/// A route request issued on behalf of the funder, to retry a failed transaction.
+struct RetryRouteRequest {
+    /// request_id of the failed transaction
+    request_id: Uid,
+    dest_payment: u128,
+    max_fees: u128,
+}
+
+/// Merge consecutive report mutations messages. Mutations are kept in order, and every
+/// acknowledgement is sent together with all the mutations that precede it.
+fn merge_report_mutations<B>(
+    reports_mutations: Vec<ReportMutations<B>>,
+) -> Vec<ReportMutations<B>> {
+    let mut merged = Vec::new();
+    let mut mutations = Vec::new();
+    for report_mutations in reports_mutations {
+        mutations.extend(report_mutations.mutations);
+        if report_mutations.opt_app_request_id.is_some() {
+            merged.push(ReportMutations {
+                opt_app_request_id: report_mutations.opt_app_request_id,
+                mutations: std::mem::replace(&mut mutations, Vec::new()),
+            });
+        }
+    }
+    if !mutations.is_empty() {
+        merged.push(ReportMutations {
+            opt_app_request_id: None,
+            mutations,
+        });
+    }
+    merged
+}
+
+/// The requests of a batch, in order. Nested batches are replaced by their requests.
+fn flatten_batch<B>(app_messages: Vec<AppToAppServer<B>>) -> Vec<AppToAppServer<B>> {
+    let mut flat = Vec::new();
+    let mut stack = vec![app_messages.into_iter()];
+    while let Some(iter) = stack.last_mut() {
+        match iter.next() {
+            Some(AppToAppServer {
+                app_request: AppRequest::Batch(inner_app_messages),
+                ..
+            }) => stack.push(inner_app_messages.into_iter()),
+            Some(app_message) => flat.push(app_message),
+            None => {
+                stack.pop();
+            }
+        }
+    }
+    flat
+}
+
+/// The friend a funder report mutation is about.
+/// Returns None for mutations that do not concern a specific friend.
+fn funder_mutation_friend<B>(funder_mutation: &FunderReportMutation<B>) -> Option<&PublicKey> {
+    match funder_mutation {
+        FunderReportMutation::AddFriend(add_friend_report) => {
+            Some(&add_friend_report.friend_public_key)
+        }
+        FunderReportMutation::RemoveFriend(friend_public_key)
+        | FunderReportMutation::PkFriendReportMutation((friend_public_key, _)) => {
+            Some(friend_public_key)
+        }
+        FunderReportMutation::AddRelay(_)
+        | FunderReportMutation::RemoveRelay(_)
+        | FunderReportMutation::SetRelayLatency(_)
+        | FunderReportMutation::SetRebalanceSuggestions(_) => None,
+    }
+}
+
+/// Does a funder report mutation change the balances we have with our friends?
+fn is_balance_mutation<B>(funder_mutation: &FunderReportMutation<B>) -> bool {
+    match funder_mutation {
+        FunderReportMutation::AddFriend(_) | FunderReportMutation::RemoveFriend(_) => true,
+        FunderReportMutation::PkFriendReportMutation((_, friend_mutation)) => match friend_mutation
+        {
+            FriendReportMutation::SetChannelStatus(_) => true,
+            _ => false,
+        },
+        FunderReportMutation::AddRelay(_)
+        | FunderReportMutation::RemoveRelay(_)
+        | FunderReportMutation::SetRelayLatency(_)
+        | FunderReportMutation::SetRebalanceSuggestions(_) => false,
+    }
 }
 
 impl<B> App<B>
 where
     B: Clone,
 {
-    pub fn new(permissions: AppPermissions, sender: mpsc::Sender<AppServerToApp<B>>) -> Self {
+    pub fn new(
+        public_key: PublicKey,
+        permissions: AppPermissions,
+        subscriptions: Vec<AppSubscription>,
+        sender: mpsc::Sender<AppServerToApp<B>>,
+    ) -> Self {
         App {
+            public_key,
             permissions,
+            subscriptions,
+            friends: FriendsFilter::All,
+            topics: HashSet::new(),
             opt_sender: Some(sender),
+            opt_held_reports: None,
+        }
+    }
+
+    /// Hold back report mutations until `release_reports()` is called.
+    fn hold_reports(&mut self) {
+        if self.opt_held_reports.is_none() {
+            self.opt_held_reports = Some(Vec::new());
+        }
+    }
+
+    /// Send all the held report mutations, and stop holding report mutations.
+    async fn release_reports(&mut self) {
+        if let Some(held_reports) = self.opt_held_reports.take() {
+            for report_mutations in merge_report_mutations(held_reports) {
+                self.send(AppServerToApp::ReportMutations(report_mutations))
+                    .await;
+            }
+        }
+    }
+
+    async fn send_report_mutations(&mut self, report_mutations: ReportMutations<B>) {
+        match &mut self.opt_held_reports {
+            Some(held_reports) => held_reports.push(report_mutations),
+            None => {
+                self.send(AppServerToApp::ReportMutations(report_mutations))
+                    .await
+            }
+        }
+    }
+
+    fn set_report_subscription(&mut self, report_subscription: ReportSubscription) {
+        self.subscriptions = report_subscription.subscriptions;
+        self.friends = report_subscription.friends;
+    }
+
+    /// Start receiving messages published under `topic`.
+    /// Returns false if the topic name is too long, or the app has too many subscriptions.
+    fn subscribe_topic(&mut self, topic: AppTopic) -> bool {
+        if topic.name.len() > MAX_APP_TOPIC_LEN {
+            return false;
+        }
+        if self.topics.len() >= MAX_APP_TOPIC_SUBSCRIPTIONS && !self.topics.contains(&topic) {
+            return false;
         }
+        self.topics.insert(topic);
+        true
+    }
+
+    fn unsubscribe_topic(&mut self, topic: &AppTopic) {
+        self.topics.remove(topic);
+    }
+
+    /// Is the app interested in this report mutation?
+    fn is_subscribed(&self, mutation: &NodeReportMutation<B>) -> bool {
+        let funder_mutation = match mutation {
+            NodeReportMutation::Funder(funder_mutation) => funder_mutation,
+            NodeReportMutation::IndexClient(_) => {
+                return self
+                    .subscriptions
+                    .contains(&AppSubscription::IndexClientReport)
+            }
+        };
+
+        if let Some(friend_public_key) = funder_mutation_friend(funder_mutation) {
+            if !self.friends.contains(friend_public_key) {
+                return false;
+            }
+        }
+
+        self.subscriptions.contains(&AppSubscription::FunderReport)
+            || (self
+                .subscriptions
+                .contains(&AppSubscription::FriendBalances)
+                && is_balance_mutation(funder_mutation))
     }
 
     pub async fn send(&mut self, message: AppServerToApp<B>) {
@@ -93,11 +296,43 @@ where
     }
 }
 
+/// The hello message sent to newly connected apps.
+/// Lists the optional features supported by this node.
+pub fn server_hello() -> ServerHello {
+    ServerHello {
+        features: vec![
+            NodeFeature::RequestRoutes,
+            NodeFeature::RequestExposure,
+            NodeFeature::SetNodeConfig,
+            NodeFeature::PermissionDenied,
+            NodeFeature::FriendProposals,
+            NodeFeature::RequestFriendDetail,
+            NodeFeature::ReportSubscription,
+            NodeFeature::ExportChannelProof,
+            NodeFeature::Batch,
+            NodeFeature::RotateKey,
+            NodeFeature::RequestBalanceHistory,
+            NodeFeature::CurrencyExchange,
+            NodeFeature::AppMessages,
+            NodeFeature::RequestReceipts,
+            NodeFeature::QuotePayment,
+            NodeFeature::RequestError,
+        ],
+    }
+}
+
 pub struct AppServer<B: Clone, TF, TIC, S> {
     to_funder: TF,
     to_index_client: TIC,
     from_app_sender: mpsc::Sender<(u128, Option<AppToAppServer<B>>)>,
     node_report: NodeReport<B>,
+    /// Periodic snapshots of the balances with our friends
+    balance_history: BalanceHistory,
+    /// Receipts obtained and invoices issued by the node
+    receipt_archive: ReceiptArchive,
+    /// The current archive tick. Continues from the latest archived entry after a restart.
+    archive_tick: u64,
+    db_client: DatabaseClient<ReceiptArchiveMutation>,
     incoming_connections_closed: bool,
     /// A long cyclic incrementing counter,
     /// allows to give every connection a unique number.
@@ -109,42 +344,130 @@ pub struct AppServer<B: Clone, TF, TIC, S> {
     route_requests: HashMap<Uid, u128>,
     close_payment_requests: HashMap<PaymentId, u128>,
     transactions: HashMap<Uid, u128>,
+    exposure_requests: HashMap<Uid, u128>,
+    channel_proof_requests: HashMap<Uid, u128>,
+    quote_requests: HashMap<Uid, u128>,
+    /// Requests sent to the funder that were not acknowledged yet, by their app request id.
+    /// Allows us to report errors of the funder to the app that issued the request.
+    funder_requests: HashMap<Uid, u128>,
+    /// Requests sent to the index client that were not acknowledged yet, by their app request id.
+    index_client_requests: HashMap<Uid, u128>,
+    /// Route requests issued on behalf of the funder, to retry failed transactions.
+    /// Maps the request_id of the route request to the failed transaction.
+    retry_route_requests: HashMap<Uid, RetryRouteRequest>,
+    /// Batches of requests that were not fully acknowledged yet, by the request id of the batch.
+    batches: HashMap<Uid, PendingBatch>,
+    /// Maps the request id of every request inside a pending batch to the request id of the batch.
+    batched_requests: HashMap<Uid, Uid>,
     spawner: S,
 }
 
-/// Check if we should process an app_request from an app with certain permissions
-fn check_request_permissions<B>(
-    app_permissions: &AppPermissions,
-    app_request: &AppRequest<B>,
-) -> bool {
-    match app_request {
-        AppRequest::AddRelay(_) => app_permissions.config,
-        AppRequest::RemoveRelay(_) => app_permissions.config,
-        AppRequest::CreatePayment(_) => app_permissions.buyer,
-        AppRequest::CreateTransaction(_) => app_permissions.buyer,
-        AppRequest::RequestClosePayment(_) => app_permissions.buyer,
-        AppRequest::AckClosePayment(_) => app_permissions.buyer,
-
-        AppRequest::AddInvoice(_) => app_permissions.seller,
-        AppRequest::CancelInvoice(_) => app_permissions.seller,
-        AppRequest::CommitInvoice(_) => app_permissions.seller,
-
-        AppRequest::AddFriend(_) => app_permissions.config,
-        AppRequest::SetFriendRelays(_) => app_permissions.config,
-        AppRequest::SetFriendName(_) => app_permissions.config,
-        AppRequest::RemoveFriend(_) => app_permissions.config,
-        AppRequest::EnableFriend(_) => app_permissions.config,
-        AppRequest::DisableFriend(_) => app_permissions.config,
-        AppRequest::OpenFriendCurrency(_) => app_permissions.config,
-        AppRequest::CloseFriendCurrency(_) => app_permissions.config,
-        AppRequest::SetFriendCurrencyMaxDebt(_) => app_permissions.config,
-        AppRequest::SetFriendCurrencyRate(_) => app_permissions.config,
-        AppRequest::RemoveFriendCurrency(_) => app_permissions.config,
-        AppRequest::ResetFriendChannel(_) => app_permissions.config,
-        AppRequest::RequestRoutes(_) => app_permissions.routes,
-        AppRequest::AddIndexServer(_) => app_permissions.config,
-        AppRequest::RemoveIndexServer(_) => app_permissions.config,
-    }
+/// Pick the cheapest route with enough capacity to retry a failed transaction.
+/// Returns the route together with the fees to pay along it.
+fn select_retry_route(
+    multi_routes: &[MultiRoute],
+    dest_payment: u128,
+    max_fees: u128,
+) -> Option<(FriendsRoute, u128)> {
+    multi_routes
+        .iter()
+        .flat_map(|multi_route| multi_route.routes.iter())
+        .filter_map(|route_capacity_rate| {
+            let fees = route_capacity_rate.rate.calc_fee(dest_payment)?;
+            if fees > max_fees || dest_payment.checked_add(fees)? > route_capacity_rate.capacity {
+                return None;
+            }
+            Some((route_capacity_rate.route.clone(), fees))
+        })
+        .min_by_key(|(_route, fees)| *fees)
+}
+
+/// The permissions an app must have in order to issue `app_request`
+fn required_permissions<B>(app_request: &AppRequest<B>) -> Vec<AppPermission> {
+    let permission = match app_request {
+        // A batch requires the permissions of all the requests inside it:
+        AppRequest::Batch(app_messages) => {
+            return app_messages
+                .iter()
+                .flat_map(|app_message| required_permissions(&app_message.app_request))
+                .collect()
+        }
+        AppRequest::AddRelay(_) => AppPermission::Config,
+        AppRequest::RemoveRelay(_) => AppPermission::Config,
+        AppRequest::CreatePayment(_) => AppPermission::Buyer,
+        AppRequest::CreateTransaction(_) => AppPermission::Buyer,
+        AppRequest::CreateExchangeTransaction(_) => AppPermission::Buyer,
+        AppRequest::Rebalance(_) => AppPermission::Buyer,
+        AppRequest::RequestClosePayment(_) => AppPermission::Buyer,
+        AppRequest::AckClosePayment(_) => AppPermission::Buyer,
+        AppRequest::QuotePayment(_) => AppPermission::Buyer,
+
+        AppRequest::AddInvoice(_) => AppPermission::Seller,
+        AppRequest::CancelInvoice(_) => AppPermission::Seller,
+        AppRequest::CommitInvoice(_) => AppPermission::Seller,
+
+        AppRequest::AddFriend(_) => AppPermission::Config,
+        AppRequest::SetFriendRelays(_) => AppPermission::Config,
+        AppRequest::SetFriendName(_) => AppPermission::Config,
+        AppRequest::RemoveFriend(_) => AppPermission::Config,
+        AppRequest::EnableFriend(_) => AppPermission::Config,
+        AppRequest::DisableFriend(_) => AppPermission::Config,
+        AppRequest::OpenFriendCurrency(_) => AppPermission::Config,
+        AppRequest::CloseFriendCurrency(_) => AppPermission::Config,
+        AppRequest::SetFriendCurrencyMaxDebt(_) => AppPermission::Config,
+        AppRequest::SetFriendMaxOutflow(_) => AppPermission::Config,
+        AppRequest::SetFriendCurrencyRate(_) => AppPermission::Config,
+        AppRequest::SetExchangeRate(_) => AppPermission::Config,
+        AppRequest::RemoveFriendCurrency(_) => AppPermission::Config,
+        AppRequest::ResetFriendChannel(_) => AppPermission::Config,
+        AppRequest::DrainFriend(_) => AppPermission::Config,
+        AppRequest::RequestRoutes(_) => AppPermission::Routes,
+        AppRequest::AddIndexServer(_) => AppPermission::Config,
+        AppRequest::RemoveIndexServer(_) => AppPermission::Config,
+        AppRequest::SetNodeConfig(_) => AppPermission::Config,
+        AppRequest::RequestExposure(_) => AppPermission::Reports,
+        AppRequest::SendFriendProposal(_) => AppPermission::Config,
+        AppRequest::RemoveFriendProposal(_) => AppPermission::Config,
+        AppRequest::RequestFriendDetail(_) => AppPermission::Reports,
+        AppRequest::SetReportSubscription(_) => AppPermission::Reports,
+        AppRequest::ExportChannelProof(_) => AppPermission::Reports,
+        AppRequest::RotateKey(_) => AppPermission::Config,
+        AppRequest::RequestBalanceHistory(_) => AppPermission::Reports,
+        AppRequest::RequestReceipts(_) => AppPermission::Reports,
+        AppRequest::PublishAppMessage(_) => AppPermission::Publish,
+        // Any app may receive messages published by other apps:
+        AppRequest::SubscribeAppTopic(_) | AppRequest::UnsubscribeAppTopic(_) => return Vec::new(),
+    };
+    vec![permission]
+}
+
+/// The current state of one friend, as seen in the funder report
+fn friend_detail<B>(
+    funder_report: &FunderReport<B>,
+    friend_public_key: &PublicKey,
+) -> FriendDetailResult<B>
+where
+    B: Clone,
+{
+    let friend_report = match funder_report.friends.get(friend_public_key) {
+        Some(friend_report) => friend_report,
+        None => return FriendDetailResult::NotFound,
+    };
+    let relay_latencies = funder_report
+        .relay_latencies
+        .iter()
+        .filter(|relay_latency| {
+            friend_report
+                .remote_relays
+                .iter()
+                .any(|relay_address| relay_address.public_key == relay_latency.public_key)
+        })
+        .cloned()
+        .collect();
+    FriendDetailResult::Found(FriendDetail {
+        friend_report: friend_report.clone(),
+        relay_latencies,
+    })
 }
 
 impl<B, TF, TIC, S> AppServer<B, TF, TIC, S>
@@ -159,6 +482,9 @@ where
         to_index_client: TIC,
         from_app_sender: mpsc::Sender<(u128, Option<AppToAppServer<B>>)>,
         node_report: NodeReport<B>,
+        balance_history_config: BalanceHistoryConfig,
+        receipt_archive: ReceiptArchive,
+        db_client: DatabaseClient<ReceiptArchiveMutation>,
         spawner: S,
     ) -> Self {
         AppServer {
@@ -166,12 +492,24 @@ where
             to_index_client,
             from_app_sender,
             node_report,
+            balance_history: BalanceHistory::new(balance_history_config),
+            archive_tick: receipt_archive.last_tick,
+            receipt_archive,
+            db_client,
             incoming_connections_closed: false,
             app_counter: 0,
             apps: HashMap::new(),
             route_requests: HashMap::new(),
             close_payment_requests: HashMap::new(),
             transactions: HashMap::new(),
+            exposure_requests: HashMap::new(),
+            channel_proof_requests: HashMap::new(),
+            quote_requests: HashMap::new(),
+            funder_requests: HashMap::new(),
+            index_client_requests: HashMap::new(),
+            retry_route_requests: HashMap::new(),
+            batches: HashMap::new(),
+            batched_requests: HashMap::new(),
             spawner,
         }
     }
@@ -182,7 +520,9 @@ where
         incoming_app_connection: IncomingAppConnection<B>,
     ) -> Result<(), AppServerError> {
         let IncomingAppConnection {
+            app_public_key,
             app_permissions,
+            app_subscriptions,
             report_sender,
         } = incoming_app_connection;
 
@@ -214,7 +554,7 @@ where
             .map_err(|_| AppServerError::SpawnError)?;
 
         let sender = sink_to_sender(sender, &self.spawner);
-        let app = App::new(app_permissions, sender);
+        let app = App::new(app_public_key, app_permissions, app_subscriptions, sender);
 
         self.apps.insert(self.app_counter, app);
         self.app_counter = self.app_counter.wrapping_add(1);
@@ -232,15 +572,194 @@ where
         Ok(())
     }
 
-    /// Send node report mutations to all connected apps
-    pub async fn broadcast_node_report_mutations(&mut self, report_mutations: ReportMutations<B>) {
-        // Send node report mutations to all connected apps
-        for app in &mut self.apps.values_mut() {
-            app.send(AppServerToApp::ReportMutations(report_mutations.clone()))
+    pub fn handle_timer_tick(&mut self) {
+        self.balance_history.tick(&self.node_report.funder_report);
+        self.archive_tick = self.archive_tick.wrapping_add(1);
+    }
+
+    /// Apply a mutation to the receipt archive, and save it to the database
+    async fn mutate_archive(
+        &mut self,
+        mutation: ReceiptArchiveMutation,
+    ) -> Result<(), AppServerError> {
+        self.db_client
+            .mutate(vec![mutation.clone()])
+            .await
+            .map_err(|_| AppServerError::DatabaseError)?;
+        let _ = self.receipt_archive.mutate(&mutation);
+        Ok(())
+    }
+
+    /// Remember the seller of a new payment, to archive it together with the receipt
+    async fn archive_create_payment(
+        &mut self,
+        create_payment: &CreatePayment,
+    ) -> Result<(), AppServerError> {
+        let pending_payment = PendingPayment {
+            payment_id: create_payment.payment_id.clone(),
+            dest_public_key: create_payment.dest_public_key.clone(),
+        };
+        self.mutate_archive(ReceiptArchiveMutation::AddPendingPayment(pending_payment))
+            .await
+    }
+
+    /// Archive the receipt of a successful payment. A receipt is only archived once, even if it is
+    /// sent again for repeated `RequestClosePayment` requests.
+    async fn archive_close_payment(
+        &mut self,
+        response_close_payment: &ResponseClosePayment,
+    ) -> Result<(), AppServerError> {
+        let payment_id = &response_close_payment.payment_id;
+        let dest_public_key = match self.receipt_archive.pending_dest(payment_id) {
+            Some(dest_public_key) => dest_public_key.clone(),
+            None => return Ok(()),
+        };
+        let mutation = match &response_close_payment.status {
+            PaymentStatus::Success(payment_status_success) => {
+                ReceiptArchiveMutation::AddReceipt(ArchivedReceipt {
+                    tick: self.archive_tick,
+                    payment_id: payment_id.clone(),
+                    dest_public_key,
+                    receipt: payment_status_success.receipt.clone(),
+                })
+            }
+            PaymentStatus::PaymentNotFound | PaymentStatus::Canceled(_) => {
+                ReceiptArchiveMutation::RemovePendingPayment(payment_id.clone())
+            }
+        };
+        self.mutate_archive(mutation).await
+    }
+
+    async fn archive_add_invoice(
+        &mut self,
+        add_invoice: &AddInvoice,
+    ) -> Result<(), AppServerError> {
+        if self
+            .receipt_archive
+            .invoice(&add_invoice.invoice_id)
+            .is_some()
+        {
+            // The funder refuses invoices with an existing invoice id:
+            return Ok(());
+        }
+        let archived_invoice = ArchivedInvoice {
+            tick: self.archive_tick,
+            invoice_id: add_invoice.invoice_id.clone(),
+            currency: add_invoice.currency.clone(),
+            total_dest_payment: add_invoice.total_dest_payment,
+            opt_commit: None,
+        };
+        self.mutate_archive(ReceiptArchiveMutation::AddInvoice(archived_invoice))
+            .await
+    }
+
+    async fn archive_commit_invoice(&mut self, commit: &Commit) -> Result<(), AppServerError> {
+        match self.receipt_archive.invoice(&commit.invoice_id) {
+            Some(archived_invoice) if archived_invoice.opt_commit.is_none() => {}
+            _ => return Ok(()),
+        }
+        self.mutate_archive(ReceiptArchiveMutation::CommitInvoice(commit.clone()))
+            .await
+    }
+
+    /// Send node report mutations to all connected apps.
+    /// Every app only gets the kinds of mutations it has subscribed to.
+    /// Apps without the reports permission only get acknowledgements for their requests.
+    /// The acknowledgement (`opt_app_request_id`) is only sent to the app that issued the request
+    /// (`opt_app_id`). A request that is part of a batch is not acknowledged to its app. The batch
+    /// is acknowledged instead, once all of its requests were acknowledged.
+    pub async fn broadcast_node_report_mutations(
+        &mut self,
+        report_mutations: ReportMutations<B>,
+        opt_app_id: Option<u128>,
+    ) {
+        let opt_batched = report_mutations
+            .opt_app_request_id
+            .as_ref()
+            .and_then(|app_request_id| {
+                let batch_request_id = self.batched_requests.remove(app_request_id)?;
+                let app_id = self.batches.get(&batch_request_id)?.app_id;
+                Some((app_request_id.clone(), batch_request_id, app_id))
+            });
+
+        for (app_id, app) in &mut self.apps {
+            let mutations = if app.permissions.reports {
+                report_mutations
+                    .mutations
+                    .iter()
+                    .filter(|mutation| app.is_subscribed(mutation))
+                    .cloned()
+                    .collect()
+            } else {
+                Vec::new()
+            };
+
+            // Requests inside a batch are acknowledged together with the batch:
+            let opt_app_request_id = match (&opt_batched, opt_app_id) {
+                (None, Some(request_app_id)) if request_app_id == *app_id => {
+                    report_mutations.opt_app_request_id.clone()
+                }
+                _ => None,
+            };
+
+            if mutations.is_empty() && opt_app_request_id.is_none() {
+                continue;
+            }
+
+            app.send_report_mutations(ReportMutations {
+                opt_app_request_id,
+                mutations,
+            })
+            .await;
+        }
+
+        if let Some((app_request_id, batch_request_id, _)) = opt_batched {
+            self.complete_batched_request(&app_request_id, &batch_request_id)
                 .await;
         }
     }
 
+    /// Mark a request of a batch as handled.
+    /// Once all the requests of the batch were handled, the batch is acknowledged.
+    async fn complete_batched_request(&mut self, app_request_id: &Uid, batch_request_id: &Uid) {
+        let batch = match self.batches.get_mut(batch_request_id) {
+            Some(batch) => batch,
+            None => return,
+        };
+        batch.pending_requests.remove(app_request_id);
+        if !batch.pending_requests.is_empty() {
+            return;
+        }
+
+        let app_id = batch.app_id;
+        self.batches.remove(batch_request_id);
+        let is_app_batch_pending = self.batches.values().any(|batch| batch.app_id == app_id);
+
+        if let Some(app) = self.apps.get_mut(&app_id) {
+            app.send_report_mutations(ReportMutations {
+                opt_app_request_id: Some(batch_request_id.clone()),
+                mutations: Vec::new(),
+            })
+            .await;
+            if !is_app_batch_pending {
+                app.release_reports().await;
+            }
+        }
+    }
+
+    /// Mark a request that is handled by the app server itself as handled, if it is part of a
+    /// batch. Returns false if the request is not part of a batch.
+    async fn complete_if_batched(&mut self, app_request_id: &Uid) -> bool {
+        match self.batched_requests.remove(app_request_id) {
+            Some(batch_request_id) => {
+                self.complete_batched_request(app_request_id, &batch_request_id)
+                    .await;
+                true
+            }
+            None => false,
+        }
+    }
+
     pub async fn handle_from_funder(
         &mut self,
         funder_message: FunderOutgoingControl<B>,
@@ -264,6 +783,7 @@ where
                 }
             }
             FunderOutgoingControl::ResponseClosePayment(response_close_payment) => {
+                self.archive_close_payment(&response_close_payment).await?;
                 // Find the app that issued the request, and forward the response to this app:
                 let app_id = if let Some(app_id) = self
                     .close_payment_requests
@@ -281,7 +801,71 @@ where
                     .await;
                 }
             }
+            FunderOutgoingControl::ResponseExposure(response_exposure) => {
+                // Find the app that issued the request, and forward the response to this app:
+                let app_id = if let Some(app_id) =
+                    self.exposure_requests.remove(&response_exposure.request_id)
+                {
+                    app_id
+                } else {
+                    warn!("ResponseExposure: Could not find app that initiated RequestExposure");
+                    return Ok(());
+                };
+                if let Some(app) = self.apps.get_mut(&app_id) {
+                    app.send(AppServerToApp::ResponseExposure(response_exposure))
+                        .await;
+                }
+            }
+            FunderOutgoingControl::ResponseChannelProof(response_channel_proof) => {
+                // Find the app that issued the request, and forward the response to this app:
+                let app_id = if let Some(app_id) = self
+                    .channel_proof_requests
+                    .remove(&response_channel_proof.request_id)
+                {
+                    app_id
+                } else {
+                    warn!("ResponseChannelProof: Could not find app that initiated ExportChannelProof");
+                    return Ok(());
+                };
+                if let Some(app) = self.apps.get_mut(&app_id) {
+                    app.send(AppServerToApp::ResponseChannelProof(response_channel_proof))
+                        .await;
+                }
+            }
+            FunderOutgoingControl::ResponseQuotePayment(response_quote_payment) => {
+                // Find the app that issued the request, and forward the response to this app:
+                let app_id = if let Some(app_id) = self
+                    .quote_requests
+                    .remove(&response_quote_payment.request_id)
+                {
+                    app_id
+                } else {
+                    warn!("ResponseQuotePayment: Could not find app that initiated QuotePayment");
+                    return Ok(());
+                };
+                if let Some(app) = self.apps.get_mut(&app_id) {
+                    app.send(AppServerToApp::ResponseQuotePayment(response_quote_payment))
+                        .await;
+                }
+            }
+            FunderOutgoingControl::RequestError(request_error) => {
+                // Find the app that issued the request, and forward the error to this app:
+                let app_id =
+                    if let Some(app_id) = self.funder_requests.get(&request_error.app_request_id) {
+                        *app_id
+                    } else {
+                        warn!("RequestError: Could not find app that initiated the request");
+                        return Ok(());
+                    };
+                self.send_request_error(app_id, request_error).await;
+            }
             FunderOutgoingControl::ReportMutations(funder_report_mutations) => {
+                // The request was acknowledged, no more errors may arrive for it:
+                let opt_app_id = funder_report_mutations
+                    .opt_app_request_id
+                    .as_ref()
+                    .and_then(|app_request_id| self.funder_requests.remove(app_request_id));
+
                 let mut index_mutations = Vec::new();
                 for funder_report_mutation in &funder_report_mutations.mutations {
                     // Transform the funder report mutation to index mutations
@@ -313,18 +897,80 @@ where
                     report_mutations.mutations.push(mutation);
                 }
 
-                self.broadcast_node_report_mutations(report_mutations).await;
+                self.broadcast_node_report_mutations(report_mutations, opt_app_id)
+                    .await;
+            }
+            FunderOutgoingControl::RequestAlternativeRoute(request_alternative_route) => {
+                self.handle_request_alternative_route(request_alternative_route)
+                    .await?;
             }
         }
         Ok(())
     }
 
+    /// The funder wants to retry a failed transaction.
+    /// We ask the index client for an alternative route that avoids the failing friend.
+    async fn handle_request_alternative_route(
+        &mut self,
+        request_alternative_route: RequestAlternativeRoute,
+    ) -> Result<(), AppServerError> {
+        let RequestAlternativeRoute {
+            request_id,
+            routes_request_id,
+            currency,
+            dest_public_key,
+            dest_payment,
+            max_fees,
+            exclude_friend,
+        } = request_alternative_route;
+
+        let local_public_key = self.node_report.funder_report.local_public_key.clone();
+        let request_routes = proto::index_server::messages::RequestRoutes {
+            request_id: routes_request_id.clone(),
+            currency,
+            capacity: dest_payment,
+            source: local_public_key.clone(),
+            destination: dest_public_key,
+            opt_exclude: Some(Edge {
+                from_public_key: local_public_key,
+                to_public_key: exclude_friend,
+            }),
+            constraints: RouteConstraints::default(),
+            ranking: RouteRanking::default(),
+        };
+
+        let retry_route_request = RetryRouteRequest {
+            request_id,
+            dest_payment,
+            max_fees,
+        };
+        if self
+            .retry_route_requests
+            .insert(routes_request_id.clone(), retry_route_request)
+            .is_some()
+        {
+            warn!("RequestAlternativeRoute: request_id clash.");
+        }
+
+        self.to_index_client
+            .send(AppServerToIndexClient::AppRequest((
+                routes_request_id,
+                IndexClientRequest::RequestRoutes(request_routes),
+            )))
+            .await
+            .map_err(|_| AppServerError::SendToIndexClientError)
+    }
+
     pub async fn handle_from_index_client(
         &mut self,
         index_client_message: IndexClientToAppServer<B>,
     ) -> Result<(), AppServerError> {
         match index_client_message {
             IndexClientToAppServer::ReportMutations(index_client_report_mutations) => {
+                let opt_app_id = index_client_report_mutations
+                    .opt_app_request_id
+                    .as_ref()
+                    .and_then(|app_request_id| self.index_client_requests.remove(app_request_id));
                 let mut report_mutations = ReportMutations {
                     opt_app_request_id: index_client_report_mutations.opt_app_request_id,
                     mutations: Vec::new(),
@@ -336,9 +982,38 @@ where
                     report_mutations.mutations.push(mutation);
                 }
 
-                self.broadcast_node_report_mutations(report_mutations).await;
+                self.broadcast_node_report_mutations(report_mutations, opt_app_id)
+                    .await;
             }
             IndexClientToAppServer::ResponseRoutes(client_response_routes) => {
+                // Check if this is a route we requested on behalf of the funder:
+                if let Some(retry_route_request) = self
+                    .retry_route_requests
+                    .remove(&client_response_routes.request_id)
+                {
+                    let opt_route = match &client_response_routes.result {
+                        ResponseRoutesResult::Success(multi_routes) => select_retry_route(
+                            multi_routes,
+                            retry_route_request.dest_payment,
+                            retry_route_request.max_fees,
+                        ),
+                        ResponseRoutesResult::Failure => None,
+                    };
+                    let request_id = retry_route_request.request_id;
+                    let retry_transaction = RetryTransaction {
+                        request_id: request_id.clone(),
+                        opt_route,
+                    };
+                    return self
+                        .to_funder
+                        .send(FunderIncomingControl::new(
+                            request_id,
+                            FunderControl::RetryTransaction(retry_transaction),
+                        ))
+                        .await
+                        .map_err(|_| AppServerError::SendToFunderError);
+                }
+
                 // We search for the app that issued the request, and send it the response.
                 let app_id = if let Some(app_id) = self
                     .route_requests
@@ -373,6 +1048,11 @@ where
                 // Remove the application. We assert that this application exists
                 // in our apps map:
                 self.apps.remove(&app_id).unwrap();
+                // Forget the pending batches of the application:
+                self.batches.retain(|_, batch| batch.app_id != app_id);
+                let batches = &self.batches;
+                self.batched_requests
+                    .retain(|_, batch_request_id| batches.contains_key(batch_request_id));
                 if self.apps.is_empty() && self.incoming_connections_closed {
                     return Err(AppServerError::AllAppsClosed);
                 }
@@ -382,9 +1062,15 @@ where
         }
     }
 
-    fn check_app_permissions(&self, app_id: u128, app_message: &AppToAppServer<B>) -> bool {
+    /// Make sure that an app has the permission to issue a request.
+    /// If not, a PermissionDenied message is sent to the app.
+    async fn check_app_permissions(
+        &mut self,
+        app_id: u128,
+        app_message: &AppToAppServer<B>,
+    ) -> bool {
         // Get the relevant application:
-        let app = match self.apps.get(&app_id) {
+        let app = match self.apps.get_mut(&app_id) {
             Some(app) => app,
             None => {
                 warn!("App {:?} does not exist!", app_id);
@@ -393,27 +1079,38 @@ where
         };
 
         // Make sure this message is allowed for this application:
-        if !check_request_permissions(&app.permissions, &app_message.app_request) {
+        let opt_missing_permission = required_permissions(&app_message.app_request)
+            .into_iter()
+            .find(|permission| !app.permissions.contains(permission));
+        if let Some(permission) = opt_missing_permission {
             warn!(
                 "App {:?} does not have permissions for {:?}",
                 app_id, app_message
             );
+            let permission_denied = PermissionDenied {
+                app_request_id: app_message.app_request_id.clone(),
+                permission,
+            };
+            app.send(AppServerToApp::PermissionDenied(permission_denied))
+                .await;
+            app.send(AppServerToApp::RequestError(RequestError {
+                app_request_id: app_message.app_request_id.clone(),
+                error_code: RequestErrorCode::Unauthorized,
+                detail: format!("Missing permission {:?}", permission),
+            }))
+            .await;
             return false;
         }
 
         true
     }
 
-    // Clippy doesn't like `match {}` blocks with that many arms
-    #[allow(clippy::cognitive_complexity)]
     async fn handle_app_message(
         &mut self,
         app_id: u128,
         app_message: AppToAppServer<B>,
     ) -> Result<(), AppServerError> {
-        if !self.check_app_permissions(app_id, &app_message) {
-            // Eliminate application's connection:
-            self.apps.remove(&app_id);
+        if !self.check_app_permissions(app_id, &app_message).await {
             return Ok(());
         }
 
@@ -422,9 +1119,127 @@ where
             app_request_id,
         } = app_message;
 
+        match app_request {
+            AppRequest::Batch(app_messages) => {
+                self.handle_batch(app_id, app_request_id, app_messages)
+                    .await
+            }
+            app_request => {
+                self.handle_app_request(app_id, app_request_id, app_request)
+                    .await
+            }
+        }
+    }
+
+    /// Handle the requests of a batch one by one.
+    /// The batch is acknowledged once all of its requests were acknowledged. Until then, report
+    /// mutations sent to the app are held back, and then sent together with the acknowledgement.
+    async fn handle_batch(
+        &mut self,
+        app_id: u128,
+        batch_request_id: Uid,
+        app_messages: Vec<AppToAppServer<B>>,
+    ) -> Result<(), AppServerError> {
+        let app_messages = flatten_batch(app_messages);
+
+        let mut pending_requests = HashSet::new();
+        for app_message in &app_messages {
+            if !pending_requests.insert(app_message.app_request_id.clone())
+                || self
+                    .batched_requests
+                    .insert(app_message.app_request_id.clone(), batch_request_id.clone())
+                    .is_some()
+            {
+                warn!("Batch: request_id clash.");
+            }
+        }
+
+        if let Some(app) = self.apps.get_mut(&app_id) {
+            app.hold_reports();
+        }
+        let batch = PendingBatch {
+            app_id,
+            pending_requests,
+        };
+        if self
+            .batches
+            .insert(batch_request_id.clone(), batch)
+            .is_some()
+        {
+            warn!("Batch: batch request_id clash.");
+        }
+
+        if app_messages.is_empty() {
+            // Nothing to wait for, acknowledge the batch immediately:
+            self.complete_batched_request(&batch_request_id, &batch_request_id)
+                .await;
+        }
+
+        for app_message in app_messages {
+            self.handle_app_request(app_id, app_message.app_request_id, app_message.app_request)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Acknowledge a request that was handled by the app server itself, without causing
+    /// any report mutations.
+    async fn ack_app_request(&mut self, app_id: u128, app_request_id: Uid) {
+        if self.complete_if_batched(&app_request_id).await {
+            return;
+        }
+        if let Some(app) = self.apps.get_mut(&app_id) {
+            app.send_report_mutations(ReportMutations {
+                opt_app_request_id: Some(app_request_id),
+                mutations: Vec::new(),
+            })
+            .await;
+        }
+    }
+
+    /// Let an app know that one of its requests could not be handled.
+    async fn send_request_error(&mut self, app_id: u128, request_error: RequestError) {
+        if let Some(app) = self.apps.get_mut(&app_id) {
+            app.send(AppServerToApp::RequestError(request_error)).await;
+        }
+    }
+
+    /// Deliver a message published by an app to all the other app connections subscribed to
+    /// its topic. Other connections of the publishing app receive the message too, if they are
+    /// subscribed.
+    async fn publish_app_message(&mut self, app_id: u128, publish_app_message: PublishAppMessage) {
+        let app_public_key = match self.apps.get(&app_id) {
+            Some(app) => app.public_key.clone(),
+            None => return,
+        };
+        let app_message = AppMessage {
+            topic: AppTopic {
+                app_public_key,
+                name: publish_app_message.topic_name,
+            },
+            data: publish_app_message.data,
+        };
+        for (other_app_id, app) in &mut self.apps {
+            if *other_app_id != app_id && app.topics.contains(&app_message.topic) {
+                app.send(AppServerToApp::AppMessage(app_message.clone()))
+                    .await;
+            }
+        }
+    }
+
+    // Clippy doesn't like `match {}` blocks with that many arms
+    #[allow(clippy::cognitive_complexity)]
+    async fn handle_app_request(
+        &mut self,
+        app_id: u128,
+        app_request_id: Uid,
+        app_request: AppRequest<B>,
+    ) -> Result<(), AppServerError> {
         macro_rules! to_funder {
             ( $x:expr ) => {{
                 use FunderControl::*;
+                // Keep track of which application issued this request:
+                self.funder_requests.insert(app_request_id.clone(), app_id);
                 self.to_funder
                     .send(FunderIncomingControl::new(app_request_id, $x))
                     .await
@@ -435,6 +1250,9 @@ where
         macro_rules! to_index_client {
             ( $x:expr ) => {{
                 use IndexClientRequest::*;
+                // Keep track of which application issued this request:
+                self.index_client_requests
+                    .insert(app_request_id.clone(), app_id);
                 self.to_index_client
                     .send(AppServerToIndexClient::AppRequest((app_request_id, $x)))
                     .await
@@ -444,10 +1262,16 @@ where
 
         use AppRequest::*;
         match app_request {
+            // Batches are flattened by `handle_batch()`:
+            Batch(_) => unreachable!(),
+
             // Requests that go to funder:
             AddRelay(x) => to_funder!(AddRelay(x)),
             RemoveRelay(x) => to_funder!(RemoveRelay(x)),
-            CreatePayment(x) => to_funder!(CreatePayment(x)),
+            CreatePayment(x) => {
+                self.archive_create_payment(&x).await?;
+                to_funder!(CreatePayment(x))
+            }
             RequestClosePayment(payment_id) => {
                 if self
                     .close_payment_requests
@@ -459,14 +1283,33 @@ where
                 to_funder!(RequestClosePayment(payment_id))
             }
             AckClosePayment(x) => to_funder!(AckClosePayment(x)),
-            AddInvoice(x) => to_funder!(AddInvoice(x)),
+            AddInvoice(x) => {
+                if x.opt_expiry_ticks == Some(0) {
+                    warn!("AddInvoice: Expiry ticks must be positive.");
+                    let request_error = RequestError {
+                        app_request_id: app_request_id.clone(),
+                        error_code: RequestErrorCode::InvalidRequest,
+                        detail: "Expiry ticks must be positive".to_owned(),
+                    };
+                    self.send_request_error(app_id, request_error).await;
+                    self.ack_app_request(app_id, app_request_id).await;
+                    return Ok(());
+                }
+                self.archive_add_invoice(&x).await?;
+                to_funder!(AddInvoice(x))
+            }
             CancelInvoice(x) => to_funder!(CancelInvoice(x)),
-            CommitInvoice(x) => to_funder!(CommitInvoice(x)),
+            CommitInvoice(x) => {
+                self.archive_commit_invoice(&x).await?;
+                to_funder!(CommitInvoice(x))
+            }
             AddFriend(x) => to_funder!(AddFriend(x)),
             SetFriendRelays(x) => to_funder!(SetFriendRelays(x)),
             SetFriendName(x) => to_funder!(SetFriendName(x)),
             SetFriendCurrencyMaxDebt(x) => to_funder!(SetFriendCurrencyMaxDebt(x)),
+            SetFriendMaxOutflow(x) => to_funder!(SetFriendMaxOutflow(x)),
             SetFriendCurrencyRate(x) => to_funder!(SetFriendCurrencyRate(x)),
+            SetExchangeRate(x) => to_funder!(SetExchangeRate(x)),
             RemoveFriendCurrency(x) => to_funder!(RemoveFriendCurrency(x)),
             ResetFriendChannel(x) => to_funder!(ResetFriendChannel(x)),
             CreateTransaction(create_transaction) => {
@@ -475,6 +1318,18 @@ where
                     .insert(create_transaction.request_id.clone(), app_id);
                 to_funder!(CreateTransaction(create_transaction))
             }
+            CreateExchangeTransaction(create_exchange_transaction) => {
+                // Keep track of which application issued this request:
+                self.transactions
+                    .insert(create_exchange_transaction.request_id.clone(), app_id);
+                to_funder!(CreateExchangeTransaction(create_exchange_transaction))
+            }
+            Rebalance(rebalance) => {
+                // Keep track of which application issued this request:
+                self.transactions
+                    .insert(rebalance.request_id.clone(), app_id);
+                to_funder!(Rebalance(rebalance))
+            }
             RemoveFriend(friend_public_key) => {
                 let remove_friend = proto::funder::messages::RemoveFriend { friend_public_key };
                 to_funder!(RemoveFriend(remove_friend))
@@ -486,6 +1341,10 @@ where
                 };
                 to_funder!(SetFriendStatus(set_friend_status))
             }
+            DrainFriend(friend_public_key) => {
+                let drain_friend = proto::funder::messages::DrainFriend { friend_public_key };
+                to_funder!(DrainFriend(drain_friend))
+            }
             DisableFriend(friend_public_key) => {
                 let set_friend_status = SetFriendStatus {
                     friend_public_key,
@@ -510,6 +1369,41 @@ where
                 to_funder!(SetFriendCurrencyRequestsStatus(set_requests_status))
             }
 
+            RequestExposure(request_id) => {
+                // Keep track of which application issued this request:
+                if self
+                    .exposure_requests
+                    .insert(request_id.clone(), app_id)
+                    .is_some()
+                {
+                    warn!("RequestExposure: request_id clash.");
+                }
+                to_funder!(RequestExposure(request_id))
+            }
+            ExportChannelProof(export_channel_proof) => {
+                // Keep track of which application issued this request:
+                if self
+                    .channel_proof_requests
+                    .insert(export_channel_proof.request_id.clone(), app_id)
+                    .is_some()
+                {
+                    warn!("ExportChannelProof: request_id clash.");
+                }
+                to_funder!(ExportChannelProof(export_channel_proof))
+            }
+            QuotePayment(quote_payment) => {
+                // Keep track of which application issued this request:
+                if self
+                    .quote_requests
+                    .insert(quote_payment.request_id.clone(), app_id)
+                    .is_some()
+                {
+                    warn!("QuotePayment: request_id clash.");
+                }
+                to_funder!(QuotePayment(quote_payment))
+            }
+            RotateKey(x) => to_funder!(RotateKey(x)),
+
             // Requests that go to index client:
             AddIndexServer(x) => to_index_client!(AddIndexServer(x)),
             RemoveIndexServer(x) => to_index_client!(RemoveIndexServer(x)),
@@ -524,17 +1418,155 @@ where
                 }
                 to_index_client!(RequestRoutes(request_routes))
             }
+            SendFriendProposal(x) => to_index_client!(SendFriendProposal(x)),
+            RemoveFriendProposal(x) => to_index_client!(RemoveFriendProposal(x)),
+
+            // Requests answered from the report kept by the app server:
+            RequestFriendDetail(friend_public_key) => {
+                let response_friend_detail = ResponseFriendDetail {
+                    result: friend_detail(&self.node_report.funder_report, &friend_public_key),
+                    friend_public_key,
+                };
+                if let Some(app) = self.apps.get_mut(&app_id) {
+                    app.send(AppServerToApp::ResponseFriendDetail(response_friend_detail))
+                        .await;
+                }
+                let _ = self.complete_if_batched(&app_request_id).await;
+                Ok(())
+            }
+            RequestBalanceHistory(request_balance_history) => {
+                let response_balance_history = ResponseBalanceHistory {
+                    current_tick: self.balance_history.current_tick(),
+                    balance_deltas: self.balance_history.balance_deltas(
+                        &request_balance_history.friend_public_key,
+                        &request_balance_history.currency,
+                        request_balance_history.from_tick,
+                        request_balance_history.to_tick,
+                    ),
+                    request_id: request_balance_history.request_id,
+                };
+                if let Some(app) = self.apps.get_mut(&app_id) {
+                    app.send(AppServerToApp::ResponseBalanceHistory(
+                        response_balance_history,
+                    ))
+                    .await;
+                }
+                let _ = self.complete_if_batched(&app_request_id).await;
+                Ok(())
+            }
+            RequestReceipts(request_receipts) => {
+                let responses_receipts = self
+                    .receipt_archive
+                    .query(&request_receipts, self.archive_tick);
+                if let Some(app) = self.apps.get_mut(&app_id) {
+                    for response_receipts in responses_receipts {
+                        app.send(AppServerToApp::ResponseReceipts(response_receipts))
+                            .await;
+                    }
+                }
+                let _ = self.complete_if_batched(&app_request_id).await;
+                Ok(())
+            }
+
+            // Requests that only affect this app connection:
+            SetReportSubscription(report_subscription) => {
+                if let Some(app) = self.apps.get_mut(&app_id) {
+                    app.set_report_subscription(report_subscription);
+                }
+                self.ack_app_request(app_id, app_request_id).await;
+                Ok(())
+            }
+            SubscribeAppTopic(app_topic) => {
+                let is_subscribed = match self.apps.get_mut(&app_id) {
+                    Some(app) => app.subscribe_topic(app_topic),
+                    None => true,
+                };
+                if !is_subscribed {
+                    warn!("SubscribeAppTopic: Invalid topic or too many subscriptions.");
+                    let request_error = RequestError {
+                        app_request_id: app_request_id.clone(),
+                        error_code: RequestErrorCode::InvalidRequest,
+                        detail: "Invalid topic or too many subscriptions".to_owned(),
+                    };
+                    self.send_request_error(app_id, request_error).await;
+                }
+                self.ack_app_request(app_id, app_request_id).await;
+                Ok(())
+            }
+            UnsubscribeAppTopic(app_topic) => {
+                if let Some(app) = self.apps.get_mut(&app_id) {
+                    app.unsubscribe_topic(&app_topic);
+                }
+                self.ack_app_request(app_id, app_request_id).await;
+                Ok(())
+            }
+
+            // Messages between apps connected to the node:
+            PublishAppMessage(publish_app_message) => {
+                if publish_app_message.topic_name.len() > MAX_APP_TOPIC_LEN {
+                    warn!("PublishAppMessage: Topic name is too long.");
+                    let request_error = RequestError {
+                        app_request_id: app_request_id.clone(),
+                        error_code: RequestErrorCode::InvalidRequest,
+                        detail: "Topic name is too long".to_owned(),
+                    };
+                    self.send_request_error(app_id, request_error).await;
+                } else {
+                    self.publish_app_message(app_id, publish_app_message).await;
+                }
+                self.ack_app_request(app_id, app_request_id).await;
+                Ok(())
+            }
+
+            // Configuration changes, sent to the component that uses the parameter:
+            SetNodeConfig(set_node_config) => {
+                let value = match &set_node_config {
+                    proto::app_server::messages::SetNodeConfig::MaxOperationsInBatch(value)
+                    | proto::app_server::messages::SetNodeConfig::MaxPendingUserRequests(value)
+                    | proto::app_server::messages::SetNodeConfig::IndexKeepaliveTicks(value) => {
+                        *value
+                    }
+                };
+                if value == 0 {
+                    warn!("SetNodeConfig: Configuration values must be positive.");
+                    let request_error = RequestError {
+                        app_request_id: app_request_id.clone(),
+                        error_code: RequestErrorCode::InvalidRequest,
+                        detail: "Configuration values must be positive".to_owned(),
+                    };
+                    self.send_request_error(app_id, request_error).await;
+                    self.ack_app_request(app_id, app_request_id).await;
+                    return Ok(());
+                }
+                let value = usize::try_from(value).unwrap_or(usize::max_value());
+
+                match set_node_config {
+                    proto::app_server::messages::SetNodeConfig::MaxOperationsInBatch(_) => {
+                        to_funder!(SetConfig(SetFunderConfig::MaxOperationsInBatch(value)))
+                    }
+                    proto::app_server::messages::SetNodeConfig::MaxPendingUserRequests(_) => {
+                        to_funder!(SetConfig(SetFunderConfig::MaxPendingUserRequests(value)))
+                    }
+                    proto::app_server::messages::SetNodeConfig::IndexKeepaliveTicks(_) => {
+                        to_index_client!(SetKeepaliveTicks(value))
+                    }
+                }
+            }
         }
     }
 }
 
-pub async fn app_server_loop<B, FF, TF, FIC, TIC, IC, S>(
+pub async fn app_server_loop<B, FF, TF, FIC, TIC, IC, TS, S>(
     from_funder: FF,
     to_funder: TF,
     from_index_client: FIC,
     to_index_client: TIC,
     incoming_connections: IC,
     initial_node_report: NodeReport<B>,
+    balance_history_config: BalanceHistoryConfig,
+    receipt_archive: ReceiptArchive,
+    db_client: DatabaseClient<ReceiptArchiveMutation>,
+    timer_stream: TS,
     spawner: S,
 ) -> Result<(), AppServerError>
 where
@@ -544,6 +1576,7 @@ where
     FIC: Stream<Item = IndexClientToAppServer<B>> + Unpin + Send,
     TIC: Sink<AppServerToIndexClient<B>> + Unpin,
     IC: Stream<Item = IncomingAppConnection<B>> + Unpin + Send,
+    TS: Stream + Unpin + Send,
     S: Spawn,
 {
     let (from_app_sender, from_app_receiver) = mpsc::channel(0);
@@ -552,6 +1585,9 @@ where
         to_index_client,
         from_app_sender,
         initial_node_report,
+        balance_history_config,
+        receipt_archive,
+        db_client,
         spawner,
     );
 
@@ -573,11 +1609,14 @@ where
             AppServerEvent::IncomingConnectionsClosed,
         )));
 
+    let timer_stream = timer_stream.map(|_| AppServerEvent::TimerTick);
+
     let mut events = select_streams![
         from_funder,
         from_index_client,
         from_app_receiver,
-        incoming_connections
+        incoming_connections,
+        timer_stream
     ];
 
     while let Some(event) = events.next().await {
@@ -605,6 +1644,7 @@ where
             AppServerEvent::FromApp((app_id, opt_app_message)) => {
                 app_server.handle_from_app(app_id, opt_app_message).await?
             }
+            AppServerEvent::TimerTick => app_server.handle_timer_tick(),
         }
     }
     Ok(())
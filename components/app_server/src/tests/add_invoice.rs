@@ -0,0 +1,123 @@
+use std::convert::TryFrom;
+
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use proto::crypto::{InvoiceId, PublicKey, Uid};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppSubscription, AppToAppServer,
+};
+use proto::funder::messages::{AddInvoice, Currency, FunderControl, RequestErrorCode};
+
+use super::utils::spawn_dummy_app_server;
+use crate::server::IncomingAppConnection;
+
+async fn task_app_server_loop_add_invoice<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        _funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+    let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+
+    let app_permissions = AppPermissions {
+        routes: false,
+        buyer: false,
+        seller: true,
+        config: false,
+        reports: true,
+        publish: false,
+    };
+
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
+        app_permissions,
+        app_subscriptions: AppSubscription::all(),
+        report_sender,
+    };
+
+    connections_sender
+        .send(incoming_app_connection)
+        .await
+        .unwrap();
+
+    let (_report, conn_sender) = report_receiver.await.unwrap();
+    conn_sender.send(server_conn_pair).unwrap();
+
+    let currency = Currency::try_from("FST".to_owned()).unwrap();
+
+    // Zero expiry ticks are rejected, and the invoice is not forwarded:
+    let add_invoice = AddInvoice {
+        invoice_id: InvoiceId::from(&[1; InvoiceId::len()]),
+        currency: currency.clone(),
+        total_dest_payment: 20,
+        opt_expiry_ticks: Some(0),
+    };
+    let app_to_app_server = AppToAppServer::new(
+        Uid::from(&[21; Uid::len()]),
+        AppRequest::AddInvoice(add_invoice),
+    );
+    app_sender.send(app_to_app_server).await.unwrap();
+
+    match app_receiver.next().await.unwrap() {
+        AppServerToApp::RequestError(request_error) => {
+            assert_eq!(request_error.app_request_id, Uid::from(&[21; Uid::len()]));
+            assert_eq!(request_error.error_code, RequestErrorCode::InvalidRequest);
+        }
+        _ => unreachable!(),
+    };
+    match app_receiver.next().await.unwrap() {
+        AppServerToApp::ReportMutations(report_mutations) => {
+            assert_eq!(
+                report_mutations.opt_app_request_id,
+                Some(Uid::from(&[21; Uid::len()]))
+            );
+            assert!(report_mutations.mutations.is_empty());
+        }
+        _ => unreachable!(),
+    };
+
+    // Positive expiry ticks are forwarded to the funder:
+    let add_invoice = AddInvoice {
+        invoice_id: InvoiceId::from(&[2; InvoiceId::len()]),
+        currency,
+        total_dest_payment: 20,
+        opt_expiry_ticks: Some(8),
+    };
+    let app_to_app_server = AppToAppServer::new(
+        Uid::from(&[22; Uid::len()]),
+        AppRequest::AddInvoice(add_invoice.clone()),
+    );
+    app_sender.send(app_to_app_server).await.unwrap();
+
+    let to_funder_message = funder_receiver.next().await.unwrap();
+    assert_eq!(
+        to_funder_message.app_request_id,
+        Uid::from(&[22; Uid::len()])
+    );
+    assert_eq!(
+        to_funder_message.funder_control,
+        FunderControl::AddInvoice(add_invoice)
+    );
+}
+
+#[test]
+fn test_app_server_loop_add_invoice() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_add_invoice(thread_pool.clone()));
+}
@@ -0,0 +1,235 @@
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use proto::crypto::{PublicKey, Uid};
+
+use proto::app_server::messages::{
+    AppMessage, AppPermission, AppPermissions, AppRequest, AppServerToApp, AppSubscription,
+    AppToAppServer, AppTopic, PublishAppMessage, ReportMutations,
+};
+use proto::consts::MAX_APP_TOPIC_LEN;
+use proto::funder::messages::RequestErrorCode;
+
+use super::utils::spawn_dummy_app_server;
+use crate::server::IncomingAppConnection;
+
+/// Verify that `to_app_message` is an empty acknowledgement of the request `app_request_id`
+fn assert_ack(to_app_message: AppServerToApp<u32>, app_request_id: Uid) {
+    match to_app_message {
+        AppServerToApp::ReportMutations(ReportMutations {
+            opt_app_request_id,
+            mutations,
+        }) => {
+            assert_eq!(opt_app_request_id, Some(app_request_id));
+            assert!(mutations.is_empty());
+        }
+        _ => unreachable!(),
+    }
+}
+
+async fn task_app_server_loop_app_messages<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        _funder_sender,
+        _funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    // A publishing app, and an app that may only listen:
+    let pk_publisher = PublicKey::from(&[0xa0; PublicKey::len()]);
+    let pk_listener = PublicKey::from(&[0xa1; PublicKey::len()]);
+
+    let mut app_senders = Vec::new();
+    let mut app_receivers = Vec::new();
+    for (app_public_key, publish) in vec![(pk_publisher.clone(), true), (pk_listener, false)] {
+        let (app_sender, app_server_receiver) = mpsc::channel(0);
+        let (app_server_sender, app_receiver) = mpsc::channel(1);
+        let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+        let app_permissions = AppPermissions {
+            routes: false,
+            buyer: false,
+            seller: false,
+            config: false,
+            reports: false,
+            publish,
+        };
+
+        let (report_sender, report_receiver) = oneshot::channel();
+        let incoming_app_connection = IncomingAppConnection {
+            app_public_key,
+            app_permissions,
+            app_subscriptions: AppSubscription::all(),
+            report_sender,
+        };
+
+        connections_sender
+            .send(incoming_app_connection)
+            .await
+            .unwrap();
+
+        let (_report, conn_sender) = report_receiver.await.unwrap();
+        conn_sender.send(server_conn_pair).unwrap();
+
+        app_senders.push(app_sender);
+        app_receivers.push(app_receiver);
+    }
+    let mut publisher_receiver = app_receivers.remove(0);
+    let mut listener_receiver = app_receivers.remove(0);
+    let mut publisher_sender = app_senders.remove(0);
+    let mut listener_sender = app_senders.remove(0);
+
+    let payments_topic = AppTopic {
+        app_public_key: pk_publisher.clone(),
+        name: "payments".to_owned(),
+    };
+
+    // Subscribing does not require any permission:
+    listener_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[0; Uid::len()]),
+            AppRequest::SubscribeAppTopic(payments_topic.clone()),
+        ))
+        .await
+        .unwrap();
+    assert_ack(
+        listener_receiver.next().await.unwrap(),
+        Uid::from(&[0; Uid::len()]),
+    );
+
+    publisher_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[1; Uid::len()]),
+            AppRequest::PublishAppMessage(PublishAppMessage {
+                topic_name: "payments".to_owned(),
+                data: vec![1, 2, 3],
+            }),
+        ))
+        .await
+        .unwrap();
+    assert_ack(
+        publisher_receiver.next().await.unwrap(),
+        Uid::from(&[1; Uid::len()]),
+    );
+    match listener_receiver.next().await.unwrap() {
+        AppServerToApp::AppMessage(app_message) => assert_eq!(
+            app_message,
+            AppMessage {
+                topic: payments_topic.clone(),
+                data: vec![1, 2, 3],
+            }
+        ),
+        _ => unreachable!(),
+    };
+
+    // A topic nobody is subscribed to:
+    publisher_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[2; Uid::len()]),
+            AppRequest::PublishAppMessage(PublishAppMessage {
+                topic_name: "invoices".to_owned(),
+                data: vec![4],
+            }),
+        ))
+        .await
+        .unwrap();
+    assert_ack(
+        publisher_receiver.next().await.unwrap(),
+        Uid::from(&[2; Uid::len()]),
+    );
+
+    // The listener may not publish. Nothing was sent to the listener before the
+    // PermissionDenied message:
+    listener_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[3; Uid::len()]),
+            AppRequest::PublishAppMessage(PublishAppMessage {
+                topic_name: "payments".to_owned(),
+                data: vec![5],
+            }),
+        ))
+        .await
+        .unwrap();
+    match listener_receiver.next().await.unwrap() {
+        AppServerToApp::PermissionDenied(permission_denied) => {
+            assert_eq!(
+                permission_denied.app_request_id,
+                Uid::from(&[3; Uid::len()])
+            );
+            assert_eq!(permission_denied.permission, AppPermission::Publish);
+        }
+        _ => unreachable!(),
+    };
+    match listener_receiver.next().await.unwrap() {
+        AppServerToApp::RequestError(request_error) => {
+            assert_eq!(request_error.app_request_id, Uid::from(&[3; Uid::len()]));
+            assert_eq!(request_error.error_code, RequestErrorCode::Unauthorized);
+        }
+        _ => unreachable!(),
+    };
+
+    listener_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[4; Uid::len()]),
+            AppRequest::UnsubscribeAppTopic(payments_topic),
+        ))
+        .await
+        .unwrap();
+    assert_ack(
+        listener_receiver.next().await.unwrap(),
+        Uid::from(&[4; Uid::len()]),
+    );
+
+    publisher_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[5; Uid::len()]),
+            AppRequest::PublishAppMessage(PublishAppMessage {
+                topic_name: "payments".to_owned(),
+                data: vec![6],
+            }),
+        ))
+        .await
+        .unwrap();
+    assert_ack(
+        publisher_receiver.next().await.unwrap(),
+        Uid::from(&[5; Uid::len()]),
+    );
+
+    // A topic name that is too long is rejected with a request error, but the request is still
+    // acknowledged. The message published after unsubscribing was not sent to the listener:
+    listener_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[6; Uid::len()]),
+            AppRequest::SubscribeAppTopic(AppTopic {
+                app_public_key: pk_publisher,
+                name: "a".repeat(MAX_APP_TOPIC_LEN + 1),
+            }),
+        ))
+        .await
+        .unwrap();
+    match listener_receiver.next().await.unwrap() {
+        AppServerToApp::RequestError(request_error) => {
+            assert_eq!(request_error.app_request_id, Uid::from(&[6; Uid::len()]));
+            assert_eq!(request_error.error_code, RequestErrorCode::InvalidRequest);
+        }
+        _ => unreachable!(),
+    };
+    assert_ack(
+        listener_receiver.next().await.unwrap(),
+        Uid::from(&[6; Uid::len()]),
+    );
+}
+
+#[test]
+fn test_app_server_loop_app_messages() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_app_messages(thread_pool.clone()));
+}
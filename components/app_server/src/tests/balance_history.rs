@@ -0,0 +1,250 @@
+use std::convert::TryFrom;
+
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use proto::crypto::{PublicKey, Uid};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppSubscription, AppToAppServer, BalanceDelta,
+    RequestBalanceHistory, ResponseBalanceHistory,
+};
+use proto::funder::messages::{Currency, FunderOutgoingControl};
+use proto::report::messages::{
+    AddFriendReport, ChannelConsistentReport, ChannelStatusReport, CurrencyReport,
+    FriendReportMutation, FunderReportMutation, FunderReportMutations, McBalanceReport,
+};
+
+use super::utils::spawn_dummy_app_server_with_timer;
+use crate::balance_history::BalanceHistoryConfig;
+use crate::server::IncomingAppConnection;
+
+fn channel_status(currency: &Currency, balance: i128) -> ChannelStatusReport {
+    ChannelStatusReport::Consistent(ChannelConsistentReport {
+        currency_reports: vec![CurrencyReport {
+            currency: currency.clone(),
+            balance: McBalanceReport {
+                balance,
+                local_pending_debt: 0,
+                remote_pending_debt: 0,
+            },
+        }],
+    })
+}
+
+async fn task_app_server_loop_balance_history<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let balance_history_config = BalanceHistoryConfig {
+        snapshot_ticks: 2,
+        max_snapshots: 2,
+    };
+    let (
+        mut funder_sender,
+        _funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+        mut tick_sender,
+    ) = spawn_dummy_app_server_with_timer(balance_history_config, spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(1);
+    let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+
+    let app_permissions = AppPermissions {
+        routes: false,
+        buyer: false,
+        seller: false,
+        config: false,
+        reports: true,
+        publish: false,
+    };
+
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
+        app_permissions,
+        app_subscriptions: vec![AppSubscription::FunderReport],
+        report_sender,
+    };
+
+    connections_sender
+        .send(incoming_app_connection)
+        .await
+        .unwrap();
+
+    let (_report, conn_sender) = report_receiver.await.unwrap();
+    conn_sender.send(server_conn_pair).unwrap();
+
+    let friend_public_key = PublicKey::from(&[0xcc; PublicKey::len()]);
+    let currency = Currency::try_from("FST".to_owned()).unwrap();
+
+    let mutations = vec![FunderReportMutation::AddFriend(AddFriendReport {
+        friend_public_key: friend_public_key.clone(),
+        name: "friend".to_owned(),
+        relays: Vec::new(),
+        opt_last_incoming_move_token: None,
+        channel_status: channel_status(&currency, 10),
+    })];
+    funder_sender
+        .send(FunderOutgoingControl::ReportMutations(
+            FunderReportMutations {
+                opt_app_request_id: None,
+                mutations,
+            },
+        ))
+        .await
+        .unwrap();
+    match app_receiver.next().await.unwrap() {
+        AppServerToApp::ReportMutations(_) => {}
+        _ => unreachable!(),
+    }
+
+    // A snapshot is taken on tick 2.
+    // Sending tick 3 makes sure that tick 2 was handled:
+    for _ in 0..3 {
+        tick_sender.send(()).await.unwrap();
+    }
+
+    let mutations = vec![FunderReportMutation::PkFriendReportMutation((
+        friend_public_key.clone(),
+        FriendReportMutation::SetChannelStatus(channel_status(&currency, 15)),
+    ))];
+    funder_sender
+        .send(FunderOutgoingControl::ReportMutations(
+            FunderReportMutations {
+                opt_app_request_id: None,
+                mutations,
+            },
+        ))
+        .await
+        .unwrap();
+    match app_receiver.next().await.unwrap() {
+        AppServerToApp::ReportMutations(_) => {}
+        _ => unreachable!(),
+    }
+
+    // Snapshot on tick 4:
+    for _ in 0..2 {
+        tick_sender.send(()).await.unwrap();
+    }
+
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[21; Uid::len()]),
+            AppRequest::RequestBalanceHistory(RequestBalanceHistory {
+                request_id: Uid::from(&[22; Uid::len()]),
+                friend_public_key: friend_public_key.clone(),
+                currency: currency.clone(),
+                from_tick: 0,
+                to_tick: u64::max_value(),
+            }),
+        ))
+        .await
+        .unwrap();
+    let response_balance_history = match app_receiver.next().await.unwrap() {
+        AppServerToApp::ResponseBalanceHistory(response_balance_history) => {
+            response_balance_history
+        }
+        _ => unreachable!(),
+    };
+    assert_eq!(
+        response_balance_history.request_id,
+        Uid::from(&[22; Uid::len()])
+    );
+    assert!(response_balance_history.current_tick >= 4);
+    assert_eq!(
+        response_balance_history.balance_deltas,
+        vec![
+            BalanceDelta {
+                tick: 2,
+                balance: 10,
+                delta: 0,
+            },
+            BalanceDelta {
+                tick: 4,
+                balance: 15,
+                delta: 5,
+            },
+        ]
+    );
+
+    // Snapshot on tick 6. Only the two latest snapshots are kept:
+    for _ in 0..2 {
+        tick_sender.send(()).await.unwrap();
+    }
+
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[23; Uid::len()]),
+            AppRequest::RequestBalanceHistory(RequestBalanceHistory {
+                request_id: Uid::from(&[24; Uid::len()]),
+                friend_public_key: friend_public_key.clone(),
+                currency: currency.clone(),
+                from_tick: 0,
+                to_tick: 6,
+            }),
+        ))
+        .await
+        .unwrap();
+    let response_balance_history = match app_receiver.next().await.unwrap() {
+        AppServerToApp::ResponseBalanceHistory(response_balance_history) => {
+            response_balance_history
+        }
+        _ => unreachable!(),
+    };
+    assert_eq!(
+        response_balance_history.balance_deltas,
+        vec![
+            BalanceDelta {
+                tick: 4,
+                balance: 15,
+                delta: 5,
+            },
+            BalanceDelta {
+                tick: 6,
+                balance: 15,
+                delta: 0,
+            },
+        ]
+    );
+
+    // Unknown currency:
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[25; Uid::len()]),
+            AppRequest::RequestBalanceHistory(RequestBalanceHistory {
+                request_id: Uid::from(&[26; Uid::len()]),
+                friend_public_key,
+                currency: Currency::try_from("FST2".to_owned()).unwrap(),
+                from_tick: 0,
+                to_tick: u64::max_value(),
+            }),
+        ))
+        .await
+        .unwrap();
+    match app_receiver.next().await.unwrap() {
+        AppServerToApp::ResponseBalanceHistory(ResponseBalanceHistory {
+            request_id,
+            balance_deltas,
+            ..
+        }) => {
+            assert_eq!(request_id, Uid::from(&[26; Uid::len()]));
+            assert!(balance_deltas.is_empty());
+        }
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_app_server_loop_balance_history() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_balance_history(thread_pool.clone()));
+}
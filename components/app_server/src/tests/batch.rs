@@ -0,0 +1,151 @@
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use proto::crypto::{PublicKey, Uid};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppSubscription, AppToAppServer,
+    NodeReportMutation, ReportMutations,
+};
+use proto::funder::messages::{FunderControl, FunderOutgoingControl};
+use proto::report::messages::{FunderReportMutation, FunderReportMutations};
+
+use super::utils::{dummy_named_relay_address, spawn_dummy_app_server};
+use crate::server::IncomingAppConnection;
+
+async fn task_app_server_loop_batch<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+    let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+
+    let app_permissions = AppPermissions {
+        routes: false,
+        buyer: false,
+        seller: false,
+        config: true,
+        reports: true,
+        publish: false,
+    };
+
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
+        app_permissions,
+        app_subscriptions: AppSubscription::all(),
+        report_sender,
+    };
+
+    connections_sender
+        .send(incoming_app_connection)
+        .await
+        .unwrap();
+
+    let (_report, conn_sender) = report_receiver.await.unwrap();
+    conn_sender.send(server_conn_pair).unwrap();
+
+    // Send a batch of two commands through the app:
+    let batch_request_id = Uid::from(&[21; Uid::len()]);
+    let app_request_ids = vec![Uid::from(&[22; Uid::len()]), Uid::from(&[23; Uid::len()])];
+    let batch = app_request_ids
+        .iter()
+        .enumerate()
+        .map(|(i, app_request_id)| {
+            AppToAppServer::new(
+                app_request_id.clone(),
+                AppRequest::AddRelay(dummy_named_relay_address(i as u8)),
+            )
+        })
+        .collect();
+    app_sender
+        .send(AppToAppServer::new(
+            batch_request_id.clone(),
+            AppRequest::Batch(batch),
+        ))
+        .await
+        .unwrap();
+
+    // Both commands are forwarded to the Funder, in order:
+    for (i, app_request_id) in app_request_ids.iter().enumerate() {
+        let to_funder_message = funder_receiver.next().await.unwrap();
+        assert_eq!(&to_funder_message.app_request_id, app_request_id);
+        match to_funder_message.funder_control {
+            FunderControl::AddRelay(address) => {
+                assert_eq!(address, dummy_named_relay_address(i as u8))
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    // The Funder acknowledges both commands:
+    for (i, app_request_id) in app_request_ids.iter().enumerate() {
+        funder_sender
+            .send(FunderOutgoingControl::ReportMutations(
+                FunderReportMutations {
+                    opt_app_request_id: Some(app_request_id.clone()),
+                    mutations: vec![FunderReportMutation::AddRelay(dummy_named_relay_address(
+                        i as u8,
+                    ))],
+                },
+            ))
+            .await
+            .unwrap();
+    }
+
+    // The app gets a single acknowledgement for the whole batch, with all the mutations:
+    let to_app_message = app_receiver.next().await.unwrap();
+    assert_eq!(
+        to_app_message,
+        AppServerToApp::ReportMutations(ReportMutations {
+            opt_app_request_id: Some(batch_request_id),
+            mutations: vec![
+                NodeReportMutation::Funder(FunderReportMutation::AddRelay(
+                    dummy_named_relay_address(0)
+                )),
+                NodeReportMutation::Funder(FunderReportMutation::AddRelay(
+                    dummy_named_relay_address(1)
+                )),
+            ],
+        })
+    );
+
+    // An empty batch is acknowledged immediately:
+    let batch_request_id = Uid::from(&[24; Uid::len()]);
+    app_sender
+        .send(AppToAppServer::new(
+            batch_request_id.clone(),
+            AppRequest::Batch(Vec::new()),
+        ))
+        .await
+        .unwrap();
+
+    let to_app_message = app_receiver.next().await.unwrap();
+    assert_eq!(
+        to_app_message,
+        AppServerToApp::ReportMutations(ReportMutations {
+            opt_app_request_id: Some(batch_request_id),
+            mutations: Vec::new(),
+        })
+    );
+}
+
+#[test]
+fn test_app_server_loop_batch() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_batch(thread_pool.clone()));
+}
@@ -0,0 +1,165 @@
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use proto::crypto::{PublicKey, Uid};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppSubscription, AppToAppServer, FriendDetail,
+    FriendDetailResult, RelayAddress, ResponseFriendDetail,
+};
+use proto::funder::messages::FunderOutgoingControl;
+use proto::report::messages::{
+    AddFriendReport, BandwidthReport, ChannelConsistentReport, ChannelStatusReport,
+    DrainStatusReport, FriendLivenessReport, FriendReport, FriendStatusReport,
+    FunderReportMutation, FunderReportMutations, RelayLatencyReport, SecureChannelStatsReport,
+};
+
+use super::utils::{dummy_named_relay_address, spawn_dummy_app_server};
+use crate::server::IncomingAppConnection;
+
+async fn task_app_server_loop_friend_detail<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        _funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(1);
+    let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+
+    let app_permissions = AppPermissions {
+        routes: false,
+        buyer: false,
+        seller: false,
+        config: false,
+        reports: true,
+        publish: false,
+    };
+
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
+        app_permissions,
+        app_subscriptions: vec![AppSubscription::FunderReport],
+        report_sender,
+    };
+
+    connections_sender
+        .send(incoming_app_connection)
+        .await
+        .unwrap();
+
+    let (_report, conn_sender) = report_receiver.await.unwrap();
+    conn_sender.send(server_conn_pair).unwrap();
+
+    let friend_public_key = PublicKey::from(&[0xcc; PublicKey::len()]);
+
+    // The friend does not exist yet:
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[21; Uid::len()]),
+            AppRequest::RequestFriendDetail(friend_public_key.clone()),
+        ))
+        .await
+        .unwrap();
+    let to_app_message = app_receiver.next().await.unwrap();
+    assert_eq!(
+        to_app_message,
+        AppServerToApp::ResponseFriendDetail(ResponseFriendDetail {
+            friend_public_key: friend_public_key.clone(),
+            result: FriendDetailResult::NotFound,
+        })
+    );
+
+    let friend_relay: RelayAddress<u32> = dummy_named_relay_address(5).into();
+    let friend_relay_latency = RelayLatencyReport {
+        public_key: friend_relay.public_key.clone(),
+        opt_latency_ms: Some(30),
+    };
+    let mutations = vec![
+        FunderReportMutation::AddFriend(AddFriendReport {
+            friend_public_key: friend_public_key.clone(),
+            name: "friend".to_owned(),
+            relays: vec![friend_relay.clone()],
+            opt_last_incoming_move_token: None,
+            channel_status: ChannelStatusReport::Consistent(ChannelConsistentReport {
+                currency_reports: Vec::new(),
+            }),
+        }),
+        FunderReportMutation::SetRelayLatency(friend_relay_latency.clone()),
+        // A latency of one of our own relays:
+        FunderReportMutation::SetRelayLatency(RelayLatencyReport {
+            public_key: dummy_named_relay_address(0).public_key,
+            opt_latency_ms: Some(10),
+        }),
+    ];
+    funder_sender
+        .send(FunderOutgoingControl::ReportMutations(
+            FunderReportMutations {
+                opt_app_request_id: None,
+                mutations,
+            },
+        ))
+        .await
+        .unwrap();
+
+    // Wait until the mutations are applied:
+    match app_receiver.next().await.unwrap() {
+        AppServerToApp::ReportMutations(_) => {}
+        _ => unreachable!(),
+    }
+
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[22; Uid::len()]),
+            AppRequest::RequestFriendDetail(friend_public_key.clone()),
+        ))
+        .await
+        .unwrap();
+
+    // Only the latencies of the friend's relays are included:
+    let friend_report = FriendReport {
+        name: "friend".to_owned(),
+        remote_relays: vec![friend_relay],
+        currency_configs: Vec::new(),
+        opt_last_incoming_move_token: None,
+        liveness: FriendLivenessReport::Offline,
+        channel_status: ChannelStatusReport::Consistent(ChannelConsistentReport {
+            currency_reports: Vec::new(),
+        }),
+        status: FriendStatusReport::Disabled,
+        missed_beats: 0,
+        is_gated: false,
+        secure_channel_stats: SecureChannelStatsReport::default(),
+        drain_status: DrainStatusReport::Active,
+        bandwidth: BandwidthReport::default(),
+    };
+    let to_app_message = app_receiver.next().await.unwrap();
+    assert_eq!(
+        to_app_message,
+        AppServerToApp::ResponseFriendDetail(ResponseFriendDetail {
+            friend_public_key,
+            result: FriendDetailResult::Found(FriendDetail {
+                friend_report,
+                relay_latencies: vec![friend_relay_latency],
+            }),
+        })
+    );
+}
+
+#[test]
+fn test_app_server_loop_friend_detail() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_friend_detail(thread_pool.clone()));
+}
@@ -0,0 +1,106 @@
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use std::convert::TryFrom;
+
+use proto::crypto::{PublicKey, Uid};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppSubscription, AppToAppServer, SendFriendProposal,
+};
+use proto::funder::messages::Currency;
+use proto::index_client::messages::{AppServerToIndexClient, IndexClientRequest};
+
+use super::utils::spawn_dummy_app_server;
+use crate::server::IncomingAppConnection;
+
+async fn task_app_server_loop_friend_proposals<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        _funder_sender,
+        _funder_receiver,
+        _index_client_sender,
+        mut index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, _app_receiver) = mpsc::channel(0);
+    let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+
+    let app_permissions = AppPermissions {
+        routes: false,
+        buyer: false,
+        seller: false,
+        config: true,
+        reports: true,
+        publish: false,
+    };
+
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
+        app_permissions,
+        app_subscriptions: AppSubscription::all(),
+        report_sender,
+    };
+
+    connections_sender
+        .send(incoming_app_connection)
+        .await
+        .unwrap();
+
+    let (_report, conn_sender) = report_receiver.await.unwrap();
+    conn_sender.send(server_conn_pair).unwrap();
+
+    // Friend proposals are forwarded to the index client:
+    let send_friend_proposal = SendFriendProposal {
+        dest_public_key: PublicKey::from(&[0xbb; PublicKey::len()]),
+        relays: Vec::new(),
+        currency: Currency::try_from("FST".to_owned()).unwrap(),
+        proposed_max_debt: 100,
+    };
+    let app_request = AppToAppServer::new(
+        Uid::from(&[24; Uid::len()]),
+        AppRequest::SendFriendProposal(send_friend_proposal.clone()),
+    );
+    app_sender.send(app_request).await.unwrap();
+
+    let to_index_client_message = index_client_receiver.next().await.unwrap();
+    assert_eq!(
+        to_index_client_message,
+        AppServerToIndexClient::AppRequest((
+            Uid::from(&[24; Uid::len()]),
+            IndexClientRequest::SendFriendProposal(send_friend_proposal)
+        ))
+    );
+
+    // Rejecting a received proposal:
+    let app_request = AppToAppServer::new(
+        Uid::from(&[25; Uid::len()]),
+        AppRequest::RemoveFriendProposal(PublicKey::from(&[0xcc; PublicKey::len()])),
+    );
+    app_sender.send(app_request).await.unwrap();
+
+    let to_index_client_message = index_client_receiver.next().await.unwrap();
+    assert_eq!(
+        to_index_client_message,
+        AppServerToIndexClient::AppRequest((
+            Uid::from(&[25; Uid::len()]),
+            IndexClientRequest::RemoveFriendProposal(PublicKey::from(&[0xcc; PublicKey::len()]))
+        ))
+    );
+}
+
+#[test]
+fn test_app_server_loop_friend_proposals() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_friend_proposals(thread_pool.clone()));
+}
@@ -1,7 +1,23 @@
+mod add_invoice;
 mod all_apps_closed;
+mod app_messages;
+mod balance_history;
+mod batch;
+mod export_channel_proof;
+mod friend_detail;
+mod friend_proposals;
 mod funder_command;
 mod index_client_command;
+mod permission_denied;
+mod quote_payment;
+mod receipt_archive;
+mod report_subscription;
+mod request_error;
+mod request_exposure;
 mod request_routes;
 mod request_send_funds;
+mod retry_transaction;
+mod set_node_config;
+mod subscriptions;
 mod two_apps;
 mod utils;
@@ -0,0 +1,149 @@
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use proto::crypto::{InvoiceId, PublicKey, Uid};
+
+use proto::app_server::messages::{
+    AppPermission, AppPermissions, AppRequest, AppServerToApp, AppSubscription, AppToAppServer,
+    PermissionDenied,
+};
+use proto::funder::messages::{FunderControl, FunderOutgoingControl, RequestErrorCode};
+use proto::report::messages::{FunderReportMutation, FunderReportMutations};
+
+use super::utils::{dummy_named_relay_address, spawn_dummy_app_server};
+use crate::server::IncomingAppConnection;
+
+async fn task_app_server_loop_permission_denied<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+    let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+
+    // The app may only configure the node, and does not receive reports:
+    let app_permissions = AppPermissions {
+        routes: false,
+        buyer: false,
+        seller: false,
+        config: true,
+        reports: false,
+        publish: false,
+    };
+
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
+        app_permissions,
+        app_subscriptions: AppSubscription::all(),
+        report_sender,
+    };
+
+    connections_sender
+        .send(incoming_app_connection)
+        .await
+        .unwrap();
+
+    let (_report, conn_sender) = report_receiver.await.unwrap();
+    conn_sender.send(server_conn_pair).unwrap();
+
+    // Try to cancel an invoice (Requires the seller permission):
+    let app_request = AppToAppServer::new(
+        Uid::from(&[21; Uid::len()]),
+        AppRequest::CancelInvoice(InvoiceId::from(&[1; InvoiceId::len()])),
+    );
+    app_sender.send(app_request).await.unwrap();
+
+    let to_app_message = app_receiver.next().await.unwrap();
+    assert_eq!(
+        to_app_message,
+        AppServerToApp::PermissionDenied(PermissionDenied {
+            app_request_id: Uid::from(&[21; Uid::len()]),
+            permission: AppPermission::Seller,
+        })
+    );
+
+    // Followed by a request error:
+    let to_app_message = app_receiver.next().await.unwrap();
+    match to_app_message {
+        AppServerToApp::RequestError(request_error) => {
+            assert_eq!(request_error.app_request_id, Uid::from(&[21; Uid::len()]));
+            assert_eq!(request_error.error_code, RequestErrorCode::Unauthorized);
+        }
+        _ => unreachable!(),
+    };
+
+    // The connection remains open, allowed requests are still served:
+    let app_request = AppToAppServer::new(
+        Uid::from(&[22; Uid::len()]),
+        AppRequest::AddRelay(dummy_named_relay_address(2)),
+    );
+    app_sender.send(app_request).await.unwrap();
+
+    let to_funder_message = funder_receiver.next().await.unwrap();
+    assert_eq!(
+        to_funder_message.app_request_id,
+        Uid::from(&[22; Uid::len()])
+    );
+    assert_eq!(
+        to_funder_message.funder_control,
+        FunderControl::AddRelay(dummy_named_relay_address(2))
+    );
+
+    // Mutations that are not related to a request of the app are not sent at all.
+    // The app only gets an acknowledgement for its own request, without the mutations:
+    let funder_report_mutations = FunderReportMutations {
+        opt_app_request_id: None,
+        mutations: vec![FunderReportMutation::RemoveRelay(PublicKey::from(
+            &[1; PublicKey::len()],
+        ))],
+    };
+    funder_sender
+        .send(FunderOutgoingControl::ReportMutations(
+            funder_report_mutations,
+        ))
+        .await
+        .unwrap();
+
+    let funder_report_mutations = FunderReportMutations {
+        opt_app_request_id: Some(Uid::from(&[22; Uid::len()])),
+        mutations: vec![FunderReportMutation::AddRelay(dummy_named_relay_address(2))],
+    };
+    funder_sender
+        .send(FunderOutgoingControl::ReportMutations(
+            funder_report_mutations,
+        ))
+        .await
+        .unwrap();
+
+    let to_app_message = app_receiver.next().await.unwrap();
+    match to_app_message {
+        AppServerToApp::ReportMutations(report_mutations) => {
+            assert_eq!(
+                report_mutations.opt_app_request_id,
+                Some(Uid::from(&[22; Uid::len()]))
+            );
+            assert!(report_mutations.mutations.is_empty());
+        }
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_app_server_loop_permission_denied() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_permission_denied(thread_pool.clone()));
+}
@@ -0,0 +1,137 @@
+use std::convert::TryFrom;
+
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use proto::crypto::{PublicKey, Uid};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppSubscription, AppToAppServer,
+};
+use proto::funder::messages::{
+    Currency, FriendsRoute, FunderControl, FunderOutgoingControl, HopFreeze, QuotePayment,
+    ResponseQuotePayment,
+};
+
+use super::utils::spawn_dummy_app_server;
+use crate::server::IncomingAppConnection;
+
+async fn task_app_server_loop_quote_payment<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(1);
+    let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+
+    let app_permissions = AppPermissions {
+        routes: false,
+        buyer: true,
+        seller: false,
+        config: false,
+        reports: false,
+        publish: false,
+    };
+
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
+        app_permissions,
+        app_subscriptions: AppSubscription::all(),
+        report_sender,
+    };
+
+    connections_sender
+        .send(incoming_app_connection)
+        .await
+        .unwrap();
+
+    let (_report, conn_sender) = report_receiver.await.unwrap();
+    conn_sender.send(server_conn_pair).unwrap();
+
+    let quote_payment = QuotePayment {
+        request_id: Uid::from(&[3; Uid::len()]),
+        currency: Currency::try_from("FST".to_owned()).unwrap(),
+        route: FriendsRoute {
+            public_keys: vec![
+                PublicKey::from(&[0xaa; PublicKey::len()]),
+                PublicKey::from(&[0xee; PublicKey::len()]),
+            ],
+        },
+        mediator_rates: Vec::new(),
+        dest_payment: 10,
+        opt_max_fee_per_hop: None,
+    };
+    let app_request = AppToAppServer::new(
+        Uid::from(&[21; Uid::len()]),
+        AppRequest::QuotePayment(quote_payment.clone()),
+    );
+    app_sender.send(app_request).await.unwrap();
+
+    // The request should be forwarded to the funder:
+    let to_funder_message = funder_receiver.next().await.unwrap();
+    assert_eq!(
+        to_funder_message.app_request_id,
+        Uid::from(&[21; Uid::len()])
+    );
+    assert_eq!(
+        to_funder_message.funder_control,
+        FunderControl::QuotePayment(quote_payment)
+    );
+
+    // A response that does not match any open request is discarded:
+    let response_quote_payment = ResponseQuotePayment {
+        request_id: Uid::from(&[2; Uid::len()]),
+        fees: 0,
+        hop_freezes: Vec::new(),
+        opt_rejection: None,
+    };
+    funder_sender
+        .send(FunderOutgoingControl::ResponseQuotePayment(
+            response_quote_payment,
+        ))
+        .await
+        .unwrap();
+
+    let response_quote_payment = ResponseQuotePayment {
+        request_id: Uid::from(&[3; Uid::len()]),
+        fees: 0,
+        hop_freezes: vec![HopFreeze {
+            to_public_key: PublicKey::from(&[0xee; PublicKey::len()]),
+            frozen_credits: 10,
+        }],
+        opt_rejection: None,
+    };
+    funder_sender
+        .send(FunderOutgoingControl::ResponseQuotePayment(
+            response_quote_payment.clone(),
+        ))
+        .await
+        .unwrap();
+
+    // Only the matching response arrives at the app:
+    let to_app_message = app_receiver.next().await.unwrap();
+    assert_eq!(
+        to_app_message,
+        AppServerToApp::ResponseQuotePayment(response_quote_payment)
+    );
+}
+
+#[test]
+fn test_app_server_loop_quote_payment() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_quote_payment(thread_pool.clone()));
+}
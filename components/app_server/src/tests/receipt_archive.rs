@@ -0,0 +1,370 @@
+use std::convert::TryFrom;
+
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use proto::crypto::{
+    HashResult, HashedLock, InvoiceId, PaymentId, PlainLock, PublicKey, Signature, Uid,
+};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppSubscription, AppToAppServer, RequestReceipts,
+    ResponseReceipts,
+};
+use proto::funder::messages::{
+    AddInvoice, Commit, CreatePayment, Currency, FunderControl, FunderOutgoingControl,
+    PaymentStatus, PaymentStatusSuccess, Receipt, ResponseClosePayment,
+};
+
+use database::{DatabaseClient, DatabaseRequest};
+
+use super::utils::spawn_dummy_app_server_with_db;
+use crate::balance_history::BalanceHistoryConfig;
+use crate::receipt_archive::{ReceiptArchive, ReceiptArchiveMutation};
+use crate::server::IncomingAppConnection;
+
+/// Wait for a single mutation to be saved to the database, and acknowledge it
+async fn next_db_mutation(
+    db_request_receiver: &mut mpsc::Receiver<DatabaseRequest<ReceiptArchiveMutation>>,
+) -> ReceiptArchiveMutation {
+    let mutate_request = match db_request_receiver.next().await.unwrap() {
+        DatabaseRequest::Mutate(mutate_request) => mutate_request,
+        DatabaseRequest::Subscribe(_) => unreachable!(),
+    };
+    mutate_request.response_sender.send(()).unwrap();
+    let mut mutations = mutate_request.mutations;
+    assert_eq!(mutations.len(), 1);
+    mutations.pop().unwrap()
+}
+
+fn request_receipts(
+    request_id: Uid,
+    from_tick: u64,
+    opt_counterparty: Option<PublicKey>,
+    opt_invoice_id: Option<InvoiceId>,
+) -> AppRequest {
+    AppRequest::RequestReceipts(RequestReceipts {
+        request_id,
+        from_tick,
+        to_tick: u64::max_value(),
+        opt_counterparty,
+        opt_invoice_id,
+    })
+}
+
+async fn task_app_server_loop_receipt_archive<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let balance_history_config = BalanceHistoryConfig {
+        snapshot_ticks: 0,
+        max_snapshots: 0,
+    };
+    // An archive loaded from the database. Archive ticks continue from its latest entry:
+    let mut receipt_archive = ReceiptArchive::new();
+    receipt_archive.last_tick = 10;
+
+    let (db_request_sender, mut db_request_receiver) = mpsc::channel(0);
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+        mut tick_sender,
+    ) = spawn_dummy_app_server_with_db(
+        balance_history_config,
+        receipt_archive,
+        DatabaseClient::new(db_request_sender),
+        spawner.clone(),
+    );
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(1);
+    let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+
+    let app_permissions = AppPermissions {
+        routes: false,
+        buyer: true,
+        seller: true,
+        config: false,
+        reports: true,
+        publish: false,
+    };
+
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
+        app_permissions,
+        app_subscriptions: AppSubscription::all(),
+        report_sender,
+    };
+
+    connections_sender
+        .send(incoming_app_connection)
+        .await
+        .unwrap();
+
+    let (_report, conn_sender) = report_receiver.await.unwrap();
+    conn_sender.send(server_conn_pair).unwrap();
+
+    let currency = Currency::try_from("FST".to_owned()).unwrap();
+    let seller_public_key = PublicKey::from(&[0xcc; PublicKey::len()]);
+    let payment_id = PaymentId::from(&[1; PaymentId::len()]);
+    let paid_invoice_id = InvoiceId::from(&[2; InvoiceId::len()]);
+    let issued_invoice_id = InvoiceId::from(&[3; InvoiceId::len()]);
+
+    // Buyer: Create a payment. The seller is remembered until the receipt arrives:
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[11; Uid::len()]),
+            AppRequest::CreatePayment(CreatePayment {
+                payment_id: payment_id.clone(),
+                invoice_id: paid_invoice_id.clone(),
+                currency: currency.clone(),
+                total_dest_payment: 20,
+                dest_public_key: seller_public_key.clone(),
+                opt_max_fee_per_hop: None,
+            }),
+        ))
+        .await
+        .unwrap();
+    match next_db_mutation(&mut db_request_receiver).await {
+        ReceiptArchiveMutation::AddPendingPayment(pending_payment) => {
+            assert_eq!(pending_payment.payment_id, payment_id);
+            assert_eq!(pending_payment.dest_public_key, seller_public_key);
+        }
+        _ => unreachable!(),
+    }
+    let to_funder_message = funder_receiver.next().await.unwrap();
+    match to_funder_message.funder_control {
+        FunderControl::CreatePayment(_) => {}
+        _ => unreachable!(),
+    }
+
+    let receipt = Receipt {
+        response_hash: HashResult::from(&[4; HashResult::len()]),
+        invoice_id: paid_invoice_id.clone(),
+        currency: currency.clone(),
+        src_plain_lock: PlainLock::from(&[5; PlainLock::len()]),
+        dest_plain_lock: PlainLock::from(&[6; PlainLock::len()]),
+        is_complete: true,
+        dest_payment: 20,
+        total_dest_payment: 20,
+        change: 0,
+        signature: Signature::from(&[7; Signature::len()]),
+    };
+    let response_close_payment = ResponseClosePayment {
+        payment_id: payment_id.clone(),
+        status: PaymentStatus::Success(PaymentStatusSuccess {
+            receipt: receipt.clone(),
+            ack_uid: Uid::from(&[8; Uid::len()]),
+        }),
+    };
+
+    // The receipt is archived only once, even if it is sent again:
+    for _ in 0..2 {
+        funder_sender
+            .send(FunderOutgoingControl::ResponseClosePayment(
+                response_close_payment.clone(),
+            ))
+            .await
+            .unwrap();
+    }
+    match next_db_mutation(&mut db_request_receiver).await {
+        ReceiptArchiveMutation::AddReceipt(archived_receipt) => {
+            assert_eq!(archived_receipt.tick, 10);
+            assert_eq!(archived_receipt.payment_id, payment_id);
+            assert_eq!(archived_receipt.dest_public_key, seller_public_key);
+            assert_eq!(archived_receipt.receipt, receipt);
+        }
+        _ => unreachable!(),
+    }
+
+    // Sending a second tick makes sure that the first tick was handled:
+    for _ in 0..2 {
+        tick_sender.send(()).await.unwrap();
+    }
+
+    // Seller: Issue an invoice, and commit it:
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[12; Uid::len()]),
+            AppRequest::AddInvoice(AddInvoice {
+                invoice_id: issued_invoice_id.clone(),
+                currency: currency.clone(),
+                total_dest_payment: 30,
+                opt_expiry_ticks: None,
+            }),
+        ))
+        .await
+        .unwrap();
+    let invoice_tick = match next_db_mutation(&mut db_request_receiver).await {
+        ReceiptArchiveMutation::AddInvoice(archived_invoice) => {
+            assert_eq!(archived_invoice.invoice_id, issued_invoice_id);
+            assert_eq!(archived_invoice.total_dest_payment, 30);
+            assert!(archived_invoice.opt_commit.is_none());
+            archived_invoice.tick
+        }
+        _ => unreachable!(),
+    };
+    assert!(invoice_tick > 10);
+    let to_funder_message = funder_receiver.next().await.unwrap();
+    match to_funder_message.funder_control {
+        FunderControl::AddInvoice(_) => {}
+        _ => unreachable!(),
+    }
+
+    let commit = Commit {
+        response_hash: HashResult::from(&[9; HashResult::len()]),
+        src_plain_lock: PlainLock::from(&[10; PlainLock::len()]),
+        dest_hashed_lock: HashedLock::from(&[11; HashedLock::len()]),
+        dest_payment: 30,
+        total_dest_payment: 30,
+        change: 0,
+        invoice_id: issued_invoice_id.clone(),
+        currency: currency.clone(),
+        signature: Signature::from(&[12; Signature::len()]),
+    };
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[13; Uid::len()]),
+            AppRequest::CommitInvoice(commit.clone()),
+        ))
+        .await
+        .unwrap();
+    match next_db_mutation(&mut db_request_receiver).await {
+        ReceiptArchiveMutation::CommitInvoice(archived_commit) => {
+            assert_eq!(archived_commit, commit)
+        }
+        _ => unreachable!(),
+    }
+    let to_funder_message = funder_receiver.next().await.unwrap();
+    match to_funder_message.funder_control {
+        FunderControl::CommitInvoice(_) => {}
+        _ => unreachable!(),
+    }
+
+    // All the entries. Receipts are sent before invoices:
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[14; Uid::len()]),
+            request_receipts(Uid::from(&[15; Uid::len()]), 0, None, None),
+        ))
+        .await
+        .unwrap();
+    match app_receiver.next().await.unwrap() {
+        AppServerToApp::ResponseReceipts(ResponseReceipts {
+            request_id,
+            receipts,
+            invoices,
+            is_last,
+            ..
+        }) => {
+            assert_eq!(request_id, Uid::from(&[15; Uid::len()]));
+            assert_eq!(receipts.len(), 1);
+            assert_eq!(receipts[0].receipt, receipt);
+            assert!(invoices.is_empty());
+            assert!(!is_last);
+        }
+        _ => unreachable!(),
+    }
+    match app_receiver.next().await.unwrap() {
+        AppServerToApp::ResponseReceipts(ResponseReceipts {
+            current_tick,
+            receipts,
+            invoices,
+            is_last,
+            ..
+        }) => {
+            assert!(current_tick >= invoice_tick);
+            assert!(receipts.is_empty());
+            assert_eq!(invoices.len(), 1);
+            assert_eq!(invoices[0].opt_commit, Some(commit));
+            assert!(is_last);
+        }
+        _ => unreachable!(),
+    }
+
+    // Filter by counterparty. Issued invoices have no counterparty:
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[16; Uid::len()]),
+            request_receipts(
+                Uid::from(&[17; Uid::len()]),
+                0,
+                Some(seller_public_key),
+                None,
+            ),
+        ))
+        .await
+        .unwrap();
+    match app_receiver.next().await.unwrap() {
+        AppServerToApp::ResponseReceipts(ResponseReceipts {
+            receipts,
+            invoices,
+            is_last,
+            ..
+        }) => {
+            assert_eq!(receipts.len(), 1);
+            assert!(invoices.is_empty());
+            assert!(is_last);
+        }
+        _ => unreachable!(),
+    }
+
+    // Filter by invoice id:
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[18; Uid::len()]),
+            request_receipts(Uid::from(&[19; Uid::len()]), 0, None, Some(paid_invoice_id)),
+        ))
+        .await
+        .unwrap();
+    match app_receiver.next().await.unwrap() {
+        AppServerToApp::ResponseReceipts(ResponseReceipts {
+            receipts,
+            invoices,
+            is_last,
+            ..
+        }) => {
+            assert_eq!(receipts.len(), 1);
+            assert!(invoices.is_empty());
+            assert!(is_last);
+        }
+        _ => unreachable!(),
+    }
+
+    // Filter by tick range:
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[20; Uid::len()]),
+            request_receipts(Uid::from(&[21; Uid::len()]), 11, None, None),
+        ))
+        .await
+        .unwrap();
+    match app_receiver.next().await.unwrap() {
+        AppServerToApp::ResponseReceipts(ResponseReceipts {
+            receipts,
+            invoices,
+            is_last,
+            ..
+        }) => {
+            assert!(receipts.is_empty());
+            assert_eq!(invoices.len(), 1);
+            assert!(is_last);
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_app_server_loop_receipt_archive() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_receipt_archive(thread_pool.clone()));
+}
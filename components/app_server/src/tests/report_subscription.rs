@@ -0,0 +1,156 @@
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use proto::crypto::{PublicKey, Uid};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppSubscription, AppToAppServer, FriendsFilter,
+    NodeReportMutation, ReportMutations, ReportSubscription,
+};
+use proto::funder::messages::FunderOutgoingControl;
+use proto::report::messages::{
+    AddFriendReport, ChannelConsistentReport, ChannelStatusReport, FriendReportMutation,
+    FunderReportMutation, FunderReportMutations,
+};
+
+use super::utils::{dummy_named_relay_address, spawn_dummy_app_server};
+use crate::server::IncomingAppConnection;
+
+fn add_friend_mutation(friend_public_key: &PublicKey) -> FunderReportMutation<u32> {
+    FunderReportMutation::AddFriend(AddFriendReport {
+        friend_public_key: friend_public_key.clone(),
+        name: "friend".to_owned(),
+        relays: Vec::new(),
+        opt_last_incoming_move_token: None,
+        channel_status: ChannelStatusReport::Consistent(ChannelConsistentReport {
+            currency_reports: Vec::new(),
+        }),
+    })
+}
+
+async fn task_app_server_loop_report_subscription<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        _funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(1);
+    let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+
+    let app_permissions = AppPermissions {
+        routes: false,
+        buyer: false,
+        seller: false,
+        config: false,
+        reports: true,
+        publish: false,
+    };
+
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
+        app_permissions,
+        app_subscriptions: AppSubscription::all(),
+        report_sender,
+    };
+
+    connections_sender
+        .send(incoming_app_connection)
+        .await
+        .unwrap();
+
+    let (_report, conn_sender) = report_receiver.await.unwrap();
+    conn_sender.send(server_conn_pair).unwrap();
+
+    let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+    let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+
+    // The app is only interested in the balances of friend A:
+    let app_request_id = Uid::from(&[21; Uid::len()]);
+    app_sender
+        .send(AppToAppServer::new(
+            app_request_id.clone(),
+            AppRequest::SetReportSubscription(ReportSubscription {
+                subscriptions: vec![AppSubscription::FriendBalances],
+                friends: FriendsFilter::Only(vec![pk_a.clone()]),
+            }),
+        ))
+        .await
+        .unwrap();
+
+    // The request is acknowledged:
+    let to_app_message = app_receiver.next().await.unwrap();
+    assert_eq!(
+        to_app_message,
+        AppServerToApp::ReportMutations(ReportMutations {
+            opt_app_request_id: Some(app_request_id),
+            mutations: Vec::new(),
+        })
+    );
+
+    let channel_status = ChannelStatusReport::Consistent(ChannelConsistentReport {
+        currency_reports: Vec::new(),
+    });
+    let mutations = vec![
+        add_friend_mutation(&pk_a),
+        add_friend_mutation(&pk_b),
+        FunderReportMutation::AddRelay(dummy_named_relay_address(3)),
+        FunderReportMutation::PkFriendReportMutation((
+            pk_a.clone(),
+            FriendReportMutation::SetName("friend_a".to_owned()),
+        )),
+        FunderReportMutation::PkFriendReportMutation((
+            pk_b.clone(),
+            FriendReportMutation::SetChannelStatus(channel_status.clone()),
+        )),
+        FunderReportMutation::PkFriendReportMutation((
+            pk_a.clone(),
+            FriendReportMutation::SetChannelStatus(channel_status.clone()),
+        )),
+    ];
+    funder_sender
+        .send(FunderOutgoingControl::ReportMutations(
+            FunderReportMutations {
+                opt_app_request_id: None,
+                mutations,
+            },
+        ))
+        .await
+        .unwrap();
+
+    // Only balance related mutations of friend A reach the app:
+    let to_app_message = app_receiver.next().await.unwrap();
+    assert_eq!(
+        to_app_message,
+        AppServerToApp::ReportMutations(ReportMutations {
+            opt_app_request_id: None,
+            mutations: vec![
+                NodeReportMutation::Funder(add_friend_mutation(&pk_a)),
+                NodeReportMutation::Funder(FunderReportMutation::PkFriendReportMutation((
+                    pk_a,
+                    FriendReportMutation::SetChannelStatus(channel_status),
+                ))),
+            ],
+        })
+    );
+}
+
+#[test]
+fn test_app_server_loop_report_subscription() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_report_subscription(
+        thread_pool.clone(),
+    ));
+}
@@ -0,0 +1,169 @@
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use proto::crypto::{PublicKey, Uid};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppSubscription, AppToAppServer,
+};
+use proto::funder::messages::{
+    FriendStatus, FunderControl, FunderOutgoingControl, RemoveFriend, RequestError,
+    RequestErrorCode, SetFriendStatus,
+};
+use proto::report::messages::FunderReportMutations;
+
+use super::utils::spawn_dummy_app_server;
+use crate::server::IncomingAppConnection;
+
+async fn task_app_server_loop_request_error<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(1);
+    let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+
+    let app_permissions = AppPermissions {
+        routes: false,
+        buyer: false,
+        seller: false,
+        config: true,
+        reports: false,
+        publish: false,
+    };
+
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
+        app_permissions,
+        app_subscriptions: AppSubscription::all(),
+        report_sender,
+    };
+
+    connections_sender
+        .send(incoming_app_connection)
+        .await
+        .unwrap();
+
+    let (_report, conn_sender) = report_receiver.await.unwrap();
+    conn_sender.send(server_conn_pair).unwrap();
+
+    let friend_public_key = PublicKey::from(&[0xee; PublicKey::len()]);
+    let app_request = AppToAppServer::new(
+        Uid::from(&[21; Uid::len()]),
+        AppRequest::EnableFriend(friend_public_key.clone()),
+    );
+    app_sender.send(app_request).await.unwrap();
+
+    let to_funder_message = funder_receiver.next().await.unwrap();
+    assert_eq!(
+        to_funder_message.funder_control,
+        FunderControl::SetFriendStatus(SetFriendStatus {
+            friend_public_key: friend_public_key.clone(),
+            status: FriendStatus::Enabled,
+        })
+    );
+
+    // An error that does not match any open request is discarded:
+    funder_sender
+        .send(FunderOutgoingControl::RequestError(RequestError {
+            app_request_id: Uid::from(&[20; Uid::len()]),
+            error_code: RequestErrorCode::FriendNotFound,
+            detail: "FriendDoesNotExist".to_owned(),
+        }))
+        .await
+        .unwrap();
+
+    // The funder could not find the friend.
+    // The error is sent to the app, followed by the acknowledgement:
+    let request_error = RequestError {
+        app_request_id: Uid::from(&[21; Uid::len()]),
+        error_code: RequestErrorCode::FriendNotFound,
+        detail: "FriendDoesNotExist".to_owned(),
+    };
+    funder_sender
+        .send(FunderOutgoingControl::RequestError(request_error.clone()))
+        .await
+        .unwrap();
+    funder_sender
+        .send(FunderOutgoingControl::ReportMutations(
+            FunderReportMutations {
+                opt_app_request_id: Some(Uid::from(&[21; Uid::len()])),
+                mutations: Vec::new(),
+            },
+        ))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        app_receiver.next().await.unwrap(),
+        AppServerToApp::RequestError(request_error)
+    );
+    match app_receiver.next().await.unwrap() {
+        AppServerToApp::ReportMutations(report_mutations) => assert_eq!(
+            report_mutations.opt_app_request_id,
+            Some(Uid::from(&[21; Uid::len()]))
+        ),
+        _ => unreachable!(),
+    };
+
+    // Errors are no longer forwarded once the request was acknowledged:
+    funder_sender
+        .send(FunderOutgoingControl::RequestError(RequestError {
+            app_request_id: Uid::from(&[21; Uid::len()]),
+            error_code: RequestErrorCode::NotReady,
+            detail: String::new(),
+        }))
+        .await
+        .unwrap();
+
+    let app_request = AppToAppServer::new(
+        Uid::from(&[22; Uid::len()]),
+        AppRequest::RemoveFriend(friend_public_key.clone()),
+    );
+    app_sender.send(app_request).await.unwrap();
+
+    let to_funder_message = funder_receiver.next().await.unwrap();
+    assert_eq!(
+        to_funder_message.funder_control,
+        FunderControl::RemoveFriend(RemoveFriend { friend_public_key })
+    );
+
+    funder_sender
+        .send(FunderOutgoingControl::ReportMutations(
+            FunderReportMutations {
+                opt_app_request_id: Some(Uid::from(&[22; Uid::len()])),
+                mutations: Vec::new(),
+            },
+        ))
+        .await
+        .unwrap();
+
+    // Only the acknowledgement of the second request arrives:
+    match app_receiver.next().await.unwrap() {
+        AppServerToApp::ReportMutations(report_mutations) => assert_eq!(
+            report_mutations.opt_app_request_id,
+            Some(Uid::from(&[22; Uid::len()]))
+        ),
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_app_server_loop_request_error() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_request_error(thread_pool.clone()));
+}
@@ -0,0 +1,112 @@
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use proto::crypto::{PublicKey, Uid};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppSubscription, AppToAppServer,
+};
+use proto::funder::messages::{FunderControl, FunderOutgoingControl, ResponseExposure};
+
+use super::utils::spawn_dummy_app_server;
+use crate::server::IncomingAppConnection;
+
+async fn task_app_server_loop_request_exposure<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(1);
+    let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+
+    let app_permissions = AppPermissions {
+        routes: false,
+        buyer: false,
+        seller: false,
+        config: false,
+        reports: true,
+        publish: false,
+    };
+
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
+        app_permissions,
+        app_subscriptions: AppSubscription::all(),
+        report_sender,
+    };
+
+    connections_sender
+        .send(incoming_app_connection)
+        .await
+        .unwrap();
+
+    let (_report, conn_sender) = report_receiver.await.unwrap();
+    conn_sender.send(server_conn_pair).unwrap();
+
+    let app_request = AppToAppServer::new(
+        Uid::from(&[21; Uid::len()]),
+        AppRequest::RequestExposure(Uid::from(&[3; Uid::len()])),
+    );
+    app_sender.send(app_request).await.unwrap();
+
+    // The request should be forwarded to the funder:
+    let to_funder_message = funder_receiver.next().await.unwrap();
+    assert_eq!(
+        to_funder_message.app_request_id,
+        Uid::from(&[21; Uid::len()])
+    );
+    assert_eq!(
+        to_funder_message.funder_control,
+        FunderControl::RequestExposure(Uid::from(&[3; Uid::len()]))
+    );
+
+    // A response that does not match any open request is discarded:
+    let response_exposure = ResponseExposure {
+        request_id: Uid::from(&[2; Uid::len()]),
+        friends: Vec::new(),
+        totals: Vec::new(),
+    };
+    funder_sender
+        .send(FunderOutgoingControl::ResponseExposure(response_exposure))
+        .await
+        .unwrap();
+
+    let response_exposure = ResponseExposure {
+        request_id: Uid::from(&[3; Uid::len()]),
+        friends: Vec::new(),
+        totals: Vec::new(),
+    };
+    funder_sender
+        .send(FunderOutgoingControl::ResponseExposure(
+            response_exposure.clone(),
+        ))
+        .await
+        .unwrap();
+
+    // Only the matching response arrives at the app:
+    let to_app_message = app_receiver.next().await.unwrap();
+    assert_eq!(
+        to_app_message,
+        AppServerToApp::ResponseExposure(response_exposure)
+    );
+}
+
+#[test]
+fn test_app_server_loop_request_exposure() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_request_exposure(thread_pool.clone()));
+}
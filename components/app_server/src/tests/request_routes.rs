@@ -9,12 +9,15 @@ use common::conn::ConnPair;
 
 use proto::crypto::{PublicKey, Uid};
 
-use proto::app_server::messages::{AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppSubscription, AppToAppServer,
+};
 use proto::funder::messages::Currency;
 use proto::index_client::messages::{
     AppServerToIndexClient, ClientResponseRoutes, IndexClientRequest, IndexClientToAppServer,
     RequestRoutes, ResponseRoutesResult,
 };
+use proto::index_server::messages::{RouteConstraints, RouteRanking};
 
 use super::utils::spawn_dummy_app_server;
 use crate::server::IncomingAppConnection;
@@ -41,11 +44,15 @@ where
         buyer: true,
         seller: true,
         config: true,
+        reports: true,
+        publish: false,
     };
 
     let (report_sender, report_receiver) = oneshot::channel();
     let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
         app_permissions,
+        app_subscriptions: AppSubscription::all(),
         report_sender,
     };
 
@@ -68,10 +75,14 @@ where
         buyer: true,
         seller: true,
         config: true,
+        reports: true,
+        publish: false,
     };
     let (report_sender, report_receiver) = oneshot::channel();
     let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa1; PublicKey::len()]),
         app_permissions,
+        app_subscriptions: AppSubscription::all(),
         report_sender,
     };
 
@@ -96,6 +107,8 @@ where
         source: PublicKey::from(&[0xee; PublicKey::len()]),
         destination: PublicKey::from(&[0xff; PublicKey::len()]),
         opt_exclude: None,
+        constraints: RouteConstraints::default(),
+        ranking: RouteRanking::default(),
     };
 
     let to_app_server = AppToAppServer::new(
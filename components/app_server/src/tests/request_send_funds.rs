@@ -9,7 +9,9 @@ use common::conn::ConnPair;
 
 use proto::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
 
-use proto::app_server::messages::{AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppSubscription, AppToAppServer,
+};
 use proto::funder::messages::{
     CreatePayment, CreateTransaction, Currency, FriendsRoute, FunderControl, FunderOutgoingControl,
     RequestResult, TransactionResult,
@@ -40,11 +42,15 @@ where
         buyer: true,
         seller: true,
         config: true,
+        reports: true,
+        publish: false,
     };
 
     let (report_sender, report_receiver) = oneshot::channel();
     let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
         app_permissions,
+        app_subscriptions: AppSubscription::all(),
         report_sender,
     };
 
@@ -67,11 +73,15 @@ where
         buyer: true,
         seller: true,
         config: true,
+        reports: true,
+        publish: false,
     };
 
     let (report_sender, report_receiver) = oneshot::channel();
     let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa1; PublicKey::len()]),
         app_permissions,
+        app_subscriptions: AppSubscription::all(),
         report_sender,
     };
 
@@ -97,6 +107,7 @@ where
         currency: currency1.clone(),
         total_dest_payment: 20,
         dest_public_key: pk_f.clone(),
+        opt_max_fee_per_hop: None,
     };
     let to_app_server = AppToAppServer::new(
         Uid::from(&[22; Uid::len()]),
@@ -125,6 +136,7 @@ where
         },
         dest_payment: 20,
         fees: 4,
+        opt_refund_ticks: None,
     };
     let to_app_server = AppToAppServer::new(
         Uid::from(&[23; Uid::len()]),
@@ -0,0 +1,150 @@
+use std::convert::TryFrom;
+
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use proto::crypto::{PublicKey, Uid};
+
+use proto::funder::messages::{
+    Currency, FriendsRoute, FunderControl, FunderOutgoingControl, Rate, RequestAlternativeRoute,
+};
+use proto::index_client::messages::{
+    AppServerToIndexClient, ClientResponseRoutes, IndexClientRequest, IndexClientToAppServer,
+    ResponseRoutesResult,
+};
+use proto::index_server::messages::{Edge, MultiRoute, RouteCapacityRate};
+
+use super::utils::spawn_dummy_app_server;
+
+async fn task_app_server_loop_retry_transaction<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        mut index_client_sender,
+        mut index_client_receiver,
+        _connections_sender,
+        initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let local_public_key = initial_node_report.funder_report.local_public_key.clone();
+    let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+
+    // Funder asks for an alternative route:
+    let request_alternative_route = RequestAlternativeRoute {
+        request_id: Uid::from(&[3; Uid::len()]),
+        routes_request_id: Uid::from(&[4; Uid::len()]),
+        currency: currency1.clone(),
+        dest_public_key: PublicKey::from(&[0xff; PublicKey::len()]),
+        dest_payment: 20,
+        max_fees: 4,
+        exclude_friend: PublicKey::from(&[0xee; PublicKey::len()]),
+    };
+    funder_sender
+        .send(FunderOutgoingControl::RequestAlternativeRoute(
+            request_alternative_route,
+        ))
+        .await
+        .unwrap();
+
+    // A RequestRoutes command should be sent to the IndexClient:
+    let to_index_client_message = index_client_receiver.next().await.unwrap();
+    match to_index_client_message {
+        AppServerToIndexClient::AppRequest((
+            app_request_id,
+            IndexClientRequest::RequestRoutes(request_routes),
+        )) => {
+            assert_eq!(app_request_id, Uid::from(&[4; Uid::len()]));
+            assert_eq!(request_routes.request_id, Uid::from(&[4; Uid::len()]));
+            assert_eq!(request_routes.currency, currency1);
+            assert_eq!(request_routes.capacity, 20);
+            assert_eq!(request_routes.source, local_public_key);
+            assert_eq!(
+                request_routes.destination,
+                PublicKey::from(&[0xff; PublicKey::len()])
+            );
+            assert_eq!(
+                request_routes.opt_exclude,
+                Some(Edge {
+                    from_public_key: local_public_key.clone(),
+                    to_public_key: PublicKey::from(&[0xee; PublicKey::len()]),
+                })
+            );
+        }
+        _ => unreachable!(),
+    };
+
+    // IndexClient returns three routes:
+    // - The first one does not have enough capacity to pay the fees.
+    // - The second one is too expensive.
+    // - Only the third one can be used.
+    let small_route = FriendsRoute {
+        public_keys: vec![
+            local_public_key.clone(),
+            PublicKey::from(&[0xcc; PublicKey::len()]),
+            PublicKey::from(&[0xff; PublicKey::len()]),
+        ],
+    };
+    let expensive_route = FriendsRoute {
+        public_keys: vec![
+            local_public_key.clone(),
+            PublicKey::from(&[0xdd; PublicKey::len()]),
+            PublicKey::from(&[0xff; PublicKey::len()]),
+        ],
+    };
+    let large_route = FriendsRoute {
+        public_keys: vec![
+            local_public_key.clone(),
+            PublicKey::from(&[0xbb; PublicKey::len()]),
+            PublicKey::from(&[0xff; PublicKey::len()]),
+        ],
+    };
+    let multi_route = MultiRoute {
+        routes: vec![
+            RouteCapacityRate {
+                route: small_route,
+                capacity: 21,
+                rate: Rate { mul: 0, add: 2 },
+            },
+            RouteCapacityRate {
+                route: expensive_route,
+                capacity: 100,
+                rate: Rate { mul: 0, add: 5 },
+            },
+            RouteCapacityRate {
+                route: large_route.clone(),
+                capacity: 30,
+                rate: Rate { mul: 0, add: 3 },
+            },
+        ],
+    };
+    let client_response_routes = ClientResponseRoutes {
+        request_id: Uid::from(&[4; Uid::len()]),
+        result: ResponseRoutesResult::Success(vec![multi_route]),
+    };
+    index_client_sender
+        .send(IndexClientToAppServer::ResponseRoutes(
+            client_response_routes,
+        ))
+        .await
+        .unwrap();
+
+    // The route should be forwarded to the Funder:
+    let to_funder_message = funder_receiver.next().await.unwrap();
+    match to_funder_message.funder_control {
+        FunderControl::RetryTransaction(retry_transaction) => {
+            assert_eq!(retry_transaction.request_id, Uid::from(&[3; Uid::len()]));
+            assert_eq!(retry_transaction.opt_route, Some((large_route, 3)));
+        }
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_app_server_loop_retry_transaction() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_retry_transaction(thread_pool.clone()));
+}
@@ -0,0 +1,124 @@
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use proto::crypto::{PublicKey, Uid};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppSubscription, AppToAppServer, SetNodeConfig,
+};
+use proto::funder::messages::{FunderControl, RequestErrorCode, SetFunderConfig};
+use proto::index_client::messages::{AppServerToIndexClient, IndexClientRequest};
+
+use super::utils::spawn_dummy_app_server;
+use crate::server::IncomingAppConnection;
+
+async fn task_app_server_loop_set_node_config<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        _funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        mut index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+    let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+
+    let app_permissions = AppPermissions {
+        routes: false,
+        buyer: false,
+        seller: false,
+        config: true,
+        reports: true,
+        publish: false,
+    };
+
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
+        app_permissions,
+        app_subscriptions: AppSubscription::all(),
+        report_sender,
+    };
+
+    connections_sender
+        .send(incoming_app_connection)
+        .await
+        .unwrap();
+
+    let (_report, conn_sender) = report_receiver.await.unwrap();
+    conn_sender.send(server_conn_pair).unwrap();
+
+    // Zero values are rejected, and are not forwarded:
+    let set_node_config = AppToAppServer::new(
+        Uid::from(&[21; Uid::len()]),
+        AppRequest::SetNodeConfig(SetNodeConfig::MaxOperationsInBatch(0)),
+    );
+    app_sender.send(set_node_config).await.unwrap();
+
+    match app_receiver.next().await.unwrap() {
+        AppServerToApp::RequestError(request_error) => {
+            assert_eq!(request_error.app_request_id, Uid::from(&[21; Uid::len()]));
+            assert_eq!(request_error.error_code, RequestErrorCode::InvalidRequest);
+        }
+        _ => unreachable!(),
+    };
+    match app_receiver.next().await.unwrap() {
+        AppServerToApp::ReportMutations(report_mutations) => {
+            assert_eq!(
+                report_mutations.opt_app_request_id,
+                Some(Uid::from(&[21; Uid::len()]))
+            );
+            assert!(report_mutations.mutations.is_empty());
+        }
+        _ => unreachable!(),
+    };
+
+    // Funder parameters are forwarded to the funder:
+    let set_node_config = AppToAppServer::new(
+        Uid::from(&[22; Uid::len()]),
+        AppRequest::SetNodeConfig(SetNodeConfig::MaxOperationsInBatch(16)),
+    );
+    app_sender.send(set_node_config).await.unwrap();
+
+    let to_funder_message = funder_receiver.next().await.unwrap();
+    assert_eq!(
+        to_funder_message.app_request_id,
+        Uid::from(&[22; Uid::len()])
+    );
+    assert_eq!(
+        to_funder_message.funder_control,
+        FunderControl::SetConfig(SetFunderConfig::MaxOperationsInBatch(16))
+    );
+
+    // Index client parameters are forwarded to the index client:
+    let set_node_config = AppToAppServer::new(
+        Uid::from(&[23; Uid::len()]),
+        AppRequest::SetNodeConfig(SetNodeConfig::IndexKeepaliveTicks(20)),
+    );
+    app_sender.send(set_node_config).await.unwrap();
+
+    let to_index_client_message = index_client_receiver.next().await.unwrap();
+    assert_eq!(
+        to_index_client_message,
+        AppServerToIndexClient::AppRequest((
+            Uid::from(&[23; Uid::len()]),
+            IndexClientRequest::SetKeepaliveTicks(20)
+        ))
+    );
+}
+
+#[test]
+fn test_app_server_loop_set_node_config() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_set_node_config(thread_pool.clone()));
+}
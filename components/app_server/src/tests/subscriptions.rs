@@ -0,0 +1,128 @@
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use proto::crypto::{PublicKey, Uid};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppSubscription, AppToAppServer,
+};
+use proto::index_client::messages::{
+    AppServerToIndexClient, IndexClientReportMutation, IndexClientReportMutations,
+    IndexClientRequest, IndexClientToAppServer,
+};
+use proto::index_server::messages::NamedIndexServerAddress;
+
+use super::utils::spawn_dummy_app_server;
+use crate::server::IncomingAppConnection;
+
+async fn task_app_server_loop_subscriptions<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        _funder_sender,
+        _funder_receiver,
+        mut index_client_sender,
+        mut index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(1);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(1);
+    let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+
+    let app_permissions = AppPermissions {
+        routes: true,
+        buyer: true,
+        seller: true,
+        config: true,
+        reports: true,
+        publish: false,
+    };
+
+    // The app is only interested in funder reports:
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
+        app_permissions,
+        app_subscriptions: vec![AppSubscription::FunderReport],
+        report_sender,
+    };
+
+    connections_sender
+        .send(incoming_app_connection)
+        .await
+        .unwrap();
+
+    let (_report, conn_sender) = report_receiver.await.unwrap();
+    conn_sender.send(server_conn_pair).unwrap();
+
+    let named_index_server_address = NamedIndexServerAddress {
+        public_key: PublicKey::from(&[0xaa; PublicKey::len()]),
+        address: 300u32,
+        name: "IndexServer300".to_string(),
+    };
+
+    // The app adds an index server:
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[3; Uid::len()]),
+        AppRequest::AddIndexServer(named_index_server_address.clone()),
+    );
+    app_sender.send(to_app_server).await.unwrap();
+
+    match index_client_receiver.next().await.unwrap() {
+        AppServerToIndexClient::AppRequest((
+            app_request_id,
+            IndexClientRequest::AddIndexServer(_),
+        )) => assert_eq!(app_request_id, Uid::from(&[3; Uid::len()])),
+        _ => unreachable!(),
+    };
+
+    let index_client_report_mutation =
+        IndexClientReportMutation::AddIndexServer(named_index_server_address);
+
+    // Index client mutations should not reach the app:
+    index_client_sender
+        .send(IndexClientToAppServer::ReportMutations(
+            IndexClientReportMutations {
+                opt_app_request_id: None,
+                mutations: vec![index_client_report_mutation.clone()],
+            },
+        ))
+        .await
+        .unwrap();
+
+    // Mutations caused by the app's request are still acknowledged:
+    index_client_sender
+        .send(IndexClientToAppServer::ReportMutations(
+            IndexClientReportMutations {
+                opt_app_request_id: Some(Uid::from(&[3; Uid::len()])),
+                mutations: vec![index_client_report_mutation],
+            },
+        ))
+        .await
+        .unwrap();
+
+    let to_app_message = app_receiver.next().await.unwrap();
+    match to_app_message {
+        AppServerToApp::ReportMutations(report_mutations) => {
+            assert_eq!(
+                report_mutations.opt_app_request_id,
+                Some(Uid::from(&[3; Uid::len()]))
+            );
+            assert!(report_mutations.mutations.is_empty());
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_app_server_loop_subscriptions() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_subscriptions(thread_pool.clone()));
+}
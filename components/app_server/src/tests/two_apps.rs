@@ -5,15 +5,21 @@ use futures::{SinkExt, StreamExt};
 
 use common::conn::ConnPair;
 
-use proto::crypto::PublicKey;
+use proto::crypto::{PublicKey, Uid};
 
-use proto::app_server::messages::{AppPermissions, AppServerToApp, NodeReportMutation};
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppSubscription, AppToAppServer,
+    NodeReportMutation, ReportMutations,
+};
+use proto::funder::messages::{
+    FunderControl, FunderOutgoingControl, FunderReportMutation, FunderReportMutations,
+};
 use proto::index_client::messages::{
     IndexClientReportMutation, IndexClientReportMutations, IndexClientToAppServer,
 };
 use proto::index_server::messages::NamedIndexServerAddress;
 
-use super::utils::spawn_dummy_app_server;
+use super::utils::{dummy_named_relay_address, spawn_dummy_app_server};
 use crate::server::IncomingAppConnection;
 
 async fn task_app_server_loop_two_apps<S>(spawner: S)
@@ -21,15 +27,15 @@ where
     S: Spawn + Clone + Send + 'static,
 {
     let (
-        _funder_sender,
-        _funder_receiver,
+        mut funder_sender,
+        mut funder_receiver,
         mut index_client_sender,
         _index_client_receiver,
         mut connections_sender,
         initial_node_report,
     ) = spawn_dummy_app_server(spawner.clone());
 
-    let (_app_sender0, app_server_receiver) = mpsc::channel(1);
+    let (mut app_sender0, app_server_receiver) = mpsc::channel(1);
     let (app_server_sender, mut app_receiver0) = mpsc::channel(1);
     let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
     let app_permissions = AppPermissions {
@@ -37,11 +43,15 @@ where
         buyer: true,
         seller: true,
         config: true,
+        reports: true,
+        publish: false,
     };
 
     let (report_sender, report_receiver) = oneshot::channel();
     let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
         app_permissions,
+        app_subscriptions: AppSubscription::all(),
         report_sender,
     };
 
@@ -64,11 +74,15 @@ where
         buyer: true,
         seller: true,
         config: true,
+        reports: true,
+        publish: false,
     };
 
     let (report_sender, report_receiver) = oneshot::channel();
     let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa1; PublicKey::len()]),
         app_permissions,
+        app_subscriptions: AppSubscription::all(),
         report_sender,
     };
 
@@ -154,6 +168,50 @@ where
         }
         _ => unreachable!(),
     }
+
+    // The first app sends a request to the funder:
+    let app_request_id = Uid::from(&[22; Uid::len()]);
+    app_sender0
+        .send(AppToAppServer::new(
+            app_request_id.clone(),
+            AppRequest::AddRelay(dummy_named_relay_address(0)),
+        ))
+        .await
+        .unwrap();
+
+    let to_funder_message = funder_receiver.next().await.unwrap();
+    assert_eq!(to_funder_message.app_request_id, app_request_id);
+    match to_funder_message.funder_control {
+        FunderControl::AddRelay(_) => {}
+        _ => unreachable!(),
+    };
+
+    let funder_report_mutation = FunderReportMutation::AddRelay(dummy_named_relay_address(0));
+    funder_sender
+        .send(FunderOutgoingControl::ReportMutations(
+            FunderReportMutations {
+                opt_app_request_id: Some(app_request_id.clone()),
+                mutations: vec![funder_report_mutation.clone()],
+            },
+        ))
+        .await
+        .unwrap();
+
+    // Both apps get the mutations, but only the first app gets the acknowledgement:
+    assert_eq!(
+        app_receiver0.next().await.unwrap(),
+        AppServerToApp::ReportMutations(ReportMutations {
+            opt_app_request_id: Some(app_request_id),
+            mutations: vec![NodeReportMutation::Funder(funder_report_mutation.clone())],
+        })
+    );
+    assert_eq!(
+        app_receiver1.next().await.unwrap(),
+        AppServerToApp::ReportMutations(ReportMutations {
+            opt_app_request_id: None,
+            mutations: vec![NodeReportMutation::Funder(funder_report_mutation)],
+        })
+    );
 }
 
 #[test]
@@ -2,7 +2,9 @@ use std::collections::HashMap;
 
 use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
-use futures::{FutureExt, TryFutureExt};
+use futures::{FutureExt, StreamExt, TryFutureExt};
+
+use database::{DatabaseClient, DatabaseRequest};
 
 use proto::crypto::PublicKey;
 
@@ -14,6 +16,8 @@ use proto::index_client::messages::{
 use proto::index_server::messages::NamedIndexServerAddress;
 use proto::report::messages::FunderReport;
 
+use crate::balance_history::BalanceHistoryConfig;
+use crate::receipt_archive::{ReceiptArchive, ReceiptArchiveMutation};
 use crate::server::{app_server_loop, IncomingAppConnection};
 
 /// A helper function to quickly create a dummy NamedRelayAddress.
@@ -45,6 +49,83 @@ pub fn spawn_dummy_app_server<S>(
     mpsc::Sender<IncomingAppConnection<u32>>,
     NodeReport<u32>,
 )
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let balance_history_config = BalanceHistoryConfig {
+        snapshot_ticks: 0,
+        max_snapshots: 0,
+    };
+    let (
+        funder_sender,
+        funder_receiver,
+        index_client_sender,
+        index_client_receiver,
+        connections_sender,
+        initial_node_report,
+        _tick_sender,
+    ) = spawn_dummy_app_server_with_timer(balance_history_config, spawner);
+
+    (
+        funder_sender,
+        funder_receiver,
+        index_client_sender,
+        index_client_receiver,
+        connections_sender,
+        initial_node_report,
+    )
+}
+
+/// Like `spawn_dummy_app_server`, but also returns a sender of timer ticks.
+pub fn spawn_dummy_app_server_with_timer<S>(
+    balance_history_config: BalanceHistoryConfig,
+    spawner: S,
+) -> (
+    mpsc::Sender<FunderOutgoingControl<u32>>,
+    mpsc::Receiver<FunderIncomingControl<u32>>,
+    mpsc::Sender<IndexClientToAppServer<u32>>,
+    mpsc::Receiver<AppServerToIndexClient<u32>>,
+    mpsc::Sender<IncomingAppConnection<u32>>,
+    NodeReport<u32>,
+    mpsc::Sender<()>,
+)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    // A database that accepts all mutations:
+    let (db_request_sender, mut db_request_receiver) = mpsc::channel(0);
+    let db_fut = async move {
+        while let Some(request) = db_request_receiver.next().await {
+            if let DatabaseRequest::Mutate(mutate_request) = request {
+                let _ = mutate_request.response_sender.send(());
+            }
+        }
+    };
+    spawner.spawn(db_fut).unwrap();
+
+    spawn_dummy_app_server_with_db(
+        balance_history_config,
+        ReceiptArchive::new(),
+        DatabaseClient::new(db_request_sender),
+        spawner,
+    )
+}
+
+/// Like `spawn_dummy_app_server_with_timer`, but with a given receipt archive and database client.
+pub fn spawn_dummy_app_server_with_db<S>(
+    balance_history_config: BalanceHistoryConfig,
+    receipt_archive: ReceiptArchive,
+    db_client: DatabaseClient<ReceiptArchiveMutation>,
+    spawner: S,
+) -> (
+    mpsc::Sender<FunderOutgoingControl<u32>>,
+    mpsc::Receiver<FunderIncomingControl<u32>>,
+    mpsc::Sender<IndexClientToAppServer<u32>>,
+    mpsc::Receiver<AppServerToIndexClient<u32>>,
+    mpsc::Sender<IncomingAppConnection<u32>>,
+    NodeReport<u32>,
+    mpsc::Sender<()>,
+)
 where
     S: Spawn + Clone + Send + 'static,
 {
@@ -56,6 +137,8 @@ where
 
     let (connections_sender, incoming_connections) = mpsc::channel(0);
 
+    let (tick_sender, timer_stream) = mpsc::channel::<()>(0);
+
     // Create a dummy initial_node_report:
     let funder_report = FunderReport {
         local_public_key: PublicKey::from(&[0xaa; PublicKey::len()]),
@@ -63,6 +146,8 @@ where
             .into_iter()
             .collect(),
         friends: HashMap::new(),
+        relay_latencies: Vec::new(),
+        rebalance_suggestions: Vec::new(),
     };
 
     let server100 = NamedIndexServerAddress {
@@ -80,6 +165,7 @@ where
     let index_client_report = IndexClientReport {
         index_servers: vec![server100, server101],
         opt_connected_server: Some(PublicKey::from(&[0xaa; PublicKey::len()])),
+        friend_proposals: Vec::new(),
     };
 
     let initial_node_report = NodeReport {
@@ -94,6 +180,10 @@ where
         to_index_client,
         incoming_connections,
         initial_node_report.clone(),
+        balance_history_config,
+        receipt_archive,
+        db_client,
+        timer_stream,
         spawner.clone(),
     )
     .map_err(|e| error!("app_server_loop() error: {:?}", e))
@@ -108,5 +198,6 @@ where
         index_client_receiver,
         connections_sender,
         initial_node_report,
+        tick_sender,
     )
 }
@@ -14,3 +14,4 @@ pub mod stindex;
 pub mod stmgrlib;
 pub mod stnode;
 pub mod strelay;
+pub mod ticks;
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::marker::Unpin;
 
@@ -12,10 +12,11 @@ use common::transform_pool::transform_pool_loop;
 use proto::consts::INDEX_NODE_TIMEOUT_TICKS;
 use proto::crypto::PublicKey;
 use proto::index_server::messages::{
-    IndexClientToServer, IndexServerToClient, IndexServerToServer,
+    IndexAdminToServer, IndexClientToServer, IndexServerDirectory, IndexServerToAdmin,
+    IndexServerToClient, IndexServerToServer,
 };
 
-use proto::proto_ser::{ProtoDeserialize, ProtoSerialize};
+use proto::proto_ser::{ProtoDeserializeChecked, ProtoSerialize};
 
 use timer::TimerClient;
 
@@ -23,9 +24,11 @@ use crypto::rand::CryptoRandom;
 
 use identity::IdentityClient;
 
+use database::DatabaseClient;
+
 use connection::create_version_encrypt_keepalive;
 
-use index_server::{index_server, IndexServerError};
+use index_server::{index_server, AdminMutation, AdminState, CapacityDecay, IndexServerError};
 
 #[derive(Clone)]
 struct ConnTransformer<CT, S> {
@@ -89,7 +92,7 @@ where
             // Deserialize received data
             let _ = c_self.spawner.spawn(async move {
                 while let Some(data) = receiver.next().await {
-                    let message = match IndexClientToServer::proto_deserialize(&data) {
+                    let message = match IndexClientToServer::proto_deserialize_checked(&data) {
                         Ok(message) => message,
                         Err(_) => {
                             error!("Error deserializing index_client_to_server");
@@ -117,6 +120,74 @@ where
         })
     }
 
+    /// Transform a raw connection from a query tool into connection with the same layers as a
+    /// client connection.
+    ///
+    /// The public key of a query tool is not used, so query tools may use a temporary identity.
+    pub fn incoming_index_query_conn_transform(
+        &mut self,
+        conn_pair: ConnPairVec,
+    ) -> BoxFuture<'_, Option<ConnPair<IndexServerToClient, IndexClientToServer>>> {
+        let mut c_self = self.clone();
+        Box::pin(async move {
+            let (_public_key, conn_pair) = c_self
+                .incoming_index_client_conn_transform(conn_pair)
+                .await?;
+            Some(conn_pair)
+        })
+    }
+
+    /// Transform a raw connection from an admin into connection with the following layers:
+    /// - Version prefix
+    /// - Encryption
+    /// - keepalives
+    /// - Serialization
+    ///
+    /// Note that the admin is not authorized here. The index server only accepts admin
+    /// connections from known public keys.
+    pub fn incoming_index_admin_conn_transform(
+        &mut self,
+        conn_pair: ConnPairVec,
+    ) -> BoxFuture<'_, Option<(PublicKey, ConnPair<IndexServerToAdmin, IndexAdminToServer>)>> {
+        let mut c_self = self.clone();
+        Box::pin(async move {
+            let (public_key, conn_pair) = c_self.version_enc_keepalive(None, conn_pair).await?;
+
+            let (mut sender, mut receiver) = conn_pair.split();
+
+            let (user_sender, mut from_user_sender) = mpsc::channel::<IndexServerToAdmin>(0);
+            let (mut to_user_receiver, user_receiver) = mpsc::channel(0);
+
+            // Deserialize received data
+            let _ = c_self.spawner.spawn(async move {
+                while let Some(data) = receiver.next().await {
+                    let message = match IndexAdminToServer::proto_deserialize_checked(&data) {
+                        Ok(message) => message,
+                        Err(_) => {
+                            error!("Error deserializing index_admin_to_server");
+                            return;
+                        }
+                    };
+                    if to_user_receiver.send(message).await.is_err() {
+                        return;
+                    }
+                }
+            });
+
+            // Serialize sent data:
+            let _ = c_self.spawner.spawn(async move {
+                while let Some(message) = from_user_sender.next().await {
+                    let data = message.proto_serialize();
+                    if sender.send(data).await.is_err() {
+                        return;
+                    }
+                }
+            });
+
+            Some((public_key, ConnPair::from_raw(user_sender, user_receiver)))
+        })
+    }
+
     pub fn incoming_index_server_conn_transform(
         &mut self,
         conn_pair: ConnPairVec,
@@ -139,7 +210,7 @@ where
             // Deserialize received data
             let _ = c_self.spawner.spawn(async move {
                 while let Some(data) = receiver.next().await {
-                    let message = match IndexServerToServer::proto_deserialize(&data) {
+                    let message = match IndexServerToServer::proto_deserialize_checked(&data) {
                         Ok(message) => message,
                         Err(_) => {
                             error!("Error deserializing index_server_to_server");
@@ -186,7 +257,7 @@ where
             // Deserialize received data
             let _ = c_self.spawner.spawn(async move {
                 while let Some(data) = receiver.next().await {
-                    let message = match IndexServerToServer::proto_deserialize(&data) {
+                    let message = match IndexServerToServer::proto_deserialize_checked(&data) {
                         Ok(message) => message,
                         Err(_) => {
                             error!("Error deserializing index_server_to_server");
@@ -222,16 +293,24 @@ pub enum NetIndexServerError {
     SpawnError,
 }
 
-pub async fn net_index_server<A, ICC, ISC, SC, R, GS, S>(
+pub async fn net_index_server<A, ICC, ISC, IAC, IQC, SC, R, GS, S>(
     incoming_client_raw_conns: ICC,
     incoming_server_raw_conns: ISC,
+    incoming_admin_raw_conns: IAC,
+    incoming_query_raw_conns: IQC,
     raw_server_net_connector: SC,
     identity_client: IdentityClient,
     timer_client: TimerClient,
     rng: R,
     trusted_servers: HashMap<PublicKey, A>,
+    admin_public_keys: HashSet<PublicKey>,
+    admin_state: AdminState,
+    admin_db_client: DatabaseClient<AdminMutation>,
+    opt_directory: Option<IndexServerDirectory>,
     max_concurrent_encrypt: usize,
     backoff_ticks: usize,
+    pow_difficulty: u8,
+    capacity_decay: CapacityDecay,
     graph_service_spawner: GS,
     spawner: S,
 ) -> Result<(), NetIndexServerError>
@@ -240,6 +319,8 @@ where
     SC: FutTransform<Input = A, Output = Option<ConnPairVec>> + Clone + Send + 'static,
     ICC: Stream<Item = ConnPairVec> + Unpin + Send + 'static,
     ISC: Stream<Item = ConnPairVec> + Unpin + Send + 'static,
+    IAC: Stream<Item = ConnPairVec> + Unpin + Send + 'static,
+    IQC: Stream<Item = ConnPairVec> + Unpin + Send + 'static,
     R: CryptoRandom + Clone + 'static,
     GS: Spawn + Send + 'static,
     S: Spawn + Clone + Send + Sync + 'static,
@@ -306,6 +387,54 @@ where
         .spawn(pool_fut)
         .map_err(|_| NetIndexServerError::SpawnError)?;
 
+    // Transform incoming admin connections:
+    let c_conn_transformer = conn_transformer.clone();
+    let incoming_admin_transform = FuncFutTransform::new(move |raw_conn| {
+        let mut c_conn_transformer = c_conn_transformer.clone();
+        Box::pin(async move {
+            c_conn_transformer
+                .incoming_index_admin_conn_transform(raw_conn)
+                .await
+        })
+    });
+    let (admin_conns_sender, incoming_admin_conns) = mpsc::channel(0);
+    let pool_fut = transform_pool_loop(
+        incoming_admin_raw_conns,
+        admin_conns_sender,
+        incoming_admin_transform,
+        max_concurrent_encrypt,
+        spawner.clone(),
+    )
+    .map_err(|e| error!("admin incoming transform_pool_loop() error: {:?}", e))
+    .map(|_| ());
+    spawner
+        .spawn(pool_fut)
+        .map_err(|_| NetIndexServerError::SpawnError)?;
+
+    // Transform incoming query connections:
+    let c_conn_transformer = conn_transformer.clone();
+    let incoming_query_transform = FuncFutTransform::new(move |raw_conn| {
+        let mut c_conn_transformer = c_conn_transformer.clone();
+        Box::pin(async move {
+            c_conn_transformer
+                .incoming_index_query_conn_transform(raw_conn)
+                .await
+        })
+    });
+    let (query_conns_sender, incoming_query_conns) = mpsc::channel(0);
+    let pool_fut = transform_pool_loop(
+        incoming_query_raw_conns,
+        query_conns_sender,
+        incoming_query_transform,
+        max_concurrent_encrypt,
+        spawner.clone(),
+    )
+    .map_err(|e| error!("query incoming transform_pool_loop() error: {:?}", e))
+    .map(|_| ());
+    spawner
+        .spawn(pool_fut)
+        .map_err(|_| NetIndexServerError::SpawnError)?;
+
     // Apply transform to create server connector:
     let c_conn_transformer = conn_transformer.clone();
     let server_connector = FuncFutTransform::new(move |(public_key, net_address)| {
@@ -322,12 +451,20 @@ where
     index_server(
         local_public_key,
         trusted_servers,
+        admin_public_keys,
+        admin_state,
+        admin_db_client,
+        opt_directory,
         incoming_server_conns,
         incoming_client_conns,
+        incoming_admin_conns,
+        incoming_query_conns,
         server_connector,
         timer_client,
         INDEX_NODE_TIMEOUT_TICKS,
         backoff_ticks,
+        pow_difficulty,
+        capacity_decay,
         rng,
         graph_service_spawner,
         spawner.clone(),
@@ -1,34 +1,41 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use std::fs;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
 
+use futures::channel::mpsc;
 use futures::executor::{block_on, ThreadPool};
 use futures::task::SpawnExt;
+use futures::{FutureExt, TryFutureExt};
 
 use structopt::StructOpt;
 
 use common::conn::Listener;
-use common::int_convert::usize_to_u64;
 
 use crypto::identity::SoftwareEd25519Identity;
 use crypto::rand::system_random;
 
 use identity::{create_identity, IdentityClient};
 
+use database::file_db::FileDb;
+use database::{database_loop, AtomicDb, DatabaseClient};
+
+use index_server::{AdminState, CapacityDecay};
+
 use derive_more::From;
 
 use crate::stindex::net_index::{net_index_server, NetIndexServerError};
-use proto::consts::{MAX_FRAME_LENGTH, TICK_MS};
-use timer::create_timer;
+use crate::ticks::create_bin_timer;
+use proto::consts::MAX_FRAME_LENGTH;
+use proto::crypto::PublicKey;
+use proto::index_server::messages::IndexServerDirectory;
 
 use net::{TcpConnector, TcpListener};
 
 // use proto::file::identity::load_identity_from_file;
 // use proto::file::index_server::{load_trusted_servers, IndexServerDirectoryError};
-use proto::file::{IdentityFile, IndexServerFile};
+use proto::file::{IdentityFile, IndexAdminFile, IndexServerFile};
 use proto::ser_string::{deserialize_from_string, StringSerdeError};
 
 // TODO: Maybe take as a command line argument in the future?
@@ -37,6 +44,15 @@ use proto::ser_string::{deserialize_from_string, StringSerdeError};
 pub const MAX_CONCURRENT_ENCRYPT: usize = 0x200;
 /// Amount of ticks we wait before attempting to reconnect to a remote index server.
 pub const BACKOFF_TICKS: usize = 0x8;
+/// Proof of work difficulty (Amount of leading zero bits) required from nodes that send
+/// mutations at a low rate. Nodes that send mutations at a higher rate have to meet a higher
+/// difficulty.
+pub const POW_DIFFICULTY: u8 = 8;
+/// Default amount of ticks it takes for a capacity reported by a node to decay to half, if the
+/// capacity is not updated.
+pub const DECAY_HALF_LIFE: u64 = 0x100;
+/// Default amount of ticks after which an edge that was not updated is removed.
+pub const DECAY_HORIZON: u64 = 0x800;
 
 /// stindex: Offst Index Server
 /// A server used to index the Offst network. Collects topology information from nodes, and serves
@@ -56,6 +72,38 @@ pub struct StIndexCmd {
     /// Directory path of trusted index servers
     #[structopt(parse(from_os_str), short = "t", long = "trusted")]
     pub trusted: PathBuf,
+    /// Take timer ticks from stdin instead of the internal clock.
+    /// Every line is an amount of ticks (An empty line is a single tick).
+    #[structopt(long = "stdin_ticks")]
+    pub stdin_ticks: bool,
+    /// Amount of ticks it takes for a reported capacity to decay to half, if it is not updated.
+    /// Zero means that capacities never decay.
+    #[structopt(long = "decay_half_life")]
+    pub decay_half_life: Option<u64>,
+    /// Amount of ticks after which an edge that was not updated is removed
+    #[structopt(long = "decay_horizon")]
+    pub decay_horizon: Option<u64>,
+    /// Listening address for admins.
+    /// The admin interface is disabled if no address is provided.
+    #[structopt(long = "ladmin")]
+    pub ladmin: Option<SocketAddr>,
+    /// Directory path of admin tickets (Created using `stmgr admin-ticket`)
+    #[structopt(parse(from_os_str), long = "admins")]
+    pub admins: Option<PathBuf>,
+    /// Path of the admin database file, keeping the journal of changes applied by admins and the
+    /// nodes and edges they removed. Created if it does not exist.
+    /// Required if an admin listening address is provided.
+    #[structopt(parse(from_os_str), long = "admin_db")]
+    pub admin_db: Option<PathBuf>,
+    /// Listening address for read only route queries.
+    /// Queries do not require a registered client identity, and are rate limited.
+    /// The query interface is disabled if no address is provided.
+    #[structopt(long = "lquery")]
+    pub lquery: Option<SocketAddr>,
+    /// Path of a signed index server directory file (Created using `stmgr sign-directory`),
+    /// served to nodes that request it
+    #[structopt(parse(from_os_str), long = "directory")]
+    pub directory: Option<PathBuf>,
 }
 
 #[allow(clippy::enum_variant_names)]
@@ -66,6 +114,10 @@ pub enum IndexServerBinError {
     NetIndexServerError(NetIndexServerError),
     LoadIdentityError,
     CreateIdentityError,
+    /// An admin listening address was provided without an admin database
+    MissingAdminDb,
+    LoadAdminDbError,
+    SpawnError,
     // LoadTrustedServersError(IndexServerDirectoryError),
     IoError(std::io::Error),
     StringSerdeError(StringSerdeError),
@@ -86,14 +138,47 @@ pub fn load_trusted_servers(dir_path: &Path) -> Result<Vec<IndexServerFile>, Ind
     Ok(res_trusted)
 }
 
+/// Load a directory of admin ticket files, and return the public keys of all the admins
+pub fn load_admins(dir_path: &Path) -> Result<HashSet<PublicKey>, IndexServerBinError> {
+    let mut res_admins = HashSet::new();
+    for entry in fs::read_dir(dir_path)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_dir() {
+            continue;
+        }
+        let index_admin_file: IndexAdminFile =
+            deserialize_from_string(&fs::read_to_string(&path)?)?;
+        res_admins.insert(index_admin_file.public_key);
+    }
+    Ok(res_admins)
+}
+
 pub fn stindex(st_index_cmd: StIndexCmd) -> Result<(), IndexServerBinError> {
     let StIndexCmd {
         idfile,
         lclient,
         lserver,
         trusted,
+        stdin_ticks,
+        decay_half_life,
+        decay_horizon,
+        ladmin,
+        admins,
+        admin_db,
+        lquery,
+        directory,
     } = st_index_cmd;
 
+    if ladmin.is_some() && admin_db.is_none() {
+        return Err(IndexServerBinError::MissingAdminDb);
+    }
+
+    let capacity_decay = CapacityDecay {
+        half_life: decay_half_life.unwrap_or(DECAY_HALF_LIFE),
+        horizon: decay_horizon.unwrap_or(DECAY_HORIZON),
+    };
+
     let identity_file: IdentityFile = deserialize_from_string(&fs::read_to_string(&idfile)?)?;
     let identity = SoftwareEd25519Identity::from_private_key(&identity_file.private_key)
         .map_err(|_| IndexServerBinError::LoadIdentityError)?;
@@ -103,6 +188,16 @@ pub fn stindex(st_index_cmd: StIndexCmd) -> Result<(), IndexServerBinError> {
         .map(|index_server_file| (index_server_file.public_key, index_server_file.address))
         .collect::<HashMap<_, _>>();
 
+    let admin_public_keys = match admins {
+        Some(admins) => load_admins(&admins)?,
+        None => HashSet::new(),
+    };
+
+    let opt_directory: Option<IndexServerDirectory> = match directory {
+        Some(directory) => Some(deserialize_from_string(&fs::read_to_string(&directory)?)?),
+        None => None,
+    };
+
     // Create a ThreadPool:
     let thread_pool = ThreadPool::new().map_err(|_| IndexServerBinError::CreateThreadPoolError)?;
 
@@ -110,6 +205,33 @@ pub fn stindex(st_index_cmd: StIndexCmd) -> Result<(), IndexServerBinError> {
     let graph_service_thread_pool =
         ThreadPool::new().map_err(|_| IndexServerBinError::CreateThreadPoolError)?;
 
+    // Load the admin database, and spawn a database service:
+    let (admin_state, admin_db_client) = match admin_db {
+        Some(admin_db) => {
+            let atomic_db = if admin_db.exists() {
+                FileDb::<AdminState>::load(admin_db)
+            } else {
+                FileDb::create(admin_db, AdminState::new())
+            }
+            .map_err(|_| IndexServerBinError::LoadAdminDbError)?;
+            let admin_state = atomic_db.get_state().clone();
+
+            // A thread pool for file system operations:
+            let file_system_thread_pool =
+                ThreadPool::new().map_err(|_| IndexServerBinError::CreateThreadPoolError)?;
+            let (db_request_sender, incoming_db_requests) = mpsc::channel(0);
+            let loop_fut = database_loop(atomic_db, incoming_db_requests, file_system_thread_pool)
+                .map_err(|e| error!("database_loop() error: {:?}", e))
+                .map(|_| ());
+            thread_pool
+                .spawn(loop_fut)
+                .map_err(|_| IndexServerBinError::SpawnError)?;
+            (admin_state, DatabaseClient::new(db_request_sender))
+        }
+        // No admin may connect, so the database is never used:
+        None => (AdminState::new(), DatabaseClient::new(mpsc::channel(0).0)),
+    };
+
     // Spawn identity service:
     let (sender, identity_loop) = create_identity(identity);
     thread_pool
@@ -118,8 +240,7 @@ pub fn stindex(st_index_cmd: StIndexCmd) -> Result<(), IndexServerBinError> {
     let identity_client = IdentityClient::new(sender);
 
     // Get a timer client:
-    let dur = Duration::from_millis(usize_to_u64(TICK_MS).unwrap());
-    let timer_client = create_timer(dur, thread_pool.clone())
+    let timer_client = create_bin_timer(stdin_ticks, thread_pool.clone())
         .map_err(|_| IndexServerBinError::CreateTimerError)?;
 
     // Start listening to clients:
@@ -130,6 +251,28 @@ pub fn stindex(st_index_cmd: StIndexCmd) -> Result<(), IndexServerBinError> {
     let server_tcp_listener = TcpListener::new(MAX_FRAME_LENGTH, thread_pool.clone());
     let (_config_sender, incoming_server_raw_conns) = server_tcp_listener.listen(lserver);
 
+    // Start listening to admins, if required:
+    let incoming_admin_raw_conns = match ladmin {
+        Some(ladmin) => {
+            let admin_tcp_listener = TcpListener::new(MAX_FRAME_LENGTH, thread_pool.clone());
+            let (_config_sender, incoming_admin_raw_conns) = admin_tcp_listener.listen(ladmin);
+            incoming_admin_raw_conns
+        }
+        // An empty stream of connections:
+        None => mpsc::channel(0).1,
+    };
+
+    // Start listening to route queries, if required:
+    let incoming_query_raw_conns = match lquery {
+        Some(lquery) => {
+            let query_tcp_listener = TcpListener::new(MAX_FRAME_LENGTH, thread_pool.clone());
+            let (_config_sender, incoming_query_raw_conns) = query_tcp_listener.listen(lquery);
+            incoming_query_raw_conns
+        }
+        // An empty stream of connections:
+        None => mpsc::channel(0).1,
+    };
+
     // A tcp connector, Used to connect to remote servers:
     let raw_server_net_connector = TcpConnector::new(MAX_FRAME_LENGTH, thread_pool.clone());
 
@@ -138,13 +281,21 @@ pub fn stindex(st_index_cmd: StIndexCmd) -> Result<(), IndexServerBinError> {
     let index_server_fut = net_index_server(
         incoming_client_raw_conns,
         incoming_server_raw_conns,
+        incoming_admin_raw_conns,
+        incoming_query_raw_conns,
         raw_server_net_connector,
         identity_client,
         timer_client,
         rng,
         trusted_servers,
+        admin_public_keys,
+        admin_state,
+        admin_db_client,
+        opt_directory,
         MAX_CONCURRENT_ENCRYPT,
         BACKOFF_TICKS,
+        POW_DIFFICULTY,
+        capacity_decay,
         graph_service_thread_pool,
         thread_pool,
     );
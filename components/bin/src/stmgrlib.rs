@@ -7,20 +7,31 @@ use derive_more::From;
 
 use structopt::StructOpt;
 
+use crypto::derive::{derive_private_key, DerivationPath, DerivationPathError, Seed};
 use crypto::identity::{Identity, SoftwareEd25519Identity};
 use crypto::rand::{system_random, RandGen};
 
 use proto::app_server::messages::AppPermissions;
 use proto::crypto::PrivateKey;
+use proto::limits::MAX_DIRECTORY_INDEX_SERVERS;
 use proto::net::messages::{NetAddress, NetAddressError};
+use proto::report::messages::ChannelStatusReport;
 
-use database::file_db::FileDb;
-use node::NodeState;
+use signature::index_directory::create_index_server_directory;
+use signature::key_rotation::create_key_rotation;
+
+use database::file_db::{FileDb, FileDbError, FileDbSecret};
+use database::AtomicDb;
+use node::{create_node_report, verify_node_state, NodeState, VerifyNodeStateError};
 
 use proto::file::{
-    IdentityFile, IndexServerFile, NodeAddressFile, RelayAddressFile, TrustedAppFile,
+    IdentityFile, IndexAdminFile, IndexDirectoryKeyFile, IndexServerFile, NodeAddressFile,
+    RelayAddressFile, TrustedAppFile,
+};
+use proto::index_server::messages::NamedIndexServerAddress;
+use proto::ser_string::{
+    deserialize_from_string, public_key_to_string, serialize_to_string, StringSerdeError,
 };
-use proto::ser_string::{deserialize_from_string, serialize_to_string, StringSerdeError};
 
 #[derive(Debug, From)]
 pub enum InitNodeDbError {
@@ -39,6 +50,69 @@ pub struct InitNodeDbCmd {
     /// Database output file path
     #[structopt(parse(from_os_str), short = "o", long = "output")]
     pub output_path: PathBuf,
+    /// Encrypt the database file, using a key derived from the identity
+    #[structopt(long = "encrypt")]
+    pub encrypt: bool,
+    /// Encrypt the database file, using a key derived from the passphrase in this file
+    /// (Instead of the identity)
+    #[structopt(parse(from_os_str), long = "passfile")]
+    pub opt_passphrase_path: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct BackupNodeDbCmd {
+    /// StCtrl app identity file path
+    #[structopt(parse(from_os_str), short = "i", long = "idfile")]
+    pub idfile_path: PathBuf,
+    /// Database file path
+    #[structopt(parse(from_os_str), short = "d", long = "database")]
+    pub database_path: PathBuf,
+    /// The database file is encrypted using a key derived from the passphrase in this file
+    /// (Instead of the identity)
+    #[structopt(parse(from_os_str), long = "db_passfile")]
+    pub opt_db_passphrase_path: Option<PathBuf>,
+    /// Backup output file path
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output_path: PathBuf,
+    /// Encrypt the backup, using a key derived from the passphrase in this file
+    #[structopt(parse(from_os_str), long = "passfile")]
+    pub passphrase_path: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct RestoreNodeDbCmd {
+    /// StCtrl app identity file path
+    #[structopt(parse(from_os_str), short = "i", long = "idfile")]
+    pub idfile_path: PathBuf,
+    /// Backup file path
+    #[structopt(parse(from_os_str), short = "b", long = "backup")]
+    pub backup_path: PathBuf,
+    /// The backup is encrypted using a key derived from the passphrase in this file
+    #[structopt(parse(from_os_str), long = "passfile")]
+    pub passphrase_path: PathBuf,
+    /// Database output file path
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output_path: PathBuf,
+    /// Encrypt the database file, using a key derived from the identity
+    #[structopt(long = "encrypt")]
+    pub encrypt: bool,
+    /// Encrypt the database file, using a key derived from the passphrase in this file
+    /// (Instead of the identity)
+    #[structopt(parse(from_os_str), long = "db_passfile")]
+    pub opt_db_passphrase_path: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct VerifyBackupCmd {
+    /// Backup file path
+    #[structopt(parse(from_os_str), short = "b", long = "backup")]
+    pub backup_path: PathBuf,
+    /// The backup is encrypted using a key derived from the passphrase in this file
+    #[structopt(parse(from_os_str), long = "passfile")]
+    pub passphrase_path: PathBuf,
+    /// Make sure that the backup belongs to the node with this identity file
+    #[structopt(parse(from_os_str), short = "i", long = "idfile")]
+    pub opt_idfile_path: Option<PathBuf>,
 }
 
 #[derive(Debug, StructOpt)]
@@ -48,6 +122,19 @@ pub struct GenIdentCmd {
     pub output_path: PathBuf,
 }
 
+#[derive(Debug, StructOpt)]
+pub struct DeriveIdentCmd {
+    /// Path to a file containing the seed phrase
+    #[structopt(parse(from_os_str), long = "seedfile")]
+    pub seed_path: PathBuf,
+    /// Derivation path of the identity (For example: m/1/0 for the first app identity)
+    #[structopt(long = "path", default_value = "m/0")]
+    pub derivation_path: String,
+    /// Identity file output file path
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output_path: PathBuf,
+}
+
 #[derive(Debug, StructOpt)]
 pub struct AppTicketCmd {
     /// StCtrl app identity file path
@@ -68,6 +155,12 @@ pub struct AppTicketCmd {
     /// Permission to change configuration
     #[structopt(long = "pconfig")]
     pub pconfig: bool,
+    /// Permission to receive reports about the node's state
+    #[structopt(long = "preports")]
+    pub preports: bool,
+    /// Permission to publish messages to other apps connected to the node
+    #[structopt(long = "ppublish")]
+    pub ppublish: bool,
 }
 
 #[derive(Debug, StructOpt)]
@@ -96,6 +189,43 @@ pub struct IndexTicketCmd {
     pub address: String,
 }
 
+#[derive(Debug, StructOpt)]
+pub struct AdminTicketCmd {
+    /// Admin identity file path
+    #[structopt(parse(from_os_str), short = "i", long = "idfile")]
+    pub idfile_path: PathBuf,
+    /// Index server admin ticket output file path
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output_path: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct DirectoryTicketCmd {
+    /// Directory signer identity file path
+    #[structopt(parse(from_os_str), short = "i", long = "idfile")]
+    pub idfile_path: PathBuf,
+    /// Index directory key ticket output file path
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output_path: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct SignDirectoryCmd {
+    /// Directory signer identity file path
+    #[structopt(parse(from_os_str), short = "i", long = "idfile")]
+    pub idfile_path: PathBuf,
+    /// Index server directory output file path
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output_path: PathBuf,
+    /// Version of the directory. Must be larger than the version of any previously published
+    /// directory.
+    #[structopt(short = "v", long = "version")]
+    pub version: u64,
+    /// Index server ticket file path. The name of the index server is the name of the file.
+    #[structopt(parse(from_os_str), long = "index")]
+    pub index_paths: Vec<PathBuf>,
+}
+
 #[derive(Debug, StructOpt)]
 pub struct NodeTicketCmd {
     /// StCtrl app identity file path
@@ -109,6 +239,19 @@ pub struct NodeTicketCmd {
     pub address: String,
 }
 
+#[derive(Debug, StructOpt)]
+pub struct RotateIdentCmd {
+    /// Current identity file path
+    #[structopt(parse(from_os_str), short = "i", long = "idfile")]
+    pub idfile_path: PathBuf,
+    /// New identity file output path
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output_path: PathBuf,
+    /// Key rotation proof output path
+    #[structopt(parse(from_os_str), short = "p", long = "proof")]
+    pub proof_path: PathBuf,
+}
+
 /// stmgr: offST ManaGeR
 /// A util for managing Offst entities and files
 #[derive(Debug, StructOpt)]
@@ -117,9 +260,26 @@ pub enum StMgrCmd {
     /// Initialize a new (empty) node database
     #[structopt(name = "init-node-db")]
     InitNodeDb(InitNodeDbCmd),
+    /// Create an encrypted backup of a node database
+    #[structopt(name = "backup-node-db")]
+    BackupNodeDb(BackupNodeDbCmd),
+    /// Restore a node database from a backup
+    #[structopt(name = "restore-node-db")]
+    RestoreNodeDb(RestoreNodeDbCmd),
+    /// Verify a backup and print a summary of its contents, without restoring it
+    #[structopt(name = "verify-backup")]
+    VerifyBackup(VerifyBackupCmd),
     /// Randomly generate a new identity file
     #[structopt(name = "gen-ident")]
     GenIdent(GenIdentCmd),
+    /// Derive an identity file from a seed phrase. All the identities derived from one seed
+    /// phrase can be restored from a backup of the seed phrase.
+    #[structopt(name = "derive-ident")]
+    DeriveIdent(DeriveIdentCmd),
+    /// Generate a new identity to replace an existing identity, together with a key rotation
+    /// proof signed by both identities
+    #[structopt(name = "rotate-ident")]
+    RotateIdent(RotateIdentCmd),
     /// Create an application ticket
     #[structopt(name = "app-ticket")]
     AppTicket(AppTicketCmd),
@@ -129,6 +289,15 @@ pub enum StMgrCmd {
     /// Create an index server ticket
     #[structopt(name = "index-ticket")]
     IndexTicket(IndexTicketCmd),
+    /// Create an index server admin ticket
+    #[structopt(name = "admin-ticket")]
+    AdminTicket(AdminTicketCmd),
+    /// Create an index directory key ticket
+    #[structopt(name = "directory-ticket")]
+    DirectoryTicket(DirectoryTicketCmd),
+    /// Create a signed index server directory
+    #[structopt(name = "sign-directory")]
+    SignDirectory(SignDirectoryCmd),
     /// Create a node server ticket
     #[structopt(name = "node-ticket")]
     NodeTicket(NodeTicketCmd),
@@ -138,6 +307,8 @@ fn init_node_db(
     InitNodeDbCmd {
         idfile_path,
         output_path,
+        encrypt,
+        opt_passphrase_path,
     }: InitNodeDbCmd,
 ) -> Result<(), InitNodeDbError> {
     // Make sure that output does not exist.
@@ -154,9 +325,271 @@ fn init_node_db(
         .map_err(|_| InitNodeDbError::LoadIdentityError)?;
     let local_public_key = identity.get_public_key();
 
+    // The secret used to encrypt the database file, if any:
+    let opt_db_secret = match opt_passphrase_path {
+        Some(passphrase_path) => Some(FileDbSecret::Passphrase(read_passphrase(&passphrase_path)?)),
+        None if encrypt => Some(FileDbSecret::PrivateKey(identity_file.private_key.clone())),
+        None => None,
+    };
+
     // Create a new database file:
     let initial_state = NodeState::<NetAddress>::new(local_public_key);
-    let _ = FileDb::create(output_path, initial_state).map_err(|_| InitNodeDbError::FileDbError)?;
+    let _ = match &opt_db_secret {
+        Some(db_secret) => FileDb::create_encrypted(output_path, initial_state, db_secret),
+        None => FileDb::create(output_path, initial_state),
+    }
+    .map_err(|_| InitNodeDbError::FileDbError)?;
+
+    Ok(())
+}
+
+/// Read a passphrase from a file. Trailing whitespace (Like a newline) is not a part of the
+/// passphrase.
+fn read_passphrase(passphrase_path: &PathBuf) -> Result<String, std::io::Error> {
+    Ok(fs::read_to_string(passphrase_path)?.trim_end().to_owned())
+}
+
+#[derive(Debug, From)]
+pub enum BackupNodeDbError {
+    OutputAlreadyExists,
+    LoadIdentityError,
+    LoadDbError,
+    InvalidNodeState(VerifyNodeStateError),
+    CreateBackupError,
+    StringSerdeError(StringSerdeError),
+    IoError(std::io::Error),
+}
+
+/// Create a backup of a node database.
+/// The backup contains the identity (Public key) of the node, its friends, the token channels
+/// with the friends and the index servers configuration. It is always encrypted, using a key
+/// derived from a passphrase.
+///
+/// The database file is written atomically, so a backup may be created while the node is running.
+fn backup_node_db(
+    BackupNodeDbCmd {
+        idfile_path,
+        database_path,
+        opt_db_passphrase_path,
+        output_path,
+        passphrase_path,
+    }: BackupNodeDbCmd,
+) -> Result<(), BackupNodeDbError> {
+    // Make sure that output does not exist.
+    if output_path.exists() {
+        return Err(BackupNodeDbError::OutputAlreadyExists);
+    }
+
+    // Parse identity file:
+    let identity_file: IdentityFile = deserialize_from_string(&fs::read_to_string(&idfile_path)?)?;
+    let identity = SoftwareEd25519Identity::from_private_key(&identity_file.private_key)
+        .map_err(|_| BackupNodeDbError::LoadIdentityError)?;
+    let local_public_key = identity.get_public_key();
+
+    // Load database:
+    let file_db = match FileDb::<NodeState<NetAddress>>::load(database_path.clone()) {
+        Ok(file_db) => file_db,
+        Err(FileDbError::FileEncrypted) => {
+            let db_secret = match opt_db_passphrase_path {
+                Some(db_passphrase_path) => {
+                    FileDbSecret::Passphrase(read_passphrase(&db_passphrase_path)?)
+                }
+                None => FileDbSecret::PrivateKey(identity_file.private_key.clone()),
+            };
+            FileDb::<NodeState<NetAddress>>::load_encrypted(database_path, &db_secret)
+                .map_err(|_| BackupNodeDbError::LoadDbError)?
+        }
+        Err(_) => return Err(BackupNodeDbError::LoadDbError),
+    };
+    let node_state = file_db.get_state().clone();
+
+    // There is no point in keeping a backup that can not be restored:
+    verify_node_state(&node_state, &local_public_key)?;
+
+    let backup_secret = FileDbSecret::Passphrase(read_passphrase(&passphrase_path)?);
+    let _ = FileDb::create_encrypted(output_path, node_state, &backup_secret)
+        .map_err(|_| BackupNodeDbError::CreateBackupError)?;
+
+    Ok(())
+}
+
+#[derive(Debug, From)]
+pub enum RestoreNodeDbError {
+    OutputAlreadyExists,
+    LoadIdentityError,
+    /// Backups are always encrypted
+    BackupNotEncrypted,
+    LoadBackupError,
+    /// The backup belongs to another node, or its token channels are not valid
+    InvalidBackup(VerifyNodeStateError),
+    FileDbError,
+    StringSerdeError(StringSerdeError),
+    IoError(std::io::Error),
+}
+
+/// Create a node database from a backup.
+/// The backup is accepted only if it belongs to the node with the given identity, and all of its
+/// token channels are valid.
+fn restore_node_db(
+    RestoreNodeDbCmd {
+        idfile_path,
+        backup_path,
+        passphrase_path,
+        output_path,
+        encrypt,
+        opt_db_passphrase_path,
+    }: RestoreNodeDbCmd,
+) -> Result<(), RestoreNodeDbError> {
+    // Make sure that output does not exist.
+    // We never override an existing database.
+    if output_path.exists() {
+        return Err(RestoreNodeDbError::OutputAlreadyExists);
+    }
+
+    // Parse identity file:
+    let identity_file: IdentityFile = deserialize_from_string(&fs::read_to_string(&idfile_path)?)?;
+    let identity = SoftwareEd25519Identity::from_private_key(&identity_file.private_key)
+        .map_err(|_| RestoreNodeDbError::LoadIdentityError)?;
+    let local_public_key = identity.get_public_key();
+
+    // Load backup:
+    let backup = match FileDb::<NodeState<NetAddress>>::load(backup_path.clone()) {
+        Err(FileDbError::FileEncrypted) => {
+            let backup_secret = FileDbSecret::Passphrase(read_passphrase(&passphrase_path)?);
+            FileDb::<NodeState<NetAddress>>::load_encrypted(backup_path, &backup_secret)
+                .map_err(|_| RestoreNodeDbError::LoadBackupError)?
+        }
+        Ok(_) => return Err(RestoreNodeDbError::BackupNotEncrypted),
+        Err(_) => return Err(RestoreNodeDbError::LoadBackupError),
+    };
+    let node_state = backup.get_state().clone();
+
+    verify_node_state(&node_state, &local_public_key).map_err(RestoreNodeDbError::InvalidBackup)?;
+
+    // The secret used to encrypt the database file, if any:
+    let opt_db_secret = match opt_db_passphrase_path {
+        Some(db_passphrase_path) => Some(FileDbSecret::Passphrase(read_passphrase(
+            &db_passphrase_path,
+        )?)),
+        None if encrypt => Some(FileDbSecret::PrivateKey(identity_file.private_key.clone())),
+        None => None,
+    };
+
+    let _ = match &opt_db_secret {
+        Some(db_secret) => FileDb::create_encrypted(output_path, node_state, db_secret),
+        None => FileDb::create(output_path, node_state),
+    }
+    .map_err(|_| RestoreNodeDbError::FileDbError)?;
+
+    Ok(())
+}
+
+#[derive(Debug, From)]
+pub enum VerifyBackupError {
+    LoadIdentityError,
+    /// Backups are always encrypted
+    BackupNotEncrypted,
+    /// The backup could not be decrypted (Wrong passphrase, or the backup file was modified)
+    LoadBackupError,
+    /// The backup belongs to another node, or its token channels are not valid
+    InvalidBackup(VerifyNodeStateError),
+    StringSerdeError(StringSerdeError),
+    IoError(std::io::Error),
+}
+
+/// Verify a backup, and print a summary of its contents: The identity of the node, its index
+/// servers, and for every friend the balances and the amount of mutations (Move tokens) of the
+/// token channel.
+///
+/// The backup is only read, so this may be used to routinely validate backups, without touching
+/// a live node.
+fn verify_backup(
+    VerifyBackupCmd {
+        backup_path,
+        passphrase_path,
+        opt_idfile_path,
+    }: VerifyBackupCmd,
+    writer: &mut impl Write,
+) -> Result<(), VerifyBackupError> {
+    // Load backup. Decryption fails if the backup was modified:
+    let backup = match FileDb::<NodeState<NetAddress>>::load(backup_path.clone()) {
+        Err(FileDbError::FileEncrypted) => {
+            let backup_secret = FileDbSecret::Passphrase(read_passphrase(&passphrase_path)?);
+            FileDb::<NodeState<NetAddress>>::load_encrypted(backup_path, &backup_secret)
+                .map_err(|_| VerifyBackupError::LoadBackupError)?
+        }
+        Ok(_) => return Err(VerifyBackupError::BackupNotEncrypted),
+        Err(_) => return Err(VerifyBackupError::LoadBackupError),
+    };
+    let node_state = backup.get_state();
+
+    // Verify against the given identity, or against the identity stored in the backup:
+    let local_public_key = match opt_idfile_path {
+        Some(idfile_path) => {
+            let identity_file: IdentityFile =
+                deserialize_from_string(&fs::read_to_string(&idfile_path)?)?;
+            SoftwareEd25519Identity::from_private_key(&identity_file.private_key)
+                .map_err(|_| VerifyBackupError::LoadIdentityError)?
+                .get_public_key()
+        }
+        None => node_state.funder_state.local_public_key.clone(),
+    };
+    verify_node_state(node_state, &local_public_key).map_err(VerifyBackupError::InvalidBackup)?;
+
+    let node_report = create_node_report(node_state);
+    let funder_report = &node_report.funder_report;
+
+    writeln!(writer, "Backup is valid.")?;
+    writeln!(
+        writer,
+        "Identity: {}",
+        public_key_to_string(&funder_report.local_public_key)
+    )?;
+    writeln!(
+        writer,
+        "Index servers: {}",
+        node_report.index_client_report.index_servers.len()
+    )?;
+    writeln!(writer, "Friends: {}", funder_report.friends.len())?;
+
+    let mut total_mutations = 0u128;
+    for (friend_public_key, friend_report) in &funder_report.friends {
+        // Every move token is a signed mutation of the token channel:
+        let mutations = friend_report
+            .opt_last_incoming_move_token
+            .as_ref()
+            .map(|move_token_hashed_report| {
+                move_token_hashed_report
+                    .token_info
+                    .counters
+                    .move_token_counter
+            })
+            .unwrap_or(0);
+        total_mutations = total_mutations.saturating_add(mutations);
+
+        writeln!(
+            writer,
+            "- {} ({}), mutations: {}",
+            friend_report.name,
+            public_key_to_string(friend_public_key),
+            mutations
+        )?;
+        match &friend_report.channel_status {
+            ChannelStatusReport::Consistent(channel_consistent_report) => {
+                for currency_report in &channel_consistent_report.currency_reports {
+                    writeln!(
+                        writer,
+                        "    {}: {}",
+                        currency_report.currency, currency_report.balance.balance
+                    )?;
+                }
+            }
+            ChannelStatusReport::Inconsistent(_) => {
+                writeln!(writer, "    Inconsistent channel")?;
+            }
+        }
+    }
+    writeln!(writer, "Mutations: {}", total_mutations)?;
 
     Ok(())
 }
@@ -186,6 +619,85 @@ fn gen_identity(GenIdentCmd { output_path }: GenIdentCmd) -> Result<(), GenIdent
     Ok(())
 }
 
+#[derive(Debug, From)]
+pub enum DeriveIdentityError {
+    OutputAlreadyExists,
+    EmptySeedPhrase,
+    DerivationPathError(DerivationPathError),
+    StringSerdeError(StringSerdeError),
+    IoError(std::io::Error),
+}
+
+/// Derive an identity file from a seed phrase, at a given derivation path
+fn derive_identity(
+    DeriveIdentCmd {
+        seed_path,
+        derivation_path,
+        output_path,
+    }: DeriveIdentCmd,
+) -> Result<(), DeriveIdentityError> {
+    let derivation_path = derivation_path.parse::<DerivationPath>()?;
+
+    let seed_phrase = read_passphrase(&seed_path)?;
+    if seed_phrase.is_empty() {
+        return Err(DeriveIdentityError::EmptySeedPhrase);
+    }
+    let private_key = derive_private_key(&Seed::from_phrase(&seed_phrase), &derivation_path);
+
+    if output_path.exists() {
+        return Err(DeriveIdentityError::OutputAlreadyExists);
+    }
+
+    let mut file = File::create(output_path)?;
+    file.write_all(&serialize_to_string(&IdentityFile { private_key })?.as_bytes())?;
+
+    Ok(())
+}
+
+#[derive(Debug, From)]
+pub enum RotateIdentityError {
+    OutputAlreadyExists,
+    LoadIdentityError,
+    StringSerdeError(StringSerdeError),
+    IoError(std::io::Error),
+}
+
+/// Generate a new identity file, and a proof that the new identity replaces the old one.
+/// The proof should be sent to the node (Using an app with config permissions), which announces
+/// the new key to all friends. The node should then be restarted with the new identity file.
+fn rotate_identity(
+    RotateIdentCmd {
+        idfile_path,
+        output_path,
+        proof_path,
+    }: RotateIdentCmd,
+) -> Result<(), RotateIdentityError> {
+    let identity_file: IdentityFile = deserialize_from_string(&fs::read_to_string(&idfile_path)?)?;
+    let old_identity = SoftwareEd25519Identity::from_private_key(&identity_file.private_key)
+        .map_err(|_| RotateIdentityError::LoadIdentityError)?;
+
+    // Make sure that outputs do not exist:
+    if output_path.exists() || proof_path.exists() {
+        return Err(RotateIdentityError::OutputAlreadyExists);
+    }
+
+    // Generate a new random keypair:
+    let rng = system_random();
+    let private_key = PrivateKey::rand_gen(&rng);
+    let new_identity = SoftwareEd25519Identity::from_private_key(&private_key)
+        .map_err(|_| RotateIdentityError::LoadIdentityError)?;
+
+    let key_rotation = create_key_rotation(&old_identity, &new_identity);
+
+    let mut file = File::create(output_path)?;
+    file.write_all(&serialize_to_string(&IdentityFile { private_key })?.as_bytes())?;
+
+    let mut file = File::create(proof_path)?;
+    file.write_all(&serialize_to_string(&key_rotation)?.as_bytes())?;
+
+    Ok(())
+}
+
 #[derive(Debug, From)]
 pub enum AppTicketError {
     OutputAlreadyExists,
@@ -209,6 +721,8 @@ fn app_ticket(
         pbuyer,
         pseller,
         pconfig,
+        preports,
+        ppublish,
     }: AppTicketCmd,
 ) -> Result<(), AppTicketError> {
     // Obtain app's public key:
@@ -230,6 +744,8 @@ fn app_ticket(
         buyer: pbuyer,
         seller: pseller,
         config: pconfig,
+        reports: preports,
+        publish: ppublish,
     };
 
     // Store app ticket to file:
@@ -323,6 +839,135 @@ fn index_ticket(
     Ok(())
 }
 
+#[derive(Debug, From)]
+pub enum AdminTicketError {
+    OutputAlreadyExists,
+    LoadIdentityError,
+    IoError(std::io::Error),
+    StringSerdeError(StringSerdeError),
+}
+
+/// Create an index server admin ticket
+/// The ticket can be fed into an index server, to allow the admin to connect to its admin
+/// interface
+fn admin_ticket(
+    AdminTicketCmd {
+        idfile_path,
+        output_path,
+    }: AdminTicketCmd,
+) -> Result<(), AdminTicketError> {
+    // Make sure that output does not exist.
+    if output_path.exists() {
+        return Err(AdminTicketError::OutputAlreadyExists);
+    }
+
+    // Parse identity file:
+    let identity_file: IdentityFile = deserialize_from_string(&fs::read_to_string(&idfile_path)?)?;
+    let identity = SoftwareEd25519Identity::from_private_key(&identity_file.private_key)
+        .map_err(|_| AdminTicketError::LoadIdentityError)?;
+
+    let index_admin_file = IndexAdminFile {
+        public_key: identity.get_public_key(),
+    };
+
+    let mut file = File::create(output_path)?;
+    file.write_all(&serialize_to_string(&index_admin_file)?.as_bytes())?;
+    Ok(())
+}
+
+#[derive(Debug, From)]
+pub enum DirectoryTicketError {
+    OutputAlreadyExists,
+    LoadIdentityError,
+    IoError(std::io::Error),
+    StringSerdeError(StringSerdeError),
+}
+
+/// Create an index directory key ticket
+/// The ticket can be fed into a node, to keep its list of index servers in sync with the
+/// directories signed by the directory signer
+fn directory_ticket(
+    DirectoryTicketCmd {
+        idfile_path,
+        output_path,
+    }: DirectoryTicketCmd,
+) -> Result<(), DirectoryTicketError> {
+    // Make sure that output does not exist.
+    if output_path.exists() {
+        return Err(DirectoryTicketError::OutputAlreadyExists);
+    }
+
+    // Parse identity file:
+    let identity_file: IdentityFile = deserialize_from_string(&fs::read_to_string(&idfile_path)?)?;
+    let identity = SoftwareEd25519Identity::from_private_key(&identity_file.private_key)
+        .map_err(|_| DirectoryTicketError::LoadIdentityError)?;
+
+    let index_directory_key_file = IndexDirectoryKeyFile {
+        public_key: identity.get_public_key(),
+    };
+
+    let mut file = File::create(output_path)?;
+    file.write_all(&serialize_to_string(&index_directory_key_file)?.as_bytes())?;
+    Ok(())
+}
+
+#[derive(Debug, From)]
+pub enum SignDirectoryError {
+    OutputAlreadyExists,
+    LoadIdentityError,
+    InvalidIndexPath,
+    TooManyIndexServers,
+    IoError(std::io::Error),
+    StringSerdeError(StringSerdeError),
+}
+
+/// Create an index server directory, signed by the directory signer
+/// The directory can be fed into the index servers, which publish it to the nodes
+fn sign_directory(
+    SignDirectoryCmd {
+        idfile_path,
+        output_path,
+        version,
+        index_paths,
+    }: SignDirectoryCmd,
+) -> Result<(), SignDirectoryError> {
+    // Make sure that output does not exist.
+    if output_path.exists() {
+        return Err(SignDirectoryError::OutputAlreadyExists);
+    }
+
+    if index_paths.len() > MAX_DIRECTORY_INDEX_SERVERS {
+        return Err(SignDirectoryError::TooManyIndexServers);
+    }
+
+    // Parse identity file:
+    let identity_file: IdentityFile = deserialize_from_string(&fs::read_to_string(&idfile_path)?)?;
+    let identity = SoftwareEd25519Identity::from_private_key(&identity_file.private_key)
+        .map_err(|_| SignDirectoryError::LoadIdentityError)?;
+
+    let mut index_servers = Vec::new();
+    for index_path in index_paths {
+        let name = index_path
+            .file_stem()
+            .and_then(|file_stem| file_stem.to_str())
+            .ok_or(SignDirectoryError::InvalidIndexPath)?
+            .to_owned();
+        let index_server_file: IndexServerFile =
+            deserialize_from_string(&fs::read_to_string(&index_path)?)?;
+        index_servers.push(NamedIndexServerAddress {
+            public_key: index_server_file.public_key,
+            address: index_server_file.address,
+            name,
+        });
+    }
+
+    let index_server_directory = create_index_server_directory(&identity, version, index_servers);
+
+    let mut file = File::create(output_path)?;
+    file.write_all(&serialize_to_string(&index_server_directory)?.as_bytes())?;
+    Ok(())
+}
+
 #[derive(Debug, From)]
 pub enum NodeTicketError {
     OutputAlreadyExists,
@@ -366,20 +1011,36 @@ fn node_ticket(
 #[derive(Debug, From)]
 pub enum StmError {
     InitNodeDbError(InitNodeDbError),
+    BackupNodeDbError(BackupNodeDbError),
+    RestoreNodeDbError(RestoreNodeDbError),
+    VerifyBackupError(VerifyBackupError),
     GenIdentityError(GenIdentityError),
+    DeriveIdentityError(DeriveIdentityError),
+    RotateIdentityError(RotateIdentityError),
     AppTicketError(AppTicketError),
     RelayTicketError(RelayTicketError),
     IndexTicketError(IndexTicketError),
+    AdminTicketError(AdminTicketError),
+    DirectoryTicketError(DirectoryTicketError),
+    SignDirectoryError(SignDirectoryError),
     NodeTicketError(NodeTicketError),
 }
 
 pub fn stmgr(st_mgr_cmd: StMgrCmd) -> Result<(), StmError> {
     match st_mgr_cmd {
         StMgrCmd::InitNodeDb(i) => init_node_db(i)?,
+        StMgrCmd::BackupNodeDb(i) => backup_node_db(i)?,
+        StMgrCmd::RestoreNodeDb(i) => restore_node_db(i)?,
+        StMgrCmd::VerifyBackup(i) => verify_backup(i, &mut std::io::stdout())?,
         StMgrCmd::GenIdent(i) => gen_identity(i)?,
+        StMgrCmd::DeriveIdent(i) => derive_identity(i)?,
+        StMgrCmd::RotateIdent(i) => rotate_identity(i)?,
         StMgrCmd::AppTicket(i) => app_ticket(i)?,
         StMgrCmd::RelayTicket(i) => relay_ticket(i)?,
         StMgrCmd::IndexTicket(i) => index_ticket(i)?,
+        StMgrCmd::AdminTicket(i) => admin_ticket(i)?,
+        StMgrCmd::DirectoryTicket(i) => directory_ticket(i)?,
+        StMgrCmd::SignDirectory(i) => sign_directory(i)?,
         StMgrCmd::NodeTicket(i) => node_ticket(i)?,
     }
 
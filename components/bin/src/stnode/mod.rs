@@ -1,6 +1,8 @@
 mod file_trusted_apps;
 mod net_node;
+mod node_runner;
 mod stnodelib;
 
 pub use self::net_node::{net_node, NetNodeError, TrustedApps};
+pub use self::node_runner::{NodeInstance, NodeRunner, RunningNode};
 pub use self::stnodelib::{stnode, NodeBinError, StNodeCmd};
@@ -1,10 +1,11 @@
 use std::fmt::Debug;
 
 use futures::channel::{mpsc, oneshot};
-use futures::future::RemoteHandle;
+use futures::future::{self, RemoteHandle};
 use futures::task::{Spawn, SpawnExt};
 use futures::{FutureExt, SinkExt, Stream, StreamExt, TryFutureExt};
 
+use common::budget::{budget_sender, BudgetError};
 use common::conn::{BoxFuture, ConnPair, ConnPairVec, FuncFutTransform, FutTransform};
 use common::transform_pool::transform_pool_loop;
 
@@ -14,12 +15,18 @@ use identity::IdentityClient;
 
 use database::DatabaseClient;
 
-use proto::app_server::messages::{AppPermissions, AppServerToApp, AppToAppServer, NodeReport};
+use proto::app_server::messages::{
+    AppHello, AppPermissions, AppServerToApp, AppToAppServer, NodeReport,
+};
+use proto::consts::MAX_APP_BUFFERED_BYTES;
 use proto::crypto::PublicKey;
 use proto::net::messages::NetAddress;
 use proto::proto_ser::{ProtoDeserialize, ProtoSerialize};
 
-use connection::{create_encrypt_keepalive, create_version_encrypt_keepalive};
+use connection::{
+    create_adaptive_keepalive, create_encrypt_keepalive, create_version_encrypt_keepalive,
+    KeepAliveBounds,
+};
 
 use timer::TimerClient;
 
@@ -30,7 +37,8 @@ use version::VersionPrefix;
 */
 
 use node::{
-    node, ConnPairServer, IncomingAppConnection, NodeConfig, NodeError, NodeMutation, NodeState,
+    node, server_hello, ConnPairServer, IncomingAppConnection, NodeConfig, NodeError, NodeMutation,
+    NodeRequest, NodeState,
 };
 
 #[derive(Debug)]
@@ -39,9 +47,18 @@ pub enum NetNodeError {
     RequestPublicKeyError,
     SpawnError,
     DatabaseIdentityMismatch,
+    /// A node with the same identity is already running
+    DuplicateIdentity,
     NodeError(NodeError),
 }
 
+/// A record of an app that was disconnected for not keeping up with the messages sent to it.
+#[derive(Debug)]
+struct AppEviction {
+    public_key: PublicKey,
+    budget_error: BudgetError,
+}
+
 #[derive(Clone)]
 struct AppConnTransform<CT, TA, S> {
     conn_transform: CT,
@@ -85,6 +102,12 @@ where
             // Tell app about its permissions:
             sender.send(app_permissions.proto_serialize()).await.ok()?;
 
+            // Tell app about the features we support, and find out what the app wants to receive:
+            sender.send(server_hello().proto_serialize()).await.ok()?;
+            let app_hello_data = receiver.next().await?;
+            let app_hello = AppHello::proto_deserialize(&app_hello_data).ok()?;
+
+            let app_public_key = public_key.clone();
             let (report_sender, report_receiver) =
                 oneshot::channel::<(NodeReport, oneshot::Sender<ConnPairServer<NetAddress>>)>();
 
@@ -103,28 +126,39 @@ where
                         let (mut to_user_receiver, user_receiver) = mpsc::channel(0);
 
                         // Deserialize received data
-                        let _ = c_spawner.spawn(async move {
-                            let _ = async move {
-                                while let Some(data) = receiver.next().await {
-                                    let message = AppToAppServer::proto_deserialize(&data).ok()?;
-                                    to_user_receiver.send(message).await.ok()?;
-                                }
-                                Some(())
+                        let (deserialize_fut, deserialize_handle) = future::abortable(async move {
+                            while let Some(data) = receiver.next().await {
+                                let message = AppToAppServer::proto_deserialize(&data).ok()?;
+                                to_user_receiver.send(message).await.ok()?;
                             }
-                            .await;
+                            Some(())
                         });
+                        let _ = c_spawner.spawn(deserialize_fut.map(|_| ()));
 
-                        // Serialize sent data:
+                        // Serialize sent data.
+                        // Messages are sent without waiting for the app. An app that does not
+                        // keep up with the messages sent to it is disconnected:
+                        let mut sender =
+                            budget_sender(sender, MAX_APP_BUFFERED_BYTES, Vec::len, &c_spawner);
                         let _ = c_spawner.spawn(async move {
-                            let _ = async move {
-                                while let Some(message) = from_user_sender.next().await {
-                                    // let data = serialize_app_server_to_app(&message);
-                                    let data = message.proto_serialize();
-                                    sender.send(data).await.ok()?;
+                            while let Some(message) = from_user_sender.next().await {
+                                let data = message.proto_serialize();
+                                match sender.try_send(data) {
+                                    Ok(()) => {}
+                                    Err(BudgetError::Closed) => break,
+                                    Err(budget_error) => {
+                                        let app_eviction = AppEviction {
+                                            public_key,
+                                            budget_error,
+                                        };
+                                        warn!("App disconnected: {:?}", app_eviction);
+                                        break;
+                                    }
                                 }
-                                Some(())
                             }
-                            .await;
+                            // Stop receiving messages from the app, so that the app server
+                            // will know that the app was disconnected:
+                            deserialize_handle.abort();
                         });
 
                         conn_sender
@@ -136,7 +170,9 @@ where
                 .ok()?;
 
             Some(IncomingAppConnection {
+                app_public_key,
                 app_permissions: app_permissions.clone(),
+                app_subscriptions: app_hello.subscriptions,
                 report_sender,
             })
         })
@@ -191,6 +227,48 @@ where
     Ok((pool_handle, incoming_apps))
 }
 
+/// Authenticate incoming connections to the embedded relay of the node.
+/// Remote sides are not known ahead of time, just like in a standalone relay.
+fn transform_incoming_relay_conns<IRC, R, S>(
+    incoming_relay_raw_conns: IRC,
+    identity_client: IdentityClient,
+    rng: R,
+    timer_client: TimerClient,
+    max_concurrent_encrypt: usize,
+    spawner: S,
+) -> Result<(RemoteHandle<()>, mpsc::Receiver<(PublicKey, ConnPairVec)>), NetNodeError>
+where
+    IRC: Stream<Item = ConnPairVec> + Unpin + Send + 'static,
+    R: CryptoRandom + Clone + 'static,
+    S: Spawn + Clone + Send + 'static,
+{
+    let conn_transform =
+        create_version_encrypt_keepalive(timer_client, identity_client, rng, spawner.clone());
+
+    let relay_conn_transform = FuncFutTransform::new(move |conn_pair| {
+        let mut c_conn_transform = conn_transform.clone();
+        Box::pin(async move { c_conn_transform.transform((None, conn_pair)).await })
+    });
+
+    let (incoming_relay_conns_sender, incoming_relay_conns) = mpsc::channel(0);
+
+    let pool_fut = transform_pool_loop(
+        incoming_relay_raw_conns,
+        incoming_relay_conns_sender,
+        relay_conn_transform,
+        max_concurrent_encrypt,
+        spawner.clone(),
+    )
+    .map_err(|e| error!("transform_pool_loop() error: {:?}", e))
+    .map(|_| ());
+
+    let pool_handle = spawner
+        .spawn_with_handle(pool_fut)
+        .map_err(|_| NetNodeError::SpawnError)?;
+
+    Ok((pool_handle, incoming_relay_conns))
+}
+
 pub trait TrustedApps {
     /// Get the permissions of an app. Returns None if the app is not trusted at all.
     fn app_permissions<'a>(
@@ -199,8 +277,12 @@ pub trait TrustedApps {
     ) -> BoxFuture<'a, Option<AppPermissions>>;
 }
 
-pub async fn net_node<IAC, C, R, TA, S>(
+pub async fn net_node<IAC, IRC, NR, C, R, TA, S>(
     incoming_app_raw_conns: IAC,
+    // Raw connections to the embedded relay, if the embedded relay is enabled:
+    opt_incoming_relay_raw_conns: Option<IRC>,
+    // Requests from a `NodeHandle`:
+    incoming_requests: NR,
     connector: C,
     timer_client: TimerClient,
     identity_client: IdentityClient,
@@ -213,6 +295,8 @@ pub async fn net_node<IAC, C, R, TA, S>(
 ) -> Result<(), NetNodeError>
 where
     IAC: Stream<Item = ConnPairVec> + Unpin + Send + 'static,
+    IRC: Stream<Item = ConnPairVec> + Unpin + Send + 'static,
+    NR: Stream<Item = NodeRequest> + Unpin + Send + 'static,
     C: FutTransform<Input = NetAddress, Output = Option<ConnPairVec>> + Clone + Send + 'static,
     R: CryptoRandom + Clone + 'static,
     TA: TrustedApps + Send + Clone + 'static,
@@ -230,6 +314,22 @@ where
         spawner.clone(),
     )?;
 
+    let (_opt_relay_pool_handle, incoming_relay_conns) = match opt_incoming_relay_raw_conns {
+        Some(incoming_relay_raw_conns) => {
+            let (relay_pool_handle, incoming_relay_conns) = transform_incoming_relay_conns(
+                incoming_relay_raw_conns,
+                identity_client.clone(),
+                rng.clone(),
+                timer_client.clone(),
+                node_config.max_concurrent_encrypt,
+                spawner.clone(),
+            )?;
+            (Some(relay_pool_handle), incoming_relay_conns)
+        }
+        // No connections will ever arrive:
+        None => (None, mpsc::channel(0).1),
+    };
+
     let conn_transform = create_version_encrypt_keepalive(
         timer_client.clone(),
         identity_client.clone(),
@@ -249,10 +349,24 @@ where
         })
     });
 
+    let (keepalive_report_sender, keepalive_reports) = mpsc::channel(node_config.channel_len);
+    let (secure_channel_report_sender, secure_channel_reports) =
+        mpsc::channel(node_config.channel_len);
+    let keepalive_bounds = KeepAliveBounds {
+        min_ticks: node_config.keepalive_min_ticks,
+        max_ticks: node_config.keepalive_ticks / 2,
+    };
+    let adaptive_client = create_adaptive_keepalive(keepalive_bounds, spawner.clone())
+        .map_err(|_| NetNodeError::SpawnError)?;
     let encrypt_keepalive = create_encrypt_keepalive(
         timer_client.clone(),
         identity_client.clone(),
         rng.clone(),
+        node_config.ticks_to_rekey,
+        node_config.ticks_to_resume,
+        keepalive_report_sender,
+        secure_channel_report_sender,
+        adaptive_client,
         spawner.clone(),
     );
 
@@ -264,7 +378,11 @@ where
         database_client,
         secure_connector,
         encrypt_keepalive,
+        keepalive_reports,
+        secure_channel_reports,
         incoming_apps,
+        incoming_relay_conns,
+        incoming_requests,
         rng,
         spawner.clone(),
     )
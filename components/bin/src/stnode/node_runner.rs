@@ -0,0 +1,163 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use futures::channel::mpsc;
+use futures::future::RemoteHandle;
+use futures::task::{Spawn, SpawnExt};
+use futures::Stream;
+
+use common::conn::{ConnPairVec, FutTransform};
+
+use crypto::rand::CryptoRandom;
+
+use identity::IdentityClient;
+
+use database::DatabaseClient;
+
+use proto::crypto::PublicKey;
+use proto::net::messages::NetAddress;
+
+use timer::TimerClient;
+
+use node::{NodeConfig, NodeHandle, NodeMutation, NodeState};
+
+use crate::stnode::net_node::{net_node, NetNodeError, TrustedApps};
+
+/// Everything that belongs to a single node hosted by a `NodeRunner`.
+pub struct NodeInstance<IAC, TA> {
+    pub identity_client: IdentityClient,
+    pub node_config: NodeConfig,
+    pub trusted_apps: TA,
+    pub node_state: NodeState<NetAddress>,
+    pub database_client: DatabaseClient<NodeMutation<NetAddress>>,
+    /// Raw connections from apps, for this node only
+    pub incoming_app_raw_conns: IAC,
+    /// Raw connections to the embedded relay of this node, if the embedded relay is enabled
+    pub opt_incoming_relay_raw_conns: Option<IAC>,
+}
+
+/// A node running inside a `NodeRunner`
+pub struct RunningNode {
+    pub local_public_key: PublicKey,
+    /// Used for a graceful shutdown of the node
+    pub node_handle: NodeHandle,
+    /// Resolves when the node stops. Dropping it stops the node.
+    pub node_done: RemoteHandle<Result<(), NetNodeError>>,
+}
+
+/// Marks the identity of a node as taken, for as long as the node runs.
+struct IdentityGuard {
+    running_public_keys: Arc<Mutex<HashSet<PublicKey>>>,
+    local_public_key: PublicKey,
+}
+
+impl Drop for IdentityGuard {
+    fn drop(&mut self) {
+        self.running_public_keys
+            .lock()
+            .unwrap()
+            .remove(&self.local_public_key);
+    }
+}
+
+/// Hosts multiple independent nodes in one process.
+/// Every node has its own identity, database and configuration. All the nodes share the timer,
+/// the spawner and the connector used to reach remote servers.
+pub struct NodeRunner<C, R, S> {
+    connector: C,
+    timer_client: TimerClient,
+    rng: R,
+    spawner: S,
+    /// Public keys of the nodes that are currently running
+    running_public_keys: Arc<Mutex<HashSet<PublicKey>>>,
+}
+
+impl<C, R, S> NodeRunner<C, R, S>
+where
+    C: FutTransform<Input = NetAddress, Output = Option<ConnPairVec>> + Clone + Send + 'static,
+    R: CryptoRandom + Clone + 'static,
+    S: Spawn + Clone + Send + 'static,
+{
+    pub fn new(connector: C, timer_client: TimerClient, rng: R, spawner: S) -> Self {
+        NodeRunner {
+            connector,
+            timer_client,
+            rng,
+            spawner,
+            running_public_keys: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Start running a node.
+    /// Two nodes with the same identity may not run at the same time.
+    pub async fn spawn_node<IAC, TA>(
+        &mut self,
+        node_instance: NodeInstance<IAC, TA>,
+    ) -> Result<RunningNode, NetNodeError>
+    where
+        IAC: Stream<Item = ConnPairVec> + Unpin + Send + 'static,
+        TA: TrustedApps + Send + Clone + 'static,
+    {
+        let NodeInstance {
+            identity_client,
+            node_config,
+            trusted_apps,
+            node_state,
+            database_client,
+            incoming_app_raw_conns,
+            opt_incoming_relay_raw_conns,
+        } = node_instance;
+
+        let local_public_key = identity_client
+            .request_public_key()
+            .await
+            .map_err(|_| NetNodeError::RequestPublicKeyError)?;
+
+        if !self
+            .running_public_keys
+            .lock()
+            .unwrap()
+            .insert(local_public_key.clone())
+        {
+            return Err(NetNodeError::DuplicateIdentity);
+        }
+        let identity_guard = IdentityGuard {
+            running_public_keys: self.running_public_keys.clone(),
+            local_public_key: local_public_key.clone(),
+        };
+
+        let (request_sender, incoming_requests) = mpsc::channel(node_config.channel_len);
+
+        let net_node_fut = net_node(
+            incoming_app_raw_conns,
+            opt_incoming_relay_raw_conns,
+            incoming_requests,
+            self.connector.clone(),
+            self.timer_client.clone(),
+            identity_client,
+            self.rng.clone(),
+            node_config,
+            trusted_apps,
+            node_state,
+            database_client,
+            self.spawner.clone(),
+        );
+
+        let node_fut = async move {
+            // The identity may be used again once the node stops (Or is dropped):
+            let _identity_guard = identity_guard;
+            net_node_fut.await
+        };
+
+        let node_done = self
+            .spawner
+            .spawn_with_handle(node_fut)
+            .map_err(|_| NetNodeError::SpawnError)?;
+
+        Ok(RunningNode {
+            local_public_key,
+            node_handle: NodeHandle::new(request_sender),
+            node_done,
+        })
+    }
+}
@@ -1,59 +1,90 @@
+use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::fs;
 use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
 
 use derive_more::From;
 
 use futures::channel::mpsc;
 use futures::executor::{block_on, ThreadPool};
+use futures::future;
 use futures::task::SpawnExt;
 use futures::{FutureExt, TryFutureExt};
 
 use structopt::StructOpt;
 
-use common::conn::Listener;
-use common::int_convert::usize_to_u64;
+use common::conn::{ConnPairVec, Listener};
 
 use crypto::identity::SoftwareEd25519Identity;
 use crypto::rand::system_random;
 
 use identity::{create_identity, IdentityClient};
-use timer::create_timer;
 
-use database::file_db::FileDb;
+use database::file_db::{FileDb, FileDbSecret};
 use database::{database_loop, AtomicDb, DatabaseClient};
 
-use net::{TcpConnector, TcpListener};
+use net::{create_quic_runtime, QuicConnector, TcpConnector, TcpListener, TransportConnector};
 use proto::consts::{
-    KEEPALIVE_TICKS, MAX_FRAME_LENGTH, MAX_NODE_RELAYS, MAX_OPERATIONS_IN_BATCH, TICKS_TO_REKEY,
-    TICK_MS,
+    KEEPALIVE_TICKS, MAX_FRAME_LENGTH, MAX_NODE_RELAYS, MAX_OPERATIONS_IN_BATCH, MAX_ROUTE_LEN,
+    TICKS_TO_REKEY, TICKS_TO_RESUME,
 };
 use proto::net::messages::NetAddress;
 use proto::ser_string::{deserialize_from_string, StringSerdeError};
 
-use proto::file::IdentityFile;
+use proto::file::{IdentityFile, IndexDirectoryKeyFile};
 
 use node::{NodeConfig, NodeState};
 
 use crate::stnode::file_trusted_apps::FileTrustedApps;
-use crate::stnode::net_node::{net_node, NetNodeError};
+use crate::stnode::net_node::NetNodeError;
+use crate::stnode::node_runner::{NodeInstance, NodeRunner};
+use crate::ticks::create_bin_timer;
 
 /// Memory allocated to a channel in memory (Used to connect two components)
 const CHANNEL_LEN: usize = 0x20;
 /// The amount of ticks we wait before attempting to reconnect
 const BACKOFF_TICKS: usize = 0x8;
+/// Minimal amount of ticks between keepalives sent to a friend
+const KEEPALIVE_MIN_TICKS: usize = 0x4;
 /// Maximum amount of encryption set ups (diffie hellman) that we allow to occur at the same
 /// time.
 const MAX_CONCURRENT_ENCRYPT: usize = 0x8;
 /// The size we allocate for the user send funds requests queue.
 const MAX_PENDING_USER_REQUESTS: usize = 0x20;
+/// Maximum amount of times a failed transaction is retried through an alternative route.
+const MAX_TRANSACTION_RETRIES: u64 = 0x2;
 /// Maximum amount of concurrent index client requests:
 const MAX_OPEN_INDEX_CLIENT_REQUESTS: usize = 0x8;
+/// The amount of ticks routes received from an index server are cached:
+const ROUTE_CACHE_TICKS: usize = 0x10;
+/// Rotate the routes returned for repeated requests to the same destination:
+const ROUTE_ROTATION: bool = true;
+/// Smooth the capacities advertised to the index servers during short capacity drops:
+const CAPACITY_SMOOTHING: bool = true;
+/// Amount of index servers we keep a session with at the same time:
+const MAX_INDEX_SESSIONS: usize = 2;
 /// The amount of ticks we are willing to wait until a connection is established (Through
 /// the relay)
 const CONN_TIMEOUT_TICKS: usize = 0x8;
+/// Tunnel accepted relay connections over a single connection to the relay.
+/// Disabled, to keep working with relays that do not support multiplexed listening.
+const RELAY_MULTIPLEX: bool = false;
+/// The amount of ticks between measurements of the latency to relays:
+const RELAY_PROBE_TICKS: usize = 0x40;
+/// Maximum amount of ticks a graceful shutdown waits for pending operations to settle:
+const SHUTDOWN_TICKS: usize = 0x20;
+/// Amount of ticks until a request originated by this node expires:
+const REQUEST_EXPIRY_TICKS: u64 = 0x40;
+/// New requests are not queued through a friend that was online less than this percentage of the
+/// recent ticks:
+const MIN_FRIEND_UPTIME_PERCENT: u8 = 50;
+/// Channel reset terms of a friend are accepted automatically if they match our own terms:
+const AUTO_RESET_TOLERANCE: Option<u128> = Some(0);
+/// Amount of ticks between two snapshots of the balances with every friend (10 minutes):
+const BALANCE_SNAPSHOT_TICKS: usize = 600;
+/// Maximum amount of balance snapshots kept for every friend (One day):
+const MAX_BALANCE_SNAPSHOTS: usize = 144;
 /*
 /// Maximum amount of concurrent applications
 /// going through the incoming connection transform at the same time
@@ -66,6 +97,12 @@ pub enum NodeBinError {
     LoadIdentityError,
     CreateThreadPoolError,
     CreateTimerError,
+    CreateQuicRuntimeError,
+    /// A different amount of identity files, listening addresses, databases and trusted
+    /// directories was given
+    NodeArgsMismatch,
+    /// An invalid public address was given for an embedded relay
+    InvalidRelayAddress,
     LoadDbError,
     SpawnError,
     NetNodeError(NetNodeError),
@@ -79,129 +116,344 @@ pub enum NodeBinError {
 ///
 ///『將欲奪之，必固與之』
 ///
+/// Multiple nodes (Each with its own identity) may run in one process, by passing `--idfile`,
+/// `--laddr`, `--database` and `--trusted` once for every node. The n-th occurrence of every
+/// option belongs to the n-th node.
 #[derive(Debug, StructOpt)]
 #[structopt(name = "stnode")]
 pub struct StNodeCmd {
     /// StCtrl app identity file path
-    #[structopt(parse(from_os_str), short = "i", long = "idfile")]
-    pub idfile: PathBuf,
+    #[structopt(
+        parse(from_os_str),
+        short = "i",
+        long = "idfile",
+        raw(required = "true")
+    )]
+    pub idfile: Vec<PathBuf>,
     /// Listening address (Used for communication with apps)
-    #[structopt(short = "l", long = "laddr")]
-    pub laddr: SocketAddr,
+    #[structopt(short = "l", long = "laddr", raw(required = "true"))]
+    pub laddr: Vec<SocketAddr>,
     /// Database file path
-    #[structopt(parse(from_os_str), short = "d", long = "database")]
-    pub database: PathBuf,
+    #[structopt(
+        parse(from_os_str),
+        short = "d",
+        long = "database",
+        raw(required = "true")
+    )]
+    pub database: Vec<PathBuf>,
     /// Directory path of trusted applications
-    #[structopt(parse(from_os_str), short = "t", long = "trusted")]
-    pub trusted: PathBuf,
+    #[structopt(
+        parse(from_os_str),
+        short = "t",
+        long = "trusted",
+        raw(required = "true")
+    )]
+    pub trusted: Vec<PathBuf>,
+    /// Take timer ticks from stdin instead of the internal clock.
+    /// Every line is an amount of ticks (An empty line is a single tick).
+    #[structopt(long = "stdin_ticks")]
+    pub stdin_ticks: bool,
+    /// Encrypt the database file, using a key derived from the identity.
+    /// A plaintext database file is encrypted when loaded.
+    #[structopt(long = "encrypt_db")]
+    pub encrypt_db: bool,
+    /// Encrypt the database file, using a key derived from the passphrase in this file
+    /// (Instead of the identity). The same passphrase is used for the databases of all the nodes.
+    /// A plaintext database file is encrypted when loaded.
+    #[structopt(parse(from_os_str), long = "passfile")]
+    pub opt_passphrase_path: Option<PathBuf>,
+    /// Never connect to the relays of friends. Only accept connections from friends through our
+    /// own relays (For nodes behind restrictive firewalls). Applies to all the nodes.
+    #[structopt(long = "listen_only")]
+    pub listen_only: bool,
+    /// Listening address of an embedded relay, that lets friends reach this node (And each
+    /// other) through it. Given once for every node, together with `--relay_address`.
+    /// The embedded relay is disabled if not given.
+    #[structopt(long = "relay_laddr")]
+    pub relay_laddr: Vec<SocketAddr>,
+    /// Public address of the embedded relay, advertised to friends (For example:
+    /// `node.example.com:1338`)
+    #[structopt(long = "relay_address")]
+    pub relay_address: Vec<String>,
+    /// Keep the list of index servers in sync with the index server directory signed by the key
+    /// in this file (Created using `stmgr directory-ticket`). Applies to all the nodes.
+    #[structopt(parse(from_os_str), long = "directory_key")]
+    pub opt_directory_key_path: Option<PathBuf>,
 }
 
-pub fn stnode(st_node_cmd: StNodeCmd) -> Result<(), NodeBinError> {
-    let StNodeCmd {
-        idfile,
-        laddr,
-        database,
-        trusted,
-    } = st_node_cmd;
-
-    // Parse identity file:
-    let identity_file: IdentityFile = deserialize_from_string(&fs::read_to_string(&idfile)?)?;
-    let identity = SoftwareEd25519Identity::from_private_key(&identity_file.private_key)
-        .map_err(|_| NodeBinError::LoadIdentityError)?;
-
-    // Create a ThreadPool:
-    let thread_pool = ThreadPool::new().map_err(|_| NodeBinError::CreateThreadPoolError)?;
-
-    // Create thread pool for file system operations:
-    let file_system_thread_pool =
-        ThreadPool::new().map_err(|_| NodeBinError::CreateThreadPoolError)?;
-
-    // Spawn identity service:
-    let (sender, identity_loop) = create_identity(identity);
-    thread_pool
-        .spawn(identity_loop)
-        .map_err(|_| NodeBinError::SpawnError)?;
-    let identity_client = IdentityClient::new(sender);
-
-    // Get a timer client:
-    let dur = Duration::from_millis(usize_to_u64(TICK_MS).unwrap());
-    let timer_client =
-        create_timer(dur, thread_pool.clone()).map_err(|_| NodeBinError::CreateTimerError)?;
-
-    // Fill in node configuration:
-    let node_config = NodeConfig {
+fn create_node_config() -> NodeConfig {
+    NodeConfig {
         /// Memory allocated to a channel in memory (Used to connect two components)
         channel_len: CHANNEL_LEN,
         /// The amount of ticks we wait before attempting to reconnect
         backoff_ticks: BACKOFF_TICKS,
         /// The amount of ticks we wait until we decide an idle connection has timed out.
         keepalive_ticks: KEEPALIVE_TICKS,
+        /// Minimal amount of ticks between keepalives sent to a friend.
+        keepalive_min_ticks: KEEPALIVE_MIN_TICKS,
         /// Amount of ticks to wait until the next rekeying (Channel encryption)
         ticks_to_rekey: TICKS_TO_REKEY,
+        /// Amount of ticks a closed channel may be resumed without a full handshake
+        ticks_to_resume: TICKS_TO_RESUME,
         /// Maximum amount of encryption set ups (diffie hellman) that we allow to occur at the same
         /// time.
         max_concurrent_encrypt: MAX_CONCURRENT_ENCRYPT,
         /// The amount of ticks we are willing to wait until a connection is established (Through
         /// the relay)
         conn_timeout_ticks: CONN_TIMEOUT_TICKS,
+        /// Tunnel accepted relay connections over a single connection to the relay.
+        relay_multiplex: RELAY_MULTIPLEX,
+        relay_probe_ticks: RELAY_PROBE_TICKS,
+        /// Connect to friends' relays, and not only wait for friends to connect.
+        listen_only: false,
+        /// Do not act as a relay for our friends.
+        opt_embedded_relay_address: None,
         /// Maximum amount of operations in one move token message
         max_operations_in_batch: MAX_OPERATIONS_IN_BATCH,
         /// The size we allocate for the user send funds requests queue.
         max_pending_user_requests: MAX_PENDING_USER_REQUESTS,
+        /// Maximum length of the route of a transaction originated by this node.
+        max_route_len: MAX_ROUTE_LEN,
+        /// Maximum amount of times a failed transaction is retried through an alternative route.
+        max_transaction_retries: MAX_TRANSACTION_RETRIES,
         /// Maximum amount of concurrent index client requests:
         max_open_index_client_requests: MAX_OPEN_INDEX_CLIENT_REQUESTS,
+        /// The amount of ticks routes received from an index server are cached:
+        route_cache_ticks: ROUTE_CACHE_TICKS,
+        /// Rotate the routes returned for repeated requests to the same destination:
+        route_rotation: ROUTE_ROTATION,
+        /// Smooth the capacities advertised to the index servers during short capacity drops:
+        capacity_smoothing: CAPACITY_SMOOTHING,
+        /// Amount of index servers we keep a session with at the same time:
+        max_index_sessions: MAX_INDEX_SESSIONS,
+        /// Do not sync the index servers with an index server directory.
+        opt_index_directory_key: None,
         /// Maximum amount of relays a node may use.
         max_node_relays: MAX_NODE_RELAYS,
+        /// Maximum amount of ticks a graceful shutdown waits for pending operations to settle.
+        shutdown_ticks: SHUTDOWN_TICKS,
+        /// Amount of ticks until a request originated by this node expires.
+        request_expiry_ticks: REQUEST_EXPIRY_TICKS,
+        /// Minimum recent uptime of a friend for new requests to be queued through it.
+        min_friend_uptime_percent: MIN_FRIEND_UPTIME_PERCENT,
+        /// Maximum difference between the reset terms of a friend and ours, for automatic resets.
+        opt_auto_reset_tolerance: AUTO_RESET_TOLERANCE,
+        /// Amount of ticks between two balance snapshots.
+        balance_snapshot_ticks: BALANCE_SNAPSHOT_TICKS,
+        /// Maximum amount of balance snapshots kept for every friend.
+        max_balance_snapshots: MAX_BALANCE_SNAPSHOTS,
         /*
         /// Maximum amount of incoming app connections we set up at the same time
         // max_concurrent_incoming_apps: MAX_CONCURRENT_INCOMING_APPS,
          */
-    };
+    }
+}
 
-    // A tcp connector, Used to connect to remote servers:
-    let tcp_connector = TcpConnector::new(MAX_FRAME_LENGTH, thread_pool.clone());
+/// Load the identity and database of a single node, and start listening to its apps (And to
+/// connections to its embedded relay, if `opt_relay_laddr` is given).
+fn load_node_instance(
+    idfile: &Path,
+    laddr: SocketAddr,
+    opt_relay_laddr: Option<SocketAddr>,
+    database: PathBuf,
+    trusted: PathBuf,
+    encrypt_db: bool,
+    opt_passphrase: &Option<String>,
+    thread_pool: &ThreadPool,
+    file_system_thread_pool: &ThreadPool,
+) -> Result<NodeInstance<mpsc::Receiver<ConnPairVec>, FileTrustedApps>, NodeBinError> {
+    // Parse identity file:
+    let identity_file: IdentityFile = deserialize_from_string(&fs::read_to_string(idfile)?)?;
+    let identity = SoftwareEd25519Identity::from_private_key(&identity_file.private_key)
+        .map_err(|_| NodeBinError::LoadIdentityError)?;
 
-    // Obtain secure cryptographic random:
-    let rng = system_random();
+    // Spawn identity service:
+    let (sender, identity_loop) = create_identity(identity);
+    thread_pool
+        .spawn(identity_loop)
+        .map_err(|_| NodeBinError::SpawnError)?;
+    let identity_client = IdentityClient::new(sender);
+
+    // The secret used to encrypt the database file, if any:
+    let opt_db_secret = match opt_passphrase {
+        Some(passphrase) => Some(FileDbSecret::Passphrase(passphrase.clone())),
+        None if encrypt_db => Some(FileDbSecret::PrivateKey(identity_file.private_key.clone())),
+        None => None,
+    };
 
     // Load database:
-    let atomic_db =
-        FileDb::<NodeState<NetAddress>>::load(database).map_err(|_| NodeBinError::LoadDbError)?;
+    let atomic_db = match &opt_db_secret {
+        Some(db_secret) => FileDb::<NodeState<NetAddress>>::load_encrypted(database, db_secret),
+        None => FileDb::<NodeState<NetAddress>>::load(database),
+    }
+    .map_err(|_| NodeBinError::LoadDbError)?;
 
     // Start listening to apps:
     let app_tcp_listener = TcpListener::new(MAX_FRAME_LENGTH, thread_pool.clone());
     let (_config_sender, incoming_app_raw_conns) = app_tcp_listener.listen(laddr);
 
-    let trusted_apps = FileTrustedApps::new(trusted.into());
+    // Start listening to connections to the embedded relay:
+    let opt_incoming_relay_raw_conns = opt_relay_laddr.map(|relay_laddr| {
+        let relay_tcp_listener = TcpListener::new(MAX_FRAME_LENGTH, thread_pool.clone());
+        let (_config_sender, incoming_relay_raw_conns) = relay_tcp_listener.listen(relay_laddr);
+        incoming_relay_raw_conns
+    });
+
+    let trusted_apps = FileTrustedApps::new(trusted);
 
     // Get initial node_state:
     let node_state = atomic_db.get_state().clone();
 
     // Spawn database service:
     let (db_request_sender, incoming_db_requests) = mpsc::channel(0);
-    let loop_fut = database_loop(atomic_db, incoming_db_requests, file_system_thread_pool)
-        .map_err(|e| error!("database_loop() error: {:?}", e))
-        .map(|_| ());
+    let loop_fut = database_loop(
+        atomic_db,
+        incoming_db_requests,
+        file_system_thread_pool.clone(),
+    )
+    .map_err(|e| error!("database_loop() error: {:?}", e))
+    .map(|_| ());
 
     thread_pool
         .spawn(loop_fut)
-        .map_err(|_| NetNodeError::SpawnError)?;
+        .map_err(|_| NodeBinError::SpawnError)?;
 
     // Obtain a client to the database service:
     let database_client = DatabaseClient::new(db_request_sender);
 
-    let node_fut = net_node(
-        incoming_app_raw_conns,
-        tcp_connector,
-        timer_client,
+    Ok(NodeInstance {
         identity_client,
-        rng,
-        node_config,
+        node_config: create_node_config(),
         trusted_apps,
         node_state,
         database_client,
-        thread_pool,
+        incoming_app_raw_conns,
+        opt_incoming_relay_raw_conns,
+    })
+}
+
+pub fn stnode(st_node_cmd: StNodeCmd) -> Result<(), NodeBinError> {
+    let StNodeCmd {
+        idfile,
+        laddr,
+        database,
+        trusted,
+        stdin_ticks,
+        encrypt_db,
+        opt_passphrase_path,
+        listen_only,
+        relay_laddr,
+        relay_address,
+        opt_directory_key_path,
+    } = st_node_cmd;
+
+    // Every node needs exactly one of each:
+    let num_nodes = idfile.len();
+    if laddr.len() != num_nodes || database.len() != num_nodes || trusted.len() != num_nodes {
+        return Err(NodeBinError::NodeArgsMismatch);
+    }
+
+    // Embedded relays are either enabled for all the nodes, or disabled for all of them:
+    if relay_laddr.len() != relay_address.len()
+        || (!relay_laddr.is_empty() && relay_laddr.len() != num_nodes)
+    {
+        return Err(NodeBinError::NodeArgsMismatch);
+    }
+    let relay_address = relay_address
+        .into_iter()
+        .map(NetAddress::try_from)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| NodeBinError::InvalidRelayAddress)?;
+    let mut relay_args = relay_laddr.into_iter().zip(relay_address.into_iter());
+
+    // Create a ThreadPool:
+    let thread_pool = ThreadPool::new().map_err(|_| NodeBinError::CreateThreadPoolError)?;
+
+    // Create thread pool for file system operations:
+    let file_system_thread_pool =
+        ThreadPool::new().map_err(|_| NodeBinError::CreateThreadPoolError)?;
+
+    // Get a timer client:
+    let timer_client = create_bin_timer(stdin_ticks, thread_pool.clone())
+        .map_err(|_| NodeBinError::CreateTimerError)?;
+
+    // A connector used to connect to remote servers.
+    // Every address is reached using TCP or QUIC, according to its prefix (For example:
+    // `quic://relay.example.com:1339`).
+    // The QUIC runtime must stay alive for as long as the nodes run:
+    let quic_runtime = create_quic_runtime().map_err(|_| NodeBinError::CreateQuicRuntimeError)?;
+    let net_connector = TransportConnector::new(
+        TcpConnector::new(MAX_FRAME_LENGTH, thread_pool.clone()),
+        QuicConnector::new(
+            MAX_FRAME_LENGTH,
+            quic_runtime.handle().clone(),
+            thread_pool.clone(),
+        ),
     );
 
-    block_on(node_fut).map_err(NodeBinError::NetNodeError)
+    // Obtain secure cryptographic random:
+    let rng = system_random();
+
+    let opt_index_directory_key = match opt_directory_key_path {
+        Some(directory_key_path) => {
+            let index_directory_key_file: IndexDirectoryKeyFile =
+                deserialize_from_string(&fs::read_to_string(&directory_key_path)?)?;
+            Some(index_directory_key_file.public_key)
+        }
+        None => None,
+    };
+
+    let opt_passphrase = match opt_passphrase_path {
+        Some(passphrase_path) => Some(fs::read_to_string(&passphrase_path)?.trim_end().to_owned()),
+        None => None,
+    };
+
+    // All the nodes share the timer, the connector and the thread pool:
+    let mut node_runner = NodeRunner::new(net_connector, timer_client, rng, thread_pool.clone());
+
+    let mut nodes_done = Vec::new();
+    let node_args = idfile
+        .iter()
+        .zip(laddr.into_iter())
+        .zip(database.into_iter())
+        .zip(trusted.into_iter());
+    for (((idfile, laddr), database), trusted) in node_args {
+        let opt_relay_args = relay_args.next();
+        let mut node_instance = load_node_instance(
+            idfile,
+            laddr,
+            opt_relay_args
+                .as_ref()
+                .map(|(relay_laddr, _relay_address)| *relay_laddr),
+            database,
+            trusted,
+            encrypt_db,
+            &opt_passphrase,
+            &thread_pool,
+            &file_system_thread_pool,
+        )?;
+        node_instance.node_config.listen_only = listen_only;
+        node_instance.node_config.opt_index_directory_key = opt_index_directory_key.clone();
+        node_instance.node_config.opt_embedded_relay_address =
+            opt_relay_args.map(|(_relay_laddr, relay_address)| relay_address);
+        let running_node = block_on(node_runner.spawn_node(node_instance))?;
+        info!(
+            "stnode: Running node {:?} (Identity file: {:?})",
+            running_node.local_public_key, idfile
+        );
+        // TODO: Use the node handles for a graceful shutdown (For example, on SIGTERM)
+        nodes_done.push(running_node.node_done);
+    }
+
+    // A node that stops does not stop the other nodes:
+    let mut res = Ok(());
+    for node_res in block_on(future::join_all(nodes_done)) {
+        if let Err(e) = node_res {
+            error!("stnode: Node stopped with an error: {:?}", e);
+            if res.is_ok() {
+                res = Err(NodeBinError::NetNodeError(e));
+            }
+        }
+    }
+    res
 }
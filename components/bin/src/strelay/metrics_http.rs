@@ -0,0 +1,80 @@
+use std::net::SocketAddr;
+
+use async_std::net::{TcpListener as AsyncStdTcpListener, TcpStream};
+
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use futures::task::{Spawn, SpawnExt};
+use futures::StreamExt;
+
+use relay::RelayMetrics;
+
+/// Maximum size of an HTTP request header we are willing to read
+const MAX_REQUEST_HEADER_LEN: usize = 0x1000;
+
+/// The path where the metrics are served
+const METRICS_PATH: &str = "/metrics";
+
+/// Read an HTTP request header, and return the path of the request.
+/// Returns None if the request is not a valid GET request.
+async fn read_request_path(tcp_stream: &mut TcpStream) -> Option<String> {
+    let mut header = Vec::new();
+    let mut buff = [0u8; 0x100];
+    while !header.ends_with(b"\r\n\r\n") {
+        let num_read = tcp_stream.read(&mut buff).await.ok()?;
+        if num_read == 0 || header.len() + num_read > MAX_REQUEST_HEADER_LEN {
+            return None;
+        }
+        header.extend_from_slice(&buff[..num_read]);
+    }
+
+    let header = String::from_utf8(header).ok()?;
+    let mut request_line = header.lines().next()?.split_whitespace();
+    if request_line.next()? != "GET" {
+        return None;
+    }
+    request_line.next().map(str::to_owned)
+}
+
+async fn serve_metrics_conn(mut tcp_stream: TcpStream, metrics: RelayMetrics) {
+    let response = match read_request_path(&mut tcp_stream).await {
+        Some(ref path) if path == METRICS_PATH => {
+            let body = metrics.render_prometheus();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        Some(_) => {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+        }
+        None => {
+            "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+        }
+    };
+    if let Err(e) = tcp_stream.write_all(response.as_bytes()).await {
+        warn!("serve_metrics_conn(): Failed sending response: {:?}", e);
+    }
+}
+
+/// Serve the relay server metrics in the Prometheus text format, over HTTP.
+/// The metrics are available at the path `/metrics`.
+pub fn serve_metrics<S>(laddr: SocketAddr, metrics: RelayMetrics, spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let c_spawner = spawner.clone();
+    let _ = spawner.spawn(async move {
+        let listener = match AsyncStdTcpListener::bind(&laddr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                warn!("Failed listening for metrics on {:?}: {:?}", laddr, e);
+                return;
+            }
+        };
+        let mut incoming_streams = listener.incoming();
+        while let Some(Ok(tcp_stream)) = incoming_streams.next().await {
+            let _ = c_spawner.spawn(serve_metrics_conn(tcp_stream, metrics.clone()));
+        }
+    });
+}
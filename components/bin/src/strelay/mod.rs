@@ -1,5 +1,7 @@
+mod metrics_http;
 mod net_relay;
 mod strelaylib;
 
+pub use self::metrics_http::serve_metrics;
 pub use self::net_relay::net_relay_server;
 pub use self::strelaylib::{strelay, RelayServerBinError, StRelayCmd};
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::marker::Unpin;
 
 use futures::channel::mpsc;
@@ -6,10 +7,10 @@ use futures::{FutureExt, Stream, TryFutureExt};
 
 use derive_more::*;
 
-use common::conn::{BoxFuture, ConnPairVec, FutTransform};
+use common::conn::{BoxFuture, ConnPairVec, FuncFutTransform, FutTransform};
 use common::transform_pool::transform_pool_loop;
 
-use proto::consts::{CONN_TIMEOUT_TICKS, KEEPALIVE_TICKS};
+use proto::consts::{MAX_RELAY_LISTENERS, MAX_RELAY_TUNNEL_BUFFERED_BYTES};
 use proto::crypto::PublicKey;
 
 use crypto::rand::CryptoRandom;
@@ -19,7 +20,7 @@ use timer::TimerClient;
 
 use connection::create_version_encrypt_keepalive;
 
-use relay::{relay_server, RelayServerError};
+use relay::{relay_server, RelayMetrics, RelayServerError, RelayTimeouts};
 
 #[derive(Debug, From)]
 pub enum NetRelayServerError {
@@ -76,16 +77,26 @@ where
     }
 }
 
-pub async fn net_relay_server<IRC, R, S>(
+/// `peers` are the trusted peer relays, and their addresses. `raw_peer_net_connector` is used to
+/// open connections to peer relays.
+/// `relay_timeouts` limit the lifetime of the connections to the relay.
+/// `metrics` is updated with the activity of the relay server.
+pub async fn net_relay_server<IRC, A, PC, R, S>(
     incoming_raw_conns: IRC,
+    raw_peer_net_connector: PC,
     identity_client: IdentityClient,
     timer_client: TimerClient,
     rng: R,
+    peers: HashMap<PublicKey, A>,
+    relay_timeouts: RelayTimeouts,
     max_concurrent_encrypt: usize,
+    metrics: RelayMetrics,
     spawner: S,
 ) -> Result<(), NetRelayServerError>
 where
     IRC: Stream<Item = ConnPairVec> + Unpin + Send + 'static,
+    A: Clone + Send + Sync + 'static,
+    PC: FutTransform<Input = A, Output = Option<ConnPairVec>> + Clone + Send + 'static,
     R: CryptoRandom + Clone + 'static,
     S: Spawn + Clone + Send + Sync + 'static,
 {
@@ -94,7 +105,7 @@ where
     let transform = AnonSecureChannel::new(
         timer_client.clone(),
         identity_client.clone(),
-        rng,
+        rng.clone(),
         spawner.clone(),
     );
 
@@ -112,11 +123,36 @@ where
         .spawn(enc_pool_fut)
         .map_err(|_| NetRelayServerError::SpawnError)?;
 
+    // Apply transform to create peer relays connector:
+    let c_timer_client = timer_client.clone();
+    let c_identity_client = identity_client.clone();
+    let c_spawner = spawner.clone();
+    let peer_connector = FuncFutTransform::new(move |(public_key, net_address)| {
+        let mut c_raw_peer_net_connector = raw_peer_net_connector.clone();
+        let mut conn_transform = create_version_encrypt_keepalive(
+            c_timer_client.clone(),
+            c_identity_client.clone(),
+            rng.clone(),
+            c_spawner.clone(),
+        );
+        Box::pin(async move {
+            let raw_conn = c_raw_peer_net_connector.transform(net_address).await?;
+            let (_public_key, conn_pair) = conn_transform
+                .transform((Some(public_key), raw_conn))
+                .await?;
+            Some(conn_pair)
+        })
+    });
+
     relay_server(
         incoming_enc_conns,
         timer_client,
-        CONN_TIMEOUT_TICKS,
-        KEEPALIVE_TICKS,
+        relay_timeouts,
+        MAX_RELAY_LISTENERS,
+        MAX_RELAY_TUNNEL_BUFFERED_BYTES,
+        peers,
+        peer_connector,
+        metrics,
         spawner.clone(),
     )
     .await?;
@@ -1,12 +1,17 @@
+use std::collections::HashMap;
 use std::fs;
 use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
 
 use derive_more::From;
 
+use async_std::net::TcpStream;
+
+use futures::channel::mpsc;
 use futures::executor::{block_on, ThreadPool};
-use futures::task::SpawnExt;
+use futures::stream::select;
+use futures::task::{Spawn, SpawnExt};
+use futures::{SinkExt, StreamExt};
 
 use structopt::StructOpt;
 
@@ -16,15 +21,15 @@ use crypto::identity::SoftwareEd25519Identity;
 use crypto::rand::system_random;
 use identity::{create_identity, IdentityClient};
 
-use proto::consts::{MAX_FRAME_LENGTH, TICK_MS};
-
-use common::int_convert::usize_to_u64;
+use proto::consts::MAX_FRAME_LENGTH;
 
+use crate::strelay::metrics_http::serve_metrics;
 use crate::strelay::net_relay::{net_relay_server, NetRelayServerError};
-use net::TcpListener;
-use timer::create_timer;
+use crate::ticks::create_bin_timer;
+use net::{bind_tcp_listener, create_quic_runtime, QuicListener, TcpConnector, TcpListener};
+use relay::{ws_listener, RelayMetrics, RelayTimeouts};
 
-use proto::file::IdentityFile;
+use proto::file::{IdentityFile, RelayAddressFile};
 use proto::ser_string::{deserialize_from_string, StringSerdeError};
 
 // TODO: Maybe take as a command line argument in the future?
@@ -32,6 +37,10 @@ use proto::ser_string::{deserialize_from_string, StringSerdeError};
 /// We set this number to avoid DoS from half finished encrypted channel negotiations.
 pub const MAX_CONCURRENT_ENCRYPT: usize = 0x200;
 
+/// Maximum amount of concurrent WebSocket handshakes.
+/// We set this number to avoid DoS from clients that never finish their handshake.
+pub const MAX_CONCURRENT_WS_HANDSHAKES: usize = 0x200;
+
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug, From)]
 pub enum RelayServerBinError {
@@ -39,6 +48,7 @@ pub enum RelayServerBinError {
     LoadIdentityError,
     CreateIdentityError,
     CreateTimerError,
+    CreateQuicRuntimeError,
     NetRelayServerError(NetRelayServerError),
     IoError(std::io::Error),
     StringSerdeError(StringSerdeError),
@@ -52,19 +62,133 @@ pub struct StRelayCmd {
     /// StCtrl app identity file path
     #[structopt(parse(from_os_str), short = "i", long = "idfile")]
     pub idfile: PathBuf,
-    /// Listening address (Example: 0.0.0.0:1337)
+    /// Listening address (Example: 0.0.0.0:1337).
+    /// Listening on [::] (Example: [::]:1337) accepts both IPv6 and IPv4 connections
     #[structopt(short = "l", long = "laddr")]
     pub laddr: SocketAddr,
+    /// Optional listening address for WebSocket connections (Example: 0.0.0.0:1338)
+    #[structopt(short = "w", long = "ws_laddr")]
+    pub ws_laddr: Option<SocketAddr>,
+    /// Optional listening address for QUIC connections (Example: 0.0.0.0:1339)
+    #[structopt(short = "q", long = "quic_laddr")]
+    pub quic_laddr: Option<SocketAddr>,
+    /// Optional listening address for serving metrics over HTTP, in the Prometheus text format
+    /// (Example: 127.0.0.1:9100). Metrics are served at the path /metrics
+    #[structopt(short = "m", long = "metrics_laddr")]
+    pub metrics_laddr: Option<SocketAddr>,
+    /// Optional directory path of trusted peer relays.
+    /// Connections to nodes listening on a peer relay are forwarded to the peer relay.
+    #[structopt(parse(from_os_str), short = "p", long = "peers")]
+    pub peers: Option<PathBuf>,
+    /// Take timer ticks from stdin instead of the internal clock.
+    /// Every line is an amount of ticks (An empty line is a single tick).
+    #[structopt(long = "stdin_ticks")]
+    pub stdin_ticks: bool,
+    /// Optional maximum amount of concurrent TCP connections from a single host.
+    /// An IPv4 host is counted as the same host whether it connects over IPv4 or over IPv6
+    /// (v4-mapped address)
+    #[structopt(long = "max_client_conns")]
+    pub max_client_conns: Option<usize>,
+    /// Optional amount of ticks a listen connection may go without any incoming connection
+    /// before it is closed. Listen connections are never closed for being idle by default
+    #[structopt(long = "listen_idle_ticks")]
+    pub listen_idle_ticks: Option<usize>,
+    /// Optional amount of ticks no data may pass through a tunnel before it is closed
+    #[structopt(long = "tunnel_idle_ticks")]
+    pub tunnel_idle_ticks: Option<usize>,
+    /// Optional maximum lifetime of a tunnel, in ticks. Tunnels may live forever by default
+    #[structopt(long = "tunnel_max_ticks")]
+    pub tunnel_max_ticks: Option<usize>,
+    /// Optional amount of ticks a tunnel stays open after one of its sides was closed
+    #[structopt(long = "half_open_ticks")]
+    pub half_open_ticks: Option<usize>,
+}
+
+/// Listen for incoming TCP connections, returning the raw TCP streams.
+fn listen_tcp_streams<S>(laddr: SocketAddr, spawner: S) -> mpsc::Receiver<TcpStream>
+where
+    S: Spawn,
+{
+    let (mut stream_sender, stream_receiver) = mpsc::channel(0);
+    let _ = spawner.spawn(async move {
+        let listener = match bind_tcp_listener(laddr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                warn!("Failed listening on {:?}: {:?}", laddr, e);
+                return;
+            }
+        };
+        let mut incoming_streams = listener.incoming();
+        while let Some(res_tcp_stream) = incoming_streams.next().await {
+            // A failure to accept one connection should not stop the listener:
+            let tcp_stream = match res_tcp_stream {
+                Ok(tcp_stream) => tcp_stream,
+                Err(e) => {
+                    warn!("Failed accepting a connection on {:?}: {:?}", laddr, e);
+                    continue;
+                }
+            };
+            if stream_sender.send(tcp_stream).await.is_err() {
+                return;
+            }
+        }
+    });
+    stream_receiver
+}
+
+/// Load a directory of relay address files, and return a map representing
+/// the information from all files
+fn load_peers(dir_path: &Path) -> Result<Vec<RelayAddressFile>, RelayServerBinError> {
+    let mut res_peers = Vec::new();
+    for entry in fs::read_dir(dir_path)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_dir() {
+            continue;
+        }
+        res_peers.push(deserialize_from_string(&fs::read_to_string(&path)?)?);
+    }
+    Ok(res_peers)
 }
 
 pub fn strelay(st_relay_cmd: StRelayCmd) -> Result<(), RelayServerBinError> {
-    let StRelayCmd { idfile, laddr } = st_relay_cmd;
+    let StRelayCmd {
+        idfile,
+        laddr,
+        ws_laddr,
+        quic_laddr,
+        metrics_laddr,
+        peers,
+        stdin_ticks,
+        max_client_conns,
+        listen_idle_ticks,
+        tunnel_idle_ticks,
+        tunnel_max_ticks,
+        half_open_ticks,
+    } = st_relay_cmd;
+
+    let default_timeouts = RelayTimeouts::default();
+    let relay_timeouts = RelayTimeouts {
+        opt_listen_idle_ticks: listen_idle_ticks,
+        tunnel_idle_ticks: tunnel_idle_ticks.unwrap_or(default_timeouts.tunnel_idle_ticks),
+        opt_tunnel_max_ticks: tunnel_max_ticks,
+        half_open_ticks: half_open_ticks.unwrap_or(default_timeouts.half_open_ticks),
+        ..default_timeouts
+    };
 
     // Parse identity file:
     let identity_file: IdentityFile = deserialize_from_string(&fs::read_to_string(&idfile)?)?;
     let identity = SoftwareEd25519Identity::from_private_key(&identity_file.private_key)
         .map_err(|_| RelayServerBinError::LoadIdentityError)?;
 
+    let peers = match peers {
+        Some(peers) => load_peers(&peers)?
+            .into_iter()
+            .map(|relay_address_file| (relay_address_file.public_key, relay_address_file.address))
+            .collect::<HashMap<_, _>>(),
+        None => HashMap::new(),
+    };
+
     // Create a ThreadPool:
     let thread_pool = ThreadPool::new().map_err(|_| RelayServerBinError::CreateThreadPoolError)?;
 
@@ -75,21 +199,74 @@ pub fn strelay(st_relay_cmd: StRelayCmd) -> Result<(), RelayServerBinError> {
         .map_err(|_| RelayServerBinError::CreateIdentityError)?;
     let identity_client = IdentityClient::new(sender);
 
-    let dur = Duration::from_millis(usize_to_u64(TICK_MS).unwrap());
-    let timer_client = create_timer(dur, thread_pool.clone())
+    let timer_client = create_bin_timer(stdin_ticks, thread_pool.clone())
         .map_err(|_| RelayServerBinError::CreateTimerError)?;
 
     let rng = system_random();
 
-    let tcp_listener = TcpListener::new(MAX_FRAME_LENGTH, thread_pool.clone());
+    let tcp_listener = match max_client_conns {
+        Some(max_client_conns) => TcpListener::with_max_client_conns(
+            MAX_FRAME_LENGTH,
+            max_client_conns,
+            thread_pool.clone(),
+        ),
+        None => TcpListener::new(MAX_FRAME_LENGTH, thread_pool.clone()),
+    };
     let (_config_sender, incoming_raw_conns) = tcp_listener.listen(laddr);
 
+    // WebSocket connections (For example, from browsers) are handled just like TCP connections
+    // once the WebSocket handshake is done:
+    let incoming_raw_conns = if let Some(ws_laddr) = ws_laddr {
+        let incoming_ws_conns = ws_listener(
+            listen_tcp_streams(ws_laddr, thread_pool.clone()),
+            MAX_FRAME_LENGTH,
+            timer_client.clone(),
+            relay_timeouts.conn_timeout_ticks,
+            MAX_CONCURRENT_WS_HANDSHAKES,
+            thread_pool.clone(),
+        );
+        select(incoming_raw_conns, incoming_ws_conns).boxed()
+    } else {
+        incoming_raw_conns.boxed()
+    };
+
+    // QUIC connections are handled just like TCP connections.
+    // The QUIC runtime must stay alive for as long as the relay server runs:
+    let (incoming_raw_conns, _opt_quic_runtime) = if let Some(quic_laddr) = quic_laddr {
+        let quic_runtime =
+            create_quic_runtime().map_err(|_| RelayServerBinError::CreateQuicRuntimeError)?;
+        let quic_listener = QuicListener::new(
+            MAX_FRAME_LENGTH,
+            quic_runtime.handle().clone(),
+            thread_pool.clone(),
+        );
+        let (_quic_config_sender, incoming_quic_conns) = quic_listener.listen(quic_laddr);
+        (
+            select(incoming_raw_conns, incoming_quic_conns).boxed(),
+            Some(quic_runtime),
+        )
+    } else {
+        (incoming_raw_conns, None)
+    };
+
+    let metrics = RelayMetrics::new();
+    if let Some(metrics_laddr) = metrics_laddr {
+        serve_metrics(metrics_laddr, metrics.clone(), thread_pool.clone());
+    }
+
+    // A tcp connector, Used to connect to peer relays:
+    let raw_peer_net_connector = TcpConnector::new(MAX_FRAME_LENGTH, thread_pool.clone());
+
     let relay_server_fut = net_relay_server(
         incoming_raw_conns,
+        raw_peer_net_connector,
         identity_client,
         timer_client,
         rng,
+        peers,
+        relay_timeouts,
         MAX_CONCURRENT_ENCRYPT,
+        metrics,
         thread_pool,
     );
 
@@ -0,0 +1,73 @@
+use std::io::{self, BufRead};
+use std::thread;
+use std::time::Duration;
+
+use futures::channel::mpsc;
+use futures::executor::block_on;
+use futures::task::Spawn;
+use futures::SinkExt;
+
+use common::int_convert::usize_to_u64;
+
+use proto::consts::TICK_MS;
+use timer::{create_timer, create_timer_incoming, TimerClient, TimerError};
+
+/// Parse a line from the ticks control pipe.
+/// An empty line means a single tick. Otherwise the line should contain the amount of ticks to
+/// send.
+fn parse_ticks_line(line: &str) -> Option<usize> {
+    let line = line.trim();
+    if line.is_empty() {
+        Some(1)
+    } else {
+        line.parse::<usize>().ok()
+    }
+}
+
+/// Read ticks from stdin, and forward them to `tick_sender`.
+/// Returns when stdin is closed.
+fn stdin_ticks_loop(mut tick_sender: mpsc::Sender<()>) {
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                error!("stdin_ticks_loop(): Failed to read line: {:?}", e);
+                return;
+            }
+        };
+        let num_ticks = match parse_ticks_line(&line) {
+            Some(num_ticks) => num_ticks,
+            None => {
+                warn!("stdin_ticks_loop(): Invalid ticks line: {:?}", line);
+                continue;
+            }
+        };
+        for _ in 0..num_ticks {
+            if block_on(tick_sender.send(())).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Create a timer client for a binary.
+///
+/// By default the timer ticks every TICK_MS milliseconds. If `stdin_ticks` is set, ticks are
+/// taken from stdin instead: Every line is the amount of ticks to send (An empty line is one
+/// tick). This allows to run the binaries in accelerated time, for example to test long term
+/// behaviours (rekeying, pruning, expiry) in soak tests.
+/// Closing stdin stops the timer.
+pub fn create_bin_timer<S>(stdin_ticks: bool, spawner: S) -> Result<TimerClient, TimerError>
+where
+    S: Spawn,
+{
+    if !stdin_ticks {
+        let dur = Duration::from_millis(usize_to_u64(TICK_MS).unwrap());
+        return create_timer(dur, spawner);
+    }
+
+    let (tick_sender, tick_receiver) = mpsc::channel(0);
+    thread::spawn(move || stdin_ticks_loop(tick_sender));
+    create_timer_incoming(tick_receiver, spawner)
+}
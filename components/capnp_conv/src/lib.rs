@@ -53,6 +53,13 @@ pub trait ToCapnpBytes {
 pub trait FromCapnpBytes: Sized {
     /// Deserialize a Rust struct from bytes using Capnp
     fn from_capnp_bytes(bytes: &[u8]) -> Result<Self, CapnpConvError>;
+
+    /// Deserialize a Rust struct from bytes using Capnp, reading at most
+    /// `traversal_limit_in_words` words of the (unpacked) message.
+    fn from_capnp_bytes_limited(
+        bytes: &[u8],
+        traversal_limit_in_words: u64,
+    ) -> Result<Self, CapnpConvError>;
 }
 
 impl<T> ToCapnpBytes for T
@@ -80,12 +87,28 @@ where
     T: for<'a> ReadCapnp<'a>,
 {
     fn from_capnp_bytes(bytes: &[u8]) -> Result<Self, CapnpConvError> {
-        let mut cursor = io::Cursor::new(&bytes);
-        let reader = capnp::serialize_packed::read_message(
-            &mut cursor,
-            capnp::message::ReaderOptions::new(),
-        )?;
-        let struct_reader = reader.get_root::<T::ReaderType>()?;
-        Ok(Self::read_capnp(&struct_reader)?)
+        read_capnp_bytes(bytes, capnp::message::ReaderOptions::new())
+    }
+
+    fn from_capnp_bytes_limited(
+        bytes: &[u8],
+        traversal_limit_in_words: u64,
+    ) -> Result<Self, CapnpConvError> {
+        let mut reader_options = capnp::message::ReaderOptions::new();
+        reader_options.traversal_limit_in_words(traversal_limit_in_words);
+        read_capnp_bytes(bytes, reader_options)
     }
 }
+
+fn read_capnp_bytes<T>(
+    bytes: &[u8],
+    reader_options: capnp::message::ReaderOptions,
+) -> Result<T, CapnpConvError>
+where
+    T: for<'a> ReadCapnp<'a>,
+{
+    let mut cursor = io::Cursor::new(&bytes);
+    let reader = capnp::serialize_packed::read_message(&mut cursor, reader_options)?;
+    let struct_reader = reader.get_root::<T::ReaderType>()?;
+    Ok(T::read_capnp(&struct_reader)?)
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use proto::crypto::PublicKey;
+use proto::report::messages::BandwidthReport;
+
+/// Counts the bytes of messages exchanged with every friend, and aggregates them into periodic
+/// reports.
+///
+/// Bytes are counted over all the connections to a friend, so that reconnections are not lost.
+pub struct BandwidthMeter {
+    /// Bytes counted since the last report, for every friend that exchanged any bytes
+    pending: HashMap<PublicKey, BandwidthReport>,
+    report_ticks: usize,
+    ticks_left: usize,
+}
+
+impl BandwidthMeter {
+    pub fn new(report_ticks: usize) -> Self {
+        BandwidthMeter {
+            pending: HashMap::new(),
+            report_ticks,
+            ticks_left: report_ticks,
+        }
+    }
+
+    /// A message of `num_bytes` was sent to a friend
+    pub fn sent(&mut self, friend_public_key: &PublicKey, num_bytes: usize) {
+        let bandwidth = self
+            .pending
+            .entry(friend_public_key.clone())
+            .or_insert_with(BandwidthReport::default);
+        bandwidth.bytes_sent = bandwidth.bytes_sent.saturating_add(num_bytes as u64);
+    }
+
+    /// A message of `num_bytes` was received from a friend
+    pub fn received(&mut self, friend_public_key: &PublicKey, num_bytes: usize) {
+        let bandwidth = self
+            .pending
+            .entry(friend_public_key.clone())
+            .or_insert_with(BandwidthReport::default);
+        bandwidth.bytes_received = bandwidth.bytes_received.saturating_add(num_bytes as u64);
+    }
+
+    /// Forget the bytes counted for a removed friend
+    pub fn remove_friend(&mut self, friend_public_key: &PublicKey) {
+        let _ = self.pending.remove(friend_public_key);
+    }
+
+    /// Advance time by one tick.
+    /// Once every `report_ticks` ticks, returns the bytes counted for every friend since the
+    /// previous report.
+    pub fn tick(&mut self) -> Vec<(PublicKey, BandwidthReport)> {
+        self.ticks_left = self.ticks_left.saturating_sub(1);
+        if self.ticks_left > 0 {
+            return Vec::new();
+        }
+        self.ticks_left = self.report_ticks;
+        self.pending.drain().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bandwidth_meter() {
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let mut bandwidth_meter = BandwidthMeter::new(3);
+
+        bandwidth_meter.sent(&pk_a, 10);
+        bandwidth_meter.received(&pk_a, 4);
+        bandwidth_meter.sent(&pk_a, 5);
+        bandwidth_meter.received(&pk_b, 7);
+        bandwidth_meter.remove_friend(&pk_b);
+
+        assert!(bandwidth_meter.tick().is_empty());
+        assert!(bandwidth_meter.tick().is_empty());
+        assert_eq!(
+            bandwidth_meter.tick(),
+            vec![(
+                pk_a.clone(),
+                BandwidthReport {
+                    bytes_sent: 15,
+                    bytes_received: 4,
+                }
+            )]
+        );
+
+        // Friends that exchanged no bytes are not reported:
+        for _ in 0..3 {
+            assert!(bandwidth_meter.tick().is_empty());
+        }
+
+        bandwidth_meter.received(&pk_b, 1);
+        bandwidth_meter.tick();
+        bandwidth_meter.tick();
+        assert_eq!(
+            bandwidth_meter.tick(),
+            vec![(
+                pk_b,
+                BandwidthReport {
+                    bytes_sent: 0,
+                    bytes_received: 1,
+                }
+            )]
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::hash::Hash;
 use std::marker::Unpin;
 
 use futures::channel::{mpsc, oneshot};
@@ -11,18 +12,31 @@ use common::conn::{BoxStream, ConnPairVec, FutTransform, Listener};
 use common::select_streams::select_streams;
 use crypto::identity::compare_public_key;
 
-use proto::crypto::PublicKey;
-use proto::funder::messages::{ChannelerToFunder, ChannelerUpdateFriend, FunderToChanneler};
+use proto::consts::FRIEND_QUEUE_CONGESTION_DEPTH;
+use proto::crypto::{PublicKey, Uid};
+use proto::funder::messages::{
+    ChannelerToFunder, ChannelerUpdateFriend, FunderToChanneler, MessageDelivery,
+};
+use proto::keepalive::messages::KeepAliveReport;
+use proto::secure_channel::messages::SecureChannelReport;
 
+use crate::bandwidth::BandwidthMeter;
 use crate::connect_pool::{ConnectPoolControl, CpConfigClient, CpConnectClient};
 use crate::listen_pool::LpConfig;
-use crate::overwrite_channel::overwrite_send_all;
+use crate::outgoing_queues::OutgoingQueues;
+use crate::relay_prober::{probe_relay, RelayLatencies};
 
 #[derive(Debug)]
 pub enum ChannelerEvent<RA> {
     FromFunder(FunderToChanneler<RA>),
     Connection((PublicKey, ConnPairVec)),
     FriendEvent(FriendEvent),
+    KeepAliveReport((PublicKey, KeepAliveReport)),
+    SecureChannelReport((PublicKey, SecureChannelReport)),
+    /// Time to measure the latency of all relays
+    ProbeTick,
+    RelayProbeDone((RA, Option<u64>)),
+    TimerTick,
     ListenerClosed,
     FunderClosed,
 }
@@ -30,6 +44,8 @@ pub enum ChannelerEvent<RA> {
 #[derive(Debug)]
 pub enum FriendEvent {
     IncomingMessage((PublicKey, Vec<u8>)),
+    /// A message was sent over a connection to a friend
+    SendDone((PublicKey, u64)), // (friend_public_key, conn_id)
     ReceiverClosed(PublicKey),
 }
 
@@ -44,24 +60,34 @@ pub enum ChannelerError {
     ListenerClosed,
     FunderClosed,
     ConnectorConfigError,
+    RequestTimerStreamError,
 }
 
 struct Connected<T> {
+    /// Identifies the connection, as the same friend may reconnect
+    conn_id: u64,
     opt_sender: Option<mpsc::Sender<T>>,
-    // TODO: Do we really need the closer here? Check it.
-    #[allow(unused)]
     /// When dropped, this will trigger closing of the receiving side task:
-    closer: oneshot::Sender<()>,
+    opt_closer: Option<oneshot::Sender<()>>,
 }
 
 impl<T> Connected<T> {
-    pub fn new(sender: mpsc::Sender<T>, closer: oneshot::Sender<()>) -> Self {
+    pub fn new(conn_id: u64, sender: mpsc::Sender<T>, closer: oneshot::Sender<()>) -> Self {
         Connected {
+            conn_id,
             opt_sender: Some(sender),
-            closer,
+            opt_closer: Some(closer),
         }
     }
 
+    /// Close the connection.
+    /// Subsequent sends will fail. The receiving side task is closed, and the friend will be
+    /// reported as offline.
+    pub fn close(&mut self) {
+        self.opt_sender.take();
+        self.opt_closer.take();
+    }
+
     /// Send an item.
     /// If a failure occurs, the internal sender is removed
     /// and subsequent sends will fail too.
@@ -97,6 +123,10 @@ struct OutFriend<RA> {
     config_client: CpConfigClient<RA>,
     connect_client: CpConnectClient,
     status: OutFriendStatus,
+    /// Relays of the friend, as configured by the Funder
+    relays: Vec<RA>,
+    /// Relays of the friend, in the order last sent to the connect pool
+    sorted_relays: Vec<RA>,
 }
 
 struct Friends<RA> {
@@ -133,30 +163,60 @@ impl<RA> Friends<RA> {
 
         None
     }
+
+    /// Is `public_key` a friend (Connected or not)?
+    pub fn contains(&self, public_key: &PublicKey) -> bool {
+        self.in_friends.contains_key(public_key) || self.out_friends.contains_key(public_key)
+    }
 }
 
-struct Channeler<RA, C, S, TF> {
+struct Channeler<RA, C, RC, S, TF> {
     local_public_key: PublicKey,
     friends: Friends<RA>,
     connector: C,
+    /// Connector used to measure the latency of relays
+    relay_connector: RC,
     /// Configuration sender for the listening task:
     listen_config: mpsc::Sender<LpConfig<RA>>,
+    /// Our relays, as configured by the Funder
+    local_relays: Vec<RA>,
+    relay_latencies: RelayLatencies<RA>,
+    /// Cancelers of probes that are currently in progress
+    relay_probes: HashMap<RA, oneshot::Sender<()>>,
+    /// Messages waiting to be sent to friends
+    outgoing_queues: OutgoingQueues,
+    /// Maximum amount of messages being sent to friends at the same time
+    max_concurrent_sends: usize,
+    /// Bytes exchanged with friends, periodically reported to the Funder
+    bandwidth_meter: BandwidthMeter,
+    /// Used to identify the next connection to a friend
+    next_conn_id: u64,
+    /// Never connect to friends' relays. All friends are expected to connect to us, through our
+    /// relays.
+    listen_only: bool,
     spawner: S,
     to_funder: TF,
     event_sender: mpsc::Sender<ChannelerEvent<RA>>,
 }
 
-impl<RA, C, S, TF> Channeler<RA, C, S, TF>
+impl<RA, C, RC, S, TF> Channeler<RA, C, RC, S, TF>
 where
-    RA: Clone + Send + Sync + 'static,
+    RA: Hash + Eq + Clone + Send + Sync + 'static,
     C: FutTransform<Input = PublicKey, Output = ConnectPoolControl<RA>> + Clone + Send + 'static,
+    RC: FutTransform<Input = RA, Output = Option<ConnPairVec>> + Clone + Send + 'static,
     S: Spawn + Clone + Send + 'static,
-    TF: Sink<ChannelerToFunder> + Send + Unpin,
+    TF: Sink<ChannelerToFunder<RA>> + Send + Unpin,
 {
     fn new(
         local_public_key: PublicKey,
         connector: C,
+        relay_connector: RC,
         listen_config: mpsc::Sender<LpConfig<RA>>,
+        max_friend_queue_len: usize,
+        max_concurrent_sends: usize,
+        send_timeout_ticks: usize,
+        bandwidth_report_ticks: usize,
+        listen_only: bool,
         spawner: S,
         to_funder: TF,
         event_sender: mpsc::Sender<ChannelerEvent<RA>>,
@@ -165,17 +225,102 @@ where
             local_public_key,
             friends: Friends::new(),
             connector,
+            relay_connector,
             listen_config,
+            local_relays: Vec::new(),
+            relay_latencies: RelayLatencies::new(),
+            relay_probes: HashMap::new(),
+            outgoing_queues: OutgoingQueues::new(max_friend_queue_len, send_timeout_ticks),
+            max_concurrent_sends,
+            bandwidth_meter: BandwidthMeter::new(bandwidth_report_ticks),
+            next_conn_id: 0,
+            listen_only,
             spawner,
             to_funder,
             event_sender,
         }
     }
 
+    /// Let the Funder know when the amount of messages waiting to be sent to a friend crosses
+    /// `FRIEND_QUEUE_CONGESTION_DEPTH`, so that it can slow down.
+    async fn report_queue_depth<'a>(
+        &'a mut self,
+        friend_public_key: &'a PublicKey,
+        prev_depth: usize,
+    ) -> Result<(), ChannelerError> {
+        let depth = self.outgoing_queues.depth(friend_public_key);
+        let was_congested = prev_depth >= FRIEND_QUEUE_CONGESTION_DEPTH;
+        let is_congested = depth >= FRIEND_QUEUE_CONGESTION_DEPTH;
+        if was_congested == is_congested {
+            return Ok(());
+        }
+
+        let to_funder = ChannelerToFunder::QueueDepth((friend_public_key.clone(), depth));
+        self.to_funder
+            .send(to_funder)
+            .await
+            .map_err(|_| ChannelerError::SendToFunderFailed)
+    }
+
+    /// Let the Funder know what happened to messages it asked us to send to a friend.
+    async fn report_delivery<'a>(
+        &'a mut self,
+        friend_public_key: &'a PublicKey,
+        message_ids: Vec<Uid>,
+        delivery: MessageDelivery,
+    ) -> Result<(), ChannelerError> {
+        for message_id in message_ids {
+            let to_funder = ChannelerToFunder::MessageDelivery((
+                friend_public_key.clone(),
+                message_id,
+                delivery,
+            ));
+            self.to_funder
+                .send(to_funder)
+                .await
+                .map_err(|_| ChannelerError::SendToFunderFailed)?;
+        }
+        Ok(())
+    }
+
+    /// Send queued messages to friends, as long as we are not sending too many messages at the
+    /// same time.
+    async fn send_queued(&mut self) -> Result<(), ChannelerError> {
+        while self.outgoing_queues.num_sending() < self.max_concurrent_sends {
+            let (friend_public_key, _message_id, message) = match self.outgoing_queues.pop_next() {
+                Some(next) => next,
+                None => break,
+            };
+            let prev_depth = self.outgoing_queues.depth(&friend_public_key) + 1;
+
+            let message_len = message.len();
+            let is_sent = match self.friends.get_friend_connected(&friend_public_key) {
+                Some(friend_connected) => friend_connected.send(message).await,
+                None => false,
+            };
+            if is_sent {
+                self.bandwidth_meter.sent(&friend_public_key, message_len);
+            } else {
+                // The connection to the friend was closed:
+                let message_ids = self.outgoing_queues.remove_friend(&friend_public_key);
+                self.report_delivery(
+                    &friend_public_key,
+                    message_ids,
+                    MessageDelivery::FriendOffline,
+                )
+                .await?;
+            }
+            self.report_queue_depth(&friend_public_key, prev_depth)
+                .await?;
+        }
+        Ok(())
+    }
+
     /// Should we wait for a connection from `friend_public_key`.
     /// In other words: Is the remote side active?
     fn is_listen_friend(&self, friend_public_key: &PublicKey) -> bool {
-        compare_public_key(&self.local_public_key, friend_public_key) == Ordering::Less
+        self.listen_only
+            || compare_public_key(&self.local_public_key, friend_public_key) == Ordering::Less
     }
 
     fn connect_out_friend(&mut self, friend_public_key: &PublicKey) -> Result<(), ChannelerError> {
@@ -233,6 +378,8 @@ where
                 config_client,
                 connect_client,
                 status: OutFriendStatus::Connecting,
+                relays: Vec::new(),
+                sorted_relays: Vec::new(),
             };
             self.friends
                 .out_friends
@@ -247,23 +394,43 @@ where
         funder_to_channeler: FunderToChanneler<RA>,
     ) -> Result<(), ChannelerError> {
         match funder_to_channeler {
-            FunderToChanneler::Message((public_key, message)) => {
-                let friend_connected = match self.friends.get_friend_connected(&public_key) {
-                    Some(friend_connected) => friend_connected,
-                    None => {
-                        error!(
-                            "Attempt to send a message to unavailable friend: {:?}",
-                            public_key
-                        );
-                        return Ok(());
-                    }
-                };
+            FunderToChanneler::Message((public_key, message_id, message, priority)) => {
+                if self.friends.get_friend_connected(&public_key).is_none() {
+                    error!(
+                        "Attempt to send a message to unavailable friend: {:?}",
+                        public_key
+                    );
+                    return self
+                        .report_delivery(
+                            &public_key,
+                            vec![message_id],
+                            MessageDelivery::FriendOffline,
+                        )
+                        .await;
+                }
 
-                // TODO: Should we check errors here?
-                let _ = friend_connected.send(message).await;
-                Ok(())
+                let prev_depth = self.outgoing_queues.depth(&public_key);
+                if let Some(dropped_message_id) =
+                    self.outgoing_queues
+                        .push(&public_key, message_id, message, priority)
+                {
+                    warn!(
+                        "Outgoing queue of friend {:?} is full. Discarding the oldest message",
+                        public_key
+                    );
+                    self.report_delivery(
+                        &public_key,
+                        vec![dropped_message_id],
+                        MessageDelivery::Dropped,
+                    )
+                    .await?;
+                }
+                self.report_queue_depth(&public_key, prev_depth).await?;
+                self.send_queued().await
             }
             FunderToChanneler::SetRelays(addresses) => {
+                self.local_relays = addresses.clone();
+
                 // Our local listening addresses were set.
                 // We update the listener accordingly:
                 self.listen_config
@@ -292,9 +459,13 @@ where
                 } else if let Some(out_friend) =
                     self.friends.out_friends.get_mut(&friend_public_key)
                 {
+                    // Relays with lower latency are attempted first:
+                    let sorted_relays = self.relay_latencies.sort_addresses(&friend_relays);
+                    out_friend.relays = friend_relays;
+                    out_friend.sorted_relays = sorted_relays.clone();
                     out_friend
                         .config_client
-                        .config(friend_relays)
+                        .config(sorted_relays)
                         .await
                         .map_err(|_| ChannelerError::ConnectorConfigError)?;
                 }
@@ -302,6 +473,16 @@ where
                 Ok(())
             }
             FunderToChanneler::RemoveFriend(friend_public_key) => {
+                self.bandwidth_meter.remove_friend(&friend_public_key);
+                let message_ids = self.outgoing_queues.remove_friend(&friend_public_key);
+                self.report_delivery(
+                    &friend_public_key,
+                    message_ids,
+                    MessageDelivery::FriendOffline,
+                )
+                .await?;
+                self.send_queued().await?;
+
                 if self.friends.in_friends.remove(&friend_public_key).is_some() {
                     let lp_config = LpConfig::RemoveFriend(friend_public_key.clone());
                     self.listen_config
@@ -329,16 +510,32 @@ where
         // Close fut_recv whenever closer is closed.
         let (closer, close_receiver) = oneshot::channel::<()>();
 
-        // We use an overwrite channel to make sure we are never stuck on trying to send a
-        // message to remote friend. A friend only needs to know the most recent message,
-        // so previous pending messages may be discarded.
-        let (friend_sender, friend_receiver) = mpsc::channel(0);
+        let conn_id = self.next_conn_id;
+        self.next_conn_id = self.next_conn_id.wrapping_add(1);
+
+        // Messages are sent to the remote friend one at a time. We are notified whenever a
+        // message was sent, so that we are never stuck on trying to send a message to a slow
+        // friend. Pending messages wait in the outgoing queue of the friend.
+        let (friend_sender, mut friend_receiver) = mpsc::channel(0);
+        let mut c_event_sender = self.event_sender.clone();
+        let c_friend_public_key = friend_public_key.clone();
+        let fut_send = async move {
+            let mut sender = sender;
+            while let Some(message) = friend_receiver.next().await {
+                if sender.send(message).await.is_err() {
+                    return;
+                }
+                let send_done_event = ChannelerEvent::FriendEvent(FriendEvent::SendDone((
+                    c_friend_public_key.clone(),
+                    conn_id,
+                )));
+                if c_event_sender.send(send_done_event).await.is_err() {
+                    return;
+                }
+            }
+        };
         self.spawner
-            .spawn(
-                overwrite_send_all(sender, friend_receiver)
-                    .map_err(|e| error!("overwrite_send_all() error: {:?}", e))
-                    .map(|_| ()),
-            )
+            .spawn(fut_send)
             .map_err(|_| ChannelerError::SpawnError)?;
 
         if let Some(in_friend) = self.friends.in_friends.get_mut(&friend_public_key) {
@@ -351,7 +548,7 @@ where
                     return Ok(());
                 }
                 InFriend::Listening => {
-                    *in_friend = InFriend::Connected(Connected::new(friend_sender, closer))
+                    *in_friend = InFriend::Connected(Connected::new(conn_id, friend_sender, closer))
                 }
             }
         } else if let Some(mut out_friend) = self.friends.out_friends.get_mut(&friend_public_key) {
@@ -365,7 +562,7 @@ where
                 }
                 OutFriendStatus::Connecting => {
                     out_friend.status =
-                        OutFriendStatus::Connected(Connected::new(friend_sender, closer))
+                        OutFriendStatus::Connected(Connected::new(conn_id, friend_sender, closer))
                 }
             }
         } else {
@@ -418,13 +615,44 @@ where
     ) -> Result<(), ChannelerError> {
         match friend_event {
             FriendEvent::IncomingMessage((friend_public_key, data)) => {
+                self.bandwidth_meter
+                    .received(&friend_public_key, data.len());
                 let message = ChannelerToFunder::Message((friend_public_key, data));
                 self.to_funder
                     .send(message)
                     .await
                     .map_err(|_| ChannelerError::SendToFunderFailed)?
             }
+            FriendEvent::SendDone((friend_public_key, conn_id)) => {
+                let is_current_conn = self
+                    .friends
+                    .get_friend_connected(&friend_public_key)
+                    .map(|friend_connected| friend_connected.conn_id == conn_id)
+                    .unwrap_or(false);
+                if is_current_conn {
+                    if let Some(message_id) = self.outgoing_queues.send_done(&friend_public_key) {
+                        self.report_delivery(
+                            &friend_public_key,
+                            vec![message_id],
+                            MessageDelivery::Sent,
+                        )
+                        .await?;
+                    }
+                    self.send_queued().await?;
+                }
+            }
             FriendEvent::ReceiverClosed(friend_public_key) => {
+                // Messages waiting for the friend are discarded. The Funder resends what is
+                // needed when the friend is online again:
+                let message_ids = self.outgoing_queues.remove_friend(&friend_public_key);
+                self.report_delivery(
+                    &friend_public_key,
+                    message_ids,
+                    MessageDelivery::FriendOffline,
+                )
+                .await?;
+                self.send_queued().await?;
+
                 // Report Funder that the friend is offline:
                 let to_funder = ChannelerToFunder::Offline(friend_public_key.clone());
                 self.to_funder
@@ -458,24 +686,203 @@ where
         }
         Ok(())
     }
+
+    /// Forward a keepalive report of a friend's connection to the Funder.
+    async fn handle_keepalive_report(
+        &mut self,
+        friend_public_key: PublicKey,
+        keepalive_report: KeepAliveReport,
+    ) -> Result<(), ChannelerError> {
+        if self
+            .friends
+            .get_friend_connected(&friend_public_key)
+            .is_none()
+        {
+            // This report might belong to a connection we have already discarded.
+            return Ok(());
+        }
+
+        if keepalive_report.missed_beats > 0 {
+            warn!(
+                "Friend {:?} missed {} keepalive beats (sent: {}, received: {})",
+                friend_public_key,
+                keepalive_report.missed_beats,
+                keepalive_report.sent_keepalives,
+                keepalive_report.received_keepalives
+            );
+        }
+
+        let to_funder = ChannelerToFunder::KeepAliveReport((friend_public_key, keepalive_report));
+        self.to_funder
+            .send(to_funder)
+            .await
+            .map_err(|_| ChannelerError::SendToFunderFailed)
+    }
+
+    /// Forward a secure channel report of a friend's connection to the Funder.
+    async fn handle_secure_channel_report(
+        &mut self,
+        friend_public_key: PublicKey,
+        secure_channel_report: SecureChannelReport,
+    ) -> Result<(), ChannelerError> {
+        // A decryption failure closes the connection, so we might have discarded the connection
+        // already. We only require that the friend still exists:
+        if !self.friends.contains(&friend_public_key) {
+            return Ok(());
+        }
+
+        match secure_channel_report {
+            SecureChannelReport::DecryptionFailure | SecureChannelReport::NonceReplay => warn!(
+                "Friend {:?} sent an invalid ciphertext: {:?}",
+                friend_public_key, secure_channel_report
+            ),
+            SecureChannelReport::Rekey | SecureChannelReport::Established(_) => {}
+        }
+
+        let to_funder =
+            ChannelerToFunder::SecureChannelReport((friend_public_key, secure_channel_report));
+        self.to_funder
+            .send(to_funder)
+            .await
+            .map_err(|_| ChannelerError::SendToFunderFailed)
+    }
+
+    /// Close the connections to friends that take too long to receive a message, and
+    /// periodically report the bandwidth used by every friend to the Funder.
+    async fn handle_timer_tick(&mut self) -> Result<(), ChannelerError> {
+        for (friend_public_key, bandwidth) in self.bandwidth_meter.tick() {
+            self.to_funder
+                .send(ChannelerToFunder::Bandwidth((friend_public_key, bandwidth)))
+                .await
+                .map_err(|_| ChannelerError::SendToFunderFailed)?;
+        }
+
+        for (friend_public_key, message_id) in self.outgoing_queues.tick() {
+            warn!(
+                "Sending a message to friend {:?} timed out. Closing connection",
+                friend_public_key
+            );
+            self.report_delivery(
+                &friend_public_key,
+                vec![message_id],
+                MessageDelivery::TimedOut,
+            )
+            .await?;
+            // The friend will be reported as offline once the connection is closed:
+            if let Some(friend_connected) = self.friends.get_friend_connected(&friend_public_key) {
+                friend_connected.close();
+            }
+        }
+        self.send_queued().await
+    }
+
+    /// All the relays we currently use: Our relays, and the relays of friends we connect to.
+    fn known_relays(&self) -> HashSet<RA> {
+        let mut known_relays = self.local_relays.iter().cloned().collect::<HashSet<_>>();
+        for out_friend in self.friends.out_friends.values() {
+            known_relays.extend(out_friend.relays.iter().cloned());
+        }
+        known_relays
+    }
+
+    /// Start measuring the latency of all known relays.
+    fn handle_probe_tick(&mut self) -> Result<(), ChannelerError> {
+        let known_relays = self.known_relays();
+        self.relay_latencies.retain(&known_relays);
+
+        for address in known_relays {
+            if let Some(canceler) = self.relay_probes.remove(&address) {
+                // The previous probe took too long. It will be reported as a failure,
+                // and we will probe this relay again on the next tick.
+                let _ = canceler.send(());
+                continue;
+            }
+
+            let (cancel_sender, cancel_receiver) = oneshot::channel();
+            let c_relay_connector = self.relay_connector.clone();
+            let c_address = address.clone();
+            let mut c_event_sender = self.event_sender.clone();
+            let probe_fut = async move {
+                let opt_latency_ms =
+                    probe_relay(c_relay_connector, c_address.clone(), cancel_receiver).await;
+                let event = ChannelerEvent::RelayProbeDone((c_address, opt_latency_ms));
+                let _ = c_event_sender.send(event).await;
+            };
+
+            self.spawner
+                .spawn(probe_fut)
+                .map_err(|_| ChannelerError::SpawnError)?;
+            self.relay_probes.insert(address, cancel_sender);
+        }
+        Ok(())
+    }
+
+    /// Record a latency measurement of a relay, report it to the Funder, and update the order in
+    /// which we attempt the relays of our friends.
+    async fn handle_relay_probe_done(
+        &mut self,
+        address: RA,
+        opt_sample_ms: Option<u64>,
+    ) -> Result<(), ChannelerError> {
+        let _ = self.relay_probes.remove(&address);
+        if !self.known_relays().contains(&address) {
+            // The relay was removed while we were probing it.
+            return Ok(());
+        }
+
+        let opt_latency_ms = self.relay_latencies.update(address.clone(), opt_sample_ms);
+        self.to_funder
+            .send(ChannelerToFunder::RelayLatency((address, opt_latency_ms)))
+            .await
+            .map_err(|_| ChannelerError::SendToFunderFailed)?;
+
+        for out_friend in self.friends.out_friends.values_mut() {
+            let sorted_relays = self.relay_latencies.sort_addresses(&out_friend.relays);
+            if sorted_relays == out_friend.sorted_relays {
+                continue;
+            }
+            out_friend.sorted_relays = sorted_relays.clone();
+            out_friend
+                .config_client
+                .config(sorted_relays)
+                .await
+                .map_err(|_| ChannelerError::ConnectorConfigError)?;
+        }
+        Ok(())
+    }
 }
 
-pub async fn channeler_loop<FF, TF, RA, C, L, S>(
+pub async fn channeler_loop<FF, TF, RA, C, RC, L, KR, SR, PT, TS, S>(
     local_public_key: PublicKey,
     from_funder: FF,
     to_funder: TF,
     connector: C,
+    relay_connector: RC,
     listener: L,
+    keepalive_reports: KR,
+    secure_channel_reports: SR,
+    probe_ticks: PT,
+    timer_stream: TS,
+    max_friend_queue_len: usize,
+    max_concurrent_sends: usize,
+    send_timeout_ticks: usize,
+    bandwidth_report_ticks: usize,
+    listen_only: bool,
     spawner: S,
 ) -> Result<(), ChannelerError>
 where
     FF: Stream<Item = FunderToChanneler<RA>> + Send + Unpin,
-    TF: Sink<ChannelerToFunder> + Send + Unpin,
-    RA: Clone + Send + Sync + Debug + 'static,
+    TF: Sink<ChannelerToFunder<RA>> + Send + Unpin,
+    RA: Hash + Eq + Clone + Send + Sync + Debug + 'static,
     C: FutTransform<Input = PublicKey, Output = ConnectPoolControl<RA>> + Clone + Send + 'static,
+    RC: FutTransform<Input = RA, Output = Option<ConnPairVec>> + Clone + Send + 'static,
     L: Listener<Connection = (PublicKey, ConnPairVec), Config = LpConfig<RA>, Arg = ()>
         + Clone
         + Send,
+    KR: Stream<Item = (PublicKey, KeepAliveReport)> + Send + Unpin,
+    SR: Stream<Item = (PublicKey, SecureChannelReport)> + Send + Unpin,
+    PT: Stream + Send + Unpin,
+    TS: Stream + Send + Unpin,
     S: Spawn + Clone + Send + 'static,
 {
     let (event_sender, event_receiver) = mpsc::channel(0);
@@ -485,12 +892,27 @@ where
     let mut channeler = Channeler::new(
         local_public_key,
         connector,
+        relay_connector,
         listen_config,
+        max_friend_queue_len,
+        max_concurrent_sends,
+        send_timeout_ticks,
+        bandwidth_report_ticks,
+        listen_only,
         spawner,
         to_funder,
         event_sender,
     );
 
+    if listen_only {
+        // Let the Funder know that friends can only reach us when they connect to us:
+        channeler
+            .to_funder
+            .send(ChannelerToFunder::ListenOnly)
+            .await
+            .map_err(|_| ChannelerError::SendToFunderFailed)?;
+    }
+
     // Forward incoming listen connections:
     let mut c_event_sender = channeler.event_sender.clone();
     let incoming_listen_conns = incoming_listen_conns.map(ChannelerEvent::Connection);
@@ -510,7 +932,22 @@ where
         .map(ChannelerEvent::FromFunder)
         .chain(stream::once(future::ready(ChannelerEvent::FunderClosed)));
 
-    let mut events = select_streams![event_receiver, from_funder];
+    let keepalive_reports = keepalive_reports.map(ChannelerEvent::KeepAliveReport);
+
+    let secure_channel_reports = secure_channel_reports.map(ChannelerEvent::SecureChannelReport);
+
+    let probe_ticks = probe_ticks.map(|_| ChannelerEvent::ProbeTick);
+
+    let timer_stream = timer_stream.map(|_| ChannelerEvent::TimerTick);
+
+    let mut events = select_streams![
+        event_receiver,
+        from_funder,
+        keepalive_reports,
+        secure_channel_reports,
+        probe_ticks,
+        timer_stream
+    ];
 
     while let Some(event) = events.next().await {
         match event {
@@ -523,6 +960,23 @@ where
             ChannelerEvent::FriendEvent(friend_event) => {
                 channeler.handle_friend_event(friend_event).await?
             }
+            ChannelerEvent::KeepAliveReport((public_key, keepalive_report)) => {
+                channeler
+                    .handle_keepalive_report(public_key, keepalive_report)
+                    .await?
+            }
+            ChannelerEvent::SecureChannelReport((public_key, secure_channel_report)) => {
+                channeler
+                    .handle_secure_channel_report(public_key, secure_channel_report)
+                    .await?
+            }
+            ChannelerEvent::ProbeTick => channeler.handle_probe_tick()?,
+            ChannelerEvent::RelayProbeDone((address, opt_latency_ms)) => {
+                channeler
+                    .handle_relay_probe_done(address, opt_latency_ms)
+                    .await?
+            }
+            ChannelerEvent::TimerTick => channeler.handle_timer_tick().await?,
             ChannelerEvent::ListenerClosed => return Err(ChannelerError::ListenerClosed),
             ChannelerEvent::FunderClosed => return Err(ChannelerError::FunderClosed),
         };
@@ -537,7 +991,12 @@ mod tests {
 
     use common::dummy_connector::DummyConnector;
     use common::dummy_listener::DummyListener;
-    use proto::crypto::PublicKey;
+    use proto::consts::{
+        BANDWIDTH_REPORT_TICKS, FRIEND_SEND_TIMEOUT_TICKS, MAX_CONCURRENT_FRIEND_SENDS,
+        MAX_FRIEND_QUEUE_LEN,
+    };
+    use proto::crypto::{PublicKey, Uid};
+    use proto::funder::messages::MessagePriority;
 
     /// Test the case of a friend the channeler initiates connection to.
     async fn task_channeler_loop_connect_friend<S>(spawner: S)
@@ -563,6 +1022,13 @@ mod tests {
         let (listener_req_sender, mut listener_req_receiver) = mpsc::channel(0);
         let listener = DummyListener::new(listener_req_sender, spawner.clone());
 
+        let (mut keepalive_report_sender, keepalive_reports) = mpsc::channel(0);
+        let (mut secure_channel_report_sender, secure_channel_reports) = mpsc::channel(0);
+
+        // Relays are not probed in this test:
+        let (relay_conn_request_sender, _relay_conn_request_receiver) = mpsc::channel(0);
+        let relay_connector = DummyConnector::new(relay_conn_request_sender);
+
         spawner
             .spawn(
                 channeler_loop(
@@ -570,7 +1036,17 @@ mod tests {
                     from_funder,
                     to_funder,
                     connector,
+                    relay_connector,
                     listener,
+                    keepalive_reports,
+                    secure_channel_reports,
+                    stream::pending::<()>(),
+                    stream::pending::<()>(),
+                    MAX_FRIEND_QUEUE_LEN,
+                    MAX_CONCURRENT_FRIEND_SENDS,
+                    FRIEND_SEND_TIMEOUT_TICKS,
+                    BANDWIDTH_REPORT_TICKS,
+                    false,
                     spawner.clone(),
                 )
                 .map_err(|e| error!("Error in channeler_loop(): {:?}", e))
@@ -658,11 +1134,27 @@ mod tests {
 
         // Send a message to pks[0]:
         funder_sender
-            .send(FunderToChanneler::Message((pks[0].clone(), vec![1, 2, 3])))
+            .send(FunderToChanneler::Message((
+                pks[0].clone(),
+                Uid::from(&[0; Uid::len()]),
+                vec![1, 2, 3],
+                MessagePriority::Normal,
+            )))
             .await
             .unwrap();
         assert_eq!(pk0_receiver.next().await.unwrap(), vec![1, 2, 3]);
 
+        // The message should be reported as sent:
+        let channeler_to_funder = funder_receiver.next().await.unwrap();
+        match channeler_to_funder {
+            ChannelerToFunder::MessageDelivery((public_key, message_id, delivery)) => {
+                assert_eq!(public_key, pks[0]);
+                assert_eq!(message_id, Uid::from(&[0; Uid::len()]));
+                assert_eq!(delivery, MessageDelivery::Sent);
+            }
+            _ => unreachable!(),
+        };
+
         // Send a message from pks[0]:
         pk0_sender.send(vec![3, 2, 1]).await.unwrap();
 
@@ -676,6 +1168,41 @@ mod tests {
             _ => unreachable!(),
         };
 
+        // A keepalive report for pks[0] should be forwarded to the funder:
+        let keepalive_report = KeepAliveReport {
+            missed_beats: 1,
+            sent_keepalives: 2,
+            received_keepalives: 3,
+        };
+        keepalive_report_sender
+            .send((pks[0].clone(), keepalive_report.clone()))
+            .await
+            .unwrap();
+
+        let channeler_to_funder = funder_receiver.next().await.unwrap();
+        match channeler_to_funder {
+            ChannelerToFunder::KeepAliveReport((public_key, report)) => {
+                assert_eq!(public_key, pks[0]);
+                assert_eq!(report, keepalive_report);
+            }
+            _ => unreachable!(),
+        };
+
+        // A secure channel report for pks[0] should be forwarded to the funder:
+        secure_channel_report_sender
+            .send((pks[0].clone(), SecureChannelReport::NonceReplay))
+            .await
+            .unwrap();
+
+        let channeler_to_funder = funder_receiver.next().await.unwrap();
+        match channeler_to_funder {
+            ChannelerToFunder::SecureChannelReport((public_key, report)) => {
+                assert_eq!(public_key, pks[0]);
+                assert_eq!(report, SecureChannelReport::NonceReplay);
+            }
+            _ => unreachable!(),
+        };
+
         // Drop pks[0] connection:
         drop(pk0_sender);
         drop(pk0_receiver);
@@ -769,6 +1296,10 @@ mod tests {
         let (listener_req_sender, mut listener_req_receiver) = mpsc::channel(0);
         let listener = DummyListener::new(listener_req_sender, spawner.clone());
 
+        // Relays are not probed in this test:
+        let (relay_conn_request_sender, _relay_conn_request_receiver) = mpsc::channel(0);
+        let relay_connector = DummyConnector::new(relay_conn_request_sender);
+
         spawner
             .spawn(
                 channeler_loop(
@@ -776,7 +1307,17 @@ mod tests {
                     from_funder,
                     to_funder,
                     connector,
+                    relay_connector,
                     listener,
+                    stream::pending(),
+                    stream::pending(),
+                    stream::pending::<()>(),
+                    stream::pending::<()>(),
+                    MAX_FRIEND_QUEUE_LEN,
+                    MAX_CONCURRENT_FRIEND_SENDS,
+                    FRIEND_SEND_TIMEOUT_TICKS,
+                    BANDWIDTH_REPORT_TICKS,
+                    false,
                     spawner.clone(),
                 )
                 .map_err(|e| error!("Error in channeler_loop(): {:?}", e))
@@ -832,11 +1373,27 @@ mod tests {
 
             // Send a message to pks[2]:
             funder_sender
-                .send(FunderToChanneler::Message((pks[2].clone(), vec![1, 2, 3])))
+                .send(FunderToChanneler::Message((
+                    pks[2].clone(),
+                    Uid::from(&[2; Uid::len()]),
+                    vec![1, 2, 3],
+                    MessagePriority::Normal,
+                )))
                 .await
                 .unwrap();
             assert_eq!(pk2_receiver.next().await.unwrap(), vec![1, 2, 3]);
 
+            // The message should be reported as sent:
+            let channeler_to_funder = funder_receiver.next().await.unwrap();
+            match channeler_to_funder {
+                ChannelerToFunder::MessageDelivery((public_key, message_id, delivery)) => {
+                    assert_eq!(public_key, pks[2]);
+                    assert_eq!(message_id, Uid::from(&[2; Uid::len()]));
+                    assert_eq!(delivery, MessageDelivery::Sent);
+                }
+                _ => unreachable!(),
+            };
+
             // Send a message from pks2:
             pk2_sender.send(vec![3, 2, 1]).await.unwrap();
 
@@ -875,6 +1432,107 @@ mod tests {
         block_on(task_channeler_loop_listen_friend(thread_pool.clone()));
     }
 
+    /// Test a listen only channeler: A friend we would usually connect to is expected to connect
+    /// to us.
+    async fn task_channeler_loop_listen_only<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (mut funder_sender, from_funder) = mpsc::channel(0);
+        let (to_funder, mut funder_receiver) = mpsc::channel(0);
+
+        // Our local public key will be pks[1]. pks[0] < pks[1], so usually we would initiate the
+        // connection to pks[0]:
+        let mut pks = (0..2)
+            .map(|i| PublicKey::from(&[i; PublicKey::len()]))
+            .collect::<Vec<PublicKey>>();
+        pks.sort_by(compare_public_key);
+
+        let (conn_request_sender, _conn_request_receiver) = mpsc::channel(0);
+        let connector = DummyConnector::new(conn_request_sender);
+
+        let (listener_req_sender, mut listener_req_receiver) = mpsc::channel(0);
+        let listener = DummyListener::new(listener_req_sender, spawner.clone());
+
+        // Relays are not probed in this test:
+        let (relay_conn_request_sender, _relay_conn_request_receiver) = mpsc::channel(0);
+        let relay_connector = DummyConnector::new(relay_conn_request_sender);
+
+        spawner
+            .spawn(
+                channeler_loop(
+                    pks[1].clone(),
+                    from_funder,
+                    to_funder,
+                    connector,
+                    relay_connector,
+                    listener,
+                    stream::pending(),
+                    stream::pending(),
+                    stream::pending::<()>(),
+                    stream::pending::<()>(),
+                    MAX_FRIEND_QUEUE_LEN,
+                    MAX_CONCURRENT_FRIEND_SENDS,
+                    FRIEND_SEND_TIMEOUT_TICKS,
+                    BANDWIDTH_REPORT_TICKS,
+                    true,
+                    spawner.clone(),
+                )
+                .map_err(|e| error!("Error in channeler_loop(): {:?}", e))
+                .map(|_| ()),
+            )
+            .unwrap();
+
+        // The funder is told that we only listen:
+        match funder_receiver.next().await.unwrap() {
+            ChannelerToFunder::ListenOnly => {}
+            _ => unreachable!(),
+        };
+
+        funder_sender
+            .send(FunderToChanneler::SetRelays(vec![0x1u32]))
+            .await
+            .unwrap();
+        let mut listener_request = listener_req_receiver.next().await.unwrap();
+        let lp_config = listener_request.config_receiver.next().await.unwrap();
+        assert_eq!(lp_config, LpConfig::SetLocalAddresses(vec![0x1u32]));
+
+        // Add a friend. We wait for the friend to connect, instead of connecting to its relays:
+        let channeler_update_friend = ChannelerUpdateFriend {
+            friend_public_key: pks[0].clone(),
+            friend_relays: vec![0x0u32],
+            local_relays: vec![0x1u32],
+        };
+        funder_sender
+            .send(FunderToChanneler::UpdateFriend(channeler_update_friend))
+            .await
+            .unwrap();
+        let lp_config = listener_request.config_receiver.next().await.unwrap();
+        assert_eq!(
+            lp_config,
+            LpConfig::UpdateFriend((pks[0].clone(), vec![0x1u32]))
+        );
+
+        let (_pk0_sender, receiver) = mpsc::channel(0);
+        let (sender, _pk0_receiver) = mpsc::channel(0);
+        listener_request
+            .conn_sender
+            .send((pks[0].clone(), ConnPairVec::from_raw(sender, receiver)))
+            .await
+            .unwrap();
+
+        match funder_receiver.next().await.unwrap() {
+            ChannelerToFunder::Online(public_key) => assert_eq!(public_key, pks[0]),
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn test_channeler_loop_listen_only() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_channeler_loop_listen_only(thread_pool.clone()));
+    }
+
     // ------------------------------------------------------------
     // ------------------------------------------------------------
 
@@ -904,6 +1562,10 @@ mod tests {
         let (listener_req_sender, mut listener_req_receiver) = mpsc::channel(0);
         let listener = DummyListener::new(listener_req_sender, spawner.clone());
 
+        // Relays are not probed in this test:
+        let (relay_conn_request_sender, _relay_conn_request_receiver) = mpsc::channel(0);
+        let relay_connector = DummyConnector::new(relay_conn_request_sender);
+
         spawner
             .spawn(
                 channeler_loop(
@@ -911,7 +1573,17 @@ mod tests {
                     from_funder,
                     to_funder,
                     connector,
+                    relay_connector,
                     listener,
+                    stream::pending(),
+                    stream::pending(),
+                    stream::pending::<()>(),
+                    stream::pending::<()>(),
+                    MAX_FRIEND_QUEUE_LEN,
+                    MAX_CONCURRENT_FRIEND_SENDS,
+                    FRIEND_SEND_TIMEOUT_TICKS,
+                    BANDWIDTH_REPORT_TICKS,
+                    false,
                     spawner.clone(),
                 )
                 .map_err(|e| error!("Error in channeler_loop(): {:?}", e))
@@ -979,6 +1651,27 @@ mod tests {
                 _ => unreachable!(),
             };
         }
+
+        // A message to a removed friend can not be delivered:
+        funder_sender
+            .send(FunderToChanneler::Message((
+                pks[2].clone(),
+                Uid::from(&[5; Uid::len()]),
+                vec![1, 2, 3],
+                MessagePriority::Normal,
+            )))
+            .await
+            .unwrap();
+
+        let channeler_to_funder = funder_receiver.next().await.unwrap();
+        match channeler_to_funder {
+            ChannelerToFunder::MessageDelivery((public_key, message_id, delivery)) => {
+                assert_eq!(public_key, pks[2]);
+                assert_eq!(message_id, Uid::from(&[5; Uid::len()]));
+                assert_eq!(delivery, MessageDelivery::FriendOffline);
+            }
+            _ => unreachable!(),
+        };
     }
 
     #[test]
@@ -1014,6 +1707,10 @@ mod tests {
         let (listener_req_sender, mut listener_req_receiver) = mpsc::channel(1);
         let listener = DummyListener::new(listener_req_sender, spawner.clone());
 
+        // Relays are not probed in this test:
+        let (relay_conn_request_sender, _relay_conn_request_receiver) = mpsc::channel(0);
+        let relay_connector = DummyConnector::new(relay_conn_request_sender);
+
         spawner
             .spawn(
                 channeler_loop(
@@ -1021,7 +1718,17 @@ mod tests {
                     from_funder,
                     to_funder,
                     connector,
+                    relay_connector,
                     listener,
+                    stream::pending(),
+                    stream::pending(),
+                    stream::pending::<()>(),
+                    stream::pending::<()>(),
+                    MAX_FRIEND_QUEUE_LEN,
+                    MAX_CONCURRENT_FRIEND_SENDS,
+                    FRIEND_SEND_TIMEOUT_TICKS,
+                    BANDWIDTH_REPORT_TICKS,
+                    false,
                     spawner.clone(),
                 )
                 .map_err(|e| error!("Error in channeler_loop(): {:?}", e))
@@ -1099,6 +1806,147 @@ mod tests {
         ));
     }
 
+    /// Test measuring the latency of relays, and attempting faster relays first.
+    async fn task_channeler_loop_probe_relays<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (mut funder_sender, from_funder) = mpsc::channel(0);
+        let (to_funder, mut funder_receiver) = mpsc::channel(0);
+
+        // Our local public key will be pks[1]. pks[0] < pks[1], hence we initiate the connection
+        // to pks[0].
+        let mut pks = (0..2)
+            .map(|i| PublicKey::from(&[i; PublicKey::len()]))
+            .collect::<Vec<PublicKey>>();
+        pks.sort_by(compare_public_key);
+
+        let (conn_request_sender, mut conn_request_receiver) = mpsc::channel(0);
+        let connector = DummyConnector::new(conn_request_sender);
+
+        let (listener_req_sender, mut listener_req_receiver) = mpsc::channel(0);
+        let listener = DummyListener::new(listener_req_sender, spawner.clone());
+
+        let (relay_conn_request_sender, mut relay_conn_request_receiver) = mpsc::channel(0);
+        let relay_connector = DummyConnector::new(relay_conn_request_sender);
+
+        let (mut probe_tick_sender, probe_ticks) = mpsc::channel::<()>(0);
+
+        spawner
+            .spawn(
+                channeler_loop(
+                    pks[1].clone(),
+                    from_funder,
+                    to_funder,
+                    connector,
+                    relay_connector,
+                    listener,
+                    stream::pending(),
+                    stream::pending(),
+                    probe_ticks,
+                    stream::pending::<()>(),
+                    MAX_FRIEND_QUEUE_LEN,
+                    MAX_CONCURRENT_FRIEND_SENDS,
+                    FRIEND_SEND_TIMEOUT_TICKS,
+                    BANDWIDTH_REPORT_TICKS,
+                    false,
+                    spawner.clone(),
+                )
+                .map_err(|e| error!("Error in channeler_loop(): {:?}", e))
+                .map(|_| ()),
+            )
+            .unwrap();
+
+        let mut listener_request = listener_req_receiver.next().await.unwrap();
+
+        funder_sender
+            .send(FunderToChanneler::SetRelays(vec![0x1u32]))
+            .await
+            .unwrap();
+        let _lp_config = listener_request.config_receiver.next().await.unwrap();
+
+        // Add a friend with two relays:
+        let channeler_update_friend = ChannelerUpdateFriend {
+            friend_public_key: pks[0].clone(),
+            friend_relays: vec![0x2u32, 0x3u32],
+            local_relays: vec![0x1u32],
+        };
+        funder_sender
+            .send(FunderToChanneler::UpdateFriend(channeler_update_friend))
+            .await
+            .unwrap();
+
+        let conn_request = conn_request_receiver.next().await.unwrap();
+        assert_eq!(conn_request.address, pks[0]);
+        let (connect_sender0, _connect_receiver0) = mpsc::channel(0);
+        let (config_sender0, mut config_receiver0) = mpsc::channel(0);
+        conn_request.reply((
+            CpConfigClient::new(config_sender0),
+            CpConnectClient::new(connect_sender0),
+        ));
+
+        // No relay was measured yet, so the original order is kept:
+        assert_eq!(config_receiver0.next().await.unwrap(), vec![0x2u32, 0x3u32]);
+
+        // All of our relays and the friend's relays are probed:
+        probe_tick_sender.send(()).await.unwrap();
+        let mut relay_conn_requests = HashMap::new();
+        for _ in 0..3 {
+            let relay_conn_request = relay_conn_request_receiver.next().await.unwrap();
+            relay_conn_requests.insert(relay_conn_request.address, relay_conn_request);
+        }
+        let mut addresses = relay_conn_requests.keys().cloned().collect::<Vec<_>>();
+        addresses.sort();
+        assert_eq!(addresses, vec![0x1u32, 0x2u32, 0x3u32]);
+
+        // Relay 0x3 is reachable, and echoes back our pings:
+        let (local_sender, remote_receiver) = mpsc::channel(0);
+        let (remote_sender, local_receiver) = mpsc::channel(0);
+        relay_conn_requests
+            .remove(&0x3u32)
+            .unwrap()
+            .reply(Some(ConnPairVec::from_raw(local_sender, local_receiver)));
+        // Skip InitConnection::Ping, and echo back everything else:
+        spawner
+            .spawn(
+                remote_receiver
+                    .skip(1)
+                    .map(Ok)
+                    .forward(remote_sender)
+                    .map(|_| ()),
+            )
+            .unwrap();
+
+        match funder_receiver.next().await.unwrap() {
+            ChannelerToFunder::RelayLatency((address, opt_latency_ms)) => {
+                assert_eq!(address, 0x3u32);
+                assert!(opt_latency_ms.is_some());
+            }
+            _ => unreachable!(),
+        };
+
+        // The friend's relays are now attempted in a different order:
+        assert_eq!(config_receiver0.next().await.unwrap(), vec![0x3u32, 0x2u32]);
+
+        // Relays 0x1 and 0x2 are not reachable:
+        for &address in &[0x2u32, 0x1u32] {
+            relay_conn_requests.remove(&address).unwrap().reply(None);
+            match funder_receiver.next().await.unwrap() {
+                ChannelerToFunder::RelayLatency((reported_address, opt_latency_ms)) => {
+                    assert_eq!(reported_address, address);
+                    assert!(opt_latency_ms.is_none());
+                }
+                _ => unreachable!(),
+            };
+        }
+    }
+
+    #[test]
+    fn test_channeler_loop_probe_relays() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_channeler_loop_probe_relays(thread_pool.clone()));
+    }
+
     // TODO: Add tests to make sure access control works properly?
     // If a friend with a strange public key tries to connect, he should not be able to succeed?
 }
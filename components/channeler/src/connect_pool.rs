@@ -254,18 +254,33 @@ where
         Ok(())
     }
 
+    /// Set the addresses of the friend's relays.
+    /// The addresses are attempted in the order given in the configuration.
     pub fn handle_config_request(&mut self, config: Vec<RA>) -> Result<(), ConnectPoolError> {
         let old_addresses = self.addresses.iter().cloned().collect::<HashSet<_>>();
 
-        let new_addresses: HashSet<RA> = config.into_iter().collect::<HashSet<_>>();
+        let new_addresses: HashSet<RA> = config.iter().cloned().collect::<HashSet<_>>();
 
         for removed_address in old_addresses.difference(&new_addresses) {
             self.remove_address(removed_address.clone())?;
         }
 
-        for added_address in new_addresses.difference(&old_addresses) {
-            self.add_address(added_address.clone())?;
+        // Add new addresses in the configured order, so that the first of them is attempted
+        // first if we are waiting for an address:
+        let mut added_addresses = HashSet::new();
+        for address in &config {
+            if !old_addresses.contains(address) && added_addresses.insert(address.clone()) {
+                self.add_address(address.clone())?;
+            }
         }
+
+        // Reorder the remaining addresses according to the configuration.
+        // (An address we are currently connecting through is not in the queue)
+        let mut queued_addresses = self.addresses.drain(..).collect::<HashSet<_>>();
+        self.addresses = config
+            .into_iter()
+            .filter(|address| queued_addresses.remove(address))
+            .collect();
         Ok(())
     }
 
@@ -654,6 +669,81 @@ mod tests {
         block_on(task_pool_connector_cyclic_connect(thread_pool.clone()));
     }
 
+    async fn task_connect_pool_config_order<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        // Create a mock time service:
+        let (mut tick_sender_receiver, mut timer_client) =
+            dummy_timer_multi_sender(spawner.clone());
+
+        let (conn_request_sender, mut conn_request_receiver) = mpsc::channel(0);
+        let client_connector = DummyConnector::new(conn_request_sender);
+
+        // We don't need encryption for this test:
+        let encrypt_transform = FuncFutTransform::new(|(_public_key, conn_pair)| {
+            Box::pin(future::ready(Some(conn_pair)))
+        });
+
+        let timer_stream = timer_client.request_timer_stream().await.unwrap();
+        let _tick_sender = tick_sender_receiver.next().await.unwrap();
+
+        // Used for debugging the loop:
+        let (event_sender, mut event_receiver) = mpsc::channel(0);
+
+        let (request_sender, incoming_requests) = mpsc::channel(0);
+        let (config_sender, incoming_config) = mpsc::channel(0);
+
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+
+        let loop_fut = connect_pool_loop(
+            incoming_requests,
+            incoming_config,
+            timer_stream,
+            encrypt_transform,
+            pk_b.clone(), // friend_public_key
+            2,            // backoff_ticks
+            client_connector,
+            spawner.clone(),
+            Some(event_sender),
+        )
+        .map_err(|e| error!("connect_pool_loop() error: {:?}", e))
+        .map(|_| ());
+
+        spawner.spawn(loop_fut).unwrap();
+
+        let mut connect_client = CpConnectClient::new(request_sender);
+        let mut config_client = CpConfigClient::new(config_sender);
+
+        for addresses in &[vec![0x2u32, 0x0u32, 0x1u32], vec![0x1u32, 0x0u32, 0x2u32]] {
+            config_client.config(addresses.clone()).await.unwrap();
+            event_receiver.next().await.unwrap();
+
+            // The first configured address is attempted first:
+            let connect_fut = connect_client.connect();
+            let handle_connect_fut = async {
+                event_receiver.next().await.unwrap(); // Connection request event
+                let conn_request = conn_request_receiver.next().await.unwrap();
+                let (local_sender, _remote_receiver) = mpsc::channel(0);
+                let (_remote_sender, local_receiver) = mpsc::channel(0);
+
+                let (address, pk) = &conn_request.address;
+                assert_eq!(address, &addresses[0]);
+                assert_eq!(pk, &pk_b);
+
+                conn_request.reply(Some(ConnPairVec::from_raw(local_sender, local_receiver)));
+                event_receiver.next().await.unwrap(); // connection attempt done event
+            };
+            let (_local_conn, ()) = join(connect_fut, handle_connect_fut).await;
+        }
+    }
+
+    #[test]
+    fn test_connect_pool_config_order() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_connect_pool_config_order(thread_pool.clone()));
+    }
+
     async fn task_pool_connector_backoff_ticks<S>(spawner: S)
     where
         S: Spawn + Clone + Send + 'static,
@@ -14,12 +14,14 @@ extern crate log;
 #[macro_use]
 extern crate common;
 
+mod bandwidth;
 mod channeler;
 mod connect_pool;
 // mod connector_utils;
 mod listen_pool;
 mod listen_pool_state;
-mod overwrite_channel;
+mod outgoing_queues;
+mod relay_prober;
 mod spawn;
 mod types;
 
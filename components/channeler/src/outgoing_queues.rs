@@ -0,0 +1,288 @@
+use std::collections::{HashMap, VecDeque};
+
+use proto::crypto::{PublicKey, Uid};
+use proto::funder::messages::MessagePriority;
+
+/// A message that is currently being sent to a friend
+struct SendingMessage {
+    message_id: Uid,
+    ticks_left: usize,
+}
+
+struct FriendQueue {
+    messages: VecDeque<(Uid, Vec<u8>)>,
+    /// The priority of the most recent message queued for the friend
+    priority: MessagePriority,
+    /// The message currently being sent to the friend, if any
+    opt_sending: Option<SendingMessage>,
+    /// The turn in which the friend was last served. Used to serve friends with the same
+    /// priority in turns.
+    last_served: u64,
+}
+
+impl FriendQueue {
+    fn new() -> Self {
+        FriendQueue {
+            messages: VecDeque::new(),
+            priority: MessagePriority::Normal,
+            opt_sending: None,
+            last_served: 0,
+        }
+    }
+}
+
+/// Messages waiting to be sent to friends.
+/// Every friend has a queue of its own, and only one message is sent to a friend at a time.
+/// When more friends are waiting than we can serve, friends with higher priority messages are
+/// served first.
+pub struct OutgoingQueues {
+    queues: HashMap<PublicKey, FriendQueue>,
+    max_queue_len: usize,
+    /// Amount of ticks a message may take to be sent
+    send_timeout_ticks: usize,
+    /// Amount of friends that are currently being sent a message
+    num_sending: usize,
+    turn: u64,
+}
+
+impl OutgoingQueues {
+    pub fn new(max_queue_len: usize, send_timeout_ticks: usize) -> Self {
+        assert!(max_queue_len > 0);
+
+        OutgoingQueues {
+            queues: HashMap::new(),
+            max_queue_len,
+            send_timeout_ticks,
+            num_sending: 0,
+            turn: 0,
+        }
+    }
+
+    /// Queue a message to a friend.
+    /// Returns the id of the oldest message of the friend, if it was discarded to make room.
+    pub fn push(
+        &mut self,
+        friend_public_key: &PublicKey,
+        message_id: Uid,
+        message: Vec<u8>,
+        priority: MessagePriority,
+    ) -> Option<Uid> {
+        let friend_queue = self
+            .queues
+            .entry(friend_public_key.clone())
+            .or_insert_with(FriendQueue::new);
+
+        friend_queue.priority = priority;
+        friend_queue.messages.push_back((message_id, message));
+        if friend_queue.messages.len() > self.max_queue_len {
+            friend_queue
+                .messages
+                .pop_front()
+                .map(|(message_id, _message)| message_id)
+        } else {
+            None
+        }
+    }
+
+    /// Take the next message to be sent, if any.
+    /// The friend is considered to be sending until `send_done()` is called, or until the send
+    /// times out.
+    pub fn pop_next(&mut self) -> Option<(PublicKey, Uid, Vec<u8>)> {
+        let (friend_public_key, friend_queue) = self
+            .queues
+            .iter_mut()
+            .filter(|(_, friend_queue)| {
+                friend_queue.opt_sending.is_none() && !friend_queue.messages.is_empty()
+            })
+            // Highest priority first. Between friends with the same priority, the friend that
+            // waited the longest goes first:
+            .max_by(|(_, queue_a), (_, queue_b)| {
+                queue_a
+                    .priority
+                    .cmp(&queue_b.priority)
+                    .then_with(|| queue_b.last_served.cmp(&queue_a.last_served))
+            })?;
+
+        let (message_id, message) = friend_queue.messages.pop_front()?;
+        self.turn = self.turn.wrapping_add(1);
+        friend_queue.last_served = self.turn;
+        friend_queue.opt_sending = Some(SendingMessage {
+            message_id: message_id.clone(),
+            ticks_left: self.send_timeout_ticks,
+        });
+        self.num_sending += 1;
+        Some((friend_public_key.clone(), message_id, message))
+    }
+
+    /// A message was sent to a friend.
+    /// Returns the id of the sent message.
+    pub fn send_done(&mut self, friend_public_key: &PublicKey) -> Option<Uid> {
+        let friend_queue = self.queues.get_mut(friend_public_key)?;
+        let sending_message = friend_queue.opt_sending.take()?;
+        self.num_sending -= 1;
+        Some(sending_message.message_id)
+    }
+
+    /// Discard all the messages of a friend.
+    /// Returns the ids of the discarded messages, including the message being sent.
+    pub fn remove_friend(&mut self, friend_public_key: &PublicKey) -> Vec<Uid> {
+        let friend_queue = match self.queues.remove(friend_public_key) {
+            Some(friend_queue) => friend_queue,
+            None => return Vec::new(),
+        };
+
+        let mut message_ids = Vec::new();
+        if let Some(sending_message) = friend_queue.opt_sending {
+            self.num_sending -= 1;
+            message_ids.push(sending_message.message_id);
+        }
+        message_ids.extend(
+            friend_queue
+                .messages
+                .into_iter()
+                .map(|(message_id, _message)| message_id),
+        );
+        message_ids
+    }
+
+    /// Advance the time by one tick.
+    /// Returns the messages that took too long to be sent. The friends of those messages are not
+    /// considered to be sending anymore.
+    pub fn tick(&mut self) -> Vec<(PublicKey, Uid)> {
+        let mut timed_out = Vec::new();
+        for (friend_public_key, friend_queue) in &mut self.queues {
+            let is_timed_out = match &mut friend_queue.opt_sending {
+                Some(sending_message) => {
+                    sending_message.ticks_left = sending_message.ticks_left.saturating_sub(1);
+                    sending_message.ticks_left == 0
+                }
+                None => false,
+            };
+            if is_timed_out {
+                let sending_message = friend_queue.opt_sending.take().unwrap();
+                self.num_sending -= 1;
+                timed_out.push((friend_public_key.clone(), sending_message.message_id));
+            }
+        }
+        timed_out
+    }
+
+    /// Amount of messages waiting to be sent to a friend
+    pub fn depth(&self, friend_public_key: &PublicKey) -> usize {
+        self.queues
+            .get(friend_public_key)
+            .map(|friend_queue| friend_queue.messages.len())
+            .unwrap_or(0)
+    }
+
+    /// Amount of friends that are currently being sent a message
+    pub fn num_sending(&self) -> usize {
+        self.num_sending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uid(i: u8) -> Uid {
+        Uid::from(&[i; Uid::len()])
+    }
+
+    #[test]
+    fn test_outgoing_queues_basic() {
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let mut outgoing_queues = OutgoingQueues::new(2, 4);
+        assert!(outgoing_queues.pop_next().is_none());
+
+        assert_eq!(
+            outgoing_queues.push(&pk_a, uid(1), vec![1], MessagePriority::Normal),
+            None
+        );
+        assert_eq!(
+            outgoing_queues.push(&pk_a, uid(2), vec![2], MessagePriority::Normal),
+            None
+        );
+        // The queue is full. The oldest message is discarded:
+        assert_eq!(
+            outgoing_queues.push(&pk_a, uid(3), vec![3], MessagePriority::Normal),
+            Some(uid(1))
+        );
+        assert_eq!(outgoing_queues.depth(&pk_a), 2);
+
+        assert_eq!(
+            outgoing_queues.pop_next(),
+            Some((pk_a.clone(), uid(2), vec![2]))
+        );
+        assert_eq!(outgoing_queues.num_sending(), 1);
+        // Only one message is sent to a friend at a time:
+        assert!(outgoing_queues.pop_next().is_none());
+
+        assert_eq!(outgoing_queues.send_done(&pk_a), Some(uid(2)));
+        assert_eq!(outgoing_queues.num_sending(), 0);
+        assert_eq!(
+            outgoing_queues.pop_next(),
+            Some((pk_a.clone(), uid(3), vec![3]))
+        );
+        assert_eq!(outgoing_queues.depth(&pk_a), 0);
+
+        assert_eq!(outgoing_queues.remove_friend(&pk_a), vec![uid(3)]);
+        assert_eq!(outgoing_queues.num_sending(), 0);
+        assert!(outgoing_queues.pop_next().is_none());
+        assert!(outgoing_queues.remove_friend(&pk_a).is_empty());
+    }
+
+    #[test]
+    fn test_outgoing_queues_priority() {
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let pk_c = PublicKey::from(&[0xcc; PublicKey::len()]);
+        let mut outgoing_queues = OutgoingQueues::new(8, 4);
+
+        outgoing_queues.push(&pk_a, uid(1), vec![0xa1], MessagePriority::Normal);
+        outgoing_queues.push(&pk_a, uid(2), vec![0xa2], MessagePriority::Normal);
+        outgoing_queues.push(&pk_b, uid(3), vec![0xb1], MessagePriority::Normal);
+        outgoing_queues.push(&pk_c, uid(4), vec![0xc1], MessagePriority::TokenWanted);
+
+        // A friend that wants the token is served first:
+        assert_eq!(
+            outgoing_queues.pop_next(),
+            Some((pk_c.clone(), uid(4), vec![0xc1]))
+        );
+        outgoing_queues.send_done(&pk_c);
+
+        // Friends with the same priority are served in turns:
+        let (first_pk, _, _) = outgoing_queues.pop_next().unwrap();
+        outgoing_queues.send_done(&first_pk);
+        let (second_pk, _, _) = outgoing_queues.pop_next().unwrap();
+        outgoing_queues.send_done(&second_pk);
+        assert_ne!(first_pk, second_pk);
+
+        assert_eq!(
+            outgoing_queues.pop_next(),
+            Some((pk_a.clone(), uid(2), vec![0xa2]))
+        );
+        assert!(outgoing_queues.pop_next().is_none());
+    }
+
+    #[test]
+    fn test_outgoing_queues_timeout() {
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let mut outgoing_queues = OutgoingQueues::new(8, 2);
+
+        outgoing_queues.push(&pk_a, uid(1), vec![1], MessagePriority::Normal);
+        outgoing_queues.push(&pk_a, uid(2), vec![2], MessagePriority::Normal);
+        assert_eq!(
+            outgoing_queues.pop_next(),
+            Some((pk_a.clone(), uid(1), vec![1]))
+        );
+
+        assert!(outgoing_queues.tick().is_empty());
+        assert_eq!(outgoing_queues.tick(), vec![(pk_a.clone(), uid(1))]);
+        assert_eq!(outgoing_queues.num_sending(), 0);
+        // The message that timed out will not be reported as sent:
+        assert_eq!(outgoing_queues.send_done(&pk_a), None);
+
+        assert_eq!(outgoing_queues.remove_friend(&pk_a), vec![uid(2)]);
+    }
+}
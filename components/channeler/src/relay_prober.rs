@@ -0,0 +1,128 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use futures::channel::oneshot;
+use futures::{select, FutureExt};
+
+use common::conn::{ConnPairVec, FutTransform};
+
+use proto::consts::RELAY_PROBE_PINGS;
+
+use relay::ping_relay;
+
+/// Latest measurements of connection latency to relays.
+///
+/// A new measurement is averaged with the previous one, so that a single slow connection attempt
+/// does not reorder the relays. A failed measurement marks the relay as unreachable immediately.
+pub struct RelayLatencies<RA> {
+    /// Latency in milliseconds. None if the last probe of the relay failed.
+    latencies: HashMap<RA, Option<u64>>,
+}
+
+impl<RA> RelayLatencies<RA>
+where
+    RA: Hash + Eq + Clone,
+{
+    pub fn new() -> Self {
+        RelayLatencies {
+            latencies: HashMap::new(),
+        }
+    }
+
+    /// Record a new measurement of a relay.
+    /// Returns the updated latency of the relay.
+    pub fn update(&mut self, address: RA, opt_sample_ms: Option<u64>) -> Option<u64> {
+        let opt_latency_ms = match (self.latencies.get(&address), opt_sample_ms) {
+            (Some(Some(prev_ms)), Some(sample_ms)) => {
+                Some(prev_ms.saturating_mul(3).saturating_add(sample_ms) / 4)
+            }
+            (_, opt_sample_ms) => opt_sample_ms,
+        };
+        self.latencies.insert(address, opt_latency_ms);
+        opt_latency_ms
+    }
+
+    /// Forget measurements of relays we don't use anymore.
+    pub fn retain(&mut self, addresses: &HashSet<RA>) {
+        self.latencies
+            .retain(|address, _| addresses.contains(address));
+    }
+
+    /// Sort relay addresses by preference:
+    /// Measured relays by increasing latency, then relays we have not measured yet, then relays
+    /// we failed to reach. Relays of the same rank keep their original order.
+    pub fn sort_addresses(&self, addresses: &[RA]) -> Vec<RA> {
+        let mut sorted = addresses.to_vec();
+        sorted.sort_by_key(|address| match self.latencies.get(address) {
+            Some(Some(latency_ms)) => (0, *latency_ms),
+            None => (1, 0),
+            Some(None) => (2, 0),
+        });
+        sorted
+    }
+}
+
+/// Measure the round trip time through a relay, using ping frames of the relay protocol.
+/// A relay that accepts connections but does not answer pings is considered unreachable.
+/// Returns None if we could not ping the relay, or if the probe was canceled.
+pub async fn probe_relay<RA, C>(
+    mut connector: C,
+    address: RA,
+    canceler: oneshot::Receiver<()>,
+) -> Option<u64>
+where
+    C: FutTransform<Input = RA, Output = Option<ConnPairVec>>,
+{
+    // TODO: How to remove this Box::pin?
+    let probe_fut = Box::pin(async move {
+        ping_relay(&mut connector, address, RELAY_PROBE_PINGS)
+            .await
+            .map_err(|e| warn!("probe_relay(): ping_relay() error: {:?}", e))
+            .ok()
+    });
+
+    select! {
+        probe_fut = probe_fut.fuse() => probe_fut,
+        _ = canceler.fuse() => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relay_latencies_sort() {
+        let mut relay_latencies = RelayLatencies::<u32>::new();
+        let addresses = vec![0u32, 1, 2, 3, 4];
+
+        // Nothing was measured, the original order is kept:
+        assert_eq!(relay_latencies.sort_addresses(&addresses), addresses);
+
+        relay_latencies.update(1, None);
+        relay_latencies.update(2, Some(200));
+        relay_latencies.update(4, Some(50));
+        assert_eq!(
+            relay_latencies.sort_addresses(&addresses),
+            vec![4u32, 2, 0, 3, 1]
+        );
+    }
+
+    #[test]
+    fn test_relay_latencies_update() {
+        let mut relay_latencies = RelayLatencies::<u32>::new();
+
+        assert_eq!(relay_latencies.update(0, Some(100)), Some(100));
+        // Measurements are averaged:
+        assert_eq!(relay_latencies.update(0, Some(200)), Some(125));
+        // A failure is applied immediately:
+        assert_eq!(relay_latencies.update(0, None), None);
+        // The first measurement after a failure is not averaged:
+        assert_eq!(relay_latencies.update(0, Some(40)), Some(40));
+
+        // Measurements of relays that are not used are forgotten:
+        relay_latencies.update(1, Some(10));
+        relay_latencies.retain(&vec![1u32].into_iter().collect());
+        assert_eq!(relay_latencies.sort_addresses(&[0u32, 1]), vec![1u32, 0]);
+    }
+}
@@ -3,12 +3,19 @@ use std::hash::Hash;
 
 use futures::channel::mpsc;
 use futures::task::Spawn;
+use futures::{stream, Stream, StreamExt};
 
-use common::conn::{BoxFuture, ConnPairVec, FutTransform};
+use common::conn::{BoxFuture, BoxStream, ConnPairVec, FutTransform};
 use timer::TimerClient;
 
+use proto::consts::{
+    BANDWIDTH_REPORT_TICKS, FRIEND_SEND_TIMEOUT_TICKS, MAX_CONCURRENT_FRIEND_SENDS,
+    MAX_FRIEND_QUEUE_LEN,
+};
 use proto::crypto::PublicKey;
 use proto::funder::messages::{ChannelerToFunder, FunderToChanneler};
+use proto::keepalive::messages::KeepAliveReport;
+use proto::secure_channel::messages::SecureChannelReport;
 
 use relay::{ClientConnector, ClientListener};
 
@@ -93,16 +100,21 @@ pub enum SpawnChannelerError {
 
 // TODO: Possibly rename this function and module, as the channeler future
 // is not spawned here.
-pub async fn spawn_channeler<RA, C, EKT, S>(
+pub async fn spawn_channeler<RA, C, EKT, KR, SR, S>(
     local_public_key: PublicKey,
     timer_client: TimerClient,
     backoff_ticks: usize,
     conn_timeout_ticks: usize,
+    relay_multiplex: bool,
     max_concurrent_encrypt: usize,
+    relay_probe_ticks: usize,
+    listen_only: bool,
     connector: C,
     encrypt_keepalive: EKT,
+    keepalive_reports: KR,
+    secure_channel_reports: SR,
     from_funder: mpsc::Receiver<FunderToChanneler<RA>>,
-    to_funder: mpsc::Sender<ChannelerToFunder>,
+    to_funder: mpsc::Sender<ChannelerToFunder<RA>>,
     spawner: S,
 ) -> Result<(), ChannelerError>
 where
@@ -114,8 +126,30 @@ where
         > + Clone
         + Send
         + 'static,
+    KR: Stream<Item = (PublicKey, KeepAliveReport)> + Unpin + Send,
+    SR: Stream<Item = (PublicKey, SecureChannelReport)> + Unpin + Send,
     S: Spawn + Clone + Send + 'static,
 {
+    // Ticks to measure the latency of the relays. 0 disables the measurements:
+    let probe_ticks: BoxStream<'static, ()> = if relay_probe_ticks == 0 {
+        Box::pin(stream::pending())
+    } else {
+        let interval = timer_client
+            .clone()
+            .request_interval(relay_probe_ticks)
+            .await
+            .map_err(|_| ChannelerError::RequestTimerStreamError)?;
+        Box::pin(interval.map(|_| ()))
+    };
+
+    // Ticks to detect messages that take too long to be sent to friends, and to report
+    // bandwidth:
+    let timer_stream = timer_client
+        .clone()
+        .request_timer_stream()
+        .await
+        .map_err(|_| ChannelerError::RequestTimerStreamError)?;
+
     let client_connector = ClientConnector::new(connector.clone());
 
     let connect_encrypt_transform = ConnectEncryptTransform::new(encrypt_keepalive.clone());
@@ -129,8 +163,9 @@ where
     );
 
     let client_listener = ClientListener::new(
-        connector,
+        connector.clone(),
         conn_timeout_ticks,
+        relay_multiplex,
         timer_client.clone(),
         spawner.clone(),
     );
@@ -153,7 +188,17 @@ where
         from_funder,
         to_funder,
         pool_connector,
+        connector,
         pool_listener,
+        keepalive_reports,
+        secure_channel_reports,
+        probe_ticks,
+        timer_stream,
+        MAX_FRIEND_QUEUE_LEN,
+        MAX_CONCURRENT_FRIEND_SENDS,
+        FRIEND_SEND_TIMEOUT_TICKS,
+        BANDWIDTH_REPORT_TICKS,
+        listen_only,
         c_spawner,
     )
     .await
@@ -0,0 +1,144 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use futures::channel::mpsc;
+use futures::future::{self, AbortHandle};
+use futures::sink::{Sink, SinkExt};
+use futures::stream::StreamExt;
+use futures::task::{Spawn, SpawnExt};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BudgetError {
+    /// The consumer does not keep up with the messages sent to it.
+    /// Sending the message would exceed the budget of buffered bytes.
+    TooSlow {
+        buffered_bytes: usize,
+        max_buffered_bytes: usize,
+    },
+    /// The consumer was closed, or was already dropped for being too slow.
+    Closed,
+}
+
+/// A sender that never waits for its consumer.
+/// Messages are buffered until the consumer receives them, up to a budget of buffered bytes.
+/// A consumer that exceeds its budget is dropped, together with all the messages buffered for it.
+pub struct BudgetSender<T> {
+    sender: mpsc::UnboundedSender<T>,
+    buffered_bytes: Arc<AtomicUsize>,
+    max_buffered_bytes: usize,
+    item_size: fn(&T) -> usize,
+    abort_handle: AbortHandle,
+}
+
+impl<T> BudgetSender<T> {
+    /// Amount of bytes sent that were not yet received by the consumer.
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes.load(Ordering::SeqCst)
+    }
+
+    /// Send a message without waiting for the consumer.
+    /// If the message does not fit into the budget, the consumer is dropped.
+    pub fn try_send(&mut self, item: T) -> Result<(), BudgetError> {
+        if self.sender.is_closed() {
+            return Err(BudgetError::Closed);
+        }
+
+        let size = (self.item_size)(&item);
+        let buffered_bytes = self.buffered_bytes.fetch_add(size, Ordering::SeqCst);
+        if buffered_bytes.saturating_add(size) > self.max_buffered_bytes {
+            self.abort_handle.abort();
+            self.sender.close_channel();
+            return Err(BudgetError::TooSlow {
+                buffered_bytes,
+                max_buffered_bytes: self.max_buffered_bytes,
+            });
+        }
+
+        self.sender
+            .unbounded_send(item)
+            .map_err(|_| BudgetError::Closed)
+    }
+}
+
+/// Wrap `sink` with a `BudgetSender`, allowing at most `max_buffered_bytes` bytes
+/// (As measured by `item_size`) to be buffered for it.
+/// `max_buffered_bytes` should be large enough to contain the largest possible message.
+pub fn budget_sender<T>(
+    mut sink: impl Sink<T> + Unpin + Send + 'static,
+    max_buffered_bytes: usize,
+    item_size: fn(&T) -> usize,
+    spawner: &impl Spawn,
+) -> BudgetSender<T>
+where
+    T: Send + 'static,
+{
+    let (sender, mut receiver) = mpsc::unbounded::<T>();
+    let buffered_bytes = Arc::new(AtomicUsize::new(0));
+
+    let c_buffered_bytes = buffered_bytes.clone();
+    let forward_fut = async move {
+        while let Some(item) = receiver.next().await {
+            let size = item_size(&item);
+            if sink.send(item).await.is_err() {
+                return;
+            }
+            c_buffered_bytes.fetch_sub(size, Ordering::SeqCst);
+        }
+    };
+
+    // Aborting the forwarding task drops the sink and all the buffered messages:
+    let (forward_fut, abort_handle) = future::abortable(forward_fut);
+    spawner
+        .spawn(async move {
+            let _ = forward_fut.await;
+        })
+        .unwrap();
+
+    BudgetSender {
+        sender,
+        buffered_bytes,
+        max_buffered_bytes,
+        item_size,
+        abort_handle,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::LocalPool;
+
+    #[test]
+    fn test_budget_sender_too_slow() {
+        let mut local_pool = LocalPool::new();
+        let spawner = local_pool.spawner();
+
+        let (sink, mut receiver) = mpsc::channel::<Vec<u8>>(0);
+        let mut sender = budget_sender(sink, 10, Vec::len, &spawner);
+
+        // The consumer receives everything that was sent:
+        sender.try_send(vec![0u8; 4]).unwrap();
+        sender.try_send(vec![1u8; 4]).unwrap();
+        assert_eq!(local_pool.run_until(receiver.next()), Some(vec![0u8; 4]));
+        assert_eq!(local_pool.run_until(receiver.next()), Some(vec![1u8; 4]));
+        local_pool.run_until_stalled();
+        assert_eq!(sender.buffered_bytes(), 0);
+
+        // The consumer stalls:
+        sender.try_send(vec![2u8; 6]).unwrap();
+        sender.try_send(vec![3u8; 4]).unwrap();
+        assert_eq!(
+            sender.try_send(vec![4u8; 1]),
+            Err(BudgetError::TooSlow {
+                buffered_bytes: 10,
+                max_buffered_bytes: 10,
+            })
+        );
+        assert_eq!(sender.try_send(vec![5u8; 1]), Err(BudgetError::Closed));
+
+        // The consumer was dropped, together with its buffered messages:
+        local_pool.run_until_stalled();
+        assert_eq!(local_pool.run_until(receiver.next()), None);
+    }
+}
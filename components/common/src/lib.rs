@@ -25,6 +25,7 @@ pub mod define_fixed_bytes;
 // pub mod frame_codec;
 pub mod access_control;
 pub mod async_test_utils;
+pub mod budget;
 pub mod caller_info;
 // pub mod canonical_serialize;
 pub mod conn;
@@ -40,3 +41,5 @@ pub mod transform_pool;
 #[macro_use]
 pub mod ser_utils;
 pub mod test_executor;
+#[cfg(feature = "testing")]
+pub mod testing;
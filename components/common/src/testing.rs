@@ -0,0 +1,121 @@
+//! Scriptable behaviors for mock services.
+//!
+//! A mock service (For example, the mock timer, identity or database) consults a `MockScript`
+//! whenever it receives a request. The script decides whether the request is handled normally,
+//! fails, or is delayed until the test releases it. This allows tests to simulate slow or failing
+//! services deterministically.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use futures::channel::oneshot;
+
+/// Behavior of a mock service for a single request.
+#[derive(Debug)]
+pub enum MockAction {
+    /// Handle the request normally.
+    Respond,
+    /// Fail the request.
+    Fail,
+    /// Handle the request once the matching sender is used.
+    /// If the sender is dropped, the request fails.
+    Delay(oneshot::Receiver<()>),
+}
+
+impl MockAction {
+    /// Wait according to the action.
+    /// Returns true if the request should be handled, or false if it should fail.
+    pub async fn perform(self) -> bool {
+        match self {
+            MockAction::Respond => true,
+            MockAction::Fail => false,
+            MockAction::Delay(receiver) => receiver.await.is_ok(),
+        }
+    }
+}
+
+/// A queue of actions, shared between a test and a mock service.
+/// Every request consumes the next action. When the queue is empty, requests are handled
+/// normally.
+#[derive(Debug, Clone, Default)]
+pub struct MockScript {
+    actions: Arc<Mutex<VecDeque<MockAction>>>,
+}
+
+impl MockScript {
+    pub fn new() -> Self {
+        MockScript {
+            actions: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Add an action to the end of the script.
+    pub fn push(&self, action: MockAction) {
+        self.actions.lock().unwrap().push_back(action);
+    }
+
+    /// Handle the next `count` requests normally.
+    pub fn respond(&self, count: usize) {
+        for _ in 0..count {
+            self.push(MockAction::Respond);
+        }
+    }
+
+    /// Fail the next `count` requests.
+    pub fn fail(&self, count: usize) {
+        for _ in 0..count {
+            self.push(MockAction::Fail);
+        }
+    }
+
+    /// Delay the next request. The request is handled when the returned sender is used, and
+    /// fails if the returned sender is dropped.
+    pub fn delay(&self) -> oneshot::Sender<()> {
+        let (sender, receiver) = oneshot::channel();
+        self.push(MockAction::Delay(receiver));
+        sender
+    }
+
+    /// Take the action for the next request.
+    pub fn next_action(&self) -> MockAction {
+        self.actions
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or(MockAction::Respond)
+    }
+
+    /// Amount of actions that were not consumed yet.
+    pub fn pending(&self) -> usize {
+        self.actions.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_mock_script_order() {
+        let script = MockScript::new();
+        script.fail(1);
+        script.respond(1);
+        let delay_sender = script.delay();
+        let dropped_sender = script.delay();
+        assert_eq!(script.pending(), 4);
+
+        assert!(!block_on(script.next_action().perform()));
+        assert!(block_on(script.next_action().perform()));
+
+        delay_sender.send(()).unwrap();
+        assert!(block_on(script.next_action().perform()));
+
+        drop(dropped_sender);
+        assert!(!block_on(script.next_action().perform()));
+
+        // An empty script handles requests normally:
+        assert_eq!(script.pending(), 0);
+        assert!(block_on(script.next_action().perform()));
+    }
+}
@@ -7,6 +7,7 @@
     clippy::new_without_default
 )]
 
+#[macro_use]
 extern crate log;
 
 mod transforms;
@@ -14,3 +15,7 @@ mod transforms;
 pub use self::transforms::{
     create_encrypt_keepalive, create_secure_connector, create_version_encrypt_keepalive,
 };
+
+pub use keepalive::{
+    create_adaptive_keepalive, AdaptiveKeepAliveClient, AdaptiveKeepAliveError, KeepAliveBounds,
+};
@@ -1,26 +1,41 @@
-use futures::task::Spawn;
+use futures::channel::mpsc;
+use futures::task::{Spawn, SpawnExt};
+use futures::{SinkExt, StreamExt};
 
 use common::conn::{ConnPairVec, FuncFutTransform, FutTransform};
 
-use proto::consts::{KEEPALIVE_TICKS, PROTOCOL_VERSION, TICKS_TO_REKEY};
+use proto::consts::{KEEPALIVE_TICKS, PROTOCOL_VERSION, TICKS_TO_REKEY, TICKS_TO_RESUME};
 use proto::crypto::PublicKey;
+use proto::keepalive::messages::KeepAliveReport;
 use proto::net::messages::NetAddress;
+use proto::secure_channel::messages::SecureChannelReport;
 
 use crypto::rand::CryptoRandom;
 
 use identity::IdentityClient;
 use timer::TimerClient;
 
-use keepalive::KeepAliveChannel;
+use keepalive::{AdaptiveKeepAliveClient, KeepAliveChannel};
 use secure_channel::SecureChannel;
 use version::VersionPrefix;
 
 /// Create an encrypt-keepalive transformation:
 /// Composes: Encryption * Keepalive
+///
+/// Keepalive reports of every resulting connection are sent through `keepalive_report_sender`,
+/// together with the public key of the remote side. Secure channel reports (Decryption
+/// failures, nonce replays and rekeys) are sent through `secure_channel_report_sender`.
+/// The interval between keepalives sent to every remote side is adapted by `adaptive_client`.
+/// Closed channels may be resumed without a full handshake for `ticks_to_resume` ticks.
 pub fn create_encrypt_keepalive<R, S>(
     timer_client: TimerClient,
     identity_client: IdentityClient,
     rng: R,
+    ticks_to_rekey: usize,
+    ticks_to_resume: usize,
+    keepalive_report_sender: mpsc::Sender<(PublicKey, KeepAliveReport)>,
+    secure_channel_report_sender: mpsc::Sender<(PublicKey, SecureChannelReport)>,
+    adaptive_client: AdaptiveKeepAliveClient,
     spawner: S,
 ) -> impl FutTransform<
     Input = (Option<PublicKey>, ConnPairVec),
@@ -32,25 +47,52 @@ where
     R: CryptoRandom + Clone + 'static,
 {
     // Wrap the connection (Version * Encrypt * Keepalive):
-    let encrypt_transform = SecureChannel::new(
+    let encrypt_transform = SecureChannel::new_with_reports(
         identity_client,
         rng,
         timer_client.clone(),
-        TICKS_TO_REKEY,
+        ticks_to_rekey,
+        ticks_to_resume,
+        secure_channel_report_sender,
+        spawner.clone(),
+    );
+    let keepalive_transform = KeepAliveChannel::new_adaptive(
+        timer_client,
+        KEEPALIVE_TICKS,
+        adaptive_client,
         spawner.clone(),
     );
-    let keepalive_transform = KeepAliveChannel::new(timer_client, KEEPALIVE_TICKS, spawner);
 
     // Note that this transform does not contain the version prefix, as it is applied to a
     // connection between two nodes, relayed using a relay server.
     FuncFutTransform::new(move |(opt_public_key, conn_pair_vec)| {
         let mut c_encrypt_transform = encrypt_transform.clone();
         let mut c_keepalive_transform = keepalive_transform.clone();
+        let mut c_keepalive_report_sender = keepalive_report_sender.clone();
+        let c_spawner = spawner.clone();
         Box::pin(async move {
             let (public_key, conn_pair_vec) = c_encrypt_transform
                 .transform((opt_public_key, conn_pair_vec))
                 .await?;
-            let conn_pair_vec = c_keepalive_transform.transform(conn_pair_vec).await;
+
+            // Attach the remote public key to the keepalive reports of this connection:
+            let (report_sender, report_receiver) = mpsc::channel(0);
+            let c_public_key = public_key.clone();
+            let mut report_receiver =
+                report_receiver.map(move |report| Ok((c_public_key.clone(), report)));
+            let forward_reports_fut = async move {
+                let _ = c_keepalive_report_sender
+                    .send_all(&mut report_receiver)
+                    .await;
+            };
+            if c_spawner.spawn(forward_reports_fut).is_err() {
+                warn!("create_encrypt_keepalive(): Failed to spawn keepalive reports forwarding");
+                return None;
+            }
+
+            let conn_pair_vec = c_keepalive_transform
+                .transform_adaptive(public_key.clone(), conn_pair_vec, Some(report_sender))
+                .await;
             Some((public_key, conn_pair_vec))
         })
     })
@@ -79,6 +121,7 @@ where
         rng,
         timer_client.clone(),
         TICKS_TO_REKEY,
+        TICKS_TO_RESUME,
         spawner.clone(),
     );
     let keepalive_transform = KeepAliveChannel::new(timer_client, KEEPALIVE_TICKS, spawner);
@@ -0,0 +1,327 @@
+//! Hierarchical derivation of keys from a single seed.
+//!
+//! A seed is derived from a seed phrase chosen by the user. All the keys of the user (The node
+//! identity, app identities and invoice keys) are derived from the seed, each selected by a
+//! derivation path. Backing up the seed phrase is enough to restore all of them.
+//!
+//! Every derivation step uses the private seed of the parent, so a derived key reveals nothing
+//! about its parent or its siblings.
+
+use std::fmt;
+use std::str::FromStr;
+
+use ring::digest;
+use ring::hmac::{self, SigningKey};
+use ring::pbkdf2;
+use ring::signature::Ed25519KeyPair;
+
+use proto::crypto::{PrivateKey, PublicKey};
+
+pub const SEED_LEN: usize = 32;
+
+/// Separates seeds derived from a seed phrase from keys derived for any other purpose.
+const SEED_PHRASE_SALT: &[u8] = b"offst derive seed";
+
+/// Amount of PBKDF2 iterations used to derive a seed from a seed phrase.
+const SEED_PHRASE_ITERATIONS: u32 = 100_000;
+
+/// PKCS#8 v2 document of an Ed25519 key pair (RFC 5958, RFC 8410), as used for `PrivateKey`:
+/// `PKCS8_SEED_PREFIX ++ seed ++ PKCS8_PUBLIC_KEY_PREFIX ++ public_key`
+const PKCS8_SEED_PREFIX: [u8; 16] = [
+    0x30, 0x53, 0x02, 0x01, 0x01, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+const PKCS8_PUBLIC_KEY_PREFIX: [u8; 5] = [0xa1, 0x23, 0x03, 0x21, 0x00];
+
+/// First path index of the node identity
+pub const NODE_IDENTITY_INDEX: u32 = 0;
+/// First path index of app identities
+pub const APP_IDENTITY_INDEX: u32 = 1;
+/// First path index of invoice keys
+pub const INVOICE_KEY_INDEX: u32 = 2;
+
+/// A secret seed, from which keys are derived.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Seed([u8; SEED_LEN]);
+
+impl fmt::Debug for Seed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Never print the secret seed:
+        write!(f, "Seed(..)")
+    }
+}
+
+impl From<&[u8; SEED_LEN]> for Seed {
+    fn from(seed: &[u8; SEED_LEN]) -> Self {
+        Seed(*seed)
+    }
+}
+
+impl Seed {
+    /// Derive a seed from a seed phrase.
+    pub fn from_phrase(seed_phrase: &str) -> Self {
+        let mut seed = [0x00u8; SEED_LEN];
+        pbkdf2::derive(
+            &digest::SHA256,
+            SEED_PHRASE_ITERATIONS,
+            SEED_PHRASE_SALT,
+            seed_phrase.as_bytes(),
+            &mut seed,
+        );
+        Seed(seed)
+    }
+
+    fn child(&self, index: u32) -> Seed {
+        let signing_key = SigningKey::new(&digest::SHA512_256, &self.0);
+        let mut seed = [0x00u8; SEED_LEN];
+        seed.copy_from_slice(hmac::sign(&signing_key, &index.to_be_bytes()).as_ref());
+        Seed(seed)
+    }
+
+    /// Derive the seed at `path` below this seed.
+    pub fn derive(&self, path: &DerivationPath) -> Seed {
+        path.indices
+            .iter()
+            .fold(self.clone(), |seed, index| seed.child(*index))
+    }
+}
+
+/// A path of derivation steps, written as `m/1/0` (For app identity number 0).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DerivationPath {
+    indices: Vec<u32>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DerivationPathError {
+    /// A path must begin with `m`
+    MissingRoot,
+    InvalidIndex,
+}
+
+impl DerivationPath {
+    pub fn new(indices: Vec<u32>) -> Self {
+        DerivationPath { indices }
+    }
+
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    /// The path of one level below this path
+    pub fn child(&self, index: u32) -> Self {
+        let mut indices = self.indices.clone();
+        indices.push(index);
+        DerivationPath { indices }
+    }
+
+    pub fn node_identity() -> Self {
+        DerivationPath::new(vec![NODE_IDENTITY_INDEX])
+    }
+
+    pub fn app_identity(app_index: u32) -> Self {
+        DerivationPath::new(vec![APP_IDENTITY_INDEX, app_index])
+    }
+
+    pub fn invoice_key(invoice_index: u32) -> Self {
+        DerivationPath::new(vec![INVOICE_KEY_INDEX, invoice_index])
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "m")?;
+        for index in &self.indices {
+            write!(f, "/{}", index)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = DerivationPathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('/');
+        if parts.next() != Some("m") {
+            return Err(DerivationPathError::MissingRoot);
+        }
+        let indices = parts
+            .map(|part| part.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| DerivationPathError::InvalidIndex)?;
+        Ok(DerivationPath { indices })
+    }
+}
+
+/// Create the Ed25519 key pair whose private seed is `seed`, and encode it as a PKCS#8
+/// document.
+fn private_key_from_seed(seed: &Seed) -> PrivateKey {
+    // Any 32 bytes are a valid Ed25519 seed:
+    let key_pair = Ed25519KeyPair::from_seed_unchecked(untrusted::Input::from(&seed.0)).unwrap();
+    let public_key_bytes = key_pair.public_key_bytes();
+    assert_eq!(public_key_bytes.len(), PublicKey::len());
+
+    let mut pkcs8 = Vec::with_capacity(PrivateKey::len());
+    pkcs8.extend_from_slice(&PKCS8_SEED_PREFIX);
+    pkcs8.extend_from_slice(&seed.0);
+    pkcs8.extend_from_slice(&PKCS8_PUBLIC_KEY_PREFIX);
+    pkcs8.extend_from_slice(public_key_bytes);
+
+    let mut private_key = PrivateKey::default();
+    private_key.clone_from_slice(&pkcs8);
+    private_key
+}
+
+/// Derive the private key at `path` below `seed`.
+/// The derived seed is the private seed of an Ed25519 key pair.
+pub fn derive_private_key(seed: &Seed, path: &DerivationPath) -> PrivateKey {
+    private_key_from_seed(&seed.derive(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::identity::derive_public_key;
+
+    fn hex_bytes(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn hex_public_key(hex: &str) -> PublicKey {
+        let mut public_key = PublicKey::default();
+        public_key.clone_from_slice(&hex_bytes(hex));
+        public_key
+    }
+
+    #[test]
+    fn test_private_key_from_seed() {
+        // RFC 8032, section 7.1, Test 1:
+        let mut seed_bytes = [0u8; SEED_LEN];
+        seed_bytes.copy_from_slice(&hex_bytes(
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+        ));
+        let private_key = private_key_from_seed(&Seed::from(&seed_bytes));
+
+        let expected_public_key =
+            hex_public_key("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+        assert_eq!(
+            derive_public_key(&private_key).unwrap(),
+            expected_public_key
+        );
+
+        // The PKCS#8 document contains the seed and the public key:
+        assert_eq!(&private_key[..16], &PKCS8_SEED_PREFIX[..]);
+        assert_eq!(&private_key[16..48], &seed_bytes[..]);
+        assert_eq!(&private_key[48..53], &PKCS8_PUBLIC_KEY_PREFIX[..]);
+        assert_eq!(&private_key[53..], &expected_public_key[..]);
+    }
+
+    #[test]
+    fn test_derive_private_key_known_answers() {
+        let seed = Seed::from_phrase("correct horse battery staple");
+        let known_answers = [
+            (
+                "m/0",
+                "beeb32da51992049128c48a939351c6c4479f84ff53fab0233313d93d742a9f0",
+            ),
+            (
+                "m/1/0",
+                "bf302f06e8086ce2ae34802e5bbb8a5e649c6336a5c0ecb6f1f6e8a4f12ce661",
+            ),
+            (
+                "m/1/7",
+                "07741c9909002e0d0a566729db0c88154e017be9a29df74ce84dfebe2d090eda",
+            ),
+            (
+                "m/2/3",
+                "06b72fa5365943196d4e396b89efefbc8743ce27442fcda4234186e05c451589",
+            ),
+        ];
+        for (path, public_key) in &known_answers {
+            let path = path.parse::<DerivationPath>().unwrap();
+            assert_eq!(
+                derive_public_key(&derive_private_key(&seed, &path)).unwrap(),
+                hex_public_key(public_key)
+            );
+        }
+
+        // A seed given directly, and the seed itself as the key (The empty path):
+        let seed = Seed::from(&[0x01; SEED_LEN]);
+        assert_eq!(
+            derive_public_key(&derive_private_key(&seed, &DerivationPath::node_identity()))
+                .unwrap(),
+            hex_public_key("556e6aa44a0492a081e7e114920e4f2b97cb2d90e7aaae9a6b0785152036adf1")
+        );
+        assert_eq!(
+            derive_public_key(&derive_private_key(&seed, &DerivationPath::new(Vec::new())))
+                .unwrap(),
+            hex_public_key("8a88e3dd7409f195fd52db2d3cba5d72ca6709bf1d94121bf3748801b40f6f5c")
+        );
+    }
+
+    #[test]
+    fn test_derivation_path_str() {
+        let path = DerivationPath::app_identity(7);
+        assert_eq!(path.to_string(), "m/1/7");
+        assert_eq!("m/1/7".parse::<DerivationPath>().unwrap(), path);
+        assert_eq!(
+            "m".parse::<DerivationPath>().unwrap(),
+            DerivationPath::new(Vec::new())
+        );
+        assert_eq!(
+            "1/7".parse::<DerivationPath>(),
+            Err(DerivationPathError::MissingRoot)
+        );
+        assert_eq!(
+            "m/1/x".parse::<DerivationPath>(),
+            Err(DerivationPathError::InvalidIndex)
+        );
+    }
+
+    #[test]
+    fn test_derive_private_key() {
+        let seed = Seed::from_phrase("correct horse battery staple");
+        assert_eq!(seed, Seed::from_phrase("correct horse battery staple"));
+
+        // Derivation is deterministic:
+        let node_key = derive_private_key(&seed, &DerivationPath::node_identity());
+        let node_public_key = derive_public_key(&node_key).unwrap();
+        assert_eq!(
+            derive_public_key(&derive_private_key(&seed, &DerivationPath::node_identity()))
+                .unwrap(),
+            node_public_key
+        );
+
+        // Different paths give different keys:
+        let app_public_key0 =
+            derive_public_key(&derive_private_key(&seed, &DerivationPath::app_identity(0)))
+                .unwrap();
+        let app_public_key1 =
+            derive_public_key(&derive_private_key(&seed, &DerivationPath::app_identity(1)))
+                .unwrap();
+        assert_ne!(node_public_key, app_public_key0);
+        assert_ne!(app_public_key0, app_public_key1);
+
+        // Deriving step by step is the same as deriving the full path:
+        let app_seed = seed.derive(&DerivationPath::new(vec![APP_IDENTITY_INDEX]));
+        assert_eq!(
+            app_seed.derive(&DerivationPath::new(vec![1])),
+            seed.derive(&DerivationPath::app_identity(1))
+        );
+
+        // A different seed phrase gives different keys:
+        let other_seed = Seed::from_phrase("correct horse battery staple!");
+        assert_ne!(
+            derive_public_key(&derive_private_key(
+                &other_seed,
+                &DerivationPath::node_identity()
+            ))
+            .unwrap(),
+            node_public_key
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use ring::digest::{digest, SHA512_256};
+use ring::digest::{digest, SHA1, SHA512_256};
 
 use proto::crypto::HashResult;
 
@@ -12,6 +12,18 @@ pub fn sha_512_256(data: &[u8]) -> HashResult {
     HashResult::from(&inner)
 }
 
+/// Length of a SHA1 digest, in bytes
+pub const SHA1_LEN: usize = 20;
+
+/// Calculate SHA1 over the given data.
+/// SHA1 is broken and must not be used for any security purpose. It is only provided for
+/// compatibility with external protocols (For example, the WebSocket opening handshake).
+pub fn sha1_legacy(data: &[u8]) -> [u8; SHA1_LEN] {
+    let mut output = [0x00; SHA1_LEN];
+    output.copy_from_slice(digest(&SHA1, data).as_ref());
+    output
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -30,4 +42,13 @@ mod tests {
 
         assert_eq!(hash_res.as_ref(), expected);
     }
+
+    #[test]
+    fn sha1_legacy_basic() {
+        let expected = [
+            0xa9, 0x99, 0x3e, 0x36, 0x47, 0x06, 0x81, 0x6a, 0xba, 0x3e, 0x25, 0x71, 0x78, 0x50,
+            0xc2, 0x6c, 0x9c, 0xd0, 0xd8, 0x9d,
+        ];
+        assert_eq!(sha1_legacy(b"abc"), expected);
+    }
 }
@@ -12,12 +12,15 @@ extern crate common;
 #[macro_use]
 extern crate serde;
 
+pub mod derive;
 pub mod dh;
 pub mod error;
 pub mod hash;
 pub mod hash_lock;
 pub mod identity;
 // pub mod nonce_window;
+pub mod pow;
 pub mod rand;
+pub mod storage_key;
 pub mod sym_encrypt;
 pub mod test_utils;
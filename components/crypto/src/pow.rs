@@ -0,0 +1,106 @@
+use byteorder::{BigEndian, WriteBytesExt};
+
+use proto::crypto::HashResult;
+
+use crate::hash::sha_512_256;
+
+/// Calculate the proof of work hash of a buffer, using a given nonce:
+/// sha_512_256(buff || nonce)
+pub fn pow_hash(buff: &[u8], nonce: u64) -> HashResult {
+    let mut data = Vec::with_capacity(buff.len() + 8);
+    data.extend_from_slice(buff);
+    data.write_u64::<BigEndian>(nonce).unwrap();
+    sha_512_256(&data)
+}
+
+/// Amount of leading zero bits in a hash
+pub fn leading_zero_bits(hash: &HashResult) -> u32 {
+    let mut zero_bits = 0;
+    for byte in hash.as_ref() {
+        zero_bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    zero_bits
+}
+
+/// Check if `nonce` is a valid proof of work for `buff`.
+/// The proof of work hash must begin with at least `difficulty` zero bits.
+pub fn verify_pow(buff: &[u8], nonce: u64, difficulty: u8) -> bool {
+    leading_zero_bits(&pow_hash(buff, nonce)) >= u32::from(difficulty)
+}
+
+/// Search for a valid proof of work nonce for `buff` among `num_nonces` consecutive nonces,
+/// starting from `first_nonce`. Allows solving in chunks.
+pub fn search_pow(buff: &[u8], difficulty: u8, first_nonce: u64, num_nonces: u64) -> Option<u64> {
+    let mut nonce = first_nonce;
+    for _ in 0..num_nonces {
+        if verify_pow(buff, nonce, difficulty) {
+            return Some(nonce);
+        }
+        nonce = nonce.wrapping_add(1);
+    }
+    None
+}
+
+/// Find a valid proof of work nonce for `buff`.
+/// On average, 2^difficulty hashes are calculated.
+pub fn solve_pow(buff: &[u8], difficulty: u8) -> u64 {
+    let mut nonce = 0u64;
+    while !verify_pow(buff, nonce, difficulty) {
+        nonce = nonce.wrapping_add(1);
+    }
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leading_zero_bits() {
+        let mut hash_bytes = [0u8; HashResult::len()];
+        assert_eq!(
+            leading_zero_bits(&HashResult::from(&hash_bytes)),
+            8 * HashResult::len() as u32
+        );
+
+        hash_bytes[0] = 0x80;
+        assert_eq!(leading_zero_bits(&HashResult::from(&hash_bytes)), 0);
+
+        hash_bytes[0] = 0x00;
+        hash_bytes[1] = 0x10;
+        hash_bytes[2] = 0xff;
+        assert_eq!(leading_zero_bits(&HashResult::from(&hash_bytes)), 11);
+    }
+
+    #[test]
+    fn test_solve_verify_pow() {
+        let buff = b"Proof of work buffer";
+        // Any nonce is valid for difficulty 0:
+        assert!(verify_pow(buff, 0x1234, 0));
+
+        for difficulty in 0..12 {
+            let nonce = solve_pow(buff, difficulty);
+            assert!(verify_pow(buff, nonce, difficulty));
+            assert!(leading_zero_bits(&pow_hash(buff, nonce)) >= u32::from(difficulty));
+        }
+
+        // The proof is bound to the buffer:
+        let nonce = solve_pow(buff, 10);
+        assert_ne!(pow_hash(buff, nonce), pow_hash(b"Another buffer", nonce));
+    }
+
+    #[test]
+    fn test_search_pow() {
+        let buff = b"Proof of work buffer";
+        let nonce = solve_pow(buff, 8);
+
+        // solve_pow() returns the first valid nonce:
+        assert_eq!(search_pow(buff, 8, 0, nonce + 1), Some(nonce));
+        assert_eq!(search_pow(buff, 8, 0, nonce), None);
+        assert_eq!(search_pow(buff, 8, nonce, 1), Some(nonce));
+        assert_eq!(search_pow(buff, 8, nonce, 0), None);
+    }
+}
@@ -0,0 +1,59 @@
+//! Derivation of keys used to encrypt local storage (For example, the node's database file).
+
+use ring::digest;
+use ring::hkdf::extract_and_expand;
+use ring::hmac::SigningKey;
+use ring::pbkdf2;
+
+use proto::crypto::PrivateKey;
+
+use crate::sym_encrypt::{SymmetricKey, SYMMETRIC_KEY_LEN};
+
+/// Separates keys derived for local storage from keys derived for any other purpose.
+const STORAGE_KEY_INFO: &[u8] = b"offst storage key";
+
+/// Amount of PBKDF2 iterations used to derive a key from a passphrase.
+/// Makes guessing weak passphrases expensive.
+const PASSPHRASE_ITERATIONS: u32 = 100_000;
+
+/// Derive a storage key from a private key.
+/// The private key is already uniformly random, so no key stretching is required.
+pub fn storage_key_from_private_key(private_key: &PrivateKey, salt: &[u8]) -> SymmetricKey {
+    let salt_key = SigningKey::new(&digest::SHA512_256, salt);
+    let mut key = [0x00u8; SYMMETRIC_KEY_LEN];
+    extract_and_expand(&salt_key, private_key, STORAGE_KEY_INFO, &mut key);
+    SymmetricKey::from(&key)
+}
+
+/// Derive a storage key from a passphrase.
+pub fn storage_key_from_passphrase(passphrase: &str, salt: &[u8]) -> SymmetricKey {
+    let mut key = [0x00u8; SYMMETRIC_KEY_LEN];
+    pbkdf2::derive(
+        &digest::SHA256,
+        PASSPHRASE_ITERATIONS,
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    SymmetricKey::from(&key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_storage_key_derivation() {
+        let private_key = PrivateKey::from(&[1u8; PrivateKey::len()]);
+        let key1 = storage_key_from_private_key(&private_key, &[2u8; 16]);
+        assert_eq!(key1, storage_key_from_private_key(&private_key, &[2u8; 16]));
+        // A different salt gives a different key:
+        assert_ne!(key1, storage_key_from_private_key(&private_key, &[3u8; 16]));
+
+        let key2 = storage_key_from_passphrase("passphrase", &[2u8; 16]);
+        assert_eq!(key2, storage_key_from_passphrase("passphrase", &[2u8; 16]));
+        assert_ne!(key2, storage_key_from_passphrase("Passphrase", &[2u8; 16]));
+        assert_ne!(key2, storage_key_from_passphrase("passphrase", &[3u8; 16]));
+        assert_ne!(key1, key2);
+    }
+}
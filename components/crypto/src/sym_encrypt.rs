@@ -4,6 +4,7 @@ use ring;
 use ring::aead::{open_in_place, seal_in_place, OpeningKey, SealingKey, CHACHA20_POLY1305};
 
 use crate::error::CryptoError;
+use crate::rand::CryptoRandom;
 
 use common::big_array::BigArray;
 
@@ -115,8 +116,21 @@ impl Decryptor {
         })
     }
 
+    /// Does `cipher_msg` carry the nonce expected for the next message?
+    /// A message carrying any other nonce was either replayed or reordered.
+    /// Returns None if `cipher_msg` is too short to carry a nonce.
+    pub fn has_expected_nonce(&self, cipher_msg: &[u8]) -> Option<bool> {
+        if cipher_msg.len() < ENC_NONCE_LEN {
+            return None;
+        }
+        Some(&cipher_msg[..ENC_NONCE_LEN] == self.nonce_counter.as_ref())
+    }
+
     /// Decrypt and authenticate a message.
     pub fn decrypt(&mut self, cipher_msg: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        if cipher_msg.len() < ENC_NONCE_LEN {
+            return Err(CryptoError);
+        }
         let enc_nonce = &cipher_msg[..ENC_NONCE_LEN];
         if enc_nonce != self.nonce_counter.as_ref() {
             // Nonce doesn't match!
@@ -136,10 +150,58 @@ impl Decryptor {
     }
 }
 
+/// Encrypt a single message using a random nonce.
+/// Unlike `Encryptor`, no state is kept between messages. This is useful for data that is
+/// encrypted many times using the same key, like a file that is rewritten on every change.
+pub fn seal_random_nonce<R: CryptoRandom>(
+    symmetric_key: &SymmetricKey,
+    plain_msg: &[u8],
+    rng: &R,
+) -> Result<Vec<u8>, CryptoError> {
+    let sealing_key = SealingKey::new(&CHACHA20_POLY1305, symmetric_key)?;
+    let mut enc_nonce = [0u8; ENC_NONCE_LEN];
+    rng.fill(&mut enc_nonce)?;
+
+    // Put the nonce in the beginning of the resulting buffer:
+    let mut msg_buffer = enc_nonce.to_vec();
+    msg_buffer.extend(plain_msg);
+    // Extend the message with TAG_LEN zeroes. This leaves space for the tag:
+    msg_buffer.extend(iter::repeat(0).take(TAG_LEN).collect::<Vec<u8>>());
+    let ad: [u8; 0] = [];
+
+    let length = seal_in_place(
+        &sealing_key,
+        &enc_nonce,
+        &ad,
+        &mut msg_buffer[ENC_NONCE_LEN..],
+        TAG_LEN,
+    )?;
+    Ok(msg_buffer[..ENC_NONCE_LEN + length].to_vec())
+}
+
+/// Decrypt and authenticate a message encrypted using `seal_random_nonce()`.
+pub fn open_random_nonce(
+    symmetric_key: &SymmetricKey,
+    cipher_msg: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    if cipher_msg.len() < ENC_NONCE_LEN {
+        return Err(CryptoError);
+    }
+    let opening_key = OpeningKey::new(&CHACHA20_POLY1305, symmetric_key)?;
+    let enc_nonce = &cipher_msg[..ENC_NONCE_LEN];
+    let mut msg_buffer = cipher_msg[ENC_NONCE_LEN..].to_vec();
+    let ad: [u8; 0] = [];
+
+    let slice = open_in_place(&opening_key, enc_nonce, &ad, 0, &mut msg_buffer)?;
+    Ok(slice.to_vec())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::test_utils::DummyRandom;
+
     #[test]
     fn increase_nonce_basic() {
         let mut nonce = [0, 0, 0, 0];
@@ -174,4 +236,62 @@ mod tests {
 
         assert_eq!(plain_msg, &decrypted_msg[..]);
     }
+
+    #[test]
+    fn test_decryptor_expected_nonce() {
+        let symmetric_key = SymmetricKey::from(&[1; SYMMETRIC_KEY_LEN]);
+        let mut encryptor = Encryptor::new(&symmetric_key).unwrap();
+        let mut decryptor = Decryptor::new(&symmetric_key).unwrap();
+
+        let cipher_msg = encryptor.encrypt(b"Hello world!").unwrap();
+        assert_eq!(decryptor.has_expected_nonce(&cipher_msg), Some(true));
+        decryptor.decrypt(&cipher_msg).unwrap();
+
+        // A replayed message carries an old nonce:
+        assert_eq!(decryptor.has_expected_nonce(&cipher_msg), Some(false));
+        assert!(decryptor.decrypt(&cipher_msg).is_err());
+
+        // A truncated message does not carry a nonce:
+        assert_eq!(decryptor.has_expected_nonce(&[0u8; 4]), None);
+        assert!(decryptor.decrypt(&[0u8; 4]).is_err());
+
+        // A tampered message carries the expected nonce, but can not be authenticated:
+        let mut cipher_msg = encryptor.encrypt(b"Hello world!").unwrap();
+        let last = cipher_msg.len() - 1;
+        cipher_msg[last] ^= 1;
+        assert_eq!(decryptor.has_expected_nonce(&cipher_msg), Some(true));
+        assert!(decryptor.decrypt(&cipher_msg).is_err());
+    }
+
+    #[test]
+    fn test_seal_open_random_nonce() {
+        let rng = DummyRandom::new(&[1u8]);
+        let symmetric_key = SymmetricKey::from(&[1; SYMMETRIC_KEY_LEN]);
+
+        let plain_msg = b"Hello world!";
+        let cipher_msg1 = seal_random_nonce(&symmetric_key, plain_msg, &rng).unwrap();
+        let cipher_msg2 = seal_random_nonce(&symmetric_key, plain_msg, &rng).unwrap();
+        // Every message is encrypted using a different nonce:
+        assert_ne!(cipher_msg1, cipher_msg2);
+
+        assert_eq!(
+            open_random_nonce(&symmetric_key, &cipher_msg1).unwrap(),
+            plain_msg.to_vec()
+        );
+        assert_eq!(
+            open_random_nonce(&symmetric_key, &cipher_msg2).unwrap(),
+            plain_msg.to_vec()
+        );
+
+        // A different key can not open the message:
+        let other_key = SymmetricKey::from(&[2; SYMMETRIC_KEY_LEN]);
+        assert!(open_random_nonce(&other_key, &cipher_msg1).is_err());
+
+        // A modified message can not be opened:
+        let mut modified_msg = cipher_msg1.clone();
+        let last = modified_msg.len() - 1;
+        modified_msg[last] ^= 1;
+        assert!(open_random_nonce(&symmetric_key, &modified_msg).is_err());
+        assert!(open_random_nonce(&symmetric_key, &[0u8; 4]).is_err());
+    }
 }
@@ -5,6 +5,10 @@ use std::fmt::Debug;
 
 use crate::atomic_db::AtomicDb;
 
+/// Amount of applied mutations that may wait for a subscriber. A subscriber that falls behind
+/// is dropped.
+const SUBSCRIPTION_BUFFER: usize = 0x100;
+
 #[derive(Debug)]
 pub enum DatabaseError<ADE> {
     AtomicDbError(ADE),
@@ -13,11 +17,27 @@ pub enum DatabaseError<ADE> {
 
 // A request to apply mutations to the database
 #[derive(Debug)]
-pub struct DatabaseRequest<M> {
+pub struct MutateRequest<M> {
     pub mutations: Vec<M>,
     pub response_sender: oneshot::Sender<()>,
 }
 
+#[derive(Debug)]
+pub enum DatabaseRequest<M> {
+    Mutate(MutateRequest<M>),
+    /// Receive all the mutations applied to the database from now on
+    Subscribe(mpsc::Sender<AppliedMutation<M>>),
+}
+
+/// A mutation that was applied to the database.
+/// Sequence numbers start from 0 when the database service starts, and grow by one for every
+/// applied mutation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedMutation<M> {
+    pub seq: u64,
+    pub mutation: M,
+}
+
 #[derive(Clone, Debug)]
 pub struct DatabaseClient<M> {
     request_sender: mpsc::Sender<DatabaseRequest<M>>,
@@ -39,10 +59,10 @@ where
 
     pub async fn mutate(&mut self, mutations: Vec<M>) -> Result<(), DatabaseClientError> {
         let (response_sender, request_done) = oneshot::channel();
-        let database_request = DatabaseRequest {
+        let database_request = DatabaseRequest::Mutate(MutateRequest {
             mutations,
             response_sender,
-        };
+        });
         // Send the request:
         self.request_sender
             .send(database_request)
@@ -56,6 +76,43 @@ where
 
         Ok(())
     }
+
+    /// Subscribe to mutations applied to the database, for example to replicate the database
+    /// state to an external system.
+    ///
+    /// The returned stream yields every mutation applied after the subscription, in order.
+    /// The stream ends if the subscriber does not keep up with the database (Or if the database
+    /// service is closed), so a stream never skips sequence numbers.
+    pub async fn subscribe(
+        &mut self,
+    ) -> Result<mpsc::Receiver<AppliedMutation<M>>, DatabaseClientError> {
+        let (sender, receiver) = mpsc::channel(SUBSCRIPTION_BUFFER);
+        self.request_sender
+            .send(DatabaseRequest::Subscribe(sender))
+            .await
+            .map_err(|_| DatabaseClientError::SendError)?;
+        Ok(receiver)
+    }
+}
+
+/// Send applied mutations to all subscribers.
+/// Subscribers that are closed or fall behind are removed.
+fn notify_subscribers<M>(
+    subscribers: Vec<mpsc::Sender<AppliedMutation<M>>>,
+    applied_mutations: &[AppliedMutation<M>],
+) -> Vec<mpsc::Sender<AppliedMutation<M>>>
+where
+    M: Clone,
+{
+    subscribers
+        .into_iter()
+        .filter_map(|mut subscriber| {
+            for applied_mutation in applied_mutations {
+                subscriber.try_send(applied_mutation.clone()).ok()?;
+            }
+            Some(subscriber)
+        })
+        .collect()
 }
 
 pub async fn database_loop<AD, S>(
@@ -65,7 +122,7 @@ pub async fn database_loop<AD, S>(
 ) -> Result<AD, DatabaseError<AD::Error>>
 where
     AD: AtomicDb + Send + 'static,
-    AD::Mutation: Debug + Send + 'static,
+    AD::Mutation: Debug + Clone + Send + 'static,
     AD::Error: Send + 'static,
     S: Spawn,
 {
@@ -74,22 +131,44 @@ where
     // TODO: Maybe there will be a better way to do this in the future (Possibly a future version
     // of async-std/Tokio that has this feature)
 
+    let mut subscribers = Vec::new();
+    let mut next_seq = 0u64;
+
     while let Some(database_request) = incoming_requests.next().await {
-        let DatabaseRequest {
+        let MutateRequest {
             mutations,
             response_sender,
-        } = database_request;
+        } = match database_request {
+            DatabaseRequest::Mutate(mutate_request) => mutate_request,
+            DatabaseRequest::Subscribe(subscriber) => {
+                subscribers.push(subscriber);
+                continue;
+            }
+        };
         let mutate_fut = future::lazy(move |_| {
             atomic_db
                 .mutate_db(&mutations[..])
                 .map_err(DatabaseError::AtomicDbError)?;
-            Ok(atomic_db)
+            Ok((atomic_db, mutations))
         });
         let handle = database_spawner
             .spawn_with_handle(mutate_fut)
             .map_err(|_| DatabaseError::SpawnError)?;
 
-        atomic_db = handle.await?;
+        let (new_atomic_db, mutations) = handle.await?;
+        atomic_db = new_atomic_db;
+
+        let mut applied_mutations = Vec::new();
+        for mutation in mutations {
+            applied_mutations.push(AppliedMutation {
+                seq: next_seq,
+                mutation,
+            });
+            next_seq += 1;
+        }
+        if !subscribers.is_empty() {
+            subscribers = notify_subscribers(subscribers, &applied_mutations);
+        }
 
         // Notify client that the database mutation request was processed:
         let _ = response_sender.send(());
@@ -117,7 +196,7 @@ mod tests {
     }
 
     /// A dummy mutation (used for testing)
-    #[derive(Debug)]
+    #[derive(Debug, Clone, PartialEq, Eq)]
     enum DummyMutation {
         Inc,
         Dec,
@@ -201,4 +280,61 @@ mod tests {
         let thread_pool = ThreadPool::new().unwrap();
         LocalPool::new().run_until(task_database_loop_basic(thread_pool.clone()));
     }
+
+    async fn task_database_loop_subscribe<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let atomic_db = DummyAtomicDb::new();
+        let (request_sender, incoming_requests) = mpsc::channel(0);
+        let loop_fut = database_loop(atomic_db, incoming_requests, spawner.clone());
+        let loop_res_fut = spawner.spawn_with_handle(loop_fut).unwrap();
+
+        let mut db_client = DatabaseClient::new(request_sender);
+        // Mutations applied before the subscription are not sent to the subscriber:
+        db_client.mutate(vec![DummyMutation::Inc]).await.unwrap();
+
+        let mut subscription = db_client.subscribe().await.unwrap();
+        db_client
+            .mutate(vec![DummyMutation::Inc, DummyMutation::Dec])
+            .await
+            .unwrap();
+        db_client.mutate(vec![DummyMutation::Inc]).await.unwrap();
+
+        assert_eq!(
+            subscription.next().await.unwrap(),
+            AppliedMutation {
+                seq: 1,
+                mutation: DummyMutation::Inc
+            }
+        );
+        assert_eq!(
+            subscription.next().await.unwrap(),
+            AppliedMutation {
+                seq: 2,
+                mutation: DummyMutation::Dec
+            }
+        );
+        assert_eq!(
+            subscription.next().await.unwrap(),
+            AppliedMutation {
+                seq: 3,
+                mutation: DummyMutation::Inc
+            }
+        );
+
+        // A closed subscription does not affect the database:
+        drop(subscription);
+        db_client.mutate(vec![DummyMutation::Inc]).await.unwrap();
+
+        drop(db_client);
+        let atomic_db = loop_res_fut.await.unwrap();
+        assert_eq!(atomic_db.dummy_state.x, 3);
+    }
+
+    #[test]
+    fn test_database_loop_subscribe() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new().run_until(task_database_loop_subscribe(thread_pool.clone()));
+    }
 }
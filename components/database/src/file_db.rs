@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::io;
 use std::io::prelude::*;
 use std::path::PathBuf;
@@ -10,9 +11,19 @@ use serde::Serialize;
 
 use atomicwrites;
 
+use crypto::rand::{system_random, OffstSystemRandom, RandGen};
+use crypto::storage_key::{storage_key_from_passphrase, storage_key_from_private_key};
+use crypto::sym_encrypt::{open_random_nonce, seal_random_nonce, SymmetricKey};
+
+use proto::crypto::{PrivateKey, Salt};
+
 use crate::atomic_db::AtomicDb;
 use common::mutable_state::MutableState;
 
+/// Prefix of an encrypted database file.
+/// Plaintext database files are JSON documents, and can never begin with this prefix.
+const ENCRYPTED_MAGIC: &[u8] = b"OFFSTDBENC1";
+
 #[derive(Debug)]
 pub enum FileDbError<ME> {
     OpenError(io::Error),
@@ -21,6 +32,83 @@ pub enum FileDbError<ME> {
     SerdeJsonError(serde_json::Error),
     MutateError(ME),
     FileAlreadyExists,
+    /// The database file is encrypted, but no secret was provided.
+    FileEncrypted,
+    /// Failed to encrypt the database
+    EncryptError,
+    /// Failed to decrypt the database file: Wrong secret, or a corrupt file.
+    DecryptError,
+}
+
+/// The secret used to derive the key of an encrypted database file.
+#[derive(Clone)]
+pub enum FileDbSecret {
+    /// The private key of the node's identity
+    PrivateKey(PrivateKey),
+    /// A separate passphrase
+    Passphrase(String),
+}
+
+impl FileDbSecret {
+    fn derive_key(&self, salt: &[u8]) -> SymmetricKey {
+        match self {
+            FileDbSecret::PrivateKey(private_key) => {
+                storage_key_from_private_key(private_key, salt)
+            }
+            FileDbSecret::Passphrase(passphrase) => storage_key_from_passphrase(passphrase, salt),
+        }
+    }
+}
+
+/// Encryption of the database file at rest
+struct FileDbCipher {
+    salt: Salt,
+    symmetric_key: SymmetricKey,
+    rng: OffstSystemRandom,
+}
+
+impl FileDbCipher {
+    /// Create a cipher with a new random salt
+    fn new(secret: &FileDbSecret) -> Self {
+        let rng = system_random();
+        let salt = Salt::rand_gen(&rng);
+        FileDbCipher {
+            symmetric_key: secret.derive_key(&salt),
+            salt,
+            rng,
+        }
+    }
+
+    /// Encrypt the serialized state.
+    /// File format: `ENCRYPTED_MAGIC || salt || sealed serialized state`
+    fn encrypt<ME>(&self, plain: &[u8]) -> Result<Vec<u8>, FileDbError<ME>> {
+        let sealed = seal_random_nonce(&self.symmetric_key, plain, &self.rng)
+            .map_err(|_| FileDbError::EncryptError)?;
+        let mut data = ENCRYPTED_MAGIC.to_vec();
+        data.extend_from_slice(&self.salt);
+        data.extend(sealed);
+        Ok(data)
+    }
+
+    /// Decrypt the contents of an encrypted database file.
+    /// Returns the cipher used for the file, together with the serialized state.
+    fn decrypt<ME>(secret: &FileDbSecret, data: &[u8]) -> Result<(Self, Vec<u8>), FileDbError<ME>> {
+        let data = &data[ENCRYPTED_MAGIC.len()..];
+        if data.len() < Salt::len() {
+            return Err(FileDbError::DecryptError);
+        }
+        let salt = Salt::try_from(&data[..Salt::len()]).map_err(|_| FileDbError::DecryptError)?;
+        let symmetric_key = secret.derive_key(&salt);
+        let plain = open_random_nonce(&symmetric_key, &data[Salt::len()..])
+            .map_err(|_| FileDbError::DecryptError)?;
+
+        let cipher = FileDbCipher {
+            salt,
+            symmetric_key,
+            rng: system_random(),
+        };
+        Ok((cipher, plain))
+    }
 }
 
 pub struct FileDb<S> {
@@ -28,6 +116,8 @@ pub struct FileDb<S> {
     path_buf: PathBuf,
     /// Current state represented by the database:
     state: S,
+    /// Encryption of the database file. None for a plaintext database file.
+    opt_cipher: Option<FileDbCipher>,
 }
 
 impl<S> FileDb<S>
@@ -41,6 +131,25 @@ where
     pub fn create(
         path_buf: PathBuf,
         initial_state: S,
+    ) -> Result<Self, FileDbError<S::MutateError>> {
+        Self::create_inner(path_buf, initial_state, None)
+    }
+
+    /// Create a new encrypted database file from an initial state.
+    /// The key of the file is derived from `secret`.
+    /// Aborts if destination file already exists
+    pub fn create_encrypted(
+        path_buf: PathBuf,
+        initial_state: S,
+        secret: &FileDbSecret,
+    ) -> Result<Self, FileDbError<S::MutateError>> {
+        Self::create_inner(path_buf, initial_state, Some(FileDbCipher::new(secret)))
+    }
+
+    fn create_inner(
+        path_buf: PathBuf,
+        initial_state: S,
+        opt_cipher: Option<FileDbCipher>,
     ) -> Result<Self, FileDbError<S::MutateError>> {
         if path_buf.exists() {
             return Err(FileDbError::FileAlreadyExists);
@@ -50,29 +159,86 @@ where
         // Serialize the state:
         let ser_string =
             serde_json::to_string_pretty(&initial_state).map_err(FileDbError::SerdeJsonError)?;
-        // Save the new state to file, atomically:
-        let af = atomicwrites::AtomicFile::new(&path_buf, atomicwrites::AllowOverwrite);
-        af.write(|fw| fw.write_all(ser_string.as_bytes()))
-            .map_err(FileDbError::WriteError)?;
-
         let state: S = serde_json::from_str(&ser_string).map_err(FileDbError::SerdeJsonError)?;
 
-        Ok(FileDb { path_buf, state })
+        let file_db = FileDb {
+            path_buf,
+            state,
+            opt_cipher,
+        };
+        file_db.write_file(ser_string.as_bytes())?;
+        Ok(file_db)
     }
 
     /// Load an existing database from file
-    /// Returns an error if database file does not exist
+    /// Returns an error if database file does not exist, or if the database file is encrypted.
     pub fn load(path_buf: PathBuf) -> Result<Self, FileDbError<S::MutateError>> {
-        let mut f = File::open(&path_buf).map_err(FileDbError::OpenError)?;
-        // read the whole file
-        let mut ser_string = String::new();
-        f.read_to_string(&mut ser_string)
-            .map_err(FileDbError::ReadError)?;
+        let data = read_file(&path_buf)?;
+        if data.starts_with(ENCRYPTED_MAGIC) {
+            return Err(FileDbError::FileEncrypted);
+        }
 
-        let state: S = serde_json::from_str(&ser_string).map_err(FileDbError::SerdeJsonError)?;
+        let state: S = serde_json::from_slice(&data).map_err(FileDbError::SerdeJsonError)?;
+
+        Ok(FileDb {
+            path_buf,
+            state,
+            opt_cipher: None,
+        })
+    }
+
+    /// Load an existing encrypted database from file, using the secret the file's key was
+    /// derived from.
+    /// A plaintext database file is migrated: It is encrypted (Using a key derived from
+    /// `secret`) and rewritten before returning.
+    /// Returns an error if database file does not exist
+    pub fn load_encrypted(
+        path_buf: PathBuf,
+        secret: &FileDbSecret,
+    ) -> Result<Self, FileDbError<S::MutateError>> {
+        let data = read_file(&path_buf)?;
+        if !data.starts_with(ENCRYPTED_MAGIC) {
+            // A plaintext database file. Migrate it to an encrypted file:
+            let state: S = serde_json::from_slice(&data).map_err(FileDbError::SerdeJsonError)?;
+            let file_db = FileDb {
+                path_buf,
+                state,
+                opt_cipher: Some(FileDbCipher::new(secret)),
+            };
+            file_db.write_file(&data)?;
+            return Ok(file_db);
+        }
+
+        let (cipher, plain) = FileDbCipher::decrypt(secret, &data)?;
+        let state: S = serde_json::from_slice(&plain).map_err(FileDbError::SerdeJsonError)?;
 
-        Ok(FileDb { path_buf, state })
+        Ok(FileDb {
+            path_buf,
+            state,
+            opt_cipher: Some(cipher),
+        })
     }
+
+    /// Save the serialized state to file, atomically.
+    /// The state is encrypted first if the database is encrypted.
+    fn write_file(&self, ser_data: &[u8]) -> Result<(), FileDbError<S::MutateError>> {
+        let data = match &self.opt_cipher {
+            Some(cipher) => cipher.encrypt(ser_data)?,
+            None => ser_data.to_vec(),
+        };
+
+        let af = atomicwrites::AtomicFile::new(&self.path_buf, atomicwrites::AllowOverwrite);
+        af.write(|fw| fw.write_all(&data))
+            .map_err(FileDbError::WriteError)
+    }
+}
+
+/// Read the whole contents of a database file
+fn read_file<ME>(path_buf: &PathBuf) -> Result<Vec<u8>, FileDbError<ME>> {
+    let mut f = File::open(path_buf).map_err(FileDbError::OpenError)?;
+    let mut data = Vec::new();
+    f.read_to_end(&mut data).map_err(FileDbError::ReadError)?;
+    Ok(data)
 }
 
 impl<S> AtomicDb for FileDb<S>
@@ -104,11 +270,7 @@ where
             serde_json::to_string_pretty(&self.state).map_err(FileDbError::SerdeJsonError)?;
 
         // Save the new state to file, atomically:
-        let af = atomicwrites::AtomicFile::new(&self.path_buf, atomicwrites::AllowOverwrite);
-        af.write(|fw| fw.write_all(ser_string.as_bytes()))
-            .map_err(FileDbError::WriteError)?;
-
-        Ok(())
+        self.write_file(ser_string.as_bytes())
     }
 }
 
@@ -198,4 +360,68 @@ mod tests {
         // Remove temporary directory:
         dir.close().unwrap();
     }
+
+    #[test]
+    fn test_file_db_encrypted() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("database_file");
+
+        let secret = FileDbSecret::PrivateKey(PrivateKey::from(&[1u8; PrivateKey::len()]));
+        let initial_state = DummyState::new(0);
+        let mut file_db =
+            FileDb::<DummyState>::create_encrypted(file_path.clone(), initial_state, &secret)
+                .unwrap();
+        file_db
+            .mutate_db(&[DummyMutation::Inc, DummyMutation::Inc])
+            .unwrap();
+        drop(file_db);
+
+        // The state is not readable on disk:
+        let data = std::fs::read(&file_path).unwrap();
+        assert!(data.starts_with(ENCRYPTED_MAGIC));
+        assert!(!String::from_utf8_lossy(&data).contains("\"x\""));
+
+        // Loading requires the right secret:
+        match FileDb::<DummyState>::load(file_path.clone()) {
+            Err(FileDbError::FileEncrypted) => {}
+            _ => unreachable!(),
+        }
+        let wrong_secret = FileDbSecret::PrivateKey(PrivateKey::from(&[2u8; PrivateKey::len()]));
+        match FileDb::<DummyState>::load_encrypted(file_path.clone(), &wrong_secret) {
+            Err(FileDbError::DecryptError) => {}
+            _ => unreachable!(),
+        }
+
+        let file_db = FileDb::<DummyState>::load_encrypted(file_path.clone(), &secret).unwrap();
+        assert_eq!(file_db.get_state().x, 2);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_file_db_migrate_plaintext() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("database_file");
+
+        let initial_state = DummyState::new(3);
+        let file_db = FileDb::<DummyState>::create(file_path.clone(), initial_state).unwrap();
+        drop(file_db);
+
+        // Loading a plaintext database with a secret encrypts the database file:
+        let secret = FileDbSecret::Passphrase("passphrase".to_owned());
+        let mut file_db = FileDb::<DummyState>::load_encrypted(file_path.clone(), &secret).unwrap();
+        assert_eq!(file_db.get_state().x, 3);
+        assert!(std::fs::read(&file_path)
+            .unwrap()
+            .starts_with(ENCRYPTED_MAGIC));
+
+        file_db.mutate_db(&[DummyMutation::Dec]).unwrap();
+        drop(file_db);
+
+        assert!(FileDb::<DummyState>::load(file_path.clone()).is_err());
+        let file_db = FileDb::<DummyState>::load_encrypted(file_path.clone(), &secret).unwrap();
+        assert_eq!(file_db.get_state().x, 2);
+
+        dir.close().unwrap();
+    }
 }
@@ -16,5 +16,11 @@ mod atomic_db;
 mod database;
 pub mod file_db;
 
+#[cfg(feature = "testing")]
+pub mod testing;
+
 pub use self::atomic_db::AtomicDb;
-pub use self::database::{database_loop, DatabaseClient, DatabaseClientError, DatabaseRequest};
+pub use self::database::{
+    database_loop, AppliedMutation, DatabaseClient, DatabaseClientError, DatabaseRequest,
+    MutateRequest,
+};
@@ -0,0 +1,150 @@
+//! A mock database service, for deterministic tests of crates that use a `DatabaseClient`.
+
+use std::sync::{Arc, Mutex};
+
+use futures::channel::mpsc;
+use futures::{Future, StreamExt};
+
+use common::mutable_state::MutableState;
+use common::testing::MockScript;
+
+use crate::database::{DatabaseClient, DatabaseRequest, MutateRequest};
+
+/// A handle to the in memory state of a mock database.
+#[derive(Debug, Clone)]
+pub struct MockDatabase<S> {
+    state: Arc<Mutex<S>>,
+}
+
+impl<S> MockDatabase<S>
+where
+    S: Clone,
+{
+    /// Get a copy of the current state of the database.
+    pub fn state(&self) -> S {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+/// Create a mock database service that keeps its state in memory, and consults `script` before
+/// every request.
+///
+/// Mutations of a request are applied atomically: If one of them fails, none of them is applied
+/// and the request fails. A failed request is reported to the client as
+/// `DatabaseClientError::ResponseCanceled`.
+/// Requests are handled one at a time, so a delayed request also delays the requests after it.
+/// Subscriptions are not supported: A subscription stream ends immediately.
+pub fn create_mock_database<S>(
+    initial_state: S,
+    script: MockScript,
+) -> (
+    DatabaseClient<S::Mutation>,
+    MockDatabase<S>,
+    impl Future<Output = ()>,
+)
+where
+    S: MutableState + Clone,
+{
+    let (request_sender, mut incoming_requests) = mpsc::channel::<DatabaseRequest<S::Mutation>>(0);
+    let mock_database = MockDatabase {
+        state: Arc::new(Mutex::new(initial_state)),
+    };
+
+    let c_mock_database = mock_database.clone();
+    let database_fut = async move {
+        while let Some(database_request) = incoming_requests.next().await {
+            let MutateRequest {
+                mutations,
+                response_sender,
+            } = match database_request {
+                DatabaseRequest::Mutate(mutate_request) => mutate_request,
+                DatabaseRequest::Subscribe(_) => continue,
+            };
+
+            if !script.next_action().perform().await {
+                // Dropping the response sender fails the request.
+                continue;
+            }
+
+            let mut state = c_mock_database.state.lock().unwrap();
+            let mut new_state = state.clone();
+            if mutations
+                .iter()
+                .all(|mutation| new_state.mutate(mutation).is_ok())
+            {
+                *state = new_state;
+                let _ = response_sender.send(());
+            }
+        }
+    };
+
+    (
+        DatabaseClient::new(request_sender),
+        mock_database,
+        database_fut,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::LocalPool;
+    use futures::task::SpawnExt;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct DummyState {
+        x: u32,
+    }
+
+    #[derive(Debug)]
+    enum DummyMutation {
+        Inc,
+        Dec,
+    }
+
+    impl MutableState for DummyState {
+        type Mutation = DummyMutation;
+        type MutateError = ();
+
+        fn mutate(&mut self, mutation: &Self::Mutation) -> Result<(), Self::MutateError> {
+            self.x = match mutation {
+                DummyMutation::Inc => self.x.checked_add(1).ok_or(())?,
+                DummyMutation::Dec => self.x.checked_sub(1).ok_or(())?,
+            };
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_database() {
+        let script = MockScript::new();
+        let (mut db_client, mock_database, database_fut) =
+            create_mock_database(DummyState { x: 0 }, script.clone());
+
+        let mut local_pool = LocalPool::new();
+        local_pool.spawner().spawn(database_fut).unwrap();
+
+        local_pool
+            .run_until(db_client.mutate(vec![DummyMutation::Inc, DummyMutation::Inc]))
+            .unwrap();
+        assert_eq!(mock_database.state(), DummyState { x: 2 });
+
+        // A scripted failure:
+        script.fail(1);
+        assert!(local_pool
+            .run_until(db_client.mutate(vec![DummyMutation::Inc]))
+            .is_err());
+        assert_eq!(mock_database.state(), DummyState { x: 2 });
+
+        // A failing mutation fails the whole request:
+        assert!(local_pool
+            .run_until(db_client.mutate(vec![
+                DummyMutation::Dec,
+                DummyMutation::Dec,
+                DummyMutation::Dec
+            ]))
+            .is_err());
+        assert_eq!(mock_database.state(), DummyState { x: 2 });
+    }
+}
@@ -0,0 +1,108 @@
+use std::convert::TryFrom;
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+use proto::crypto::{PublicKey, Uid};
+use proto::funder::messages::Currency;
+
+use offst_funder::freeze_guard::{FreezeGuard, FreezeGuardMutation, FrozenRequest};
+
+/// Amount of requests frozen at the same time, similar to a busy forwarding node.
+const NUM_FROZEN_REQUESTS: usize = 10_000;
+const NUM_FRIENDS: usize = 16;
+const NUM_CURRENCIES: usize = 2;
+
+fn friend_public_key(i: usize) -> PublicKey {
+    PublicKey::from(&[(i % NUM_FRIENDS) as u8; PublicKey::len()])
+}
+
+fn currency(i: usize) -> Currency {
+    Currency::try_from(format!("FST{}", i % NUM_CURRENCIES)).unwrap()
+}
+
+fn request_id(i: usize) -> Uid {
+    let mut uid = [0u8; Uid::len()];
+    uid[..8].copy_from_slice(&(i as u64).to_be_bytes());
+    Uid::from(&uid)
+}
+
+fn frozen_request(i: usize) -> FrozenRequest {
+    FrozenRequest {
+        friend_public_key: friend_public_key(i),
+        currency: currency(i / NUM_FRIENDS),
+        // Requests are forwarded between all friends. Some requests are our own:
+        opt_origin: if i % 7 == 0 {
+            None
+        } else {
+            Some(friend_public_key(i / 3))
+        },
+        credits: (i as u128 % 100) + 1,
+    }
+}
+
+/// A freeze guard with `NUM_FROZEN_REQUESTS` frozen requests
+fn busy_freeze_guard() -> FreezeGuard {
+    let mut freeze_guard = FreezeGuard::new();
+    for i in 0..NUM_FROZEN_REQUESTS {
+        freeze_guard.mutate(&FreezeGuardMutation::AddFrozen((
+            request_id(i),
+            frozen_request(i),
+        )));
+    }
+    freeze_guard
+}
+
+fn bench_freeze_guard_update(c: &mut Criterion) {
+    let freeze_guard = busy_freeze_guard();
+
+    // Cloning the freeze guard is cheap, because its maps share structure:
+    let add_mutation = FreezeGuardMutation::AddFrozen((
+        request_id(NUM_FROZEN_REQUESTS),
+        frozen_request(NUM_FROZEN_REQUESTS),
+    ));
+    c.bench_function("freeze_guard add (10k frozen requests)", |b| {
+        b.iter_batched(
+            || freeze_guard.clone(),
+            |mut freeze_guard| {
+                freeze_guard.mutate(&add_mutation);
+                freeze_guard
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    let sub_mutation = FreezeGuardMutation::SubFrozen(request_id(NUM_FROZEN_REQUESTS / 2));
+    c.bench_function("freeze_guard sub (10k frozen requests)", |b| {
+        b.iter_batched(
+            || freeze_guard.clone(),
+            |mut freeze_guard| {
+                freeze_guard.mutate(&sub_mutation);
+                freeze_guard
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn bench_freeze_guard_query(c: &mut Criterion) {
+    let freeze_guard = busy_freeze_guard();
+    let friend_public_key = friend_public_key(3);
+    let currency = currency(1);
+
+    c.bench_function("freeze_guard frozen (10k frozen requests)", |b| {
+        b.iter(|| black_box(freeze_guard.frozen(&friend_public_key, &currency)))
+    });
+
+    c.bench_function("freeze_guard max_origin (10k frozen requests)", |b| {
+        b.iter(|| {
+            black_box(
+                freeze_guard
+                    .frozen_credits(&friend_public_key, &currency)
+                    .and_then(|frozen_credits| frozen_credits.max_origin()),
+            )
+        })
+    });
+}
+
+criterion_group!(benches, bench_freeze_guard_update, bench_freeze_guard_query);
+criterion_main!(benches);
@@ -0,0 +1,81 @@
+use std::fmt::Debug;
+
+use signature::canonical::CanonicalSerialize;
+
+use proto::crypto::PublicKey;
+use proto::funder::messages::{ChannelProofResult, ExportChannelProof, ResponseChannelProof};
+use proto::proto_ser::ProtoSerialize;
+use proto::report::messages::{ChannelProof, MoveTokenHashedReport};
+
+use crate::friend::ChannelStatus;
+use crate::state::FunderState;
+use crate::types::{create_hashed, MoveTokenHashed};
+
+/// The first move token of a token channel is not signed, and commits to no balances.
+/// There is no point in exporting it.
+fn is_initial_move_token(move_token_hashed: &MoveTokenHashed) -> bool {
+    let counters = &move_token_hashed.token_info.counters;
+    counters.inconsistency_counter == 0 && counters.move_token_counter == 0
+}
+
+/// Collect the latest signed move tokens of the token channel with a friend.
+/// Returns None if the friend does not exist.
+pub fn create_channel_proof<B>(
+    state: &FunderState<B>,
+    friend_public_key: &PublicKey,
+) -> Option<ChannelProof>
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let friend = state.friends.get(friend_public_key)?;
+
+    let opt_last_incoming_move_token = friend
+        .channel_status
+        .get_last_incoming_move_token_hashed()
+        .filter(|move_token_hashed| !is_initial_move_token(move_token_hashed))
+        .as_ref()
+        .map(MoveTokenHashedReport::from);
+
+    let opt_last_outgoing_move_token = match &friend.channel_status {
+        // Only the last incoming move token is kept for an inconsistent channel:
+        ChannelStatus::Inconsistent(_) => None,
+        ChannelStatus::Consistent(channel_consistent) => channel_consistent
+            .token_channel
+            .get_outgoing()
+            .map(|tc_out_borrow| {
+                let tc_outgoing = tc_out_borrow.tc_outgoing;
+                create_hashed(&tc_outgoing.move_token_out, &tc_outgoing.token_info)
+            })
+            .filter(|move_token_hashed| !is_initial_move_token(move_token_hashed))
+            .as_ref()
+            .map(MoveTokenHashedReport::from),
+    };
+
+    Some(ChannelProof {
+        local_public_key: state.local_public_key.clone(),
+        friend_public_key: friend_public_key.clone(),
+        opt_last_incoming_move_token,
+        opt_last_outgoing_move_token,
+    })
+}
+
+/// Export the latest signed state of the token channel with a friend, as a serialized
+/// `ChannelProof`.
+pub fn export_channel_proof<B>(
+    state: &FunderState<B>,
+    export_channel_proof: ExportChannelProof,
+) -> ResponseChannelProof
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let result = match create_channel_proof(state, &export_channel_proof.friend_public_key) {
+        Some(channel_proof) => ChannelProofResult::Found(channel_proof.proto_serialize()),
+        None => ChannelProofResult::NotFound,
+    };
+
+    ResponseChannelProof {
+        request_id: export_channel_proof.request_id,
+        friend_public_key: export_channel_proof.friend_public_key,
+        result,
+    }
+}
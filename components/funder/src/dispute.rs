@@ -0,0 +1,81 @@
+use std::fmt::Debug;
+
+use signature::canonical::CanonicalSerialize;
+
+use proto::crypto::PublicKey;
+use proto::funder::messages::{Currency, PendingTransaction};
+use proto::proto_ser::ProtoSerialize;
+use proto::report::messages::{
+    CurrencyReport, DisputeEvidence, DisputedTransaction, McBalanceReport,
+};
+
+use crate::channel_proof::create_channel_proof;
+use crate::friend::ChannelStatus;
+use crate::state::FunderState;
+
+fn create_disputed_transaction(
+    currency: &Currency,
+    is_incoming: bool,
+    pending_transaction: &PendingTransaction,
+) -> DisputedTransaction {
+    DisputedTransaction {
+        request_id: pending_transaction.request_id.clone(),
+        currency: currency.clone(),
+        is_incoming,
+        route: pending_transaction.route.clone(),
+        dest_payment: pending_transaction.dest_payment,
+        left_fees: pending_transaction.left_fees,
+    }
+}
+
+/// Assemble the evidence of a dispute with a friend: The last signed move tokens of both sides,
+/// the balances and the pending transactions of the token channel.
+/// Must be called before the channel becomes inconsistent, because an inconsistent channel does
+/// not keep its token channel.
+///
+/// Returns None if the friend does not exist, or if the channel with the friend is already
+/// inconsistent.
+pub fn create_dispute_evidence<B>(
+    state: &FunderState<B>,
+    friend_public_key: &PublicKey,
+) -> Option<DisputeEvidence>
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let friend = state.friends.get(friend_public_key)?;
+    let token_channel = match &friend.channel_status {
+        ChannelStatus::Inconsistent(_) => return None,
+        ChannelStatus::Consistent(channel_consistent) => &channel_consistent.token_channel,
+    };
+
+    let channel_proof = create_channel_proof(state, friend_public_key)?;
+
+    let mut currency_reports = Vec::new();
+    let mut pending_transactions = Vec::new();
+    for (currency, mutual_credit) in token_channel.get_mutual_credits() {
+        let mc_state = mutual_credit.state();
+        currency_reports.push(CurrencyReport {
+            currency: currency.clone(),
+            balance: McBalanceReport::from(&mc_state.balance),
+        });
+
+        let mc_pending = &mc_state.pending_transactions;
+        pending_transactions.extend(mc_pending.local.values().map(|pending_transaction| {
+            create_disputed_transaction(currency, false, pending_transaction)
+        }));
+        pending_transactions.extend(mc_pending.remote.values().map(|pending_transaction| {
+            create_disputed_transaction(currency, true, pending_transaction)
+        }));
+    }
+
+    // Sorted, so that the evidence does not depend on the order of iteration over hash maps:
+    currency_reports.sort_by(|a, b| a.currency.cmp(&b.currency));
+    pending_transactions
+        .sort_by(|a, b| (&a.currency, &a.request_id).cmp(&(&b.currency, &b.request_id)));
+
+    Some(DisputeEvidence {
+        channel_proof: channel_proof.proto_serialize(),
+        currency_reports,
+        pending_transactions,
+    })
+}
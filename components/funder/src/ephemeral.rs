@@ -1,19 +1,47 @@
+use super::freeze_guard::{FreezeGuard, FreezeGuardMutation};
+use super::invoices::{Invoices, InvoicesMutation};
 use super::liveness::{Liveness, LivenessMutation};
+use super::outflows::{Outflows, OutflowsMutation};
+use super::rebalance::{Rebalance, RebalanceMutation};
+use super::refunds::{Refunds, RefundsMutation};
+use super::request_origins::{RequestOrigins, RequestOriginsMutation};
+use super::requests_expiry::{RequestsExpiry, RequestsExpiryMutation};
 
 #[derive(Clone, Default)]
 pub struct Ephemeral {
     pub liveness: Liveness,
+    pub invoices: Invoices,
+    pub requests_expiry: RequestsExpiry,
+    pub refunds: Refunds,
+    pub outflows: Outflows,
+    pub request_origins: RequestOrigins,
+    pub freeze_guard: FreezeGuard,
+    pub rebalance: Rebalance,
 }
 
 #[derive(Debug)]
 pub enum EphemeralMutation {
     LivenessMutation(LivenessMutation),
+    InvoicesMutation(InvoicesMutation),
+    RequestsExpiryMutation(RequestsExpiryMutation),
+    RefundsMutation(RefundsMutation),
+    OutflowsMutation(OutflowsMutation),
+    RequestOriginsMutation(RequestOriginsMutation),
+    FreezeGuardMutation(FreezeGuardMutation),
+    RebalanceMutation(RebalanceMutation),
 }
 
 impl Ephemeral {
     pub fn new() -> Ephemeral {
         Ephemeral {
             liveness: Liveness::new(),
+            invoices: Invoices::new(),
+            requests_expiry: RequestsExpiry::new(),
+            refunds: Refunds::new(),
+            outflows: Outflows::new(),
+            request_origins: RequestOrigins::new(),
+            freeze_guard: FreezeGuard::new(),
+            rebalance: Rebalance::new(),
         }
     }
 
@@ -22,6 +50,27 @@ impl Ephemeral {
             EphemeralMutation::LivenessMutation(liveness_mutation) => {
                 self.liveness.mutate(liveness_mutation)
             }
+            EphemeralMutation::InvoicesMutation(invoices_mutation) => {
+                self.invoices.mutate(invoices_mutation)
+            }
+            EphemeralMutation::RequestsExpiryMutation(requests_expiry_mutation) => {
+                self.requests_expiry.mutate(requests_expiry_mutation)
+            }
+            EphemeralMutation::RefundsMutation(refunds_mutation) => {
+                self.refunds.mutate(refunds_mutation)
+            }
+            EphemeralMutation::OutflowsMutation(outflows_mutation) => {
+                self.outflows.mutate(outflows_mutation)
+            }
+            EphemeralMutation::RequestOriginsMutation(request_origins_mutation) => {
+                self.request_origins.mutate(request_origins_mutation)
+            }
+            EphemeralMutation::FreezeGuardMutation(freeze_guard_mutation) => {
+                self.freeze_guard.mutate(freeze_guard_mutation)
+            }
+            EphemeralMutation::RebalanceMutation(rebalance_mutation) => {
+                self.rebalance.mutate(rebalance_mutation)
+            }
         }
     }
 }
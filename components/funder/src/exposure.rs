@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use common::safe_arithmetic::{SafeSignedArithmetic, SafeUnsignedArithmetic};
+
+use signature::canonical::CanonicalSerialize;
+
+use proto::crypto::{PublicKey, Uid};
+use proto::funder::messages::{
+    Currency, CurrencyExposure, FriendCurrencyExposure, FriendExposure, ResponseExposure,
+};
+
+use crate::friend::{ChannelStatus, FriendState};
+use crate::mutual_credit::types::McBalance;
+use crate::state::FunderState;
+
+/// Balance with a friend after all in-flight requests are resolved.
+fn currency_exposure(currency: &Currency, mc_balance: &McBalance) -> FriendCurrencyExposure {
+    // Requests we have sent (local pending debt) are paid by us if they succeed.
+    // Requests the friend has sent (remote pending debt) are paid by the friend if they succeed.
+    let all_succeed_balance = mc_balance
+        .balance
+        .saturating_add_unsigned(mc_balance.remote_pending_debt)
+        .saturating_sub_unsigned(mc_balance.local_pending_debt);
+
+    FriendCurrencyExposure {
+        currency: currency.clone(),
+        all_fail_balance: mc_balance.balance,
+        all_succeed_balance,
+    }
+}
+
+fn friend_exposure<B>(friend_public_key: &PublicKey, friend: &FriendState<B>) -> FriendExposure
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let mut currencies = match &friend.channel_status {
+        ChannelStatus::Consistent(channel_consistent) => channel_consistent
+            .token_channel
+            .get_mutual_credits()
+            .iter()
+            .map(|(currency, mutual_credit)| {
+                currency_exposure(currency, &mutual_credit.state().balance)
+            })
+            .collect::<Vec<_>>(),
+        // An inconsistent channel has no requests in flight.
+        // Any reset will be done according to the balance we have proposed:
+        ChannelStatus::Inconsistent(channel_inconsistent) => channel_inconsistent
+            .local_reset_terms
+            .balance_for_reset
+            .iter()
+            .map(|currency_balance| FriendCurrencyExposure {
+                currency: currency_balance.currency.clone(),
+                all_fail_balance: currency_balance.balance,
+                all_succeed_balance: currency_balance.balance,
+            })
+            .collect::<Vec<_>>(),
+    };
+    currencies.sort_by(|a, b| a.currency.cmp(&b.currency));
+
+    FriendExposure {
+        friend_public_key: friend_public_key.clone(),
+        currencies,
+    }
+}
+
+/// Sum the credits owed to us by all friends, for every currency.
+fn total_exposure(friends: &[FriendExposure]) -> Vec<CurrencyExposure> {
+    let mut totals: BTreeMap<Currency, (u128, u128)> = BTreeMap::new();
+    for friend_currency_exposure in friends.iter().flat_map(|friend| friend.currencies.iter()) {
+        let (all_fail, all_succeed) = totals
+            .entry(friend_currency_exposure.currency.clone())
+            .or_insert((0, 0));
+        // Only credits owed to us are counted:
+        *all_fail =
+            all_fail.saturating_add_signed(friend_currency_exposure.all_fail_balance.max(0));
+        *all_succeed =
+            all_succeed.saturating_add_signed(friend_currency_exposure.all_succeed_balance.max(0));
+    }
+
+    totals
+        .into_iter()
+        .map(
+            |(currency, (all_fail_exposure, all_succeed_exposure))| CurrencyExposure {
+                currency,
+                all_fail_exposure,
+                all_succeed_exposure,
+            },
+        )
+        .collect()
+}
+
+/// Calculate worst case credit exposure to every friend, and in total, for the two extreme
+/// outcomes of the in-flight requests: all fail, or all succeed.
+pub fn calc_exposure<B>(state: &FunderState<B>, request_id: Uid) -> ResponseExposure
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let mut friends = state
+        .friends
+        .iter()
+        .map(|(friend_public_key, friend)| friend_exposure(friend_public_key, friend))
+        .collect::<Vec<_>>();
+    friends.sort_by(|a, b| a.friend_public_key.cmp(&b.friend_public_key));
+
+    let totals = total_exposure(&friends);
+
+    ResponseExposure {
+        request_id,
+        friends,
+        totals,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_currency_exposure() {
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let mc_balance = McBalance {
+            balance: 10,
+            local_pending_debt: 30,
+            remote_pending_debt: 5,
+        };
+        let exposure = currency_exposure(&currency, &mc_balance);
+        assert_eq!(exposure.all_fail_balance, 10);
+        assert_eq!(exposure.all_succeed_balance, -15);
+
+        let mc_balance = McBalance {
+            balance: i128::max_value() - 1,
+            local_pending_debt: 0,
+            remote_pending_debt: 7,
+        };
+        let exposure = currency_exposure(&currency, &mc_balance);
+        assert_eq!(exposure.all_succeed_balance, i128::max_value());
+    }
+
+    #[test]
+    fn test_total_exposure() {
+        let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+        let currency2 = Currency::try_from("FST2".to_owned()).unwrap();
+
+        let friends = vec![
+            FriendExposure {
+                friend_public_key: PublicKey::from(&[0xaa; PublicKey::len()]),
+                currencies: vec![
+                    FriendCurrencyExposure {
+                        currency: currency1.clone(),
+                        all_fail_balance: 10,
+                        all_succeed_balance: -15,
+                    },
+                    FriendCurrencyExposure {
+                        currency: currency2.clone(),
+                        all_fail_balance: 3,
+                        all_succeed_balance: 4,
+                    },
+                ],
+            },
+            FriendExposure {
+                friend_public_key: PublicKey::from(&[0xbb; PublicKey::len()]),
+                currencies: vec![FriendCurrencyExposure {
+                    currency: currency1.clone(),
+                    all_fail_balance: -20,
+                    all_succeed_balance: 8,
+                }],
+            },
+        ];
+
+        assert_eq!(
+            total_exposure(&friends),
+            vec![
+                CurrencyExposure {
+                    currency: currency1,
+                    all_fail_exposure: 10,
+                    all_succeed_exposure: 8,
+                },
+                CurrencyExposure {
+                    currency: currency2,
+                    all_fail_exposure: 3,
+                    all_succeed_exposure: 4,
+                },
+            ]
+        );
+    }
+}
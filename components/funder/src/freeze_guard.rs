@@ -0,0 +1,491 @@
+use std::fmt::Debug;
+
+use im::hashmap::HashMap as ImHashMap;
+use im::ordset::OrdSet as ImOrdSet;
+
+use signature::canonical::CanonicalSerialize;
+
+use proto::crypto::{PublicKey, Uid};
+use proto::funder::messages::{Currency, PendingTransaction};
+
+use crate::friend::{ChannelStatus, FriendMutation};
+use crate::mutual_credit::types::McMutation;
+use crate::request_origins::RequestOrigins;
+use crate::state::{FunderMutation, FunderState};
+use crate::token_channel::{TcMutation, TokenChannel};
+
+/// Credits frozen by a single request we sent to a friend (A local pending transaction).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrozenRequest {
+    pub friend_public_key: PublicKey,
+    pub currency: Currency,
+    /// The friend that sent us the request, or None if we are the origin of the request.
+    pub opt_origin: Option<PublicKey>,
+    pub credits: u128,
+}
+
+/// Credits frozen to a single friend in a single currency, by the origin of the requests.
+#[derive(Debug, Clone, Default)]
+pub struct FrozenCredits {
+    total: u128,
+    by_origin: ImHashMap<Option<PublicKey>, u128>,
+    /// Same entries as `by_origin`, ordered by the amount of frozen credits
+    by_amount: ImOrdSet<(u128, Option<PublicKey>)>,
+}
+
+impl FrozenCredits {
+    fn set_origin(&mut self, opt_origin: &Option<PublicKey>, old_credits: u128, credits: u128) {
+        if old_credits > 0 {
+            self.by_amount.remove(&(old_credits, opt_origin.clone()));
+        }
+        if credits > 0 {
+            self.by_origin.insert(opt_origin.clone(), credits);
+            self.by_amount.insert((credits, opt_origin.clone()));
+        } else {
+            self.by_origin.remove(opt_origin);
+        }
+    }
+
+    fn add(&mut self, opt_origin: &Option<PublicKey>, credits: u128) {
+        let old_credits = self.from_origin(opt_origin);
+        self.set_origin(opt_origin, old_credits, old_credits.saturating_add(credits));
+        self.total = self.total.saturating_add(credits);
+    }
+
+    fn sub(&mut self, opt_origin: &Option<PublicKey>, credits: u128) {
+        let old_credits = self.from_origin(opt_origin);
+        self.set_origin(opt_origin, old_credits, old_credits.saturating_sub(credits));
+        self.total = self.total.saturating_sub(credits);
+    }
+
+    /// Total credits frozen
+    pub fn total(&self) -> u128 {
+        self.total
+    }
+
+    /// Credits frozen on behalf of requests that arrived from `opt_origin`.
+    /// `None` stands for requests originated by the local node.
+    pub fn from_origin(&self, opt_origin: &Option<PublicKey>) -> u128 {
+        self.by_origin.get(opt_origin).cloned().unwrap_or(0)
+    }
+
+    /// The origin that froze the largest amount of credits, and the amount it froze
+    pub fn max_origin(&self) -> Option<(&Option<PublicKey>, u128)> {
+        self.by_amount
+            .get_max()
+            .map(|(credits, opt_origin)| (opt_origin, *credits))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_origin.is_empty()
+    }
+}
+
+/// An index of the credits frozen by the requests we sent to friends, by friend and currency.
+/// Every update costs O(log n), where n is the amount of pending requests, so the frozen credits
+/// never have to be recalculated from the pending transactions.
+#[derive(Debug, Clone, Default)]
+pub struct FreezeGuard {
+    /// (friend_public_key, currency) -> frozen credits
+    frozen: ImHashMap<(PublicKey, Currency), FrozenCredits>,
+    /// request_id -> frozen request
+    requests: ImHashMap<Uid, FrozenRequest>,
+}
+
+#[derive(Debug)]
+pub enum FreezeGuardMutation {
+    AddFrozen((Uid, FrozenRequest)),
+    SubFrozen(Uid),
+    /// Forget all the requests sent to a friend
+    RemoveFriend(PublicKey),
+    /// A friend has rotated its key: (old_public_key, new_public_key)
+    ReplaceFriend((PublicKey, PublicKey)),
+}
+
+impl FreezeGuard {
+    pub fn new() -> FreezeGuard {
+        FreezeGuard {
+            frozen: ImHashMap::new(),
+            requests: ImHashMap::new(),
+        }
+    }
+
+    /// Build the index from scratch. Used when the funder starts.
+    /// `request_origins` must be an index of the requests in `state`.
+    pub fn from_state<B>(state: &FunderState<B>, request_origins: &RequestOrigins) -> FreezeGuard
+    where
+        B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+    {
+        let mut freeze_guard = FreezeGuard::new();
+        for (friend_public_key, friend) in &state.friends {
+            if let ChannelStatus::Consistent(channel_consistent) = &friend.channel_status {
+                for mutation in token_channel_freezes(
+                    friend_public_key,
+                    &channel_consistent.token_channel,
+                    request_origins,
+                ) {
+                    freeze_guard.mutate(&mutation);
+                }
+            }
+        }
+        freeze_guard
+    }
+
+    pub fn mutate(&mut self, mutation: &FreezeGuardMutation) {
+        match mutation {
+            FreezeGuardMutation::AddFrozen((request_id, frozen_request)) => {
+                self.add_frozen(request_id.clone(), frozen_request.clone())
+            }
+            FreezeGuardMutation::SubFrozen(request_id) => {
+                let _ = self.sub_frozen(request_id);
+            }
+            FreezeGuardMutation::RemoveFriend(friend_public_key) => {
+                let request_ids = self
+                    .requests
+                    .iter()
+                    .filter(|(_request_id, frozen_request)| {
+                        &frozen_request.friend_public_key == friend_public_key
+                    })
+                    .map(|(request_id, _frozen_request)| request_id.clone())
+                    .collect::<Vec<_>>();
+                for request_id in request_ids {
+                    let _ = self.sub_frozen(&request_id);
+                }
+            }
+            FreezeGuardMutation::ReplaceFriend((old_public_key, new_public_key)) => {
+                let request_ids = self
+                    .requests
+                    .iter()
+                    .filter(|(_request_id, frozen_request)| {
+                        &frozen_request.friend_public_key == old_public_key
+                            || frozen_request.opt_origin.as_ref() == Some(old_public_key)
+                    })
+                    .map(|(request_id, _frozen_request)| request_id.clone())
+                    .collect::<Vec<_>>();
+                for request_id in request_ids {
+                    let mut frozen_request = match self.sub_frozen(&request_id) {
+                        Some(frozen_request) => frozen_request,
+                        None => continue,
+                    };
+                    if &frozen_request.friend_public_key == old_public_key {
+                        frozen_request.friend_public_key = new_public_key.clone();
+                    }
+                    if frozen_request.opt_origin.as_ref() == Some(old_public_key) {
+                        frozen_request.opt_origin = Some(new_public_key.clone());
+                    }
+                    self.add_frozen(request_id, frozen_request);
+                }
+            }
+        }
+    }
+
+    fn add_frozen(&mut self, request_id: Uid, frozen_request: FrozenRequest) {
+        // A request is never frozen twice:
+        let _ = self.sub_frozen(&request_id);
+
+        self.frozen
+            .entry((
+                frozen_request.friend_public_key.clone(),
+                frozen_request.currency.clone(),
+            ))
+            .or_insert_with(FrozenCredits::default)
+            .add(&frozen_request.opt_origin, frozen_request.credits);
+        self.requests.insert(request_id, frozen_request);
+    }
+
+    fn sub_frozen(&mut self, request_id: &Uid) -> Option<FrozenRequest> {
+        let frozen_request = self.requests.remove(request_id)?;
+        let key = (
+            frozen_request.friend_public_key.clone(),
+            frozen_request.currency.clone(),
+        );
+        if let Some(frozen_credits) = self.frozen.get_mut(&key) {
+            frozen_credits.sub(&frozen_request.opt_origin, frozen_request.credits);
+            // Cleanup:
+            if frozen_credits.is_empty() {
+                let _ = self.frozen.remove(&key);
+            }
+        }
+        Some(frozen_request)
+    }
+
+    /// Credits frozen to a friend in a currency
+    pub fn frozen(&self, friend_public_key: &PublicKey, currency: &Currency) -> u128 {
+        self.frozen_credits(friend_public_key, currency)
+            .map(FrozenCredits::total)
+            .unwrap_or(0)
+    }
+
+    /// Credits frozen to a friend in a currency, by origin.
+    /// Returns None if no credits are frozen to the friend in this currency.
+    pub fn frozen_credits(
+        &self,
+        friend_public_key: &PublicKey,
+        currency: &Currency,
+    ) -> Option<&FrozenCredits> {
+        self.frozen
+            .get(&(friend_public_key.clone(), currency.clone()))
+    }
+
+    /// Amount of requests currently frozen
+    pub fn num_requests(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// Can `opt_origin` freeze more credits to a friend in a currency?
+    /// `None` stands for requests originated by the local node.
+    ///
+    /// An origin that already froze more credits to the friend than all the other origins
+    /// together may not freeze more credits, until some of its requests are resolved. This way a
+    /// single origin can not keep growing its share of the credits frozen to a friend while other
+    /// origins send requests through the same friend.
+    ///
+    /// Only requests of remote origins are checked: Requests of the local node are not limited by
+    /// the share of the other origins (They are limited by the max outflow of the friend), but
+    /// still count toward the share of the other origins.
+    pub fn may_freeze(
+        &self,
+        friend_public_key: &PublicKey,
+        currency: &Currency,
+        opt_origin: &Option<PublicKey>,
+    ) -> bool {
+        let frozen_credits = match self.frozen_credits(friend_public_key, currency) {
+            Some(frozen_credits) => frozen_credits,
+            None => return true,
+        };
+        let from_origin = frozen_credits.from_origin(opt_origin);
+        let from_others = frozen_credits.total().saturating_sub(from_origin);
+        from_others == 0 || from_origin <= from_others
+    }
+}
+
+fn frozen_request(
+    friend_public_key: &PublicKey,
+    currency: &Currency,
+    pending_transaction: &PendingTransaction,
+    request_origins: &RequestOrigins,
+) -> FreezeGuardMutation {
+    // The origin may have sent us the request in a different currency (An exchange):
+    let opt_origin = request_origins
+        .get_origin(&pending_transaction.request_id)
+        .map(|(origin_public_key, _origin_currency)| origin_public_key.clone());
+
+    FreezeGuardMutation::AddFrozen((
+        pending_transaction.request_id.clone(),
+        FrozenRequest {
+            friend_public_key: friend_public_key.clone(),
+            currency: currency.clone(),
+            opt_origin,
+            // A pending transaction freezes its payment and fees:
+            credits: pending_transaction
+                .dest_payment
+                .saturating_add(pending_transaction.left_fees),
+        },
+    ))
+}
+
+/// Freeze all the local pending transactions of a token channel.
+fn token_channel_freezes<B>(
+    friend_public_key: &PublicKey,
+    token_channel: &TokenChannel<B>,
+    request_origins: &RequestOrigins,
+) -> Vec<FreezeGuardMutation>
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let mut mutations = Vec::new();
+    for (currency, mutual_credit) in token_channel.get_mutual_credits() {
+        for pending_transaction in mutual_credit.state().pending_transactions.local.values() {
+            mutations.push(frozen_request(
+                friend_public_key,
+                currency,
+                pending_transaction,
+                request_origins,
+            ));
+        }
+    }
+    mutations
+}
+
+/// Calculate the changes to the index caused by a funder mutation.
+/// `request_origins` must already include the changes caused by `funder_mutation`.
+pub fn funder_mutation_to_freeze_guard_mutations<B>(
+    funder_mutation: &FunderMutation<B>,
+    request_origins: &RequestOrigins,
+) -> Vec<FreezeGuardMutation>
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    match funder_mutation {
+        FunderMutation::FriendMutation((friend_public_key, friend_mutation)) => {
+            match friend_mutation {
+                FriendMutation::TcMutation(TcMutation::McMutation((currency, mc_mutation))) => {
+                    match mc_mutation {
+                        McMutation::InsertLocalPendingTransaction(pending_transaction) => {
+                            vec![frozen_request(
+                                friend_public_key,
+                                currency,
+                                pending_transaction,
+                                request_origins,
+                            )]
+                        }
+                        McMutation::RemoveLocalPendingTransaction(request_id) => {
+                            vec![FreezeGuardMutation::SubFrozen(request_id.clone())]
+                        }
+                        _ => Vec::new(),
+                    }
+                }
+                FriendMutation::SetInconsistent(_) => {
+                    vec![FreezeGuardMutation::RemoveFriend(friend_public_key.clone())]
+                }
+                FriendMutation::SetConsistent(token_channel) => {
+                    let mut mutations =
+                        vec![FreezeGuardMutation::RemoveFriend(friend_public_key.clone())];
+                    mutations.extend(token_channel_freezes(
+                        friend_public_key,
+                        token_channel,
+                        request_origins,
+                    ));
+                    mutations
+                }
+                _ => Vec::new(),
+            }
+        }
+        FunderMutation::RemoveFriend(friend_public_key) => {
+            vec![FreezeGuardMutation::RemoveFriend(friend_public_key.clone())]
+        }
+        FunderMutation::RotateFriendKey(key_rotation) => {
+            vec![FreezeGuardMutation::ReplaceFriend((
+                key_rotation.old_public_key.clone(),
+                key_rotation.new_public_key.clone(),
+            ))]
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    fn frozen_request_to(
+        friend_public_key: &PublicKey,
+        currency: &Currency,
+        opt_origin: Option<&PublicKey>,
+        credits: u128,
+    ) -> FrozenRequest {
+        FrozenRequest {
+            friend_public_key: friend_public_key.clone(),
+            currency: currency.clone(),
+            opt_origin: opt_origin.cloned(),
+            credits,
+        }
+    }
+
+    #[test]
+    fn test_freeze_guard_basic() {
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let pk_c = PublicKey::from(&[0xcc; PublicKey::len()]);
+        let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+        let currency2 = Currency::try_from("FST2".to_owned()).unwrap();
+
+        let mut freeze_guard = FreezeGuard::new();
+        assert_eq!(freeze_guard.frozen(&pk_b, &currency1), 0);
+
+        // Two requests from a, and one request of our own, all sent to b:
+        freeze_guard.mutate(&FreezeGuardMutation::AddFrozen((
+            Uid::from(&[1; Uid::len()]),
+            frozen_request_to(&pk_b, &currency1, Some(&pk_a), 10),
+        )));
+        freeze_guard.mutate(&FreezeGuardMutation::AddFrozen((
+            Uid::from(&[2; Uid::len()]),
+            frozen_request_to(&pk_b, &currency1, Some(&pk_a), 15),
+        )));
+        freeze_guard.mutate(&FreezeGuardMutation::AddFrozen((
+            Uid::from(&[3; Uid::len()]),
+            frozen_request_to(&pk_b, &currency1, None, 20),
+        )));
+        // A request in another currency:
+        freeze_guard.mutate(&FreezeGuardMutation::AddFrozen((
+            Uid::from(&[4; Uid::len()]),
+            frozen_request_to(&pk_b, &currency2, Some(&pk_a), 7),
+        )));
+
+        assert_eq!(freeze_guard.frozen(&pk_b, &currency1), 45);
+        assert_eq!(freeze_guard.frozen(&pk_b, &currency2), 7);
+        let frozen_credits = freeze_guard.frozen_credits(&pk_b, &currency1).unwrap();
+        assert_eq!(frozen_credits.from_origin(&Some(pk_a.clone())), 25);
+        assert_eq!(frozen_credits.from_origin(&None), 20);
+        assert_eq!(frozen_credits.max_origin(), Some((&Some(pk_a.clone()), 25)));
+
+        freeze_guard.mutate(&FreezeGuardMutation::SubFrozen(Uid::from(&[2; Uid::len()])));
+        let frozen_credits = freeze_guard.frozen_credits(&pk_b, &currency1).unwrap();
+        assert_eq!(frozen_credits.total(), 30);
+        assert_eq!(frozen_credits.max_origin(), Some((&None, 20)));
+
+        // Removing an unknown request has no effect:
+        freeze_guard.mutate(&FreezeGuardMutation::SubFrozen(Uid::from(&[2; Uid::len()])));
+        assert_eq!(freeze_guard.frozen(&pk_b, &currency1), 30);
+
+        // a rotates its key:
+        freeze_guard.mutate(&FreezeGuardMutation::ReplaceFriend((
+            pk_a.clone(),
+            pk_c.clone(),
+        )));
+        let frozen_credits = freeze_guard.frozen_credits(&pk_b, &currency1).unwrap();
+        assert_eq!(frozen_credits.from_origin(&Some(pk_a.clone())), 0);
+        assert_eq!(frozen_credits.from_origin(&Some(pk_c.clone())), 10);
+
+        // b is removed. All the requests sent to b are forgotten:
+        freeze_guard.mutate(&FreezeGuardMutation::RemoveFriend(pk_b.clone()));
+        assert!(freeze_guard.frozen_credits(&pk_b, &currency1).is_none());
+        assert!(freeze_guard.frozen_credits(&pk_b, &currency2).is_none());
+        assert_eq!(freeze_guard.num_requests(), 0);
+    }
+
+    #[test]
+    fn test_freeze_guard_may_freeze() {
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let pk_c = PublicKey::from(&[0xcc; PublicKey::len()]);
+        let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+
+        let mut freeze_guard = FreezeGuard::new();
+        assert!(freeze_guard.may_freeze(&pk_b, &currency1, &Some(pk_a.clone())));
+
+        // a is the only origin that froze credits to b. a may keep freezing credits:
+        freeze_guard.mutate(&FreezeGuardMutation::AddFrozen((
+            Uid::from(&[1; Uid::len()]),
+            frozen_request_to(&pk_b, &currency1, Some(&pk_a), 30),
+        )));
+        assert!(freeze_guard.may_freeze(&pk_b, &currency1, &Some(pk_a.clone())));
+
+        // c and the local node froze less credits than a:
+        freeze_guard.mutate(&FreezeGuardMutation::AddFrozen((
+            Uid::from(&[2; Uid::len()]),
+            frozen_request_to(&pk_b, &currency1, Some(&pk_c), 10),
+        )));
+        freeze_guard.mutate(&FreezeGuardMutation::AddFrozen((
+            Uid::from(&[3; Uid::len()]),
+            frozen_request_to(&pk_b, &currency1, None, 15),
+        )));
+        assert!(!freeze_guard.may_freeze(&pk_b, &currency1, &Some(pk_a.clone())));
+        assert!(freeze_guard.may_freeze(&pk_b, &currency1, &Some(pk_c.clone())));
+        assert!(freeze_guard.may_freeze(&pk_b, &currency1, &None));
+
+        // The other origins catch up with a:
+        freeze_guard.mutate(&FreezeGuardMutation::AddFrozen((
+            Uid::from(&[4; Uid::len()]),
+            frozen_request_to(&pk_b, &currency1, Some(&pk_c), 5),
+        )));
+        assert!(freeze_guard.may_freeze(&pk_b, &currency1, &Some(pk_a.clone())));
+
+        // The request of the local node is resolved. a froze more than the others again:
+        freeze_guard.mutate(&FreezeGuardMutation::SubFrozen(Uid::from(&[3; Uid::len()])));
+        assert!(!freeze_guard.may_freeze(&pk_b, &currency1, &Some(pk_a.clone())));
+        assert!(freeze_guard.may_freeze(&pk_b, &currency1, &Some(pk_c.clone())));
+    }
+}
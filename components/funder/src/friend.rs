@@ -2,26 +2,49 @@ use im::vector::Vector as ImVec;
 use std::collections::HashMap as ImHashMap;
 use std::fmt::Debug;
 
+use common::int_convert::usize_to_u64;
 use common::ser_utils::{ser_b64, ser_map_str_any, ser_string};
 
 use signature::canonical::CanonicalSerialize;
+use signature::signature_buff::{LEGACY_SIGNATURE_BUFF_VERSION, SIGNATURE_BUFF_VERSION};
 
 use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
-use proto::crypto::PublicKey;
+use proto::consts::{FRIEND_PROTOCOL_VERSION, MAX_FRAME_LENGTH};
+use proto::crypto::{PublicKey, Uid};
 use proto::funder::messages::{
-    CancelSendFundsOp, CollectSendFundsOp, Currency, FriendStatus, Rate, RequestSendFundsOp,
+    friend_features, CancelSendFundsOp, CollectSendFundsOp, Currency, FriendCapabilities,
+    FriendStatus, FriendTcOp, KeyRotation, MaxOutflow, Rate, RefundSendFundsOp, RequestSendFundsOp,
     ResetTerms, ResponseSendFundsOp,
 };
+use proto::report::messages::{ClosingStatement, DisputeEvidence};
 
 use crate::token_channel::{TcMutation, TokenChannel};
 use crate::types::MoveTokenHashed;
 
-/// Any operation that goes backwards (With respect to the initial request)
+/// Optional friend protocol features supported by this implementation.
+/// (See `proto::funder::messages::friend_features`)
+pub const LOCAL_FRIEND_FEATURES: u64 = friend_features::CURRENCY_EXCHANGE | friend_features::CHANGE;
+
+/// Any operation that goes backwards (With respect to the initial request).
+/// Refunds are the exception: They go forward, but just like the backwards operations they only
+/// resolve existing pending transactions.
 #[derive(Arbitrary, Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
 pub enum BackwardsOp {
     Response(ResponseSendFundsOp),
     Cancel(CancelSendFundsOp),
     Collect(CollectSendFundsOp),
+    Refund(RefundSendFundsOp),
+}
+
+impl BackwardsOp {
+    pub fn request_id(&self) -> &Uid {
+        match self {
+            BackwardsOp::Response(response_send_funds) => &response_send_funds.request_id,
+            BackwardsOp::Cancel(cancel_send_funds) => &cancel_send_funds.request_id,
+            BackwardsOp::Collect(collect_send_funds) => &collect_send_funds.request_id,
+            BackwardsOp::Refund(refund_send_funds) => &refund_send_funds.request_id,
+        }
+    }
 }
 
 #[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -68,6 +91,9 @@ pub struct ChannelInconsistent {
     pub opt_last_incoming_move_token: Option<MoveTokenHashed>,
     pub local_reset_terms: ResetTerms,
     pub opt_remote_reset_terms: Option<ResetTerms>,
+    /// The state of the channel right before it became inconsistent
+    #[serde(default)]
+    pub dispute_evidence: DisputeEvidence,
 }
 
 #[derive(Arbitrary, PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
@@ -121,6 +147,34 @@ pub struct CurrencyConfig {
     pub remote_max_debt: u128,
     /// Can new requests be sent through the mutual credit with this friend?
     pub is_open: bool,
+    /// Limit over the credits sent to this friend during a window of time
+    pub opt_max_outflow: Option<MaxOutflow>,
+}
+
+/// Progress of closing the channel with a friend gracefully (See `FunderControl::DrainFriend`).
+#[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum DrainStatus {
+    Active,
+    /// New requests are refused. We wait for all the pending requests to settle.
+    Draining,
+    /// All pending requests were settled, and no new requests will be accepted.
+    Closed(ClosingStatement),
+}
+
+impl Default for DrainStatus {
+    fn default() -> Self {
+        DrainStatus::Active
+    }
+}
+
+impl DrainStatus {
+    /// Are new requests accepted through this friend?
+    pub fn is_active(&self) -> bool {
+        match self {
+            DrainStatus::Active => true,
+            DrainStatus::Draining | DrainStatus::Closed(_) => false,
+        }
+    }
 }
 
 #[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -147,6 +201,15 @@ pub struct FriendState<B: Clone> {
     pub status: FriendStatus,
     /// Mutual credit channel information
     pub channel_status: ChannelStatus<B>,
+    /// The last capabilities we have received from the remote friend.
+    /// None if the friend has never sent its capabilities.
+    pub opt_remote_capabilities: Option<FriendCapabilities>,
+    /// The last key rotation of the remote friend. Proves that move tokens signed by the previous
+    /// key of the friend were sent by this friend.
+    pub opt_remote_key_rotation: Option<KeyRotation>,
+    /// Are we closing the channel with this friend?
+    #[serde(default)]
+    pub drain_status: DrainStatus,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -161,15 +224,19 @@ pub enum FriendMutation<B: Clone> {
     PopFrontPendingRequest,
     PushBackPendingBackwardsOp((Currency, BackwardsOp)),
     PopFrontPendingBackwardsOp,
+    RemovePendingBackwardsOps(Uid),
     PushBackPendingUserRequest((Currency, RequestSendFundsOp)),
     PopFrontPendingUserRequest,
     RemovePendingRequestsCurrency(Currency),
     RemovePendingUserRequestsCurrency(Currency),
     RemovePendingRequests,
+    RemovePendingRequest(Uid),
     SetStatus(FriendStatus),
     SetRemoteRelays(Vec<RelayAddress<B>>),
     SetName(String),
     SetSentLocalRelays(SentLocalRelays<B>),
+    SetRemoteCapabilities(FriendCapabilities),
+    SetDrainStatus(DrainStatus),
 }
 
 impl CurrencyConfig {
@@ -178,6 +245,7 @@ impl CurrencyConfig {
             rate: Rate::new(),
             remote_max_debt: 0,
             is_open: false,
+            opt_max_outflow: None,
         }
     }
 }
@@ -208,6 +276,87 @@ where
             currency_configs: ImHashMap::new(),
             status: FriendStatus::Disabled,
             channel_status: ChannelStatus::Consistent(channel_consistent),
+            opt_remote_capabilities: None,
+            opt_remote_key_rotation: None,
+            drain_status: DrainStatus::Active,
+        }
+    }
+
+    /// Replace the public key of one of the sides, after that side has rotated its key.
+    /// The token channel is kept: Move tokens created from now on contain the new key.
+    pub fn rotate_key(&mut self, key_rotation: &KeyRotation) {
+        let KeyRotation {
+            old_public_key,
+            new_public_key,
+            ..
+        } = key_rotation;
+
+        if &self.local_public_key == old_public_key {
+            self.local_public_key = new_public_key.clone();
+        }
+        if &self.remote_public_key == old_public_key {
+            self.remote_public_key = new_public_key.clone();
+            self.opt_remote_key_rotation = Some(key_rotation.clone());
+        }
+        if let ChannelStatus::Consistent(channel_consistent) = &mut self.channel_status {
+            channel_consistent
+                .token_channel
+                .rotate_key(old_public_key, new_public_key);
+        }
+    }
+
+    /// Our capabilities with respect to this friend
+    pub fn local_capabilities(&self) -> FriendCapabilities {
+        let mut currencies = self.currency_configs.keys().cloned().collect::<Vec<_>>();
+        currencies.sort();
+        FriendCapabilities {
+            currencies,
+            features: LOCAL_FRIEND_FEATURES,
+            max_message_size: usize_to_u64(MAX_FRAME_LENGTH).unwrap(),
+            protocol_version: FRIEND_PROTOCOL_VERSION,
+        }
+    }
+
+    /// Capabilities supported by both sides.
+    /// Returns None if the remote friend has never sent its capabilities. In that case no optional
+    /// feature may be used.
+    pub fn common_capabilities(&self) -> Option<FriendCapabilities> {
+        self.opt_remote_capabilities
+            .as_ref()
+            .map(|remote_capabilities| self.local_capabilities().intersect(remote_capabilities))
+    }
+
+    /// Layout of the signed buffers of the move tokens we send to this friend.
+    /// Friends of the initial friend protocol (Or friends that have never sent their
+    /// capabilities) only verify the legacy layout.
+    pub fn signature_version(&self) -> u32 {
+        match self.common_capabilities() {
+            Some(common_capabilities) if common_capabilities.protocol_version >= 1 => {
+                SIGNATURE_BUFF_VERSION
+            }
+            _ => LEGACY_SIGNATURE_BUFF_VERSION,
+        }
+    }
+
+    /// Can responses with change (See `ResponseSendFundsOp::change`) be sent to this friend?
+    /// Change was added in version 1 of the friend protocol, as an optional feature.
+    pub fn supports_change(&self) -> bool {
+        match self.common_capabilities() {
+            Some(common_capabilities) => {
+                common_capabilities.protocol_version >= 1
+                    && common_capabilities.supports(friend_features::CHANGE)
+            }
+            None => false,
+        }
+    }
+
+    /// Can `operation` be sent to this friend?
+    /// If the remote friend has never sent its capabilities, only operations of the initial
+    /// friend protocol (Requiring no optional features) may be sent.
+    pub fn supports_op(&self, operation: &FriendTcOp) -> bool {
+        match self.common_capabilities() {
+            Some(common_capabilities) => common_capabilities.supports_op(operation),
+            None => operation.min_protocol_version() == 0 && operation.required_features() == 0,
         }
     }
 
@@ -313,6 +462,16 @@ where
                     unreachable!();
                 }
             }
+            FriendMutation::RemovePendingBackwardsOps(request_id) => {
+                // Remove all the queued operations of a single request:
+                if let ChannelStatus::Consistent(channel_consistent) = &mut self.channel_status {
+                    channel_consistent
+                        .pending_backwards_ops
+                        .retain(|(_, backwards_op)| backwards_op.request_id() != request_id);
+                } else {
+                    unreachable!();
+                }
+            }
             FriendMutation::PushBackPendingUserRequest((currency, request_send_funds)) => {
                 if let ChannelStatus::Consistent(channel_consistent) = &mut self.channel_status {
                     channel_consistent
@@ -357,6 +516,19 @@ where
                     unreachable!();
                 }
             }
+            FriendMutation::RemovePendingRequest(request_id) => {
+                // Remove a single request (Forwarded or originated by the user) from the queues:
+                if let ChannelStatus::Consistent(channel_consistent) = &mut self.channel_status {
+                    channel_consistent
+                        .pending_requests
+                        .retain(|(_, request)| &request.request_id != request_id);
+                    channel_consistent
+                        .pending_user_requests
+                        .retain(|(_, request)| &request.request_id != request_id);
+                } else {
+                    unreachable!();
+                }
+            }
             FriendMutation::SetStatus(friend_status) => {
                 self.status = friend_status.clone();
             }
@@ -369,6 +541,12 @@ where
             FriendMutation::SetSentLocalRelays(sent_local_relays) => {
                 self.sent_local_relays = sent_local_relays.clone();
             }
+            FriendMutation::SetRemoteCapabilities(remote_capabilities) => {
+                self.opt_remote_capabilities = Some(remote_capabilities.clone());
+            }
+            FriendMutation::SetDrainStatus(drain_status) => {
+                self.drain_status = drain_status.clone();
+            }
         }
     }
 }
@@ -3,19 +3,24 @@ use std::hash::Hash;
 
 use futures::channel::mpsc;
 use futures::stream::select;
-use futures::{future, stream, SinkExt, StreamExt};
+use futures::{future, stream, SinkExt, Stream, StreamExt};
 
 use signature::canonical::CanonicalSerialize;
 
 use crypto::rand::CryptoRandom;
-use identity::IdentityClient;
+use identity::SignatureBackend;
 
 use database::DatabaseClient;
+use timer::TimerTick;
 
-use proto::funder::messages::{FunderIncomingControl, FunderOutgoingControl};
+use proto::funder::messages::{
+    FunderControl, FunderIncomingControl, FunderOutgoingControl, SetFunderConfig,
+};
 
 use crate::ephemeral::Ephemeral;
+use crate::freeze_guard::FreezeGuard;
 use crate::handler::funder_handle_message;
+use crate::request_origins::RequestOrigins;
 use crate::state::{FunderMutation, FunderState};
 use crate::types::{FunderIncoming, FunderIncomingComm, FunderOutgoingComm};
 
@@ -36,23 +41,31 @@ pub enum FunderEvent<B> {
     IncomingCommClosed,
 }
 
-pub async fn inner_funder_loop<B, R>(
-    mut identity_client: IdentityClient,
+pub async fn inner_funder_loop<B, SB, R, TS>(
+    mut identity_client: SB,
     rng: R,
     incoming_control: mpsc::Receiver<FunderIncomingControl<B>>,
     incoming_comm: mpsc::Receiver<FunderIncomingComm<B>>,
+    timer_stream: TS,
     control_sender: mpsc::Sender<FunderOutgoingControl<B>>,
     comm_sender: mpsc::Sender<FunderOutgoingComm<B>>,
     mut funder_state: FunderState<B>,
     mut db_client: DatabaseClient<FunderMutation<B>>,
-    max_operations_in_batch: usize,
+    mut max_operations_in_batch: usize,
     max_node_relays: usize,
-    max_pending_user_requests: usize,
+    mut max_pending_user_requests: usize,
+    max_route_len: usize,
+    max_transaction_retries: u64,
+    request_expiry_ticks: u64,
+    min_friend_uptime_percent: u8,
+    opt_auto_reset_tolerance: Option<u128>,
     mut opt_event_sender: Option<mpsc::Sender<FunderEvent<B>>>,
 ) -> Result<(), FunderError>
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug + Hash,
+    SB: SignatureBackend,
     R: CryptoRandom + 'static,
+    TS: Stream<Item = TimerTick> + Unpin + Send,
 {
     // Transform error type:
     let mut comm_sender = comm_sender.sink_map_err(|_| ());
@@ -60,6 +73,9 @@ where
 
     // let mut db_runner = DbRunner::new(atomic_db);
     let mut ephemeral = Ephemeral::new();
+    // Requests that were pending when the funder was last stopped:
+    ephemeral.request_origins = RequestOrigins::from_state(&funder_state);
+    ephemeral.freeze_guard = FreezeGuard::from_state(&funder_state, &ephemeral.request_origins);
 
     // Select over all possible events:
     let incoming_control = incoming_control
@@ -74,11 +90,18 @@ where
             FunderEvent::FunderIncoming(FunderIncoming::Comm(incoming_comm_msg))
         })
         .chain(stream::once(future::ready(FunderEvent::IncomingCommClosed)));
+    // Ticks are only used for invoices and requests expiry, and for sampling the uptime of friends.
+    // If the timer stream is closed, invoices and requests simply do not expire, and friends are
+    // never gated.
+    let timer_stream = timer_stream.map(|_| FunderEvent::FunderIncoming(FunderIncoming::TimerTick));
     // Chain the Init message first:
     let mut incoming_messages = stream::once(future::ready(FunderEvent::FunderIncoming(
         FunderIncoming::Init,
     )))
-    .chain(select(incoming_control, incoming_comm));
+    .chain(select(
+        incoming_control,
+        select(incoming_comm, timer_stream),
+    ));
 
     while let Some(funder_event) = incoming_messages.next().await {
         // Read one message from incoming messages:
@@ -88,6 +111,21 @@ where
             FunderEvent::FunderIncoming(funder_incoming) => funder_incoming,
         };
 
+        // Configuration changes are applied here, as they affect the handling of all the
+        // following messages:
+        if let FunderIncoming::Control(FunderIncomingControl {
+            funder_control: FunderControl::SetConfig(set_funder_config),
+            ..
+        }) = &funder_incoming
+        {
+            match set_funder_config {
+                SetFunderConfig::MaxOperationsInBatch(value) => max_operations_in_batch = *value,
+                SetFunderConfig::MaxPendingUserRequests(value) => {
+                    max_pending_user_requests = *value
+                }
+            }
+        }
+
         let res = funder_handle_message(
             &mut identity_client,
             &rng,
@@ -96,6 +134,11 @@ where
             max_node_relays,
             max_operations_in_batch,
             max_pending_user_requests,
+            max_route_len,
+            max_transaction_retries,
+            request_expiry_ticks,
+            min_friend_uptime_percent,
+            opt_auto_reset_tolerance,
             funder_incoming,
         )
         .await;
@@ -148,28 +191,37 @@ where
     Ok(())
 }
 
-pub async fn funder_loop<B, R>(
-    identity_client: IdentityClient,
+pub async fn funder_loop<B, SB, R, TS>(
+    identity_client: SB,
     rng: R,
     incoming_control: mpsc::Receiver<FunderIncomingControl<B>>,
     incoming_comm: mpsc::Receiver<FunderIncomingComm<B>>,
+    timer_stream: TS,
     control_sender: mpsc::Sender<FunderOutgoingControl<B>>,
     comm_sender: mpsc::Sender<FunderOutgoingComm<B>>,
     max_operations_in_batch: usize,
     max_node_relays: usize,
     max_pending_user_requests: usize,
+    max_route_len: usize,
+    max_transaction_retries: u64,
+    request_expiry_ticks: u64,
+    min_friend_uptime_percent: u8,
+    opt_auto_reset_tolerance: Option<u128>,
     funder_state: FunderState<B>,
     db_client: DatabaseClient<FunderMutation<B>>,
 ) -> Result<(), FunderError>
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug + Hash,
+    SB: SignatureBackend,
     R: CryptoRandom + 'static,
+    TS: Stream<Item = TimerTick> + Unpin + Send,
 {
     inner_funder_loop(
         identity_client,
         rng,
         incoming_control,
         incoming_comm,
+        timer_stream,
         control_sender,
         comm_sender,
         funder_state,
@@ -177,6 +229,11 @@ where
         max_operations_in_batch,
         max_node_relays,
         max_pending_user_requests,
+        max_route_len,
+        max_transaction_retries,
+        request_expiry_ticks,
+        min_friend_uptime_percent,
+        opt_auto_reset_tolerance,
         None,
     )
     .await
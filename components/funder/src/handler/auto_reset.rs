@@ -0,0 +1,122 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crypto::identity::compare_public_key;
+
+use proto::crypto::PublicKey;
+use proto::funder::messages::ResetTerms;
+
+/// Check if we should accept the reset terms proposed by a friend without asking the user.
+///
+/// The proposal is accepted if, for every currency, the balance proposed by the friend differs
+/// from our own balance by at most `tolerance` credits. Both sides must propose the same set of
+/// currencies.
+///
+/// Only the side with the lower public key accepts automatically. If both sides accepted at the
+/// same time, each side would reset the channel using the other side's terms, causing a new
+/// inconsistency. The other side waits for the reset move token, which is signed and verified
+/// like any other move token.
+pub fn should_auto_reset(
+    local_public_key: &PublicKey,
+    remote_public_key: &PublicKey,
+    local_reset_terms: &ResetTerms,
+    remote_reset_terms: &ResetTerms,
+    tolerance: u128,
+) -> bool {
+    if compare_public_key(local_public_key, remote_public_key) != Ordering::Less {
+        return false;
+    }
+
+    if local_reset_terms.balance_for_reset.len() != remote_reset_terms.balance_for_reset.len() {
+        return false;
+    }
+
+    let local_balances = local_reset_terms
+        .balance_for_reset
+        .iter()
+        .map(|currency_balance| (&currency_balance.currency, currency_balance.balance))
+        .collect::<HashMap<_, _>>();
+
+    remote_reset_terms
+        .balance_for_reset
+        .iter()
+        .all(|currency_balance| {
+            let local_balance = match local_balances.get(&currency_balance.currency) {
+                Some(local_balance) => *local_balance,
+                None => return false,
+            };
+            // The remote balance is measured from the remote side's point of view, so we expect
+            // the two balances to sum to zero:
+            match local_balance.checked_add(currency_balance.balance) {
+                Some(diff) => diff
+                    .checked_abs()
+                    .map_or(false, |diff| diff as u128 <= tolerance),
+                None => false,
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use proto::crypto::Signature;
+    use proto::funder::messages::{Currency, CurrencyBalance};
+
+    fn reset_terms(balances: &[(&str, i128)]) -> ResetTerms {
+        ResetTerms {
+            reset_token: Signature::from(&[0; Signature::len()]),
+            inconsistency_counter: 1,
+            balance_for_reset: balances
+                .iter()
+                .map(|(currency, balance)| CurrencyBalance {
+                    currency: Currency::try_from((*currency).to_owned()).unwrap(),
+                    balance: *balance,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_should_auto_reset() {
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let (low_pk, high_pk) = if compare_public_key(&pk_a, &pk_b) == Ordering::Less {
+            (pk_a, pk_b)
+        } else {
+            (pk_b, pk_a)
+        };
+
+        let local = reset_terms(&[("FST1", 100), ("FST2", -20)]);
+
+        // Exact match:
+        let remote = reset_terms(&[("FST2", 20), ("FST1", -100)]);
+        assert!(should_auto_reset(&low_pk, &high_pk, &local, &remote, 0));
+        // Only the side with the lower public key accepts automatically:
+        assert!(!should_auto_reset(&high_pk, &low_pk, &local, &remote, 0));
+
+        // Within tolerance:
+        let remote = reset_terms(&[("FST1", -103), ("FST2", 18)]);
+        assert!(!should_auto_reset(&low_pk, &high_pk, &local, &remote, 2));
+        assert!(should_auto_reset(&low_pk, &high_pk, &local, &remote, 3));
+
+        // Different currencies:
+        let remote = reset_terms(&[("FST1", -100)]);
+        assert!(!should_auto_reset(&low_pk, &high_pk, &local, &remote, 0));
+        let remote = reset_terms(&[("FST1", -100), ("FST3", 20)]);
+        assert!(!should_auto_reset(&low_pk, &high_pk, &local, &remote, 0));
+
+        // Overflow:
+        let local = reset_terms(&[("FST1", i128::max_value())]);
+        let remote = reset_terms(&[("FST1", i128::max_value())]);
+        assert!(!should_auto_reset(
+            &low_pk,
+            &high_pk,
+            &local,
+            &remote,
+            u128::max_value()
+        ));
+    }
+}
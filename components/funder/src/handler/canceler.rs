@@ -6,8 +6,9 @@ use crypto::rand::{CryptoRandom, RandGen};
 
 use proto::crypto::{PublicKey, Uid};
 use proto::funder::messages::{
-    Currency, FunderOutgoingControl, PaymentStatus, PaymentStatusSuccess, RequestResult,
-    RequestSendFundsOp, ResponseClosePayment, TransactionResult,
+    Currency, FriendTcOp, FunderOutgoingControl, PaymentStatus, PaymentStatusSuccess,
+    RequestAlternativeRoute, RequestResult, RequestSendFundsOp, ResponseClosePayment,
+    TransactionResult,
 };
 
 use crate::handler::state_wrap::MutableFunderState;
@@ -15,8 +16,11 @@ use crate::handler::types::SendCommands;
 use crate::handler::utils::find_request_origin;
 
 use crate::friend::{BackwardsOp, ChannelStatus, FriendMutation};
-use crate::state::{FunderMutation, Payment, PaymentStage};
-use crate::types::{create_cancel_send_funds, create_pending_transaction};
+use crate::state::{FunderMutation, Payment, PaymentStage, PendingRetry};
+use crate::types::{
+    create_cancel_send_funds, create_pending_transaction, create_refund_send_funds,
+    create_request_send_funds,
+};
 
 #[derive(Debug)]
 pub enum CurrencyChoice {
@@ -47,6 +51,89 @@ pub fn reply_with_cancel<B>(
     send_commands.set_try_send(remote_public_key);
 }
 
+/// Check if a refund of a request is already queued to be sent to `friend_public_key`.
+pub fn is_refund_queued<B>(
+    m_state: &MutableFunderState<B>,
+    friend_public_key: &PublicKey,
+    request_id: &Uid,
+) -> bool
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let friend = if let Some(friend) = m_state.state().friends.get(friend_public_key) {
+        friend
+    } else {
+        return false;
+    };
+    match &friend.channel_status {
+        ChannelStatus::Inconsistent(_) => false,
+        ChannelStatus::Consistent(channel_consistent) => channel_consistent
+            .pending_backwards_ops
+            .iter()
+            .any(|(_, backwards_op)| match backwards_op {
+                BackwardsOp::Refund(refund_send_funds) => {
+                    &refund_send_funds.request_id == request_id
+                }
+                _ => false,
+            }),
+    }
+}
+
+/// Refund a request that we have sent to `friend_public_key`, releasing the credits frozen for
+/// it. Does nothing if the request is not pending with this friend, or if a refund was already
+/// queued.
+pub fn refund_request<B>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    friend_public_key: &PublicKey,
+    currency: &Currency,
+    request_id: &Uid,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let friend = if let Some(friend) = m_state.state().friends.get(friend_public_key) {
+        friend
+    } else {
+        return;
+    };
+    let is_pending = match &friend.channel_status {
+        ChannelStatus::Inconsistent(_) => false,
+        ChannelStatus::Consistent(channel_consistent) => channel_consistent
+            .token_channel
+            .get_mutual_credits()
+            .get(currency)
+            .map(|mutual_credit| {
+                mutual_credit
+                    .state()
+                    .pending_transactions
+                    .local
+                    .contains_key(request_id)
+            })
+            .unwrap_or(false),
+    };
+    if !is_pending || is_refund_queued(m_state, friend_public_key, request_id) {
+        return;
+    }
+
+    let refund_send_funds = create_refund_send_funds(request_id.clone());
+    // A friend that does not support refunds resolves the request the usual way:
+    if !friend.supports_op(&FriendTcOp::RefundSendFunds(refund_send_funds.clone())) {
+        warn!(
+            "refund_request(): Friend {:?} does not support refunds",
+            friend_public_key
+        );
+        return;
+    }
+    let friend_mutation = FriendMutation::PushBackPendingBackwardsOp((
+        currency.clone(),
+        BackwardsOp::Refund(refund_send_funds),
+    ));
+    let funder_mutation =
+        FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+    send_commands.set_try_send(friend_public_key);
+}
+
 /// Remove a local transaction (Where this node is the buyer side)
 pub fn remove_transaction<B, R>(
     m_state: &mut MutableFunderState<B>,
@@ -161,6 +248,100 @@ pub fn remove_transaction<B, R>(
     m_state.mutate(funder_mutation);
 }
 
+/// Handle a failure of a transaction that was originated by this node.
+/// If the transaction may still be retried, we keep it open and ask for an alternative route.
+/// Otherwise, we report the failure to the user and remove the transaction.
+pub fn fail_local_transaction<B, R>(
+    m_state: &mut MutableFunderState<B>,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    rng: &R,
+    friend_public_key: &PublicKey,
+    currency: &Currency,
+    request_send_funds: &RequestSendFundsOp,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+    R: CryptoRandom,
+{
+    let request_id = &request_send_funds.request_id;
+
+    // TODO: Can this unwrap() ever panic?
+    let open_transaction = m_state.state().open_transactions.get(request_id).unwrap();
+
+    // TODO: Can this unwrap() ever panic?
+    let payment = m_state
+        .state()
+        .payments
+        .get(&open_transaction.payment_id)
+        .unwrap();
+
+    // There is no point in retrying if the payment was already completed:
+    let payment_open = match payment.stage {
+        PaymentStage::NewTransactions(_) | PaymentStage::InProgress(_) => true,
+        PaymentStage::Success(..)
+        | PaymentStage::Canceled(_)
+        | PaymentStage::AfterSuccessAck(_) => false,
+    };
+
+    if open_transaction.retries_left == 0 || !payment_open {
+        let transaction_result = TransactionResult {
+            request_id: request_id.clone(),
+            result: RequestResult::Failure,
+        };
+        outgoing_control.push(FunderOutgoingControl::TransactionResult(transaction_result));
+        remove_transaction(m_state, outgoing_control, rng, request_id);
+        return;
+    }
+
+    // Reconstruct the full route (The request only contains the tail of the route):
+    let mut full_request_send_funds = request_send_funds.clone();
+    full_request_send_funds
+        .route
+        .public_keys
+        .insert(0, friend_public_key.clone());
+    full_request_send_funds
+        .route
+        .public_keys
+        .insert(0, m_state.state().local_public_key.clone());
+
+    let pending_retry = PendingRetry {
+        currency: currency.clone(),
+        request_send_funds: full_request_send_funds,
+    };
+    request_alternative_route(outgoing_control, rng, &pending_retry);
+
+    let funder_mutation =
+        FunderMutation::SetTransactionPendingRetry((request_id.clone(), pending_retry));
+    m_state.mutate(funder_mutation);
+}
+
+/// Ask for an alternative route for a transaction that is waiting to be retried.
+pub fn request_alternative_route<B, R>(
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    rng: &R,
+    pending_retry: &PendingRetry,
+) where
+    B: Clone,
+    R: CryptoRandom,
+{
+    let request_send_funds = &pending_retry.request_send_funds;
+    // The route of a pending retry starts with the local node, followed by the friend that
+    // reported the failure. The last public key on the route is the destination:
+    let public_keys = &request_send_funds.route.public_keys;
+
+    let request_alternative_route = RequestAlternativeRoute {
+        request_id: request_send_funds.request_id.clone(),
+        routes_request_id: Uid::rand_gen(rng),
+        currency: pending_retry.currency.clone(),
+        dest_public_key: public_keys.last().unwrap().clone(),
+        dest_payment: request_send_funds.dest_payment,
+        max_fees: request_send_funds.left_fees,
+        exclude_friend: public_keys[1].clone(),
+    };
+    outgoing_control.push(FunderOutgoingControl::RequestAlternativeRoute(
+        request_alternative_route,
+    ));
+}
+
 /// Cancel outgoing local requests that are already inside the token channel (Possibly already
 /// communicated to the remote side). This is a violent operation, as we break our promises for
 /// forwarded requests. This should only be done during unfriending.
@@ -189,16 +370,15 @@ pub fn cancel_local_pending_transactions<B, R>(
 
         // Prepare a list of all remote requests that we need to cancel:
         for (local_request_id, pending_local_transaction) in pending_local_transactions {
-            let opt_origin_public_key =
-                find_request_origin(m_state.state(), &currency, &local_request_id).cloned();
-            match opt_origin_public_key {
-                Some(origin_public_key) => {
+            let opt_origin = find_request_origin(m_state, &currency, &local_request_id);
+            match opt_origin {
+                Some((origin_public_key, origin_currency)) => {
                     // We have found the friend that is the origin of this request.
                     // We send him a cancel message.
                     let cancel_send_funds =
                         create_cancel_send_funds(pending_local_transaction.request_id);
                     let friend_mutation = FriendMutation::PushBackPendingBackwardsOp((
-                        currency.clone(),
+                        origin_currency,
                         BackwardsOp::Cancel(cancel_send_funds),
                     ));
                     let funder_mutation = FunderMutation::FriendMutation((
@@ -211,18 +391,14 @@ pub fn cancel_local_pending_transactions<B, R>(
                 }
                 None => {
                     // We are the origin of this request.
-                    // We send a cancel message through the control:
-                    let transaction_result = TransactionResult {
-                        request_id: pending_local_transaction.request_id.clone(),
-                        result: RequestResult::Failure,
-                    };
-                    outgoing_control
-                        .push(FunderOutgoingControl::TransactionResult(transaction_result));
-                    remove_transaction(
+                    // We either retry or send a failure message through the control:
+                    fail_local_transaction(
                         m_state,
                         outgoing_control,
                         rng,
-                        &pending_local_transaction.request_id,
+                        friend_public_key,
+                        &currency,
+                        &create_request_send_funds(&pending_local_transaction),
                     );
                 }
             };
@@ -230,26 +406,94 @@ pub fn cancel_local_pending_transactions<B, R>(
     }
 }
 
-/// Cancel a pending request
+/// Cancel all the queued operations that `friend_public_key` can not handle.
+/// Should be called whenever the capabilities of the friend change.
+pub fn cancel_unsupported_operations<B, R>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    rng: &R,
+    friend_public_key: &PublicKey,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+    R: CryptoRandom,
+{
+    let friend = m_state.state().friends.get(friend_public_key).unwrap();
+    let channel_consistent = match &friend.channel_status {
+        ChannelStatus::Inconsistent(_) => return,
+        ChannelStatus::Consistent(channel_consistent) => channel_consistent,
+    };
+
+    let unsupported_requests = channel_consistent
+        .pending_requests
+        .iter()
+        .chain(channel_consistent.pending_user_requests.iter())
+        .filter(|(_, request_send_funds)| {
+            !friend.supports_op(&FriendTcOp::RequestSendFunds(request_send_funds.clone()))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let unsupported_refunds = channel_consistent
+        .pending_backwards_ops
+        .iter()
+        .filter_map(|(_, backwards_op)| match backwards_op {
+            BackwardsOp::Refund(refund_send_funds)
+                if !friend.supports_op(&FriendTcOp::RefundSendFunds(refund_send_funds.clone())) =>
+            {
+                Some(refund_send_funds.request_id.clone())
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    for (currency, request_send_funds) in unsupported_requests {
+        let friend_mutation =
+            FriendMutation::RemovePendingRequest(request_send_funds.request_id.clone());
+        let funder_mutation =
+            FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+        m_state.mutate(funder_mutation);
+
+        cancel_request(
+            m_state,
+            send_commands,
+            outgoing_control,
+            rng,
+            friend_public_key,
+            &currency,
+            &request_send_funds,
+        );
+    }
+
+    // The friend resolves the refunded requests the usual way:
+    for request_id in unsupported_refunds {
+        let friend_mutation = FriendMutation::RemovePendingBackwardsOps(request_id);
+        let funder_mutation =
+            FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+        m_state.mutate(funder_mutation);
+    }
+}
+
+/// Cancel a pending request that was queued to be sent to `friend_public_key`.
 pub fn cancel_request<B, R>(
     m_state: &mut MutableFunderState<B>,
     send_commands: &mut SendCommands,
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
     rng: &R,
+    friend_public_key: &PublicKey,
     currency: &Currency,
     pending_request: &RequestSendFundsOp,
 ) where
     B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
     R: CryptoRandom,
 {
-    let opt_origin_public_key =
-        find_request_origin(m_state.state(), &currency, &pending_request.request_id).cloned();
-    match opt_origin_public_key {
-        Some(origin_public_key) => {
+    let opt_origin = find_request_origin(m_state, &currency, &pending_request.request_id);
+    match opt_origin {
+        Some((origin_public_key, origin_currency)) => {
             let pending_local_transaction = create_pending_transaction(&pending_request);
             let cancel_send_funds = create_cancel_send_funds(pending_local_transaction.request_id);
             let friend_mutation = FriendMutation::PushBackPendingBackwardsOp((
-                currency.clone(),
+                origin_currency,
                 BackwardsOp::Cancel(cancel_send_funds),
             ));
             let funder_mutation =
@@ -259,12 +503,14 @@ pub fn cancel_request<B, R>(
         }
         None => {
             // We are the origin of this request:
-            let transaction_result = TransactionResult {
-                request_id: pending_request.request_id.clone(),
-                result: RequestResult::Failure,
-            };
-            outgoing_control.push(FunderOutgoingControl::TransactionResult(transaction_result));
-            remove_transaction(m_state, outgoing_control, rng, &pending_request.request_id);
+            fail_local_transaction(
+                m_state,
+                outgoing_control,
+                rng,
+                friend_public_key,
+                currency,
+                pending_request,
+            );
         }
     };
 }
@@ -323,6 +569,7 @@ pub fn cancel_pending_requests<B, R>(
             send_commands,
             outgoing_control,
             rng,
+            friend_public_key,
             &currency,
             &pending_request,
         );
@@ -342,6 +589,7 @@ pub fn cancel_pending_requests<B, R>(
             send_commands,
             outgoing_control,
             rng,
+            friend_public_key,
             &currency,
             &pending_user_request,
         );
@@ -401,6 +649,7 @@ pub fn cancel_nonuser_pending_requests<B, R>(
             send_commands,
             outgoing_control,
             rng,
+            friend_public_key,
             &currency,
             &pending_request,
         );
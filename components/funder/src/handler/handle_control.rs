@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::fmt::Debug;
 
 use signature::canonical::CanonicalSerialize;
@@ -7,29 +8,38 @@ use crypto::rand::{CryptoRandom, RandGen};
 
 use proto::crypto::{InvoiceId, PaymentId, PlainLock, PublicKey, Uid};
 
-use crate::friend::{BackwardsOp, ChannelStatus, CurrencyConfig, FriendMutation};
-use crate::state::{FunderMutation, NewTransactions, Payment, PaymentStage};
+use crate::friend::{BackwardsOp, ChannelStatus, CurrencyConfig, DrainStatus, FriendMutation};
+use crate::state::{FunderMutation, NewTransactions, Payment, PaymentStage, PendingRetry};
 
 use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
 use proto::funder::messages::{
     AckClosePayment, AddFriend, AddInvoice, ChannelerUpdateFriend, CollectSendFundsOp, Commit,
-    CreatePayment, CreateTransaction, FriendStatus, FunderControl, FunderOutgoingControl,
-    PaymentStatus, PaymentStatusSuccess, RemoveFriend, RemoveFriendCurrency, RequestResult,
-    RequestSendFundsOp, ResetFriendChannel, ResponseClosePayment, SetFriendCurrencyMaxDebt,
-    SetFriendCurrencyRate, SetFriendCurrencyRequestsStatus, SetFriendName, SetFriendRelays,
-    SetFriendStatus, TransactionResult,
+    CreateExchangeTransaction, CreatePayment, CreateTransaction, Currency, CurrencyExchange,
+    DrainFriend, FriendStatus, FriendTcOp, FriendsRoute, FunderControl, FunderOutgoingControl,
+    KeyRotation, PaymentStatus, PaymentStatusSuccess, Rebalance, RemoveFriend,
+    RemoveFriendCurrency, RequestErrorCode, RequestResult, RequestSendFundsOp, ResetFriendChannel,
+    ResponseClosePayment, RetryTransaction, SetExchangeRate, SetFriendCurrencyMaxDebt,
+    SetFriendCurrencyRate, SetFriendCurrencyRequestsStatus, SetFriendMaxOutflow, SetFriendName,
+    SetFriendRelays, SetFriendStatus, TransactionRejection, TransactionResult,
 };
-use signature::verify::verify_commit;
+use signature::verify::{verify_commit, verify_key_rotation};
 
-use crate::ephemeral::Ephemeral;
+use crate::channel_proof::export_channel_proof;
+use crate::ephemeral::EphemeralMutation;
+use crate::exposure::calc_exposure;
 use crate::handler::canceler::{
     cancel_local_pending_transactions, cancel_nonuser_pending_requests, cancel_pending_requests,
-    reply_with_cancel, CurrencyChoice,
+    remove_transaction, reply_with_cancel, CurrencyChoice,
 };
 use crate::handler::prepare::prepare_commit;
+use crate::handler::quote::calc_quote;
 use crate::handler::state_wrap::{MutableEphemeral, MutableFunderState};
 use crate::handler::types::SendCommands;
-use crate::handler::utils::{find_local_pending_transaction, find_request_origin, is_friend_ready};
+use crate::handler::utils::{
+    charge_outflow, find_local_pending_transaction, find_request_origin, is_friend_ready,
+};
+use crate::invoices::InvoicesMutation;
+use crate::outflows::OutflowsMutation;
 
 use crate::types::ChannelerConfig;
 
@@ -57,6 +67,80 @@ pub enum HandleControlError {
     FriendCurrencyDoesNotExist,
     CanNotRemoveActiveCurrency,
     CurrencyNotConfigured,
+    TransactionDoesNotExist,
+    NoPendingRetry,
+    NoAlternativeRoute,
+    MaxOutflowExceeded,
+    InvalidKeyRotation,
+    InvalidExchangeRate,
+    /// The friend is draining (or closed), and may not accept new requests.
+    FriendDraining,
+    /// The friend does not support the requested operation
+    UnsupportedByFriend,
+    /// The transaction violates the limits set for outgoing transactions.
+    /// The reason is reported back to the user.
+    TransactionRejected(TransactionRejection),
+}
+
+/// The result reported to the user for a transaction that could not be sent
+fn failure_request_result(e: &HandleControlError) -> RequestResult {
+    match e {
+        HandleControlError::TransactionRejected(transaction_rejection) => {
+            RequestResult::Rejected(transaction_rejection.clone())
+        }
+        _ => RequestResult::Failure,
+    }
+}
+
+/// The error code reported to the app that issued a control request that could not be handled
+pub fn request_error_code(e: &HandleControlError) -> RequestErrorCode {
+    match e {
+        HandleControlError::FriendDoesNotExist => RequestErrorCode::FriendNotFound,
+        HandleControlError::FriendCurrencyDoesNotExist
+        | HandleControlError::CurrencyNotConfigured => RequestErrorCode::CurrencyNotFound,
+        HandleControlError::OpenPaymentNotFound
+        | HandleControlError::PaymentDoesNotExist
+        | HandleControlError::InvoiceDoesNotExist
+        | HandleControlError::TransactionDoesNotExist
+        | HandleControlError::NoPendingRetry
+        | HandleControlError::NoAlternativeRoute => RequestErrorCode::NotFound,
+        HandleControlError::RequestAlreadyInProgress
+        | HandleControlError::PaymentAlreadyOpen
+        | HandleControlError::InvoiceAlreadyExists => RequestErrorCode::AlreadyExists,
+        HandleControlError::PendingUserRequestsFull
+        | HandleControlError::MaxNodeRelaysReached
+        | HandleControlError::MaxOutflowExceeded => RequestErrorCode::LimitReached,
+        HandleControlError::NotInvitedToReset
+        | HandleControlError::FriendNotReady
+        | HandleControlError::NewTransactionsNotAllowed
+        | HandleControlError::CanNotRemoveActiveCurrency
+        | HandleControlError::FriendDraining
+        | HandleControlError::UnsupportedByFriend => RequestErrorCode::NotReady,
+        HandleControlError::ResetTokenMismatch
+        | HandleControlError::NotFirstInRoute
+        | HandleControlError::PaymentDestNotLastInRoute
+        | HandleControlError::InvalidRoute
+        | HandleControlError::AckStateInvalid
+        | HandleControlError::AckMismatch
+        | HandleControlError::InvalidCommit
+        | HandleControlError::InvalidKeyRotation
+        | HandleControlError::InvalidExchangeRate => RequestErrorCode::InvalidRequest,
+        HandleControlError::TransactionRejected(transaction_rejection) => {
+            RequestErrorCode::Rejected(transaction_rejection.clone())
+        }
+    }
+}
+
+/// Reject routes that are longer than `max_route_len`
+fn check_route_len(route: &FriendsRoute, max_route_len: usize) -> Result<(), HandleControlError> {
+    if route.len() > max_route_len {
+        return Err(HandleControlError::TransactionRejected(
+            TransactionRejection::RouteTooLong(
+                u32::try_from(max_route_len).unwrap_or(u32::max_value()),
+            ),
+        ));
+    }
+    Ok(())
 }
 
 fn control_set_friend_currency_max_debt<B>(
@@ -103,6 +187,55 @@ where
     Ok(())
 }
 
+fn control_set_friend_max_outflow<B>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    set_friend_max_outflow: SetFriendMaxOutflow,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    // Make sure that friend exists:
+    let friend = m_state
+        .state()
+        .friends
+        .get(&set_friend_max_outflow.friend_public_key)
+        .ok_or(HandleControlError::FriendDoesNotExist)?;
+
+    // If the newly proposed limit is the same as the old one, we do nothing:
+    let mut new_currency_config = if let Some(currency_config) = friend
+        .currency_configs
+        .get(&set_friend_max_outflow.currency)
+    {
+        if currency_config.opt_max_outflow == set_friend_max_outflow.opt_max_outflow {
+            return Ok(());
+        }
+        currency_config.clone()
+    } else {
+        CurrencyConfig::new()
+    };
+
+    new_currency_config.opt_max_outflow = set_friend_max_outflow.opt_max_outflow;
+
+    let friend_mutation = FriendMutation::UpdateCurrencyConfig((
+        set_friend_max_outflow.currency.clone(),
+        new_currency_config,
+    ));
+    let funder_mutation = FunderMutation::FriendMutation((
+        set_friend_max_outflow.friend_public_key.clone(),
+        friend_mutation,
+    ));
+    m_state.mutate(funder_mutation);
+
+    // A new limit starts with a new window:
+    let outflows_mutation = OutflowsMutation::Remove((
+        set_friend_max_outflow.friend_public_key,
+        set_friend_max_outflow.currency,
+    ));
+    m_ephemeral.mutate(EphemeralMutation::OutflowsMutation(outflows_mutation));
+    Ok(())
+}
+
 fn control_reset_friend_channel<B>(
     m_state: &mut MutableFunderState<B>,
     send_commands: &mut SendCommands,
@@ -395,6 +528,11 @@ where
         CurrencyConfig::new()
     };
 
+    // A draining friend stays closed for new requests:
+    if set_friend_currency_requests_status.status.is_open() && !friend.drain_status.is_active() {
+        return Err(HandleControlError::FriendDraining);
+    }
+
     // If remote requests were previously open, and now they were closed:
     if !set_friend_currency_requests_status.status.is_open() {
         // Cancel all messages pending for this friend with this currency.
@@ -430,6 +568,70 @@ where
     Ok(())
 }
 
+/// Start closing the channel with a friend gracefully: New requests are not accepted through the
+/// friend anymore, and queued requests are canceled. The channel is closed once all the pending
+/// transactions with the friend are settled (See `tick_drains`).
+fn control_drain_friend<B, R>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    rng: &R,
+    drain_friend: DrainFriend,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+    R: CryptoRandom,
+{
+    let friend_public_key = drain_friend.friend_public_key;
+    let friend = m_state
+        .state()
+        .friends
+        .get(&friend_public_key)
+        .ok_or(HandleControlError::FriendDoesNotExist)?;
+
+    // Draining was already started:
+    if !friend.drain_status.is_active() {
+        return Ok(());
+    }
+
+    // Balances can only be settled over a consistent channel:
+    if let ChannelStatus::Inconsistent(_) = &friend.channel_status {
+        return Err(HandleControlError::FriendNotReady);
+    }
+
+    // Close all currencies for new requests coming from the friend:
+    let currency_configs = friend.currency_configs.clone();
+    for (currency, mut currency_config) in currency_configs {
+        if !currency_config.is_open {
+            continue;
+        }
+        currency_config.is_open = false;
+        let friend_mutation = FriendMutation::UpdateCurrencyConfig((currency, currency_config));
+        let funder_mutation =
+            FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+        m_state.mutate(funder_mutation);
+    }
+
+    // Cancel all the requests that were not yet sent to the friend, including requests of the
+    // local user:
+    cancel_pending_requests(
+        m_state,
+        send_commands,
+        outgoing_control,
+        rng,
+        &friend_public_key,
+        &CurrencyChoice::All,
+    );
+
+    let friend_mutation = FriendMutation::SetDrainStatus(DrainStatus::Draining);
+    let funder_mutation =
+        FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+
+    send_commands.set_try_send(&friend_public_key);
+    Ok(())
+}
+
 fn control_set_friend_relays<B>(
     m_state: &mut MutableFunderState<B>,
     outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
@@ -622,6 +824,7 @@ where
         currency: create_payment.currency.clone(),
         total_dest_payment: create_payment.total_dest_payment,
         dest_public_key: create_payment.dest_public_key.clone(),
+        opt_max_fee_per_hop: create_payment.opt_max_fee_per_hop,
     });
 
     let payment = Payment {
@@ -636,12 +839,19 @@ where
     Ok(())
 }
 
+/// `opt_exchange` contains the currency of the channel with the first friend on the route, and the
+/// credits (In that currency) given to the node that exchanges the request to the currency of the
+/// payment. None if the request is sent in the currency of the payment.
 fn control_create_transaction_inner<B>(
     m_state: &mut MutableFunderState<B>,
-    ephemeral: &Ephemeral,
+    m_ephemeral: &mut MutableEphemeral,
     send_commands: &mut SendCommands,
     max_pending_user_requests: usize,
+    max_route_len: usize,
+    max_transaction_retries: u64,
+    request_expiry_ticks: u64,
     create_transaction: CreateTransaction,
+    opt_exchange: Option<(Currency, u128)>,
 ) -> Result<(), HandleControlError>
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
@@ -679,6 +889,20 @@ where
     if !route.is_valid() {
         return Err(HandleControlError::InvalidRoute);
     }
+
+    check_route_len(route, max_route_len)?;
+
+    // Every node on the route, except for us and the destination, may charge fees:
+    if let Some(max_fee_per_hop) = new_transactions.opt_max_fee_per_hop {
+        let num_mediators = route.len().saturating_sub(2);
+        let max_fees = max_fee_per_hop.saturating_mul(num_mediators as u128);
+        if create_transaction.fees > max_fees {
+            return Err(HandleControlError::TransactionRejected(
+                TransactionRejection::FeesExceedCap(max_fees),
+            ));
+        }
+    }
+
     let friend_public_key = route.public_keys[1].clone();
 
     let friend = match m_state.state().friends.get(&friend_public_key) {
@@ -686,9 +910,34 @@ where
         None => Err(HandleControlError::FriendDoesNotExist),
     }?;
 
-    let currency = new_transactions.currency.clone();
+    let (currency, dest_payment, total_dest_payment, opt_exchange) = match opt_exchange {
+        Some((currency, exchanged_payment)) => {
+            let currency_exchange = CurrencyExchange {
+                dest_currency: new_transactions.currency.clone(),
+                dest_payment: create_transaction.dest_payment,
+                total_dest_payment: new_transactions.total_dest_payment,
+            };
+            (
+                currency,
+                exchanged_payment,
+                exchanged_payment,
+                Some(currency_exchange),
+            )
+        }
+        None => (
+            new_transactions.currency.clone(),
+            create_transaction.dest_payment,
+            new_transactions.total_dest_payment,
+            None,
+        ),
+    };
 
-    if !is_friend_ready(m_state.state(), ephemeral, &friend_public_key, &currency) {
+    if !is_friend_ready(
+        m_state.state(),
+        m_ephemeral.ephemeral(),
+        &friend_public_key,
+        &currency,
+    ) {
         return Err(HandleControlError::FriendNotReady);
     }
 
@@ -731,10 +980,53 @@ where
         return Err(HandleControlError::PendingUserRequestsFull);
     }
 
+    let mut route_tail = create_transaction.route;
+    // Remove ourselves from the remaining route:
+    route_tail.public_keys.remove(0);
+    // Remove next node from the route:
+    route_tail.public_keys.remove(0);
+
+    let request_send_funds = RequestSendFundsOp {
+        request_id: create_transaction.request_id,
+        src_hashed_lock: src_plain_lock.hash_lock(),
+        route: route_tail,
+        dest_payment,
+        total_dest_payment,
+        invoice_id: new_transactions.invoice_id.clone(),
+        left_fees: create_transaction.fees,
+        expiry_ticks: request_expiry_ticks,
+        refund_ticks: create_transaction.opt_refund_ticks.unwrap_or(0),
+        opt_exchange,
+    };
+
+    // The friend must be able to handle the request (For example, a request with a refund
+    // countdown may only be sent to a friend that supports refunds):
+    let pending_op = FriendTcOp::RequestSendFunds(request_send_funds.clone());
+    if !m_state
+        .state()
+        .friends
+        .get(&friend_public_key)
+        .unwrap()
+        .supports_op(&pending_op)
+    {
+        return Err(HandleControlError::UnsupportedByFriend);
+    }
+
+    if !charge_outflow(
+        m_state.state(),
+        m_ephemeral,
+        &friend_public_key,
+        &currency,
+        &request_send_funds,
+    ) {
+        return Err(HandleControlError::MaxOutflowExceeded);
+    }
+
     // Keep PlainLock:
     let funder_mutation = FunderMutation::AddTransaction((
-        create_transaction.request_id.clone(),
+        request_send_funds.request_id.clone(),
         create_transaction.payment_id.clone(),
+        max_transaction_retries,
     ));
     m_state.mutate(funder_mutation);
 
@@ -751,23 +1043,7 @@ where
     let funder_mutation = FunderMutation::UpdatePayment((create_transaction.payment_id, payment));
     m_state.mutate(funder_mutation);
 
-    let mut route_tail = create_transaction.route;
-    // Remove ourselves from the remaining route:
-    route_tail.public_keys.remove(0);
-    // Remove next node from the route:
-    route_tail.public_keys.remove(0);
-
     // Push the request:
-    let request_send_funds = RequestSendFundsOp {
-        request_id: create_transaction.request_id,
-        src_hashed_lock: src_plain_lock.hash_lock(),
-        route: route_tail,
-        dest_payment: create_transaction.dest_payment,
-        total_dest_payment: new_transactions.total_dest_payment,
-        invoice_id: new_transactions.invoice_id,
-        left_fees: create_transaction.fees,
-    };
-
     let friend_mutation =
         FriendMutation::PushBackPendingUserRequest((currency, request_send_funds));
     let funder_mutation =
@@ -781,11 +1057,15 @@ where
 
 fn control_create_transaction<B>(
     m_state: &mut MutableFunderState<B>,
-    ephemeral: &Ephemeral,
+    m_ephemeral: &mut MutableEphemeral,
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
     send_commands: &mut SendCommands,
     max_pending_user_requests: usize,
+    max_route_len: usize,
+    max_transaction_retries: u64,
+    request_expiry_ticks: u64,
     create_transaction: CreateTransaction,
+    opt_exchange: Option<(Currency, u128)>,
 ) -> Result<(), HandleControlError>
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
@@ -803,6 +1083,12 @@ where
         return Err(HandleControlError::NewTransactionsNotAllowed);
     };
 
+    // The currency of the channel the transaction is sent through:
+    let currency = match &opt_exchange {
+        Some((currency, _exchanged_payment)) => currency.clone(),
+        None => new_transactions.currency.clone(),
+    };
+
     // If we already have this transaction:
     // - If we have a ready response, we return a Commit message.
     // - Else, we do nothing.
@@ -818,13 +1104,13 @@ where
             &open_transaction.opt_response,
             find_local_pending_transaction(
                 m_state.state(),
-                &new_transactions.currency,
+                &currency,
                 &create_transaction.request_id,
             ),
         ) {
             let transaction_result = if response_send_funds.is_complete {
                 let commit = prepare_commit(
-                    new_transactions.currency.clone(),
+                    currency.clone(),
                     response_send_funds,
                     pending_transaction,
                     payment.src_plain_lock.clone(),
@@ -849,15 +1135,19 @@ where
     // Otherwise, we return the internal error and return a response failure message.
     if let Err(e) = control_create_transaction_inner(
         m_state,
-        ephemeral,
+        m_ephemeral,
         send_commands,
         max_pending_user_requests,
+        max_route_len,
+        max_transaction_retries,
+        request_expiry_ticks,
         create_transaction.clone(),
+        opt_exchange,
     ) {
         error!("control_create_transaction_inner() failed: {:?}", e);
         let transaction_result = TransactionResult {
             request_id: create_transaction.request_id,
-            result: RequestResult::Failure,
+            result: failure_request_result(&e),
         };
 
         outgoing_control.push(FunderOutgoingControl::TransactionResult(transaction_result));
@@ -868,6 +1158,322 @@ where
     Ok(())
 }
 
+fn control_create_exchange_transaction<B>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    send_commands: &mut SendCommands,
+    max_pending_user_requests: usize,
+    max_route_len: usize,
+    request_expiry_ticks: u64,
+    create_exchange_transaction: CreateExchangeTransaction,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let create_transaction = CreateTransaction {
+        payment_id: create_exchange_transaction.payment_id,
+        request_id: create_exchange_transaction.request_id,
+        route: create_exchange_transaction.route,
+        dest_payment: create_exchange_transaction.dest_payment,
+        fees: create_exchange_transaction.fees,
+        opt_refund_ticks: Some(create_exchange_transaction.refund_ticks),
+    };
+
+    // Exchange transactions are never retried: An alternative route would have to pass through
+    // another exchanging node, possibly with another rate.
+    control_create_transaction(
+        m_state,
+        m_ephemeral,
+        outgoing_control,
+        send_commands,
+        max_pending_user_requests,
+        max_route_len,
+        0,
+        request_expiry_ticks,
+        create_transaction,
+        Some((
+            create_exchange_transaction.currency,
+            create_exchange_transaction.exchanged_payment,
+        )),
+    )
+}
+
+/// Move credits between two friends by paying ourselves along a cycle route.
+/// An invoice and a payment are opened for the self payment, and the invoice is committed by
+/// ourselves once the transaction completes (See `handle_response_send_funds`).
+fn control_rebalance<B, R>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    send_commands: &mut SendCommands,
+    rng: &R,
+    max_pending_user_requests: usize,
+    max_route_len: usize,
+    max_transaction_retries: u64,
+    request_expiry_ticks: u64,
+    rebalance: Rebalance,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+    R: CryptoRandom,
+{
+    let local_public_key = m_state.state().local_public_key.clone();
+
+    // The route must start and end with us, passing through at least two friends:
+    let public_keys = &rebalance.route.public_keys;
+    if public_keys.len() < 4
+        || public_keys.first() != Some(&local_public_key)
+        || public_keys.last() != Some(&local_public_key)
+    {
+        return Err(HandleControlError::InvalidRoute);
+    }
+
+    // Checked before anything is opened, so that a failure leaves no leftovers:
+    if m_state
+        .state()
+        .open_invoices
+        .contains_key(&rebalance.invoice_id)
+    {
+        return Err(HandleControlError::InvoiceAlreadyExists);
+    }
+    if m_state.state().payments.contains_key(&rebalance.payment_id) {
+        return Err(HandleControlError::PaymentAlreadyOpen);
+    }
+
+    let add_invoice = AddInvoice {
+        invoice_id: rebalance.invoice_id.clone(),
+        currency: rebalance.currency.clone(),
+        total_dest_payment: rebalance.amount,
+        opt_expiry_ticks: None,
+    };
+    control_add_invoice(m_state, m_ephemeral, rng, add_invoice)?;
+
+    let create_payment = CreatePayment {
+        payment_id: rebalance.payment_id.clone(),
+        invoice_id: rebalance.invoice_id,
+        currency: rebalance.currency,
+        total_dest_payment: rebalance.amount,
+        dest_public_key: local_public_key,
+        opt_max_fee_per_hop: None,
+    };
+    control_create_payment(m_state, rng, create_payment)?;
+
+    let create_transaction = CreateTransaction {
+        payment_id: rebalance.payment_id,
+        request_id: rebalance.request_id,
+        route: rebalance.route,
+        dest_payment: rebalance.amount,
+        fees: rebalance.fees,
+        opt_refund_ticks: None,
+    };
+    control_create_transaction(
+        m_state,
+        m_ephemeral,
+        outgoing_control,
+        send_commands,
+        max_pending_user_requests,
+        max_route_len,
+        max_transaction_retries,
+        request_expiry_ticks,
+        create_transaction,
+        None,
+    )
+}
+
+/// Queue a failed transaction again, through an alternative route.
+fn control_retry_transaction_inner<B>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    send_commands: &mut SendCommands,
+    max_pending_user_requests: usize,
+    max_route_len: usize,
+    request_expiry_ticks: u64,
+    pending_retry: PendingRetry,
+    opt_route: Option<(FriendsRoute, u128)>,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let (route, fees) = opt_route.ok_or(HandleControlError::NoAlternativeRoute)?;
+
+    let PendingRetry {
+        currency,
+        request_send_funds,
+    } = pending_retry;
+
+    // We have to be the first on the route:
+    match route.public_keys.first() {
+        Some(first) if *first == m_state.state().local_public_key => Ok(()),
+        _ => Err(HandleControlError::NotFirstInRoute),
+    }?;
+
+    // The alternative route must lead to the same destination:
+    match (
+        route.public_keys.last(),
+        request_send_funds.route.public_keys.last(),
+    ) {
+        (Some(last), Some(dest)) if last == dest => Ok(()),
+        _ => Err(HandleControlError::PaymentDestNotLastInRoute),
+    }?;
+
+    if !route.is_valid() {
+        return Err(HandleControlError::InvalidRoute);
+    }
+
+    check_route_len(&route, max_route_len)?;
+
+    // We may not pay more fees than were approved for the original route:
+    if fees > request_send_funds.left_fees {
+        return Err(HandleControlError::TransactionRejected(
+            TransactionRejection::FeesExceedCap(request_send_funds.left_fees),
+        ));
+    }
+
+    let friend_public_key = route.public_keys[1].clone();
+
+    let friend = match m_state.state().friends.get(&friend_public_key) {
+        Some(friend) => Ok(friend),
+        None => Err(HandleControlError::FriendDoesNotExist),
+    }?;
+
+    if !is_friend_ready(
+        m_state.state(),
+        m_ephemeral.ephemeral(),
+        &friend_public_key,
+        &currency,
+    ) {
+        return Err(HandleControlError::FriendNotReady);
+    }
+
+    let channel_consistent = match &friend.channel_status {
+        ChannelStatus::Inconsistent(_) => unreachable!(),
+        ChannelStatus::Consistent(channel_consistent) => channel_consistent,
+    };
+
+    // Make sure that the previous attempt is not still pending with this friend:
+    if channel_consistent
+        .token_channel
+        .get_mutual_credits()
+        .get(&currency)
+        .ok_or(HandleControlError::FriendCurrencyDoesNotExist)?
+        .state()
+        .pending_transactions
+        .local
+        .contains_key(&request_send_funds.request_id)
+    {
+        return Err(HandleControlError::RequestAlreadyInProgress);
+    }
+
+    // Check if we have room to push this message:
+    if channel_consistent.pending_user_requests.len() >= max_pending_user_requests {
+        return Err(HandleControlError::PendingUserRequestsFull);
+    }
+
+    let mut route_tail = route;
+    // Remove ourselves from the remaining route:
+    route_tail.public_keys.remove(0);
+    // Remove next node from the route:
+    route_tail.public_keys.remove(0);
+
+    // Push the request again, through the new route, with the fees of the new route.
+    // This is a new attempt, so the request gets its full amount of expiry ticks again.
+    // (The refund ticks were never decreased, as we are the origin of the request):
+    let request_send_funds = RequestSendFundsOp {
+        route: route_tail,
+        left_fees: fees,
+        expiry_ticks: request_expiry_ticks,
+        ..request_send_funds
+    };
+
+    // The friend must be able to handle the request (For example, a request with a refund
+    // countdown may only be sent to a friend that supports refunds):
+    let pending_op = FriendTcOp::RequestSendFunds(request_send_funds.clone());
+    if !m_state
+        .state()
+        .friends
+        .get(&friend_public_key)
+        .unwrap()
+        .supports_op(&pending_op)
+    {
+        return Err(HandleControlError::UnsupportedByFriend);
+    }
+
+    if !charge_outflow(
+        m_state.state(),
+        m_ephemeral,
+        &friend_public_key,
+        &currency,
+        &request_send_funds,
+    ) {
+        return Err(HandleControlError::MaxOutflowExceeded);
+    }
+
+    let friend_mutation =
+        FriendMutation::PushBackPendingUserRequest((currency, request_send_funds));
+    let funder_mutation =
+        FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+
+    // Signal the sender to attempt to send:
+    send_commands.set_try_send(&friend_public_key);
+    Ok(())
+}
+
+fn control_retry_transaction<B, R>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    send_commands: &mut SendCommands,
+    rng: &R,
+    max_pending_user_requests: usize,
+    max_route_len: usize,
+    request_expiry_ticks: u64,
+    retry_transaction: RetryTransaction,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+    R: CryptoRandom,
+{
+    let request_id = retry_transaction.request_id;
+
+    let open_transaction = m_state
+        .state()
+        .open_transactions
+        .get(&request_id)
+        .ok_or(HandleControlError::TransactionDoesNotExist)?;
+
+    let pending_retry = open_transaction
+        .opt_pending_retry
+        .clone()
+        .ok_or(HandleControlError::NoPendingRetry)?;
+
+    let funder_mutation = FunderMutation::ClearTransactionPendingRetry(request_id.clone());
+    m_state.mutate(funder_mutation);
+
+    // If we can not retry, the transaction fails:
+    if let Err(e) = control_retry_transaction_inner(
+        m_state,
+        m_ephemeral,
+        send_commands,
+        max_pending_user_requests,
+        max_route_len,
+        request_expiry_ticks,
+        pending_retry,
+        retry_transaction.opt_route,
+    ) {
+        warn!("control_retry_transaction_inner() failed: {:?}", e);
+        let transaction_result = TransactionResult {
+            request_id: request_id.clone(),
+            result: failure_request_result(&e),
+        };
+        outgoing_control.push(FunderOutgoingControl::TransactionResult(transaction_result));
+        remove_transaction(m_state, outgoing_control, rng, &request_id);
+    }
+
+    Ok(())
+}
+
 fn control_request_close_payment<B, R>(
     m_state: &mut MutableFunderState<B>,
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
@@ -1012,6 +1618,7 @@ where
 
 fn control_add_invoice<B, R>(
     m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
     rng: &R,
     add_invoice: AddInvoice,
 ) -> Result<(), HandleControlError>
@@ -1030,19 +1637,27 @@ where
     // Randomly generate a lock. We only reveal this lock when sending the Collect message.
     let dest_plain_lock = PlainLock::rand_gen(rng);
 
+    // Start the expiry countdown:
+    if let Some(expiry_ticks) = add_invoice.opt_expiry_ticks {
+        let invoices_mutation =
+            InvoicesMutation::SetTicksLeft((add_invoice.invoice_id.clone(), expiry_ticks));
+        m_ephemeral.mutate(EphemeralMutation::InvoicesMutation(invoices_mutation));
+    }
+
     // Add new invoice:
     let funder_mutation = FunderMutation::AddInvoice((
         add_invoice.invoice_id,
         add_invoice.currency,
         add_invoice.total_dest_payment,
         dest_plain_lock,
+        add_invoice.opt_expiry_ticks,
     ));
     m_state.mutate(funder_mutation);
 
     Ok(())
 }
 
-fn control_cancel_invoice<B>(
+pub fn control_cancel_invoice<B>(
     m_state: &mut MutableFunderState<B>,
     send_commands: &mut SendCommands,
     invoice_id: InvoiceId,
@@ -1062,10 +1677,8 @@ where
         // Explaining the unwrap() below:
         // We expect that the origin of this request must be from an existing friend.
         // We can not be the originator of this request.
-        let friend_public_key =
-            find_request_origin(m_state.state(), &open_invoice.currency, &request_id)
-                .unwrap()
-                .clone();
+        let (friend_public_key, _currency) =
+            find_request_origin(m_state, &open_invoice.currency, &request_id).unwrap();
         reply_with_cancel(
             m_state,
             send_commands,
@@ -1082,7 +1695,7 @@ where
     Ok(())
 }
 
-fn control_commit_invoice<B>(
+pub fn control_commit_invoice<B>(
     m_state: &mut MutableFunderState<B>,
     send_commands: &mut SendCommands,
     commit: &Commit,
@@ -1109,10 +1722,10 @@ where
 
     // Push collect messages for all pending requests
     for request_id in &open_invoice.incoming_transactions {
-        let friend_public_key = if let Some(friend_public_key) =
-            find_request_origin(m_state.state(), &open_invoice.currency, request_id)
+        let friend_public_key = if let Some((friend_public_key, _currency)) =
+            find_request_origin(m_state, &open_invoice.currency, request_id)
         {
-            friend_public_key.clone()
+            friend_public_key
         } else {
             warn!("control_commit_invoice(): Failed to find request origin");
             continue;
@@ -1143,6 +1756,58 @@ where
     Ok(())
 }
 
+/// Announce a new identity key to all friends.
+/// The new key is used after the node restarts with the new identity. Until then, we keep
+/// announcing the key rotation every time a friend connects, and avoid creating new move tokens.
+fn control_set_exchange_rate<B>(
+    m_state: &mut MutableFunderState<B>,
+    set_exchange_rate: SetExchangeRate,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    if set_exchange_rate.from_currency == set_exchange_rate.to_currency {
+        return Err(HandleControlError::InvalidExchangeRate);
+    }
+    if let Some(rate) = &set_exchange_rate.opt_rate {
+        if rate.from_credits == 0 {
+            return Err(HandleControlError::InvalidExchangeRate);
+        }
+    }
+
+    // Requests already forwarded keep the terms they were exchanged by:
+    let funder_mutation = FunderMutation::SetExchangeRate((
+        set_exchange_rate.from_currency,
+        set_exchange_rate.to_currency,
+        set_exchange_rate.opt_rate,
+    ));
+    m_state.mutate(funder_mutation);
+    Ok(())
+}
+
+fn control_rotate_key<B>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    key_rotation: KeyRotation,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    if key_rotation.old_public_key != m_state.state().local_public_key
+        || !verify_key_rotation(&key_rotation)
+    {
+        return Err(HandleControlError::InvalidKeyRotation);
+    }
+
+    m_state.mutate(FunderMutation::SetKeyRotation(key_rotation));
+
+    let friend_public_keys = m_state.state().friends.keys().cloned().collect::<Vec<_>>();
+    for friend_public_key in &friend_public_keys {
+        send_commands.set_send_key_rotation(friend_public_key);
+    }
+    Ok(())
+}
+
 pub fn handle_control_message<B, R>(
     m_state: &mut MutableFunderState<B>,
     m_ephemeral: &mut MutableEphemeral,
@@ -1152,6 +1817,9 @@ pub fn handle_control_message<B, R>(
     rng: &R,
     max_node_relays: usize,
     max_pending_user_requests: usize,
+    max_route_len: usize,
+    max_transaction_retries: u64,
+    request_expiry_ticks: u64,
     incoming_control: FunderControl<B>,
 ) -> Result<(), HandleControlError>
 where
@@ -1167,10 +1835,18 @@ where
             )
         }
 
+        FunderControl::SetFriendMaxOutflow(set_friend_max_outflow) => {
+            control_set_friend_max_outflow(m_state, m_ephemeral, set_friend_max_outflow)
+        }
+
         FunderControl::ResetFriendChannel(reset_friend_channel) => {
             control_reset_friend_channel(m_state, send_commands, reset_friend_channel)
         }
 
+        FunderControl::DrainFriend(drain_friend) => {
+            control_drain_friend(m_state, send_commands, outgoing_control, rng, drain_friend)
+        }
+
         FunderControl::AddRelay(named_relay_address) => control_add_relay(
             m_state,
             send_commands,
@@ -1243,11 +1919,38 @@ where
         }
         FunderControl::CreateTransaction(create_transaction) => control_create_transaction(
             m_state,
-            m_ephemeral.ephemeral(),
+            m_ephemeral,
             outgoing_control,
             send_commands,
             max_pending_user_requests,
+            max_route_len,
+            max_transaction_retries,
+            request_expiry_ticks,
             create_transaction,
+            None,
+        ),
+        FunderControl::CreateExchangeTransaction(create_exchange_transaction) => {
+            control_create_exchange_transaction(
+                m_state,
+                m_ephemeral,
+                outgoing_control,
+                send_commands,
+                max_pending_user_requests,
+                max_route_len,
+                request_expiry_ticks,
+                create_exchange_transaction,
+            )
+        }
+        FunderControl::RetryTransaction(retry_transaction) => control_retry_transaction(
+            m_state,
+            m_ephemeral,
+            outgoing_control,
+            send_commands,
+            rng,
+            max_pending_user_requests,
+            max_route_len,
+            request_expiry_ticks,
+            retry_transaction,
         ),
         FunderControl::RequestClosePayment(payment_id) => {
             control_request_close_payment(m_state, outgoing_control, rng, payment_id)
@@ -1257,12 +1960,69 @@ where
         }
 
         // Seller API:
-        FunderControl::AddInvoice(add_invoice) => control_add_invoice(m_state, rng, add_invoice),
+        FunderControl::AddInvoice(add_invoice) => {
+            control_add_invoice(m_state, m_ephemeral, rng, add_invoice)
+        }
         FunderControl::CancelInvoice(invoice_id) => {
             control_cancel_invoice(m_state, send_commands, invoice_id)
         }
         FunderControl::CommitInvoice(commit) => {
             control_commit_invoice(m_state, send_commands, &commit)
         }
+
+        // Configuration changes are applied by the funder loop. Nothing to do here.
+        FunderControl::SetConfig(_) => Ok(()),
+        FunderControl::SetExchangeRate(set_exchange_rate) => {
+            control_set_exchange_rate(m_state, set_exchange_rate)
+        }
+
+        // Analysis:
+        FunderControl::RequestExposure(request_id) => {
+            let response_exposure = calc_exposure(m_state.state(), request_id);
+            outgoing_control.push(FunderOutgoingControl::ResponseExposure(response_exposure));
+            Ok(())
+        }
+        FunderControl::QuotePayment(quote_payment) => {
+            let response_quote_payment = calc_quote(
+                m_state.state(),
+                m_ephemeral.ephemeral(),
+                max_pending_user_requests,
+                max_route_len,
+                quote_payment,
+            );
+            outgoing_control.push(FunderOutgoingControl::ResponseQuotePayment(
+                response_quote_payment,
+            ));
+            Ok(())
+        }
+
+        // Rebalancing:
+        FunderControl::Rebalance(rebalance) => control_rebalance(
+            m_state,
+            m_ephemeral,
+            outgoing_control,
+            send_commands,
+            rng,
+            max_pending_user_requests,
+            max_route_len,
+            max_transaction_retries,
+            request_expiry_ticks,
+            rebalance,
+        ),
+
+        // Disputes:
+        FunderControl::ExportChannelProof(export_channel_proof_req) => {
+            let response_channel_proof =
+                export_channel_proof(m_state.state(), export_channel_proof_req);
+            outgoing_control.push(FunderOutgoingControl::ResponseChannelProof(
+                response_channel_proof,
+            ));
+            Ok(())
+        }
+
+        // Identity:
+        FunderControl::RotateKey(key_rotation) => {
+            control_rotate_key(m_state, send_commands, key_rotation)
+        }
     }
 }
@@ -5,50 +5,60 @@ use signature::canonical::CanonicalSerialize;
 
 use crypto::rand::{CryptoRandom, RandGen};
 
+use proto::consts::REFUND_TICKS_MARGIN;
 use proto::crypto::{PublicKey, Signature, Uid};
 
 use proto::app_server::messages::RelayAddress;
 use proto::funder::messages::{
     BalanceInfo, CancelSendFundsOp, ChannelerUpdateFriend, CollectSendFundsOp, CountersInfo,
-    Currency, CurrencyBalance, CurrencyBalanceInfo, FriendMessage, FunderOutgoingControl, McInfo,
-    MoveTokenRequest, PaymentStatus, PaymentStatusSuccess, PendingTransaction, RequestResult,
+    Currency, CurrencyBalance, CurrencyBalanceInfo, FriendCapabilities, FriendMessage,
+    FriendStatus, FriendTcOp, FunderOutgoingControl, KeyRotation, McInfo, MoveTokenRequest,
+    PaymentStatus, PaymentStatusSuccess, PendingTransaction, RefundSendFundsOp, RequestResult,
     RequestSendFundsOp, ResetTerms, ResponseClosePayment, ResponseSendFundsOp, TokenInfo,
     TransactionResult,
 };
 use signature::signature_buff::hash_token_info;
-use signature::verify::verify_move_token;
+use signature::verify::{verify_key_rotation, verify_move_token};
 
 use crate::mutual_credit::incoming::{
     IncomingCancelSendFundsOp, IncomingCollectSendFundsOp, IncomingMessage,
-    IncomingResponseSendFundsOp,
+    IncomingRefundSendFundsOp, IncomingResponseSendFundsOp,
 };
 use crate::token_channel::{MoveTokenReceived, ReceiveMoveTokenOutput, TokenChannel};
 
-use crate::types::{create_pending_transaction, ChannelerConfig};
+use crate::types::{
+    create_cancel_send_funds, create_pending_transaction, create_request_send_funds,
+    ChannelerConfig,
+};
 
+use crate::dispute::create_dispute_evidence;
+use crate::ephemeral::EphemeralMutation;
 use crate::friend::{
     BackwardsOp, ChannelInconsistent, ChannelStatus, CurrencyConfig, FriendMutation,
     SentLocalRelays,
 };
+use crate::liveness::LivenessMutation;
 use crate::state::{FunderMutation, FunderState, Payment, PaymentStage};
 
-use crate::ephemeral::Ephemeral;
-
+use crate::handler::auto_reset::should_auto_reset;
 use crate::handler::canceler::{
-    cancel_local_pending_transactions, cancel_pending_requests, remove_transaction,
-    reply_with_cancel, CurrencyChoice,
+    cancel_local_pending_transactions, cancel_pending_requests, cancel_unsupported_operations,
+    fail_local_transaction, is_refund_queued, refund_request, reply_with_cancel, CurrencyChoice,
 };
+use crate::handler::handle_control::control_commit_invoice;
 use crate::handler::prepare::{prepare_commit, prepare_receipt};
 use crate::handler::state_wrap::{MutableEphemeral, MutableFunderState};
 use crate::handler::types::SendCommands;
 use crate::handler::utils::{
-    find_remote_pending_transaction, find_request_origin, is_friend_ready,
+    charge_outflow, find_remote_pending_transaction, find_request_origin, is_friend_ready,
 };
 
 #[derive(Debug)]
 pub enum HandleFriendError {
     FriendDoesNotExist,
     InconsistencyWhenTokenOwned,
+    InvalidKeyRotation,
+    KeyRotationConflict,
 }
 
 /// Generate a random token to be used for resetting the channel.
@@ -215,16 +225,26 @@ fn forward_request<B>(
 
 #[derive(Debug)]
 enum CheckRequest {
-    Complete,
+    /// The invoice is fully paid. Contains the change: The amount paid above total_dest_payment.
+    Complete(u128),
     Success,
     Failure,
 }
 
 /// Check if we can add a request into a local OpenInvoice
-fn check_request<B>(state: &FunderState<B>, request_send_funds: &RequestSendFundsOp) -> CheckRequest
+fn check_request<B>(
+    state: &FunderState<B>,
+    currency: &Currency,
+    request_send_funds: &RequestSendFundsOp,
+) -> CheckRequest
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
 {
+    // A request that was supposed to be exchanged on the way was not exchanged:
+    if request_send_funds.opt_exchange.is_some() {
+        return CheckRequest::Failure;
+    }
+
     // First make sure that we have a matching open invoice for this transaction:
     let open_invoice =
         if let Some(open_invoice) = state.open_invoices.get(&request_send_funds.invoice_id) {
@@ -239,7 +259,9 @@ where
         }
     }
 
-    if open_invoice.total_dest_payment != request_send_funds.total_dest_payment {
+    if &open_invoice.currency != currency
+        || open_invoice.total_dest_payment != request_send_funds.total_dest_payment
+    {
         return CheckRequest::Failure;
     }
     if request_send_funds.dest_payment > request_send_funds.total_dest_payment {
@@ -260,6 +282,11 @@ where
             .unwrap();
     }
 
+    // An invoice that is already fully paid does not accept more transactions:
+    if total_paid >= open_invoice.total_dest_payment {
+        return CheckRequest::Failure;
+    }
+
     // Check if we have room to pay more with the new transaction:
     let new_total_paid =
         if let Some(new_total_paid) = total_paid.checked_add(request_send_funds.dest_payment) {
@@ -273,28 +300,18 @@ where
         // Request is allowed, the invoice is not fully paid:
         Ordering::Less => CheckRequest::Success,
         // Request is allowed, and the invoice is fully paid:
-        Ordering::Equal => CheckRequest::Complete,
-        // We do not allow paying more than total_dest_payment
-        Ordering::Greater => CheckRequest::Failure,
-    }
-
-    /*
-    if new_total_paid < open_invoice.total_dest_payment {
-        // Request is allowed, the invoice is not fully paid:
-        CheckRequest::Success
-    } else if new_total_paid == open_invoice.total_dest_payment {
-        // Request is allowed, and the invoice is fully paid:
-        CheckRequest::Complete
-    } else {
-        // We do not allow paying more than total_dest_payment
-        CheckRequest::Failure
+        Ordering::Equal => CheckRequest::Complete(0),
+        // Request is allowed, the invoice is overpaid.
+        // The overpaid amount is given back to the buyer as change:
+        Ordering::Greater => {
+            CheckRequest::Complete(new_total_paid - open_invoice.total_dest_payment)
+        }
     }
-    */
 }
 
 fn handle_request_send_funds<B>(
     m_state: &mut MutableFunderState<B>,
-    ephemeral: &Ephemeral,
+    m_ephemeral: &mut MutableEphemeral,
     send_commands: &mut SendCommands,
     remote_public_key: &PublicKey,
     currency: &Currency,
@@ -302,23 +319,50 @@ fn handle_request_send_funds<B>(
 ) where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
 {
+    // A draining (or closed) friend may not open new transactions:
+    let friend = m_state.state().friends.get(remote_public_key).unwrap();
+    if !friend.drain_status.is_active() {
+        reply_with_cancel(
+            m_state,
+            send_commands,
+            remote_public_key,
+            currency,
+            &request_send_funds.request_id,
+        );
+        return;
+    }
+
     if request_send_funds.route.is_empty() {
         // We are the destination of this request.
 
-        let is_complete = match check_request(m_state.state(), &request_send_funds) {
-            CheckRequest::Failure => {
-                reply_with_cancel(
-                    m_state,
-                    send_commands,
-                    remote_public_key,
-                    currency,
-                    &request_send_funds.request_id,
-                );
-                return;
-            }
-            CheckRequest::Success => false,
-            CheckRequest::Complete => true,
-        };
+        let (is_complete, change) =
+            match check_request(m_state.state(), currency, &request_send_funds) {
+                CheckRequest::Failure => {
+                    reply_with_cancel(
+                        m_state,
+                        send_commands,
+                        remote_public_key,
+                        currency,
+                        &request_send_funds.request_id,
+                    );
+                    return;
+                }
+                CheckRequest::Success => (false, 0),
+                CheckRequest::Complete(change) => (true, change),
+            };
+
+        // Friends of the initial friend protocol can not pass change back to the buyer.
+        // An overpaying request can not be answered through them:
+        if change > 0 && !friend.supports_change() {
+            reply_with_cancel(
+                m_state,
+                send_commands,
+                remote_public_key,
+                currency,
+                &request_send_funds.request_id,
+            );
+            return;
+        }
 
         // Set the src_hashed_lock for the OpenInvoice if required.
         // (Happens only when the first transaction for this invoice is received):
@@ -352,6 +396,7 @@ fn handle_request_send_funds<B>(
             currency.clone(),
             pending_transaction,
             is_complete,
+            change,
         );
         send_commands.set_try_send(&remote_public_key);
         return;
@@ -378,6 +423,22 @@ fn handle_request_send_funds<B>(
         return;
     }
 
+    // If the request asks to be exchanged to the currency of its destination, and we exchange
+    // between the two currencies, the request is forwarded in the currency of the destination:
+    let opt_exchange_rate = request_send_funds
+        .opt_exchange
+        .as_ref()
+        .and_then(|currency_exchange| {
+            m_state
+                .state()
+                .exchange_rate(currency, &currency_exchange.dest_currency)
+        })
+        .cloned();
+    let next_currency = match (&opt_exchange_rate, &request_send_funds.opt_exchange) {
+        (Some(_), Some(currency_exchange)) => currency_exchange.dest_currency.clone(),
+        _ => currency.clone(),
+    };
+
     // The node on the route has to be one of our friends:
     let next_public_key = request_send_funds.route.index_to_pk(0).unwrap().clone();
     let opt_next_friend = m_state.state().friends.get(&next_public_key);
@@ -386,9 +447,14 @@ fn handle_request_send_funds<B>(
     // If we forward the request to an offline friend, the request could be stuck for a long
     // time before a response arrives.
     let friend_ready = if let Some(next_friend) = opt_next_friend {
-        if let Some(currency_config) = next_friend.currency_configs.get(currency) {
+        if let Some(currency_config) = next_friend.currency_configs.get(&next_currency) {
             if currency_config.is_open {
-                is_friend_ready(m_state.state(), ephemeral, &next_public_key, currency)
+                is_friend_ready(
+                    m_state.state(),
+                    m_ephemeral.ephemeral(),
+                    &next_public_key,
+                    &next_currency,
+                )
             } else {
                 false
             }
@@ -444,6 +510,110 @@ fn handle_request_send_funds<B>(
         }
     };
 
+    // Take our refund margin: We refund the next node `REFUND_TICKS_MARGIN` ticks before the
+    // previous node may refund us. If there is not enough time left, we cancel the request:
+    if request_send_funds.refund_ticks > 0 {
+        if request_send_funds.refund_ticks <= REFUND_TICKS_MARGIN {
+            reply_with_cancel(
+                m_state,
+                send_commands,
+                remote_public_key,
+                currency,
+                &request_id,
+            );
+            return;
+        }
+        request_send_funds.refund_ticks -= REFUND_TICKS_MARGIN;
+    }
+
+    if let Some(exchange_rate) = opt_exchange_rate {
+        // Exchange the request. The credits we receive must cover the payment we give in the
+        // currency of the destination. The fees left for the next mediators are exchanged too:
+        let currency_exchange = request_send_funds.opt_exchange.take().unwrap();
+        let opt_exchanged = exchange_rate
+            .convert(request_send_funds.dest_payment)
+            .and_then(|exchanged_payment| {
+                Some((
+                    exchanged_payment,
+                    exchange_rate.convert(request_send_funds.left_fees)?,
+                ))
+            });
+        match opt_exchanged {
+            Some((exchanged_payment, left_fees))
+                if exchanged_payment >= currency_exchange.dest_payment =>
+            {
+                request_send_funds.dest_payment = currency_exchange.dest_payment;
+                request_send_funds.total_dest_payment = currency_exchange.total_dest_payment;
+                request_send_funds.left_fees = left_fees;
+            }
+            _ => {
+                reply_with_cancel(
+                    m_state,
+                    send_commands,
+                    remote_public_key,
+                    currency,
+                    &request_id,
+                );
+                return;
+            }
+        }
+    }
+
+    // Make sure that the node that sent us the request does not keep freezing more of our credits
+    // with the next node than all the other origins of requests together:
+    if !m_ephemeral.ephemeral().freeze_guard.may_freeze(
+        &next_public_key,
+        &next_currency,
+        &Some(remote_public_key.clone()),
+    ) {
+        reply_with_cancel(
+            m_state,
+            send_commands,
+            remote_public_key,
+            currency,
+            &request_id,
+        );
+        return;
+    }
+
+    // Make sure that the outflow limit of the next node (If any) allows this request:
+    if !charge_outflow(
+        m_state.state(),
+        m_ephemeral,
+        &next_public_key,
+        &next_currency,
+        &request_send_funds,
+    ) {
+        reply_with_cancel(
+            m_state,
+            send_commands,
+            remote_public_key,
+            currency,
+            &request_id,
+        );
+        return;
+    }
+
+    // The next node must be able to handle the request (For example, a request with a refund
+    // countdown may only be sent to a node that supports refunds):
+    let pending_op = FriendTcOp::RequestSendFunds(request_send_funds.clone());
+    if !m_state
+        .state()
+        .friends
+        .get(&next_public_key)
+        .unwrap()
+        .supports_op(&pending_op)
+    {
+        reply_with_cancel(
+            m_state,
+            send_commands,
+            remote_public_key,
+            currency,
+            &request_id,
+        );
+        return;
+    }
+
     // Remove the next node from remaining route.
     request_send_funds.route.public_keys.remove(0);
 
@@ -451,7 +621,7 @@ fn handle_request_send_funds<B>(
     forward_request(
         m_state,
         send_commands,
-        currency,
+        &next_currency,
         request_send_funds,
         &next_public_key,
     );
@@ -475,17 +645,59 @@ fn handle_request_send_funds_cancel<B>(
     );
 }
 
+/// Check if a request that we have sent has lost its origin:
+/// We are not the origin of the request, and the node that sent it to us has already refunded it.
+/// Operations for such a request can not be passed backwards anymore.
+fn is_origin_refunded<B>(
+    m_state: &MutableFunderState<B>,
+    currency: &Currency,
+    request_id: &Uid,
+) -> bool
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    find_request_origin(m_state, currency, request_id).is_none()
+        && !m_state.state().open_transactions.contains_key(request_id)
+}
+
 fn handle_response_send_funds<B>(
     m_state: &mut MutableFunderState<B>,
     send_commands: &mut SendCommands,
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    remote_public_key: &PublicKey,
     currency: &Currency,
     response_send_funds: ResponseSendFundsOp,
     pending_transaction: PendingTransaction,
 ) where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
 {
-    match find_request_origin(m_state.state(), currency, &response_send_funds.request_id).cloned() {
+    if is_origin_refunded(m_state, currency, &response_send_funds.request_id) {
+        warn!(
+            "handle_response_send_funds(): Request was refunded: {:?}",
+            response_send_funds.request_id
+        );
+        return;
+    }
+
+    // We are refunding this request, so this response is of no use:
+    if is_refund_queued(m_state, remote_public_key, &response_send_funds.request_id) {
+        return;
+    }
+
+    // Our own open transactions are checked first: The request of a self payment (A cycle route)
+    // also arrives back to us through the last friend on the route, and therefore has an external
+    // origin too.
+    let opt_origin = if m_state
+        .state()
+        .open_transactions
+        .contains_key(&response_send_funds.request_id)
+    {
+        None
+    } else {
+        find_request_origin(m_state, currency, &response_send_funds.request_id)
+    };
+
+    match opt_origin {
         None => {
             // We couldn't find any external origin.
             // It means that we are the origin of this request
@@ -512,6 +724,18 @@ fn handle_response_send_funds<B>(
                     payment.src_plain_lock.clone(),
                 );
 
+                // We are also the seller of a self payment, so we commit it right away:
+                let is_self_payment = pending_transaction.route.public_keys.last()
+                    == Some(&m_state.state().local_public_key);
+                if is_self_payment {
+                    if let Err(e) = control_commit_invoice(m_state, send_commands, &commit) {
+                        warn!(
+                            "handle_response_send_funds(): Failed to commit self payment: {:?}",
+                            e
+                        );
+                    }
+                }
+
                 TransactionResult {
                     request_id: response_send_funds.request_id.clone(),
                     result: RequestResult::Complete(commit),
@@ -524,11 +748,23 @@ fn handle_response_send_funds<B>(
             };
             outgoing_control.push(FunderOutgoingControl::TransactionResult(transaction_result));
         }
-        Some(friend_public_key) => {
-            // Queue this response message to another token channel:
-            let response_op = BackwardsOp::Response(response_send_funds);
+        Some((friend_public_key, origin_currency)) => {
+            let origin_friend = m_state.state().friends.get(&friend_public_key).unwrap();
+            let response_op = FriendTcOp::ResponseSendFunds(response_send_funds.clone());
+            let backwards_op = if origin_friend.supports_op(&response_op) {
+                // Queue this response message to another token channel:
+                BackwardsOp::Response(response_send_funds)
+            } else {
+                // The origin can not handle this response (For example, it does not support
+                // change). We cancel the request instead:
+                warn!(
+                    "handle_response_send_funds(): Response is not supported by origin {:?}",
+                    friend_public_key
+                );
+                BackwardsOp::Cancel(create_cancel_send_funds(response_send_funds.request_id))
+            };
             let friend_mutation =
-                FriendMutation::PushBackPendingBackwardsOp((currency.clone(), response_op));
+                FriendMutation::PushBackPendingBackwardsOp((origin_currency, backwards_op));
             let funder_mutation =
                 FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
             m_state.mutate(funder_mutation);
@@ -543,6 +779,7 @@ fn handle_cancel_send_funds<B, R>(
     send_commands: &mut SendCommands,
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
     rng: &R,
+    remote_public_key: &PublicKey,
     currency: &Currency,
     cancel_send_funds: CancelSendFundsOp,
     pending_transaction: PendingTransaction,
@@ -550,31 +787,48 @@ fn handle_cancel_send_funds<B, R>(
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
     R: CryptoRandom,
 {
-    match find_request_origin(m_state.state(), currency, &cancel_send_funds.request_id).cloned() {
+    // The request is not pending anymore, so there is nothing left to refund:
+    if is_refund_queued(m_state, remote_public_key, &cancel_send_funds.request_id) {
+        let friend_mutation =
+            FriendMutation::RemovePendingBackwardsOps(cancel_send_funds.request_id.clone());
+        let funder_mutation =
+            FunderMutation::FriendMutation((remote_public_key.clone(), friend_mutation));
+        m_state.mutate(funder_mutation);
+
+        if find_request_origin(m_state, currency, &cancel_send_funds.request_id).is_none() {
+            // Either we are the origin of this request (The transaction has already failed when
+            // the refund was queued), or the request was already refunded by the previous node:
+            return;
+        }
+    }
+
+    if is_origin_refunded(m_state, currency, &cancel_send_funds.request_id) {
+        warn!(
+            "handle_cancel_send_funds(): Request was refunded: {:?}",
+            cancel_send_funds.request_id
+        );
+        return;
+    }
+
+    match find_request_origin(m_state, currency, &cancel_send_funds.request_id) {
         None => {
             // We are the origin of this request, and we got a cancellation.
-
-            // Update buyer transactions (requests that were originated by us):
-            remove_transaction(
+            // We either retry through an alternative route, or inform the user about the
+            // transaction failure:
+            fail_local_transaction(
                 m_state,
                 outgoing_control,
                 rng,
-                &cancel_send_funds.request_id,
+                remote_public_key,
+                currency,
+                &create_request_send_funds(&pending_transaction),
             );
-
-            // Inform user about the transaction failure:
-            outgoing_control.push(FunderOutgoingControl::TransactionResult(
-                TransactionResult {
-                    request_id: pending_transaction.request_id,
-                    result: RequestResult::Failure,
-                },
-            ));
         }
-        Some(friend_public_key) => {
+        Some((friend_public_key, origin_currency)) => {
             // Queue this Cancel message to another token channel:
             let cancel_op = BackwardsOp::Cancel(cancel_send_funds);
             let friend_mutation =
-                FriendMutation::PushBackPendingBackwardsOp((currency.clone(), cancel_op));
+                FriendMutation::PushBackPendingBackwardsOp((origin_currency, cancel_op));
             let funder_mutation =
                 FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
             m_state.mutate(funder_mutation);
@@ -589,6 +843,7 @@ fn handle_collect_send_funds<B, R>(
     send_commands: &mut SendCommands,
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
     rng: &R,
+    remote_public_key: &PublicKey,
     currency: &Currency,
     collect_send_funds: CollectSendFundsOp,
     pending_transaction: PendingTransaction,
@@ -596,9 +851,27 @@ fn handle_collect_send_funds<B, R>(
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
     R: CryptoRandom,
 {
+    // The collect arrived before our refund was sent. The request is not pending anymore, so
+    // there is nothing left to refund:
+    if is_refund_queued(m_state, remote_public_key, &collect_send_funds.request_id) {
+        let friend_mutation =
+            FriendMutation::RemovePendingBackwardsOps(collect_send_funds.request_id.clone());
+        let funder_mutation =
+            FunderMutation::FriendMutation((remote_public_key.clone(), friend_mutation));
+        m_state.mutate(funder_mutation);
+    }
+
+    if is_origin_refunded(m_state, currency, &collect_send_funds.request_id) {
+        warn!(
+            "handle_collect_send_funds(): Request was refunded: {:?}",
+            collect_send_funds.request_id
+        );
+        return;
+    }
+
     // Check if we are the origin of this transaction (Did we send the RequestSendFundsOp
     // message?):
-    match find_request_origin(m_state.state(), currency, &collect_send_funds.request_id).cloned() {
+    match find_request_origin(m_state, currency, &collect_send_funds.request_id) {
         None => {
             // We are the origin of this request, and we got a Collect message
             let open_transaction = m_state
@@ -712,11 +985,11 @@ fn handle_collect_send_funds<B, R>(
                 FunderMutation::RemoveTransaction(collect_send_funds.request_id.clone());
             m_state.mutate(funder_mutation);
         }
-        Some(friend_public_key) => {
+        Some((friend_public_key, origin_currency)) => {
             // Queue this Collect message to another token channel:
             let collect_op = BackwardsOp::Collect(collect_send_funds);
             let friend_mutation =
-                FriendMutation::PushBackPendingBackwardsOp((currency.clone(), collect_op));
+                FriendMutation::PushBackPendingBackwardsOp((origin_currency, collect_op));
             let funder_mutation =
                 FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
             m_state.mutate(funder_mutation);
@@ -726,6 +999,78 @@ fn handle_collect_send_funds<B, R>(
     };
 }
 
+/// Handle a refund of a request that was sent to us.
+/// The refund is passed on to the next node on the route. If we are the destination of the
+/// request, it is removed from its invoice.
+fn handle_refund_send_funds<B>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    remote_public_key: &PublicKey,
+    currency: &Currency,
+    refund_send_funds: RefundSendFundsOp,
+    pending_transaction: PendingTransaction,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let request_id = refund_send_funds.request_id;
+
+    // Operations queued back to the remote side for this request can not be sent anymore:
+    let friend_mutation = FriendMutation::RemovePendingBackwardsOps(request_id.clone());
+    let funder_mutation =
+        FunderMutation::FriendMutation((remote_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+
+    let next_public_key = if let Some(next_public_key) = pending_transaction.route.index_to_pk(0) {
+        next_public_key.clone()
+    } else {
+        // We are the destination of this request.
+        // A refunded transaction will not be collected, so it does not pay for the invoice:
+        let is_incoming_transaction = m_state
+            .state()
+            .open_invoices
+            .get(&pending_transaction.invoice_id)
+            .map(|open_invoice| open_invoice.incoming_transactions.contains(&request_id))
+            .unwrap_or(false);
+        if is_incoming_transaction {
+            let funder_mutation = FunderMutation::RemoveIncomingTransaction((
+                pending_transaction.invoice_id.clone(),
+                request_id,
+            ));
+            m_state.mutate(funder_mutation);
+        }
+        return;
+    };
+
+    if !m_state.state().friends.contains_key(&next_public_key) {
+        return;
+    }
+
+    // The request might still wait to be forwarded:
+    let friend_mutation = FriendMutation::RemovePendingRequest(request_id.clone());
+    let funder_mutation =
+        FunderMutation::FriendMutation((next_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+
+    // If the request was already forwarded, we refund it too:
+    refund_request(
+        m_state,
+        send_commands,
+        &next_public_key,
+        currency,
+        &request_id,
+    );
+    // The request might have been forwarded in the currency of its destination:
+    if let Some(currency_exchange) = &pending_transaction.opt_exchange {
+        refund_request(
+            m_state,
+            send_commands,
+            &next_public_key,
+            &currency_exchange.dest_currency,
+            &request_id,
+        );
+    }
+}
+
 /// Process valid incoming operations from remote side.
 fn handle_move_token_output<B, R>(
     m_state: &mut MutableFunderState<B>,
@@ -745,7 +1090,7 @@ fn handle_move_token_output<B, R>(
             IncomingMessage::Request(request_send_funds) => {
                 handle_request_send_funds(
                     m_state,
-                    m_ephemeral.ephemeral(),
+                    m_ephemeral,
                     send_commands,
                     remote_public_key,
                     currency,
@@ -769,6 +1114,7 @@ fn handle_move_token_output<B, R>(
                     m_state,
                     send_commands,
                     outgoing_control,
+                    remote_public_key,
                     currency,
                     incoming_response,
                     pending_transaction,
@@ -783,6 +1129,7 @@ fn handle_move_token_output<B, R>(
                     send_commands,
                     outgoing_control,
                     rng,
+                    remote_public_key,
                     currency,
                     incoming_cancel,
                     pending_transaction,
@@ -797,11 +1144,25 @@ fn handle_move_token_output<B, R>(
                     send_commands,
                     outgoing_control,
                     rng,
+                    remote_public_key,
                     currency,
                     incoming_collect,
                     pending_transaction,
                 );
             }
+            IncomingMessage::Refund(IncomingRefundSendFundsOp {
+                pending_transaction,
+                incoming_refund,
+            }) => {
+                handle_refund_send_funds(
+                    m_state,
+                    send_commands,
+                    remote_public_key,
+                    currency,
+                    incoming_refund,
+                    pending_transaction,
+                );
+            }
         }
     }
 }
@@ -825,6 +1186,8 @@ fn handle_move_token_error<B, R>(
     let opt_last_incoming_move_token = token_channel.get_last_incoming_move_token_hashed().cloned();
     // Send an InconsistencyError message to remote side:
     let local_reset_terms = gen_reset_terms(&token_channel, rng);
+    // Collect the evidence before the pending transactions are canceled:
+    let dispute_evidence = create_dispute_evidence(m_state.state(), remote_public_key).unwrap();
 
     // Cancel all internal pending requests inside token channel:
     cancel_local_pending_transactions(
@@ -850,6 +1213,7 @@ fn handle_move_token_error<B, R>(
         opt_last_incoming_move_token,
         local_reset_terms,
         opt_remote_reset_terms: None,
+        dispute_evidence,
     };
     let friend_mutation = FriendMutation::SetInconsistent(channel_inconsistent);
     let funder_mutation =
@@ -1047,11 +1411,108 @@ where
     Ok(())
 }
 
+/// Remember the capabilities of the remote friend.
+/// Capabilities are attached to every move token request sent by the remote friend.
+fn handle_capabilities<B, R>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    rng: &R,
+    remote_public_key: &PublicKey,
+    remote_capabilities: FriendCapabilities,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+    R: CryptoRandom,
+{
+    let friend = m_state.state().friends.get(remote_public_key).unwrap();
+    if friend.opt_remote_capabilities.as_ref() == Some(&remote_capabilities) {
+        // Nothing has changed:
+        return;
+    }
+
+    let friend_mutation = FriendMutation::SetRemoteCapabilities(remote_capabilities);
+    let funder_mutation =
+        FunderMutation::FriendMutation((remote_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+
+    // The friend might not support some of the operations we have queued for it anymore:
+    cancel_unsupported_operations(
+        m_state,
+        send_commands,
+        outgoing_control,
+        rng,
+        remote_public_key,
+    );
+}
+
+/// A friend has replaced its identity key.
+/// We keep the token channel with the friend, and start using the new key. The connection with
+/// the friend is closed, as it was authenticated using the old key.
+fn handle_key_rotation<B, R>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    send_commands: &mut SendCommands,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
+    rng: &R,
+    remote_public_key: &PublicKey,
+    key_rotation: KeyRotation,
+) -> Result<(), HandleFriendError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+    R: CryptoRandom,
+{
+    if &key_rotation.old_public_key != remote_public_key || !verify_key_rotation(&key_rotation) {
+        return Err(HandleFriendError::InvalidKeyRotation);
+    }
+
+    // Make sure we don't override another friend:
+    let new_public_key = &key_rotation.new_public_key;
+    if m_state.state().friends.contains_key(new_public_key)
+        || new_public_key == &m_state.state().local_public_key
+    {
+        return Err(HandleFriendError::KeyRotationConflict);
+    }
+
+    // Pending requests were not sent yet. They are canceled, as they might contain routes with
+    // the old key:
+    cancel_pending_requests(
+        m_state,
+        send_commands,
+        outgoing_control,
+        rng,
+        remote_public_key,
+        &CurrencyChoice::All,
+    );
+
+    let liveness_mutation = LivenessMutation::SetOffline(remote_public_key.clone());
+    m_ephemeral.mutate(EphemeralMutation::LivenessMutation(liveness_mutation));
+    outgoing_channeler_config.push(ChannelerConfig::RemoveFriend(remote_public_key.clone()));
+
+    m_state.mutate(FunderMutation::RotateFriendKey(key_rotation.clone()));
+
+    let friend = m_state.state().friends.get(new_public_key).unwrap();
+    if let FriendStatus::Enabled = friend.status {
+        // Reconnect to the friend using the new key:
+        let update_friend = ChannelerUpdateFriend {
+            friend_public_key: new_public_key.clone(),
+            friend_relays: friend.remote_relays.clone(),
+            local_relays: friend.sent_local_relays.to_vec(),
+        };
+        outgoing_channeler_config.push(ChannelerConfig::UpdateFriend(update_friend));
+    }
+    Ok(())
+}
+
+/// Handle reset terms sent by a friend.
+/// If `opt_auto_reset_tolerance` is set and the friend's terms match our own terms within the
+/// tolerance, the reset is accepted without asking the user.
 fn handle_inconsistency_error<B, R>(
     m_state: &mut MutableFunderState<B>,
     send_commands: &mut SendCommands,
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
     rng: &R,
+    opt_auto_reset_tolerance: Option<u128>,
     remote_public_key: &PublicKey,
     remote_reset_terms: ResetTerms,
 ) -> Result<(), HandleFriendError>
@@ -1070,25 +1531,31 @@ where
 
     // Obtain information about our reset terms:
     let friend = m_state.state().friends.get(remote_public_key).unwrap();
-    let (channel_was_consistent, new_local_reset_terms, opt_last_incoming_move_token) =
-        match &friend.channel_status {
-            ChannelStatus::Consistent(channel_consistent) => {
-                let token_channel = &channel_consistent.token_channel;
-                if token_channel.get_incoming().is_some() {
-                    return Err(HandleFriendError::InconsistencyWhenTokenOwned);
-                }
-                (
-                    true,
-                    gen_reset_terms(&token_channel, rng),
-                    token_channel.get_last_incoming_move_token_hashed().cloned(),
-                )
+    let (
+        channel_was_consistent,
+        new_local_reset_terms,
+        opt_last_incoming_move_token,
+        dispute_evidence,
+    ) = match &friend.channel_status {
+        ChannelStatus::Consistent(channel_consistent) => {
+            let token_channel = &channel_consistent.token_channel;
+            if token_channel.get_incoming().is_some() {
+                return Err(HandleFriendError::InconsistencyWhenTokenOwned);
             }
-            ChannelStatus::Inconsistent(channel_inconsistent) => (
-                false,
-                channel_inconsistent.local_reset_terms.clone(),
-                channel_inconsistent.opt_last_incoming_move_token.clone(),
-            ),
-        };
+            (
+                true,
+                gen_reset_terms(&token_channel, rng),
+                token_channel.get_last_incoming_move_token_hashed().cloned(),
+                create_dispute_evidence(m_state.state(), remote_public_key).unwrap(),
+            )
+        }
+        ChannelStatus::Inconsistent(channel_inconsistent) => (
+            false,
+            channel_inconsistent.local_reset_terms.clone(),
+            channel_inconsistent.opt_last_incoming_move_token.clone(),
+            channel_inconsistent.dispute_evidence.clone(),
+        ),
+    };
 
     if channel_was_consistent {
         // Cancel all pending requests to this friend:
@@ -1102,11 +1569,23 @@ where
         );
     }
 
+    let auto_reset = match opt_auto_reset_tolerance {
+        Some(tolerance) => should_auto_reset(
+            &m_state.state().local_public_key,
+            remote_public_key,
+            &new_local_reset_terms,
+            &new_remote_reset_terms,
+            tolerance,
+        ),
+        None => false,
+    };
+
     // Keep outgoing InconsistencyError message details in memory:
     let channel_inconsistent = ChannelInconsistent {
         opt_last_incoming_move_token,
         local_reset_terms: new_local_reset_terms,
         opt_remote_reset_terms: Some(new_remote_reset_terms),
+        dispute_evidence,
     };
     let friend_mutation = FriendMutation::SetInconsistent(channel_inconsistent);
     let funder_mutation =
@@ -1117,6 +1596,11 @@ where
     if channel_was_consistent {
         send_commands.set_try_send(remote_public_key);
     }
+
+    if auto_reset {
+        // We can not sign here. The signed reset move token is created by the sender:
+        send_commands.set_local_reset(remote_public_key);
+    }
     Ok(())
 }
 
@@ -1127,6 +1611,7 @@ pub fn handle_friend_message<B, R>(
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
     outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
     rng: &R,
+    opt_auto_reset_tolerance: Option<u128>,
     remote_public_key: &PublicKey,
     friend_message: FriendMessage<B>,
 ) -> Result<(), HandleFriendError>
@@ -1141,24 +1626,49 @@ where
     }?;
 
     match friend_message {
-        FriendMessage::MoveTokenRequest(friend_move_token_request) => handle_move_token_request(
+        FriendMessage::MoveTokenRequest(mut friend_move_token_request) => {
+            // Nodes that predate capabilities do not attach them:
+            if let Some(remote_capabilities) = friend_move_token_request.opt_capabilities.take() {
+                handle_capabilities(
+                    m_state,
+                    send_commands,
+                    outgoing_control,
+                    rng,
+                    remote_public_key,
+                    remote_capabilities,
+                );
+            }
+            handle_move_token_request(
+                m_state,
+                m_ephemeral,
+                send_commands,
+                outgoing_control,
+                outgoing_channeler_config,
+                rng,
+                remote_public_key,
+                friend_move_token_request,
+            )
+        }
+
+        FriendMessage::InconsistencyError(remote_reset_terms) => handle_inconsistency_error(
             m_state,
-            m_ephemeral,
             send_commands,
             outgoing_control,
-            outgoing_channeler_config,
             rng,
+            opt_auto_reset_tolerance,
             remote_public_key,
-            friend_move_token_request,
+            remote_reset_terms,
         ),
 
-        FriendMessage::InconsistencyError(remote_reset_terms) => handle_inconsistency_error(
+        FriendMessage::KeyRotation(key_rotation) => handle_key_rotation(
             m_state,
+            m_ephemeral,
             send_commands,
             outgoing_control,
+            outgoing_channeler_config,
             rng,
             remote_public_key,
-            remote_reset_terms,
+            key_rotation,
         ),
     }
 }
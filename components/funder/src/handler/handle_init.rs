@@ -1,9 +1,12 @@
 use signature::canonical::CanonicalSerialize;
 use std::fmt::Debug;
 
+use crypto::rand::CryptoRandom;
+
 use proto::app_server::messages::RelayAddress;
-use proto::funder::messages::{ChannelerUpdateFriend, FriendStatus};
+use proto::funder::messages::{ChannelerUpdateFriend, FriendStatus, FunderOutgoingControl};
 
+use crate::handler::canceler::request_alternative_route;
 use crate::handler::state_wrap::MutableFunderState;
 use crate::types::ChannelerConfig;
 
@@ -51,15 +54,39 @@ pub fn handle_init<B>(
     }
 }
 
+/// Ask again for alternative routes for all the transactions that were waiting to be retried.
+/// Route requests are not persisted, so the routes we asked for before the last shutdown will
+/// never arrive.
+pub fn resume_pending_retries<B, R>(
+    m_state: &MutableFunderState<B>,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    rng: &R,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+    R: CryptoRandom,
+{
+    for open_transaction in m_state.state().open_transactions.values() {
+        if let Some(pending_retry) = &open_transaction.opt_pending_retry {
+            request_alternative_route(outgoing_control, rng, pending_retry);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use proto::crypto::PublicKey;
-    use proto::funder::messages::AddFriend;
+    use std::convert::TryFrom;
+
+    use crypto::hash_lock::HashLock;
+    use crypto::test_utils::DummyRandom;
+
+    use proto::crypto::{InvoiceId, PaymentId, PlainLock, PublicKey, Uid};
+    use proto::funder::messages::{AddFriend, Currency, FriendsRoute, RequestSendFundsOp};
 
     use crate::friend::FriendMutation;
-    use crate::state::{FunderMutation, FunderState};
+    use crate::request_origins::RequestOrigins;
+    use crate::state::{FunderMutation, FunderState, PendingRetry};
 
     use crate::handler::state_wrap::MutableFunderState;
     use crate::handler::tests::utils::{dummy_named_relay_address, dummy_relay_address};
@@ -87,7 +114,8 @@ mod tests {
         let funder_mutation = FunderMutation::FriendMutation((pk_b.clone(), friend_mutation));
         state.mutate(&funder_mutation);
 
-        let mut m_state = MutableFunderState::new(state);
+        let request_origins = RequestOrigins::from_state(&state);
+        let mut m_state = MutableFunderState::new(state, request_origins);
         let mut outgoing_channeler_config = Vec::new();
         handle_init(&mut m_state, &mut outgoing_channeler_config);
 
@@ -120,4 +148,66 @@ mod tests {
             _ => unreachable!(),
         };
     }
+
+    #[test]
+    fn test_resume_pending_retries() {
+        let local_pk = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let pk_c = PublicKey::from(&[0xcc; PublicKey::len()]);
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+
+        let mut state = FunderState::<u32>::new(local_pk.clone(), Vec::new());
+
+        let request_id = Uid::from(&[3; Uid::len()]);
+        let payment_id = PaymentId::from(&[4; PaymentId::len()]);
+        state.mutate(&FunderMutation::AddTransaction((
+            request_id.clone(),
+            payment_id,
+            1,
+        )));
+
+        // The transaction failed before the last shutdown, and waits for an alternative route:
+        let request_send_funds = RequestSendFundsOp {
+            request_id: request_id.clone(),
+            src_hashed_lock: PlainLock::from(&[5; PlainLock::len()]).hash_lock(),
+            route: FriendsRoute {
+                public_keys: vec![local_pk, pk_b.clone(), pk_c.clone()],
+            },
+            dest_payment: 10,
+            total_dest_payment: 10,
+            invoice_id: InvoiceId::from(&[6; InvoiceId::len()]),
+            left_fees: 2,
+            expiry_ticks: 0,
+            refund_ticks: 0,
+            opt_exchange: None,
+        };
+        let pending_retry = PendingRetry {
+            currency: currency.clone(),
+            request_send_funds,
+        };
+        state.mutate(&FunderMutation::SetTransactionPendingRetry((
+            request_id.clone(),
+            pending_retry,
+        )));
+
+        let request_origins = RequestOrigins::from_state(&state);
+        let m_state = MutableFunderState::new(state, request_origins);
+        let rng = DummyRandom::new(&[1u8]);
+        let mut outgoing_control = Vec::new();
+        resume_pending_retries(&m_state, &mut outgoing_control, &rng);
+
+        assert_eq!(outgoing_control.len(), 1);
+        match outgoing_control.remove(0) {
+            FunderOutgoingControl::RequestAlternativeRoute(request_alternative_route) => {
+                assert_eq!(request_alternative_route.request_id, request_id);
+                assert_ne!(request_alternative_route.routes_request_id, request_id);
+                assert_eq!(request_alternative_route.currency, currency);
+                assert_eq!(request_alternative_route.dest_public_key, pk_c);
+                assert_eq!(request_alternative_route.dest_payment, 10);
+                assert_eq!(request_alternative_route.max_fees, 2);
+                assert_eq!(request_alternative_route.exclude_friend, pk_b);
+            }
+            _ => unreachable!(),
+        };
+    }
 }
@@ -4,7 +4,8 @@ use signature::canonical::CanonicalSerialize;
 
 use crypto::rand::CryptoRandom;
 
-use proto::funder::messages::{FriendStatus, FunderOutgoingControl};
+use proto::funder::messages::{FriendStatus, FunderOutgoingControl, MessageDelivery};
+use proto::secure_channel::messages::SecureChannelReport;
 
 use crate::types::IncomingLivenessMessage;
 
@@ -57,7 +58,11 @@ where
                 return Err(HandleLivenessError::FriendAlreadyOnline);
             }
 
+            // The resent move token request also lets the remote side know which optional
+            // features we support:
             send_commands.set_resend_outgoing(&friend_public_key);
+            // The remote side might have missed our key rotation announcement:
+            send_commands.set_send_key_rotation(&friend_public_key);
 
             let liveness_mutation = LivenessMutation::SetOnline(friend_public_key.clone());
             let ephemeral_mutation = EphemeralMutation::LivenessMutation(liveness_mutation);
@@ -86,6 +91,169 @@ where
                 &CurrencyChoice::All,
             );
         }
+        IncomingLivenessMessage::MissedBeats((friend_public_key, missed_beats)) => {
+            // The keepalive report might race with an offline notification, or with the removal
+            // of the friend. We only keep track of missed beats for online friends.
+            if m_state.state().friends.get(&friend_public_key).is_none()
+                || !m_ephemeral
+                    .ephemeral()
+                    .liveness
+                    .is_online(&friend_public_key)
+            {
+                return Ok(());
+            }
+
+            if m_ephemeral
+                .ephemeral()
+                .liveness
+                .missed_beats(&friend_public_key)
+                == missed_beats
+            {
+                // Nothing has changed:
+                return Ok(());
+            }
+
+            let liveness_mutation =
+                LivenessMutation::SetMissedBeats((friend_public_key.clone(), missed_beats));
+            let ephemeral_mutation = EphemeralMutation::LivenessMutation(liveness_mutation);
+            m_ephemeral.mutate(ephemeral_mutation);
+        }
+        IncomingLivenessMessage::SecureChannelReport((
+            friend_public_key,
+            secure_channel_report,
+        )) => {
+            // A decryption failure closes the connection, so the report might arrive after the
+            // friend went offline. We only require that the friend still exists:
+            if m_state.state().friends.get(&friend_public_key).is_none() {
+                return Ok(());
+            }
+
+            let mut secure_channel_stats = m_ephemeral
+                .ephemeral()
+                .liveness
+                .secure_channel_stats(&friend_public_key);
+            match secure_channel_report {
+                SecureChannelReport::DecryptionFailure => {
+                    secure_channel_stats.decryption_failures =
+                        secure_channel_stats.decryption_failures.saturating_add(1);
+                }
+                SecureChannelReport::NonceReplay => {
+                    secure_channel_stats.nonce_replays =
+                        secure_channel_stats.nonce_replays.saturating_add(1);
+                }
+                SecureChannelReport::Rekey => {
+                    secure_channel_stats.rekeys = secure_channel_stats.rekeys.saturating_add(1);
+                }
+                // Not a diagnostic event (Only affects the wire format chosen by the node):
+                SecureChannelReport::Established(_) => return Ok(()),
+            }
+
+            let liveness_mutation = LivenessMutation::SetSecureChannelStats((
+                friend_public_key.clone(),
+                secure_channel_stats,
+            ));
+            let ephemeral_mutation = EphemeralMutation::LivenessMutation(liveness_mutation);
+            m_ephemeral.mutate(ephemeral_mutation);
+        }
+        IncomingLivenessMessage::Bandwidth((friend_public_key, bandwidth)) => {
+            // The last report of a connection might arrive after the friend went offline. We
+            // only require that the friend still exists:
+            if m_state.state().friends.get(&friend_public_key).is_none() {
+                return Ok(());
+            }
+
+            let mut total_bandwidth = m_ephemeral
+                .ephemeral()
+                .liveness
+                .bandwidth(&friend_public_key);
+            total_bandwidth.add(&bandwidth);
+
+            let liveness_mutation =
+                LivenessMutation::SetBandwidth((friend_public_key.clone(), total_bandwidth));
+            let ephemeral_mutation = EphemeralMutation::LivenessMutation(liveness_mutation);
+            m_ephemeral.mutate(ephemeral_mutation);
+        }
+        IncomingLivenessMessage::RelayLatency((relay_public_key, opt_latency_ms)) => {
+            if m_ephemeral
+                .ephemeral()
+                .liveness
+                .relay_latency(&relay_public_key)
+                == Some(opt_latency_ms)
+            {
+                // Nothing has changed:
+                return Ok(());
+            }
+
+            let liveness_mutation =
+                LivenessMutation::SetRelayLatency((relay_public_key, opt_latency_ms));
+            let ephemeral_mutation = EphemeralMutation::LivenessMutation(liveness_mutation);
+            m_ephemeral.mutate(ephemeral_mutation);
+        }
+        IncomingLivenessMessage::QueueDepth((friend_public_key, queue_depth)) => {
+            // Queue depth reports might race with an offline notification, or with the removal
+            // of the friend. We only keep track of queue depths for online friends.
+            if m_state.state().friends.get(&friend_public_key).is_none()
+                || !m_ephemeral
+                    .ephemeral()
+                    .liveness
+                    .is_online(&friend_public_key)
+            {
+                return Ok(());
+            }
+
+            if m_ephemeral
+                .ephemeral()
+                .liveness
+                .queue_depth(&friend_public_key)
+                == queue_depth
+            {
+                // Nothing has changed:
+                return Ok(());
+            }
+
+            let liveness_mutation =
+                LivenessMutation::SetQueueDepth((friend_public_key.clone(), queue_depth));
+            let ephemeral_mutation = EphemeralMutation::LivenessMutation(liveness_mutation);
+            m_ephemeral.mutate(ephemeral_mutation);
+        }
+        IncomingLivenessMessage::MessageDelivery((friend_public_key, message_id, delivery)) => {
+            match delivery {
+                // Messages to offline friends are resent when the friend is online again:
+                MessageDelivery::Sent | MessageDelivery::FriendOffline => return Ok(()),
+                MessageDelivery::Dropped | MessageDelivery::TimedOut => {}
+            }
+
+            if m_state.state().friends.get(&friend_public_key).is_none()
+                || !m_ephemeral
+                    .ephemeral()
+                    .liveness
+                    .is_online(&friend_public_key)
+            {
+                return Ok(());
+            }
+
+            // Resending the last message is enough for the friend to resynchronize. We ignore
+            // earlier messages, to avoid resending to a friend with a full queue over and over.
+            if m_ephemeral
+                .ephemeral()
+                .liveness
+                .last_message(&friend_public_key)
+                != Some(&message_id)
+            {
+                return Ok(());
+            }
+
+            warn!(
+                "Last message to friend {:?} was not delivered ({:?}). Resending",
+                friend_public_key, delivery
+            );
+            send_commands.set_resend_outgoing(&friend_public_key);
+        }
+        IncomingLivenessMessage::ListenOnly => {
+            let liveness_mutation = LivenessMutation::SetListenOnly;
+            let ephemeral_mutation = EphemeralMutation::LivenessMutation(liveness_mutation);
+            m_ephemeral.mutate(ephemeral_mutation);
+        }
     };
     Ok(())
 }
@@ -105,6 +273,7 @@ mod tests {
 
     use crate::ephemeral::Ephemeral;
     use crate::friend::{ChannelStatus, FriendMutation};
+    use crate::request_origins::RequestOrigins;
     use crate::state::{FunderMutation, FunderState};
 
     use crate::handler::state_wrap::{MutableEphemeral, MutableFunderState};
@@ -157,7 +326,8 @@ mod tests {
 
         let ephemeral = Ephemeral::new();
 
-        let mut m_state = MutableFunderState::new(state);
+        let request_origins = RequestOrigins::from_state(&state);
+        let mut m_state = MutableFunderState::new(state, request_origins);
         let mut m_ephemeral = MutableEphemeral::new(ephemeral);
         let mut send_commands = SendCommands::new();
         let mut outgoing_control = Vec::new();
@@ -0,0 +1,1081 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+
+use signature::canonical::CanonicalSerialize;
+
+use crypto::rand::CryptoRandom;
+
+use proto::consts::{MAX_REBALANCE_SUGGESTIONS, REBALANCE_ANALYSIS_TICKS, REFUND_TICKS_MARGIN};
+use proto::crypto::Uid;
+use proto::funder::messages::{Currency, CurrencyBalance, FunderOutgoingControl};
+use proto::proto_ser::ProtoSerialize;
+use proto::report::messages::ClosingStatement;
+
+use crate::channel_proof::create_channel_proof;
+use crate::ephemeral::EphemeralMutation;
+use crate::friend::{ChannelConsistent, ChannelStatus, DrainStatus, FriendMutation};
+use crate::handler::canceler::{
+    cancel_request, fail_local_transaction, is_refund_queued, refund_request,
+};
+use crate::handler::handle_control::control_cancel_invoice;
+use crate::handler::state_wrap::{MutableEphemeral, MutableFunderState};
+use crate::handler::types::SendCommands;
+use crate::handler::utils::find_remote_pending_transaction;
+use crate::invoices::InvoicesMutation;
+use crate::liveness::LivenessMutation;
+use crate::outflows::OutflowsMutation;
+use crate::rebalance::{calc_rebalance_suggestions, RebalanceMutation};
+use crate::refunds::RefundsMutation;
+use crate::requests_expiry::RequestsExpiryMutation;
+use crate::state::FunderMutation;
+use crate::types::create_request_send_funds;
+
+/// Start the expiry countdowns of all open invoices.
+/// Countdowns are not persistent, therefore after a restart every invoice gets its full amount of
+/// expiry ticks again.
+pub fn init_invoices_expiry<B>(m_state: &MutableFunderState<B>, m_ephemeral: &mut MutableEphemeral)
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    for (invoice_id, open_invoice) in &m_state.state().open_invoices {
+        if let Some(expiry_ticks) = open_invoice.opt_expiry_ticks {
+            let invoices_mutation =
+                InvoicesMutation::SetTicksLeft((invoice_id.clone(), expiry_ticks));
+            m_ephemeral.mutate(EphemeralMutation::InvoicesMutation(invoices_mutation));
+        }
+    }
+}
+
+/// Sample the uptime of all friends, advance the expiry countdowns of open invoices and queued
+/// requests, the refund countdowns of pending requests and the windows of outflow limits, close
+/// the channels of drained friends and periodically look for channels that should be rebalanced.
+pub fn handle_timer_tick<B, R>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    send_commands: &mut SendCommands,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    rng: &R,
+    min_friend_uptime_percent: u8,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+    R: CryptoRandom,
+{
+    tick_liveness(m_state, m_ephemeral, min_friend_uptime_percent);
+    tick_invoices_expiry(m_state, m_ephemeral, send_commands);
+    tick_requests_expiry(m_state, m_ephemeral, send_commands, outgoing_control, rng);
+    tick_refunds(m_state, m_ephemeral, send_commands, outgoing_control, rng);
+    tick_outflows(m_ephemeral);
+    tick_drains(m_state, send_commands);
+    tick_rebalance(m_state, m_ephemeral);
+}
+
+/// Are all the transactions with a friend settled?
+fn is_drained<B>(channel_consistent: &ChannelConsistent<B>) -> bool
+where
+    B: Clone,
+{
+    channel_consistent.pending_requests.is_empty()
+        && channel_consistent.pending_user_requests.is_empty()
+        && channel_consistent.pending_backwards_ops.is_empty()
+        && channel_consistent
+            .token_channel
+            .get_mutual_credits()
+            .values()
+            .all(|mutual_credit| {
+                let pending_transactions = &mutual_credit.state().pending_transactions;
+                pending_transactions.local.is_empty() && pending_transactions.remote.is_empty()
+            })
+}
+
+/// Close the channels of draining friends whose transactions were all settled.
+///
+/// The final balances are proposed to the friend by sending it a move token (Possibly empty),
+/// which commits to the final balances. Once such a move token was sent, the channel is closed,
+/// and the latest move tokens of the channel are kept as a closing statement.
+fn tick_drains<B>(m_state: &mut MutableFunderState<B>, send_commands: &mut SendCommands)
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let draining_friends: Vec<_> = m_state
+        .state()
+        .friends
+        .iter()
+        .filter(|(_, friend)| friend.drain_status == DrainStatus::Draining)
+        .map(|(friend_public_key, _)| friend_public_key.clone())
+        .collect();
+
+    for friend_public_key in draining_friends {
+        let friend = m_state.state().friends.get(&friend_public_key).unwrap();
+        let channel_consistent = match &friend.channel_status {
+            ChannelStatus::Consistent(channel_consistent) => channel_consistent,
+            // Nothing can be settled until the channel is reset:
+            ChannelStatus::Inconsistent(_) => continue,
+        };
+        if !is_drained(channel_consistent) {
+            continue;
+        }
+
+        let token_channel = &channel_consistent.token_channel;
+        if token_channel.get_outgoing().is_none() {
+            // We have the token. Our next move token proposes the final balances:
+            send_commands.set_remote_wants_token(&friend_public_key);
+            continue;
+        }
+
+        let mut final_balances: Vec<_> = token_channel
+            .get_mutual_credits()
+            .iter()
+            .map(|(currency, mutual_credit)| CurrencyBalance {
+                currency: currency.clone(),
+                balance: mutual_credit.state().balance.balance,
+            })
+            .collect();
+        final_balances.sort_by(|a, b| a.currency.cmp(&b.currency));
+
+        let channel_proof = create_channel_proof(m_state.state(), &friend_public_key).unwrap();
+        let closing_statement = ClosingStatement {
+            final_balances,
+            channel_proof: channel_proof.proto_serialize(),
+        };
+
+        let friend_mutation =
+            FriendMutation::SetDrainStatus(DrainStatus::Closed(closing_statement));
+        let funder_mutation = FunderMutation::FriendMutation((friend_public_key, friend_mutation));
+        m_state.mutate(funder_mutation);
+    }
+}
+
+/// Take an uptime sample of every friend, and update the gating of friends accordingly:
+/// New requests are not queued through a friend whose recent uptime is below
+/// `min_friend_uptime_percent`. A friend whose uptime was not sampled yet is never gated.
+/// Friends of a listen only node are online only when they choose to connect, so they are never
+/// gated either.
+fn tick_liveness<B>(
+    m_state: &MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    min_friend_uptime_percent: u8,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let friends: Vec<_> = m_state.state().friends.keys().cloned().collect();
+    let liveness_mutation = LivenessMutation::SampleUptime(friends.clone());
+    m_ephemeral.mutate(EphemeralMutation::LivenessMutation(liveness_mutation));
+
+    for friend_public_key in friends {
+        let liveness = &m_ephemeral.ephemeral().liveness;
+        let is_gated = match liveness.uptime_percent(&friend_public_key) {
+            Some(uptime_percent) => {
+                !liveness.listen_only && uptime_percent < min_friend_uptime_percent
+            }
+            None => false,
+        };
+        if liveness.is_gated(&friend_public_key) == is_gated {
+            continue;
+        }
+        let liveness_mutation = LivenessMutation::SetGated((friend_public_key, is_gated));
+        m_ephemeral.mutate(EphemeralMutation::LivenessMutation(liveness_mutation));
+    }
+}
+
+/// Advance the windows of outflow limits.
+/// Once a window ends, the friend may be sent up to the full limit again.
+fn tick_outflows(m_ephemeral: &mut MutableEphemeral) {
+    let windows = m_ephemeral.ephemeral().outflows.windows.clone();
+    for ((friend_public_key, currency), window) in windows {
+        let outflows_mutation = if window.ticks_left > 1 {
+            OutflowsMutation::SetTicksLeft((friend_public_key, currency, window.ticks_left - 1))
+        } else {
+            OutflowsMutation::Remove((friend_public_key, currency))
+        };
+        m_ephemeral.mutate(EphemeralMutation::OutflowsMutation(outflows_mutation));
+    }
+}
+
+/// Advance the countdown to the next rebalancing analysis.
+/// Once the countdown ends, the balances with all friends are inspected, and the rebalancing
+/// suggestions are updated if they have changed.
+fn tick_rebalance<B>(m_state: &MutableFunderState<B>, m_ephemeral: &mut MutableEphemeral)
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let ticks_left = m_ephemeral.ephemeral().rebalance.ticks_left;
+    if ticks_left > 1 {
+        let rebalance_mutation = RebalanceMutation::SetTicksLeft(ticks_left - 1);
+        m_ephemeral.mutate(EphemeralMutation::RebalanceMutation(rebalance_mutation));
+        return;
+    }
+
+    let rebalance_mutation = RebalanceMutation::SetTicksLeft(REBALANCE_ANALYSIS_TICKS);
+    m_ephemeral.mutate(EphemeralMutation::RebalanceMutation(rebalance_mutation));
+
+    let suggestions = calc_rebalance_suggestions(m_state.state(), MAX_REBALANCE_SUGGESTIONS);
+    if suggestions != m_ephemeral.ephemeral().rebalance.suggestions {
+        let rebalance_mutation = RebalanceMutation::SetSuggestions(suggestions);
+        m_ephemeral.mutate(EphemeralMutation::RebalanceMutation(rebalance_mutation));
+    }
+}
+
+/// Advance the expiry countdowns of open invoices.
+/// Expired invoices are canceled, together with all their pending incoming transactions.
+fn tick_invoices_expiry<B>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    send_commands: &mut SendCommands,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let invoices_ticks_left = m_ephemeral.ephemeral().invoices.ticks_left.clone();
+    for (invoice_id, ticks_left) in invoices_ticks_left {
+        if !m_state.state().open_invoices.contains_key(&invoice_id) {
+            // The invoice was already committed or canceled:
+            let invoices_mutation = InvoicesMutation::Remove(invoice_id);
+            m_ephemeral.mutate(EphemeralMutation::InvoicesMutation(invoices_mutation));
+            continue;
+        }
+
+        if ticks_left > 1 {
+            let invoices_mutation = InvoicesMutation::SetTicksLeft((invoice_id, ticks_left - 1));
+            m_ephemeral.mutate(EphemeralMutation::InvoicesMutation(invoices_mutation));
+            continue;
+        }
+
+        // The invoice has expired:
+        let invoices_mutation = InvoicesMutation::Remove(invoice_id.clone());
+        m_ephemeral.mutate(EphemeralMutation::InvoicesMutation(invoices_mutation));
+        // We have just checked that the invoice exists, so this should never fail:
+        if let Err(e) = control_cancel_invoice(m_state, send_commands, invoice_id) {
+            warn!(
+                "handle_timer_tick(): Failed to cancel expired invoice: {:?}",
+                e
+            );
+        }
+    }
+}
+
+/// Advance the expiry countdowns of queued requests (Forwarded requests and requests originated
+/// by the user).
+/// A countdown starts at the request's `expiry_ticks` on the first tick the request is queued.
+/// Expired requests are removed from the queue and canceled, releasing the credits frozen for
+/// them along the route, instead of holding them until the origin gives up.
+fn tick_requests_expiry<B, R>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    send_commands: &mut SendCommands,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    rng: &R,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+    R: CryptoRandom,
+{
+    // Collect all the queued requests that may expire:
+    let mut queued_requests = Vec::new();
+    for (friend_public_key, friend) in &m_state.state().friends {
+        let channel_consistent = match &friend.channel_status {
+            ChannelStatus::Consistent(channel_consistent) => channel_consistent,
+            ChannelStatus::Inconsistent(_) => continue,
+        };
+        for (currency, request_send_funds) in channel_consistent
+            .pending_requests
+            .iter()
+            .chain(channel_consistent.pending_user_requests.iter())
+        {
+            if request_send_funds.expiry_ticks > 0 {
+                queued_requests.push((
+                    friend_public_key.clone(),
+                    currency.clone(),
+                    request_send_funds.clone(),
+                ));
+            }
+        }
+    }
+
+    // Remove countdowns of requests that were already sent or canceled:
+    let queued_request_ids: HashSet<_> = queued_requests
+        .iter()
+        .map(|(_, _, request_send_funds)| request_send_funds.request_id.clone())
+        .collect();
+    let counted_request_ids: Vec<_> = m_ephemeral
+        .ephemeral()
+        .requests_expiry
+        .ticks_left
+        .keys()
+        .cloned()
+        .collect();
+    for request_id in counted_request_ids {
+        if !queued_request_ids.contains(&request_id) {
+            let requests_expiry_mutation = RequestsExpiryMutation::Remove(request_id);
+            m_ephemeral.mutate(EphemeralMutation::RequestsExpiryMutation(
+                requests_expiry_mutation,
+            ));
+        }
+    }
+
+    for (friend_public_key, currency, request_send_funds) in queued_requests {
+        let request_id = request_send_funds.request_id.clone();
+        let ticks_left = m_ephemeral
+            .ephemeral()
+            .requests_expiry
+            .ticks_left
+            .get(&request_id)
+            .cloned()
+            .unwrap_or(request_send_funds.expiry_ticks);
+
+        if ticks_left > 1 {
+            let requests_expiry_mutation =
+                RequestsExpiryMutation::SetTicksLeft((request_id, ticks_left - 1));
+            m_ephemeral.mutate(EphemeralMutation::RequestsExpiryMutation(
+                requests_expiry_mutation,
+            ));
+            continue;
+        }
+
+        // The request has expired:
+        let requests_expiry_mutation = RequestsExpiryMutation::Remove(request_id.clone());
+        m_ephemeral.mutate(EphemeralMutation::RequestsExpiryMutation(
+            requests_expiry_mutation,
+        ));
+
+        let friend_mutation = FriendMutation::RemovePendingRequest(request_id);
+        let funder_mutation =
+            FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+        m_state.mutate(funder_mutation);
+
+        cancel_request(
+            m_state,
+            send_commands,
+            outgoing_control,
+            rng,
+            &friend_public_key,
+            &currency,
+            &request_send_funds,
+        );
+    }
+}
+
+/// Advance the refund countdowns of pending refundable requests.
+/// A countdown starts on the first tick the request is pending: For a request we have received,
+/// it starts at the request's `refund_ticks` minus our margin. For a request we have originated,
+/// it starts at the request's `refund_ticks`.
+/// When a countdown ends, the request is refunded to the next node on the route. If we are the
+/// origin of the request, the transaction fails. A forwarded request that is still queued is
+/// canceled instead.
+fn tick_refunds<B, R>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    send_commands: &mut SendCommands,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    rng: &R,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+    R: CryptoRandom,
+{
+    // Collect all the pending refundable requests:
+    // request_id -> (currency, initial ticks, is_mediator)
+    let mut refundable_requests: HashMap<Uid, (Currency, u64, bool)> = HashMap::new();
+
+    // Requests we have received from a friend. We never refund requests we are the destination
+    // of:
+    for friend in m_state.state().friends.values() {
+        let channel_consistent = match &friend.channel_status {
+            ChannelStatus::Consistent(channel_consistent) => channel_consistent,
+            ChannelStatus::Inconsistent(_) => continue,
+        };
+        for (currency, mutual_credit) in channel_consistent.token_channel.get_mutual_credits() {
+            for pending_transaction in mutual_credit.state().pending_transactions.remote.values() {
+                if pending_transaction.refund_ticks > 0 && !pending_transaction.route.is_empty() {
+                    refundable_requests.insert(
+                        pending_transaction.request_id.clone(),
+                        (
+                            currency.clone(),
+                            pending_transaction
+                                .refund_ticks
+                                .saturating_sub(REFUND_TICKS_MARGIN),
+                            true,
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    // Requests we have originated. Requests that are already being refunded are skipped:
+    for (friend_public_key, friend) in &m_state.state().friends {
+        let channel_consistent = match &friend.channel_status {
+            ChannelStatus::Consistent(channel_consistent) => channel_consistent,
+            ChannelStatus::Inconsistent(_) => continue,
+        };
+        for (currency, mutual_credit) in channel_consistent.token_channel.get_mutual_credits() {
+            for pending_transaction in mutual_credit.state().pending_transactions.local.values() {
+                if pending_transaction.refund_ticks > 0
+                    && !is_refund_queued(
+                        m_state,
+                        friend_public_key,
+                        &pending_transaction.request_id,
+                    )
+                {
+                    refundable_requests
+                        .entry(pending_transaction.request_id.clone())
+                        .or_insert((currency.clone(), pending_transaction.refund_ticks, false));
+                }
+            }
+        }
+    }
+
+    // Remove countdowns of requests that were already resolved:
+    let counted_request_ids: Vec<_> = m_ephemeral
+        .ephemeral()
+        .refunds
+        .ticks_left
+        .keys()
+        .cloned()
+        .collect();
+    for request_id in counted_request_ids {
+        if !refundable_requests.contains_key(&request_id) {
+            let refunds_mutation = RefundsMutation::Remove(request_id);
+            m_ephemeral.mutate(EphemeralMutation::RefundsMutation(refunds_mutation));
+        }
+    }
+
+    for (request_id, (currency, refund_ticks, is_mediator)) in refundable_requests {
+        let ticks_left = m_ephemeral
+            .ephemeral()
+            .refunds
+            .ticks_left
+            .get(&request_id)
+            .cloned()
+            .unwrap_or(refund_ticks);
+
+        if ticks_left == 0 {
+            // Already refunded. Waiting for the previous node to resolve the request:
+            continue;
+        }
+
+        if ticks_left > 1 {
+            let refunds_mutation = RefundsMutation::SetTicksLeft((request_id, ticks_left - 1));
+            m_ephemeral.mutate(EphemeralMutation::RefundsMutation(refunds_mutation));
+            continue;
+        }
+
+        // The countdown has ended.
+        // A forwarded request keeps an expired countdown until the previous node resolves it, so
+        // that the countdown will not start again:
+        let refunds_mutation = if is_mediator {
+            RefundsMutation::SetTicksLeft((request_id.clone(), 0))
+        } else {
+            RefundsMutation::Remove(request_id.clone())
+        };
+        m_ephemeral.mutate(EphemeralMutation::RefundsMutation(refunds_mutation));
+
+        refund_expired_request(
+            m_state,
+            send_commands,
+            outgoing_control,
+            rng,
+            &currency,
+            &request_id,
+            is_mediator,
+        );
+    }
+}
+
+/// Refund a request whose refund countdown has ended.
+fn refund_expired_request<B, R>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    rng: &R,
+    currency: &Currency,
+    request_id: &Uid,
+    is_mediator: bool,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+    R: CryptoRandom,
+{
+    // Find the friends we have sent this request to, and the friends this request is still
+    // queued to. A request we have exchanged is sent in the currency of its destination:
+    let mut currencies = vec![currency.clone()];
+    if is_mediator {
+        if let Some(currency_exchange) =
+            find_remote_pending_transaction(m_state.state(), currency, request_id)
+                .and_then(|pending_transaction| pending_transaction.opt_exchange.as_ref())
+        {
+            currencies.push(currency_exchange.dest_currency.clone());
+        }
+    }
+    let mut sent_requests = Vec::new();
+    let mut queued_requests = Vec::new();
+    for (friend_public_key, friend) in &m_state.state().friends {
+        let channel_consistent = match &friend.channel_status {
+            ChannelStatus::Consistent(channel_consistent) => channel_consistent,
+            ChannelStatus::Inconsistent(_) => continue,
+        };
+        for currency0 in &currencies {
+            if let Some(pending_transaction) = channel_consistent
+                .token_channel
+                .get_mutual_credits()
+                .get(currency0)
+                .and_then(|mutual_credit| {
+                    mutual_credit
+                        .state()
+                        .pending_transactions
+                        .local
+                        .get(request_id)
+                })
+            {
+                sent_requests.push((
+                    friend_public_key.clone(),
+                    currency0.clone(),
+                    create_request_send_funds(pending_transaction),
+                ));
+            }
+        }
+        for (currency0, request_send_funds) in &channel_consistent.pending_requests {
+            if currencies.contains(currency0) && &request_send_funds.request_id == request_id {
+                queued_requests.push((
+                    friend_public_key.clone(),
+                    currency0.clone(),
+                    request_send_funds.clone(),
+                ));
+            }
+        }
+    }
+
+    for (friend_public_key, currency, request_send_funds) in sent_requests {
+        refund_request(
+            m_state,
+            send_commands,
+            &friend_public_key,
+            &currency,
+            request_id,
+        );
+
+        if !is_mediator && m_state.state().open_transactions.contains_key(request_id) {
+            // We are the origin of this request, and we give up on it.
+            // We either retry through an alternative route, or inform the user about the
+            // transaction failure:
+            fail_local_transaction(
+                m_state,
+                outgoing_control,
+                rng,
+                &friend_public_key,
+                &currency,
+                &request_send_funds,
+            );
+        }
+    }
+
+    for (friend_public_key, currency, request_send_funds) in queued_requests {
+        let friend_mutation = FriendMutation::RemovePendingRequest(request_id.clone());
+        let funder_mutation =
+            FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+        m_state.mutate(funder_mutation);
+
+        cancel_request(
+            m_state,
+            send_commands,
+            outgoing_control,
+            rng,
+            &friend_public_key,
+            &currency,
+            &request_send_funds,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use crypto::test_utils::DummyRandom;
+
+    use crypto::hash_lock::HashLock;
+
+    use proto::crypto::{InvoiceId, PaymentId, PlainLock, PublicKey, Uid};
+    use proto::funder::messages::{
+        AddFriend, Currency, FriendCapabilities, FriendsRoute, RequestResult, RequestSendFundsOp,
+    };
+
+    use crate::ephemeral::Ephemeral;
+    use crate::mutual_credit::types::McMutation;
+    use crate::outflows::OutflowWindow;
+    use crate::request_origins::RequestOrigins;
+    use crate::state::{FunderState, NewTransactions, Payment, PaymentStage};
+    use crate::token_channel::TcMutation;
+    use crate::types::create_pending_transaction;
+
+    use crate::handler::tests::utils::dummy_named_relay_address;
+
+    #[test]
+    fn test_handle_timer_tick_invoice_expiry() {
+        let local_pk = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let relays = vec![dummy_named_relay_address(0)];
+        let mut state = FunderState::<u32>::new(local_pk, relays);
+
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let invoice_id1 = InvoiceId::from(&[1; InvoiceId::len()]);
+        let invoice_id2 = InvoiceId::from(&[2; InvoiceId::len()]);
+
+        // An invoice that expires after two ticks:
+        state.mutate(&FunderMutation::AddInvoice((
+            invoice_id1.clone(),
+            currency.clone(),
+            100,
+            PlainLock::from(&[0; PlainLock::len()]),
+            Some(2),
+        )));
+        // An invoice that never expires:
+        state.mutate(&FunderMutation::AddInvoice((
+            invoice_id2.clone(),
+            currency,
+            100,
+            PlainLock::from(&[0; PlainLock::len()]),
+            None,
+        )));
+
+        let request_origins = RequestOrigins::from_state(&state);
+        let mut m_state = MutableFunderState::new(state, request_origins);
+        let mut m_ephemeral = MutableEphemeral::new(Ephemeral::new());
+        let mut send_commands = SendCommands::new();
+        let mut outgoing_control = Vec::new();
+        let rng = DummyRandom::new(&[1u8]);
+
+        init_invoices_expiry(&m_state, &mut m_ephemeral);
+        assert_eq!(m_ephemeral.ephemeral().invoices.ticks_left.len(), 1);
+
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            &mut outgoing_control,
+            &rng,
+            0,
+        );
+        assert!(m_state.state().open_invoices.contains_key(&invoice_id1));
+
+        // The first invoice expires:
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            &mut outgoing_control,
+            &rng,
+            0,
+        );
+        assert!(!m_state.state().open_invoices.contains_key(&invoice_id1));
+        assert!(m_state.state().open_invoices.contains_key(&invoice_id2));
+        assert!(m_ephemeral.ephemeral().invoices.ticks_left.is_empty());
+    }
+
+    #[test]
+    fn test_handle_timer_tick_request_expiry() {
+        let local_pk = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let friend_pk = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let dest_pk = PublicKey::from(&[0xcc; PublicKey::len()]);
+        let relays = vec![dummy_named_relay_address(0)];
+        let mut state = FunderState::<u32>::new(local_pk, relays);
+
+        state.mutate(&FunderMutation::AddFriend(AddFriend {
+            friend_public_key: friend_pk.clone(),
+            relays: Vec::new(),
+            name: "friend".into(),
+        }));
+
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let request_id = Uid::from(&[1; Uid::len()]);
+        let payment_id = PaymentId::from(&[2; PaymentId::len()]);
+        let invoice_id = InvoiceId::from(&[3; InvoiceId::len()]);
+        let src_plain_lock = PlainLock::from(&[4; PlainLock::len()]);
+
+        // A payment with a single transaction, waiting in the user requests queue:
+        state.mutate(&FunderMutation::UpdatePayment((
+            payment_id.clone(),
+            Payment {
+                src_plain_lock: src_plain_lock.clone(),
+                stage: PaymentStage::NewTransactions(NewTransactions {
+                    num_transactions: 1,
+                    invoice_id: invoice_id.clone(),
+                    currency: currency.clone(),
+                    total_dest_payment: 10,
+                    dest_public_key: dest_pk.clone(),
+                    opt_max_fee_per_hop: None,
+                }),
+            },
+        )));
+        state.mutate(&FunderMutation::AddTransaction((
+            request_id.clone(),
+            payment_id,
+            0,
+        )));
+
+        let request_send_funds = RequestSendFundsOp {
+            request_id: request_id.clone(),
+            src_hashed_lock: src_plain_lock.hash_lock(),
+            route: FriendsRoute {
+                public_keys: vec![dest_pk],
+            },
+            dest_payment: 10,
+            total_dest_payment: 10,
+            invoice_id,
+            left_fees: 0,
+            expiry_ticks: 2,
+            refund_ticks: 0,
+            opt_exchange: None,
+        };
+        let friend_mutation =
+            FriendMutation::PushBackPendingUserRequest((currency, request_send_funds));
+        state.mutate(&FunderMutation::FriendMutation((
+            friend_pk.clone(),
+            friend_mutation,
+        )));
+
+        let request_origins = RequestOrigins::from_state(&state);
+        let mut m_state = MutableFunderState::new(state, request_origins);
+        let mut m_ephemeral = MutableEphemeral::new(Ephemeral::new());
+        let mut send_commands = SendCommands::new();
+        let mut outgoing_control = Vec::new();
+        let rng = DummyRandom::new(&[1u8]);
+
+        let pending_user_requests_len = |m_state: &MutableFunderState<u32>| {
+            let friend = m_state.state().friends.get(&friend_pk).unwrap();
+            match &friend.channel_status {
+                ChannelStatus::Consistent(channel_consistent) => {
+                    channel_consistent.pending_user_requests.len()
+                }
+                ChannelStatus::Inconsistent(_) => unreachable!(),
+            }
+        };
+
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            &mut outgoing_control,
+            &rng,
+            0,
+        );
+        assert_eq!(pending_user_requests_len(&m_state), 1);
+        assert_eq!(
+            m_ephemeral
+                .ephemeral()
+                .requests_expiry
+                .ticks_left
+                .get(&request_id),
+            Some(&1)
+        );
+        assert!(outgoing_control.is_empty());
+
+        // The request expires, and the transaction fails (No retries are left):
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            &mut outgoing_control,
+            &rng,
+            0,
+        );
+        assert_eq!(pending_user_requests_len(&m_state), 0);
+        assert!(m_ephemeral
+            .ephemeral()
+            .requests_expiry
+            .ticks_left
+            .is_empty());
+        assert!(!m_state.state().open_transactions.contains_key(&request_id));
+        match &outgoing_control[0] {
+            FunderOutgoingControl::TransactionResult(transaction_result) => {
+                assert_eq!(transaction_result.request_id, request_id);
+                assert_eq!(transaction_result.result, RequestResult::Failure);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_handle_timer_tick_refund() {
+        let local_pk = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let friend_pk = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let dest_pk = PublicKey::from(&[0xcc; PublicKey::len()]);
+        let relays = vec![dummy_named_relay_address(0)];
+        let mut state = FunderState::<u32>::new(local_pk, relays);
+
+        state.mutate(&FunderMutation::AddFriend(AddFriend {
+            friend_public_key: friend_pk.clone(),
+            relays: Vec::new(),
+            name: "friend".into(),
+        }));
+
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let request_id = Uid::from(&[1; Uid::len()]);
+        let payment_id = PaymentId::from(&[2; PaymentId::len()]);
+        let invoice_id = InvoiceId::from(&[3; InvoiceId::len()]);
+        let src_plain_lock = PlainLock::from(&[4; PlainLock::len()]);
+
+        // The friend supports refunds:
+        state.mutate(&FunderMutation::FriendMutation((
+            friend_pk.clone(),
+            FriendMutation::SetRemoteCapabilities(FriendCapabilities {
+                currencies: vec![currency.clone()],
+                features: 0,
+                max_message_size: 0x10000,
+                protocol_version: 1,
+            }),
+        )));
+
+        for tc_mutation in vec![
+            TcMutation::SetLocalActiveCurrencies(vec![currency.clone()]),
+            TcMutation::SetRemoteActiveCurrencies(vec![currency.clone()]),
+            TcMutation::AddMutualCredit(currency.clone()),
+        ] {
+            state.mutate(&FunderMutation::FriendMutation((
+                friend_pk.clone(),
+                FriendMutation::TcMutation(tc_mutation),
+            )));
+        }
+
+        // A payment with a single transaction, already sent to the friend:
+        state.mutate(&FunderMutation::UpdatePayment((
+            payment_id.clone(),
+            Payment {
+                src_plain_lock: src_plain_lock.clone(),
+                stage: PaymentStage::NewTransactions(NewTransactions {
+                    num_transactions: 1,
+                    invoice_id: invoice_id.clone(),
+                    currency: currency.clone(),
+                    total_dest_payment: 10,
+                    dest_public_key: dest_pk.clone(),
+                    opt_max_fee_per_hop: None,
+                }),
+            },
+        )));
+        state.mutate(&FunderMutation::AddTransaction((
+            request_id.clone(),
+            payment_id,
+            0,
+        )));
+
+        let request_send_funds = RequestSendFundsOp {
+            request_id: request_id.clone(),
+            src_hashed_lock: src_plain_lock.hash_lock(),
+            route: FriendsRoute {
+                public_keys: vec![dest_pk],
+            },
+            dest_payment: 10,
+            total_dest_payment: 10,
+            invoice_id,
+            left_fees: 0,
+            expiry_ticks: 0,
+            refund_ticks: 2,
+            opt_exchange: None,
+        };
+        let pending_transaction = create_pending_transaction(&request_send_funds);
+        for mc_mutation in vec![
+            McMutation::InsertLocalPendingTransaction(pending_transaction),
+            McMutation::SetLocalPendingDebt(10),
+        ] {
+            state.mutate(&FunderMutation::FriendMutation((
+                friend_pk.clone(),
+                FriendMutation::TcMutation(TcMutation::McMutation((currency.clone(), mc_mutation))),
+            )));
+        }
+
+        let request_origins = RequestOrigins::from_state(&state);
+        let mut m_state = MutableFunderState::new(state, request_origins);
+        let mut m_ephemeral = MutableEphemeral::new(Ephemeral::new());
+        let mut send_commands = SendCommands::new();
+        let mut outgoing_control = Vec::new();
+        let rng = DummyRandom::new(&[1u8]);
+
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            &mut outgoing_control,
+            &rng,
+            0,
+        );
+        assert!(!is_refund_queued(&m_state, &friend_pk, &request_id));
+        assert_eq!(
+            m_ephemeral.ephemeral().refunds.ticks_left.get(&request_id),
+            Some(&1)
+        );
+        assert!(outgoing_control.is_empty());
+
+        // The refund countdown ends. A refund is queued, and the transaction fails (No retries
+        // are left):
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            &mut outgoing_control,
+            &rng,
+            0,
+        );
+        assert!(is_refund_queued(&m_state, &friend_pk, &request_id));
+        assert!(m_ephemeral.ephemeral().refunds.ticks_left.is_empty());
+        assert!(!m_state.state().open_transactions.contains_key(&request_id));
+        match &outgoing_control[0] {
+            FunderOutgoingControl::TransactionResult(transaction_result) => {
+                assert_eq!(transaction_result.request_id, request_id);
+                assert_eq!(transaction_result.result, RequestResult::Failure);
+            }
+            _ => unreachable!(),
+        }
+
+        // The countdown does not start again while the refund is queued:
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            &mut outgoing_control,
+            &rng,
+            0,
+        );
+        assert!(m_ephemeral.ephemeral().refunds.ticks_left.is_empty());
+        assert_eq!(outgoing_control.len(), 1);
+    }
+
+    #[test]
+    fn test_handle_timer_tick_gating() {
+        let local_pk = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let friend_pk = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let relays = vec![dummy_named_relay_address(0)];
+        let mut state = FunderState::<u32>::new(local_pk, relays);
+
+        state.mutate(&FunderMutation::AddFriend(AddFriend {
+            friend_public_key: friend_pk.clone(),
+            relays: Vec::new(),
+            name: "friend".into(),
+        }));
+
+        let request_origins = RequestOrigins::from_state(&state);
+        let mut m_state = MutableFunderState::new(state, request_origins);
+        let mut m_ephemeral = MutableEphemeral::new(Ephemeral::new());
+        let mut send_commands = SendCommands::new();
+        let mut outgoing_control = Vec::new();
+        let rng = DummyRandom::new(&[1u8]);
+
+        // The friend is offline, its uptime is 0%:
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            &mut outgoing_control,
+            &rng,
+            50,
+        );
+        assert!(m_ephemeral.ephemeral().liveness.is_gated(&friend_pk));
+
+        m_ephemeral.mutate(EphemeralMutation::LivenessMutation(
+            LivenessMutation::SetOnline(friend_pk.clone()),
+        ));
+
+        // Uptime of 50% is enough:
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            &mut outgoing_control,
+            &rng,
+            50,
+        );
+        assert_eq!(
+            m_ephemeral.ephemeral().liveness.uptime_percent(&friend_pk),
+            Some(50)
+        );
+        assert!(!m_ephemeral.ephemeral().liveness.is_gated(&friend_pk));
+
+        // Gating is disabled with a threshold of 0%:
+        m_ephemeral.mutate(EphemeralMutation::LivenessMutation(
+            LivenessMutation::SetOffline(friend_pk.clone()),
+        ));
+        for _ in 0..4 {
+            handle_timer_tick(
+                &mut m_state,
+                &mut m_ephemeral,
+                &mut send_commands,
+                &mut outgoing_control,
+                &rng,
+                0,
+            );
+        }
+        assert!(!m_ephemeral.ephemeral().liveness.is_gated(&friend_pk));
+
+        // Friends of a listen only node are not gated, even with a low uptime:
+        m_ephemeral.mutate(EphemeralMutation::LivenessMutation(
+            LivenessMutation::SetListenOnly,
+        ));
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            &mut outgoing_control,
+            &rng,
+            50,
+        );
+        assert!(m_ephemeral.ephemeral().liveness.uptime_percent(&friend_pk) < Some(50));
+        assert!(!m_ephemeral.ephemeral().liveness.is_gated(&friend_pk));
+        assert!(outgoing_control.is_empty());
+    }
+
+    #[test]
+    fn test_handle_timer_tick_outflows() {
+        let local_pk = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let friend_pk = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let relays = vec![dummy_named_relay_address(0)];
+        let state = FunderState::<u32>::new(local_pk, relays);
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+
+        let request_origins = RequestOrigins::from_state(&state);
+        let mut m_state = MutableFunderState::new(state, request_origins);
+        let mut m_ephemeral = MutableEphemeral::new(Ephemeral::new());
+        let mut send_commands = SendCommands::new();
+        let mut outgoing_control = Vec::new();
+        let rng = DummyRandom::new(&[1u8]);
+
+        // A window that ends after two ticks:
+        m_ephemeral.mutate(EphemeralMutation::OutflowsMutation(
+            OutflowsMutation::SetWindow((
+                friend_pk.clone(),
+                currency.clone(),
+                OutflowWindow {
+                    outflow: 30,
+                    ticks_left: 2,
+                },
+            )),
+        ));
+
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            &mut outgoing_control,
+            &rng,
+            0,
+        );
+        assert_eq!(
+            m_ephemeral
+                .ephemeral()
+                .outflows
+                .outflow(&friend_pk, &currency),
+            30
+        );
+
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            &mut outgoing_control,
+            &rng,
+            0,
+        );
+        assert_eq!(
+            m_ephemeral
+                .ephemeral()
+                .outflows
+                .outflow(&friend_pk, &currency),
+            0
+        );
+        assert!(m_ephemeral.ephemeral().outflows.windows.is_empty());
+    }
+}
@@ -3,27 +3,31 @@ use std::hash::Hash;
 
 use signature::canonical::CanonicalSerialize;
 
-use crypto::rand::CryptoRandom;
+use crypto::rand::{CryptoRandom, RandGen};
 
 use proto::app_server::messages::RelayAddress;
 use proto::crypto::Uid;
-use proto::funder::messages::FunderOutgoingControl;
+use proto::funder::messages::{FunderOutgoingControl, RequestError};
 use proto::report::messages::{FunderReportMutation, FunderReportMutations};
 
-use identity::IdentityClient;
+use identity::{SignatureBackend, SignatureBackendError};
 
 use crate::state::{FunderMutation, FunderState};
 
-use crate::handler::handle_control::handle_control_message;
+use crate::handler::handle_control::{handle_control_message, request_error_code};
 use crate::handler::handle_friend::{handle_friend_message, HandleFriendError};
-use crate::handler::handle_init::handle_init;
+use crate::handler::handle_init::{handle_init, resume_pending_retries};
 use crate::handler::handle_liveness::{handle_liveness_message, HandleLivenessError};
+use crate::handler::handle_timer::{handle_timer_tick, init_invoices_expiry};
 use crate::handler::sender::create_friend_messages;
 use crate::handler::state_wrap::{MutableEphemeral, MutableFunderState};
 use crate::handler::types::SendCommands;
 
 use crate::ephemeral::{Ephemeral, EphemeralMutation};
+use crate::freeze_guard::funder_mutation_to_freeze_guard_mutations;
+use crate::liveness::LivenessMutation;
 use crate::report::{ephemeral_mutation_to_report_mutations, funder_mutation_to_report_mutations};
+use crate::request_origins::funder_mutation_to_request_origins_mutations;
 use crate::types::{ChannelerConfig, FunderIncoming, FunderIncomingComm, FunderOutgoingComm};
 
 #[derive(Debug)]
@@ -31,6 +35,8 @@ pub enum FunderHandlerError {
     // HandleControlError(HandleControlError),
     HandleFriendError(HandleFriendError),
     HandleLivenessError(HandleLivenessError),
+    /// Signing a move token or a response has failed. None of the changes are applied.
+    SignatureBackendError(SignatureBackendError),
 }
 
 pub struct FunderHandlerOutput<B>
@@ -55,6 +61,11 @@ pub fn funder_handle_incoming<B, R>(
     rng: &R,
     max_node_relays: usize,
     max_pending_user_requests: usize,
+    max_route_len: usize,
+    max_transaction_retries: u64,
+    request_expiry_ticks: u64,
+    min_friend_uptime_percent: u8,
+    opt_auto_reset_tolerance: Option<u128>,
     funder_incoming: FunderIncoming<B>,
 ) -> Result<FunderHandleIncomingOutput<B>, FunderHandlerError>
 where
@@ -68,6 +79,20 @@ where
     let opt_app_request_id = match funder_incoming {
         FunderIncoming::Init => {
             handle_init(&m_state, &mut outgoing_channeler_config);
+            init_invoices_expiry(&m_state, &mut m_ephemeral);
+            resume_pending_retries(&m_state, &mut outgoing_control, rng);
+            None
+        }
+
+        FunderIncoming::TimerTick => {
+            handle_timer_tick(
+                &mut m_state,
+                &mut m_ephemeral,
+                &mut send_commands,
+                &mut outgoing_control,
+                rng,
+                min_friend_uptime_percent,
+            );
             None
         }
 
@@ -83,9 +108,17 @@ where
                 rng,
                 max_node_relays,
                 max_pending_user_requests,
+                max_route_len,
+                max_transaction_retries,
+                request_expiry_ticks,
                 funder_incoming_control.funder_control,
             ) {
                 warn!("handle_control_error(): {:?}", e);
+                outgoing_control.push(FunderOutgoingControl::RequestError(RequestError {
+                    app_request_id: funder_incoming_control.app_request_id.clone(),
+                    error_code: request_error_code(&e),
+                    detail: format!("{:?}", e),
+                }));
             }
             Some(funder_incoming_control.app_request_id)
         }
@@ -110,6 +143,7 @@ where
                         &mut outgoing_control,
                         &mut outgoing_channeler_config,
                         rng,
+                        opt_auto_reset_tolerance,
                         &origin_public_key,
                         friend_message,
                     )
@@ -159,31 +193,43 @@ where
     report_mutations
 }
 
-pub async fn funder_handle_message<'a, B, R>(
-    identity_client: &'a mut IdentityClient,
+pub async fn funder_handle_message<'a, B, SB, R>(
+    identity_client: &'a mut SB,
     rng: &'a R,
     funder_state: FunderState<B>,
     funder_ephemeral: Ephemeral,
     max_node_relays: usize,
     max_operations_in_batch: usize,
     max_pending_user_requests: usize,
+    max_route_len: usize,
+    max_transaction_retries: u64,
+    request_expiry_ticks: u64,
+    min_friend_uptime_percent: u8,
+    opt_auto_reset_tolerance: Option<u128>,
     funder_incoming: FunderIncoming<B>,
 ) -> Result<FunderHandlerOutput<B>, FunderHandlerError>
 where
     B: 'a + Clone + PartialEq + Eq + CanonicalSerialize + Debug + Hash,
+    SB: SignatureBackend,
     R: CryptoRandom + 'a,
 {
-    let mut m_state = MutableFunderState::new(funder_state);
+    let mut m_state =
+        MutableFunderState::new(funder_state, funder_ephemeral.request_origins.clone());
     let mut m_ephemeral = MutableEphemeral::new(funder_ephemeral);
     let mut outgoing_comms = Vec::new();
 
-    let (send_commands, handle_outgoing_control, outgoing_channeler_config, opt_app_request_id) =
+    let (send_commands, mut handle_outgoing_control, outgoing_channeler_config, opt_app_request_id) =
         funder_handle_incoming(
             &mut m_state,
             &mut m_ephemeral,
             rng,
             max_node_relays,
             max_pending_user_requests,
+            max_route_len,
+            max_transaction_retries,
+            request_expiry_ticks,
+            min_friend_uptime_percent,
+            opt_auto_reset_tolerance,
             funder_incoming,
         )?;
 
@@ -192,7 +238,10 @@ where
     }
 
     // Sign all unsigned responses and then queue them as mutations
-    m_state.sign_responses(identity_client, rng).await;
+    m_state
+        .sign_responses(identity_client, rng)
+        .await
+        .map_err(FunderHandlerError::SignatureBackendError)?;
 
     // Send all possible messages according to SendCommands
     // TODO: Maybe we should output outgoing_comms instead of friend_messages and
@@ -201,21 +250,53 @@ where
         &mut m_state,
         m_ephemeral.ephemeral(),
         &send_commands,
+        &mut handle_outgoing_control,
         max_operations_in_batch,
         identity_client,
         rng,
     )
-    .await;
+    .await
+    .map_err(FunderHandlerError::SignatureBackendError)?;
 
     for channeler_config in outgoing_channeler_config {
         outgoing_comms.push(FunderOutgoingComm::ChannelerConfig(channeler_config));
     }
 
-    for friend_message in friend_messages {
-        outgoing_comms.push(FunderOutgoingComm::FriendMessage(friend_message));
+    for (friend_public_key, friend_message) in friend_messages {
+        // The Channeler reports the delivery of the message using this id:
+        let message_id = Uid::rand_gen(rng);
+        let liveness_mutation =
+            LivenessMutation::SetLastMessage((friend_public_key.clone(), message_id.clone()));
+        m_ephemeral.mutate(EphemeralMutation::LivenessMutation(liveness_mutation));
+        outgoing_comms.push(FunderOutgoingComm::FriendMessage((
+            friend_public_key,
+            message_id,
+            friend_message,
+        )));
     }
 
     let (initial_state, funder_mutations, _state) = m_state.done();
+
+    // Keep the indices of the ephemeral in sync with the funder state:
+    for funder_mutation in &funder_mutations {
+        for request_origins_mutation in
+            funder_mutation_to_request_origins_mutations(funder_mutation)
+        {
+            m_ephemeral.mutate(EphemeralMutation::RequestOriginsMutation(
+                request_origins_mutation,
+            ));
+        }
+        // The origin of a forwarded request is known only after its request origin was inserted:
+        for freeze_guard_mutation in funder_mutation_to_freeze_guard_mutations(
+            funder_mutation,
+            &m_ephemeral.ephemeral().request_origins,
+        ) {
+            m_ephemeral.mutate(EphemeralMutation::FreezeGuardMutation(
+                freeze_guard_mutation,
+            ));
+        }
+    }
+
     let (ephemeral_mutations, _ephemeral) = m_ephemeral.done();
 
     // Add reports:
@@ -230,7 +311,15 @@ where
         mutations: report_mutations,
     };
 
-    let mut outgoing_control = Vec::new();
+    // A request error is sent before the acknowledgement of its request, so that an app
+    // waiting for the acknowledgement already knows that the request has failed:
+    let (mut outgoing_control, handle_outgoing_control): (Vec<_>, Vec<_>) = handle_outgoing_control
+        .into_iter()
+        .partition(|outgoing_control| match outgoing_control {
+            FunderOutgoingControl::RequestError(_) => true,
+            _ => false,
+        });
+
     if !funder_report_mutations.mutations.is_empty()
         || funder_report_mutations.opt_app_request_id.is_some()
     {
@@ -1,10 +1,13 @@
+mod auto_reset;
 mod canceler;
 mod handle_control;
 mod handle_friend;
 mod handle_init;
 mod handle_liveness;
+mod handle_timer;
 mod handler;
 mod prepare;
+mod quote;
 mod sender;
 mod state_wrap;
 mod types;
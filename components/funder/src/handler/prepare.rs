@@ -5,6 +5,26 @@ use proto::funder::messages::{
     TransactionStage,
 };
 
+/// The terms of the invoice the destination of `pending_transaction` signed over: Currency,
+/// payment and total payment. For an exchanged transaction these are the terms after the exchange.
+fn dest_terms(
+    currency: &Currency,
+    pending_transaction: &PendingTransaction,
+) -> (Currency, u128, u128) {
+    match &pending_transaction.opt_exchange {
+        Some(currency_exchange) => (
+            currency_exchange.dest_currency.clone(),
+            currency_exchange.dest_payment,
+            currency_exchange.total_dest_payment,
+        ),
+        None => (
+            currency.clone(),
+            pending_transaction.dest_payment,
+            pending_transaction.total_dest_payment,
+        ),
+    }
+}
+
 pub fn prepare_receipt(
     currency: &Currency,
     collect_send_funds: &CollectSendFundsOp,
@@ -17,20 +37,25 @@ pub fn prepare_receipt(
     let response_hash = hash::sha_512_256(&hash_buff);
     // = sha512/256(requestId || randNonce)
 
-    let is_complete = match &pending_transaction.stage {
-        TransactionStage::Response(_dest_hashed_lock, is_complete) => *is_complete,
+    let (is_complete, change) = match &pending_transaction.stage {
+        TransactionStage::Response(_dest_hashed_lock, is_complete, change) => {
+            (*is_complete, *change)
+        }
         _ => unreachable!(),
     };
 
+    let (currency, dest_payment, total_dest_payment) = dest_terms(currency, pending_transaction);
+
     Receipt {
         response_hash,
         invoice_id: pending_transaction.invoice_id.clone(),
-        currency: currency.clone(),
+        currency,
         src_plain_lock: collect_send_funds.src_plain_lock.clone(),
         dest_plain_lock: collect_send_funds.dest_plain_lock.clone(),
         is_complete,
-        dest_payment: pending_transaction.dest_payment,
-        total_dest_payment: pending_transaction.total_dest_payment,
+        dest_payment,
+        total_dest_payment,
+        change,
         signature: response_send_funds.signature.clone(),
     }
 }
@@ -50,12 +75,15 @@ pub fn prepare_commit(
     let response_hash = hash::sha_512_256(&hash_buff);
     // = sha512/256(requestId || randNonce)
 
+    let (currency, dest_payment, total_dest_payment) = dest_terms(&currency, pending_transaction);
+
     Commit {
         response_hash,
         src_plain_lock,
         dest_hashed_lock: response_send_funds.dest_hashed_lock.clone(),
-        dest_payment: pending_transaction.dest_payment,
-        total_dest_payment: pending_transaction.total_dest_payment,
+        dest_payment,
+        total_dest_payment,
+        change: response_send_funds.change,
         invoice_id: pending_transaction.invoice_id.clone(),
         currency,
         signature: response_send_funds.signature.clone(),
@@ -0,0 +1,255 @@
+use std::convert::TryFrom;
+use std::fmt::Debug;
+
+use signature::canonical::CanonicalSerialize;
+
+use proto::funder::messages::{
+    FriendsRoute, HopFreeze, QuotePayment, QuoteRejection, Rate, ResponseQuotePayment,
+    TransactionRejection,
+};
+
+use crate::ephemeral::Ephemeral;
+use crate::friend::ChannelStatus;
+use crate::state::FunderState;
+
+use crate::handler::utils::is_friend_ready;
+
+/// Calculate the total fees of a payment through `route`, and the credits frozen on every hop.
+///
+/// Every mediator charges a fee over `dest_payment`. The credits frozen on a hop are
+/// `dest_payment` together with the fees of all the mediators that are still ahead on the route.
+fn calc_hop_freezes(
+    route: &FriendsRoute,
+    mediator_rates: &[Rate],
+    dest_payment: u128,
+) -> Result<(u128, Vec<HopFreeze>), QuoteRejection> {
+    if mediator_rates.len() != route.len().saturating_sub(2) {
+        return Err(QuoteRejection::InvalidMediatorRates);
+    }
+
+    let mediator_fees = mediator_rates
+        .iter()
+        .map(|rate| rate.calc_fee(dest_payment))
+        .collect::<Option<Vec<_>>>()
+        .ok_or(QuoteRejection::FeesOverflow)?;
+
+    let mut hop_freezes = Vec::new();
+    let mut left_fees: u128 = 0;
+    // Iterate from the destination back to us, accumulating the fees left on the route:
+    for (index, to_public_key) in route.public_keys.iter().enumerate().skip(1).rev() {
+        let frozen_credits = dest_payment
+            .checked_add(left_fees)
+            .ok_or(QuoteRejection::FeesOverflow)?;
+        hop_freezes.push(HopFreeze {
+            to_public_key: to_public_key.clone(),
+            frozen_credits,
+        });
+        // The node we send to on this hop is a mediator, unless it is the destination:
+        if index > 1 {
+            left_fees = left_fees
+                .checked_add(mediator_fees[index - 2])
+                .ok_or(QuoteRejection::FeesOverflow)?;
+        }
+    }
+    hop_freezes.reverse();
+
+    Ok((left_fees, hop_freezes))
+}
+
+/// Checks done by the local node before queueing a new transaction to the first friend on the
+/// route (See `control_create_transaction_inner`).
+fn check_local_limits<B>(
+    state: &FunderState<B>,
+    ephemeral: &Ephemeral,
+    max_pending_user_requests: usize,
+    max_route_len: usize,
+    quote_payment: &QuotePayment,
+    fees: u128,
+    frozen_credits: u128,
+) -> Result<(), QuoteRejection>
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let route = &quote_payment.route;
+
+    if route.len() > max_route_len {
+        return Err(QuoteRejection::Rejected(
+            TransactionRejection::RouteTooLong(
+                u32::try_from(max_route_len).unwrap_or(u32::max_value()),
+            ),
+        ));
+    }
+
+    // Every node on the route, except for us and the destination, may charge fees:
+    if let Some(max_fee_per_hop) = quote_payment.opt_max_fee_per_hop {
+        let num_mediators = route.len().saturating_sub(2);
+        let max_fees = max_fee_per_hop.saturating_mul(num_mediators as u128);
+        if fees > max_fees {
+            return Err(QuoteRejection::Rejected(
+                TransactionRejection::FeesExceedCap(max_fees),
+            ));
+        }
+    }
+
+    let friend_public_key = &route.public_keys[1];
+    let friend = state
+        .friends
+        .get(friend_public_key)
+        .ok_or(QuoteRejection::FriendNotReady)?;
+
+    if !is_friend_ready(state, ephemeral, friend_public_key, &quote_payment.currency) {
+        return Err(QuoteRejection::FriendNotReady);
+    }
+
+    let channel_consistent = match &friend.channel_status {
+        ChannelStatus::Inconsistent(_) => return Err(QuoteRejection::FriendNotReady),
+        ChannelStatus::Consistent(channel_consistent) => channel_consistent,
+    };
+
+    if channel_consistent.pending_user_requests.len() >= max_pending_user_requests {
+        return Err(QuoteRejection::PendingUserRequestsFull);
+    }
+
+    if let Some(max_outflow) = friend
+        .currency_configs
+        .get(&quote_payment.currency)
+        .and_then(|currency_config| currency_config.opt_max_outflow.as_ref())
+    {
+        if ephemeral
+            .outflows
+            .charge(
+                friend_public_key,
+                &quote_payment.currency,
+                max_outflow,
+                frozen_credits,
+            )
+            .is_none()
+        {
+            return Err(QuoteRejection::MaxOutflowExceeded);
+        }
+    }
+
+    Ok(())
+}
+
+/// Quote a payment through a route, without changing any state.
+/// Reports the fees and frozen credits of the payment, and whether the local node would reject
+/// it.
+pub fn calc_quote<B>(
+    state: &FunderState<B>,
+    ephemeral: &Ephemeral,
+    max_pending_user_requests: usize,
+    max_route_len: usize,
+    quote_payment: QuotePayment,
+) -> ResponseQuotePayment
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let route = &quote_payment.route;
+
+    // We have to be the first on the route, and the route must be valid:
+    let is_first = route
+        .public_keys
+        .first()
+        .map_or(false, |first| first == &state.local_public_key);
+    let hop_freezes_res = if !is_first || !route.is_valid() {
+        Err(QuoteRejection::InvalidRoute)
+    } else {
+        calc_hop_freezes(
+            route,
+            &quote_payment.mediator_rates,
+            quote_payment.dest_payment,
+        )
+    };
+
+    let (fees, hop_freezes, opt_rejection) = match hop_freezes_res {
+        Ok((fees, hop_freezes)) => {
+            let opt_rejection = check_local_limits(
+                state,
+                ephemeral,
+                max_pending_user_requests,
+                max_route_len,
+                &quote_payment,
+                fees,
+                hop_freezes[0].frozen_credits,
+            )
+            .err();
+            (fees, hop_freezes, opt_rejection)
+        }
+        Err(rejection) => (0, Vec::new(), Some(rejection)),
+    };
+
+    ResponseQuotePayment {
+        request_id: quote_payment.request_id,
+        fees,
+        hop_freezes,
+        opt_rejection,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proto::crypto::PublicKey;
+
+    #[test]
+    fn test_calc_hop_freezes() {
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let pk_c = PublicKey::from(&[0xcc; PublicKey::len()]);
+        let pk_d = PublicKey::from(&[0xdd; PublicKey::len()]);
+
+        let route = FriendsRoute {
+            public_keys: vec![pk_a.clone(), pk_b.clone(), pk_c.clone(), pk_d.clone()],
+        };
+        let mediator_rates = vec![Rate { mul: 0, add: 1 }, Rate { mul: 0, add: 2 }];
+        let (fees, hop_freezes) = calc_hop_freezes(&route, &mediator_rates, 10).unwrap();
+        assert_eq!(fees, 3);
+        assert_eq!(
+            hop_freezes,
+            vec![
+                HopFreeze {
+                    to_public_key: pk_b.clone(),
+                    frozen_credits: 13,
+                },
+                HopFreeze {
+                    to_public_key: pk_c.clone(),
+                    frozen_credits: 12,
+                },
+                HopFreeze {
+                    to_public_key: pk_d.clone(),
+                    frozen_credits: 10,
+                },
+            ]
+        );
+
+        // A direct payment to a friend has no fees:
+        let route = FriendsRoute {
+            public_keys: vec![pk_a.clone(), pk_b.clone()],
+        };
+        let (fees, hop_freezes) = calc_hop_freezes(&route, &[], 10).unwrap();
+        assert_eq!(fees, 0);
+        assert_eq!(
+            hop_freezes,
+            vec![HopFreeze {
+                to_public_key: pk_b.clone(),
+                frozen_credits: 10,
+            }]
+        );
+
+        // Every mediator must have a rate:
+        assert_eq!(
+            calc_hop_freezes(&route, &mediator_rates, 10),
+            Err(QuoteRejection::InvalidMediatorRates)
+        );
+
+        let route = FriendsRoute {
+            public_keys: vec![pk_a, pk_b, pk_c],
+        };
+        assert_eq!(
+            calc_hop_freezes(&route, &[Rate { mul: 0, add: 1 }], u128::max_value()),
+            Err(QuoteRejection::FeesOverflow)
+        );
+    }
+}
@@ -9,16 +9,19 @@ use signature::canonical::CanonicalSerialize;
 use crypto::rand::{CryptoRandom, RandGen};
 
 use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
-use proto::crypto::{PublicKey, RandValue};
+use proto::crypto::{PublicKey, RandValue, Uid};
 use proto::funder::messages::{
     BalanceInfo, ChannelerUpdateFriend, CountersInfo, Currency, CurrencyBalanceInfo,
-    CurrencyOperations, FriendMessage, FriendTcOp, McInfo, MoveTokenRequest, TokenInfo,
+    CurrencyOperations, FriendMessage, FriendTcOp, FunderOutgoingControl, McInfo, MoveTokenRequest,
+    RequestSendFundsOp, TokenInfo,
 };
 
-use identity::IdentityClient;
+use identity::{SignatureBackend, SignatureBackendError};
 
 use crate::mutual_credit::outgoing::{OutgoingMc, QueueOperationError};
-use crate::types::{create_unsigned_move_token, sign_move_token, ChannelerConfig};
+use crate::types::{
+    create_cancel_send_funds, create_unsigned_move_token, sign_move_token, ChannelerConfig,
+};
 
 use crate::friend::{
     BackwardsOp, ChannelInconsistent, ChannelStatus, CurrencyConfig, FriendMutation,
@@ -27,6 +30,7 @@ use crate::friend::{
 use crate::token_channel::{SendMoveTokenOutput, SetDirection, TcMutation, TokenChannel};
 
 use crate::ephemeral::Ephemeral;
+use crate::handler::canceler::cancel_request;
 use crate::handler::state_wrap::MutableFunderState;
 use crate::handler::types::{FriendSendCommands, SendCommands};
 use crate::state::{FunderMutation, FunderState};
@@ -36,6 +40,7 @@ pub type OutgoingMessage<B> = (PublicKey, FriendMessage<B>);
 #[derive(Debug)]
 enum PendingQueueError {
     MaxOperationsReached,
+    UnsupportedOperation,
 }
 
 #[derive(Debug)]
@@ -110,6 +115,12 @@ where
             .friends
             .get(&self.friend_public_key)
             .unwrap();
+
+        // Never send an operation the remote friend can not handle:
+        if !friend.supports_op(operation) {
+            return Err(PendingQueueError::UnsupportedOperation);
+        }
+
         let token_channel = match &friend.channel_status {
             ChannelStatus::Consistent(channel_consistent) => &channel_consistent.token_channel,
             ChannelStatus::Inconsistent(_) => unreachable!(),
@@ -185,6 +196,7 @@ fn transmit_outgoing<B>(
     let move_token_request = MoveTokenRequest {
         move_token,
         token_wanted,
+        opt_capabilities: Some(friend.local_capabilities()),
     };
 
     outgoing_messages.push((
@@ -193,14 +205,16 @@ fn transmit_outgoing<B>(
     ));
 }
 
-pub async fn apply_local_reset<'a, B, R>(
+pub async fn apply_local_reset<'a, B, SB, R>(
     m_state: &'a mut MutableFunderState<B>,
     friend_public_key: &'a PublicKey,
     channel_inconsistent: &'a ChannelInconsistent,
-    identity_client: &'a mut IdentityClient,
+    identity_client: &'a mut SB,
     rng: &'a R,
-) where
+) -> Result<(), SignatureBackendError>
+where
     B: Clone + CanonicalSerialize + PartialEq + Eq + Debug + Hash,
+    SB: SignatureBackend,
     R: CryptoRandom,
 {
     // TODO: How to do this without unwrap?:
@@ -280,6 +294,13 @@ pub async fn apply_local_reset<'a, B, R>(
         },
     };
 
+    let signature_version = m_state
+        .state()
+        .friends
+        .get(friend_public_key)
+        .unwrap()
+        .signature_version();
+
     let rand_nonce = RandValue::rand_gen(rng);
     let u_reset_move_token = create_unsigned_move_token(
         // No operations are required for a reset move token
@@ -289,9 +310,10 @@ pub async fn apply_local_reset<'a, B, R>(
         &token_info,
         remote_reset_terms.reset_token.clone(),
         rand_nonce,
+        signature_version,
     );
 
-    let reset_move_token = sign_move_token(u_reset_move_token, identity_client).await;
+    let reset_move_token = sign_move_token(u_reset_move_token, identity_client).await?;
 
     let token_channel = TokenChannel::new_from_local_reset(
         &reset_move_token,
@@ -324,20 +346,26 @@ pub async fn apply_local_reset<'a, B, R>(
             m_state.mutate(funder_mutation);
         }
     }
+    Ok(())
 }
 
-async fn send_friend_iter1<'a, B, R>(
+async fn send_friend_iter1<'a, B, SB, R>(
     m_state: &'a mut MutableFunderState<B>,
     friend_public_key: &'a PublicKey,
     friend_send_commands: &'a FriendSendCommands,
     pending_move_tokens: &'a mut HashMap<PublicKey, PendingMoveToken<B>>,
-    identity_client: &'a mut IdentityClient,
+    cancel_send_commands: &'a mut SendCommands,
+    outgoing_control: &'a mut Vec<FunderOutgoingControl<B>>,
+    identity_client: &'a mut SB,
     rng: &'a R,
     max_operations_in_batch: usize,
+    ephemeral: &'a Ephemeral,
     mut outgoing_messages: &'a mut Vec<OutgoingMessage<B>>,
     outgoing_channeler_config: &'a mut Vec<ChannelerConfig<RelayAddress<B>>>,
-) where
+) -> Result<(), SignatureBackendError>
+where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug + Hash,
+    SB: SignatureBackend,
     R: CryptoRandom,
 {
     // If we got here, it must be because some send_commands attribute was set:
@@ -347,12 +375,17 @@ async fn send_friend_iter1<'a, B, R>(
             || friend_send_commands.resend_outgoing
             || friend_send_commands.remote_wants_token
             || friend_send_commands.local_reset
+            || friend_send_commands.send_key_rotation
     );
 
+    // While our key rotation is pending, we do not sign new move tokens. The remote friend
+    // expects move tokens signed by our new key:
+    let is_key_rotation_pending = m_state.state().is_key_rotation_pending();
+
     let friend = m_state.state().friends.get(friend_public_key).unwrap();
 
     // Check if we need to perform a local reset:
-    if friend_send_commands.local_reset {
+    if friend_send_commands.local_reset && !is_key_rotation_pending {
         if let ChannelStatus::Inconsistent(channel_inconsistent) = &friend.channel_status {
             let c_channel_inconsistent = channel_inconsistent.clone();
             apply_local_reset(
@@ -362,7 +395,7 @@ async fn send_friend_iter1<'a, B, R>(
                 identity_client,
                 rng,
             )
-            .await;
+            .await?;
 
             // TODO: Is the token wanted after reset?
             let is_token_wanted = true;
@@ -372,7 +405,7 @@ async fn send_friend_iter1<'a, B, R>(
                 is_token_wanted,
                 &mut outgoing_messages,
             );
-            return;
+            return Ok(());
         } else {
             unreachable!();
         }
@@ -391,7 +424,7 @@ async fn send_friend_iter1<'a, B, R>(
                     ),
                 ));
             }
-            return;
+            return Ok(());
         }
     };
 
@@ -405,8 +438,11 @@ async fn send_friend_iter1<'a, B, R>(
         // (Currently the token is at the remote side)
         let is_pending = estimate_should_send(m_state.state(), friend_public_key);
         if is_pending || friend_send_commands.resend_outgoing {
-            let is_token_wanted =
-                is_pending || tc_outgoing.move_token_out.opt_local_relays.is_some();
+            // A listen only node is reachable only while the friend is connected to it, so it
+            // asks for the token whenever it can:
+            let is_token_wanted = is_pending
+                || tc_outgoing.move_token_out.opt_local_relays.is_some()
+                || ephemeral.liveness.listen_only;
             transmit_outgoing(
                 m_state,
                 &friend_public_key,
@@ -415,7 +451,11 @@ async fn send_friend_iter1<'a, B, R>(
             );
         }
 
-        return;
+        return Ok(());
+    }
+
+    if is_key_rotation_pending {
+        return Ok(());
     }
 
     // If we are here, the token channel is incoming:
@@ -442,11 +482,16 @@ async fn send_friend_iter1<'a, B, R>(
 
     let _ = collect_outgoing_move_token(
         m_state,
+        ephemeral,
         outgoing_channeler_config,
+        cancel_send_commands,
+        outgoing_control,
+        rng,
         friend_public_key,
         pending_move_token,
         friend_send_commands.resend_relays,
     );
+    Ok(())
 }
 
 /// Do we need to send anything to the remote side?
@@ -498,14 +543,18 @@ where
 /// Queue an operation to a PendingMoveToken.
 /// On cancel, queue a Cancel message to the relevant friend,
 /// or (if we are the origin of the request): send a failure notification through the control.
-fn queue_operation<'a, B>(
+fn queue_operation<'a, B, R>(
     m_state: &'a mut MutableFunderState<B>,
     pending_move_token: &'a mut PendingMoveToken<B>,
+    cancel_send_commands: &'a mut SendCommands,
+    outgoing_control: &'a mut Vec<FunderOutgoingControl<B>>,
+    rng: &'a R,
     currency: &Currency,
     operation: &'a FriendTcOp,
 ) -> Result<(), CollectOutgoingError>
 where
     B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+    R: CryptoRandom,
 {
     match pending_move_token.queue_operation(currency, operation, m_state) {
         Ok(()) => Ok(()),
@@ -514,6 +563,68 @@ where
             // We will send this message next time we have the token:
             Err(CollectOutgoingError::MaxOperationsReached)
         }
+        Err(PendingQueueError::UnsupportedOperation) => {
+            // Operations a friend does not support are cancelled when they are queued, or when
+            // the capabilities of the friend change (See `cancel_unsupported_operations`).
+            // Anything left is answered here, so that it will not block the rest of the queue:
+            warn!(
+                "Operation is not supported by friend {:?}: {:?}",
+                pending_move_token.friend_public_key, operation
+            );
+            match operation {
+                FriendTcOp::RequestSendFunds(request_send_funds) => {
+                    let friend_public_key = pending_move_token.friend_public_key.clone();
+                    cancel_request(
+                        m_state,
+                        cancel_send_commands,
+                        outgoing_control,
+                        rng,
+                        &friend_public_key,
+                        currency,
+                        request_send_funds,
+                    );
+                    Ok(())
+                }
+                FriendTcOp::ResponseSendFunds(response_send_funds) => {
+                    // The request is canceled instead:
+                    remove_incoming_transaction(m_state, &response_send_funds.request_id);
+                    let cancel_op = FriendTcOp::CancelSendFunds(create_cancel_send_funds(
+                        response_send_funds.request_id.clone(),
+                    ));
+                    queue_operation(
+                        m_state,
+                        pending_move_token,
+                        cancel_send_commands,
+                        outgoing_control,
+                        rng,
+                        currency,
+                        &cancel_op,
+                    )
+                }
+                // A friend that does not support refunds resolves the request the usual way:
+                FriendTcOp::RefundSendFunds(_) => Ok(()),
+                // Supported by all friends:
+                FriendTcOp::CancelSendFunds(_) | FriendTcOp::CollectSendFunds(_) => unreachable!(),
+            }
+        }
+    }
+}
+
+/// If we are the destination of a request, stop counting it toward the payment of its invoice.
+fn remove_incoming_transaction<B>(m_state: &mut MutableFunderState<B>, request_id: &Uid)
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let opt_invoice_id = m_state
+        .state()
+        .open_invoices
+        .iter()
+        .find(|(_, open_invoice)| open_invoice.incoming_transactions.contains(request_id))
+        .map(|(invoice_id, _)| invoice_id.clone());
+    if let Some(invoice_id) = opt_invoice_id {
+        let funder_mutation =
+            FunderMutation::RemoveIncomingTransaction((invoice_id, request_id.clone()));
+        m_state.mutate(funder_mutation);
     }
 }
 
@@ -526,21 +637,55 @@ fn backwards_op_to_friend_tc_op(backwards_op: BackwardsOp) -> FriendTcOp {
         BackwardsOp::Collect(collect_send_funds) => {
             FriendTcOp::CollectSendFunds(collect_send_funds)
         }
+        BackwardsOp::Refund(refund_send_funds) => FriendTcOp::RefundSendFunds(refund_send_funds),
+    }
+}
+
+/// Set the amount of ticks a queued request has left until it expires, before sending it to the
+/// next node on the route.
+fn set_request_expiry_ticks(ephemeral: &Ephemeral, request_send_funds: &mut RequestSendFundsOp) {
+    if let Some(ticks_left) = ephemeral
+        .requests_expiry
+        .ticks_left
+        .get(&request_send_funds.request_id)
+    {
+        request_send_funds.expiry_ticks = *ticks_left;
+    }
+}
+
+/// Set the amount of ticks a forwarded request has left until we may refund it, before sending it
+/// to the next node on the route.
+fn set_request_refund_ticks(ephemeral: &Ephemeral, request_send_funds: &mut RequestSendFundsOp) {
+    if request_send_funds.refund_ticks == 0 {
+        return;
+    }
+    match ephemeral
+        .refunds
+        .ticks_left
+        .get(&request_send_funds.request_id)
+    {
+        Some(ticks_left) if *ticks_left > 0 => request_send_funds.refund_ticks = *ticks_left,
+        _ => {}
     }
 }
 
 /// Given a friend with an incoming move token state, create the largest possible move token to
 /// send to the remote side.
 /// Requests that fail to be processed are moved to the cancel queues of the relevant friends.
-fn collect_outgoing_move_token<'a, B>(
+fn collect_outgoing_move_token<'a, B, R>(
     m_state: &'a mut MutableFunderState<B>,
+    ephemeral: &'a Ephemeral,
     outgoing_channeler_config: &'a mut Vec<ChannelerConfig<RelayAddress<B>>>,
+    cancel_send_commands: &'a mut SendCommands,
+    outgoing_control: &'a mut Vec<FunderOutgoingControl<B>>,
+    rng: &'a R,
     friend_public_key: &'a PublicKey,
     pending_move_token: &'a mut PendingMoveToken<B>,
     resend_relays: bool,
 ) -> Result<(), CollectOutgoingError>
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug + Hash,
+    R: CryptoRandom,
 {
     /*
     - Check if last sent local address is up to date.
@@ -645,12 +790,20 @@ where
         ChannelStatus::Inconsistent(_) => unreachable!(),
     };
 
-    // Send pending responses (Response, Cancel, Collect)
+    // Send pending responses (Response, Cancel, Collect) and refunds
     // TODO: Possibly replace this clone with something more efficient later:
     let mut pending_backwards_ops = channel_consistent.pending_backwards_ops.clone();
     while let Some((currency, pending_backwards_op)) = pending_backwards_ops.pop_front() {
         let pending_op = backwards_op_to_friend_tc_op(pending_backwards_op);
-        queue_operation(m_state, pending_move_token, &currency, &pending_op)?;
+        queue_operation(
+            m_state,
+            pending_move_token,
+            cancel_send_commands,
+            outgoing_control,
+            rng,
+            &currency,
+            &pending_op,
+        )?;
 
         let friend_mutation = FriendMutation::PopFrontPendingBackwardsOp;
         let funder_mutation =
@@ -667,9 +820,19 @@ where
     // Send pending requests:
     // TODO: Possibly replace this clone with something more efficient later:
     let mut pending_requests = channel_consistent.pending_requests.clone();
-    while let Some((currency, pending_request)) = pending_requests.pop_front() {
+    while let Some((currency, mut pending_request)) = pending_requests.pop_front() {
+        set_request_expiry_ticks(ephemeral, &mut pending_request);
+        set_request_refund_ticks(ephemeral, &mut pending_request);
         let pending_op = FriendTcOp::RequestSendFunds(pending_request);
-        queue_operation(m_state, pending_move_token, &currency, &pending_op)?;
+        queue_operation(
+            m_state,
+            pending_move_token,
+            cancel_send_commands,
+            outgoing_control,
+            rng,
+            &currency,
+            &pending_op,
+        )?;
         let friend_mutation = FriendMutation::PopFrontPendingRequest;
         let funder_mutation =
             FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
@@ -684,9 +847,18 @@ where
 
     // Send as many pending user requests as possible:
     let mut pending_user_requests = channel_consistent.pending_user_requests.clone();
-    while let Some((currency, request_send_funds)) = pending_user_requests.pop_front() {
+    while let Some((currency, mut request_send_funds)) = pending_user_requests.pop_front() {
+        set_request_expiry_ticks(ephemeral, &mut request_send_funds);
         let pending_op = FriendTcOp::RequestSendFunds(request_send_funds);
-        queue_operation(m_state, pending_move_token, &currency, &pending_op)?;
+        queue_operation(
+            m_state,
+            pending_move_token,
+            cancel_send_commands,
+            outgoing_control,
+            rng,
+            &currency,
+            &pending_op,
+        )?;
         let friend_mutation = FriendMutation::PopFrontPendingUserRequest;
         let funder_mutation =
             FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
@@ -695,13 +867,17 @@ where
     Ok(())
 }
 
-fn append_cancels_to_move_token<B>(
+fn append_cancels_to_move_token<B, R>(
     m_state: &mut MutableFunderState<B>,
+    cancel_send_commands: &mut SendCommands,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    rng: &R,
     friend_public_key: &PublicKey,
     pending_move_token: &mut PendingMoveToken<B>,
 ) -> Result<(), CollectOutgoingError>
 where
     B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+    R: CryptoRandom,
 {
     let friend = m_state.state().friends.get(friend_public_key).unwrap();
     let channel_consistent = match &friend.channel_status {
@@ -714,7 +890,15 @@ where
     let mut pending_backwards_ops = channel_consistent.pending_backwards_ops.clone();
     while let Some((currency, pending_backwards_op)) = pending_backwards_ops.pop_front() {
         let pending_op = backwards_op_to_friend_tc_op(pending_backwards_op);
-        queue_operation(m_state, pending_move_token, &currency, &pending_op)?;
+        queue_operation(
+            m_state,
+            pending_move_token,
+            cancel_send_commands,
+            outgoing_control,
+            rng,
+            &currency,
+            &pending_op,
+        )?;
 
         let friend_mutation = FriendMutation::PopFrontPendingBackwardsOp;
         let funder_mutation =
@@ -724,15 +908,17 @@ where
     Ok(())
 }
 
-async fn send_move_token<'a, B, R>(
+async fn send_move_token<'a, B, SB, R>(
     m_state: &'a mut MutableFunderState<B>,
     friend_public_key: PublicKey,
     pending_move_token: PendingMoveToken<B>,
-    identity_client: &'a mut IdentityClient,
+    identity_client: &'a mut SB,
     rng: &'a R,
     outgoing_messages: &'a mut Vec<OutgoingMessage<B>>,
-) where
+) -> Result<(), SignatureBackendError>
+where
     B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+    SB: SignatureBackend,
     R: CryptoRandom,
 {
     let PendingMoveToken {
@@ -749,7 +935,7 @@ async fn send_move_token<'a, B, R>(
         && opt_local_relays.is_none()
         && !may_send_empty
     {
-        return;
+        return Ok(());
     }
 
     // We want the token back if we just set a new address, to be sure
@@ -785,6 +971,7 @@ async fn send_move_token<'a, B, R>(
             opt_local_relays,
             opt_active_currencies,
             rand_nonce,
+            friend.signature_version(),
         )
         .unwrap();
 
@@ -798,7 +985,7 @@ async fn send_move_token<'a, B, R>(
 
     // Apply final SetDirection mutation (Can not be created from inside of the TokenChannel
     // because a signature is required.
-    let move_token = sign_move_token(unsigned_move_token, identity_client).await;
+    let move_token = sign_move_token(unsigned_move_token, identity_client).await?;
     let tc_mutation = TcMutation::SetDirection(SetDirection::Outgoing((move_token, token_info)));
     let friend_mutation = FriendMutation::TcMutation(tc_mutation);
     let funder_mutation =
@@ -817,24 +1004,31 @@ async fn send_move_token<'a, B, R>(
     let move_token_request = MoveTokenRequest {
         move_token,
         token_wanted,
+        opt_capabilities: Some(friend.local_capabilities()),
     };
 
     outgoing_messages.push((
         friend_public_key.clone(),
         FriendMessage::MoveTokenRequest(move_token_request),
     ));
+    Ok(())
 }
 
+/// Make sure that every friend that was queued new pending messages will get them:
+/// Friends with an incoming token channel get a PendingMoveToken, and friends with an outgoing
+/// token channel are asked for the token.
 fn init_cancel_pending_move_token<B>(
+    m_state: &MutableFunderState<B>,
     ephemeral: &Ephemeral,
     max_operations_in_batch: usize,
-    cancel_public_keys: &HashSet<PublicKey>,
+    cancel_send_commands: &SendCommands,
     pending_move_tokens: &mut HashMap<PublicKey, PendingMoveToken<B>>,
+    outgoing_messages: &mut Vec<OutgoingMessage<B>>,
 ) where
-    B: Clone + Eq + CanonicalSerialize + Debug,
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
 {
     let pending_move_token_keys = pending_move_tokens.keys().cloned().collect::<HashSet<_>>();
-    for friend_public_key in cancel_public_keys {
+    for friend_public_key in cancel_send_commands.send_commands.keys() {
         // Make sure that this friend is ready,
         // and that it doesn't already have a PendingMoveToken:
 
@@ -844,6 +1038,24 @@ fn init_cancel_pending_move_token<B>(
             continue;
         }
 
+        let friend = m_state.state().friends.get(friend_public_key).unwrap();
+        let token_channel = match &friend.channel_status {
+            ChannelStatus::Consistent(channel_consistent) => &channel_consistent.token_channel,
+            ChannelStatus::Inconsistent(_) => continue,
+        };
+
+        if token_channel.get_outgoing().is_some() {
+            // We will send the pending messages when the token arrives:
+            let is_token_wanted = true;
+            transmit_outgoing(
+                m_state,
+                friend_public_key,
+                is_token_wanted,
+                outgoing_messages,
+            );
+            continue;
+        }
+
         let may_send_empty = false;
         let pending_move_token = PendingMoveToken::new(
             friend_public_key.clone(),
@@ -855,19 +1067,24 @@ fn init_cancel_pending_move_token<B>(
 }
 
 /// Send all possible messages according to SendCommands
-pub async fn create_friend_messages<'a, B, R>(
+pub async fn create_friend_messages<'a, B, SB, R>(
     m_state: &'a mut MutableFunderState<B>,
     ephemeral: &'a Ephemeral,
     send_commands: &'a SendCommands,
+    outgoing_control: &'a mut Vec<FunderOutgoingControl<B>>,
     max_operations_in_batch: usize,
-    identity_client: &'a mut IdentityClient,
+    identity_client: &'a mut SB,
     rng: &'a R,
-) -> (
-    Vec<OutgoingMessage<B>>,
-    Vec<ChannelerConfig<RelayAddress<B>>>,
-)
+) -> Result<
+    (
+        Vec<OutgoingMessage<B>>,
+        Vec<ChannelerConfig<RelayAddress<B>>>,
+    ),
+    SignatureBackendError,
+>
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug + Hash,
+    SB: SignatureBackend,
     R: CryptoRandom,
 {
     let mut outgoing_messages = Vec::new();
@@ -875,7 +1092,7 @@ where
     let mut pending_move_tokens: HashMap<PublicKey, PendingMoveToken<B>> = HashMap::new();
 
     // First iteration:
-    let cancel_public_keys = HashSet::new();
+    let mut cancel_send_commands = SendCommands::new();
     for (friend_public_key, friend_send_commands) in &send_commands.send_commands {
         if !ephemeral.liveness.is_online(friend_public_key) {
             continue;
@@ -885,28 +1102,40 @@ where
             friend_public_key,
             friend_send_commands,
             &mut pending_move_tokens,
+            &mut cancel_send_commands,
+            outgoing_control,
             identity_client,
             rng,
             max_operations_in_batch,
+            ephemeral,
             &mut outgoing_messages,
             &mut outgoing_channeler_config,
         )
-        .await;
+        .await?;
     }
 
     // Create PendingMoveToken-s for all the friends that were queued
     // new pending messages during `send_friend_iter1`:
     init_cancel_pending_move_token(
+        m_state,
         ephemeral,
         max_operations_in_batch,
-        &cancel_public_keys,
+        &cancel_send_commands,
         &mut pending_move_tokens,
+        &mut outgoing_messages,
     );
 
     // Second iteration (Attempt to queue Cancel-s created in the first iteration):
     for (friend_public_key, pending_move_token) in &mut pending_move_tokens {
         assert!(ephemeral.liveness.is_online(&friend_public_key));
-        let _ = append_cancels_to_move_token(m_state, friend_public_key, pending_move_token);
+        let _ = append_cancels_to_move_token(
+            m_state,
+            &mut cancel_send_commands,
+            outgoing_control,
+            rng,
+            friend_public_key,
+            pending_move_token,
+        );
     }
 
     // Send all pending move tokens:
@@ -920,8 +1149,29 @@ where
             rng,
             &mut outgoing_messages,
         )
-        .await;
+        .await?;
+    }
+
+    // Keep announcing our new key until we start using it:
+    for (friend_public_key, friend_send_commands) in &send_commands.send_commands {
+        if !friend_send_commands.send_key_rotation
+            || !ephemeral.liveness.is_online(friend_public_key)
+            || !m_state.state().is_key_rotation_pending()
+        {
+            continue;
+        }
+        let friend = m_state.state().friends.get(friend_public_key).unwrap();
+        // Friends that did not send us their capabilities do not know the `KeyRotation` message:
+        if friend.opt_remote_capabilities.is_none() {
+            continue;
+        }
+        if let Some(key_rotation) = &m_state.state().opt_key_rotation {
+            outgoing_messages.push((
+                friend_public_key.clone(),
+                FriendMessage::KeyRotation(key_rotation.clone()),
+            ));
+        }
     }
 
-    (outgoing_messages, outgoing_channeler_config)
+    Ok((outgoing_messages, outgoing_channeler_config))
 }
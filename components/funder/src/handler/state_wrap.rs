@@ -8,12 +8,13 @@ use crypto::rand::{CryptoRandom, RandGen};
 use proto::crypto::{PublicKey, RandValue};
 use proto::funder::messages::{Currency, PendingTransaction};
 
-use identity::IdentityClient;
+use identity::{SignatureBackend, SignatureBackendError};
 
 use crate::state::{FunderMutation, FunderState};
 
 use crate::ephemeral::{Ephemeral, EphemeralMutation};
 use crate::friend::{BackwardsOp, FriendMutation};
+use crate::request_origins::{funder_mutation_to_request_origins_mutations, RequestOrigins};
 use crate::types::create_response_send_funds;
 
 #[derive(Debug, Clone)]
@@ -22,11 +23,14 @@ pub struct SemiResponse {
     currency: Currency,
     pending_transaction: PendingTransaction,
     is_complete: bool,
+    change: u128,
 }
 
 pub struct MutableFunderState<B: Clone> {
     initial_state: FunderState<B>,
     state: FunderState<B>,
+    /// Kept in sync with `state` on every mutation
+    request_origins: RequestOrigins,
     unsigned_responses: Vec<SemiResponse>,
     mutations: Vec<FunderMutation<B>>,
 }
@@ -35,10 +39,12 @@ impl<B> MutableFunderState<B>
 where
     B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
 {
-    pub fn new(state: FunderState<B>) -> Self {
+    /// `request_origins` must be an index of the requests in `state`.
+    pub fn new(state: FunderState<B>, request_origins: RequestOrigins) -> Self {
         MutableFunderState {
             initial_state: state.clone(),
             state,
+            request_origins,
             unsigned_responses: Vec::new(),
             mutations: Vec::new(),
         }
@@ -53,17 +59,22 @@ where
         currency: Currency,
         pending_transaction: PendingTransaction,
         is_complete: bool,
+        change: u128,
     ) {
         self.unsigned_responses.push(SemiResponse {
             friend_public_key,
             currency,
             pending_transaction,
             is_complete,
+            change,
         });
     }
 
     pub fn mutate(&mut self, mutation: FunderMutation<B>) {
         self.state.mutate(&mutation);
+        for request_origins_mutation in funder_mutation_to_request_origins_mutations(&mutation) {
+            self.request_origins.mutate(&request_origins_mutation);
+        }
         self.mutations.push(mutation);
     }
 
@@ -71,12 +82,18 @@ where
         &self.state
     }
 
+    pub fn request_origins(&self) -> &RequestOrigins {
+        &self.request_origins
+    }
+
     /// Sign all unsigned responses and apply them as mutations
-    pub async fn sign_responses<'a, R>(
+    pub async fn sign_responses<'a, SB, R>(
         &'a mut self,
-        identity_client: &'a mut IdentityClient,
+        identity_client: &'a mut SB,
         rng: &'a R,
-    ) where
+    ) -> Result<(), SignatureBackendError>
+    where
+        SB: SignatureBackend,
         R: CryptoRandom,
     {
         while let Some(semi_response) = self.unsigned_responses.pop() {
@@ -85,6 +102,7 @@ where
                 currency,
                 pending_transaction,
                 is_complete,
+                change,
             } = semi_response;
 
             // Get corresponding dest_plain_lock:
@@ -104,10 +122,11 @@ where
                 &pending_transaction,
                 dest_plain_lock.hash_lock(),
                 is_complete,
+                change,
                 rand_nonce,
                 identity_client,
             )
-            .await;
+            .await?;
 
             let backwards_op = BackwardsOp::Response(response_send_funds);
             let friend_mutation =
@@ -116,6 +135,7 @@ where
                 FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
             self.mutate(funder_mutation);
         }
+        Ok(())
     }
 
     pub fn done(self) -> (FunderState<B>, Vec<FunderMutation<B>>, FunderState<B>) {
@@ -173,9 +173,9 @@ async fn task_handler_change_address(
     .await
     .unwrap();
 
-    assert_eq!(outgoing_comms.len(), 1);
+    assert_eq!(outgoing_comms.len(), 2);
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk2);
                 assert_eq!(move_token_request.token_wanted, true);
@@ -220,9 +220,9 @@ async fn task_handler_change_address(
     .await
     .unwrap();
 
-    assert_eq!(outgoing_comms.len(), 1);
+    assert_eq!(outgoing_comms.len(), 2);
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk1);
                 assert_eq!(move_token_request.token_wanted, true);
@@ -259,7 +259,7 @@ async fn task_handler_change_address(
 
     assert_eq!(outgoing_comms.len(), 2);
     let friend_message = match &outgoing_comms[1] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk2);
                 assert_eq!(move_token_request.token_wanted, true);
@@ -296,7 +296,7 @@ async fn task_handler_change_address(
 
     assert_eq!(outgoing_comms.len(), 1);
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk1);
                 assert_eq!(move_token_request.token_wanted, false);
@@ -371,7 +371,7 @@ async fn task_handler_change_address(
     };
 
     let friend_message = match &outgoing_comms[2] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk2);
                 assert_eq!(move_token_request.token_wanted, true);
@@ -406,7 +406,7 @@ async fn task_handler_change_address(
 
     assert_eq!(outgoing_comms.len(), 1);
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk1);
                 assert_eq!(move_token_request.token_wanted, false);
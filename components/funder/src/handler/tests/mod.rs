@@ -1,4 +1,5 @@
 mod change_address;
 mod pair_basic;
 mod pair_inconsistency;
+mod sign_failure;
 pub mod utils;
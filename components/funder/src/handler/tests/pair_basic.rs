@@ -18,13 +18,13 @@ use proto::crypto::{InvoiceId, PaymentId, PrivateKey, PublicKey, Uid};
 use proto::funder::messages::{
     AckClosePayment, AddFriend, AddInvoice, CreatePayment, CreateTransaction, Currency,
     FriendMessage, FriendStatus, FriendsRoute, FunderControl, FunderIncomingControl,
-    FunderOutgoingControl, PaymentStatus, Rate, RequestResult, RequestsStatus,
+    FunderOutgoingControl, PaymentStatus, Rate, RequestErrorCode, RequestResult, RequestsStatus,
     SetFriendCurrencyMaxDebt, SetFriendCurrencyRate, SetFriendCurrencyRequestsStatus,
     SetFriendStatus,
 };
 
 use crate::ephemeral::Ephemeral;
-use crate::friend::ChannelStatus;
+use crate::friend::{ChannelStatus, LOCAL_FRIEND_FEATURES};
 use crate::state::FunderState;
 use crate::types::{
     ChannelerConfig, FunderIncoming, FunderIncomingComm, FunderOutgoingComm,
@@ -186,11 +186,14 @@ async fn task_handler_pair_basic<'a>(
 
     assert_eq!(outgoing_comms.len(), 1);
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk2);
                 // Token is wanted because Node1 wants to send his configured address later.
                 assert_eq!(move_token_request.token_wanted, true);
+                // Node1 also sends its capabilities:
+                let capabilities = move_token_request.opt_capabilities.as_ref().unwrap();
+                assert_eq!(capabilities.features, LOCAL_FRIEND_FEATURES);
 
                 let friend_move_token = &move_token_request.move_token;
                 // assert_eq!(friend_move_token.move_token_counter, 0);
@@ -233,6 +236,12 @@ async fn task_handler_pair_basic<'a>(
 
     // Node2: Receive MoveToken from Node1:
     // (Node2 should be able to discard this duplicate message)
+    let remote_capabilities = match &friend_message {
+        FriendMessage::MoveTokenRequest(move_token_request) => {
+            move_token_request.opt_capabilities.clone()
+        }
+        _ => unreachable!(),
+    };
     let funder_incoming =
         FunderIncoming::Comm(FunderIncomingComm::Friend((pk1.clone(), friend_message)));
     let (outgoing_comms, _outgoing_control) = Box::pin(apply_funder_incoming(
@@ -245,11 +254,17 @@ async fn task_handler_pair_basic<'a>(
     .await
     .unwrap();
 
+    // Node2 remembers the capabilities of Node1:
+    let friend1 = state2.friends.get(&pk1).unwrap();
+    assert_eq!(friend1.opt_remote_capabilities, remote_capabilities);
+    // Both sides advertise the optional features, so they may be used between them:
+    assert!(friend1.supports_change());
+
     // The same message should be again sent by Node2:
     assert_eq!(outgoing_comms.len(), 1);
 
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk1);
                 assert_eq!(move_token_request.token_wanted, true);
@@ -286,7 +301,7 @@ async fn task_handler_pair_basic<'a>(
     assert_eq!(outgoing_comms.len(), 2);
     // Node1 should send a message containing opt_local_relays to Node2:
     let friend_message = match &outgoing_comms[1] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk2);
                 assert_eq!(move_token_request.token_wanted, true);
@@ -324,7 +339,7 @@ async fn task_handler_pair_basic<'a>(
 
     // Node2 sends an empty move token to node1:
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk1);
                 assert_eq!(move_token_request.token_wanted, false);
@@ -381,7 +396,7 @@ async fn task_handler_pair_basic<'a>(
     // Node1 produces outgoing communication (Adding an active currency):
     assert_eq!(outgoing_comms.len(), 1);
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk2);
                 assert_eq!(move_token_request.token_wanted, false);
@@ -430,7 +445,7 @@ async fn task_handler_pair_basic<'a>(
     // Node2 produces outgoing communication (Adding an active currency):
     assert_eq!(outgoing_comms.len(), 1);
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk1);
                 assert_eq!(move_token_request.token_wanted, false);
@@ -484,6 +499,7 @@ async fn task_handler_pair_basic<'a>(
         invoice_id: InvoiceId::from(&[1u8; InvoiceId::len()]),
         currency: currency.clone(),
         total_dest_payment: 16,
+        opt_expiry_ticks: None,
     };
 
     let incoming_control_message = FunderIncomingControl::new(
@@ -512,6 +528,7 @@ async fn task_handler_pair_basic<'a>(
         currency: currency.clone(),
         total_dest_payment: 16,
         dest_public_key: pk3.clone(),
+        opt_max_fee_per_hop: None,
     };
 
     let incoming_control_message = FunderIncomingControl::new(
@@ -543,6 +560,7 @@ async fn task_handler_pair_basic<'a>(
         },
         dest_payment: 16,
         fees: 4,
+        opt_refund_ticks: None,
     };
 
     let incoming_control_message = FunderIncomingControl::new(
@@ -565,7 +583,7 @@ async fn task_handler_pair_basic<'a>(
     assert_eq!(outgoing_comms.len(), 1);
 
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk1);
                 assert_eq!(move_token_request.token_wanted, true);
@@ -594,7 +612,7 @@ async fn task_handler_pair_basic<'a>(
     assert_eq!(outgoing_comms.len(), 1);
 
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk2);
                 assert_eq!(move_token_request.token_wanted, false);
@@ -622,7 +640,7 @@ async fn task_handler_pair_basic<'a>(
     // Node2 sends a RequestSendFunds to Node1:
     assert_eq!(outgoing_comms.len(), 1);
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk1);
                 assert_eq!(move_token_request.token_wanted, false);
@@ -650,7 +668,7 @@ async fn task_handler_pair_basic<'a>(
     // Node1 sends a CancelSendFunds to Node2:
     assert_eq!(outgoing_comms.len(), 1);
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk2);
                 assert_eq!(move_token_request.token_wanted, false);
@@ -832,6 +850,7 @@ async fn task_handler_pair_basic<'a>(
         currency: currency.clone(),
         total_dest_payment: 16,
         dest_public_key: pk1.clone(),
+        opt_max_fee_per_hop: None,
     };
 
     let incoming_control_message = FunderIncomingControl::new(
@@ -862,6 +881,7 @@ async fn task_handler_pair_basic<'a>(
         },
         dest_payment: 16,
         fees: 4,
+        opt_refund_ticks: None,
     };
 
     let incoming_control_message = FunderIncomingControl::new(
@@ -886,7 +906,9 @@ async fn task_handler_pair_basic<'a>(
     // Node2 will send a RequestFunds message to Node1
     assert_eq!(outgoing_comms.len(), 1);
     let friend_message =
-        if let FunderOutgoingComm::FriendMessage((_pk, friend_message)) = &outgoing_comms[0] {
+        if let FunderOutgoingComm::FriendMessage((_pk, _message_id, friend_message)) =
+            &outgoing_comms[0]
+        {
             friend_message.clone()
         } else {
             unreachable!();
@@ -908,7 +930,7 @@ async fn task_handler_pair_basic<'a>(
     // Node1 sends a ResponseSendFunds to Node2:
     assert_eq!(outgoing_comms.len(), 1);
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(_move_token_request) = friend_message {
                 assert_eq!(pk, &pk2);
             // let friend_move_token = &move_token_request.move_token;
@@ -970,7 +992,7 @@ async fn task_handler_pair_basic<'a>(
     assert_eq!(outgoing_control.len(), 1);
 
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(_move_token_request) = friend_message {
                 assert_eq!(pk, &pk2);
             // let friend_move_token = &move_token_request.move_token;
@@ -1002,7 +1024,7 @@ async fn task_handler_pair_basic<'a>(
 
     // Node2 gives token to Node1:
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(_move_token_request) = friend_message {
                 assert_eq!(pk, &pk1);
             // let friend_move_token = &move_token_request.move_token;
@@ -1034,7 +1056,7 @@ async fn task_handler_pair_basic<'a>(
 
     // Node1 sends a Collect message to Node2:
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(_move_token_request) = friend_message {
                 assert_eq!(pk, &pk2);
             // let friend_move_token = &move_token_request.move_token;
@@ -1188,6 +1210,45 @@ async fn task_handler_pair_basic<'a>(
         PaymentStatus::PaymentNotFound => {}
         _ => unreachable!(),
     }
+
+    // Node2: Enable a friend that does not exist:
+    let set_friend_status = SetFriendStatus {
+        friend_public_key: pk3.clone(),
+        status: FriendStatus::Enabled,
+    };
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[24; Uid::len()]),
+        FunderControl::SetFriendStatus(set_friend_status),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    let (outgoing_comms, outgoing_control) = Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state2,
+        &mut ephemeral2,
+        &mut rng,
+        identity_client2,
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(outgoing_comms.len(), 0);
+    assert_eq!(outgoing_control.len(), 2);
+
+    // The request error is sent before the acknowledgement:
+    match &outgoing_control[0] {
+        FunderOutgoingControl::RequestError(request_error) => {
+            assert_eq!(request_error.app_request_id, Uid::from(&[24; Uid::len()]));
+            assert_eq!(request_error.error_code, RequestErrorCode::FriendNotFound);
+        }
+        _ => unreachable!(),
+    };
+    match &outgoing_control[1] {
+        FunderOutgoingControl::ReportMutations(funder_report_mutations) => assert_eq!(
+            funder_report_mutations.opt_app_request_id,
+            Some(Uid::from(&[24; Uid::len()]))
+        ),
+        _ => unreachable!(),
+    };
 }
 
 #[test]
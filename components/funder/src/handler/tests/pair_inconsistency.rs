@@ -182,9 +182,9 @@ async fn task_handler_pair_inconsistency<'a>(
     .await
     .unwrap();
 
-    assert_eq!(outgoing_comms.len(), 1);
+    assert_eq!(outgoing_comms.len(), 2);
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk2);
                 // Token is wanted because Node1 wants to send his configured address later.
@@ -218,7 +218,7 @@ async fn task_handler_pair_inconsistency<'a>(
     .unwrap();
 
     // Node2 sends information about his address to Node1, and updates channeler
-    assert_eq!(outgoing_comms.len(), 2);
+    assert_eq!(outgoing_comms.len(), 3);
 
     match &outgoing_comms[0] {
         FunderOutgoingComm::ChannelerConfig(ChannelerConfig::UpdateFriend(update_friend)) => {
@@ -247,7 +247,7 @@ async fn task_handler_pair_inconsistency<'a>(
     assert_eq!(outgoing_comms.len(), 1);
 
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk1);
                 assert_eq!(move_token_request.token_wanted, true);
@@ -284,7 +284,7 @@ async fn task_handler_pair_inconsistency<'a>(
     assert_eq!(outgoing_comms.len(), 2);
     // Node1 should send a message containing opt_local_relays to Node2:
     let friend_message = match &outgoing_comms[1] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk2);
                 assert_eq!(move_token_request.token_wanted, true);
@@ -322,7 +322,7 @@ async fn task_handler_pair_inconsistency<'a>(
 
     // Node2 sends an empty move token to node1:
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk1);
                 assert_eq!(move_token_request.token_wanted, false);
@@ -379,7 +379,7 @@ async fn task_handler_pair_inconsistency<'a>(
     // Node1 produces outgoing communication (Adding an active currency):
     assert_eq!(outgoing_comms.len(), 1);
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk2);
                 assert_eq!(move_token_request.token_wanted, false);
@@ -428,7 +428,7 @@ async fn task_handler_pair_inconsistency<'a>(
     // Node2 produces outgoing communication (Adding an active currency):
     assert_eq!(outgoing_comms.len(), 1);
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk1);
                 assert_eq!(move_token_request.token_wanted, false);
@@ -509,7 +509,7 @@ async fn task_handler_pair_inconsistency<'a>(
     // Node1 produces outgoing communication (Adding an active currency):
     assert_eq!(outgoing_comms.len(), 1);
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk2);
                 assert_eq!(move_token_request.token_wanted, false);
@@ -575,7 +575,7 @@ async fn task_handler_pair_inconsistency<'a>(
     // Node2 produces outgoing communication (Adding an active currency):
     assert_eq!(outgoing_comms.len(), 1);
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk1);
                 assert_eq!(move_token_request.token_wanted, false);
@@ -605,7 +605,7 @@ async fn task_handler_pair_inconsistency<'a>(
     /////////////////////////////////////////////////
 
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::InconsistencyError(reset_terms) = friend_message {
                 assert_eq!(reset_terms.inconsistency_counter, 1);
                 assert_eq!(
@@ -641,7 +641,7 @@ async fn task_handler_pair_inconsistency<'a>(
     assert_eq!(outgoing_comms.len(), 1);
 
     let (friend_message, reset_token2) = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::InconsistencyError(reset_terms) = friend_message {
                 assert_eq!(reset_terms.inconsistency_counter, 1);
                 assert_eq!(
@@ -719,7 +719,7 @@ async fn task_handler_pair_inconsistency<'a>(
     // Node1 should send a MoveToken message that resolves the inconsistency:
     assert_eq!(outgoing_comms.len(), 1);
     let friend_message = match &outgoing_comms[0] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk2);
                 // Token is wanted because local relays were sent:
@@ -752,7 +752,7 @@ async fn task_handler_pair_inconsistency<'a>(
     // Node2 should send back a move token with local relays:
     assert_eq!(outgoing_comms.len(), 2);
     let friend_message = match &outgoing_comms[1] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk1);
                 // Token is wanted because local relays were sent:
@@ -785,7 +785,7 @@ async fn task_handler_pair_inconsistency<'a>(
     // Node1 sends to Node2 an empty move token message (Because token is wanted by Node2):
     assert_eq!(outgoing_comms.len(), 2);
     let friend_message = match &outgoing_comms[1] {
-        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((pk, _message_id, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk2);
                 assert_eq!(move_token_request.token_wanted, false);
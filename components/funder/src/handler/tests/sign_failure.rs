@@ -0,0 +1,140 @@
+use std::cmp::Ordering;
+
+use futures::executor::LocalPool;
+use futures::future;
+
+use common::conn::BoxFuture;
+
+use identity::{SignatureBackend, SignatureBackendError};
+
+use crypto::identity::compare_public_key;
+use crypto::rand::RngContainer;
+use crypto::test_utils::DummyRandom;
+
+use proto::crypto::{PublicKey, Signature, Uid};
+use proto::funder::messages::{
+    AddFriend, FriendStatus, FunderControl, FunderIncomingControl, SetFriendStatus,
+};
+
+use super::utils::{apply_funder_incoming, dummy_named_relay_address, dummy_relay_address};
+
+use crate::ephemeral::Ephemeral;
+use crate::handler::handler::FunderHandlerError;
+use crate::state::FunderState;
+use crate::types::{FunderIncoming, FunderIncomingComm, IncomingLivenessMessage};
+
+/// A signature backend that fails to sign (For example: a disconnected device)
+struct FailingBackend {
+    public_key: PublicKey,
+}
+
+impl SignatureBackend for FailingBackend {
+    fn request_public_key(&mut self) -> BoxFuture<'_, Result<PublicKey, SignatureBackendError>> {
+        Box::pin(future::ready(Ok(self.public_key.clone())))
+    }
+
+    fn request_signature(
+        &mut self,
+        _message: Vec<u8>,
+    ) -> BoxFuture<'_, Result<Signature, SignatureBackendError>> {
+        Box::pin(future::ready(Err(SignatureBackendError::Unavailable)))
+    }
+}
+
+async fn task_handler_sign_failure() {
+    let pk_a = PublicKey::from(&[0x11; PublicKey::len()]);
+    let pk_b = PublicKey::from(&[0x22; PublicKey::len()]);
+
+    // pk1 is the second sender of the token channel. It holds the token, and has to send its
+    // relays to pk2 in a signed move token:
+    let (pk1, pk2) = if compare_public_key(&pk_a, &pk_b) == Ordering::Greater {
+        (pk_a, pk_b)
+    } else {
+        (pk_b, pk_a)
+    };
+    let mut backend1 = FailingBackend {
+        public_key: pk1.clone(),
+    };
+
+    let mut state1 = FunderState::<u32>::new(pk1.clone(), vec![dummy_named_relay_address(1)]);
+    let mut ephemeral1 = Ephemeral::new();
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+
+    let funder_incoming = FunderIncoming::Init;
+    Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        &mut backend1,
+    ))
+    .await
+    .unwrap();
+
+    // Node1: Add friend 2, and enable it:
+    let add_friend = AddFriend {
+        friend_public_key: pk2.clone(),
+        relays: vec![dummy_relay_address(2)],
+        name: String::from("pk2"),
+    };
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[11; Uid::len()]),
+        FunderControl::AddFriend(add_friend),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        &mut backend1,
+    ))
+    .await
+    .unwrap();
+
+    let set_friend_status = SetFriendStatus {
+        friend_public_key: pk2.clone(),
+        status: FriendStatus::Enabled,
+    };
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[12; Uid::len()]),
+        FunderControl::SetFriendStatus(set_friend_status),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        &mut backend1,
+    ))
+    .await
+    .unwrap();
+
+    // Node2 is online. Node1 has to sign a move token, but the backend fails:
+    let state_before = state1.clone();
+    let incoming_liveness_message = IncomingLivenessMessage::Online(pk2.clone());
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Liveness(incoming_liveness_message));
+    let res = Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        &mut backend1,
+    ))
+    .await;
+
+    match res {
+        Err(FunderHandlerError::SignatureBackendError(SignatureBackendError::Unavailable)) => {}
+        _ => unreachable!(),
+    };
+    // None of the changes were applied:
+    assert_eq!(state1, state_before);
+}
+
+#[test]
+fn test_handler_sign_failure() {
+    let mut local_pool = LocalPool::new();
+    local_pool.run_until(task_handler_sign_failure());
+}
@@ -1,7 +1,7 @@
 use std::fmt::Debug;
 use std::hash::Hash;
 
-use identity::IdentityClient;
+use identity::SignatureBackend;
 
 use crypto::rand::CryptoRandom;
 use signature::canonical::CanonicalSerialize;
@@ -18,6 +18,11 @@ use crate::types::{FunderIncoming, FunderOutgoingComm};
 const TEST_MAX_NODE_RELAYS: usize = 16;
 const TEST_MAX_OPERATIONS_IN_BATCH: usize = 16;
 const TEST_MAX_PENDING_USER_REQUESTS: usize = 16;
+const TEST_MAX_ROUTE_LEN: usize = 4;
+const TEST_MAX_TRANSACTION_RETRIES: u64 = 0;
+const TEST_REQUEST_EXPIRY_TICKS: u64 = 0;
+const TEST_MIN_FRIEND_UPTIME_PERCENT: u8 = 0;
+const TEST_AUTO_RESET_TOLERANCE: Option<u128> = None;
 
 /// A helper function to quickly create a dummy NamedRelayAddress.
 pub fn dummy_named_relay_address(index: u8) -> NamedRelayAddress<u32> {
@@ -35,15 +40,16 @@ pub fn dummy_relay_address(index: u8) -> RelayAddress<u32> {
 
 /// A helper function. Applies an incoming funder message, updating state and ephemeral
 /// accordingly:
-pub async fn apply_funder_incoming<'a, B, R>(
+pub async fn apply_funder_incoming<'a, B, SB, R>(
     funder_incoming: FunderIncoming<B>,
     state: &'a mut FunderState<B>,
     ephemeral: &'a mut Ephemeral,
     rng: &'a mut R,
-    identity_client: &'a mut IdentityClient,
+    identity_client: &'a mut SB,
 ) -> Result<(Vec<FunderOutgoingComm<B>>, Vec<FunderOutgoingControl<B>>), FunderHandlerError>
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug + Hash + 'a,
+    SB: SignatureBackend,
     R: CryptoRandom + 'a,
 {
     let funder_handler_output = funder_handle_message(
@@ -54,6 +60,11 @@ where
         TEST_MAX_NODE_RELAYS,
         TEST_MAX_OPERATIONS_IN_BATCH,
         TEST_MAX_PENDING_USER_REQUESTS,
+        TEST_MAX_ROUTE_LEN,
+        TEST_MAX_TRANSACTION_RETRIES,
+        TEST_REQUEST_EXPIRY_TICKS,
+        TEST_MIN_FRIEND_UPTIME_PERCENT,
+        TEST_AUTO_RESET_TOLERANCE,
         funder_incoming,
     )
     .await?;
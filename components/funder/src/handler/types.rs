@@ -14,6 +14,8 @@ pub struct FriendSendCommands {
     pub remote_wants_token: bool,
     /// We want to perform a local reset
     pub local_reset: bool,
+    /// Announce our pending key rotation to the remote side
+    pub send_key_rotation: bool,
 }
 
 impl FriendSendCommands {
@@ -24,6 +26,7 @@ impl FriendSendCommands {
             resend_outgoing: false,
             remote_wants_token: false,
             local_reset: false,
+            send_key_rotation: false,
         }
     }
 }
@@ -79,4 +82,12 @@ impl SendCommands {
             .or_insert_with(FriendSendCommands::new);
         friend_send_commands.local_reset = true;
     }
+
+    pub fn set_send_key_rotation(&mut self, friend_public_key: &PublicKey) {
+        let friend_send_commands = self
+            .send_commands
+            .entry(friend_public_key.clone())
+            .or_insert_with(FriendSendCommands::new);
+        friend_send_commands.send_key_rotation = true;
+    }
 }
@@ -2,55 +2,56 @@ use std::fmt::Debug;
 
 use signature::canonical::CanonicalSerialize;
 
-use proto::funder::messages::{Currency, PendingTransaction};
+use proto::funder::messages::{Currency, PendingTransaction, RequestSendFundsOp};
 
 use proto::crypto::{PublicKey, Uid};
 
 use crate::state::FunderState;
 
-use crate::ephemeral::Ephemeral;
+use crate::ephemeral::{Ephemeral, EphemeralMutation};
 use crate::friend::ChannelStatus;
+use crate::handler::state_wrap::{MutableEphemeral, MutableFunderState};
+use crate::outflows::OutflowsMutation;
 
-/// Find the originator of a pending local request.
+/// Find the originator of a pending local request, sent in `currency`.
 /// This should be a pending remote request at some other friend.
-/// Returns the public key of a friend. If we are the origin of this request, the function returns None.
-///
-/// TODO: We need to change this search to be O(1) in the future. Possibly by maintaining a map
-/// between request_id and (friend_public_key, friend).
-pub fn find_request_origin<'a, B>(
-    state: &'a FunderState<B>,
+/// Returns the public key of a friend, and the currency of the remote request. The currencies
+/// differ if we have exchanged the request to the currency of its destination.
+/// If we are the origin of this request, the function returns None.
+pub fn find_request_origin<B>(
+    m_state: &MutableFunderState<B>,
     currency: &Currency,
     request_id: &Uid,
-) -> Option<&'a PublicKey>
+) -> Option<(PublicKey, Currency)>
 where
     B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
 {
-    for (friend_public_key, friend) in &state.friends {
-        match &friend.channel_status {
-            ChannelStatus::Inconsistent(_) => continue,
-            ChannelStatus::Consistent(channel_consistent) => {
-                let mutual_credit = if let Some(mutual_credit) = channel_consistent
-                    .token_channel
-                    .get_mutual_credits()
-                    .get(currency)
-                {
-                    mutual_credit
-                } else {
-                    continue;
-                };
+    let (friend_public_key, origin_currency) = m_state.request_origins().get_origin(request_id)?;
+    if origin_currency == currency {
+        return Some((friend_public_key.clone(), origin_currency.clone()));
+    }
 
-                if mutual_credit
-                    .state()
-                    .pending_transactions
-                    .remote
-                    .contains_key(request_id)
-                {
-                    return Some(friend_public_key);
-                }
-            }
+    // The request was received in another currency. It is ours only if we have exchanged it to
+    // `currency`:
+    let friend = m_state.state().friends.get(friend_public_key)?;
+    let channel_consistent = match &friend.channel_status {
+        ChannelStatus::Consistent(channel_consistent) => channel_consistent,
+        ChannelStatus::Inconsistent(_) => return None,
+    };
+    let pending_transaction = channel_consistent
+        .token_channel
+        .get_mutual_credits()
+        .get(origin_currency)?
+        .state()
+        .pending_transactions
+        .remote
+        .get(request_id)?;
+    match &pending_transaction.opt_exchange {
+        Some(currency_exchange) if &currency_exchange.dest_currency == currency => {
+            Some((friend_public_key.clone(), origin_currency.clone()))
         }
+        _ => None,
     }
-    None
 }
 
 /// Find an outgoing pending transaction
@@ -127,6 +128,56 @@ where
     None
 }
 
+/// Check the outflow limit of a friend (If any) before queueing a request to this friend.
+/// If the request is allowed, the credits it sends (Payment and fees) are counted in the current
+/// window of the limit.
+/// Returns false if queueing the request would exceed the limit.
+pub fn charge_outflow<B>(
+    state: &FunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    friend_public_key: &PublicKey,
+    currency: &Currency,
+    request_send_funds: &RequestSendFundsOp,
+) -> bool
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let friend = state.friends.get(friend_public_key).unwrap();
+    let max_outflow = match friend
+        .currency_configs
+        .get(currency)
+        .and_then(|currency_config| currency_config.opt_max_outflow.as_ref())
+    {
+        Some(max_outflow) => max_outflow,
+        None => return true,
+    };
+
+    let amount = if let Some(amount) = request_send_funds
+        .dest_payment
+        .checked_add(request_send_funds.left_fees)
+    {
+        amount
+    } else {
+        return false;
+    };
+
+    let window = if let Some(window) =
+        m_ephemeral
+            .ephemeral()
+            .outflows
+            .charge(friend_public_key, currency, max_outflow, amount)
+    {
+        window
+    } else {
+        return false;
+    };
+
+    let outflows_mutation =
+        OutflowsMutation::SetWindow((friend_public_key.clone(), currency.clone(), window));
+    m_ephemeral.mutate(EphemeralMutation::OutflowsMutation(outflows_mutation));
+    true
+}
+
 pub fn is_friend_ready<B>(
     state: &FunderState<B>,
     ephemeral: &Ephemeral,
@@ -141,6 +192,21 @@ where
         return false;
     }
 
+    // New requests are not queued through a draining (or closed) friend:
+    if !friend.drain_status.is_active() {
+        return false;
+    }
+
+    // New requests are not queued through friends with a low recent uptime:
+    if ephemeral.liveness.is_gated(friend_public_key) {
+        return false;
+    }
+
+    // New requests are not queued through friends whose outgoing queue is congested:
+    if ephemeral.liveness.is_congested(friend_public_key) {
+        return false;
+    }
+
     // Make sure that the channel is consistent:
     let token_channel = match &friend.channel_status {
         ChannelStatus::Inconsistent(_) => return false,
@@ -0,0 +1,35 @@
+use im::hashmap::HashMap as ImHashMap;
+
+use proto::crypto::InvoiceId;
+
+/// Countdowns of open invoices that expire.
+/// Invoices without expiry are not kept here.
+#[derive(Clone, Default)]
+pub struct Invoices {
+    pub ticks_left: ImHashMap<InvoiceId, u64>,
+}
+
+#[derive(Debug)]
+pub enum InvoicesMutation {
+    SetTicksLeft((InvoiceId, u64)),
+    Remove(InvoiceId),
+}
+
+impl Invoices {
+    pub fn new() -> Invoices {
+        Invoices {
+            ticks_left: ImHashMap::new(),
+        }
+    }
+
+    pub fn mutate(&mut self, mutation: &InvoicesMutation) {
+        match mutation {
+            InvoicesMutation::SetTicksLeft((invoice_id, ticks_left)) => {
+                let _ = self.ticks_left.insert(invoice_id.clone(), *ticks_left);
+            }
+            InvoicesMutation::Remove(invoice_id) => {
+                let _ = self.ticks_left.remove(invoice_id);
+            }
+        }
+    }
+}
@@ -18,19 +18,31 @@ extern crate serde;
 #[macro_use]
 extern crate quickcheck_derive;
 
+mod channel_proof;
+mod dispute;
 mod ephemeral;
+mod exposure;
+pub mod freeze_guard;
 mod friend;
 mod funder;
 mod handler;
+mod invoices;
 mod liveness;
 mod mutual_credit;
+mod outflows;
+mod rebalance;
+mod refunds;
 pub mod report;
+mod request_origins;
+mod requests_expiry;
 mod state;
 mod token_channel;
 pub mod types;
+mod verify;
 
 #[cfg(test)]
 mod tests;
 
 pub use self::funder::{funder_loop, FunderError};
 pub use self::state::{FunderMutation, FunderState};
+pub use self::verify::{check_funder_state, verify_funder_state, VerifyStateError};
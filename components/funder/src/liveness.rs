@@ -1,22 +1,103 @@
+use std::collections::VecDeque;
+
+use im::hashmap::HashMap as ImHashMap;
 use im::hashset::HashSet as ImHashSet;
 
-use proto::crypto::PublicKey;
+use proto::consts::{FRIEND_QUEUE_CONGESTION_DEPTH, FRIEND_UPTIME_WINDOW_TICKS};
+use proto::crypto::{PublicKey, Uid};
+use proto::report::messages::{BandwidthReport, SecureChannelStatsReport};
+
+/// Online samples of a friend, one for every tick, over a sliding window of ticks.
+#[derive(Clone, Debug, Default)]
+pub struct UptimeWindow {
+    samples: VecDeque<bool>,
+    /// Amount of samples in which the friend was online
+    online_samples: usize,
+}
+
+impl UptimeWindow {
+    fn push(&mut self, is_online: bool, window_ticks: usize) {
+        self.samples.push_back(is_online);
+        if is_online {
+            self.online_samples += 1;
+        }
+        while self.samples.len() > window_ticks {
+            if let Some(true) = self.samples.pop_front() {
+                self.online_samples -= 1;
+            }
+        }
+    }
+
+    /// Percentage of the samples in which the friend was online.
+    /// Returns None if no samples were taken yet.
+    pub fn uptime_percent(&self) -> Option<u8> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        Some((self.online_samples * 100 / self.samples.len()) as u8)
+    }
+}
 
 #[derive(Clone, Default)]
 pub struct Liveness {
     pub friends: ImHashSet<PublicKey>,
+    /// Amount of consecutive keepalive beats missed by online friends.
+    /// Friends that missed no beats are not kept here.
+    pub missed_beats: ImHashMap<PublicKey, u64>,
+    /// Latest latency measured to every relay (Ours and our friends'), in milliseconds.
+    /// None if the relay could not be reached.
+    pub relay_latencies: ImHashMap<PublicKey, Option<u64>>,
+    /// Recent uptime of every friend, sampled on every timer tick.
+    pub uptime: ImHashMap<PublicKey, UptimeWindow>,
+    /// Friends we do not queue new requests through, because their recent uptime is too low.
+    pub gated: ImHashSet<PublicKey>,
+    /// Amount of messages waiting to be sent to online friends, as last reported by the
+    /// Channeler. Friends with no waiting messages are not kept here.
+    pub queue_depths: ImHashMap<PublicKey, usize>,
+    /// Id of the last message sent to every online friend.
+    pub last_messages: ImHashMap<PublicKey, Uid>,
+    /// Diagnostics of the secure channels with every friend, counted since the node started.
+    /// Kept while the friend is offline. Forgotten when the friend is removed.
+    pub secure_channel_stats: ImHashMap<PublicKey, SecureChannelStatsReport>,
+    /// Bytes exchanged with every friend, counted since the node started.
+    /// Kept while the friend is offline. Forgotten when the friend is removed.
+    pub bandwidth: ImHashMap<PublicKey, BandwidthReport>,
+    /// We only accept connections from friends, and never connect to them.
+    /// Friends are expected to be online only from time to time.
+    pub listen_only: bool,
 }
 
 #[derive(Debug)]
 pub enum LivenessMutation {
     SetOnline(PublicKey),
     SetOffline(PublicKey),
+    SetMissedBeats((PublicKey, u64)),
+    SetRelayLatency((PublicKey, Option<u64>)),
+    /// Take an uptime sample of all the given friends.
+    /// The uptime, secure channel stats and bandwidth of friends that were not given (Removed
+    /// friends) are forgotten.
+    SampleUptime(Vec<PublicKey>),
+    SetGated((PublicKey, bool)),
+    SetQueueDepth((PublicKey, usize)),
+    SetLastMessage((PublicKey, Uid)),
+    SetSecureChannelStats((PublicKey, SecureChannelStatsReport)),
+    SetBandwidth((PublicKey, BandwidthReport)),
+    SetListenOnly,
 }
 
 impl Liveness {
     pub fn new() -> Liveness {
         Liveness {
             friends: ImHashSet::new(),
+            missed_beats: ImHashMap::new(),
+            relay_latencies: ImHashMap::new(),
+            uptime: ImHashMap::new(),
+            gated: ImHashSet::new(),
+            queue_depths: ImHashMap::new(),
+            last_messages: ImHashMap::new(),
+            secure_channel_stats: ImHashMap::new(),
+            bandwidth: ImHashMap::new(),
+            listen_only: false,
         }
     }
 
@@ -24,9 +105,77 @@ impl Liveness {
         match mutation {
             LivenessMutation::SetOnline(public_key) => {
                 self.friends.insert(public_key.clone());
+                let _ = self.missed_beats.remove(public_key);
             }
             LivenessMutation::SetOffline(public_key) => {
                 let _ = self.friends.remove(public_key);
+                let _ = self.missed_beats.remove(public_key);
+                let _ = self.queue_depths.remove(public_key);
+                let _ = self.last_messages.remove(public_key);
+            }
+            LivenessMutation::SetMissedBeats((public_key, missed_beats)) => {
+                if *missed_beats == 0 {
+                    let _ = self.missed_beats.remove(public_key);
+                } else {
+                    self.missed_beats.insert(public_key.clone(), *missed_beats);
+                }
+            }
+            LivenessMutation::SetRelayLatency((public_key, opt_latency_ms)) => {
+                self.relay_latencies
+                    .insert(public_key.clone(), *opt_latency_ms);
+            }
+            LivenessMutation::SampleUptime(friends) => {
+                let mut uptime = ImHashMap::new();
+                let mut gated = ImHashSet::new();
+                for friend_public_key in friends {
+                    let mut uptime_window = self
+                        .uptime
+                        .get(friend_public_key)
+                        .cloned()
+                        .unwrap_or_default();
+                    uptime_window.push(
+                        self.friends.contains(friend_public_key),
+                        FRIEND_UPTIME_WINDOW_TICKS,
+                    );
+                    uptime.insert(friend_public_key.clone(), uptime_window);
+                    if self.gated.contains(friend_public_key) {
+                        gated.insert(friend_public_key.clone());
+                    }
+                }
+                self.uptime = uptime;
+                self.gated = gated;
+                self.secure_channel_stats
+                    .retain(|public_key, _| friends.contains(public_key));
+                self.bandwidth
+                    .retain(|public_key, _| friends.contains(public_key));
+            }
+            LivenessMutation::SetGated((public_key, is_gated)) => {
+                if *is_gated {
+                    self.gated.insert(public_key.clone());
+                } else {
+                    let _ = self.gated.remove(public_key);
+                }
+            }
+            LivenessMutation::SetQueueDepth((public_key, queue_depth)) => {
+                if *queue_depth == 0 {
+                    let _ = self.queue_depths.remove(public_key);
+                } else {
+                    self.queue_depths.insert(public_key.clone(), *queue_depth);
+                }
+            }
+            LivenessMutation::SetLastMessage((public_key, message_id)) => {
+                self.last_messages
+                    .insert(public_key.clone(), message_id.clone());
+            }
+            LivenessMutation::SetSecureChannelStats((public_key, secure_channel_stats)) => {
+                self.secure_channel_stats
+                    .insert(public_key.clone(), secure_channel_stats.clone());
+            }
+            LivenessMutation::SetBandwidth((public_key, bandwidth)) => {
+                self.bandwidth.insert(public_key.clone(), bandwidth.clone());
+            }
+            LivenessMutation::SetListenOnly => {
+                self.listen_only = true;
             }
         }
     }
@@ -34,6 +183,62 @@ impl Liveness {
     pub fn is_online(&self, friend_public_key: &PublicKey) -> bool {
         self.friends.contains(&friend_public_key)
     }
+
+    pub fn missed_beats(&self, friend_public_key: &PublicKey) -> u64 {
+        self.missed_beats
+            .get(friend_public_key)
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// Latest latency measured to a relay.
+    /// Returns None if the relay was never measured.
+    pub fn relay_latency(&self, relay_public_key: &PublicKey) -> Option<Option<u64>> {
+        self.relay_latencies.get(relay_public_key).cloned()
+    }
+
+    /// Percentage of the recent ticks in which a friend was online.
+    /// Returns None if the uptime of the friend was not sampled yet.
+    pub fn uptime_percent(&self, friend_public_key: &PublicKey) -> Option<u8> {
+        self.uptime
+            .get(friend_public_key)
+            .and_then(UptimeWindow::uptime_percent)
+    }
+
+    pub fn is_gated(&self, friend_public_key: &PublicKey) -> bool {
+        self.gated.contains(friend_public_key)
+    }
+
+    pub fn queue_depth(&self, friend_public_key: &PublicKey) -> usize {
+        self.queue_depths
+            .get(friend_public_key)
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// Id of the last message sent to a friend since it went online.
+    pub fn last_message(&self, friend_public_key: &PublicKey) -> Option<&Uid> {
+        self.last_messages.get(friend_public_key)
+    }
+
+    pub fn secure_channel_stats(&self, friend_public_key: &PublicKey) -> SecureChannelStatsReport {
+        self.secure_channel_stats
+            .get(friend_public_key)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn bandwidth(&self, friend_public_key: &PublicKey) -> BandwidthReport {
+        self.bandwidth
+            .get(friend_public_key)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Are too many messages waiting to be sent to a friend?
+    pub fn is_congested(&self, friend_public_key: &PublicKey) -> bool {
+        self.queue_depth(friend_public_key) >= FRIEND_QUEUE_CONGESTION_DEPTH
+    }
 }
 
 #[cfg(test)]
@@ -86,4 +291,212 @@ mod tests {
         assert!(!liveness.is_online(&pk_b));
         assert!(!liveness.is_online(&pk_c));
     }
+
+    #[test]
+    fn test_liveness_secure_channel_stats() {
+        let mut liveness = Liveness::new();
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+
+        assert_eq!(
+            liveness.secure_channel_stats(&pk_a),
+            SecureChannelStatsReport::default()
+        );
+
+        let secure_channel_stats = SecureChannelStatsReport {
+            decryption_failures: 1,
+            nonce_replays: 2,
+            rekeys: 3,
+        };
+        liveness.mutate(&LivenessMutation::SetSecureChannelStats((
+            pk_a.clone(),
+            secure_channel_stats.clone(),
+        )));
+        assert_eq!(liveness.secure_channel_stats(&pk_a), secure_channel_stats);
+
+        // Stats are kept while the friend is offline:
+        liveness.mutate(&LivenessMutation::SetOffline(pk_a.clone()));
+        assert_eq!(liveness.secure_channel_stats(&pk_a), secure_channel_stats);
+
+        // Stats of removed friends are forgotten:
+        liveness.mutate(&LivenessMutation::SampleUptime(vec![pk_b.clone()]));
+        assert_eq!(
+            liveness.secure_channel_stats(&pk_a),
+            SecureChannelStatsReport::default()
+        );
+    }
+
+    #[test]
+    fn test_liveness_bandwidth() {
+        let mut liveness = Liveness::new();
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+
+        assert_eq!(liveness.bandwidth(&pk_a), BandwidthReport::default());
+
+        let bandwidth = BandwidthReport {
+            bytes_sent: 100,
+            bytes_received: 200,
+        };
+        liveness.mutate(&LivenessMutation::SetBandwidth((
+            pk_a.clone(),
+            bandwidth.clone(),
+        )));
+        assert_eq!(liveness.bandwidth(&pk_a), bandwidth);
+
+        // Bandwidth is kept while the friend is offline:
+        liveness.mutate(&LivenessMutation::SetOffline(pk_a.clone()));
+        assert_eq!(liveness.bandwidth(&pk_a), bandwidth);
+
+        // Bandwidth of removed friends is forgotten:
+        liveness.mutate(&LivenessMutation::SampleUptime(vec![pk_b.clone()]));
+        assert_eq!(liveness.bandwidth(&pk_a), BandwidthReport::default());
+    }
+
+    #[test]
+    fn test_liveness_missed_beats() {
+        let mut liveness = Liveness::new();
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+
+        liveness.mutate(&LivenessMutation::SetOnline(pk_a.clone()));
+        assert_eq!(liveness.missed_beats(&pk_a), 0);
+
+        liveness.mutate(&LivenessMutation::SetMissedBeats((pk_a.clone(), 1)));
+        assert_eq!(liveness.missed_beats(&pk_a), 1);
+
+        // Recovery:
+        liveness.mutate(&LivenessMutation::SetMissedBeats((pk_a.clone(), 0)));
+        assert_eq!(liveness.missed_beats(&pk_a), 0);
+        assert!(liveness.missed_beats.is_empty());
+
+        // Going offline clears missed beats:
+        liveness.mutate(&LivenessMutation::SetMissedBeats((pk_a.clone(), 2)));
+        liveness.mutate(&LivenessMutation::SetOffline(pk_a.clone()));
+        assert_eq!(liveness.missed_beats(&pk_a), 0);
+        assert!(!liveness.is_online(&pk_a));
+    }
+
+    #[test]
+    fn test_liveness_relay_latency() {
+        let mut liveness = Liveness::new();
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        assert_eq!(liveness.relay_latency(&pk_a), None);
+
+        liveness.mutate(&LivenessMutation::SetRelayLatency((pk_a.clone(), Some(40))));
+        assert_eq!(liveness.relay_latency(&pk_a), Some(Some(40)));
+
+        // Relay could not be reached:
+        liveness.mutate(&LivenessMutation::SetRelayLatency((pk_a.clone(), None)));
+        assert_eq!(liveness.relay_latency(&pk_a), Some(None));
+    }
+
+    #[test]
+    fn test_liveness_queue_depth() {
+        let mut liveness = Liveness::new();
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+
+        liveness.mutate(&LivenessMutation::SetOnline(pk_a.clone()));
+        assert_eq!(liveness.queue_depth(&pk_a), 0);
+        assert!(!liveness.is_congested(&pk_a));
+
+        liveness.mutate(&LivenessMutation::SetQueueDepth((
+            pk_a.clone(),
+            FRIEND_QUEUE_CONGESTION_DEPTH,
+        )));
+        assert!(liveness.is_congested(&pk_a));
+
+        liveness.mutate(&LivenessMutation::SetQueueDepth((
+            pk_a.clone(),
+            FRIEND_QUEUE_CONGESTION_DEPTH - 1,
+        )));
+        assert!(!liveness.is_congested(&pk_a));
+
+        liveness.mutate(&LivenessMutation::SetQueueDepth((pk_a.clone(), 0)));
+        assert!(liveness.queue_depths.is_empty());
+
+        // Going offline clears the queue depth:
+        liveness.mutate(&LivenessMutation::SetQueueDepth((
+            pk_a.clone(),
+            FRIEND_QUEUE_CONGESTION_DEPTH,
+        )));
+        liveness.mutate(&LivenessMutation::SetOffline(pk_a.clone()));
+        assert_eq!(liveness.queue_depth(&pk_a), 0);
+    }
+
+    #[test]
+    fn test_liveness_last_message() {
+        let mut liveness = Liveness::new();
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let uid1 = Uid::from(&[1; Uid::len()]);
+        let uid2 = Uid::from(&[2; Uid::len()]);
+
+        liveness.mutate(&LivenessMutation::SetOnline(pk_a.clone()));
+        assert_eq!(liveness.last_message(&pk_a), None);
+
+        liveness.mutate(&LivenessMutation::SetLastMessage((pk_a.clone(), uid1)));
+        liveness.mutate(&LivenessMutation::SetLastMessage((
+            pk_a.clone(),
+            uid2.clone(),
+        )));
+        assert_eq!(liveness.last_message(&pk_a), Some(&uid2));
+
+        // Going offline clears the last message:
+        liveness.mutate(&LivenessMutation::SetOffline(pk_a.clone()));
+        assert_eq!(liveness.last_message(&pk_a), None);
+    }
+
+    #[test]
+    fn test_uptime_window() {
+        let mut uptime_window = UptimeWindow::default();
+        assert_eq!(uptime_window.uptime_percent(), None);
+
+        uptime_window.push(false, 4);
+        assert_eq!(uptime_window.uptime_percent(), Some(0));
+        uptime_window.push(true, 4);
+        assert_eq!(uptime_window.uptime_percent(), Some(50));
+        uptime_window.push(true, 4);
+        uptime_window.push(true, 4);
+        assert_eq!(uptime_window.uptime_percent(), Some(75));
+
+        // The first (offline) sample slides out of the window:
+        uptime_window.push(true, 4);
+        assert_eq!(uptime_window.uptime_percent(), Some(100));
+        uptime_window.push(false, 4);
+        assert_eq!(uptime_window.uptime_percent(), Some(75));
+    }
+
+    #[test]
+    fn test_liveness_sample_uptime() {
+        let mut liveness = Liveness::new();
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+        assert_eq!(liveness.uptime_percent(&pk_a), None);
+
+        liveness.mutate(&LivenessMutation::SetOnline(pk_a.clone()));
+        liveness.mutate(&LivenessMutation::SampleUptime(vec![
+            pk_a.clone(),
+            pk_b.clone(),
+        ]));
+        assert_eq!(liveness.uptime_percent(&pk_a), Some(100));
+        assert_eq!(liveness.uptime_percent(&pk_b), Some(0));
+
+        liveness.mutate(&LivenessMutation::SetOffline(pk_a.clone()));
+        liveness.mutate(&LivenessMutation::SampleUptime(vec![
+            pk_a.clone(),
+            pk_b.clone(),
+        ]));
+        assert_eq!(liveness.uptime_percent(&pk_a), Some(50));
+
+        liveness.mutate(&LivenessMutation::SetGated((pk_b.clone(), true)));
+        assert!(liveness.is_gated(&pk_b));
+        liveness.mutate(&LivenessMutation::SetGated((pk_b.clone(), false)));
+        assert!(!liveness.is_gated(&pk_b));
+
+        // pk_b was removed. Its uptime and gating are forgotten:
+        liveness.mutate(&LivenessMutation::SetGated((pk_b.clone(), true)));
+        liveness.mutate(&LivenessMutation::SampleUptime(vec![pk_a.clone()]));
+        assert_eq!(liveness.uptime_percent(&pk_b), None);
+        assert!(!liveness.is_gated(&pk_b));
+        assert_eq!(liveness.uptime_percent(&pk_a), Some(33));
+    }
 }
@@ -4,8 +4,8 @@ use crypto::identity::verify_signature;
 use common::safe_arithmetic::SafeSignedArithmetic;
 
 use proto::funder::messages::{
-    CancelSendFundsOp, CollectSendFundsOp, FriendTcOp, PendingTransaction, RequestSendFundsOp,
-    ResponseSendFundsOp, TransactionStage,
+    CancelSendFundsOp, CollectSendFundsOp, FriendTcOp, PendingTransaction, RefundSendFundsOp,
+    RequestSendFundsOp, ResponseSendFundsOp, TransactionStage,
 };
 use signature::signature_buff::create_response_signature_buffer;
 
@@ -31,6 +31,12 @@ pub struct IncomingCollectSendFundsOp {
     pub incoming_collect: CollectSendFundsOp,
 }
 
+#[derive(Debug)]
+pub struct IncomingRefundSendFundsOp {
+    pub pending_transaction: PendingTransaction,
+    pub incoming_refund: RefundSendFundsOp,
+}
+
 #[derive(Debug)]
 pub enum IncomingMessage {
     Request(RequestSendFundsOp),
@@ -38,6 +44,7 @@ pub enum IncomingMessage {
     Response(IncomingResponseSendFundsOp),
     Cancel(IncomingCancelSendFundsOp),
     Collect(IncomingCollectSendFundsOp),
+    Refund(IncomingRefundSendFundsOp),
 }
 
 /// Resulting tasks to perform after processing an incoming operation.
@@ -59,6 +66,9 @@ pub enum ProcessOperationError {
     InvalidDestPlainLock,
     NotExpectingCollect,
     DestPaymentExceedsTotal,
+    NotRefundable,
+    /// The change in a response is larger than the payment, or the invoice is not complete
+    InvalidChange,
 }
 
 #[derive(Debug)]
@@ -111,6 +121,9 @@ pub fn process_operation(
         FriendTcOp::CollectSendFunds(collect_send_funds) => {
             process_collect_send_funds(mutual_credit, collect_send_funds)
         }
+        FriendTcOp::RefundSendFunds(refund_send_funds) => {
+            process_refund_send_funds(mutual_credit, refund_send_funds)
+        }
     }
 }
 
@@ -236,6 +249,14 @@ fn process_response_send_funds(
         return Err(ProcessOperationError::NotExpectingResponse);
     }
 
+    // Change may only be given for the transaction that completes the invoice,
+    // and it can not exceed the payment of this transaction:
+    if response_send_funds.change > pending_transaction.signed_dest_payment()
+        || (response_send_funds.change > 0 && !response_send_funds.is_complete)
+    {
+        return Err(ProcessOperationError::InvalidChange);
+    }
+
     let mut mc_mutations = Vec::new();
 
     // Set the stage to Response, and remember dest_hashed_lock:
@@ -244,6 +265,7 @@ fn process_response_send_funds(
         TransactionStage::Response(
             response_send_funds.dest_hashed_lock.clone(),
             response_send_funds.is_complete,
+            response_send_funds.change,
         ),
     ));
     mutual_credit.mutate(&mc_mutation);
@@ -327,8 +349,10 @@ fn process_collect_send_funds(
         .ok_or(ProcessOperationError::RequestDoesNotExist)?
         .clone();
 
-    let dest_hashed_lock = match &pending_transaction.stage {
-        TransactionStage::Response(dest_hashed_lock, _is_complete) => dest_hashed_lock,
+    let (dest_hashed_lock, change) = match &pending_transaction.stage {
+        TransactionStage::Response(dest_hashed_lock, _is_complete, change) => {
+            (dest_hashed_lock, *change)
+        }
         _ => return Err(ProcessOperationError::NotExpectingCollect),
     };
 
@@ -349,6 +373,13 @@ fn process_collect_send_funds(
     // Note: The unwrap() above should never fail, because this was already checked during the
     // request message processing.
 
+    // The change is given back to us. We only pay the rest:
+    let pay_credits = freeze_credits
+        .checked_sub(pending_transaction.channel_change(change))
+        .unwrap();
+    // Note: The unwrap() above should never fail, because change was checked to not exceed
+    // dest_payment when the response was received.
+
     let mut mc_mutations = Vec::new();
 
     // Remove entry from local_pending hashmap:
@@ -373,7 +404,7 @@ fn process_collect_send_funds(
         .state()
         .balance
         .balance
-        .checked_sub_unsigned(freeze_credits)
+        .checked_sub_unsigned(pay_credits)
         .unwrap();
 
     let mc_mutation = McMutation::SetBalance(new_balance);
@@ -390,3 +421,58 @@ fn process_collect_send_funds(
         mc_mutations,
     })
 }
+
+fn process_refund_send_funds(
+    mutual_credit: &mut MutualCredit,
+    refund_send_funds: RefundSendFundsOp,
+) -> Result<ProcessOperationOutput, ProcessOperationError> {
+    // Make sure that id exists in remote_pending hashmap,
+    // and access saved request details.
+    let remote_pending_transactions = &mutual_credit.state().pending_transactions.remote;
+
+    // Obtain pending request:
+    let pending_transaction = remote_pending_transactions
+        .get(&refund_send_funds.request_id)
+        .ok_or(ProcessOperationError::RequestDoesNotExist)?
+        .clone();
+
+    // Only requests that were sent as refundable may be refunded:
+    if pending_transaction.refund_ticks == 0 {
+        return Err(ProcessOperationError::NotRefundable);
+    }
+
+    let mut mc_mutations = Vec::new();
+
+    // Remove entry from remote_pending hashmap:
+    let mc_mutation =
+        McMutation::RemoveRemotePendingTransaction(refund_send_funds.request_id.clone());
+    mutual_credit.mutate(&mc_mutation);
+    mc_mutations.push(mc_mutation);
+
+    let freeze_credits = pending_transaction
+        .dest_payment
+        .checked_add(pending_transaction.left_fees)
+        .unwrap();
+
+    // Decrease frozen credits:
+    let new_remote_pending_debt = mutual_credit
+        .state()
+        .balance
+        .remote_pending_debt
+        .checked_sub(freeze_credits)
+        .unwrap();
+
+    let mc_mutation = McMutation::SetRemotePendingDebt(new_remote_pending_debt);
+    mutual_credit.mutate(&mc_mutation);
+    mc_mutations.push(mc_mutation);
+
+    let incoming_message = Some(IncomingMessage::Refund(IncomingRefundSendFundsOp {
+        pending_transaction,
+        incoming_refund: refund_send_funds,
+    }));
+
+    Ok(ProcessOperationOutput {
+        incoming_message,
+        mc_mutations,
+    })
+}
@@ -4,8 +4,8 @@ use crypto::identity::verify_signature;
 use common::safe_arithmetic::SafeSignedArithmetic;
 
 use proto::funder::messages::{
-    CancelSendFundsOp, CollectSendFundsOp, FriendTcOp, RequestSendFundsOp, ResponseSendFundsOp,
-    TransactionStage,
+    CancelSendFundsOp, CollectSendFundsOp, FriendTcOp, RefundSendFundsOp, RequestSendFundsOp,
+    ResponseSendFundsOp, TransactionStage,
 };
 use signature::signature_buff::create_response_signature_buffer;
 
@@ -34,6 +34,9 @@ pub enum QueueOperationError {
     InvalidSrcPlainLock,
     InvalidDestPlainLock,
     DestPaymentExceedsTotal,
+    NotRefundable,
+    /// The change in a response is larger than the payment, or the invoice is not complete
+    InvalidChange,
 }
 
 /// A wrapper over a token channel, accumulating operations to be sent as one transaction.
@@ -63,6 +66,9 @@ impl OutgoingMc {
             FriendTcOp::CollectSendFunds(collect_send_funds) => {
                 self.queue_collect_send_funds(collect_send_funds)
             }
+            FriendTcOp::RefundSendFunds(refund_send_funds) => {
+                self.queue_refund_send_funds(refund_send_funds)
+            }
         }
     }
 
@@ -165,6 +171,14 @@ impl OutgoingMc {
             return Err(QueueOperationError::NotExpectingResponse);
         }
 
+        // Change may only be given for the transaction that completes the invoice,
+        // and it can not exceed the payment of this transaction:
+        if response_send_funds.change > pending_transaction.signed_dest_payment()
+            || (response_send_funds.change > 0 && !response_send_funds.is_complete)
+        {
+            return Err(QueueOperationError::InvalidChange);
+        }
+
         let mut mc_mutations = Vec::new();
 
         // Set the stage to Response, and remember dest_hashed_lock:
@@ -173,6 +187,7 @@ impl OutgoingMc {
             TransactionStage::Response(
                 response_send_funds.dest_hashed_lock.clone(),
                 response_send_funds.is_complete,
+                response_send_funds.change,
             ),
         ));
         self.mutual_credit.mutate(&mc_mutation);
@@ -237,8 +252,10 @@ impl OutgoingMc {
             .clone();
         // TODO: Possibly get rid of clone() here for optimization later
 
-        let dest_hashed_lock = match &pending_transaction.stage {
-            TransactionStage::Response(dest_hashed_lock, _is_complete) => dest_hashed_lock,
+        let (dest_hashed_lock, change) = match &pending_transaction.stage {
+            TransactionStage::Response(dest_hashed_lock, _is_complete, change) => {
+                (dest_hashed_lock, *change)
+            }
             _ => return Err(QueueOperationError::NotExpectingCollect),
         };
 
@@ -257,6 +274,13 @@ impl OutgoingMc {
             .checked_add(pending_transaction.left_fees)
             .unwrap();
 
+        // The change is given back to the remote side. We only collect the rest:
+        let collect_credits = freeze_credits
+            .checked_sub(pending_transaction.channel_change(change))
+            .unwrap();
+        // Above unwrap() should never fail. Change was checked to not exceed dest_payment when the
+        // response was queued.
+
         // Remove entry from remote_pending hashmap:
         let mut mc_mutations = Vec::new();
         let mc_mutation = McMutation::RemoveRemotePendingTransaction(collect_send_funds.request_id);
@@ -283,7 +307,7 @@ impl OutgoingMc {
             .state()
             .balance
             .balance
-            .checked_add_unsigned(freeze_credits)
+            .checked_add_unsigned(collect_credits)
             .unwrap();
         // Above unwrap() should never fail. This was already checked when a request message was
         // received.
@@ -294,4 +318,50 @@ impl OutgoingMc {
 
         Ok(mc_mutations)
     }
+
+    fn queue_refund_send_funds(
+        &mut self,
+        refund_send_funds: RefundSendFundsOp,
+    ) -> Result<Vec<McMutation>, QueueOperationError> {
+        // Make sure that id exists in local_pending hashmap,
+        // and access saved request details.
+        let local_pending_transactions = &self.mutual_credit.state().pending_transactions.local;
+
+        // Obtain pending request:
+        let pending_transaction = local_pending_transactions
+            .get(&refund_send_funds.request_id)
+            .ok_or(QueueOperationError::RequestDoesNotExist)?;
+
+        // Only requests that were sent as refundable may be refunded:
+        if pending_transaction.refund_ticks == 0 {
+            return Err(QueueOperationError::NotRefundable);
+        }
+
+        let freeze_credits = pending_transaction
+            .dest_payment
+            .checked_add(pending_transaction.left_fees)
+            .unwrap();
+
+        // Remove entry from local hashmap:
+        let mut mc_mutations = Vec::new();
+
+        let mc_mutation = McMutation::RemoveLocalPendingTransaction(refund_send_funds.request_id);
+        self.mutual_credit.mutate(&mc_mutation);
+        mc_mutations.push(mc_mutation);
+
+        // Decrease frozen credits:
+        let new_local_pending_debt = self
+            .mutual_credit
+            .state()
+            .balance
+            .local_pending_debt
+            .checked_sub(freeze_credits)
+            .unwrap();
+
+        let mc_mutation = McMutation::SetLocalPendingDebt(new_local_pending_debt);
+        self.mutual_credit.mutate(&mc_mutation);
+        mc_mutations.push(mc_mutation);
+
+        Ok(mc_mutations)
+    }
 }
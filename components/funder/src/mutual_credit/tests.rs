@@ -7,8 +7,8 @@ use crypto::test_utils::DummyRandom;
 
 use proto::crypto::{InvoiceId, PlainLock, PrivateKey, PublicKey, RandValue, Signature, Uid};
 use proto::funder::messages::{
-    CancelSendFundsOp, CollectSendFundsOp, Currency, FriendTcOp, FriendsRoute, RequestSendFundsOp,
-    ResponseSendFundsOp,
+    CancelSendFundsOp, CollectSendFundsOp, Currency, FriendTcOp, FriendsRoute, RefundSendFundsOp,
+    RequestSendFundsOp, ResponseSendFundsOp,
 };
 use signature::signature_buff::create_response_signature_buffer;
 
@@ -79,6 +79,9 @@ fn test_request_response_collect_send_funds() {
         total_dest_payment: 10,
         invoice_id,
         left_fees: 5,
+        expiry_ticks: 0,
+        refund_ticks: 0,
+        opt_exchange: None,
     };
 
     let pending_transaction = create_pending_transaction(&request_send_funds);
@@ -101,6 +104,7 @@ fn test_request_response_collect_send_funds() {
         request_id: request_id.clone(),
         dest_hashed_lock: dest_plain_lock.hash_lock(),
         is_complete: false,
+        change: 0,
         rand_nonce: rand_nonce.clone(),
         signature: Signature::from(&[0; Signature::len()]),
     };
@@ -145,6 +149,160 @@ fn test_request_response_collect_send_funds() {
     assert_eq!(mutual_credit.state().balance.remote_pending_debt, 0);
 }
 
+#[test]
+fn test_request_response_collect_send_funds_with_change() {
+    let currency = Currency::try_from("OFFST".to_owned()).unwrap();
+
+    let local_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+    let remote_public_key = PublicKey::from(&[0xbb; PublicKey::len()]);
+    let balance = 0;
+    let mut mutual_credit =
+        MutualCredit::new(&local_public_key, &remote_public_key, &currency, balance);
+
+    // -----[RequestSendFunds]--------
+    // -----------------------------
+    let rng = DummyRandom::new(&[1u8]);
+    let private_key = PrivateKey::rand_gen(&rng);
+    let identity = SoftwareEd25519Identity::from_private_key(&private_key).unwrap();
+    let public_key_c = identity.get_public_key();
+
+    let request_id = Uid::from(&[3; Uid::len()]);
+    let route = FriendsRoute {
+        public_keys: vec![
+            PublicKey::from(&[0xaa; PublicKey::len()]),
+            PublicKey::from(&[0xbb; PublicKey::len()]),
+            public_key_c.clone(),
+        ],
+    };
+    let invoice_id = InvoiceId::from(&[0; InvoiceId::len()]);
+    let src_plain_lock = PlainLock::from(&[1; PlainLock::len()]);
+
+    let request_send_funds = RequestSendFundsOp {
+        request_id: request_id.clone(),
+        src_hashed_lock: src_plain_lock.hash_lock(),
+        route,
+        dest_payment: 10,
+        total_dest_payment: 20,
+        invoice_id,
+        left_fees: 5,
+        expiry_ticks: 0,
+        refund_ticks: 0,
+        opt_exchange: None,
+    };
+
+    let pending_transaction = create_pending_transaction(&request_send_funds);
+    apply_outgoing(
+        &mut mutual_credit,
+        &FriendTcOp::RequestSendFunds(request_send_funds),
+    )
+    .unwrap();
+
+    assert_eq!(mutual_credit.state().balance.balance, 0);
+    assert_eq!(mutual_credit.state().balance.local_pending_debt, 10 + 5);
+    assert_eq!(mutual_credit.state().balance.remote_pending_debt, 0);
+
+    // -----[ResponseSendFunds]--------
+    // --------------------------------
+    let rand_nonce = RandValue::from(&[5; RandValue::len()]);
+    let dest_plain_lock = PlainLock::from(&[2; PlainLock::len()]);
+
+    // Change is only allowed if the invoice is complete:
+    let mut response_send_funds = ResponseSendFundsOp {
+        request_id: request_id.clone(),
+        dest_hashed_lock: dest_plain_lock.hash_lock(),
+        is_complete: false,
+        change: 4,
+        rand_nonce: rand_nonce.clone(),
+        signature: Signature::from(&[0; Signature::len()]),
+    };
+
+    let sign_buffer = create_response_signature_buffer(
+        &currency,
+        response_send_funds.clone(),
+        &pending_transaction,
+    );
+    response_send_funds.signature = identity.sign(&sign_buffer);
+
+    assert!(apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::ResponseSendFunds(response_send_funds),
+        100,
+    )
+    .is_err());
+
+    // Change can not exceed dest_payment:
+    let mut response_send_funds = ResponseSendFundsOp {
+        request_id: request_id.clone(),
+        dest_hashed_lock: dest_plain_lock.hash_lock(),
+        is_complete: true,
+        change: 11,
+        rand_nonce: rand_nonce.clone(),
+        signature: Signature::from(&[0; Signature::len()]),
+    };
+
+    let sign_buffer = create_response_signature_buffer(
+        &currency,
+        response_send_funds.clone(),
+        &pending_transaction,
+    );
+    response_send_funds.signature = identity.sign(&sign_buffer);
+
+    assert!(apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::ResponseSendFunds(response_send_funds),
+        100,
+    )
+    .is_err());
+
+    let mut response_send_funds = ResponseSendFundsOp {
+        request_id: request_id.clone(),
+        dest_hashed_lock: dest_plain_lock.hash_lock(),
+        is_complete: true,
+        change: 4,
+        rand_nonce: rand_nonce.clone(),
+        signature: Signature::from(&[0; Signature::len()]),
+    };
+
+    let sign_buffer = create_response_signature_buffer(
+        &currency,
+        response_send_funds.clone(),
+        &pending_transaction,
+    );
+    response_send_funds.signature = identity.sign(&sign_buffer);
+
+    apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::ResponseSendFunds(response_send_funds),
+        100,
+    )
+    .unwrap();
+
+    // We expect that no changes to balance happened yet:
+    assert_eq!(mutual_credit.state().balance.balance, 0);
+    assert_eq!(mutual_credit.state().balance.local_pending_debt, 10 + 5);
+    assert_eq!(mutual_credit.state().balance.remote_pending_debt, 0);
+
+    // -----[CollectSendFunds]--------
+    // --------------------------------
+    let collect_send_funds = CollectSendFundsOp {
+        request_id,
+        src_plain_lock,
+        dest_plain_lock,
+    };
+
+    apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::CollectSendFunds(collect_send_funds),
+        100,
+    )
+    .unwrap();
+
+    // The change is not paid:
+    assert_eq!(mutual_credit.state().balance.balance, -(15 - 4));
+    assert_eq!(mutual_credit.state().balance.local_pending_debt, 0);
+    assert_eq!(mutual_credit.state().balance.remote_pending_debt, 0);
+}
+
 #[test]
 fn test_request_cancel_send_funds() {
     let currency = Currency::try_from("OFFST".to_owned()).unwrap();
@@ -181,6 +339,9 @@ fn test_request_cancel_send_funds() {
         total_dest_payment: 10,
         invoice_id,
         left_fees: 5,
+        expiry_ticks: 0,
+        refund_ticks: 0,
+        opt_exchange: None,
     };
 
     apply_outgoing(
@@ -245,6 +406,9 @@ fn test_request_response_cancel_send_funds() {
         total_dest_payment: 10,
         invoice_id,
         left_fees: 5,
+        expiry_ticks: 0,
+        refund_ticks: 0,
+        opt_exchange: None,
     };
 
     let pending_transaction = create_pending_transaction(&request_send_funds);
@@ -267,6 +431,7 @@ fn test_request_response_cancel_send_funds() {
         request_id: request_id.clone(),
         dest_hashed_lock: dest_plain_lock.hash_lock(),
         is_complete: true,
+        change: 0,
         rand_nonce: rand_nonce.clone(),
         signature: Signature::from(&[0; Signature::len()]),
     };
@@ -305,3 +470,92 @@ fn test_request_response_cancel_send_funds() {
     assert_eq!(mutual_credit.state().balance.local_pending_debt, 0);
     assert_eq!(mutual_credit.state().balance.remote_pending_debt, 0);
 }
+
+#[test]
+fn test_request_refund_send_funds() {
+    let currency = Currency::try_from("OFFST".to_owned()).unwrap();
+
+    let local_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+    let remote_public_key = PublicKey::from(&[0xbb; PublicKey::len()]);
+    let balance = 0;
+    let mut mutual_credit =
+        MutualCredit::new(&local_public_key, &remote_public_key, &currency, balance);
+
+    // -----[RequestSendFunds]--------
+    // -----------------------------
+    let refundable_request_id = Uid::from(&[3; Uid::len()]);
+    let request_id = Uid::from(&[4; Uid::len()]);
+    let route = FriendsRoute {
+        public_keys: vec![PublicKey::from(&[0xcc; PublicKey::len()])],
+    };
+    let invoice_id = InvoiceId::from(&[0; InvoiceId::len()]);
+    let src_plain_lock = PlainLock::from(&[1; PlainLock::len()]);
+
+    // A refundable request from the remote side:
+    let request_send_funds = RequestSendFundsOp {
+        request_id: refundable_request_id.clone(),
+        src_hashed_lock: src_plain_lock.hash_lock(),
+        route: route.clone(),
+        dest_payment: 10,
+        total_dest_payment: 10,
+        invoice_id: invoice_id.clone(),
+        left_fees: 5,
+        expiry_ticks: 0,
+        refund_ticks: 0x20,
+        opt_exchange: None,
+    };
+
+    apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::RequestSendFunds(request_send_funds),
+        100,
+    )
+    .unwrap();
+
+    // A request from the local side that is never refunded:
+    let request_send_funds = RequestSendFundsOp {
+        request_id: request_id.clone(),
+        src_hashed_lock: src_plain_lock.hash_lock(),
+        route,
+        dest_payment: 10,
+        total_dest_payment: 10,
+        invoice_id,
+        left_fees: 5,
+        expiry_ticks: 0,
+        refund_ticks: 0,
+        opt_exchange: None,
+    };
+
+    apply_outgoing(
+        &mut mutual_credit,
+        &FriendTcOp::RequestSendFunds(request_send_funds),
+    )
+    .unwrap();
+
+    assert_eq!(mutual_credit.state().balance.balance, 0);
+    assert_eq!(mutual_credit.state().balance.local_pending_debt, 10 + 5);
+    assert_eq!(mutual_credit.state().balance.remote_pending_debt, 10 + 5);
+
+    // -----[RefundSendFunds]--------
+    // ------------------------------
+    let refund_send_funds = RefundSendFundsOp { request_id };
+    assert!(apply_outgoing(
+        &mut mutual_credit,
+        &FriendTcOp::RefundSendFunds(refund_send_funds)
+    )
+    .is_err());
+
+    let refund_send_funds = RefundSendFundsOp {
+        request_id: refundable_request_id,
+    };
+    apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::RefundSendFunds(refund_send_funds),
+        100,
+    )
+    .unwrap();
+
+    assert_eq!(mutual_credit.state().balance.balance, 0);
+    assert_eq!(mutual_credit.state().balance.local_pending_debt, 10 + 5);
+    assert_eq!(mutual_credit.state().balance.remote_pending_debt, 0);
+}
@@ -24,6 +24,18 @@ pub struct McIdents {
     pub remote_public_key: PublicKey,
 }
 
+impl McIdents {
+    /// Replace the public key of one of the sides, after that side has rotated its key.
+    pub fn rotate_key(&mut self, old_public_key: &PublicKey, new_public_key: &PublicKey) {
+        if &self.local_public_key == old_public_key {
+            self.local_public_key = new_public_key.clone();
+        }
+        if &self.remote_public_key == old_public_key {
+            self.remote_public_key = new_public_key.clone();
+        }
+    }
+}
+
 // TODO: Rename this to McBalance
 #[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct McBalance {
@@ -137,6 +149,11 @@ impl MutualCredit {
         &self.state
     }
 
+    /// Replace the public key of one of the sides, after that side has rotated its key.
+    pub fn rotate_key(&mut self, old_public_key: &PublicKey, new_public_key: &PublicKey) {
+        self.state.idents.rotate_key(old_public_key, new_public_key);
+    }
+
     pub fn mutate(&mut self, mc_mutation: &McMutation) {
         match mc_mutation {
             McMutation::SetBalance(balance) => self.set_balance(*balance),
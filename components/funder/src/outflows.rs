@@ -0,0 +1,156 @@
+use im::hashmap::HashMap as ImHashMap;
+
+use proto::crypto::PublicKey;
+use proto::funder::messages::{Currency, MaxOutflow};
+
+/// Credits sent to a friend during the current window of its outflow limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutflowWindow {
+    pub outflow: u128,
+    pub ticks_left: u64,
+}
+
+/// Current windows of outflow limits, by friend and currency.
+/// A window starts when credits are first sent to a friend (For a currency with an outflow limit),
+/// and ends after `window_ticks` ticks.
+#[derive(Clone, Default)]
+pub struct Outflows {
+    pub windows: ImHashMap<(PublicKey, Currency), OutflowWindow>,
+}
+
+#[derive(Debug)]
+pub enum OutflowsMutation {
+    SetWindow((PublicKey, Currency, OutflowWindow)),
+    SetTicksLeft((PublicKey, Currency, u64)),
+    Remove((PublicKey, Currency)),
+}
+
+impl Outflows {
+    pub fn new() -> Outflows {
+        Outflows {
+            windows: ImHashMap::new(),
+        }
+    }
+
+    pub fn mutate(&mut self, mutation: &OutflowsMutation) {
+        match mutation {
+            OutflowsMutation::SetWindow((public_key, currency, window)) => {
+                let _ = self
+                    .windows
+                    .insert((public_key.clone(), currency.clone()), window.clone());
+            }
+            OutflowsMutation::SetTicksLeft((public_key, currency, ticks_left)) => {
+                if let Some(window) = self
+                    .windows
+                    .get_mut(&(public_key.clone(), currency.clone()))
+                {
+                    window.ticks_left = *ticks_left;
+                }
+            }
+            OutflowsMutation::Remove((public_key, currency)) => {
+                let _ = self.windows.remove(&(public_key.clone(), currency.clone()));
+            }
+        }
+    }
+
+    /// Credits sent to a friend during the current window
+    pub fn outflow(&self, public_key: &PublicKey, currency: &Currency) -> u128 {
+        self.windows
+            .get(&(public_key.clone(), currency.clone()))
+            .map(|window| window.outflow)
+            .unwrap_or(0)
+    }
+
+    /// Calculate the window of a friend after sending it `amount` more credits.
+    /// Returns None if sending `amount` credits exceeds `max_outflow`.
+    pub fn charge(
+        &self,
+        public_key: &PublicKey,
+        currency: &Currency,
+        max_outflow: &MaxOutflow,
+        amount: u128,
+    ) -> Option<OutflowWindow> {
+        let window = self
+            .windows
+            .get(&(public_key.clone(), currency.clone()))
+            .cloned()
+            .unwrap_or(OutflowWindow {
+                outflow: 0,
+                ticks_left: max_outflow.window_ticks,
+            });
+
+        let outflow = window.outflow.checked_add(amount)?;
+        if outflow > max_outflow.max_outflow {
+            return None;
+        }
+        Some(OutflowWindow { outflow, ..window })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_outflows_charge() {
+        let public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let max_outflow = MaxOutflow {
+            max_outflow: 100,
+            window_ticks: 5,
+        };
+
+        let mut outflows = Outflows::new();
+        assert_eq!(outflows.outflow(&public_key, &currency), 0);
+
+        // A new window starts:
+        let window = outflows
+            .charge(&public_key, &currency, &max_outflow, 60)
+            .unwrap();
+        assert_eq!(
+            window,
+            OutflowWindow {
+                outflow: 60,
+                ticks_left: 5
+            }
+        );
+        outflows.mutate(&OutflowsMutation::SetWindow((
+            public_key.clone(),
+            currency.clone(),
+            window,
+        )));
+        outflows.mutate(&OutflowsMutation::SetTicksLeft((
+            public_key.clone(),
+            currency.clone(),
+            2,
+        )));
+        assert_eq!(outflows.outflow(&public_key, &currency), 60);
+
+        // The window continues, and the limit is exceeded:
+        assert!(outflows
+            .charge(&public_key, &currency, &max_outflow, 41)
+            .is_none());
+        assert!(outflows
+            .charge(&public_key, &currency, &max_outflow, u128::max_value())
+            .is_none());
+        assert_eq!(
+            outflows.charge(&public_key, &currency, &max_outflow, 40),
+            Some(OutflowWindow {
+                outflow: 100,
+                ticks_left: 2
+            })
+        );
+
+        // Once the window ends, credits may be sent again:
+        outflows.mutate(&OutflowsMutation::Remove((
+            public_key.clone(),
+            currency.clone(),
+        )));
+        assert_eq!(outflows.outflow(&public_key, &currency), 0);
+        assert!(outflows
+            .charge(&public_key, &currency, &max_outflow, 100)
+            .is_some());
+    }
+}
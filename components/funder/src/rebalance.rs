@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use common::safe_arithmetic::{SafeSignedArithmetic, SafeUnsignedArithmetic};
+
+use signature::canonical::CanonicalSerialize;
+
+use proto::crypto::PublicKey;
+use proto::funder::messages::{Currency, FriendStatus};
+use proto::report::messages::RebalanceSuggestion;
+
+use crate::friend::{ChannelStatus, DrainStatus};
+use crate::mutual_credit::types::McBalance;
+use crate::state::FunderState;
+
+/// Latest rebalancing suggestions, and the amount of ticks left until the next analysis.
+/// Suggestions are not persistent, therefore the first analysis happens right after a restart.
+#[derive(Clone, Default)]
+pub struct Rebalance {
+    pub suggestions: Vec<RebalanceSuggestion>,
+    pub ticks_left: usize,
+}
+
+#[derive(Debug)]
+pub enum RebalanceMutation {
+    SetTicksLeft(usize),
+    SetSuggestions(Vec<RebalanceSuggestion>),
+}
+
+impl Rebalance {
+    pub fn new() -> Rebalance {
+        Rebalance {
+            suggestions: Vec::new(),
+            ticks_left: 0,
+        }
+    }
+
+    pub fn mutate(&mut self, mutation: &RebalanceMutation) {
+        match mutation {
+            RebalanceMutation::SetTicksLeft(ticks_left) => {
+                self.ticks_left = *ticks_left;
+            }
+            RebalanceMutation::SetSuggestions(suggestions) => {
+                self.suggestions = suggestions.clone();
+            }
+        }
+    }
+}
+
+/// Amount of credits a friend may still send us in one currency
+#[derive(Debug, Clone)]
+struct FriendCapacity {
+    public_key: PublicKey,
+    remote_max_debt: u128,
+    recv_capacity: u128,
+}
+
+/// Credits the friend may still send us before reaching the maximum debt we allow it.
+/// Requests the friend has sent (remote pending debt) are counted as if they succeed.
+fn recv_capacity(remote_max_debt: u128, mc_balance: &McBalance) -> u128 {
+    let pending_balance = mc_balance
+        .balance
+        .saturating_add_unsigned(mc_balance.remote_pending_debt);
+    remote_max_debt.saturating_sub_signed(pending_balance)
+}
+
+/// Pair friends that can hardly send us credits with friends that have plenty of spare receive
+/// capacity, for a single currency.
+///
+/// A friend is depleted if less than a quarter of its maximum debt is left, and has spare
+/// capacity if more than three quarters are left. Every self payment moves both friends towards
+/// half of their maximum debt.
+fn currency_suggestions(
+    currency: &Currency,
+    capacities: &[FriendCapacity],
+) -> Vec<RebalanceSuggestion> {
+    let mut depleted = Vec::new();
+    let mut spare = Vec::new();
+    for capacity in capacities {
+        let target = capacity.remote_max_debt / 2;
+        let quarter = capacity.remote_max_debt / 4;
+        if capacity.recv_capacity < quarter {
+            depleted.push((capacity.public_key.clone(), target - capacity.recv_capacity));
+        } else if capacity.recv_capacity > capacity.remote_max_debt - quarter {
+            spare.push((capacity.public_key.clone(), capacity.recv_capacity - target));
+        }
+    }
+
+    // Largest amounts first. Sorted by public key otherwise, to get stable suggestions:
+    depleted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    spare.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    let mut suggestions = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < depleted.len() && j < spare.len() {
+        let amount = depleted[i].1.min(spare[j].1);
+        suggestions.push(RebalanceSuggestion {
+            currency: currency.clone(),
+            from_public_key: spare[j].0.clone(),
+            to_public_key: depleted[i].0.clone(),
+            amount,
+        });
+        depleted[i].1 -= amount;
+        spare[j].1 -= amount;
+        if depleted[i].1 == 0 {
+            i += 1;
+        }
+        if spare[j].1 == 0 {
+            j += 1;
+        }
+    }
+    suggestions
+}
+
+/// Inspect the balances with all friends, and suggest self payments that restore the receive
+/// capacity of depleted friends, using the spare receive capacity of other friends.
+///
+/// Only enabled and active friends with a consistent channel and open requests are considered.
+/// At most `max_suggestions` suggestions are returned, largest amounts first.
+pub fn calc_rebalance_suggestions<B>(
+    state: &FunderState<B>,
+    max_suggestions: usize,
+) -> Vec<RebalanceSuggestion>
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let mut capacities: BTreeMap<Currency, Vec<FriendCapacity>> = BTreeMap::new();
+    for (friend_public_key, friend) in &state.friends {
+        if friend.status != FriendStatus::Enabled || friend.drain_status != DrainStatus::Active {
+            continue;
+        }
+        let channel_consistent = match &friend.channel_status {
+            ChannelStatus::Consistent(channel_consistent) => channel_consistent,
+            ChannelStatus::Inconsistent(_) => continue,
+        };
+        for (currency, mutual_credit) in channel_consistent.token_channel.get_mutual_credits() {
+            let remote_max_debt = match friend.currency_configs.get(currency) {
+                Some(currency_config) if currency_config.is_open => currency_config.remote_max_debt,
+                _ => continue,
+            };
+            if remote_max_debt == 0 {
+                continue;
+            }
+            capacities
+                .entry(currency.clone())
+                .or_insert_with(Vec::new)
+                .push(FriendCapacity {
+                    public_key: friend_public_key.clone(),
+                    remote_max_debt,
+                    recv_capacity: recv_capacity(remote_max_debt, &mutual_credit.state().balance),
+                });
+        }
+    }
+
+    let mut suggestions = capacities
+        .iter()
+        .flat_map(|(currency, currency_capacities)| {
+            currency_suggestions(currency, currency_capacities)
+        })
+        .collect::<Vec<_>>();
+
+    // The order of suggestions of a single currency is kept, as sorting is stable:
+    suggestions.sort_by(|a, b| b.amount.cmp(&a.amount));
+    suggestions.truncate(max_suggestions);
+    suggestions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    fn friend_capacity(i: u8, remote_max_debt: u128, recv_capacity: u128) -> FriendCapacity {
+        FriendCapacity {
+            public_key: PublicKey::from(&[i; PublicKey::len()]),
+            remote_max_debt,
+            recv_capacity,
+        }
+    }
+
+    #[test]
+    fn test_recv_capacity() {
+        let mc_balance = McBalance {
+            balance: 30,
+            local_pending_debt: 40,
+            remote_pending_debt: 20,
+        };
+        assert_eq!(recv_capacity(100, &mc_balance), 50);
+        assert_eq!(recv_capacity(40, &mc_balance), 0);
+
+        // We owe the friend credits:
+        let mc_balance = McBalance {
+            balance: -30,
+            local_pending_debt: 0,
+            remote_pending_debt: 0,
+        };
+        assert_eq!(recv_capacity(100, &mc_balance), 130);
+    }
+
+    #[test]
+    fn test_currency_suggestions() {
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let pk = |i: u8| PublicKey::from(&[i; PublicKey::len()]);
+
+        let capacities = vec![
+            // Depleted, needs 40 credits:
+            friend_capacity(0, 100, 10),
+            // Neither depleted nor spare:
+            friend_capacity(1, 100, 60),
+            // Spare, can give 30 credits:
+            friend_capacity(2, 100, 80),
+            // Depleted, needs 100 credits:
+            friend_capacity(3, 200, 0),
+            // Spare, can give 130 credits:
+            friend_capacity(4, 100, 180),
+        ];
+
+        assert_eq!(
+            currency_suggestions(&currency, &capacities),
+            vec![
+                RebalanceSuggestion {
+                    currency: currency.clone(),
+                    from_public_key: pk(4),
+                    to_public_key: pk(3),
+                    amount: 100,
+                },
+                RebalanceSuggestion {
+                    currency: currency.clone(),
+                    from_public_key: pk(4),
+                    to_public_key: pk(0),
+                    amount: 30,
+                },
+                RebalanceSuggestion {
+                    currency: currency.clone(),
+                    from_public_key: pk(2),
+                    to_public_key: pk(0),
+                    amount: 10,
+                },
+            ]
+        );
+
+        // Nothing to suggest without friends that have spare capacity:
+        let capacities = vec![friend_capacity(0, 100, 10), friend_capacity(1, 100, 60)];
+        assert!(currency_suggestions(&currency, &capacities).is_empty());
+    }
+}
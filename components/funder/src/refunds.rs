@@ -0,0 +1,40 @@
+use im::hashmap::HashMap as ImHashMap;
+
+use proto::crypto::Uid;
+
+/// Countdowns of pending refundable requests, by request id.
+/// A countdown starts when a request first sees a tick (After it was received from the previous
+/// node, or after it was sent to the next node if we are the origin of the request). When the
+/// countdown ends, the request is refunded.
+///
+/// An expired countdown of a forwarded request stays at 0 until the request is resolved, so that
+/// it will not start again.
+#[derive(Clone, Default)]
+pub struct Refunds {
+    pub ticks_left: ImHashMap<Uid, u64>,
+}
+
+#[derive(Debug)]
+pub enum RefundsMutation {
+    SetTicksLeft((Uid, u64)),
+    Remove(Uid),
+}
+
+impl Refunds {
+    pub fn new() -> Refunds {
+        Refunds {
+            ticks_left: ImHashMap::new(),
+        }
+    }
+
+    pub fn mutate(&mut self, mutation: &RefundsMutation) {
+        match mutation {
+            RefundsMutation::SetTicksLeft((request_id, ticks_left)) => {
+                let _ = self.ticks_left.insert(request_id.clone(), *ticks_left);
+            }
+            RefundsMutation::Remove(request_id) => {
+                let _ = self.ticks_left.remove(request_id);
+            }
+        }
+    }
+}
@@ -3,18 +3,21 @@ use std::collections::HashMap as ImHashMap;
 use signature::canonical::CanonicalSerialize;
 
 use proto::report::messages::{
-    AddFriendReport, ChannelConsistentReport, ChannelInconsistentReport, ChannelStatusReport,
-    CurrencyConfigReport, CurrencyReport, FriendLivenessReport, FriendReport, FriendReportMutation,
+    AddFriendReport, BandwidthReport, ChannelConsistentReport, ChannelInconsistentReport,
+    ChannelStatusReport, CurrencyConfigReport, CurrencyOutflowReport, CurrencyReport,
+    DrainStatusReport, FriendLivenessReport, FriendReport, FriendReportMutation,
     FriendStatusReport, FunderReport, FunderReportMutation, McBalanceReport, MoveTokenHashedReport,
-    ResetTermsReport,
+    RelayLatencyReport, ResetTermsReport, SecureChannelStatsReport,
 };
 
 use crate::types::MoveTokenHashed;
 
 use crate::ephemeral::{Ephemeral, EphemeralMutation};
-use crate::friend::{ChannelStatus, FriendMutation, FriendState};
+use crate::friend::{ChannelStatus, DrainStatus, FriendMutation, FriendState};
 use crate::liveness::LivenessMutation;
 use crate::mutual_credit::types::McBalance;
+use crate::outflows::{Outflows, OutflowsMutation};
+use crate::rebalance::RebalanceMutation;
 use crate::state::{FunderMutation, FunderState};
 
 impl From<&McBalance> for McBalanceReport {
@@ -27,6 +30,18 @@ impl From<&McBalance> for McBalanceReport {
     }
 }
 
+impl From<&DrainStatus> for DrainStatusReport {
+    fn from(drain_status: &DrainStatus) -> DrainStatusReport {
+        match drain_status {
+            DrainStatus::Active => DrainStatusReport::Active,
+            DrainStatus::Draining => DrainStatusReport::Draining,
+            DrainStatus::Closed(closing_statement) => {
+                DrainStatusReport::Closed(closing_statement.clone())
+            }
+        }
+    }
+}
+
 impl From<&MoveTokenHashed> for MoveTokenHashedReport {
     fn from(move_token_hashed: &MoveTokenHashed) -> MoveTokenHashedReport {
         MoveTokenHashedReport {
@@ -58,6 +73,7 @@ where
                         .balance_for_reset
                         .clone(),
                     opt_remote_reset_terms,
+                    dispute_evidence: channel_inconsistent.dispute_evidence.clone(),
                 };
                 ChannelStatusReport::Inconsistent(channel_inconsistent_report)
             }
@@ -85,6 +101,11 @@ where
 fn create_friend_report<B>(
     friend_state: &FriendState<B>,
     friend_liveness: &FriendLivenessReport,
+    missed_beats: u64,
+    is_gated: bool,
+    secure_channel_stats: SecureChannelStatsReport,
+    bandwidth: BandwidthReport,
+    outflows: &Outflows,
 ) -> FriendReport<B>
 where
     B: Clone + CanonicalSerialize,
@@ -99,10 +120,12 @@ where
             .into_iter()
             // .cloned()
             .map(|(currency, currency_config)| CurrencyConfigReport {
+                outflow: outflows.outflow(&friend_state.remote_public_key, &currency),
                 currency,
                 rate: currency_config.rate,
                 remote_max_debt: currency_config.remote_max_debt,
                 is_open: currency_config.is_open,
+                opt_max_outflow: currency_config.opt_max_outflow,
             })
             .collect(),
         remote_relays: friend_state.remote_relays.clone(),
@@ -113,6 +136,11 @@ where
         liveness: friend_liveness.clone(),
         channel_status,
         status: FriendStatusReport::from(&friend_state.status),
+        missed_beats,
+        is_gated,
+        secure_channel_stats,
+        drain_status: DrainStatusReport::from(&friend_state.drain_status),
+        bandwidth,
     }
 }
 
@@ -127,7 +155,19 @@ where
         } else {
             FriendLivenessReport::Offline
         };
-        let friend_report = create_friend_report(&friend_state, &friend_liveness);
+        let missed_beats = ephemeral.liveness.missed_beats(friend_public_key);
+        let is_gated = ephemeral.liveness.is_gated(friend_public_key);
+        let secure_channel_stats = ephemeral.liveness.secure_channel_stats(friend_public_key);
+        let bandwidth = ephemeral.liveness.bandwidth(friend_public_key);
+        let friend_report = create_friend_report(
+            &friend_state,
+            &friend_liveness,
+            missed_beats,
+            is_gated,
+            secure_channel_stats,
+            bandwidth,
+            &ephemeral.outflows,
+        );
         friends.insert(friend_public_key.clone(), friend_report);
     }
 
@@ -135,6 +175,16 @@ where
         local_public_key: funder_state.local_public_key.clone(),
         relays: funder_state.relays.clone().into_iter().collect(),
         friends: friends.into_iter().collect(),
+        relay_latencies: ephemeral
+            .liveness
+            .relay_latencies
+            .iter()
+            .map(|(public_key, opt_latency_ms)| RelayLatencyReport {
+                public_key: public_key.clone(),
+                opt_latency_ms: *opt_latency_ms,
+            })
+            .collect(),
+        rebalance_suggestions: ephemeral.rebalance.suggestions.clone(),
     }
 }
 
@@ -171,9 +221,11 @@ where
         | FriendMutation::PopFrontPendingRequest
         | FriendMutation::PushBackPendingBackwardsOp(_)
         | FriendMutation::PopFrontPendingBackwardsOp
+        | FriendMutation::RemovePendingBackwardsOps(_)
         | FriendMutation::PushBackPendingUserRequest(_)
         | FriendMutation::PopFrontPendingUserRequest
         | FriendMutation::RemovePendingRequests
+        | FriendMutation::RemovePendingRequest(_)
         | FriendMutation::RemovePendingRequestsCurrency(_)
         | FriendMutation::RemovePendingUserRequestsCurrency(_) => vec![],
         FriendMutation::SetStatus(friend_status) => vec![FriendReportMutation::SetStatus(
@@ -190,13 +242,19 @@ where
                     rate: currency_config.rate.clone(),
                     remote_max_debt: currency_config.remote_max_debt,
                     is_open: currency_config.is_open,
+                    opt_max_outflow: currency_config.opt_max_outflow.clone(),
+                    // Not updated by this mutation. See `OutflowsMutation`:
+                    outflow: 0,
                 },
             )]
         }
         FriendMutation::RemoveCurrencyConfig(currency) => {
             vec![FriendReportMutation::RemoveCurrencyConfig(currency.clone())]
         }
-        FriendMutation::SetSentLocalRelays(_) => vec![],
+        FriendMutation::SetSentLocalRelays(_) | FriendMutation::SetRemoteCapabilities(_) => vec![],
+        FriendMutation::SetDrainStatus(drain_status) => vec![FriendReportMutation::SetDrainStatus(
+            DrainStatusReport::from(drain_status),
+        )],
         FriendMutation::SetInconsistent(_) | FriendMutation::SetConsistent(_) => {
             let channel_status_report = ChannelStatusReport::from(&friend_after.channel_status);
             let set_channel_status = FriendReportMutation::SetChannelStatus(channel_status_report);
@@ -268,15 +326,76 @@ where
                 friend_public_key.clone(),
             )]
         }
+        FunderMutation::RotateFriendKey(key_rotation) => {
+            // Ignored rotations (See `FunderState::mutate`) do not change the report:
+            if !funder_state
+                .friends
+                .contains_key(&key_rotation.old_public_key)
+                || funder_state
+                    .friends
+                    .contains_key(&key_rotation.new_public_key)
+            {
+                return Vec::new();
+            }
+            // The friend is reported as a new friend with the new key:
+            let friend_public_key = &key_rotation.new_public_key;
+            let friend_after = funder_state_after.friends.get(friend_public_key).unwrap();
+            let add_friend_report = AddFriendReport {
+                friend_public_key: friend_public_key.clone(),
+                name: friend_after.name.clone(),
+                relays: friend_after.remote_relays.clone(),
+                opt_last_incoming_move_token: friend_after
+                    .channel_status
+                    .get_last_incoming_move_token_hashed()
+                    .map(|move_token_hashed| MoveTokenHashedReport::from(&move_token_hashed)),
+                channel_status: ChannelStatusReport::from(&friend_after.channel_status),
+            };
+            let mut friend_report_mutations = vec![FriendReportMutation::SetStatus(
+                FriendStatusReport::from(&friend_after.status),
+            )];
+            for (currency, currency_config) in &friend_after.currency_configs {
+                friend_report_mutations.push(FriendReportMutation::UpdateCurrencyConfig(
+                    CurrencyConfigReport {
+                        currency: currency.clone(),
+                        rate: currency_config.rate.clone(),
+                        remote_max_debt: currency_config.remote_max_debt,
+                        is_open: currency_config.is_open,
+                        opt_max_outflow: currency_config.opt_max_outflow.clone(),
+                        outflow: 0,
+                    },
+                ));
+            }
+
+            let mut report_mutations = vec![
+                FunderReportMutation::RemoveFriend(key_rotation.old_public_key.clone()),
+                FunderReportMutation::AddFriend(add_friend_report),
+            ];
+            report_mutations.extend(friend_report_mutations.into_iter().map(
+                |friend_report_mutation| {
+                    FunderReportMutation::PkFriendReportMutation((
+                        friend_public_key.clone(),
+                        friend_report_mutation,
+                    ))
+                },
+            ));
+            report_mutations
+        }
+        // Our own key rotation is applied when the node restarts, before the initial report is
+        // created:
+        FunderMutation::SetKeyRotation(_) | FunderMutation::ApplyKeyRotation => vec![],
         FunderMutation::AddInvoice(_)
         | FunderMutation::AddIncomingTransaction(_)
+        | FunderMutation::RemoveIncomingTransaction(_)
         | FunderMutation::SetInvoiceSrcHashedLock(_)
         | FunderMutation::RemoveInvoice(_)
         | FunderMutation::AddTransaction(_)
         | FunderMutation::RemoveTransaction(_)
         | FunderMutation::SetTransactionResponse(_)
+        | FunderMutation::SetTransactionPendingRetry(_)
+        | FunderMutation::ClearTransactionPendingRetry(_)
         | FunderMutation::UpdatePayment(_)
-        | FunderMutation::RemovePayment(_) => vec![],
+        | FunderMutation::RemovePayment(_)
+        | FunderMutation::SetExchangeRate(_) => vec![],
     }
 }
 
@@ -324,6 +443,108 @@ where
                     friend_report_mutation,
                 ))]
             }
+            LivenessMutation::SetMissedBeats((public_key, missed_beats)) => {
+                if !funder_state.friends.contains_key(public_key) {
+                    // We ignore the liveness mutation if friend does not exist.
+                    return Vec::new();
+                }
+                let friend_report_mutation = FriendReportMutation::SetMissedBeats(*missed_beats);
+                vec![FunderReportMutation::PkFriendReportMutation((
+                    public_key.clone(),
+                    friend_report_mutation,
+                ))]
+            }
+            LivenessMutation::SetRelayLatency((public_key, opt_latency_ms)) => {
+                vec![FunderReportMutation::SetRelayLatency(RelayLatencyReport {
+                    public_key: public_key.clone(),
+                    opt_latency_ms: *opt_latency_ms,
+                })]
+            }
+            // Uptime samples are not reported. Only the gating decision based on them is:
+            LivenessMutation::SampleUptime(_) => Vec::new(),
+            LivenessMutation::SetGated((public_key, is_gated)) => {
+                if !funder_state.friends.contains_key(public_key) {
+                    // We ignore the liveness mutation if friend does not exist.
+                    return Vec::new();
+                }
+                let friend_report_mutation = FriendReportMutation::SetGated(*is_gated);
+                vec![FunderReportMutation::PkFriendReportMutation((
+                    public_key.clone(),
+                    friend_report_mutation,
+                ))]
+            }
+            // Queue depths change often, and are only used by the Funder to slow down:
+            LivenessMutation::SetQueueDepth(_) => Vec::new(),
+            // Message ids are only used to match delivery reports from the Channeler:
+            LivenessMutation::SetLastMessage(_) => Vec::new(),
+            LivenessMutation::SetSecureChannelStats((public_key, secure_channel_stats)) => {
+                if !funder_state.friends.contains_key(public_key) {
+                    // We ignore the liveness mutation if friend does not exist.
+                    return Vec::new();
+                }
+                let friend_report_mutation =
+                    FriendReportMutation::SetSecureChannelStats(secure_channel_stats.clone());
+                vec![FunderReportMutation::PkFriendReportMutation((
+                    public_key.clone(),
+                    friend_report_mutation,
+                ))]
+            }
+            LivenessMutation::SetBandwidth((public_key, bandwidth)) => {
+                if !funder_state.friends.contains_key(public_key) {
+                    // We ignore the liveness mutation if friend does not exist.
+                    return Vec::new();
+                }
+                let friend_report_mutation = FriendReportMutation::SetBandwidth(bandwidth.clone());
+                vec![FunderReportMutation::PkFriendReportMutation((
+                    public_key.clone(),
+                    friend_report_mutation,
+                ))]
+            }
+            // The connectivity mode only affects the Funder's expectations from friends:
+            LivenessMutation::SetListenOnly => Vec::new(),
+        },
+        // Invoice countdowns are not reported:
+        EphemeralMutation::InvoicesMutation(_) => Vec::new(),
+        // Request countdowns are not reported:
+        EphemeralMutation::RequestsExpiryMutation(_) => Vec::new(),
+        // Refund countdowns are not reported. A refund itself is reported through the pending
+        // debts of the channel's balances:
+        EphemeralMutation::RefundsMutation(_) => Vec::new(),
+        EphemeralMutation::OutflowsMutation(outflows_mutation) => {
+            let (public_key, currency, outflow) = match outflows_mutation {
+                OutflowsMutation::SetWindow((public_key, currency, window)) => {
+                    (public_key, currency, window.outflow)
+                }
+                // Window countdowns are not reported:
+                OutflowsMutation::SetTicksLeft(_) => return Vec::new(),
+                OutflowsMutation::Remove((public_key, currency)) => (public_key, currency, 0),
+            };
+            if !funder_state.friends.contains_key(public_key) {
+                // We ignore the outflows mutation if friend does not exist.
+                return Vec::new();
+            }
+            let friend_report_mutation =
+                FriendReportMutation::SetCurrencyOutflow(CurrencyOutflowReport {
+                    currency: currency.clone(),
+                    outflow,
+                });
+            vec![FunderReportMutation::PkFriendReportMutation((
+                public_key.clone(),
+                friend_report_mutation,
+            ))]
+        }
+        // The request origins index is derived from the funder state:
+        EphemeralMutation::RequestOriginsMutation(_) => Vec::new(),
+        // Frozen credits are reported through the pending debts of the channel's balances:
+        EphemeralMutation::FreezeGuardMutation(_) => Vec::new(),
+        EphemeralMutation::RebalanceMutation(rebalance_mutation) => match rebalance_mutation {
+            // The analysis countdown is not reported:
+            RebalanceMutation::SetTicksLeft(_) => Vec::new(),
+            RebalanceMutation::SetSuggestions(suggestions) => {
+                vec![FunderReportMutation::SetRebalanceSuggestions(
+                    suggestions.clone(),
+                )]
+            }
         },
     }
 }
@@ -342,6 +563,7 @@ mod test {
     };
     use signature::signature_buff::{
         hash_token_info, move_token_hashed_report_signature_buff, move_token_signature_buff,
+        LEGACY_SIGNATURE_BUFF_VERSION, SIGNATURE_BUFF_VERSION,
     };
 
     #[test]
@@ -366,29 +588,35 @@ mod test {
             },
         };
 
-        let move_token = MoveToken::<u32> {
-            currencies_operations: Vec::new(),
-            opt_local_relays: None,
-            opt_active_currencies: None,
-            info_hash: hash_token_info(&token_info),
-            old_token: Signature::from(&[0x55; Signature::len()]),
-            rand_nonce: RandValue::from(&[0x66; RandValue::len()]),
-            new_token: Signature::from(&[0x77; Signature::len()]),
-        };
+        for &signature_version in &[LEGACY_SIGNATURE_BUFF_VERSION, SIGNATURE_BUFF_VERSION] {
+            let move_token = MoveToken::<u32> {
+                currencies_operations: Vec::new(),
+                opt_local_relays: None,
+                opt_active_currencies: None,
+                info_hash: hash_token_info(&token_info),
+                old_token: Signature::from(&[0x55; Signature::len()]),
+                rand_nonce: RandValue::from(&[0x66; RandValue::len()]),
+                signature_version,
+                new_token: Signature::from(&[0x77; Signature::len()]),
+            };
 
-        let move_token_hashed = create_hashed(&move_token, &token_info);
-        let move_token_hashed_report = MoveTokenHashedReport::from(&move_token_hashed);
+            let move_token_hashed = create_hashed(&move_token, &token_info);
+            let move_token_hashed_report = MoveTokenHashedReport::from(&move_token_hashed);
 
-        // Make sure that we get the same signature buffer from all the different representations
-        // of MoveToken:
-        let sig_buff = move_token_signature_buff(move_token.clone());
-        let sig_buff_report = move_token_hashed_report_signature_buff(&move_token_hashed_report);
+            // Make sure that we get the same signature buffer from all the different
+            // representations of MoveToken:
+            let sig_buff = move_token_signature_buff(move_token.clone());
+            let sig_buff_report = move_token_hashed_report_signature_buff(
+                &move_token_hashed_report,
+                signature_version,
+            );
 
-        assert_eq!(sig_buff, sig_buff_report);
-        assert_eq!(move_token.new_token, move_token_hashed.new_token);
-        assert_eq!(
-            move_token_hashed.new_token,
-            move_token_hashed_report.new_token
-        );
+            assert_eq!(sig_buff, sig_buff_report);
+            assert_eq!(move_token.new_token, move_token_hashed.new_token);
+            assert_eq!(
+                move_token_hashed.new_token,
+                move_token_hashed_report.new_token
+            );
+        }
     }
 }
@@ -0,0 +1,230 @@
+use std::fmt::Debug;
+
+use im::hashmap::HashMap as ImHashMap;
+
+use signature::canonical::CanonicalSerialize;
+
+use proto::crypto::{PublicKey, Uid};
+use proto::funder::messages::Currency;
+
+use crate::friend::{ChannelStatus, FriendMutation};
+use crate::mutual_credit::types::McMutation;
+use crate::state::{FunderMutation, FunderState};
+use crate::token_channel::{TcMutation, TokenChannel};
+
+/// An index of all the requests we received from friends and did not complete yet (Remote pending
+/// transactions), by request id.
+/// Allows finding the origin of a request we have forwarded without going over all friends.
+#[derive(Clone, Default)]
+pub struct RequestOrigins {
+    /// request_id -> (friend_public_key, currency)
+    pub origins: ImHashMap<Uid, (PublicKey, Currency)>,
+}
+
+#[derive(Debug)]
+pub enum RequestOriginsMutation {
+    Insert((Uid, PublicKey, Currency)),
+    Remove(Uid),
+    /// Forget all the requests received from a friend
+    RemoveFriend(PublicKey),
+    /// A friend has rotated its key: (old_public_key, new_public_key)
+    ReplaceFriend((PublicKey, PublicKey)),
+}
+
+impl RequestOrigins {
+    pub fn new() -> RequestOrigins {
+        RequestOrigins {
+            origins: ImHashMap::new(),
+        }
+    }
+
+    /// Build the index from scratch. Used when the funder starts.
+    pub fn from_state<B>(state: &FunderState<B>) -> RequestOrigins
+    where
+        B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+    {
+        let mut request_origins = RequestOrigins::new();
+        for (friend_public_key, friend) in &state.friends {
+            if let ChannelStatus::Consistent(channel_consistent) = &friend.channel_status {
+                for mutation in
+                    token_channel_origins(friend_public_key, &channel_consistent.token_channel)
+                {
+                    request_origins.mutate(&mutation);
+                }
+            }
+        }
+        request_origins
+    }
+
+    pub fn mutate(&mut self, mutation: &RequestOriginsMutation) {
+        match mutation {
+            RequestOriginsMutation::Insert((request_id, friend_public_key, currency)) => {
+                let _ = self.origins.insert(
+                    request_id.clone(),
+                    (friend_public_key.clone(), currency.clone()),
+                );
+            }
+            RequestOriginsMutation::Remove(request_id) => {
+                let _ = self.origins.remove(request_id);
+            }
+            RequestOriginsMutation::RemoveFriend(friend_public_key) => {
+                self.origins
+                    .retain(|_request_id, (public_key, _currency)| public_key != friend_public_key);
+            }
+            RequestOriginsMutation::ReplaceFriend((old_public_key, new_public_key)) => {
+                for (public_key, _currency) in self.origins.values_mut() {
+                    if public_key == old_public_key {
+                        *public_key = new_public_key.clone();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Find the friend that sent us a request.
+    /// Returns None if the request was not received from a friend (For example, if we are the
+    /// origin of the request).
+    pub fn get(&self, currency: &Currency, request_id: &Uid) -> Option<&PublicKey> {
+        match self.get_origin(request_id) {
+            Some((friend_public_key, origin_currency)) if origin_currency == currency => {
+                Some(friend_public_key)
+            }
+            _ => None,
+        }
+    }
+
+    /// Find the friend that sent us a request, and the currency the request was sent in.
+    pub fn get_origin(&self, request_id: &Uid) -> Option<&(PublicKey, Currency)> {
+        self.origins.get(request_id)
+    }
+}
+
+/// Insert all the remote pending transactions of a token channel.
+fn token_channel_origins<B>(
+    friend_public_key: &PublicKey,
+    token_channel: &TokenChannel<B>,
+) -> Vec<RequestOriginsMutation>
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let mut mutations = Vec::new();
+    for (currency, mutual_credit) in token_channel.get_mutual_credits() {
+        for request_id in mutual_credit.state().pending_transactions.remote.keys() {
+            mutations.push(RequestOriginsMutation::Insert((
+                request_id.clone(),
+                friend_public_key.clone(),
+                currency.clone(),
+            )));
+        }
+    }
+    mutations
+}
+
+/// Calculate the changes to the index caused by a funder mutation.
+pub fn funder_mutation_to_request_origins_mutations<B>(
+    funder_mutation: &FunderMutation<B>,
+) -> Vec<RequestOriginsMutation>
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    match funder_mutation {
+        FunderMutation::FriendMutation((friend_public_key, friend_mutation)) => {
+            match friend_mutation {
+                FriendMutation::TcMutation(TcMutation::McMutation((currency, mc_mutation))) => {
+                    match mc_mutation {
+                        McMutation::InsertRemotePendingTransaction(pending_transaction) => {
+                            vec![RequestOriginsMutation::Insert((
+                                pending_transaction.request_id.clone(),
+                                friend_public_key.clone(),
+                                currency.clone(),
+                            ))]
+                        }
+                        McMutation::RemoveRemotePendingTransaction(request_id) => {
+                            vec![RequestOriginsMutation::Remove(request_id.clone())]
+                        }
+                        _ => Vec::new(),
+                    }
+                }
+                FriendMutation::SetInconsistent(_) => vec![RequestOriginsMutation::RemoveFriend(
+                    friend_public_key.clone(),
+                )],
+                FriendMutation::SetConsistent(token_channel) => {
+                    let mut mutations = vec![RequestOriginsMutation::RemoveFriend(
+                        friend_public_key.clone(),
+                    )];
+                    mutations.extend(token_channel_origins(friend_public_key, token_channel));
+                    mutations
+                }
+                _ => Vec::new(),
+            }
+        }
+        FunderMutation::RemoveFriend(friend_public_key) => {
+            vec![RequestOriginsMutation::RemoveFriend(
+                friend_public_key.clone(),
+            )]
+        }
+        FunderMutation::RotateFriendKey(key_rotation) => {
+            vec![RequestOriginsMutation::ReplaceFriend((
+                key_rotation.old_public_key.clone(),
+                key_rotation.new_public_key.clone(),
+            ))]
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_request_origins_basic() {
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+        let currency2 = Currency::try_from("FST2".to_owned()).unwrap();
+        let uid1 = Uid::from(&[1; Uid::len()]);
+        let uid2 = Uid::from(&[2; Uid::len()]);
+        let uid3 = Uid::from(&[3; Uid::len()]);
+
+        let mut request_origins = RequestOrigins::new();
+        assert_eq!(request_origins.get(&currency1, &uid1), None);
+
+        request_origins.mutate(&RequestOriginsMutation::Insert((
+            uid1.clone(),
+            pk_a.clone(),
+            currency1.clone(),
+        )));
+        request_origins.mutate(&RequestOriginsMutation::Insert((
+            uid2.clone(),
+            pk_a.clone(),
+            currency2.clone(),
+        )));
+        request_origins.mutate(&RequestOriginsMutation::Insert((
+            uid3.clone(),
+            pk_b.clone(),
+            currency1.clone(),
+        )));
+        assert_eq!(request_origins.get(&currency1, &uid1), Some(&pk_a));
+        assert_eq!(request_origins.get(&currency2, &uid2), Some(&pk_a));
+        assert_eq!(request_origins.get(&currency1, &uid3), Some(&pk_b));
+        // Wrong currency:
+        assert_eq!(request_origins.get(&currency2, &uid1), None);
+
+        request_origins.mutate(&RequestOriginsMutation::Remove(uid1.clone()));
+        assert_eq!(request_origins.get(&currency1, &uid1), None);
+
+        let pk_c = PublicKey::from(&[0xcc; PublicKey::len()]);
+        request_origins.mutate(&RequestOriginsMutation::ReplaceFriend((
+            pk_b.clone(),
+            pk_c.clone(),
+        )));
+        assert_eq!(request_origins.get(&currency1, &uid3), Some(&pk_c));
+
+        request_origins.mutate(&RequestOriginsMutation::RemoveFriend(pk_a.clone()));
+        assert_eq!(request_origins.get(&currency2, &uid2), None);
+        assert_eq!(request_origins.get(&currency1, &uid3), Some(&pk_c));
+    }
+}
@@ -0,0 +1,36 @@
+use im::hashmap::HashMap as ImHashMap;
+
+use proto::crypto::Uid;
+
+/// Countdowns of queued requests that expire, by request id.
+/// A countdown starts when a queued request first sees a tick, and ends when the request is sent
+/// to the next node on the route.
+#[derive(Clone, Default)]
+pub struct RequestsExpiry {
+    pub ticks_left: ImHashMap<Uid, u64>,
+}
+
+#[derive(Debug)]
+pub enum RequestsExpiryMutation {
+    SetTicksLeft((Uid, u64)),
+    Remove(Uid),
+}
+
+impl RequestsExpiry {
+    pub fn new() -> RequestsExpiry {
+        RequestsExpiry {
+            ticks_left: ImHashMap::new(),
+        }
+    }
+
+    pub fn mutate(&mut self, mutation: &RequestsExpiryMutation) {
+        match mutation {
+            RequestsExpiryMutation::SetTicksLeft((request_id, ticks_left)) => {
+                let _ = self.ticks_left.insert(request_id.clone(), *ticks_left);
+            }
+            RequestsExpiryMutation::Remove(request_id) => {
+                let _ = self.ticks_left.remove(request_id);
+            }
+        }
+    }
+}
@@ -5,15 +5,18 @@ use std::collections::HashMap as ImHashMap;
 use im::hashset::HashSet as ImHashSet;
 use im::vector::Vector as ImVec;
 
-use common::ser_utils::{ser_b64, ser_map_b64_any, ser_option_b64, ser_string};
+use common::ser_utils::{ser_b64, ser_map_b64_any, ser_option_b64, ser_option_string, ser_string};
 use signature::canonical::CanonicalSerialize;
 
 use proto::crypto::{HashedLock, InvoiceId, PaymentId, PlainLock, PublicKey, Uid};
 
 use proto::app_server::messages::NamedRelayAddress;
-use proto::funder::messages::{AddFriend, Currency, Receipt, ResponseSendFundsOp};
+use proto::funder::messages::{
+    AddFriend, Currency, ExchangeRate, KeyRotation, Receipt, RequestSendFundsOp,
+    ResponseSendFundsOp,
+};
 
-use crate::friend::{FriendMutation, FriendState};
+use crate::friend::{ChannelStatus, FriendMutation, FriendState};
 
 #[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct FunderState<B: Clone> {
@@ -38,6 +41,21 @@ pub struct FunderState<B: Clone> {
     /// Ongoing payments (For which this node is the buyer):
     #[serde(with = "ser_map_b64_any")]
     pub payments: ImHashMap<PaymentId, Payment>,
+    /// Our last key rotation. The rotation is pending (Not yet applied) as long as the new public
+    /// key is different from `local_public_key`.
+    pub opt_key_rotation: Option<KeyRotation>,
+    /// Rates in which we exchange currencies for requests we forward between friends.
+    #[serde(default)]
+    pub exchange_rates: ImVec<CurrencyExchangeRate>,
+}
+
+/// A rate in which we exchange credits received in `from_currency` to credits sent in
+/// `to_currency`.
+#[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct CurrencyExchangeRate {
+    pub from_currency: Currency,
+    pub to_currency: Currency,
+    pub rate: ExchangeRate,
 }
 
 /// A state of a Payment where new transactions may still be added.
@@ -53,6 +71,10 @@ pub struct NewTransactions {
     pub total_dest_payment: u128,
     #[serde(with = "ser_b64")]
     pub dest_public_key: PublicKey,
+    /// Maximum fees per node that forwards a transaction of this payment
+    #[serde(default)]
+    #[serde(with = "ser_option_string")]
+    pub opt_max_fee_per_hop: Option<u128>,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -106,16 +128,25 @@ pub struct OpenInvoice {
     /// Multiple transactions are possible for a single invoice in case of a multi-route payment.
     // TODO: Add serde hint
     pub incoming_transactions: ImHashSet<Uid>,
+    /// Amount of ticks until this invoice is canceled automatically.
+    /// The countdown itself is not persistent, and restarts when the funder is restarted.
+    pub opt_expiry_ticks: Option<u64>,
 }
 
 impl OpenInvoice {
-    pub fn new(currency: Currency, total_dest_payment: u128, dest_plain_lock: PlainLock) -> Self {
+    pub fn new(
+        currency: Currency,
+        total_dest_payment: u128,
+        dest_plain_lock: PlainLock,
+        opt_expiry_ticks: Option<u64>,
+    ) -> Self {
         OpenInvoice {
             currency,
             total_dest_payment,
             dest_plain_lock,
             opt_src_hashed_lock: None,
             incoming_transactions: ImHashSet::new(),
+            opt_expiry_ticks,
         }
     }
 }
@@ -127,6 +158,21 @@ pub struct OpenTransaction {
     pub payment_id: PaymentId,
     /// A response (if we got one):
     pub opt_response: Option<ResponseSendFundsOp>,
+    /// Amount of times we may still retry this transaction through an alternative route.
+    /// Transactions stored before retries were introduced are never retried.
+    #[serde(default)]
+    pub retries_left: u64,
+    /// A failed request, waiting for an alternative route.
+    pub opt_pending_retry: Option<PendingRetry>,
+}
+
+/// A failed local request, waiting for an alternative route.
+#[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct PendingRetry {
+    pub currency: Currency,
+    /// The original request. The route inside this request is the full route (Starting from the
+    /// local node).
+    pub request_send_funds: RequestSendFundsOp,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -137,15 +183,25 @@ pub enum FunderMutation<B: Clone> {
     RemoveRelay(PublicKey),
     AddFriend(AddFriend<B>),
     RemoveFriend(PublicKey),
-    AddInvoice((InvoiceId, Currency, u128, PlainLock)), // (invoice_id, currency, total_dest_payment, dest_plain_lock)
-    AddIncomingTransaction((InvoiceId, Uid)),           // (invoice_id, request_id)
-    SetInvoiceSrcHashedLock((InvoiceId, HashedLock)),   // (invoice_id, src_hashed_lock)
+    AddInvoice((InvoiceId, Currency, u128, PlainLock, Option<u64>)), // (invoice_id, currency, total_dest_payment, dest_plain_lock, opt_expiry_ticks)
+    AddIncomingTransaction((InvoiceId, Uid)),                        // (invoice_id, request_id)
+    RemoveIncomingTransaction((InvoiceId, Uid)),                     // (invoice_id, request_id)
+    SetInvoiceSrcHashedLock((InvoiceId, HashedLock)), // (invoice_id, src_hashed_lock)
     RemoveInvoice(InvoiceId),
-    AddTransaction((Uid, PaymentId)), // (request_id, payment_id)
+    AddTransaction((Uid, PaymentId, u64)), // (request_id, payment_id, retries_left)
     SetTransactionResponse(ResponseSendFundsOp), // (request_id, response_send_funds)
-    RemoveTransaction(Uid),           // request_id
+    SetTransactionPendingRetry((Uid, PendingRetry)), // (request_id, pending_retry)
+    ClearTransactionPendingRetry(Uid),     // request_id
+    RemoveTransaction(Uid),                // request_id
     UpdatePayment((PaymentId, Payment)),
     RemovePayment(PaymentId),
+    /// A friend has rotated its key
+    RotateFriendKey(KeyRotation),
+    /// We are going to rotate our key
+    SetKeyRotation(KeyRotation),
+    /// Start using the new key of our pending key rotation
+    ApplyKeyRotation,
+    SetExchangeRate((Currency, Currency, Option<ExchangeRate>)), // (from_currency, to_currency, opt_rate)
 }
 
 impl<B> FunderState<B>
@@ -163,9 +219,65 @@ where
             open_invoices: ImHashMap::new(),
             open_transactions: ImHashMap::new(),
             payments: ImHashMap::new(),
+            opt_key_rotation: None,
+            exchange_rates: ImVec::new(),
+        }
+    }
+
+    /// The rate in which we exchange credits received in `from_currency` to credits sent in
+    /// `to_currency` (If we exchange between the two currencies).
+    pub fn exchange_rate(
+        &self,
+        from_currency: &Currency,
+        to_currency: &Currency,
+    ) -> Option<&ExchangeRate> {
+        self.exchange_rates
+            .iter()
+            .find(|exchange_rate| {
+                &exchange_rate.from_currency == from_currency
+                    && &exchange_rate.to_currency == to_currency
+            })
+            .map(|exchange_rate| &exchange_rate.rate)
+    }
+
+    /// Did we announce a new key that we are not using yet?
+    pub fn is_key_rotation_pending(&self) -> bool {
+        match &self.opt_key_rotation {
+            Some(key_rotation) => key_rotation.new_public_key != self.local_public_key,
+            None => false,
         }
     }
 
+    /// Is there no work in progress that depends on our friends?
+    /// The state is settled if there are no open transactions, no operations waiting to be sent to
+    /// friends, and no pending transactions in any of the mutual credits.
+    pub fn is_settled(&self) -> bool {
+        if !self.open_transactions.is_empty() {
+            return false;
+        }
+        self.friends
+            .values()
+            .all(|friend| match &friend.channel_status {
+                // Pending operations are discarded when a channel becomes inconsistent:
+                ChannelStatus::Inconsistent(_) => true,
+                ChannelStatus::Consistent(channel_consistent) => {
+                    channel_consistent.pending_requests.is_empty()
+                        && channel_consistent.pending_backwards_ops.is_empty()
+                        && channel_consistent.pending_user_requests.is_empty()
+                        && channel_consistent
+                            .token_channel
+                            .get_mutual_credits()
+                            .values()
+                            .all(|mutual_credit| {
+                                let pending_transactions =
+                                    &mutual_credit.state().pending_transactions;
+                                pending_transactions.local.is_empty()
+                                    && pending_transactions.remote.is_empty()
+                            })
+                }
+            })
+    }
+
     // TODO: Use MutableState trait instead:
     pub fn mutate(&mut self, funder_mutation: &FunderMutation<B>) {
         match funder_mutation {
@@ -208,6 +320,7 @@ where
                 currency,
                 total_dest_payment,
                 dest_plain_lock,
+                opt_expiry_ticks,
             )) => {
                 self.open_invoices.insert(
                     invoice_id.clone(),
@@ -215,6 +328,7 @@ where
                         currency.clone(),
                         *total_dest_payment,
                         dest_plain_lock.clone(),
+                        *opt_expiry_ticks,
                     ),
                 );
             }
@@ -224,6 +338,10 @@ where
                     .incoming_transactions
                     .insert(request_id.clone());
             }
+            FunderMutation::RemoveIncomingTransaction((invoice_id, request_id)) => {
+                let open_invoice = self.open_invoices.get_mut(invoice_id).unwrap();
+                let _ = open_invoice.incoming_transactions.remove(request_id);
+            }
             FunderMutation::SetInvoiceSrcHashedLock((invoice_id, src_hashed_lock)) => {
                 let open_invoice = self.open_invoices.get_mut(invoice_id).unwrap();
                 assert!(open_invoice.opt_src_hashed_lock.is_none());
@@ -232,10 +350,12 @@ where
             FunderMutation::RemoveInvoice(invoice_id) => {
                 let _ = self.open_invoices.remove(invoice_id);
             }
-            FunderMutation::AddTransaction((request_id, payment_id)) => {
+            FunderMutation::AddTransaction((request_id, payment_id, retries_left)) => {
                 let open_transaction = OpenTransaction {
                     payment_id: payment_id.clone(),
                     opt_response: None,
+                    retries_left: *retries_left,
+                    opt_pending_retry: None,
                 };
                 let _ = self
                     .open_transactions
@@ -250,6 +370,17 @@ where
                 assert!(open_transaction.opt_response.take().is_none());
                 open_transaction.opt_response = Some(response_send_funds.clone());
             }
+            FunderMutation::SetTransactionPendingRetry((request_id, pending_retry)) => {
+                let open_transaction = self.open_transactions.get_mut(request_id).unwrap();
+                // Every pending retry consumes one retry:
+                open_transaction.retries_left =
+                    open_transaction.retries_left.checked_sub(1).unwrap();
+                open_transaction.opt_pending_retry = Some(pending_retry.clone());
+            }
+            FunderMutation::ClearTransactionPendingRetry(request_id) => {
+                let open_transaction = self.open_transactions.get_mut(request_id).unwrap();
+                open_transaction.opt_pending_retry = None;
+            }
             FunderMutation::RemoveTransaction(request_id) => {
                 let _ = self.open_transactions.remove(request_id);
             }
@@ -259,6 +390,334 @@ where
             FunderMutation::RemovePayment(payment_id) => {
                 let _ = self.payments.remove(payment_id);
             }
+            FunderMutation::RotateFriendKey(key_rotation) => {
+                // A rotation to the key of another friend is ignored, so that the other friend is
+                // not overridden:
+                if !self.friends.contains_key(&key_rotation.new_public_key) {
+                    if let Some(mut friend) = self.friends.remove(&key_rotation.old_public_key) {
+                        friend.rotate_key(key_rotation);
+                        self.friends
+                            .insert(key_rotation.new_public_key.clone(), friend);
+                    }
+                }
+            }
+            FunderMutation::SetKeyRotation(key_rotation) => {
+                self.opt_key_rotation = Some(key_rotation.clone());
+            }
+            FunderMutation::ApplyKeyRotation => {
+                let key_rotation = self.opt_key_rotation.clone().unwrap();
+                assert_eq!(key_rotation.old_public_key, self.local_public_key);
+                self.local_public_key = key_rotation.new_public_key.clone();
+                for friend in self.friends.values_mut() {
+                    friend.rotate_key(&key_rotation);
+                }
+            }
+            FunderMutation::SetExchangeRate((from_currency, to_currency, opt_rate)) => {
+                self.exchange_rates.retain(|exchange_rate| {
+                    &exchange_rate.from_currency != from_currency
+                        || &exchange_rate.to_currency != to_currency
+                });
+                if let Some(rate) = opt_rate {
+                    self.exchange_rates.push_back(CurrencyExchangeRate {
+                        from_currency: from_currency.clone(),
+                        to_currency: to_currency.clone(),
+                        rate: rate.clone(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use proto::crypto::Signature;
+    use proto::funder::messages::{FriendsRoute, PendingTransaction, ResetTerms, TransactionStage};
+    use proto::report::messages::DisputeEvidence;
+
+    use crate::friend::{ChannelInconsistent, DrainStatus};
+    use crate::mutual_credit::types::McMutation;
+    use crate::token_channel::TcMutation;
+
+    #[test]
+    fn test_funder_state_is_settled() {
+        let local_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let mut funder_state = FunderState::<u32>::new(local_public_key, Vec::new());
+        assert!(funder_state.is_settled());
+
+        let request_id = Uid::from(&[3; Uid::len()]);
+        let payment_id = PaymentId::from(&[4; PaymentId::len()]);
+        funder_state.mutate(&FunderMutation::AddTransaction((
+            request_id.clone(),
+            payment_id,
+            0,
+        )));
+        assert!(!funder_state.is_settled());
+
+        funder_state.mutate(&FunderMutation::RemoveTransaction(request_id));
+        assert!(funder_state.is_settled());
+    }
+
+    #[test]
+    fn test_funder_state_rotate_friend_key_conflict() {
+        let local_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let pk_c = PublicKey::from(&[0xcc; PublicKey::len()]);
+        let pk_d = PublicKey::from(&[0xdd; PublicKey::len()]);
+        let mut funder_state = FunderState::<u32>::new(local_public_key, Vec::new());
+
+        for (friend_public_key, name) in &[(&pk_b, "b"), (&pk_c, "c")] {
+            let add_friend = AddFriend {
+                friend_public_key: (*friend_public_key).clone(),
+                relays: Vec::new(),
+                name: (*name).into(),
+            };
+            funder_state.mutate(&FunderMutation::AddFriend(add_friend));
+        }
+        let funder_state_before = funder_state.clone();
+
+        let key_rotation = |old_public_key: &PublicKey, new_public_key: &PublicKey| KeyRotation {
+            old_public_key: old_public_key.clone(),
+            new_public_key: new_public_key.clone(),
+            old_signature: Signature::from(&[0; Signature::len()]),
+            new_signature: Signature::from(&[1; Signature::len()]),
+        };
+
+        // A friend can not take the key of another friend:
+        funder_state.mutate(&FunderMutation::RotateFriendKey(key_rotation(&pk_b, &pk_c)));
+        assert_eq!(funder_state, funder_state_before);
+
+        // Rotating the key of a friend we don't have does nothing:
+        funder_state.mutate(&FunderMutation::RotateFriendKey(key_rotation(&pk_d, &pk_c)));
+        assert_eq!(funder_state, funder_state_before);
+
+        funder_state.mutate(&FunderMutation::RotateFriendKey(key_rotation(&pk_b, &pk_d)));
+        assert!(!funder_state.friends.contains_key(&pk_b));
+        assert_eq!(funder_state.friends.get(&pk_d).unwrap().name, "b");
+    }
+
+    /// Remove a field from a serialized json object
+    fn remove_field(value: &mut serde_json::Value, field: &str) {
+        assert!(value.as_object_mut().unwrap().remove(field).is_some());
+    }
+
+    #[test]
+    fn test_funder_state_deserialize_baseline() {
+        let local_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let remote_public_key = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let mut funder_state = FunderState::<u32>::new(local_public_key, Vec::new());
+
+        let add_friend = AddFriend {
+            friend_public_key: remote_public_key.clone(),
+            relays: Vec::new(),
+            name: "remote".into(),
+        };
+        funder_state.mutate(&FunderMutation::AddFriend(add_friend));
+
+        let channel_inconsistent = ChannelInconsistent {
+            opt_last_incoming_move_token: None,
+            local_reset_terms: ResetTerms {
+                reset_token: Signature::from(&[0; Signature::len()]),
+                inconsistency_counter: 1,
+                balance_for_reset: Vec::new(),
+            },
+            opt_remote_reset_terms: None,
+            dispute_evidence: DisputeEvidence::default(),
+        };
+        funder_state.mutate(&FunderMutation::FriendMutation((
+            remote_public_key.clone(),
+            FriendMutation::SetInconsistent(channel_inconsistent),
+        )));
+
+        let request_id = Uid::from(&[3; Uid::len()]);
+        let payment_id = PaymentId::from(&[4; PaymentId::len()]);
+        funder_state.mutate(&FunderMutation::AddTransaction((
+            request_id.clone(),
+            payment_id.clone(),
+            0,
+        )));
+
+        let payment = Payment {
+            src_plain_lock: PlainLock::from(&[5; PlainLock::len()]),
+            stage: PaymentStage::NewTransactions(NewTransactions {
+                num_transactions: 1,
+                invoice_id: InvoiceId::from(&[6; InvoiceId::len()]),
+                currency: Currency::try_from("FST".to_owned()).unwrap(),
+                total_dest_payment: 100,
+                dest_public_key: remote_public_key.clone(),
+                opt_max_fee_per_hop: None,
+            }),
+        };
+        funder_state.mutate(&FunderMutation::UpdatePayment((payment_id, payment)));
+
+        // A friend with a consistent channel and a queued request:
+        let consistent_public_key = PublicKey::from(&[0xcc; PublicKey::len()]);
+        let add_friend = AddFriend {
+            friend_public_key: consistent_public_key.clone(),
+            relays: Vec::new(),
+            name: "consistent".into(),
+        };
+        funder_state.mutate(&FunderMutation::AddFriend(add_friend));
+
+        let request_send_funds = RequestSendFundsOp {
+            request_id: Uid::from(&[7; Uid::len()]),
+            src_hashed_lock: HashedLock::from(&[8; HashedLock::len()]),
+            route: FriendsRoute {
+                public_keys: vec![consistent_public_key.clone()],
+            },
+            dest_payment: 20,
+            total_dest_payment: 20,
+            invoice_id: InvoiceId::from(&[9; InvoiceId::len()]),
+            left_fees: 0,
+            expiry_ticks: 0,
+            refund_ticks: 0,
+            opt_exchange: None,
+        };
+        funder_state.mutate(&FunderMutation::FriendMutation((
+            consistent_public_key.clone(),
+            FriendMutation::PushBackPendingUserRequest((
+                Currency::try_from("FST".to_owned()).unwrap(),
+                request_send_funds,
+            )),
+        )));
+
+        // The consistent channel has pending transactions in both directions:
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let local_pending_transaction = PendingTransaction {
+            request_id: Uid::from(&[10; Uid::len()]),
+            route: FriendsRoute {
+                public_keys: vec![consistent_public_key.clone()],
+            },
+            dest_payment: 30,
+            total_dest_payment: 30,
+            invoice_id: InvoiceId::from(&[11; InvoiceId::len()]),
+            left_fees: 0,
+            src_hashed_lock: HashedLock::from(&[12; HashedLock::len()]),
+            expiry_ticks: 0,
+            refund_ticks: 0,
+            opt_exchange: None,
+            stage: TransactionStage::Request,
+        };
+        let remote_pending_transaction = PendingTransaction {
+            request_id: Uid::from(&[13; Uid::len()]),
+            route: FriendsRoute {
+                public_keys: Vec::new(),
+            },
+            dest_payment: 40,
+            total_dest_payment: 40,
+            invoice_id: InvoiceId::from(&[14; InvoiceId::len()]),
+            left_fees: 0,
+            src_hashed_lock: HashedLock::from(&[15; HashedLock::len()]),
+            expiry_ticks: 0,
+            refund_ticks: 0,
+            opt_exchange: None,
+            stage: TransactionStage::Response(HashedLock::from(&[16; HashedLock::len()]), true, 0),
+        };
+        let tc_mutations = vec![
+            TcMutation::SetLocalActiveCurrencies(vec![currency.clone()]),
+            TcMutation::SetRemoteActiveCurrencies(vec![currency.clone()]),
+            TcMutation::AddMutualCredit(currency.clone()),
+            TcMutation::McMutation((
+                currency.clone(),
+                McMutation::InsertLocalPendingTransaction(local_pending_transaction),
+            )),
+            TcMutation::McMutation((
+                currency,
+                McMutation::InsertRemotePendingTransaction(remote_pending_transaction),
+            )),
+        ];
+        for tc_mutation in tc_mutations {
+            funder_state.mutate(&FunderMutation::FriendMutation((
+                consistent_public_key.clone(),
+                FriendMutation::TcMutation(tc_mutation),
+            )));
+        }
+
+        // Remove the fields that did not exist in the original state format:
+        let mut value: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&funder_state).unwrap()).unwrap();
+        remove_field(&mut value, "opt_key_rotation");
+        remove_field(&mut value, "exchange_rates");
+        for friend in value["friends"].as_object_mut().unwrap().values_mut() {
+            remove_field(friend, "opt_remote_capabilities");
+            remove_field(friend, "opt_remote_key_rotation");
+            remove_field(friend, "drain_status");
+            if let Some(channel_inconsistent) = friend["channel_status"].get_mut("Inconsistent") {
+                remove_field(channel_inconsistent, "dispute_evidence");
+            }
+            if let Some(channel_consistent) = friend["channel_status"].get_mut("Consistent") {
+                let token_channel = &mut channel_consistent["token_channel"];
+                remove_field(token_channel, "idents");
+                if let Some(tc_outgoing) = token_channel["direction"].get_mut("Outgoing") {
+                    remove_field(&mut tc_outgoing["move_token_out"], "signature_version");
+                }
+                for mutual_credit in token_channel["mutual_credits"]
+                    .as_object_mut()
+                    .unwrap()
+                    .values_mut()
+                {
+                    let pending_transactions = &mut mutual_credit["state"]["pending_transactions"];
+                    for side in &["local", "remote"] {
+                        for pending_transaction in pending_transactions[*side]
+                            .as_object_mut()
+                            .unwrap()
+                            .values_mut()
+                        {
+                            remove_field(pending_transaction, "expiry_ticks");
+                            remove_field(pending_transaction, "refund_ticks");
+                            remove_field(pending_transaction, "opt_exchange");
+                            // A response stage is (dest_hashed_lock, is_complete, change):
+                            if let Some(response) = pending_transaction["stage"].get_mut("Response")
+                            {
+                                assert!(response.as_array_mut().unwrap().pop().is_some());
+                            }
+                        }
+                    }
+                }
+                for pending_request in channel_consistent["pending_user_requests"]
+                    .as_array_mut()
+                    .unwrap()
+                {
+                    // A pending request is a (currency, request_send_funds) pair:
+                    let request_send_funds = &mut pending_request[1];
+                    remove_field(request_send_funds, "expiry_ticks");
+                    remove_field(request_send_funds, "refund_ticks");
+                    remove_field(request_send_funds, "opt_exchange");
+                }
+            }
+        }
+        for open_transaction in value["open_transactions"]
+            .as_object_mut()
+            .unwrap()
+            .values_mut()
+        {
+            remove_field(open_transaction, "retries_left");
+            remove_field(open_transaction, "opt_pending_retry");
         }
+        for payment in value["payments"].as_object_mut().unwrap().values_mut() {
+            remove_field(
+                &mut payment["stage"]["NewTransactions"],
+                "opt_max_fee_per_hop",
+            );
+        }
+
+        let baseline_state: FunderState<u32> = serde_json::from_str(&value.to_string()).unwrap();
+        assert_eq!(baseline_state, funder_state);
+
+        let friend = baseline_state.friends.get(&remote_public_key).unwrap();
+        assert_eq!(friend.drain_status, DrainStatus::Active);
+        assert!(baseline_state.exchange_rates.is_empty());
+        assert_eq!(
+            baseline_state
+                .open_transactions
+                .get(&request_id)
+                .unwrap()
+                .retries_left,
+            0
+        );
     }
 }
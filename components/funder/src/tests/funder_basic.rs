@@ -4,10 +4,12 @@ use common::test_executor::TestExecutor;
 
 use proto::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
 use proto::funder::messages::{
-    AckClosePayment, AddInvoice, CreatePayment, CreateTransaction, Currency, FriendStatus,
-    FriendsRoute, FunderControl, PaymentStatus, Rate, RequestResult, RequestsStatus,
+    AckClosePayment, AddInvoice, ChannelProofResult, CreatePayment, CreateTransaction, Currency,
+    ExportChannelProof, FriendStatus, FriendsRoute, FunderControl, HopFreeze, PaymentStatus,
+    QuotePayment, QuoteRejection, Rate, RequestResult, RequestsStatus,
 };
 
+use signature::channel_proof::{latest_token_info, verify_channel_proof_blob};
 use signature::verify::verify_receipt;
 
 use super::utils::{create_node_controls, dummy_relay_address};
@@ -98,6 +100,7 @@ async fn task_funder_basic(test_executor: TestExecutor) {
         invoice_id: InvoiceId::from(&[1u8; InvoiceId::len()]),
         currency: currency1.clone(),
         total_dest_payment: 4,
+        opt_expiry_ticks: None,
     };
     node_controls[1]
         .send(FunderControl::AddInvoice(add_invoice))
@@ -110,6 +113,7 @@ async fn task_funder_basic(test_executor: TestExecutor) {
         currency: currency1.clone(),
         total_dest_payment: 4,
         dest_public_key: node_controls[1].public_key.clone(),
+        opt_max_fee_per_hop: None,
     };
     node_controls[0]
         .send(FunderControl::CreatePayment(create_payment))
@@ -126,6 +130,7 @@ async fn task_funder_basic(test_executor: TestExecutor) {
         },
         dest_payment: 3,
         fees: 1,
+        opt_refund_ticks: None,
     };
 
     node_controls[0]
@@ -152,6 +157,7 @@ async fn task_funder_basic(test_executor: TestExecutor) {
         },
         dest_payment: 4,
         fees: 1,
+        opt_refund_ticks: None,
     };
 
     node_controls[0]
@@ -177,6 +183,7 @@ async fn task_funder_basic(test_executor: TestExecutor) {
         },
         dest_payment: 1,
         fees: 1,
+        opt_refund_ticks: None,
     };
 
     node_controls[0]
@@ -251,6 +258,130 @@ async fn task_funder_basic(test_executor: TestExecutor) {
 
     // Verify receipt:
     assert!(verify_receipt(&receipt, &public_keys[1]));
+
+    // 1: Node 0 owes us 6 credits. No requests are in flight:
+    node_controls[1]
+        .send(FunderControl::RequestExposure(Uid::from(
+            &[7u8; Uid::len()],
+        )))
+        .await;
+    let response_exposure = node_controls[1]
+        .recv_until_response_exposure()
+        .await
+        .unwrap();
+    assert_eq!(response_exposure.request_id, Uid::from(&[7u8; Uid::len()]));
+    assert_eq!(response_exposure.friends.len(), 1);
+    assert_eq!(
+        response_exposure.friends[0].friend_public_key,
+        public_keys[0]
+    );
+    let currency_exposure = response_exposure
+        .totals
+        .iter()
+        .find(|currency_exposure| currency_exposure.currency == currency1)
+        .unwrap();
+    assert_eq!(currency_exposure.all_fail_exposure, 6);
+    assert_eq!(currency_exposure.all_succeed_exposure, 6);
+
+    // 0: Quote a payment to node 1, without sending it:
+    node_controls[0]
+        .send(FunderControl::QuotePayment(QuotePayment {
+            request_id: Uid::from(&[10u8; Uid::len()]),
+            currency: currency1.clone(),
+            route: FriendsRoute {
+                public_keys: vec![public_keys[0].clone(), public_keys[1].clone()],
+            },
+            mediator_rates: Vec::new(),
+            dest_payment: 5,
+            opt_max_fee_per_hop: None,
+        }))
+        .await;
+    let response_quote_payment = node_controls[0]
+        .recv_until_response_quote_payment()
+        .await
+        .unwrap();
+    assert_eq!(
+        response_quote_payment.request_id,
+        Uid::from(&[10u8; Uid::len()])
+    );
+    assert_eq!(response_quote_payment.fees, 0);
+    assert_eq!(
+        response_quote_payment.hop_freezes,
+        vec![HopFreeze {
+            to_public_key: public_keys[1].clone(),
+            frozen_credits: 5,
+        }]
+    );
+    assert_eq!(response_quote_payment.opt_rejection, None);
+
+    // 0: We must be the first on a quoted route:
+    node_controls[0]
+        .send(FunderControl::QuotePayment(QuotePayment {
+            request_id: Uid::from(&[11u8; Uid::len()]),
+            currency: currency1.clone(),
+            route: FriendsRoute {
+                public_keys: vec![public_keys[1].clone(), public_keys[0].clone()],
+            },
+            mediator_rates: Vec::new(),
+            dest_payment: 5,
+            opt_max_fee_per_hop: None,
+        }))
+        .await;
+    let response_quote_payment = node_controls[0]
+        .recv_until_response_quote_payment()
+        .await
+        .unwrap();
+    assert!(response_quote_payment.hop_freezes.is_empty());
+    assert_eq!(
+        response_quote_payment.opt_rejection,
+        Some(QuoteRejection::InvalidRoute)
+    );
+
+    // 1: Export the signed state of the channel with node 0:
+    node_controls[1]
+        .send(FunderControl::ExportChannelProof(ExportChannelProof {
+            request_id: Uid::from(&[8u8; Uid::len()]),
+            friend_public_key: public_keys[0].clone(),
+        }))
+        .await;
+    let response_channel_proof = node_controls[1]
+        .recv_until_response_channel_proof()
+        .await
+        .unwrap();
+    assert_eq!(
+        response_channel_proof.request_id,
+        Uid::from(&[8u8; Uid::len()])
+    );
+    let blob = match response_channel_proof.result {
+        ChannelProofResult::Found(blob) => blob,
+        ChannelProofResult::NotFound => unreachable!(),
+    };
+
+    // Anyone can verify the proof, without access to any of the nodes:
+    let channel_proof = verify_channel_proof_blob(&blob).unwrap();
+    assert_eq!(channel_proof.local_public_key, public_keys[1]);
+    assert_eq!(channel_proof.friend_public_key, public_keys[0]);
+    let token_info = latest_token_info(&channel_proof).unwrap();
+    let currency_balance_info = token_info
+        .mc
+        .balances
+        .iter()
+        .find(|currency_balance_info| currency_balance_info.currency == currency1)
+        .unwrap();
+    assert_eq!(currency_balance_info.balance_info.balance, 6);
+
+    // 1: There is no channel with an unknown friend:
+    node_controls[1]
+        .send(FunderControl::ExportChannelProof(ExportChannelProof {
+            request_id: Uid::from(&[9u8; Uid::len()]),
+            friend_public_key: PublicKey::from(&[0xaa; PublicKey::len()]),
+        }))
+        .await;
+    let response_channel_proof = node_controls[1]
+        .recv_until_response_channel_proof()
+        .await
+        .unwrap();
+    assert_eq!(response_channel_proof.result, ChannelProofResult::NotFound);
 }
 
 #[test]
@@ -0,0 +1,269 @@
+use std::convert::TryFrom;
+
+use common::test_executor::TestExecutor;
+
+use proto::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
+use proto::funder::messages::{
+    AckClosePayment, AddInvoice, CreateExchangeTransaction, CreatePayment, Currency, ExchangeRate,
+    FriendStatus, FriendsRoute, FunderControl, PaymentStatus, Rate, RequestResult, RequestsStatus,
+    SetExchangeRate,
+};
+
+use super::utils::{create_node_controls, dummy_relay_address};
+
+async fn task_funder_exchange_payment(test_executor: TestExecutor) {
+    let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+    let currency2 = Currency::try_from("FST2".to_owned()).unwrap();
+
+    /*
+     * 0 -- 1 -- 2
+     *
+     * 0 and 1 trade in currency1. 1 and 2 trade in currency2.
+     * 1 exchanges currency1 to currency2.
+     */
+    let num_nodes = 3;
+    let mut node_controls = create_node_controls(num_nodes, test_executor.clone()).await;
+
+    let public_keys = node_controls
+        .iter()
+        .map(|nc| nc.public_key.clone())
+        .collect::<Vec<PublicKey>>();
+
+    // Add friends:
+    let relays0 = vec![dummy_relay_address(0)];
+    let relays1 = vec![dummy_relay_address(1)];
+    let relays2 = vec![dummy_relay_address(2)];
+    node_controls[0]
+        .add_friend(&public_keys[1], relays1.clone(), "node1")
+        .await;
+    node_controls[1]
+        .add_friend(&public_keys[0], relays0, "node0")
+        .await;
+    node_controls[1]
+        .add_friend(&public_keys[2], relays2, "node2")
+        .await;
+    node_controls[2]
+        .add_friend(&public_keys[1], relays1, "node1")
+        .await;
+
+    // Enable friends:
+    node_controls[0]
+        .set_friend_status(&public_keys[1], FriendStatus::Enabled)
+        .await;
+    node_controls[1]
+        .set_friend_status(&public_keys[0], FriendStatus::Enabled)
+        .await;
+    node_controls[1]
+        .set_friend_status(&public_keys[2], FriendStatus::Enabled)
+        .await;
+    node_controls[2]
+        .set_friend_status(&public_keys[1], FriendStatus::Enabled)
+        .await;
+
+    test_executor.wait().await;
+
+    // Add active currencies:
+    node_controls[0]
+        .set_friend_currencies(&public_keys[1], vec![currency1.clone()])
+        .await;
+    node_controls[1]
+        .set_friend_currencies(&public_keys[0], vec![currency1.clone()])
+        .await;
+    node_controls[1]
+        .set_friend_currencies(&public_keys[2], vec![currency2.clone()])
+        .await;
+    node_controls[2]
+        .set_friend_currencies(&public_keys[1], vec![currency2.clone()])
+        .await;
+
+    test_executor.wait().await;
+
+    node_controls[0]
+        .wait_until_currency_active(&public_keys[1], &currency1)
+        .await;
+    node_controls[1]
+        .wait_until_currency_active(&public_keys[0], &currency1)
+        .await;
+    node_controls[1]
+        .wait_until_currency_active(&public_keys[2], &currency2)
+        .await;
+    node_controls[2]
+        .wait_until_currency_active(&public_keys[1], &currency2)
+        .await;
+
+    test_executor.wait().await;
+
+    // Node 1 takes 5 credits of currency1 from node 0 for forwarding messages:
+    node_controls[1]
+        .set_friend_currency_rate(&public_keys[0], &currency1, Rate { mul: 0, add: 5 })
+        .await;
+
+    // Node 1 gives 2 credits of currency2 for every credit of currency1:
+    node_controls[1]
+        .send(FunderControl::SetExchangeRate(SetExchangeRate {
+            from_currency: currency1.clone(),
+            to_currency: currency2.clone(),
+            opt_rate: Some(ExchangeRate {
+                from_credits: 1,
+                to_credits: 2,
+            }),
+        }))
+        .await;
+
+    // Set remote max debt:
+    node_controls[0]
+        .set_remote_max_debt(&public_keys[1], &currency1, 200)
+        .await;
+    node_controls[1]
+        .set_remote_max_debt(&public_keys[0], &currency1, 100)
+        .await;
+    node_controls[1]
+        .set_remote_max_debt(&public_keys[2], &currency2, 300)
+        .await;
+    node_controls[2]
+        .set_remote_max_debt(&public_keys[1], &currency2, 400)
+        .await;
+
+    // Open requests, allowing this route: 0 --> 1 --> 2
+    node_controls[0]
+        .set_requests_status(&public_keys[1], &currency1, RequestsStatus::Open)
+        .await;
+    node_controls[1]
+        .set_requests_status(&public_keys[0], &currency1, RequestsStatus::Open)
+        .await;
+    node_controls[1]
+        .set_requests_status(&public_keys[2], &currency2, RequestsStatus::Open)
+        .await;
+    node_controls[2]
+        .set_requests_status(&public_keys[1], &currency2, RequestsStatus::Open)
+        .await;
+
+    node_controls[0]
+        .wait_until_ready(&public_keys[1], &currency1)
+        .await;
+    node_controls[1]
+        .wait_until_ready(&public_keys[2], &currency2)
+        .await;
+
+    // Let node 2 open an invoice in currency2:
+    let add_invoice = AddInvoice {
+        invoice_id: InvoiceId::from(&[1u8; InvoiceId::len()]),
+        currency: currency2.clone(),
+        total_dest_payment: 30,
+        opt_expiry_ticks: None,
+    };
+    node_controls[2]
+        .send(FunderControl::AddInvoice(add_invoice))
+        .await;
+
+    // Create payment 0 --> 2
+    let create_payment = CreatePayment {
+        payment_id: PaymentId::from(&[2u8; PaymentId::len()]),
+        invoice_id: InvoiceId::from(&[1u8; InvoiceId::len()]),
+        currency: currency2.clone(),
+        total_dest_payment: 30,
+        dest_public_key: node_controls[2].public_key.clone(),
+        opt_max_fee_per_hop: None,
+    };
+    node_controls[0]
+        .send(FunderControl::CreatePayment(create_payment))
+        .await;
+
+    // Create transaction 0 --> 2, paying node 1 in currency1:
+    let create_exchange_transaction = CreateExchangeTransaction {
+        payment_id: PaymentId::from(&[2u8; PaymentId::len()]),
+        request_id: Uid::from(&[5u8; Uid::len()]),
+        route: FriendsRoute {
+            public_keys: vec![
+                public_keys[0].clone(),
+                public_keys[1].clone(),
+                public_keys[2].clone(),
+            ],
+        },
+        currency: currency1.clone(),
+        exchanged_payment: 15,
+        dest_payment: 30,
+        fees: 5,
+        refund_ticks: 0,
+    };
+    node_controls[0]
+        .send(FunderControl::CreateExchangeTransaction(
+            create_exchange_transaction,
+        ))
+        .await;
+    let transaction_result = node_controls[0]
+        .recv_until_transaction_result()
+        .await
+        .unwrap();
+
+    // The commit is over the terms of the invoice:
+    let commit = match transaction_result.result {
+        RequestResult::Complete(commit) => commit,
+        _ => unreachable!(),
+    };
+    assert_eq!(commit.currency, currency2);
+    assert_eq!(commit.dest_payment, 30);
+    assert_eq!(commit.total_dest_payment, 30);
+
+    // Commit: 0 ==> 2  (Out of band)
+    node_controls[2]
+        .send(FunderControl::CommitInvoice(commit))
+        .await;
+
+    test_executor.wait().await;
+
+    // 0: Expect a receipt:
+    node_controls[0]
+        .send(FunderControl::RequestClosePayment(PaymentId::from(
+            &[2u8; PaymentId::len()],
+        )))
+        .await;
+    let response_close_payment = node_controls[0]
+        .recv_until_response_close_payment()
+        .await
+        .unwrap();
+    let (receipt, ack_uid) = match response_close_payment.status {
+        PaymentStatus::Success(payment_status_success) => (
+            payment_status_success.receipt,
+            payment_status_success.ack_uid,
+        ),
+        _ => unreachable!(),
+    };
+
+    let ack_close_payment = AckClosePayment {
+        payment_id: PaymentId::from(&[2u8; PaymentId::len()]),
+        ack_uid,
+    };
+    node_controls[0]
+        .send(FunderControl::AckClosePayment(ack_close_payment))
+        .await;
+
+    assert_eq!(receipt.currency, currency2);
+    assert_eq!(receipt.dest_payment, 30);
+    assert_eq!(receipt.total_dest_payment, 30);
+
+    test_executor.wait().await;
+
+    // Node 2 got the credits in currency2:
+    node_controls[2]
+        .wait_friend_balance(&public_keys[1], &currency2, 30)
+        .await;
+    node_controls[1]
+        .wait_friend_balance(&public_keys[2], &currency2, -30)
+        .await;
+
+    // Node 1 got the exchanged payment and its fees in currency1:
+    node_controls[1]
+        .wait_friend_balance(&public_keys[0], &currency1, 20)
+        .await;
+    node_controls[0]
+        .wait_friend_balance(&public_keys[1], &currency1, -20)
+        .await;
+}
+
+#[test]
+fn test_funder_exchange_payment() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_funder_exchange_payment(test_executor.clone()));
+    assert!(res.is_output());
+}
@@ -155,6 +155,7 @@ async fn task_funder_forward_payment(test_executor: TestExecutor) {
         invoice_id: InvoiceId::from(&[1u8; InvoiceId::len()]),
         currency: currency1.clone(),
         total_dest_payment: 15,
+        opt_expiry_ticks: None,
     };
     node_controls[2]
         .send(FunderControl::AddInvoice(add_invoice))
@@ -167,6 +168,7 @@ async fn task_funder_forward_payment(test_executor: TestExecutor) {
         currency: currency1.clone(),
         total_dest_payment: 15,
         dest_public_key: node_controls[2].public_key.clone(),
+        opt_max_fee_per_hop: None,
     };
     node_controls[0]
         .send(FunderControl::CreatePayment(create_payment))
@@ -185,6 +187,7 @@ async fn task_funder_forward_payment(test_executor: TestExecutor) {
         },
         dest_payment: 15,
         fees: 5,
+        opt_refund_ticks: None,
     };
     node_controls[0]
         .send(FunderControl::CreateTransaction(create_transaction))
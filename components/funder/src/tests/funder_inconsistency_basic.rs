@@ -86,6 +86,7 @@ async fn task_funder_inconsistency_basic(test_executor: TestExecutor) {
         invoice_id: InvoiceId::from(&[1u8; InvoiceId::len()]),
         currency: currency1.clone(),
         total_dest_payment: 4,
+        opt_expiry_ticks: None,
     };
     node_controls[1]
         .send(FunderControl::AddInvoice(add_invoice))
@@ -98,6 +99,7 @@ async fn task_funder_inconsistency_basic(test_executor: TestExecutor) {
         currency: currency1.clone(),
         total_dest_payment: 4,
         dest_public_key: node_controls[1].public_key.clone(),
+        opt_max_fee_per_hop: None,
     };
     node_controls[0]
         .send(FunderControl::CreatePayment(create_payment))
@@ -112,6 +114,7 @@ async fn task_funder_inconsistency_basic(test_executor: TestExecutor) {
         },
         dest_payment: 4,
         fees: 1,
+        opt_refund_ticks: None,
     };
 
     node_controls[0]
@@ -229,6 +232,36 @@ async fn task_funder_inconsistency_basic(test_executor: TestExecutor) {
     };
     node_controls[0].recv_until(pred).await;
 
+    // Node1 had a consistent channel with the previous balance, which is kept as evidence:
+    let pred = |report: &FunderReport<_>| {
+        let friend = report.friends.get(&public_keys[0]).unwrap();
+        match &friend.channel_status {
+            ChannelStatusReport::Consistent(_) => false,
+            ChannelStatusReport::Inconsistent(_) => true,
+        }
+    };
+    node_controls[1].recv_until(pred).await;
+
+    let friend = node_controls[1]
+        .report
+        .friends
+        .get(&public_keys[0])
+        .unwrap();
+    let dispute_evidence = match &friend.channel_status {
+        ChannelStatusReport::Consistent(_) => unreachable!(),
+        ChannelStatusReport::Inconsistent(channel_inconsistent_report) => {
+            &channel_inconsistent_report.dispute_evidence
+        }
+    };
+    assert!(!dispute_evidence.channel_proof.is_empty());
+    let currency_report = dispute_evidence
+        .currency_reports
+        .iter()
+        .find(|currency_report| currency_report.currency == currency1)
+        .unwrap();
+    assert_eq!(currency_report.balance.balance, 5);
+    assert!(dispute_evidence.pending_transactions.is_empty());
+
     // Resolve inconsistency
     // ---------------------
 
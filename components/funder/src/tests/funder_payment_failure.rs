@@ -152,6 +152,7 @@ async fn task_funder_payment_failure(test_executor: TestExecutor) {
         currency: currency1.clone(),
         total_dest_payment: 15,
         dest_public_key: node_controls[3].public_key.clone(),
+        opt_max_fee_per_hop: None,
     };
     node_controls[0]
         .send(FunderControl::CreatePayment(create_payment))
@@ -171,6 +172,7 @@ async fn task_funder_payment_failure(test_executor: TestExecutor) {
         },
         dest_payment: 15,
         fees: 5,
+        opt_refund_ticks: None,
     };
     node_controls[0]
         .send(FunderControl::CreateTransaction(create_transaction))
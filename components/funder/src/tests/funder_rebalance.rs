@@ -0,0 +1,209 @@
+use std::convert::TryFrom;
+
+use common::test_executor::TestExecutor;
+
+use proto::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
+use proto::funder::messages::{
+    AckClosePayment, Currency, FriendStatus, FriendsRoute, FunderControl, PaymentStatus, Rate,
+    Rebalance, RequestResult, RequestsStatus,
+};
+
+use super::utils::{create_node_controls, dummy_relay_address};
+
+async fn task_funder_rebalance(test_executor: TestExecutor) {
+    let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+
+    /*
+     * 0 -- 1
+     *  \  /
+     *   2
+     */
+    let num_nodes = 3;
+    let mut node_controls = create_node_controls(num_nodes, test_executor.clone()).await;
+
+    let public_keys = node_controls
+        .iter()
+        .map(|nc| nc.public_key.clone())
+        .collect::<Vec<PublicKey>>();
+
+    // Every pair of nodes are friends:
+    let pairs = [(0, 1), (1, 2), (2, 0)];
+    for &(a, b) in &pairs {
+        node_controls[a]
+            .add_friend(
+                &public_keys[b],
+                vec![dummy_relay_address(b as u8)],
+                "friend",
+            )
+            .await;
+        node_controls[b]
+            .add_friend(
+                &public_keys[a],
+                vec![dummy_relay_address(a as u8)],
+                "friend",
+            )
+            .await;
+    }
+
+    for &(a, b) in &pairs {
+        node_controls[a]
+            .set_friend_status(&public_keys[b], FriendStatus::Enabled)
+            .await;
+        node_controls[b]
+            .set_friend_status(&public_keys[a], FriendStatus::Enabled)
+            .await;
+    }
+
+    test_executor.wait().await;
+
+    for &(a, b) in &pairs {
+        node_controls[a]
+            .set_friend_currencies(&public_keys[b], vec![currency1.clone()])
+            .await;
+        node_controls[b]
+            .set_friend_currencies(&public_keys[a], vec![currency1.clone()])
+            .await;
+    }
+
+    test_executor.wait().await;
+
+    for &(a, b) in &pairs {
+        node_controls[a]
+            .wait_until_currency_active(&public_keys[b], &currency1)
+            .await;
+        node_controls[b]
+            .wait_until_currency_active(&public_keys[a], &currency1)
+            .await;
+    }
+
+    test_executor.wait().await;
+
+    // Mediators take one credit for forwarding a request:
+    node_controls[1]
+        .set_friend_currency_rate(&public_keys[0], &currency1, Rate { mul: 0, add: 1 })
+        .await;
+    node_controls[2]
+        .set_friend_currency_rate(&public_keys[1], &currency1, Rate { mul: 0, add: 1 })
+        .await;
+
+    for &(a, b) in &pairs {
+        node_controls[a]
+            .set_remote_max_debt(&public_keys[b], &currency1, 100)
+            .await;
+        node_controls[b]
+            .set_remote_max_debt(&public_keys[a], &currency1, 100)
+            .await;
+        node_controls[a]
+            .set_requests_status(&public_keys[b], &currency1, RequestsStatus::Open)
+            .await;
+        node_controls[b]
+            .set_requests_status(&public_keys[a], &currency1, RequestsStatus::Open)
+            .await;
+    }
+
+    // Wait until the route 0 --> 1 --> 2 --> 0 is ready:
+    node_controls[0]
+        .wait_until_ready(&public_keys[1], &currency1)
+        .await;
+    node_controls[1]
+        .wait_until_ready(&public_keys[2], &currency1)
+        .await;
+    node_controls[2]
+        .wait_until_ready(&public_keys[0], &currency1)
+        .await;
+
+    // Node 0 pays itself, moving credits from node 2 to node 1:
+    let rebalance = Rebalance {
+        payment_id: PaymentId::from(&[2u8; PaymentId::len()]),
+        invoice_id: InvoiceId::from(&[1u8; InvoiceId::len()]),
+        request_id: Uid::from(&[5u8; Uid::len()]),
+        currency: currency1.clone(),
+        route: FriendsRoute {
+            public_keys: vec![
+                public_keys[0].clone(),
+                public_keys[1].clone(),
+                public_keys[2].clone(),
+                public_keys[0].clone(),
+            ],
+        },
+        amount: 20,
+        fees: 2,
+    };
+    node_controls[0]
+        .send(FunderControl::Rebalance(rebalance))
+        .await;
+    let transaction_result = node_controls[0]
+        .recv_until_transaction_result()
+        .await
+        .unwrap();
+
+    match transaction_result.result {
+        RequestResult::Complete(_) => {}
+        _ => unreachable!(),
+    };
+
+    // The invoice is committed by node 0 itself. Wait until we get a receipt:
+    test_executor.wait().await;
+
+    node_controls[0]
+        .send(FunderControl::RequestClosePayment(PaymentId::from(
+            &[2u8; PaymentId::len()],
+        )))
+        .await;
+    let response_close_payment = node_controls[0]
+        .recv_until_response_close_payment()
+        .await
+        .unwrap();
+    let (receipt, ack_uid) = match response_close_payment.status {
+        PaymentStatus::Success(payment_status_success) => (
+            payment_status_success.receipt,
+            payment_status_success.ack_uid,
+        ),
+        _ => unreachable!(),
+    };
+
+    let ack_close_payment = AckClosePayment {
+        payment_id: PaymentId::from(&[2u8; PaymentId::len()]),
+        ack_uid,
+    };
+    node_controls[0]
+        .send(FunderControl::AckClosePayment(ack_close_payment))
+        .await;
+
+    assert_eq!(
+        receipt.invoice_id,
+        InvoiceId::from(&[1u8; InvoiceId::len()])
+    );
+    assert_eq!(receipt.dest_payment, 20);
+
+    test_executor.wait().await;
+
+    // Node 0 paid node 1 (Including fees), and was paid by node 2:
+    node_controls[0]
+        .wait_friend_balance(&public_keys[1], &currency1, -22)
+        .await;
+    node_controls[0]
+        .wait_friend_balance(&public_keys[2], &currency1, 20)
+        .await;
+
+    // Every mediator got its fee:
+    node_controls[1]
+        .wait_friend_balance(&public_keys[0], &currency1, 22)
+        .await;
+    node_controls[1]
+        .wait_friend_balance(&public_keys[2], &currency1, -21)
+        .await;
+    node_controls[2]
+        .wait_friend_balance(&public_keys[1], &currency1, 21)
+        .await;
+    node_controls[2]
+        .wait_friend_balance(&public_keys[0], &currency1, -20)
+        .await;
+}
+
+#[test]
+fn test_funder_rebalance() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_funder_rebalance(test_executor.clone()));
+    assert!(res.is_output());
+}
@@ -0,0 +1,127 @@
+use std::convert::TryFrom;
+
+use common::test_executor::TestExecutor;
+
+use proto::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
+use proto::funder::messages::{
+    CreatePayment, CreateTransaction, Currency, FriendsRoute, FunderControl, RequestResult,
+    TransactionRejection,
+};
+
+use super::utils::create_node_controls;
+
+async fn task_funder_transaction_limits(test_executor: TestExecutor) {
+    let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+
+    let num_nodes = 1;
+    let mut node_controls = create_node_controls(num_nodes, test_executor.clone()).await;
+    let local_public_key = node_controls[0].public_key.clone();
+
+    let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+    let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+    let pk_c = PublicKey::from(&[0xcc; PublicKey::len()]);
+    let dest_public_key = PublicKey::from(&[0xdd; PublicKey::len()]);
+
+    // Every forwarding node may take at most 2 credits of fees:
+    let create_payment = CreatePayment {
+        payment_id: PaymentId::from(&[2u8; PaymentId::len()]),
+        invoice_id: InvoiceId::from(&[1u8; InvoiceId::len()]),
+        currency: currency1.clone(),
+        total_dest_payment: 10,
+        dest_public_key: dest_public_key.clone(),
+        opt_max_fee_per_hop: Some(2),
+    };
+    node_controls[0]
+        .send(FunderControl::CreatePayment(create_payment))
+        .await;
+
+    // A route longer than the maximum route length of the node:
+    let create_transaction = CreateTransaction {
+        payment_id: PaymentId::from(&[2u8; PaymentId::len()]),
+        request_id: Uid::from(&[5u8; Uid::len()]),
+        route: FriendsRoute {
+            public_keys: vec![
+                local_public_key.clone(),
+                pk_a.clone(),
+                pk_b.clone(),
+                pk_c.clone(),
+                dest_public_key.clone(),
+            ],
+        },
+        dest_payment: 10,
+        fees: 1,
+        opt_refund_ticks: None,
+    };
+    node_controls[0]
+        .send(FunderControl::CreateTransaction(create_transaction))
+        .await;
+
+    let transaction_result = node_controls[0]
+        .recv_until_transaction_result()
+        .await
+        .unwrap();
+    assert_eq!(transaction_result.request_id, Uid::from(&[5u8; Uid::len()]));
+    assert_eq!(
+        transaction_result.result,
+        RequestResult::Rejected(TransactionRejection::RouteTooLong(4))
+    );
+
+    // Fees above the cap (One forwarding node, at most 2 credits):
+    let create_transaction = CreateTransaction {
+        payment_id: PaymentId::from(&[2u8; PaymentId::len()]),
+        request_id: Uid::from(&[6u8; Uid::len()]),
+        route: FriendsRoute {
+            public_keys: vec![
+                local_public_key.clone(),
+                pk_a.clone(),
+                dest_public_key.clone(),
+            ],
+        },
+        dest_payment: 10,
+        fees: 3,
+        opt_refund_ticks: None,
+    };
+    node_controls[0]
+        .send(FunderControl::CreateTransaction(create_transaction))
+        .await;
+
+    let transaction_result = node_controls[0]
+        .recv_until_transaction_result()
+        .await
+        .unwrap();
+    assert_eq!(transaction_result.request_id, Uid::from(&[6u8; Uid::len()]));
+    assert_eq!(
+        transaction_result.result,
+        RequestResult::Rejected(TransactionRejection::FeesExceedCap(2))
+    );
+
+    // Fees within the cap (Two forwarding nodes, at most 4 credits). The transaction passes the
+    // limits, but fails because we have no such friend:
+    let create_transaction = CreateTransaction {
+        payment_id: PaymentId::from(&[2u8; PaymentId::len()]),
+        request_id: Uid::from(&[7u8; Uid::len()]),
+        route: FriendsRoute {
+            public_keys: vec![local_public_key, pk_a, pk_b, dest_public_key],
+        },
+        dest_payment: 10,
+        fees: 3,
+        opt_refund_ticks: None,
+    };
+    node_controls[0]
+        .send(FunderControl::CreateTransaction(create_transaction))
+        .await;
+
+    let transaction_result = node_controls[0]
+        .recv_until_transaction_result()
+        .await
+        .unwrap();
+    assert_eq!(transaction_result.request_id, Uid::from(&[7u8; Uid::len()]));
+    assert_eq!(transaction_result.result, RequestResult::Failure);
+}
+
+#[test]
+fn test_funder_transaction_limits() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_funder_transaction_limits(test_executor.clone()));
+    assert!(res.is_output());
+}
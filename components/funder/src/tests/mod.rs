@@ -1,7 +1,11 @@
 mod funder_basic;
 mod funder_error_command;
+mod funder_exchange_payment;
 mod funder_forward_payment;
 mod funder_inconsistency_basic;
 mod funder_payment_failure;
+mod funder_payment_retry;
+mod funder_rebalance;
+mod funder_transaction_limits;
 
 pub mod utils;
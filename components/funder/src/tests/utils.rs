@@ -22,14 +22,16 @@ use proto::report::messages::{
 use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
 use proto::funder::messages::{
     AddFriend, Currency, FriendStatus, FunderControl, FunderIncomingControl, FunderOutgoingControl,
-    Rate, RemoveFriend, RemoveFriendCurrency, RequestsStatus, ResponseClosePayment,
-    SetFriendCurrencyMaxDebt, SetFriendCurrencyRate, SetFriendCurrencyRequestsStatus,
-    SetFriendStatus, TransactionResult,
+    Rate, RemoveFriend, RemoveFriendCurrency, RequestAlternativeRoute, RequestError,
+    RequestsStatus, ResponseChannelProof, ResponseClosePayment, ResponseExposure,
+    ResponseQuotePayment, SetFriendCurrencyMaxDebt, SetFriendCurrencyRate,
+    SetFriendCurrencyRequestsStatus, SetFriendStatus, TransactionResult,
 };
 
-use database::DatabaseClient;
+use database::{DatabaseClient, DatabaseRequest};
 
 use identity::{create_identity, IdentityClient};
+use timer::TimerTick;
 
 use crate::ephemeral::Ephemeral;
 use crate::funder::inner_funder_loop;
@@ -43,6 +45,11 @@ use crate::types::{
 const TEST_MAX_NODE_RELAYS: usize = 16;
 const TEST_MAX_OPERATIONS_IN_BATCH: usize = 16;
 const TEST_MAX_PENDING_USER_REQUESTS: usize = 16;
+const TEST_MAX_ROUTE_LEN: usize = 4;
+const TEST_MAX_TRANSACTION_RETRIES: u64 = 0;
+const TEST_REQUEST_EXPIRY_TICKS: u64 = 0;
+const TEST_MIN_FRIEND_UPTIME_PERCENT: u8 = 0;
+const TEST_AUTO_RESET_TOLERANCE: Option<u128> = None;
 
 // This is required to make sure the tests are not stuck.
 //
@@ -92,7 +99,7 @@ async fn router_handle_outgoing_comm<'a, B: 'a>(
     B: Debug,
 {
     match outgoing_comm {
-        FunderOutgoingComm::FriendMessage((dest_public_key, friend_message)) => {
+        FunderOutgoingComm::FriendMessage((dest_public_key, _message_id, friend_message)) => {
             let node = nodes.get_mut(&dest_public_key).unwrap();
             assert!(node.friends.contains(&src_public_key));
             let incoming_comm_message =
@@ -217,6 +224,11 @@ pub enum NodeRecv<B: Clone> {
     ReportMutations(FunderReportMutations<B>),
     ResponseClosePayment(ResponseClosePayment),
     TransactionResult(TransactionResult),
+    RequestAlternativeRoute(RequestAlternativeRoute),
+    ResponseExposure(ResponseExposure),
+    ResponseChannelProof(ResponseChannelProof),
+    ResponseQuotePayment(ResponseQuotePayment),
+    RequestError(RequestError),
 }
 
 impl<B> NodeControl<B>
@@ -272,6 +284,21 @@ where
             FunderOutgoingControl::TransactionResult(transaction_result) => {
                 Some(NodeRecv::TransactionResult(transaction_result))
             }
+            FunderOutgoingControl::RequestAlternativeRoute(request_alternative_route) => {
+                Some(NodeRecv::RequestAlternativeRoute(request_alternative_route))
+            }
+            FunderOutgoingControl::ResponseExposure(response_exposure) => {
+                Some(NodeRecv::ResponseExposure(response_exposure))
+            }
+            FunderOutgoingControl::ResponseChannelProof(response_channel_proof) => {
+                Some(NodeRecv::ResponseChannelProof(response_channel_proof))
+            }
+            FunderOutgoingControl::ResponseQuotePayment(response_quote_payment) => {
+                Some(NodeRecv::ResponseQuotePayment(response_quote_payment))
+            }
+            FunderOutgoingControl::RequestError(request_error) => {
+                Some(NodeRecv::RequestError(request_error))
+            }
         }
     }
 
@@ -284,6 +311,11 @@ where
                 NodeRecv::ReportMutations(_) => {}
                 NodeRecv::TransactionResult(_) => unreachable!(),
                 NodeRecv::ResponseClosePayment(_) => unreachable!(),
+                NodeRecv::RequestAlternativeRoute(_) => unreachable!(),
+                NodeRecv::ResponseExposure(_) => unreachable!(),
+                NodeRecv::ResponseChannelProof(_) => unreachable!(),
+                NodeRecv::ResponseQuotePayment(_) => unreachable!(),
+                NodeRecv::RequestError(_) => {}
             };
         }
     }
@@ -294,6 +326,30 @@ where
                 NodeRecv::ReportMutations(_) => {}
                 NodeRecv::TransactionResult(transaction_result) => return Some(transaction_result),
                 NodeRecv::ResponseClosePayment(_) => {}
+                NodeRecv::RequestAlternativeRoute(_) => {}
+                NodeRecv::ResponseExposure(_) => {}
+                NodeRecv::ResponseChannelProof(_) => {}
+                NodeRecv::ResponseQuotePayment(_) => {}
+                NodeRecv::RequestError(_) => {}
+            };
+        }
+    }
+
+    pub async fn recv_until_request_alternative_route(
+        &mut self,
+    ) -> Option<RequestAlternativeRoute> {
+        loop {
+            match self.recv().await? {
+                NodeRecv::ReportMutations(_) => {}
+                NodeRecv::TransactionResult(_) => {}
+                NodeRecv::ResponseClosePayment(_) => {}
+                NodeRecv::RequestAlternativeRoute(request_alternative_route) => {
+                    return Some(request_alternative_route)
+                }
+                NodeRecv::ResponseExposure(_) => {}
+                NodeRecv::ResponseChannelProof(_) => {}
+                NodeRecv::ResponseQuotePayment(_) => {}
+                NodeRecv::RequestError(_) => {}
             };
         }
     }
@@ -306,6 +362,60 @@ where
                 NodeRecv::ResponseClosePayment(response_close_payment) => {
                     return Some(response_close_payment)
                 }
+                NodeRecv::RequestAlternativeRoute(_) => {}
+                NodeRecv::ResponseExposure(_) => {}
+                NodeRecv::ResponseChannelProof(_) => {}
+                NodeRecv::ResponseQuotePayment(_) => {}
+                NodeRecv::RequestError(_) => {}
+            };
+        }
+    }
+
+    pub async fn recv_until_response_exposure(&mut self) -> Option<ResponseExposure> {
+        loop {
+            match self.recv().await? {
+                NodeRecv::ReportMutations(_) => {}
+                NodeRecv::TransactionResult(_) => {}
+                NodeRecv::ResponseClosePayment(_) => {}
+                NodeRecv::RequestAlternativeRoute(_) => {}
+                NodeRecv::ResponseExposure(response_exposure) => return Some(response_exposure),
+                NodeRecv::ResponseChannelProof(_) => {}
+                NodeRecv::ResponseQuotePayment(_) => {}
+                NodeRecv::RequestError(_) => {}
+            };
+        }
+    }
+
+    pub async fn recv_until_response_channel_proof(&mut self) -> Option<ResponseChannelProof> {
+        loop {
+            match self.recv().await? {
+                NodeRecv::ReportMutations(_) => {}
+                NodeRecv::TransactionResult(_) => {}
+                NodeRecv::ResponseClosePayment(_) => {}
+                NodeRecv::RequestAlternativeRoute(_) => {}
+                NodeRecv::ResponseExposure(_) => {}
+                NodeRecv::ResponseChannelProof(response_channel_proof) => {
+                    return Some(response_channel_proof)
+                }
+                NodeRecv::ResponseQuotePayment(_) => {}
+                NodeRecv::RequestError(_) => {}
+            };
+        }
+    }
+
+    pub async fn recv_until_response_quote_payment(&mut self) -> Option<ResponseQuotePayment> {
+        loop {
+            match self.recv().await? {
+                NodeRecv::ReportMutations(_) => {}
+                NodeRecv::TransactionResult(_) => {}
+                NodeRecv::ResponseClosePayment(_) => {}
+                NodeRecv::RequestAlternativeRoute(_) => {}
+                NodeRecv::ResponseExposure(_) => {}
+                NodeRecv::ResponseChannelProof(_) => {}
+                NodeRecv::ResponseQuotePayment(response_quote_payment) => {
+                    return Some(response_quote_payment)
+                }
+                NodeRecv::RequestError(_) => {}
             };
         }
     }
@@ -533,6 +643,19 @@ where
 /// This allows having a conversation between any two nodes.
 /// We use A = u32:
 pub async fn create_node_controls<S>(num_nodes: usize, spawner: S) -> Vec<NodeControl<u32>>
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    create_node_controls_with_retries(num_nodes, TEST_MAX_TRANSACTION_RETRIES, spawner).await
+}
+
+/// Create node controls, where every node may retry failed transactions
+/// up to `max_transaction_retries` times.
+pub async fn create_node_controls_with_retries<S>(
+    num_nodes: usize,
+    max_transaction_retries: u64,
+    spawner: S,
+) -> Vec<NodeControl<u32>>
 where
     S: Spawn + Clone + Send + 'static,
 {
@@ -570,7 +693,9 @@ where
         let fut_dispose_db_requests = async move {
             // Read all incoming db requests:
             while let Some(request) = incoming_db_requests.next().await {
-                let _ = request.response_sender.send(());
+                if let DatabaseRequest::Mutate(mutate_request) = request {
+                    let _ = mutate_request.response_sender.send(());
+                }
             }
         };
         spawner.spawn(fut_dispose_db_requests).unwrap();
@@ -581,11 +706,15 @@ where
         let (send_comm, incoming_comm) = mpsc::channel(CHANNEL_SIZE);
         let (comm_sender, recv_comm) = mpsc::channel(CHANNEL_SIZE);
 
+        // Invoices do not expire in these tests:
+        let (_tick_sender, timer_stream) = mpsc::channel::<TimerTick>(0);
+
         let funder_fut = inner_funder_loop(
             identity_client.clone(),
             DummyRandom::new(&[i as u8]),
             incoming_control,
             incoming_comm,
+            timer_stream,
             control_sender,
             comm_sender,
             funder_state,
@@ -593,6 +722,11 @@ where
             TEST_MAX_NODE_RELAYS,
             TEST_MAX_OPERATIONS_IN_BATCH,
             TEST_MAX_PENDING_USER_REQUESTS,
+            TEST_MAX_ROUTE_LEN,
+            max_transaction_retries,
+            TEST_REQUEST_EXPIRY_TICKS,
+            TEST_MIN_FRIEND_UPTIME_PERCENT,
+            TEST_AUTO_RESET_TOLERANCE,
             None,
         );
 
@@ -18,14 +18,15 @@ use proto::funder::messages::{
     BalanceInfo, CountersInfo, Currency, CurrencyBalanceInfo, CurrencyOperations, McInfo,
     MoveToken, TokenInfo, UnsignedMoveToken,
 };
-use signature::signature_buff::hash_token_info;
-use signature::verify::verify_move_token;
+use proto::report::messages::MoveTokenHashedReport;
+use signature::signature_buff::{hash_token_info, LEGACY_SIGNATURE_BUFF_VERSION};
+use signature::verify::{verify_move_token, verify_move_token_hashed_report};
 
 use crate::mutual_credit::incoming::{
     process_operations_list, IncomingMessage, ProcessOperationOutput, ProcessTransListError,
 };
 use crate::mutual_credit::outgoing::OutgoingMc;
-use crate::mutual_credit::types::{McMutation, MutualCredit};
+use crate::mutual_credit::types::{McIdents, McMutation, MutualCredit};
 
 use crate::types::{create_hashed, create_unsigned_move_token, MoveTokenHashed};
 
@@ -90,6 +91,7 @@ pub enum TcDirection<B> {
 #[derive(Clone, Debug)]
 pub struct TcInBorrow<'a> {
     pub tc_incoming: &'a TcIncoming,
+    idents: &'a McIdents,
     mutual_credits: &'a ImHashMap<Currency, MutualCredit>,
     active_currencies: &'a ActiveCurrencies,
 }
@@ -97,6 +99,7 @@ pub struct TcInBorrow<'a> {
 #[derive(Clone, Debug)]
 pub struct TcOutBorrow<'a, B> {
     pub tc_outgoing: &'a TcOutgoing<B>,
+    idents: &'a McIdents,
     mutual_credits: &'a ImHashMap<Currency, MutualCredit>,
     active_currencies: &'a ActiveCurrencies,
 }
@@ -108,13 +111,57 @@ pub enum TcDirectionBorrow<'a, B> {
 }
 
 #[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(from = "StoredTokenChannel<B>")]
 pub struct TokenChannel<B> {
     direction: TcDirection<B>,
+    /// The current public keys of both sides.
+    /// Move tokens exchanged before one of the sides has rotated its key still contain the old key.
+    idents: McIdents,
     #[serde(with = "ser_map_str_any")]
     mutual_credits: ImHashMap<Currency, MutualCredit>,
     active_currencies: ActiveCurrencies,
 }
 
+/// A stored `TokenChannel`. Token channels stored before key rotation was introduced have no
+/// `idents`. Their public keys are taken from the last move token instead.
+#[derive(Deserialize)]
+struct StoredTokenChannel<B> {
+    direction: TcDirection<B>,
+    #[serde(default)]
+    idents: Option<McIdents>,
+    #[serde(with = "ser_map_str_any")]
+    mutual_credits: ImHashMap<Currency, MutualCredit>,
+    active_currencies: ActiveCurrencies,
+}
+
+impl<B> From<StoredTokenChannel<B>> for TokenChannel<B> {
+    fn from(stored: StoredTokenChannel<B>) -> Self {
+        let idents = stored.idents.unwrap_or_else(|| match &stored.direction {
+            TcDirection::Incoming(tc_incoming) => {
+                // The incoming move token was created by the remote side:
+                let mc_info = &tc_incoming.move_token_in.token_info.mc;
+                McIdents {
+                    local_public_key: mc_info.remote_public_key.clone(),
+                    remote_public_key: mc_info.local_public_key.clone(),
+                }
+            }
+            TcDirection::Outgoing(tc_outgoing) => {
+                let mc_info = &tc_outgoing.token_info.mc;
+                McIdents {
+                    local_public_key: mc_info.local_public_key.clone(),
+                    remote_public_key: mc_info.remote_public_key.clone(),
+                }
+            }
+        });
+        TokenChannel {
+            direction: stored.direction,
+            idents,
+            mutual_credits: stored.mutual_credits,
+            active_currencies: stored.active_currencies,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ReceiveMoveTokenError {
     ChainInconsistency,
@@ -218,17 +265,43 @@ fn initial_move_token<B>(
         opt_active_currencies: None,
         info_hash: hash_token_info(&token_info),
         rand_nonce: rand_nonce_from_public_key(&high_public_key),
+        signature_version: LEGACY_SIGNATURE_BUFF_VERSION,
         new_token: token_from_public_key(&high_public_key),
     };
 
     (move_token, token_info)
 }
 
+/// Check if the signature of a move token received from the remote side is valid.
+/// The move token must be signed by the sender stated in its token info. It is up to the caller to
+/// check that the stated keys belong to the two sides of the token channel.
+/// The deterministic initial move token (Which is not signed) is also accepted.
+pub fn verify_incoming_move_token_hashed<B>(move_token_hashed: &MoveTokenHashed) -> bool
+where
+    B: Clone + CanonicalSerialize,
+{
+    let sender_public_key = &move_token_hashed.token_info.mc.local_public_key;
+    let receiver_public_key = &move_token_hashed.token_info.mc.remote_public_key;
+
+    let (initial_move_token, token_info) =
+        initial_move_token::<B>(sender_public_key, receiver_public_key);
+    if &create_hashed(&initial_move_token, &token_info) == move_token_hashed {
+        return true;
+    }
+
+    let move_token_hashed_report = MoveTokenHashedReport::from(move_token_hashed);
+    verify_move_token_hashed_report(&move_token_hashed_report, sender_public_key)
+}
+
 impl<B> TokenChannel<B>
 where
     B: Clone + CanonicalSerialize,
 {
     pub fn new(local_public_key: &PublicKey, remote_public_key: &PublicKey) -> Self {
+        let idents = McIdents {
+            local_public_key: local_public_key.clone(),
+            remote_public_key: remote_public_key.clone(),
+        };
         if compare_public_key(&local_public_key, &remote_public_key) == Ordering::Less {
             // We are the first sender
             let (move_token_out, token_info) =
@@ -240,6 +313,7 @@ where
             };
             TokenChannel {
                 direction: TcDirection::Outgoing(tc_outgoing),
+                idents,
                 mutual_credits: ImHashMap::new(),
                 active_currencies: ActiveCurrencies::new(),
             }
@@ -252,6 +326,7 @@ where
             let tc_incoming = TcIncoming { move_token_in };
             TokenChannel {
                 direction: TcDirection::Incoming(tc_incoming),
+                idents,
                 mutual_credits: ImHashMap::new(),
                 active_currencies: ActiveCurrencies::new(),
             }
@@ -266,6 +341,19 @@ where
         &self.active_currencies
     }
 
+    pub fn get_idents(&self) -> &McIdents {
+        &self.idents
+    }
+
+    /// Replace the public key of one of the sides, after that side has rotated its key.
+    /// Move tokens created from now on contain the new key.
+    pub fn rotate_key(&mut self, old_public_key: &PublicKey, new_public_key: &PublicKey) {
+        self.idents.rotate_key(old_public_key, new_public_key);
+        for mutual_credit in self.mutual_credits.values_mut() {
+            mutual_credit.rotate_key(old_public_key, new_public_key);
+        }
+    }
+
     pub fn new_from_remote_reset(
         reset_move_token: &MoveToken<B>,
         remote_token_info: &TokenInfo,
@@ -299,6 +387,10 @@ where
 
         TokenChannel {
             direction: TcDirection::Incoming(tc_incoming),
+            idents: McIdents {
+                local_public_key: remote_token_info.mc.remote_public_key.clone(),
+                remote_public_key: remote_token_info.mc.local_public_key.clone(),
+            },
             mutual_credits,
             active_currencies: ActiveCurrencies {
                 local: active_currencies.clone(),
@@ -338,6 +430,10 @@ where
 
         TokenChannel {
             direction: TcDirection::Outgoing(tc_outgoing),
+            idents: McIdents {
+                local_public_key: token_info.mc.local_public_key.clone(),
+                remote_public_key: token_info.mc.remote_public_key.clone(),
+            },
             mutual_credits,
             active_currencies: ActiveCurrencies {
                 local: active_currencies.clone(),
@@ -361,11 +457,13 @@ where
         match &self.direction {
             TcDirection::Incoming(tc_incoming) => TcDirectionBorrow::In(TcInBorrow {
                 tc_incoming: &tc_incoming,
+                idents: &self.idents,
                 mutual_credits: &self.mutual_credits,
                 active_currencies: &self.active_currencies,
             }),
             TcDirection::Outgoing(tc_outgoing) => TcDirectionBorrow::Out(TcOutBorrow {
                 tc_outgoing: &tc_outgoing,
+                idents: &self.idents,
                 mutual_credits: &self.mutual_credits,
                 active_currencies: &self.active_currencies,
             }),
@@ -421,17 +519,10 @@ where
                 assert!(self.active_currencies.local.contains(currency));
                 assert!(self.active_currencies.remote.contains(currency));
 
-                let token_info = match &self.direction {
-                    TcDirection::Incoming(tc_incoming) => {
-                        tc_incoming.move_token_in.token_info.clone().flip()
-                    }
-                    TcDirection::Outgoing(tc_outgoing) => tc_outgoing.token_info.clone(),
-                };
-
                 let balance = 0;
                 let new_mutual_credit = MutualCredit::new(
-                    &token_info.mc.local_public_key,
-                    &token_info.mc.remote_public_key,
+                    &self.idents.local_public_key,
+                    &self.idents.remote_public_key,
                     currency,
                     balance,
                 );
@@ -492,6 +583,7 @@ impl<'a> TcInBorrow<'a> {
     fn create_token_channel<B>(&self) -> TokenChannel<B> {
         TokenChannel {
             direction: TcDirection::Incoming(self.tc_incoming.clone()),
+            idents: self.idents.clone(),
             mutual_credits: self.mutual_credits.clone(),
             active_currencies: self.active_currencies.clone(),
         }
@@ -524,6 +616,7 @@ impl<'a> TcInBorrow<'a> {
         opt_local_relays: Option<Vec<RelayAddress<B>>>,
         opt_active_currencies: Option<Vec<Currency>>,
         rand_nonce: RandValue,
+        signature_version: u32,
     ) -> Result<SendMoveTokenOutput<B>, SendMoveTokenError>
     where
         B: CanonicalSerialize + Clone,
@@ -615,8 +708,8 @@ impl<'a> TcInBorrow<'a> {
 
         let token_info = TokenInfo {
             mc: McInfo {
-                local_public_key: cur_token_info.mc.remote_public_key.clone(),
-                remote_public_key: cur_token_info.mc.local_public_key.clone(),
+                local_public_key: tc_in_borrow.idents.local_public_key.clone(),
+                remote_public_key: tc_in_borrow.idents.remote_public_key.clone(),
                 balances,
             },
             counters: CountersInfo {
@@ -632,6 +725,7 @@ impl<'a> TcInBorrow<'a> {
             &token_info,
             tc_in_borrow.tc_incoming.move_token_in.new_token.clone(),
             rand_nonce,
+            signature_version,
         );
 
         Ok(SendMoveTokenOutput {
@@ -675,6 +769,7 @@ where
     fn create_token_channel(&self) -> TokenChannel<B> {
         TokenChannel {
             direction: TcDirection::Outgoing(self.tc_outgoing.clone()),
+            idents: self.idents.clone(),
             mutual_credits: self.mutual_credits.clone(),
             active_currencies: self.active_currencies.clone(),
         }
@@ -694,8 +789,10 @@ where
         // Note that we only verify the signature here, and not at the Incoming part.
         // This allows the genesis move token to occur smoothly, even though its signature
         // is not correct.
-        let remote_public_key = &tc_out_borrow.tc_outgoing.token_info.mc.remote_public_key;
-        if !verify_move_token(new_move_token.clone(), remote_public_key) {
+        if !verify_move_token(
+            new_move_token.clone(),
+            &tc_out_borrow.idents.remote_public_key,
+        ) {
             return Err(ReceiveMoveTokenError::InvalidSignature);
         }
 
@@ -816,18 +913,8 @@ where
 
         let expected_token_info = TokenInfo {
             mc: McInfo {
-                local_public_key: tc_out_borrow
-                    .tc_outgoing
-                    .token_info
-                    .mc
-                    .local_public_key
-                    .clone(),
-                remote_public_key: tc_out_borrow
-                    .tc_outgoing
-                    .token_info
-                    .mc
-                    .remote_public_key
-                    .clone(),
+                local_public_key: tc_out_borrow.idents.local_public_key.clone(),
+                remote_public_key: tc_out_borrow.idents.remote_public_key.clone(),
                 balances: expected_balances,
             },
             counters: CountersInfo {
@@ -880,7 +967,7 @@ mod tests {
     use crypto::rand::RandGen;
     use crypto::test_utils::DummyRandom;
 
-    use signature::signature_buff::move_token_signature_buff;
+    use signature::signature_buff::{move_token_signature_buff, SIGNATURE_BUFF_VERSION};
 
     /// A helper function to sign an UnsignedMoveToken using an identity:
     fn dummy_sign_move_token<B, I>(
@@ -900,10 +987,28 @@ mod tests {
             opt_active_currencies: unsigned_move_token.opt_active_currencies,
             info_hash: unsigned_move_token.info_hash,
             rand_nonce: unsigned_move_token.rand_nonce,
+            signature_version: unsigned_move_token.signature_version,
             new_token: identity.sign(&signature_buff),
         }
     }
 
+    #[test]
+    fn test_token_channel_deserialize_without_idents() {
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+
+        // One of the token channels is incoming, and the other is outgoing:
+        for token_channel in &[
+            TokenChannel::<u32>::new(&pk_a, &pk_b),
+            TokenChannel::<u32>::new(&pk_b, &pk_a),
+        ] {
+            let mut value = serde_json::to_value(token_channel).unwrap();
+            assert!(value.as_object_mut().unwrap().remove("idents").is_some());
+            let baseline_token_channel: TokenChannel<u32> = serde_json::from_value(value).unwrap();
+            assert_eq!(&baseline_token_channel, token_channel);
+        }
+    }
+
     #[test]
     fn test_initial_direction() {
         let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
@@ -993,6 +1098,7 @@ mod tests {
                 opt_local_relays,
                 opt_active_currencies,
                 rand_nonce,
+                SIGNATURE_BUFF_VERSION,
             )
             .unwrap();
 
@@ -1088,6 +1194,7 @@ mod tests {
                 opt_local_relays,
                 opt_active_currencies,
                 rand_nonce,
+                SIGNATURE_BUFF_VERSION,
             )
             .unwrap();
 
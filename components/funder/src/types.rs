@@ -7,53 +7,61 @@ use proto::crypto::{HashResult, HashedLock, PublicKey, RandValue, Signature, Uid
 use proto::app_server::messages::RelayAddress;
 use proto::funder::messages::{
     CancelSendFundsOp, ChannelerUpdateFriend, Currency, CurrencyOperations, FriendMessage,
-    FunderIncomingControl, FunderOutgoingControl, MoveToken, PendingTransaction,
-    RequestSendFundsOp, ResponseSendFundsOp, TokenInfo, TransactionStage, UnsignedMoveToken,
-    UnsignedResponseSendFundsOp,
+    FunderIncomingControl, FunderOutgoingControl, MessageDelivery, MoveToken, PendingTransaction,
+    RefundSendFundsOp, RequestSendFundsOp, ResponseSendFundsOp, TokenInfo, TransactionStage,
+    UnsignedMoveToken, UnsignedResponseSendFundsOp,
 };
+use proto::report::messages::BandwidthReport;
+use proto::secure_channel::messages::SecureChannelReport;
 
 use signature::signature_buff::{
-    create_response_signature_buffer, hash_token_info, move_token_signature_buff, prefix_hash,
+    create_response_signature_buffer, hash_token_info, move_token_signature_buff_into, prefix_hash,
 };
 
-use identity::IdentityClient;
+use identity::{SignatureBackend, SignatureBackendError};
 
-pub async fn sign_move_token<'a, B>(
+pub async fn sign_move_token<'a, B, SB>(
     unsigned_move_token: UnsignedMoveToken<B>,
-    identity_client: &'a mut IdentityClient,
-) -> MoveToken<B>
+    identity_client: &'a mut SB,
+) -> Result<MoveToken<B>, SignatureBackendError>
 where
     B: CanonicalSerialize + Clone + 'a,
+    SB: SignatureBackend,
 {
-    let signature_buff = move_token_signature_buff(unsigned_move_token.clone());
-    let new_token = identity_client
-        .request_signature(signature_buff)
-        .await
-        .unwrap();
+    // Building the signature buffer from a reference avoids cloning the whole move token:
+    let mut signature_buff = Vec::new();
+    move_token_signature_buff_into(&unsigned_move_token, &mut signature_buff);
+    let new_token = identity_client.request_signature(signature_buff).await?;
 
-    MoveToken {
+    Ok(MoveToken {
         old_token: unsigned_move_token.old_token,
         currencies_operations: unsigned_move_token.currencies_operations,
         opt_local_relays: unsigned_move_token.opt_local_relays,
         opt_active_currencies: unsigned_move_token.opt_active_currencies,
         info_hash: unsigned_move_token.info_hash,
         rand_nonce: unsigned_move_token.rand_nonce,
+        signature_version: unsigned_move_token.signature_version,
         new_token,
-    }
+    })
 }
 
-pub async fn create_response_send_funds<'a>(
+pub async fn create_response_send_funds<'a, SB>(
     currency: &Currency,
     pending_transaction: &'a PendingTransaction,
     dest_hashed_lock: HashedLock,
     is_complete: bool,
+    change: u128,
     rand_nonce: RandValue,
-    identity_client: &'a mut IdentityClient,
-) -> ResponseSendFundsOp {
+    identity_client: &'a mut SB,
+) -> Result<ResponseSendFundsOp, SignatureBackendError>
+where
+    SB: SignatureBackend,
+{
     let u_response_send_funds = UnsignedResponseSendFundsOp {
         request_id: pending_transaction.request_id.clone(),
         dest_hashed_lock,
         is_complete,
+        change,
         rand_nonce,
     };
 
@@ -62,24 +70,26 @@ pub async fn create_response_send_funds<'a>(
         u_response_send_funds.clone(),
         pending_transaction,
     );
-    let signature = identity_client
-        .request_signature(signature_buff)
-        .await
-        .unwrap();
+    let signature = identity_client.request_signature(signature_buff).await?;
 
-    ResponseSendFundsOp {
+    Ok(ResponseSendFundsOp {
         request_id: u_response_send_funds.request_id,
         dest_hashed_lock: u_response_send_funds.dest_hashed_lock,
         is_complete: u_response_send_funds.is_complete,
+        change: u_response_send_funds.change,
         rand_nonce: u_response_send_funds.rand_nonce,
         signature,
-    }
+    })
 }
 
 pub fn create_cancel_send_funds(request_id: Uid) -> CancelSendFundsOp {
     CancelSendFundsOp { request_id }
 }
 
+pub fn create_refund_send_funds(request_id: Uid) -> RefundSendFundsOp {
+    RefundSendFundsOp { request_id }
+}
+
 pub fn create_pending_transaction(request_send_funds: &RequestSendFundsOp) -> PendingTransaction {
     PendingTransaction {
         request_id: request_send_funds.request_id.clone(),
@@ -89,10 +99,29 @@ pub fn create_pending_transaction(request_send_funds: &RequestSendFundsOp) -> Pe
         invoice_id: request_send_funds.invoice_id.clone(),
         left_fees: request_send_funds.left_fees,
         src_hashed_lock: request_send_funds.src_hashed_lock.clone(),
+        expiry_ticks: request_send_funds.expiry_ticks,
+        refund_ticks: request_send_funds.refund_ticks,
+        opt_exchange: request_send_funds.opt_exchange.clone(),
         stage: TransactionStage::Request,
     }
 }
 
+/// Recreate the original request from a pending transaction.
+pub fn create_request_send_funds(pending_transaction: &PendingTransaction) -> RequestSendFundsOp {
+    RequestSendFundsOp {
+        request_id: pending_transaction.request_id.clone(),
+        src_hashed_lock: pending_transaction.src_hashed_lock.clone(),
+        route: pending_transaction.route.clone(),
+        dest_payment: pending_transaction.dest_payment,
+        total_dest_payment: pending_transaction.total_dest_payment,
+        invoice_id: pending_transaction.invoice_id.clone(),
+        left_fees: pending_transaction.left_fees,
+        expiry_ticks: pending_transaction.expiry_ticks,
+        refund_ticks: pending_transaction.refund_ticks,
+        opt_exchange: pending_transaction.opt_exchange.clone(),
+    }
+}
+
 /*
 #[derive(Arbitrary, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub enum UnsignedFriendTcOp {
@@ -126,6 +155,7 @@ pub fn create_unsigned_move_token<B>(
     token_info: &TokenInfo,
     old_token: Signature,
     rand_nonce: RandValue,
+    signature_version: u32,
 ) -> UnsignedMoveToken<B> {
     UnsignedMoveToken {
         old_token,
@@ -134,6 +164,7 @@ pub fn create_unsigned_move_token<B>(
         opt_active_currencies,
         info_hash: hash_token_info(token_info),
         rand_nonce,
+        signature_version,
     }
 }
 
@@ -190,6 +221,21 @@ where
 pub enum IncomingLivenessMessage {
     Online(PublicKey),
     Offline(PublicKey),
+    /// Amount of consecutive keepalive beats missed by an online friend
+    MissedBeats((PublicKey, u64)),
+    /// A diagnostic event reported by the secure channel layer of a connection to a friend
+    SecureChannelReport((PublicKey, SecureChannelReport)),
+    /// Bytes exchanged with a friend since the previous bandwidth report of the friend
+    Bandwidth((PublicKey, BandwidthReport)),
+    /// Latest latency measurement (In milliseconds) of a relay, identified by its public key.
+    /// None if the relay could not be reached.
+    RelayLatency((PublicKey, Option<u64>)),
+    /// Amount of messages waiting to be sent to a friend
+    QueueDepth((PublicKey, usize)),
+    /// The fate of a message sent to a friend, identified by its message id
+    MessageDelivery((PublicKey, Uid, MessageDelivery)),
+    /// We only accept connections from friends, and never connect to them
+    ListenOnly,
 }
 
 pub struct FriendInconsistencyError {
@@ -219,6 +265,7 @@ pub enum FunderIncomingComm<B> {
 #[derive(Clone, Debug)]
 pub enum FunderIncoming<B> {
     Init,
+    TimerTick,
     Control(FunderIncomingControl<B>),
     Comm(FunderIncomingComm<B>),
 }
@@ -236,6 +283,7 @@ where
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum FunderOutgoingComm<B> {
-    FriendMessage((PublicKey, FriendMessage<B>)),
+    /// (friend_public_key, message_id, friend_message)
+    FriendMessage((PublicKey, Uid, FriendMessage<B>)),
     ChannelerConfig(ChannelerConfig<RelayAddress<B>>),
 }
@@ -0,0 +1,476 @@
+use common::safe_arithmetic::SafeSignedArithmetic;
+
+use signature::canonical::CanonicalSerialize;
+use signature::signature_buff::hash_token_info;
+use signature::verify::verify_key_rotation;
+
+use proto::crypto::PublicKey;
+use proto::funder::messages::{Currency, KeyRotation, PendingTransaction};
+
+use crate::friend::{ChannelStatus, FriendState};
+use crate::mutual_credit::types::MutualCredit;
+use crate::state::FunderState;
+use crate::token_channel::{verify_incoming_move_token_hashed, TokenChannel};
+use crate::types::create_hashed;
+
+#[derive(Debug)]
+pub enum VerifyStateError {
+    /// Local or remote public keys stored for a friend do not match the funder state.
+    FriendKeysMismatch(PublicKey),
+    /// The last incoming move token from a friend is not signed by the friend.
+    InvalidMoveTokenSignature(PublicKey),
+    /// Mutual credit balance with a friend can not be reported without overflowing.
+    BalanceOverflow((PublicKey, Currency)),
+    /// The info hash of the last outgoing move token does not match the stored token info.
+    InvalidTokenInfoHash(PublicKey),
+    /// The last outgoing move token is not signed by the local side.
+    InvalidOutgoingMoveTokenSignature(PublicKey),
+    /// The last outgoing move token does not continue the last incoming move token.
+    BrokenTokenChain(PublicKey),
+    /// Frozen credits do not add up to the pending transactions of a mutual credit.
+    PendingDebtMismatch((PublicKey, Currency)),
+}
+
+/// Check that a public key found inside a move token is `public_key`, or the previous key of
+/// `public_key` according to a verified key rotation.
+fn is_same_identity(
+    token_public_key: &PublicKey,
+    public_key: &PublicKey,
+    opt_key_rotation: &Option<KeyRotation>,
+) -> bool {
+    if token_public_key == public_key {
+        return true;
+    }
+    match opt_key_rotation {
+        Some(key_rotation) => {
+            &key_rotation.old_public_key == token_public_key
+                && &key_rotation.new_public_key == public_key
+                && verify_key_rotation(key_rotation)
+        }
+        None => false,
+    }
+}
+
+/// Sum the credits frozen by a set of pending transactions.
+/// Returns None on overflow.
+fn sum_frozen_credits<'a>(
+    pending_transactions: impl Iterator<Item = &'a PendingTransaction>,
+) -> Option<u128> {
+    let mut sum: u128 = 0;
+    for pending_transaction in pending_transactions {
+        sum = sum.checked_add(
+            pending_transaction
+                .dest_payment
+                .checked_add(pending_transaction.left_fees)?,
+        )?;
+    }
+    Some(sum)
+}
+
+/// Check the consistency of the last outgoing move token of a token channel, if we hold one.
+fn check_outgoing_move_token<B>(
+    funder_state: &FunderState<B>,
+    friend_public_key: &PublicKey,
+    token_channel: &TokenChannel<B>,
+    issues: &mut Vec<VerifyStateError>,
+) where
+    B: Clone + CanonicalSerialize,
+{
+    let tc_outgoing = match token_channel.get_outgoing() {
+        Some(tc_out_borrow) => tc_out_borrow.tc_outgoing,
+        None => return,
+    };
+
+    let move_token_out = &tc_outgoing.move_token_out;
+    let token_info = &tc_outgoing.token_info;
+    if move_token_out.info_hash != hash_token_info(token_info) {
+        issues.push(VerifyStateError::InvalidTokenInfoHash(
+            friend_public_key.clone(),
+        ));
+    }
+
+    // The move token might have been created before we rotated our key:
+    if !is_same_identity(
+        &token_info.mc.local_public_key,
+        &funder_state.local_public_key,
+        &funder_state.opt_key_rotation,
+    ) || !verify_incoming_move_token_hashed::<B>(&create_hashed(move_token_out, token_info))
+    {
+        issues.push(VerifyStateError::InvalidOutgoingMoveTokenSignature(
+            friend_public_key.clone(),
+        ));
+    }
+
+    // A reset starts a new chain of move tokens, with a larger inconsistency counter:
+    if let Some(prev_move_token_in) = &tc_outgoing.opt_prev_move_token_in {
+        if prev_move_token_in.token_info.counters.inconsistency_counter
+            == token_info.counters.inconsistency_counter
+            && move_token_out.old_token != prev_move_token_in.new_token
+        {
+            issues.push(VerifyStateError::BrokenTokenChain(
+                friend_public_key.clone(),
+            ));
+        }
+    }
+}
+
+/// Check the bookkeeping of a single mutual credit.
+fn check_mutual_credit<B>(
+    friend_public_key: &PublicKey,
+    friend: &FriendState<B>,
+    currency: &Currency,
+    mutual_credit: &MutualCredit,
+    issues: &mut Vec<VerifyStateError>,
+) where
+    B: Clone,
+{
+    let mc_state = mutual_credit.state();
+    if mc_state.idents.local_public_key != friend.local_public_key
+        || mc_state.idents.remote_public_key != friend.remote_public_key
+    {
+        issues.push(VerifyStateError::FriendKeysMismatch(
+            friend_public_key.clone(),
+        ));
+    }
+
+    // Make sure that reports could be created for this balance:
+    let balance = &mc_state.balance;
+    if balance
+        .balance
+        .checked_add_unsigned(balance.remote_pending_debt)
+        .is_none()
+        || balance
+            .balance
+            .checked_sub_unsigned(balance.local_pending_debt)
+            .is_none()
+    {
+        issues.push(VerifyStateError::BalanceOverflow((
+            friend_public_key.clone(),
+            currency.clone(),
+        )));
+    }
+
+    // Every pending transaction freezes its payment and fees:
+    let pending_transactions = &mc_state.pending_transactions;
+    if sum_frozen_credits(pending_transactions.local.values()) != Some(balance.local_pending_debt)
+        || sum_frozen_credits(pending_transactions.remote.values())
+            != Some(balance.remote_pending_debt)
+    {
+        issues.push(VerifyStateError::PendingDebtMismatch((
+            friend_public_key.clone(),
+            currency.clone(),
+        )));
+    }
+}
+
+/// Check a single friend of the funder state, appending all the problems found to `issues`.
+fn check_friend<B>(
+    funder_state: &FunderState<B>,
+    friend_public_key: &PublicKey,
+    friend: &FriendState<B>,
+    issues: &mut Vec<VerifyStateError>,
+) where
+    B: Clone + CanonicalSerialize,
+{
+    if friend.local_public_key != funder_state.local_public_key
+        || &friend.remote_public_key != friend_public_key
+    {
+        // Other checks are meaningless if we don't know who the friend is:
+        issues.push(VerifyStateError::FriendKeysMismatch(
+            friend_public_key.clone(),
+        ));
+        return;
+    }
+
+    if let Some(move_token_hashed) = friend.channel_status.get_last_incoming_move_token_hashed() {
+        // The move token might have been created before one of the sides rotated its key:
+        let token_mc = &move_token_hashed.token_info.mc;
+        if !is_same_identity(
+            &token_mc.local_public_key,
+            &friend.remote_public_key,
+            &friend.opt_remote_key_rotation,
+        ) || !is_same_identity(
+            &token_mc.remote_public_key,
+            &friend.local_public_key,
+            &funder_state.opt_key_rotation,
+        ) {
+            issues.push(VerifyStateError::FriendKeysMismatch(
+                friend_public_key.clone(),
+            ));
+        } else if !verify_incoming_move_token_hashed::<B>(&move_token_hashed) {
+            issues.push(VerifyStateError::InvalidMoveTokenSignature(
+                friend_public_key.clone(),
+            ));
+        }
+    }
+
+    let channel_consistent = match &friend.channel_status {
+        ChannelStatus::Consistent(channel_consistent) => channel_consistent,
+        ChannelStatus::Inconsistent(_) => return,
+    };
+    let token_channel = &channel_consistent.token_channel;
+
+    let idents = token_channel.get_idents();
+    if idents.local_public_key != friend.local_public_key
+        || idents.remote_public_key != friend.remote_public_key
+    {
+        issues.push(VerifyStateError::FriendKeysMismatch(
+            friend_public_key.clone(),
+        ));
+        return;
+    }
+
+    check_outgoing_move_token(funder_state, friend_public_key, token_channel, issues);
+
+    for (currency, mutual_credit) in token_channel.get_mutual_credits() {
+        check_mutual_credit(friend_public_key, friend, currency, mutual_credit, issues);
+    }
+}
+
+/// Find all the inconsistencies of a funder state loaded from the database.
+/// Recomputes the hashes of the token channels, verifies the signatures of the last move tokens
+/// and checks that the frozen credits match the pending transactions.
+pub fn check_funder_state<B>(funder_state: &FunderState<B>) -> Vec<VerifyStateError>
+where
+    B: Clone + CanonicalSerialize,
+{
+    let mut issues = Vec::new();
+    for (friend_public_key, friend) in &funder_state.friends {
+        check_friend(funder_state, friend_public_key, friend, &mut issues);
+    }
+    issues
+}
+
+/// Verify the consistency of a funder state loaded from the database.
+/// This allows to refuse starting a node with a corrupt state, instead of
+/// corrupting channels with remote friends at runtime.
+pub fn verify_funder_state<B>(funder_state: &FunderState<B>) -> Result<(), VerifyStateError>
+where
+    B: Clone + CanonicalSerialize,
+{
+    match check_funder_state(funder_state).into_iter().next() {
+        Some(issue) => Err(issue),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cmp::Ordering;
+
+    use crypto::identity::{compare_public_key, Identity, SoftwareEd25519Identity};
+    use crypto::rand::RandGen;
+    use crypto::test_utils::DummyRandom;
+
+    use proto::crypto::{PrivateKey, Signature};
+
+    use proto::funder::messages::{AddFriend, ResetTerms};
+    use proto::report::messages::DisputeEvidence;
+    use signature::key_rotation::create_key_rotation;
+
+    use std::convert::TryFrom;
+
+    use crate::friend::{ChannelInconsistent, FriendMutation};
+    use crate::mutual_credit::types::McMutation;
+    use crate::state::FunderMutation;
+    use crate::token_channel::{SetDirection, TcMutation};
+
+    /// Create a funder state with one friend, where the local side
+    /// holds the (initial) incoming move token.
+    fn create_funder_state() -> (FunderState<u32>, PublicKey) {
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let (local_pk, remote_pk) = if compare_public_key(&pk_a, &pk_b) == Ordering::Less {
+            (pk_b, pk_a)
+        } else {
+            (pk_a, pk_b)
+        };
+
+        let mut funder_state = FunderState::<u32>::new(local_pk, Vec::new());
+        let add_friend = AddFriend {
+            friend_public_key: remote_pk.clone(),
+            relays: Vec::new(),
+            name: "remote".into(),
+        };
+        funder_state.mutate(&FunderMutation::AddFriend(add_friend));
+        (funder_state, remote_pk)
+    }
+
+    #[test]
+    fn test_verify_funder_state_valid() {
+        let (funder_state, _remote_pk) = create_funder_state();
+        verify_funder_state(&funder_state).unwrap();
+    }
+
+    #[test]
+    fn test_verify_funder_state_keys_mismatch() {
+        let (mut funder_state, remote_pk) = create_funder_state();
+        let friend = funder_state.friends.get_mut(&remote_pk).unwrap();
+        friend.local_public_key = PublicKey::from(&[0xcc; PublicKey::len()]);
+
+        match verify_funder_state(&funder_state) {
+            Err(VerifyStateError::FriendKeysMismatch(public_key)) => {
+                assert_eq!(public_key, remote_pk)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_verify_funder_state_invalid_signature() {
+        let (mut funder_state, remote_pk) = create_funder_state();
+        let friend = funder_state.friends.get_mut(&remote_pk).unwrap();
+        let mut move_token_hashed = friend
+            .channel_status
+            .get_last_incoming_move_token_hashed()
+            .unwrap();
+        // Tamper with the last incoming move token:
+        move_token_hashed.token_info.counters.move_token_counter += 1;
+
+        let channel_inconsistent = ChannelInconsistent {
+            opt_last_incoming_move_token: Some(move_token_hashed),
+            local_reset_terms: ResetTerms {
+                reset_token: Signature::from(&[0; Signature::len()]),
+                inconsistency_counter: 1,
+                balance_for_reset: Vec::new(),
+            },
+            opt_remote_reset_terms: None,
+            dispute_evidence: DisputeEvidence {
+                channel_proof: Vec::new(),
+                currency_reports: Vec::new(),
+                pending_transactions: Vec::new(),
+            },
+        };
+        friend.channel_status = ChannelStatus::Inconsistent(channel_inconsistent);
+
+        match verify_funder_state(&funder_state) {
+            Err(VerifyStateError::InvalidMoveTokenSignature(public_key)) => {
+                assert_eq!(public_key, remote_pk)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_verify_funder_state_friend_key_rotation() {
+        let create_identity = |seed: u8| {
+            let rng = DummyRandom::new(&[seed]);
+            let private_key = PrivateKey::rand_gen(&rng);
+            SoftwareEd25519Identity::from_private_key(&private_key).unwrap()
+        };
+        let old_identity = create_identity(1);
+        let new_identity = create_identity(2);
+
+        // The local side holds the initial incoming move token, signed with the old key:
+        let local_pk = PublicKey::from(&[0xff; PublicKey::len()]);
+        let mut funder_state = FunderState::<u32>::new(local_pk, Vec::new());
+        let add_friend = AddFriend {
+            friend_public_key: old_identity.get_public_key(),
+            relays: Vec::new(),
+            name: "remote".into(),
+        };
+        funder_state.mutate(&FunderMutation::AddFriend(add_friend));
+
+        let key_rotation = create_key_rotation(&old_identity, &new_identity);
+        funder_state.mutate(&FunderMutation::RotateFriendKey(key_rotation));
+        assert!(!funder_state
+            .friends
+            .contains_key(&old_identity.get_public_key()));
+        verify_funder_state(&funder_state).unwrap();
+
+        // Without the key rotation, the incoming move token was not sent by the friend:
+        let new_pk = new_identity.get_public_key();
+        let friend = funder_state.friends.get_mut(&new_pk).unwrap();
+        friend.opt_remote_key_rotation = None;
+        match verify_funder_state(&funder_state) {
+            Err(VerifyStateError::FriendKeysMismatch(public_key)) => assert_eq!(public_key, new_pk),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_check_funder_state_pending_debt_mismatch() {
+        let (mut funder_state, remote_pk) = create_funder_state();
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        for tc_mutation in vec![
+            TcMutation::SetLocalActiveCurrencies(vec![currency.clone()]),
+            TcMutation::SetRemoteActiveCurrencies(vec![currency.clone()]),
+            TcMutation::AddMutualCredit(currency.clone()),
+            // Frozen credits without any pending transaction:
+            TcMutation::McMutation((currency.clone(), McMutation::SetLocalPendingDebt(10))),
+        ] {
+            funder_state.mutate(&FunderMutation::FriendMutation((
+                remote_pk.clone(),
+                FriendMutation::TcMutation(tc_mutation),
+            )));
+        }
+
+        let issues = check_funder_state(&funder_state);
+        assert_eq!(issues.len(), 1);
+        match &issues[0] {
+            VerifyStateError::PendingDebtMismatch((public_key, issue_currency)) => {
+                assert_eq!(public_key, &remote_pk);
+                assert_eq!(issue_currency, &currency);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_check_funder_state_outgoing_token_info_hash() {
+        // The local side is the first sender, holding the (initial) outgoing move token:
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let (local_pk, remote_pk) = if compare_public_key(&pk_a, &pk_b) == Ordering::Less {
+            (pk_a, pk_b)
+        } else {
+            (pk_b, pk_a)
+        };
+
+        let mut funder_state = FunderState::<u32>::new(local_pk, Vec::new());
+        let add_friend = AddFriend {
+            friend_public_key: remote_pk.clone(),
+            relays: Vec::new(),
+            name: "remote".into(),
+        };
+        funder_state.mutate(&FunderMutation::AddFriend(add_friend));
+        assert!(check_funder_state(&funder_state).is_empty());
+
+        let friend = funder_state.friends.get(&remote_pk).unwrap();
+        let tc_outgoing = match &friend.channel_status {
+            ChannelStatus::Consistent(channel_consistent) => channel_consistent
+                .token_channel
+                .get_outgoing()
+                .unwrap()
+                .tc_outgoing
+                .clone(),
+            ChannelStatus::Inconsistent(_) => unreachable!(),
+        };
+        // Tamper with the stored token info:
+        let mut token_info = tc_outgoing.token_info.clone();
+        token_info.counters.move_token_counter += 1;
+        let set_direction = SetDirection::Outgoing((tc_outgoing.move_token_out, token_info));
+        funder_state.mutate(&FunderMutation::FriendMutation((
+            remote_pk.clone(),
+            FriendMutation::TcMutation(TcMutation::SetDirection(set_direction)),
+        )));
+
+        // All the problems are reported:
+        let issues = check_funder_state(&funder_state);
+        assert_eq!(issues.len(), 2);
+        match &issues[0] {
+            VerifyStateError::InvalidTokenInfoHash(public_key) => {
+                assert_eq!(public_key, &remote_pk)
+            }
+            _ => unreachable!(),
+        }
+        match &issues[1] {
+            VerifyStateError::InvalidOutgoingMoveTokenSignature(public_key) => {
+                assert_eq!(public_key, &remote_pk)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,436 @@
+use std::convert::TryFrom;
+
+use futures::{future, SinkExt, StreamExt};
+
+use common::conn::{BoxFuture, ConnPairVec};
+
+use crypto::derive::{derive_private_key, DerivationPath, Seed};
+use crypto::identity::{Identity, SoftwareEd25519Identity};
+use proto::crypto::{PublicKey, Signature};
+
+use crate::client::IdentityClient;
+
+#[derive(Debug)]
+pub enum SignatureBackendError {
+    /// The backend could not be reached (Closed service, disconnected device or process).
+    Unavailable,
+    /// The backend failed to produce a signature.
+    SignFailed,
+    /// The backend can not derive keys.
+    DerivationUnsupported,
+}
+
+/// A holder of the local private key, capable of signing messages.
+/// The private key itself never has to leave the backend. This allows the signatures to be
+/// calculated in software, by a hardware security module or by an external signer process.
+///
+/// There is no built in PKCS#11 backend: Hardware security modules are reached through an
+/// external signer process (See `ExternalSignerBackend`), so that the node does not have to load
+/// a vendor library into its own process.
+pub trait SignatureBackend {
+    /// Get the public key matching the backend's private key.
+    fn request_public_key(&mut self) -> BoxFuture<'_, Result<PublicKey, SignatureBackendError>>;
+
+    /// Sign a message using the backend's private key.
+    fn request_signature(
+        &mut self,
+        message: Vec<u8>,
+    ) -> BoxFuture<'_, Result<Signature, SignatureBackendError>>;
+
+    /// Get the public key derived at `path`.
+    fn request_derived_public_key(
+        &mut self,
+        _path: DerivationPath,
+    ) -> BoxFuture<'_, Result<PublicKey, SignatureBackendError>> {
+        Box::pin(future::ready(Err(
+            SignatureBackendError::DerivationUnsupported,
+        )))
+    }
+
+    /// Sign a message using the private key derived at `path`.
+    fn request_derived_signature(
+        &mut self,
+        _path: DerivationPath,
+        _message: Vec<u8>,
+    ) -> BoxFuture<'_, Result<Signature, SignatureBackendError>> {
+        Box::pin(future::ready(Err(
+            SignatureBackendError::DerivationUnsupported,
+        )))
+    }
+}
+
+/// A signature backend that keeps the private key in memory (Ring Ed25519).
+pub struct SoftwareBackend<I> {
+    identity: I,
+}
+
+impl<I> SoftwareBackend<I> {
+    pub fn new(identity: I) -> Self {
+        SoftwareBackend { identity }
+    }
+}
+
+impl<I> SignatureBackend for SoftwareBackend<I>
+where
+    I: Identity,
+{
+    fn request_public_key(&mut self) -> BoxFuture<'_, Result<PublicKey, SignatureBackendError>> {
+        Box::pin(future::ready(Ok(self.identity.get_public_key())))
+    }
+
+    fn request_signature(
+        &mut self,
+        message: Vec<u8>,
+    ) -> BoxFuture<'_, Result<Signature, SignatureBackendError>> {
+        Box::pin(future::ready(Ok(self.identity.sign(&message))))
+    }
+}
+
+/// A signature backend that derives all of its keys from a single seed.
+/// The node identity is the key at `DerivationPath::node_identity()`.
+pub struct SeedBackend {
+    seed: Seed,
+}
+
+impl SeedBackend {
+    pub fn new(seed: Seed) -> Self {
+        SeedBackend { seed }
+    }
+
+    fn derived_identity(
+        &self,
+        path: &DerivationPath,
+    ) -> Result<SoftwareEd25519Identity, SignatureBackendError> {
+        let private_key = derive_private_key(&self.seed, path);
+        SoftwareEd25519Identity::from_private_key(&private_key)
+            .map_err(|_| SignatureBackendError::SignFailed)
+    }
+}
+
+impl SignatureBackend for SeedBackend {
+    fn request_public_key(&mut self) -> BoxFuture<'_, Result<PublicKey, SignatureBackendError>> {
+        self.request_derived_public_key(DerivationPath::node_identity())
+    }
+
+    fn request_signature(
+        &mut self,
+        message: Vec<u8>,
+    ) -> BoxFuture<'_, Result<Signature, SignatureBackendError>> {
+        self.request_derived_signature(DerivationPath::node_identity(), message)
+    }
+
+    fn request_derived_public_key(
+        &mut self,
+        path: DerivationPath,
+    ) -> BoxFuture<'_, Result<PublicKey, SignatureBackendError>> {
+        let res = self
+            .derived_identity(&path)
+            .map(|identity| identity.get_public_key());
+        Box::pin(future::ready(res))
+    }
+
+    fn request_derived_signature(
+        &mut self,
+        path: DerivationPath,
+        message: Vec<u8>,
+    ) -> BoxFuture<'_, Result<Signature, SignatureBackendError>> {
+        let res = self
+            .derived_identity(&path)
+            .map(|identity| identity.sign(&message));
+        Box::pin(future::ready(res))
+    }
+}
+
+/// Request code of the external signer protocol: Get the public key
+const EXTERNAL_REQUEST_PUBLIC_KEY: u8 = 0;
+/// Request code of the external signer protocol: Sign a message
+const EXTERNAL_REQUEST_SIGNATURE: u8 = 1;
+
+/// A signature backend that delegates signing to an external signer (For example, a separate
+/// process that holds the private key, or a bridge to the PKCS#11 module of a hardware security
+/// module). The signer is reached through a connection of raw messages, and answers every
+/// request with exactly one response, in order:
+///
+/// - `[0]`: Answered with the public key (32 bytes).
+/// - `[1] ++ message`: Answered with the signature over `message` (64 bytes).
+///
+/// A response of any other length (For example, an empty response) means that the signer refused
+/// the request.
+pub struct ExternalSignerBackend {
+    conn_pair: ConnPairVec,
+}
+
+impl ExternalSignerBackend {
+    pub fn new(conn_pair: ConnPairVec) -> Self {
+        ExternalSignerBackend { conn_pair }
+    }
+
+    async fn request(&mut self, request: Vec<u8>) -> Result<Vec<u8>, SignatureBackendError> {
+        self.conn_pair
+            .sender
+            .send(request)
+            .await
+            .map_err(|_| SignatureBackendError::Unavailable)?;
+        self.conn_pair
+            .receiver
+            .next()
+            .await
+            .ok_or(SignatureBackendError::Unavailable)
+    }
+}
+
+impl SignatureBackend for ExternalSignerBackend {
+    fn request_public_key(&mut self) -> BoxFuture<'_, Result<PublicKey, SignatureBackendError>> {
+        Box::pin(async move {
+            let response = self.request(vec![EXTERNAL_REQUEST_PUBLIC_KEY]).await?;
+            PublicKey::try_from(&response[..]).map_err(|_| SignatureBackendError::SignFailed)
+        })
+    }
+
+    fn request_signature(
+        &mut self,
+        message: Vec<u8>,
+    ) -> BoxFuture<'_, Result<Signature, SignatureBackendError>> {
+        Box::pin(async move {
+            let mut request = vec![EXTERNAL_REQUEST_SIGNATURE];
+            request.extend_from_slice(&message);
+            let response = self.request(request).await?;
+            Signature::try_from(&response[..]).map_err(|_| SignatureBackendError::SignFailed)
+        })
+    }
+}
+
+impl SignatureBackend for IdentityClient {
+    fn request_public_key(&mut self) -> BoxFuture<'_, Result<PublicKey, SignatureBackendError>> {
+        let fut = IdentityClient::request_public_key(self);
+        Box::pin(async move { fut.await.map_err(|_| SignatureBackendError::Unavailable) })
+    }
+
+    fn request_signature(
+        &mut self,
+        message: Vec<u8>,
+    ) -> BoxFuture<'_, Result<Signature, SignatureBackendError>> {
+        let fut = IdentityClient::request_signature(self, message);
+        Box::pin(async move { fut.await.map_err(|_| SignatureBackendError::Unavailable) })
+    }
+
+    fn request_derived_public_key(
+        &mut self,
+        path: DerivationPath,
+    ) -> BoxFuture<'_, Result<PublicKey, SignatureBackendError>> {
+        let fut = IdentityClient::request_derived_public_key(self, path);
+        Box::pin(async move { fut.await.map_err(|_| SignatureBackendError::Unavailable) })
+    }
+
+    fn request_derived_signature(
+        &mut self,
+        path: DerivationPath,
+        message: Vec<u8>,
+    ) -> BoxFuture<'_, Result<Signature, SignatureBackendError>> {
+        let fut = IdentityClient::request_derived_signature(self, path, message);
+        Box::pin(async move { fut.await.map_err(|_| SignatureBackendError::Unavailable) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::channel::mpsc;
+    use futures::executor::LocalPool;
+    use futures::task::SpawnExt;
+
+    use crypto::identity::{derive_public_key, verify_signature};
+    use crypto::rand::RandGen;
+    use crypto::test_utils::DummyRandom;
+
+    use proto::crypto::PrivateKey;
+
+    use crate::identity::create_identity_from_backend;
+
+    /// A backend that can not sign messages (For example: a disconnected device)
+    struct FailingBackend;
+
+    impl SignatureBackend for FailingBackend {
+        fn request_public_key(
+            &mut self,
+        ) -> BoxFuture<'_, Result<PublicKey, SignatureBackendError>> {
+            Box::pin(future::ready(Ok(PublicKey::from(
+                &[0xaa; PublicKey::len()],
+            ))))
+        }
+
+        fn request_signature(
+            &mut self,
+            _message: Vec<u8>,
+        ) -> BoxFuture<'_, Result<Signature, SignatureBackendError>> {
+            Box::pin(future::ready(Err(SignatureBackendError::SignFailed)))
+        }
+    }
+
+    /// Sign a message using any signature backend
+    async fn sign_message<SB>(backend: &mut SB, message: &[u8]) -> (PublicKey, Signature)
+    where
+        SB: SignatureBackend,
+    {
+        let public_key = backend.request_public_key().await.unwrap();
+        let signature = backend.request_signature(message.to_vec()).await.unwrap();
+        (public_key, signature)
+    }
+
+    #[test]
+    fn test_identity_client_as_backend() {
+        let secure_rand = DummyRandom::new(&[3u8]);
+        let private_key = PrivateKey::rand_gen(&secure_rand);
+        let identity = SoftwareEd25519Identity::from_private_key(&private_key).unwrap();
+
+        let (requests_sender, identity_fut) =
+            create_identity_from_backend(SoftwareBackend::new(identity));
+        let mut identity_client = IdentityClient::new(requests_sender);
+
+        let mut local_pool = LocalPool::new();
+        local_pool.spawner().spawn(identity_fut).unwrap();
+
+        let my_message = b"This is my message!";
+        let (public_key, signature) =
+            local_pool.run_until(sign_message(&mut identity_client, &my_message[..]));
+        assert!(verify_signature(&my_message[..], &public_key, &signature));
+    }
+
+    #[test]
+    fn test_identity_seed_backend() {
+        let seed = Seed::from_phrase("seed phrase");
+        let (requests_sender, identity_fut) =
+            create_identity_from_backend(SeedBackend::new(seed.clone()));
+        let identity_client = IdentityClient::new(requests_sender);
+
+        let mut local_pool = LocalPool::new();
+        local_pool.spawner().spawn(identity_fut).unwrap();
+
+        // The node identity is derived from the seed:
+        let public_key = local_pool
+            .run_until(identity_client.request_public_key())
+            .unwrap();
+        let node_private_key = derive_private_key(&seed, &DerivationPath::node_identity());
+        assert_eq!(public_key, derive_public_key(&node_private_key).unwrap());
+
+        // Signatures of derived keys are served by derivation path:
+        let app_path = DerivationPath::app_identity(3);
+        let app_public_key = local_pool
+            .run_until(identity_client.request_derived_public_key(app_path.clone()))
+            .unwrap();
+        assert_ne!(app_public_key, public_key);
+
+        let my_message = b"This is my message!";
+        let signature = local_pool
+            .run_until(identity_client.request_derived_signature(app_path, my_message.to_vec()))
+            .unwrap();
+        assert!(verify_signature(
+            &my_message[..],
+            &app_public_key,
+            &signature
+        ));
+        assert!(!verify_signature(&my_message[..], &public_key, &signature));
+    }
+
+    /// An external signer that holds `identity`, and refuses to sign empty messages
+    async fn external_signer<I>(
+        identity: I,
+        mut requests: mpsc::Receiver<Vec<u8>>,
+        mut responses: mpsc::Sender<Vec<u8>>,
+    ) where
+        I: Identity,
+    {
+        while let Some(request) = requests.next().await {
+            let response = match request.split_first() {
+                Some((&EXTERNAL_REQUEST_PUBLIC_KEY, _)) => identity.get_public_key().to_vec(),
+                Some((&EXTERNAL_REQUEST_SIGNATURE, message)) if !message.is_empty() => {
+                    identity.sign(message).to_vec()
+                }
+                _ => Vec::new(),
+            };
+            if responses.send(response).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn test_identity_external_signer_backend() {
+        let secure_rand = DummyRandom::new(&[4u8]);
+        let private_key = PrivateKey::rand_gen(&secure_rand);
+        let identity = SoftwareEd25519Identity::from_private_key(&private_key).unwrap();
+        let expected_public_key = identity.get_public_key();
+
+        let (request_sender, request_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (response_sender, response_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let conn_pair = ConnPairVec::from_raw(request_sender, response_receiver);
+
+        let mut local_pool = LocalPool::new();
+        local_pool
+            .spawner()
+            .spawn(external_signer(identity, request_receiver, response_sender))
+            .unwrap();
+
+        let (requests_sender, identity_fut) =
+            create_identity_from_backend(ExternalSignerBackend::new(conn_pair));
+        let mut identity_client = IdentityClient::new(requests_sender);
+        local_pool.spawner().spawn(identity_fut).unwrap();
+
+        let my_message = b"This is my message!";
+        let (public_key, signature) =
+            local_pool.run_until(sign_message(&mut identity_client, &my_message[..]));
+        assert_eq!(public_key, expected_public_key);
+        assert!(verify_signature(&my_message[..], &public_key, &signature));
+
+        // The signer refuses to sign:
+        assert!(local_pool
+            .run_until(identity_client.request_signature(Vec::new()))
+            .is_err());
+
+        // The connection to the signer stays usable after a refusal:
+        assert!(local_pool
+            .run_until(identity_client.request_signature(my_message.to_vec()))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_external_signer_backend_unavailable() {
+        let (request_sender, request_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (_response_sender, response_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let conn_pair = ConnPairVec::from_raw(request_sender, response_receiver);
+        // The signer is gone:
+        drop(request_receiver);
+
+        let mut backend = ExternalSignerBackend::new(conn_pair);
+        let mut local_pool = LocalPool::new();
+        match local_pool.run_until(backend.request_signature(b"message".to_vec())) {
+            Err(SignatureBackendError::Unavailable) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_identity_failing_backend() {
+        let (requests_sender, identity_fut) = create_identity_from_backend(FailingBackend);
+        let identity_client = IdentityClient::new(requests_sender);
+
+        let mut local_pool = LocalPool::new();
+        local_pool.spawner().spawn(identity_fut).unwrap();
+
+        let public_key = local_pool
+            .run_until(identity_client.request_public_key())
+            .unwrap();
+        assert_eq!(public_key, PublicKey::from(&[0xaa; PublicKey::len()]));
+
+        // The client gets an error instead of a signature:
+        assert!(local_pool
+            .run_until(identity_client.request_signature(b"message".to_vec()))
+            .is_err());
+
+        // The backend can not derive keys:
+        assert!(local_pool
+            .run_until(identity_client.request_derived_public_key(DerivationPath::node_identity()))
+            .is_err());
+    }
+}
@@ -2,6 +2,7 @@ use futures::channel::{mpsc, oneshot};
 use futures::{Future, TryFutureExt};
 
 use common::futures_compat::send_to_sink;
+use crypto::derive::DerivationPath;
 use proto::crypto::{PublicKey, Signature};
 
 use crate::messages::{ResponsePublicKey, ResponseSignature, ToIdentity};
@@ -62,6 +63,38 @@ impl IdentityClient {
         self.request_response(request, rx)
             .map_ok(|response_public_key: ResponsePublicKey| response_public_key.public_key)
     }
+
+    /// Request a signature over a provided message, using the key derived at `path`.
+    /// Fails if the Identity can not derive keys.
+    pub fn request_derived_signature(
+        &self,
+        path: DerivationPath,
+        message: Vec<u8>,
+    ) -> impl Future<Output = Result<Signature, IdentityClientError>> {
+        let (tx, rx) = oneshot::channel::<ResponseSignature>();
+        let request = ToIdentity::RequestDerivedSignature {
+            path,
+            message,
+            response_sender: tx,
+        };
+        self.request_response(request, rx)
+            .map_ok(|response_signature| response_signature.signature)
+    }
+
+    /// Request the public key derived at `path`.
+    /// Fails if the Identity can not derive keys.
+    pub fn request_derived_public_key(
+        &self,
+        path: DerivationPath,
+    ) -> impl Future<Output = Result<PublicKey, IdentityClientError>> {
+        let (tx, rx) = oneshot::channel();
+        let request = ToIdentity::RequestDerivedPublicKey {
+            path,
+            response_sender: tx,
+        };
+        self.request_response(request, rx)
+            .map_ok(|response_public_key: ResponsePublicKey| response_public_key.public_key)
+    }
 }
 
 #[cfg(test)]
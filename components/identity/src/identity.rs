@@ -3,7 +3,8 @@ use futures::prelude::*;
 
 use crypto::identity::Identity;
 
-use super::messages::{ResponsePublicKey, ResponseSignature, ToIdentity};
+use crate::backend::{SignatureBackend, SoftwareBackend};
+use crate::messages::{ResponsePublicKey, ResponseSignature, ToIdentity};
 
 /*
 pub enum IdentityError {
@@ -16,28 +17,54 @@ pub enum IdentityError {
 pub fn create_identity<I: Identity>(
     identity: I,
 ) -> (mpsc::Sender<ToIdentity>, impl Future<Output = ()>) {
-    let (requests_sender, requests_receiver) = mpsc::channel::<ToIdentity>(0);
-    let identity = requests_receiver.for_each(move |request| {
-        match request {
-            ToIdentity::RequestSignature {
-                message,
-                response_sender,
-            } => {
-                let _ = response_sender.send(ResponseSignature {
-                    signature: identity.sign(&message),
-                });
-                // It is possible that sending the response didn't work.
-                // We don't care about this.
-                future::ready(())
-            }
-            ToIdentity::RequestPublicKey { response_sender } => {
-                let _ = response_sender.send(ResponsePublicKey {
-                    public_key: identity.get_public_key(),
-                });
-                future::ready(())
+    create_identity_from_backend(SoftwareBackend::new(identity))
+}
+
+/// Create a new security module that obtains signatures from a signature backend.
+pub fn create_identity_from_backend<SB: SignatureBackend>(
+    mut backend: SB,
+) -> (mpsc::Sender<ToIdentity>, impl Future<Output = ()>) {
+    let (requests_sender, mut requests_receiver) = mpsc::channel::<ToIdentity>(0);
+    let identity = async move {
+        while let Some(request) = requests_receiver.next().await {
+            match request {
+                ToIdentity::RequestSignature {
+                    message,
+                    response_sender,
+                } => {
+                    // If the backend fails, the response sender is dropped,
+                    // and the client gets an error.
+                    if let Ok(signature) = backend.request_signature(message).await {
+                        let _ = response_sender.send(ResponseSignature { signature });
+                    }
+                }
+                ToIdentity::RequestPublicKey { response_sender } => {
+                    if let Ok(public_key) = backend.request_public_key().await {
+                        let _ = response_sender.send(ResponsePublicKey { public_key });
+                    }
+                }
+                ToIdentity::RequestDerivedSignature {
+                    path,
+                    message,
+                    response_sender,
+                } => {
+                    if let Ok(signature) = backend.request_derived_signature(path, message).await {
+                        let _ = response_sender.send(ResponseSignature { signature });
+                    }
+                }
+                ToIdentity::RequestDerivedPublicKey {
+                    path,
+                    response_sender,
+                } => {
+                    if let Ok(public_key) = backend.request_derived_public_key(path).await {
+                        let _ = response_sender.send(ResponsePublicKey { public_key });
+                    }
+                }
             }
+            // It is possible that sending the response didn't work.
+            // We don't care about this.
         }
-    });
+    };
 
     (requests_sender, identity)
 }
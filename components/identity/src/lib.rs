@@ -10,9 +10,15 @@
 
 extern crate futures;
 
+mod backend;
 mod client;
 mod identity;
 mod messages;
+#[cfg(feature = "testing")]
+pub mod testing;
 
+pub use crate::backend::{
+    ExternalSignerBackend, SeedBackend, SignatureBackend, SignatureBackendError, SoftwareBackend,
+};
 pub use crate::client::IdentityClient;
-pub use crate::identity::create_identity;
+pub use crate::identity::{create_identity, create_identity_from_backend};
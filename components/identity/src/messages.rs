@@ -1,5 +1,6 @@
 use futures::channel::oneshot;
 
+use crypto::derive::DerivationPath;
 use proto::crypto::{PublicKey, Signature};
 
 /// The response from security module client to security module.
@@ -14,6 +15,17 @@ pub enum ToIdentity {
     RequestPublicKey {
         response_sender: oneshot::Sender<ResponsePublicKey>,
     },
+    /// Request to sign a message using the key derived at `path`.
+    RequestDerivedSignature {
+        path: DerivationPath,
+        message: Vec<u8>,
+        response_sender: oneshot::Sender<ResponseSignature>,
+    },
+    /// Request the public key derived at `path`.
+    RequestDerivedPublicKey {
+        path: DerivationPath,
+        response_sender: oneshot::Sender<ResponsePublicKey>,
+    },
 }
 
 /// Return requested signature over a message
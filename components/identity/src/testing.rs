@@ -0,0 +1,122 @@
+//! A mock identity service, for deterministic tests of crates that use an `IdentityClient`.
+
+use futures::channel::mpsc;
+use futures::Future;
+
+use common::conn::BoxFuture;
+use common::testing::MockScript;
+
+use crypto::identity::Identity;
+use proto::crypto::{PublicKey, Signature};
+
+use crate::backend::{SignatureBackend, SignatureBackendError, SoftwareBackend};
+use crate::identity::create_identity_from_backend;
+use crate::messages::ToIdentity;
+
+/// A signature backend that consults a script before every request.
+/// Failed requests return `SignatureBackendError::Unavailable`.
+pub struct MockBackend<SB> {
+    backend: SB,
+    script: MockScript,
+}
+
+impl<SB> MockBackend<SB> {
+    pub fn new(backend: SB, script: MockScript) -> Self {
+        MockBackend { backend, script }
+    }
+}
+
+impl<SB> SignatureBackend for MockBackend<SB>
+where
+    SB: SignatureBackend + Send,
+{
+    fn request_public_key(&mut self) -> BoxFuture<'_, Result<PublicKey, SignatureBackendError>> {
+        let action = self.script.next_action();
+        Box::pin(async move {
+            if !action.perform().await {
+                return Err(SignatureBackendError::Unavailable);
+            }
+            self.backend.request_public_key().await
+        })
+    }
+
+    fn request_signature(
+        &mut self,
+        message: Vec<u8>,
+    ) -> BoxFuture<'_, Result<Signature, SignatureBackendError>> {
+        let action = self.script.next_action();
+        Box::pin(async move {
+            if !action.perform().await {
+                return Err(SignatureBackendError::Unavailable);
+            }
+            self.backend.request_signature(message).await
+        })
+    }
+}
+
+/// Create a mock identity service that signs using `identity`, and consults `script` before
+/// every request.
+/// Requests are handled one at a time, so a delayed request also delays the requests after it.
+pub fn create_mock_identity<I>(
+    identity: I,
+    script: MockScript,
+) -> (mpsc::Sender<ToIdentity>, impl Future<Output = ()>)
+where
+    I: Identity + Send,
+{
+    create_identity_from_backend(MockBackend::new(SoftwareBackend::new(identity), script))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::LocalPool;
+    use futures::task::SpawnExt;
+
+    use crypto::identity::SoftwareEd25519Identity;
+    use crypto::rand::RandGen;
+    use crypto::test_utils::DummyRandom;
+
+    use proto::crypto::PrivateKey;
+
+    use crate::client::IdentityClient;
+
+    #[test]
+    fn test_mock_identity() {
+        let rng = DummyRandom::new(&[1u8]);
+        let private_key = PrivateKey::rand_gen(&rng);
+        let identity = SoftwareEd25519Identity::from_private_key(&private_key).unwrap();
+        let public_key = identity.get_public_key();
+
+        let script = MockScript::new();
+        let (requests_sender, identity_fut) = create_mock_identity(identity, script.clone());
+        let identity_client = IdentityClient::new(requests_sender);
+
+        let mut local_pool = LocalPool::new();
+        local_pool.spawner().spawn(identity_fut).unwrap();
+
+        // An empty script handles requests normally:
+        assert_eq!(
+            local_pool
+                .run_until(identity_client.request_public_key())
+                .unwrap(),
+            public_key
+        );
+
+        script.fail(1);
+        assert!(local_pool
+            .run_until(identity_client.request_signature(vec![1, 2, 3]))
+            .is_err());
+
+        // A delayed request is handled after it was released:
+        let delay_sender = script.delay();
+        delay_sender.send(()).unwrap();
+        assert_eq!(
+            local_pool
+                .run_until(identity_client.request_public_key())
+                .unwrap(),
+            public_key
+        );
+    }
+}
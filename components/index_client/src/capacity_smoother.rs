@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+
+use proto::crypto::PublicKey;
+use proto::funder::messages::Currency;
+use proto::index_server::messages::{IndexMutation, UpdateFriendCurrency};
+
+/// Every tick the rolling volumes lose 1/2^VOLUME_DECAY_SHIFT of their value.
+const VOLUME_DECAY_SHIFT: u32 = 4;
+
+/// Rolling statistics of the credits flow with a friend, in a certain currency.
+/// The volumes are observed through changes of the friend's receive capacity.
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FriendCapacityStats {
+    pub public_key: PublicKey,
+    pub currency: Currency,
+    /// Decaying sum of credits the friend sent to us (Decreases of the receive capacity)
+    pub recv_volume: u128,
+    /// Decaying sum of credits we sent to the friend (Increases of the receive capacity)
+    pub send_volume: u128,
+}
+
+/// Decay a rolling volume by one tick. Small volumes eventually reach zero.
+fn decay_volume(volume: u128) -> u128 {
+    volume.saturating_sub(std::cmp::max(volume >> VOLUME_DECAY_SHIFT, 1))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct FriendCurrency {
+    public_key: PublicKey,
+    currency: Currency,
+}
+
+#[derive(Debug)]
+struct SmoothEntry {
+    recv_volume: u128,
+    send_volume: u128,
+    /// Last update received from the funder. None if we only know the statistics of this friend
+    /// (For example, right after loading the statistics from the database).
+    opt_reported: Option<UpdateFriendCurrency>,
+    /// Receive capacity we currently advertise to the index servers
+    advertised_capacity: u128,
+}
+
+impl SmoothEntry {
+    /// Capacity that is regularly taken and given back (For example, during a token hold).
+    /// A drop of the receive capacity up to this amount is likely to be transient.
+    fn round_trip_volume(&self) -> u128 {
+        std::cmp::min(self.recv_volume, self.send_volume)
+    }
+
+    fn smoothed_capacity(&self, reported_capacity: u128) -> u128 {
+        std::cmp::max(
+            reported_capacity,
+            std::cmp::min(
+                self.advertised_capacity,
+                reported_capacity.saturating_add(self.round_trip_volume()),
+            ),
+        )
+    }
+}
+
+/// Smooths the receive capacities advertised to the index servers.
+///
+/// A friend's receive capacity drops whenever a request is pending, and goes back up when the
+/// request is cancelled. Advertising those transient drops makes the index servers return routes
+/// that fail. The smoother keeps rolling statistics of the volume sent and received through every
+/// friend, and keeps advertising the previous capacity during drops that fit inside the volume
+/// that is regularly taken and given back. Capacity increases are advertised immediately.
+#[derive(Debug)]
+pub struct CapacitySmoother {
+    entries: HashMap<FriendCurrency, SmoothEntry>,
+    enabled: bool,
+}
+
+impl CapacitySmoother {
+    pub fn new(enabled: bool, friend_capacity_stats: Vec<FriendCapacityStats>) -> Self {
+        let entries = if enabled {
+            friend_capacity_stats
+                .into_iter()
+                .map(|stats| {
+                    let friend_currency = FriendCurrency {
+                        public_key: stats.public_key,
+                        currency: stats.currency,
+                    };
+                    let entry = SmoothEntry {
+                        recv_volume: stats.recv_volume,
+                        send_volume: stats.send_volume,
+                        opt_reported: None,
+                        advertised_capacity: 0,
+                    };
+                    (friend_currency, entry)
+                })
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
+        CapacitySmoother { entries, enabled }
+    }
+
+    /// Record a mutation received from the funder, and return the mutation that should be
+    /// advertised instead.
+    pub fn apply_mutation(&mut self, mutation: IndexMutation) -> IndexMutation {
+        if !self.enabled {
+            return mutation;
+        }
+
+        match mutation {
+            IndexMutation::UpdateFriendCurrency(update_friend_currency) => {
+                let friend_currency = FriendCurrency {
+                    public_key: update_friend_currency.public_key.clone(),
+                    currency: update_friend_currency.currency.clone(),
+                };
+                let entry = self.entries.entry(friend_currency).or_insert(SmoothEntry {
+                    recv_volume: 0,
+                    send_volume: 0,
+                    opt_reported: None,
+                    advertised_capacity: 0,
+                });
+
+                let new_capacity = update_friend_currency.recv_capacity;
+                if let Some(reported) = &entry.opt_reported {
+                    let old_capacity = reported.recv_capacity;
+                    if new_capacity < old_capacity {
+                        entry.recv_volume = entry
+                            .recv_volume
+                            .saturating_add(old_capacity - new_capacity);
+                    } else {
+                        entry.send_volume = entry
+                            .send_volume
+                            .saturating_add(new_capacity - old_capacity);
+                    }
+                }
+
+                entry.advertised_capacity = entry.smoothed_capacity(new_capacity);
+                let mut advertised = update_friend_currency.clone();
+                advertised.recv_capacity = entry.advertised_capacity;
+                entry.opt_reported = Some(update_friend_currency);
+                IndexMutation::UpdateFriendCurrency(advertised)
+            }
+            IndexMutation::RemoveFriendCurrency(remove_friend_currency) => {
+                self.entries.remove(&FriendCurrency {
+                    public_key: remove_friend_currency.public_key.clone(),
+                    currency: remove_friend_currency.currency.clone(),
+                });
+                IndexMutation::RemoveFriendCurrency(remove_friend_currency)
+            }
+        }
+    }
+
+    /// Handle a time tick. The rolling volumes decay, and the advertised capacities move towards
+    /// the reported capacities.
+    /// Returns mutations for the friends whose advertised capacity has changed.
+    pub fn tick(&mut self) -> Vec<IndexMutation> {
+        let mut mutations = Vec::new();
+        for entry in self.entries.values_mut() {
+            entry.recv_volume = decay_volume(entry.recv_volume);
+            entry.send_volume = decay_volume(entry.send_volume);
+
+            let reported = match &entry.opt_reported {
+                Some(reported) => reported,
+                None => continue,
+            };
+            let new_advertised_capacity = entry.smoothed_capacity(reported.recv_capacity);
+            if new_advertised_capacity != entry.advertised_capacity {
+                entry.advertised_capacity = new_advertised_capacity;
+                let mut advertised = reported.clone();
+                advertised.recv_capacity = new_advertised_capacity;
+                mutations.push(IndexMutation::UpdateFriendCurrency(advertised));
+            }
+        }
+
+        // Forget friends we have no information about:
+        self.entries.retain(|_, entry| {
+            entry.opt_reported.is_some() || entry.recv_volume > 0 || entry.send_volume > 0
+        });
+        mutations
+    }
+
+    /// Current statistics, to be saved to the database.
+    /// Returns None if smoothing is disabled.
+    pub fn stats(&self) -> Option<Vec<FriendCapacityStats>> {
+        if !self.enabled {
+            return None;
+        }
+
+        Some(
+            self.entries
+                .iter()
+                .filter(|(_, entry)| entry.recv_volume > 0 || entry.send_volume > 0)
+                .map(|(friend_currency, entry)| FriendCapacityStats {
+                    public_key: friend_currency.public_key.clone(),
+                    currency: friend_currency.currency.clone(),
+                    recv_volume: entry.recv_volume,
+                    send_volume: entry.send_volume,
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use proto::funder::messages::Rate;
+
+    fn update(recv_capacity: u128) -> IndexMutation {
+        IndexMutation::UpdateFriendCurrency(UpdateFriendCurrency {
+            public_key: PublicKey::from(&[0xaa; PublicKey::len()]),
+            currency: Currency::try_from("FST".to_owned()).unwrap(),
+            recv_capacity,
+            rate: Rate { mul: 0, add: 1 },
+        })
+    }
+
+    fn advertised_capacity(mutation: &IndexMutation) -> u128 {
+        match mutation {
+            IndexMutation::UpdateFriendCurrency(update_friend_currency) => {
+                update_friend_currency.recv_capacity
+            }
+            IndexMutation::RemoveFriendCurrency(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_capacity_smoother_transient_drop() {
+        let mut smoother = CapacitySmoother::new(true, Vec::new());
+
+        // Without any history, drops are advertised as is:
+        assert_eq!(
+            advertised_capacity(&smoother.apply_mutation(update(100))),
+            100
+        );
+        assert_eq!(
+            advertised_capacity(&smoother.apply_mutation(update(40))),
+            40
+        );
+        assert_eq!(
+            advertised_capacity(&smoother.apply_mutation(update(100))),
+            100
+        );
+
+        // 60 credits were taken and given back. A similar drop is now smoothed:
+        assert_eq!(
+            advertised_capacity(&smoother.apply_mutation(update(50))),
+            100
+        );
+        assert_eq!(
+            advertised_capacity(&smoother.apply_mutation(update(100))),
+            100
+        );
+    }
+
+    #[test]
+    fn test_capacity_smoother_large_drop() {
+        let mut smoother = CapacitySmoother::new(true, Vec::new());
+        smoother.apply_mutation(update(100));
+        smoother.apply_mutation(update(40));
+        smoother.apply_mutation(update(100));
+
+        // A drop larger than the round trip volume (60) is only partially smoothed:
+        assert_eq!(
+            advertised_capacity(&smoother.apply_mutation(update(20))),
+            80
+        );
+    }
+
+    #[test]
+    fn test_capacity_smoother_converges() {
+        let mut smoother = CapacitySmoother::new(true, Vec::new());
+        smoother.apply_mutation(update(100));
+        smoother.apply_mutation(update(0));
+        smoother.apply_mutation(update(100));
+        assert_eq!(
+            advertised_capacity(&smoother.apply_mutation(update(0))),
+            100
+        );
+
+        // The advertised capacity eventually reaches the reported capacity:
+        let mut last_advertised = 100;
+        for _ in 0..0x100 {
+            for mutation in smoother.tick() {
+                let advertised = advertised_capacity(&mutation);
+                assert!(advertised < last_advertised);
+                last_advertised = advertised;
+            }
+        }
+        assert_eq!(last_advertised, 0);
+        assert!(smoother.tick().is_empty());
+    }
+
+    #[test]
+    fn test_capacity_smoother_stats() {
+        let mut smoother = CapacitySmoother::new(true, Vec::new());
+        smoother.apply_mutation(update(100));
+        smoother.apply_mutation(update(40));
+        smoother.apply_mutation(update(100));
+
+        let stats = smoother.stats().unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].recv_volume, 60);
+        assert_eq!(stats[0].send_volume, 60);
+
+        // Statistics loaded from the database are used right away:
+        let mut smoother = CapacitySmoother::new(true, stats);
+        assert_eq!(
+            advertised_capacity(&smoother.apply_mutation(update(100))),
+            100
+        );
+        assert_eq!(
+            advertised_capacity(&smoother.apply_mutation(update(50))),
+            100
+        );
+    }
+
+    #[test]
+    fn test_capacity_smoother_disabled() {
+        let mut smoother = CapacitySmoother::new(false, Vec::new());
+        smoother.apply_mutation(update(100));
+        smoother.apply_mutation(update(40));
+        smoother.apply_mutation(update(100));
+        assert_eq!(
+            advertised_capacity(&smoother.apply_mutation(update(50))),
+            50
+        );
+        assert!(smoother.tick().is_empty());
+        assert!(smoother.stats().is_none());
+    }
+}
@@ -3,6 +3,7 @@ use futures::task::{Spawn, SpawnExt};
 use futures::FutureExt;
 
 use proto::crypto::PublicKey;
+use proto::index_server::messages::{FriendProposal, ResumeSession};
 
 use crypto::rand::CryptoRandom;
 
@@ -15,7 +16,9 @@ use crate::single_client::{
 };
 
 pub type ControlSender = mpsc::Sender<SingleClientControl>;
-pub type CloseReceiver = oneshot::Receiver<Result<(), SingleClientError>>;
+/// Notifies about disconnection. If any mutations were sent during the session, a token for
+/// resuming the session is also returned.
+pub type CloseReceiver = oneshot::Receiver<(Result<(), SingleClientError>, Option<ResumeSession>)>;
 pub type SessionHandle = (ControlSender, CloseReceiver);
 
 #[derive(Clone)]
@@ -24,6 +27,8 @@ pub struct IndexClientSession<C, R, S> {
     local_public_key: PublicKey,
     identity_client: IdentityClient,
     rng: R,
+    /// Friend proposals received through all sessions are sent here:
+    friend_proposal_sender: mpsc::Sender<FriendProposal>,
     spawner: S,
 }
 
@@ -40,6 +45,7 @@ where
         local_public_key: PublicKey,
         identity_client: IdentityClient,
         rng: R,
+        friend_proposal_sender: mpsc::Sender<FriendProposal>,
         spawner: S,
     ) -> Self {
         IndexClientSession {
@@ -47,6 +53,7 @@ where
             local_public_key,
             identity_client,
             rng,
+            friend_proposal_sender,
             spawner,
         }
     }
@@ -58,10 +65,12 @@ where
             .await?
             .split();
 
-        let first_time_hash = first_server_time_hash(&mut from_server).await.ok()?;
+        let (first_time_hash, first_opt_pow_difficulty) =
+            first_server_time_hash(&mut from_server).await.ok()?;
         let (control_sender, incoming_control) = mpsc::channel(0);
 
         let (close_sender, close_receiver) = oneshot::channel();
+        let (resume_session_sender, mut resume_session_receiver) = oneshot::channel();
 
         let single_client_fut = single_client_loop(
             ConnPair::from_raw(to_server, from_server),
@@ -70,9 +79,13 @@ where
             self.identity_client.clone(),
             self.rng.clone(),
             first_time_hash,
+            first_opt_pow_difficulty,
+            self.friend_proposal_sender.clone(),
+            resume_session_sender,
         )
-        .map(|res| {
-            if let Err(res) = close_sender.send(res) {
+        .map(move |res| {
+            let opt_resume_session = resume_session_receiver.try_recv().ok().flatten();
+            if let Err(res) = close_sender.send((res, opt_resume_session)) {
                 error!("Failed to send result from single_client_loop(): {:?}", res);
             }
         });
@@ -134,12 +147,14 @@ mod tests {
 
         let (connector_sender, mut connector_receiver) = mpsc::channel(0);
         let connector = DummyConnector::<u32, _>::new(connector_sender);
+        let (friend_proposal_sender, _incoming_friend_proposals) = mpsc::channel(0);
 
         let mut index_client_session = IndexClientSession::new(
             connector,
             local_public_key,
             identity_client,
             rng,
+            friend_proposal_sender,
             spawner.clone(),
         );
 
@@ -165,8 +180,10 @@ mod tests {
         let (_control_sender, close_receiver) = opt_session_handle.unwrap();
 
         drop(server_sender);
-        let single_client_loop_res = close_receiver.await.unwrap();
+        let (single_client_loop_res, opt_resume_session) = close_receiver.await.unwrap();
         assert_eq!(single_client_loop_res, Err(SingleClientError::ServerClosed));
+        // No mutations were sent, so there is nothing to resume:
+        assert!(opt_resume_session.is_none());
     }
 
     #[test]
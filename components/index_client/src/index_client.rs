@@ -1,8 +1,9 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::Debug;
 use std::marker::Unpin;
 
 use futures::channel::{mpsc, oneshot};
+use futures::stream::FuturesUnordered;
 use futures::task::{Spawn, SpawnExt};
 use futures::{future, select, stream, FutureExt, Sink, SinkExt, Stream, StreamExt, TryFutureExt};
 
@@ -15,26 +16,75 @@ use proto::crypto::{PublicKey, Uid};
 
 use database::DatabaseClient;
 
+use proto::app_server::messages::SendFriendProposal;
+use proto::consts::INDEX_SESSION_RESUME_TICKS;
 use proto::index_client::messages::{
     AppServerToIndexClient, ClientResponseRoutes, IndexClientReportMutation,
     IndexClientReportMutations, IndexClientRequest, IndexClientToAppServer, IndexMutation,
     RequestRoutes, ResponseRoutesResult,
 };
-use proto::index_server::messages::{IndexServerAddress, NamedIndexServerAddress};
+use proto::index_server::messages::{
+    FriendProposal, IndexServerAddress, IndexServerDirectory, NamedIndexServerAddress,
+    ResponseServerStatus, ResumeSession,
+};
+use proto::net::messages::NetAddress;
+
+use signature::verify::verify_index_server_directory;
 
+use crate::capacity_smoother::{CapacitySmoother, FriendCapacityStats};
 use crate::client_session::{ControlSender, SessionHandle};
+use crate::pending_mutations::PendingMutations;
+use crate::route_cache::RouteCache;
+use crate::route_merge::merge_multi_routes;
+use crate::route_rotation::RouteRotation;
 use crate::seq_friends::SeqFriendsClient;
 use crate::single_client::SingleClientControl;
 
+/// The amount of ticks between two saves of the capacity statistics to the database.
+const CAPACITY_STATS_SAVE_TICKS: usize = 0x100;
+
+/// Maximum amount of received friend proposals we keep in our inbox.
+/// Proposals received when the inbox is full are dropped.
+const MAX_FRIEND_PROPOSALS: usize = 0x40;
+
+/// The amount of ticks between two status requests sent to a connected index server.
+const SERVER_STATUS_TICKS: usize = 0x40;
+
+/// An index server that did not receive a time hash from its peer servers for more than this
+/// amount of ticks is considered unhealthy. We avoid connecting to unhealthy servers if other
+/// servers are available.
+const MAX_TIME_HASH_AGE: u64 = 0x10;
+
+/// Maximum amount of mutations kept while we are not connected to any index server.
+const MAX_PENDING_MUTATIONS: usize = 0x400;
+
+/// The amount of ticks between two requests for the index server directory.
+const DIRECTORY_REFRESH_TICKS: usize = 0x400;
+
 #[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, Default)]
 pub struct IndexClientConfig<ISA> {
     pub index_servers: Vec<NamedIndexServerAddress<ISA>>,
+    /// Rolling statistics of the credits flow with our friends.
+    /// Used to smooth the capacities we advertise to the index servers.
+    #[serde(default)]
+    pub friend_capacity_stats: Vec<FriendCapacityStats>,
+    /// Mutations generated while we were not connected to any index server.
+    /// Sent to the first index server we connect to.
+    #[serde(default)]
+    pub pending_mutations: Vec<IndexMutation>,
+    /// Version of the last index server directory applied to `index_servers`.
+    /// Older directories are ignored, so that a server can not roll back the list.
+    #[serde(default)]
+    pub directory_version: u64,
 }
 
 impl<ISA> IndexClientConfig<ISA> {
     pub fn new() -> Self {
         IndexClientConfig {
             index_servers: Vec::new(),
+            friend_capacity_stats: Vec::new(),
+            pending_mutations: Vec::new(),
+            directory_version: 0,
         }
     }
 }
@@ -43,6 +93,9 @@ impl<ISA> IndexClientConfig<ISA> {
 pub enum IndexClientConfigMutation<ISA> {
     AddIndexServer(NamedIndexServerAddress<ISA>),
     RemoveIndexServer(PublicKey),
+    SetFriendCapacityStats(Vec<FriendCapacityStats>),
+    SetPendingMutations(Vec<IndexMutation>),
+    SetDirectoryVersion(u64),
 }
 
 impl<ISA> MutableState for IndexClientConfig<ISA>
@@ -66,6 +119,15 @@ where
                 self.index_servers
                     .retain(|named_index_server| &named_index_server.public_key != public_key);
             }
+            IndexClientConfigMutation::SetFriendCapacityStats(friend_capacity_stats) => {
+                self.friend_capacity_stats = friend_capacity_stats.clone();
+            }
+            IndexClientConfigMutation::SetPendingMutations(pending_mutations) => {
+                self.pending_mutations = pending_mutations.clone();
+            }
+            IndexClientConfigMutation::SetDirectoryVersion(directory_version) => {
+                self.directory_version = *directory_version;
+            }
         };
         Ok(())
     }
@@ -88,6 +150,17 @@ struct ServerConnected<ISA> {
     /// Decrementing counter. When reaches 0 we send a SendMutations
     /// to the server and reset this value to keepalive_ticks:
     ticks_to_send_keepalive: usize,
+    /// Decrementing counter. When reaches 0 we request the status of the server and reset this
+    /// value to SERVER_STATUS_TICKS:
+    ticks_to_request_status: usize,
+}
+
+/// A recently closed session with an index server, that may be resumed if we reconnect to the
+/// same server soon enough.
+#[derive(Debug)]
+struct ResumableSession {
+    resume_session: ResumeSession,
+    ticks_left: usize,
 }
 
 #[derive(Debug)]
@@ -111,9 +184,12 @@ pub enum IndexClientError {
 enum IndexClientEvent<ISA> {
     FromAppServer(AppServerToIndexClient<ISA>),
     AppServerClosed,
-    IndexServerConnected(ControlSender),
-    IndexServerClosed,
-    ResponseRoutes((Uid, ResponseRoutesResult)),
+    IndexServerConnected((usize, ControlSender)),
+    IndexServerClosed((usize, Option<ResumeSession>)),
+    ResponseRoutes((RequestRoutes, ResponseRoutesResult)),
+    ServerStatus((PublicKey, ResponseServerStatus)),
+    Directory(IndexServerDirectory),
+    FriendProposal(FriendProposal),
     TimerTick,
 }
 
@@ -129,13 +205,77 @@ struct IndexClient<ISA, TAS, ICS, S> {
     index_client_session: ICS,
     max_open_requests: usize,
     num_open_requests: usize,
+    /// Recently received routes, used to avoid querying the index servers with
+    /// similar requests:
+    route_cache: RouteCache,
+    /// Spreads repeated payments to the same destination over the known routes:
+    route_rotation: RouteRotation,
+    /// Smooths the capacities we advertise to the index servers:
+    capacity_smoother: CapacitySmoother,
+    /// Decrementing counter. When reaches 0 we save the capacity statistics to the database and
+    /// reset this value to CAPACITY_STATS_SAVE_TICKS:
+    ticks_to_save_capacity_stats: usize,
     keepalive_ticks: usize,
     backoff_ticks: usize,
-    conn_status: ConnStatus<ISA>,
+    /// Sessions with index servers. Every session is connected to a different index server:
+    sessions: Vec<ConnStatus<ISA>>,
+    /// Last status reported by index servers. Used to prefer healthier servers when connecting:
+    server_statuses: HashMap<PublicKey, ResponseServerStatus>,
+    /// Recently closed sessions, by the public key of their index server:
+    resumable_sessions: HashMap<PublicKey, ResumableSession>,
+    /// The index server reported to the app server as our connected server:
+    opt_reported_server: Option<PublicKey>,
+    /// Public keys of the nodes whose friend proposals are in our inbox.
+    /// The inbox itself is kept in the report of the app server, and is not persisted.
+    friend_proposals: HashSet<PublicKey>,
+    /// Mutations generated while we were not connected to any index server:
+    pending_mutations: PendingMutations,
+    /// The key that signs the index server directory. If set, our list of index servers is
+    /// managed according to the directory:
+    opt_directory_public_key: Option<PublicKey>,
+    /// Version of the last directory we have applied:
+    directory_version: u64,
+    /// Decrementing counter. When reaches 0 we request the directory from one of the connected
+    /// servers and reset this value to DIRECTORY_REFRESH_TICKS:
+    ticks_to_request_directory: usize,
     db_client: DatabaseClient<IndexClientConfigMutation<ISA>>,
     spawner: S,
 }
 
+/// Calculate the changes that turn our list of index servers into the list of `directory_servers`.
+/// Returns the public keys of the servers that are missing from the directory, and the servers
+/// that are new (Or have a new address).
+fn directory_changes<ISA>(
+    index_servers: &VecDeque<IndexServerAddress<ISA>>,
+    directory_servers: &[NamedIndexServerAddress<ISA>],
+) -> (Vec<PublicKey>, Vec<NamedIndexServerAddress<ISA>>)
+where
+    ISA: Eq + Clone,
+{
+    let removed_servers = index_servers
+        .iter()
+        .filter(|index_server| {
+            !directory_servers
+                .iter()
+                .any(|directory_server| directory_server.public_key == index_server.public_key)
+        })
+        .map(|index_server| index_server.public_key.clone())
+        .collect();
+
+    let added_servers = directory_servers
+        .iter()
+        .filter(|directory_server| {
+            !index_servers.iter().any(|index_server| {
+                index_server.public_key == directory_server.public_key
+                    && index_server.address == directory_server.address
+            })
+        })
+        .cloned()
+        .collect();
+
+    (removed_servers, added_servers)
+}
+
 /// Send our full friends state as mutations to the server.
 /// We do this in a separate task so that we don't block user requests or incoming funder reports.
 async fn send_full_state(
@@ -180,7 +320,7 @@ async fn send_full_state(
 
 impl<ISA, TAS, ICS, S> IndexClient<ISA, TAS, ICS, S>
 where
-    ISA: Debug + Eq + Clone + Send + 'static,
+    ISA: Debug + Eq + Clone + Send + From<NetAddress> + 'static,
     TAS: Sink<IndexClientToAppServer<ISA>> + Unpin,
     ICS: FutTransform<Input = IndexServerAddress<ISA>, Output = Option<SessionHandle>>
         + Clone
@@ -195,8 +335,13 @@ where
         seq_friends_client: SeqFriendsClient,
         index_client_session: ICS,
         max_open_requests: usize,
+        route_cache_ticks: usize,
+        route_rotation: bool,
+        capacity_smoothing: bool,
         keepalive_ticks: usize,
         backoff_ticks: usize,
+        max_sessions: usize,
+        opt_directory_public_key: Option<PublicKey>,
         db_client: DatabaseClient<IndexClientConfigMutation<ISA>>,
         spawner: S,
     ) -> Self {
@@ -217,33 +362,124 @@ where
             index_client_session,
             max_open_requests,
             num_open_requests: 0,
+            route_cache: RouteCache::new(route_cache_ticks),
+            route_rotation: RouteRotation::new(route_rotation),
+            capacity_smoother: CapacitySmoother::new(
+                capacity_smoothing,
+                index_client_config.friend_capacity_stats,
+            ),
+            ticks_to_save_capacity_stats: CAPACITY_STATS_SAVE_TICKS,
             keepalive_ticks,
             backoff_ticks,
-            conn_status: ConnStatus::Empty(backoff_ticks),
+            // We always keep at least one session:
+            sessions: (0..std::cmp::max(max_sessions, 1))
+                .map(|_| ConnStatus::Empty(backoff_ticks))
+                .collect(),
+            server_statuses: HashMap::new(),
+            resumable_sessions: HashMap::new(),
+            opt_reported_server: None,
+            friend_proposals: HashSet::new(),
+            pending_mutations: PendingMutations::new(
+                MAX_PENDING_MUTATIONS,
+                index_client_config.pending_mutations,
+            ),
+            opt_directory_public_key,
+            directory_version: index_client_config.directory_version,
+            // Request the directory as soon as we are connected to a server:
+            ticks_to_request_directory: 0,
             db_client,
             spawner,
         }
     }
 
-    /// Attempt to connect to server.
-    /// If there are no index servers known, do nothing.
-    fn try_connect_to_server(&mut self) -> Result<(), IndexClientError> {
-        // Make sure that conn_status is empty:
-        if let ConnStatus::Empty(_) = self.conn_status {
+    /// Is there a session connected (or connecting) to the index server `public_key`?
+    fn is_server_in_use(&self, public_key: &PublicKey) -> bool {
+        self.sessions.iter().any(|conn_status| match conn_status {
+            ConnStatus::Empty(_) => false,
+            ConnStatus::Connecting(server_connecting) => {
+                &server_connecting.index_server.public_key == public_key
+            }
+            ConnStatus::Connected(server_connected) => {
+                &server_connected.index_server.public_key == public_key
+            }
+        })
+    }
+
+    /// Is the index server `public_key` healthy, according to its last reported status?
+    /// Servers that did not report their status yet are considered healthy.
+    fn is_server_healthy(&self, public_key: &PublicKey) -> bool {
+        match self.server_statuses.get(public_key) {
+            Some(server_status) => server_status.time_hash_age <= MAX_TIME_HASH_AGE,
+            None => true,
+        }
+    }
+
+    /// Attempt to connect all the empty sessions to servers.
+    fn try_connect_to_servers(&mut self) -> Result<(), IndexClientError> {
+        for session_index in 0..self.sessions.len() {
+            if let ConnStatus::Empty(_) = self.sessions[session_index] {
+                self.try_connect_to_server(session_index)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Attempt to connect the session `session_index` to a server.
+    /// If there are no index servers known that are not used by other sessions, do nothing.
+    /// Unhealthy servers are used only if there are no other free servers.
+    /// Servers we have a resumable session with are preferred, to avoid resending our full state.
+    fn try_connect_to_server(&mut self, session_index: usize) -> Result<(), IndexClientError> {
+        // Make sure that the session is empty:
+        if let ConnStatus::Empty(_) = self.sessions[session_index] {
         } else {
             unreachable!();
         }
 
-        let index_server = match self.index_servers.pop_front() {
+        let mut opt_index_server = self
+            .index_servers
+            .iter()
+            .find(|index_server| {
+                self.resumable_sessions
+                    .contains_key(&index_server.public_key)
+                    && !self.is_server_in_use(&index_server.public_key)
+                    && self.is_server_healthy(&index_server.public_key)
+            })
+            .cloned();
+        let mut opt_unhealthy_server = None;
+        let rotations = if opt_index_server.is_some() {
+            0
+        } else {
+            self.index_servers.len()
+        };
+        for _ in 0..rotations {
+            let index_server = self.index_servers.pop_front().unwrap();
+            // Move the address to the end, rotating the addresses VecDeque 1 to the left:
+            self.index_servers.push_back(index_server.clone());
+            if self.is_server_in_use(&index_server.public_key) {
+                continue;
+            }
+            if self.is_server_healthy(&index_server.public_key) {
+                opt_index_server = Some(index_server);
+                break;
+            }
+            if opt_unhealthy_server.is_none() {
+                opt_unhealthy_server = Some(index_server);
+            }
+        }
+
+        let index_server = match opt_index_server.or(opt_unhealthy_server) {
             Some(index_server) => index_server,
             None => {
-                // We don't have any index servers to connect to:
-                self.conn_status = ConnStatus::Empty(0);
+                // We don't have any free index servers to connect to:
+                self.sessions[session_index] = ConnStatus::Empty(0);
                 return Ok(());
             }
         };
-        // Move the chosen address to the end, rotating the addresses VecDeque 1 to the left:
-        self.index_servers.push_back(index_server.clone());
+
+        let opt_resume_session = self
+            .resumable_sessions
+            .remove(&index_server.public_key)
+            .map(|resumable_session| resumable_session.resume_session);
 
         let mut c_index_client_session = self.index_client_session.clone();
         let mut c_event_sender = self.event_sender.clone();
@@ -253,7 +489,7 @@ where
             index_server: index_server.clone(),
             opt_cancel_sender: Some(cancel_sender),
         };
-        self.conn_status = ConnStatus::Connecting(server_connecting);
+        self.sessions[session_index] = ConnStatus::Connecting(server_connecting);
 
         let c_seq_friends_client = self.seq_friends_client.clone();
         let c_spawner = self.spawner.clone();
@@ -265,7 +501,24 @@ where
         // TODO: Can we remove the Box::pin() from here? How?
         let connect_fut = Box::pin(async move {
             let res = c_index_client_session.transform(index_server).await?;
-            let (control_sender, close_receiver) = res;
+            let (mut control_sender, close_receiver) = res;
+
+            // Try to resume our previous session with the server. If the server still knows our
+            // full state, there is no need to send it again:
+            let session_resumed = match opt_resume_session {
+                Some(resume_session) => {
+                    let (resume_sender, resume_receiver) = oneshot::channel();
+                    control_sender
+                        .send(SingleClientControl::ResumeSession((
+                            resume_session,
+                            resume_sender,
+                        )))
+                        .await
+                        .ok()?;
+                    resume_receiver.await.unwrap_or(false)
+                }
+                None => false,
+            };
 
             let c_control_sender = control_sender.clone();
             let send_full_state_cancellable_fut = async move {
@@ -283,21 +536,26 @@ where
                 };
             };
 
-            c_spawner.spawn(send_full_state_cancellable_fut).ok()?;
+            if !session_resumed {
+                c_spawner.spawn(send_full_state_cancellable_fut).ok()?;
+            }
 
             let _ = c_event_sender
-                .send(IndexClientEvent::IndexServerConnected(control_sender))
+                .send(IndexClientEvent::IndexServerConnected((
+                    session_index,
+                    control_sender,
+                )))
                 .await;
-            let _ = close_receiver.await;
-            Some(())
+            let (_res, opt_resume_session) = close_receiver.await.ok()?;
+            opt_resume_session
         });
 
         let mut c_event_sender = self.event_sender.clone();
         let cancellable_fut = async move {
             // During the connection stage it is possible to cancel using the `cancel_sender`:
-            select! {
-                _connect_fut = connect_fut.fuse() => (),
-                _ = cancel_receiver.fuse() => (),
+            let opt_resume_session = select! {
+                opt_resume_session = connect_fut.fuse() => opt_resume_session,
+                _ = cancel_receiver.fuse() => None,
             };
             // Connection was closed or cancelled:
 
@@ -308,7 +566,10 @@ where
 
             // Notify main task about closed connection:
             let _ = c_event_sender
-                .send(IndexClientEvent::IndexServerClosed)
+                .send(IndexClientEvent::IndexServerClosed((
+                    session_index,
+                    opt_resume_session,
+                )))
                 .await;
         };
 
@@ -337,6 +598,26 @@ where
         &mut self,
         app_request_id: Uid,
         named_index_server_address: NamedIndexServerAddress<ISA>,
+    ) -> Result<(), IndexClientError> {
+        self.add_index_server(Some(app_request_id), named_index_server_address)
+            .await
+    }
+
+    pub async fn handle_from_app_server_remove_index_server(
+        &mut self,
+        app_request_id: Uid,
+        public_key: PublicKey,
+    ) -> Result<(), IndexClientError> {
+        self.remove_index_server(Some(app_request_id), public_key)
+            .await
+    }
+
+    /// Add an index server, either by a request from an app (`opt_app_request_id`) or according
+    /// to the index server directory.
+    async fn add_index_server(
+        &mut self,
+        opt_app_request_id: Option<Uid>,
+        named_index_server_address: NamedIndexServerAddress<ISA>,
     ) -> Result<(), IndexClientError> {
         // Update database:
         self.db_client
@@ -359,7 +640,7 @@ where
         let index_client_report_mutation =
             IndexClientReportMutation::AddIndexServer(named_index_server_address.clone());
         let index_client_report_mutations = IndexClientReportMutations {
-            opt_app_request_id: Some(app_request_id),
+            opt_app_request_id,
             mutations: vec![index_client_report_mutation],
         };
         self.to_app_server
@@ -369,18 +650,15 @@ where
             .await
             .map_err(|_| IndexClientError::SendToAppServerFailed)?;
 
-        if let ConnStatus::Empty(_) = self.conn_status {
-        } else {
-            return Ok(());
-        }
-        // We are here if conn_status is empty.
-        // This means we should initiate connection to an index server
-        self.try_connect_to_server()
+        // Initiate connections to index servers for the empty sessions:
+        self.try_connect_to_servers()
     }
 
-    pub async fn handle_from_app_server_remove_index_server(
+    /// Remove an index server, either by a request from an app (`opt_app_request_id`) or
+    /// according to the index server directory.
+    async fn remove_index_server(
         &mut self,
-        app_request_id: Uid,
+        opt_app_request_id: Option<Uid>,
         public_key: PublicKey,
     ) -> Result<(), IndexClientError> {
         // Update database:
@@ -394,12 +672,14 @@ where
         // Remove address:
         self.index_servers
             .retain(|index_server| index_server.public_key != public_key);
+        self.server_statuses.remove(&public_key);
+        self.resumable_sessions.remove(&public_key);
 
         // Send report:
         let index_client_report_mutation =
             IndexClientReportMutation::RemoveIndexServer(public_key.clone());
         let index_client_report_mutations = IndexClientReportMutations {
-            opt_app_request_id: Some(app_request_id),
+            opt_app_request_id,
             mutations: vec![index_client_report_mutation],
         };
         self.to_app_server
@@ -410,19 +690,21 @@ where
             .map_err(|_| IndexClientError::SendToAppServerFailed)?;
 
         // Disconnect a current server connection if it uses the removed address:
-        match &mut self.conn_status {
-            ConnStatus::Empty(_) => {} // Nothing to do here
-            ConnStatus::Connecting(server_connecting) => {
-                if server_connecting.index_server.public_key == public_key {
-                    if let Some(cancel_sender) = server_connecting.opt_cancel_sender.take() {
-                        let _ = cancel_sender.send(());
+        for conn_status in &mut self.sessions {
+            match conn_status {
+                ConnStatus::Empty(_) => {} // Nothing to do here
+                ConnStatus::Connecting(server_connecting) => {
+                    if server_connecting.index_server.public_key == public_key {
+                        if let Some(cancel_sender) = server_connecting.opt_cancel_sender.take() {
+                            let _ = cancel_sender.send(());
+                        }
                     }
                 }
-            }
-            ConnStatus::Connected(server_connected) => {
-                if server_connected.index_server.public_key == public_key {
-                    server_connected.opt_control_sender.take();
-                    server_connected.opt_cancel_sender.take();
+                ConnStatus::Connected(server_connected) => {
+                    if server_connected.index_server.public_key == public_key {
+                        server_connected.opt_control_sender.take();
+                        server_connected.opt_cancel_sender.take();
+                    }
                 }
             }
         }
@@ -446,51 +728,77 @@ where
             .await
             .map_err(|_| IndexClientError::SendToAppServerFailed)?;
 
+        // Answer from the cache if we have recently received routes for a similar request:
+        if let Some(mut multi_routes) = self.route_cache.get(&request_routes) {
+            self.route_rotation
+                .rotate(&request_routes, &mut multi_routes);
+            let client_response_routes = ClientResponseRoutes {
+                request_id: request_routes.request_id,
+                result: ResponseRoutesResult::Success(multi_routes),
+            };
+            return self
+                .to_app_server
+                .send(IndexClientToAppServer::ResponseRoutes(
+                    client_response_routes,
+                ))
+                .await
+                .map_err(|_| IndexClientError::SendToAppServerFailed);
+        }
+
         if self.num_open_requests >= self.max_open_requests {
             return self
                 .return_response_routes_failure(request_routes.request_id)
                 .await;
         }
 
-        // Check server connection status:
-        let mut server_connected = match &mut self.conn_status {
-            ConnStatus::Empty(_) | ConnStatus::Connecting(_) => {
-                return self
-                    .return_response_routes_failure(request_routes.request_id)
-                    .await
-            }
-            ConnStatus::Connected(server_connected) => server_connected,
-        };
+        // Send the request to all the connected servers:
+        let mut response_receivers = Vec::new();
+        for conn_status in &mut self.sessions {
+            let server_connected = match conn_status {
+                ConnStatus::Empty(_) | ConnStatus::Connecting(_) => continue,
+                ConnStatus::Connected(server_connected) => server_connected,
+            };
 
-        let mut control_sender = match server_connected.opt_control_sender.take() {
-            Some(control_sender) => control_sender,
-            None => {
-                return self
-                    .return_response_routes_failure(request_routes.request_id)
-                    .await
-            }
-        };
+            let mut control_sender = match server_connected.opt_control_sender.take() {
+                Some(control_sender) => control_sender,
+                None => continue,
+            };
 
-        let c_request_id = request_routes.request_id.clone();
-        let (response_sender, response_receiver) = oneshot::channel();
-        let single_client_control =
-            SingleClientControl::RequestRoutes((request_routes, response_sender));
+            let (response_sender, response_receiver) = oneshot::channel();
+            let single_client_control =
+                SingleClientControl::RequestRoutes((request_routes.clone(), response_sender));
 
-        match control_sender.send(single_client_control).await {
-            Ok(()) => server_connected.opt_control_sender = Some(control_sender),
-            Err(_) => return self.return_response_routes_failure(c_request_id).await,
-        };
+            if let Ok(()) = control_sender.send(single_client_control).await {
+                server_connected.opt_control_sender = Some(control_sender);
+                response_receivers.push(response_receiver);
+            }
+        }
 
+        if response_receivers.is_empty() {
+            return self
+                .return_response_routes_failure(request_routes.request_id)
+                .await;
+        }
+
+        let c_request_routes = request_routes;
         let mut c_event_sender = self.event_sender.clone();
         let request_fut = async move {
-            let response_routes_result = match response_receiver.await {
-                Ok(routes) => ResponseRoutesResult::Success(routes),
-                Err(_) => ResponseRoutesResult::Failure,
+            // Collect the responses in the order of their arrival:
+            let responses = response_receivers
+                .into_iter()
+                .collect::<FuturesUnordered<_>>()
+                .filter_map(|res_routes| future::ready(res_routes.ok()))
+                .collect::<Vec<_>>()
+                .await;
+            let response_routes_result = if responses.is_empty() {
+                ResponseRoutesResult::Failure
+            } else {
+                ResponseRoutesResult::Success(merge_multi_routes(responses))
             };
             // TODO: Should report error here if failure occurs?
             let _ = c_event_sender
                 .send(IndexClientEvent::ResponseRoutes((
-                    c_request_id,
+                    c_request_routes,
                     response_routes_result,
                 )))
                 .await;
@@ -503,27 +811,44 @@ where
     }
 
     pub async fn handle_from_app_server_apply_mutations(
+        &mut self,
+        mutations: Vec<IndexMutation>,
+    ) -> Result<(), IndexClientError> {
+        let mutations = mutations
+            .into_iter()
+            .map(|mutation| self.capacity_smoother.apply_mutation(mutation))
+            .collect();
+        self.apply_mutations(mutations).await
+    }
+
+    /// Apply mutations of our friends state, and send them to the connected index servers.
+    async fn apply_mutations(
         &mut self,
         mut mutations: Vec<IndexMutation>,
     ) -> Result<(), IndexClientError> {
         // Update state:
         for mutation in &mutations {
+            self.route_cache.apply_mutation(mutation);
             self.seq_friends_client
                 .mutate(mutation.clone())
                 .await
                 .map_err(|_| IndexClientError::SeqFriendsError)?;
         }
 
-        // Check if server is ready:
-        let server_connected = match &mut self.conn_status {
-            ConnStatus::Empty(_) | ConnStatus::Connecting(_) => return Ok(()), // Server is not ready
-            ConnStatus::Connected(server_connected) => server_connected,
-        };
-
-        let mut control_sender = match server_connected.opt_control_sender.take() {
-            Some(control_sender) => control_sender,
-            None => return Ok(()),
-        };
+        // Check if any server is ready:
+        let is_server_ready = self.sessions.iter().any(|conn_status| match conn_status {
+            ConnStatus::Empty(_) | ConnStatus::Connecting(_) => false,
+            ConnStatus::Connected(server_connected) => {
+                server_connected.opt_control_sender.is_some()
+            }
+        });
+        if !is_server_ready {
+            // Keep the mutations until we connect to a server:
+            for mutation in mutations {
+                self.pending_mutations.push(mutation);
+            }
+            return self.save_pending_mutations().await;
+        }
 
         // Append to mutations a state of a friend chosen sequentially.
         // Maybe in the future we will find a better way to do this.
@@ -539,18 +864,135 @@ where
             mutations.push(IndexMutation::UpdateFriendCurrency(update_friend_currency));
         }
 
-        if let Ok(()) = control_sender
-            .send(SingleClientControl::SendMutations(mutations))
-            .await
-        {
-            server_connected.opt_control_sender = Some(control_sender);
+        for conn_status in &mut self.sessions {
+            let server_connected = match conn_status {
+                ConnStatus::Empty(_) | ConnStatus::Connecting(_) => continue,
+                ConnStatus::Connected(server_connected) => server_connected,
+            };
+
+            let mut control_sender = match server_connected.opt_control_sender.take() {
+                Some(control_sender) => control_sender,
+                None => continue,
+            };
+
+            if let Ok(()) = control_sender
+                .send(SingleClientControl::SendMutations(mutations.clone()))
+                .await
+            {
+                server_connected.opt_control_sender = Some(control_sender);
+            }
+            // Reset ticks_to_send_keepalive:
+            server_connected.ticks_to_send_keepalive = self.keepalive_ticks;
         }
-        // Reset ticks_to_send_keepalive:
-        server_connected.ticks_to_send_keepalive = self.keepalive_ticks;
 
         Ok(())
     }
 
+    /// Save the pending mutations to the database, so that they survive a restart.
+    async fn save_pending_mutations(&mut self) -> Result<(), IndexClientError> {
+        self.db_client
+            .mutate(vec![IndexClientConfigMutation::SetPendingMutations(
+                self.pending_mutations.to_vec(),
+            )])
+            .await
+            .map_err(|_| IndexClientError::DatabaseError)
+    }
+
+    pub async fn handle_from_app_server_set_keepalive_ticks(
+        &mut self,
+        app_request_id: Uid,
+        keepalive_ticks: usize,
+    ) -> Result<(), IndexClientError> {
+        if keepalive_ticks == 0 {
+            warn!("SetKeepaliveTicks: keepalive_ticks must be positive. Ignoring.");
+        } else {
+            self.keepalive_ticks = keepalive_ticks;
+            // Apply to the current connections:
+            for conn_status in &mut self.sessions {
+                if let ConnStatus::Connected(server_connected) = conn_status {
+                    server_connected.ticks_to_send_keepalive = std::cmp::min(
+                        server_connected.ticks_to_send_keepalive,
+                        self.keepalive_ticks,
+                    );
+                }
+            }
+        }
+
+        // Send empty report (Indicates that we received the request):
+        let index_client_report_mutations = IndexClientReportMutations {
+            opt_app_request_id: Some(app_request_id),
+            mutations: Vec::new(),
+        };
+        self.to_app_server
+            .send(IndexClientToAppServer::ReportMutations(
+                index_client_report_mutations,
+            ))
+            .await
+            .map_err(|_| IndexClientError::SendToAppServerFailed)
+    }
+
+    pub async fn handle_from_app_server_send_friend_proposal(
+        &mut self,
+        app_request_id: Uid,
+        send_friend_proposal: SendFriendProposal,
+    ) -> Result<(), IndexClientError> {
+        // Send the proposal through all the connected servers, to improve the chance of delivery:
+        let mut is_sent = false;
+        for conn_status in &mut self.sessions {
+            let server_connected = match conn_status {
+                ConnStatus::Empty(_) | ConnStatus::Connecting(_) => continue,
+                ConnStatus::Connected(server_connected) => server_connected,
+            };
+
+            let mut control_sender = match server_connected.opt_control_sender.take() {
+                Some(control_sender) => control_sender,
+                None => continue,
+            };
+
+            let single_client_control =
+                SingleClientControl::SendFriendProposal(send_friend_proposal.clone());
+            if let Ok(()) = control_sender.send(single_client_control).await {
+                server_connected.opt_control_sender = Some(control_sender);
+                is_sent = true;
+            }
+        }
+
+        if !is_sent {
+            warn!("SendFriendProposal: Not connected to any index server");
+        }
+
+        // Send empty report (Indicates that we received the request):
+        let index_client_report_mutations = IndexClientReportMutations {
+            opt_app_request_id: Some(app_request_id),
+            mutations: Vec::new(),
+        };
+        self.to_app_server
+            .send(IndexClientToAppServer::ReportMutations(
+                index_client_report_mutations,
+            ))
+            .await
+            .map_err(|_| IndexClientError::SendToAppServerFailed)
+    }
+
+    pub async fn handle_from_app_server_remove_friend_proposal(
+        &mut self,
+        app_request_id: Uid,
+        public_key: PublicKey,
+    ) -> Result<(), IndexClientError> {
+        self.friend_proposals.remove(&public_key);
+
+        let index_client_report_mutations = IndexClientReportMutations {
+            opt_app_request_id: Some(app_request_id),
+            mutations: vec![IndexClientReportMutation::RemoveFriendProposal(public_key)],
+        };
+        self.to_app_server
+            .send(IndexClientToAppServer::ReportMutations(
+                index_client_report_mutations,
+            ))
+            .await
+            .map_err(|_| IndexClientError::SendToAppServerFailed)
+    }
+
     pub async fn handle_from_app_server(
         &mut self,
         app_server_to_index_client: AppServerToIndexClient<ISA>,
@@ -573,6 +1015,27 @@ where
                         self.handle_from_app_server_request_routes(app_request_id, request_routes)
                             .await
                     }
+                    IndexClientRequest::SetKeepaliveTicks(keepalive_ticks) => {
+                        self.handle_from_app_server_set_keepalive_ticks(
+                            app_request_id,
+                            keepalive_ticks,
+                        )
+                        .await
+                    }
+                    IndexClientRequest::SendFriendProposal(send_friend_proposal) => {
+                        self.handle_from_app_server_send_friend_proposal(
+                            app_request_id,
+                            send_friend_proposal,
+                        )
+                        .await
+                    }
+                    IndexClientRequest::RemoveFriendProposal(public_key) => {
+                        self.handle_from_app_server_remove_friend_proposal(
+                            app_request_id,
+                            public_key,
+                        )
+                        .await
+                    }
                 }
             }
             AppServerToIndexClient::ApplyMutations(mutations) => {
@@ -583,9 +1046,10 @@ where
 
     pub async fn handle_index_server_connected(
         &mut self,
-        control_sender: ControlSender,
+        session_index: usize,
+        mut control_sender: ControlSender,
     ) -> Result<(), IndexClientError> {
-        let (index_server, opt_cancel_sender) = match &mut self.conn_status {
+        let (index_server, opt_cancel_sender) = match &mut self.sessions[session_index] {
             ConnStatus::Empty(_) => {
                 error!("Did not attempt to connect!");
                 return Ok(());
@@ -600,13 +1064,35 @@ where
             ),
         };
 
-        self.conn_status = ConnStatus::Connected(ServerConnected {
+        self.sessions[session_index] = ConnStatus::Connected(ServerConnected {
             index_server: index_server.clone(),
             opt_control_sender: Some(control_sender.clone()),
             opt_cancel_sender,
             ticks_to_send_keepalive: self.keepalive_ticks,
+            ticks_to_request_status: SERVER_STATUS_TICKS,
         });
 
+        // Send the mutations generated while we were not connected to any server:
+        if !self.pending_mutations.is_empty() {
+            if control_sender
+                .send(SingleClientControl::SendMutations(
+                    self.pending_mutations.to_vec(),
+                ))
+                .await
+                .is_ok()
+            {
+                self.pending_mutations.clear();
+                self.save_pending_mutations().await?;
+            }
+        }
+
+        // Only one connected server is reported. Other servers are reported only after the
+        // reported server is disconnected:
+        if self.opt_reported_server.is_some() {
+            return Ok(());
+        }
+        self.opt_reported_server = Some(index_server.public_key.clone());
+
         // Send report:
         let index_client_report_mutation =
             IndexClientReportMutation::SetConnectedServer(Some(index_server.public_key.clone()));
@@ -624,10 +1110,54 @@ where
         Ok(())
     }
 
-    pub async fn handle_index_server_closed(&mut self) -> Result<(), IndexClientError> {
-        if let ConnStatus::Connected(_) = self.conn_status {
+    pub async fn handle_index_server_closed(
+        &mut self,
+        session_index: usize,
+        opt_resume_session: Option<ResumeSession>,
+    ) -> Result<(), IndexClientError> {
+        let opt_closed_server = match &self.sessions[session_index] {
+            ConnStatus::Empty(_) | ConnStatus::Connecting(_) => None,
+            ConnStatus::Connected(server_connected) => {
+                Some(server_connected.index_server.public_key.clone())
+            }
+        };
+        self.sessions[session_index] = ConnStatus::Empty(self.backoff_ticks);
+
+        // Remember the session, in case we reconnect to the same server soon:
+        if let (Some(closed_server), Some(resume_session)) =
+            (&opt_closed_server, opt_resume_session)
+        {
+            if self
+                .index_servers
+                .iter()
+                .any(|index_server| &index_server.public_key == closed_server)
+            {
+                self.resumable_sessions.insert(
+                    closed_server.clone(),
+                    ResumableSession {
+                        resume_session,
+                        ticks_left: INDEX_SESSION_RESUME_TICKS,
+                    },
+                );
+            }
+        }
+
+        if opt_closed_server.is_some() && opt_closed_server == self.opt_reported_server {
+            // Report another connected server, if we have one:
+            let opt_connected_server =
+                self.sessions
+                    .iter()
+                    .find_map(|conn_status| match conn_status {
+                        ConnStatus::Empty(_) | ConnStatus::Connecting(_) => None,
+                        ConnStatus::Connected(server_connected) => {
+                            Some(server_connected.index_server.public_key.clone())
+                        }
+                    });
+            self.opt_reported_server = opt_connected_server.clone();
+
             // Send report:
-            let index_client_report_mutation = IndexClientReportMutation::SetConnectedServer(None);
+            let index_client_report_mutation =
+                IndexClientReportMutation::SetConnectedServer(opt_connected_server);
             let index_client_report_mutations = IndexClientReportMutations {
                 opt_app_request_id: None,
                 mutations: vec![index_client_report_mutation],
@@ -639,19 +1169,24 @@ where
                 .await
                 .map_err(|_| IndexClientError::SendToAppServerFailed)?;
         }
-        self.conn_status = ConnStatus::Empty(self.backoff_ticks);
         Ok(())
     }
 
     pub async fn handle_response_routes(
         &mut self,
-        request_id: Uid,
-        response_routes_result: ResponseRoutesResult,
+        request_routes: RequestRoutes,
+        mut response_routes_result: ResponseRoutesResult,
     ) -> Result<(), IndexClientError> {
         self.num_open_requests = self.num_open_requests.checked_sub(1).unwrap();
 
+        if let ResponseRoutesResult::Success(multi_routes) = &mut response_routes_result {
+            self.route_cache
+                .insert(&request_routes, multi_routes.clone());
+            self.route_rotation.rotate(&request_routes, multi_routes);
+        }
+
         let client_response_routes = ClientResponseRoutes {
-            request_id,
+            request_id: request_routes.request_id,
             result: response_routes_result,
         };
 
@@ -663,14 +1198,207 @@ where
             .map_err(|_| IndexClientError::SendToAppServerFailed)
     }
 
+    /// Save the capacity statistics to the database, once every CAPACITY_STATS_SAVE_TICKS ticks.
+    /// A verified friend proposal was received through one of the sessions.
+    /// A newer proposal from the same node replaces the older one.
+    pub fn handle_server_status(
+        &mut self,
+        public_key: PublicKey,
+        response_server_status: ResponseServerStatus,
+    ) {
+        // The server might have been removed while we were waiting for its status:
+        if self
+            .index_servers
+            .iter()
+            .any(|index_server| index_server.public_key == public_key)
+        {
+            self.server_statuses
+                .insert(public_key, response_server_status);
+        }
+    }
+
+    /// An index server directory was received from one of the servers.
+    /// If the directory is signed by the directory key and is newer than the last directory we
+    /// have applied, we replace our list of index servers with the list in the directory.
+    pub async fn handle_directory(
+        &mut self,
+        directory: IndexServerDirectory,
+    ) -> Result<(), IndexClientError> {
+        let directory_public_key = match &self.opt_directory_public_key {
+            Some(directory_public_key) => directory_public_key,
+            None => return Ok(()),
+        };
+        if !verify_index_server_directory(&directory, directory_public_key) {
+            warn!("Received an index server directory with an invalid signature");
+            return Ok(());
+        }
+        if directory.version <= self.directory_version {
+            // We already have this directory (Or a newer one):
+            return Ok(());
+        }
+        if directory.index_servers.is_empty() {
+            // Keep our current servers, instead of losing contact with the index altogether:
+            warn!("Received an empty index server directory. Ignoring.");
+            return Ok(());
+        }
+
+        let directory_servers = directory
+            .index_servers
+            .into_iter()
+            .map(|named_index_server_address| NamedIndexServerAddress {
+                public_key: named_index_server_address.public_key,
+                address: ISA::from(named_index_server_address.address),
+                name: named_index_server_address.name,
+            })
+            .collect::<Vec<_>>();
+
+        let (removed_servers, added_servers) =
+            directory_changes(&self.index_servers, &directory_servers);
+        for public_key in removed_servers {
+            self.remove_index_server(None, public_key).await?;
+        }
+        for named_index_server_address in added_servers {
+            self.add_index_server(None, named_index_server_address)
+                .await?;
+        }
+
+        self.directory_version = directory.version;
+        self.db_client
+            .mutate(vec![IndexClientConfigMutation::SetDirectoryVersion(
+                directory.version,
+            )])
+            .await
+            .map_err(|_| IndexClientError::DatabaseError)
+    }
+
+    /// Request the index server directory from one of the connected servers, once every
+    /// DIRECTORY_REFRESH_TICKS ticks.
+    async fn tick_request_directory(&mut self) -> Result<(), IndexClientError> {
+        if self.opt_directory_public_key.is_none() {
+            return Ok(());
+        }
+        self.ticks_to_request_directory = self.ticks_to_request_directory.saturating_sub(1);
+        if self.ticks_to_request_directory != 0 {
+            return Ok(());
+        }
+
+        // If we are not connected to any server, we try again on the next tick:
+        let opt_control_sender = self
+            .sessions
+            .iter()
+            .find_map(|conn_status| match conn_status {
+                ConnStatus::Empty(_) | ConnStatus::Connecting(_) => None,
+                ConnStatus::Connected(server_connected) => {
+                    server_connected.opt_control_sender.clone()
+                }
+            });
+        let mut control_sender = match opt_control_sender {
+            Some(control_sender) => control_sender,
+            None => return Ok(()),
+        };
+        self.ticks_to_request_directory = DIRECTORY_REFRESH_TICKS;
+
+        let (response_sender, response_receiver) = oneshot::channel();
+        if control_sender
+            .send(SingleClientControl::RequestDirectory(response_sender))
+            .await
+            .is_err()
+        {
+            return Ok(());
+        }
+
+        let mut c_event_sender = self.event_sender.clone();
+        let directory_fut = async move {
+            if let Ok(Some(directory)) = response_receiver.await {
+                let _ = c_event_sender
+                    .send(IndexClientEvent::Directory(directory))
+                    .await;
+            }
+        };
+        self.spawner
+            .spawn(directory_fut)
+            .map_err(|_| IndexClientError::SpawnError)
+    }
+
+    pub async fn handle_friend_proposal(
+        &mut self,
+        friend_proposal: FriendProposal,
+    ) -> Result<(), IndexClientError> {
+        if !self
+            .friend_proposals
+            .contains(&friend_proposal.src_public_key)
+            && self.friend_proposals.len() >= MAX_FRIEND_PROPOSALS
+        {
+            warn!("Friend proposals inbox is full. Dropping proposal.");
+            return Ok(());
+        }
+        self.friend_proposals
+            .insert(friend_proposal.src_public_key.clone());
+
+        let index_client_report_mutations = IndexClientReportMutations {
+            opt_app_request_id: None,
+            mutations: vec![IndexClientReportMutation::AddFriendProposal(
+                friend_proposal,
+            )],
+        };
+        self.to_app_server
+            .send(IndexClientToAppServer::ReportMutations(
+                index_client_report_mutations,
+            ))
+            .await
+            .map_err(|_| IndexClientError::SendToAppServerFailed)
+    }
+
+    async fn tick_save_capacity_stats(&mut self) -> Result<(), IndexClientError> {
+        self.ticks_to_save_capacity_stats = self.ticks_to_save_capacity_stats.saturating_sub(1);
+        if self.ticks_to_save_capacity_stats != 0 {
+            return Ok(());
+        }
+        self.ticks_to_save_capacity_stats = CAPACITY_STATS_SAVE_TICKS;
+
+        if let Some(friend_capacity_stats) = self.capacity_smoother.stats() {
+            self.db_client
+                .mutate(vec![IndexClientConfigMutation::SetFriendCapacityStats(
+                    friend_capacity_stats,
+                )])
+                .await
+                .map_err(|_| IndexClientError::DatabaseError)?;
+        }
+        Ok(())
+    }
+
     pub async fn handle_timer_tick(&mut self) -> Result<(), IndexClientError> {
-        // Make sure that we are connected to any server:
-        let server_connected: &mut ServerConnected<ISA> = match self.conn_status {
+        self.route_cache.tick();
+        self.route_rotation.tick();
+        self.resumable_sessions
+            .retain(|_public_key, resumable_session| {
+                resumable_session.ticks_left = resumable_session.ticks_left.saturating_sub(1);
+                resumable_session.ticks_left > 0
+            });
+
+        let smoothed_mutations = self.capacity_smoother.tick();
+        if !smoothed_mutations.is_empty() {
+            self.apply_mutations(smoothed_mutations).await?;
+        }
+        self.tick_save_capacity_stats().await?;
+        self.tick_request_directory().await?;
+
+        for session_index in 0..self.sessions.len() {
+            self.tick_session(session_index).await?;
+        }
+        Ok(())
+    }
+
+    /// Reconnect the session `session_index` if needed, or send a keepalive (or a status request)
+    /// to its server.
+    async fn tick_session(&mut self, session_index: usize) -> Result<(), IndexClientError> {
+        // Make sure that we are connected to a server:
+        let server_connected: &mut ServerConnected<ISA> = match self.sessions[session_index] {
             ConnStatus::Empty(ref mut ticks_to_reconnect) => {
                 // Backoff mechanism for reconnection, so that we don't DoS the index servers.
                 *ticks_to_reconnect = (*ticks_to_reconnect).saturating_sub(1);
                 if *ticks_to_reconnect == 0 {
-                    self.try_connect_to_server()?;
+                    self.try_connect_to_server(session_index)?;
                 }
                 return Ok(());
             }
@@ -683,6 +1411,38 @@ where
             None => return Ok(()), // Not connected to server
         };
 
+        server_connected.ticks_to_request_status =
+            server_connected.ticks_to_request_status.saturating_sub(1);
+
+        if server_connected.ticks_to_request_status == 0 {
+            server_connected.ticks_to_request_status = SERVER_STATUS_TICKS;
+
+            let (response_sender, response_receiver) = oneshot::channel();
+            if control_sender
+                .send(SingleClientControl::RequestServerStatus(response_sender))
+                .await
+                .is_err()
+            {
+                return Ok(());
+            }
+
+            let server_public_key = server_connected.index_server.public_key.clone();
+            let mut c_event_sender = self.event_sender.clone();
+            let status_fut = async move {
+                if let Ok(response_server_status) = response_receiver.await {
+                    let _ = c_event_sender
+                        .send(IndexClientEvent::ServerStatus((
+                            server_public_key,
+                            response_server_status,
+                        )))
+                        .await;
+                }
+            };
+            self.spawner
+                .spawn(status_fut)
+                .map_err(|_| IndexClientError::SpawnError)?;
+        }
+
         server_connected.ticks_to_send_keepalive =
             server_connected.ticks_to_send_keepalive.saturating_sub(1);
 
@@ -723,27 +1483,34 @@ where
     }
 }
 
-pub async fn index_client_loop<ISA, FAS, TAS, ICS, TS, S>(
+pub async fn index_client_loop<ISA, FAS, TAS, ICS, IFP, TS, S>(
     from_app_server: FAS,
     to_app_server: TAS,
     index_client_config: IndexClientConfig<ISA>,
     seq_friends_client: SeqFriendsClient,
     index_client_session: ICS,
     max_open_requests: usize,
+    route_cache_ticks: usize,
+    route_rotation: bool,
+    capacity_smoothing: bool,
     keepalive_ticks: usize,
     backoff_ticks: usize,
+    max_sessions: usize,
+    opt_directory_public_key: Option<PublicKey>,
     db_client: DatabaseClient<IndexClientConfigMutation<ISA>>,
+    incoming_friend_proposals: IFP,
     timer_stream: TS,
     spawner: S,
 ) -> Result<(), IndexClientError>
 where
-    ISA: Debug + Eq + Clone + Send + 'static,
+    ISA: Debug + Eq + Clone + Send + From<NetAddress> + 'static,
     FAS: Stream<Item = AppServerToIndexClient<ISA>> + Send + Unpin,
     TAS: Sink<IndexClientToAppServer<ISA>> + Unpin,
     ICS: FutTransform<Input = IndexServerAddress<ISA>, Output = Option<SessionHandle>>
         + Clone
         + Send
         + 'static,
+    IFP: Stream<Item = FriendProposal> + Send + Unpin,
     TS: Stream + Send + Unpin,
     S: Spawn + Clone + Send + 'static,
 {
@@ -755,13 +1522,18 @@ where
         seq_friends_client,
         index_client_session,
         max_open_requests,
+        route_cache_ticks,
+        route_rotation,
+        capacity_smoothing,
         keepalive_ticks,
         backoff_ticks,
+        max_sessions,
+        opt_directory_public_key,
         db_client,
         spawner,
     );
 
-    index_client.try_connect_to_server()?;
+    index_client.try_connect_to_servers()?;
 
     let timer_stream = timer_stream.map(|_| IndexClientEvent::TimerTick);
 
@@ -773,7 +1545,14 @@ where
             IndexClientEvent::AppServerClosed,
         )));
 
-    let mut events = select_streams![event_receiver, from_app_server, timer_stream];
+    let incoming_friend_proposals = incoming_friend_proposals.map(IndexClientEvent::FriendProposal);
+
+    let mut events = select_streams![
+        event_receiver,
+        from_app_server,
+        incoming_friend_proposals,
+        timer_stream
+    ];
 
     while let Some(event) = events.next().await {
         match event {
@@ -783,19 +1562,30 @@ where
                     .await?
             }
             IndexClientEvent::AppServerClosed => return Err(IndexClientError::AppServerClosed),
-            IndexClientEvent::IndexServerConnected(control_sender) => {
+            IndexClientEvent::IndexServerConnected((session_index, control_sender)) => {
                 index_client
-                    .handle_index_server_connected(control_sender)
+                    .handle_index_server_connected(session_index, control_sender)
                     .await?
             }
-            IndexClientEvent::IndexServerClosed => {
-                index_client.handle_index_server_closed().await?
+            IndexClientEvent::IndexServerClosed((session_index, opt_resume_session)) => {
+                index_client
+                    .handle_index_server_closed(session_index, opt_resume_session)
+                    .await?
             }
-            IndexClientEvent::ResponseRoutes((request_id, response_routes_result)) => {
+            IndexClientEvent::ResponseRoutes((request_routes, response_routes_result)) => {
                 index_client
-                    .handle_response_routes(request_id, response_routes_result)
+                    .handle_response_routes(request_routes, response_routes_result)
                     .await?
             }
+            IndexClientEvent::ServerStatus((public_key, response_server_status)) => {
+                index_client.handle_server_status(public_key, response_server_status)
+            }
+            IndexClientEvent::Directory(directory) => {
+                index_client.handle_directory(directory).await?
+            }
+            IndexClientEvent::FriendProposal(friend_proposal) => {
+                index_client.handle_friend_proposal(friend_proposal).await?
+            }
             IndexClientEvent::TimerTick => index_client.handle_timer_tick().await?,
         };
     }
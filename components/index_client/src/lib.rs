@@ -20,8 +20,13 @@ extern crate quickcheck_derive;
 #[macro_use]
 extern crate common;
 
+mod capacity_smoother;
 mod client_session;
 mod index_client;
+mod pending_mutations;
+mod route_cache;
+mod route_merge;
+mod route_rotation;
 mod seq_friends;
 mod seq_map;
 mod single_client;
@@ -30,5 +35,6 @@ mod spawn;
 #[cfg(test)]
 mod tests;
 
+pub use self::capacity_smoother::FriendCapacityStats;
 pub use self::index_client::{IndexClientConfig, IndexClientConfigMutation, IndexClientError};
 pub use self::spawn::{spawn_index_client, SpawnIndexClientError};
@@ -0,0 +1,163 @@
+use std::collections::VecDeque;
+
+use proto::crypto::PublicKey;
+use proto::funder::messages::Currency;
+use proto::index_server::messages::IndexMutation;
+
+/// The friend and currency a mutation applies to.
+fn mutation_key(mutation: &IndexMutation) -> (&PublicKey, &Currency) {
+    match mutation {
+        IndexMutation::UpdateFriendCurrency(update_friend_currency) => (
+            &update_friend_currency.public_key,
+            &update_friend_currency.currency,
+        ),
+        IndexMutation::RemoveFriendCurrency(remove_friend_currency) => (
+            &remove_friend_currency.public_key,
+            &remove_friend_currency.currency,
+        ),
+    }
+}
+
+/// Mutations generated while we were not connected to any index server.
+/// The mutations are sent to the first index server we connect to.
+///
+/// Only the latest mutation of every friend and currency is kept, as it overrides all the
+/// previous mutations. At most `max_mutations` mutations are kept. When the queue is full, the
+/// oldest mutation is discarded.
+#[derive(Debug)]
+pub struct PendingMutations {
+    max_mutations: usize,
+    mutations: VecDeque<IndexMutation>,
+}
+
+impl PendingMutations {
+    pub fn new(max_mutations: usize, mutations: Vec<IndexMutation>) -> Self {
+        let mut pending_mutations = PendingMutations {
+            max_mutations,
+            mutations: VecDeque::new(),
+        };
+        for mutation in mutations {
+            pending_mutations.push(mutation);
+        }
+        pending_mutations
+    }
+
+    pub fn push(&mut self, mutation: IndexMutation) {
+        let key = mutation_key(&mutation);
+        if let Some(index) = self
+            .mutations
+            .iter()
+            .position(|pending_mutation| mutation_key(pending_mutation) == key)
+        {
+            let _ = self.mutations.remove(index);
+        }
+
+        if self.mutations.len() >= self.max_mutations {
+            warn!("Pending index mutations queue is full. Discarding oldest mutation.");
+            let _ = self.mutations.pop_front();
+        }
+        self.mutations.push_back(mutation);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mutations.is_empty()
+    }
+
+    /// The pending mutations, from the oldest to the newest.
+    pub fn to_vec(&self) -> Vec<IndexMutation> {
+        self.mutations.iter().cloned().collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.mutations.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use proto::funder::messages::Rate;
+    use proto::index_server::messages::{RemoveFriendCurrency, UpdateFriendCurrency};
+
+    fn pk(seed: u8) -> PublicKey {
+        PublicKey::from(&[seed; PublicKey::len()])
+    }
+
+    fn update(seed: u8, currency: &Currency, recv_capacity: u128) -> IndexMutation {
+        IndexMutation::UpdateFriendCurrency(UpdateFriendCurrency {
+            public_key: pk(seed),
+            currency: currency.clone(),
+            recv_capacity,
+            rate: Rate { mul: 0, add: 1 },
+        })
+    }
+
+    fn remove(seed: u8, currency: &Currency) -> IndexMutation {
+        IndexMutation::RemoveFriendCurrency(RemoveFriendCurrency {
+            public_key: pk(seed),
+            currency: currency.clone(),
+        })
+    }
+
+    #[test]
+    fn test_pending_mutations_coalesce() {
+        let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+        let currency2 = Currency::try_from("FST2".to_owned()).unwrap();
+
+        let mut pending_mutations = PendingMutations::new(8, Vec::new());
+        assert!(pending_mutations.is_empty());
+
+        pending_mutations.push(update(1, &currency1, 10));
+        pending_mutations.push(update(1, &currency2, 20));
+        pending_mutations.push(update(2, &currency1, 30));
+        // Overrides the first update:
+        pending_mutations.push(update(1, &currency1, 40));
+        // Overrides the update of friend 2:
+        pending_mutations.push(remove(2, &currency1));
+
+        assert_eq!(
+            pending_mutations.to_vec(),
+            vec![
+                update(1, &currency2, 20),
+                update(1, &currency1, 40),
+                remove(2, &currency1),
+            ]
+        );
+
+        pending_mutations.clear();
+        assert!(pending_mutations.is_empty());
+    }
+
+    #[test]
+    fn test_pending_mutations_bounded() {
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+
+        let mutations = (0..4u8)
+            .map(|seed| update(seed, &currency, 10))
+            .collect::<Vec<_>>();
+        let mut pending_mutations = PendingMutations::new(3, mutations);
+        // The oldest mutation was discarded:
+        assert_eq!(
+            pending_mutations.to_vec(),
+            vec![
+                update(1, &currency, 10),
+                update(2, &currency, 10),
+                update(3, &currency, 10),
+            ]
+        );
+
+        // Overriding a mutation does not discard other mutations:
+        pending_mutations.push(update(1, &currency, 20));
+        assert_eq!(
+            pending_mutations.to_vec(),
+            vec![
+                update(2, &currency, 10),
+                update(3, &currency, 10),
+                update(1, &currency, 20),
+            ]
+        );
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+
+use proto::crypto::PublicKey;
+use proto::funder::messages::{Currency, FriendsRoute};
+use proto::index_server::messages::{
+    Edge, IndexMutation, MultiRoute, RequestRoutes, RouteConstraints, RouteRanking,
+};
+
+/// Key for a cached routes request.
+/// Requested capacities are grouped into buckets of powers of 2, so that similar requests
+/// share the same cached routes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct RouteCacheKey {
+    currency: Currency,
+    source: PublicKey,
+    destination: PublicKey,
+    opt_exclude: Option<Edge>,
+    constraints: RouteConstraints,
+    ranking: RouteRanking,
+    capacity_bucket: u32,
+}
+
+impl RouteCacheKey {
+    fn new(request_routes: &RequestRoutes) -> Self {
+        RouteCacheKey {
+            currency: request_routes.currency.clone(),
+            source: request_routes.source.clone(),
+            destination: request_routes.destination.clone(),
+            opt_exclude: request_routes.opt_exclude.clone(),
+            constraints: request_routes.constraints.clone(),
+            ranking: request_routes.ranking.clone(),
+            capacity_bucket: capacity_bucket(request_routes.capacity),
+        }
+    }
+}
+
+/// The amount of significant bits in the capacity.
+fn capacity_bucket(capacity: u128) -> u32 {
+    128 - capacity.leading_zeros()
+}
+
+#[derive(Debug)]
+struct RouteCacheEntry {
+    multi_routes: Vec<MultiRoute>,
+    ticks_left: usize,
+}
+
+/// Total capacity of the routes of a multi route
+fn multi_route_capacity(multi_route: &MultiRoute) -> u128 {
+    multi_route
+        .routes
+        .iter()
+        .fold(0u128, |total, route_capacity_rate| {
+            total.saturating_add(route_capacity_rate.capacity)
+        })
+}
+
+/// Does the route pass through the edge (a, b) (In any direction)?
+fn route_uses_edge(route: &FriendsRoute, a: &PublicKey, b: &PublicKey) -> bool {
+    route
+        .public_keys
+        .windows(2)
+        .any(|pair| (&pair[0] == a && &pair[1] == b) || (&pair[0] == b && &pair[1] == a))
+}
+
+/// Recently received routes from index servers.
+/// Allows to answer similar routes requests without querying the index servers again.
+#[derive(Debug)]
+pub struct RouteCache {
+    entries: HashMap<RouteCacheKey, RouteCacheEntry>,
+    /// Amount of ticks routes are kept in the cache. 0 disables the cache.
+    ttl_ticks: usize,
+}
+
+impl RouteCache {
+    pub fn new(ttl_ticks: usize) -> Self {
+        RouteCache {
+            entries: HashMap::new(),
+            ttl_ticks,
+        }
+    }
+
+    /// Get cached routes for a request (If exist).
+    /// Requests in the same capacity bucket may ask for more capacity than the request the
+    /// routes were cached for, so only multi routes that can carry the requested capacity are
+    /// returned. If none of the cached multi routes can, this is a cache miss.
+    pub fn get(&self, request_routes: &RequestRoutes) -> Option<Vec<MultiRoute>> {
+        let entry = self.entries.get(&RouteCacheKey::new(request_routes))?;
+        let multi_routes = entry
+            .multi_routes
+            .iter()
+            .filter(|multi_route| multi_route_capacity(multi_route) >= request_routes.capacity)
+            .cloned()
+            .collect::<Vec<_>>();
+        if multi_routes.is_empty() {
+            None
+        } else {
+            Some(multi_routes)
+        }
+    }
+
+    /// Cache routes received for a request.
+    /// Empty responses are not cached, as new routes may show up at any time.
+    pub fn insert(&mut self, request_routes: &RequestRoutes, multi_routes: Vec<MultiRoute>) {
+        if self.ttl_ticks == 0 || multi_routes.is_empty() {
+            return;
+        }
+        let entry = RouteCacheEntry {
+            multi_routes,
+            ticks_left: self.ttl_ticks,
+        };
+        self.entries
+            .insert(RouteCacheKey::new(request_routes), entry);
+    }
+
+    /// Remove cached routes that go through a local friend whose capacity has changed.
+    pub fn apply_mutation(&mut self, mutation: &IndexMutation) {
+        let (friend_public_key, currency) = match mutation {
+            IndexMutation::UpdateFriendCurrency(update_friend_currency) => (
+                &update_friend_currency.public_key,
+                &update_friend_currency.currency,
+            ),
+            IndexMutation::RemoveFriendCurrency(remove_friend_currency) => (
+                &remove_friend_currency.public_key,
+                &remove_friend_currency.currency,
+            ),
+        };
+
+        self.entries.retain(|key, entry| {
+            if &key.currency != currency {
+                return true;
+            }
+            // The local node is the source of the routes:
+            !entry
+                .multi_routes
+                .iter()
+                .flat_map(|multi_route| multi_route.routes.iter())
+                .any(|route_capacity_rate| {
+                    route_uses_edge(&route_capacity_rate.route, &key.source, friend_public_key)
+                })
+        });
+    }
+
+    /// Handle a time tick. Expired routes are removed.
+    pub fn tick(&mut self) {
+        for entry in self.entries.values_mut() {
+            entry.ticks_left = entry.ticks_left.saturating_sub(1);
+        }
+        self.entries.retain(|_, entry| entry.ticks_left > 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use proto::crypto::Uid;
+    use proto::funder::messages::Rate;
+    use proto::index_server::messages::{
+        RemoveFriendCurrency, RouteCapacityRate, UpdateFriendCurrency,
+    };
+
+    fn pk(seed: u8) -> PublicKey {
+        PublicKey::from(&[seed; PublicKey::len()])
+    }
+
+    fn request_routes(currency: &Currency, capacity: u128) -> RequestRoutes {
+        RequestRoutes {
+            request_id: Uid::from(&[0; Uid::len()]),
+            currency: currency.clone(),
+            capacity,
+            source: pk(0),
+            destination: pk(3),
+            opt_exclude: None,
+            constraints: RouteConstraints::default(),
+            ranking: RouteRanking::default(),
+        }
+    }
+
+    fn multi_routes() -> Vec<MultiRoute> {
+        vec![MultiRoute {
+            routes: vec![RouteCapacityRate {
+                route: FriendsRoute {
+                    public_keys: vec![pk(0), pk(1), pk(2), pk(3)],
+                },
+                capacity: 100,
+                rate: Rate { mul: 0, add: 1 },
+            }],
+        }]
+    }
+
+    #[test]
+    fn test_capacity_bucket() {
+        assert_eq!(capacity_bucket(0), 0);
+        assert_eq!(capacity_bucket(1), 1);
+        assert_eq!(capacity_bucket(2), 2);
+        assert_eq!(capacity_bucket(3), 2);
+        assert_eq!(capacity_bucket(4), 3);
+        assert_eq!(capacity_bucket(u128::max_value()), 128);
+    }
+
+    #[test]
+    fn test_route_cache_get_insert_expire() {
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let mut route_cache = RouteCache::new(2);
+
+        route_cache.insert(&request_routes(&currency, 40), multi_routes());
+        // Capacities in the same bucket share the cached routes:
+        assert_eq!(
+            route_cache.get(&request_routes(&currency, 50)),
+            Some(multi_routes())
+        );
+        assert_eq!(route_cache.get(&request_routes(&currency, 70)), None);
+
+        // Empty responses are not cached:
+        route_cache.insert(&request_routes(&currency, 70), Vec::new());
+        assert_eq!(route_cache.get(&request_routes(&currency, 70)), None);
+
+        route_cache.tick();
+        assert!(route_cache.get(&request_routes(&currency, 40)).is_some());
+        route_cache.tick();
+        assert!(route_cache.get(&request_routes(&currency, 40)).is_none());
+    }
+
+    #[test]
+    fn test_route_cache_get_filters_capacity() {
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let mut route_cache = RouteCache::new(8);
+
+        let mut cached_multi_routes = multi_routes();
+        // A multi route of two routes, with a total capacity of 120:
+        cached_multi_routes.push(MultiRoute {
+            routes: vec![
+                RouteCapacityRate {
+                    route: FriendsRoute {
+                        public_keys: vec![pk(0), pk(1), pk(3)],
+                    },
+                    capacity: 60,
+                    rate: Rate { mul: 0, add: 1 },
+                },
+                RouteCapacityRate {
+                    route: FriendsRoute {
+                        public_keys: vec![pk(0), pk(2), pk(3)],
+                    },
+                    capacity: 60,
+                    rate: Rate { mul: 0, add: 1 },
+                },
+            ],
+        });
+        route_cache.insert(&request_routes(&currency, 70), cached_multi_routes.clone());
+
+        // Both multi routes can carry 100 credits:
+        assert_eq!(
+            route_cache.get(&request_routes(&currency, 100)),
+            Some(cached_multi_routes.clone())
+        );
+        // Only the second multi route can carry 110 credits:
+        assert_eq!(
+            route_cache.get(&request_routes(&currency, 110)),
+            Some(vec![cached_multi_routes[1].clone()])
+        );
+        // None of the multi routes can carry 127 credits (Same capacity bucket):
+        assert_eq!(route_cache.get(&request_routes(&currency, 127)), None);
+    }
+
+    #[test]
+    fn test_route_cache_disabled() {
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let mut route_cache = RouteCache::new(0);
+        route_cache.insert(&request_routes(&currency, 40), multi_routes());
+        assert_eq!(route_cache.get(&request_routes(&currency, 40)), None);
+    }
+
+    #[test]
+    fn test_route_cache_apply_mutation() {
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let other_currency = Currency::try_from("FST2".to_owned()).unwrap();
+        let mut route_cache = RouteCache::new(8);
+        route_cache.insert(&request_routes(&currency, 40), multi_routes());
+
+        // Mutations of other currencies or unrelated friends do not invalidate routes:
+        route_cache.apply_mutation(&IndexMutation::RemoveFriendCurrency(RemoveFriendCurrency {
+            public_key: pk(1),
+            currency: other_currency,
+        }));
+        route_cache.apply_mutation(&IndexMutation::RemoveFriendCurrency(RemoveFriendCurrency {
+            public_key: pk(5),
+            currency: currency.clone(),
+        }));
+        // The edge (1, 2) is not adjacent to the local node:
+        route_cache.apply_mutation(&IndexMutation::RemoveFriendCurrency(RemoveFriendCurrency {
+            public_key: pk(2),
+            currency: currency.clone(),
+        }));
+        assert!(route_cache.get(&request_routes(&currency, 40)).is_some());
+
+        route_cache.apply_mutation(&IndexMutation::UpdateFriendCurrency(UpdateFriendCurrency {
+            public_key: pk(1),
+            currency: currency.clone(),
+            recv_capacity: 10,
+            rate: Rate { mul: 0, add: 1 },
+        }));
+        assert!(route_cache.get(&request_routes(&currency, 40)).is_none());
+    }
+}
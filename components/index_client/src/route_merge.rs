@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use proto::crypto::PublicKey;
+use proto::index_server::messages::MultiRoute;
+
+/// The paths of all the routes of a multi route. Two multi routes with the same paths are
+/// considered the same multi route, possibly with different capacities.
+fn multi_route_paths(multi_route: &MultiRoute) -> Vec<Vec<PublicKey>> {
+    multi_route
+        .routes
+        .iter()
+        .map(|route_capacity_rate| route_capacity_rate.route.public_keys.clone())
+        .collect()
+}
+
+/// Merge routes responses received from multiple index servers for the same routes request.
+///
+/// `responses` must be ordered by their arrival time. Multi routes that appear in more than one
+/// response are returned only once, at the position of their first appearance. The capacities
+/// are taken from the last response, as it was computed last and contains the freshest capacity
+/// data.
+pub fn merge_multi_routes(responses: Vec<Vec<MultiRoute>>) -> Vec<MultiRoute> {
+    let mut merged: Vec<MultiRoute> = Vec::new();
+    let mut positions: HashMap<Vec<Vec<PublicKey>>, usize> = HashMap::new();
+
+    for multi_route in responses.into_iter().flatten() {
+        let paths = multi_route_paths(&multi_route);
+        match positions.get(&paths) {
+            Some(&position) => merged[position] = multi_route,
+            None => {
+                positions.insert(paths, merged.len());
+                merged.push(multi_route);
+            }
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proto::funder::messages::{FriendsRoute, Rate};
+    use proto::index_server::messages::RouteCapacityRate;
+
+    fn pk(seed: u8) -> PublicKey {
+        PublicKey::from(&[seed; PublicKey::len()])
+    }
+
+    /// A multi route containing a single route through the given mediator
+    fn multi_route(mediator: u8, capacity: u128) -> MultiRoute {
+        MultiRoute {
+            routes: vec![RouteCapacityRate {
+                route: FriendsRoute {
+                    public_keys: vec![pk(0), pk(mediator), pk(5)],
+                },
+                capacity,
+                rate: Rate { mul: 0, add: 1 },
+            }],
+        }
+    }
+
+    #[test]
+    fn test_merge_multi_routes_single_response() {
+        let response = vec![multi_route(1, 100), multi_route(2, 50)];
+        assert_eq!(merge_multi_routes(vec![response.clone()]), response);
+        assert!(merge_multi_routes(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn test_merge_multi_routes_dedup_freshest() {
+        let responses = vec![
+            vec![multi_route(1, 100), multi_route(2, 50)],
+            vec![multi_route(3, 70), multi_route(1, 80)],
+            vec![multi_route(2, 40)],
+        ];
+        assert_eq!(
+            merge_multi_routes(responses),
+            vec![multi_route(1, 80), multi_route(2, 40), multi_route(3, 70)]
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+
+use proto::crypto::PublicKey;
+use proto::funder::messages::Currency;
+use proto::index_server::messages::{MultiRoute, RequestRoutes};
+
+/// Amount of ticks without routes requests to a destination, after which the rotation position
+/// for this destination is forgotten.
+const ROTATION_IDLE_TICKS: usize = 0x100;
+
+/// Maximum amount of routes responses to the same destination that may start with a route through
+/// the same mediator. Routes through mediators over the limit are skipped, until all the returned
+/// routes pass through mediators over the limit. The usage of mediators is then counted again.
+const MAX_MEDIATOR_USES: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct RotationKey {
+    currency: Currency,
+    destination: PublicKey,
+}
+
+#[derive(Debug)]
+struct RotationEntry {
+    /// Amount of routes responses already returned for this destination
+    counter: usize,
+    ticks_left: usize,
+    /// Amount of routes responses that started with a route through each mediator
+    mediator_uses: HashMap<PublicKey, usize>,
+}
+
+/// Rotates the order of the routes returned for repeated routes requests to the same destination.
+///
+/// Applications usually pick the first routes they receive. Without rotation, repeated payments to
+/// the same destination all go through the same mediators, allowing those mediators to learn the
+/// full payment history between the local node and the destination.
+#[derive(Debug)]
+pub struct RouteRotation {
+    entries: HashMap<RotationKey, RotationEntry>,
+    enabled: bool,
+}
+
+/// Rotate a vector to the left by `counter` positions.
+fn rotate<T>(items: &mut Vec<T>, counter: usize) {
+    if !items.is_empty() {
+        let len = items.len();
+        items.rotate_left(counter % len);
+    }
+}
+
+/// All the mediators of a multi route (Excluding the source and destination of every route).
+fn mediators(multi_route: &MultiRoute) -> impl Iterator<Item = &PublicKey> {
+    multi_route.routes.iter().flat_map(|route_capacity_rate| {
+        let public_keys = &route_capacity_rate.route.public_keys;
+        public_keys
+            .iter()
+            .skip(1)
+            .take(public_keys.len().saturating_sub(2))
+    })
+}
+
+impl RouteRotation {
+    pub fn new(enabled: bool) -> Self {
+        RouteRotation {
+            entries: HashMap::new(),
+            enabled,
+        }
+    }
+
+    /// Reorder the routes returned for a routes request.
+    /// Every request to the same destination starts from the next known route, skipping routes
+    /// through mediators that were already used too many times for this destination.
+    pub fn rotate(&mut self, request_routes: &RequestRoutes, multi_routes: &mut Vec<MultiRoute>) {
+        if !self.enabled || multi_routes.is_empty() {
+            return;
+        }
+
+        let key = RotationKey {
+            currency: request_routes.currency.clone(),
+            destination: request_routes.destination.clone(),
+        };
+        let entry = self.entries.entry(key).or_insert(RotationEntry {
+            counter: 0,
+            ticks_left: ROTATION_IDLE_TICKS,
+            mediator_uses: HashMap::new(),
+        });
+
+        rotate(multi_routes, entry.counter);
+        for multi_route in multi_routes.iter_mut() {
+            rotate(&mut multi_route.routes, entry.counter);
+        }
+
+        // Skip routes through mediators over the limit:
+        let mediator_uses = &entry.mediator_uses;
+        let opt_index = multi_routes.iter().position(|multi_route| {
+            mediators(multi_route).all(|mediator| {
+                mediator_uses.get(mediator).cloned().unwrap_or(0) < MAX_MEDIATOR_USES
+            })
+        });
+        let index = match opt_index {
+            Some(index) => index,
+            None => {
+                // All the mediators are over the limit. Start counting again:
+                entry.mediator_uses.clear();
+                0
+            }
+        };
+        multi_routes[..=index].rotate_right(1);
+
+        for mediator in mediators(&multi_routes[0]) {
+            *entry.mediator_uses.entry(mediator.clone()).or_insert(0) += 1;
+        }
+
+        entry.counter = entry.counter.wrapping_add(1);
+        entry.ticks_left = ROTATION_IDLE_TICKS;
+    }
+
+    /// Handle a time tick. Idle destinations are forgotten.
+    pub fn tick(&mut self) {
+        for entry in self.entries.values_mut() {
+            entry.ticks_left = entry.ticks_left.saturating_sub(1);
+        }
+        self.entries.retain(|_, entry| entry.ticks_left > 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use proto::crypto::Uid;
+    use proto::funder::messages::{FriendsRoute, Rate};
+    use proto::index_server::messages::{RouteCapacityRate, RouteConstraints, RouteRanking};
+
+    fn pk(seed: u8) -> PublicKey {
+        PublicKey::from(&[seed; PublicKey::len()])
+    }
+
+    fn request_routes(currency: &Currency, destination: PublicKey) -> RequestRoutes {
+        RequestRoutes {
+            request_id: Uid::from(&[0; Uid::len()]),
+            currency: currency.clone(),
+            capacity: 40,
+            source: pk(0),
+            destination,
+            opt_exclude: None,
+            constraints: RouteConstraints::default(),
+            ranking: RouteRanking::default(),
+        }
+    }
+
+    /// A multi route containing a single route through the given mediator
+    fn multi_route(mediator: u8, destination: u8) -> MultiRoute {
+        MultiRoute {
+            routes: vec![RouteCapacityRate {
+                route: FriendsRoute {
+                    public_keys: vec![pk(0), pk(mediator), pk(destination)],
+                },
+                capacity: 100,
+                rate: Rate { mul: 0, add: 1 },
+            }],
+        }
+    }
+
+    /// The mediator of the first route in the response
+    fn first_mediator(multi_routes: &[MultiRoute]) -> PublicKey {
+        multi_routes[0].routes[0].route.public_keys[1].clone()
+    }
+
+    #[test]
+    fn test_route_rotation_rotates() {
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let mut route_rotation = RouteRotation::new(true);
+
+        let mut first_mediators = Vec::new();
+        for _ in 0..4 {
+            let mut multi_routes = vec![multi_route(1, 5), multi_route(2, 5), multi_route(3, 5)];
+            route_rotation.rotate(&request_routes(&currency, pk(5)), &mut multi_routes);
+            first_mediators.push(first_mediator(&multi_routes));
+        }
+        assert_eq!(first_mediators, vec![pk(1), pk(2), pk(3), pk(1)]);
+
+        // Requests to another destination start from the first route:
+        let mut multi_routes = vec![multi_route(1, 6), multi_route(2, 6)];
+        route_rotation.rotate(&request_routes(&currency, pk(6)), &mut multi_routes);
+        assert_eq!(first_mediator(&multi_routes), pk(1));
+    }
+
+    #[test]
+    fn test_route_rotation_mediator_limit() {
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let mut route_rotation = RouteRotation::new(true);
+
+        // Two of the routes go through the same mediator:
+        let mut first_mediators = Vec::new();
+        for _ in 0..9 {
+            let mut multi_routes = vec![multi_route(1, 5), multi_route(1, 5), multi_route(2, 5)];
+            route_rotation.rotate(&request_routes(&currency, pk(5)), &mut multi_routes);
+            first_mediators.push(first_mediator(&multi_routes));
+        }
+
+        // Once a mediator reaches the limit it is skipped, until all the mediators reach the limit:
+        assert_eq!(
+            first_mediators,
+            vec![
+                pk(1),
+                pk(1),
+                pk(2),
+                pk(1),
+                pk(1),
+                pk(2),
+                pk(2),
+                pk(2),
+                pk(2),
+            ]
+        );
+        assert_eq!(
+            first_mediators[..8]
+                .iter()
+                .filter(|mediator| **mediator == pk(1))
+                .count(),
+            MAX_MEDIATOR_USES
+        );
+    }
+
+    #[test]
+    fn test_route_rotation_disabled() {
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let mut route_rotation = RouteRotation::new(false);
+
+        for _ in 0..3 {
+            let mut multi_routes = vec![multi_route(1, 5), multi_route(2, 5)];
+            route_rotation.rotate(&request_routes(&currency, pk(5)), &mut multi_routes);
+            assert_eq!(first_mediator(&multi_routes), pk(1));
+        }
+    }
+
+    #[test]
+    fn test_route_rotation_idle_expire() {
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let mut route_rotation = RouteRotation::new(true);
+
+        let mut multi_routes = vec![multi_route(1, 5), multi_route(2, 5)];
+        route_rotation.rotate(&request_routes(&currency, pk(5)), &mut multi_routes);
+
+        for _ in 0..ROTATION_IDLE_TICKS {
+            route_rotation.tick();
+        }
+
+        // The rotation position was forgotten:
+        let mut multi_routes = vec![multi_route(1, 5), multi_route(2, 5)];
+        route_rotation.rotate(&request_routes(&currency, pk(5)), &mut multi_routes);
+        assert_eq!(first_mediator(&multi_routes), pk(1));
+    }
+}
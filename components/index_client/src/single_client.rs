@@ -1,31 +1,56 @@
 use std::collections::HashMap;
 use std::marker::Unpin;
+use std::pin::Pin;
+use std::task::{Context, Poll};
 
-use futures::channel::oneshot;
-use futures::{future, stream, Sink, SinkExt, Stream, StreamExt};
+use futures::channel::{mpsc, oneshot};
+use futures::{future, stream, Future, Sink, SinkExt, Stream, StreamExt};
 
 use common::conn::{BoxStream, ConnPair};
 use common::select_streams::select_streams;
 
 use proto::crypto::{HashResult, PublicKey, RandValue, Signature, Uid};
 
+use proto::app_server::messages::SendFriendProposal;
 use proto::index_server::messages::{
-    IndexClientToServer, IndexMutation, IndexServerToClient, MultiRoute, MutationsUpdate,
-    RequestRoutes, ResponseRoutes,
+    FriendProposal, IndexClientToServer, IndexMutation, IndexServerDirectory, IndexServerToClient,
+    MultiRoute, MutationsUpdate, RequestDirectory, RequestRoutes, RequestServerStatus,
+    ResponseRoutes, ResponseServerStatus, ResumeSession,
 };
 
-use signature::signature_buff::create_mutations_update_signature_buff;
+use signature::signature_buff::{
+    create_mutations_update_pow_buff, create_mutations_update_signature_buff,
+    friend_proposal_signature_buff, LEGACY_SIGNATURE_BUFF_VERSION, SIGNATURE_BUFF_VERSION,
+};
+use signature::verify::verify_friend_proposal;
 
+use crypto::pow::search_pow;
 use crypto::rand::{CryptoRandom, RandGen};
 
-use identity::IdentityClient;
+use identity::SignatureBackend;
 
 pub type ServerConn = ConnPair<IndexClientToServer, IndexServerToClient>;
 
+/// Highest proof of work difficulty we agree to meet. Meeting a difficulty takes
+/// 2^difficulty hashes on average. We disconnect from servers that ask for more.
+pub const MAX_POW_DIFFICULTY: u8 = 24;
+
+/// Amount of proof of work nonces we try before letting other tasks run
+const POW_CHUNK_NONCES: u64 = 0x400;
+
 #[derive(Debug)]
 pub enum SingleClientControl {
     RequestRoutes((RequestRoutes, oneshot::Sender<Vec<MultiRoute>>)),
     SendMutations(Vec<IndexMutation>),
+    SendFriendProposal(SendFriendProposal),
+    RequestServerStatus(oneshot::Sender<ResponseServerStatus>),
+    /// Continue a previous session with the server. The response tells whether the server still
+    /// knows the full state we sent during the previous session.
+    /// Must be sent before any mutations.
+    ResumeSession((ResumeSession, oneshot::Sender<bool>)),
+    /// Request the index server directory served by the server.
+    /// The directory is returned as is, and must be verified by the caller.
+    RequestDirectory(oneshot::Sender<Option<IndexServerDirectory>>),
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -35,6 +60,49 @@ pub enum SingleClientError {
     SendToServerError,
     RequestSignatureFailed,
     CounterOverflow,
+    /// The server asked for a proof of work difficulty above `MAX_POW_DIFFICULTY`
+    PowDifficultyTooHigh(u8),
+}
+
+/// A future that is pending exactly once, letting other tasks of the executor run.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        context.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// Find a valid proof of work nonce for `buff`.
+/// Solving may take a while, so we let other tasks run between chunks of attempts.
+async fn solve_pow_yielding(buff: &[u8], difficulty: u8) -> u64 {
+    let mut first_nonce = 0u64;
+    loop {
+        if let Some(nonce) = search_pow(buff, difficulty, first_nonce, POW_CHUNK_NONCES) {
+            return nonce;
+        }
+        first_nonce = first_nonce.wrapping_add(POW_CHUNK_NONCES);
+        YieldNow(false).await;
+    }
+}
+
+/// Make sure that a proof of work difficulty sent by the server is not too high
+fn check_pow_difficulty(pow_difficulty: u8) -> Result<u8, SingleClientError> {
+    if pow_difficulty > MAX_POW_DIFFICULTY {
+        warn!(
+            "Index server asked for a proof of work difficulty of {}",
+            pow_difficulty
+        );
+        return Err(SingleClientError::PowDifficultyTooHigh(pow_difficulty));
+    }
+    Ok(pow_difficulty)
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -46,9 +114,9 @@ enum SingleClientEvent {
     ControlClosed,
 }
 
-struct SingleClient<TS, R> {
+struct SingleClient<TS, SB, R> {
     local_public_key: PublicKey,
-    identity_client: IdentityClient,
+    identity_client: SB,
     rng: R,
     to_server: TS,
     session_id: Uid,
@@ -56,23 +124,47 @@ struct SingleClient<TS, R> {
     /// Last time_hash sent by the server
     /// We use this value to prove that our signatures are recent
     server_time_hash: HashResult,
+    /// Last proof of work difficulty sent by the server.
+    /// Our mutations updates must meet this difficulty.
+    pow_difficulty: u8,
+    /// Layout of the signed buffer of our mutations updates
+    signature_version: u32,
     /// Unanswered requests, waiting for a response from the server
     open_requests: HashMap<Uid, oneshot::Sender<Vec<MultiRoute>>>,
+    /// Unanswered server status requests
+    open_status_requests: HashMap<Uid, oneshot::Sender<ResponseServerStatus>>,
+    /// An unanswered session resumption request
+    opt_resume_sender: Option<oneshot::Sender<bool>>,
+    /// Unanswered directory requests
+    open_directory_requests: HashMap<Uid, oneshot::Sender<Option<IndexServerDirectory>>>,
+    /// Verified friend proposals received from the server are passed to the IndexClient
+    /// through this sender:
+    friend_proposal_sender: mpsc::Sender<FriendProposal>,
 }
 
-impl<TS, R> SingleClient<TS, R>
+impl<TS, SB, R> SingleClient<TS, SB, R>
 where
     TS: Sink<IndexClientToServer> + Unpin,
+    SB: SignatureBackend,
     R: CryptoRandom,
 {
     pub fn new(
         local_public_key: PublicKey,
-        identity_client: IdentityClient,
+        identity_client: SB,
         rng: R,
         to_server: TS,
         session_id: Uid,
         server_time_hash: HashResult,
+        opt_pow_difficulty: Option<u8>,
+        friend_proposal_sender: mpsc::Sender<FriendProposal>,
     ) -> Self {
+        // Index servers that predate signature buffer versions never send a proof of work
+        // difficulty, and only verify the legacy layout:
+        let signature_version = if opt_pow_difficulty.is_some() {
+            SIGNATURE_BUFF_VERSION
+        } else {
+            LEGACY_SIGNATURE_BUFF_VERSION
+        };
         SingleClient {
             local_public_key,
             identity_client,
@@ -81,10 +173,25 @@ where
             session_id,
             counter: 0,
             server_time_hash,
+            pow_difficulty: opt_pow_difficulty.unwrap_or(0),
+            signature_version,
             open_requests: HashMap::new(),
+            open_status_requests: HashMap::new(),
+            opt_resume_sender: None,
+            open_directory_requests: HashMap::new(),
+            friend_proposal_sender,
         }
     }
 
+    /// A token that allows resuming the current session later.
+    /// Returns None if no mutations were sent during the session.
+    pub fn resume_session(&self) -> Option<ResumeSession> {
+        Some(ResumeSession {
+            session_id: self.session_id.clone(),
+            counter: self.counter.checked_sub(1)?,
+        })
+    }
+
     /// Handle a message coming from the server
     pub async fn handle_server_message(
         &mut self,
@@ -92,6 +199,9 @@ where
     ) -> Result<(), SingleClientError> {
         match index_server_to_client {
             IndexServerToClient::TimeHash(time_hash) => self.server_time_hash = time_hash,
+            IndexServerToClient::PowDifficulty(pow_difficulty) => {
+                self.pow_difficulty = check_pow_difficulty(pow_difficulty)?
+            }
             IndexServerToClient::ResponseRoutes(response_routes) => {
                 let ResponseRoutes {
                     request_id,
@@ -114,6 +224,66 @@ where
                     );
                 }
             }
+            IndexServerToClient::FriendProposal(friend_proposal) => {
+                if friend_proposal.dest_public_key != self.local_public_key
+                    || !verify_friend_proposal(&friend_proposal)
+                {
+                    warn!("Received an invalid friend proposal from the server");
+                    return Ok(());
+                }
+                // We don't wait for the IndexClient here, because the IndexClient might be
+                // waiting for us:
+                if let Err(e) = self.friend_proposal_sender.try_send(friend_proposal) {
+                    warn!("Failed to pass a friend proposal: {:?}", e);
+                }
+            }
+            IndexServerToClient::ResponseRelays(response_relays) => {
+                // Relays are discovered before the node starts (See `relay::discover_relays`):
+                warn!(
+                    "Received unexpected relays response for request_id: {:?}",
+                    &response_relays.request_id
+                );
+            }
+            IndexServerToClient::ResponseServerStatus(response_server_status) => {
+                let response_sender = match self
+                    .open_status_requests
+                    .remove(&response_server_status.request_id)
+                {
+                    Some(response_sender) => response_sender,
+                    None => {
+                        warn!(
+                            "Received a server status for unrecognized request_id: {:?}",
+                            &response_server_status.request_id
+                        );
+                        return Ok(());
+                    }
+                };
+                let _ = response_sender.send(response_server_status);
+            }
+            IndexServerToClient::SessionResumed(session_resumed) => {
+                match self.opt_resume_sender.take() {
+                    Some(resume_sender) => {
+                        let _ = resume_sender.send(session_resumed);
+                    }
+                    None => warn!("Received an unexpected session resumption response"),
+                }
+            }
+            IndexServerToClient::ResponseDirectory(response_directory) => {
+                let response_sender = match self
+                    .open_directory_requests
+                    .remove(&response_directory.request_id)
+                {
+                    Some(response_sender) => response_sender,
+                    None => {
+                        warn!(
+                            "Received a directory for unrecognized request_id: {:?}",
+                            &response_directory.request_id
+                        );
+                        return Ok(());
+                    }
+                };
+                let _ = response_sender.send(response_directory.opt_directory);
+            }
         }
         Ok(())
     }
@@ -145,15 +315,27 @@ where
                     counter: self.counter,
                     rand_nonce: RandValue::rand_gen(&self.rng),
                     signature: Signature::default(),
+                    pow_nonce: 0,
                 };
 
                 // Calculate signature:
                 mutations_update.signature = self
                     .identity_client
-                    .request_signature(create_mutations_update_signature_buff(&mutations_update))
+                    .request_signature(create_mutations_update_signature_buff(
+                        &mutations_update,
+                        self.signature_version,
+                    ))
                     .await
                     .map_err(|_| SingleClientError::RequestSignatureFailed)?;
 
+                // Calculate proof of work. The server throttles us by raising the difficulty if
+                // we send mutations too often:
+                mutations_update.pow_nonce = solve_pow_yielding(
+                    &create_mutations_update_pow_buff(&mutations_update),
+                    self.pow_difficulty,
+                )
+                .await;
+
                 // Advance counter:
                 // We assume that the counter will never reach the wrapping point, because it is of
                 // size at least 64 bits, but we still add an error here, just in case.
@@ -169,19 +351,92 @@ where
                     .await
                     .map_err(|_| SingleClientError::SendToServerError)?;
             }
+            SingleClientControl::SendFriendProposal(send_friend_proposal) => {
+                let mut friend_proposal = FriendProposal {
+                    src_public_key: self.local_public_key.clone(),
+                    dest_public_key: send_friend_proposal.dest_public_key,
+                    relays: send_friend_proposal.relays,
+                    currency: send_friend_proposal.currency,
+                    proposed_max_debt: send_friend_proposal.proposed_max_debt,
+                    rand_nonce: RandValue::rand_gen(&self.rng),
+                    signature: Signature::default(),
+                };
+
+                // Calculate signature:
+                friend_proposal.signature = self
+                    .identity_client
+                    .request_signature(friend_proposal_signature_buff(&friend_proposal))
+                    .await
+                    .map_err(|_| SingleClientError::RequestSignatureFailed)?;
+
+                let to_server_message = IndexClientToServer::SendFriendProposal(friend_proposal);
+                self.to_server
+                    .send(to_server_message)
+                    .await
+                    .map_err(|_| SingleClientError::SendToServerError)?;
+            }
+            SingleClientControl::RequestServerStatus(response_sender) => {
+                let request_id = Uid::rand_gen(&self.rng);
+                self.open_status_requests
+                    .insert(request_id.clone(), response_sender);
+
+                let to_server_message =
+                    IndexClientToServer::RequestServerStatus(RequestServerStatus { request_id });
+                self.to_server
+                    .send(to_server_message)
+                    .await
+                    .map_err(|_| SingleClientError::SendToServerError)?;
+            }
+            SingleClientControl::ResumeSession((resume_session, resume_sender)) => {
+                // We continue the previous session whether the server accepts the resumption or
+                // not. If the server has forgotten the session, a new one begins from its
+                // point of view.
+                self.session_id = resume_session.session_id.clone();
+                self.counter = resume_session
+                    .counter
+                    .checked_add(1)
+                    .ok_or(SingleClientError::CounterOverflow)?;
+                self.opt_resume_sender = Some(resume_sender);
+
+                let to_server_message = IndexClientToServer::ResumeSession(resume_session);
+                self.to_server
+                    .send(to_server_message)
+                    .await
+                    .map_err(|_| SingleClientError::SendToServerError)?;
+            }
+            SingleClientControl::RequestDirectory(response_sender) => {
+                let request_id = Uid::rand_gen(&self.rng);
+                self.open_directory_requests
+                    .insert(request_id.clone(), response_sender);
+
+                let to_server_message =
+                    IndexClientToServer::RequestDirectory(RequestDirectory { request_id });
+                self.to_server
+                    .send(to_server_message)
+                    .await
+                    .map_err(|_| SingleClientError::SendToServerError)?;
+            }
         }
         Ok(())
     }
 }
 
 /// Wait for the first time hash sent from the server.
+/// Returns the time hash, together with the proof of work difficulty the server sent before the
+/// time hash (None if the server did not send any).
 pub async fn first_server_time_hash(
     from_server: &mut BoxStream<'static, IndexServerToClient>,
-) -> Result<HashResult, SingleClientError> {
+) -> Result<(HashResult, Option<u8>), SingleClientError> {
+    let mut opt_pow_difficulty = None;
     loop {
         match from_server.next().await {
             None => return Err(SingleClientError::ServerClosed),
-            Some(IndexServerToClient::TimeHash(time_hash)) => return Ok(time_hash),
+            Some(IndexServerToClient::TimeHash(time_hash)) => {
+                return Ok((time_hash, opt_pow_difficulty))
+            }
+            Some(IndexServerToClient::PowDifficulty(new_pow_difficulty)) => {
+                opt_pow_difficulty = Some(check_pow_difficulty(new_pow_difficulty)?)
+            }
             Some(index_server_to_client) => warn!(
                 "first_server_time_hash(): Received message {:?} before first time has",
                 index_server_to_client
@@ -190,16 +445,20 @@ pub async fn first_server_time_hash(
     }
 }
 
-pub async fn single_client_loop<IC, R>(
+pub async fn single_client_loop<IC, SB, R>(
     server_conn: ServerConn,
     incoming_control: IC,
     local_public_key: PublicKey,
-    identity_client: IdentityClient,
+    identity_client: SB,
     rng: R,
     first_server_time_hash: HashResult,
+    first_opt_pow_difficulty: Option<u8>,
+    friend_proposal_sender: mpsc::Sender<FriendProposal>,
+    resume_session_sender: oneshot::Sender<ResumeSession>,
 ) -> Result<(), SingleClientError>
 where
     IC: Stream<Item = SingleClientControl> + Send + Unpin,
+    SB: SignatureBackend,
     R: CryptoRandom,
 {
     let (to_server, from_server) = server_conn.split();
@@ -212,6 +471,8 @@ where
         to_server,
         session_id,
         first_server_time_hash,
+        first_opt_pow_difficulty,
+        friend_proposal_sender,
     );
 
     let from_server = from_server
@@ -226,23 +487,35 @@ where
 
     let mut events = select_streams![from_server, incoming_control];
 
-    while let Some(event) = events.next().await {
-        match event {
+    let res = loop {
+        let event = match events.next().await {
+            Some(event) => event,
+            None => break Ok(()),
+        };
+        let event_res = match event {
             SingleClientEvent::FromServer(index_server_to_client) => {
                 single_client
                     .handle_server_message(index_server_to_client)
-                    .await?
+                    .await
             }
-            SingleClientEvent::ServerClosed => return Err(SingleClientError::ServerClosed),
+            SingleClientEvent::ServerClosed => Err(SingleClientError::ServerClosed),
             SingleClientEvent::Control(index_client_control) => {
                 single_client
                     .handle_control_message(index_client_control)
-                    .await?
+                    .await
             }
-            SingleClientEvent::ControlClosed => return Err(SingleClientError::ControlClosed),
+            SingleClientEvent::ControlClosed => Err(SingleClientError::ControlClosed),
+        };
+        if let Err(e) = event_res {
+            break Err(e);
         }
+    };
+
+    // Allow resuming this session after reconnecting:
+    if let Some(resume_session) = single_client.resume_session() {
+        let _ = resume_session_sender.send(resume_session);
     }
-    Ok(())
+    res
 }
 
 #[cfg(test)]
@@ -259,14 +532,15 @@ mod tests {
 
     use proto::crypto::PrivateKey;
     use proto::funder::messages::Currency;
+    use proto::index_server::messages::{RouteConstraints, RouteRanking};
 
-    use signature::verify::verify_mutations_update;
+    use signature::verify::{mutations_update_pow_bits, verify_mutations_update};
 
     use crypto::identity::{Identity, SoftwareEd25519Identity};
     use crypto::rand::RandGen;
     use crypto::test_utils::DummyRandom;
 
-    use identity::create_identity;
+    use identity::{create_identity, IdentityClient};
 
     async fn task_first_server_time_hash() {
         let (mut to_server, from_server) = mpsc::channel(0);
@@ -277,7 +551,7 @@ mod tests {
         let fut_time_hash = first_server_time_hash(&mut from_server_boxed);
 
         let (_, res_time_hash) = join(fut_send, fut_time_hash).await;
-        assert_eq!(res_time_hash.unwrap(), time_hash);
+        assert_eq!(res_time_hash.unwrap(), (time_hash, None));
     }
 
     #[test]
@@ -285,6 +559,58 @@ mod tests {
         block_on(task_first_server_time_hash());
     }
 
+    async fn task_first_server_time_hash_pow_difficulty() {
+        let (mut to_server, from_server) = mpsc::channel(2);
+        let time_hash = HashResult::from(&[1; HashResult::len()]);
+
+        to_server
+            .send(IndexServerToClient::PowDifficulty(5))
+            .await
+            .unwrap();
+        to_server
+            .send(IndexServerToClient::TimeHash(time_hash.clone()))
+            .await
+            .unwrap();
+
+        let mut from_server_boxed = from_server.boxed();
+        let res_time_hash = first_server_time_hash(&mut from_server_boxed).await;
+        assert_eq!(res_time_hash.unwrap(), (time_hash, Some(5)));
+    }
+
+    #[test]
+    fn test_first_server_time_hash_pow_difficulty() {
+        block_on(task_first_server_time_hash_pow_difficulty());
+    }
+
+    async fn task_first_server_time_hash_pow_difficulty_too_high() {
+        let (mut to_server, from_server) = mpsc::channel(2);
+
+        to_server
+            .send(IndexServerToClient::PowDifficulty(MAX_POW_DIFFICULTY + 1))
+            .await
+            .unwrap();
+        to_server
+            .send(IndexServerToClient::TimeHash(HashResult::from(
+                &[1; HashResult::len()],
+            )))
+            .await
+            .unwrap();
+
+        let mut from_server_boxed = from_server.boxed();
+        let res_time_hash = first_server_time_hash(&mut from_server_boxed).await;
+        assert_eq!(
+            res_time_hash,
+            Err(SingleClientError::PowDifficultyTooHigh(
+                MAX_POW_DIFFICULTY + 1
+            ))
+        );
+    }
+
+    #[test]
+    fn test_first_server_time_hash_pow_difficulty_too_high() {
+        block_on(task_first_server_time_hash_pow_difficulty_too_high());
+    }
+
     async fn task_first_server_time_hash_server_closed() {
         let (to_server, from_server) = mpsc::channel(0);
         // Simulate closing the connection to the server:
@@ -310,6 +636,7 @@ mod tests {
         let (mut server_sender, client_receiver) = mpsc::channel(0);
         let (client_sender, mut server_receiver) = mpsc::channel(0);
         let (mut control_sender, incoming_control) = mpsc::channel(0);
+        let (friend_proposal_sender, mut incoming_friend_proposals) = mpsc::channel(1);
 
         // Create identity_client:
         let rng = DummyRandom::new(&[1u8]);
@@ -323,6 +650,7 @@ mod tests {
         let server_conn = ConnPair::from_raw(client_sender, client_receiver);
         let rng = DummyRandom::new(&[2u8]);
         let first_server_time_hash = HashResult::from(&[1; HashResult::len()]);
+        let (resume_session_sender, resume_session_receiver) = oneshot::channel();
 
         let loop_fut = single_client_loop(
             server_conn,
@@ -331,12 +659,21 @@ mod tests {
             identity_client,
             rng,
             first_server_time_hash,
+            Some(0),
+            friend_proposal_sender,
+            resume_session_sender,
         )
         .map_err(|e| error!("single_client_loop() error: {:?}", e))
         .map(|_| ());
 
         spawner.spawn(loop_fut).unwrap();
 
+        // Server requires a proof of work:
+        server_sender
+            .send(IndexServerToClient::PowDifficulty(6))
+            .await
+            .unwrap();
+
         // Send some time hashes from server:
         for i in 2..8 {
             let time_hash = HashResult::from(&[i; HashResult::len()]);
@@ -354,6 +691,8 @@ mod tests {
             source: PublicKey::from(&[0xcc; PublicKey::len()]),
             destination: PublicKey::from(&[0xdd; PublicKey::len()]),
             opt_exclude: None,
+            constraints: RouteConstraints::default(),
+            ranking: RouteRanking::default(),
         };
 
         let (response_sender, response_receiver) = oneshot::channel();
@@ -408,10 +747,70 @@ mod tests {
                     );
 
                     assert!(verify_mutations_update(&mutations_update));
+                    assert!(mutations_update_pow_bits(&mutations_update) >= 6);
                 }
                 _ => unreachable!(),
             };
         }
+
+        // Send a friend proposal:
+        let send_friend_proposal = SendFriendProposal {
+            dest_public_key: PublicKey::from(&[0xdd; PublicKey::len()]),
+            relays: vec![],
+            currency: currency.clone(),
+            proposed_max_debt: 100,
+        };
+        control_sender
+            .send(SingleClientControl::SendFriendProposal(
+                send_friend_proposal,
+            ))
+            .await
+            .unwrap();
+
+        // The signed proposal is sent to the server:
+        let sent_friend_proposal = match server_receiver.next().await.unwrap() {
+            IndexClientToServer::SendFriendProposal(friend_proposal) => friend_proposal,
+            _ => unreachable!(),
+        };
+        assert_eq!(sent_friend_proposal.src_public_key, local_public_key);
+        assert_eq!(sent_friend_proposal.proposed_max_debt, 100);
+        assert!(verify_friend_proposal(&sent_friend_proposal));
+
+        // A proposal destined to another node is ignored:
+        server_sender
+            .send(IndexServerToClient::FriendProposal(sent_friend_proposal))
+            .await
+            .unwrap();
+
+        // A proposal from another node is passed on:
+        let rng = DummyRandom::new(&[3u8]);
+        let remote_pkcs8 = PrivateKey::rand_gen(&rng);
+        let remote_identity = SoftwareEd25519Identity::from_private_key(&remote_pkcs8).unwrap();
+        let mut friend_proposal = FriendProposal {
+            src_public_key: remote_identity.get_public_key(),
+            dest_public_key: local_public_key.clone(),
+            relays: vec![],
+            currency: currency.clone(),
+            proposed_max_debt: 200,
+            rand_nonce: RandValue::from(&[4; RandValue::len()]),
+            signature: Signature::default(),
+        };
+        friend_proposal.signature =
+            remote_identity.sign(&friend_proposal_signature_buff(&friend_proposal));
+        server_sender
+            .send(IndexServerToClient::FriendProposal(friend_proposal.clone()))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            incoming_friend_proposals.next().await.unwrap(),
+            friend_proposal
+        );
+
+        // When the connection is closed, we get a token for resuming the session:
+        drop(server_sender);
+        let resume_session = resume_session_receiver.await.unwrap();
+        assert_eq!(resume_session.counter, 2);
     }
 
     #[test]
@@ -419,4 +818,151 @@ mod tests {
         let thread_pool = ThreadPool::new().unwrap();
         block_on(task_single_client_loop_basic(thread_pool.clone()));
     }
+
+    async fn task_single_client_loop_resume_session<S>(spawner: S)
+    where
+        S: Spawn,
+    {
+        let (mut server_sender, client_receiver) = mpsc::channel(0);
+        let (client_sender, mut server_receiver) = mpsc::channel(0);
+        let (mut control_sender, incoming_control) = mpsc::channel(0);
+        let (friend_proposal_sender, _incoming_friend_proposals) = mpsc::channel(1);
+
+        // Create identity_client:
+        let rng = DummyRandom::new(&[1u8]);
+        let pkcs8 = PrivateKey::rand_gen(&rng);
+        let identity = SoftwareEd25519Identity::from_private_key(&pkcs8).unwrap();
+        let local_public_key = identity.get_public_key();
+        let (requests_sender, identity_server) = create_identity(identity);
+        spawner.spawn(identity_server.map(|_| ())).unwrap();
+        let identity_client = IdentityClient::new(requests_sender);
+
+        let server_conn = ConnPair::from_raw(client_sender, client_receiver);
+        let rng = DummyRandom::new(&[2u8]);
+        let first_server_time_hash = HashResult::from(&[1; HashResult::len()]);
+        let (resume_session_sender, resume_session_receiver) = oneshot::channel();
+
+        let loop_fut = single_client_loop(
+            server_conn,
+            incoming_control,
+            local_public_key,
+            identity_client,
+            rng,
+            first_server_time_hash,
+            Some(0),
+            friend_proposal_sender,
+            resume_session_sender,
+        )
+        .map_err(|e| error!("single_client_loop() error: {:?}", e))
+        .map(|_| ());
+
+        spawner.spawn(loop_fut).unwrap();
+
+        let prev_resume_session = ResumeSession {
+            session_id: Uid::from(&[5; Uid::len()]),
+            counter: 7,
+        };
+        let (resume_sender, resume_receiver) = oneshot::channel();
+        control_sender
+            .send(SingleClientControl::ResumeSession((
+                prev_resume_session.clone(),
+                resume_sender,
+            )))
+            .await
+            .unwrap();
+
+        match server_receiver.next().await.unwrap() {
+            IndexClientToServer::ResumeSession(resume_session) => {
+                assert_eq!(resume_session, prev_resume_session)
+            }
+            _ => unreachable!(),
+        };
+        server_sender
+            .send(IndexServerToClient::SessionResumed(true))
+            .await
+            .unwrap();
+        assert!(resume_receiver.await.unwrap());
+
+        // Mutations continue the previous session:
+        control_sender
+            .send(SingleClientControl::SendMutations(vec![]))
+            .await
+            .unwrap();
+        match server_receiver.next().await.unwrap() {
+            IndexClientToServer::MutationsUpdate(mutations_update) => {
+                assert_eq!(mutations_update.session_id, Uid::from(&[5; Uid::len()]));
+                assert_eq!(mutations_update.counter, 8);
+            }
+            _ => unreachable!(),
+        };
+
+        drop(server_sender);
+        let resume_session = resume_session_receiver.await.unwrap();
+        assert_eq!(
+            resume_session,
+            ResumeSession {
+                session_id: Uid::from(&[5; Uid::len()]),
+                counter: 8,
+            }
+        );
+    }
+
+    #[test]
+    fn test_single_client_loop_resume_session() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_single_client_loop_resume_session(thread_pool.clone()));
+    }
+
+    async fn task_single_client_loop_pow_difficulty_too_high<S>(spawner: S)
+    where
+        S: Spawn,
+    {
+        let (mut server_sender, client_receiver) = mpsc::channel(0);
+        let (client_sender, _server_receiver) = mpsc::channel(0);
+        let (_control_sender, incoming_control) = mpsc::channel(0);
+        let (friend_proposal_sender, _incoming_friend_proposals) = mpsc::channel(1);
+
+        let rng = DummyRandom::new(&[1u8]);
+        let pkcs8 = PrivateKey::rand_gen(&rng);
+        let identity = SoftwareEd25519Identity::from_private_key(&pkcs8).unwrap();
+        let local_public_key = identity.get_public_key();
+        let (requests_sender, identity_server) = create_identity(identity);
+        spawner.spawn(identity_server.map(|_| ())).unwrap();
+        let identity_client = IdentityClient::new(requests_sender);
+
+        let server_conn = ConnPair::from_raw(client_sender, client_receiver);
+        let (resume_session_sender, _resume_session_receiver) = oneshot::channel();
+
+        let loop_fut = single_client_loop(
+            server_conn,
+            incoming_control,
+            local_public_key,
+            identity_client,
+            DummyRandom::new(&[2u8]),
+            HashResult::from(&[1; HashResult::len()]),
+            Some(0),
+            friend_proposal_sender,
+            resume_session_sender,
+        );
+
+        // The server asks for a difficulty we are not willing to meet:
+        let fut_send =
+            server_sender.send(IndexServerToClient::PowDifficulty(MAX_POW_DIFFICULTY + 1));
+
+        let (loop_res, _) = join(loop_fut, fut_send).await;
+        assert_eq!(
+            loop_res,
+            Err(SingleClientError::PowDifficultyTooHigh(
+                MAX_POW_DIFFICULTY + 1
+            ))
+        );
+    }
+
+    #[test]
+    fn test_single_client_loop_pow_difficulty_too_high() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_single_client_loop_pow_difficulty_too_high(
+            thread_pool.clone(),
+        ));
+    }
 }
@@ -10,7 +10,8 @@ use identity::IdentityClient;
 use timer::TimerClient;
 
 use proto::crypto::PublicKey;
-use proto::proto_ser::{ProtoDeserialize, ProtoSerialize};
+use proto::net::messages::NetAddress;
+use proto::proto_ser::{ProtoDeserializeChecked, ProtoSerialize};
 
 use crypto::rand::CryptoRandom;
 
@@ -33,6 +34,9 @@ use crate::seq_friends::create_seq_friends_service;
 use crate::seq_map::SeqMap;
 use crate::single_client::ServerConn;
 
+/// Amount of received friend proposals that may wait for the IndexClient to handle them.
+const FRIEND_PROPOSALS_BUFFER: usize = 0x10;
+
 #[derive(Clone)]
 /// Connect to an index server
 pub struct SerdeClientConnector<C, S> {
@@ -70,7 +74,7 @@ where
             // Deserialize incoming data:
             let deser_fut = async move {
                 while let Some(data) = data_receiver.next().await {
-                    let message = match IndexServerToClient::proto_deserialize(&data) {
+                    let message = match IndexServerToClient::proto_deserialize_checked(&data) {
                         Ok(message) => message,
                         Err(_) => {
                             error!("deserialize index_server_to_client error");
@@ -122,14 +126,19 @@ pub async fn spawn_index_client<ISA, C, R, S>(
     from_app_server: mpsc::Receiver<AppServerToIndexClient<ISA>>,
     to_app_server: mpsc::Sender<IndexClientToAppServer<ISA>>,
     max_open_index_client_requests: usize,
+    route_cache_ticks: usize,
+    route_rotation: bool,
+    capacity_smoothing: bool,
     keepalive_ticks: usize,
     backoff_ticks: usize,
+    max_index_sessions: usize,
+    opt_directory_public_key: Option<PublicKey>,
     index_connector: C,
     rng: R,
     spawner: S,
 ) -> Result<impl Future<Output = Result<(), IndexClientError>>, SpawnIndexClientError>
 where
-    ISA: Debug + Eq + Clone + Send + Sync + 'static,
+    ISA: Debug + Eq + Clone + Send + Sync + From<NetAddress> + 'static,
     C: FutTransform<Input = IndexServerAddress<ISA>, Output = Option<ConnPairVec>>
         + Clone
         + Send
@@ -148,11 +157,14 @@ where
 
     let serde_client_connector = SerdeClientConnector::new(index_connector, spawner.clone());
 
+    let (friend_proposal_sender, incoming_friend_proposals) =
+        mpsc::channel(FRIEND_PROPOSALS_BUFFER);
     let index_client_session = IndexClientSession::new(
         serde_client_connector,
         local_public_key,
         identity_client,
         rng,
+        friend_proposal_sender,
         spawner.clone(),
     );
 
@@ -163,9 +175,15 @@ where
         seq_friends_client,
         index_client_session,
         max_open_index_client_requests,
+        route_cache_ticks,
+        route_rotation,
+        capacity_smoothing,
         keepalive_ticks,
         backoff_ticks,
+        max_index_sessions,
+        opt_directory_public_key,
         database_client,
+        incoming_friend_proposals,
         timer_stream,
         spawner.clone(),
     );
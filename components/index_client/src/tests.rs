@@ -7,14 +7,28 @@ use futures::{FutureExt, SinkExt, StreamExt, TryFutureExt};
 
 use common::dummy_connector::{ConnRequest, DummyConnector};
 
-use proto::crypto::{PublicKey, Uid};
+use proto::app_server::messages::SendFriendProposal;
+use proto::crypto::{PublicKey, RandValue, Signature, Uid};
 
-use proto::funder::messages::{Currency, Rate};
+use proto::funder::messages::{Currency, FriendsRoute, Rate};
 use proto::index_client::messages::{
     AppServerToIndexClient, IndexClientReportMutation, IndexClientRequest, IndexClientToAppServer,
     IndexMutation, RequestRoutes, ResponseRoutesResult, UpdateFriendCurrency,
 };
-use proto::index_server::messages::{IndexServerAddress, NamedIndexServerAddress};
+use proto::index_server::messages::{
+    FriendProposal, IndexServerAddress, MultiRoute, NamedIndexServerAddress, ResumeSession,
+    RouteCapacityRate, RouteConstraints, RouteRanking,
+};
+
+use proto::net::messages::NetAddress;
+
+use crypto::identity::{Identity, SoftwareEd25519Identity};
+use crypto::rand::RandGen;
+use crypto::test_utils::DummyRandom;
+
+use proto::crypto::PrivateKey;
+
+use signature::index_directory::create_index_server_directory;
 
 use database::{DatabaseClient, DatabaseRequest};
 
@@ -23,6 +37,10 @@ use crate::index_client::{index_client_loop, IndexClientConfig, IndexClientConfi
 use crate::seq_friends::{SeqFriendsClient, SeqFriendsRequest};
 use crate::single_client::{SingleClientControl, SingleClientError};
 
+fn net_address(address: &str) -> NetAddress {
+    NetAddress::try_from(address.to_owned()).unwrap()
+}
+
 /// A test util struct
 /// Holds sender/receiver interface for an IndexClient.
 struct IndexClientControl<ISA> {
@@ -31,6 +49,7 @@ struct IndexClientControl<ISA> {
     seq_friends_receiver: mpsc::Receiver<SeqFriendsRequest>,
     session_receiver: mpsc::Receiver<ConnRequest<IndexServerAddress<ISA>, Option<SessionHandle>>>,
     database_req_receiver: mpsc::Receiver<DatabaseRequest<IndexClientConfigMutation<ISA>>>,
+    friend_proposal_sender: mpsc::Sender<FriendProposal>,
     tick_sender: mpsc::Sender<()>,
     // TODO: Check: Why is this field unused?
     #[allow(unused)]
@@ -42,7 +61,10 @@ struct IndexClientControl<ISA> {
 }
 
 /// Create a basic IndexClientControl, used for testing
-fn basic_index_client<S>(spawner: S) -> IndexClientControl<u32>
+fn basic_index_client<S>(
+    spawner: S,
+    opt_directory_public_key: Option<PublicKey>,
+) -> IndexClientControl<NetAddress>
 where
     S: Spawn + Clone + Send + 'static,
 {
@@ -51,11 +73,14 @@ where
 
     let index_server37 = NamedIndexServerAddress {
         public_key: PublicKey::from(&[0x37; PublicKey::len()]),
-        address: 0x1337u32,
+        address: net_address("0x1337"),
         name: "0x1337".to_owned(),
     };
     let index_client_config = IndexClientConfig {
         index_servers: vec![index_server37],
+        friend_capacity_stats: Vec::new(),
+        pending_mutations: Vec::new(),
+        directory_version: 0,
     };
 
     let (seq_friends_sender, seq_friends_receiver) = mpsc::channel(0);
@@ -68,9 +93,14 @@ where
     let db_client = DatabaseClient::new(database_req_sender);
 
     let max_open_requests = 2;
+    let route_cache_ticks = 16;
+    let route_rotation = false;
+    let capacity_smoothing = false;
     let keepalive_ticks = 8;
     let backoff_ticks = 4;
+    let max_sessions = 1;
 
+    let (friend_proposal_sender, incoming_friend_proposals) = mpsc::channel(0);
     let (tick_sender, timer_stream) = mpsc::channel::<()>(0);
 
     let loop_fut = index_client_loop(
@@ -80,9 +110,15 @@ where
         seq_friends_client,
         index_client_session,
         max_open_requests,
+        route_cache_ticks,
+        route_rotation,
+        capacity_smoothing,
         keepalive_ticks,
         backoff_ticks,
+        max_sessions,
+        opt_directory_public_key,
         db_client,
+        incoming_friend_proposals,
         timer_stream,
         spawner.clone(),
     )
@@ -97,6 +133,7 @@ where
         seq_friends_receiver,
         session_receiver,
         database_req_receiver,
+        friend_proposal_sender,
         tick_sender,
         max_open_requests,
         keepalive_ticks,
@@ -125,13 +162,35 @@ where
         };
     }
 
+    /// Expect an empty IndexClient report, acknowledging an app request
+    async fn expect_empty_report(&mut self, app_request_id: Uid) {
+        match self.app_server_receiver.next().await.unwrap() {
+            IndexClientToAppServer::ReportMutations(ic_report_mutations) => {
+                assert_eq!(ic_report_mutations.opt_app_request_id, Some(app_request_id));
+                assert!(ic_report_mutations.mutations.is_empty());
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    /// Expect a response for a routes request
+    async fn expect_response_routes(&mut self, request_id: Uid, result: ResponseRoutesResult) {
+        match self.app_server_receiver.next().await.unwrap() {
+            IndexClientToAppServer::ResponseRoutes(client_response_routes) => {
+                assert_eq!(client_response_routes.request_id, request_id);
+                assert_eq!(client_response_routes.result, result);
+            }
+            _ => unreachable!(),
+        };
+    }
+
     /// Expect a connection to index server of a certain public key
     async fn expect_server_connection(
         &mut self,
         index_server: IndexServerAddress<ISA>,
     ) -> (
         mpsc::Receiver<SingleClientControl>,
-        oneshot::Sender<Result<(), SingleClientError>>,
+        oneshot::Sender<(Result<(), SingleClientError>, Option<ResumeSession>)>,
     ) {
         // Wait for a connection request:
         let session_conn_request = self.session_receiver.next().await.unwrap();
@@ -189,7 +248,10 @@ where
             .await
             .unwrap();
 
-        let db_request = self.database_req_receiver.next().await.unwrap();
+        let db_request = match self.database_req_receiver.next().await.unwrap() {
+            DatabaseRequest::Mutate(mutate_request) => mutate_request,
+            DatabaseRequest::Subscribe(_) => unreachable!(),
+        };
         assert_eq!(
             db_request.mutations,
             vec![IndexClientConfigMutation::AddIndexServer(
@@ -227,7 +289,10 @@ where
             .await
             .unwrap();
 
-        let db_request = self.database_req_receiver.next().await.unwrap();
+        let db_request = match self.database_req_receiver.next().await.unwrap() {
+            DatabaseRequest::Mutate(mutate_request) => mutate_request,
+            DatabaseRequest::Subscribe(_) => unreachable!(),
+        };
         assert_eq!(
             db_request.mutations,
             vec![IndexClientConfigMutation::RemoveIndexServer(
@@ -259,23 +324,23 @@ async fn task_index_client_loop_add_remove_index_server<S>(spawner: S)
 where
     S: Spawn + Clone + Send + 'static,
 {
-    let mut icc = basic_index_client(spawner.clone());
+    let mut icc = basic_index_client(spawner.clone(), None);
 
     let index_server = IndexServerAddress {
         public_key: PublicKey::from(&[0x37; PublicKey::len()]),
-        address: 0x1337,
+        address: net_address("0x1337"),
     };
     let (mut control_receiver, close_sender) = icc.expect_server_connection(index_server).await;
 
     icc.add_index_server(NamedIndexServerAddress {
         public_key: PublicKey::from(&[0x38; PublicKey::len()]),
-        address: 0x1338,
+        address: net_address("0x1338"),
         name: "0x1338".to_owned(),
     })
     .await;
     icc.add_index_server(NamedIndexServerAddress {
         public_key: PublicKey::from(&[0x39; PublicKey::len()]),
-        address: 0x1339,
+        address: net_address("0x1339"),
         name: "0x1339".to_owned(),
     })
     .await;
@@ -289,7 +354,7 @@ where
     while let Some(_control_message) = control_receiver.next().await {}
 
     // close_sender should notify that the connection was closed:
-    let _ = close_sender.send(Ok(()));
+    let _ = close_sender.send((Ok(()), None));
 
     icc.expect_set_connected_server(None).await;
 
@@ -303,7 +368,7 @@ where
     let session_conn_request = icc.session_receiver.next().await.unwrap();
     let index_server = IndexServerAddress {
         public_key: PublicKey::from(&[0x39; PublicKey::len()]),
-        address: 0x1339,
+        address: net_address("0x1339"),
     };
     assert_eq!(session_conn_request.address, index_server);
 
@@ -331,10 +396,10 @@ where
 {
     let currency = Currency::try_from("FST".to_owned()).unwrap();
 
-    let mut icc = basic_index_client(spawner.clone());
+    let mut icc = basic_index_client(spawner.clone(), None);
     let index_server = IndexServerAddress {
         public_key: PublicKey::from(&[0x37; PublicKey::len()]),
-        address: 0x1337,
+        address: net_address("0x1337"),
     };
     let (mut control_receiver, _close_sender) = icc.expect_server_connection(index_server).await;
 
@@ -394,15 +459,95 @@ fn test_index_client_loop_apply_mutations() {
     block_on(task_index_client_loop_apply_mutations(thread_pool.clone()));
 }
 
+async fn task_index_client_loop_resume_session<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let currency = Currency::try_from("FST".to_owned()).unwrap();
+
+    let mut icc = basic_index_client(spawner.clone(), None);
+    let index_server = IndexServerAddress {
+        public_key: PublicKey::from(&[0x37; PublicKey::len()]),
+        address: net_address("0x1337"),
+    };
+    let (control_receiver, close_sender) = icc.expect_server_connection(index_server.clone()).await;
+
+    // The connection is closed, leaving a resumable session:
+    let resume_session = ResumeSession {
+        session_id: Uid::from(&[0x55; Uid::len()]),
+        counter: 3,
+    };
+    drop(control_receiver);
+    close_sender
+        .send((Ok(()), Some(resume_session.clone())))
+        .unwrap();
+
+    icc.expect_set_connected_server(None).await;
+
+    for _ in 0..icc.backoff_ticks {
+        icc.tick_sender.send(()).await.unwrap();
+    }
+
+    // IndexClient reconnects to the same server:
+    let session_conn_request = icc.session_receiver.next().await.unwrap();
+    assert_eq!(session_conn_request.address, index_server);
+
+    let (control_sender, mut control_receiver) = mpsc::channel(0);
+    let (_close_sender, close_receiver) = oneshot::channel();
+    session_conn_request.reply(Some((control_sender, close_receiver)));
+
+    // IndexClient attempts to resume the previous session:
+    match control_receiver.next().await.unwrap() {
+        SingleClientControl::ResumeSession((resume_session0, resumed_sender)) => {
+            assert_eq!(resume_session0, resume_session);
+            resumed_sender.send(true).unwrap();
+        }
+        _ => unreachable!(),
+    };
+
+    icc.expect_set_connected_server(Some(index_server.public_key))
+        .await;
+
+    // The session was resumed, therefore our full state is not sent again.
+    // The next request to seq_friends is caused by our mutation:
+    let update_friend_currency = UpdateFriendCurrency {
+        public_key: PublicKey::from(PublicKey::from(&[0xbb; PublicKey::len()])),
+        currency: currency.clone(),
+        recv_capacity: 100,
+        rate: Rate { mul: 0, add: 1 },
+    };
+    let index_mutation = IndexMutation::UpdateFriendCurrency(update_friend_currency);
+    icc.app_server_sender
+        .send(AppServerToIndexClient::ApplyMutations(vec![
+            index_mutation.clone()
+        ]))
+        .await
+        .unwrap();
+
+    match icc.seq_friends_receiver.next().await.unwrap() {
+        SeqFriendsRequest::Mutate(index_mutation0, response_sender) => {
+            assert_eq!(index_mutation0, index_mutation);
+            response_sender.send(()).unwrap();
+        }
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_index_client_loop_resume_session() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_index_client_loop_resume_session(thread_pool.clone()));
+}
+
 async fn task_index_client_loop_request_routes_basic<S>(spawner: S)
 where
     S: Spawn + Clone + Send + 'static,
 {
     let currency = Currency::try_from("FST".to_owned()).unwrap();
-    let mut icc = basic_index_client(spawner.clone());
+    let mut icc = basic_index_client(spawner.clone(), None);
     let index_server = IndexServerAddress {
         public_key: PublicKey::from(&[0x37; PublicKey::len()]),
-        address: 0x1337,
+        address: net_address("0x1337"),
     };
     let (mut control_receiver, _close_sender) = icc.expect_server_connection(index_server).await;
 
@@ -413,6 +558,8 @@ where
         source: PublicKey::from(PublicKey::from(&[0xee; PublicKey::len()])),
         destination: PublicKey::from(PublicKey::from(&[0xff; PublicKey::len()])),
         opt_exclude: None,
+        constraints: RouteConstraints::default(),
+        ranking: RouteRanking::default(),
     };
 
     // Request routes from IndexClient (From AppServer):
@@ -472,18 +619,154 @@ fn test_index_client_loop_request_routes_basic() {
     ));
 }
 
+async fn task_index_client_loop_request_routes_cached<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let currency = Currency::try_from("FST".to_owned()).unwrap();
+    let mut icc = basic_index_client(spawner.clone(), None);
+    let index_server = IndexServerAddress {
+        public_key: PublicKey::from(&[0x37; PublicKey::len()]),
+        address: net_address("0x1337"),
+    };
+    let (mut control_receiver, _close_sender) = icc.expect_server_connection(index_server).await;
+
+    let local_public_key = PublicKey::from(&[0xee; PublicKey::len()]);
+    let friend_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+    let destination = PublicKey::from(&[0xff; PublicKey::len()]);
+
+    let multi_routes = vec![MultiRoute {
+        routes: vec![RouteCapacityRate {
+            route: FriendsRoute {
+                public_keys: vec![
+                    local_public_key.clone(),
+                    friend_public_key.clone(),
+                    destination.clone(),
+                ],
+            },
+            capacity: 300,
+            rate: Rate { mul: 0, add: 1 },
+        }],
+    }];
+
+    let request_routes = |request_id: u8, capacity: u128| RequestRoutes {
+        request_id: Uid::from(&[request_id; Uid::len()]),
+        currency: currency.clone(),
+        capacity,
+        source: local_public_key.clone(),
+        destination: destination.clone(),
+        opt_exclude: None,
+        constraints: RouteConstraints::default(),
+        ranking: RouteRanking::default(),
+    };
+
+    // Request routes from IndexClient (From AppServer):
+    let app_server_sender = icc.app_server_sender.clone();
+    let send_request_routes = |app_request_id: u8, request_routes: RequestRoutes| {
+        let mut c_app_server_sender = app_server_sender.clone();
+        async move {
+            c_app_server_sender
+                .send(AppServerToIndexClient::AppRequest((
+                    Uid::from(&[app_request_id; Uid::len()]),
+                    IndexClientRequest::RequestRoutes(request_routes),
+                )))
+                .await
+                .unwrap();
+        }
+    };
+
+    send_request_routes(50, request_routes(3, 250)).await;
+
+    // IndexClient forwards the routes request to the server:
+    match control_receiver.next().await.unwrap() {
+        SingleClientControl::RequestRoutes((request_routes0, response_sender)) => {
+            assert_eq!(request_routes0, request_routes(3, 250));
+            response_sender.send(multi_routes.clone()).unwrap();
+        }
+        _ => unreachable!(),
+    };
+
+    icc.expect_empty_report(Uid::from(&[50; Uid::len()])).await;
+    icc.expect_response_routes(
+        Uid::from(&[3; Uid::len()]),
+        ResponseRoutesResult::Success(multi_routes.clone()),
+    )
+    .await;
+
+    // A similar request is answered from the cache, without querying the server:
+    send_request_routes(51, request_routes(4, 200)).await;
+    icc.expect_empty_report(Uid::from(&[51; Uid::len()])).await;
+    icc.expect_response_routes(
+        Uid::from(&[4; Uid::len()]),
+        ResponseRoutesResult::Success(multi_routes.clone()),
+    )
+    .await;
+
+    // The capacity with the friend on the cached route changes:
+    let update_friend_currency = UpdateFriendCurrency {
+        public_key: friend_public_key.clone(),
+        currency: currency.clone(),
+        recv_capacity: 10,
+        rate: Rate { mul: 0, add: 1 },
+    };
+    let index_mutation = IndexMutation::UpdateFriendCurrency(update_friend_currency);
+    icc.app_server_sender
+        .send(AppServerToIndexClient::ApplyMutations(vec![
+            index_mutation.clone()
+        ]))
+        .await
+        .unwrap();
+
+    match icc.seq_friends_receiver.next().await.unwrap() {
+        SeqFriendsRequest::Mutate(index_mutation0, response_sender) => {
+            assert_eq!(index_mutation0, index_mutation);
+            response_sender.send(()).unwrap();
+        }
+        _ => unreachable!(),
+    };
+    match icc.seq_friends_receiver.next().await.unwrap() {
+        SeqFriendsRequest::NextUpdate(response_sender) => {
+            response_sender.send(None).unwrap();
+        }
+        _ => unreachable!(),
+    };
+    match control_receiver.next().await.unwrap() {
+        SingleClientControl::SendMutations(mutations0) => {
+            assert_eq!(mutations0, vec![index_mutation]);
+        }
+        _ => unreachable!(),
+    };
+
+    // The cached routes were invalidated. The request is forwarded to the server:
+    send_request_routes(52, request_routes(5, 250)).await;
+    match control_receiver.next().await.unwrap() {
+        SingleClientControl::RequestRoutes((request_routes0, _response_sender)) => {
+            assert_eq!(request_routes0, request_routes(5, 250));
+        }
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_index_client_loop_request_routes_cached() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_index_client_loop_request_routes_cached(
+        thread_pool.clone(),
+    ));
+}
+
 async fn task_index_client_loop_connecting_state<S>(spawner: S)
 where
     S: Spawn + Clone + Send + 'static,
 {
     let currency = Currency::try_from("FST".to_owned()).unwrap();
-    let mut icc = basic_index_client(spawner.clone());
+    let mut icc = basic_index_client(spawner.clone(), None);
 
     // Wait for a connection request:
     let session_conn_request = icc.session_receiver.next().await.unwrap();
     let index_server = IndexServerAddress {
         public_key: PublicKey::from(&[0x37; PublicKey::len()]),
-        address: 0x1337,
+        address: net_address("0x1337"),
     };
     assert_eq!(session_conn_request.address, index_server);
 
@@ -491,7 +774,7 @@ where
     // This leaves IndexClient in "Connecting" state, where it is not yet connected to a server.
     //
     // During the "Connecting" state we expect that IndexClient
-    // will drop ApplyMutations messages:
+    // will queue ApplyMutations messages:
 
     let update_friend_currency = UpdateFriendCurrency {
         public_key: PublicKey::from(PublicKey::from(&[0xbb; PublicKey::len()])),
@@ -516,6 +799,19 @@ where
         _ => unreachable!(),
     };
 
+    // The queued mutations are saved to the database:
+    let db_request = match icc.database_req_receiver.next().await.unwrap() {
+        DatabaseRequest::Mutate(mutate_request) => mutate_request,
+        DatabaseRequest::Subscribe(_) => unreachable!(),
+    };
+    assert_eq!(
+        db_request.mutations,
+        vec![IndexClientConfigMutation::SetPendingMutations(vec![
+            index_mutation.clone()
+        ])]
+    );
+    db_request.response_sender.send(()).unwrap();
+
     // During the "Connecting" state we expect that IndexClient
     // will return a failure for RequestRoutes messages:
 
@@ -526,6 +822,8 @@ where
         source: PublicKey::from(PublicKey::from(&[0xee; PublicKey::len()])),
         destination: PublicKey::from(PublicKey::from(&[0xff; PublicKey::len()])),
         opt_exclude: None,
+        constraints: RouteConstraints::default(),
+        ranking: RouteRanking::default(),
     };
 
     // Request routes from IndexClient (From AppServer):
@@ -565,6 +863,33 @@ where
         _ => unreachable!(),
     };
 
+    // Connection to the server is established:
+    let (control_sender, mut control_receiver) = mpsc::channel(0);
+    let (_close_sender, close_receiver) = oneshot::channel();
+    session_conn_request.reply(Some((control_sender, close_receiver)));
+
+    // The queued mutations are sent to the server:
+    match control_receiver.next().await.unwrap() {
+        SingleClientControl::SendMutations(mutations) => {
+            assert_eq!(mutations, vec![index_mutation.clone()])
+        }
+        _ => unreachable!(),
+    };
+
+    // The queue is cleared:
+    let db_request = match icc.database_req_receiver.next().await.unwrap() {
+        DatabaseRequest::Mutate(mutate_request) => mutate_request,
+        DatabaseRequest::Subscribe(_) => unreachable!(),
+    };
+    assert_eq!(
+        db_request.mutations,
+        vec![IndexClientConfigMutation::SetPendingMutations(Vec::new())]
+    );
+    db_request.response_sender.send(()).unwrap();
+
+    icc.expect_set_connected_server(Some(index_server.public_key.clone()))
+        .await;
+
     // Graceful shutdown:
     let IndexClientControl {
         app_server_sender,
@@ -581,4 +906,192 @@ fn test_index_client_loop_connecting_state() {
     block_on(task_index_client_loop_connecting_state(thread_pool.clone()));
 }
 
+async fn task_index_client_loop_friend_proposals<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let currency = Currency::try_from("FST".to_owned()).unwrap();
+    let mut icc = basic_index_client(spawner.clone(), None);
+    let index_server = IndexServerAddress {
+        public_key: PublicKey::from(&[0x37; PublicKey::len()]),
+        address: net_address("0x1337"),
+    };
+    let (mut control_receiver, _close_sender) = icc.expect_server_connection(index_server).await;
+
+    // Send a friend proposal (From AppServer):
+    let send_friend_proposal = SendFriendProposal {
+        dest_public_key: PublicKey::from(&[0xee; PublicKey::len()]),
+        relays: vec![],
+        currency: currency.clone(),
+        proposed_max_debt: 100,
+    };
+    let app_server_to_index_client = AppServerToIndexClient::AppRequest((
+        Uid::from(&[53; Uid::len()]),
+        IndexClientRequest::SendFriendProposal(send_friend_proposal.clone()),
+    ));
+    icc.app_server_sender
+        .send(app_server_to_index_client)
+        .await
+        .unwrap();
+
+    // IndexClient passes the proposal to the connected server:
+    match control_receiver.next().await.unwrap() {
+        SingleClientControl::SendFriendProposal(send_friend_proposal0) => {
+            assert_eq!(send_friend_proposal0, send_friend_proposal)
+        }
+        _ => unreachable!(),
+    };
+    icc.expect_empty_report(Uid::from(&[53; Uid::len()])).await;
+
+    // A friend proposal is received from the server:
+    let friend_proposal = FriendProposal {
+        src_public_key: PublicKey::from(&[0xff; PublicKey::len()]),
+        dest_public_key: PublicKey::from(&[0xaa; PublicKey::len()]),
+        relays: vec![],
+        currency: currency.clone(),
+        proposed_max_debt: 200,
+        rand_nonce: RandValue::from(&[1; RandValue::len()]),
+        signature: Signature::from(&[2; Signature::len()]),
+    };
+    icc.friend_proposal_sender
+        .send(friend_proposal.clone())
+        .await
+        .unwrap();
+
+    // The proposal is added to the inbox:
+    match icc.app_server_receiver.next().await.unwrap() {
+        IndexClientToAppServer::ReportMutations(ic_report_mutations) => {
+            assert_eq!(ic_report_mutations.opt_app_request_id, None);
+            assert_eq!(
+                ic_report_mutations.mutations,
+                vec![IndexClientReportMutation::AddFriendProposal(
+                    friend_proposal
+                )]
+            );
+        }
+        _ => unreachable!(),
+    };
+
+    // Reject the proposal (From AppServer):
+    let app_server_to_index_client = AppServerToIndexClient::AppRequest((
+        Uid::from(&[54; Uid::len()]),
+        IndexClientRequest::RemoveFriendProposal(PublicKey::from(&[0xff; PublicKey::len()])),
+    ));
+    icc.app_server_sender
+        .send(app_server_to_index_client)
+        .await
+        .unwrap();
+
+    match icc.app_server_receiver.next().await.unwrap() {
+        IndexClientToAppServer::ReportMutations(ic_report_mutations) => {
+            assert_eq!(
+                ic_report_mutations.opt_app_request_id,
+                Some(Uid::from(&[54; Uid::len()]))
+            );
+            assert_eq!(
+                ic_report_mutations.mutations,
+                vec![IndexClientReportMutation::RemoveFriendProposal(
+                    PublicKey::from(&[0xff; PublicKey::len()])
+                )]
+            );
+        }
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_index_client_loop_friend_proposals() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_index_client_loop_friend_proposals(thread_pool.clone()));
+}
+
+async fn task_index_client_loop_directory<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let rng = DummyRandom::new(&[0xdd]);
+    let private_key = PrivateKey::rand_gen(&rng);
+    let directory_identity = SoftwareEd25519Identity::from_private_key(&private_key).unwrap();
+
+    let mut icc = basic_index_client(spawner.clone(), Some(directory_identity.get_public_key()));
+
+    let index_server = IndexServerAddress {
+        public_key: PublicKey::from(&[0x37; PublicKey::len()]),
+        address: net_address("0x1337"),
+    };
+    let (mut control_receiver, close_sender) = icc.expect_server_connection(index_server).await;
+
+    // The directory is requested from the connected server:
+    icc.tick_sender.send(()).await.unwrap();
+    let response_sender = match control_receiver.next().await.unwrap() {
+        SingleClientControl::RequestDirectory(response_sender) => response_sender,
+        _ => unreachable!(),
+    };
+
+    // The directory replaces 0x1337 with 0x1338:
+    let index_server38 = NamedIndexServerAddress {
+        public_key: PublicKey::from(&[0x38; PublicKey::len()]),
+        address: net_address("0x1338"),
+        name: "0x1338".to_owned(),
+    };
+    let directory =
+        create_index_server_directory(&directory_identity, 1, vec![index_server38.clone()]);
+    response_sender.send(Some(directory)).unwrap();
+
+    let expected_mutations = vec![
+        IndexClientConfigMutation::RemoveIndexServer(PublicKey::from(&[0x37; PublicKey::len()])),
+        IndexClientConfigMutation::AddIndexServer(index_server38.clone()),
+    ];
+    for expected_mutation in expected_mutations {
+        let db_request = match icc.database_req_receiver.next().await.unwrap() {
+            DatabaseRequest::Mutate(mutate_request) => mutate_request,
+            DatabaseRequest::Subscribe(_) => unreachable!(),
+        };
+        assert_eq!(db_request.mutations, vec![expected_mutation]);
+        db_request.response_sender.send(()).unwrap();
+
+        // Changes made according to the directory are not a response to any app request:
+        match icc.app_server_receiver.next().await.unwrap() {
+            IndexClientToAppServer::ReportMutations(ic_report_mutations) => {
+                assert_eq!(ic_report_mutations.opt_app_request_id, None);
+                assert_eq!(ic_report_mutations.mutations.len(), 1);
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    // The version of the directory is saved, so that older directories will be ignored:
+    let db_request = match icc.database_req_receiver.next().await.unwrap() {
+        DatabaseRequest::Mutate(mutate_request) => mutate_request,
+        DatabaseRequest::Subscribe(_) => unreachable!(),
+    };
+    assert_eq!(
+        db_request.mutations,
+        vec![IndexClientConfigMutation::SetDirectoryVersion(1)]
+    );
+    db_request.response_sender.send(()).unwrap();
+
+    // The session with 0x1337 is closed:
+    while let Some(_control_message) = control_receiver.next().await {}
+    let _ = close_sender.send((Ok(()), None));
+    icc.expect_set_connected_server(None).await;
+
+    for _ in 0..icc.backoff_ticks {
+        icc.tick_sender.send(()).await.unwrap();
+    }
+
+    // A new connection should be made to 0x1338:
+    let session_conn_request = icc.session_receiver.next().await.unwrap();
+    assert_eq!(
+        session_conn_request.address,
+        IndexServerAddress::from(index_server38)
+    );
+}
+
+#[test]
+fn test_index_client_loop_directory() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_index_client_loop_directory(thread_pool.clone()));
+}
+
 // TODO: Add more tests.
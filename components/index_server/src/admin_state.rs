@@ -0,0 +1,149 @@
+use std::collections::{HashSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use common::mutable_state::MutableState;
+use common::never::Never;
+use common::ser_utils::ser_seq_b64;
+
+use proto::crypto::PublicKey;
+use proto::funder::messages::Currency;
+use proto::index_server::messages::{AdminChange, AdminJournalEntry, AdminRemoveEdge};
+use proto::limits::MAX_ADMIN_JOURNAL_LEN;
+
+/// Persistent state of the admin interface of an index server.
+/// Kept in a database, so that manual changes survive a restart of the server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminState {
+    /// Recent manual changes applied by admins, oldest first.
+    /// At most `MAX_ADMIN_JOURNAL_LEN` changes are kept.
+    pub journal: VecDeque<AdminJournalEntry>,
+    /// Nodes removed by an admin. Mutations sent by these nodes are ignored.
+    #[serde(with = "ser_seq_b64")]
+    pub denied_nodes: HashSet<PublicKey>,
+    /// Edges removed by an admin. Mutations that add these edges back are ignored.
+    pub denied_edges: HashSet<AdminRemoveEdge>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AdminMutation {
+    /// Deny a node or an edge, and record the change in the journal
+    ApplyChange(AdminJournalEntry),
+}
+
+impl AdminState {
+    pub fn new() -> Self {
+        AdminState {
+            journal: VecDeque::new(),
+            denied_nodes: HashSet::new(),
+            denied_edges: HashSet::new(),
+        }
+    }
+
+    /// Was the edge from `from_public_key` to `to_public_key` in `currency` removed by an admin?
+    pub fn is_edge_denied(
+        &self,
+        currency: &Currency,
+        from_public_key: &PublicKey,
+        to_public_key: &PublicKey,
+    ) -> bool {
+        self.denied_nodes.contains(from_public_key)
+            || self.denied_nodes.contains(to_public_key)
+            || self.denied_edges.contains(&AdminRemoveEdge {
+                currency: currency.clone(),
+                from_public_key: from_public_key.clone(),
+                to_public_key: to_public_key.clone(),
+            })
+    }
+}
+
+impl MutableState for AdminState {
+    type Mutation = AdminMutation;
+    type MutateError = Never;
+
+    fn mutate(&mut self, mutation: &Self::Mutation) -> Result<(), Self::MutateError> {
+        match mutation {
+            AdminMutation::ApplyChange(journal_entry) => {
+                match &journal_entry.change {
+                    AdminChange::RemoveNode(node_public_key) => {
+                        self.denied_nodes.insert(node_public_key.clone());
+                    }
+                    AdminChange::RemoveEdge(remove_edge) => {
+                        self.denied_edges.insert(remove_edge.clone());
+                    }
+                }
+                if self.journal.len() >= MAX_ADMIN_JOURNAL_LEN {
+                    let _ = self.journal.pop_front();
+                }
+                self.journal.push_back(journal_entry.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_admin_state_deny() {
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let pk_c = PublicKey::from(&[0xcc; PublicKey::len()]);
+        let pk_admin = PublicKey::from(&[0xdd; PublicKey::len()]);
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+
+        let mut admin_state = AdminState::new();
+        assert!(!admin_state.is_edge_denied(&currency, &pk_a, &pk_b));
+
+        let remove_edge = AdminRemoveEdge {
+            currency: currency.clone(),
+            from_public_key: pk_a.clone(),
+            to_public_key: pk_b.clone(),
+        };
+        admin_state
+            .mutate(&AdminMutation::ApplyChange(AdminJournalEntry {
+                admin_public_key: pk_admin.clone(),
+                change: AdminChange::RemoveEdge(remove_edge),
+            }))
+            .unwrap();
+        assert!(admin_state.is_edge_denied(&currency, &pk_a, &pk_b));
+        // Only the removed direction is denied:
+        assert!(!admin_state.is_edge_denied(&currency, &pk_b, &pk_a));
+
+        admin_state
+            .mutate(&AdminMutation::ApplyChange(AdminJournalEntry {
+                admin_public_key: pk_admin,
+                change: AdminChange::RemoveNode(pk_c.clone()),
+            }))
+            .unwrap();
+        // All the edges of a removed node are denied:
+        assert!(admin_state.is_edge_denied(&currency, &pk_c, &pk_a));
+        assert!(admin_state.is_edge_denied(&currency, &pk_b, &pk_c));
+        assert_eq!(admin_state.journal.len(), 2);
+
+        // The state survives a round trip through the database serialization:
+        let ser_state = serde_json::to_string(&admin_state).unwrap();
+        let admin_state2: AdminState = serde_json::from_str(&ser_state).unwrap();
+        assert_eq!(admin_state, admin_state2);
+    }
+
+    #[test]
+    fn test_admin_state_journal_len() {
+        let pk_admin = PublicKey::from(&[0xdd; PublicKey::len()]);
+        let mut admin_state = AdminState::new();
+        for i in 0..MAX_ADMIN_JOURNAL_LEN + 1 {
+            let node_public_key = PublicKey::from(&[(i % 0x100) as u8; PublicKey::len()]);
+            admin_state
+                .mutate(&AdminMutation::ApplyChange(AdminJournalEntry {
+                    admin_public_key: pk_admin.clone(),
+                    change: AdminChange::RemoveNode(node_public_key),
+                }))
+                .unwrap();
+        }
+        assert_eq!(admin_state.journal.len(), MAX_ADMIN_JOURNAL_LEN);
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use proto::crypto::{PublicKey, Uid};
+use proto::index_server::messages::{ForwardMutationsUpdate, NodeSessionCounter};
+
+/// Remembers the most recent verified mutations updates of every node, so that they can be
+/// resent to servers that have missed them (anti-entropy reconciliation).
+///
+/// Only updates of the latest session of every node are kept, and at most `max_node_updates` of
+/// them. Old updates are useless anyway, because their time proofs will not be accepted.
+pub struct UpdatesLog {
+    max_node_updates: usize,
+    nodes: HashMap<PublicKey, Vec<ForwardMutationsUpdate>>,
+}
+
+impl UpdatesLog {
+    pub fn new(max_node_updates: usize) -> Self {
+        UpdatesLog {
+            max_node_updates,
+            nodes: HashMap::new(),
+        }
+    }
+
+    /// Record a verified update.
+    /// Updates of a node are assumed to be inserted in order.
+    pub fn insert(&mut self, forward_mutations_update: ForwardMutationsUpdate) {
+        let mutations_update = &forward_mutations_update.mutations_update;
+        let node_updates = self
+            .nodes
+            .entry(mutations_update.node_public_key.clone())
+            .or_insert_with(Vec::new);
+
+        if let Some(last) = node_updates.last() {
+            if last.mutations_update.session_id != mutations_update.session_id {
+                // A new session begins. Updates of the previous session are obsolete:
+                node_updates.clear();
+            }
+        }
+
+        node_updates.push(forward_mutations_update);
+        if node_updates.len() > self.max_node_updates {
+            let excess = node_updates.len() - self.max_node_updates;
+            node_updates.drain(0..excess);
+        }
+    }
+
+    /// Forget all updates of a node (Usually because the node has expired)
+    pub fn remove_node(&mut self, node_public_key: &PublicKey) {
+        let _ = self.nodes.remove(node_public_key);
+    }
+
+    /// The session and counter of the latest update known for a node
+    pub fn last_session_counter(&self, node_public_key: &PublicKey) -> Option<(&Uid, u64)> {
+        let forward_mutations_update = self.nodes.get(node_public_key)?.last()?;
+        let mutations_update = &forward_mutations_update.mutations_update;
+        Some((&mutations_update.session_id, mutations_update.counter))
+    }
+
+    /// A summary of the latest update known for every node
+    pub fn digest(&self) -> Vec<NodeSessionCounter> {
+        self.nodes
+            .values()
+            .filter_map(|node_updates| node_updates.last())
+            .map(|forward_mutations_update| {
+                let mutations_update = &forward_mutations_update.mutations_update;
+                NodeSessionCounter {
+                    node_public_key: mutations_update.node_public_key.clone(),
+                    session_id: mutations_update.session_id.clone(),
+                    counter: mutations_update.counter,
+                }
+            })
+            .collect()
+    }
+
+    /// Does the remote server know updates we are missing?
+    /// Sessions that differ from ours are not counted, as we can not tell which one is newer.
+    pub fn is_behind(&self, remote_digest: &[NodeSessionCounter]) -> bool {
+        remote_digest.iter().any(|remote| {
+            match self
+                .nodes
+                .get(&remote.node_public_key)
+                .and_then(|node_updates| node_updates.last())
+            {
+                None => true,
+                Some(forward_mutations_update) => {
+                    let mutations_update = &forward_mutations_update.mutations_update;
+                    mutations_update.session_id == remote.session_id
+                        && mutations_update.counter < remote.counter
+                }
+            }
+        })
+    }
+
+    /// Calculate the updates a remote server is missing, according to its digest.
+    pub fn missing_updates(
+        &self,
+        remote_digest: &[NodeSessionCounter],
+    ) -> Vec<ForwardMutationsUpdate> {
+        let remote_counters = remote_digest
+            .iter()
+            .map(|node_session_counter| {
+                (&node_session_counter.node_public_key, node_session_counter)
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut missing = Vec::new();
+        for (node_public_key, node_updates) in &self.nodes {
+            let opt_remote = remote_counters.get(node_public_key);
+            for forward_mutations_update in node_updates {
+                let mutations_update = &forward_mutations_update.mutations_update;
+                let is_missing = match opt_remote {
+                    None => true,
+                    // If the sessions differ, we can not tell which one is newer.
+                    // The remote server will discard our updates if they are stale.
+                    Some(remote) if remote.session_id != mutations_update.session_id => true,
+                    Some(remote) => mutations_update.counter > remote.counter,
+                };
+                if is_missing {
+                    missing.push(forward_mutations_update.clone());
+                }
+            }
+        }
+        missing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proto::crypto::{HashResult, RandValue, Signature, Uid};
+    use proto::index_server::messages::MutationsUpdate;
+
+    fn dummy_update(node: u8, session: u8, counter: u64) -> ForwardMutationsUpdate {
+        ForwardMutationsUpdate {
+            mutations_update: MutationsUpdate {
+                node_public_key: PublicKey::from(&[node; PublicKey::len()]),
+                index_mutations: Vec::new(),
+                time_hash: HashResult::from(&[0; HashResult::len()]),
+                session_id: Uid::from(&[session; Uid::len()]),
+                counter,
+                rand_nonce: RandValue::from(&[0; RandValue::len()]),
+                signature: Signature::from(&[0; Signature::len()]),
+                pow_nonce: 0,
+            },
+            time_proof_chain: Vec::new(),
+        }
+    }
+
+    fn counters(updates: &[ForwardMutationsUpdate]) -> Vec<u64> {
+        let mut counters = updates
+            .iter()
+            .map(|update| update.mutations_update.counter)
+            .collect::<Vec<_>>();
+        counters.sort();
+        counters
+    }
+
+    #[test]
+    fn test_updates_log_basic() {
+        let mut updates_log = UpdatesLog::new(3);
+        for counter in 0..5 {
+            updates_log.insert(dummy_update(0xaa, 1, counter));
+        }
+
+        // Only the last 3 updates are kept:
+        assert_eq!(counters(&updates_log.missing_updates(&[])), vec![2, 3, 4]);
+
+        let digest = updates_log.digest();
+        assert_eq!(
+            digest,
+            vec![NodeSessionCounter {
+                node_public_key: PublicKey::from(&[0xaa; PublicKey::len()]),
+                session_id: Uid::from(&[1; Uid::len()]),
+                counter: 4,
+            }]
+        );
+        // A remote server with the same digest is missing nothing:
+        assert!(updates_log.missing_updates(&digest).is_empty());
+        assert!(!updates_log.is_behind(&digest));
+
+        // A remote server that is ahead:
+        let mut ahead_digest = digest.clone();
+        ahead_digest[0].counter = 5;
+        assert!(updates_log.is_behind(&ahead_digest));
+        assert!(UpdatesLog::new(3).is_behind(&digest));
+
+        // A remote server that is behind:
+        let mut behind_digest = digest.clone();
+        behind_digest[0].counter = 2;
+        assert_eq!(
+            counters(&updates_log.missing_updates(&behind_digest)),
+            vec![3, 4]
+        );
+
+        // A remote server that knows another session:
+        let mut other_digest = digest.clone();
+        other_digest[0].session_id = Uid::from(&[2; Uid::len()]);
+        assert_eq!(
+            counters(&updates_log.missing_updates(&other_digest)),
+            vec![2, 3, 4]
+        );
+        assert!(!updates_log.is_behind(&other_digest));
+    }
+
+    #[test]
+    fn test_updates_log_new_session() {
+        let mut updates_log = UpdatesLog::new(8);
+        updates_log.insert(dummy_update(0xaa, 1, 0));
+        updates_log.insert(dummy_update(0xaa, 1, 1));
+        updates_log.insert(dummy_update(0xbb, 1, 7));
+
+        assert_eq!(
+            updates_log.last_session_counter(&PublicKey::from(&[0xaa; PublicKey::len()])),
+            Some((&Uid::from(&[1; Uid::len()]), 1))
+        );
+
+        // A new session replaces the old one:
+        updates_log.insert(dummy_update(0xaa, 2, 0));
+        let missing = updates_log.missing_updates(&[]);
+        assert_eq!(counters(&missing), vec![0, 7]);
+
+        assert_eq!(
+            updates_log.last_session_counter(&PublicKey::from(&[0xaa; PublicKey::len()])),
+            Some((&Uid::from(&[2; Uid::len()]), 0))
+        );
+
+        updates_log.remove_node(&PublicKey::from(&[0xbb; PublicKey::len()]));
+        assert_eq!(updates_log.digest().len(), 1);
+        assert!(updates_log
+            .last_session_counter(&PublicKey::from(&[0xbb; PublicKey::len()]))
+            .is_none());
+    }
+}
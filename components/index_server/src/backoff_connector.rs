@@ -1,7 +1,6 @@
 use std::marker::PhantomData;
 
 use common::conn::{BoxFuture, FutTransform};
-use timer::utils::sleep_ticks;
 use timer::TimerClient;
 
 pub struct BackoffConnector<I, O, C> {
@@ -62,9 +61,12 @@ where
                     return Some(output);
                 }
                 // Wait before we attempt to reconnect:
-                sleep_ticks(c_self.backoff_ticks, c_self.timer_client.clone())
+                let deadline = c_self
+                    .timer_client
+                    .request_deadline(c_self.backoff_ticks)
                     .await
                     .ok()?;
+                deadline.await;
             }
         })
     }
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use proto::crypto::PublicKey;
+use proto::index_server::messages::FriendProposal;
+
+struct PendingProposal {
+    friend_proposal: FriendProposal,
+    ticks_left: usize,
+}
+
+/// Keeps friend proposals sent to nodes that are not connected to this server, until the
+/// destination node connects.
+///
+/// At most one proposal is kept for every (source, destination) pair, and at most
+/// `max_node_proposals` proposals are kept for every destination node. Proposals expire after
+/// `proposal_ticks` timer ticks.
+pub struct FriendInbox {
+    max_node_proposals: usize,
+    proposal_ticks: usize,
+    /// dest_public_key -> (src_public_key -> pending proposal)
+    nodes: HashMap<PublicKey, HashMap<PublicKey, PendingProposal>>,
+}
+
+impl FriendInbox {
+    pub fn new(max_node_proposals: usize, proposal_ticks: usize) -> Self {
+        FriendInbox {
+            max_node_proposals,
+            proposal_ticks,
+            nodes: HashMap::new(),
+        }
+    }
+
+    /// Keep a verified proposal until its destination node connects.
+    /// A newer proposal from the same source replaces the older one.
+    /// Returns false if the inbox of the destination node is full.
+    pub fn insert(&mut self, friend_proposal: FriendProposal) -> bool {
+        let node_proposals = self
+            .nodes
+            .entry(friend_proposal.dest_public_key.clone())
+            .or_insert_with(HashMap::new);
+
+        if !node_proposals.contains_key(&friend_proposal.src_public_key)
+            && node_proposals.len() >= self.max_node_proposals
+        {
+            return false;
+        }
+
+        node_proposals.insert(
+            friend_proposal.src_public_key.clone(),
+            PendingProposal {
+                friend_proposal,
+                ticks_left: self.proposal_ticks,
+            },
+        );
+        true
+    }
+
+    /// Remove and return all the proposals kept for a destination node
+    pub fn take(&mut self, dest_public_key: &PublicKey) -> Vec<FriendProposal> {
+        self.nodes
+            .remove(dest_public_key)
+            .map(|node_proposals| {
+                node_proposals
+                    .into_iter()
+                    .map(|(_src_public_key, pending)| pending.friend_proposal)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Expire old proposals
+    pub fn tick(&mut self) {
+        for node_proposals in self.nodes.values_mut() {
+            node_proposals.retain(|_src_public_key, pending| {
+                pending.ticks_left = pending.ticks_left.saturating_sub(1);
+                pending.ticks_left > 0
+            });
+        }
+        self.nodes
+            .retain(|_dest_public_key, node_proposals| !node_proposals.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use proto::crypto::{RandValue, Signature};
+    use proto::funder::messages::Currency;
+
+    fn dummy_proposal(src: u8, dest: u8, proposed_max_debt: u128) -> FriendProposal {
+        FriendProposal {
+            src_public_key: PublicKey::from(&[src; PublicKey::len()]),
+            dest_public_key: PublicKey::from(&[dest; PublicKey::len()]),
+            relays: Vec::new(),
+            currency: Currency::try_from("FST".to_owned()).unwrap(),
+            proposed_max_debt,
+            rand_nonce: RandValue::from(&[0; RandValue::len()]),
+            signature: Signature::from(&[0; Signature::len()]),
+        }
+    }
+
+    fn max_debts(proposals: &[FriendProposal]) -> Vec<u128> {
+        let mut max_debts = proposals
+            .iter()
+            .map(|proposal| proposal.proposed_max_debt)
+            .collect::<Vec<_>>();
+        max_debts.sort();
+        max_debts
+    }
+
+    #[test]
+    fn test_friend_inbox_insert_take() {
+        let mut inbox = FriendInbox::new(2, 8);
+        assert!(inbox.insert(dummy_proposal(0, 9, 10)));
+        assert!(inbox.insert(dummy_proposal(1, 9, 20)));
+        // The inbox of node 9 is full:
+        assert!(!inbox.insert(dummy_proposal(2, 9, 30)));
+        // A newer proposal from the same source replaces the older one:
+        assert!(inbox.insert(dummy_proposal(0, 9, 15)));
+        assert!(inbox.insert(dummy_proposal(0, 8, 40)));
+
+        assert_eq!(
+            max_debts(&inbox.take(&PublicKey::from(&[9; PublicKey::len()]))),
+            vec![15, 20]
+        );
+        assert!(inbox
+            .take(&PublicKey::from(&[9; PublicKey::len()]))
+            .is_empty());
+        assert_eq!(
+            max_debts(&inbox.take(&PublicKey::from(&[8; PublicKey::len()]))),
+            vec![40]
+        );
+    }
+
+    #[test]
+    fn test_friend_inbox_expire() {
+        let mut inbox = FriendInbox::new(4, 2);
+        assert!(inbox.insert(dummy_proposal(0, 9, 10)));
+        inbox.tick();
+        assert!(inbox.insert(dummy_proposal(1, 9, 20)));
+        inbox.tick();
+
+        // Only the proposal inserted after the first tick is left:
+        assert_eq!(
+            max_debts(&inbox.take(&PublicKey::from(&[9; PublicKey::len()]))),
+            vec![20]
+        );
+    }
+}
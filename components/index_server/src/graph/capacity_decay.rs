@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Decay schedule for the capacities reported by nodes.
+///
+/// Time is measured in epochs. One epoch is one time hash tick of the index server.
+/// A capacity that was not updated for `half_life` epochs is halved, and it is halved again after
+/// every additional `half_life` epochs. An edge that was not updated for `horizon` epochs is
+/// removed from the graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapacityDecay {
+    /// Amount of epochs it takes for a reported capacity to decay to half.
+    /// Zero means that reported capacities never decay.
+    pub half_life: u64,
+    /// Amount of epochs after which an edge that was not updated is removed.
+    pub horizon: u64,
+}
+
+/// A capacity that may decay over time.
+pub trait Decay {
+    /// Halve the capacity `halvings` times.
+    fn halve(&self, halvings: u64) -> Self;
+}
+
+impl Decay for u128 {
+    fn halve(&self, halvings: u64) -> Self {
+        if halvings >= 128 {
+            0
+        } else {
+            self >> halvings
+        }
+    }
+}
+
+impl CapacityDecay {
+    /// Calculate the capacity of an edge that was reported with `recv_capacity`, `age` epochs ago.
+    /// Returns None if the edge should be removed.
+    pub fn decay<C: Decay>(&self, recv_capacity: &C, age: u64) -> Option<C> {
+        if age >= self.horizon {
+            return None;
+        }
+        let halvings = age.checked_div(self.half_life).unwrap_or(0);
+        Some(recv_capacity.halve(halvings))
+    }
+}
+
+/// A change to the graphs, caused by the aging of an edge.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecayUpdate<G, N, C> {
+    SetRecvCapacity(G, N, N, C),
+    RemoveEdge(G, N, N),
+}
+
+#[derive(Debug)]
+struct ReportedEdge<C> {
+    /// The capacity reported by the node
+    recv_capacity: C,
+    /// The capacity after decay
+    decayed_capacity: C,
+    /// The epoch of the last report
+    epoch: u64,
+}
+
+/// Keeps track of the last update of every edge, and decays old edges.
+#[derive(Debug)]
+pub struct EdgesDecay<G, N, C> {
+    capacity_decay: CapacityDecay,
+    epoch: u64,
+    edges: HashMap<(G, N, N), ReportedEdge<C>>,
+}
+
+impl<G, N, C> EdgesDecay<G, N, C>
+where
+    G: Hash + Eq + Clone,
+    N: Hash + Eq + Clone,
+    C: Decay + Clone + PartialEq,
+{
+    pub fn new(capacity_decay: CapacityDecay) -> Self {
+        EdgesDecay {
+            capacity_decay,
+            epoch: 0,
+            edges: HashMap::new(),
+        }
+    }
+
+    /// A node reported the capacity of an edge
+    pub fn update_edge(&mut self, g: G, a: N, b: N, recv_capacity: C) {
+        let reported_edge = ReportedEdge {
+            decayed_capacity: recv_capacity.clone(),
+            recv_capacity,
+            epoch: self.epoch,
+        };
+        let _ = self.edges.insert((g, a, b), reported_edge);
+    }
+
+    pub fn remove_edge(&mut self, g: G, a: N, b: N) {
+        let _ = self.edges.remove(&(g, a, b));
+    }
+
+    /// Forget all the edges starting from the node `a`
+    pub fn remove_node(&mut self, a: &N) {
+        self.edges.retain(|(_g, edge_a, _b), _| edge_a != a);
+    }
+
+    /// Advance time by one epoch.
+    /// Returns the changes that should be applied to the graphs.
+    pub fn epoch(&mut self) -> Vec<DecayUpdate<G, N, C>> {
+        self.epoch = self.epoch.saturating_add(1);
+
+        let epoch = self.epoch;
+        let capacity_decay = &self.capacity_decay;
+        let mut decay_updates = Vec::new();
+        self.edges.retain(|(g, a, b), reported_edge| {
+            let age = epoch.saturating_sub(reported_edge.epoch);
+            match capacity_decay.decay(&reported_edge.recv_capacity, age) {
+                None => {
+                    decay_updates.push(DecayUpdate::RemoveEdge(g.clone(), a.clone(), b.clone()));
+                    false
+                }
+                Some(decayed_capacity) => {
+                    if decayed_capacity != reported_edge.decayed_capacity {
+                        reported_edge.decayed_capacity = decayed_capacity.clone();
+                        decay_updates.push(DecayUpdate::SetRecvCapacity(
+                            g.clone(),
+                            a.clone(),
+                            b.clone(),
+                            decayed_capacity,
+                        ));
+                    }
+                    true
+                }
+            }
+        });
+        decay_updates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capacity_decay() {
+        let capacity_decay = CapacityDecay {
+            half_life: 4,
+            horizon: 10,
+        };
+        assert_eq!(capacity_decay.decay(&100u128, 0), Some(100));
+        assert_eq!(capacity_decay.decay(&100u128, 3), Some(100));
+        assert_eq!(capacity_decay.decay(&100u128, 4), Some(50));
+        assert_eq!(capacity_decay.decay(&100u128, 8), Some(25));
+        assert_eq!(capacity_decay.decay(&100u128, 9), Some(25));
+        assert_eq!(capacity_decay.decay(&100u128, 10), None);
+
+        // Capacities never decay, but edges are still removed after the horizon:
+        let capacity_decay = CapacityDecay {
+            half_life: 0,
+            horizon: 10,
+        };
+        assert_eq!(capacity_decay.decay(&100u128, 9), Some(100));
+        assert_eq!(capacity_decay.decay(&100u128, 10), None);
+
+        let capacity_decay = CapacityDecay {
+            half_life: 1,
+            horizon: 1000,
+        };
+        assert_eq!(capacity_decay.decay(&u128::max_value(), 200), Some(0));
+    }
+
+    /// Sort decay updates by the source node of the edge
+    fn sorted(
+        mut decay_updates: Vec<DecayUpdate<u8, u32, u128>>,
+    ) -> Vec<DecayUpdate<u8, u32, u128>> {
+        decay_updates.sort_by_key(|decay_update| match decay_update {
+            DecayUpdate::SetRecvCapacity(_, a, ..) | DecayUpdate::RemoveEdge(_, a, _) => *a,
+        });
+        decay_updates
+    }
+
+    #[test]
+    fn test_edges_decay() {
+        let mut edges_decay = EdgesDecay::<u8, u32, u128>::new(CapacityDecay {
+            half_life: 2,
+            horizon: 5,
+        });
+
+        edges_decay.update_edge(0, 1, 2, 100);
+        edges_decay.update_edge(0, 2, 1, 40);
+        assert!(edges_decay.epoch().is_empty());
+
+        // Second epoch: All capacities are halved.
+        assert_eq!(
+            sorted(edges_decay.epoch()),
+            vec![
+                DecayUpdate::SetRecvCapacity(0, 1, 2, 50),
+                DecayUpdate::SetRecvCapacity(0, 2, 1, 20),
+            ]
+        );
+
+        // A new report resets the age of the edge:
+        edges_decay.update_edge(0, 1, 2, 100);
+        assert!(edges_decay.epoch().is_empty());
+        assert_eq!(
+            sorted(edges_decay.epoch()),
+            vec![
+                DecayUpdate::SetRecvCapacity(0, 1, 2, 50),
+                DecayUpdate::SetRecvCapacity(0, 2, 1, 10),
+            ]
+        );
+        // The edge that was not updated reaches the horizon:
+        assert_eq!(edges_decay.epoch(), vec![DecayUpdate::RemoveEdge(0, 2, 1)]);
+
+        edges_decay.remove_node(&1);
+        for _ in 0..8 {
+            assert!(edges_decay.epoch().is_empty());
+        }
+    }
+}
@@ -3,9 +3,13 @@ use std::hash::Hash;
 
 use futures::channel::{mpsc, oneshot};
 use futures::task::{Spawn, SpawnError, SpawnExt};
-use futures::{FutureExt, SinkExt, StreamExt, TryFutureExt};
+use futures::{future, stream, FutureExt, SinkExt, Stream, StreamExt, TryFutureExt};
 
-use super::capacity_graph::{CapacityEdge, CapacityGraph, CapacityMultiRoute};
+use common::select_streams::select_streams;
+
+use routing::capacity_graph::{CapacityEdge, CapacityGraph, CapacityMultiRoute, RouteConstraints};
+
+use super::capacity_decay::{CapacityDecay, Decay, DecayUpdate, EdgesDecay};
 
 pub enum GraphRequest<G, N, C, T> {
     /// Change capacities on a directed edge:
@@ -23,97 +27,241 @@ pub enum GraphRequest<G, N, C, T> {
     RemoveNode(N, oneshot::Sender<()>),
     /// Get some routes from one node to another of at least certain capacity.
     /// If an exclude directed edge is provided, the routes must not contain this directed edge.
+    /// All the routes must satisfy the provided constraints.
     GetMultiRoutes(
         G,
         N,
         N,
         C,
         Option<(N, N)>,
+        RouteConstraints<N, C>,
         oneshot::Sender<Vec<CapacityMultiRoute<N, C, T>>>,
-    ), // (from, to, capacity, opt_exclude)
+    ), // (from, to, capacity, opt_exclude, constraints)
+    /// Get up to a certain amount of routes from one node to another of at least certain
+    /// capacity, ranked by residual capacity and recency of capacity reports.
+    GetRankedRoutes(
+        G,
+        N,
+        N,
+        C,
+        Option<(N, N)>,
+        usize,
+        RouteConstraints<N, C>,
+        oneshot::Sender<Vec<CapacityMultiRoute<N, C, T>>>,
+    ), // (from, to, capacity, opt_exclude, max_routes, constraints)
     /// Expire old outgoing edges for the specified node
     Tick(N, oneshot::Sender<()>),
+    /// Get the amount of nodes and directed edges, summed over all the graphs
+    GetSize(oneshot::Sender<(usize, usize)>),
+    /// Get the directed edges of all the graphs.
+    /// If a node is provided, only edges from or to this node are returned.
+    GetEdges(Option<N>, oneshot::Sender<Vec<GraphEdge<G, N, C, T>>>),
 }
 
+/// A directed edge of one of the graphs: (graph, from, to, capacity_edge)
+pub type GraphEdge<G, N, C, T> = (G, N, N, CapacityEdge<C, T>);
+
 #[derive(Debug)]
 pub enum GraphServiceError {
     /// Failed to spawn to self ThreadPool
     LocalSpawnError,
 }
 
+enum GraphEvent<G, N, C, T> {
+    Request(GraphRequest<G, N, C, T>),
+    RequestsClosed,
+    /// One time hash epoch has passed
+    Epoch,
+}
+
 #[allow(clippy::many_single_char_names)]
 /// Process one GraphRequest, and send the response through the provided sender.
 /// This function might perform a long computation and take a long time to complete.
 fn process_request<G, N, C, T, CG>(
     capacity_graphs: &mut HashMap<G, CG>,
+    edges_decay: &mut EdgesDecay<G, N, C>,
     graph_request: GraphRequest<G, N, C, T>,
 ) where
-    G: Hash + Eq,
+    G: Hash + Eq + Clone,
+    N: Hash + Eq + Clone,
+    C: Decay + Clone + PartialEq,
     CG: CapacityGraph<Node = N, Capacity = C, Rate = T>,
 {
     match graph_request {
         GraphRequest::UpdateEdge(g, a, b, capacity_edge, sender) => {
+            edges_decay.update_edge(
+                g.clone(),
+                a.clone(),
+                b.clone(),
+                capacity_edge.recv_capacity.clone(),
+            );
             let capacity_graph = capacity_graphs.entry(g).or_insert_with(CG::new);
             let _ = sender.send(capacity_graph.update_edge(a, b, capacity_edge));
         }
         GraphRequest::RemoveEdge(g, a, b, sender) => {
+            edges_decay.remove_edge(g.clone(), a.clone(), b.clone());
             if let Some(capacity_graph) = capacity_graphs.get_mut(&g) {
                 let _ = sender.send(capacity_graph.remove_edge(&a, &b));
             }
         }
         GraphRequest::RemoveNode(a, sender) => {
-            capacity_graphs.retain(|_g, capacity_graph| capacity_graph.remove_node(&a));
+            edges_decay.remove_node(&a);
+            // Forget about graphs that became empty:
+            capacity_graphs.retain(|_g, capacity_graph| !capacity_graph.remove_node(&a));
             let _ = sender.send(());
         }
-        GraphRequest::GetMultiRoutes(g, a, b, capacity, opt_exclude, sender) => {
+        GraphRequest::GetMultiRoutes(g, a, b, capacity, opt_exclude, constraints, sender) => {
             let routes = if let Some(capacity_graph) = capacity_graphs.get_mut(&g) {
                 match opt_exclude {
-                    Some((c, d)) => {
-                        capacity_graph.get_multi_routes(&a, &b, capacity, Some((&c, &d)))
-                    }
-                    None => capacity_graph.get_multi_routes(&a, &b, capacity, None),
+                    Some((c, d)) => capacity_graph.get_multi_routes(
+                        &a,
+                        &b,
+                        capacity,
+                        Some((&c, &d)),
+                        &constraints,
+                    ),
+                    None => capacity_graph.get_multi_routes(&a, &b, capacity, None, &constraints),
                 }
             } else {
                 vec![]
             };
             let _ = sender.send(routes);
         }
+        GraphRequest::GetRankedRoutes(
+            g,
+            a,
+            b,
+            capacity,
+            opt_exclude,
+            max_routes,
+            constraints,
+            sender,
+        ) => {
+            let routes = if let Some(capacity_graph) = capacity_graphs.get_mut(&g) {
+                let opt_exclude = opt_exclude.as_ref().map(|(c, d)| (c, d));
+                capacity_graph.get_ranked_routes(
+                    &a,
+                    &b,
+                    capacity,
+                    opt_exclude,
+                    max_routes,
+                    &constraints,
+                )
+            } else {
+                vec![]
+            };
+            let _ = sender.send(routes);
+        }
         GraphRequest::Tick(a, sender) => {
             for capacity_graph in capacity_graphs.values_mut() {
                 capacity_graph.tick(&a);
             }
             let _ = sender.send(());
         }
+        GraphRequest::GetSize(sender) => {
+            let size = capacity_graphs
+                .values()
+                .map(|capacity_graph| capacity_graph.size())
+                .fold((0, 0), |(num_nodes, num_edges), (g_nodes, g_edges)| {
+                    (num_nodes + g_nodes, num_edges + g_edges)
+                });
+            let _ = sender.send(size);
+        }
+        GraphRequest::GetEdges(opt_node, sender) => {
+            let mut edges = Vec::new();
+            for (g, capacity_graph) in capacity_graphs.iter() {
+                edges.extend(
+                    capacity_graph
+                        .get_edges(opt_node.as_ref())
+                        .into_iter()
+                        .map(|(a, b, capacity_edge)| (g.clone(), a, b, capacity_edge)),
+                );
+            }
+            let _ = sender.send(edges);
+        }
+    }
+}
+
+/// Advance time by one epoch, decaying the capacities of edges that were not updated recently.
+fn process_epoch<G, N, C, T, CG>(
+    capacity_graphs: &mut HashMap<G, CG>,
+    edges_decay: &mut EdgesDecay<G, N, C>,
+) where
+    G: Hash + Eq + Clone,
+    N: Hash + Eq + Clone,
+    C: Decay + Clone + PartialEq,
+    CG: CapacityGraph<Node = N, Capacity = C, Rate = T>,
+{
+    for decay_update in edges_decay.epoch() {
+        match decay_update {
+            DecayUpdate::SetRecvCapacity(g, a, b, recv_capacity) => {
+                let opt_old_capacity = capacity_graphs.get_mut(&g).and_then(|capacity_graph| {
+                    capacity_graph.set_recv_capacity(&a, &b, recv_capacity)
+                });
+                // The edge might have already been removed from the graph (For example, by a
+                // tick). In that case there is nothing left to decay:
+                if opt_old_capacity.is_none() {
+                    edges_decay.remove_edge(g, a, b);
+                }
+            }
+            DecayUpdate::RemoveEdge(g, a, b) => {
+                if let Some(capacity_graph) = capacity_graphs.get_mut(&g) {
+                    let _ = capacity_graph.remove_edge(&a, &b);
+                }
+            }
+        }
     }
 }
 
-async fn graph_service_loop<G, N, C, T, CG, GS>(
+async fn graph_service_loop<G, N, C, T, CG, TS, GS>(
     mut capacity_graphs: HashMap<G, CG>,
-    mut incoming_requests: mpsc::Receiver<GraphRequest<G, N, C, T>>,
+    mut edges_decay: EdgesDecay<G, N, C>,
+    incoming_requests: mpsc::Receiver<GraphRequest<G, N, C, T>>,
+    timer_stream: TS,
     graph_service_spawner: GS,
 ) -> Result<(), GraphServiceError>
 where
-    G: Send + Hash + Eq + 'static,
-    N: Send + 'static,
-    C: Send + 'static,
+    G: Send + Hash + Eq + Clone + 'static,
+    N: Send + Hash + Eq + Clone + 'static,
+    C: Send + Decay + Clone + PartialEq + 'static,
     T: Send + 'static,
     CG: CapacityGraph<Node = N, Capacity = C, Rate = T> + Send + 'static,
+    TS: Stream + Unpin + Send,
     GS: Spawn,
 {
     // We use a separate spawner to be used for long graph computations.
     // We don't want to block the external shared thread pool.
 
-    while let Some(graph_request) = incoming_requests.next().await {
+    let incoming_requests = incoming_requests
+        .map(GraphEvent::Request)
+        .chain(stream::once(future::ready(GraphEvent::RequestsClosed)));
+    let timer_stream = timer_stream.map(|_| GraphEvent::Epoch);
+    let mut events = select_streams![incoming_requests, timer_stream];
+
+    while let Some(event) = events.next().await {
+        let opt_graph_request = match event {
+            GraphEvent::Request(graph_request) => Some(graph_request),
+            GraphEvent::RequestsClosed => break,
+            GraphEvent::Epoch => None,
+        };
+
         // Run the graph computation over own pool:
-        let process_request_handle = graph_service_spawner
+        let process_handle = graph_service_spawner
             .spawn_with_handle(async move {
-                process_request(&mut capacity_graphs, graph_request);
-                capacity_graphs
+                match opt_graph_request {
+                    Some(graph_request) => {
+                        process_request(&mut capacity_graphs, &mut edges_decay, graph_request)
+                    }
+                    None => process_epoch(&mut capacity_graphs, &mut edges_decay),
+                }
+                (capacity_graphs, edges_decay)
             })
             .map_err(|_| GraphServiceError::LocalSpawnError)?;
 
         // Wait for completion of the computation on the external pool:
-        capacity_graphs = process_request_handle.await;
+        let (new_capacity_graphs, new_edges_decay) = process_handle.await;
+        capacity_graphs = new_capacity_graphs;
+        edges_decay = new_edges_decay;
     }
     Ok(())
 }
@@ -192,6 +340,7 @@ impl<G, N, C, T> GraphClient<G, N, C, T> {
     ///
     /// opt_exclude is an optional edge to exclude (The returned route must not go through this
     /// edge). This can be useful for finding non trivial loops.
+    /// All the returned routes must satisfy `constraints`.
     pub async fn get_multi_routes(
         &mut self,
         g: G,
@@ -199,6 +348,7 @@ impl<G, N, C, T> GraphClient<G, N, C, T> {
         b: N,
         capacity: C,
         opt_exclude: Option<(N, N)>,
+        constraints: RouteConstraints<N, C>,
     ) -> Result<Vec<CapacityMultiRoute<N, C, T>>, GraphClientError> {
         let (sender, receiver) = oneshot::channel();
         self.requests_sender
@@ -208,6 +358,35 @@ impl<G, N, C, T> GraphClient<G, N, C, T> {
                 b,
                 capacity,
                 opt_exclude,
+                constraints,
+                sender,
+            ))
+            .await?;
+        Ok(receiver.await?)
+    }
+
+    /// Obtain up to `max_routes` routes with capacity at least `capacity`, ranked by residual
+    /// capacity and recency of capacity reports. Best ranked routes come first.
+    pub async fn get_ranked_routes(
+        &mut self,
+        g: G,
+        a: N,
+        b: N,
+        capacity: C,
+        opt_exclude: Option<(N, N)>,
+        max_routes: usize,
+        constraints: RouteConstraints<N, C>,
+    ) -> Result<Vec<CapacityMultiRoute<N, C, T>>, GraphClientError> {
+        let (sender, receiver) = oneshot::channel();
+        self.requests_sender
+            .send(GraphRequest::GetRankedRoutes(
+                g,
+                a,
+                b,
+                capacity,
+                opt_exclude,
+                max_routes,
+                constraints,
                 sender,
             ))
             .await?;
@@ -222,31 +401,65 @@ impl<G, N, C, T> GraphClient<G, N, C, T> {
             .await?;
         Ok(receiver.await?)
     }
+
+    /// Get the amount of nodes and directed edges, summed over all the graphs
+    pub async fn get_size(&mut self) -> Result<(usize, usize), GraphClientError> {
+        let (sender, receiver) = oneshot::channel();
+        self.requests_sender
+            .send(GraphRequest::GetSize(sender))
+            .await?;
+        Ok(receiver.await?)
+    }
+
+    /// Get the directed edges of all the graphs.
+    /// If `opt_node` is provided, only edges from or to this node are returned.
+    pub async fn get_edges(
+        &mut self,
+        opt_node: Option<N>,
+    ) -> Result<Vec<GraphEdge<G, N, C, T>>, GraphClientError> {
+        let (sender, receiver) = oneshot::channel();
+        self.requests_sender
+            .send(GraphRequest::GetEdges(opt_node, sender))
+            .await?;
+        Ok(receiver.await?)
+    }
 }
 
 /// Spawn a graph service, returning a GraphClient on success.
 /// GraphClient can be cloned to allow multiple clients.
-pub fn create_graph_service<G, N, C, T, CG, GS, S>(
+///
+/// Every item of `timer_stream` is one epoch. Capacities of edges that were not updated recently
+/// decay according to `capacity_decay`.
+pub fn create_graph_service<G, N, C, T, CG, TS, GS, S>(
+    capacity_decay: CapacityDecay,
+    timer_stream: TS,
     graph_service_spawner: GS,
     spawner: S,
 ) -> Result<GraphClient<G, N, C, T>, SpawnError>
 where
-    G: Hash + Eq + Send + 'static,
-    N: Send + 'static,
-    C: Send + 'static,
+    G: Hash + Eq + Clone + Send + 'static,
+    N: Hash + Eq + Clone + Send + 'static,
+    C: Decay + Clone + PartialEq + Send + 'static,
     T: Send + 'static,
     CG: CapacityGraph<Node = N, Capacity = C, Rate = T> + Send + 'static,
+    TS: Stream + Unpin + Send + 'static,
     GS: Spawn + Send + 'static,
     S: Spawn,
 {
     let (requests_sender, requests_receiver) = mpsc::channel(0);
 
     let capacity_graphs = HashMap::<G, CG>::new();
-
-    let graph_service_loop_fut =
-        graph_service_loop(capacity_graphs, requests_receiver, graph_service_spawner)
-            .map_err(|e| error!("graph_service_loop() error: {:?}", e))
-            .map(|_| ());
+    let edges_decay = EdgesDecay::new(capacity_decay);
+
+    let graph_service_loop_fut = graph_service_loop(
+        capacity_graphs,
+        edges_decay,
+        requests_receiver,
+        timer_stream,
+        graph_service_spawner,
+    )
+    .map_err(|e| error!("graph_service_loop() error: {:?}", e))
+    .map(|_| ());
 
     spawner.spawn(graph_service_loop_fut)?;
     Ok(GraphClient::new(requests_sender))
@@ -256,9 +469,9 @@ where
 mod tests {
     use super::*;
 
-    use super::super::capacity_graph::CapacityRoute;
-    use super::super::simple_capacity_graph::SimpleCapacityGraph;
-    use super::super::test_utils::ConstRate;
+    use routing::capacity_graph::CapacityRoute;
+    use routing::simple_capacity_graph::SimpleCapacityGraph;
+    use routing::test_utils::ConstRate;
 
     use futures::executor::{block_on, ThreadPool};
 
@@ -270,16 +483,23 @@ mod tests {
         let currency1 = 1u8;
 
         let graph_service_spawner = ThreadPool::new().unwrap();
-        let mut graph_client = create_graph_service::<
-            u8,
-            u32,
-            u128,
-            ConstRate,
-            SimpleCapacityGraph<u32, ConstRate>,
-            _,
-            _,
-        >(graph_service_spawner, spawner)
-        .unwrap();
+        let capacity_decay = CapacityDecay {
+            half_life: 0x100,
+            horizon: 0x400,
+        };
+        let (_tick_sender, timer_stream) = mpsc::channel::<()>(0);
+        let mut graph_client =
+            create_graph_service::<
+                u8,
+                u32,
+                u128,
+                ConstRate,
+                SimpleCapacityGraph<u32, ConstRate>,
+                _,
+                _,
+                _,
+            >(capacity_decay, timer_stream, graph_service_spawner, spawner)
+            .unwrap();
 
         graph_client
             .update_edge(currency1, 2u32, 5u32, CapacityEdge::new(5, ConstRate(1u32)))
@@ -292,7 +512,7 @@ mod tests {
 
         assert_eq!(
             graph_client
-                .get_multi_routes(currency1, 2, 5, 29, None)
+                .get_multi_routes(currency1, 2, 5, 29, None, RouteConstraints::default())
                 .await
                 .unwrap(),
             vec![CapacityMultiRoute {
@@ -305,7 +525,7 @@ mod tests {
         );
         assert_eq!(
             graph_client
-                .get_multi_routes(currency1, 2, 5, 30, None)
+                .get_multi_routes(currency1, 2, 5, 30, None, RouteConstraints::default())
                 .await
                 .unwrap(),
             vec![CapacityMultiRoute {
@@ -318,12 +538,41 @@ mod tests {
         );
         assert_eq!(
             graph_client
-                .get_multi_routes(currency1, 2, 5, 31, None)
+                .get_multi_routes(currency1, 2, 5, 31, None, RouteConstraints::default())
                 .await
                 .unwrap(),
             vec![]
         );
 
+        // A wider route through 3:
+        graph_client
+            .update_edge(currency1, 2, 3, CapacityEdge::new(50, ConstRate(1)))
+            .await
+            .unwrap();
+        graph_client
+            .update_edge(currency1, 3, 2, CapacityEdge::new(50, ConstRate(1)))
+            .await
+            .unwrap();
+        graph_client
+            .update_edge(currency1, 3, 5, CapacityEdge::new(50, ConstRate(1)))
+            .await
+            .unwrap();
+        graph_client
+            .update_edge(currency1, 5, 3, CapacityEdge::new(50, ConstRate(1)))
+            .await
+            .unwrap();
+
+        let multi_routes = graph_client
+            .get_ranked_routes(currency1, 2, 5, 10, None, 2, RouteConstraints::default())
+            .await
+            .unwrap();
+        let routes = multi_routes
+            .into_iter()
+            .map(|multi_route| multi_route.routes[0].route.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(routes, vec![vec![2, 3, 5], vec![2, 5]]);
+        assert_eq!(graph_client.get_size().await.unwrap(), (3, 6));
+
         graph_client.tick(2).await.unwrap();
 
         assert_eq!(
@@ -332,6 +581,18 @@ mod tests {
         );
         graph_client.remove_node(2).await.unwrap();
         graph_client.remove_node(5).await.unwrap();
+        assert_eq!(graph_client.get_size().await.unwrap(), (1, 2));
+
+        let mut edges = graph_client.get_edges(None).await.unwrap();
+        edges.sort_by_key(|(_g, a, b, _capacity_edge)| (*a, *b));
+        assert_eq!(
+            edges,
+            vec![
+                (currency1, 3, 2, CapacityEdge::new(50, ConstRate(1))),
+                (currency1, 3, 5, CapacityEdge::new(50, ConstRate(1))),
+            ]
+        );
+        assert_eq!(graph_client.get_edges(Some(2)).await.unwrap().len(), 1);
     }
 
     #[test]
@@ -340,6 +601,43 @@ mod tests {
 
         block_on(task_create_graph_service_basic(thread_pool.clone()));
     }
+
+    #[test]
+    fn test_process_epoch() {
+        let currency1 = 1u8;
+        let mut capacity_graphs = HashMap::<u8, SimpleCapacityGraph<u32, ConstRate>>::new();
+        let mut edges_decay = EdgesDecay::new(CapacityDecay {
+            half_life: 2,
+            horizon: 3,
+        });
+
+        let (sender, _receiver) = oneshot::channel();
+        process_request(
+            &mut capacity_graphs,
+            &mut edges_decay,
+            GraphRequest::UpdateEdge(currency1, 2, 5, CapacityEdge::new(40, ConstRate(1)), sender),
+        );
+        let (sender, _receiver) = oneshot::channel();
+        process_request(
+            &mut capacity_graphs,
+            &mut edges_decay,
+            GraphRequest::UpdateEdge(currency1, 5, 2, CapacityEdge::new(60, ConstRate(1)), sender),
+        );
+
+        let capacity_graph = &capacity_graphs[&currency1];
+        assert_eq!(capacity_graph.get_send_capacity(&2, &5), 60);
+
+        process_epoch(&mut capacity_graphs, &mut edges_decay);
+        process_epoch(&mut capacity_graphs, &mut edges_decay);
+        // The reported capacities have decayed to half:
+        let capacity_graph = &capacity_graphs[&currency1];
+        assert_eq!(capacity_graph.get_send_capacity(&2, &5), 30);
+        assert_eq!(capacity_graph.get_send_capacity(&5, &2), 20);
+
+        process_epoch(&mut capacity_graphs, &mut edges_decay);
+        // The edges are removed after the horizon:
+        assert_eq!(capacity_graphs[&currency1].size(), (0, 0));
+    }
 }
 
 // TODO: Add a test for multiple currencies at the same time (Different values for the G type)
@@ -1,8 +1,2 @@
-mod bfs;
-pub mod capacity_graph;
+pub mod capacity_decay;
 pub mod graph_service;
-pub mod simple_capacity_graph;
-mod utils;
-
-#[cfg(test)]
-mod test_utils;
@@ -14,10 +14,16 @@ extern crate log;
 #[macro_use]
 extern crate common;
 
+mod admin_state;
+mod anti_entropy;
 mod backoff_connector;
+mod friend_inbox;
 mod graph;
+mod relay_directory;
 mod server;
 mod server_loop;
 mod verifier;
 
+pub use admin_state::{AdminMutation, AdminState};
+pub use graph::capacity_decay::CapacityDecay;
 pub use server::{index_server, IndexServerError};
@@ -0,0 +1,107 @@
+use proto::app_server::messages::RelayAddress;
+use proto::crypto::PublicKey;
+use proto::net::messages::NetAddress;
+
+/// Public relays that are currently connected to this server.
+///
+/// Nodes connect to index servers that are close to them, so the relays registered with the same
+/// index server are likely to be close to the node too.
+/// At most `max_relays` relays are kept. Relays are listed in the order they were registered, so
+/// that long lived relays are preferred.
+pub struct RelayDirectory {
+    max_relays: usize,
+    relays: Vec<RelayAddress>,
+}
+
+impl RelayDirectory {
+    pub fn new(max_relays: usize) -> Self {
+        RelayDirectory {
+            max_relays,
+            relays: Vec::new(),
+        }
+    }
+
+    /// Register a relay. A relay that is already registered may change its address.
+    /// Returns false if the directory is full.
+    pub fn announce(&mut self, public_key: PublicKey, address: NetAddress) -> bool {
+        if let Some(relay) = self
+            .relays
+            .iter_mut()
+            .find(|relay| relay.public_key == public_key)
+        {
+            relay.address = address;
+            return true;
+        }
+
+        if self.relays.len() >= self.max_relays {
+            return false;
+        }
+        self.relays.push(RelayAddress {
+            public_key,
+            address,
+        });
+        true
+    }
+
+    /// Unregister a relay (For example, because it disconnected)
+    pub fn remove(&mut self, public_key: &PublicKey) {
+        self.relays.retain(|relay| &relay.public_key != public_key);
+    }
+
+    /// List at most `max_relays` relays, not including the relay `exclude`.
+    pub fn list(&self, max_relays: usize, exclude: &PublicKey) -> Vec<RelayAddress> {
+        self.relays
+            .iter()
+            .filter(|relay| &relay.public_key != exclude)
+            .take(max_relays)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    fn pk(i: u8) -> PublicKey {
+        PublicKey::from(&[i; PublicKey::len()])
+    }
+
+    fn address(s: &str) -> NetAddress {
+        NetAddress::try_from(s.to_owned()).unwrap()
+    }
+
+    fn public_keys(relays: &[RelayAddress]) -> Vec<PublicKey> {
+        relays
+            .iter()
+            .map(|relay| relay.public_key.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_relay_directory_basic() {
+        let mut relay_directory = RelayDirectory::new(2);
+        assert!(relay_directory.announce(pk(1), address("relay1:1337")));
+        assert!(relay_directory.announce(pk(2), address("relay2:1337")));
+        // The directory is full:
+        assert!(!relay_directory.announce(pk(3), address("relay3:1337")));
+        // A registered relay may change its address:
+        assert!(relay_directory.announce(pk(1), address("relay1:1338")));
+
+        let relays = relay_directory.list(8, &pk(9));
+        assert_eq!(public_keys(&relays), vec![pk(1), pk(2)]);
+        assert_eq!(relays[0].address, address("relay1:1338"));
+
+        assert_eq!(public_keys(&relay_directory.list(1, &pk(9))), vec![pk(1)]);
+        assert_eq!(public_keys(&relay_directory.list(8, &pk(1))), vec![pk(2)]);
+
+        relay_directory.remove(&pk(1));
+        assert!(relay_directory.announce(pk(3), address("relay3:1337")));
+        assert_eq!(
+            public_keys(&relay_directory.list(8, &pk(9))),
+            vec![pk(2), pk(3)]
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::marker::Unpin;
 
@@ -7,18 +7,26 @@ use futures::Stream;
 
 use common::conn::FutTransform;
 
+use database::DatabaseClient;
+
 use proto::crypto::PublicKey;
+use proto::index_server::messages::IndexServerDirectory;
 
 use timer::TimerClient;
 
 use crypto::identity::compare_public_key;
 use crypto::rand::CryptoRandom;
 
-use crate::server_loop::{server_loop, ClientConn, ServerConn, ServerLoopError};
+use routing::simple_capacity_graph::SimpleCapacityGraph;
+
+use crate::server_loop::{
+    server_loop, AdminConn, ClientConn, QueryConn, ServerConn, ServerLoopError,
+};
 
+use crate::admin_state::{AdminMutation, AdminState};
 use crate::backoff_connector::BackoffConnector;
+use crate::graph::capacity_decay::CapacityDecay;
 use crate::graph::graph_service::create_graph_service;
-use crate::graph::simple_capacity_graph::SimpleCapacityGraph;
 use crate::verifier::simple_verifier::SimpleVerifier;
 
 #[derive(Debug)]
@@ -30,15 +38,31 @@ pub enum IndexServerError {
 
 /// Run an index server
 /// Will keep running until an error occurs.
-pub async fn index_server<A, IS, IC, SC, R, GS, S>(
+/// `pow_difficulty` is the proof of work difficulty required from clients that send mutations at
+/// a low rate. Clients that send mutations at a higher rate have to meet a higher difficulty.
+/// `capacity_decay` determines how capacities that were not updated recently decay over time.
+/// Admin connections are accepted only from `admin_public_keys`. Changes applied by admins are
+/// kept in `admin_state`, and saved using `admin_db_client`.
+/// `opt_directory` is a signed list of index servers, served to clients that request it.
+/// Query connections may request routes without registering as clients, subject to rate
+/// limiting.
+pub async fn index_server<A, IS, IC, IA, IQ, SC, R, GS, S>(
     local_public_key: PublicKey,
     trusted_servers: HashMap<PublicKey, A>,
+    admin_public_keys: HashSet<PublicKey>,
+    admin_state: AdminState,
+    admin_db_client: DatabaseClient<AdminMutation>,
+    opt_directory: Option<IndexServerDirectory>,
     incoming_server_connections: IS,
     incoming_client_connections: IC,
+    incoming_admin_connections: IA,
+    incoming_query_connections: IQ,
     server_connector: SC,
     mut timer_client: TimerClient,
     ticks_to_live: usize,
     backoff_ticks: usize,
+    pow_difficulty: u8,
+    capacity_decay: CapacityDecay,
     rng: R,
     graph_service_spawner: GS,
     spawner: S,
@@ -47,14 +71,24 @@ where
     A: Debug + Send + Sync + Clone + 'static,
     IS: Stream<Item = (PublicKey, ServerConn)> + Unpin + Send,
     IC: Stream<Item = (PublicKey, ClientConn)> + Unpin + Send,
+    IA: Stream<Item = (PublicKey, AdminConn)> + Unpin + Send,
+    IQ: Stream<Item = QueryConn> + Unpin + Send,
     SC: FutTransform<Input = (PublicKey, A), Output = Option<ServerConn>> + Clone + Send + 'static,
     R: CryptoRandom,
     S: Spawn + Clone + Send,
     GS: Spawn + Send + 'static,
 {
-    let verifier = SimpleVerifier::new(ticks_to_live, rng);
+    let verifier = SimpleVerifier::new(ticks_to_live, pow_difficulty, rng);
+
+    // Every time tick is one epoch for the graph service:
+    let graph_timer_stream = timer_client
+        .request_timer_stream()
+        .await
+        .map_err(|_| IndexServerError::RequestTimerStreamError)?;
 
-    let graph_client = create_graph_service::<_, _, _, _, SimpleCapacityGraph<_, _>, _, _>(
+    let graph_client = create_graph_service::<_, _, _, _, SimpleCapacityGraph<_, _>, _, _, _>(
+        capacity_decay,
+        graph_timer_stream,
         graph_service_spawner,
         spawner.clone(),
     )
@@ -70,8 +104,14 @@ where
     server_loop(
         local_public_key,
         trusted_servers,
+        admin_public_keys,
+        admin_state,
+        admin_db_client,
+        opt_directory,
         incoming_server_connections,
         incoming_client_connections,
+        incoming_admin_connections,
+        incoming_query_connections,
         backoff_connector,
         graph_client,
         compare_public_key,
@@ -1,5 +1,5 @@
-use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::cmp::{self, Ordering};
+use std::collections::{HashMap, HashSet};
 use std::marker::Unpin;
 
 use futures::channel::{mpsc, oneshot};
@@ -7,48 +7,83 @@ use futures::task::{Spawn, SpawnExt};
 use futures::{future, select, stream, FutureExt, SinkExt, Stream, StreamExt, TryFutureExt};
 
 use common::conn::{sink_to_sender, BoxStream, ConnPair, FutTransform};
+use common::mutable_state::MutableState;
 use common::select_streams::select_streams;
 
+use database::DatabaseClient;
+
+use proto::consts::INDEX_SESSION_RESUME_TICKS;
 use proto::crypto::{PublicKey, Uid};
 
 use proto::index_server::messages::{
-    ForwardMutationsUpdate, IndexClientToServer, IndexMutation, IndexServerToClient,
-    IndexServerToServer, MultiRoute, MutationsUpdate, ResponseRoutes, RouteCapacityRate,
-    TimeProofLink,
+    AdminChange, AdminEdge, AdminJournalEntry, ForwardMutationsUpdate, FriendProposal,
+    IndexAdminToServer, IndexClientToServer, IndexMutation, IndexServerDirectory,
+    IndexServerToAdmin, IndexServerToClient, IndexServerToServer, MultiRoute, MutationsUpdate,
+    NodeSessionCounter, RequestDirectory, RequestRelays, RequestRoutes, RequestServerStatus,
+    ResponseDirectory, ResponseEdges, ResponseJournal, ResponseRelays, ResponseRoutes,
+    ResponseServerStatus, ResumeSession, RouteCapacityRate, RouteRanking, TimeProofLink,
 };
+use proto::limits::MAX_ADMIN_EDGES;
+use proto::net::messages::NetAddress;
 
 use proto::funder::messages::{Currency, FriendsRoute, Rate};
 
-use signature::verify::verify_mutations_update;
+use signature::verify::{
+    mutations_update_pow_bits, verify_friend_proposal, verify_mutations_update,
+};
+
+use routing::capacity_graph::{CapacityEdge, RouteConstraints as GraphRouteConstraints};
 
-use crate::graph::capacity_graph::{CapacityEdge, LinearRate};
+use crate::admin_state::{AdminMutation, AdminState};
+use crate::anti_entropy::UpdatesLog;
+use crate::friend_inbox::FriendInbox;
 use crate::graph::graph_service::{GraphClient, GraphClientError};
+use crate::relay_directory::RelayDirectory;
 
 use crate::verifier::Verifier;
 
-pub type ServerConn = ConnPair<IndexServerToServer, IndexServerToServer>;
-pub type ClientConn = ConnPair<IndexServerToClient, IndexClientToServer>;
+/// Maximum amount of recent mutations updates we remember for every node, for the purpose of
+/// reconciliation with other servers.
+const MAX_NODE_UPDATES: usize = 16;
 
-impl LinearRate for Rate {
-    /// Type used to count credits
-    type K = u128;
+/// Amount of timer ticks between two consecutive digests sent to the other servers.
+const TICKS_TO_DIGEST: usize = 8;
 
-    fn zero() -> Self {
-        Rate { mul: 0, add: 0 }
-    }
+/// Maximum amount of routes returned for a request with capacity ranking.
+/// Ranking many routes is expensive, so larger requests are truncated.
+const MAX_RANKED_ROUTES: usize = 16;
 
-    fn calc_fee(&self, k: Self::K) -> Option<Self::K> {
-        self.calc_fee(k)
-    }
+/// Maximum amount of friend proposals we keep for a node that is not connected.
+const MAX_NODE_PROPOSALS: usize = 16;
 
-    fn checked_add(&self, other: &Self) -> Option<Self> {
-        // Entry wise addition:
-        Some(Rate {
-            mul: self.mul.checked_add(other.mul)?,
-            add: self.add.checked_add(other.add)?,
-        })
-    }
-}
+/// Amount of timer ticks we keep a friend proposal for a node that is not connected.
+const FRIEND_PROPOSAL_TICKS: usize = 0x400;
+
+/// Maximum amount of public relays that may be registered with the server.
+const MAX_RELAYS: usize = 0x100;
+
+/// Maximum amount of relays returned for a single request.
+const MAX_RESPONSE_RELAYS: usize = 0x20;
+
+/// Maximum amount of query connections served at the same time.
+const MAX_QUERY_CONNS: usize = 0x40;
+
+/// Amount of route requests a query connection may send every timer tick.
+const QUERY_TOKENS_PER_TICK: usize = 1;
+
+/// Maximum amount of route requests a query connection may accumulate while idle.
+const MAX_QUERY_TOKENS: usize = 4;
+
+/// Maximum amount of route requests from query connections being processed at the same time.
+/// Requests from clients are not limited, so queries can not starve registered clients.
+const MAX_PENDING_QUERIES: usize = 4;
+
+pub type ServerConn = ConnPair<IndexServerToServer, IndexServerToServer>;
+pub type ClientConn = ConnPair<IndexServerToClient, IndexClientToServer>;
+pub type AdminConn = ConnPair<IndexServerToAdmin, IndexAdminToServer>;
+/// A read only connection, used to request routes without registering as a client.
+/// Only route requests are served. Other messages are ignored.
+pub type QueryConn = ConnPair<IndexServerToClient, IndexClientToServer>;
 
 #[derive(Debug)]
 pub enum ServerLoopError {
@@ -56,6 +91,10 @@ pub enum ServerLoopError {
     GraphClientError,
     ClientEventSenderError,
     ClientSenderError,
+    AdminEventSenderError,
+    AdminSenderError,
+    AdminDatabaseError,
+    QueryEventSenderError,
     RemoteSendError,
 }
 
@@ -104,6 +143,14 @@ enum RemoteServerState {
     Listening,
 }
 
+/// A connected query connection
+#[derive(Debug)]
+struct QueryConnected {
+    connected: Connected<IndexServerToClient>,
+    /// Amount of route requests this connection may still send:
+    tokens: usize,
+}
+
 #[derive(Debug)]
 struct RemoteServer<A> {
     address: A,
@@ -118,6 +165,31 @@ struct IndexServer<A, S, SC, V, CMP> {
     compare_public_key: CMP,
     remote_servers: HashMap<PublicKey, RemoteServer<A>>,
     clients: HashMap<PublicKey, Connected<IndexServerToClient>>,
+    /// Recent verified mutations updates, used for anti-entropy reconciliation:
+    updates_log: UpdatesLog,
+    /// Friend proposals waiting for their destination node to connect:
+    friend_inbox: FriendInbox,
+    /// Public relays that are connected to this server:
+    relay_directory: RelayDirectory,
+    /// Recently disconnected clients, and the amount of ticks left for them to resume their
+    /// session:
+    resumable_clients: HashMap<PublicKey, usize>,
+    /// Public keys allowed to connect as admins:
+    admin_public_keys: HashSet<PublicKey>,
+    /// A signed list of index servers, served to clients that request it:
+    opt_directory: Option<IndexServerDirectory>,
+    admins: HashMap<PublicKey, Connected<IndexServerToAdmin>>,
+    /// Journal of manual changes applied by admins, and the nodes and edges they removed:
+    admin_state: AdminState,
+    admin_db_client: DatabaseClient<AdminMutation>,
+    /// Connected query connections, by a locally assigned id:
+    queries: HashMap<u64, QueryConnected>,
+    next_query_id: u64,
+    /// Amount of route requests from query connections currently being processed:
+    pending_queries: usize,
+    /// Amount of ticks since a time hash was last received from a peer server:
+    time_hash_age: u64,
+    ticks_to_digest: usize,
     event_sender: mpsc::Sender<IndexServerEvent>,
     spawner: S,
 }
@@ -136,6 +208,20 @@ enum IndexServerEvent {
     ClientConnection((PublicKey, ClientConn)),
     ClientClosed(PublicKey),
     ClientMutationsUpdate(MutationsUpdate),
+    ClientFriendProposal((PublicKey, FriendProposal)),
+    ClientAnnounceRelay((PublicKey, NetAddress)),
+    ClientRequestRelays((PublicKey, RequestRelays)),
+    ClientRequestServerStatus((PublicKey, RequestServerStatus)),
+    ClientResumeSession((PublicKey, ResumeSession)),
+    ClientRequestDirectory((PublicKey, RequestDirectory)),
+    AdminConnection((PublicKey, AdminConn)),
+    AdminClosed(PublicKey),
+    AdminApplyChange((PublicKey, AdminChange)),
+    AdminRequestJournal((PublicKey, Uid)),
+    QueryConnection(QueryConn),
+    QueryClosed(u64),
+    QueryRequestRoutes((u64, RequestRoutes)),
+    QueryResponseRoutes((u64, Option<ResponseRoutes>)),
     TimerTick,
     ClientListenerClosed,
     ServerListenerClosed,
@@ -166,6 +252,10 @@ where
     pub fn new(
         local_public_key: PublicKey,
         trusted_servers: HashMap<PublicKey, A>,
+        admin_public_keys: HashSet<PublicKey>,
+        admin_state: AdminState,
+        admin_db_client: DatabaseClient<AdminMutation>,
+        opt_directory: Option<IndexServerDirectory>,
         server_connector: SC,
         graph_client: GraphClient<Currency, PublicKey, u128, Rate>,
         compare_public_key: CMP,
@@ -181,6 +271,20 @@ where
             compare_public_key,
             remote_servers: HashMap::new(),
             clients: HashMap::new(),
+            updates_log: UpdatesLog::new(MAX_NODE_UPDATES),
+            friend_inbox: FriendInbox::new(MAX_NODE_PROPOSALS, FRIEND_PROPOSAL_TICKS),
+            relay_directory: RelayDirectory::new(MAX_RELAYS),
+            resumable_clients: HashMap::new(),
+            admin_public_keys,
+            opt_directory,
+            admins: HashMap::new(),
+            admin_state,
+            admin_db_client,
+            queries: HashMap::new(),
+            next_query_id: 0,
+            pending_queries: 0,
+            time_hash_age: 0,
+            ticks_to_digest: TICKS_TO_DIGEST,
             event_sender,
             spawner,
         };
@@ -289,6 +393,7 @@ where
             &mutations_update.node_public_key,
             &mutations_update.session_id,
             mutations_update.counter,
+            mutations_update_pow_bits(mutations_update),
         ) {
             Some(hashes) => hashes,
             None => {
@@ -301,6 +406,19 @@ where
 
         // The message is valid and fresh.
 
+        // Nodes removed by an admin may not add themselves back to the graph:
+        if self
+            .admin_state
+            .denied_nodes
+            .contains(&mutations_update.node_public_key)
+        {
+            warn!(
+                "{}: handle_forward_mutations_update: Ignoring mutations from denied node {:?}",
+                self.local_public_key[0], mutations_update.node_public_key
+            );
+            return Ok(());
+        }
+
         // Expire old edges for `node_public_key`:
         // Note: This tick happens every time a message is received from this `node_public_key`,
         // and not every constant amount of time.
@@ -320,6 +438,13 @@ where
         for index_mutation in &mutations_update.index_mutations {
             match index_mutation {
                 IndexMutation::UpdateFriendCurrency(update_friend_currency) => {
+                    if self.admin_state.is_edge_denied(
+                        &update_friend_currency.currency,
+                        &mutations_update.node_public_key,
+                        &update_friend_currency.public_key,
+                    ) {
+                        continue;
+                    }
                     info!(
                         "pk_source: {}, pk_friend: {}, currency: {}, recv: {}, rate: {:?}",
                         update_friend_currency.public_key[0],
@@ -355,6 +480,8 @@ where
             }
         }
 
+        self.updates_log.insert(forward_mutations_update.clone());
+
         // Try to forward to all connected servers:
         for (server_public_key, connected_server) in self.iter_connected_servers() {
             if Some(server_public_key) == opt_server_public_key.as_ref() {
@@ -376,40 +503,317 @@ where
         match server_msg {
             IndexServerToServer::TimeHash(time_hash) => {
                 let _ = self.verifier.neighbor_tick(public_key, time_hash);
+                self.time_hash_age = 0;
             }
             IndexServerToServer::ForwardMutationsUpdate(forward_mutations_update) => {
                 self.handle_forward_mutations_update(Some(public_key), forward_mutations_update)
                     .await?;
             }
+            IndexServerToServer::MutationsDigest(remote_digest) => {
+                self.handle_mutations_digest(public_key, remote_digest);
+            }
+            IndexServerToServer::ForwardFriendProposal(friend_proposal) => {
+                self.handle_friend_proposal(Some(public_key), friend_proposal);
+            }
+        };
+        Ok(())
+    }
+
+    /// Deliver a friend proposal to its destination node.
+    /// If the destination node is not connected, we keep the proposal until it connects.
+    /// Proposals received directly from clients are also forwarded to all connected servers.
+    /// Proposals forwarded by other servers are not forwarded again.
+    fn handle_friend_proposal(
+        &mut self,
+        opt_server_public_key: Option<PublicKey>,
+        friend_proposal: FriendProposal,
+    ) {
+        if !verify_friend_proposal(&friend_proposal) {
+            warn!(
+                "{}: handle_friend_proposal: Failed verifying signature from server {:?}",
+                self.local_public_key[0], opt_server_public_key
+            );
+            return;
+        }
+
+        if let Some(connected_client) = self.clients.get_mut(&friend_proposal.dest_public_key) {
+            if connected_client
+                .try_send(IndexServerToClient::FriendProposal(friend_proposal.clone()))
+                .is_ok()
+            {
+                return;
+            }
+        }
+
+        if !self.friend_inbox.insert(friend_proposal.clone()) {
+            warn!(
+                "{}: handle_friend_proposal: Inbox of node {:?} is full",
+                self.local_public_key[0], friend_proposal.dest_public_key
+            );
+        }
+
+        if opt_server_public_key.is_none() {
+            for (_server_public_key, connected_server) in self.iter_connected_servers() {
+                let _ = connected_server.try_send(IndexServerToServer::ForwardFriendProposal(
+                    friend_proposal.clone(),
+                ));
+            }
+        }
+    }
+
+    /// Anti-entropy: Send a remote server the updates it is missing.
+    /// If the remote server knows updates we are missing, we send it our own digest, so that it
+    /// will send us those updates.
+    fn handle_mutations_digest(
+        &mut self,
+        public_key: PublicKey,
+        remote_digest: Vec<NodeSessionCounter>,
+    ) {
+        let missing_updates = self.updates_log.missing_updates(&remote_digest);
+        let opt_local_digest = if self.updates_log.is_behind(&remote_digest) {
+            Some(self.updates_log.digest())
+        } else {
+            None
+        };
+
+        let connected_server = match self.remote_servers.get_mut(&public_key) {
+            Some(RemoteServer {
+                state: RemoteServerState::Connected(connected_server),
+                ..
+            }) => connected_server,
+            _ => {
+                warn!(
+                    "handle_mutations_digest(): Server {:?} is not connected",
+                    public_key
+                );
+                return;
+            }
+        };
+
+        for forward_mutations_update in missing_updates {
+            let _ = connected_server.try_send(IndexServerToServer::ForwardMutationsUpdate(
+                forward_mutations_update,
+            ));
+        }
+        if let Some(local_digest) = opt_local_digest {
+            let _ = connected_server.try_send(IndexServerToServer::MutationsDigest(local_digest));
+        }
+    }
+
+    pub async fn handle_request_server_status(
+        &mut self,
+        public_key: PublicKey,
+        request_server_status: RequestServerStatus,
+    ) -> Result<(), ServerLoopError> {
+        let (num_nodes, num_edges) = self.graph_client.get_size().await?;
+        let response_server_status = ResponseServerStatus {
+            request_id: request_server_status.request_id,
+            num_nodes: num_nodes as u64,
+            num_edges: num_edges as u64,
+            num_clients: self.clients.len() as u32,
+            num_servers: self.iter_connected_servers().count() as u32,
+            time_hash_age: self.time_hash_age,
         };
+        if let Some(connected_client) = self.clients.get_mut(&public_key) {
+            let _ = connected_client.try_send(IndexServerToClient::ResponseServerStatus(
+                response_server_status,
+            ));
+        }
+        Ok(())
+    }
+
+    /// A client asks to resume its previous session after a brief disconnect.
+    /// The session is resumed only if we know all the mutations the client has sent in that
+    /// session. Otherwise the client has to resend its full state.
+    pub fn handle_resume_session(&mut self, public_key: PublicKey, resume_session: ResumeSession) {
+        let is_resumable = self.resumable_clients.remove(&public_key).is_some();
+        let is_synced = self.updates_log.last_session_counter(&public_key)
+            == Some((&resume_session.session_id, resume_session.counter));
+        let session_resumed = is_resumable && is_synced;
+        if let Some(connected_client) = self.clients.get_mut(&public_key) {
+            let _ = connected_client.try_send(IndexServerToClient::SessionResumed(session_resumed));
+        }
+    }
+
+    /// Apply a manual change to the capacity graph, and record it in the admin journal.
+    /// Removed nodes and edges are denied, so that they will not show up again if their nodes
+    /// send new mutations. Note that the change is local to this server.
+    pub async fn handle_admin_change(
+        &mut self,
+        admin_public_key: PublicKey,
+        change: AdminChange,
+    ) -> Result<(), ServerLoopError> {
+        info!("Admin {:?} applies change: {:?}", admin_public_key, change);
+        match &change {
+            AdminChange::RemoveNode(node_public_key) => {
+                self.updates_log.remove_node(node_public_key);
+                self.graph_client
+                    .remove_node(node_public_key.clone())
+                    .await?;
+            }
+            AdminChange::RemoveEdge(remove_edge) => {
+                let _ = self
+                    .graph_client
+                    .remove_edge(
+                        remove_edge.currency.clone(),
+                        remove_edge.from_public_key.clone(),
+                        remove_edge.to_public_key.clone(),
+                    )
+                    .await?;
+            }
+        }
+
+        // The change is saved before it is applied to the in memory state:
+        let admin_mutation = AdminMutation::ApplyChange(AdminJournalEntry {
+            admin_public_key,
+            change,
+        });
+        self.admin_db_client
+            .mutate(vec![admin_mutation.clone()])
+            .await
+            .map_err(|_| ServerLoopError::AdminDatabaseError)?;
+        let _ = self.admin_state.mutate(&admin_mutation);
         Ok(())
     }
 
+    pub fn handle_admin_request_journal(&mut self, admin_public_key: PublicKey, request_id: Uid) {
+        let response_journal = ResponseJournal {
+            request_id,
+            entries: self.admin_state.journal.iter().cloned().collect(),
+        };
+        if let Some(connected_admin) = self.admins.get_mut(&admin_public_key) {
+            let _ = connected_admin.try_send(IndexServerToAdmin::ResponseJournal(response_journal));
+        }
+    }
+
     pub async fn handle_timer_tick(&mut self) -> Result<(), ServerLoopError> {
         let (time_hash, removed_nodes) = self.verifier.tick();
+        self.time_hash_age = self.time_hash_age.saturating_add(1);
 
         // Try to send the time tick to all servers. Sending to some of them might fail:
         for (_server_public_key, connected_server) in self.iter_connected_servers() {
             let _ = connected_server.try_send(IndexServerToServer::TimeHash(time_hash.clone()));
         }
 
-        // Try to send time tick to all connected clients:
-        for connected_client in self.clients.values_mut() {
+        // Try to send time tick to all connected clients.
+        // The proof of work difficulty is sent first, so that it is known to the client before
+        // the client uses the new time hash:
+        for (client_public_key, connected_client) in self.clients.iter_mut() {
+            let pow_difficulty = self.verifier.pow_difficulty(client_public_key);
+            let _ = connected_client.try_send(IndexServerToClient::PowDifficulty(pow_difficulty));
             let _ = connected_client.try_send(IndexServerToClient::TimeHash(time_hash.clone()));
         }
 
+        self.friend_inbox.tick();
+
+        for query_connected in self.queries.values_mut() {
+            query_connected.tokens = cmp::min(
+                query_connected.tokens.saturating_add(QUERY_TOKENS_PER_TICK),
+                MAX_QUERY_TOKENS,
+            );
+        }
+
+        self.resumable_clients.retain(|_public_key, ticks_left| {
+            *ticks_left = ticks_left.saturating_sub(1);
+            *ticks_left > 0
+        });
+
         // Update the graph service about removed nodes:
         for node_public_key in removed_nodes {
+            self.updates_log.remove_node(&node_public_key);
             self.graph_client.remove_node(node_public_key).await?;
         }
 
+        // Periodically reconcile with the other servers:
+        self.ticks_to_digest = self.ticks_to_digest.saturating_sub(1);
+        if self.ticks_to_digest == 0 {
+            self.ticks_to_digest = TICKS_TO_DIGEST;
+            let digest = self.updates_log.digest();
+            // There is nothing to reconcile if we don't know any updates. Servers that know
+            // updates will send us their digests.
+            if !digest.is_empty() {
+                for (_server_public_key, connected_server) in self.iter_connected_servers() {
+                    let _ = connected_server
+                        .try_send(IndexServerToServer::MutationsDigest(digest.clone()));
+                }
+            }
+        }
+
         Ok(())
     }
 }
 
+/// Find routes for a route request, using the capacity graph
+async fn calc_response_routes(
+    graph_client: &mut GraphClient<Currency, PublicKey, u128, Rate>,
+    request_routes: RequestRoutes,
+) -> Result<ResponseRoutes, ServerLoopError> {
+    let opt_exclude_edge = request_routes
+        .opt_exclude
+        .map(|edge| (edge.from_public_key.clone(), edge.to_public_key));
+
+    let constraints = request_routes.constraints;
+    let graph_constraints = GraphRouteConstraints {
+        blacklist: constraints.blacklist,
+        opt_max_route_len: constraints
+            .opt_max_route_len
+            .map(|max_route_len| max_route_len as usize),
+        capacity_margin: constraints.capacity_margin,
+    };
+
+    let graph_multi_routes = match request_routes.ranking {
+        RouteRanking::ShortestPath => {
+            graph_client
+                .get_multi_routes(
+                    request_routes.currency.clone(),
+                    request_routes.source.clone(),
+                    request_routes.destination.clone(),
+                    request_routes.capacity,
+                    opt_exclude_edge,
+                    graph_constraints,
+                )
+                .await?
+        }
+        RouteRanking::Capacity(max_routes) => {
+            graph_client
+                .get_ranked_routes(
+                    request_routes.currency.clone(),
+                    request_routes.source.clone(),
+                    request_routes.destination.clone(),
+                    request_routes.capacity,
+                    opt_exclude_edge,
+                    cmp::min(max_routes as usize, MAX_RANKED_ROUTES),
+                    graph_constraints,
+                )
+                .await?
+        }
+    };
+    let multi_routes = graph_multi_routes
+        .into_iter()
+        .map(|graph_multi_route| MultiRoute {
+            routes: graph_multi_route
+                .routes
+                .into_iter()
+                .map(|graph_route| RouteCapacityRate {
+                    route: FriendsRoute {
+                        public_keys: graph_route.route,
+                    },
+                    capacity: graph_route.capacity,
+                    rate: graph_route.rate,
+                })
+                .collect(),
+        })
+        .collect::<Vec<_>>();
+
+    Ok(ResponseRoutes {
+        request_id: request_routes.request_id,
+        multi_routes,
+    })
+}
+
 async fn client_handler(
     mut graph_client: GraphClient<Currency, PublicKey, u128, Rate>,
-    _public_key: PublicKey, // TODO: unused?
+    public_key: PublicKey,
     client_conn: ClientConn,
     mut event_sender: mpsc::Sender<IndexServerEvent>,
 ) -> Result<(), ServerLoopError> {
@@ -425,56 +829,194 @@ async fn client_handler(
                     .map_err(|_| ServerLoopError::ClientEventSenderError)?;
             }
             IndexClientToServer::RequestRoutes(request_routes) => {
-                let opt_exclude_edge = request_routes
-                    .opt_exclude
-                    .map(|edge| (edge.from_public_key.clone(), edge.to_public_key));
-
-                let graph_multi_routes = graph_client
-                    .get_multi_routes(
-                        request_routes.currency.clone(),
-                        request_routes.source.clone(),
-                        request_routes.destination.clone(),
-                        request_routes.capacity,
-                        opt_exclude_edge,
-                    )
-                    .await?;
-                let multi_routes = graph_multi_routes
-                    .into_iter()
-                    .map(|graph_multi_route| MultiRoute {
-                        routes: graph_multi_route
-                            .routes
-                            .into_iter()
-                            .map(|graph_route| RouteCapacityRate {
-                                route: FriendsRoute {
-                                    public_keys: graph_route.route,
-                                },
-                                capacity: graph_route.capacity,
-                                rate: graph_route.rate,
-                            })
-                            .collect(),
-                    })
-                    .collect::<Vec<_>>();
-
-                let response_routes = ResponseRoutes {
-                    request_id: request_routes.request_id,
-                    multi_routes,
-                };
+                let response_routes =
+                    calc_response_routes(&mut graph_client, request_routes).await?;
                 let message = IndexServerToClient::ResponseRoutes(response_routes);
                 sender
                     .send(message)
                     .await
                     .map_err(|_| ServerLoopError::ClientSenderError)?;
             }
+            IndexClientToServer::SendFriendProposal(friend_proposal) => {
+                // Forward to main server future to process:
+                event_sender
+                    .send(IndexServerEvent::ClientFriendProposal((
+                        public_key.clone(),
+                        friend_proposal,
+                    )))
+                    .await
+                    .map_err(|_| ServerLoopError::ClientEventSenderError)?;
+            }
+            IndexClientToServer::AnnounceRelay(address) => {
+                // Forward to main server future to process:
+                event_sender
+                    .send(IndexServerEvent::ClientAnnounceRelay((
+                        public_key.clone(),
+                        address,
+                    )))
+                    .await
+                    .map_err(|_| ServerLoopError::ClientEventSenderError)?;
+            }
+            IndexClientToServer::RequestRelays(request_relays) => {
+                // Forward to main server future to process:
+                event_sender
+                    .send(IndexServerEvent::ClientRequestRelays((
+                        public_key.clone(),
+                        request_relays,
+                    )))
+                    .await
+                    .map_err(|_| ServerLoopError::ClientEventSenderError)?;
+            }
+            IndexClientToServer::RequestServerStatus(request_server_status) => {
+                // Forward to main server future to process:
+                event_sender
+                    .send(IndexServerEvent::ClientRequestServerStatus((
+                        public_key.clone(),
+                        request_server_status,
+                    )))
+                    .await
+                    .map_err(|_| ServerLoopError::ClientEventSenderError)?;
+            }
+            IndexClientToServer::ResumeSession(resume_session) => {
+                // Forward to main server future to process:
+                event_sender
+                    .send(IndexServerEvent::ClientResumeSession((
+                        public_key.clone(),
+                        resume_session,
+                    )))
+                    .await
+                    .map_err(|_| ServerLoopError::ClientEventSenderError)?;
+            }
+            IndexClientToServer::RequestDirectory(request_directory) => {
+                // Forward to main server future to process:
+                event_sender
+                    .send(IndexServerEvent::ClientRequestDirectory((
+                        public_key.clone(),
+                        request_directory,
+                    )))
+                    .await
+                    .map_err(|_| ServerLoopError::ClientEventSenderError)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn admin_handler(
+    mut graph_client: GraphClient<Currency, PublicKey, u128, Rate>,
+    public_key: PublicKey,
+    admin_conn: AdminConn,
+    mut event_sender: mpsc::Sender<IndexServerEvent>,
+) -> Result<(), ServerLoopError> {
+    let (mut sender, mut receiver) = admin_conn.split();
+
+    while let Some(admin_msg) = receiver.next().await {
+        match admin_msg {
+            IndexAdminToServer::RequestEdges(request_edges) => {
+                let admin_edges = graph_client
+                    .get_edges(request_edges.opt_public_key)
+                    .await?
+                    .into_iter()
+                    .map(
+                        |(currency, from_public_key, to_public_key, capacity_edge)| AdminEdge {
+                            currency,
+                            from_public_key,
+                            to_public_key,
+                            recv_capacity: capacity_edge.recv_capacity,
+                            rate: capacity_edge.rate,
+                        },
+                    )
+                    .collect::<Vec<_>>();
+
+                // Split a large response into multiple messages. We always send at least one
+                // message, even if there are no edges:
+                let num_messages = cmp::max(
+                    1,
+                    (admin_edges.len() + MAX_ADMIN_EDGES - 1) / MAX_ADMIN_EDGES,
+                );
+                let mut admin_edges = admin_edges.into_iter();
+                for i in 0..num_messages {
+                    let response_edges = ResponseEdges {
+                        request_id: request_edges.request_id.clone(),
+                        edges: admin_edges.by_ref().take(MAX_ADMIN_EDGES).collect(),
+                        is_last: i + 1 == num_messages,
+                    };
+                    sender
+                        .send(IndexServerToAdmin::ResponseEdges(response_edges))
+                        .await
+                        .map_err(|_| ServerLoopError::AdminSenderError)?;
+                }
+            }
+            IndexAdminToServer::ApplyChange(change) => {
+                // Forward to main server future to process:
+                event_sender
+                    .send(IndexServerEvent::AdminApplyChange((
+                        public_key.clone(),
+                        change,
+                    )))
+                    .await
+                    .map_err(|_| ServerLoopError::AdminEventSenderError)?;
+            }
+            IndexAdminToServer::RequestJournal(request_id) => {
+                // Forward to main server future to process:
+                event_sender
+                    .send(IndexServerEvent::AdminRequestJournal((
+                        public_key.clone(),
+                        request_id,
+                    )))
+                    .await
+                    .map_err(|_| ServerLoopError::AdminEventSenderError)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Forward route requests from a query connection to the main server future.
+/// Query connections are read only, so any other message is ignored.
+async fn query_handler(
+    query_id: u64,
+    mut receiver: BoxStream<'static, IndexClientToServer>,
+    mut event_sender: mpsc::Sender<IndexServerEvent>,
+) -> Result<(), ServerLoopError> {
+    while let Some(query_msg) = receiver.next().await {
+        match query_msg {
+            IndexClientToServer::RequestRoutes(request_routes) => {
+                event_sender
+                    .send(IndexServerEvent::QueryRequestRoutes((
+                        query_id,
+                        request_routes,
+                    )))
+                    .await
+                    .map_err(|_| ServerLoopError::QueryEventSenderError)?;
+            }
+            _ => warn!(
+                "query_handler(): Query connection {} sent a non query message",
+                query_id
+            ),
         }
     }
     Ok(())
 }
 
-pub async fn server_loop<A, IS, IC, SC, CMP, V, TS, S>(
+/// Run the main loop of an index server.
+/// Admin connections are accepted only from `admin_public_keys`. Admins may inspect the capacity
+/// graph and remove nodes or edges from it. Removed nodes and edges are kept in `admin_state`,
+/// and saved using `admin_db_client`.
+/// Query connections may only request routes. They are not registered as clients, and their
+/// requests are rate limited and served with a lower priority than requests from clients.
+/// `opt_directory` is a signed list of index servers, served to clients that request it.
+pub async fn server_loop<A, IS, IC, IA, IQ, SC, CMP, V, TS, S>(
     local_public_key: PublicKey,
     trusted_servers: HashMap<PublicKey, A>,
+    admin_public_keys: HashSet<PublicKey>,
+    admin_state: AdminState,
+    admin_db_client: DatabaseClient<AdminMutation>,
+    opt_directory: Option<IndexServerDirectory>,
     incoming_server_connections: IS,
     incoming_client_connections: IC,
+    incoming_admin_connections: IA,
+    incoming_query_connections: IQ,
     server_connector: SC,
     graph_client: GraphClient<Currency, PublicKey, u128, Rate>,
     compare_public_key: CMP,
@@ -487,6 +1029,8 @@ where
     A: Clone + Send + std::fmt::Debug + 'static,
     IS: Stream<Item = (PublicKey, ServerConn)> + Unpin + Send,
     IC: Stream<Item = (PublicKey, ClientConn)> + Unpin + Send,
+    IA: Stream<Item = (PublicKey, AdminConn)> + Unpin + Send,
+    IQ: Stream<Item = QueryConn> + Unpin + Send,
     SC: FutTransform<Input = (PublicKey, A), Output = Option<ServerConn>> + Clone + Send + 'static,
     V: Verifier<Node = PublicKey, Neighbor = PublicKey, SessionId = Uid>,
     CMP: Clone + Fn(&PublicKey, &PublicKey) -> Ordering + Sync,
@@ -503,6 +1047,10 @@ where
     let mut index_server = IndexServer::new(
         local_public_key,
         trusted_servers,
+        admin_public_keys,
+        admin_state,
+        admin_db_client,
+        opt_directory,
         server_connector,
         graph_client,
         compare_public_key,
@@ -532,12 +1080,22 @@ where
             IndexServerEvent::ClientListenerClosed,
         )));
 
+    // The admin interface is optional, so we keep running if the admin listener is closed:
+    let incoming_admin_connections =
+        incoming_admin_connections.map(IndexServerEvent::AdminConnection);
+
+    // The query interface is also optional:
+    let incoming_query_connections =
+        incoming_query_connections.map(IndexServerEvent::QueryConnection);
+
     let timer_stream = timer_stream.map(|_| IndexServerEvent::TimerTick);
 
     let mut events = select_streams![
         event_receiver,
         incoming_server_connections,
         incoming_client_connections,
+        incoming_admin_connections,
+        incoming_query_connections,
         timer_stream
     ];
 
@@ -569,7 +1127,13 @@ where
                 let (sender, receiver) = server_conn.split();
                 let sender = sink_to_sender(sender, &spawner);
 
-                remote_server.state = RemoteServerState::Connected(Connected::new(sender));
+                let mut server_connected = Connected::new(sender);
+                // Reconcile with the newly connected server:
+                let digest = index_server.updates_log.digest();
+                if !digest.is_empty() {
+                    let _ = server_connected.try_send(IndexServerToServer::MutationsDigest(digest));
+                }
+                remote_server.state = RemoteServerState::Connected(server_connected);
 
                 let c_public_key = public_key.clone();
                 let receiver = receiver
@@ -644,9 +1208,13 @@ where
                     .spawner
                     .spawn(client_handler_fut)
                     .map_err(|_| ServerLoopError::SpawnError)?;
-                index_server
-                    .clients
-                    .insert(public_key, Connected::new(c_sender));
+                let mut client_connected = Connected::new(c_sender);
+                // Deliver the friend proposals that were sent while the client was not connected:
+                for friend_proposal in index_server.friend_inbox.take(&public_key) {
+                    let _ = client_connected
+                        .try_send(IndexServerToClient::FriendProposal(friend_proposal));
+                }
+                index_server.clients.insert(public_key, client_connected);
             }
             IndexServerEvent::ClientMutationsUpdate(mutations_update) => {
                 let forward_mutations_update = ForwardMutationsUpdate {
@@ -658,89 +1226,962 @@ where
                     .handle_forward_mutations_update(None, forward_mutations_update)
                     .await?;
             }
+            IndexServerEvent::ClientFriendProposal((public_key, friend_proposal)) => {
+                // A client may only send proposals on its own behalf:
+                if friend_proposal.src_public_key != public_key {
+                    warn!(
+                        "Client {:?} sent a friend proposal on behalf of another node",
+                        public_key
+                    );
+                    continue;
+                }
+                index_server.handle_friend_proposal(None, friend_proposal);
+            }
+            IndexServerEvent::ClientAnnounceRelay((public_key, address)) => {
+                // The relay is authenticated by the connection, so a client may only register
+                // itself as a relay:
+                if !index_server
+                    .relay_directory
+                    .announce(public_key.clone(), address)
+                {
+                    warn!(
+                        "Relay directory is full. Ignoring announcement from {:?}",
+                        public_key
+                    );
+                }
+            }
+            IndexServerEvent::ClientRequestRelays((public_key, request_relays)) => {
+                let max_relays = cmp::min(request_relays.max_relays as usize, MAX_RESPONSE_RELAYS);
+                let response_relays = ResponseRelays {
+                    request_id: request_relays.request_id,
+                    relays: index_server.relay_directory.list(max_relays, &public_key),
+                };
+                if let Some(connected_client) = index_server.clients.get_mut(&public_key) {
+                    let _ = connected_client
+                        .try_send(IndexServerToClient::ResponseRelays(response_relays));
+                }
+            }
+            IndexServerEvent::ClientRequestServerStatus((public_key, request_server_status)) => {
+                index_server
+                    .handle_request_server_status(public_key, request_server_status)
+                    .await?
+            }
             IndexServerEvent::ClientClosed(public_key) => {
                 // Client connection closed
                 if index_server.clients.remove(&public_key).is_none() {
                     error!("A non existent client {:?} was closed.", public_key);
                 }
+                // A relay is only listed while it is connected:
+                index_server.relay_directory.remove(&public_key);
+                // The client may resume its session if it reconnects soon:
+                index_server
+                    .resumable_clients
+                    .insert(public_key, INDEX_SESSION_RESUME_TICKS);
             }
-            IndexServerEvent::TimerTick => index_server.handle_timer_tick().await?,
-            IndexServerEvent::ClientListenerClosed => {
-                warn!("server_loop() client listener closed!");
-                break;
+            IndexServerEvent::ClientResumeSession((public_key, resume_session)) => {
+                index_server.handle_resume_session(public_key, resume_session)
             }
-            IndexServerEvent::ServerListenerClosed => {
-                warn!("server_loop() server listener closed!");
-                break;
+            IndexServerEvent::ClientRequestDirectory((public_key, request_directory)) => {
+                let response_directory = ResponseDirectory {
+                    request_id: request_directory.request_id,
+                    opt_directory: index_server.opt_directory.clone(),
+                };
+                if let Some(connected_client) = index_server.clients.get_mut(&public_key) {
+                    let _ = connected_client
+                        .try_send(IndexServerToClient::ResponseDirectory(response_directory));
+                }
             }
-        }
-        // debug_event_sender is used to control the order of events during testing.
-        // This is useful to have deterministic test results.
-        // Normal code should have no use of this sender, and it should be set to None.
-        if let Some(ref mut debug_event_sender) = &mut opt_debug_event_sender {
-            let _ = debug_event_sender.send(()).await;
-        }
-    }
-    Ok(())
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    use std::convert::TryFrom;
+            IndexServerEvent::AdminConnection((public_key, admin_conn)) => {
+                if !index_server.admin_public_keys.contains(&public_key) {
+                    warn!(
+                        "Non admin {:?} attempted an admin connection. Aborting.",
+                        public_key
+                    );
+                    continue;
+                }
+                if index_server.admins.contains_key(&public_key) {
+                    error!("Admin {:?} already connected! Aborting.", public_key);
+                    continue;
+                }
 
-    use futures::executor::{block_on, ThreadPool};
-    use futures::task::Spawn;
+                let (sender, receiver) = admin_conn.split();
+                let sender = sink_to_sender(sender, &spawner);
+                let c_sender = sender.clone();
 
-    use crypto::identity::SoftwareEd25519Identity;
-    use crypto::rand::RandGen;
+                let mut c_event_sender = index_server.event_sender.clone();
+                let c_public_key = public_key.clone();
+                let admin_handler_fut = admin_handler(
+                    index_server.graph_client.clone(),
+                    public_key.clone(),
+                    AdminConn::from_raw(sender, receiver),
+                    index_server.event_sender.clone(),
+                )
+                .map_err(|e| error!("admin_handler() error: {:?}", e))
+                .then(|_| async move {
+                    let _ = c_event_sender
+                        .send(IndexServerEvent::AdminClosed(c_public_key))
+                        .await;
+                });
+
+                index_server
+                    .spawner
+                    .spawn(admin_handler_fut)
+                    .map_err(|_| ServerLoopError::SpawnError)?;
+                index_server
+                    .admins
+                    .insert(public_key, Connected::new(c_sender));
+            }
+            IndexServerEvent::AdminClosed(public_key) => {
+                if index_server.admins.remove(&public_key).is_none() {
+                    error!("A non existent admin {:?} was closed.", public_key);
+                }
+            }
+            IndexServerEvent::AdminApplyChange((public_key, change)) => {
+                index_server.handle_admin_change(public_key, change).await?
+            }
+            IndexServerEvent::AdminRequestJournal((public_key, request_id)) => {
+                index_server.handle_admin_request_journal(public_key, request_id)
+            }
+            IndexServerEvent::QueryConnection(query_conn) => {
+                if index_server.queries.len() >= MAX_QUERY_CONNS {
+                    warn!("Too many query connections. Aborting.");
+                    continue;
+                }
+                let query_id = index_server.next_query_id;
+                index_server.next_query_id = index_server.next_query_id.wrapping_add(1);
+
+                let (sender, receiver) = query_conn.split();
+                let sender = sink_to_sender(sender, &spawner);
+
+                let mut c_event_sender = index_server.event_sender.clone();
+                let query_handler_fut =
+                    query_handler(query_id, receiver, index_server.event_sender.clone())
+                        .map_err(|e| error!("query_handler() error: {:?}", e))
+                        .then(move |_| async move {
+                            let _ = c_event_sender
+                                .send(IndexServerEvent::QueryClosed(query_id))
+                                .await;
+                        });
+
+                index_server
+                    .spawner
+                    .spawn(query_handler_fut)
+                    .map_err(|_| ServerLoopError::SpawnError)?;
+                index_server.queries.insert(
+                    query_id,
+                    QueryConnected {
+                        connected: Connected::new(sender),
+                        tokens: MAX_QUERY_TOKENS,
+                    },
+                );
+            }
+            IndexServerEvent::QueryClosed(query_id) => {
+                if index_server.queries.remove(&query_id).is_none() {
+                    error!("A non existent query connection {} was closed.", query_id);
+                }
+            }
+            IndexServerEvent::QueryRequestRoutes((query_id, request_routes)) => {
+                let query_connected = match index_server.queries.get_mut(&query_id) {
+                    Some(query_connected) => query_connected,
+                    None => continue,
+                };
+                if query_connected.tokens == 0
+                    || index_server.pending_queries >= MAX_PENDING_QUERIES
+                {
+                    warn!(
+                        "Query connection {} exceeded its rate limit. Dropping request.",
+                        query_id
+                    );
+                    continue;
+                }
+                query_connected.tokens -= 1;
+                index_server.pending_queries += 1;
+
+                let mut c_graph_client = index_server.graph_client.clone();
+                let mut c_event_sender = index_server.event_sender.clone();
+                index_server
+                    .spawner
+                    .spawn(async move {
+                        let opt_response_routes =
+                            calc_response_routes(&mut c_graph_client, request_routes)
+                                .await
+                                .map_err(|e| error!("calc_response_routes() error: {:?}", e))
+                                .ok();
+                        let _ = c_event_sender
+                            .send(IndexServerEvent::QueryResponseRoutes((
+                                query_id,
+                                opt_response_routes,
+                            )))
+                            .await;
+                    })
+                    .map_err(|_| ServerLoopError::SpawnError)?;
+            }
+            IndexServerEvent::QueryResponseRoutes((query_id, opt_response_routes)) => {
+                index_server.pending_queries = index_server.pending_queries.saturating_sub(1);
+                if let (Some(query_connected), Some(response_routes)) =
+                    (index_server.queries.get_mut(&query_id), opt_response_routes)
+                {
+                    let _ = query_connected
+                        .connected
+                        .try_send(IndexServerToClient::ResponseRoutes(response_routes));
+                }
+            }
+            IndexServerEvent::TimerTick => index_server.handle_timer_tick().await?,
+            IndexServerEvent::ClientListenerClosed => {
+                warn!("server_loop() client listener closed!");
+                break;
+            }
+            IndexServerEvent::ServerListenerClosed => {
+                warn!("server_loop() server listener closed!");
+                break;
+            }
+        }
+        // debug_event_sender is used to control the order of events during testing.
+        // This is useful to have deterministic test results.
+        // Normal code should have no use of this sender, and it should be set to None.
+        if let Some(ref mut debug_event_sender) = &mut opt_debug_event_sender {
+            let _ = debug_event_sender.send(()).await;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use futures::executor::{block_on, ThreadPool};
+    use futures::task::Spawn;
+
+    use crypto::identity::SoftwareEd25519Identity;
+    use crypto::pow::solve_pow;
+    use crypto::rand::RandGen;
     use crypto::test_utils::DummyRandom;
 
-    use proto::crypto::{PrivateKey, PublicKey, RandValue, Signature};
-    use proto::funder::messages::Currency;
-    use proto::index_server::messages::{RemoveFriendCurrency, RequestRoutes};
+    use proto::crypto::{PrivateKey, PublicKey, RandValue, Signature};
+    use proto::funder::messages::Currency;
+    use proto::index_server::messages::{
+        RemoveFriendCurrency, RequestEdges, RequestRoutes, RouteConstraints, RouteRanking,
+    };
+
+    use common::dummy_connector::{ConnRequest, DummyConnector};
+    use common::testing::MockScript;
+    use database::testing::{create_mock_database, MockDatabase};
+    use identity::{create_identity, IdentityClient};
+
+    use signature::signature_buff::{
+        create_mutations_update_pow_buff, create_mutations_update_signature_buff,
+        SIGNATURE_BUFF_VERSION,
+    };
+
+    use crate::graph::graph_service::GraphRequest;
+    use crate::verifier::simple_verifier::SimpleVerifier;
+
+    /// Size of channel used for channels between servers, or channels between a server and a
+    /// client. A value bigger than 0 is used to make sure try_send() doesn't fail during mutations
+    /// forwarding or when sending time hash ticks.
+    const CHANNEL_SIZE: usize = 16;
+
+    fn create_identity_client<S>(spawner: S, seed: &[u8]) -> IdentityClient
+    where
+        S: Spawn,
+    {
+        let rng = DummyRandom::new(seed);
+        let pkcs8 = PrivateKey::rand_gen(&rng);
+        let identity = SoftwareEd25519Identity::from_private_key(&pkcs8).unwrap();
+        let (requests_sender, identity_server) = create_identity(identity);
+        let identity_client = IdentityClient::new(requests_sender);
+        spawner
+            .spawn(identity_server.then(|_| future::ready(())))
+            .unwrap();
+        identity_client
+    }
+
+    /// Create an in memory database for the admin state
+    fn create_admin_db<S>(spawner: &S) -> (DatabaseClient<AdminMutation>, MockDatabase<AdminState>)
+    where
+        S: Spawn,
+    {
+        let (admin_db_client, mock_database, database_fut) =
+            create_mock_database(AdminState::new(), MockScript::new());
+        spawner.spawn(database_fut).unwrap();
+        (admin_db_client, mock_database)
+    }
+
+    async fn task_index_server_loop_single_server<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+        let server_pk = PublicKey::from(&[0; PublicKey::len()]);
+
+        let local_public_key = server_pk.clone();
+        let trusted_servers: HashMap<PublicKey, u8> = HashMap::new();
+
+        let (_server_connections_sender, incoming_server_connections) = mpsc::channel(0);
+        let (mut client_connections_sender, incoming_client_connections) = mpsc::channel(0);
+
+        let (conn_request_sender, _conn_request_receiver) = mpsc::channel(0);
+        let server_connector = DummyConnector::new(conn_request_sender);
+
+        let (mut tick_sender, timer_stream) = mpsc::channel::<()>(0);
+
+        let (graph_requests_sender, mut graph_requests_receiver) = mpsc::channel(0);
+        let graph_client = GraphClient::new(graph_requests_sender);
+
+        let compare_public_key = |pk_a: &PublicKey, pk_b: &PublicKey| pk_a.cmp(pk_b);
+
+        // TODO: Do we have a way to create a mock SimpleVerifier that will work correctly?
+        // Currently DummyVerifier will cause infinite cycles when forwarding messages.
+        let rng = DummyRandom::new(&[0u8]);
+        let verifier = SimpleVerifier::new(8, 4, rng);
+
+        let server_loop_fut = server_loop(
+            local_public_key,
+            trusted_servers,
+            HashSet::new(),
+            AdminState::new(),
+            create_admin_db(&spawner).0,
+            None,
+            incoming_server_connections,
+            incoming_client_connections,
+            stream::empty(),
+            stream::empty(),
+            server_connector,
+            graph_client,
+            compare_public_key,
+            verifier,
+            timer_stream,
+            spawner.clone(),
+            None,
+        )
+        .map_err(|e| error!("Error in server_loop(): {:?}", e))
+        .map(|_| ());
+
+        spawner.spawn(server_loop_fut).unwrap();
+
+        let identity_client = create_identity_client(spawner.clone(), &[1, 1]);
+        let client_public_key = identity_client.request_public_key().await.unwrap();
+
+        let (mut client_sender, server_receiver) = mpsc::channel(CHANNEL_SIZE);
+        let (server_sender, mut client_receiver) = mpsc::channel(CHANNEL_SIZE);
+        client_connections_sender
+            .send((
+                client_public_key.clone(),
+                ConnPair::from_raw(server_sender, server_receiver),
+            ))
+            .await
+            .unwrap();
+
+        // Client requests routes:
+        let request_id = Uid::from(&[0; Uid::len()]);
+        let request_routes = RequestRoutes {
+            request_id: request_id.clone(),
+            currency: currency1.clone(),
+            capacity: 100,
+            source: PublicKey::from(&[8; PublicKey::len()]),
+            destination: PublicKey::from(&[9; PublicKey::len()]),
+            opt_exclude: None,
+            constraints: RouteConstraints::default(),
+            ranking: RouteRanking::default(),
+        };
+        client_sender
+            .send(IndexClientToServer::RequestRoutes(request_routes))
+            .await
+            .unwrap();
+
+        // Handle the graph request:
+        match graph_requests_receiver.next().await.unwrap() {
+            GraphRequest::GetMultiRoutes(
+                currency,
+                src,
+                dest,
+                capacity,
+                opt_exclude,
+                constraints,
+                response_sender,
+            ) => {
+                assert_eq!(currency, currency1);
+                assert_eq!(src, PublicKey::from(&[8; PublicKey::len()]));
+                assert_eq!(dest, PublicKey::from(&[9; PublicKey::len()]));
+                assert_eq!(capacity, 100);
+                assert_eq!(opt_exclude, None);
+                assert_eq!(constraints, GraphRouteConstraints::default());
+                response_sender.send(Vec::new()).unwrap();
+            }
+            _ => unreachable!(),
+        }
+
+        match client_receiver.next().await.unwrap() {
+            IndexServerToClient::ResponseRoutes(response_routes) => {
+                assert_eq!(response_routes.request_id, request_id);
+                assert!(response_routes.multi_routes.is_empty());
+            }
+            _ => unreachable!(),
+        };
+
+        // Client requests routes ranked by capacity:
+        let request_id = Uid::from(&[1; Uid::len()]);
+        let request_routes = RequestRoutes {
+            request_id: request_id.clone(),
+            currency: currency1.clone(),
+            capacity: 100,
+            source: PublicKey::from(&[8; PublicKey::len()]),
+            destination: PublicKey::from(&[9; PublicKey::len()]),
+            opt_exclude: None,
+            constraints: RouteConstraints::default(),
+            ranking: RouteRanking::Capacity(1000),
+        };
+        client_sender
+            .send(IndexClientToServer::RequestRoutes(request_routes))
+            .await
+            .unwrap();
+
+        // The amount of ranked routes is limited:
+        match graph_requests_receiver.next().await.unwrap() {
+            GraphRequest::GetRankedRoutes(
+                currency,
+                _src,
+                _dest,
+                capacity,
+                _opt_exclude,
+                max_routes,
+                _constraints,
+                response_sender,
+            ) => {
+                assert_eq!(currency, currency1);
+                assert_eq!(capacity, 100);
+                assert_eq!(max_routes, MAX_RANKED_ROUTES);
+                response_sender.send(Vec::new()).unwrap();
+            }
+            _ => unreachable!(),
+        }
+
+        match client_receiver.next().await.unwrap() {
+            IndexServerToClient::ResponseRoutes(response_routes) => {
+                assert_eq!(response_routes.request_id, request_id);
+                assert!(response_routes.multi_routes.is_empty());
+            }
+            _ => unreachable!(),
+        };
+
+        // Server should periodically send time hashes to the client,
+        // together with the required proof of work difficulty:
+        tick_sender.send(()).await.unwrap();
+
+        let pow_difficulty = match client_receiver.next().await.unwrap() {
+            IndexServerToClient::PowDifficulty(pow_difficulty) => pow_difficulty,
+            _ => unreachable!(),
+        };
+        assert_eq!(pow_difficulty, 4);
+
+        let time_hash = match client_receiver.next().await.unwrap() {
+            IndexServerToClient::TimeHash(time_hash) => time_hash,
+            _ => unreachable!(),
+        };
+
+        // Send mutations update to the server:
+        let index_mutations = vec![IndexMutation::RemoveFriendCurrency(RemoveFriendCurrency {
+            public_key: PublicKey::from(&[11; PublicKey::len()]),
+            currency: currency1.clone(),
+        })];
+
+        let mut mutations_update = MutationsUpdate {
+            node_public_key: client_public_key.clone(),
+            index_mutations,
+            time_hash,
+            session_id: Uid::from(&[0; Uid::len()]),
+            counter: 0,
+            rand_nonce: RandValue::from(&[0; RandValue::len()]),
+            signature: Signature::from(&[0; Signature::len()]),
+            pow_nonce: 0,
+        };
+
+        // Calculate signature:
+        mutations_update.signature = identity_client
+            .request_signature(create_mutations_update_signature_buff(
+                &mutations_update,
+                SIGNATURE_BUFF_VERSION,
+            ))
+            .await
+            .unwrap();
+
+        // Calculate proof of work:
+        mutations_update.pow_nonce = solve_pow(
+            &create_mutations_update_pow_buff(&mutations_update),
+            pow_difficulty,
+        );
+
+        client_sender
+            .send(IndexClientToServer::MutationsUpdate(mutations_update))
+            .await
+            .unwrap();
+
+        // Handle tick request:
+        match graph_requests_receiver.next().await.unwrap() {
+            GraphRequest::Tick(node, response_sender) => {
+                assert_eq!(node, client_public_key);
+                response_sender.send(()).unwrap();
+            }
+            _ => unreachable!(),
+        }
+
+        // Handle the graph request:
+        match graph_requests_receiver.next().await.unwrap() {
+            GraphRequest::RemoveEdge(currency, src, dest, response_sender) => {
+                assert_eq!(currency, currency1);
+                assert_eq!(src, client_public_key);
+                assert_eq!(dest, PublicKey::from(&[11; PublicKey::len()]));
+                response_sender.send(None).unwrap();
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_index_server_loop_single_server() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_index_server_loop_single_server(thread_pool.clone()));
+    }
+
+    async fn task_index_server_loop_relay_directory<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let local_public_key = PublicKey::from(&[0; PublicKey::len()]);
+        let trusted_servers: HashMap<PublicKey, u8> = HashMap::new();
+
+        let (_server_connections_sender, incoming_server_connections) = mpsc::channel(0);
+        let (mut client_connections_sender, incoming_client_connections) = mpsc::channel(0);
+
+        let (conn_request_sender, _conn_request_receiver) = mpsc::channel(0);
+        let server_connector = DummyConnector::new(conn_request_sender);
+
+        let (_tick_sender, timer_stream) = mpsc::channel::<()>(0);
+
+        let (graph_requests_sender, _graph_requests_receiver) = mpsc::channel(0);
+        let graph_client = GraphClient::new(graph_requests_sender);
+
+        let compare_public_key = |pk_a: &PublicKey, pk_b: &PublicKey| pk_a.cmp(pk_b);
+
+        let rng = DummyRandom::new(&[0u8]);
+        let verifier = SimpleVerifier::new(8, 4, rng);
+
+        // Used to wait until the server handles every event:
+        let (debug_event_sender, mut debug_event_receiver) = mpsc::channel(0);
+
+        let server_loop_fut = server_loop(
+            local_public_key,
+            trusted_servers,
+            HashSet::new(),
+            AdminState::new(),
+            create_admin_db(&spawner).0,
+            None,
+            incoming_server_connections,
+            incoming_client_connections,
+            stream::empty(),
+            stream::empty(),
+            server_connector,
+            graph_client,
+            compare_public_key,
+            verifier,
+            timer_stream,
+            spawner.clone(),
+            Some(debug_event_sender),
+        )
+        .map_err(|e| error!("Error in server_loop(): {:?}", e))
+        .map(|_| ());
+
+        spawner.spawn(server_loop_fut).unwrap();
+
+        // A relay connects and registers itself:
+        let relay_public_key = PublicKey::from(&[1; PublicKey::len()]);
+        let relay_address = NetAddress::try_from("relay1:1337".to_owned()).unwrap();
+        let (mut relay_sender, server_receiver) = mpsc::channel(CHANNEL_SIZE);
+        let (server_sender, _relay_receiver) = mpsc::channel(CHANNEL_SIZE);
+        client_connections_sender
+            .send((
+                relay_public_key.clone(),
+                ConnPair::from_raw(server_sender, server_receiver),
+            ))
+            .await
+            .unwrap();
+        debug_event_receiver.next().await.unwrap();
+
+        relay_sender
+            .send(IndexClientToServer::AnnounceRelay(relay_address.clone()))
+            .await
+            .unwrap();
+        debug_event_receiver.next().await.unwrap();
+
+        // A node connects and asks for relays:
+        let node_public_key = PublicKey::from(&[2; PublicKey::len()]);
+        let (mut node_sender, server_receiver) = mpsc::channel(CHANNEL_SIZE);
+        let (server_sender, mut node_receiver) = mpsc::channel(CHANNEL_SIZE);
+        client_connections_sender
+            .send((
+                node_public_key.clone(),
+                ConnPair::from_raw(server_sender, server_receiver),
+            ))
+            .await
+            .unwrap();
+        debug_event_receiver.next().await.unwrap();
+
+        let request_relays = RequestRelays {
+            request_id: Uid::from(&[3; Uid::len()]),
+            max_relays: 8,
+        };
+        node_sender
+            .send(IndexClientToServer::RequestRelays(request_relays))
+            .await
+            .unwrap();
+        debug_event_receiver.next().await.unwrap();
+
+        match node_receiver.next().await.unwrap() {
+            IndexServerToClient::ResponseRelays(response_relays) => {
+                assert_eq!(response_relays.request_id, Uid::from(&[3; Uid::len()]));
+                assert_eq!(response_relays.relays.len(), 1);
+                assert_eq!(response_relays.relays[0].public_key, relay_public_key);
+                assert_eq!(response_relays.relays[0].address, relay_address);
+            }
+            _ => unreachable!(),
+        };
+
+        // The relay disconnects, and is not listed anymore:
+        drop(relay_sender);
+        debug_event_receiver.next().await.unwrap();
+
+        let request_relays = RequestRelays {
+            request_id: Uid::from(&[4; Uid::len()]),
+            max_relays: 8,
+        };
+        node_sender
+            .send(IndexClientToServer::RequestRelays(request_relays))
+            .await
+            .unwrap();
+        debug_event_receiver.next().await.unwrap();
+
+        match node_receiver.next().await.unwrap() {
+            IndexServerToClient::ResponseRelays(response_relays) => {
+                assert_eq!(response_relays.request_id, Uid::from(&[4; Uid::len()]));
+                assert!(response_relays.relays.is_empty());
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn test_index_server_loop_relay_directory() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_index_server_loop_relay_directory(thread_pool.clone()));
+    }
+
+    async fn task_index_server_loop_server_status<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let local_public_key = PublicKey::from(&[0; PublicKey::len()]);
+        let trusted_servers: HashMap<PublicKey, u8> = HashMap::new();
+
+        let (_server_connections_sender, incoming_server_connections) = mpsc::channel(0);
+        let (mut client_connections_sender, incoming_client_connections) = mpsc::channel(0);
+
+        let (conn_request_sender, _conn_request_receiver) = mpsc::channel(0);
+        let server_connector = DummyConnector::new(conn_request_sender);
+
+        let (mut tick_sender, timer_stream) = mpsc::channel::<()>(0);
+
+        let (graph_requests_sender, mut graph_requests_receiver) = mpsc::channel(0);
+        let graph_client = GraphClient::new(graph_requests_sender);
+
+        let compare_public_key = |pk_a: &PublicKey, pk_b: &PublicKey| pk_a.cmp(pk_b);
+
+        let rng = DummyRandom::new(&[0u8]);
+        let verifier = SimpleVerifier::new(8, 4, rng);
+
+        // Used to wait until the server handles every event:
+        let (debug_event_sender, mut debug_event_receiver) = mpsc::channel(0);
+
+        let server_loop_fut = server_loop(
+            local_public_key,
+            trusted_servers,
+            HashSet::new(),
+            AdminState::new(),
+            create_admin_db(&spawner).0,
+            None,
+            incoming_server_connections,
+            incoming_client_connections,
+            stream::empty(),
+            stream::empty(),
+            server_connector,
+            graph_client,
+            compare_public_key,
+            verifier,
+            timer_stream,
+            spawner.clone(),
+            Some(debug_event_sender),
+        )
+        .map_err(|e| error!("Error in server_loop(): {:?}", e))
+        .map(|_| ());
+
+        spawner.spawn(server_loop_fut).unwrap();
+
+        let node_public_key = PublicKey::from(&[1; PublicKey::len()]);
+        let (mut node_sender, server_receiver) = mpsc::channel(CHANNEL_SIZE);
+        let (server_sender, mut node_receiver) = mpsc::channel(CHANNEL_SIZE);
+        client_connections_sender
+            .send((
+                node_public_key.clone(),
+                ConnPair::from_raw(server_sender, server_receiver),
+            ))
+            .await
+            .unwrap();
+        debug_event_receiver.next().await.unwrap();
+
+        // No time hash was received from a peer server during this tick:
+        tick_sender.send(()).await.unwrap();
+        debug_event_receiver.next().await.unwrap();
+
+        let request_server_status = RequestServerStatus {
+            request_id: Uid::from(&[2; Uid::len()]),
+        };
+        node_sender
+            .send(IndexClientToServer::RequestServerStatus(
+                request_server_status,
+            ))
+            .await
+            .unwrap();
+
+        // The server asks the graph service for the size of the graph:
+        match graph_requests_receiver.next().await.unwrap() {
+            GraphRequest::GetSize(response_sender) => response_sender.send((5, 7)).unwrap(),
+            _ => unreachable!(),
+        };
+        debug_event_receiver.next().await.unwrap();
+
+        loop {
+            match node_receiver.next().await.unwrap() {
+                IndexServerToClient::PowDifficulty(_) | IndexServerToClient::TimeHash(_) => {}
+                IndexServerToClient::ResponseServerStatus(response_server_status) => {
+                    assert_eq!(
+                        response_server_status,
+                        ResponseServerStatus {
+                            request_id: Uid::from(&[2; Uid::len()]),
+                            num_nodes: 5,
+                            num_edges: 7,
+                            num_clients: 1,
+                            num_servers: 0,
+                            time_hash_age: 1,
+                        }
+                    );
+                    break;
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_index_server_loop_server_status() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_index_server_loop_server_status(thread_pool.clone()));
+    }
+
+    async fn task_index_server_loop_admin<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+        let local_public_key = PublicKey::from(&[0; PublicKey::len()]);
+        let trusted_servers: HashMap<PublicKey, u8> = HashMap::new();
+        let admin_public_key = PublicKey::from(&[1; PublicKey::len()]);
+        let mut admin_public_keys = HashSet::new();
+        admin_public_keys.insert(admin_public_key.clone());
+
+        let (_server_connections_sender, incoming_server_connections) = mpsc::channel(0);
+        let (_client_connections_sender, incoming_client_connections) = mpsc::channel(0);
+        let (mut admin_connections_sender, incoming_admin_connections) = mpsc::channel(0);
+
+        let (conn_request_sender, _conn_request_receiver) = mpsc::channel(0);
+        let server_connector = DummyConnector::new(conn_request_sender);
+
+        let (_tick_sender, timer_stream) = mpsc::channel::<()>(0);
+
+        let (graph_requests_sender, mut graph_requests_receiver) = mpsc::channel(0);
+        let graph_client = GraphClient::new(graph_requests_sender);
 
-    use common::dummy_connector::{ConnRequest, DummyConnector};
-    use identity::{create_identity, IdentityClient};
+        let compare_public_key = |pk_a: &PublicKey, pk_b: &PublicKey| pk_a.cmp(pk_b);
 
-    use signature::signature_buff::create_mutations_update_signature_buff;
+        let rng = DummyRandom::new(&[0u8]);
+        let verifier = SimpleVerifier::new(8, 4, rng);
 
-    use crate::graph::graph_service::GraphRequest;
-    use crate::verifier::simple_verifier::SimpleVerifier;
+        let (admin_db_client, admin_db) = create_admin_db(&spawner);
 
-    /// Size of channel used for channels between servers, or channels between a server and a
-    /// client. A value bigger than 0 is used to make sure try_send() doesn't fail during mutations
-    /// forwarding or when sending time hash ticks.
-    const CHANNEL_SIZE: usize = 16;
+        // Used to wait until the server handles every event:
+        let (debug_event_sender, mut debug_event_receiver) = mpsc::channel(0);
 
-    fn create_identity_client<S>(spawner: S, seed: &[u8]) -> IdentityClient
-    where
-        S: Spawn,
-    {
-        let rng = DummyRandom::new(seed);
-        let pkcs8 = PrivateKey::rand_gen(&rng);
-        let identity = SoftwareEd25519Identity::from_private_key(&pkcs8).unwrap();
-        let (requests_sender, identity_server) = create_identity(identity);
-        let identity_client = IdentityClient::new(requests_sender);
-        spawner
-            .spawn(identity_server.then(|_| future::ready(())))
+        let server_loop_fut = server_loop(
+            local_public_key,
+            trusted_servers,
+            admin_public_keys,
+            AdminState::new(),
+            admin_db_client,
+            None,
+            incoming_server_connections,
+            incoming_client_connections,
+            incoming_admin_connections,
+            stream::empty(),
+            server_connector,
+            graph_client,
+            compare_public_key,
+            verifier,
+            timer_stream,
+            spawner.clone(),
+            Some(debug_event_sender),
+        )
+        .map_err(|e| error!("Error in server_loop(): {:?}", e))
+        .map(|_| ());
+
+        spawner.spawn(server_loop_fut).unwrap();
+
+        // A node that is not an admin attempts to connect. The connection is closed:
+        let (_other_sender, server_receiver) = mpsc::channel(CHANNEL_SIZE);
+        let (server_sender, mut other_receiver) = mpsc::channel(CHANNEL_SIZE);
+        admin_connections_sender
+            .send((
+                PublicKey::from(&[2; PublicKey::len()]),
+                ConnPair::from_raw(server_sender, server_receiver),
+            ))
+            .await
             .unwrap();
-        identity_client
+        debug_event_receiver.next().await.unwrap();
+        assert!(other_receiver.next().await.is_none());
+
+        let (mut admin_sender, server_receiver) = mpsc::channel(CHANNEL_SIZE);
+        let (server_sender, mut admin_receiver) = mpsc::channel(CHANNEL_SIZE);
+        admin_connections_sender
+            .send((
+                admin_public_key.clone(),
+                ConnPair::from_raw(server_sender, server_receiver),
+            ))
+            .await
+            .unwrap();
+        debug_event_receiver.next().await.unwrap();
+
+        // Query the edges of a node:
+        let node_public_key = PublicKey::from(&[3; PublicKey::len()]);
+        let request_edges = RequestEdges {
+            request_id: Uid::from(&[4; Uid::len()]),
+            opt_public_key: Some(node_public_key.clone()),
+        };
+        admin_sender
+            .send(IndexAdminToServer::RequestEdges(request_edges))
+            .await
+            .unwrap();
+
+        let rate = Rate { mul: 0, add: 1 };
+        match graph_requests_receiver.next().await.unwrap() {
+            GraphRequest::GetEdges(opt_node, response_sender) => {
+                assert_eq!(opt_node, Some(node_public_key.clone()));
+                response_sender
+                    .send(vec![(
+                        currency1.clone(),
+                        node_public_key.clone(),
+                        admin_public_key.clone(),
+                        CapacityEdge::new(100, rate.clone()),
+                    )])
+                    .unwrap();
+            }
+            _ => unreachable!(),
+        };
+
+        match admin_receiver.next().await.unwrap() {
+            IndexServerToAdmin::ResponseEdges(response_edges) => {
+                assert_eq!(
+                    response_edges,
+                    ResponseEdges {
+                        request_id: Uid::from(&[4; Uid::len()]),
+                        edges: vec![AdminEdge {
+                            currency: currency1.clone(),
+                            from_public_key: node_public_key.clone(),
+                            to_public_key: admin_public_key.clone(),
+                            recv_capacity: 100,
+                            rate,
+                        }],
+                        is_last: true,
+                    }
+                );
+            }
+            _ => unreachable!(),
+        }
+
+        // Remove the node:
+        admin_sender
+            .send(IndexAdminToServer::ApplyChange(AdminChange::RemoveNode(
+                node_public_key.clone(),
+            )))
+            .await
+            .unwrap();
+
+        match graph_requests_receiver.next().await.unwrap() {
+            GraphRequest::RemoveNode(node, response_sender) => {
+                assert_eq!(node, node_public_key);
+                response_sender.send(()).unwrap();
+            }
+            _ => unreachable!(),
+        };
+        debug_event_receiver.next().await.unwrap();
+
+        // The change shows up in the journal:
+        admin_sender
+            .send(IndexAdminToServer::RequestJournal(Uid::from(
+                &[5; Uid::len()],
+            )))
+            .await
+            .unwrap();
+        debug_event_receiver.next().await.unwrap();
+
+        match admin_receiver.next().await.unwrap() {
+            IndexServerToAdmin::ResponseJournal(response_journal) => {
+                assert_eq!(
+                    response_journal,
+                    ResponseJournal {
+                        request_id: Uid::from(&[5; Uid::len()]),
+                        entries: vec![AdminJournalEntry {
+                            admin_public_key,
+                            change: AdminChange::RemoveNode(node_public_key.clone()),
+                        }],
+                    }
+                );
+            }
+            _ => unreachable!(),
+        }
+
+        // The change was saved, and the node is denied:
+        let admin_state = admin_db.state();
+        assert_eq!(admin_state.journal.len(), 1);
+        assert!(admin_state.denied_nodes.contains(&node_public_key));
     }
 
-    async fn task_index_server_loop_single_server<S>(spawner: S)
+    #[test]
+    fn test_index_server_loop_admin() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_index_server_loop_admin(thread_pool.clone()));
+    }
+
+    async fn task_index_server_loop_query<S>(spawner: S)
     where
         S: Spawn + Clone + Send + 'static,
     {
         let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
-        let server_pk = PublicKey::from(&[0; PublicKey::len()]);
-
-        let local_public_key = server_pk.clone();
+        let local_public_key = PublicKey::from(&[0; PublicKey::len()]);
         let trusted_servers: HashMap<PublicKey, u8> = HashMap::new();
 
         let (_server_connections_sender, incoming_server_connections) = mpsc::channel(0);
-        let (mut client_connections_sender, incoming_client_connections) = mpsc::channel(0);
+        let (_client_connections_sender, incoming_client_connections) = mpsc::channel(0);
+        let (mut query_connections_sender, incoming_query_connections) = mpsc::channel(0);
 
         let (conn_request_sender, _conn_request_receiver) = mpsc::channel(0);
         let server_connector = DummyConnector::new(conn_request_sender);
@@ -752,145 +2193,310 @@ mod tests {
 
         let compare_public_key = |pk_a: &PublicKey, pk_b: &PublicKey| pk_a.cmp(pk_b);
 
-        // TODO: Do we have a way to create a mock SimpleVerifier that will work correctly?
-        // Currently DummyVerifier will cause infinite cycles when forwarding messages.
         let rng = DummyRandom::new(&[0u8]);
-        let verifier = SimpleVerifier::new(8, rng);
+        let verifier = SimpleVerifier::new(8, 4, rng);
+
+        // Used to wait until the server handles every event:
+        let (debug_event_sender, mut debug_event_receiver) = mpsc::channel(0);
 
         let server_loop_fut = server_loop(
             local_public_key,
             trusted_servers,
+            HashSet::new(),
+            AdminState::new(),
+            create_admin_db(&spawner).0,
+            None,
             incoming_server_connections,
             incoming_client_connections,
+            stream::empty(),
+            incoming_query_connections,
             server_connector,
             graph_client,
             compare_public_key,
             verifier,
             timer_stream,
             spawner.clone(),
-            None,
+            Some(debug_event_sender),
         )
         .map_err(|e| error!("Error in server_loop(): {:?}", e))
         .map(|_| ());
 
         spawner.spawn(server_loop_fut).unwrap();
 
-        let identity_client = create_identity_client(spawner.clone(), &[1, 1]);
-        let client_public_key = identity_client.request_public_key().await.unwrap();
-
-        let (mut client_sender, server_receiver) = mpsc::channel(CHANNEL_SIZE);
-        let (server_sender, mut client_receiver) = mpsc::channel(CHANNEL_SIZE);
-        client_connections_sender
-            .send((
-                client_public_key.clone(),
-                ConnPair::from_raw(server_sender, server_receiver),
-            ))
+        // A query connection does not require a public key:
+        let (mut query_sender, server_receiver) = mpsc::channel(CHANNEL_SIZE);
+        let (server_sender, mut query_receiver) = mpsc::channel(CHANNEL_SIZE);
+        query_connections_sender
+            .send(ConnPair::from_raw(server_sender, server_receiver))
             .await
             .unwrap();
+        debug_event_receiver.next().await.unwrap();
 
-        // Client requests routes:
-        let request_id = Uid::from(&[0; Uid::len()]);
-        let request_routes = RequestRoutes {
-            request_id: request_id.clone(),
+        let create_request_routes = |i: u8| RequestRoutes {
+            request_id: Uid::from(&[i; Uid::len()]),
             currency: currency1.clone(),
             capacity: 100,
             source: PublicKey::from(&[8; PublicKey::len()]),
             destination: PublicKey::from(&[9; PublicKey::len()]),
             opt_exclude: None,
+            constraints: RouteConstraints::default(),
+            ranking: RouteRanking::ShortestPath,
         };
-        client_sender
-            .send(IndexClientToServer::RequestRoutes(request_routes))
+
+        for i in 0..MAX_QUERY_TOKENS + 1 {
+            query_sender
+                .send(IndexClientToServer::RequestRoutes(create_request_routes(
+                    i as u8,
+                )))
+                .await
+                .unwrap();
+            debug_event_receiver.next().await.unwrap();
+            if i == MAX_QUERY_TOKENS {
+                // The last request exceeds the rate limit, and is dropped:
+                break;
+            }
+
+            match graph_requests_receiver.next().await.unwrap() {
+                GraphRequest::GetMultiRoutes(_, _, _, _, _, _, response_sender) => {
+                    response_sender.send(Vec::new()).unwrap();
+                }
+                _ => unreachable!(),
+            }
+            debug_event_receiver.next().await.unwrap();
+
+            match query_receiver.next().await.unwrap() {
+                IndexServerToClient::ResponseRoutes(response_routes) => {
+                    assert_eq!(
+                        response_routes.request_id,
+                        Uid::from(&[i as u8; Uid::len()])
+                    );
+                }
+                _ => unreachable!(),
+            };
+        }
+
+        // A query connection can not push mutations. The message is ignored:
+        query_sender
+            .send(IndexClientToServer::AnnounceRelay(
+                NetAddress::try_from("relay1:1337".to_owned()).unwrap(),
+            ))
             .await
             .unwrap();
 
-        // Handle the graph request:
+        // A time tick allows another request:
+        tick_sender.send(()).await.unwrap();
+        debug_event_receiver.next().await.unwrap();
+
+        query_sender
+            .send(IndexClientToServer::RequestRoutes(create_request_routes(
+                0x10,
+            )))
+            .await
+            .unwrap();
+        debug_event_receiver.next().await.unwrap();
+
         match graph_requests_receiver.next().await.unwrap() {
-            GraphRequest::GetMultiRoutes(
-                currency,
-                src,
-                dest,
-                capacity,
-                opt_exclude,
-                response_sender,
-            ) => {
-                assert_eq!(currency, currency1);
-                assert_eq!(src, PublicKey::from(&[8; PublicKey::len()]));
-                assert_eq!(dest, PublicKey::from(&[9; PublicKey::len()]));
-                assert_eq!(capacity, 100);
-                assert_eq!(opt_exclude, None);
+            GraphRequest::GetMultiRoutes(_, _, _, _, _, _, response_sender) => {
                 response_sender.send(Vec::new()).unwrap();
             }
             _ => unreachable!(),
         }
+        debug_event_receiver.next().await.unwrap();
 
-        match client_receiver.next().await.unwrap() {
+        // Query connections are not sent time hashes, so the next message is the response:
+        match query_receiver.next().await.unwrap() {
             IndexServerToClient::ResponseRoutes(response_routes) => {
-                assert_eq!(response_routes.request_id, request_id);
-                assert!(response_routes.multi_routes.is_empty());
+                assert_eq!(response_routes.request_id, Uid::from(&[0x10; Uid::len()]));
             }
             _ => unreachable!(),
         };
+    }
+
+    #[test]
+    fn test_index_server_loop_query() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_index_server_loop_query(thread_pool.clone()));
+    }
+
+    async fn task_index_server_loop_resume_session<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+        let local_public_key = PublicKey::from(&[0; PublicKey::len()]);
+        let trusted_servers: HashMap<PublicKey, u8> = HashMap::new();
+
+        let (_server_connections_sender, incoming_server_connections) = mpsc::channel(0);
+        let (mut client_connections_sender, incoming_client_connections) = mpsc::channel(0);
+
+        let (conn_request_sender, _conn_request_receiver) = mpsc::channel(0);
+        let server_connector = DummyConnector::new(conn_request_sender);
+
+        let (mut tick_sender, timer_stream) = mpsc::channel::<()>(0);
+
+        let (graph_requests_sender, mut graph_requests_receiver) = mpsc::channel(0);
+        let graph_client = GraphClient::new(graph_requests_sender);
+
+        let compare_public_key = |pk_a: &PublicKey, pk_b: &PublicKey| pk_a.cmp(pk_b);
+
+        let rng = DummyRandom::new(&[0u8]);
+        let verifier = SimpleVerifier::new(8, 4, rng);
+
+        // Used to wait until the server handles every event:
+        let (debug_event_sender, mut debug_event_receiver) = mpsc::channel(0);
+
+        let server_loop_fut = server_loop(
+            local_public_key,
+            trusted_servers,
+            HashSet::new(),
+            AdminState::new(),
+            create_admin_db(&spawner).0,
+            None,
+            incoming_server_connections,
+            incoming_client_connections,
+            stream::empty(),
+            stream::empty(),
+            server_connector,
+            graph_client,
+            compare_public_key,
+            verifier,
+            timer_stream,
+            spawner.clone(),
+            Some(debug_event_sender),
+        )
+        .map_err(|e| error!("Error in server_loop(): {:?}", e))
+        .map(|_| ());
+
+        spawner.spawn(server_loop_fut).unwrap();
+
+        let identity_client = create_identity_client(spawner.clone(), &[1, 1]);
+        let client_public_key = identity_client.request_public_key().await.unwrap();
+
+        let (mut client_sender, server_receiver) = mpsc::channel(CHANNEL_SIZE);
+        let (server_sender, mut client_receiver) = mpsc::channel(CHANNEL_SIZE);
+        client_connections_sender
+            .send((
+                client_public_key.clone(),
+                ConnPair::from_raw(server_sender, server_receiver),
+            ))
+            .await
+            .unwrap();
+        debug_event_receiver.next().await.unwrap();
 
-        // Server should periodically send time hashes to the client:
         tick_sender.send(()).await.unwrap();
+        debug_event_receiver.next().await.unwrap();
 
+        let pow_difficulty = match client_receiver.next().await.unwrap() {
+            IndexServerToClient::PowDifficulty(pow_difficulty) => pow_difficulty,
+            _ => unreachable!(),
+        };
         let time_hash = match client_receiver.next().await.unwrap() {
             IndexServerToClient::TimeHash(time_hash) => time_hash,
             _ => unreachable!(),
         };
 
-        // Send mutations update to the server:
-        let index_mutations = vec![IndexMutation::RemoveFriendCurrency(RemoveFriendCurrency {
-            public_key: PublicKey::from(&[11; PublicKey::len()]),
-            currency: currency1.clone(),
-        })];
-
+        // Send a mutations update to the server:
         let mut mutations_update = MutationsUpdate {
             node_public_key: client_public_key.clone(),
-            index_mutations,
+            index_mutations: vec![IndexMutation::RemoveFriendCurrency(RemoveFriendCurrency {
+                public_key: PublicKey::from(&[11; PublicKey::len()]),
+                currency: currency1.clone(),
+            })],
             time_hash,
-            session_id: Uid::from(&[0; Uid::len()]),
-            counter: 0,
+            session_id: Uid::from(&[3; Uid::len()]),
+            counter: 5,
             rand_nonce: RandValue::from(&[0; RandValue::len()]),
             signature: Signature::from(&[0; Signature::len()]),
+            pow_nonce: 0,
         };
-
-        // Calculate signature:
         mutations_update.signature = identity_client
-            .request_signature(create_mutations_update_signature_buff(&mutations_update))
+            .request_signature(create_mutations_update_signature_buff(
+                &mutations_update,
+                SIGNATURE_BUFF_VERSION,
+            ))
             .await
             .unwrap();
-
+        mutations_update.pow_nonce = solve_pow(
+            &create_mutations_update_pow_buff(&mutations_update),
+            pow_difficulty,
+        );
         client_sender
             .send(IndexClientToServer::MutationsUpdate(mutations_update))
             .await
             .unwrap();
 
-        // Handle tick request:
         match graph_requests_receiver.next().await.unwrap() {
-            GraphRequest::Tick(node, response_sender) => {
-                assert_eq!(node, client_public_key);
-                response_sender.send(()).unwrap();
-            }
+            GraphRequest::Tick(_node, response_sender) => response_sender.send(()).unwrap(),
             _ => unreachable!(),
         }
-
-        // Handle the graph request:
         match graph_requests_receiver.next().await.unwrap() {
-            GraphRequest::RemoveEdge(currency, src, dest, response_sender) => {
-                assert_eq!(currency, currency1);
-                assert_eq!(src, client_public_key);
-                assert_eq!(dest, PublicKey::from(&[11; PublicKey::len()]));
-                response_sender.send(None).unwrap();
+            GraphRequest::RemoveEdge(_currency, _src, _dest, response_sender) => {
+                response_sender.send(None).unwrap()
+            }
+            _ => unreachable!(),
+        }
+        debug_event_receiver.next().await.unwrap();
+
+        let mut resume_results = Vec::new();
+        for counter in &[4u64, 5] {
+            // Disconnect:
+            drop(client_sender);
+            drop(client_receiver);
+            debug_event_receiver.next().await.unwrap();
+
+            // Reconnect:
+            let (new_client_sender, server_receiver) = mpsc::channel(CHANNEL_SIZE);
+            let (server_sender, new_client_receiver) = mpsc::channel(CHANNEL_SIZE);
+            client_sender = new_client_sender;
+            client_receiver = new_client_receiver;
+            client_connections_sender
+                .send((
+                    client_public_key.clone(),
+                    ConnPair::from_raw(server_sender, server_receiver),
+                ))
+                .await
+                .unwrap();
+            debug_event_receiver.next().await.unwrap();
+
+            client_sender
+                .send(IndexClientToServer::ResumeSession(ResumeSession {
+                    session_id: Uid::from(&[3; Uid::len()]),
+                    counter: *counter,
+                }))
+                .await
+                .unwrap();
+            debug_event_receiver.next().await.unwrap();
+
+            match client_receiver.next().await.unwrap() {
+                IndexServerToClient::SessionResumed(session_resumed) => {
+                    resume_results.push(session_resumed)
+                }
+                _ => unreachable!(),
             }
+        }
+        // The server did not know the mutations update with counter 4.
+        // The last mutations update known to the server has the counter 5:
+        assert_eq!(resume_results, vec![false, true]);
+
+        // A session can not be resumed without disconnecting first:
+        client_sender
+            .send(IndexClientToServer::ResumeSession(ResumeSession {
+                session_id: Uid::from(&[3; Uid::len()]),
+                counter: 5,
+            }))
+            .await
+            .unwrap();
+        debug_event_receiver.next().await.unwrap();
+        match client_receiver.next().await.unwrap() {
+            IndexServerToClient::SessionResumed(session_resumed) => assert!(!session_resumed),
             _ => unreachable!(),
         }
     }
 
     #[test]
-    fn test_index_server_loop_single_server() {
+    fn test_index_server_loop_resume_session() {
         let thread_pool = ThreadPool::new().unwrap();
-        block_on(task_index_server_loop_single_server(thread_pool.clone()));
+        block_on(task_index_server_loop_resume_session(thread_pool.clone()));
     }
 
     // ###########################################################
@@ -933,7 +2539,7 @@ mod tests {
         let compare_public_key = |pk_a: &PublicKey, pk_b: &PublicKey| pk_a.cmp(pk_b);
 
         let rng = DummyRandom::new(&[0x13, 0x37, server_public_key[0]]);
-        let verifier = SimpleVerifier::new(8, rng);
+        let verifier = SimpleVerifier::new(8, 0, rng);
 
         let c_server_public_key = server_public_key.clone();
 
@@ -942,8 +2548,14 @@ mod tests {
         let server_loop_fut = server_loop(
             local_public_key,
             trusted_servers,
+            HashSet::new(),
+            AdminState::new(),
+            create_admin_db(&spawner).0,
+            None,
             incoming_server_connections,
             incoming_client_connections,
+            stream::empty(),
+            stream::empty(),
             server_connector,
             graph_client,
             compare_public_key,
@@ -1095,6 +2707,8 @@ mod tests {
             source: PublicKey::from(&[8; PublicKey::len()]),
             destination: PublicKey::from(&[9; PublicKey::len()]),
             opt_exclude: None,
+            constraints: RouteConstraints::default(),
+            ranking: RouteRanking::default(),
         };
         client_sender
             .send(IndexClientToServer::RequestRoutes(request_routes))
@@ -1114,6 +2728,7 @@ mod tests {
                 dest,
                 capacity,
                 opt_exclude,
+                constraints,
                 response_sender,
             ) => {
                 assert_eq!(currency, currency1);
@@ -1121,6 +2736,7 @@ mod tests {
                 assert_eq!(dest, PublicKey::from(&[9; PublicKey::len()]));
                 assert_eq!(capacity, 100);
                 assert_eq!(opt_exclude, None);
+                assert_eq!(constraints, GraphRouteConstraints::default());
                 response_sender.send(Vec::new()).unwrap();
             }
             _ => unreachable!(),
@@ -1142,6 +2758,10 @@ mod tests {
         }
 
         // Get time hash sent to the new client:
+        match client_receiver.next().await.unwrap() {
+            IndexServerToClient::PowDifficulty(0) => {}
+            _ => unreachable!(),
+        };
         let time_hash0 = match client_receiver.next().await.unwrap() {
             IndexServerToClient::TimeHash(time_hash) => time_hash,
             _ => unreachable!(),
@@ -1161,11 +2781,15 @@ mod tests {
             counter: 0,
             rand_nonce: RandValue::from(&[0; RandValue::len()]),
             signature: Signature::from(&[0; Signature::len()]),
+            pow_nonce: 0,
         };
 
         // Calculate signature:
         mutations_update.signature = identity_client
-            .request_signature(create_mutations_update_signature_buff(&mutations_update))
+            .request_signature(create_mutations_update_signature_buff(
+                &mutations_update,
+                SIGNATURE_BUFF_VERSION,
+            ))
             .await
             .unwrap();
 
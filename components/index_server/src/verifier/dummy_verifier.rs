@@ -40,11 +40,17 @@ impl<N, B, U> Verifier for DummyVerifier<N, B, U> {
         _node: &N,
         _session_id: &U,
         _counter: u64,
+        _pow_bits: u32,
     ) -> Option<&[HashResult]> {
         // Everything is successfully verified:
         Some(&self.hash_vec)
     }
 
+    fn pow_difficulty(&self, _node: &N) -> u8 {
+        // No proof of work is required
+        0
+    }
+
     fn tick(&mut self) -> (HashResult, Vec<N>) {
         // Nothing happens. Always the same tick.
         (HashResult::from(&[0; HashResult::len()]), Vec::new())
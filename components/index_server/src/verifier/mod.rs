@@ -1,6 +1,7 @@
 #[cfg(test)]
 pub mod dummy_verifier;
 mod hash_clock;
+mod pow_pool;
 mod ratchet;
 pub mod simple_verifier;
 mod verifier;
@@ -0,0 +1,154 @@
+use std::collections::{HashMap, VecDeque};
+
+/// Amount of messages a node may send during the rate window without paying more than the base
+/// proof of work difficulty.
+const FREE_MESSAGES: usize = 0x10;
+
+/// Maximum amount of zero bits added to the base difficulty for nodes that send many messages.
+const MAX_EXTRA_DIFFICULTY: u8 = 16;
+
+struct NodeRate {
+    /// Amount of messages received during every recent tick. The front is the current tick.
+    tick_messages: VecDeque<usize>,
+    /// The difficulty the node should currently meet
+    difficulty: u8,
+    /// The difficulty before the last tick
+    prev_difficulty: u8,
+}
+
+/// Tracks the rate of messages sent by every node, and the proof of work difficulty every node
+/// has to meet. Every doubling of the rate of messages beyond `FREE_MESSAGES` adds one zero bit
+/// to the difficulty.
+pub struct PowPool<N> {
+    base_difficulty: u8,
+    window_ticks: usize,
+    nodes: HashMap<N, NodeRate>,
+}
+
+impl<N> PowPool<N>
+where
+    N: std::cmp::Eq + std::hash::Hash + Clone,
+{
+    pub fn new(base_difficulty: u8, window_ticks: usize) -> Self {
+        assert!(window_ticks > 0);
+
+        PowPool {
+            base_difficulty,
+            window_ticks,
+            nodes: HashMap::new(),
+        }
+    }
+
+    fn calc_difficulty(&self, num_messages: usize) -> u8 {
+        let mut extra_difficulty = 0u8;
+        let mut threshold = FREE_MESSAGES;
+        while num_messages > threshold && extra_difficulty < MAX_EXTRA_DIFFICULTY {
+            extra_difficulty += 1;
+            threshold = threshold.saturating_mul(2);
+        }
+        self.base_difficulty.saturating_add(extra_difficulty)
+    }
+
+    /// The difficulty a node should meet for its next messages
+    pub fn difficulty(&self, node: &N) -> u8 {
+        self.nodes
+            .get(node)
+            .map(|node_rate| node_rate.difficulty)
+            .unwrap_or(self.base_difficulty)
+    }
+
+    /// The minimal difficulty we accept from a node.
+    /// Messages created before the last tick are allowed to meet the previous difficulty.
+    pub fn required_difficulty(&self, node: &N) -> u8 {
+        self.nodes
+            .get(node)
+            .map(|node_rate| std::cmp::min(node_rate.difficulty, node_rate.prev_difficulty))
+            .unwrap_or(self.base_difficulty)
+    }
+
+    /// Count a message received from a node
+    pub fn record(&mut self, node: &N) {
+        let base_difficulty = self.base_difficulty;
+        let node_rate = self.nodes.entry(node.clone()).or_insert_with(|| NodeRate {
+            tick_messages: vec![0].into(),
+            difficulty: base_difficulty,
+            prev_difficulty: base_difficulty,
+        });
+        if let Some(cur_messages) = node_rate.tick_messages.front_mut() {
+            *cur_messages = cur_messages.saturating_add(1);
+        }
+    }
+
+    /// Recalculate the difficulty of all nodes according to their recent rate of messages.
+    /// Nodes that did not send messages during the whole window are forgotten.
+    pub fn tick(&mut self) {
+        let window_ticks = self.window_ticks;
+        let mut nodes = std::mem::replace(&mut self.nodes, HashMap::new());
+        nodes.retain(|_node, node_rate| {
+            node_rate.tick_messages.truncate(window_ticks);
+            let num_messages = node_rate.tick_messages.iter().sum::<usize>();
+            node_rate.prev_difficulty = node_rate.difficulty;
+            node_rate.difficulty = self.calc_difficulty(num_messages);
+            node_rate.tick_messages.push_front(0);
+            num_messages > 0 || node_rate.prev_difficulty != self.base_difficulty
+        });
+        self.nodes = nodes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pow_pool_calc_difficulty() {
+        let pow_pool = PowPool::<u32>::new(8, 4);
+        assert_eq!(pow_pool.calc_difficulty(0), 8);
+        assert_eq!(pow_pool.calc_difficulty(FREE_MESSAGES), 8);
+        assert_eq!(pow_pool.calc_difficulty(FREE_MESSAGES + 1), 9);
+        assert_eq!(pow_pool.calc_difficulty(2 * FREE_MESSAGES), 9);
+        assert_eq!(pow_pool.calc_difficulty(2 * FREE_MESSAGES + 1), 10);
+        assert_eq!(
+            pow_pool.calc_difficulty(usize::max_value()),
+            8 + MAX_EXTRA_DIFFICULTY
+        );
+    }
+
+    #[test]
+    fn test_pow_pool_rate() {
+        let window_ticks = 4;
+        let mut pow_pool = PowPool::<u32>::new(2, window_ticks);
+        assert_eq!(pow_pool.difficulty(&0), 2);
+        assert_eq!(pow_pool.required_difficulty(&0), 2);
+
+        // A burst of messages from node 0:
+        for _ in 0..4 * FREE_MESSAGES {
+            pow_pool.record(&0);
+        }
+        pow_pool.record(&1);
+        pow_pool.tick();
+
+        assert_eq!(pow_pool.difficulty(&0), 4);
+        // Messages created before the tick may still meet the previous difficulty:
+        assert_eq!(pow_pool.required_difficulty(&0), 2);
+        // A node that sends few messages only pays the base difficulty:
+        assert_eq!(pow_pool.difficulty(&1), 2);
+
+        pow_pool.tick();
+        assert_eq!(pow_pool.difficulty(&0), 4);
+        assert_eq!(pow_pool.required_difficulty(&0), 4);
+
+        // The burst leaves the window:
+        for _ in 0..window_ticks - 2 {
+            pow_pool.tick();
+        }
+        assert_eq!(pow_pool.difficulty(&0), 4);
+        pow_pool.tick();
+        assert_eq!(pow_pool.difficulty(&0), 2);
+        assert_eq!(pow_pool.required_difficulty(&0), 2);
+
+        // Quiet nodes are eventually forgotten:
+        pow_pool.tick();
+        assert!(pow_pool.nodes.is_empty());
+    }
+}
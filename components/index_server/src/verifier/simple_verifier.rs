@@ -3,12 +3,14 @@ use crypto::rand::{CryptoRandom, RandGen};
 use proto::crypto::{HashResult, RandValue};
 
 use super::hash_clock::HashClock;
+use super::pow_pool::PowPool;
 use super::ratchet::RatchetPool;
 use super::verifier::Verifier;
 
 pub struct SimpleVerifier<N, B, U, R> {
     hash_clock: HashClock<B>,
     ratchet_pool: RatchetPool<N, U>,
+    pow_pool: PowPool<N>,
     rng: R,
 }
 
@@ -19,8 +21,10 @@ where
     U: std::cmp::Eq + Clone,
     R: CryptoRandom,
 {
+    /// `pow_difficulty` is the proof of work difficulty required from nodes that send messages
+    /// at a low rate. The rate of messages is measured over the last `ticks_to_live` ticks.
     #[allow(unused)]
-    pub fn new(ticks_to_live: usize, rng: R) -> Self {
+    pub fn new(ticks_to_live: usize, pow_difficulty: u8, rng: R) -> Self {
         // TODO: Security: Make sure that we don't have an off-by-one here with the decision to have
         // one ticks_to_live value for both `hash_clock` and `ratchet_pool`.
 
@@ -29,6 +33,7 @@ where
         SimpleVerifier {
             hash_clock: HashClock::new(ticks_to_live),
             ratchet_pool: RatchetPool::new(ticks_to_live),
+            pow_pool: PowPool::new(pow_difficulty, ticks_to_live),
             rng,
         }
     }
@@ -52,24 +57,40 @@ where
         node: &N,
         session_id: &U,
         counter: u64,
+        pow_bits: u32,
     ) -> Option<&[HashResult]> {
         // Check the hash time stamp:
         let tick_hash = self
             .hash_clock
             .verify_expansion_chain(origin_tick_hash, expansion_chain)?;
 
+        // Check the proof of work of messages sent directly by the node.
+        // Messages forwarded by other servers were already checked by the first server.
+        if expansion_chain.is_empty()
+            && pow_bits < u32::from(self.pow_pool.required_difficulty(node))
+        {
+            return None;
+        }
+
         // Update ratchets (This should protect against out of order messages):
         if !self.ratchet_pool.update(node, session_id, counter) {
             return None;
         }
 
+        self.pow_pool.record(node);
+
         // If we got here, the message was new:
         let hashes = self.hash_clock.get_expansion(&tick_hash).unwrap();
         Some(hashes)
     }
 
+    fn pow_difficulty(&self, node: &N) -> u8 {
+        self.pow_pool.difficulty(node)
+    }
+
     fn tick(&mut self) -> (HashResult, Vec<N>) {
         let rand_value = RandValue::rand_gen(&self.rng);
+        self.pow_pool.tick();
         (self.hash_clock.tick(rand_value), self.ratchet_pool.tick())
     }
 
@@ -96,7 +117,7 @@ mod tests {
 
         for i in 0..num_verifiers {
             let rng = DummyRandom::new(&[i as u8]);
-            svs.push(SimpleVerifier::new(ticks_to_live, rng));
+            svs.push(SimpleVerifier::new(ticks_to_live, 0, rng));
         }
 
         for _iter in 0..ticks_to_live + 1 {
@@ -115,15 +136,22 @@ mod tests {
 
         // Forwarding of a message:
         let hashes0 = svs[0]
-            .verify(&tick_hash, &[], &1234u128, &0u128, 0u64)
+            .verify(&tick_hash, &[], &1234u128, &0u128, 0u64, 0)
             .unwrap()
             .to_vec();
         let hashes1 = svs[1]
-            .verify(&tick_hash, &[&hashes0], &1234u128, &0u128, 0u64)
+            .verify(&tick_hash, &[&hashes0], &1234u128, &0u128, 0u64, 0)
             .unwrap()
             .to_vec();
         let hashes2 = svs[2]
-            .verify(&tick_hash, &[&hashes0, &hashes1], &1234u128, &0u128, 0u64)
+            .verify(
+                &tick_hash,
+                &[&hashes0, &hashes1],
+                &1234u128,
+                &0u128,
+                0u64,
+                0,
+            )
             .unwrap()
             .to_vec();
         let _hashes3 = svs[3]
@@ -133,10 +161,42 @@ mod tests {
                 &1234u128,
                 &0u128,
                 0u64,
+                0,
             )
             .unwrap()
             .to_vec();
     }
 
+    #[test]
+    fn test_simple_verifier_pow() {
+        let ticks_to_live = 8;
+        let mut sv0 = SimpleVerifier::new(ticks_to_live, 4, DummyRandom::new(&[0u8]));
+        let mut sv1 = SimpleVerifier::new(ticks_to_live, 4, DummyRandom::new(&[1u8]));
+
+        for _iter in 0..ticks_to_live + 1 {
+            let (tick_hash, _removed) = sv0.tick();
+            let _ = sv1.neighbor_tick(0, tick_hash);
+            let (tick_hash, _removed) = sv1.tick();
+            let _ = sv0.neighbor_tick(1, tick_hash);
+        }
+
+        let (tick_hash, _removed) = sv0.tick();
+        assert_eq!(sv0.pow_difficulty(&1234u128), 4);
+
+        // Not enough proof of work:
+        assert!(sv0
+            .verify(&tick_hash, &[], &1234u128, &0u128, 0u64, 3)
+            .is_none());
+        let hashes0 = sv0
+            .verify(&tick_hash, &[], &1234u128, &0u128, 0u64, 4)
+            .unwrap()
+            .to_vec();
+
+        // Forwarded messages were already checked by the first server:
+        assert!(sv1
+            .verify(&tick_hash, &[&hashes0], &1234u128, &0u128, 0u64, 0)
+            .is_some());
+    }
+
     // TODO: Add more tests?
 }
@@ -8,6 +8,9 @@ pub trait Verifier {
     /// Verify an incoming message:
     /// - Checks freshness using a chain of time hashes.
     /// - Making sure that the message is not out of order using a ratchet counter.
+    /// - Checks the proof of work of messages sent directly by the node (An empty
+    ///   `expansion_chain`). `pow_bits` is the amount of leading zero bits in the proof of work
+    ///   hash of the message.
     fn verify(
         &mut self,
         origin_tick_hash: &HashResult,
@@ -15,8 +18,13 @@ pub trait Verifier {
         node: &Self::Node,
         session_id: &Self::SessionId,
         counter: u64,
+        pow_bits: u32,
     ) -> Option<&[HashResult]>;
 
+    /// The proof of work difficulty (Amount of leading zero bits) a node should meet for its
+    /// next messages. The difficulty grows with the rate of messages sent by the node.
+    fn pow_difficulty(&self, node: &Self::Node) -> u8;
+
     /// One time tick. Returns a `tick_hash` representing the local current time,
     /// and a vector of all the nodes removed due to timeout
     fn tick(&mut self) -> (HashResult, Vec<Self::Node>);
@@ -0,0 +1,234 @@
+use std::cmp;
+use std::collections::HashMap;
+
+use futures::channel::{mpsc, oneshot};
+use futures::task::{Spawn, SpawnExt};
+use futures::{SinkExt, StreamExt};
+
+use proto::crypto::PublicKey;
+
+/// A connection that stayed alive for this amount of keepalive intervals proves that its interval
+/// is long enough to keep the NAT mappings along the way. The interval is then extended.
+const STABLE_INTERVALS: u64 = 0x10;
+
+/// Bounds for the amount of ticks between keepalives sent to a remote side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeepAliveBounds {
+    /// Keepalives are never sent more often than once every `min_ticks`.
+    pub min_ticks: usize,
+    /// Keepalives are sent at least once every `max_ticks`.
+    pub max_ticks: usize,
+}
+
+/// Keeps the interval between keepalives sent to every remote side.
+///
+/// Every remote side starts with the longest interval, to save battery and bandwidth.
+/// The interval is halved whenever a connection times out (Possibly because a NAT mapping along
+/// the way expired), and slowly extended again while connections stay alive.
+#[derive(Debug)]
+pub struct AdaptiveKeepAlive {
+    bounds: KeepAliveBounds,
+    /// Only intervals shorter than `bounds.max_ticks` are kept.
+    send_ticks: HashMap<PublicKey, usize>,
+}
+
+impl AdaptiveKeepAlive {
+    pub fn new(bounds: KeepAliveBounds) -> Self {
+        AdaptiveKeepAlive {
+            bounds,
+            send_ticks: HashMap::new(),
+        }
+    }
+
+    /// Amount of ticks between keepalives sent to `public_key`
+    pub fn send_ticks(&self, public_key: &PublicKey) -> usize {
+        *self
+            .send_ticks
+            .get(public_key)
+            .unwrap_or(&self.bounds.max_ticks)
+    }
+
+    fn set_send_ticks(&mut self, public_key: PublicKey, send_ticks: usize) {
+        let send_ticks = cmp::max(
+            cmp::min(send_ticks, self.bounds.max_ticks),
+            self.bounds.min_ticks,
+        );
+        if send_ticks >= self.bounds.max_ticks {
+            let _ = self.send_ticks.remove(&public_key);
+        } else {
+            let _ = self.send_ticks.insert(public_key, send_ticks);
+        }
+    }
+
+    /// A connection to `public_key` timed out: The remote side stopped responding.
+    pub fn timed_out(&mut self, public_key: PublicKey) {
+        let send_ticks = self.send_ticks(&public_key) / 2;
+        self.set_send_ticks(public_key, send_ticks);
+    }
+
+    /// A connection to `public_key` was closed (Without timing out), after staying alive for
+    /// `alive_ticks` ticks.
+    pub fn closed(&mut self, public_key: PublicKey, alive_ticks: u64) {
+        let send_ticks = self.send_ticks(&public_key);
+        let stable_ticks = (send_ticks as u64).saturating_mul(STABLE_INTERVALS);
+        if alive_ticks < stable_ticks {
+            // Not enough evidence that the current interval is safe:
+            return;
+        }
+        let send_ticks = send_ticks.saturating_add(cmp::max(send_ticks / 4, 1));
+        self.set_send_ticks(public_key, send_ticks);
+    }
+}
+
+#[derive(Debug)]
+pub enum AdaptiveKeepAliveError {
+    SpawnError,
+}
+
+#[derive(Debug)]
+enum AdaptiveRequest {
+    SendTicks((PublicKey, oneshot::Sender<usize>)),
+    TimedOut(PublicKey),
+    Closed((PublicKey, u64)),
+}
+
+/// A handle to a service that adapts the keepalive intervals of all the connections of a node.
+#[derive(Debug, Clone)]
+pub struct AdaptiveKeepAliveClient {
+    request_sender: mpsc::Sender<AdaptiveRequest>,
+}
+
+impl AdaptiveKeepAliveClient {
+    /// Get the amount of ticks between keepalives sent to `public_key`.
+    /// Returns None if the service is closed.
+    pub async fn request_send_ticks(&mut self, public_key: PublicKey) -> Option<usize> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        self.request_sender
+            .send(AdaptiveRequest::SendTicks((public_key, response_sender)))
+            .await
+            .ok()?;
+        response_receiver.await.ok()
+    }
+
+    /// Report that a connection to `public_key` timed out
+    pub async fn report_timed_out(&mut self, public_key: PublicKey) {
+        let _ = self
+            .request_sender
+            .send(AdaptiveRequest::TimedOut(public_key))
+            .await;
+    }
+
+    /// Report that a connection to `public_key` was closed after `alive_ticks` ticks
+    pub async fn report_closed(&mut self, public_key: PublicKey, alive_ticks: u64) {
+        let _ = self
+            .request_sender
+            .send(AdaptiveRequest::Closed((public_key, alive_ticks)))
+            .await;
+    }
+}
+
+/// Spawn a service that adapts keepalive intervals within `bounds`.
+pub fn create_adaptive_keepalive<S>(
+    bounds: KeepAliveBounds,
+    spawner: S,
+) -> Result<AdaptiveKeepAliveClient, AdaptiveKeepAliveError>
+where
+    S: Spawn,
+{
+    let (request_sender, mut incoming_requests) = mpsc::channel(0);
+
+    let service_fut = async move {
+        let mut adaptive_keepalive = AdaptiveKeepAlive::new(bounds);
+        while let Some(request) = incoming_requests.next().await {
+            match request {
+                AdaptiveRequest::SendTicks((public_key, response_sender)) => {
+                    let _ = response_sender.send(adaptive_keepalive.send_ticks(&public_key));
+                }
+                AdaptiveRequest::TimedOut(public_key) => {
+                    adaptive_keepalive.timed_out(public_key);
+                }
+                AdaptiveRequest::Closed((public_key, alive_ticks)) => {
+                    adaptive_keepalive.closed(public_key, alive_ticks);
+                }
+            }
+        }
+    };
+
+    spawner
+        .spawn(service_fut)
+        .map_err(|_| AdaptiveKeepAliveError::SpawnError)?;
+
+    Ok(AdaptiveKeepAliveClient { request_sender })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::{LocalPool, ThreadPool};
+
+    #[test]
+    fn test_adaptive_keepalive() {
+        let mut adaptive_keepalive = AdaptiveKeepAlive::new(KeepAliveBounds {
+            min_ticks: 2,
+            max_ticks: 16,
+        });
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+
+        // Remote sides start with the longest interval:
+        assert_eq!(adaptive_keepalive.send_ticks(&pk_a), 16);
+
+        adaptive_keepalive.timed_out(pk_a.clone());
+        assert_eq!(adaptive_keepalive.send_ticks(&pk_a), 8);
+        assert_eq!(adaptive_keepalive.send_ticks(&pk_b), 16);
+
+        adaptive_keepalive.timed_out(pk_a.clone());
+        adaptive_keepalive.timed_out(pk_a.clone());
+        adaptive_keepalive.timed_out(pk_a.clone());
+        // We never go below min_ticks:
+        assert_eq!(adaptive_keepalive.send_ticks(&pk_a), 2);
+
+        // A short connection does not extend the interval:
+        adaptive_keepalive.closed(pk_a.clone(), 2 * STABLE_INTERVALS - 1);
+        assert_eq!(adaptive_keepalive.send_ticks(&pk_a), 2);
+
+        adaptive_keepalive.closed(pk_a.clone(), 2 * STABLE_INTERVALS);
+        assert_eq!(adaptive_keepalive.send_ticks(&pk_a), 3);
+        adaptive_keepalive.closed(pk_a.clone(), 3 * STABLE_INTERVALS);
+        assert_eq!(adaptive_keepalive.send_ticks(&pk_a), 4);
+        adaptive_keepalive.closed(pk_a.clone(), 4 * STABLE_INTERVALS);
+        assert_eq!(adaptive_keepalive.send_ticks(&pk_a), 5);
+
+        // We never go above max_ticks:
+        for _ in 0..16 {
+            adaptive_keepalive.closed(pk_a.clone(), u64::max_value());
+        }
+        assert_eq!(adaptive_keepalive.send_ticks(&pk_a), 16);
+        assert!(adaptive_keepalive.send_ticks.is_empty());
+    }
+
+    async fn task_adaptive_keepalive_service(spawner: impl Spawn) {
+        let mut client = create_adaptive_keepalive(
+            KeepAliveBounds {
+                min_ticks: 1,
+                max_ticks: 8,
+            },
+            spawner,
+        )
+        .unwrap();
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+
+        assert_eq!(client.request_send_ticks(pk_a.clone()).await, Some(8));
+        client.report_timed_out(pk_a.clone()).await;
+        assert_eq!(client.request_send_ticks(pk_a.clone()).await, Some(4));
+        client.report_closed(pk_a.clone(), u64::max_value()).await;
+        assert_eq!(client.request_send_ticks(pk_a.clone()).await, Some(5));
+    }
+
+    #[test]
+    fn test_adaptive_keepalive_service() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new().run_until(task_adaptive_keepalive_service(thread_pool.clone()));
+    }
+}
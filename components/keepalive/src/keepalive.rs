@@ -2,7 +2,7 @@ use std::marker::Unpin;
 
 use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
-use futures::{future, stream, FutureExt, Sink, SinkExt, Stream, StreamExt, TryFutureExt};
+use futures::{future, stream, FutureExt, Sink, SinkExt, Stream, StreamExt};
 
 use derive_more::From;
 
@@ -11,9 +11,12 @@ use timer::{TimerClient, TimerTick};
 use common::conn::{BoxFuture, BoxStream, ConnPair, ConnPairVec, FutTransform};
 use common::select_streams::select_streams;
 
-use proto::keepalive::messages::KaMessage;
+use proto::crypto::PublicKey;
+use proto::keepalive::messages::{KaMessage, KeepAliveReport};
 use proto::proto_ser::{ProtoDeserialize, ProtoSerialize, ProtoSerializeError};
 
+use crate::adaptive::AdaptiveKeepAliveClient;
+
 #[derive(From, Debug)]
 pub enum KeepAliveError {
     // TimerClosed,
@@ -23,7 +26,7 @@ pub enum KeepAliveError {
 
 #[derive(Debug, Clone)]
 enum KeepAliveEvent {
-    TimerTick,
+    Beat,
     TimerClosed,
     RemoteChannelClosed,
     UserChannelClosed,
@@ -49,28 +52,55 @@ where
                         to_user, from_user,
                         timer_stream,
                         keepalive_ticks,
+                        None,
                         None).await
 }
 */
 
-async fn inner_keepalive_loop<TR, FR, TU, FU, TS>(
+/// Send a report to the user without waiting, so that a slow report receiver never stalls the
+/// keepalive maintenance. A report that can not be sent yet is kept in `opt_pending_report`
+/// (Replacing an older, stale report), and is sent on a later attempt.
+fn try_send_report(
+    report_sender: &mut mpsc::Sender<KeepAliveReport>,
+    opt_pending_report: &mut Option<KeepAliveReport>,
+) {
+    if let Some(report) = opt_pending_report.take() {
+        if let Err(e) = report_sender.try_send(report) {
+            if e.is_full() {
+                *opt_pending_report = Some(e.into_inner());
+            }
+        }
+    }
+}
+
+/// Minimal amount of consecutive beats the remote side may miss before we close the connection.
+const TIMEOUT_BEATS: u64 = 2;
+
+/// Run the keepalive maintenance.
+/// `beat_stream` yields once every beat. The remote side is expected to send us something at least
+/// once every `timeout_beats` beats, and we send a keepalive to the remote side on every beat where
+/// we had nothing else to send.
+///
+/// Returns the amount of beats that passed until the connection was closed.
+async fn inner_keepalive_loop<TR, FR, TU, FU, BS>(
     mut to_remote: TR,
     from_remote: FR,
     mut to_user: TU,
     from_user: FU,
-    timer_stream: TS,
-    keepalive_ticks: usize,
+    beat_stream: BS,
+    timeout_beats: u64,
+    mut opt_report_sender: Option<mpsc::Sender<KeepAliveReport>>,
     mut opt_event_sender: Option<mpsc::Sender<KeepAliveEvent>>,
-) -> Result<(), KeepAliveError>
+) -> Result<u64, KeepAliveError>
 where
     TR: Sink<Vec<u8>> + Unpin,
     FR: Stream<Item = Vec<u8>> + Unpin + Send,
     TU: Sink<Vec<u8>> + Unpin,
     FU: Stream<Item = Vec<u8>> + Unpin + Send,
-    TS: Stream<Item = TimerTick> + Unpin + Send,
+    BS: Stream<Item = TimerTick> + Unpin + Send,
 {
-    let timer_stream = timer_stream
-        .map(|_| KeepAliveEvent::TimerTick)
+    let beat_stream = beat_stream
+        .map(|_| KeepAliveEvent::Beat)
         .chain(stream::once(future::ready(KeepAliveEvent::TimerClosed)));
 
     let from_remote = from_remote
@@ -85,26 +115,53 @@ where
             KeepAliveEvent::UserChannelClosed,
         )));
 
-    let mut events = select_streams![timer_stream, from_remote, from_user];
+    let mut events = select_streams![beat_stream, from_remote, from_user];
+
+    // Did we receive anything from the remote side during the current beat?
+    let mut remote_active = false;
+    // Did we send anything to the remote side during the current beat?
+    let mut local_active = false;
+
+    let mut beats: u64 = 0;
+    let mut missed_beats: u64 = 0;
+    let mut sent_keepalives: u64 = 0;
+    let mut received_keepalives: u64 = 0;
 
-    // Amount of ticks remaining until we decide to close this connection (Because remote is idle):
-    let mut ticks_to_close = keepalive_ticks;
-    // Amount of ticks remaining until we need to send a new keepalive (To make sure remote side
-    // knows we are alive).
-    let mut ticks_to_send_keepalive = keepalive_ticks / 2;
+    // The latest report that the report receiver was not ready to accept:
+    let mut opt_pending_report: Option<KeepAliveReport> = None;
 
     while let Some(event) = events.next().await {
         if let Some(ref mut event_sender) = opt_event_sender {
             let _ = event_sender.send(event.clone()).await;
         }
+        if let Some(ref mut report_sender) = opt_report_sender {
+            try_send_report(report_sender, &mut opt_pending_report);
+        }
         match event {
             KeepAliveEvent::MessageFromRemote(ser_ka_message) => {
                 let ka_message = KaMessage::proto_deserialize(&ser_ka_message)?;
-                ticks_to_close = keepalive_ticks;
-                if let KaMessage::Message(message) = ka_message {
-                    if to_user.send(message).await.is_err() {
-                        warn!("keepalive_loop(): Can not send to local side");
-                        break;
+                remote_active = true;
+                match ka_message {
+                    KaMessage::KeepAlive => {
+                        received_keepalives = received_keepalives.wrapping_add(1)
+                    }
+                    KaMessage::Message(message) => {
+                        if to_user.send(message).await.is_err() {
+                            warn!("keepalive_loop(): Can not send to local side");
+                            break;
+                        }
+                    }
+                }
+                if missed_beats > 0 {
+                    // Remote side is responsive again:
+                    missed_beats = 0;
+                    if let Some(ref mut report_sender) = opt_report_sender {
+                        opt_pending_report = Some(KeepAliveReport {
+                            missed_beats,
+                            sent_keepalives,
+                            received_keepalives,
+                        });
+                        try_send_report(report_sender, &mut opt_pending_report);
                     }
                 }
             }
@@ -115,36 +172,50 @@ where
                     warn!("keepalive_loop(): Can not send to remote side");
                     break;
                 }
-                ticks_to_send_keepalive = keepalive_ticks / 2;
+                local_active = true;
             }
-            KeepAliveEvent::TimerTick => {
-                ticks_to_close = ticks_to_close.saturating_sub(1);
-                ticks_to_send_keepalive = ticks_to_send_keepalive.saturating_sub(1);
-                if ticks_to_close == 0 {
-                    return Err(KeepAliveError::RemoteTimeout);
+            KeepAliveEvent::Beat => {
+                beats = beats.saturating_add(1);
+                if remote_active {
+                    remote_active = false;
+                } else {
+                    missed_beats = missed_beats.saturating_add(1);
+                    if missed_beats >= timeout_beats {
+                        return Err(KeepAliveError::RemoteTimeout);
+                    }
+                    if let Some(ref mut report_sender) = opt_report_sender {
+                        opt_pending_report = Some(KeepAliveReport {
+                            missed_beats,
+                            sent_keepalives,
+                            received_keepalives,
+                        });
+                        try_send_report(report_sender, &mut opt_pending_report);
+                    }
                 }
-                if ticks_to_send_keepalive == 0 {
+                if !local_active {
                     let ka_message = KaMessage::KeepAlive;
                     let ser_ka_message = ka_message.proto_serialize();
                     if to_remote.send(ser_ka_message).await.is_err() {
                         warn!("Keepalive_loop(): Can not send to remote side");
                         break;
                     }
-                    ticks_to_send_keepalive = keepalive_ticks / 2;
+                    sent_keepalives = sent_keepalives.wrapping_add(1);
                 }
+                local_active = false;
             }
             KeepAliveEvent::TimerClosed
             | KeepAliveEvent::RemoteChannelClosed
             | KeepAliveEvent::UserChannelClosed => break,
         }
     }
-    Ok(())
+    Ok(beats)
 }
 
 #[derive(Clone)]
 pub struct KeepAliveChannel<S> {
     timer_client: TimerClient,
     keepalive_ticks: usize,
+    opt_adaptive_client: Option<AdaptiveKeepAliveClient>,
     spawner: S,
 }
 
@@ -160,6 +231,24 @@ where
         KeepAliveChannel {
             timer_client,
             keepalive_ticks,
+            opt_adaptive_client: None,
+            spawner,
+        }
+    }
+
+    /// Like `new`, but the interval between keepalives sent to every remote side is adapted by
+    /// `adaptive_client` (See `transform_adaptive`). The remote side is still expected to send us
+    /// something at least once every `keepalive_ticks`.
+    pub fn new_adaptive(
+        timer_client: TimerClient,
+        keepalive_ticks: usize,
+        adaptive_client: AdaptiveKeepAliveClient,
+        spawner: S,
+    ) -> KeepAliveChannel<S> {
+        KeepAliveChannel {
+            timer_client,
+            keepalive_ticks,
+            opt_adaptive_client: Some(adaptive_client),
             spawner,
         }
     }
@@ -168,35 +257,104 @@ where
     /// keepalives automatically. The output `conn_pair` looks exactly like the input pair, however
     /// it also maintains keepalives.
     fn transform_keepalive(&mut self, conn_pair: ConnPairVec) -> BoxFuture<'_, ConnPairVec> {
+        self.transform_with_reports(conn_pair, None)
+    }
+
+    /// Like `transform`, but also sends a `KeepAliveReport` through `opt_report_sender` whenever
+    /// the amount of consecutive beats missed by the remote side changes.
+    pub fn transform_with_reports(
+        &mut self,
+        conn_pair: ConnPairVec,
+        opt_report_sender: Option<mpsc::Sender<KeepAliveReport>>,
+    ) -> BoxFuture<'_, ConnPairVec> {
+        self.transform_inner(None, conn_pair, opt_report_sender)
+    }
+
+    /// Like `transform_with_reports`, for a connection with the remote side `public_key`.
+    /// If this channel was created using `new_adaptive`, keepalives are sent more often to remote
+    /// sides whose connections time out, and less often to remote sides whose connections stay
+    /// alive.
+    pub fn transform_adaptive(
+        &mut self,
+        public_key: PublicKey,
+        conn_pair: ConnPairVec,
+        opt_report_sender: Option<mpsc::Sender<KeepAliveReport>>,
+    ) -> BoxFuture<'_, ConnPairVec> {
+        self.transform_inner(Some(public_key), conn_pair, opt_report_sender)
+    }
+
+    fn transform_inner(
+        &mut self,
+        opt_public_key: Option<PublicKey>,
+        conn_pair: ConnPairVec,
+        opt_report_sender: Option<mpsc::Sender<KeepAliveReport>>,
+    ) -> BoxFuture<'_, ConnPairVec> {
         let (to_remote, from_remote) = conn_pair.split();
 
         let (to_user, user_receiver) = mpsc::channel::<Vec<u8>>(1);
         let (user_sender, from_user) = mpsc::channel::<Vec<u8>>(1);
 
+        // The remote side is expected to send us something at least once every `keepalive_ticks`.
+        // We send something at least once every beat:
+        let max_beat_ticks = std::cmp::max(self.keepalive_ticks / 2, 1);
+
         Box::pin(async move {
-            if let Ok(timer_stream) = self.timer_client.request_timer_stream().await {
+            let opt_adaptive = match (opt_public_key, self.opt_adaptive_client.clone()) {
+                (Some(public_key), Some(adaptive_client)) => Some((public_key, adaptive_client)),
+                _ => None,
+            };
+
+            let mut beat_ticks = max_beat_ticks;
+            if let Some((public_key, adaptive_client)) = &opt_adaptive {
+                if let Some(send_ticks) = adaptive_client
+                    .clone()
+                    .request_send_ticks(public_key.clone())
+                    .await
+                {
+                    beat_ticks = std::cmp::max(std::cmp::min(send_ticks, max_beat_ticks), 1);
+                }
+            }
+            let timeout_beats =
+                std::cmp::max((self.keepalive_ticks / beat_ticks) as u64, TIMEOUT_BEATS);
+
+            if let Ok(beat_stream) = self.timer_client.request_interval(beat_ticks).await {
                 let keepalive_fut = inner_keepalive_loop(
                     to_remote,
                     from_remote,
                     to_user,
                     from_user,
-                    timer_stream,
-                    self.keepalive_ticks,
+                    beat_stream,
+                    timeout_beats,
+                    opt_report_sender,
                     None,
                 )
-                .map_err(|e| {
-                    warn!(
-                        "transform_keepalive(): inner_keepalive_loop() error: {:?}",
-                        e
-                    )
-                })
-                .then(|_| future::ready(()));
+                .then(move |res| async move {
+                    if let Err(e) = &res {
+                        warn!(
+                            "transform_keepalive(): inner_keepalive_loop() error: {:?}",
+                            e
+                        );
+                    }
+                    // Let the adaptive service learn from the fate of this connection:
+                    if let Some((public_key, mut adaptive_client)) = opt_adaptive {
+                        match res {
+                            Ok(beats) => {
+                                let alive_ticks = beats.saturating_mul(beat_ticks as u64);
+                                adaptive_client.report_closed(public_key, alive_ticks).await;
+                            }
+                            Err(KeepAliveError::RemoteTimeout) => {
+                                adaptive_client.report_timed_out(public_key).await;
+                            }
+                            Err(_) => {}
+                        }
+                    }
+                });
 
                 self.spawner.spawn(keepalive_fut).unwrap();
             } else {
                 // Note: In this case the user will notice there is an error when he tries to
                 // use the connection, because to_user, from_user are dropped
-                warn!("transform_keepalive(): Error requesting timer stream");
+                warn!("transform_keepalive(): Error requesting beat stream");
             }
 
             ConnPair::from_raw(user_sender, user_receiver)
@@ -221,22 +379,21 @@ mod tests {
     use super::*;
     use futures::executor::{LocalPool, ThreadPool};
     use futures::task::{Spawn, SpawnExt};
-    use futures::FutureExt;
+    use futures::{FutureExt, TryFutureExt};
     use timer::create_timer_incoming;
 
     /// Util function for tests
     /// Possibly remove it in the future and test the KeepAliveChannel interface directly.
-    fn keepalive_channel<TR, FR, TS, S>(
+    fn keepalive_channel<TR, FR, BS, S>(
         to_remote: TR,
         from_remote: FR,
-        timer_stream: TS,
-        keepalive_ticks: usize,
+        beat_stream: BS,
         spawner: S,
     ) -> (mpsc::Sender<Vec<u8>>, mpsc::Receiver<Vec<u8>>)
     where
         TR: Sink<Vec<u8>> + Unpin + Send + 'static,
         FR: Stream<Item = Vec<u8>> + Unpin + Send + 'static,
-        TS: Stream<Item = TimerTick> + Unpin + Send + 'static,
+        BS: Stream<Item = TimerTick> + Unpin + Send + 'static,
         S: Spawn,
     {
         let (to_user, user_receiver) = mpsc::channel::<Vec<u8>>(0);
@@ -247,8 +404,9 @@ mod tests {
             from_remote,
             to_user,
             from_user,
-            timer_stream,
-            keepalive_ticks,
+            beat_stream,
+            TIMEOUT_BEATS,
+            None,
             None,
         )
         .map_err(|e| error!("[KeepAlive] inner_keepalive_loop() error: {:?}", e))
@@ -272,15 +430,16 @@ mod tests {
         let (to_user, mut user_receiver) = mpsc::channel::<Vec<u8>>(0);
         let (mut user_sender, from_user) = mpsc::channel::<Vec<u8>>(0);
 
-        let timer_stream = timer_client.request_timer_stream().await.unwrap();
-        let keepalive_ticks = 16;
+        let beat_ticks = 8;
+        let beat_stream = timer_client.request_interval(beat_ticks).await.unwrap();
         let fut_keepalive_loop = inner_keepalive_loop(
             to_remote,
             from_remote,
             to_user,
             from_user,
-            timer_stream,
-            keepalive_ticks,
+            beat_stream,
+            TIMEOUT_BEATS,
+            None,
             Some(event_sender),
         )
         // .map_err(|e| println!("client_tunnel error: {:?}", e))
@@ -306,9 +465,12 @@ mod tests {
         let vec = user_receiver.next().await.unwrap();
         assert_eq!(vec, vec![3, 2, 1]);
 
-        // Move time forward
-        for _ in 0..8usize {
-            tick_sender.send(()).await.unwrap();
+        // Move time forward two beats.
+        // No keepalive is sent during the first beat, because the user has sent a message.
+        for _ in 0..2usize {
+            for _ in 0..beat_ticks {
+                tick_sender.send(()).await.unwrap();
+            }
             event_receiver.next().await.unwrap();
         }
 
@@ -321,9 +483,11 @@ mod tests {
         remote_sender.send(vec).await.unwrap();
         event_receiver.next().await.unwrap();
 
-        // Move time forward
-        for _ in 0..16usize {
-            tick_sender.send(()).await.unwrap();
+        // Move time forward. Remote misses the two last beats:
+        for _ in 0..3usize {
+            for _ in 0..beat_ticks {
+                tick_sender.send(()).await.unwrap();
+            }
             event_receiver.next().await.unwrap();
         }
 
@@ -339,6 +503,160 @@ mod tests {
         LocalPool::new().run_until(task_keepalive_loop_basic(thread_pool.clone()));
     }
 
+    async fn task_keepalive_loop_missed_beats(spawner: impl Spawn + Clone) {
+        // Create a mock time service:
+        let (mut tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let mut timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (event_sender, mut event_receiver) = mpsc::channel(0);
+        let (report_sender, mut report_receiver) = mpsc::channel(8);
+
+        let (to_remote, mut remote_receiver) = mpsc::channel::<Vec<u8>>(1);
+        let (mut remote_sender, from_remote) = mpsc::channel::<Vec<u8>>(1);
+
+        let (to_user, mut user_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (_user_sender, from_user) = mpsc::channel::<Vec<u8>>(0);
+
+        let beat_ticks = 8;
+        let beat_stream = timer_client.request_interval(beat_ticks).await.unwrap();
+        let fut_keepalive_loop = inner_keepalive_loop(
+            to_remote,
+            from_remote,
+            to_user,
+            from_user,
+            beat_stream,
+            TIMEOUT_BEATS,
+            Some(report_sender),
+            Some(event_sender),
+        )
+        .map(|_| ());
+
+        spawner.spawn(fut_keepalive_loop).unwrap();
+
+        // Remote is silent for one beat:
+        for _ in 0..beat_ticks {
+            tick_sender.send(()).await.unwrap();
+        }
+        event_receiver.next().await.unwrap();
+
+        // We expect to see a keepalive being sent:
+        let vec = remote_receiver.next().await.unwrap();
+        assert_eq!(vec, KaMessage::KeepAlive.proto_serialize());
+
+        // Remote has missed one beat, but the connection is still alive:
+        let report = report_receiver.next().await.unwrap();
+        assert_eq!(report.missed_beats, 1);
+        assert_eq!(report.received_keepalives, 0);
+
+        // Remote sends a keepalive:
+        let vec = KaMessage::KeepAlive.proto_serialize();
+        remote_sender.send(vec).await.unwrap();
+        event_receiver.next().await.unwrap();
+
+        // Remote has recovered:
+        let report = report_receiver.next().await.unwrap();
+        assert_eq!(
+            report,
+            KeepAliveReport {
+                missed_beats: 0,
+                sent_keepalives: 1,
+                received_keepalives: 1,
+            }
+        );
+
+        // Move time forward until the connection is closed:
+        for _ in 0..3usize {
+            for _ in 0..beat_ticks {
+                tick_sender.send(()).await.unwrap();
+            }
+            event_receiver.next().await.unwrap();
+        }
+
+        // Only one more missed beat is reported before the connection is closed:
+        let report = report_receiver.next().await.unwrap();
+        assert_eq!(report.missed_beats, 1);
+        assert!(report_receiver.next().await.is_none());
+
+        let res = user_receiver.next().await;
+        assert!(res.is_none());
+    }
+
+    #[test]
+    fn test_keepalive_loop_missed_beats() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new().run_until(task_keepalive_loop_missed_beats(thread_pool.clone()));
+    }
+
+    async fn task_keepalive_loop_slow_report_receiver(spawner: impl Spawn + Clone) {
+        // Create a mock time service:
+        let (mut tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let mut timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (event_sender, mut event_receiver) = mpsc::channel(0);
+        // Reports are not read until the end of the test:
+        let (report_sender, mut report_receiver) = mpsc::channel(0);
+
+        let (to_remote, mut remote_receiver) = mpsc::channel::<Vec<u8>>(1);
+        let (mut remote_sender, from_remote) = mpsc::channel::<Vec<u8>>(1);
+
+        let (to_user, _user_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (mut user_sender, from_user) = mpsc::channel::<Vec<u8>>(0);
+
+        let beat_ticks = 8;
+        let beat_stream = timer_client.request_interval(beat_ticks).await.unwrap();
+        let fut_keepalive_loop = inner_keepalive_loop(
+            to_remote,
+            from_remote,
+            to_user,
+            from_user,
+            beat_stream,
+            TIMEOUT_BEATS,
+            Some(report_sender),
+            Some(event_sender),
+        )
+        .map(|_| ());
+
+        spawner.spawn(fut_keepalive_loop).unwrap();
+
+        // Remote is silent for one beat. The report fills the report channel:
+        for _ in 0..beat_ticks {
+            tick_sender.send(()).await.unwrap();
+        }
+        event_receiver.next().await.unwrap();
+        let vec = remote_receiver.next().await.unwrap();
+        assert_eq!(vec, KaMessage::KeepAlive.proto_serialize());
+
+        // Remote recovers. The report channel is full, so this report is kept for later:
+        let vec = KaMessage::KeepAlive.proto_serialize();
+        remote_sender.send(vec).await.unwrap();
+        event_receiver.next().await.unwrap();
+
+        // Messages from the user are still forwarded to the remote side:
+        user_sender.send(vec![1, 2, 3]).await.unwrap();
+        event_receiver.next().await.unwrap();
+        let vec = remote_receiver.next().await.unwrap();
+        assert_eq!(vec, KaMessage::Message(vec![1, 2, 3]).proto_serialize());
+
+        let report = report_receiver.next().await.unwrap();
+        assert_eq!(report.missed_beats, 1);
+
+        // The kept report is sent on the next event:
+        let vec = KaMessage::KeepAlive.proto_serialize();
+        remote_sender.send(vec).await.unwrap();
+        event_receiver.next().await.unwrap();
+        let report = report_receiver.next().await.unwrap();
+        assert_eq!(report.missed_beats, 0);
+        assert_eq!(report.received_keepalives, 1);
+    }
+
+    #[test]
+    fn test_keepalive_loop_slow_report_receiver() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new().run_until(task_keepalive_loop_slow_report_receiver(
+            thread_pool.clone(),
+        ));
+    }
+
     async fn task_keepalive_channel_basic(spawner: impl Spawn + Clone) {
         // Create a mock time service:
         let (mut tick_sender, tick_receiver) = mpsc::channel::<()>(0);
@@ -355,23 +673,19 @@ mod tests {
         let (a_sender, b_receiver) = mpsc::channel(1);
         let (b_sender, a_receiver) = mpsc::channel(1);
 
-        let timer_stream = timer_client.request_timer_stream().await.unwrap();
-        let (mut a_sender, mut a_receiver) = keepalive_channel(
-            a_sender,
-            a_receiver,
-            timer_stream,
-            keepalive_ticks,
-            spawner.clone(),
-        );
-
-        let timer_stream = timer_client.request_timer_stream().await.unwrap();
-        let (mut b_sender, mut b_receiver) = keepalive_channel(
-            b_sender,
-            b_receiver,
-            timer_stream,
-            keepalive_ticks,
-            spawner.clone(),
-        );
+        let beat_stream = timer_client
+            .request_interval(keepalive_ticks / 2)
+            .await
+            .unwrap();
+        let (mut a_sender, mut a_receiver) =
+            keepalive_channel(a_sender, a_receiver, beat_stream, spawner.clone());
+
+        let beat_stream = timer_client
+            .request_interval(keepalive_ticks / 2)
+            .await
+            .unwrap();
+        let (mut b_sender, mut b_receiver) =
+            keepalive_channel(b_sender, b_receiver, beat_stream, spawner.clone());
 
         a_sender.send(vec![1, 2, 3]).await.unwrap();
         assert_eq!(b_receiver.next().await.unwrap(), vec![1, 2, 3]);
@@ -14,6 +14,10 @@ extern crate log;
 #[macro_use]
 extern crate common;
 
+mod adaptive;
 mod keepalive;
 
+pub use self::adaptive::{
+    create_adaptive_keepalive, AdaptiveKeepAliveClient, AdaptiveKeepAliveError, KeepAliveBounds,
+};
 pub use self::keepalive::KeepAliveChannel;
@@ -11,12 +11,25 @@
 #[macro_use]
 extern crate log;
 
+mod quic_connector;
+mod quic_listener;
+mod quic_utils;
 mod tcp_connector;
 mod tcp_listener;
 #[cfg(test)]
 mod tests;
-mod types;
+mod transport;
 mod utils;
 
+pub use self::quic_connector::QuicConnector;
+pub use self::quic_listener::QuicListener;
+pub use self::quic_utils::create_quic_runtime;
 pub use self::tcp_connector::TcpConnector;
 pub use self::tcp_listener::TcpListener;
+pub use self::transport::{
+    split_transport, Transport, TransportConnector, QUIC_ADDRESS_PREFIX, TCP_ADDRESS_PREFIX,
+};
+pub use self::utils::bind_tcp_listener;
+
+/// The runtime that drives QUIC connections.
+pub use tokio::runtime::{Handle as QuicRuntimeHandle, Runtime as QuicRuntime};
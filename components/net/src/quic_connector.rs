@@ -0,0 +1,90 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use async_std::net::ToSocketAddrs;
+
+use futures::task::Spawn;
+
+use quinn::{Connection, Endpoint, RecvStream, SendStream};
+use tokio::runtime::Handle;
+
+use common::conn::{BoxFuture, ConnPairVec, FutTransform};
+
+use proto::net::messages::NetAddress;
+
+use crate::quic_utils::{create_client_config, quic_stream_to_conn_pair, QUIC_SERVER_NAME};
+
+/// Connect to remote servers using QUIC.
+/// Every connection uses its own UDP socket.
+#[derive(Debug, Clone)]
+pub struct QuicConnector<S> {
+    max_frame_length: usize,
+    runtime_handle: Handle,
+    spawner: S,
+}
+
+impl<S> QuicConnector<S> {
+    /// `runtime_handle` is a handle to the runtime that drives the QUIC connections
+    /// (See `create_quic_runtime()`)
+    pub fn new(max_frame_length: usize, runtime_handle: Handle, spawner: S) -> Self {
+        QuicConnector {
+            max_frame_length,
+            runtime_handle,
+            spawner,
+        }
+    }
+}
+
+/// Connect to a QUIC server, and open a bidirectional stream.
+async fn quic_connect(socket_addr: SocketAddr) -> Option<(Connection, SendStream, RecvStream)> {
+    let bind_ip = match socket_addr.ip() {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+
+    let mut endpoint_builder = Endpoint::builder();
+    endpoint_builder.default_client_config(create_client_config());
+    let (endpoint, _incoming) = endpoint_builder
+        .bind(&SocketAddr::new(bind_ip, 0))
+        .map_err(|e| warn!("quic_connect(): Failed binding: {:?}", e))
+        .ok()?;
+
+    let new_connection = endpoint
+        .connect(&socket_addr, QUIC_SERVER_NAME)
+        .ok()?
+        .await
+        .ok()?;
+    let connection = new_connection.connection;
+    let (send_stream, recv_stream) = connection.open_bi().await.ok()?;
+    Some((connection, send_stream, recv_stream))
+}
+
+impl<S> FutTransform for QuicConnector<S>
+where
+    S: Spawn + Send,
+{
+    type Input = NetAddress;
+    type Output = Option<ConnPairVec>;
+
+    fn transform(&mut self, net_address: Self::Input) -> BoxFuture<'_, Self::Output> {
+        Box::pin(async move {
+            let socket_addr = net_address.as_str().to_socket_addrs().await.ok()?.next()?;
+
+            // QUIC connections must be created inside the QUIC runtime:
+            let (connection, send_stream, recv_stream) = self
+                .runtime_handle
+                .spawn(quic_connect(socket_addr))
+                .await
+                .ok()??;
+
+            // Note that the remote side only learns about the stream once we send data over it.
+            // This is not a problem, because the connecting side always speaks first.
+            Some(quic_stream_to_conn_pair(
+                connection,
+                send_stream,
+                recv_stream,
+                self.max_frame_length,
+                &mut self.spawner,
+            ))
+        })
+    }
+}
@@ -0,0 +1,109 @@
+use std::net::SocketAddr;
+
+use futures::channel::mpsc;
+use futures::task::{Spawn, SpawnExt};
+use futures::{SinkExt, StreamExt};
+
+use quinn::Endpoint;
+use tokio::runtime::Handle;
+
+use common::conn::{ConnPairVec, Listener};
+
+use crate::quic_utils::{create_server_config, quic_stream_to_conn_pair};
+
+/// Listen for incoming QUIC connections
+pub struct QuicListener<S> {
+    max_frame_length: usize,
+    runtime_handle: Handle,
+    spawner: S,
+}
+
+impl<S> QuicListener<S> {
+    /// `runtime_handle` is a handle to the runtime that drives the QUIC connections
+    /// (See `create_quic_runtime()`)
+    pub fn new(max_frame_length: usize, runtime_handle: Handle, spawner: S) -> Self {
+        QuicListener {
+            max_frame_length,
+            runtime_handle,
+            spawner,
+        }
+    }
+}
+
+impl<S> Listener for QuicListener<S>
+where
+    S: Spawn + Send + Clone + 'static,
+{
+    type Connection = ConnPairVec;
+    type Config = ();
+    type Arg = SocketAddr;
+
+    fn listen(
+        self,
+        socket_addr: Self::Arg,
+    ) -> (mpsc::Sender<Self::Config>, mpsc::Receiver<Self::Connection>) {
+        let (config_sender, _config_sender_receiver) = mpsc::channel(0);
+        let (mut conn_receiver_sender, conn_receiver) = mpsc::channel(0);
+        let (streams_sender, mut incoming_streams) = mpsc::channel(0);
+
+        // Accept connections inside the QUIC runtime:
+        self.runtime_handle.spawn(async move {
+            let server_config = match create_server_config() {
+                Ok(server_config) => server_config,
+                Err(e) => {
+                    warn!("Failed creating QUIC server config: {:?}", e);
+                    return;
+                }
+            };
+            let mut endpoint_builder = Endpoint::builder();
+            endpoint_builder.listen(server_config);
+            let (_endpoint, mut incoming) = match endpoint_builder.bind(&socket_addr) {
+                Ok(endpoint_incoming) => endpoint_incoming,
+                Err(e) => {
+                    warn!("Failed listening on {:?}: {:?}", socket_addr, e);
+                    return;
+                }
+            };
+
+            while let Some(connecting) = incoming.next().await {
+                let mut c_streams_sender = streams_sender.clone();
+                // Handshakes are done concurrently, so that a slow client can not stall
+                // the other clients:
+                tokio::spawn(async move {
+                    let new_connection = match connecting.await {
+                        Ok(new_connection) => new_connection,
+                        Err(_) => return,
+                    };
+                    let connection = new_connection.connection;
+                    let mut bi_streams = new_connection.bi_streams;
+                    // Every connection uses a single bidirectional stream, opened by the client:
+                    if let Some(Ok((send_stream, recv_stream))) = bi_streams.next().await {
+                        let _ = c_streams_sender
+                            .send((connection, send_stream, recv_stream))
+                            .await;
+                    }
+                });
+            }
+        });
+
+        let mut c_spawner = self.spawner.clone();
+        let c_max_frame_length = self.max_frame_length;
+        let _ = self.spawner.spawn(async move {
+            while let Some((connection, send_stream, recv_stream)) = incoming_streams.next().await {
+                let conn_pair = quic_stream_to_conn_pair(
+                    connection,
+                    send_stream,
+                    recv_stream,
+                    c_max_frame_length,
+                    &mut c_spawner,
+                );
+                if let Err(e) = conn_receiver_sender.send(conn_pair).await {
+                    warn!("QuicListener::listen(): Send error: {:?}", e);
+                    return;
+                }
+            }
+        });
+
+        (config_sender, conn_receiver)
+    }
+}
@@ -0,0 +1,113 @@
+use std::io;
+use std::sync::Arc;
+
+use futures::task::Spawn;
+use futures_codec::{FramedRead, FramedWrite, LengthCodec};
+
+use quinn::{
+    Certificate, CertificateChain, ClientConfig, ClientConfigBuilder, Connection, PrivateKey,
+    RecvStream, SendStream, ServerConfig, ServerConfigBuilder, VarInt,
+};
+
+use tokio::runtime::{Builder, Runtime};
+
+use common::conn::ConnPairVec;
+
+use crate::utils::frames_to_conn_pair;
+
+/// The server name used during the TLS handshake.
+/// Offst does not rely on TLS for authentication (Both sides are authenticated by the encrypted
+/// channel that runs on top of the connection), so the same name is used for all servers.
+pub const QUIC_SERVER_NAME: &str = "offst";
+
+/// Create a runtime for QUIC connections.
+/// QUIC endpoints and connections are driven by this runtime, while the connection pairs they
+/// produce may be used from any executor.
+pub fn create_quic_runtime() -> io::Result<Runtime> {
+    Builder::new()
+        .threaded_scheduler()
+        .enable_all()
+        .thread_name("offst-quic")
+        .build()
+}
+
+/// Accept any certificate presented by the server.
+/// The server is authenticated later, by the encrypted channel.
+struct SkipServerVerification;
+
+impl rustls::ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _roots: &rustls::RootCertStore,
+        _presented_certs: &[rustls::Certificate],
+        _dns_name: webpki::DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+        Ok(rustls::ServerCertVerified::assertion())
+    }
+}
+
+pub fn create_client_config() -> ClientConfig {
+    let mut client_config = ClientConfigBuilder::default().build();
+    // We have just created the configuration, so there are no other references to it:
+    let tls_config = Arc::get_mut(&mut client_config.crypto).unwrap();
+    tls_config
+        .dangerous()
+        .set_certificate_verifier(Arc::new(SkipServerVerification));
+    client_config
+}
+
+#[derive(Debug)]
+pub enum QuicServerConfigError {
+    GenerateCertificateError,
+    InvalidCertificate,
+}
+
+/// Create a server configuration with a freshly generated self signed certificate.
+pub fn create_server_config() -> Result<ServerConfig, QuicServerConfigError> {
+    let cert = rcgen::generate_simple_self_signed(vec![QUIC_SERVER_NAME.into()])
+        .map_err(|_| QuicServerConfigError::GenerateCertificateError)?;
+    let cert_der = cert
+        .serialize_der()
+        .map_err(|_| QuicServerConfigError::GenerateCertificateError)?;
+    let key_der = cert.serialize_private_key_der();
+
+    let cert =
+        Certificate::from_der(&cert_der).map_err(|_| QuicServerConfigError::InvalidCertificate)?;
+    let key =
+        PrivateKey::from_der(&key_der).map_err(|_| QuicServerConfigError::InvalidCertificate)?;
+
+    let mut server_config_builder = ServerConfigBuilder::default();
+    server_config_builder
+        .certificate(CertificateChain::from_certs(vec![cert]), key)
+        .map_err(|_| QuicServerConfigError::InvalidCertificate)?;
+    Ok(server_config_builder.build())
+}
+
+/// Closes the QUIC connection when dropped.
+struct ConnectionGuard(Connection);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.close(VarInt::from_u32(0), b"");
+    }
+}
+
+/// Turn a bidirectional QUIC stream into a connection pair.
+/// Every connection uses a single bidirectional stream, carrying length prefixed frames (Just
+/// like TCP connections).
+pub fn quic_stream_to_conn_pair<S>(
+    connection: Connection,
+    send_stream: SendStream,
+    recv_stream: RecvStream,
+    _max_frame_length: usize,
+    spawner: &mut S,
+) -> ConnPairVec
+where
+    S: Spawn + Send,
+{
+    // TODO: Return support for max_frame_length
+    let sender = FramedWrite::new(send_stream, LengthCodec);
+    let receiver = FramedRead::new(recv_stream, LengthCodec);
+    frames_to_conn_pair(sender, receiver, ConnectionGuard(connection), spawner)
+}
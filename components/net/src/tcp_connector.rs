@@ -38,6 +38,7 @@ where
             Some(tcp_stream_to_conn_pair(
                 tcp_stream,
                 self.max_frame_length,
+                (),
                 &mut self.spawner,
             ))
         })
@@ -1,17 +1,60 @@
-use std::net::SocketAddr;
-
-use async_std::net::TcpListener as AsyncStdTcpListener;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
 
 use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
 use futures::{SinkExt, StreamExt};
 
-use crate::utils::tcp_stream_to_conn_pair;
+use proto::net::messages::canonical_ip;
+
+use crate::utils::{bind_tcp_listener, tcp_stream_to_conn_pair};
 use common::conn::{ConnPairVec, Listener};
 
+/// Amount of open connections from every client host
+type ClientConns = Arc<Mutex<HashMap<IpAddr, usize>>>;
+
+/// Releases the quota of a client's connection when dropped
+struct ClientConnGuard {
+    client_conns: ClientConns,
+    ip: IpAddr,
+}
+
+impl Drop for ClientConnGuard {
+    fn drop(&mut self) {
+        let mut client_conns = self.client_conns.lock().unwrap();
+        if let Some(num_conns) = client_conns.get_mut(&self.ip) {
+            *num_conns = num_conns.saturating_sub(1);
+            if *num_conns == 0 {
+                let _ = client_conns.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Take a quota for a new connection from `ip`.
+/// Returns None if the client already has `max_client_conns` open connections.
+fn acquire_client_conn(
+    client_conns: &ClientConns,
+    ip: IpAddr,
+    max_client_conns: usize,
+) -> Option<ClientConnGuard> {
+    let mut locked_client_conns = client_conns.lock().unwrap();
+    let num_conns = locked_client_conns.entry(ip).or_insert(0);
+    if *num_conns >= max_client_conns {
+        return None;
+    }
+    *num_conns += 1;
+    Some(ClientConnGuard {
+        client_conns: client_conns.clone(),
+        ip,
+    })
+}
+
 /// Listen for incoming TCP connections
 pub struct TcpListener<S> {
     max_frame_length: usize,
+    opt_max_client_conns: Option<usize>,
     spawner: S,
 }
 
@@ -19,6 +62,25 @@ impl<S> TcpListener<S> {
     pub fn new(max_frame_length: usize, spawner: S) -> Self {
         TcpListener {
             max_frame_length,
+            opt_max_client_conns: None,
+            spawner,
+        }
+    }
+
+    /// Like `new`, but accept at most `max_client_conns` concurrent connections from every
+    /// client host. Additional connections are closed immediately.
+    ///
+    /// Hosts are compared by their canonical address, so that an IPv4 client connecting to a
+    /// dual-stack listener is counted as the same client, whether it shows up with an IPv4
+    /// address or a v4-mapped IPv6 address.
+    pub fn with_max_client_conns(
+        max_frame_length: usize,
+        max_client_conns: usize,
+        spawner: S,
+    ) -> Self {
+        TcpListener {
+            max_frame_length,
+            opt_max_client_conns: Some(max_client_conns),
             spawner,
         }
     }
@@ -41,19 +103,42 @@ where
 
         let mut c_spawner = self.spawner.clone();
         let c_max_frame_length = self.max_frame_length;
+        let opt_max_client_conns = self.opt_max_client_conns;
         let _ = self.spawner.spawn(async move {
-            let listener = match AsyncStdTcpListener::bind(&socket_addr).await {
+            let listener = match bind_tcp_listener(socket_addr).await {
                 Ok(listener) => listener,
                 Err(e) => {
                     warn!("Failed listening on {:?}: {:?}", socket_addr, e);
                     return;
                 }
             };
+            let client_conns: ClientConns = Arc::new(Mutex::new(HashMap::new()));
             let mut incoming_conns = listener.incoming();
 
             while let Some(Ok(tcp_stream)) = incoming_conns.next().await {
-                let conn_pair =
-                    tcp_stream_to_conn_pair(tcp_stream, c_max_frame_length, &mut c_spawner);
+                let opt_guard = match (opt_max_client_conns, tcp_stream.peer_addr()) {
+                    (Some(max_client_conns), Ok(peer_addr)) => {
+                        let ip = canonical_ip(peer_addr.ip());
+                        match acquire_client_conn(&client_conns, ip, max_client_conns) {
+                            Some(guard) => Some(guard),
+                            None => {
+                                warn!("TcpListener::listen(): Too many connections from {}", ip);
+                                continue;
+                            }
+                        }
+                    }
+                    (Some(_), Err(e)) => {
+                        warn!("TcpListener::listen(): Unknown peer address: {:?}", e);
+                        continue;
+                    }
+                    (None, _) => None,
+                };
+                let conn_pair = tcp_stream_to_conn_pair(
+                    tcp_stream,
+                    c_max_frame_length,
+                    opt_guard,
+                    &mut c_spawner,
+                );
                 if let Err(e) = conn_receiver_sender.send(conn_pair).await {
                     warn!("TcpListener::listen(): Send error: {:?}", e);
                     return;
@@ -64,3 +149,35 @@ where
         (config_sender, conn_receiver)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn test_acquire_client_conn() {
+        let client_conns: ClientConns = Arc::new(Mutex::new(HashMap::new()));
+        let ipv4 = IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4));
+        let ipv4_mapped = IpAddr::V6(Ipv4Addr::new(1, 2, 3, 4).to_ipv6_mapped());
+        let other_ip = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+
+        let guard1 = acquire_client_conn(&client_conns, canonical_ip(ipv4), 2).unwrap();
+        let guard2 = acquire_client_conn(&client_conns, canonical_ip(ipv4_mapped), 2).unwrap();
+        // The same host, through a v4-mapped address, is counted as the same client:
+        assert!(acquire_client_conn(&client_conns, canonical_ip(ipv4_mapped), 2).is_none());
+        assert!(acquire_client_conn(&client_conns, canonical_ip(ipv4), 2).is_none());
+
+        let _guard3 = acquire_client_conn(&client_conns, canonical_ip(other_ip), 2).unwrap();
+
+        drop(guard1);
+        let guard4 = acquire_client_conn(&client_conns, canonical_ip(ipv4), 2).unwrap();
+        drop(guard2);
+        drop(guard4);
+        assert!(!client_conns
+            .lock()
+            .unwrap()
+            .contains_key(&canonical_ip(ipv4)));
+    }
+}
@@ -11,8 +11,12 @@ use common::conn::{ConnPairVec, FutTransform, Listener};
 use proto::net::messages::NetAddress;
 
 // use crate::net_connector::NetConnector;
+use crate::quic_connector::QuicConnector;
+use crate::quic_listener::QuicListener;
+use crate::quic_utils::create_quic_runtime;
 use crate::tcp_connector::TcpConnector;
 use crate::tcp_listener::TcpListener;
+use crate::transport::TransportConnector;
 
 use async_std::net::TcpListener as AsyncStdTcpListener;
 use async_std::task::sleep;
@@ -120,3 +124,58 @@ fn test_net_connector_v4_drop_sender() {
     let thread_pool = ThreadPool::new().unwrap();
     block_on(task_net_connector_v4_drop_sender(thread_pool.clone()));
 }
+
+/// Get an available UDP port we can listen on
+fn get_available_udp_port_v4() -> u16 {
+    let loopback = Ipv4Addr::new(127, 0, 0, 1);
+    let socket_addr = SocketAddr::new(IpAddr::V4(loopback), 0);
+    let socket = std::net::UdpSocket::bind(&socket_addr).unwrap();
+    socket.local_addr().unwrap().port()
+}
+
+#[test]
+fn test_quic_client_server_v4() {
+    let thread_pool = ThreadPool::new().unwrap();
+    let quic_runtime = create_quic_runtime().unwrap();
+
+    let available_port = get_available_udp_port_v4();
+    let loopback = Ipv4Addr::new(127, 0, 0, 1);
+    let socket_addr = SocketAddr::new(IpAddr::V4(loopback), available_port);
+
+    let quic_listener = QuicListener::new(
+        TEST_MAX_FRAME_LEN,
+        quic_runtime.handle().clone(),
+        thread_pool.clone(),
+    );
+    let (_config_sender, mut incoming_connections) = quic_listener.listen(socket_addr);
+
+    // The transport is picked according to the address prefix:
+    let mut connector = TransportConnector::new(
+        TcpConnector::new(TEST_MAX_FRAME_LEN, thread_pool.clone()),
+        QuicConnector::new(
+            TEST_MAX_FRAME_LEN,
+            quic_runtime.handle().clone(),
+            thread_pool.clone(),
+        ),
+    );
+    let net_address = NetAddress::try_from(format!("quic://127.0.0.1:{}", available_port)).unwrap();
+
+    block_on(async move {
+        for _ in 0..5usize {
+            let (mut client_sender, mut client_receiver) = connector
+                .transform(net_address.clone())
+                .await
+                .unwrap()
+                .split();
+
+            // The server learns about the connection once the client sends data:
+            client_sender.send(vec![1, 2, 3]).await.unwrap();
+            let (mut server_sender, mut server_receiver) =
+                incoming_connections.next().await.unwrap().split();
+            assert_eq!(server_receiver.next().await.unwrap(), vec![1, 2, 3]);
+
+            server_sender.send(vec![3, 2, 1]).await.unwrap();
+            assert_eq!(client_receiver.next().await.unwrap(), vec![3, 2, 1]);
+        }
+    });
+}
@@ -0,0 +1,89 @@
+use std::convert::TryFrom;
+
+use common::conn::{BoxFuture, ConnPairVec, FutTransform};
+
+use proto::net::messages::NetAddress;
+
+/// Prefix of addresses of servers that should be reached using QUIC.
+pub const QUIC_ADDRESS_PREFIX: &str = "quic://";
+/// Prefix of addresses of servers that should be reached using TCP.
+/// Addresses without any known prefix are also reached using TCP.
+pub const TCP_ADDRESS_PREFIX: &str = "tcp://";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Tcp,
+    Quic,
+}
+
+/// Find the transport of an address, according to its prefix.
+/// Returns the transport, and the address without the prefix.
+pub fn split_transport(address: &str) -> (Transport, &str) {
+    if address.starts_with(QUIC_ADDRESS_PREFIX) {
+        (Transport::Quic, &address[QUIC_ADDRESS_PREFIX.len()..])
+    } else if address.starts_with(TCP_ADDRESS_PREFIX) {
+        (Transport::Tcp, &address[TCP_ADDRESS_PREFIX.len()..])
+    } else {
+        (Transport::Tcp, address)
+    }
+}
+
+/// Connect to remote servers, picking the transport separately for every address.
+/// This allows, for example, reaching some of the relays of a friend using TCP and others using
+/// QUIC.
+#[derive(Debug, Clone)]
+pub struct TransportConnector<TC, QC> {
+    tcp_connector: TC,
+    quic_connector: QC,
+}
+
+impl<TC, QC> TransportConnector<TC, QC> {
+    pub fn new(tcp_connector: TC, quic_connector: QC) -> Self {
+        TransportConnector {
+            tcp_connector,
+            quic_connector,
+        }
+    }
+}
+
+impl<TC, QC> FutTransform for TransportConnector<TC, QC>
+where
+    TC: FutTransform<Input = NetAddress, Output = Option<ConnPairVec>> + Send,
+    QC: FutTransform<Input = NetAddress, Output = Option<ConnPairVec>> + Send,
+{
+    type Input = NetAddress;
+    type Output = Option<ConnPairVec>;
+
+    fn transform(&mut self, net_address: Self::Input) -> BoxFuture<'_, Self::Output> {
+        Box::pin(async move {
+            let (transport, address) = split_transport(net_address.as_str());
+            // Removing the prefix only makes the address shorter, so this should never fail:
+            let inner_net_address = NetAddress::try_from(address.to_owned()).ok()?;
+            match transport {
+                Transport::Tcp => self.tcp_connector.transform(inner_net_address).await,
+                Transport::Quic => self.quic_connector.transform(inner_net_address).await,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_transport() {
+        assert_eq!(
+            split_transport("127.0.0.1:1337"),
+            (Transport::Tcp, "127.0.0.1:1337")
+        );
+        assert_eq!(
+            split_transport("tcp://127.0.0.1:1337"),
+            (Transport::Tcp, "127.0.0.1:1337")
+        );
+        assert_eq!(
+            split_transport("quic://relay.example.com:1337"),
+            (Transport::Quic, "relay.example.com:1337")
+        );
+    }
+}
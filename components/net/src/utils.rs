@@ -1,30 +1,78 @@
-use bytes::Bytes;
+use std::fmt::Debug;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+use bytes::{Bytes, BytesMut};
 
 use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
-use futures::{future, SinkExt, StreamExt};
+use futures::{future, Sink, SinkExt, Stream, StreamExt};
 use futures_codec::{Framed, LengthCodec};
 
-use async_std::net::TcpStream;
+use async_std::net::{TcpListener as AsyncStdTcpListener, TcpStream};
+
+use net2::TcpBuilder;
 
 use common::conn::ConnPairVec;
 
 // TODO: Maybe all the logic here of ensuring closing is not required after this fix in async-std:
 // https://github.com/async-rs/async-std/issues/599
 // Check if we can simplify logic here.
-pub fn tcp_stream_to_conn_pair<S>(
+/// `guard` is dropped after the connection was closed from our side.
+pub fn tcp_stream_to_conn_pair<G, S>(
     tcp_stream: TcpStream,
     _max_frame_length: usize,
+    guard: G,
     spawner: &mut S,
 ) -> ConnPairVec
 where
+    G: Send + 'static,
     S: Spawn + Send,
 {
     // TODO: Return support for max_frame_length
     let codec = LengthCodec;
     // codec.set_max_frame_length(max_frame_length);
     let (sender, receiver) = Framed::new(tcp_stream, codec).split();
+    frames_to_conn_pair(sender, receiver, guard, spawner)
+}
+
+/// Maximum amount of pending connections waiting to be accepted on a dual-stack listener.
+const LISTEN_BACKLOG: i32 = 0x400;
+
+/// Bind a TCP listener to `socket_addr`.
+///
+/// Listening on the unspecified IPv6 address (`[::]`) binds a dual-stack socket, accepting both
+/// IPv6 and IPv4 connections, regardless of the system's default. IPv4 clients then show up with
+/// v4-mapped IPv6 addresses (See `proto::net::messages::canonical_ip`).
+pub async fn bind_tcp_listener(socket_addr: SocketAddr) -> io::Result<AsyncStdTcpListener> {
+    match socket_addr.ip() {
+        IpAddr::V6(ipv6_addr) if ipv6_addr.is_unspecified() => {
+            let std_listener = TcpBuilder::new_v6()?
+                .only_v6(false)?
+                .reuse_address(true)?
+                .bind(&socket_addr)?
+                .listen(LISTEN_BACKLOG)?;
+            Ok(AsyncStdTcpListener::from(std_listener))
+        }
+        _ => AsyncStdTcpListener::bind(&socket_addr).await,
+    }
+}
 
+/// Turn a sink and a stream of length prefixed frames into a connection pair.
+/// `guard` is dropped after the connection was closed from our side.
+pub fn frames_to_conn_pair<FS, FR, E, G, S>(
+    sender: FS,
+    receiver: FR,
+    guard: G,
+    spawner: &mut S,
+) -> ConnPairVec
+where
+    FS: Sink<Bytes> + Unpin + Send + 'static,
+    FR: Stream<Item = Result<BytesMut, E>> + Unpin + Send + 'static,
+    E: Debug,
+    G: Send + 'static,
+    S: Spawn + Send,
+{
     // Conversion layer between Vec<u8> to Bytes:
     let mut vec_sender =
         sender
@@ -54,6 +102,7 @@ where
         .spawn(async move {
             let _ = vec_sender.send_all(&mut user_sender_receiver.map(Ok)).await;
             drop(receiver_task);
+            drop(guard);
         })
         .unwrap();
 
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use futures::task::{Spawn, SpawnExt};
+use futures::{future, Future, Stream};
+
+use common::conn::{BoxFuture, ConnPairVec, FuncFutTransform};
+
+use proto::app_server::messages::NamedRelayAddress;
+use proto::consts::{MAX_RELAY_LISTENERS, MAX_RELAY_TUNNEL_BUFFERED_BYTES};
+use proto::crypto::PublicKey;
+use proto::net::messages::NetAddress;
+
+use funder::FunderMutation;
+use relay::{relay_server, RelayMetrics, RelayServerError, RelayTimeouts};
+use timer::TimerClient;
+
+use crate::node::NodeError;
+
+/// The name given to the embedded relay in the list of local relays
+pub const EMBEDDED_RELAY_NAME: &str = "embedded";
+
+/// Calculate the mutations that make the list of local relays advertise the embedded relay at
+/// `opt_embedded_relay_address`, or stop advertising it if the embedded relay is disabled.
+///
+/// The embedded relay uses the identity of the node, so it is the only local relay that may have
+/// the local public key.
+pub fn embedded_relay_mutations<'a, I>(
+    relays: I,
+    local_public_key: &PublicKey,
+    opt_embedded_relay_address: &Option<NetAddress>,
+) -> Vec<FunderMutation<NetAddress>>
+where
+    I: IntoIterator<Item = &'a NamedRelayAddress<NetAddress>>,
+{
+    let opt_cur_relay = relays
+        .into_iter()
+        .find(|named_relay_address| &named_relay_address.public_key == local_public_key);
+
+    match (opt_cur_relay, opt_embedded_relay_address) {
+        (Some(cur_relay), Some(address)) if &cur_relay.address == address => Vec::new(),
+        // Adding a relay replaces any relay with the same public key:
+        (_, Some(address)) => vec![FunderMutation::AddRelay(NamedRelayAddress {
+            public_key: local_public_key.clone(),
+            address: address.clone(),
+            name: EMBEDDED_RELAY_NAME.to_owned(),
+        })],
+        (Some(_), None) => vec![FunderMutation::RemoveRelay(local_public_key.clone())],
+        (None, None) => Vec::new(),
+    }
+}
+
+/// Spawn a relay server inside the node, sharing the timer and the spawner of the node.
+/// `incoming_relay_conns` are authenticated connections to the relay.
+///
+/// The embedded relay serves the friends of the node only, so it has no peer relays.
+pub fn spawn_embedded_relay<IRC, S>(
+    incoming_relay_conns: IRC,
+    timer_client: TimerClient,
+    spawner: S,
+) -> Result<impl Future<Output = Result<(), RelayServerError>>, NodeError>
+where
+    IRC: Stream<Item = (PublicKey, ConnPairVec)> + Unpin + Send + 'static,
+    S: Spawn + Clone + Send + 'static,
+{
+    let peer_connector = FuncFutTransform::new(
+        |_: (PublicKey, NetAddress)| -> BoxFuture<'static, Option<ConnPairVec>> {
+            Box::pin(future::ready(None))
+        },
+    );
+
+    spawner
+        .spawn_with_handle(relay_server(
+            incoming_relay_conns,
+            timer_client,
+            RelayTimeouts::default(),
+            MAX_RELAY_LISTENERS,
+            MAX_RELAY_TUNNEL_BUFFERED_BYTES,
+            HashMap::<PublicKey, NetAddress>::new(),
+            peer_connector,
+            RelayMetrics::new(),
+            spawner.clone(),
+        ))
+        .map_err(|_| NodeError::SpawnError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    fn named_relay_address(public_key: &PublicKey, address: &str) -> NamedRelayAddress {
+        NamedRelayAddress {
+            public_key: public_key.clone(),
+            address: NetAddress::try_from(address.to_owned()).unwrap(),
+            name: "relay".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_embedded_relay_mutations() {
+        let local_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let other_public_key = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let address = NetAddress::try_from("node.example.com:1338".to_owned()).unwrap();
+
+        let other_relay = named_relay_address(&other_public_key, "relay.example.com:1338");
+
+        // The embedded relay is added to the local relays:
+        let mutations = embedded_relay_mutations(
+            &[other_relay.clone()],
+            &local_public_key,
+            &Some(address.clone()),
+        );
+        match &mutations[..] {
+            [FunderMutation::AddRelay(named_relay_address)] => {
+                assert_eq!(named_relay_address.public_key, local_public_key);
+                assert_eq!(named_relay_address.address, address);
+            }
+            _ => unreachable!(),
+        }
+
+        // Nothing to do if the embedded relay is already advertised:
+        let embedded_relay = named_relay_address(&local_public_key, "node.example.com:1338");
+        let relays = vec![other_relay.clone(), embedded_relay];
+        assert!(
+            embedded_relay_mutations(&relays, &local_public_key, &Some(address.clone())).is_empty()
+        );
+
+        // The address of the embedded relay has changed:
+        let new_address = NetAddress::try_from("node.example.com:1339".to_owned()).unwrap();
+        let mutations =
+            embedded_relay_mutations(&relays, &local_public_key, &Some(new_address.clone()));
+        match &mutations[..] {
+            [FunderMutation::AddRelay(named_relay_address)] => {
+                assert_eq!(named_relay_address.address, new_address);
+            }
+            _ => unreachable!(),
+        }
+
+        // The embedded relay was disabled:
+        let mutations = embedded_relay_mutations(&relays, &local_public_key, &None);
+        match &mutations[..] {
+            [FunderMutation::RemoveRelay(public_key)] => assert_eq!(public_key, &local_public_key),
+            _ => unreachable!(),
+        }
+        assert!(embedded_relay_mutations(&[other_relay], &local_public_key, &None).is_empty());
+    }
+}
@@ -0,0 +1,73 @@
+use futures::channel::{mpsc, oneshot};
+use futures::SinkExt;
+
+use proto::net::messages::NetAddress;
+
+use crate::types::NodeState;
+
+#[derive(Debug)]
+pub enum NodeRequest {
+    /// Gracefully shut down the node.
+    /// The response is true if all pending operations have settled before the shutdown timeout.
+    Shutdown(oneshot::Sender<bool>),
+    /// Get a copy of the current state of the node (For example, to create a backup)
+    ExportState(oneshot::Sender<NodeState<NetAddress>>),
+}
+
+#[derive(Debug)]
+pub enum NodeHandleError {
+    SendError,
+    ResponseCanceled,
+}
+
+/// A handle to a running node.
+#[derive(Debug, Clone)]
+pub struct NodeHandle {
+    request_sender: mpsc::Sender<NodeRequest>,
+}
+
+impl NodeHandle {
+    pub fn new(request_sender: mpsc::Sender<NodeRequest>) -> Self {
+        NodeHandle { request_sender }
+    }
+
+    /// Gracefully shut down the node:
+    ///
+    /// 1. Stop accepting new apps and new payments, transactions and invoices from the connected
+    ///    apps.
+    /// 2. Wait (At most `NodeConfig::shutdown_ticks` ticks) for pending token channel operations
+    ///    and in-flight transactions to settle.
+    /// 3. Wait for the database to write all pending mutations.
+    /// 4. Close all connections to relays and friends (Through the channeler).
+    ///
+    /// Returns after the node was shut down. Returns true if all pending operations have settled,
+    /// or false if the shutdown timed out.
+    pub async fn shutdown(&mut self) -> Result<bool, NodeHandleError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        self.request_sender
+            .send(NodeRequest::Shutdown(response_sender))
+            .await
+            .map_err(|_| NodeHandleError::SendError)?;
+
+        response_receiver
+            .await
+            .map_err(|_| NodeHandleError::ResponseCanceled)
+    }
+
+    /// Get a copy of the current state of the node: The identity (Public key) of the node, its
+    /// friends, the token channels with the friends and the index servers configuration.
+    ///
+    /// The state contains all the mutations that were written to the database so far. It may be
+    /// stored as a backup, and verified using `verify_node_state` before it is restored.
+    pub async fn export_state(&mut self) -> Result<NodeState<NetAddress>, NodeHandleError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        self.request_sender
+            .send(NodeRequest::ExportState(response_sender))
+            .await
+            .map_err(|_| NodeHandleError::SendError)?;
+
+        response_receiver
+            .await
+            .map_err(|_| NodeHandleError::ResponseCanceled)
+    }
+}
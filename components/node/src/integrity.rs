@@ -0,0 +1,109 @@
+use std::fmt;
+
+use funder::{check_funder_state, VerifyStateError};
+
+use proto::crypto::PublicKey;
+
+use signature::canonical::CanonicalSerialize;
+
+use crate::types::NodeState;
+
+/// A problem found in a node state.
+#[derive(Debug)]
+pub enum IntegrityIssue {
+    /// The state belongs to a node with a different identity
+    IdentityMismatch,
+    /// The funder state is corrupt
+    FunderState(VerifyStateError),
+}
+
+/// All the problems found in a node state.
+#[derive(Debug)]
+pub struct IntegrityReport {
+    pub issues: Vec<IntegrityIssue>,
+}
+
+impl IntegrityReport {
+    /// Is the node state safe to use?
+    pub fn is_intact(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn has_identity_mismatch(&self) -> bool {
+        self.issues.iter().any(|issue| match issue {
+            IntegrityIssue::IdentityMismatch => true,
+            IntegrityIssue::FunderState(_) => false,
+        })
+    }
+}
+
+impl fmt::Display for IntegrityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.issues.is_empty() {
+            return write!(f, "No integrity issues found");
+        }
+        write!(f, "{} integrity issue(s) found:", self.issues.len())?;
+        for issue in &self.issues {
+            write!(f, "\n- {:?}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check the integrity of a node state loaded from the database, before any of the node's
+/// components are spawned. Unlike `verify_node_state`, all the problems are collected, to allow a
+/// detailed report.
+pub fn check_node_integrity<B>(
+    node_state: &NodeState<B>,
+    local_public_key: &PublicKey,
+) -> IntegrityReport
+where
+    B: Clone + CanonicalSerialize,
+{
+    let mut issues = Vec::new();
+    if &node_state.funder_state.local_public_key != local_public_key {
+        issues.push(IntegrityIssue::IdentityMismatch);
+    }
+    issues.extend(
+        check_funder_state(&node_state.funder_state)
+            .into_iter()
+            .map(IntegrityIssue::FunderState),
+    );
+    IntegrityReport { issues }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use funder::FunderMutation;
+    use proto::funder::messages::AddFriend;
+
+    #[test]
+    fn test_check_node_integrity() {
+        let local_pk = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let remote_pk = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let mut node_state = NodeState::<u32>::new(local_pk.clone());
+        node_state
+            .funder_state
+            .mutate(&FunderMutation::AddFriend(AddFriend {
+                friend_public_key: remote_pk.clone(),
+                relays: Vec::new(),
+                name: "remote".into(),
+            }));
+        assert!(check_node_integrity(&node_state, &local_pk).is_intact());
+
+        // Corrupt the friend and use the wrong identity. Both problems are reported:
+        let friend = node_state.funder_state.friends.get_mut(&remote_pk).unwrap();
+        friend.local_public_key = PublicKey::from(&[0xcc; PublicKey::len()]);
+        let report = check_node_integrity(&node_state, &remote_pk);
+        assert_eq!(report.issues.len(), 2);
+        assert!(report.has_identity_mismatch());
+        match &report.issues[1] {
+            IntegrityIssue::FunderState(VerifyStateError::FriendKeysMismatch(public_key)) => {
+                assert_eq!(public_key, &remote_pk)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
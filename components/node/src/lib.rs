@@ -17,9 +17,27 @@ extern crate serde;
 #[macro_use]
 extern crate quickcheck_derive;
 
+mod embedded_relay;
+mod handle;
+mod integrity;
 mod node;
 mod types;
 
+pub use self::embedded_relay::EMBEDDED_RELAY_NAME;
+pub use self::handle::{NodeHandle, NodeHandleError, NodeRequest};
+pub use self::integrity::{check_node_integrity, IntegrityIssue, IntegrityReport};
 pub use self::node::{node, NodeError};
-pub use self::types::{NodeConfig, NodeMutation, NodeState};
-pub use app_server::{ConnPairServer, IncomingAppConnection};
+pub use self::types::{
+    create_node_report, verify_node_state, NodeConfig, NodeMutation, NodeState,
+    VerifyNodeStateError,
+};
+pub use app_server::{server_hello, ConnPairServer, IncomingAppConnection};
+
+/// Mock services with scriptable behaviors, for deterministic tests
+#[cfg(feature = "testing")]
+pub mod testing {
+    pub use common::testing::{MockAction, MockScript};
+    pub use database::testing::{create_mock_database, MockDatabase};
+    pub use identity::testing::{create_mock_identity, MockBackend};
+    pub use timer::testing::{create_mock_timer, MockTimer, MockTimerError};
+}
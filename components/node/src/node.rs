@@ -1,62 +1,85 @@
-use futures::channel::mpsc;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use futures::channel::{mpsc, oneshot};
 use futures::task::{Spawn, SpawnExt};
-use futures::{select, Future, FutureExt, SinkExt, Stream, StreamExt};
+use futures::{future, stream, Future, FutureExt, SinkExt, Stream, StreamExt};
 
 use derive_more::*;
 
-use common::conn::{ConnPairVec, FuncFutTransform, FutTransform};
+use common::conn::{BoxStream, ConnPairVec, FuncFutTransform, FutTransform};
+use common::mutable_state::MutableState;
+use common::select_streams::select_streams;
 
 use crypto::rand::CryptoRandom;
 use proto::crypto::PublicKey;
 
-use database::DatabaseClient;
+use database::{DatabaseClient, DatabaseRequest};
 use identity::IdentityClient;
 use timer::TimerClient;
 
-use app_server::{app_server_loop, AppServerError, IncomingAppConnection};
+use app_server::{
+    app_server_loop, AppServerError, BalanceHistoryConfig, IncomingAppConnection,
+    ReceiptArchiveMutation,
+};
 use channeler::{spawn_channeler, ChannelerError};
 use funder::types::{
     ChannelerConfig, FunderIncomingComm, FunderOutgoingComm, IncomingLivenessMessage,
 };
-use funder::{funder_loop, FunderError, FunderState};
+use funder::{funder_loop, FunderError, FunderMutation, FunderState};
 // use keepalive::KeepAliveChannel;
 // use secure_channel::SecureChannel;
 
 use index_client::{spawn_index_client, IndexClientError};
+use relay::RelayServerError;
 
 use proto::app_server::messages::RelayAddress;
+use proto::consts::SC_COMPACT_WIRE_VERSION;
 use proto::funder::messages::{
-    ChannelerToFunder, FriendMessage, FunderIncomingControl, FunderOutgoingControl,
-    FunderToChanneler,
+    ChannelerToFunder, FriendMessage, FunderControl, FunderIncomingControl, FunderOutgoingControl,
+    FunderToChanneler, MessagePriority, RequestResult, TransactionResult,
 };
-use proto::proto_ser::{ProtoDeserialize, ProtoSerialize};
+use proto::wire::{deserialize_friend_message, serialize_friend_message, WireFormat};
 
 use proto::index_client::messages::{AppServerToIndexClient, IndexClientToAppServer};
 use proto::index_server::messages::IndexServerAddress;
+use proto::keepalive::messages::KeepAliveReport;
 use proto::net::messages::NetAddress;
 use proto::report::convert::funder_report_to_index_client_state;
+use proto::report::messages::FunderReportMutations;
+use proto::secure_channel::messages::SecureChannelReport;
 
+use crate::embedded_relay::{embedded_relay_mutations, spawn_embedded_relay};
+use crate::handle::NodeRequest;
+use crate::integrity::{check_node_integrity, IntegrityReport};
 use crate::types::{create_node_report, NodeConfig, NodeMutation, NodeState};
 
 #[derive(Debug, From)]
 pub enum NodeError {
     RequestPublicKeyError,
+    RequestTimerStreamError,
     DatabaseIdentityMismatch,
+    CorruptState(IntegrityReport),
     SpawnError,
     ChannelerError(ChannelerError),
     FunderError(FunderError),
     IndexClientError(IndexClientError),
     AppServerError(AppServerError),
+    RelayServerError(RelayServerError),
+    DatabaseMutateError,
+    DatabaseFlushError,
 }
 
-fn node_spawn_channeler<C, EKT, S>(
+fn node_spawn_channeler<C, EKT, KR, SR, S>(
     node_config: &NodeConfig,
     local_public_key: PublicKey,
     timer_client: TimerClient,
     connector: C,
     encrypt_keepalive: EKT,
+    keepalive_reports: KR,
+    secure_channel_reports: SR,
     from_funder: mpsc::Receiver<FunderToChanneler<RelayAddress>>,
-    to_funder: mpsc::Sender<ChannelerToFunder>,
+    to_funder: mpsc::Sender<ChannelerToFunder<RelayAddress>>,
     spawner: S,
 ) -> Result<impl Future<Output = Result<(), ChannelerError>>, NodeError>
 where
@@ -70,6 +93,8 @@ where
         > + Clone
         + Send
         + 'static,
+    KR: Stream<Item = (PublicKey, KeepAliveReport)> + Unpin + Send + 'static,
+    SR: Stream<Item = (PublicKey, SecureChannelReport)> + Unpin + Send + 'static,
     S: Spawn + Clone + Send + 'static,
 {
     let enc_relay_connector = FuncFutTransform::new(move |relay_address: RelayAddress| {
@@ -87,9 +112,14 @@ where
             timer_client,
             node_config.backoff_ticks,
             node_config.conn_timeout_ticks,
+            node_config.relay_multiplex,
             node_config.max_concurrent_encrypt,
+            node_config.relay_probe_ticks,
+            node_config.listen_only,
             enc_relay_connector,
             encrypt_keepalive,
+            keepalive_reports,
+            secure_channel_reports,
             from_funder,
             to_funder,
             spawner.clone(),
@@ -97,15 +127,17 @@ where
         .map_err(|_| NodeError::SpawnError)
 }
 
-fn node_spawn_funder<R, S>(
+async fn node_spawn_funder<R, S>(
     node_config: &NodeConfig,
     identity_client: IdentityClient,
+    mut timer_client: TimerClient,
     funder_state: FunderState<NetAddress>,
     mut database_client: DatabaseClient<NodeMutation<NetAddress>>,
-    mut from_channeler: mpsc::Receiver<ChannelerToFunder>,
+    mut from_channeler: mpsc::Receiver<ChannelerToFunder<RelayAddress>>,
     mut to_channeler: mpsc::Sender<FunderToChanneler<RelayAddress>>,
     from_app_server: mpsc::Receiver<FunderIncomingControl<NetAddress>>,
     to_app_server: mpsc::Sender<FunderOutgoingControl<NetAddress>>,
+    mut node_mutations_sender: mpsc::Sender<Vec<NodeMutation<NetAddress>>>,
     rng: R,
     spawner: S,
 ) -> Result<impl Future<Output = Result<(), FunderError>>, NodeError>
@@ -119,16 +151,25 @@ where
 
     let database_adapter_fut = async move {
         while let Some(request) = request_receiver.next().await {
+            let request = match request {
+                DatabaseRequest::Mutate(mutate_request) => mutate_request,
+                // Subscriptions are only served by the node's database client:
+                DatabaseRequest::Subscribe(_) => continue,
+            };
             let mutations = request
                 .mutations
                 .into_iter()
                 .map(NodeMutation::Funder)
                 .collect::<Vec<_>>();
 
-            if let Err(e) = database_client.mutate(mutations).await {
+            if let Err(e) = database_client.mutate(mutations.clone()).await {
                 error!("error in funder database adapter: {:?}", e);
                 return;
             }
+            // Let the node follow its state:
+            if node_mutations_sender.send(mutations).await.is_err() {
+                return;
+            }
             if let Err(e) = request.response_sender.send(()) {
                 error!("error in funder database adapter: {:?}", e);
                 return;
@@ -139,6 +180,11 @@ where
         .spawn(database_adapter_fut)
         .map_err(|_| NodeError::SpawnError)?;
 
+    // Friends whose current connection supports the compact wire format.
+    // Messages to other friends are sent as capnp messages:
+    let compact_friends: Arc<Mutex<HashSet<PublicKey>>> = Arc::new(Mutex::new(HashSet::new()));
+    let c_compact_friends = compact_friends.clone();
+
     // Channeler to funder adapter:
     let (mut incoming_comm_sender, incoming_comm) = mpsc::channel(0);
     let channeler_to_funder_adapter = async move {
@@ -147,11 +193,65 @@ where
                 ChannelerToFunder::Online(public_key) => Some(FunderIncomingComm::Liveness(
                     IncomingLivenessMessage::Online(public_key),
                 )),
-                ChannelerToFunder::Offline(public_key) => Some(FunderIncomingComm::Liveness(
-                    IncomingLivenessMessage::Offline(public_key),
+                ChannelerToFunder::Offline(public_key) => {
+                    c_compact_friends.lock().unwrap().remove(&public_key);
+                    Some(FunderIncomingComm::Liveness(
+                        IncomingLivenessMessage::Offline(public_key),
+                    ))
+                }
+                ChannelerToFunder::SecureChannelReport((
+                    public_key,
+                    SecureChannelReport::Established(version),
+                )) => {
+                    // The wire format was negotiated in the secure channel handshake:
+                    let mut compact_friends = c_compact_friends.lock().unwrap();
+                    if version >= SC_COMPACT_WIRE_VERSION {
+                        compact_friends.insert(public_key);
+                    } else {
+                        compact_friends.remove(&public_key);
+                    }
+                    None
+                }
+                ChannelerToFunder::KeepAliveReport((public_key, keepalive_report)) => Some(
+                    FunderIncomingComm::Liveness(IncomingLivenessMessage::MissedBeats((
+                        public_key,
+                        keepalive_report.missed_beats,
+                    ))),
+                ),
+                ChannelerToFunder::SecureChannelReport((public_key, secure_channel_report)) => {
+                    Some(FunderIncomingComm::Liveness(
+                        IncomingLivenessMessage::SecureChannelReport((
+                            public_key,
+                            secure_channel_report,
+                        )),
+                    ))
+                }
+                ChannelerToFunder::Bandwidth((public_key, bandwidth)) => {
+                    Some(FunderIncomingComm::Liveness(
+                        IncomingLivenessMessage::Bandwidth((public_key, bandwidth)),
+                    ))
+                }
+                ChannelerToFunder::RelayLatency((relay_address, opt_latency_ms)) => Some(
+                    FunderIncomingComm::Liveness(IncomingLivenessMessage::RelayLatency((
+                        relay_address.public_key,
+                        opt_latency_ms,
+                    ))),
+                ),
+                ChannelerToFunder::QueueDepth((public_key, queue_depth)) => {
+                    Some(FunderIncomingComm::Liveness(
+                        IncomingLivenessMessage::QueueDepth((public_key, queue_depth)),
+                    ))
+                }
+                ChannelerToFunder::MessageDelivery((public_key, message_id, delivery)) => Some(
+                    FunderIncomingComm::Liveness(IncomingLivenessMessage::MessageDelivery((
+                        public_key, message_id, delivery,
+                    ))),
+                ),
+                ChannelerToFunder::ListenOnly => Some(FunderIncomingComm::Liveness(
+                    IncomingLivenessMessage::ListenOnly,
                 )),
                 ChannelerToFunder::Message((public_key, data)) => {
-                    if let Ok(friend_message) = FriendMessage::proto_deserialize(&data[..]) {
+                    if let Ok(friend_message) = deserialize_friend_message(&data[..]) {
                         Some(FunderIncomingComm::Friend((public_key, friend_message)))
                     } else {
                         // We discard the message if we can't deserialize it:
@@ -188,10 +288,23 @@ where
                         FunderToChanneler::RemoveFriend(friend_public_key)
                     }
                 },
-                FunderOutgoingComm::FriendMessage((public_key, friend_message)) => {
-                    // let data = serialize_friend_message(&friend_message);
-                    let data = friend_message.proto_serialize();
-                    FunderToChanneler::Message((public_key, data))
+                FunderOutgoingComm::FriendMessage((public_key, message_id, friend_message)) => {
+                    // A friend waiting for the token should not wait behind other messages:
+                    let priority = match &friend_message {
+                        FriendMessage::MoveTokenRequest(move_token_request)
+                            if move_token_request.token_wanted =>
+                        {
+                            MessagePriority::TokenWanted
+                        }
+                        _ => MessagePriority::Normal,
+                    };
+                    let wire_format = if compact_friends.lock().unwrap().contains(&public_key) {
+                        WireFormat::Compact
+                    } else {
+                        WireFormat::Capnp
+                    };
+                    let data = serialize_friend_message(&friend_message, wire_format);
+                    FunderToChanneler::Message((public_key, message_id, data, priority))
                 }
             };
             if to_channeler.send(to_channeler_message).await.is_err() {
@@ -204,16 +317,27 @@ where
         .spawn(funder_to_channeler_adapter)
         .map_err(|_| NodeError::SpawnError)?;
 
+    let timer_stream = timer_client
+        .request_timer_stream()
+        .await
+        .map_err(|_| NodeError::RequestTimerStreamError)?;
+
     let funder_fut = funder_loop(
         identity_client,
         rng,
         from_app_server,
         incoming_comm,
+        timer_stream,
         to_app_server,
         outgoing_comm_sender,
         node_config.max_node_relays,
         node_config.max_operations_in_batch,
         node_config.max_pending_user_requests,
+        node_config.max_route_len,
+        node_config.max_transaction_retries,
+        node_config.request_expiry_ticks,
+        node_config.min_friend_uptime_percent,
+        node_config.opt_auto_reset_tolerance,
         funder_state,
         funder_db_client,
     );
@@ -230,6 +354,7 @@ async fn node_spawn_index_client<C, R, S>(
     timer_client: TimerClient,
     node_state: &NodeState<NetAddress>,
     mut database_client: DatabaseClient<NodeMutation<NetAddress>>,
+    mut node_mutations_sender: mpsc::Sender<Vec<NodeMutation<NetAddress>>>,
     from_app_server: mpsc::Receiver<AppServerToIndexClient<NetAddress>>,
     to_app_server: mpsc::Sender<IndexClientToAppServer<NetAddress>>,
     connector: C,
@@ -252,16 +377,25 @@ where
 
     let database_adapter_fut = async move {
         while let Some(request) = request_receiver.next().await {
+            let request = match request {
+                DatabaseRequest::Mutate(mutate_request) => mutate_request,
+                // Subscriptions are only served by the node's database client:
+                DatabaseRequest::Subscribe(_) => continue,
+            };
             let mutations = request
                 .mutations
                 .into_iter()
                 .map(NodeMutation::IndexClient)
                 .collect::<Vec<_>>();
 
-            if let Err(e) = database_client.mutate(mutations).await {
+            if let Err(e) = database_client.mutate(mutations.clone()).await {
                 error!("error in index_client database adapter: {:?}", e);
                 return;
             }
+            // Let the node follow its state:
+            if node_mutations_sender.send(mutations).await.is_err() {
+                return;
+            }
             if let Err(e) = request.response_sender.send(()) {
                 error!("error in index_client database adapter: {:?}", e);
                 return;
@@ -281,6 +415,7 @@ where
         rng.clone(),
         timer_client.clone(),
         node_config.ticks_to_rekey,
+        node_config.ticks_to_resume,
         spawner.clone(),
     );
 
@@ -320,8 +455,13 @@ where
         from_app_server,
         to_app_server,
         node_config.max_open_index_client_requests,
+        node_config.route_cache_ticks,
+        node_config.route_rotation,
+        node_config.capacity_smoothing,
         node_config.keepalive_ticks,
         node_config.backoff_ticks,
+        node_config.max_index_sessions,
+        node_config.opt_index_directory_key.clone(),
         index_connector,
         rng,
         spawner.clone(),
@@ -330,17 +470,226 @@ where
     .map_err(|_| NodeError::SpawnError)
 }
 
+/// Create a database client for the app server. Mutations of the receipt archive are wrapped
+/// as node mutations, and sent to the node's database.
+fn node_app_server_db_client<S>(
+    mut database_client: DatabaseClient<NodeMutation<NetAddress>>,
+    mut node_mutations_sender: mpsc::Sender<Vec<NodeMutation<NetAddress>>>,
+    spawner: &S,
+) -> Result<DatabaseClient<ReceiptArchiveMutation>, NodeError>
+where
+    S: Spawn,
+{
+    let (request_sender, mut request_receiver) = mpsc::channel(0);
+    let app_server_db_client = DatabaseClient::new(request_sender);
+
+    let database_adapter_fut = async move {
+        while let Some(request) = request_receiver.next().await {
+            let request = match request {
+                DatabaseRequest::Mutate(mutate_request) => mutate_request,
+                // Subscriptions are only served by the node's database client:
+                DatabaseRequest::Subscribe(_) => continue,
+            };
+            let mutations = request
+                .mutations
+                .into_iter()
+                .map(NodeMutation::ReceiptArchive)
+                .collect::<Vec<_>>();
+
+            if let Err(e) = database_client.mutate(mutations.clone()).await {
+                error!("error in app_server database adapter: {:?}", e);
+                return;
+            }
+            // Let the node follow its state:
+            if node_mutations_sender.send(mutations).await.is_err() {
+                return;
+            }
+            if let Err(e) = request.response_sender.send(()) {
+                error!("error in app_server database adapter: {:?}", e);
+                return;
+            }
+        }
+    };
+    spawner
+        .spawn(database_adapter_fut)
+        .map_err(|_| NodeError::SpawnError)?;
+
+    Ok(app_server_db_client)
+}
+
+#[derive(Debug)]
+enum GateEvent<T> {
+    Item(T),
+    ItemsDone,
+    Closed,
+}
+
+/// Forward incoming app connections, until a shutdown begins (`gate_closed` resolves).
+/// After that, new app connections are refused. The returned stream stays open, so that the app
+/// server keeps serving the apps that are already connected.
+fn node_gate_incoming_apps<IA, S>(
+    incoming_apps: IA,
+    gate_closed: oneshot::Receiver<()>,
+    spawner: &S,
+) -> Result<mpsc::Receiver<IncomingAppConnection<NetAddress>>, NodeError>
+where
+    IA: Stream<Item = IncomingAppConnection<NetAddress>> + Unpin + Send + 'static,
+    S: Spawn,
+{
+    let (mut apps_sender, apps_receiver) = mpsc::channel(0);
+
+    let incoming_apps = incoming_apps
+        .map(GateEvent::Item)
+        .chain(stream::once(future::ready(GateEvent::ItemsDone)));
+    let gate_closed = stream::once(gate_closed).map(|_| GateEvent::Closed);
+    let mut events = select_streams![incoming_apps, gate_closed];
+
+    let gate_fut = async move {
+        let mut is_open = true;
+        while let Some(event) = events.next().await {
+            match event {
+                GateEvent::Item(incoming_app) => {
+                    if !is_open {
+                        warn!("node_gate_incoming_apps(): Shutting down. Refusing app");
+                        continue;
+                    }
+                    if apps_sender.send(incoming_app).await.is_err() {
+                        return;
+                    }
+                }
+                GateEvent::ItemsDone => return,
+                GateEvent::Closed => is_open = false,
+            }
+        }
+    };
+
+    spawner.spawn(gate_fut).map_err(|_| NodeError::SpawnError)?;
+    Ok(apps_receiver)
+}
+
+/// Forward app requests to the funder.
+/// After a shutdown begins (`gate_closed` resolves), requests that start new work (New payments,
+/// transactions and invoices) are refused. Requests that help pending work to settle (For example:
+/// closing payments or committing invoices) are still forwarded.
+fn node_gate_funder_control<S>(
+    from_app_server: mpsc::Receiver<FunderIncomingControl<NetAddress>>,
+    mut to_funder: mpsc::Sender<FunderIncomingControl<NetAddress>>,
+    mut to_app_server: mpsc::Sender<FunderOutgoingControl<NetAddress>>,
+    gate_closed: oneshot::Receiver<()>,
+    spawner: &S,
+) -> Result<(), NodeError>
+where
+    S: Spawn,
+{
+    let from_app_server = from_app_server
+        .map(GateEvent::Item)
+        .chain(stream::once(future::ready(GateEvent::ItemsDone)));
+    let gate_closed = stream::once(gate_closed).map(|_| GateEvent::Closed);
+    let mut events = select_streams![from_app_server, gate_closed];
+
+    let gate_fut = async move {
+        let mut is_open = true;
+        while let Some(event) = events.next().await {
+            let funder_incoming_control = match event {
+                GateEvent::Item(funder_incoming_control) => funder_incoming_control,
+                GateEvent::ItemsDone => return,
+                GateEvent::Closed => {
+                    is_open = false;
+                    continue;
+                }
+            };
+
+            let is_new_work = match &funder_incoming_control.funder_control {
+                FunderControl::CreatePayment(_)
+                | FunderControl::CreateTransaction(_)
+                | FunderControl::CreateExchangeTransaction(_)
+                | FunderControl::Rebalance(_)
+                | FunderControl::AddInvoice(_) => true,
+                _ => false,
+            };
+
+            if is_open || !is_new_work {
+                if to_funder.send(funder_incoming_control).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+
+            warn!("node_gate_funder_control(): Shutting down. Refusing app request");
+            // Respond the way the funder responds to a failed request:
+            let opt_request_id = match &funder_incoming_control.funder_control {
+                FunderControl::CreateTransaction(create_transaction) => {
+                    Some(create_transaction.request_id.clone())
+                }
+                FunderControl::CreateExchangeTransaction(create_exchange_transaction) => {
+                    Some(create_exchange_transaction.request_id.clone())
+                }
+                FunderControl::Rebalance(rebalance) => Some(rebalance.request_id.clone()),
+                _ => None,
+            };
+            if let Some(request_id) = opt_request_id {
+                let transaction_result = TransactionResult {
+                    request_id,
+                    result: RequestResult::Failure,
+                };
+                if to_app_server
+                    .send(FunderOutgoingControl::TransactionResult(transaction_result))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            let report_mutations = FunderReportMutations {
+                opt_app_request_id: Some(funder_incoming_control.app_request_id),
+                mutations: Vec::new(),
+            };
+            if to_app_server
+                .send(FunderOutgoingControl::ReportMutations(report_mutations))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+    };
+
+    spawner.spawn(gate_fut).map_err(|_| NodeError::SpawnError)
+}
+
+#[derive(Debug)]
+enum NodeEvent {
+    ComponentDone(Result<(), NodeError>),
+    Request(NodeRequest),
+    NodeMutations(Vec<NodeMutation<NetAddress>>),
+    TimerTick,
+}
+
+/// A shutdown in progress
+struct NodeShutdown {
+    response_senders: Vec<oneshot::Sender<bool>>,
+    ticks_left: usize,
+}
+
 // TODO: Possibly rename this function?
-pub async fn node<C, EKT, IA, R, S>(
+pub async fn node<C, EKT, KR, SR, IA, IRC, NR, R, S>(
     node_config: NodeConfig,
     identity_client: IdentityClient,
     timer_client: TimerClient,
-    node_state: NodeState<NetAddress>,
-    database_client: DatabaseClient<NodeMutation<NetAddress>>,
+    mut node_state: NodeState<NetAddress>,
+    mut database_client: DatabaseClient<NodeMutation<NetAddress>>,
     connector: C,
     // encrypt_keepalive is used for encryption of the relayed communication between two nodes.
     encrypt_keepalive: EKT,
+    // Keepalive reports of the connections created by encrypt_keepalive:
+    keepalive_reports: KR,
+    // Secure channel reports of the connections created by encrypt_keepalive:
+    secure_channel_reports: SR,
     incoming_apps: IA,
+    // Authenticated connections to the embedded relay. Ignored if the embedded relay is disabled:
+    incoming_relay_conns: IRC,
+    // Requests from a `NodeHandle`:
+    incoming_requests: NR,
     rng: R,
     spawner: S,
 ) -> Result<(), NodeError>
@@ -355,7 +704,11 @@ where
         > + Clone
         + Send
         + 'static,
+    KR: Stream<Item = (PublicKey, KeepAliveReport)> + Unpin + Send + 'static,
+    SR: Stream<Item = (PublicKey, SecureChannelReport)> + Unpin + Send + 'static,
     IA: Stream<Item = IncomingAppConnection<NetAddress>> + Unpin + Send + 'static,
+    IRC: Stream<Item = (PublicKey, ConnPairVec)> + Unpin + Send + 'static,
+    NR: Stream<Item = NodeRequest> + Unpin + Send + 'static,
     R: CryptoRandom + Clone + 'static,
     S: Spawn + Clone + Send + 'static,
 {
@@ -365,14 +718,77 @@ where
         .await
         .map_err(|_| NodeError::RequestPublicKeyError)?;
 
-    // Make sure that the local public key in the database
-    // matches the local public key from the provided identity file:
-    if node_state.funder_state.local_public_key != local_public_key {
-        return Err(NodeError::DatabaseIdentityMismatch);
+    // If we have announced a key rotation and were started with the new identity, we can start
+    // using the new key:
+    let funder_state = &node_state.funder_state;
+    if funder_state.local_public_key != local_public_key
+        && funder_state
+            .opt_key_rotation
+            .as_ref()
+            .map(|key_rotation| &key_rotation.new_public_key)
+            == Some(&local_public_key)
+    {
+        let node_mutation = NodeMutation::Funder(FunderMutation::ApplyKeyRotation);
+        database_client
+            .mutate(vec![node_mutation.clone()])
+            .await
+            .map_err(|_| NodeError::DatabaseMutateError)?;
+        node_state.mutate(&node_mutation).unwrap();
+    }
+
+    // Make sure that the loaded state is intact, before we start talking to our friends:
+    let integrity_report = check_node_integrity(&node_state, &local_public_key);
+    if !integrity_report.is_intact() {
+        // The local public key in the database must match the local public key from the
+        // provided identity file:
+        if integrity_report.has_identity_mismatch() {
+            return Err(NodeError::DatabaseIdentityMismatch);
+        }
+        error!(
+            "node(): Refusing to start with a corrupt state loaded from database: {}",
+            integrity_report
+        );
+        return Err(NodeError::CorruptState(integrity_report));
+    }
+
+    // Advertise the embedded relay (Or stop advertising it) as one of our relays. The funder
+    // will then listen on it, and let our friends know about it:
+    let node_mutations = embedded_relay_mutations(
+        &node_state.funder_state.relays,
+        &local_public_key,
+        &node_config.opt_embedded_relay_address,
+    )
+    .into_iter()
+    .map(NodeMutation::Funder)
+    .collect::<Vec<_>>();
+    if !node_mutations.is_empty() {
+        database_client
+            .mutate(node_mutations.clone())
+            .await
+            .map_err(|_| NodeError::DatabaseMutateError)?;
+        for node_mutation in &node_mutations {
+            node_state.mutate(node_mutation).unwrap();
+        }
     }
 
     let initial_node_report = create_node_report(&node_state);
 
+    // We follow the node state, to know when pending operations have settled during a shutdown,
+    // and to export the state on request:
+    let mut followed_state = node_state.clone();
+    let (node_mutations_sender, node_mutations_receiver) = mpsc::channel(node_config.channel_len);
+
+    let timer_stream = timer_client
+        .clone()
+        .request_timer_stream()
+        .await
+        .map_err(|_| NodeError::RequestTimerStreamError)?;
+
+    // Closed when a shutdown begins:
+    let (apps_gate_closer, apps_gate_closed) = oneshot::channel::<()>();
+    let (control_gate_closer, control_gate_closed) = oneshot::channel::<()>();
+    let mut opt_gate_closers = Some((apps_gate_closer, control_gate_closer));
+
     // Channeler <--> Funder
     let (channeler_to_funder_sender, channeler_to_funder_receiver) =
         mpsc::channel(node_config.channel_len);
@@ -385,6 +801,8 @@ where
         timer_client.clone(),
         connector.clone(),
         encrypt_keepalive,
+        keepalive_reports,
+        secure_channel_reports,
         funder_to_channeler_receiver,
         channeler_to_funder_sender,
         spawner.clone(),
@@ -393,21 +811,33 @@ where
     // AppServer <--> Funder
     let (app_server_to_funder_sender, app_server_to_funder_receiver) =
         mpsc::channel(node_config.channel_len);
+    let (gate_to_funder_sender, gate_to_funder_receiver) = mpsc::channel(node_config.channel_len);
     let (funder_to_app_server_sender, funder_to_app_server_receiver) =
         mpsc::channel(node_config.channel_len);
 
+    node_gate_funder_control(
+        app_server_to_funder_receiver,
+        gate_to_funder_sender,
+        funder_to_app_server_sender.clone(),
+        control_gate_closed,
+        &spawner,
+    )?;
+
     let funder_handle = node_spawn_funder(
         &node_config,
         identity_client.clone(),
+        timer_client.clone(),
         node_state.funder_state.clone(),
         database_client.clone(),
         channeler_to_funder_receiver,
         funder_to_channeler_sender,
-        app_server_to_funder_receiver,
+        gate_to_funder_receiver,
         funder_to_app_server_sender,
+        node_mutations_sender.clone(),
         rng.clone(),
         spawner.clone(),
-    )?;
+    )
+    .await?;
 
     // AppServer <--> IndexClient
     let (app_server_to_index_client_sender, app_server_to_index_client_receiver) =
@@ -415,6 +845,23 @@ where
     let (index_client_to_app_server_sender, index_client_to_app_server_receiver) =
         mpsc::channel(node_config.channel_len);
 
+    let incoming_apps = node_gate_incoming_apps(incoming_apps, apps_gate_closed, &spawner)?;
+
+    let balance_history_config = BalanceHistoryConfig {
+        snapshot_ticks: node_config.balance_snapshot_ticks,
+        max_snapshots: node_config.max_balance_snapshots,
+    };
+    let app_server_timer_stream = timer_client
+        .clone()
+        .request_timer_stream()
+        .await
+        .map_err(|_| NodeError::RequestTimerStreamError)?;
+    let app_server_db_client = node_app_server_db_client(
+        database_client.clone(),
+        node_mutations_sender.clone(),
+        &spawner,
+    )?;
+
     let app_server_fut = app_server_loop(
         funder_to_app_server_receiver,
         app_server_to_funder_sender,
@@ -422,6 +869,10 @@ where
         app_server_to_index_client_sender,
         incoming_apps,
         initial_node_report.clone(),
+        balance_history_config,
+        node_state.receipt_archive.clone(),
+        app_server_db_client,
+        app_server_timer_stream,
         spawner.clone(),
     );
 
@@ -429,13 +880,28 @@ where
         .spawn_with_handle(app_server_fut)
         .map_err(|_| NodeError::SpawnError)?;
 
+    // The embedded relay lets our friends reach us (And each other) through our node:
+    let embedded_relay_done = match &node_config.opt_embedded_relay_address {
+        Some(_) => {
+            let embedded_relay_handle =
+                spawn_embedded_relay(incoming_relay_conns, timer_client.clone(), spawner.clone())?;
+            stream::once(
+                embedded_relay_handle
+                    .map(|res| NodeEvent::ComponentDone(res.map_err(NodeError::from))),
+            )
+            .boxed()
+        }
+        None => stream::pending().boxed(),
+    };
+
     let index_client_handle = node_spawn_index_client(
         &node_config,
         local_public_key,
         identity_client,
         timer_client,
         &node_state,
-        database_client,
+        database_client.clone(),
+        node_mutations_sender,
         app_server_to_index_client_receiver,
         index_client_to_app_server_sender,
         connector,
@@ -444,12 +910,96 @@ where
     )
     .await?;
 
-    // Wait for death of any component
-    select! {
-        res = channeler_handle.fuse() => res?,
-        res = funder_handle.fuse() => res?,
-        res = app_server_handle.fuse() => res?,
-        res = index_client_handle.fuse() => res?,
+    let channeler_done = stream::once(
+        channeler_handle.map(|res| NodeEvent::ComponentDone(res.map_err(NodeError::from))),
+    );
+    let funder_done = stream::once(
+        funder_handle.map(|res| NodeEvent::ComponentDone(res.map_err(NodeError::from))),
+    );
+    let app_server_done = stream::once(
+        app_server_handle.map(|res| NodeEvent::ComponentDone(res.map_err(NodeError::from))),
+    );
+    let index_client_done = stream::once(
+        index_client_handle.map(|res| NodeEvent::ComponentDone(res.map_err(NodeError::from))),
+    );
+    let incoming_requests = incoming_requests.map(NodeEvent::Request);
+    let node_mutations_receiver = node_mutations_receiver.map(NodeEvent::NodeMutations);
+    let timer_stream = timer_stream.map(|_| NodeEvent::TimerTick);
+
+    let mut events = select_streams![
+        channeler_done,
+        funder_done,
+        app_server_done,
+        index_client_done,
+        embedded_relay_done,
+        incoming_requests,
+        node_mutations_receiver,
+        timer_stream
+    ];
+
+    let mut opt_shutdown: Option<NodeShutdown> = None;
+
+    // Wait for death of any component, or for a shutdown to complete:
+    let is_settled = loop {
+        match events.next().await {
+            // The death of any component is the death of the node:
+            Some(NodeEvent::ComponentDone(res)) => return res,
+            None => return Ok(()),
+            Some(NodeEvent::Request(NodeRequest::Shutdown(response_sender))) => {
+                if let Some((apps_gate_closer, control_gate_closer)) = opt_gate_closers.take() {
+                    let _ = apps_gate_closer.send(());
+                    let _ = control_gate_closer.send(());
+                }
+                opt_shutdown
+                    .get_or_insert_with(|| NodeShutdown {
+                        response_senders: Vec::new(),
+                        ticks_left: node_config.shutdown_ticks,
+                    })
+                    .response_senders
+                    .push(response_sender);
+            }
+            Some(NodeEvent::Request(NodeRequest::ExportState(response_sender))) => {
+                let _ = response_sender.send(followed_state.clone());
+            }
+            Some(NodeEvent::NodeMutations(node_mutations)) => {
+                for node_mutation in &node_mutations {
+                    if let Err(e) = followed_state.mutate(node_mutation) {
+                        error!("node(): Failed to follow node mutation: {:?}", e);
+                    }
+                }
+            }
+            Some(NodeEvent::TimerTick) => {
+                if let Some(shutdown) = &mut opt_shutdown {
+                    shutdown.ticks_left = shutdown.ticks_left.saturating_sub(1);
+                }
+            }
+        }
+
+        if let Some(shutdown) = &opt_shutdown {
+            let is_settled = followed_state.funder_state.is_settled();
+            if is_settled || shutdown.ticks_left == 0 {
+                break is_settled;
+            }
+        }
+    };
+
+    if !is_settled {
+        warn!("node(): Shutting down before all pending operations have settled");
+    }
+
+    // Make sure that all the mutations sent so far were written to the database:
+    database_client
+        .mutate(Vec::new())
+        .await
+        .map_err(|_| NodeError::DatabaseFlushError)?;
+
+    // Stop all components. This also closes all the connections of the channeler:
+    drop(events);
+
+    if let Some(shutdown) = opt_shutdown {
+        for response_sender in shutdown.response_senders {
+            let _ = response_sender.send(is_settled);
+        }
     }
     Ok(())
 }
@@ -1,12 +1,14 @@
 use common::mutable_state::MutableState;
 
+use app_server::{ReceiptArchive, ReceiptArchiveMutation};
 use funder::report::create_initial_report;
-use funder::{FunderMutation, FunderState};
+use funder::{verify_funder_state, FunderMutation, FunderState, VerifyStateError};
 use index_client::{IndexClientConfig, IndexClientConfigMutation};
 
 use proto::app_server::messages::NodeReport;
 use proto::crypto::PublicKey;
 use proto::index_client::messages::IndexClientReport;
+use proto::net::messages::NetAddress;
 
 use signature::canonical::CanonicalSerialize;
 
@@ -15,12 +17,16 @@ use signature::canonical::CanonicalSerialize;
 pub enum NodeMutation<B: Clone> {
     Funder(FunderMutation<B>),
     IndexClient(IndexClientConfigMutation<B>),
+    ReceiptArchive(ReceiptArchiveMutation),
 }
 
 #[derive(Arbitrary, Debug, Clone, Serialize, Deserialize)]
 pub struct NodeState<B: Clone> {
     pub funder_state: FunderState<B>,
     pub index_client_config: IndexClientConfig<B>,
+    /// Receipts obtained and invoices issued by the node
+    #[serde(default)]
+    pub receipt_archive: ReceiptArchive,
 }
 
 impl<B> NodeState<B>
@@ -31,10 +37,34 @@ where
         NodeState {
             funder_state: FunderState::new(local_public_key, Vec::new()),
             index_client_config: IndexClientConfig::new(),
+            receipt_archive: ReceiptArchive::new(),
         }
     }
 }
 
+#[derive(Debug)]
+pub enum VerifyNodeStateError {
+    /// The state belongs to a node with a different identity
+    IdentityMismatch,
+    InvalidFunderState(VerifyStateError),
+}
+
+/// Make sure that a node state may be used by the node with the identity `local_public_key`.
+/// Used before accepting a state from the database or from a backup. Verifies the signatures and
+/// the consistency of all the token channels.
+pub fn verify_node_state<B>(
+    node_state: &NodeState<B>,
+    local_public_key: &PublicKey,
+) -> Result<(), VerifyNodeStateError>
+where
+    B: Clone + CanonicalSerialize,
+{
+    if &node_state.funder_state.local_public_key != local_public_key {
+        return Err(VerifyNodeStateError::IdentityMismatch);
+    }
+    verify_funder_state(&node_state.funder_state).map_err(VerifyNodeStateError::InvalidFunderState)
+}
+
 #[derive(Debug)]
 pub struct NodeMutateError;
 
@@ -55,6 +85,10 @@ where
                 .index_client_config
                 .mutate(index_client_mutation)
                 .map_err(|_| NodeMutateError),
+            NodeMutation::ReceiptArchive(receipt_archive_mutation) => {
+                let _ = self.receipt_archive.mutate(receipt_archive_mutation);
+                Ok(())
+            }
         }
     }
 }
@@ -68,6 +102,8 @@ where
         index_servers: index_client_config.index_servers.clone(),
         // Initially we are not connected to a server:
         opt_connected_server: None,
+        // Received friend proposals are not persisted:
+        friend_proposals: Vec::new(),
     }
 }
 
@@ -84,6 +120,10 @@ where
 
 // TODO: Possibly separate NodeConfig into parts that are protocol related, and parts that are
 // implementation related?
+/// Node configuration.
+/// Some of the parameters (`max_operations_in_batch`, `max_pending_user_requests` and the index
+/// client's `keepalive_ticks`) may later be changed at runtime, using `AppRequest::SetNodeConfig`.
+/// The keepalive of friend connections is fixed when the node starts.
 #[derive(Debug, Clone)]
 pub struct NodeConfig {
     /// Memory allocated to a channel in memory (Used to connect two components)
@@ -92,21 +132,84 @@ pub struct NodeConfig {
     pub backoff_ticks: usize,
     /// The amount of ticks we wait until we decide an idle connection has timed out.
     pub keepalive_ticks: usize,
+    /// Minimal amount of ticks between keepalives sent to a friend. Keepalives are sent more often
+    /// (Down to this bound) to friends whose connections time out, for example because a NAT
+    /// mapping along the way expires. Friends whose connections stay alive get keepalives less
+    /// often, saving battery and bandwidth.
+    pub keepalive_min_ticks: usize,
     /// Amount of ticks to wait until the next rekeying (Channel encryption)
     pub ticks_to_rekey: usize,
+    /// Amount of ticks a closed channel to a friend may be resumed without a full handshake.
+    /// 0 disables resumption.
+    pub ticks_to_resume: usize,
     /// Maximum amount of encryption set ups (diffie hellman) that we allow to occur at the same
     /// time from external communications (Channeler side)
     pub max_concurrent_encrypt: usize,
     /// The amount of ticks we are willing to wait until a connection is established.
     pub conn_timeout_ticks: usize,
+    /// Tunnel all the connections accepted through a relay over a single connection to the
+    /// relay. Requires relays that support multiplexed listening.
+    pub relay_multiplex: bool,
+    /// Amount of ticks between measurements of the round trip time to our relays and our
+    /// friends' relays. Relays with lower latency are attempted first. 0 disables measurements.
+    pub relay_probe_ticks: usize,
+    /// Never connect to friends' relays. Friends are expected to connect to us through our
+    /// relays. Useful behind firewalls that block outgoing connections to arbitrary relays.
+    /// Note that friends that wait for our connection (According to the ordering of public keys)
+    /// can not be reached in this mode.
+    pub listen_only: bool,
+    /// Act as a relay for our friends, by running a relay server inside the node. The embedded
+    /// relay is advertised to our friends as one of our relays, at this address.
+    /// None disables the embedded relay.
+    pub opt_embedded_relay_address: Option<NetAddress>,
     /// Maximum amount of operations in one move token message
     pub max_operations_in_batch: usize,
     /// The size we allocate for the user send funds requests queue.
     pub max_pending_user_requests: usize,
+    /// Maximum length of the route of a transaction originated by this node (Including this node
+    /// and the destination). Transactions along longer routes are rejected.
+    pub max_route_len: usize,
+    /// Maximum amount of times a failed transaction is retried through an alternative route
+    /// before the failure is reported to the user. 0 disables retries.
+    pub max_transaction_retries: u64,
     /// Maximum amount of concurrent index client requests:
     pub max_open_index_client_requests: usize,
+    /// The amount of ticks routes received from an index server are cached.
+    /// 0 disables the cache.
+    pub route_cache_ticks: usize,
+    /// Rotate the order of the routes returned for repeated requests to the same destination,
+    /// so that repeated payments are spread over several mediators.
+    pub route_rotation: bool,
+    /// Keep advertising a friend's previous capacity during short drops (For example, while a
+    /// request is pending), based on rolling statistics of the credits flow with the friend.
+    pub capacity_smoothing: bool,
+    /// Amount of index servers the index client keeps a session with at the same time.
+    /// Routes requests are sent to all of them, and their responses are merged.
+    pub max_index_sessions: usize,
+    /// Public key of the signer of the index server directory. If set, the list of index servers
+    /// is kept in sync with the latest directory published by the index servers.
+    pub opt_index_directory_key: Option<PublicKey>,
     /// Maximum amount of relays a node may use.
     pub max_node_relays: usize,
+    /// Maximum amount of ticks a graceful shutdown waits for pending operations (Token channel
+    /// moves and in-flight transactions) to settle.
+    pub shutdown_ticks: usize,
+    /// Amount of ticks until a request originated by this node expires, if it was not forwarded
+    /// by every node along the route. Expired requests are canceled by the node that holds them,
+    /// releasing the credits frozen along the route. 0 means that requests never expire.
+    pub request_expiry_ticks: u64,
+    /// New requests are not queued through a friend that was online less than this percentage
+    /// of the recent ticks. 0 disables this policy.
+    pub min_friend_uptime_percent: u8,
+    /// Accept a friend's channel reset terms automatically if its balances differ from ours by
+    /// at most this amount of credits (In every currency). Only terms that diverge further are
+    /// left for the user to resolve. None disables automatic resets.
+    pub opt_auto_reset_tolerance: Option<u128>,
+    /// Amount of ticks between two snapshots of the balances with every friend.
+    /// 0 disables balance snapshots.
+    pub balance_snapshot_ticks: usize,
+    /// Maximum amount of balance snapshots kept for every friend.
+    pub max_balance_snapshots: usize,
     /*
     /// Maximum amount of encryption set ups we allow to occur at the same time
     /// for incoming app connections
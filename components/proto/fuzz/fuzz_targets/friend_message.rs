@@ -0,0 +1,15 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use proto::funder::messages::FriendMessage;
+use proto::proto_ser::{ProtoDeserialize, ProtoDeserializeChecked, ProtoSerialize};
+
+fuzz_target!(|data: &[u8]| {
+    // Deserialization must never panic, whatever the input is:
+    if let Ok(msg) = FriendMessage::proto_deserialize_checked(data) {
+        // A message that was accepted must have a canonical serialization:
+        let ser_msg = msg.proto_serialize();
+        let msg2 = FriendMessage::proto_deserialize(&ser_msg).unwrap();
+        assert_eq!(msg2.proto_serialize(), ser_msg);
+    }
+});
@@ -3,21 +3,27 @@ use serde::{Deserialize, Serialize};
 use capnp_conv::{capnp_conv, CapnpConvError, ReadCapnp, WriteCapnp};
 
 use common::mutable_state::MutableState;
-use common::ser_utils::ser_b64;
+use common::ser_utils::{ser_b64, ser_option_b64, ser_string, ser_vec_b64};
 
 use crate::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
 
 use crate::funder::messages::{
-    AckClosePayment, AddFriend, AddInvoice, Commit, CreatePayment, CreateTransaction, Currency,
-    RemoveFriendCurrency, ResetFriendChannel, ResponseClosePayment, SetFriendCurrencyMaxDebt,
-    SetFriendCurrencyRate, SetFriendName, SetFriendRelays, TransactionResult,
+    AckClosePayment, AddFriend, AddInvoice, Commit, CreateExchangeTransaction, CreatePayment,
+    CreateTransaction, Currency, ExportChannelProof, KeyRotation, QuotePayment, Rebalance, Receipt,
+    RemoveFriendCurrency, RequestError, ResetFriendChannel, ResponseChannelProof,
+    ResponseClosePayment, ResponseExposure, ResponseQuotePayment, SetExchangeRate,
+    SetFriendCurrencyMaxDebt, SetFriendCurrencyRate, SetFriendMaxOutflow, SetFriendName,
+    SetFriendRelays, TransactionResult,
 };
 use crate::index_client::messages::{
     ClientResponseRoutes, IndexClientReport, IndexClientReportMutation,
 };
 use crate::index_server::messages::{NamedIndexServerAddress, RequestRoutes};
 use crate::net::messages::NetAddress;
-use crate::report::messages::{FunderReport, FunderReportMutation};
+use crate::report::messages::{
+    FriendReport, FunderReport, FunderReportMutation, RelayLatencyReport,
+};
+use crate::wrapper::Wrapper;
 
 // TODO: Move NamedRelayAddress and RelayAddress to another place in offst-proto?
 
@@ -50,14 +56,14 @@ impl<B> From<NamedRelayAddress<B>> for RelayAddress<B> {
 }
 
 #[capnp_conv(crate::report_capnp::node_report)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct NodeReport<B = NetAddress> {
     pub funder_report: FunderReport<B>,
     pub index_client_report: IndexClientReport<B>,
 }
 
 #[capnp_conv(crate::report_capnp::node_report_mutation)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum NodeReportMutation<B = NetAddress> {
     Funder(FunderReportMutation<B>),
     IndexClient(IndexClientReportMutation<B>),
@@ -89,16 +95,17 @@ impl From<OptAppRequestId> for Option<Uid> {
 }
 
 #[capnp_conv(crate::app_server_capnp::report_mutations)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ReportMutations<B = NetAddress> {
     #[capnp_conv(with = OptAppRequestId)]
+    #[serde(with = "ser_option_b64")]
     pub opt_app_request_id: Option<Uid>,
     pub mutations: Vec<NodeReportMutation<B>>,
 }
 
 #[allow(clippy::large_enum_variant)]
 #[capnp_conv(crate::app_server_capnp::app_server_to_app)]
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum AppServerToApp<B = NetAddress> {
     /// Funds:
     TransactionResult(TransactionResult),
@@ -107,6 +114,268 @@ pub enum AppServerToApp<B = NetAddress> {
     // Report(NodeReport<B>),
     ReportMutations(ReportMutations<B>),
     ResponseRoutes(ClientResponseRoutes),
+    PermissionDenied(PermissionDenied),
+    /// Analysis:
+    ResponseExposure(ResponseExposure),
+    /// The current state of one friend:
+    ResponseFriendDetail(ResponseFriendDetail<B>),
+    /// Disputes:
+    ResponseChannelProof(ResponseChannelProof),
+    /// Balance snapshots:
+    ResponseBalanceHistory(ResponseBalanceHistory),
+    /// A message published by another app connected to the node:
+    AppMessage(AppMessage),
+    /// Archived receipts and invoices:
+    ResponseReceipts(ResponseReceipts),
+    /// Payment quotes:
+    ResponseQuotePayment(ResponseQuotePayment),
+    /// A request could not be handled:
+    RequestError(RequestError),
+}
+
+/// The complete current state of one friend
+#[capnp_conv(crate::app_server_capnp::friend_detail)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FriendDetail<B = NetAddress> {
+    pub friend_report: FriendReport<B>,
+    /// Latest latencies measured to the relays of the friend
+    pub relay_latencies: Vec<RelayLatencyReport>,
+}
+
+#[capnp_conv(crate::app_server_capnp::friend_detail_result)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FriendDetailResult<B = NetAddress> {
+    Found(FriendDetail<B>),
+    /// There is no friend with the requested public key
+    NotFound,
+}
+
+/// A response to `AppRequest::RequestFriendDetail`
+#[capnp_conv(crate::app_server_capnp::response_friend_detail)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponseFriendDetail<B = NetAddress> {
+    #[serde(with = "ser_b64")]
+    pub friend_public_key: PublicKey,
+    pub result: FriendDetailResult<B>,
+}
+
+/// Request the balance snapshots taken with a friend, in one currency.
+/// Snapshots are taken periodically, every fixed amount of ticks.
+#[capnp_conv(crate::app_server_capnp::request_balance_history)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct RequestBalanceHistory {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    #[serde(with = "ser_b64")]
+    pub friend_public_key: PublicKey,
+    pub currency: Currency,
+    /// First tick of the range (inclusive)
+    pub from_tick: u64,
+    /// Last tick of the range (inclusive)
+    pub to_tick: u64,
+}
+
+/// A balance snapshot
+#[capnp_conv(crate::app_server_capnp::balance_delta)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct BalanceDelta {
+    /// The tick in which the snapshot was taken
+    pub tick: u64,
+    #[capnp_conv(with = Wrapper<i128>)]
+    #[serde(with = "ser_string")]
+    pub balance: i128,
+    /// Change of the balance since the previous snapshot
+    #[capnp_conv(with = Wrapper<i128>)]
+    #[serde(with = "ser_string")]
+    pub delta: i128,
+}
+
+/// A response to `AppRequest::RequestBalanceHistory`
+#[capnp_conv(crate::app_server_capnp::response_balance_history)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ResponseBalanceHistory {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    /// The current tick of the node, to relate the ticks of the snapshots to the current time
+    pub current_tick: u64,
+    /// Snapshots in the requested range, ordered by tick.
+    /// Empty if the friend or the currency were not found.
+    pub balance_deltas: Vec<BalanceDelta>,
+}
+
+/// A receipt obtained for a payment made by this node
+#[capnp_conv(crate::app_server_capnp::archived_receipt)]
+#[derive(Arbitrary, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ArchivedReceipt {
+    /// The archive tick in which the receipt was obtained
+    pub tick: u64,
+    #[serde(with = "ser_b64")]
+    pub payment_id: PaymentId,
+    /// The seller
+    #[serde(with = "ser_b64")]
+    pub dest_public_key: PublicKey,
+    pub receipt: Receipt,
+}
+
+#[capnp_conv(crate::app_server_capnp::archived_invoice::opt_commit)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum OptCommit {
+    Commit(Commit),
+    Empty,
+}
+
+impl From<Option<Commit>> for OptCommit {
+    fn from(opt: Option<Commit>) -> Self {
+        match opt {
+            Some(commit) => OptCommit::Commit(commit),
+            None => OptCommit::Empty,
+        }
+    }
+}
+
+impl From<OptCommit> for Option<Commit> {
+    fn from(opt: OptCommit) -> Self {
+        match opt {
+            OptCommit::Commit(commit) => Some(commit),
+            OptCommit::Empty => None,
+        }
+    }
+}
+
+/// An invoice issued by this node
+#[capnp_conv(crate::app_server_capnp::archived_invoice)]
+#[derive(Arbitrary, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ArchivedInvoice {
+    /// The archive tick in which the invoice was issued
+    pub tick: u64,
+    #[serde(with = "ser_b64")]
+    pub invoice_id: InvoiceId,
+    pub currency: Currency,
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub total_dest_payment: u128,
+    /// The commitment of the buyer, once the invoice was paid.
+    /// Empty for invoices that were canceled, or not paid yet.
+    #[capnp_conv(with = OptCommit)]
+    pub opt_commit: Option<Commit>,
+}
+
+#[capnp_conv(crate::app_server_capnp::request_receipts::opt_counterparty)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum OptCounterparty {
+    Counterparty(PublicKey),
+    Empty,
+}
+
+impl From<Option<PublicKey>> for OptCounterparty {
+    fn from(opt: Option<PublicKey>) -> Self {
+        match opt {
+            Some(counterparty) => OptCounterparty::Counterparty(counterparty),
+            None => OptCounterparty::Empty,
+        }
+    }
+}
+
+impl From<OptCounterparty> for Option<PublicKey> {
+    fn from(opt: OptCounterparty) -> Self {
+        match opt {
+            OptCounterparty::Counterparty(counterparty) => Some(counterparty),
+            OptCounterparty::Empty => None,
+        }
+    }
+}
+
+#[capnp_conv(crate::app_server_capnp::request_receipts::opt_invoice_id)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum OptInvoiceId {
+    InvoiceId(InvoiceId),
+    Empty,
+}
+
+impl From<Option<InvoiceId>> for OptInvoiceId {
+    fn from(opt: Option<InvoiceId>) -> Self {
+        match opt {
+            Some(invoice_id) => OptInvoiceId::InvoiceId(invoice_id),
+            None => OptInvoiceId::Empty,
+        }
+    }
+}
+
+impl From<OptInvoiceId> for Option<InvoiceId> {
+    fn from(opt: OptInvoiceId) -> Self {
+        match opt {
+            OptInvoiceId::InvoiceId(invoice_id) => Some(invoice_id),
+            OptInvoiceId::Empty => None,
+        }
+    }
+}
+
+/// Request the receipts and the issued invoices kept in the node's archive.
+/// Only entries matching all the given filters are returned.
+#[capnp_conv(crate::app_server_capnp::request_receipts)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct RequestReceipts {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    /// First archive tick of the range (inclusive)
+    pub from_tick: u64,
+    /// Last archive tick of the range (inclusive)
+    pub to_tick: u64,
+    /// Only receipts of payments to this seller.
+    /// Issued invoices have no counterparty, and are not returned if this filter is set.
+    #[capnp_conv(with = OptCounterparty)]
+    #[serde(with = "ser_option_b64")]
+    pub opt_counterparty: Option<PublicKey>,
+    /// Only receipts and invoices with this invoice id
+    #[capnp_conv(with = OptInvoiceId)]
+    #[serde(with = "ser_option_b64")]
+    pub opt_invoice_id: Option<InvoiceId>,
+}
+
+/// A response to `AppRequest::RequestReceipts`.
+/// Large results are split over multiple responses with the same `request_id`.
+#[capnp_conv(crate::app_server_capnp::response_receipts)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ResponseReceipts {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    /// The current archive tick, to relate the ticks of the entries to the current time
+    pub current_tick: u64,
+    /// Matching receipts, ordered by tick
+    pub receipts: Vec<ArchivedReceipt>,
+    /// Matching issued invoices, ordered by tick
+    pub invoices: Vec<ArchivedInvoice>,
+    /// Is this the last response for `request_id`?
+    pub is_last: bool,
+}
+
+/// A topic of messages published by one app.
+/// Topics are scoped by the public key of the publishing app, so that an app can not publish
+/// messages under the topics of another app.
+#[capnp_conv(crate::app_server_capnp::app_topic)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+pub struct AppTopic {
+    #[serde(with = "ser_b64")]
+    pub app_public_key: PublicKey,
+    pub name: String,
+}
+
+/// Publish a message to all the apps subscribed to the topic `topic_name` of the publishing app
+#[capnp_conv(crate::app_server_capnp::publish_app_message)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct PublishAppMessage {
+    pub topic_name: String,
+    #[serde(with = "ser_b64")]
+    pub data: Vec<u8>,
+}
+
+/// A message published by an app connected to the same node
+#[capnp_conv(crate::app_server_capnp::app_message)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct AppMessage {
+    pub topic: AppTopic,
+    #[serde(with = "ser_b64")]
+    pub data: Vec<u8>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -116,57 +385,144 @@ pub enum NamedRelaysMutation<B = NetAddress> {
 }
 
 #[capnp_conv(crate::app_server_capnp::open_friend_currency)]
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct OpenFriendCurrency {
+    #[serde(with = "ser_b64")]
     pub friend_public_key: PublicKey,
     pub currency: Currency,
 }
 
 #[capnp_conv(crate::app_server_capnp::close_friend_currency)]
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct CloseFriendCurrency {
+    #[serde(with = "ser_b64")]
     pub friend_public_key: PublicKey,
     pub currency: Currency,
 }
 
+/// A node configuration parameter that can be changed while the node is running.
+/// All values must be positive.
+/// The keepalive of friend connections is fixed when the node starts, and can not be changed here.
+#[capnp_conv(crate::app_server_capnp::set_node_config)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum SetNodeConfig {
+    /// Maximum amount of operations in one move token message (Funder)
+    MaxOperationsInBatch(u64),
+    /// Maximum amount of pending user requests for a friend (Funder)
+    MaxPendingUserRequests(u64),
+    /// Amount of ticks between keepalive messages to the index server (Index client)
+    IndexKeepaliveTicks(u64),
+}
+
+/// Propose a credit line to a node we are not friends with yet.
+/// The proposal is signed by the node and delivered through the index servers.
+#[capnp_conv(crate::app_server_capnp::send_friend_proposal)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct SendFriendProposal {
+    #[serde(with = "ser_b64")]
+    pub dest_public_key: PublicKey,
+    /// Relays the destination node can reach us through
+    pub relays: Vec<RelayAddress>,
+    pub currency: Currency,
+    /// The maximum debt we ask to be allowed to accumulate
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub proposed_max_debt: u128,
+}
+
 #[allow(clippy::large_enum_variant)]
 #[capnp_conv(crate::app_server_capnp::app_request)]
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum AppRequest<B = NetAddress> {
     /// Manage locally used relays:
     AddRelay(NamedRelayAddress<B>),
-    RemoveRelay(PublicKey),
+    RemoveRelay(#[serde(with = "ser_b64")] PublicKey),
     /// Friend management:
     AddFriend(AddFriend<B>),
     SetFriendRelays(SetFriendRelays<B>),
     SetFriendName(SetFriendName),
-    RemoveFriend(PublicKey),
-    EnableFriend(PublicKey),
-    DisableFriend(PublicKey),
+    RemoveFriend(#[serde(with = "ser_b64")] PublicKey),
+    EnableFriend(#[serde(with = "ser_b64")] PublicKey),
+    DisableFriend(#[serde(with = "ser_b64")] PublicKey),
     OpenFriendCurrency(OpenFriendCurrency),
     CloseFriendCurrency(CloseFriendCurrency),
     SetFriendCurrencyMaxDebt(SetFriendCurrencyMaxDebt),
+    /// Limit the credits sent to a friend during a window of time:
+    SetFriendMaxOutflow(SetFriendMaxOutflow),
     SetFriendCurrencyRate(SetFriendCurrencyRate),
     RemoveFriendCurrency(RemoveFriendCurrency),
     ResetFriendChannel(ResetFriendChannel),
+    /// Close the channel with a friend gracefully: Stop accepting new requests through the friend,
+    /// wait for all pending requests to settle, and then produce a closing statement.
+    /// The progress is reported in `FriendReport::drain_status`.
+    DrainFriend(#[serde(with = "ser_b64")] PublicKey),
     /// Buyer:
     CreatePayment(CreatePayment),
     CreateTransaction(CreateTransaction),
-    RequestClosePayment(PaymentId),
+    RequestClosePayment(#[serde(with = "ser_b64")] PaymentId),
     AckClosePayment(AckClosePayment),
+    /// Fees and frozen credits of a payment through a route, without sending it.
+    /// The response is sent back as `AppServerToApp::ResponseQuotePayment`.
+    QuotePayment(QuotePayment),
     /// Seller:
     AddInvoice(AddInvoice),
-    CancelInvoice(InvoiceId),
+    CancelInvoice(#[serde(with = "ser_b64")] InvoiceId),
     CommitInvoice(Commit),
     /// Request routes from one node to another:
     RequestRoutes(RequestRoutes),
     /// Manage index servers:
     AddIndexServer(NamedIndexServerAddress<B>),
-    RemoveIndexServer(PublicKey),
+    RemoveIndexServer(#[serde(with = "ser_b64")] PublicKey),
+    /// Change node configuration at runtime:
+    SetNodeConfig(SetNodeConfig),
+    /// Worst case credit exposure to friends (Contains a request_id):
+    RequestExposure(#[serde(with = "ser_b64")] Uid),
+    /// Friend proposals:
+    SendFriendProposal(SendFriendProposal),
+    /// Reject a received friend proposal, or dismiss it after it was accepted.
+    /// (Given by the public key of the proposing node)
+    RemoveFriendProposal(#[serde(with = "ser_b64")] PublicKey),
+    /// Request the current state of one friend, without subscribing to reports.
+    /// The response is sent back as `AppServerToApp::ResponseFriendDetail`.
+    RequestFriendDetail(#[serde(with = "ser_b64")] PublicKey),
+    /// Change the report mutations sent to this app connection.
+    /// Replaces the subscriptions given in `AppHello`.
+    SetReportSubscription(ReportSubscription),
+    /// Export the latest signed state of the token channel with a friend.
+    /// The response is sent back as `AppServerToApp::ResponseChannelProof`.
+    ExportChannelProof(ExportChannelProof),
+    /// Announce a new identity key of the node to all friends.
+    /// The new key is used once the node is restarted with the new identity.
+    RotateKey(KeyRotation),
+    /// Request balance snapshots taken with a friend.
+    /// The response is sent back as `AppServerToApp::ResponseBalanceHistory`.
+    RequestBalanceHistory(RequestBalanceHistory),
+    /// Request receipts and issued invoices from the node's archive.
+    /// The response is sent back as one or more `AppServerToApp::ResponseReceipts`.
+    RequestReceipts(RequestReceipts),
+    /// Exchange credits between currencies for requests forwarded through this node:
+    SetExchangeRate(SetExchangeRate),
+    /// Buyer: A transaction through a route that exchanges currencies on the way
+    CreateExchangeTransaction(CreateExchangeTransaction),
+    /// Move credits between two friends by paying ourselves along a cycle route.
+    /// Suggestions are given in `FunderReport::rebalance_suggestions`.
+    Rebalance(Rebalance),
+    /// Messaging between apps connected to the node.
+    /// Published messages are delivered as `AppServerToApp::AppMessage` to all the connected
+    /// apps subscribed to the topic. Messages are not stored, and are never sent to the node's
+    /// friends.
+    PublishAppMessage(PublishAppMessage),
+    SubscribeAppTopic(AppTopic),
+    UnsubscribeAppTopic(AppTopic),
+    /// Multiple requests, handled one by one in order. Every request has its own request id.
+    /// A single acknowledgement (With the request id of the batch) is sent once all the requests
+    /// were handled, together with all the report mutations they caused.
+    Batch(Vec<AppToAppServer<B>>),
 }
 #[capnp_conv(crate::app_server_capnp::app_to_app_server)]
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct AppToAppServer<B = NetAddress> {
+    #[serde(with = "ser_b64")]
     pub app_request_id: Uid,
     pub app_request: AppRequest<B>,
 }
@@ -230,4 +586,749 @@ pub struct AppPermissions {
     pub seller: bool,
     /// Can configure friends
     pub config: bool,
+    /// Can receive reports about the node's state.
+    /// Apps without this permission still receive acknowledgements for their own requests.
+    /// Apps configured before this permission existed always received reports.
+    #[serde(default = "default_reports_permission")]
+    pub reports: bool,
+    /// Can publish messages to other apps connected to the node
+    #[serde(default)]
+    pub publish: bool,
+}
+
+fn default_reports_permission() -> bool {
+    true
+}
+
+impl AppPermissions {
+    /// Does this set of permissions contain `permission`?
+    pub fn contains(&self, permission: &AppPermission) -> bool {
+        match permission {
+            AppPermission::Routes => self.routes,
+            AppPermission::Buyer => self.buyer,
+            AppPermission::Seller => self.seller,
+            AppPermission::Config => self.config,
+            AppPermission::Reports => self.reports,
+            AppPermission::Publish => self.publish,
+        }
+    }
+}
+
+/// A single application permission
+#[capnp_conv(crate::app_server_capnp::app_permission)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AppPermission {
+    Routes,
+    Buyer,
+    Seller,
+    Config,
+    Reports,
+    Publish,
+}
+
+/// Sent to an app that issued a request it has no permission for.
+#[capnp_conv(crate::app_server_capnp::permission_denied)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PermissionDenied {
+    /// The denied request
+    #[serde(with = "ser_b64")]
+    pub app_request_id: Uid,
+    /// The permission required for the request
+    pub permission: AppPermission,
+}
+
+/// An optional feature supported by a node.
+/// Allows apps to avoid sending requests an older node can not handle.
+#[capnp_conv(crate::app_server_capnp::node_feature)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeFeature {
+    /// Can answer `AppRequest::RequestRoutes`
+    RequestRoutes,
+    /// Can answer `AppRequest::RequestExposure`
+    RequestExposure,
+    /// Can handle `AppRequest::SetNodeConfig`
+    SetNodeConfig,
+    /// Sends `AppServerToApp::PermissionDenied` for requests the app has no permission for
+    PermissionDenied,
+    /// Can handle `AppRequest::SendFriendProposal` and `AppRequest::RemoveFriendProposal`
+    FriendProposals,
+    /// Can answer `AppRequest::RequestFriendDetail`
+    RequestFriendDetail,
+    /// Can handle `AppRequest::SetReportSubscription`
+    ReportSubscription,
+    /// Can answer `AppRequest::ExportChannelProof`
+    ExportChannelProof,
+    /// Can handle `AppRequest::Batch`
+    Batch,
+    /// Can handle `AppRequest::RotateKey`
+    RotateKey,
+    /// Can answer `AppRequest::RequestBalanceHistory`
+    RequestBalanceHistory,
+    /// Can handle `AppRequest::SetExchangeRate` and `AppRequest::CreateExchangeTransaction`
+    CurrencyExchange,
+    /// Can handle `AppRequest::PublishAppMessage`, `AppRequest::SubscribeAppTopic` and
+    /// `AppRequest::UnsubscribeAppTopic`
+    AppMessages,
+    /// Can answer `AppRequest::RequestReceipts`
+    RequestReceipts,
+    /// Can answer `AppRequest::QuotePayment`
+    QuotePayment,
+    /// Sends `AppServerToApp::RequestError` for requests that could not be handled
+    RequestError,
+}
+
+/// Sent from the node to a newly connected app, right after the app's permissions.
+#[capnp_conv(crate::app_server_capnp::server_hello)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerHello {
+    pub features: Vec<NodeFeature>,
+}
+
+impl ServerHello {
+    /// Is `feature` supported by the node?
+    pub fn supports(&self, feature: &NodeFeature) -> bool {
+        self.features.contains(feature)
+    }
+}
+
+/// A kind of report mutations an app may receive.
+#[capnp_conv(crate::app_server_capnp::app_subscription)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AppSubscription {
+    FunderReport,
+    IndexClientReport,
+    /// Only balance related funder report mutations: Friends being added or removed, and
+    /// changes to the channel status of friends.
+    FriendBalances,
+}
+
+impl AppSubscription {
+    /// Subscriptions to all kinds of report mutations
+    pub fn all() -> Vec<AppSubscription> {
+        vec![
+            AppSubscription::FunderReport,
+            AppSubscription::IndexClientReport,
+        ]
+    }
+}
+
+/// The friends an app wishes to receive report mutations about.
+#[capnp_conv(crate::app_server_capnp::report_subscription::friends)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FriendsFilter {
+    All,
+    Only(#[serde(with = "ser_vec_b64")] Vec<PublicKey>),
+}
+
+impl FriendsFilter {
+    /// Is the app interested in report mutations about this friend?
+    pub fn contains(&self, friend_public_key: &PublicKey) -> bool {
+        match self {
+            FriendsFilter::All => true,
+            FriendsFilter::Only(friends) => friends.contains(friend_public_key),
+        }
+    }
+}
+
+/// The report mutations sent to an app connection.
+/// Funder report mutations that do not concern a specific friend (For example, relays changes)
+/// are not affected by `friends`.
+#[capnp_conv(crate::app_server_capnp::report_subscription)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReportSubscription {
+    pub subscriptions: Vec<AppSubscription>,
+    pub friends: FriendsFilter,
+}
+
+/// Sent from an app to the node, in response to `ServerHello`.
+#[capnp_conv(crate::app_server_capnp::app_hello)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppHello {
+    pub subscriptions: Vec<AppSubscription>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use crate::crypto::{HashResult, HashedLock, PlainLock, RandValue, Signature};
+    use crate::funder::messages::{
+        ChannelProofResult, CurrencyBalance, CurrencyExposure, ExchangeRate,
+        FriendCurrencyExposure, FriendExposure, FriendsRoute, HopFreeze, MaxOutflow,
+        QuoteRejection, Rate, RequestErrorCode, RequestResult, TransactionRejection,
+    };
+    use crate::index_client::messages::ResponseRoutesResult;
+    use crate::index_server::messages::{
+        Edge, FriendProposal, MultiRoute, RouteCapacityRate, RouteConstraints, RouteRanking,
+    };
+    use crate::proto_ser::{ProtoDeserialize, ProtoSerialize};
+    use crate::report::messages::{
+        BandwidthReport, ChannelConsistentReport, ChannelStatusReport, ClosingStatement,
+        CurrencyConfigReport, CurrencyOutflowReport, CurrencyReport, DrainStatusReport,
+        FriendLivenessReport, FriendReportMutation, FriendStatusReport, McBalanceReport,
+        SecureChannelStatsReport,
+    };
+
+    fn dummy_net_address(address: &str) -> NetAddress {
+        NetAddress::try_from(address.to_owned()).unwrap()
+    }
+
+    fn dummy_currency() -> Currency {
+        Currency::try_from("FST".to_owned()).unwrap()
+    }
+
+    fn assert_app_to_app_server_round_trip(app_request: AppRequest) {
+        let app_to_app_server = AppToAppServer::new(Uid::from(&[0x11; Uid::len()]), app_request);
+        let ser = app_to_app_server.proto_serialize();
+        let deser = AppToAppServer::proto_deserialize(&ser).unwrap();
+        assert_eq!(deser, app_to_app_server);
+    }
+
+    fn assert_app_server_to_app_round_trip(app_server_to_app: AppServerToApp) {
+        let ser = app_server_to_app.proto_serialize();
+        let deser = AppServerToApp::proto_deserialize(&ser).unwrap();
+        assert_eq!(deser, app_server_to_app);
+    }
+
+    #[test]
+    fn test_ser_de_app_requests() {
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+
+        assert_app_to_app_server_round_trip(AppRequest::AddRelay(NamedRelayAddress {
+            public_key: pk_a.clone(),
+            address: dummy_net_address("relay.example:1337"),
+            name: "relay".to_owned(),
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::RemoveRelay(pk_a.clone()));
+        assert_app_to_app_server_round_trip(AppRequest::AddFriend(AddFriend {
+            friend_public_key: pk_b.clone(),
+            relays: vec![RelayAddress {
+                public_key: pk_a.clone(),
+                address: dummy_net_address("relay.example:1337"),
+            }],
+            name: "friend".to_owned(),
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::EnableFriend(pk_b.clone()));
+        assert_app_to_app_server_round_trip(AppRequest::SetNodeConfig(
+            SetNodeConfig::MaxOperationsInBatch(16),
+        ));
+        assert_app_to_app_server_round_trip(AppRequest::SetNodeConfig(
+            SetNodeConfig::IndexKeepaliveTicks(0x20),
+        ));
+        assert_app_to_app_server_round_trip(AppRequest::OpenFriendCurrency(OpenFriendCurrency {
+            friend_public_key: pk_b.clone(),
+            currency: dummy_currency(),
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::CreateTransaction(CreateTransaction {
+            payment_id: PaymentId::from(&[0x22; PaymentId::len()]),
+            request_id: Uid::from(&[0x33; Uid::len()]),
+            route: FriendsRoute {
+                public_keys: vec![pk_a.clone(), pk_b.clone()],
+            },
+            dest_payment: u128::max_value(),
+            fees: 7,
+            opt_refund_ticks: Some(0x30),
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::QuotePayment(QuotePayment {
+            request_id: Uid::from(&[0x34; Uid::len()]),
+            currency: dummy_currency(),
+            route: FriendsRoute {
+                public_keys: vec![pk_a.clone(), pk_b.clone()],
+            },
+            mediator_rates: vec![Rate { mul: 1, add: 2 }],
+            dest_payment: 100,
+            opt_max_fee_per_hop: Some(u128::max_value()),
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::RequestRoutes(RequestRoutes {
+            request_id: Uid::from(&[0x44; Uid::len()]),
+            currency: dummy_currency(),
+            capacity: 250,
+            source: pk_a.clone(),
+            destination: pk_b.clone(),
+            opt_exclude: Some(Edge {
+                from_public_key: pk_a.clone(),
+                to_public_key: pk_b.clone(),
+            }),
+            constraints: RouteConstraints {
+                blacklist: vec![pk_a.clone()],
+                opt_max_route_len: Some(5),
+                capacity_margin: 20,
+            },
+            ranking: RouteRanking::Capacity(3),
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::AddIndexServer(NamedIndexServerAddress {
+            public_key: pk_a.clone(),
+            address: dummy_net_address("index.example:1338"),
+            name: "index".to_owned(),
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::SendFriendProposal(SendFriendProposal {
+            dest_public_key: pk_b.clone(),
+            relays: vec![RelayAddress {
+                public_key: pk_a.clone(),
+                address: dummy_net_address("relay.example:1337"),
+            }],
+            currency: dummy_currency(),
+            proposed_max_debt: u128::max_value(),
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::RemoveFriendProposal(pk_b.clone()));
+        assert_app_to_app_server_round_trip(AppRequest::RequestFriendDetail(pk_a.clone()));
+        assert_app_to_app_server_round_trip(AppRequest::SetReportSubscription(
+            ReportSubscription {
+                subscriptions: vec![AppSubscription::FriendBalances],
+                friends: FriendsFilter::Only(vec![pk_a.clone(), pk_b.clone()]),
+            },
+        ));
+        assert_app_to_app_server_round_trip(AppRequest::SetReportSubscription(
+            ReportSubscription {
+                subscriptions: AppSubscription::all(),
+                friends: FriendsFilter::All,
+            },
+        ));
+        assert_app_to_app_server_round_trip(AppRequest::RemoveIndexServer(pk_a.clone()));
+        assert_app_to_app_server_round_trip(AppRequest::RequestExposure(Uid::from(
+            &[0x45; Uid::len()],
+        )));
+        assert_app_to_app_server_round_trip(AppRequest::ExportChannelProof(ExportChannelProof {
+            request_id: Uid::from(&[0x46; Uid::len()]),
+            friend_public_key: pk_b.clone(),
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::RotateKey(KeyRotation {
+            old_public_key: pk_a.clone(),
+            new_public_key: pk_b.clone(),
+            old_signature: Signature::from(&[0x47; Signature::len()]),
+            new_signature: Signature::from(&[0x48; Signature::len()]),
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::RequestBalanceHistory(
+            RequestBalanceHistory {
+                request_id: Uid::from(&[0x49; Uid::len()]),
+                friend_public_key: pk_a.clone(),
+                currency: dummy_currency(),
+                from_tick: 0,
+                to_tick: u64::max_value(),
+            },
+        ));
+        assert_app_to_app_server_round_trip(AppRequest::SetFriendMaxOutflow(SetFriendMaxOutflow {
+            friend_public_key: pk_a.clone(),
+            currency: dummy_currency(),
+            opt_max_outflow: Some(MaxOutflow {
+                max_outflow: u128::max_value(),
+                window_ticks: 600,
+            }),
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::SetFriendMaxOutflow(SetFriendMaxOutflow {
+            friend_public_key: pk_b.clone(),
+            currency: dummy_currency(),
+            opt_max_outflow: None,
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::SetExchangeRate(SetExchangeRate {
+            from_currency: dummy_currency(),
+            to_currency: Currency::try_from("FST2".to_owned()).unwrap(),
+            opt_rate: Some(ExchangeRate {
+                from_credits: 3,
+                to_credits: 2,
+            }),
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::SetExchangeRate(SetExchangeRate {
+            from_currency: dummy_currency(),
+            to_currency: Currency::try_from("FST2".to_owned()).unwrap(),
+            opt_rate: None,
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::CreateExchangeTransaction(
+            CreateExchangeTransaction {
+                payment_id: PaymentId::from(&[0x4a; PaymentId::len()]),
+                request_id: Uid::from(&[0x4b; Uid::len()]),
+                route: FriendsRoute {
+                    public_keys: vec![pk_a.clone(), pk_b.clone()],
+                },
+                currency: dummy_currency(),
+                exchanged_payment: 150,
+                dest_payment: u128::max_value(),
+                fees: 3,
+                refund_ticks: 0x40,
+            },
+        ));
+        assert_app_to_app_server_round_trip(AppRequest::Rebalance(Rebalance {
+            payment_id: PaymentId::from(&[0x4c; PaymentId::len()]),
+            invoice_id: InvoiceId::from(&[0x4d; InvoiceId::len()]),
+            request_id: Uid::from(&[0x4e; Uid::len()]),
+            currency: dummy_currency(),
+            route: FriendsRoute {
+                public_keys: vec![pk_a.clone(), pk_b.clone(), pk_a.clone()],
+            },
+            amount: 500,
+            fees: 2,
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::PublishAppMessage(PublishAppMessage {
+            topic_name: "payments".to_owned(),
+            data: vec![1, 2, 3],
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::SubscribeAppTopic(AppTopic {
+            app_public_key: pk_a.clone(),
+            name: "payments".to_owned(),
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::DrainFriend(pk_a.clone()));
+        assert_app_to_app_server_round_trip(AppRequest::RequestReceipts(RequestReceipts {
+            request_id: Uid::from(&[0x4a; Uid::len()]),
+            from_tick: 0,
+            to_tick: u64::max_value(),
+            opt_counterparty: Some(pk_b.clone()),
+            opt_invoice_id: None,
+        }));
+        assert_app_to_app_server_round_trip(AppRequest::UnsubscribeAppTopic(AppTopic {
+            app_public_key: pk_b,
+            name: "".to_owned(),
+        }));
+    }
+
+    #[test]
+    fn test_ser_de_app_server_to_app() {
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+
+        assert_app_server_to_app_round_trip(AppServerToApp::TransactionResult(TransactionResult {
+            request_id: Uid::from(&[0x55; Uid::len()]),
+            result: RequestResult::Failure,
+        }));
+
+        assert_app_server_to_app_round_trip(AppServerToApp::ResponseRoutes(ClientResponseRoutes {
+            request_id: Uid::from(&[0x66; Uid::len()]),
+            result: ResponseRoutesResult::Success(vec![MultiRoute {
+                routes: vec![RouteCapacityRate {
+                    route: FriendsRoute {
+                        public_keys: vec![pk_a.clone(), pk_b.clone()],
+                    },
+                    capacity: 100,
+                    rate: Rate { mul: 1, add: 2 },
+                }],
+            }]),
+        }));
+
+        assert_app_server_to_app_round_trip(AppServerToApp::ReportMutations(ReportMutations {
+            opt_app_request_id: Some(Uid::from(&[0x77; Uid::len()])),
+            mutations: vec![
+                NodeReportMutation::Funder(FunderReportMutation::RemoveRelay(pk_a.clone())),
+                NodeReportMutation::Funder(FunderReportMutation::PkFriendReportMutation((
+                    pk_b.clone(),
+                    FriendReportMutation::SetLiveness(FriendLivenessReport::Online),
+                ))),
+                NodeReportMutation::Funder(FunderReportMutation::PkFriendReportMutation((
+                    pk_b.clone(),
+                    FriendReportMutation::SetMissedBeats(2),
+                ))),
+                NodeReportMutation::Funder(FunderReportMutation::PkFriendReportMutation((
+                    pk_b.clone(),
+                    FriendReportMutation::SetGated(true),
+                ))),
+                NodeReportMutation::Funder(FunderReportMutation::PkFriendReportMutation((
+                    pk_b.clone(),
+                    FriendReportMutation::SetSecureChannelStats(SecureChannelStatsReport {
+                        decryption_failures: 1,
+                        nonce_replays: 2,
+                        rekeys: 3,
+                    }),
+                ))),
+                NodeReportMutation::Funder(FunderReportMutation::PkFriendReportMutation((
+                    pk_b.clone(),
+                    FriendReportMutation::SetBandwidth(BandwidthReport {
+                        bytes_sent: 10,
+                        bytes_received: u64::max_value(),
+                    }),
+                ))),
+                NodeReportMutation::Funder(FunderReportMutation::PkFriendReportMutation((
+                    pk_b.clone(),
+                    FriendReportMutation::SetDrainStatus(DrainStatusReport::Closed(
+                        ClosingStatement {
+                            final_balances: vec![CurrencyBalance {
+                                currency: dummy_currency(),
+                                balance: -7,
+                            }],
+                            channel_proof: vec![1, 2, 3],
+                        },
+                    )),
+                ))),
+                NodeReportMutation::Funder(FunderReportMutation::PkFriendReportMutation((
+                    pk_b.clone(),
+                    FriendReportMutation::SetCurrencyOutflow(CurrencyOutflowReport {
+                        currency: dummy_currency(),
+                        outflow: 15,
+                    }),
+                ))),
+                NodeReportMutation::Funder(FunderReportMutation::SetRelayLatency(
+                    RelayLatencyReport {
+                        public_key: pk_a.clone(),
+                        opt_latency_ms: Some(35),
+                    },
+                )),
+                NodeReportMutation::Funder(FunderReportMutation::SetRelayLatency(
+                    RelayLatencyReport {
+                        public_key: pk_b.clone(),
+                        opt_latency_ms: None,
+                    },
+                )),
+                NodeReportMutation::IndexClient(IndexClientReportMutation::SetConnectedServer(
+                    Some(pk_a.clone()),
+                )),
+                NodeReportMutation::IndexClient(IndexClientReportMutation::AddFriendProposal(
+                    FriendProposal {
+                        src_public_key: pk_b.clone(),
+                        dest_public_key: pk_a.clone(),
+                        relays: vec![RelayAddress {
+                            public_key: pk_a.clone(),
+                            address: dummy_net_address("relay.example:1337"),
+                        }],
+                        currency: dummy_currency(),
+                        proposed_max_debt: 500,
+                        rand_nonce: RandValue::from(&[0x12; RandValue::len()]),
+                        signature: Signature::from(&[0x13; Signature::len()]),
+                    },
+                )),
+                NodeReportMutation::IndexClient(IndexClientReportMutation::RemoveFriendProposal(
+                    pk_b.clone(),
+                )),
+                NodeReportMutation::IndexClient(IndexClientReportMutation::RemoveIndexServer(pk_b)),
+            ],
+        }));
+
+        assert_app_server_to_app_round_trip(AppServerToApp::ReportMutations(ReportMutations {
+            opt_app_request_id: None,
+            mutations: Vec::new(),
+        }));
+
+        assert_app_server_to_app_round_trip(AppServerToApp::PermissionDenied(PermissionDenied {
+            app_request_id: Uid::from(&[0x88; Uid::len()]),
+            permission: AppPermission::Config,
+        }));
+
+        assert_app_server_to_app_round_trip(AppServerToApp::RequestError(RequestError {
+            app_request_id: Uid::from(&[0x89; Uid::len()]),
+            error_code: RequestErrorCode::FriendNotFound,
+            detail: "FriendDoesNotExist".to_owned(),
+        }));
+        assert_app_server_to_app_round_trip(AppServerToApp::RequestError(RequestError {
+            app_request_id: Uid::from(&[0x8a; Uid::len()]),
+            error_code: RequestErrorCode::Rejected(TransactionRejection::RouteTooLong(4)),
+            detail: String::new(),
+        }));
+
+        assert_app_server_to_app_round_trip(AppServerToApp::ResponseExposure(ResponseExposure {
+            request_id: Uid::from(&[0x99; Uid::len()]),
+            friends: vec![FriendExposure {
+                friend_public_key: pk_a.clone(),
+                currencies: vec![FriendCurrencyExposure {
+                    currency: dummy_currency(),
+                    all_fail_balance: -5,
+                    all_succeed_balance: i128::max_value(),
+                }],
+            }],
+            totals: vec![CurrencyExposure {
+                currency: dummy_currency(),
+                all_fail_exposure: 0,
+                all_succeed_exposure: u128::max_value(),
+            }],
+        }));
+
+        let pk_c = PublicKey::from(&[0xcc; PublicKey::len()]);
+        let friend_report = FriendReport {
+            name: "friend_a".to_owned(),
+            remote_relays: vec![RelayAddress {
+                public_key: pk_c.clone(),
+                address: dummy_net_address("relay.example:1337"),
+            }],
+            currency_configs: vec![CurrencyConfigReport {
+                currency: dummy_currency(),
+                rate: Rate { mul: 1, add: 2 },
+                remote_max_debt: 100,
+                is_open: true,
+                opt_max_outflow: Some(MaxOutflow {
+                    max_outflow: 50,
+                    window_ticks: 10,
+                }),
+                outflow: 20,
+            }],
+            opt_last_incoming_move_token: None,
+            liveness: FriendLivenessReport::Online,
+            channel_status: ChannelStatusReport::Consistent(ChannelConsistentReport {
+                currency_reports: vec![CurrencyReport {
+                    currency: dummy_currency(),
+                    balance: McBalanceReport {
+                        balance: -10,
+                        local_pending_debt: 5,
+                        remote_pending_debt: 0,
+                    },
+                }],
+            }),
+            status: FriendStatusReport::Enabled,
+            missed_beats: 0,
+            is_gated: false,
+            secure_channel_stats: SecureChannelStatsReport {
+                decryption_failures: 0,
+                nonce_replays: 1,
+                rekeys: 5,
+            },
+            drain_status: DrainStatusReport::Draining,
+            bandwidth: BandwidthReport {
+                bytes_sent: 0x1000,
+                bytes_received: 0x200,
+            },
+        };
+        assert_app_server_to_app_round_trip(AppServerToApp::ResponseFriendDetail(
+            ResponseFriendDetail {
+                friend_public_key: pk_a.clone(),
+                result: FriendDetailResult::Found(FriendDetail {
+                    friend_report,
+                    relay_latencies: vec![RelayLatencyReport {
+                        public_key: pk_c.clone(),
+                        opt_latency_ms: Some(20),
+                    }],
+                }),
+            },
+        ));
+        assert_app_server_to_app_round_trip(AppServerToApp::ResponseFriendDetail(
+            ResponseFriendDetail {
+                friend_public_key: pk_c.clone(),
+                result: FriendDetailResult::NotFound,
+            },
+        ));
+
+        assert_app_server_to_app_round_trip(AppServerToApp::ResponseChannelProof(
+            ResponseChannelProof {
+                request_id: Uid::from(&[0x77; Uid::len()]),
+                friend_public_key: pk_a.clone(),
+                result: ChannelProofResult::Found(vec![1, 2, 3, 4]),
+            },
+        ));
+        assert_app_server_to_app_round_trip(AppServerToApp::ResponseChannelProof(
+            ResponseChannelProof {
+                request_id: Uid::from(&[0x78; Uid::len()]),
+                friend_public_key: pk_c.clone(),
+                result: ChannelProofResult::NotFound,
+            },
+        ));
+
+        assert_app_server_to_app_round_trip(AppServerToApp::ResponseBalanceHistory(
+            ResponseBalanceHistory {
+                request_id: Uid::from(&[0x79; Uid::len()]),
+                current_tick: 0x100,
+                balance_deltas: vec![
+                    BalanceDelta {
+                        tick: 0x80,
+                        balance: i128::min_value(),
+                        delta: -5,
+                    },
+                    BalanceDelta {
+                        tick: 0xc0,
+                        balance: 3,
+                        delta: 0,
+                    },
+                ],
+            },
+        ));
+
+        let receipt = Receipt {
+            response_hash: HashResult::from(&[0x01; HashResult::len()]),
+            invoice_id: InvoiceId::from(&[0x02; InvoiceId::len()]),
+            currency: dummy_currency(),
+            src_plain_lock: PlainLock::from(&[0x03; PlainLock::len()]),
+            dest_plain_lock: PlainLock::from(&[0x04; PlainLock::len()]),
+            is_complete: true,
+            dest_payment: 10,
+            total_dest_payment: 100,
+            change: 1,
+            signature: Signature::from(&[0x05; Signature::len()]),
+        };
+        let commit = Commit {
+            response_hash: HashResult::from(&[0x06; HashResult::len()]),
+            src_plain_lock: PlainLock::from(&[0x07; PlainLock::len()]),
+            dest_hashed_lock: HashedLock::from(&[0x08; HashedLock::len()]),
+            dest_payment: 100,
+            total_dest_payment: 100,
+            change: 0,
+            invoice_id: InvoiceId::from(&[0x09; InvoiceId::len()]),
+            currency: dummy_currency(),
+            signature: Signature::from(&[0x0a; Signature::len()]),
+        };
+        assert_app_server_to_app_round_trip(AppServerToApp::ResponseReceipts(ResponseReceipts {
+            request_id: Uid::from(&[0x7a; Uid::len()]),
+            current_tick: 0x100,
+            receipts: vec![ArchivedReceipt {
+                tick: 0x20,
+                payment_id: PaymentId::from(&[0x0b; PaymentId::len()]),
+                dest_public_key: pk_a.clone(),
+                receipt,
+            }],
+            invoices: vec![
+                ArchivedInvoice {
+                    tick: 0x30,
+                    invoice_id: InvoiceId::from(&[0x09; InvoiceId::len()]),
+                    currency: dummy_currency(),
+                    total_dest_payment: 100,
+                    opt_commit: Some(commit),
+                },
+                ArchivedInvoice {
+                    tick: 0x40,
+                    invoice_id: InvoiceId::from(&[0x0c; InvoiceId::len()]),
+                    currency: dummy_currency(),
+                    total_dest_payment: u128::max_value(),
+                    opt_commit: None,
+                },
+            ],
+            is_last: true,
+        }));
+
+        assert_app_server_to_app_round_trip(AppServerToApp::ResponseQuotePayment(
+            ResponseQuotePayment {
+                request_id: Uid::from(&[0x7b; Uid::len()]),
+                fees: 3,
+                hop_freezes: vec![HopFreeze {
+                    to_public_key: pk_b.clone(),
+                    frozen_credits: u128::max_value(),
+                }],
+                opt_rejection: Some(QuoteRejection::Rejected(
+                    TransactionRejection::RouteTooLong(4),
+                )),
+            },
+        ));
+        assert_app_server_to_app_round_trip(AppServerToApp::ResponseQuotePayment(
+            ResponseQuotePayment {
+                request_id: Uid::from(&[0x7c; Uid::len()]),
+                fees: 0,
+                hop_freezes: Vec::new(),
+                opt_rejection: None,
+            },
+        ));
+
+        assert_app_server_to_app_round_trip(AppServerToApp::AppMessage(AppMessage {
+            topic: AppTopic {
+                app_public_key: pk_a,
+                name: "payments".to_owned(),
+            },
+            data: Vec::new(),
+        }));
+    }
+
+    #[test]
+    fn test_ser_de_hello() {
+        let server_hello = ServerHello {
+            features: vec![
+                NodeFeature::RequestRoutes,
+                NodeFeature::PermissionDenied,
+                NodeFeature::RequestError,
+            ],
+        };
+        let ser = server_hello.proto_serialize();
+        let deser = ServerHello::proto_deserialize(&ser).unwrap();
+        assert_eq!(deser, server_hello);
+        assert!(deser.supports(&NodeFeature::RequestRoutes));
+        assert!(!deser.supports(&NodeFeature::RequestExposure));
+
+        let app_hello = AppHello {
+            subscriptions: AppSubscription::all(),
+        };
+        let ser = app_hello.proto_serialize();
+        let deser = AppHello::proto_deserialize(&ser).unwrap();
+        assert_eq!(deser, app_hello);
+    }
 }
@@ -1,6 +1,15 @@
 /// The current protocol version
 pub const PROTOCOL_VERSION: u32 = 0;
 
+/// The current version of the messages exchanged between friends.
+/// Sent to friends as a part of `FriendCapabilities`. Friends may only use operations introduced
+/// in a version both of them support.
+///
+/// - 0: The initial friend protocol
+/// - 1: Refunds (`RefundSendFunds`, and requests with a refund countdown), and move tokens
+///   signed in the versioned signature buffer layout
+pub const FRIEND_PROTOCOL_VERSION: u32 = 1;
+
 /// Maximum amount of friend operations sent in one move token message.
 pub const MAX_OPERATIONS_IN_BATCH: usize = 16;
 
@@ -10,6 +19,11 @@ pub const MAX_ROUTE_LEN: usize = 32;
 /// Maximum length for a name of a currency.
 pub const MAX_CURRENCY_LEN: usize = 16;
 
+/// Amount of refund ticks every mediator takes for itself when forwarding a refundable request.
+/// A mediator refunds the next node this amount of ticks before the previous node may refund the
+/// mediator, leaving the mediator enough time to pass back a late collect message.
+pub const REFUND_TICKS_MARGIN: u64 = 0x10;
+
 // TODO: Possibly convert TICK_MS to be u64?
 /// Amount of milliseconds in one tick:
 pub const TICK_MS: usize = 1000;
@@ -17,6 +31,48 @@ pub const TICK_MS: usize = 1000;
 /// Amount of ticks to wait before rekeying a secure channel.
 pub const TICKS_TO_REKEY: usize = 60 * 60 * (1000 / TICK_MS); // 1 hour
 
+/// Amount of ticks a secure channel may be resumed after it was closed,
+/// without performing a full handshake.
+pub const TICKS_TO_RESUME: usize = 60 * (1000 / TICK_MS); // 1 minute
+
+/// Maximum amount of consecutive resumptions of a secure channel.
+/// After this amount of resumptions a full handshake is required, so that the keys of a
+/// long lived relation are eventually refreshed with new diffie hellman secrets.
+pub const MAX_SC_RESUMPTIONS: usize = 8;
+
+/// Lowest secure channel protocol version in which both sides support the compact wire format
+/// for friend messages (See `proto::wire`).
+pub const SC_COMPACT_WIRE_VERSION: u32 = 2;
+
+/// Amount of ticks over which the recent uptime of a friend is measured.
+pub const FRIEND_UPTIME_WINDOW_TICKS: usize = 10 * 60 * (1000 / TICK_MS); // 10 minutes
+
+/// Channeler: Maximum amount of messages queued for sending to a single friend.
+/// When the queue of a friend is full, its oldest message is discarded.
+pub const MAX_FRIEND_QUEUE_LEN: usize = 0x20;
+
+/// Channeler: Maximum amount of messages being sent to friends at the same time.
+/// Other messages wait in the queues of the friends.
+pub const MAX_CONCURRENT_FRIEND_SENDS: usize = 0x10;
+
+/// New requests are not queued through a friend while the Channeler holds at least this amount
+/// of messages waiting to be sent to the friend.
+pub const FRIEND_QUEUE_CONGESTION_DEPTH: usize = 0x8;
+
+/// Channeler: Amount of ticks a message may take to be sent to a friend.
+/// A connection that takes longer is considered stalled, and is closed.
+pub const FRIEND_SEND_TIMEOUT_TICKS: usize = 30 * (1000 / TICK_MS); // 30 seconds
+
+/// Channeler: Amount of ticks between reports of the bandwidth used by every friend.
+pub const BANDWIDTH_REPORT_TICKS: usize = 60 * (1000 / TICK_MS); // 1 minute
+
+/// Funder: Amount of ticks between analyses of the balances with all friends, looking for
+/// channels that should be rebalanced.
+pub const REBALANCE_ANALYSIS_TICKS: usize = 60 * (1000 / TICK_MS); // 1 minute
+
+/// Funder: Maximum amount of rebalancing suggestions shown in the report.
+pub const MAX_REBALANCE_SUGGESTIONS: usize = 8;
+
 /// If no message was sent for this amount of ticks, the connection will be closed
 pub const KEEPALIVE_TICKS: usize = 0x20;
 
@@ -24,14 +80,57 @@ pub const KEEPALIVE_TICKS: usize = 0x20;
 /// sends identification of which type of connection it is.
 pub const CONN_TIMEOUT_TICKS: usize = 4;
 
+/// Relay server: The amount of ticks no data may pass through a tunnel before the tunnel is
+/// closed. Nodes send keepalive messages through their tunnels, so a tunnel of live nodes is
+/// never idle for that long.
+pub const RELAY_TUNNEL_IDLE_TICKS: usize = 4 * KEEPALIVE_TICKS;
+
+/// Relay server: The amount of ticks a tunnel stays open after one of its sides was closed,
+/// waiting for the other side to close.
+pub const RELAY_HALF_OPEN_TICKS: usize = KEEPALIVE_TICKS;
+
+/// Relay server: Maximum amount of nodes that may listen on a relay at the same time.
+pub const MAX_RELAY_LISTENERS: usize = 0x400;
+
+/// Relay server: The amount of ticks between two announcements of the local listeners to the
+/// peer relays.
+pub const RELAY_PEER_ANNOUNCE_TICKS: usize = 0x10;
+
+/// Relay server: Maximum amount of pings echoed over a single ping connection. The connection
+/// is closed afterwards.
+pub const MAX_RELAY_PINGS: usize = 0x10;
+
+/// Channeler: Amount of pings sent through a relay each time its latency is measured.
+/// Must not exceed `MAX_RELAY_PINGS`.
+pub const RELAY_PROBE_PINGS: usize = 3;
+
 /// The stream TCP connection is split into prefix length frames. This is the maximum allowed
 /// length for such frame, measured in bytes.
 pub const MAX_FRAME_LENGTH: usize = 1 << 20; // 1[MB]
 
+/// Relay server: Maximum amount of bytes buffered for one side of a tunnel.
+/// A side that does not keep up with the other side is disconnected.
+pub const MAX_RELAY_TUNNEL_BUFFERED_BYTES: usize = 4 * MAX_FRAME_LENGTH;
+
+/// Maximum amount of bytes buffered for a connected app.
+/// An app that does not keep up with the messages sent by the node is disconnected.
+pub const MAX_APP_BUFFERED_BYTES: usize = 4 * MAX_FRAME_LENGTH;
+
+/// Maximum length of the name of a topic of messages published by an app, measured in bytes.
+pub const MAX_APP_TOPIC_LEN: usize = 0x40;
+
+/// Maximum amount of topics a single app connection may subscribe to.
+pub const MAX_APP_TOPIC_SUBSCRIPTIONS: usize = 0x40;
+
 /// Index server: The amount of ticks it takes for an idle node to be removed from the
 /// index server database.
 pub const INDEX_NODE_TIMEOUT_TICKS: usize = 60 * (1000 / TICK_MS); // 1 minute
 
+/// Index client: The amount of ticks after a disconnect during which a session with an index
+/// server may be resumed. Must be shorter than `INDEX_NODE_TIMEOUT_TICKS`, otherwise the server
+/// will have forgotten the state of the node anyway.
+pub const INDEX_SESSION_RESUME_TICKS: usize = 30 * (1000 / TICK_MS); // 30 seconds
+
 /// Maximum length for an address string used in NetAddress
 pub const MAX_NET_ADDRESS_LENGTH: usize = 256;
 
@@ -63,6 +63,22 @@ pub struct IndexServerFile {
     pub address: NetAddress,
 }
 
+/// A public key allowed to use the admin interface of an index server.
+#[derive(Arbitrary, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexAdminFile {
+    #[serde(with = "ser_b64")]
+    pub public_key: PublicKey,
+}
+
+/// A public key that signs index server directories.
+#[derive(Arbitrary, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexDirectoryKeyFile {
+    #[serde(with = "ser_b64")]
+    pub public_key: PublicKey,
+}
+
 /// A helper structure for serialize and deserializing NodeAddress.
 #[derive(Arbitrary, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
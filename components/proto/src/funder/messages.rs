@@ -19,10 +19,12 @@ use crate::crypto::{
 
 use crate::app_server::messages::{NamedRelayAddress, RelayAddress};
 use crate::consts::{MAX_CURRENCY_LEN, MAX_ROUTE_LEN};
+use crate::keepalive::messages::KeepAliveReport;
 use crate::net::messages::NetAddress;
-use crate::report::messages::FunderReportMutations;
+use crate::report::messages::{BandwidthReport, FunderReportMutations};
+use crate::secure_channel::messages::SecureChannelReport;
 
-use common::ser_utils::{ser_b64, ser_seq_str, ser_string, ser_vec_b64};
+use common::ser_utils::{ser_b64, ser_option_string, ser_seq_str, ser_string, ser_vec_b64};
 
 use crate::wrapper::Wrapper;
 
@@ -35,10 +37,33 @@ pub struct ChannelerUpdateFriend<RA> {
     pub local_relays: Vec<RA>,
 }
 
+/// The priority of a message sent to a friend.
+/// When the Channeler can not keep up, friends with higher priority messages are served first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MessagePriority {
+    Normal,
+    /// We want the token back from the friend (Pending token channel activity)
+    TokenWanted,
+}
+
+/// The fate of a message sent to a friend, as reported by the Channeler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageDelivery {
+    /// The message was written to the connection with the friend
+    Sent,
+    /// The friend was not connected, or the connection was closed before the message was sent
+    FriendOffline,
+    /// The message was discarded from the outgoing queue of the friend, because the queue was full
+    Dropped,
+    /// Sending the message took too long. The connection to the friend is closed.
+    TimedOut,
+}
+
 #[derive(Debug)]
 pub enum FunderToChanneler<RA> {
-    /// Send a message to a friend
-    Message((PublicKey, Vec<u8>)), // (friend_public_key, message)
+    /// Send a message to a friend.
+    /// The Channeler reports the delivery of the message using `message_id`.
+    Message((PublicKey, Uid, Vec<u8>, MessagePriority)), // (friend_public_key, message_id, message, priority)
     /// Set address for relay used by local node
     SetRelays(Vec<RA>),
     /// Request to add a new friend or update friend's information
@@ -48,13 +73,31 @@ pub enum FunderToChanneler<RA> {
 }
 
 #[derive(Debug)]
-pub enum ChannelerToFunder {
+pub enum ChannelerToFunder<RA> {
     /// A friend is now online
     Online(PublicKey),
     /// A friend is now offline
     Offline(PublicKey),
     /// Incoming message from a remote friend
     Message((PublicKey, Vec<u8>)), // (friend_public_key, message)
+    /// The keepalive layer of a connection to a friend reported missed (or recovered) beats
+    KeepAliveReport((PublicKey, KeepAliveReport)), // (friend_public_key, keepalive_report)
+    /// The secure channel layer of a connection to a friend reported a diagnostic event
+    SecureChannelReport((PublicKey, SecureChannelReport)), // (friend_public_key, report)
+    /// Bytes exchanged with a friend since the previous bandwidth report of the friend.
+    /// Reported periodically, only for friends that exchanged any bytes.
+    Bandwidth((PublicKey, BandwidthReport)), // (friend_public_key, bandwidth)
+    /// A new latency measurement (In milliseconds) of a relay. None if the relay could not be
+    /// reached.
+    RelayLatency((RA, Option<u64>)), // (relay_address, opt_latency_ms)
+    /// Amount of messages waiting to be sent to a friend. Reported whenever the amount crosses
+    /// `FRIEND_QUEUE_CONGESTION_DEPTH`.
+    QueueDepth((PublicKey, usize)), // (friend_public_key, queue_depth)
+    /// Delivery report of a message sent to a friend
+    MessageDelivery((PublicKey, Uid, MessageDelivery)), // (friend_public_key, message_id, delivery)
+    /// We never connect to friends, and only accept connections from friends through our relays.
+    /// Sent once, when the Channeler starts.
+    ListenOnly,
 }
 
 // -------------------------------------------
@@ -91,6 +134,62 @@ pub struct RequestSendFundsOp {
     #[capnp_conv(with = Wrapper<u128>)]
     #[serde(with = "ser_string")]
     pub left_fees: u128,
+    /// Amount of ticks left until this request expires.
+    /// Every mediator subtracts the amount of ticks the request waited before being forwarded,
+    /// and cancels the request if it expires before it was forwarded.
+    /// 0 means that the request never expires.
+    #[serde(default)]
+    pub expiry_ticks: u64,
+    /// Amount of ticks left until the sender of this request may refund it, if the destination
+    /// has not revealed its lock by then.
+    /// Every mediator subtracts `REFUND_TICKS_MARGIN` ticks, and the amount of ticks the request
+    /// waited before being forwarded.
+    /// 0 means that the request is never refunded.
+    #[serde(default)]
+    pub refund_ticks: u64,
+    /// The terms of the request after it is exchanged to the currency of the destination.
+    /// None if the request is already in the currency of the destination.
+    #[capnp_conv(with = OptCurrencyExchange)]
+    pub opt_exchange: Option<CurrencyExchange>,
+}
+
+/// The terms of a request on the destination side of a currency exchange.
+/// The destination signs its response over these terms.
+#[capnp_conv(crate::funder_capnp::currency_exchange)]
+#[derive(Arbitrary, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct CurrencyExchange {
+    pub dest_currency: Currency,
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub dest_payment: u128,
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub total_dest_payment: u128,
+}
+
+#[capnp_conv(crate::funder_capnp::request_send_funds_op::opt_exchange)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptCurrencyExchange {
+    Empty,
+    Exchange(CurrencyExchange),
+}
+
+impl From<Option<CurrencyExchange>> for OptCurrencyExchange {
+    fn from(opt: Option<CurrencyExchange>) -> Self {
+        match opt {
+            Some(currency_exchange) => OptCurrencyExchange::Exchange(currency_exchange),
+            None => OptCurrencyExchange::Empty,
+        }
+    }
+}
+
+impl From<OptCurrencyExchange> for Option<CurrencyExchange> {
+    fn from(opt: OptCurrencyExchange) -> Self {
+        match opt {
+            OptCurrencyExchange::Exchange(currency_exchange) => Some(currency_exchange),
+            OptCurrencyExchange::Empty => None,
+        }
+    }
 }
 
 #[capnp_conv(crate::funder_capnp::response_send_funds_op)]
@@ -101,6 +200,11 @@ pub struct ResponseSendFundsOp {
     #[serde(with = "ser_b64")]
     pub dest_hashed_lock: HashedLock,
     pub is_complete: bool,
+    /// Amount of credits out of `dest_payment` that the destination gives back, because this
+    /// transaction paid more than what was left to pay for the invoice.
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(default, with = "ser_string")]
+    pub change: u128,
     #[serde(with = "ser_b64")]
     pub rand_nonce: RandValue,
     #[serde(with = "ser_b64")]
@@ -114,6 +218,8 @@ pub struct UnsignedResponseSendFundsOp {
     #[serde(with = "ser_b64")]
     pub dest_hashed_lock: HashedLock,
     pub is_complete: bool,
+    #[serde(default, with = "ser_string")]
+    pub change: u128,
     #[serde(with = "ser_b64")]
     pub rand_nonce: RandValue,
 }
@@ -125,6 +231,13 @@ pub struct CancelSendFundsOp {
     pub request_id: Uid,
 }
 
+#[capnp_conv(crate::funder_capnp::refund_send_funds_op)]
+#[derive(Arbitrary, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct RefundSendFundsOp {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+}
+
 #[allow(clippy::large_enum_variant)]
 #[capnp_conv(crate::common_capnp::commit)]
 #[derive(Arbitrary, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
@@ -141,6 +254,10 @@ pub struct Commit {
     #[capnp_conv(with = Wrapper<u128>)]
     #[serde(with = "ser_string")]
     pub total_dest_payment: u128,
+    /// Amount of credits out of `dest_payment` that the seller gives back
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(default, with = "ser_string")]
+    pub change: u128,
     #[serde(with = "ser_b64")]
     pub invoice_id: InvoiceId,
     #[serde(with = "ser_string")]
@@ -167,6 +284,62 @@ pub enum FriendTcOp {
     ResponseSendFunds(ResponseSendFundsOp),
     CancelSendFunds(CancelSendFundsOp),
     CollectSendFunds(CollectSendFundsOp),
+    RefundSendFunds(RefundSendFundsOp),
+}
+
+impl FriendTcOp {
+    /// The first version of the friend protocol (See `FRIEND_PROTOCOL_VERSION`) that includes this
+    /// operation. A new operation type must not be sent to a friend that does not support this
+    /// version.
+    pub fn min_protocol_version(&self) -> u32 {
+        match self {
+            // A request with a refund countdown may be refunded by the sender:
+            FriendTcOp::RequestSendFunds(request_send_funds)
+                if request_send_funds.refund_ticks > 0 =>
+            {
+                1
+            }
+            // A request that is exchanged to another currency on the way:
+            FriendTcOp::RequestSendFunds(request_send_funds)
+                if request_send_funds.opt_exchange.is_some() =>
+            {
+                1
+            }
+            // A response that gives change back to the buyer:
+            FriendTcOp::ResponseSendFunds(response_send_funds)
+                if response_send_funds.change > 0 =>
+            {
+                1
+            }
+            FriendTcOp::RefundSendFunds(_) => 1,
+            FriendTcOp::RequestSendFunds(_)
+            | FriendTcOp::ResponseSendFunds(_)
+            | FriendTcOp::CancelSendFunds(_)
+            | FriendTcOp::CollectSendFunds(_) => 0,
+        }
+    }
+
+    /// Optional features (See `friend_features`) that both sides must support for this operation
+    /// to be sent
+    pub fn required_features(&self) -> u64 {
+        match self {
+            FriendTcOp::RequestSendFunds(request_send_funds)
+                if request_send_funds.opt_exchange.is_some() =>
+            {
+                friend_features::CURRENCY_EXCHANGE
+            }
+            FriendTcOp::ResponseSendFunds(response_send_funds)
+                if response_send_funds.change > 0 =>
+            {
+                friend_features::CHANGE
+            }
+            FriendTcOp::RequestSendFunds(_)
+            | FriendTcOp::ResponseSendFunds(_)
+            | FriendTcOp::CancelSendFunds(_)
+            | FriendTcOp::CollectSendFunds(_)
+            | FriendTcOp::RefundSendFunds(_) => 0,
+        }
+    }
 }
 
 #[capnp_conv(crate::funder_capnp::move_token::opt_local_relays)]
@@ -190,6 +363,7 @@ impl Into<UnsignedResponseSendFundsOp> for ResponseSendFundsOp {
             request_id: self.request_id,
             dest_hashed_lock: self.dest_hashed_lock,
             is_complete: self.is_complete,
+            change: self.change,
             rand_nonce: self.rand_nonce,
         }
     }
@@ -309,6 +483,10 @@ pub struct MoveToken<B = NetAddress> {
     pub info_hash: HashResult,
     #[serde(with = "ser_b64")]
     pub rand_nonce: RandValue,
+    /// Layout of the signed buffer. Move tokens of nodes that predate signature buffer versions
+    /// (And move tokens stored by them) use the legacy layout (0).
+    #[serde(default)]
+    pub signature_version: u32,
     #[serde(with = "ser_b64")]
     pub new_token: Signature,
 }
@@ -324,6 +502,7 @@ pub struct UnsignedMoveToken<B = NetAddress> {
     pub info_hash: HashResult,
     #[serde(with = "ser_b64")]
     pub rand_nonce: RandValue,
+    pub signature_version: u32,
 }
 
 impl<B> Into<UnsignedMoveToken<B>> for MoveToken<B> {
@@ -335,6 +514,7 @@ impl<B> Into<UnsignedMoveToken<B>> for MoveToken<B> {
             opt_active_currencies: self.opt_active_currencies,
             info_hash: self.info_hash,
             rand_nonce: self.rand_nonce,
+            signature_version: self.signature_version,
         }
     }
 }
@@ -393,6 +573,103 @@ pub struct MoveTokenRequest<B = NetAddress> {
     pub move_token: MoveToken<B>,
     // Do we want the remote side to return the token:
     pub token_wanted: bool,
+    /// Capabilities of the sender. Always empty when sent by nodes that predate capabilities.
+    #[capnp_conv(with = OptCapabilities)]
+    pub opt_capabilities: Option<FriendCapabilities>,
+}
+
+#[capnp_conv(crate::funder_capnp::move_token_request::opt_capabilities)]
+#[derive(Arbitrary, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum OptCapabilities {
+    Empty,
+    Capabilities(FriendCapabilities),
+}
+
+impl From<Option<FriendCapabilities>> for OptCapabilities {
+    fn from(opt: Option<FriendCapabilities>) -> Self {
+        match opt {
+            Some(capabilities) => OptCapabilities::Capabilities(capabilities),
+            None => OptCapabilities::Empty,
+        }
+    }
+}
+
+impl From<OptCapabilities> for Option<FriendCapabilities> {
+    fn from(opt: OptCapabilities) -> Self {
+        match opt {
+            OptCapabilities::Capabilities(capabilities) => Some(capabilities),
+            OptCapabilities::Empty => None,
+        }
+    }
+}
+
+/// Optional protocol features. Each feature is a bit in `FriendCapabilities::features`.
+/// A feature may only be used with a friend if both sides support it.
+pub mod friend_features {
+    /// Requests may be exchanged to another currency on the way.
+    pub const CURRENCY_EXCHANGE: u64 = 1;
+    /// Responses may give change back to the buyer.
+    pub const CHANGE: u64 = 1 << 1;
+}
+
+/// Capabilities of a node, attached to the move token requests it sends to its friends.
+#[capnp_conv(crate::funder_capnp::friend_capabilities)]
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FriendCapabilities {
+    /// Currencies we are willing to use with the remote friend
+    pub currencies: Vec<Currency>,
+    /// Bitmap of supported optional features (See `friend_features`)
+    pub features: u64,
+    /// Maximum size of a message we are willing to receive
+    pub max_message_size: u64,
+    /// Latest version of the friend protocol we support (See `FRIEND_PROTOCOL_VERSION`)
+    pub protocol_version: u32,
+}
+
+impl FriendCapabilities {
+    pub fn supports(&self, feature: u64) -> bool {
+        self.features & feature == feature
+    }
+
+    /// Can `operation` be sent? Should be checked against the capabilities supported by both
+    /// sides (See `intersect()`).
+    pub fn supports_op(&self, operation: &FriendTcOp) -> bool {
+        self.protocol_version >= operation.min_protocol_version()
+            && self.supports(operation.required_features())
+    }
+
+    /// Capabilities supported by both sides
+    pub fn intersect(&self, other: &FriendCapabilities) -> FriendCapabilities {
+        FriendCapabilities {
+            currencies: self
+                .currencies
+                .iter()
+                .filter(|currency| other.currencies.contains(currency))
+                .cloned()
+                .collect(),
+            features: self.features & other.features,
+            max_message_size: std::cmp::min(self.max_message_size, other.max_message_size),
+            protocol_version: std::cmp::min(self.protocol_version, other.protocol_version),
+        }
+    }
+}
+
+/// A statement that a node has replaced its identity key.
+/// Signed by both the old and the new keys, proving that the two keys belong to the same node.
+#[capnp_conv(crate::funder_capnp::key_rotation)]
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyRotation {
+    #[serde(with = "ser_b64")]
+    pub old_public_key: PublicKey,
+    #[serde(with = "ser_b64")]
+    pub new_public_key: PublicKey,
+    /// Signature by the old key
+    #[serde(with = "ser_b64")]
+    pub old_signature: Signature,
+    /// Signature by the new key
+    #[serde(with = "ser_b64")]
+    pub new_signature: Signature,
 }
 
 #[capnp_conv(crate::funder_capnp::friend_message)]
@@ -401,6 +678,7 @@ pub struct MoveTokenRequest<B = NetAddress> {
 pub enum FriendMessage<B = NetAddress> {
     MoveTokenRequest(MoveTokenRequest<B>),
     InconsistencyError(ResetTerms),
+    KeyRotation(KeyRotation),
 }
 
 /// A `Receipt` is received if a `RequestSendFunds` is successful.
@@ -426,6 +704,11 @@ pub struct Receipt {
     #[capnp_conv(with = Wrapper<u128>)]
     #[serde(with = "ser_string")]
     pub total_dest_payment: u128,
+    /// Amount of credits out of `dest_payment` that the seller gave back.
+    /// The seller received `dest_payment - change` credits.
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(default, with = "ser_string")]
+    pub change: u128,
     #[serde(with = "ser_b64")]
     pub signature: Signature,
     /*
@@ -435,6 +718,7 @@ pub struct Receipt {
     #   srcHashedLock ||
     #   dstHashedLock ||
     #   isComplete ||       (Assumed to be True)
+    #   change ||
     #   destPayment ||
     #   totalDestPayment ||
     #   invoiceId ||
@@ -446,7 +730,11 @@ pub struct Receipt {
 #[derive(Arbitrary, Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 pub enum TransactionStage {
     Request,
-    Response(#[serde(with = "ser_b64")] HashedLock, bool), // inner: (dest_hashed_lock, is_complete)
+    Response(
+        #[serde(with = "ser_b64")] HashedLock,
+        bool,
+        #[serde(default, with = "ser_string")] u128,
+    ), // inner: (dest_hashed_lock, is_complete, change)
 }
 
 #[derive(Arbitrary, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
@@ -464,12 +752,48 @@ pub struct PendingTransaction {
     pub left_fees: u128,
     #[serde(with = "ser_b64")]
     pub src_hashed_lock: HashedLock,
+    #[serde(default)]
+    pub expiry_ticks: u64,
+    #[serde(default)]
+    pub refund_ticks: u64,
+    pub opt_exchange: Option<CurrencyExchange>,
     pub stage: TransactionStage,
 }
 
 // ==================================================================
 // ==================================================================
 
+impl PendingTransaction {
+    /// Payment of the invoice, as signed by the destination.
+    /// For an exchanged transaction this is the payment after the exchange.
+    pub fn signed_dest_payment(&self) -> u128 {
+        match &self.opt_exchange {
+            Some(currency_exchange) => currency_exchange.dest_payment,
+            None => self.dest_payment,
+        }
+    }
+
+    /// Convert `change` given back by the destination to credits of the channel this transaction
+    /// is pending at. For an exchanged transaction, change is given in the currency of the
+    /// destination, and is converted back in the proportion of the exchange (Rounded down).
+    ///
+    /// Assumes that `change` does not exceed `signed_dest_payment()`.
+    pub fn channel_change(&self, change: u128) -> u128 {
+        match &self.opt_exchange {
+            Some(currency_exchange) => {
+                if currency_exchange.dest_payment == 0 {
+                    return 0;
+                }
+                let res = BigUint::from(change) * BigUint::from(self.dest_payment)
+                    / BigUint::from(currency_exchange.dest_payment);
+                // The result never exceeds self.dest_payment:
+                res.to_u128().unwrap()
+            }
+            None => change,
+        }
+    }
+}
+
 impl FriendsRoute {
     pub fn len(&self) -> usize {
         self.public_keys.len()
@@ -640,6 +964,67 @@ impl Rate {
     }
 }
 
+/// The rate in which a node exchanges credits of one currency to credits of another currency:
+/// `to_credits` credits are given for every `from_credits` credits received.
+#[capnp_conv(crate::common_capnp::exchange_rate)]
+#[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExchangeRate {
+    pub from_credits: u64,
+    pub to_credits: u64,
+}
+
+impl ExchangeRate {
+    /// Amount of credits given in exchange for `credits` credits (Rounded down).
+    /// Returns None if `from_credits` is 0, or if the result does not fit in u128.
+    pub fn convert(&self, credits: u128) -> Option<u128> {
+        if self.from_credits == 0 {
+            return None;
+        }
+        let res = BigUint::from(credits) * BigUint::from(self.to_credits)
+            / BigUint::from(self.from_credits);
+        res.to_u128()
+    }
+}
+
+/// A limit over the amount of credits that may be sent to a friend (For a certain currency)
+/// during a window of `window_ticks` timer ticks.
+/// Credits sent include both our own payments and requests forwarded through the friend.
+#[capnp_conv(crate::common_capnp::max_outflow)]
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaxOutflow {
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub max_outflow: u128,
+    pub window_ticks: u64,
+}
+
+#[capnp_conv(crate::common_capnp::opt_max_outflow)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptMaxOutflow {
+    MaxOutflow(MaxOutflow),
+    Empty,
+}
+
+// TODO: Replace with a macro:
+impl From<Option<MaxOutflow>> for OptMaxOutflow {
+    fn from(opt: Option<MaxOutflow>) -> Self {
+        match opt {
+            Some(max_outflow) => OptMaxOutflow::MaxOutflow(max_outflow),
+            None => OptMaxOutflow::Empty,
+        }
+    }
+}
+
+impl From<OptMaxOutflow> for Option<MaxOutflow> {
+    fn from(opt: OptMaxOutflow) -> Self {
+        match opt {
+            OptMaxOutflow::MaxOutflow(max_outflow) => Some(max_outflow),
+            OptMaxOutflow::Empty => None,
+        }
+    }
+}
+
 #[capnp_conv(crate::app_server_capnp::add_friend)]
 #[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AddFriend<B = NetAddress> {
@@ -654,6 +1039,13 @@ pub struct RemoveFriend {
     pub friend_public_key: PublicKey,
 }
 
+/// Close the channel with a friend gracefully: Stop accepting new requests through the friend,
+/// wait for all pending requests to settle, and then produce a closing statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DrainFriend {
+    pub friend_public_key: PublicKey,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SetFriendCurrencyRequestsStatus {
     pub friend_public_key: PublicKey,
@@ -668,46 +1060,159 @@ pub struct SetFriendStatus {
 }
 
 #[capnp_conv(crate::app_server_capnp::set_friend_currency_max_debt)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SetFriendCurrencyMaxDebt {
+    #[serde(with = "ser_b64")]
     pub friend_public_key: PublicKey,
     pub currency: Currency,
     #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
     pub remote_max_debt: u128,
 }
 
-#[capnp_conv(crate::app_server_capnp::set_friend_name)]
+/// Limit the credits sent to a friend during a window of time.
+/// `opt_max_outflow = None` removes the limit.
+#[capnp_conv(crate::app_server_capnp::set_friend_max_outflow)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetFriendMaxOutflow {
+    #[serde(with = "ser_b64")]
+    pub friend_public_key: PublicKey,
+    pub currency: Currency,
+    #[capnp_conv(with = OptMaxOutflow)]
+    pub opt_max_outflow: Option<MaxOutflow>,
+}
+
+/// Set the rate for exchanging credits received in `from_currency` to credits sent in
+/// `to_currency`. `opt_rate = None` stops exchanging between the two currencies.
+#[capnp_conv(crate::app_server_capnp::set_exchange_rate)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetExchangeRate {
+    pub from_currency: Currency,
+    pub to_currency: Currency,
+    #[capnp_conv(with = OptExchangeRate)]
+    pub opt_rate: Option<ExchangeRate>,
+}
+
+#[capnp_conv(crate::app_server_capnp::set_exchange_rate::opt_rate)]
 #[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptExchangeRate {
+    Rate(ExchangeRate),
+    Empty,
+}
+
+impl From<Option<ExchangeRate>> for OptExchangeRate {
+    fn from(opt: Option<ExchangeRate>) -> Self {
+        match opt {
+            Some(rate) => OptExchangeRate::Rate(rate),
+            None => OptExchangeRate::Empty,
+        }
+    }
+}
+
+impl From<OptExchangeRate> for Option<ExchangeRate> {
+    fn from(opt: OptExchangeRate) -> Self {
+        match opt {
+            OptExchangeRate::Rate(rate) => Some(rate),
+            OptExchangeRate::Empty => None,
+        }
+    }
+}
+
+#[capnp_conv(crate::app_server_capnp::set_friend_name)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SetFriendName {
+    #[serde(with = "ser_b64")]
     pub friend_public_key: PublicKey,
     pub name: String,
 }
 
 #[capnp_conv(crate::app_server_capnp::set_friend_relays)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SetFriendRelays<B = NetAddress> {
+    #[serde(with = "ser_b64")]
     pub friend_public_key: PublicKey,
     pub relays: Vec<RelayAddress<B>>,
 }
 
 #[capnp_conv(crate::app_server_capnp::reset_friend_channel)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ResetFriendChannel {
+    #[serde(with = "ser_b64")]
     pub friend_public_key: PublicKey,
+    #[serde(with = "ser_b64")]
     pub reset_token: Signature,
 }
 
-#[capnp_conv(crate::app_server_capnp::set_friend_currency_rate)]
+/// Export the latest signed state of the token channel with a friend
+#[capnp_conv(crate::app_server_capnp::export_channel_proof)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportChannelProof {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    #[serde(with = "ser_b64")]
+    pub friend_public_key: PublicKey,
+}
+
+/// Calculate the fees and the credits frozen on every hop of a payment through a route, and
+/// check if the payment would be rejected by the local node, without sending anything.
+#[capnp_conv(crate::app_server_capnp::quote_payment)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotePayment {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub currency: Currency,
+    pub route: FriendsRoute,
+    /// The rate of every node on the route, except for the first and the last (Ordered as in the
+    /// route).
+    pub mediator_rates: Vec<Rate>,
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub dest_payment: u128,
+    /// Fees cap for every node that forwards the payment, as in `CreatePayment`
+    #[capnp_conv(with = QuoteOptMaxFeePerHop)]
+    #[serde(with = "ser_option_string")]
+    pub opt_max_fee_per_hop: Option<u128>,
+}
+
+#[capnp_conv(crate::app_server_capnp::quote_payment::opt_max_fee_per_hop)]
 #[derive(Debug, Clone, PartialEq, Eq)]
+enum QuoteOptMaxFeePerHop {
+    Empty,
+    #[capnp_conv(with = Wrapper<u128>)]
+    MaxFeePerHop(u128),
+}
+
+impl From<Option<u128>> for QuoteOptMaxFeePerHop {
+    fn from(opt: Option<u128>) -> Self {
+        match opt {
+            Some(max_fee_per_hop) => QuoteOptMaxFeePerHop::MaxFeePerHop(max_fee_per_hop),
+            None => QuoteOptMaxFeePerHop::Empty,
+        }
+    }
+}
+
+impl From<QuoteOptMaxFeePerHop> for Option<u128> {
+    fn from(opt: QuoteOptMaxFeePerHop) -> Self {
+        match opt {
+            QuoteOptMaxFeePerHop::MaxFeePerHop(max_fee_per_hop) => Some(max_fee_per_hop),
+            QuoteOptMaxFeePerHop::Empty => None,
+        }
+    }
+}
+
+#[capnp_conv(crate::app_server_capnp::set_friend_currency_rate)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SetFriendCurrencyRate {
+    #[serde(with = "ser_b64")]
     pub friend_public_key: PublicKey,
     pub currency: Currency,
     pub rate: Rate,
 }
 
 #[capnp_conv(crate::app_server_capnp::remove_friend_currency)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RemoveFriendCurrency {
+    #[serde(with = "ser_b64")]
     pub friend_public_key: PublicKey,
     pub currency: Currency,
 }
@@ -728,52 +1233,255 @@ pub struct ReceiptAck {
 
 /// Start a payment, possibly by paying through multiple routes.
 #[capnp_conv(crate::app_server_capnp::create_payment)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CreatePayment {
     /// payment_id is a randomly generated value (by the user), allowing the user to refer to a
     /// certain payment.
+    #[serde(with = "ser_b64")]
     pub payment_id: PaymentId,
+    #[serde(with = "ser_b64")]
     pub invoice_id: InvoiceId,
     pub currency: Currency,
     #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
     pub total_dest_payment: u128,
+    #[serde(with = "ser_b64")]
     pub dest_public_key: PublicKey,
+    /// The fees of every transaction of this payment may not exceed this amount for every node
+    /// that forwards the transaction. Transactions with larger fees are rejected.
+    /// None means that fees are not limited.
+    #[capnp_conv(with = OptMaxFeePerHop)]
+    #[serde(with = "ser_option_string")]
+    pub opt_max_fee_per_hop: Option<u128>,
+}
+
+#[capnp_conv(crate::app_server_capnp::create_payment::opt_max_fee_per_hop)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptMaxFeePerHop {
+    Empty,
+    #[capnp_conv(with = Wrapper<u128>)]
+    MaxFeePerHop(u128),
+}
+
+impl From<Option<u128>> for OptMaxFeePerHop {
+    fn from(opt: Option<u128>) -> Self {
+        match opt {
+            Some(max_fee_per_hop) => OptMaxFeePerHop::MaxFeePerHop(max_fee_per_hop),
+            None => OptMaxFeePerHop::Empty,
+        }
+    }
+}
+
+impl From<OptMaxFeePerHop> for Option<u128> {
+    fn from(opt: OptMaxFeePerHop) -> Self {
+        match opt {
+            OptMaxFeePerHop::MaxFeePerHop(max_fee_per_hop) => Some(max_fee_per_hop),
+            OptMaxFeePerHop::Empty => None,
+        }
+    }
+}
+
+#[capnp_conv(crate::app_server_capnp::create_transaction::opt_refund_ticks)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptRefundTicks {
+    Empty,
+    RefundTicks(u64),
+}
+
+impl From<Option<u64>> for OptRefundTicks {
+    fn from(opt: Option<u64>) -> Self {
+        match opt {
+            Some(refund_ticks) => OptRefundTicks::RefundTicks(refund_ticks),
+            None => OptRefundTicks::Empty,
+        }
+    }
+}
+
+impl From<OptRefundTicks> for Option<u64> {
+    fn from(opt: OptRefundTicks) -> Self {
+        match opt {
+            OptRefundTicks::RefundTicks(refund_ticks) => Some(refund_ticks),
+            OptRefundTicks::Empty => None,
+        }
+    }
 }
 
 /// Start a payment, possibly by paying through multiple routes.
 #[capnp_conv(crate::app_server_capnp::create_transaction)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CreateTransaction {
     /// A payment id of an existing payment.
+    #[serde(with = "ser_b64")]
     pub payment_id: PaymentId,
     /// Randomly generated request_id (by the user),
     /// allows the user to refer to this request later.
+    #[serde(with = "ser_b64")]
     pub request_id: Uid,
     pub route: FriendsRoute,
     #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
     pub dest_payment: u128,
     #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub fees: u128,
+    /// Amount of ticks the destination has to reveal its lock, before the transaction is
+    /// refunded automatically. None means that the transaction is never refunded.
+    #[capnp_conv(with = OptRefundTicks)]
+    pub opt_refund_ticks: Option<u64>,
+}
+
+/// A transaction through a route that exchanges currencies on the way.
+/// The payment is in the currency of the invoice, while credits are sent to the first friend on
+/// the route in `currency`. The node on the route that exchanges `currency` to the currency of the
+/// payment gives `dest_payment` credits for the `exchanged_payment` credits it receives.
+#[capnp_conv(crate::app_server_capnp::create_exchange_transaction)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreateExchangeTransaction {
+    /// A payment id of an existing payment.
+    #[serde(with = "ser_b64")]
+    pub payment_id: PaymentId,
+    /// Randomly generated request_id (by the user),
+    /// allows the user to refer to this request later.
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub route: FriendsRoute,
+    /// Currency of the channel with the first friend on the route
+    pub currency: Currency,
+    /// Credits (In `currency`) given to the exchanging node
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub exchanged_payment: u128,
+    /// Credits (In the currency of the payment) given to the destination
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub dest_payment: u128,
+    /// Fees (In `currency`). The exchanging node exchanges the fees left for the mediators after
+    /// it.
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
     pub fees: u128,
+    /// Amount of ticks the destination has to reveal its lock, before the transaction is
+    /// refunded automatically. 0 means that the transaction is never refunded.
+    pub refund_ticks: u64,
+}
+
+/// Move credits between two friends by paying ourselves along a cycle route:
+/// `[local, to, ..., from, local]`. The node opens an invoice and a payment for the self payment,
+/// and commits the invoice by itself once the transaction completes.
+/// The result is sent back as a `TransactionResult` with `request_id`, and the payment is closed
+/// like any other payment.
+#[capnp_conv(crate::app_server_capnp::rebalance)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rebalance {
+    /// A new payment id, for the self payment.
+    #[serde(with = "ser_b64")]
+    pub payment_id: PaymentId,
+    /// A new invoice id, for the self payment.
+    #[serde(with = "ser_b64")]
+    pub invoice_id: InvoiceId,
+    /// Randomly generated request_id (by the user),
+    /// allows the user to refer to this request later.
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub currency: Currency,
+    /// A cycle route that starts and ends with the local public key
+    pub route: FriendsRoute,
+    /// Credits paid to ourselves
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub amount: u128,
+    /// Fees for the mediators along the route
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub fees: u128,
+}
+
+/// An alternative route for a transaction that failed and is waiting to be retried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryTransaction {
+    /// request_id of the original transaction
+    pub request_id: Uid,
+    /// An alternative route and the fees to pay along it,
+    /// or None if no alternative route could be found.
+    pub opt_route: Option<(FriendsRoute, u128)>, // (route, fees)
+}
+
+/// Sent by the Funder when a locally originated transaction failed, but may still be retried
+/// through an alternative route.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestAlternativeRoute {
+    /// request_id of the failed transaction
+    pub request_id: Uid,
+    /// request_id to use for the route request sent to the index client.
+    /// Differs from the request_id of the transaction, to avoid clashing with route requests
+    /// issued by apps.
+    pub routes_request_id: Uid,
+    pub currency: Currency,
+    /// Destination of the transaction
+    pub dest_public_key: PublicKey,
+    /// Amount of credits the destination should receive
+    pub dest_payment: u128,
+    /// Maximum amount of fees we may pay along the alternative route.
+    /// (The fees approved for the original route)
+    pub max_fees: u128,
+    /// The friend that reported the failure.
+    /// The alternative route should not go through this friend.
+    pub exclude_friend: PublicKey,
+}
+
+#[capnp_conv(crate::app_server_capnp::add_invoice::opt_expiry_ticks)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptExpiryTicks {
+    Empty,
+    ExpiryTicks(u64),
+}
+
+impl From<Option<u64>> for OptExpiryTicks {
+    fn from(opt: Option<u64>) -> Self {
+        match opt {
+            Some(expiry_ticks) => OptExpiryTicks::ExpiryTicks(expiry_ticks),
+            None => OptExpiryTicks::Empty,
+        }
+    }
+}
+
+impl From<OptExpiryTicks> for Option<u64> {
+    fn from(opt: OptExpiryTicks) -> Self {
+        match opt {
+            OptExpiryTicks::ExpiryTicks(expiry_ticks) => Some(expiry_ticks),
+            OptExpiryTicks::Empty => None,
+        }
+    }
 }
 
 /// Start an invoice (A request for payment).
 #[capnp_conv(crate::app_server_capnp::add_invoice)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AddInvoice {
     /// Randomly generated invoice_id, allows to refer to this invoice.
+    #[serde(with = "ser_b64")]
     pub invoice_id: InvoiceId,
     /// Currency in use
     pub currency: Currency,
     /// Total amount of credits to be paid.
     #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
     pub total_dest_payment: u128,
+    /// Amount of ticks until the invoice is canceled automatically.
+    /// Requests for an expired invoice are failed.
+    /// Must be positive. The countdown is not persistent: it restarts whenever the node is
+    /// restarted.
+    #[capnp_conv(with = OptExpiryTicks)]
+    pub opt_expiry_ticks: Option<u64>,
 }
 
 /// Start an invoice (A request for payment).
 #[capnp_conv(crate::app_server_capnp::ack_close_payment)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AckClosePayment {
+    #[serde(with = "ser_b64")]
     pub payment_id: PaymentId,
+    #[serde(with = "ser_b64")]
     pub ack_uid: Uid,
 }
 
@@ -786,21 +1494,44 @@ pub enum FunderControl<B> {
     RemoveFriend(RemoveFriend),
     SetFriendStatus(SetFriendStatus),
     SetFriendCurrencyMaxDebt(SetFriendCurrencyMaxDebt),
+    SetFriendMaxOutflow(SetFriendMaxOutflow),
     SetFriendRelays(SetFriendRelays<B>),
     SetFriendName(SetFriendName),
     SetFriendCurrencyRate(SetFriendCurrencyRate),
     SetFriendCurrencyRequestsStatus(SetFriendCurrencyRequestsStatus),
     RemoveFriendCurrency(RemoveFriendCurrency),
     ResetFriendChannel(ResetFriendChannel),
+    DrainFriend(DrainFriend),
     // Buyer API:
     CreatePayment(CreatePayment),
     CreateTransaction(CreateTransaction),
+    CreateExchangeTransaction(CreateExchangeTransaction),
+    RetryTransaction(RetryTransaction),
     RequestClosePayment(PaymentId),
     AckClosePayment(AckClosePayment),
     // Seller API:
     AddInvoice(AddInvoice),
     CancelInvoice(InvoiceId),
     CommitInvoice(Commit),
+    // Configuration:
+    SetConfig(SetFunderConfig),
+    SetExchangeRate(SetExchangeRate),
+    // Analysis:
+    RequestExposure(Uid),
+    QuotePayment(QuotePayment),
+    // Rebalancing:
+    Rebalance(Rebalance),
+    // Disputes:
+    ExportChannelProof(ExportChannelProof),
+    // Identity:
+    RotateKey(KeyRotation),
+}
+
+/// A funder parameter that can be changed while the funder is running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetFunderConfig {
+    MaxOperationsInBatch(usize),
+    MaxPendingUserRequests(usize),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -818,52 +1549,249 @@ impl<B> FunderIncomingControl<B> {
     }
 }
 
+/// The reason for rejecting a transaction before it is sent
+#[capnp_conv(crate::app_server_capnp::transaction_rejection)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransactionRejection {
+    /// The route is longer than the maximum route length of the node (Given)
+    RouteTooLong(u32),
+    /// The fees are larger than the cap of the payment for this route (Given)
+    #[capnp_conv(with = Wrapper<u128>)]
+    FeesExceedCap(#[serde(with = "ser_string")] u128),
+}
+
 #[allow(clippy::large_enum_variant)]
 #[capnp_conv(crate::app_server_capnp::request_result)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RequestResult {
     Complete(Commit),
     Success,
     // TODO: Should we add more information to the failure here?
     Failure,
+    /// The transaction was rejected by the node, and was never sent
+    Rejected(TransactionRejection),
 }
 
 #[capnp_conv(crate::app_server_capnp::transaction_result)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TransactionResult {
+    #[serde(with = "ser_b64")]
     pub request_id: Uid,
     pub result: RequestResult,
 }
 
 #[capnp_conv(crate::app_server_capnp::payment_status_success)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PaymentStatusSuccess {
     pub receipt: Receipt,
+    #[serde(with = "ser_b64")]
     pub ack_uid: Uid,
 }
 
 #[allow(clippy::large_enum_variant)]
 #[capnp_conv(crate::app_server_capnp::payment_status)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PaymentStatus {
     PaymentNotFound,
     Success(PaymentStatusSuccess),
-    Canceled(Uid), // ack_id
+    Canceled(#[serde(with = "ser_b64")] Uid), // ack_id
 }
 
 #[capnp_conv(crate::app_server_capnp::response_close_payment)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ResponseClosePayment {
+    #[serde(with = "ser_b64")]
     pub payment_id: PaymentId,
     pub status: PaymentStatus,
 }
 
+/// Balance with a friend (in one currency) after all in-flight requests are resolved.
+/// A positive balance means that the friend owes us credits.
+#[capnp_conv(crate::app_server_capnp::friend_currency_exposure)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FriendCurrencyExposure {
+    pub currency: Currency,
+    /// Balance if all in-flight requests fail
+    #[capnp_conv(with = Wrapper<i128>)]
+    #[serde(with = "ser_string")]
+    pub all_fail_balance: i128,
+    /// Balance if all in-flight requests succeed
+    #[capnp_conv(with = Wrapper<i128>)]
+    #[serde(with = "ser_string")]
+    pub all_succeed_balance: i128,
+}
+
+#[capnp_conv(crate::app_server_capnp::friend_exposure)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FriendExposure {
+    #[serde(with = "ser_b64")]
+    pub friend_public_key: PublicKey,
+    pub currencies: Vec<FriendCurrencyExposure>,
+}
+
+/// Total amount of credits owed to us by all friends (in one currency).
+/// Only positive balances are counted, as credits we owe one friend do not cover for credits
+/// another friend owes us.
+#[capnp_conv(crate::app_server_capnp::currency_exposure)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CurrencyExposure {
+    pub currency: Currency,
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub all_fail_exposure: u128,
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub all_succeed_exposure: u128,
+}
+
+#[capnp_conv(crate::app_server_capnp::response_exposure)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponseExposure {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub friends: Vec<FriendExposure>,
+    pub totals: Vec<CurrencyExposure>,
+}
+
+#[capnp_conv(crate::app_server_capnp::channel_proof_result)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChannelProofResult {
+    /// A serialized `ChannelProof`. Can be verified without trusting the exporting node.
+    Found(#[serde(with = "ser_b64")] Vec<u8>),
+    /// There is no friend with the requested public key
+    NotFound,
+}
+
+/// A response to `FunderControl::ExportChannelProof`
+#[capnp_conv(crate::app_server_capnp::response_channel_proof)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponseChannelProof {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    #[serde(with = "ser_b64")]
+    pub friend_public_key: PublicKey,
+    pub result: ChannelProofResult,
+}
+
+/// The reason the local node would reject a quoted payment
+#[capnp_conv(crate::app_server_capnp::quote_rejection)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QuoteRejection {
+    /// We are not the first node on the route, or the route is not valid
+    InvalidRoute,
+    /// The amount of rates does not match the amount of mediators on the route
+    InvalidMediatorRates,
+    /// The fees or the frozen credits can not be represented
+    FeesOverflow,
+    Rejected(TransactionRejection),
+    /// The first friend on the route is not ready to forward requests in this currency
+    FriendNotReady,
+    PendingUserRequestsFull,
+    MaxOutflowExceeded,
+}
+
+/// Credits frozen on one hop of a route
+#[capnp_conv(crate::app_server_capnp::hop_freeze)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HopFreeze {
+    /// The receiving node of the hop
+    #[serde(with = "ser_b64")]
+    pub to_public_key: PublicKey,
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub frozen_credits: u128,
+}
+
+#[capnp_conv(crate::app_server_capnp::response_quote_payment::opt_rejection)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum OptQuoteRejection {
+    Rejection(QuoteRejection),
+    Empty,
+}
+
+impl From<Option<QuoteRejection>> for OptQuoteRejection {
+    fn from(opt: Option<QuoteRejection>) -> Self {
+        match opt {
+            Some(rejection) => OptQuoteRejection::Rejection(rejection),
+            None => OptQuoteRejection::Empty,
+        }
+    }
+}
+
+impl From<OptQuoteRejection> for Option<QuoteRejection> {
+    fn from(opt: OptQuoteRejection) -> Self {
+        match opt {
+            OptQuoteRejection::Rejection(rejection) => Some(rejection),
+            OptQuoteRejection::Empty => None,
+        }
+    }
+}
+
+/// A response to `FunderControl::QuotePayment`
+#[capnp_conv(crate::app_server_capnp::response_quote_payment)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponseQuotePayment {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    /// Total fees paid to the mediators on the route
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub fees: u128,
+    /// Credits frozen on every hop of the route, in order. Empty if the fees could not be
+    /// calculated.
+    pub hop_freezes: Vec<HopFreeze>,
+    /// Set if the local node would reject the payment
+    #[capnp_conv(with = OptQuoteRejection)]
+    pub opt_rejection: Option<QuoteRejection>,
+}
+
+/// The reason a request of an app was rejected
+#[capnp_conv(crate::app_server_capnp::request_error_code)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RequestErrorCode {
+    /// The request is malformed
+    InvalidRequest,
+    /// The app is not allowed to issue the request
+    Unauthorized,
+    FriendNotFound,
+    /// The currency is not configured for the friend
+    CurrencyNotFound,
+    /// The payment, invoice or transaction does not exist
+    NotFound,
+    AlreadyExists,
+    /// A configured limit does not allow the request
+    LimitReached,
+    /// The request can not be handled in the current state (For example, the friend is not
+    /// ready)
+    NotReady,
+    /// The transaction violates the limits set for outgoing transactions
+    Rejected(TransactionRejection),
+}
+
+/// Sent to an app when a request could not be handled.
+/// Always sent before the acknowledgement of the request.
+#[capnp_conv(crate::app_server_capnp::request_error)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestError {
+    /// The rejected request
+    #[serde(with = "ser_b64")]
+    pub app_request_id: Uid,
+    pub error_code: RequestErrorCode,
+    /// A human readable description of the error
+    pub detail: String,
+}
+
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum FunderOutgoingControl<B: Clone> {
     TransactionResult(TransactionResult),
     ResponseClosePayment(ResponseClosePayment),
     ReportMutations(FunderReportMutations<B>),
+    RequestAlternativeRoute(RequestAlternativeRoute),
+    ResponseExposure(ResponseExposure),
+    ResponseChannelProof(ResponseChannelProof),
+    ResponseQuotePayment(ResponseQuotePayment),
+    RequestError(RequestError),
 }
 
 impl Currency {
@@ -961,6 +1889,193 @@ mod tests {
         assert_eq!(is_route_part_valid(&[1, 2, 3, 2, 4]), false); // should have no repetitions in a partial route
     }
 
+    #[test]
+    fn test_friend_capabilities_intersect() {
+        let fst = Currency::try_from("FST".to_owned()).unwrap();
+        let fst2 = Currency::try_from("FST2".to_owned()).unwrap();
+
+        let local = FriendCapabilities {
+            currencies: vec![fst.clone(), fst2.clone()],
+            features: friend_features::CURRENCY_EXCHANGE | friend_features::CHANGE,
+            max_message_size: 0x1000,
+            protocol_version: 2,
+        };
+        let remote = FriendCapabilities {
+            currencies: vec![fst2.clone()],
+            features: friend_features::CURRENCY_EXCHANGE,
+            max_message_size: 0x800,
+            protocol_version: 1,
+        };
+
+        let common = local.intersect(&remote);
+        assert_eq!(common.currencies, vec![fst2]);
+        assert!(common.supports(friend_features::CURRENCY_EXCHANGE));
+        assert!(!common.supports(friend_features::CHANGE));
+        assert_eq!(common.max_message_size, 0x800);
+        assert_eq!(common.protocol_version, 1);
+
+        // Operations of the initial friend protocol are always supported:
+        let cancel_op = FriendTcOp::CancelSendFunds(CancelSendFundsOp {
+            request_id: Uid::from(&[0; Uid::len()]),
+        });
+        assert!(common.supports_op(&cancel_op));
+
+        // Refunds were added in version 1 of the friend protocol:
+        let refund_op = FriendTcOp::RefundSendFunds(RefundSendFundsOp {
+            request_id: Uid::from(&[0; Uid::len()]),
+        });
+        assert!(common.supports_op(&refund_op));
+        let old_remote = FriendCapabilities {
+            protocol_version: 0,
+            ..remote
+        };
+        assert!(!local.intersect(&old_remote).supports_op(&refund_op));
+
+        // Currency exchanges were added in version 1 of the friend protocol:
+        let request_send_funds = RequestSendFundsOp {
+            request_id: Uid::from(&[0; Uid::len()]),
+            src_hashed_lock: HashedLock::from(&[1; HashedLock::len()]),
+            route: FriendsRoute {
+                public_keys: vec![
+                    PublicKey::from(&[2; PublicKey::len()]),
+                    PublicKey::from(&[3; PublicKey::len()]),
+                ],
+            },
+            dest_payment: 10,
+            total_dest_payment: 10,
+            invoice_id: InvoiceId::from(&[4; InvoiceId::len()]),
+            left_fees: 0,
+            expiry_ticks: 0,
+            refund_ticks: 0,
+            opt_exchange: None,
+        };
+        let request_op = FriendTcOp::RequestSendFunds(request_send_funds.clone());
+        assert!(local.intersect(&old_remote).supports_op(&request_op));
+
+        let exchange_request_op = FriendTcOp::RequestSendFunds(RequestSendFundsOp {
+            opt_exchange: Some(CurrencyExchange {
+                dest_currency: fst.clone(),
+                dest_payment: 5,
+                total_dest_payment: 5,
+            }),
+            ..request_send_funds
+        });
+        assert!(common.supports_op(&exchange_request_op));
+        assert!(!local
+            .intersect(&old_remote)
+            .supports_op(&exchange_request_op));
+        // Both sides must also support the currency exchange feature:
+        let no_features_remote = FriendCapabilities {
+            features: 0,
+            ..common
+        };
+        assert!(!local
+            .intersect(&no_features_remote)
+            .supports_op(&exchange_request_op));
+    }
+
+    #[test]
+    fn test_exchange_rate_convert() {
+        let exchange_rate = ExchangeRate {
+            from_credits: 3,
+            to_credits: 2,
+        };
+        assert_eq!(exchange_rate.convert(0), Some(0));
+        assert_eq!(exchange_rate.convert(9), Some(6));
+        // Rounded down:
+        assert_eq!(exchange_rate.convert(10), Some(6));
+
+        let exchange_rate = ExchangeRate {
+            from_credits: 1,
+            to_credits: 2,
+        };
+        assert_eq!(exchange_rate.convert(u128::max_value()), None);
+
+        let exchange_rate = ExchangeRate {
+            from_credits: 0,
+            to_credits: 2,
+        };
+        assert_eq!(exchange_rate.convert(5), None);
+    }
+
+    #[test]
+    fn test_pending_transaction_channel_change() {
+        let mut pending_transaction = PendingTransaction {
+            request_id: Uid::from(&[1; Uid::len()]),
+            route: FriendsRoute {
+                public_keys: Vec::new(),
+            },
+            dest_payment: 15,
+            total_dest_payment: 15,
+            invoice_id: InvoiceId::from(&[2; InvoiceId::len()]),
+            left_fees: 5,
+            src_hashed_lock: HashedLock::from(&[3; HashedLock::len()]),
+            expiry_ticks: 0,
+            refund_ticks: 0,
+            opt_exchange: None,
+            stage: TransactionStage::Request,
+        };
+        assert_eq!(pending_transaction.signed_dest_payment(), 15);
+        assert_eq!(pending_transaction.channel_change(4), 4);
+
+        pending_transaction.opt_exchange = Some(CurrencyExchange {
+            dest_currency: Currency::try_from("FST2".to_owned()).unwrap(),
+            dest_payment: 30,
+            total_dest_payment: 60,
+        });
+        assert_eq!(pending_transaction.signed_dest_payment(), 30);
+        assert_eq!(pending_transaction.channel_change(30), 15);
+        // Rounded down:
+        assert_eq!(pending_transaction.channel_change(5), 2);
+    }
+
+    #[test]
+    fn test_deserialize_without_change() {
+        // Pending transactions and receipts were serialized without change before change
+        // was added:
+        let pending_transaction = PendingTransaction {
+            request_id: Uid::from(&[1; Uid::len()]),
+            route: FriendsRoute {
+                public_keys: Vec::new(),
+            },
+            dest_payment: 15,
+            total_dest_payment: 15,
+            invoice_id: InvoiceId::from(&[2; InvoiceId::len()]),
+            left_fees: 5,
+            src_hashed_lock: HashedLock::from(&[3; HashedLock::len()]),
+            expiry_ticks: 0,
+            refund_ticks: 0,
+            opt_exchange: None,
+            stage: TransactionStage::Response(HashedLock::from(&[4; HashedLock::len()]), true, 0),
+        };
+        let mut value = serde_json::to_value(&pending_transaction).unwrap();
+        assert!(value["stage"]["Response"]
+            .as_array_mut()
+            .unwrap()
+            .pop()
+            .is_some());
+        let baseline_pending_transaction: PendingTransaction =
+            serde_json::from_value(value).unwrap();
+        assert_eq!(baseline_pending_transaction, pending_transaction);
+
+        let receipt = Receipt {
+            response_hash: HashResult::from(&[5; HashResult::len()]),
+            invoice_id: InvoiceId::from(&[2; InvoiceId::len()]),
+            currency: Currency::try_from("FST".to_owned()).unwrap(),
+            src_plain_lock: PlainLock::from(&[6; PlainLock::len()]),
+            dest_plain_lock: PlainLock::from(&[7; PlainLock::len()]),
+            is_complete: true,
+            dest_payment: 15,
+            total_dest_payment: 15,
+            change: 0,
+            signature: Signature::from(&[8; Signature::len()]),
+        };
+        let mut value = serde_json::to_value(&receipt).unwrap();
+        assert!(value.as_object_mut().unwrap().remove("change").is_some());
+        let baseline_receipt: Receipt = serde_json::from_value(value).unwrap();
+        assert_eq!(baseline_receipt, receipt);
+    }
+
     use im::hashset::HashSet as ImHashSet;
 
     #[derive(Arbitrary, Clone)]
@@ -1,13 +1,18 @@
 use std::collections::HashMap;
 
+use serde::{Deserialize, Serialize};
+
 use capnp_conv::{capnp_conv, CapnpConvError, ReadCapnp, WriteCapnp};
 
+use common::ser_utils::{ser_b64, ser_option_b64};
+
+use crate::app_server::messages::SendFriendProposal;
 use crate::crypto::{PublicKey, Uid};
 use crate::funder::messages::{Currency, Rate};
+use crate::index_server::messages::{FriendProposal, MultiRoute, NamedIndexServerAddress};
 pub use crate::index_server::messages::{
     IndexMutation, RemoveFriendCurrency, RequestRoutes, UpdateFriendCurrency,
 };
-use crate::index_server::messages::{MultiRoute, NamedIndexServerAddress};
 use crate::net::messages::NetAddress;
 
 // TODO: Possibly rename to something more meaningful?
@@ -54,14 +59,18 @@ impl From<OptConnectedServer> for Option<PublicKey> {
 }
 
 #[capnp_conv(crate::report_capnp::index_client_report)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 /// ISA stands for Index Server Address
 pub struct IndexClientReport<ISA = NetAddress> {
     /// A list of trusted index servers.
     pub index_servers: Vec<NamedIndexServerAddress<ISA>>,
     /// The server we are currently connected to (None if not connected).
     #[capnp_conv(with = OptConnectedServer)]
+    #[serde(with = "ser_option_b64")]
     pub opt_connected_server: Option<PublicKey>,
+    /// Friend proposals received from other nodes, waiting to be accepted or rejected.
+    /// At most one proposal is kept for every proposing node.
+    pub friend_proposals: Vec<FriendProposal>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -98,24 +107,28 @@ impl From<SetConnectedServer> for Option<PublicKey> {
 }
 
 #[capnp_conv(crate::report_capnp::index_client_report_mutation)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum IndexClientReportMutation<ISA = NetAddress> {
     AddIndexServer(NamedIndexServerAddress<ISA>),
-    RemoveIndexServer(PublicKey),
+    RemoveIndexServer(#[serde(with = "ser_b64")] PublicKey),
     #[capnp_conv(with = SetConnectedServer)]
-    SetConnectedServer(Option<PublicKey>),
+    SetConnectedServer(#[serde(with = "ser_option_b64")] Option<PublicKey>),
+    AddFriendProposal(FriendProposal),
+    /// Remove the friend proposal sent by a node (Given by its public key)
+    RemoveFriendProposal(#[serde(with = "ser_b64")] PublicKey),
 }
 
 #[capnp_conv(crate::app_server_capnp::response_routes_result)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ResponseRoutesResult {
     Success(Vec<MultiRoute>),
     Failure,
 }
 
 #[capnp_conv(crate::app_server_capnp::client_response_routes)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ClientResponseRoutes {
+    #[serde(with = "ser_b64")]
     pub request_id: Uid,
     pub result: ResponseRoutesResult,
 }
@@ -137,6 +150,12 @@ pub enum IndexClientRequest<ISA> {
     AddIndexServer(NamedIndexServerAddress<ISA>),
     RemoveIndexServer(PublicKey),
     RequestRoutes(RequestRoutes),
+    /// Change the amount of ticks between keepalive messages sent to the index server
+    SetKeepaliveTicks(usize),
+    /// Sign a friend proposal and send it through the connected index server
+    SendFriendProposal(SendFriendProposal),
+    /// Remove a received friend proposal (Given by the public key of the proposing node)
+    RemoveFriendProposal(PublicKey),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -169,6 +188,17 @@ where
             IndexClientReportMutation::SetConnectedServer(opt_public_key) => {
                 self.opt_connected_server = opt_public_key.clone();
             }
+            IndexClientReportMutation::AddFriendProposal(friend_proposal) => {
+                // A newer proposal from the same node replaces the older one:
+                self.friend_proposals.retain(|cur_friend_proposal| {
+                    cur_friend_proposal.src_public_key != friend_proposal.src_public_key
+                });
+                self.friend_proposals.push(friend_proposal.clone());
+            }
+            IndexClientReportMutation::RemoveFriendProposal(public_key) => {
+                self.friend_proposals
+                    .retain(|friend_proposal| &friend_proposal.src_public_key != public_key);
+            }
         }
     }
 }
@@ -2,17 +2,20 @@ use serde::{Deserialize, Serialize};
 
 use capnp_conv::{capnp_conv, CapnpConvError, ReadCapnp, WriteCapnp};
 
-use common::ser_utils::{ser_b64, ser_string};
+use common::ser_utils::{ser_b64, ser_string, ser_vec_b64};
 
+use crate::app_server::messages::RelayAddress;
 use crate::crypto::{HashResult, PublicKey, RandValue, Signature, Uid};
 use crate::funder::messages::{Currency, FriendsRoute, Rate};
 use crate::net::messages::NetAddress;
 use crate::wrapper::Wrapper;
 
 #[capnp_conv(crate::index_capnp::edge)]
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
 pub struct Edge {
+    #[serde(with = "ser_b64")]
     pub from_public_key: PublicKey,
+    #[serde(with = "ser_b64")]
     pub to_public_key: PublicKey,
 }
 
@@ -42,22 +45,89 @@ impl From<OptExclude> for Option<Edge> {
     }
 }
 
+#[capnp_conv(crate::index_capnp::route_constraints::opt_max_route_len)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum OptMaxRouteLen {
+    Empty,
+    MaxRouteLen(u32),
+}
+
+impl From<Option<u32>> for OptMaxRouteLen {
+    fn from(opt: Option<u32>) -> Self {
+        match opt {
+            Some(max_route_len) => OptMaxRouteLen::MaxRouteLen(max_route_len),
+            None => OptMaxRouteLen::Empty,
+        }
+    }
+}
+
+impl From<OptMaxRouteLen> for Option<u32> {
+    fn from(opt: OptMaxRouteLen) -> Self {
+        match opt {
+            OptMaxRouteLen::MaxRouteLen(max_route_len) => Some(max_route_len),
+            OptMaxRouteLen::Empty => None,
+        }
+    }
+}
+
+/// Optional constraints on the routes returned for a `RequestRoutes`.
+/// The default value does not constrain the returned routes.
+#[capnp_conv(crate::index_capnp::route_constraints)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Default, Serialize, Deserialize)]
+pub struct RouteConstraints {
+    /// Public keys that must not show up as mediators in any returned route.
+    /// Useful for avoiding a mediator that failed previous payments.
+    #[serde(with = "ser_vec_b64")]
+    pub blacklist: Vec<PublicKey>,
+    /// Maximum amount of public keys in a returned route (Including source and destination).
+    #[capnp_conv(with = OptMaxRouteLen)]
+    pub opt_max_route_len: Option<u32>,
+    /// Every returned route must be able to carry this capacity on top of the requested capacity.
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub capacity_margin: u128,
+}
+
+/// How to rank the routes returned for a `RequestRoutes`.
+#[capnp_conv(crate::index_capnp::route_ranking)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
+pub enum RouteRanking {
+    /// A single route with the least amount of hops.
+    ShortestPath,
+    /// Up to the given amount of routes, ranked by their residual capacity (Capacity left after
+    /// sending the requested capacity), discounted for capacity reports that were not refreshed
+    /// recently.
+    Capacity(u32),
+}
+
+impl Default for RouteRanking {
+    fn default() -> Self {
+        RouteRanking::ShortestPath
+    }
+}
+
 /// IndexClient -> IndexServer
 #[capnp_conv(crate::index_capnp::request_routes)]
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct RequestRoutes {
+    #[serde(with = "ser_b64")]
     pub request_id: Uid,
     pub currency: Currency,
     /// Wanted capacity for the route.
     /// 0 means we want to optimize for capacity??
     #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
     pub capacity: u128,
+    #[serde(with = "ser_b64")]
     pub source: PublicKey,
+    #[serde(with = "ser_b64")]
     pub destination: PublicKey,
     /// This directed edge must not show up any any route inside the multi-route.
     /// Useful for finding non trivial directed loops.
     #[capnp_conv(with = OptExclude)]
     pub opt_exclude: Option<Edge>,
+    pub constraints: RouteConstraints,
+    pub ranking: RouteRanking,
 }
 
 #[capnp_conv(crate::index_capnp::route_capacity_rate)]
@@ -92,14 +162,16 @@ pub struct ResponseRoutes {
 
 // TODO: Possibly think of a better name for this structure?
 #[capnp_conv(crate::index_capnp::update_friend_currency)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct UpdateFriendCurrency {
     /// Friend's public key
+    #[serde(with = "ser_b64")]
     pub public_key: PublicKey,
     /// Currency being updated
     pub currency: Currency,
     /// To denote local requests closed, assign 0 to recvCapacity
     #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
     pub recv_capacity: u128,
     /// The rate we charge for forwarding messages to another friend from this friend.
     /// For example, in the following diagram we are X and A is the friend we are updating:
@@ -113,9 +185,10 @@ pub struct UpdateFriendCurrency {
 
 // TODO: Possibly think of a better name for this structure?
 #[capnp_conv(crate::index_capnp::remove_friend_currency)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RemoveFriendCurrency {
     /// Friend's public key
+    #[serde(with = "ser_b64")]
     pub public_key: PublicKey,
     /// Currency being removed
     pub currency: Currency,
@@ -123,7 +196,7 @@ pub struct RemoveFriendCurrency {
 
 /// IndexClient -> IndexServer
 #[capnp_conv(crate::index_capnp::index_mutation)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum IndexMutation {
     UpdateFriendCurrency(UpdateFriendCurrency),
     RemoveFriendCurrency(RemoveFriendCurrency),
@@ -154,6 +227,10 @@ pub struct MutationsUpdate {
     ///           counter ||
     ///           randNonce)
     pub signature: Signature,
+    /// Proof of work nonce. The proof of work hash of the message must begin with at least the
+    /// amount of zero bits required by the server. The required amount grows with the rate of
+    /// messages sent by the node.
+    pub pow_nonce: u64,
 }
 
 #[capnp_conv(crate::index_capnp::time_proof_link)]
@@ -176,11 +253,279 @@ pub struct ForwardMutationsUpdate {
     pub time_proof_chain: Vec<TimeProofLink>,
 }
 
+/// The latest `MutationsUpdate` an index server knows for a node session.
+#[capnp_conv(crate::index_capnp::node_session_counter)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeSessionCounter {
+    pub node_public_key: PublicKey,
+    pub session_id: Uid,
+    pub counter: u64,
+}
+
+/// A signed proposal to open a credit line with a node we are not friends with yet.
+/// Delivered to the destination node through the index servers, so that establishing a new
+/// credit line does not require an out of band exchange of friend tickets.
+#[capnp_conv(crate::index_capnp::friend_proposal)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FriendProposal {
+    /// Public key of the proposing node
+    #[serde(with = "ser_b64")]
+    pub src_public_key: PublicKey,
+    /// Public key of the node the proposal is sent to
+    #[serde(with = "ser_b64")]
+    pub dest_public_key: PublicKey,
+    /// Relays the proposing node can be reached through
+    pub relays: Vec<RelayAddress>,
+    pub currency: Currency,
+    /// The maximum debt the proposing node asks to be allowed to accumulate
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub proposed_max_debt: u128,
+    /// Rand nonce, used as a security measure for the signature.
+    #[serde(with = "ser_b64")]
+    pub rand_nonce: RandValue,
+    /// signature(sha_512_256("FRIEND_PROPOSAL") ||
+    ///           srcPublicKey ||
+    ///           destPublicKey ||
+    ///           relays ||
+    ///           currency ||
+    ///           proposedMaxDebt ||
+    ///           randNonce)
+    #[serde(with = "ser_b64")]
+    pub signature: Signature,
+}
+
+/// IndexClient -> IndexServer
+/// Request public relays that are registered with the index server.
+#[capnp_conv(crate::index_capnp::request_relays)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestRelays {
+    pub request_id: Uid,
+    /// Maximum amount of relays to return
+    pub max_relays: u32,
+}
+
+/// IndexServer -> IndexClient
+#[capnp_conv(crate::index_capnp::response_relays)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseRelays {
+    pub request_id: Uid,
+    pub relays: Vec<RelayAddress>,
+}
+
+/// IndexClient -> IndexServer
+/// Request the current status of the index server.
+/// Clients use the status to prefer healthier servers.
+#[capnp_conv(crate::index_capnp::request_server_status)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestServerStatus {
+    pub request_id: Uid,
+}
+
+/// IndexClient -> IndexServer
+/// Resume a previous session after a brief disconnect, instead of resending the full state.
+/// Must be sent before any `MutationsUpdate` of the new connection.
+#[capnp_conv(crate::index_capnp::resume_session)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResumeSession {
+    pub session_id: Uid,
+    /// Counter of the last `MutationsUpdate` sent by the client in this session
+    pub counter: u64,
+}
+
+/// IndexServer -> IndexClient
+#[capnp_conv(crate::index_capnp::response_server_status)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseServerStatus {
+    pub request_id: Uid,
+    /// Amount of nodes with outgoing edges, summed over all the currencies
+    pub num_nodes: u64,
+    /// Amount of directed edges, summed over all the currencies
+    pub num_edges: u64,
+    /// Amount of currently connected clients
+    pub num_clients: u32,
+    /// Amount of currently connected peer servers
+    pub num_servers: u32,
+    /// Amount of ticks since a time hash was last received from a peer server.
+    /// A server that does not receive time hashes can not verify mutations forwarded by other
+    /// servers.
+    pub time_hash_age: u64,
+}
+
+/// A list of index servers, signed by a directory key.
+/// Nodes that trust the directory key use the directory to manage their list of index servers.
+#[capnp_conv(crate::index_capnp::index_server_directory)]
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexServerDirectory {
+    /// Increased by the directory key whenever the list changes.
+    /// A node only applies a directory with a version higher than the last directory it has
+    /// applied.
+    pub version: u64,
+    pub index_servers: Vec<NamedIndexServerAddress>,
+    /// signature(sha_512_256("INDEX_SERVER_DIRECTORY") ||
+    ///           version ||
+    ///           indexServers)
+    #[serde(with = "ser_b64")]
+    pub signature: Signature,
+}
+
+/// IndexClient -> IndexServer
+/// Request the index server directory served by the index server.
+#[capnp_conv(crate::index_capnp::request_directory)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestDirectory {
+    pub request_id: Uid,
+}
+
+#[capnp_conv(crate::index_capnp::response_directory::opt_directory)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum OptDirectory {
+    Empty,
+    Directory(IndexServerDirectory),
+}
+
+impl From<Option<IndexServerDirectory>> for OptDirectory {
+    fn from(opt: Option<IndexServerDirectory>) -> Self {
+        match opt {
+            Some(directory) => OptDirectory::Directory(directory),
+            None => OptDirectory::Empty,
+        }
+    }
+}
+
+impl From<OptDirectory> for Option<IndexServerDirectory> {
+    fn from(opt: OptDirectory) -> Self {
+        match opt {
+            OptDirectory::Directory(directory) => Some(directory),
+            OptDirectory::Empty => None,
+        }
+    }
+}
+
+/// IndexServer -> IndexClient
+#[capnp_conv(crate::index_capnp::response_directory)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseDirectory {
+    pub request_id: Uid,
+    /// None if the index server does not serve a directory
+    #[capnp_conv(with = OptDirectory)]
+    pub opt_directory: Option<IndexServerDirectory>,
+}
+
+/// IndexServer -> IndexAdmin
+/// A directed edge of the capacity graph of the index server.
+#[capnp_conv(crate::index_capnp::admin_edge)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminEdge {
+    pub currency: Currency,
+    pub from_public_key: PublicKey,
+    pub to_public_key: PublicKey,
+    #[capnp_conv(with = Wrapper<u128>)]
+    pub recv_capacity: u128,
+    pub rate: Rate,
+}
+
+#[capnp_conv(crate::index_capnp::request_edges::opt_public_key)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum OptPublicKey {
+    Empty,
+    PublicKey(PublicKey),
+}
+
+impl From<Option<PublicKey>> for OptPublicKey {
+    fn from(opt: Option<PublicKey>) -> Self {
+        match opt {
+            Some(public_key) => OptPublicKey::PublicKey(public_key),
+            None => OptPublicKey::Empty,
+        }
+    }
+}
+
+impl From<OptPublicKey> for Option<PublicKey> {
+    fn from(opt: OptPublicKey) -> Self {
+        match opt {
+            OptPublicKey::PublicKey(public_key) => Some(public_key),
+            OptPublicKey::Empty => None,
+        }
+    }
+}
+
+/// IndexAdmin -> IndexServer
+#[capnp_conv(crate::index_capnp::request_edges)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestEdges {
+    pub request_id: Uid,
+    /// Only return edges from or to this node. None dumps the whole capacity graph.
+    #[capnp_conv(with = OptPublicKey)]
+    pub opt_public_key: Option<PublicKey>,
+}
+
+/// IndexServer -> IndexAdmin
+/// A large response is split into multiple messages with the same `request_id`.
+#[capnp_conv(crate::index_capnp::response_edges)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseEdges {
+    pub request_id: Uid,
+    pub edges: Vec<AdminEdge>,
+    /// Is this the last message of the response?
+    pub is_last: bool,
+}
+
+#[capnp_conv(crate::index_capnp::admin_remove_edge)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AdminRemoveEdge {
+    pub currency: Currency,
+    #[serde(with = "ser_b64")]
+    pub from_public_key: PublicKey,
+    #[serde(with = "ser_b64")]
+    pub to_public_key: PublicKey,
+}
+
+/// IndexAdmin -> IndexServer
+/// A manual change to the capacity graph, for example to get rid of a known abusive node.
+/// Removed nodes and edges are denied: Mutations that add them back are ignored.
+#[capnp_conv(crate::index_capnp::admin_change)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AdminChange {
+    /// Remove a node and all the edges going out of this node
+    RemoveNode(#[serde(with = "ser_b64")] PublicKey),
+    RemoveEdge(AdminRemoveEdge),
+}
+
+#[capnp_conv(crate::index_capnp::admin_journal_entry)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminJournalEntry {
+    /// The admin that applied the change
+    #[serde(with = "ser_b64")]
+    pub admin_public_key: PublicKey,
+    pub change: AdminChange,
+}
+
+/// IndexServer -> IndexAdmin
+#[capnp_conv(crate::index_capnp::response_journal)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseJournal {
+    pub request_id: Uid,
+    /// Most recent changes, oldest first
+    pub entries: Vec<AdminJournalEntry>,
+}
+
 #[capnp_conv(crate::index_capnp::index_server_to_client)]
 #[derive(Debug)]
 pub enum IndexServerToClient {
     TimeHash(HashResult),
     ResponseRoutes(ResponseRoutes),
+    /// A friend proposal sent to the client by another node
+    FriendProposal(FriendProposal),
+    /// Amount of leading zero bits required from the proof of work of the next
+    /// `MutationsUpdate` messages sent by the client
+    PowDifficulty(u8),
+    ResponseRelays(ResponseRelays),
+    ResponseServerStatus(ResponseServerStatus),
+    /// A response to `ResumeSession`. False means that the client should resend its full state.
+    SessionResumed(bool),
+    ResponseDirectory(ResponseDirectory),
 }
 
 #[capnp_conv(crate::index_capnp::index_client_to_server)]
@@ -188,6 +533,15 @@ pub enum IndexServerToClient {
 pub enum IndexClientToServer {
     MutationsUpdate(MutationsUpdate),
     RequestRoutes(RequestRoutes),
+    SendFriendProposal(FriendProposal),
+    /// Sent by a relay: Register the relay as a public relay, reachable at the given address.
+    /// The public key of the relay is the public key of the connection. The registration lasts
+    /// while the relay stays connected.
+    AnnounceRelay(NetAddress),
+    RequestRelays(RequestRelays),
+    RequestServerStatus(RequestServerStatus),
+    ResumeSession(ResumeSession),
+    RequestDirectory(RequestDirectory),
 }
 
 #[capnp_conv(crate::index_capnp::index_server_to_server)]
@@ -195,6 +549,27 @@ pub enum IndexClientToServer {
 pub enum IndexServerToServer {
     TimeHash(HashResult),
     ForwardMutationsUpdate(ForwardMutationsUpdate),
+    /// A summary of the latest known `MutationsUpdate` for every node.
+    /// The receiving server replies with the updates the sender is missing.
+    MutationsDigest(Vec<NodeSessionCounter>),
+    /// A friend proposal for a node that is not connected to the sending server.
+    ForwardFriendProposal(FriendProposal),
+}
+
+#[capnp_conv(crate::index_capnp::index_admin_to_server)]
+#[derive(Debug)]
+pub enum IndexAdminToServer {
+    RequestEdges(RequestEdges),
+    ApplyChange(AdminChange),
+    /// Request the journal of recent manual changes
+    RequestJournal(Uid),
+}
+
+#[capnp_conv(crate::index_capnp::index_server_to_admin)]
+#[derive(Debug)]
+pub enum IndexServerToAdmin {
+    ResponseEdges(ResponseEdges),
+    ResponseJournal(ResponseJournal),
 }
 
 // ----------------------------------------------
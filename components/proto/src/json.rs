@@ -0,0 +1,163 @@
+//! JSON serialization of the messages exchanged between a node and its apps.
+//! Allows clients that do not have capnp tooling (For example: web dashboards and scripts) to
+//! talk to a node.
+
+use derive_more::From;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::app_server::messages::{AppServerToApp, AppToAppServer, NodeReport};
+use crate::funder::messages::Receipt;
+
+#[derive(Debug, From)]
+pub enum JsonSerializeError {
+    SerdeJsonError(serde_json::Error),
+}
+
+pub trait JsonSerialize: Serialize {
+    /// Serialize a Rust struct into a JSON string
+    fn json_serialize(&self) -> Result<String, JsonSerializeError> {
+        Ok(serde_json::to_string(self)?)
+    }
+}
+
+pub trait JsonDeserialize: DeserializeOwned {
+    /// Deserialize a Rust struct from a JSON string
+    fn json_deserialize(input: &str) -> Result<Self, JsonSerializeError> {
+        Ok(serde_json::from_str(input)?)
+    }
+}
+
+impl<B> JsonSerialize for AppToAppServer<B> where B: Serialize {}
+impl<B> JsonDeserialize for AppToAppServer<B> where B: DeserializeOwned {}
+
+impl<B> JsonSerialize for AppServerToApp<B> where B: Serialize {}
+impl<B> JsonDeserialize for AppServerToApp<B> where B: DeserializeOwned {}
+
+impl<B> JsonSerialize for NodeReport<B> where B: Serialize {}
+impl<B> JsonDeserialize for NodeReport<B> where B: DeserializeOwned {}
+
+impl JsonSerialize for Receipt {}
+impl JsonDeserialize for Receipt {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+
+    use crate::app_server::messages::{AppRequest, NodeReportMutation, ReportMutations};
+    use crate::crypto::{HashResult, InvoiceId, PaymentId, PlainLock, PublicKey, Signature, Uid};
+    use crate::funder::messages::{CreatePayment, Currency};
+    use crate::index_client::messages::{IndexClientReport, IndexClientReportMutation};
+    use crate::net::messages::NetAddress;
+    use crate::report::messages::{
+        FriendReportMutation, FunderReport, FunderReportMutation, RebalanceSuggestion,
+        RelayLatencyReport,
+    };
+
+    fn assert_json_round_trip<T>(msg: T)
+    where
+        T: JsonSerialize + JsonDeserialize + PartialEq + std::fmt::Debug,
+    {
+        let json_str = msg.json_serialize().unwrap();
+        let msg2 = T::json_deserialize(&json_str).unwrap();
+        assert_eq!(msg, msg2);
+    }
+
+    #[test]
+    fn test_json_app_to_app_server() {
+        let create_payment = CreatePayment {
+            payment_id: PaymentId::from(&[1; PaymentId::len()]),
+            invoice_id: InvoiceId::from(&[2; InvoiceId::len()]),
+            currency: Currency::try_from("FST".to_owned()).unwrap(),
+            total_dest_payment: u128::max_value(),
+            dest_public_key: PublicKey::from(&[3; PublicKey::len()]),
+            opt_max_fee_per_hop: Some(5),
+        };
+        let app_to_app_server = AppToAppServer::<NetAddress>::new(
+            Uid::from(&[4; Uid::len()]),
+            AppRequest::CreatePayment(create_payment),
+        );
+        let json_str = app_to_app_server.json_serialize().unwrap();
+        // Byte arrays are encoded as base64 strings, and large numbers as decimal strings:
+        assert!(json_str.contains("\"340282366920938463463374607431768211455\""));
+        assert!(!json_str.contains("[3,3,"));
+        assert_json_round_trip(app_to_app_server);
+
+        assert_json_round_trip(AppToAppServer::<NetAddress>::new(
+            Uid::from(&[5; Uid::len()]),
+            AppRequest::RemoveFriend(PublicKey::from(&[6; PublicKey::len()])),
+        ));
+    }
+
+    #[test]
+    fn test_json_app_server_to_app() {
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let report_mutations = ReportMutations::<NetAddress> {
+            opt_app_request_id: Some(Uid::from(&[0x77; Uid::len()])),
+            mutations: vec![
+                NodeReportMutation::Funder(FunderReportMutation::PkFriendReportMutation((
+                    pk_a.clone(),
+                    FriendReportMutation::SetMissedBeats(3),
+                ))),
+                NodeReportMutation::Funder(FunderReportMutation::SetRelayLatency(
+                    RelayLatencyReport {
+                        public_key: pk_a.clone(),
+                        opt_latency_ms: None,
+                    },
+                )),
+                NodeReportMutation::Funder(FunderReportMutation::SetRebalanceSuggestions(vec![
+                    RebalanceSuggestion {
+                        currency: Currency::try_from("FST".to_owned()).unwrap(),
+                        from_public_key: pk_a.clone(),
+                        to_public_key: PublicKey::from(&[0xbb; PublicKey::len()]),
+                        amount: 500,
+                    },
+                ])),
+                NodeReportMutation::IndexClient(IndexClientReportMutation::SetConnectedServer(
+                    Some(pk_a),
+                )),
+            ],
+        };
+        assert_json_round_trip(AppServerToApp::ReportMutations(report_mutations));
+    }
+
+    #[test]
+    fn test_json_node_report() {
+        let node_report = NodeReport::<NetAddress> {
+            funder_report: FunderReport {
+                local_public_key: PublicKey::from(&[0xaa; PublicKey::len()]),
+                relays: Vec::new(),
+                friends: HashMap::new(),
+                relay_latencies: Vec::new(),
+                rebalance_suggestions: Vec::new(),
+            },
+            index_client_report: IndexClientReport {
+                index_servers: Vec::new(),
+                opt_connected_server: None,
+                friend_proposals: Vec::new(),
+            },
+        };
+        assert_json_round_trip(node_report);
+    }
+
+    #[test]
+    fn test_json_receipt() {
+        let receipt = Receipt {
+            response_hash: HashResult::from(&[0; HashResult::len()]),
+            invoice_id: InvoiceId::from(&[1; InvoiceId::len()]),
+            currency: Currency::try_from("FST".to_owned()).unwrap(),
+            src_plain_lock: PlainLock::from(&[2; PlainLock::len()]),
+            dest_plain_lock: PlainLock::from(&[3; PlainLock::len()]),
+            is_complete: true,
+            dest_payment: 10,
+            total_dest_payment: 15,
+            change: 0,
+            signature: Signature::from(&[4; Signature::len()]),
+        };
+        assert_json_round_trip(receipt);
+    }
+}
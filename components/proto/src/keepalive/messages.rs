@@ -6,3 +6,17 @@ pub enum KaMessage {
     KeepAlive,
     Message(Vec<u8>),
 }
+
+/// A liveness report emitted by the keepalive layer of a connection.
+/// Sent whenever the amount of consecutive missed beats changes, so that
+/// a flaky link is visible before the connection is declared dead.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct KeepAliveReport {
+    /// Amount of keepalive periods that passed without hearing from the remote side.
+    /// Zero means the remote side is (again) responsive.
+    pub missed_beats: u64,
+    /// Total amount of keepalive messages sent to the remote side.
+    pub sent_keepalives: u64,
+    /// Total amount of keepalive messages received from the remote side.
+    pub received_keepalives: u64,
+}
@@ -22,13 +22,17 @@ pub mod file;
 pub mod funder;
 pub mod index_client;
 pub mod index_server;
+#[cfg(feature = "json")]
+pub mod json;
 pub mod keepalive;
+pub mod limits;
 pub mod net;
 pub mod proto_ser;
 pub mod relay;
 pub mod report;
 pub mod secure_channel;
 pub mod ser_string;
+pub mod wire;
 pub mod wrapper;
 
 include_schema!(report_capnp, "report_capnp");
@@ -0,0 +1,454 @@
+//! Maximum sizes for messages received from remote peers.
+//!
+//! A message is first checked against `MAX_MESSAGE_LEN` and `MAX_TRAVERSAL_WORDS` while it is
+//! being deserialized, so that a malicious peer can not cause huge allocations. Messages
+//! received from remote peers are then checked against the list limits (See `CheckLimits`), so
+//! that the rest of the code never has to handle unreasonably long routes or lists of
+//! operations.
+
+use crate::consts::MAX_FRAME_LENGTH;
+pub use crate::consts::{MAX_NODE_RELAYS, MAX_RELAY_LISTENERS, MAX_ROUTE_LEN};
+
+use crate::funder::messages::{
+    CurrencyOperations, FriendCapabilities, FriendMessage, FriendTcOp, FriendsRoute, MoveToken,
+    MoveTokenRequest, RequestSendFundsOp, ResetTerms,
+};
+use crate::index_server::messages::{
+    ForwardMutationsUpdate, FriendProposal, IndexAdminToServer, IndexClientToServer,
+    IndexServerToAdmin, IndexServerToClient, IndexServerToServer, MultiRoute, MutationsUpdate,
+    RequestRoutes, ResponseDirectory, ResponseRelays, ResponseRoutes, TimeProofLink,
+};
+use crate::relay::messages::PeerListeners;
+
+/// Maximum length of a serialized message, measured in bytes.
+/// A message can not be longer than the frame that carries it.
+pub const MAX_MESSAGE_LEN: usize = MAX_FRAME_LENGTH;
+
+/// Maximum amount of (unpacked) words read while deserializing a message.
+/// Packed messages may expand when unpacked, so this limit is what bounds the amount of memory
+/// allocated for a message.
+pub const MAX_TRAVERSAL_WORDS: u64 = (4 * MAX_FRAME_LENGTH / 8) as u64;
+
+/// Maximum amount of operations (Over all currencies) in a single move token message.
+pub const MAX_OPERATIONS_IN_MOVE_TOKEN: usize = 0x100;
+
+/// Maximum amount of currencies listed in a single message.
+pub const MAX_CURRENCIES: usize = 0x100;
+
+/// Maximum amount of routes in a multi route.
+pub const MAX_ROUTES_IN_MULTI_ROUTE: usize = 0x40;
+
+/// Maximum amount of multi routes in a response from an index server.
+pub const MAX_MULTI_ROUTES: usize = 0x40;
+
+/// Maximum amount of public keys in a routes blacklist.
+pub const MAX_BLACKLIST_LEN: usize = 0x100;
+
+/// Maximum amount of relays listed by an index server in a single response.
+pub const MAX_LISTED_RELAYS: usize = 0x100;
+
+/// Maximum amount of index mutations in a single mutations update.
+pub const MAX_INDEX_MUTATIONS: usize = 0x400;
+
+/// Maximum amount of links in a time proof chain.
+pub const MAX_TIME_PROOF_CHAIN_LEN: usize = 0x40;
+
+/// Maximum amount of hashes in a single time proof link.
+pub const MAX_TIME_PROOF_LINK_HASHES: usize = 0x100;
+
+/// Maximum amount of node sessions in a mutations digest.
+pub const MAX_MUTATIONS_DIGEST_LEN: usize = 0x10000;
+
+/// Maximum amount of edges in a single edges response sent to an index server admin.
+pub const MAX_ADMIN_EDGES: usize = 0x1000;
+
+/// Maximum amount of entries in the journal of manual changes of an index server.
+pub const MAX_ADMIN_JOURNAL_LEN: usize = 0x400;
+
+/// Maximum amount of index servers listed in an index server directory.
+pub const MAX_DIRECTORY_INDEX_SERVERS: usize = 0x40;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LimitsError {
+    RouteTooLong,
+    TooManyOperations,
+    TooManyCurrencies,
+    TooManyRelays,
+    TooManyRoutes,
+    TooManyMultiRoutes,
+    BlacklistTooLong,
+    TooManyIndexMutations,
+    TimeProofChainTooLong,
+    TooManyTimeProofHashes,
+    MutationsDigestTooLong,
+    TooManyListeners,
+    TooManyAdminEdges,
+    AdminJournalTooLong,
+    TooManyDirectoryIndexServers,
+}
+
+/// Verify that a message received from a remote peer is within the allowed limits.
+pub trait CheckLimits {
+    fn check_limits(&self) -> Result<(), LimitsError>;
+}
+
+fn check_len<T>(list: &[T], max_len: usize, error: LimitsError) -> Result<(), LimitsError> {
+    if list.len() > max_len {
+        return Err(error);
+    }
+    Ok(())
+}
+
+impl CheckLimits for FriendsRoute {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        check_len(&self.public_keys, MAX_ROUTE_LEN, LimitsError::RouteTooLong)
+    }
+}
+
+impl CheckLimits for RequestSendFundsOp {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        self.route.check_limits()
+    }
+}
+
+impl CheckLimits for CurrencyOperations {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        check_len(
+            &self.operations,
+            MAX_OPERATIONS_IN_MOVE_TOKEN,
+            LimitsError::TooManyOperations,
+        )?;
+        for operation in &self.operations {
+            if let FriendTcOp::RequestSendFunds(request_send_funds) = operation {
+                request_send_funds.check_limits()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<B> CheckLimits for MoveToken<B> {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        check_len(
+            &self.currencies_operations,
+            MAX_CURRENCIES,
+            LimitsError::TooManyCurrencies,
+        )?;
+        let num_operations = self
+            .currencies_operations
+            .iter()
+            .map(|currency_operations| currency_operations.operations.len())
+            .sum::<usize>();
+        if num_operations > MAX_OPERATIONS_IN_MOVE_TOKEN {
+            return Err(LimitsError::TooManyOperations);
+        }
+        for currency_operations in &self.currencies_operations {
+            currency_operations.check_limits()?;
+        }
+        if let Some(local_relays) = &self.opt_local_relays {
+            check_len(local_relays, MAX_NODE_RELAYS, LimitsError::TooManyRelays)?;
+        }
+        if let Some(active_currencies) = &self.opt_active_currencies {
+            check_len(
+                active_currencies,
+                MAX_CURRENCIES,
+                LimitsError::TooManyCurrencies,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<B> CheckLimits for MoveTokenRequest<B> {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        self.move_token.check_limits()?;
+        if let Some(capabilities) = &self.opt_capabilities {
+            capabilities.check_limits()?;
+        }
+        Ok(())
+    }
+}
+
+impl CheckLimits for ResetTerms {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        check_len(
+            &self.balance_for_reset,
+            MAX_CURRENCIES,
+            LimitsError::TooManyCurrencies,
+        )
+    }
+}
+
+impl CheckLimits for FriendCapabilities {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        check_len(
+            &self.currencies,
+            MAX_CURRENCIES,
+            LimitsError::TooManyCurrencies,
+        )
+    }
+}
+
+impl<B> CheckLimits for FriendMessage<B> {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        match self {
+            FriendMessage::MoveTokenRequest(move_token_request) => {
+                move_token_request.check_limits()
+            }
+            FriendMessage::InconsistencyError(reset_terms) => reset_terms.check_limits(),
+            // Fixed size:
+            FriendMessage::KeyRotation(_) => Ok(()),
+        }
+    }
+}
+
+impl CheckLimits for MultiRoute {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        check_len(
+            &self.routes,
+            MAX_ROUTES_IN_MULTI_ROUTE,
+            LimitsError::TooManyRoutes,
+        )?;
+        for route_capacity_rate in &self.routes {
+            route_capacity_rate.route.check_limits()?;
+        }
+        Ok(())
+    }
+}
+
+impl CheckLimits for ResponseRoutes {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        check_len(
+            &self.multi_routes,
+            MAX_MULTI_ROUTES,
+            LimitsError::TooManyMultiRoutes,
+        )?;
+        for multi_route in &self.multi_routes {
+            multi_route.check_limits()?;
+        }
+        Ok(())
+    }
+}
+
+impl CheckLimits for RequestRoutes {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        check_len(
+            &self.constraints.blacklist,
+            MAX_BLACKLIST_LEN,
+            LimitsError::BlacklistTooLong,
+        )
+    }
+}
+
+impl CheckLimits for FriendProposal {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        check_len(&self.relays, MAX_NODE_RELAYS, LimitsError::TooManyRelays)
+    }
+}
+
+impl CheckLimits for ResponseRelays {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        check_len(&self.relays, MAX_LISTED_RELAYS, LimitsError::TooManyRelays)
+    }
+}
+
+impl CheckLimits for ResponseDirectory {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        if let Some(directory) = &self.opt_directory {
+            check_len(
+                &directory.index_servers,
+                MAX_DIRECTORY_INDEX_SERVERS,
+                LimitsError::TooManyDirectoryIndexServers,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl CheckLimits for MutationsUpdate {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        check_len(
+            &self.index_mutations,
+            MAX_INDEX_MUTATIONS,
+            LimitsError::TooManyIndexMutations,
+        )
+    }
+}
+
+impl CheckLimits for TimeProofLink {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        check_len(
+            &self.hashes,
+            MAX_TIME_PROOF_LINK_HASHES,
+            LimitsError::TooManyTimeProofHashes,
+        )
+    }
+}
+
+impl CheckLimits for ForwardMutationsUpdate {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        self.mutations_update.check_limits()?;
+        check_len(
+            &self.time_proof_chain,
+            MAX_TIME_PROOF_CHAIN_LEN,
+            LimitsError::TimeProofChainTooLong,
+        )?;
+        for time_proof_link in &self.time_proof_chain {
+            time_proof_link.check_limits()?;
+        }
+        Ok(())
+    }
+}
+
+impl CheckLimits for IndexServerToClient {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        match self {
+            IndexServerToClient::TimeHash(_)
+            | IndexServerToClient::PowDifficulty(_)
+            | IndexServerToClient::ResponseServerStatus(_)
+            | IndexServerToClient::SessionResumed(_) => Ok(()),
+            IndexServerToClient::ResponseRoutes(response_routes) => response_routes.check_limits(),
+            IndexServerToClient::FriendProposal(friend_proposal) => friend_proposal.check_limits(),
+            IndexServerToClient::ResponseRelays(response_relays) => response_relays.check_limits(),
+            IndexServerToClient::ResponseDirectory(response_directory) => {
+                response_directory.check_limits()
+            }
+        }
+    }
+}
+
+impl CheckLimits for IndexClientToServer {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        match self {
+            IndexClientToServer::AnnounceRelay(_)
+            | IndexClientToServer::RequestRelays(_)
+            | IndexClientToServer::RequestServerStatus(_)
+            | IndexClientToServer::ResumeSession(_)
+            | IndexClientToServer::RequestDirectory(_) => Ok(()),
+            IndexClientToServer::MutationsUpdate(mutations_update) => {
+                mutations_update.check_limits()
+            }
+            IndexClientToServer::RequestRoutes(request_routes) => request_routes.check_limits(),
+            IndexClientToServer::SendFriendProposal(friend_proposal) => {
+                friend_proposal.check_limits()
+            }
+        }
+    }
+}
+
+impl CheckLimits for IndexServerToServer {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        match self {
+            IndexServerToServer::TimeHash(_) => Ok(()),
+            IndexServerToServer::ForwardMutationsUpdate(forward_mutations_update) => {
+                forward_mutations_update.check_limits()
+            }
+            IndexServerToServer::MutationsDigest(node_session_counters) => check_len(
+                node_session_counters,
+                MAX_MUTATIONS_DIGEST_LEN,
+                LimitsError::MutationsDigestTooLong,
+            ),
+            IndexServerToServer::ForwardFriendProposal(friend_proposal) => {
+                friend_proposal.check_limits()
+            }
+        }
+    }
+}
+
+impl CheckLimits for IndexAdminToServer {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        match self {
+            IndexAdminToServer::RequestEdges(_)
+            | IndexAdminToServer::ApplyChange(_)
+            | IndexAdminToServer::RequestJournal(_) => Ok(()),
+        }
+    }
+}
+
+impl CheckLimits for IndexServerToAdmin {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        match self {
+            IndexServerToAdmin::ResponseEdges(response_edges) => check_len(
+                &response_edges.edges,
+                MAX_ADMIN_EDGES,
+                LimitsError::TooManyAdminEdges,
+            ),
+            IndexServerToAdmin::ResponseJournal(response_journal) => check_len(
+                &response_journal.entries,
+                MAX_ADMIN_JOURNAL_LEN,
+                LimitsError::AdminJournalTooLong,
+            ),
+        }
+    }
+}
+
+impl CheckLimits for PeerListeners {
+    fn check_limits(&self) -> Result<(), LimitsError> {
+        check_len(
+            &self.public_keys,
+            MAX_RELAY_LISTENERS,
+            LimitsError::TooManyListeners,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::crypto::PublicKey;
+    use crate::proto_ser::{
+        ProtoDeserialize, ProtoDeserializeChecked, ProtoSerialize, ProtoSerializeError,
+    };
+
+    fn route(len: usize) -> FriendsRoute {
+        FriendsRoute {
+            public_keys: (0..len)
+                .map(|i| PublicKey::from(&[i as u8; PublicKey::len()]))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_check_limits_route() {
+        assert_eq!(route(MAX_ROUTE_LEN).check_limits(), Ok(()));
+        assert_eq!(
+            route(MAX_ROUTE_LEN + 1).check_limits(),
+            Err(LimitsError::RouteTooLong)
+        );
+    }
+
+    #[test]
+    fn test_proto_deserialize_checked() {
+        let ser_route = route(MAX_ROUTE_LEN).proto_serialize();
+        assert_eq!(
+            FriendsRoute::proto_deserialize_checked(&ser_route).unwrap(),
+            route(MAX_ROUTE_LEN)
+        );
+
+        let ser_route = route(MAX_ROUTE_LEN + 1).proto_serialize();
+        // Plain deserialization does not check list limits:
+        assert!(FriendsRoute::proto_deserialize(&ser_route).is_ok());
+        match FriendsRoute::proto_deserialize_checked(&ser_route) {
+            Err(ProtoSerializeError::LimitsError(LimitsError::RouteTooLong)) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_proto_deserialize_message_too_long() {
+        let data = vec![0u8; MAX_MESSAGE_LEN + 1];
+        match FriendsRoute::proto_deserialize(&data) {
+            Err(ProtoSerializeError::MessageTooLong) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_proto_deserialize_traversal_limit() {
+        // A packed segment table announcing a single segment of 2^24 words, which is more than
+        // MAX_TRAVERSAL_WORDS:
+        let data = vec![0x80, 0x01];
+        assert!(FriendsRoute::proto_deserialize(&data).is_err());
+    }
+}
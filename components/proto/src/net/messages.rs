@@ -1,4 +1,6 @@
 use std::convert::TryFrom;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
@@ -10,6 +12,7 @@ use capnp_conv::{capnp_conv, CapnpConvError, ReadCapnp, WriteCapnp};
 use common::ser_utils::ser_string;
 
 use crate::consts::MAX_NET_ADDRESS_LENGTH;
+use crate::wrapper::Wrapper;
 
 #[capnp_conv(crate::common_capnp::net_address)]
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Display)]
@@ -74,3 +77,153 @@ impl FromStr for NetAddress {
         })
     }
 }
+
+#[capnp_conv(crate::common_capnp::tcp_address_v4)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TcpAddressV4 {
+    pub address: u32,
+    pub port: u16,
+}
+
+#[capnp_conv(crate::common_capnp::tcp_address_v6)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TcpAddressV6 {
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub address: u128,
+    pub port: u16,
+}
+
+/// A resolved TCP address.
+///
+/// Two addresses may point to the same host while not being equal: An IPv4 client connecting to
+/// a dual-stack listener shows up with a v4-mapped IPv6 address (`::ffff:a.b.c.d`).
+/// Use `canonical()` before comparing hosts.
+#[capnp_conv(crate::common_capnp::tcp_address)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TcpAddress {
+    V4(TcpAddressV4),
+    V6(TcpAddressV6),
+}
+
+/// Convert a v4-mapped IPv6 address (`::ffff:a.b.c.d`) to the IPv4 address it represents.
+/// Any other address is returned unchanged.
+///
+/// Note that IPv4-compatible addresses (`::a.b.c.d`) are deprecated and are not converted, so that
+/// `::1` is never confused with `0.0.0.1`.
+pub fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(ipv6_addr) => match ipv6_addr.segments() {
+            [0, 0, 0, 0, 0, 0xffff, high, low] => IpAddr::V4(Ipv4Addr::new(
+                (high >> 8) as u8,
+                high as u8,
+                (low >> 8) as u8,
+                low as u8,
+            )),
+            _ => ip,
+        },
+    }
+}
+
+impl TcpAddress {
+    pub fn ip(&self) -> IpAddr {
+        match self {
+            TcpAddress::V4(tcp_address_v4) => IpAddr::V4(Ipv4Addr::from(tcp_address_v4.address)),
+            TcpAddress::V6(tcp_address_v6) => IpAddr::V6(Ipv6Addr::from(tcp_address_v6.address)),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        match self {
+            TcpAddress::V4(tcp_address_v4) => tcp_address_v4.port,
+            TcpAddress::V6(tcp_address_v6) => tcp_address_v6.port,
+        }
+    }
+
+    /// The canonical form of this address: v4-mapped IPv6 addresses are converted to IPv4.
+    /// Two canonical addresses are equal if and only if they point to the same host and port.
+    pub fn canonical(&self) -> TcpAddress {
+        TcpAddress::from(SocketAddr::new(canonical_ip(self.ip()), self.port()))
+    }
+}
+
+impl From<SocketAddr> for TcpAddress {
+    fn from(socket_addr: SocketAddr) -> Self {
+        let port = socket_addr.port();
+        match socket_addr.ip() {
+            IpAddr::V4(ipv4_addr) => TcpAddress::V4(TcpAddressV4 {
+                address: u32::from(ipv4_addr),
+                port,
+            }),
+            IpAddr::V6(ipv6_addr) => TcpAddress::V6(TcpAddressV6 {
+                address: u128::from(ipv6_addr),
+                port,
+            }),
+        }
+    }
+}
+
+impl From<&TcpAddress> for SocketAddr {
+    fn from(tcp_address: &TcpAddress) -> Self {
+        SocketAddr::new(tcp_address.ip(), tcp_address.port())
+    }
+}
+
+impl fmt::Display for TcpAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", SocketAddr::from(self))
+    }
+}
+
+#[derive(Debug)]
+pub enum TcpAddressError {
+    InvalidAddress,
+}
+
+impl FromStr for TcpAddress {
+    type Err = TcpAddressError;
+
+    /// Parse an address of the form `127.0.0.1:1337` or `[::1]:1337`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let socket_addr = SocketAddr::from_str(s).map_err(|_| TcpAddressError::InvalidAddress)?;
+        Ok(TcpAddress::from(socket_addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::proto_ser::{ProtoDeserialize, ProtoSerialize};
+
+    #[test]
+    fn test_tcp_address_canonical() {
+        let tcp_address_v4 = TcpAddress::from_str("1.2.3.4:1337").unwrap();
+        let tcp_address_mapped = TcpAddress::from_str("[::ffff:1.2.3.4]:1337").unwrap();
+        assert_ne!(tcp_address_v4, tcp_address_mapped);
+        assert_eq!(tcp_address_v4.canonical(), tcp_address_mapped.canonical());
+        assert_eq!(tcp_address_mapped.canonical().to_string(), "1.2.3.4:1337");
+
+        // Other IPv6 addresses are kept as is:
+        let tcp_address_v6 = TcpAddress::from_str("[::1]:1337").unwrap();
+        assert_eq!(tcp_address_v6.canonical(), tcp_address_v6);
+        let tcp_address_v6 = TcpAddress::from_str("[2001:db8::1]:1337").unwrap();
+        assert_eq!(tcp_address_v6.canonical(), tcp_address_v6);
+        assert_eq!(tcp_address_v6.to_string(), "[2001:db8::1]:1337");
+
+        assert!(TcpAddress::from_str("1.2.3.4").is_err());
+        assert!(TcpAddress::from_str("::1:1337").is_err());
+    }
+
+    #[test]
+    fn test_serialize_tcp_address() {
+        for address in &["1.2.3.4:1337", "[::ffff:1.2.3.4]:0", "[2001:db8::ff]:65535"] {
+            let tcp_address = TcpAddress::from_str(address).unwrap();
+            let ser = tcp_address.proto_serialize();
+            let deser = TcpAddress::proto_deserialize(&ser).unwrap();
+            assert_eq!(deser, tcp_address);
+            assert_eq!(SocketAddr::from(&deser).to_string(), *address);
+        }
+    }
+}
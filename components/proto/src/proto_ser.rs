@@ -2,9 +2,15 @@ use derive_more::From;
 
 use capnp_conv::{CapnpConvError, FromCapnpBytes, ToCapnpBytes};
 
+use crate::limits::{CheckLimits, LimitsError, MAX_MESSAGE_LEN, MAX_TRAVERSAL_WORDS};
+use crate::wire::WireError;
+
 #[derive(Debug, From)]
 pub enum ProtoSerializeError {
     CapnpConvError(CapnpConvError),
+    MessageTooLong,
+    LimitsError(LimitsError),
+    WireError(WireError),
 }
 
 pub trait ProtoSerialize {
@@ -17,6 +23,12 @@ pub trait ProtoDeserialize: Sized {
     fn proto_deserialize(bytes: &[u8]) -> Result<Self, ProtoSerializeError>;
 }
 
+pub trait ProtoDeserializeChecked: ProtoDeserialize {
+    /// Deserialize a message received from a remote peer, and verify that it is within the
+    /// limits defined in `limits`.
+    fn proto_deserialize_checked(bytes: &[u8]) -> Result<Self, ProtoSerializeError>;
+}
+
 impl<T> ProtoSerialize for T
 where
     T: ToCapnpBytes,
@@ -31,6 +43,20 @@ where
     T: FromCapnpBytes,
 {
     fn proto_deserialize(bytes: &[u8]) -> Result<Self, ProtoSerializeError> {
-        Ok(Self::from_capnp_bytes(bytes)?)
+        if bytes.len() > MAX_MESSAGE_LEN {
+            return Err(ProtoSerializeError::MessageTooLong);
+        }
+        Ok(Self::from_capnp_bytes_limited(bytes, MAX_TRAVERSAL_WORDS)?)
+    }
+}
+
+impl<T> ProtoDeserializeChecked for T
+where
+    T: ProtoDeserialize + CheckLimits,
+{
+    fn proto_deserialize_checked(bytes: &[u8]) -> Result<Self, ProtoSerializeError> {
+        let msg = Self::proto_deserialize(bytes)?;
+        msg.check_limits()?;
+        Ok(msg)
     }
 }
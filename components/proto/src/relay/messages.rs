@@ -10,6 +10,36 @@ pub enum InitConnection {
     Accept(PublicKey),
     // remote side wants to connect to public_key
     Connect(PublicKey),
+    // remote side wants to listen, and receive all connections multiplexed over this connection
+    ListenMux,
+    // remote side is a peer relay, announcing the public keys listening on it
+    Peer,
+    // remote side is a peer relay, forwarding a connection from one of its clients
+    ForwardConnect(ForwardConnect),
+    // remote side wants to measure the round trip time to the relay
+    Ping,
+}
+
+#[capnp_conv(crate::relay_capnp::forward_connect)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ForwardConnect {
+    pub init_public_key: PublicKey,
+    pub connect_public_key: PublicKey,
+}
+
+/// Public keys listening on a peer relay.
+/// A relay forwards connections to those public keys to the peer relay.
+#[capnp_conv(crate::relay_capnp::peer_listeners)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PeerListeners {
+    pub public_keys: Vec<PublicKey>,
+}
+
+/// Sent over a ping connection. The relay echoes back every ping it receives.
+#[capnp_conv(crate::relay_capnp::relay_ping)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RelayPing {
+    pub nonce: u64,
 }
 
 #[capnp_conv(crate::relay_capnp::reject_connection)]
@@ -23,3 +53,40 @@ pub struct RejectConnection {
 pub struct IncomingConnection {
     pub public_key: PublicKey,
 }
+
+#[capnp_conv(crate::relay_capnp::open_stream)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct OpenStream {
+    pub stream_id: u64,
+    pub public_key: PublicKey,
+}
+
+#[capnp_conv(crate::relay_capnp::stream_data)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct StreamData {
+    pub stream_id: u64,
+    pub data: Vec<u8>,
+}
+
+#[capnp_conv(crate::relay_capnp::mux_message)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum MuxMessage {
+    /// A new connection from a remote public key (Sent only by the relay)
+    OpenStream(OpenStream),
+    Data(StreamData),
+    /// Close a stream. Sent by the client to reject a connection.
+    CloseStream(u64),
+    /// The relay did not accept the listen connection (Sent only by the relay, right before it
+    /// closes the connection)
+    RejectListen(RejectListen),
+}
+
+/// The reason for rejecting a multiplexed listen connection
+#[capnp_conv(crate::relay_capnp::reject_listen)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RejectListen {
+    /// The public key already has an open listen connection
+    AlreadyListening,
+    /// Too many public keys are listening on the relay
+    TooManyListeners,
+}
@@ -189,7 +189,8 @@ mod tests {
     use super::*;
 
     use crate::report::messages::{
-        ChannelConsistentReport, CurrencyConfigReport, CurrencyReport, McBalanceReport,
+        BandwidthReport, ChannelConsistentReport, CurrencyConfigReport, CurrencyReport,
+        DrainStatusReport, McBalanceReport, SecureChannelStatsReport,
     };
     use std::convert::TryFrom;
 
@@ -214,18 +215,24 @@ mod tests {
                         rate: Rate { mul: 0, add: 0 },
                         remote_max_debt: 200,
                         is_open: true,
+                        opt_max_outflow: None,
+                        outflow: 0,
                     },
                     CurrencyConfigReport {
                         currency: currency2.clone(),
                         rate: Rate { mul: 0, add: 0 },
                         remote_max_debt: 200,
                         is_open: false,
+                        opt_max_outflow: None,
+                        outflow: 0,
                     },
                     CurrencyConfigReport {
                         currency: currency3.clone(),
                         rate: Rate { mul: 1, add: 10 },
                         remote_max_debt: 200,
                         is_open: true,
+                        opt_max_outflow: None,
+                        outflow: 0,
                     },
                 ],
                 remote_relays: vec![],
@@ -260,6 +267,11 @@ mod tests {
                     ],
                 }),
                 status: FriendStatusReport::Enabled,
+                missed_beats: 0,
+                is_gated: false,
+                secure_channel_stats: SecureChannelStatsReport::default(),
+                drain_status: DrainStatusReport::Active,
+                bandwidth: BandwidthReport::default(),
             },
         );
 
@@ -272,6 +284,8 @@ mod tests {
                     rate: Rate { mul: 2, add: 2 },
                     remote_max_debt: 200,
                     is_open: true,
+                    opt_max_outflow: None,
+                    outflow: 0,
                 }],
                 remote_relays: vec![],
                 opt_last_incoming_move_token: None,
@@ -287,12 +301,19 @@ mod tests {
                     }],
                 }),
                 status: FriendStatusReport::Enabled,
+                missed_beats: 0,
+                is_gated: false,
+                secure_channel_stats: SecureChannelStatsReport::default(),
+                drain_status: DrainStatusReport::Active,
+                bandwidth: BandwidthReport::default(),
             },
         );
         let funder_report = FunderReport {
             local_public_key: pk1.clone(),
             relays: Vec::new(),
             friends,
+            relay_latencies: Vec::new(),
+            rebalance_suggestions: Vec::new(),
         };
         let friends_info: HashMap<(PublicKey, Currency), FriendInfo> =
             calc_friends_info(&funder_report).collect();
@@ -335,24 +356,32 @@ mod tests {
                         rate: Rate { mul: 0, add: 0 },
                         remote_max_debt: 200,
                         is_open: true,
+                        opt_max_outflow: None,
+                        outflow: 0,
                     },
                     CurrencyConfigReport {
                         currency: currency2.clone(),
                         rate: Rate { mul: 1, add: 10 },
                         remote_max_debt: 200,
                         is_open: true,
+                        opt_max_outflow: None,
+                        outflow: 0,
                     },
                     CurrencyConfigReport {
                         currency: currency3.clone(),
                         rate: Rate { mul: 1, add: 10 },
                         remote_max_debt: 200,
                         is_open: false,
+                        opt_max_outflow: None,
+                        outflow: 0,
                     },
                     CurrencyConfigReport {
                         currency: currency4.clone(),
                         rate: Rate { mul: 1, add: 10 },
                         remote_max_debt: 40,
                         is_open: true,
+                        opt_max_outflow: None,
+                        outflow: 0,
                     },
                 ],
                 remote_relays: vec![],
@@ -395,6 +424,11 @@ mod tests {
                     ],
                 }),
                 status: FriendStatusReport::Enabled,
+                missed_beats: 0,
+                is_gated: false,
+                secure_channel_stats: SecureChannelStatsReport::default(),
+                drain_status: DrainStatusReport::Active,
+                bandwidth: BandwidthReport::default(),
             },
         );
 
@@ -402,6 +436,8 @@ mod tests {
             local_public_key: pk1.clone(),
             relays: Vec::new(),
             friends,
+            relay_latencies: Vec::new(),
+            rebalance_suggestions: Vec::new(),
         };
 
         let mut friends = HashMap::new();
@@ -415,18 +451,24 @@ mod tests {
                         rate: Rate { mul: 0, add: 0 },
                         remote_max_debt: 300,
                         is_open: true,
+                        opt_max_outflow: None,
+                        outflow: 0,
                     },
                     CurrencyConfigReport {
                         currency: currency3.clone(),
                         rate: Rate { mul: 1, add: 10 },
                         remote_max_debt: 200,
                         is_open: true,
+                        opt_max_outflow: None,
+                        outflow: 0,
                     },
                     CurrencyConfigReport {
                         currency: currency4.clone(),
                         rate: Rate { mul: 1, add: 10 },
                         remote_max_debt: 40,
                         is_open: true,
+                        opt_max_outflow: None,
+                        outflow: 0,
                     },
                 ],
                 remote_relays: vec![],
@@ -461,12 +503,19 @@ mod tests {
                     ],
                 }),
                 status: FriendStatusReport::Enabled,
+                missed_beats: 0,
+                is_gated: false,
+                secure_channel_stats: SecureChannelStatsReport::default(),
+                drain_status: DrainStatusReport::Active,
+                bandwidth: BandwidthReport::default(),
             },
         );
         let new_funder_report = FunderReport {
             local_public_key: pk1.clone(),
             relays: Vec::new(),
             friends,
+            relay_latencies: Vec::new(),
+            rebalance_suggestions: Vec::new(),
         };
 
         let index_mutations = calc_index_mutations(&old_funder_report, &new_funder_report);
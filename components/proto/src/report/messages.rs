@@ -6,7 +6,7 @@ use serde::{Deserialize, Serialize};
 
 use common::mutable_state::MutableState;
 use common::never::Never;
-use common::ser_utils::ser_string;
+use common::ser_utils::{ser_b64, ser_map_b64_any, ser_string};
 
 use capnp_conv::{capnp_conv, CapnpConvError, ReadCapnp, WriteCapnp};
 
@@ -14,17 +14,21 @@ use crate::crypto::{HashResult, PublicKey, RandValue, Signature, Uid};
 
 use crate::app_server::messages::{NamedRelayAddress, RelayAddress};
 use crate::funder::messages::{
-    Currency, CurrencyBalance, FriendStatus, Rate, RequestsStatus, TokenInfo,
+    Currency, CurrencyBalance, FriendStatus, FriendsRoute, MaxOutflow, OptMaxOutflow, Rate,
+    RequestsStatus, TokenInfo,
 };
 use crate::net::messages::NetAddress;
 use crate::wrapper::Wrapper;
 
 #[capnp_conv(crate::report_capnp::move_token_hashed_report)]
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MoveTokenHashedReport {
+    #[serde(with = "ser_b64")]
     pub prefix_hash: HashResult,
     pub token_info: TokenInfo,
+    #[serde(with = "ser_b64")]
     pub rand_nonce: RandValue,
+    #[serde(with = "ser_b64")]
     pub new_token: Signature,
 }
 
@@ -61,7 +65,7 @@ pub struct McBalanceReport {
 }
 
 #[capnp_conv(crate::report_capnp::friend_liveness_report)]
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum FriendLivenessReport {
     Online,
     Offline,
@@ -78,15 +82,16 @@ impl FriendLivenessReport {
 }
 
 #[capnp_conv(crate::report_capnp::currency_report)]
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Arbitrary, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CurrencyReport {
     pub currency: Currency,
     pub balance: McBalanceReport,
 }
 
 #[capnp_conv(crate::report_capnp::reset_terms_report)]
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ResetTermsReport {
+    #[serde(with = "ser_b64")]
     pub reset_token: Signature,
     pub balance_for_reset: Vec<CurrencyBalance>,
 }
@@ -117,22 +122,55 @@ impl From<OptRemoteResetTerms> for Option<ResetTermsReport> {
     }
 }
 
+/// A transaction that was pending in the channel when the channel became inconsistent.
+#[capnp_conv(crate::report_capnp::disputed_transaction)]
+#[derive(Arbitrary, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisputedTransaction {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub currency: Currency,
+    /// Was the request sent to us by the friend?
+    pub is_incoming: bool,
+    pub route: FriendsRoute,
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub dest_payment: u128,
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub left_fees: u128,
+}
+
+/// Material for resolving an inconsistency with a friend out of band (For example, by showing it
+/// to the friend). Assembled when the channel becomes inconsistent, before the pending
+/// transactions of the channel are canceled.
+#[capnp_conv(crate::report_capnp::dispute_evidence)]
+#[derive(Arbitrary, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisputeEvidence {
+    /// A serialized `ChannelProof`. Contains the last move tokens signed by each side.
+    #[serde(with = "ser_b64")]
+    pub channel_proof: Vec<u8>,
+    /// Balances of the channel in every currency, as computed by us
+    pub currency_reports: Vec<CurrencyReport>,
+    pub pending_transactions: Vec<DisputedTransaction>,
+}
+
 #[capnp_conv(crate::report_capnp::channel_inconsistent_report)]
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ChannelInconsistentReport {
     pub local_reset_terms: Vec<CurrencyBalance>,
     #[capnp_conv(with = OptRemoteResetTerms)]
     pub opt_remote_reset_terms: Option<ResetTermsReport>,
+    pub dispute_evidence: DisputeEvidence,
 }
 
 #[capnp_conv(crate::report_capnp::channel_consistent_report)]
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ChannelConsistentReport {
     pub currency_reports: Vec<CurrencyReport>,
 }
 
 #[capnp_conv(crate::report_capnp::channel_status_report)]
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ChannelStatusReport {
     Inconsistent(ChannelInconsistentReport),
     Consistent(ChannelConsistentReport),
@@ -169,6 +207,57 @@ impl From<OptLastIncomingMoveToken> for Option<MoveTokenHashedReport> {
     }
 }
 
+#[allow(clippy::large_enum_variant)]
+#[capnp_conv(crate::report_capnp::opt_last_outgoing_move_token)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OptLastOutgoingMoveToken {
+    MoveTokenHashed(MoveTokenHashedReport),
+    Empty,
+}
+
+impl From<Option<MoveTokenHashedReport>> for OptLastOutgoingMoveToken {
+    fn from(opt: Option<MoveTokenHashedReport>) -> Self {
+        match opt {
+            Some(move_token_hashed_report) => {
+                OptLastOutgoingMoveToken::MoveTokenHashed(move_token_hashed_report)
+            }
+            None => OptLastOutgoingMoveToken::Empty,
+        }
+    }
+}
+
+impl From<OptLastOutgoingMoveToken> for Option<MoveTokenHashedReport> {
+    fn from(opt: OptLastOutgoingMoveToken) -> Self {
+        match opt {
+            OptLastOutgoingMoveToken::MoveTokenHashed(move_token_hashed_report) => {
+                Some(move_token_hashed_report)
+            }
+            OptLastOutgoingMoveToken::Empty => None,
+        }
+    }
+}
+
+/// The latest signed move tokens of a token channel, as kept by one side of the channel.
+/// Every move token is signed by the side that sent it, and commits to the balances of the
+/// channel (`token_info`). A channel proof can be verified offline, allowing to prove balances
+/// out of band when a channel becomes inconsistent.
+#[capnp_conv(crate::report_capnp::channel_proof)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelProof {
+    /// The exporting node
+    #[serde(with = "ser_b64")]
+    pub local_public_key: PublicKey,
+    #[serde(with = "ser_b64")]
+    pub friend_public_key: PublicKey,
+    /// Last move token received from the friend (Signed by the friend)
+    #[capnp_conv(with = OptLastIncomingMoveToken)]
+    pub opt_last_incoming_move_token: Option<MoveTokenHashedReport>,
+    /// Last move token sent to the friend (Signed by the exporting node).
+    /// Only kept until the friend sends back a move token.
+    #[capnp_conv(with = OptLastOutgoingMoveToken)]
+    pub opt_last_outgoing_move_token: Option<MoveTokenHashedReport>,
+}
+
 #[capnp_conv(crate::report_capnp::currency_config_report)]
 #[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct CurrencyConfigReport {
@@ -182,10 +271,78 @@ pub struct CurrencyConfigReport {
     pub remote_max_debt: u128,
     /// Can requests be sent through this mutual credit?
     pub is_open: bool,
+    /// Limit over the credits sent to the friend during a window of time
+    #[capnp_conv(with = OptMaxOutflow)]
+    pub opt_max_outflow: Option<MaxOutflow>,
+    /// Credits sent to the friend during the current window
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub outflow: u128,
+}
+
+#[capnp_conv(crate::report_capnp::currency_outflow_report)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct CurrencyOutflowReport {
+    pub currency: Currency,
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub outflow: u128,
+}
+
+/// Diagnostics of the secure channels with a friend, counted since the node started.
+/// A growing amount of decryption failures or nonce replays indicates a friend (Or someone along
+/// the way) that sends malformed or replayed ciphertexts.
+#[capnp_conv(crate::report_capnp::secure_channel_stats_report)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecureChannelStatsReport {
+    pub decryption_failures: u64,
+    pub nonce_replays: u64,
+    pub rekeys: u64,
+}
+
+/// Bytes of messages exchanged with a friend through our connections to the friend.
+/// Allows to find the friends that consume most of the bandwidth of our relays.
+#[capnp_conv(crate::report_capnp::bandwidth_report)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BandwidthReport {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl BandwidthReport {
+    /// Add the bytes counted in `other`
+    pub fn add(&mut self, other: &BandwidthReport) {
+        self.bytes_sent = self.bytes_sent.saturating_add(other.bytes_sent);
+        self.bytes_received = self.bytes_received.saturating_add(other.bytes_received);
+    }
+}
+
+/// The final state of a drained channel with a friend.
+#[capnp_conv(crate::report_capnp::closing_statement)]
+#[derive(Arbitrary, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClosingStatement {
+    /// Final balance with the friend in every currency
+    pub final_balances: Vec<CurrencyBalance>,
+    /// A serialized `ChannelProof`. Contains the latest move tokens signed by both sides, which
+    /// commit to the final balances.
+    #[serde(with = "ser_b64")]
+    pub channel_proof: Vec<u8>,
+}
+
+/// Progress of closing the channel with a friend gracefully.
+#[capnp_conv(crate::report_capnp::drain_status_report)]
+#[derive(Arbitrary, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DrainStatusReport {
+    /// The friend accepts new requests
+    Active,
+    /// New requests are refused. Waiting for all pending requests to settle.
+    Draining,
+    /// All pending requests were settled. The channel is closed.
+    Closed(ClosingStatement),
 }
 
 #[capnp_conv(crate::report_capnp::friend_report)]
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FriendReport<B = NetAddress> {
     pub name: String,
     pub remote_relays: Vec<RelayAddress<B>>,
@@ -199,6 +356,16 @@ pub struct FriendReport<B = NetAddress> {
     pub liveness: FriendLivenessReport, // is the friend online/offline?
     pub channel_status: ChannelStatusReport,
     pub status: FriendStatusReport,
+    /// Amount of consecutive keepalive beats the friend has missed.
+    /// A nonzero value means the connection is flaky, but not yet considered dead.
+    pub missed_beats: u64,
+    /// Is the friend gated? New requests are not queued through a gated friend, because its
+    /// recent uptime is too low.
+    pub is_gated: bool,
+    pub secure_channel_stats: SecureChannelStatsReport,
+    pub drain_status: DrainStatusReport,
+    /// Bytes exchanged with the friend since the node started
+    pub bandwidth: BandwidthReport,
 }
 
 #[capnp_conv(crate::report_capnp::pk_friend_report)]
@@ -245,21 +412,84 @@ impl From<HashMap<PublicKey, FriendReport<NetAddress>>> for PkFriendReportList {
     }
 }
 
+#[capnp_conv(crate::report_capnp::relay_latency_report::opt_latency_ms)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OptLatencyMs {
+    LatencyMs(u64),
+    Empty,
+}
+
+// TODO: Replace with a macro:
+impl From<Option<u64>> for OptLatencyMs {
+    fn from(opt: Option<u64>) -> Self {
+        match opt {
+            Some(latency_ms) => OptLatencyMs::LatencyMs(latency_ms),
+            None => OptLatencyMs::Empty,
+        }
+    }
+}
+
+impl From<OptLatencyMs> for Option<u64> {
+    fn from(opt: OptLatencyMs) -> Self {
+        match opt {
+            OptLatencyMs::LatencyMs(latency_ms) => Some(latency_ms),
+            OptLatencyMs::Empty => None,
+        }
+    }
+}
+
+/// Latest round trip time measured through a relay, using ping frames of the relay protocol.
+#[capnp_conv(crate::report_capnp::relay_latency_report)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayLatencyReport {
+    #[serde(with = "ser_b64")]
+    pub public_key: PublicKey,
+    /// Latency in milliseconds. None if the relay could not be reached.
+    #[capnp_conv(with = OptLatencyMs)]
+    pub opt_latency_ms: Option<u64>,
+}
+
+/// A suggestion to move `amount` credits between two friends, by paying ourselves along a cycle
+/// route: `[local, to, ..., from, local]`.
+///
+/// `from_public_key` has spare receive capacity, and `to_public_key` has little receive capacity
+/// left. After the self payment, `to_public_key` can send us more credits again.
+#[capnp_conv(crate::report_capnp::rebalance_suggestion)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RebalanceSuggestion {
+    pub currency: Currency,
+    #[serde(with = "ser_b64")]
+    pub from_public_key: PublicKey,
+    #[serde(with = "ser_b64")]
+    pub to_public_key: PublicKey,
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub amount: u128,
+}
+
 /// A FunderReport is a summary of a FunderState.
 /// It contains the information the Funder exposes to the user apps of the Offst node.
 #[capnp_conv(crate::report_capnp::funder_report)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 // TODO: Removed A: Clone here and ImHashMap. Should this struct be cloneable for some reason?
 pub struct FunderReport<B = NetAddress> {
+    #[serde(with = "ser_b64")]
     pub local_public_key: PublicKey,
     pub relays: Vec<NamedRelayAddress<B>>,
     #[capnp_conv(with = PkFriendReportList)]
+    #[serde(with = "ser_map_b64_any")]
     pub friends: HashMap<PublicKey, FriendReport<B>>,
+    /// Latencies measured by the Channeler to the relays it knows about
+    /// (Our relays and our friends' relays).
+    pub relay_latencies: Vec<RelayLatencyReport>,
+    /// Suggested self payments that restore the receive capacity of depleted friends.
+    /// Recalculated periodically by the Funder.
+    pub rebalance_suggestions: Vec<RebalanceSuggestion>,
 }
 
 #[allow(clippy::large_enum_variant)]
 #[capnp_conv(crate::report_capnp::friend_report_mutation)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FriendReportMutation<B = NetAddress> {
     SetRemoteRelays(Vec<RelayAddress<B>>),
     SetName(String),
@@ -270,11 +500,18 @@ pub enum FriendReportMutation<B = NetAddress> {
     #[capnp_conv(with = OptLastIncomingMoveToken)]
     SetOptLastIncomingMoveToken(Option<MoveTokenHashedReport>),
     SetLiveness(FriendLivenessReport),
+    SetMissedBeats(u64),
+    SetGated(bool),
+    SetCurrencyOutflow(CurrencyOutflowReport),
+    SetSecureChannelStats(SecureChannelStatsReport),
+    SetDrainStatus(DrainStatusReport),
+    SetBandwidth(BandwidthReport),
 }
 
 #[capnp_conv(crate::report_capnp::add_friend_report)]
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AddFriendReport<B = NetAddress> {
+    #[serde(with = "ser_b64")]
     pub friend_public_key: PublicKey,
     pub name: String,
     pub relays: Vec<RelayAddress<B>>,
@@ -284,21 +521,22 @@ pub struct AddFriendReport<B = NetAddress> {
 }
 
 #[capnp_conv(crate::report_capnp::pk_friend_report_mutation)]
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PkFriendReportMutation<B = NetAddress> {
+    #[serde(with = "ser_b64")]
     friend_public_key: PublicKey,
     friend_report_mutation: FriendReportMutation<B>,
 }
 
-impl From<PkFriendReportMutation> for (PublicKey, FriendReportMutation) {
-    fn from(input: PkFriendReportMutation) -> Self {
+impl<B> From<PkFriendReportMutation<B>> for (PublicKey, FriendReportMutation<B>) {
+    fn from(input: PkFriendReportMutation<B>) -> Self {
         (input.friend_public_key, input.friend_report_mutation)
     }
 }
 
-impl From<(PublicKey, FriendReportMutation)> for PkFriendReportMutation {
+impl<B> From<(PublicKey, FriendReportMutation<B>)> for PkFriendReportMutation<B> {
     fn from(
-        (friend_public_key, friend_report_mutation): (PublicKey, FriendReportMutation),
+        (friend_public_key, friend_report_mutation): (PublicKey, FriendReportMutation<B>),
     ) -> Self {
         Self {
             friend_public_key,
@@ -307,16 +545,62 @@ impl From<(PublicKey, FriendReportMutation)> for PkFriendReportMutation {
     }
 }
 
+/// Serialize the (friend_public_key, friend_report_mutation) pair of
+/// `FunderReportMutation::PkFriendReportMutation` in the same form as `PkFriendReportMutation`.
+mod ser_pk_friend_report_mutation {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use common::ser_utils::ser_b64;
+
+    use super::{FriendReportMutation, PkFriendReportMutation, PublicKey};
+
+    #[derive(Serialize)]
+    struct PkFriendReportMutationRef<'a, B> {
+        #[serde(with = "ser_b64")]
+        friend_public_key: &'a PublicKey,
+        friend_report_mutation: &'a FriendReportMutation<B>,
+    }
+
+    pub fn serialize<B, S>(
+        input: &(PublicKey, FriendReportMutation<B>),
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        B: Serialize,
+        S: Serializer,
+    {
+        let (friend_public_key, friend_report_mutation) = input;
+        PkFriendReportMutationRef {
+            friend_public_key,
+            friend_report_mutation,
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, B, D>(
+        deserializer: D,
+    ) -> Result<(PublicKey, FriendReportMutation<B>), D::Error>
+    where
+        B: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Ok(PkFriendReportMutation::deserialize(deserializer)?.into())
+    }
+}
+
 #[allow(clippy::large_enum_variant)]
 #[capnp_conv(crate::report_capnp::funder_report_mutation)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FunderReportMutation<B = NetAddress> {
     AddRelay(NamedRelayAddress<B>),
-    RemoveRelay(PublicKey),
+    RemoveRelay(#[serde(with = "ser_b64")] PublicKey),
     AddFriend(AddFriendReport<B>),
-    RemoveFriend(PublicKey),
+    RemoveFriend(#[serde(with = "ser_b64")] PublicKey),
     #[capnp_conv(with = PkFriendReportMutation<NetAddress>)]
+    #[serde(with = "ser_pk_friend_report_mutation")]
     PkFriendReportMutation((PublicKey, FriendReportMutation<B>)),
+    SetRelayLatency(RelayLatencyReport),
+    SetRebalanceSuggestions(Vec<RebalanceSuggestion>),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -367,7 +651,10 @@ where
                     .iter()
                     .position(|c_c| c_c.currency == currency_config_report.currency)
                 {
+                    // The outflow is only updated by `SetCurrencyOutflow`:
+                    let outflow = self.currency_configs[pos].outflow;
                     self.currency_configs[pos] = currency_config_report.clone();
+                    self.currency_configs[pos].outflow = outflow;
                 } else {
                     // Not found:
                     self.currency_configs.push(currency_config_report.clone());
@@ -395,6 +682,32 @@ where
             }
             FriendReportMutation::SetLiveness(friend_liveness_report) => {
                 self.liveness = friend_liveness_report.clone();
+                // A change in liveness always starts a fresh connection (or none at all):
+                self.missed_beats = 0;
+            }
+            FriendReportMutation::SetMissedBeats(missed_beats) => {
+                self.missed_beats = *missed_beats;
+            }
+            FriendReportMutation::SetGated(is_gated) => {
+                self.is_gated = *is_gated;
+            }
+            FriendReportMutation::SetSecureChannelStats(secure_channel_stats) => {
+                self.secure_channel_stats = secure_channel_stats.clone();
+            }
+            FriendReportMutation::SetDrainStatus(drain_status) => {
+                self.drain_status = drain_status.clone();
+            }
+            FriendReportMutation::SetBandwidth(bandwidth) => {
+                self.bandwidth = bandwidth.clone();
+            }
+            FriendReportMutation::SetCurrencyOutflow(currency_outflow_report) => {
+                if let Some(currency_config) = self
+                    .currency_configs
+                    .iter_mut()
+                    .find(|c_c| c_c.currency == currency_outflow_report.currency)
+                {
+                    currency_config.outflow = currency_outflow_report.outflow;
+                }
             }
         };
         Ok(())
@@ -436,6 +749,11 @@ where
                     liveness: FriendLivenessReport::Offline,
                     channel_status: add_friend_report.channel_status.clone(),
                     status: FriendStatusReport::from(&FriendStatus::Disabled),
+                    missed_beats: 0,
+                    is_gated: false,
+                    secure_channel_stats: SecureChannelStatsReport::default(),
+                    drain_status: DrainStatusReport::Active,
+                    bandwidth: BandwidthReport::default(),
                 };
                 if self
                     .friends
@@ -467,6 +785,18 @@ where
                     .map_err(|_| unreachable!())?;
                 Ok(())
             }
+            FunderReportMutation::SetRelayLatency(relay_latency_report) => {
+                // Remove duplicates:
+                self.relay_latencies.retain(|cur_relay_latency_report| {
+                    cur_relay_latency_report.public_key != relay_latency_report.public_key
+                });
+                self.relay_latencies.push(relay_latency_report.clone());
+                Ok(())
+            }
+            FunderReportMutation::SetRebalanceSuggestions(rebalance_suggestions) => {
+                self.rebalance_suggestions = rebalance_suggestions.clone();
+                Ok(())
+            }
         }
     }
 }
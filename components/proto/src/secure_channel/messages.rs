@@ -1,6 +1,6 @@
 use capnp_conv::{capnp_conv, CapnpConvError, ReadCapnp, WriteCapnp};
 
-use crate::crypto::{DhPublicKey, PublicKey, RandValue, Salt, Signature};
+use crate::crypto::{DhPublicKey, HashResult, PublicKey, RandValue, Salt, Signature};
 
 #[capnp_conv(crate::dh_capnp::exchange_rand_nonce::opt_dest_public_key)]
 #[derive(Debug, PartialEq, Eq)]
@@ -27,6 +27,31 @@ impl From<OptDestPublicKey> for Option<PublicKey> {
     }
 }
 
+#[capnp_conv(crate::dh_capnp::exchange_rand_nonce::opt_ticket_id)]
+#[derive(Debug, PartialEq, Eq)]
+enum OptTicketId {
+    Empty,
+    TicketId(HashResult),
+}
+
+impl From<Option<HashResult>> for OptTicketId {
+    fn from(from: Option<HashResult>) -> Self {
+        match from {
+            Some(ticket_id) => Self::TicketId(ticket_id),
+            None => Self::Empty,
+        }
+    }
+}
+
+impl From<OptTicketId> for Option<HashResult> {
+    fn from(from: OptTicketId) -> Self {
+        match from {
+            OptTicketId::TicketId(ticket_id) => Some(ticket_id),
+            OptTicketId::Empty => None,
+        }
+    }
+}
+
 /// First Diffie-Hellman message:
 #[capnp_conv(crate::dh_capnp::exchange_rand_nonce)]
 #[derive(Debug, PartialEq, Eq)]
@@ -35,6 +60,15 @@ pub struct ExchangeRandNonce {
     pub src_public_key: PublicKey,
     #[capnp_conv(with = OptDestPublicKey)]
     pub opt_dest_public_key: Option<PublicKey>,
+    /// Id of a resumption ticket we have from a previous connection with the remote side.
+    #[capnp_conv(with = OptTicketId)]
+    pub opt_ticket_id: Option<HashResult>,
+    /// Lowest secure channel protocol version supported by the sender.
+    /// Nodes that predate version negotiation send neither `min_version` nor `max_version`,
+    /// which are then read as 0 (The legacy protocol).
+    pub min_version: u32,
+    /// Highest secure channel protocol version supported by the sender.
+    pub max_version: u32,
 }
 
 /// Second Diffie-Hellman message:
@@ -44,19 +78,11 @@ pub struct ExchangeDh {
     pub dh_public_key: DhPublicKey,
     pub rand_nonce: RandValue,
     pub key_salt: Salt,
+    /// The protocol version chosen by the sender
+    pub version: u32,
     pub signature: Signature,
 }
 
-impl ExchangeDh {
-    pub fn signature_buffer(&self) -> Vec<u8> {
-        let mut sbuffer = Vec::new();
-        sbuffer.extend_from_slice(&self.dh_public_key);
-        sbuffer.extend_from_slice(&self.rand_nonce);
-        sbuffer.extend_from_slice(&self.key_salt);
-        sbuffer
-    }
-}
-
 #[capnp_conv(crate::dh_capnp::rekey)]
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Rekey {
@@ -77,3 +103,18 @@ pub struct ChannelMessage {
     pub rand_padding: Vec<u8>,
     pub content: ChannelContent,
 }
+
+/// A diagnostic event emitted by the secure channel layer of a connection.
+/// Repeated events may indicate a remote side that sends malformed or replayed ciphertexts.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SecureChannelReport {
+    /// An incoming message could not be decrypted (It was malformed or tampered with)
+    DecryptionFailure,
+    /// An incoming message carried a nonce that was already used
+    NonceReplay,
+    /// The symmetric keys of the channel were replaced
+    Rekey,
+    /// The channel was established, using the given protocol version.
+    /// Always the first report of a channel.
+    Established(u32),
+}
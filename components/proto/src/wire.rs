@@ -0,0 +1,726 @@
+//! A compact wire format for friend messages.
+//!
+//! Capnp packed messages carry pointers and padding, which take a large part of small messages
+//! (For example, a `MoveToken` with a few operations). The compact format writes the fields of a
+//! message one after the other: Integers are encoded as variable length integers, and strings and
+//! lists are prefixed by their length.
+//!
+//! The compact format is only used between friends that both support it, as negotiated in the
+//! secure channel handshake (See `SC_COMPACT_WIRE_VERSION`). A compact message begins with
+//! `COMPACT_MARKER`, a byte that never begins a capnp packed message. This allows the receiver to
+//! deserialize messages of both formats.
+
+use std::convert::TryFrom;
+
+use crate::app_server::messages::RelayAddress;
+use crate::crypto::{
+    HashResult, HashedLock, InvoiceId, PlainLock, PublicKey, RandValue, Signature, Uid,
+};
+use crate::funder::messages::{
+    CancelSendFundsOp, CollectSendFundsOp, Currency, CurrencyBalance, CurrencyExchange,
+    CurrencyOperations, FriendCapabilities, FriendMessage, FriendTcOp, FriendsRoute, KeyRotation,
+    MoveToken, MoveTokenRequest, RefundSendFundsOp, RequestSendFundsOp, ResetTerms,
+    ResponseSendFundsOp,
+};
+use crate::limits::{CheckLimits, MAX_MESSAGE_LEN};
+use crate::net::messages::NetAddress;
+use crate::proto_ser::{ProtoDeserializeChecked, ProtoSerialize, ProtoSerializeError};
+
+/// The first byte of a message in the compact format.
+/// The first byte of a capnp packed message is the tag of the segment table, which is never zero.
+pub const COMPACT_MARKER: u8 = 0;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum WireError {
+    /// The message ended in the middle of a field
+    UnexpectedEnd,
+    /// A variable length integer does not fit its type
+    IntegerOverflow,
+    /// A list claims to have more items than the amount of bytes left in the message
+    InvalidLength,
+    InvalidBool,
+    InvalidUtf8,
+    /// An unknown variant of an enum
+    InvalidVariant,
+    /// A field is not valid for its type (For example, a currency name that is too long)
+    InvalidValue,
+    /// Bytes were left after the end of the message
+    TrailingBytes,
+}
+
+/// Serialize a Rust struct into bytes using the compact format
+pub trait WireSerialize {
+    /// Append the serialization of `self` to `writer`
+    fn wire_write(&self, writer: &mut Vec<u8>);
+
+    fn wire_serialize(&self) -> Vec<u8> {
+        let mut writer = Vec::new();
+        self.wire_write(&mut writer);
+        writer
+    }
+}
+
+/// Deserialize a Rust struct from bytes using the compact format
+pub trait WireDeserialize: Sized {
+    /// Read `Self` from the beginning of `reader`, advancing `reader` past the bytes that were read
+    fn wire_read(reader: &mut &[u8]) -> Result<Self, WireError>;
+
+    fn wire_deserialize(bytes: &[u8]) -> Result<Self, WireError> {
+        let mut reader = bytes;
+        let res = Self::wire_read(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(WireError::TrailingBytes);
+        }
+        Ok(res)
+    }
+}
+
+fn read_byte(reader: &mut &[u8]) -> Result<u8, WireError> {
+    let (&byte, rest) = reader.split_first().ok_or(WireError::UnexpectedEnd)?;
+    *reader = rest;
+    Ok(byte)
+}
+
+fn read_bytes<'a>(reader: &mut &'a [u8], len: usize) -> Result<&'a [u8], WireError> {
+    if reader.len() < len {
+        return Err(WireError::UnexpectedEnd);
+    }
+    let (bytes, rest) = reader.split_at(len);
+    *reader = rest;
+    Ok(bytes)
+}
+
+/// Write a variable length integer: 7 bits in every byte, least significant bits first.
+/// The highest bit of every byte is set if more bytes follow.
+fn write_varint(writer: &mut Vec<u8>, mut value: u128) {
+    while value >= 0x80 {
+        writer.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    writer.push(value as u8);
+}
+
+fn read_varint(reader: &mut &[u8]) -> Result<u128, WireError> {
+    let mut value = 0u128;
+    let mut shift = 0u32;
+    loop {
+        let byte = read_byte(reader)?;
+        let bits = u128::from(byte & 0x7f);
+        if shift >= 128 || (bits << shift) >> shift != bits {
+            return Err(WireError::IntegerOverflow);
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+/// Read the length of a list. Every item takes at least one byte, so a list can not be longer
+/// than the remaining bytes. This avoids large allocations for malicious lengths.
+fn read_len(reader: &mut &[u8]) -> Result<usize, WireError> {
+    let len = usize::wire_read(reader)?;
+    if len > reader.len() {
+        return Err(WireError::InvalidLength);
+    }
+    Ok(len)
+}
+
+macro_rules! impl_wire_uint {
+    ($($uint:ty),*) => {
+        $(
+            impl WireSerialize for $uint {
+                fn wire_write(&self, writer: &mut Vec<u8>) {
+                    write_varint(writer, *self as u128);
+                }
+            }
+
+            impl WireDeserialize for $uint {
+                fn wire_read(reader: &mut &[u8]) -> Result<Self, WireError> {
+                    <$uint>::try_from(read_varint(reader)?).map_err(|_| WireError::IntegerOverflow)
+                }
+            }
+        )*
+    };
+}
+
+impl_wire_uint!(u32, u64, u128, usize);
+
+/// Signed integers are zigzag encoded, so that values close to zero take few bytes.
+impl WireSerialize for i128 {
+    fn wire_write(&self, writer: &mut Vec<u8>) {
+        write_varint(writer, ((*self << 1) ^ (*self >> 127)) as u128);
+    }
+}
+
+impl WireDeserialize for i128 {
+    fn wire_read(reader: &mut &[u8]) -> Result<Self, WireError> {
+        let value = read_varint(reader)?;
+        Ok((value >> 1) as i128 ^ -((value & 1) as i128))
+    }
+}
+
+impl WireSerialize for bool {
+    fn wire_write(&self, writer: &mut Vec<u8>) {
+        writer.push(*self as u8);
+    }
+}
+
+impl WireDeserialize for bool {
+    fn wire_read(reader: &mut &[u8]) -> Result<Self, WireError> {
+        match read_byte(reader)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(WireError::InvalidBool),
+        }
+    }
+}
+
+fn write_str(writer: &mut Vec<u8>, s: &str) {
+    s.len().wire_write(writer);
+    writer.extend_from_slice(s.as_bytes());
+}
+
+impl WireSerialize for String {
+    fn wire_write(&self, writer: &mut Vec<u8>) {
+        write_str(writer, self);
+    }
+}
+
+impl WireDeserialize for String {
+    fn wire_read(reader: &mut &[u8]) -> Result<Self, WireError> {
+        let len = read_len(reader)?;
+        let bytes = read_bytes(reader, len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| WireError::InvalidUtf8)
+    }
+}
+
+impl<T> WireSerialize for Vec<T>
+where
+    T: WireSerialize,
+{
+    fn wire_write(&self, writer: &mut Vec<u8>) {
+        self.len().wire_write(writer);
+        for item in self {
+            item.wire_write(writer);
+        }
+    }
+}
+
+impl<T> WireDeserialize for Vec<T>
+where
+    T: WireDeserialize,
+{
+    fn wire_read(reader: &mut &[u8]) -> Result<Self, WireError> {
+        let len = read_len(reader)?;
+        let mut res = Vec::with_capacity(len);
+        for _ in 0..len {
+            res.push(T::wire_read(reader)?);
+        }
+        Ok(res)
+    }
+}
+
+impl<T> WireSerialize for Option<T>
+where
+    T: WireSerialize,
+{
+    fn wire_write(&self, writer: &mut Vec<u8>) {
+        match self {
+            None => writer.push(0),
+            Some(value) => {
+                writer.push(1);
+                value.wire_write(writer);
+            }
+        }
+    }
+}
+
+impl<T> WireDeserialize for Option<T>
+where
+    T: WireDeserialize,
+{
+    fn wire_read(reader: &mut &[u8]) -> Result<Self, WireError> {
+        match read_byte(reader)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::wire_read(reader)?)),
+            _ => Err(WireError::InvalidVariant),
+        }
+    }
+}
+
+/// Fixed length byte arrays are written as is, without a length prefix
+macro_rules! impl_wire_fixed_bytes {
+    ($($name:ident),*) => {
+        $(
+            impl WireSerialize for $name {
+                fn wire_write(&self, writer: &mut Vec<u8>) {
+                    writer.extend_from_slice(self.as_ref());
+                }
+            }
+
+            impl WireDeserialize for $name {
+                fn wire_read(reader: &mut &[u8]) -> Result<Self, WireError> {
+                    let bytes = read_bytes(reader, $name::len())?;
+                    // Can not fail, as we have exactly `$name::len()` bytes:
+                    Ok($name::try_from(bytes).unwrap())
+                }
+            }
+        )*
+    };
+}
+
+impl_wire_fixed_bytes!(
+    HashResult, HashedLock, InvoiceId, PlainLock, PublicKey, RandValue, Signature, Uid
+);
+
+/// Structs are written field by field, in the order of declaration
+macro_rules! impl_wire_struct {
+    ($name:ident { $($field:ident),* }) => {
+        impl WireSerialize for $name {
+            fn wire_write(&self, writer: &mut Vec<u8>) {
+                $(self.$field.wire_write(writer);)*
+            }
+        }
+
+        impl WireDeserialize for $name {
+            fn wire_read(reader: &mut &[u8]) -> Result<Self, WireError> {
+                Ok($name {
+                    $($field: WireDeserialize::wire_read(reader)?,)*
+                })
+            }
+        }
+    };
+}
+
+impl_wire_struct!(FriendsRoute { public_keys });
+impl_wire_struct!(CurrencyExchange {
+    dest_currency,
+    dest_payment,
+    total_dest_payment
+});
+impl_wire_struct!(RequestSendFundsOp {
+    request_id,
+    src_hashed_lock,
+    route,
+    dest_payment,
+    total_dest_payment,
+    invoice_id,
+    left_fees,
+    expiry_ticks,
+    refund_ticks,
+    opt_exchange
+});
+impl_wire_struct!(ResponseSendFundsOp {
+    request_id,
+    dest_hashed_lock,
+    is_complete,
+    change,
+    rand_nonce,
+    signature
+});
+impl_wire_struct!(CancelSendFundsOp { request_id });
+impl_wire_struct!(CollectSendFundsOp {
+    request_id,
+    src_plain_lock,
+    dest_plain_lock
+});
+impl_wire_struct!(RefundSendFundsOp { request_id });
+impl_wire_struct!(CurrencyOperations {
+    currency,
+    operations
+});
+impl_wire_struct!(CurrencyBalance { currency, balance });
+impl_wire_struct!(ResetTerms {
+    reset_token,
+    inconsistency_counter,
+    balance_for_reset
+});
+impl_wire_struct!(FriendCapabilities {
+    currencies,
+    features,
+    max_message_size,
+    protocol_version
+});
+impl_wire_struct!(KeyRotation {
+    old_public_key,
+    new_public_key,
+    old_signature,
+    new_signature
+});
+
+impl WireSerialize for Currency {
+    fn wire_write(&self, writer: &mut Vec<u8>) {
+        write_str(writer, self.as_str());
+    }
+}
+
+impl WireDeserialize for Currency {
+    fn wire_read(reader: &mut &[u8]) -> Result<Self, WireError> {
+        Currency::try_from(String::wire_read(reader)?).map_err(|_| WireError::InvalidValue)
+    }
+}
+
+impl WireSerialize for NetAddress {
+    fn wire_write(&self, writer: &mut Vec<u8>) {
+        write_str(writer, self.as_str());
+    }
+}
+
+impl WireDeserialize for NetAddress {
+    fn wire_read(reader: &mut &[u8]) -> Result<Self, WireError> {
+        NetAddress::try_from(String::wire_read(reader)?).map_err(|_| WireError::InvalidValue)
+    }
+}
+
+impl<B> WireSerialize for RelayAddress<B>
+where
+    B: WireSerialize,
+{
+    fn wire_write(&self, writer: &mut Vec<u8>) {
+        self.public_key.wire_write(writer);
+        self.address.wire_write(writer);
+    }
+}
+
+impl<B> WireDeserialize for RelayAddress<B>
+where
+    B: WireDeserialize,
+{
+    fn wire_read(reader: &mut &[u8]) -> Result<Self, WireError> {
+        Ok(RelayAddress {
+            public_key: PublicKey::wire_read(reader)?,
+            address: B::wire_read(reader)?,
+        })
+    }
+}
+
+impl WireSerialize for FriendTcOp {
+    fn wire_write(&self, writer: &mut Vec<u8>) {
+        match self {
+            FriendTcOp::RequestSendFunds(op) => {
+                writer.push(0);
+                op.wire_write(writer);
+            }
+            FriendTcOp::ResponseSendFunds(op) => {
+                writer.push(1);
+                op.wire_write(writer);
+            }
+            FriendTcOp::CancelSendFunds(op) => {
+                writer.push(2);
+                op.wire_write(writer);
+            }
+            FriendTcOp::CollectSendFunds(op) => {
+                writer.push(3);
+                op.wire_write(writer);
+            }
+            FriendTcOp::RefundSendFunds(op) => {
+                writer.push(4);
+                op.wire_write(writer);
+            }
+        }
+    }
+}
+
+impl WireDeserialize for FriendTcOp {
+    fn wire_read(reader: &mut &[u8]) -> Result<Self, WireError> {
+        Ok(match read_byte(reader)? {
+            0 => FriendTcOp::RequestSendFunds(WireDeserialize::wire_read(reader)?),
+            1 => FriendTcOp::ResponseSendFunds(WireDeserialize::wire_read(reader)?),
+            2 => FriendTcOp::CancelSendFunds(WireDeserialize::wire_read(reader)?),
+            3 => FriendTcOp::CollectSendFunds(WireDeserialize::wire_read(reader)?),
+            4 => FriendTcOp::RefundSendFunds(WireDeserialize::wire_read(reader)?),
+            _ => return Err(WireError::InvalidVariant),
+        })
+    }
+}
+
+impl<B> WireSerialize for MoveToken<B>
+where
+    B: WireSerialize,
+{
+    fn wire_write(&self, writer: &mut Vec<u8>) {
+        self.old_token.wire_write(writer);
+        self.currencies_operations.wire_write(writer);
+        self.opt_local_relays.wire_write(writer);
+        self.opt_active_currencies.wire_write(writer);
+        self.info_hash.wire_write(writer);
+        self.rand_nonce.wire_write(writer);
+        self.signature_version.wire_write(writer);
+        self.new_token.wire_write(writer);
+    }
+}
+
+impl<B> WireDeserialize for MoveToken<B>
+where
+    B: WireDeserialize,
+{
+    fn wire_read(reader: &mut &[u8]) -> Result<Self, WireError> {
+        Ok(MoveToken {
+            old_token: WireDeserialize::wire_read(reader)?,
+            currencies_operations: WireDeserialize::wire_read(reader)?,
+            opt_local_relays: WireDeserialize::wire_read(reader)?,
+            opt_active_currencies: WireDeserialize::wire_read(reader)?,
+            info_hash: WireDeserialize::wire_read(reader)?,
+            rand_nonce: WireDeserialize::wire_read(reader)?,
+            signature_version: WireDeserialize::wire_read(reader)?,
+            new_token: WireDeserialize::wire_read(reader)?,
+        })
+    }
+}
+
+impl<B> WireSerialize for MoveTokenRequest<B>
+where
+    B: WireSerialize,
+{
+    fn wire_write(&self, writer: &mut Vec<u8>) {
+        self.move_token.wire_write(writer);
+        self.token_wanted.wire_write(writer);
+        self.opt_capabilities.wire_write(writer);
+    }
+}
+
+impl<B> WireDeserialize for MoveTokenRequest<B>
+where
+    B: WireDeserialize,
+{
+    fn wire_read(reader: &mut &[u8]) -> Result<Self, WireError> {
+        Ok(MoveTokenRequest {
+            move_token: WireDeserialize::wire_read(reader)?,
+            token_wanted: WireDeserialize::wire_read(reader)?,
+            opt_capabilities: WireDeserialize::wire_read(reader)?,
+        })
+    }
+}
+
+impl<B> WireSerialize for FriendMessage<B>
+where
+    B: WireSerialize,
+{
+    fn wire_write(&self, writer: &mut Vec<u8>) {
+        match self {
+            FriendMessage::MoveTokenRequest(move_token_request) => {
+                writer.push(0);
+                move_token_request.wire_write(writer);
+            }
+            FriendMessage::InconsistencyError(reset_terms) => {
+                writer.push(1);
+                reset_terms.wire_write(writer);
+            }
+            FriendMessage::KeyRotation(key_rotation) => {
+                writer.push(2);
+                key_rotation.wire_write(writer);
+            }
+        }
+    }
+}
+
+impl<B> WireDeserialize for FriendMessage<B>
+where
+    B: WireDeserialize,
+{
+    fn wire_read(reader: &mut &[u8]) -> Result<Self, WireError> {
+        Ok(match read_byte(reader)? {
+            0 => FriendMessage::MoveTokenRequest(WireDeserialize::wire_read(reader)?),
+            1 => FriendMessage::InconsistencyError(WireDeserialize::wire_read(reader)?),
+            2 => FriendMessage::KeyRotation(WireDeserialize::wire_read(reader)?),
+            _ => return Err(WireError::InvalidVariant),
+        })
+    }
+}
+
+/// The format used to serialize messages sent to a friend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireFormat {
+    /// Capnp packed messages. Supported by all friends.
+    Capnp,
+    /// The compact format. Only used if the friend supports it.
+    Compact,
+}
+
+/// Serialize a message to a friend
+pub fn serialize_friend_message(
+    friend_message: &FriendMessage,
+    wire_format: WireFormat,
+) -> Vec<u8> {
+    match wire_format {
+        WireFormat::Capnp => friend_message.proto_serialize(),
+        WireFormat::Compact => {
+            let mut writer = vec![COMPACT_MARKER];
+            friend_message.wire_write(&mut writer);
+            writer
+        }
+    }
+}
+
+/// Deserialize a message received from a friend, in any of the wire formats, and verify that it
+/// is within the limits defined in `limits`.
+pub fn deserialize_friend_message(bytes: &[u8]) -> Result<FriendMessage, ProtoSerializeError> {
+    match bytes.split_first() {
+        Some((&COMPACT_MARKER, rest)) => {
+            if bytes.len() > MAX_MESSAGE_LEN {
+                return Err(ProtoSerializeError::MessageTooLong);
+            }
+            let friend_message = FriendMessage::wire_deserialize(rest)?;
+            friend_message.check_limits()?;
+            Ok(friend_message)
+        }
+        _ => FriendMessage::proto_deserialize_checked(bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::funder::messages::friend_features;
+
+    fn dummy_currency() -> Currency {
+        Currency::try_from("FST".to_owned()).unwrap()
+    }
+
+    fn dummy_move_token_request() -> MoveTokenRequest {
+        let request_send_funds = RequestSendFundsOp {
+            request_id: Uid::from(&[1; Uid::len()]),
+            src_hashed_lock: HashedLock::from(&[2; HashedLock::len()]),
+            route: FriendsRoute {
+                public_keys: vec![
+                    PublicKey::from(&[0xaa; PublicKey::len()]),
+                    PublicKey::from(&[0xbb; PublicKey::len()]),
+                ],
+            },
+            dest_payment: 100,
+            total_dest_payment: 200,
+            invoice_id: InvoiceId::from(&[3; InvoiceId::len()]),
+            left_fees: 2,
+            expiry_ticks: 0x100,
+            refund_ticks: 0,
+            opt_exchange: Some(CurrencyExchange {
+                dest_currency: dummy_currency(),
+                dest_payment: 90,
+                total_dest_payment: u128::max_value(),
+            }),
+        };
+        let collect_send_funds = CollectSendFundsOp {
+            request_id: Uid::from(&[4; Uid::len()]),
+            src_plain_lock: PlainLock::from(&[5; PlainLock::len()]),
+            dest_plain_lock: PlainLock::from(&[6; PlainLock::len()]),
+        };
+
+        MoveTokenRequest {
+            move_token: MoveToken {
+                old_token: Signature::from(&[7; Signature::len()]),
+                currencies_operations: vec![CurrencyOperations {
+                    currency: dummy_currency(),
+                    operations: vec![
+                        FriendTcOp::RequestSendFunds(request_send_funds),
+                        FriendTcOp::CollectSendFunds(collect_send_funds),
+                        FriendTcOp::CancelSendFunds(CancelSendFundsOp {
+                            request_id: Uid::from(&[8; Uid::len()]),
+                        }),
+                    ],
+                }],
+                opt_local_relays: Some(vec![RelayAddress {
+                    public_key: PublicKey::from(&[0xcc; PublicKey::len()]),
+                    address: NetAddress::try_from("relay.example:1337".to_owned()).unwrap(),
+                }]),
+                opt_active_currencies: None,
+                info_hash: HashResult::from(&[9; HashResult::len()]),
+                rand_nonce: RandValue::from(&[10; RandValue::len()]),
+                signature_version: 1,
+                new_token: Signature::from(&[11; Signature::len()]),
+            },
+            token_wanted: true,
+            opt_capabilities: Some(FriendCapabilities {
+                currencies: vec![dummy_currency()],
+                features: friend_features::CURRENCY_EXCHANGE,
+                max_message_size: 0x10000,
+                protocol_version: 1,
+            }),
+        }
+    }
+
+    fn assert_round_trip(friend_message: FriendMessage) {
+        for &wire_format in &[WireFormat::Capnp, WireFormat::Compact] {
+            let data = serialize_friend_message(&friend_message, wire_format);
+            assert_eq!(deserialize_friend_message(&data).unwrap(), friend_message);
+        }
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        for &value in &[0u128, 1, 0x7f, 0x80, 0x3fff, 0x4000, u128::max_value()] {
+            assert_eq!(u128::wire_deserialize(&value.wire_serialize()), Ok(value));
+        }
+        for &value in &[
+            0i128,
+            1,
+            -1,
+            63,
+            -64,
+            64,
+            i128::max_value(),
+            i128::min_value(),
+        ] {
+            assert_eq!(i128::wire_deserialize(&value.wire_serialize()), Ok(value));
+        }
+        // Small values take one byte:
+        assert_eq!(0x7fu64.wire_serialize().len(), 1);
+        assert_eq!((-64i128).wire_serialize().len(), 1);
+    }
+
+    #[test]
+    fn test_wire_invalid_input() {
+        // Does not fit in 32 bits:
+        assert_eq!(
+            u32::wire_deserialize(&u64::max_value().wire_serialize()),
+            Err(WireError::IntegerOverflow)
+        );
+        // A varint that never ends:
+        assert_eq!(
+            u64::wire_deserialize(&[0xff; 4]),
+            Err(WireError::UnexpectedEnd)
+        );
+        // A list that is longer than the message:
+        assert_eq!(
+            Vec::<u64>::wire_deserialize(&[0x10, 1, 2]),
+            Err(WireError::InvalidLength)
+        );
+        assert_eq!(bool::wire_deserialize(&[2]), Err(WireError::InvalidBool));
+        assert_eq!(
+            bool::wire_deserialize(&[1, 0]),
+            Err(WireError::TrailingBytes)
+        );
+    }
+
+    #[test]
+    fn test_friend_message_round_trip() {
+        assert_round_trip(FriendMessage::MoveTokenRequest(dummy_move_token_request()));
+        assert_round_trip(FriendMessage::InconsistencyError(ResetTerms {
+            reset_token: Signature::from(&[1; Signature::len()]),
+            inconsistency_counter: 3,
+            balance_for_reset: vec![CurrencyBalance {
+                currency: dummy_currency(),
+                balance: -150,
+            }],
+        }));
+        let mut move_token_request = dummy_move_token_request();
+        move_token_request.opt_capabilities = None;
+        assert_round_trip(FriendMessage::MoveTokenRequest(move_token_request));
+        assert_round_trip(FriendMessage::KeyRotation(KeyRotation {
+            old_public_key: PublicKey::from(&[1; PublicKey::len()]),
+            new_public_key: PublicKey::from(&[2; PublicKey::len()]),
+            old_signature: Signature::from(&[3; Signature::len()]),
+            new_signature: Signature::from(&[4; Signature::len()]),
+        }));
+    }
+
+    #[test]
+    fn test_compact_smaller_than_capnp() {
+        let friend_message = FriendMessage::MoveTokenRequest(dummy_move_token_request());
+        let capnp_len = serialize_friend_message(&friend_message, WireFormat::Capnp).len();
+        let compact_len = serialize_friend_message(&friend_message, WireFormat::Compact).len();
+        assert!(compact_len < capnp_len);
+    }
+}
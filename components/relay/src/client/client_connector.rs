@@ -14,6 +14,11 @@ pub enum ClientConnectorError {
 
 /// ClientConnector is an end-to-end connector to a remote node.
 /// It relies on a given connector C to a relay.
+///
+/// Note that the data sent through the resulting connection is encrypted end to end, between
+/// the two nodes. The relay does not attempt to compress relayed payloads, as encrypted data does
+/// not compress. Compression of large messages (If ever needed) should happen on the plain data,
+/// before the end to end encryption.
 #[derive(Clone)]
 pub struct ClientConnector<C> {
     connector: C,
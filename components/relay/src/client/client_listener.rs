@@ -15,6 +15,8 @@ use common::select_streams::select_streams;
 
 use timer::{TimerClient, TimerTick};
 
+use crate::mux::mux_client_loop;
+
 type AccessControlPk = AccessControl<PublicKey>;
 type AccessControlOpPk = AccessControlOp<PublicKey>;
 
@@ -26,6 +28,7 @@ pub enum ClientListenerError {
     SendToServerError,
     // ServerClosed,
     SpawnError,
+    SendConnectionError,
 }
 
 #[derive(Debug, Clone)]
@@ -37,6 +40,13 @@ enum ClientListenerEvent {
     PendingReject(PublicKey),
 }
 
+enum MuxListenerEvent {
+    AccessControlOp(AccessControlOpPk),
+    AccessControlClosed,
+    Stream((PublicKey, ConnPairVec)),
+    StreamsClosed,
+}
+
 #[derive(Debug)]
 enum AcceptConnectionError {
     ConnectionFailed,
@@ -278,24 +288,105 @@ where
     Ok(())
 }
 
+/// Listen using a single multiplexed connection to the relay.
+/// Connections from remote nodes are tunneled as streams over this connection. A connection from
+/// a remote node that is not allowed by `access_control` is rejected by closing its stream.
+async fn inner_client_listener_mux<'a, C, IAC, CS, CSE>(
+    mut connector: C,
+    access_control: &'a mut AccessControlPk,
+    incoming_access_control: &'a mut IAC,
+    mut connections_sender: CS,
+    spawner: impl Spawn + Clone + Send + 'static,
+) -> Result<(), ClientListenerError>
+where
+    C: FutTransform<Input = (), Output = Option<ConnPairVec>> + Send,
+    IAC: Stream<Item = AccessControlOp<PublicKey>> + Unpin + Send + 'static,
+    CS: Sink<(PublicKey, ConnPairVec), Error = CSE> + Unpin,
+{
+    let conn_pair = match connector.transform(()).await {
+        Some(conn_pair) => conn_pair,
+        None => return Err(ClientListenerError::ConnectionFailure),
+    };
+
+    let (mut sender, receiver) = conn_pair.split();
+    let ser_init_connection = InitConnection::ListenMux.proto_serialize();
+
+    sender
+        .send(ser_init_connection)
+        .await
+        .map_err(|_| ClientListenerError::SendInitConnectionError)?;
+
+    let (streams_sender, streams_receiver) = mpsc::channel::<(PublicKey, ConnPairVec)>(0);
+    let mux_fut = mux_client_loop(
+        ConnPairVec::from_raw(sender, receiver),
+        streams_sender,
+        spawner.clone(),
+    )
+    .map_err(|e| warn!("mux_client_loop() error: {:?}", e))
+    .map(|_| ());
+    // Dropping the handle closes the multiplexed connection:
+    let _mux_handle = spawner
+        .spawn_with_handle(mux_fut)
+        .map_err(|_| ClientListenerError::SpawnError)?;
+
+    let incoming_access_control = incoming_access_control
+        .map(MuxListenerEvent::AccessControlOp)
+        .chain(stream::once(future::ready(
+            MuxListenerEvent::AccessControlClosed,
+        )));
+
+    let streams_receiver = streams_receiver
+        .map(MuxListenerEvent::Stream)
+        .chain(stream::once(future::ready(MuxListenerEvent::StreamsClosed)));
+
+    let mut events = select_streams![incoming_access_control, streams_receiver];
+
+    while let Some(event) = events.next().await {
+        match event {
+            MuxListenerEvent::AccessControlOp(access_control_op) => {
+                access_control.apply_op(access_control_op)
+            }
+            MuxListenerEvent::Stream((public_key, conn_pair)) => {
+                if access_control.is_allowed(&public_key) {
+                    connections_sender
+                        .send((public_key, conn_pair))
+                        .await
+                        .map_err(|_| ClientListenerError::SendConnectionError)?;
+                }
+                // Otherwise the stream is dropped here, and the relay closes the connection
+                // with the remote node.
+            }
+            MuxListenerEvent::StreamsClosed => break,
+            MuxListenerEvent::AccessControlClosed => break,
+        }
+    }
+    Ok(())
+}
+
 #[derive(Clone)]
 pub struct ClientListener<C, S> {
     connector: C,
     conn_timeout_ticks: usize,
+    multiplex: bool,
     timer_client: TimerClient,
     spawner: S,
 }
 
 impl<C, S> ClientListener<C, S> {
+    /// If `multiplex` is set, all the accepted connections are tunneled over a single
+    /// connection to the relay. Otherwise, a new connection to the relay is opened for every
+    /// accepted connection.
     pub fn new(
         connector: C,
         conn_timeout_ticks: usize,
+        multiplex: bool,
         timer_client: TimerClient,
         spawner: S,
     ) -> ClientListener<C, S> {
         ClientListener {
             connector,
             conn_timeout_ticks,
+            multiplex,
             timer_client,
             spawner,
         }
@@ -328,19 +419,32 @@ where
         let const_connector = ConstFutTransform::new(self.connector.clone(), relay_address);
 
         let fut = async move {
-            inner_client_listener(
-                const_connector,
-                &mut access_control,
-                &mut access_control_receiver,
-                connections_sender,
-                self.conn_timeout_ticks,
-                self.timer_client,
-                self.spawner,
-                None,
-            )
-            .map_err(|e| warn!("inner_client_listener() error: {:?}", e))
-            .map(|_| ())
-            .await
+            if self.multiplex {
+                inner_client_listener_mux(
+                    const_connector,
+                    &mut access_control,
+                    &mut access_control_receiver,
+                    connections_sender,
+                    self.spawner,
+                )
+                .map_err(|e| warn!("inner_client_listener_mux() error: {:?}", e))
+                .map(|_| ())
+                .await
+            } else {
+                inner_client_listener(
+                    const_connector,
+                    &mut access_control,
+                    &mut access_control_receiver,
+                    connections_sender,
+                    self.conn_timeout_ticks,
+                    self.timer_client,
+                    self.spawner,
+                    None,
+                )
+                .map_err(|e| warn!("inner_client_listener() error: {:?}", e))
+                .map(|_| ())
+                .await
+            }
         };
 
         let _ = c_spawner.spawn(fut);
@@ -358,6 +462,8 @@ mod tests {
 
     use common::dummy_connector::DummyConnector;
 
+    use crate::mux::mux_relay_loop;
+
     async fn task_connect_with_timeout_basic(spawner: impl Spawn) {
         let conn_timeout_ticks = 8;
         let (_timer_sender, timer_stream) = mpsc::channel::<TimerTick>(0);
@@ -579,5 +685,92 @@ mod tests {
         LocalPool::new().run_until(task_client_listener_basic(thread_pool.clone()));
     }
 
+    async fn task_client_listener_mux(spawner: impl Spawn + Clone + Send + 'static) {
+        let (req_sender, mut req_receiver) = mpsc::channel(0);
+        let connector = DummyConnector::new(req_sender);
+        let (connections_sender, mut connections_receiver) = mpsc::channel(0);
+
+        let (_acl_sender, mut incoming_access_control) = mpsc::channel(0);
+
+        // Only connections from public key A are allowed:
+        let public_key_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let public_key_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let mut access_control = AccessControlPk::new();
+        access_control.apply_op(AccessControlOp::Add(public_key_a.clone()));
+
+        let c_spawner = spawner.clone();
+        let fut_listener = async move {
+            // HACK: Saving temp value in res to make the compiler happy.
+            let res = inner_client_listener_mux(
+                connector,
+                &mut access_control,
+                &mut incoming_access_control,
+                connections_sender,
+                c_spawner,
+            )
+            .await;
+            res
+        }
+        .map_err(|e| warn!("inner_client_listener_mux error: {:?}", e))
+        .map(|_| ());
+
+        spawner.spawn(fut_listener).unwrap();
+
+        // listener will attempt to start a main connection to the relay:
+        let (relay_sender, local_receiver) = mpsc::channel(1);
+        let (local_sender, mut relay_receiver) = mpsc::channel(1);
+        let conn_pair = ConnPairVec::from_raw(local_sender, local_receiver);
+        let req = req_receiver.next().await.unwrap();
+        req.reply(Some(conn_pair));
+
+        // First message to the relay should be InitConnection::ListenMux:
+        let vec_init_connection = relay_receiver.next().await.unwrap();
+        let init_connection = InitConnection::proto_deserialize(&vec_init_connection).unwrap();
+        if let InitConnection::ListenMux = init_connection {
+        } else {
+            unreachable!();
+        }
+
+        // The relay side of the multiplexed connection:
+        let (mut open_sender, open_receiver) = mpsc::channel(0);
+        let (relay_streams_sender, mut relay_streams) = mpsc::channel(0);
+        spawner
+            .spawn(
+                mux_relay_loop(
+                    ConnPairVec::from_raw(relay_sender, relay_receiver),
+                    open_receiver,
+                    relay_streams_sender,
+                    spawner.clone(),
+                )
+                .map(|_| ()),
+            )
+            .unwrap();
+
+        // A connection from B is not allowed, and its stream is closed:
+        open_sender.send(public_key_b.clone()).await.unwrap();
+        let (public_key, relay_stream_b) = relay_streams.next().await.unwrap();
+        assert_eq!(public_key, public_key_b);
+        let (_relay_sender_b, mut relay_receiver_b) = relay_stream_b.split();
+        assert!(relay_receiver_b.next().await.is_none());
+
+        // A connection from A is accepted:
+        open_sender.send(public_key_a.clone()).await.unwrap();
+        let (public_key, relay_stream_a) = relay_streams.next().await.unwrap();
+        assert_eq!(public_key, public_key_a);
+        let (public_key, local_stream_a) = connections_receiver.next().await.unwrap();
+        assert_eq!(public_key, public_key_a);
+
+        let (mut relay_sender_a, _relay_receiver_a) = relay_stream_a.split();
+        let (_local_sender_a, mut local_receiver_a) = local_stream_a.split();
+        relay_sender_a.send(vec![1, 2, 3]).await.unwrap();
+        assert_eq!(local_receiver_a.next().await.unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_client_listener_mux() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new().run_until(task_client_listener_mux(thread_pool.clone()));
+    }
+
     // TODO: Add a test for ClientListener.
 }
@@ -1,2 +1,4 @@
 pub mod client_connector;
 pub mod client_listener;
+pub mod relay_discovery;
+pub mod relay_ping;
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPairVec;
+
+use proto::app_server::messages::RelayAddress;
+use proto::crypto::{PublicKey, Uid};
+use proto::index_server::messages::{IndexClientToServer, IndexServerToClient, RequestRelays};
+use proto::proto_ser::{ProtoDeserializeChecked, ProtoSerialize};
+
+#[derive(Debug)]
+pub enum RelayDiscoveryError {
+    SendRequestError,
+    /// The index server closed the connection before responding
+    ConnectionClosed,
+    DeserializeError,
+}
+
+/// Ask an index server for public relays registered with it.
+/// `conn_pair` is an established connection to the index server.
+///
+/// Relays register with the index servers that are close to them, so the returned relays are
+/// likely to be close to the index server (And to us).
+pub async fn discover_relays(
+    conn_pair: ConnPairVec,
+    request_id: Uid,
+    max_relays: u32,
+) -> Result<Vec<RelayAddress>, RelayDiscoveryError> {
+    let (mut sender, mut receiver) = conn_pair.split();
+
+    let request_relays = RequestRelays {
+        request_id: request_id.clone(),
+        max_relays,
+    };
+    sender
+        .send(IndexClientToServer::RequestRelays(request_relays).proto_serialize())
+        .await
+        .map_err(|_| RelayDiscoveryError::SendRequestError)?;
+
+    while let Some(data) = receiver.next().await {
+        let message = IndexServerToClient::proto_deserialize_checked(&data)
+            .map_err(|_| RelayDiscoveryError::DeserializeError)?;
+        if let IndexServerToClient::ResponseRelays(response_relays) = message {
+            if response_relays.request_id == request_id {
+                return Ok(response_relays.relays);
+            }
+        }
+        // Other messages (Like time hashes) are not relevant for us.
+    }
+    Err(RelayDiscoveryError::ConnectionClosed)
+}
+
+/// Choose up to `num_relays` relays out of the relays returned by a few index servers.
+///
+/// Relays returned by more index servers are preferred, as their registration was confirmed by
+/// more servers. Between relays returned by the same amount of servers, relays listed earlier
+/// (Registered for a longer time) are preferred.
+/// If a relay was returned with different addresses, the first address is used.
+pub fn choose_relays(responses: &[Vec<RelayAddress>], num_relays: usize) -> Vec<RelayAddress> {
+    // public_key -> (first position, amount of servers, relay address)
+    let mut candidates: HashMap<PublicKey, (usize, usize, &RelayAddress)> = HashMap::new();
+    let mut position = 0;
+    for relays in responses {
+        for relay in relays {
+            let candidate = candidates
+                .entry(relay.public_key.clone())
+                .or_insert((position, 0, relay));
+            candidate.1 += 1;
+            position += 1;
+        }
+    }
+
+    let mut candidates = candidates.values().cloned().collect::<Vec<_>>();
+    candidates.sort_by(|(position_a, count_a, _), (position_b, count_b, _)| {
+        count_b.cmp(count_a).then(position_a.cmp(position_b))
+    });
+    candidates
+        .into_iter()
+        .take(num_relays)
+        .map(|(_, _, relay)| relay.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use futures::channel::mpsc;
+    use futures::executor::{block_on, ThreadPool};
+    use futures::task::{Spawn, SpawnExt};
+
+    use proto::crypto::HashResult;
+    use proto::index_server::messages::ResponseRelays;
+    use proto::net::messages::NetAddress;
+    use proto::proto_ser::ProtoDeserialize;
+
+    fn relay(i: u8) -> RelayAddress {
+        RelayAddress {
+            public_key: PublicKey::from(&[i; PublicKey::len()]),
+            address: NetAddress::try_from(format!("relay{}:1337", i)).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_choose_relays() {
+        let responses = vec![
+            vec![relay(1), relay(2), relay(3)],
+            vec![relay(3), relay(4)],
+            vec![relay(4), relay(3)],
+        ];
+        // Relay 3 was returned by 3 servers, relay 4 by 2 servers:
+        assert_eq!(
+            choose_relays(&responses, 3),
+            vec![relay(3), relay(4), relay(1)]
+        );
+        assert_eq!(choose_relays(&responses, 1), vec![relay(3)]);
+        assert!(choose_relays(&[], 3).is_empty());
+    }
+
+    async fn task_discover_relays<S>(spawner: S)
+    where
+        S: Spawn,
+    {
+        let (client_sender, mut server_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (mut server_sender, client_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let conn_pair = ConnPairVec::from_raw(client_sender, client_receiver);
+
+        let request_id = Uid::from(&[1; Uid::len()]);
+        let discover_fut = spawner
+            .spawn_with_handle(discover_relays(conn_pair, request_id.clone(), 4))
+            .unwrap();
+
+        let data = server_receiver.next().await.unwrap();
+        let request_relays = match IndexClientToServer::proto_deserialize(&data).unwrap() {
+            IndexClientToServer::RequestRelays(request_relays) => request_relays,
+            _ => unreachable!(),
+        };
+        assert_eq!(request_relays.request_id, request_id);
+        assert_eq!(request_relays.max_relays, 4);
+
+        // Unrelated messages are ignored:
+        let time_hash = HashResult::from(&[2; HashResult::len()]);
+        server_sender
+            .send(IndexServerToClient::TimeHash(time_hash).proto_serialize())
+            .await
+            .unwrap();
+        let response_relays = ResponseRelays {
+            request_id: Uid::from(&[3; Uid::len()]),
+            relays: vec![relay(3)],
+        };
+        server_sender
+            .send(IndexServerToClient::ResponseRelays(response_relays).proto_serialize())
+            .await
+            .unwrap();
+
+        let response_relays = ResponseRelays {
+            request_id,
+            relays: vec![relay(1), relay(2)],
+        };
+        server_sender
+            .send(IndexServerToClient::ResponseRelays(response_relays).proto_serialize())
+            .await
+            .unwrap();
+
+        assert_eq!(discover_fut.await.unwrap(), vec![relay(1), relay(2)]);
+    }
+
+    #[test]
+    fn test_discover_relays() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_discover_relays(thread_pool.clone()));
+    }
+}
@@ -0,0 +1,166 @@
+use std::convert::TryFrom;
+use std::time::Instant;
+
+use futures::{SinkExt, StreamExt};
+
+use common::conn::{ConnPairVec, FutTransform};
+
+use proto::proto_ser::{ProtoDeserialize, ProtoSerialize};
+use proto::relay::messages::{InitConnection, RelayPing};
+
+#[derive(Debug)]
+pub enum RelayPingError {
+    InnerConnectorError,
+    SendInitConnectionError,
+    SendPingError,
+    ConnectionClosed,
+    InvalidPong,
+}
+
+/// Measure the round trip time through a relay.
+///
+/// Opens a ping connection to the relay, and sends `num_pings` pings over it, one at a time.
+/// The relay echoes back every ping. Returns the shortest round trip time measured, in
+/// milliseconds. Establishing the connection is not part of the measurement.
+///
+/// `num_pings` must be positive, and must not exceed the amount of pings the relay is willing to
+/// echo over a single connection.
+pub async fn ping_relay<A, C>(
+    connector: &mut C,
+    relay_address: A,
+    num_pings: usize,
+) -> Result<u64, RelayPingError>
+where
+    C: FutTransform<Input = A, Output = Option<ConnPairVec>>,
+{
+    assert!(num_pings > 0);
+
+    let (mut sender, mut receiver) = connector
+        .transform(relay_address)
+        .await
+        .ok_or(RelayPingError::InnerConnectorError)?
+        .split();
+
+    sender
+        .send(InitConnection::Ping.proto_serialize())
+        .await
+        .map_err(|_| RelayPingError::SendInitConnectionError)?;
+
+    let mut min_rtt_ms = u64::max_value();
+    for nonce in 0..num_pings {
+        // Every connection is used for a single measurement, so a counter is a good enough nonce:
+        let relay_ping = RelayPing {
+            nonce: u64::try_from(nonce).unwrap(),
+        };
+        let start = Instant::now();
+        sender
+            .send(relay_ping.proto_serialize())
+            .await
+            .map_err(|_| RelayPingError::SendPingError)?;
+
+        let data = receiver
+            .next()
+            .await
+            .ok_or(RelayPingError::ConnectionClosed)?;
+        let elapsed = start.elapsed();
+
+        let relay_pong =
+            RelayPing::proto_deserialize(&data).map_err(|_| RelayPingError::InvalidPong)?;
+        if relay_pong != relay_ping {
+            return Err(RelayPingError::InvalidPong);
+        }
+
+        let rtt_ms = u64::try_from(elapsed.as_millis()).unwrap_or(u64::max_value());
+        min_rtt_ms = min_rtt_ms.min(rtt_ms);
+    }
+    Ok(min_rtt_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::channel::mpsc;
+    use futures::executor::{LocalPool, ThreadPool};
+    use futures::task::{Spawn, SpawnExt};
+
+    use common::dummy_connector::DummyConnector;
+
+    async fn task_ping_relay_basic(spawner: impl Spawn + Clone + Send + 'static) {
+        let (local_sender, mut relay_receiver) = mpsc::channel::<Vec<u8>>(1);
+        let (mut relay_sender, local_receiver) = mpsc::channel::<Vec<u8>>(1);
+
+        let conn_pair = ConnPairVec::from_raw(local_sender, local_receiver);
+        let (req_sender, mut req_receiver) = mpsc::channel(1);
+        let mut connector = DummyConnector::new(req_sender);
+
+        let address: u32 = 15;
+        let fut_rtt = spawner
+            .spawn_with_handle(async move { ping_relay(&mut connector, address, 3).await })
+            .unwrap();
+
+        // Wait for connection request:
+        let req = req_receiver.next().await.unwrap();
+        assert_eq!(req.address, address);
+        req.reply(Some(conn_pair));
+
+        let vec = relay_receiver.next().await.unwrap();
+        let init_connection = InitConnection::proto_deserialize(&vec).unwrap();
+        assert_eq!(init_connection, InitConnection::Ping);
+
+        // Echo back the pings:
+        for nonce in 0..3u64 {
+            let vec = relay_receiver.next().await.unwrap();
+            let relay_ping = RelayPing::proto_deserialize(&vec).unwrap();
+            assert_eq!(relay_ping, RelayPing { nonce });
+            relay_sender.send(vec).await.unwrap();
+        }
+
+        assert!(fut_rtt.await.is_ok());
+    }
+
+    #[test]
+    fn test_ping_relay_basic() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new().run_until(task_ping_relay_basic(thread_pool.clone()));
+    }
+
+    async fn task_ping_relay_invalid_pong(spawner: impl Spawn + Clone + Send + 'static) {
+        let (local_sender, mut relay_receiver) = mpsc::channel::<Vec<u8>>(1);
+        let (mut relay_sender, local_receiver) = mpsc::channel::<Vec<u8>>(1);
+
+        let conn_pair = ConnPairVec::from_raw(local_sender, local_receiver);
+        let (req_sender, mut req_receiver) = mpsc::channel(1);
+        let mut connector = DummyConnector::new(req_sender);
+
+        let address: u32 = 15;
+        let fut_rtt = spawner
+            .spawn_with_handle(async move { ping_relay(&mut connector, address, 3).await })
+            .unwrap();
+
+        let req = req_receiver.next().await.unwrap();
+        req.reply(Some(conn_pair));
+
+        // InitConnection::Ping:
+        let _vec = relay_receiver.next().await.unwrap();
+        let _vec = relay_receiver.next().await.unwrap();
+
+        // Reply with a ping that was never sent:
+        let relay_ping = RelayPing { nonce: 7 };
+        relay_sender
+            .send(relay_ping.proto_serialize())
+            .await
+            .unwrap();
+
+        match fut_rtt.await {
+            Err(RelayPingError::InvalidPong) => {}
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn test_ping_relay_invalid_pong() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new().run_until(task_ping_relay_invalid_pong(thread_pool.clone()));
+    }
+}
@@ -16,8 +16,16 @@ extern crate log;
 extern crate common;
 
 mod client;
+mod metrics;
+mod mux;
 mod server;
 
 pub use self::client::client_connector::ClientConnector;
 pub use self::client::client_listener::ClientListener;
-pub use self::server::{relay_server, RelayServerError};
+pub use self::client::relay_discovery::{choose_relays, discover_relays, RelayDiscoveryError};
+pub use self::client::relay_ping::{ping_relay, RelayPingError};
+pub use self::metrics::{ReapReason, RelayMetrics};
+pub use self::server::{
+    announce_relay, relay_server, ws_accept, ws_listener, AnnounceRelayError, RelayServerError,
+    RelayTimeouts,
+};
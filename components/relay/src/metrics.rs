@@ -0,0 +1,335 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The purpose a connection declared in its first message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnKind {
+    Listen,
+    ListenMux,
+    Accept,
+    Connect,
+    Peer,
+    ForwardConnect,
+    Ping,
+}
+
+impl ConnKind {
+    fn label(self) -> &'static str {
+        match self {
+            ConnKind::Listen => "listen",
+            ConnKind::ListenMux => "listen_mux",
+            ConnKind::Accept => "accept",
+            ConnKind::Connect => "connect",
+            ConnKind::Peer => "peer",
+            ConnKind::ForwardConnect => "forward_connect",
+            ConnKind::Ping => "ping",
+        }
+    }
+}
+
+/// A reason for the relay server to refuse (Or drop) a connection because of its limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaRejection {
+    /// The remote public key is already listening
+    AlreadyListening,
+    /// Too many public keys are listening at the same time
+    TooManyListeners,
+    /// One side of a tunnel exceeded its budget of buffered bytes
+    SlowTunnelSide,
+}
+
+impl QuotaRejection {
+    fn label(self) -> &'static str {
+        match self {
+            QuotaRejection::AlreadyListening => "already_listening",
+            QuotaRejection::TooManyListeners => "too_many_listeners",
+            QuotaRejection::SlowTunnelSide => "slow_tunnel_side",
+        }
+    }
+}
+
+/// A reason for the relay server to close a connection that outlived its limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReapReason {
+    /// A listen connection did not receive or reject any connection for too long
+    ListenIdle,
+    /// No data passed through a tunnel for too long
+    TunnelIdle,
+    /// A tunnel reached its maximum lifetime
+    TunnelLifetime,
+    /// One side of a tunnel closed, and the other side did not close in time
+    HalfOpen,
+}
+
+impl ReapReason {
+    fn label(self) -> &'static str {
+        match self {
+            ReapReason::ListenIdle => "listen_idle",
+            ReapReason::TunnelIdle => "tunnel_idle",
+            ReapReason::TunnelLifetime => "tunnel_lifetime",
+            ReapReason::HalfOpen => "half_open",
+        }
+    }
+}
+
+const CONN_KINDS: [ConnKind; 7] = [
+    ConnKind::Listen,
+    ConnKind::ListenMux,
+    ConnKind::Accept,
+    ConnKind::Connect,
+    ConnKind::Peer,
+    ConnKind::ForwardConnect,
+    ConnKind::Ping,
+];
+
+const QUOTA_REJECTIONS: [QuotaRejection; 3] = [
+    QuotaRejection::AlreadyListening,
+    QuotaRejection::TooManyListeners,
+    QuotaRejection::SlowTunnelSide,
+];
+
+const REAP_REASONS: [ReapReason; 4] = [
+    ReapReason::ListenIdle,
+    ReapReason::TunnelIdle,
+    ReapReason::TunnelLifetime,
+    ReapReason::HalfOpen,
+];
+
+#[derive(Default)]
+struct RelayMetricsInner {
+    /// Indexed by the position of the kind in `CONN_KINDS`
+    conns_total: [AtomicU64; 7],
+    /// Indexed by the position of the rejection in `QUOTA_REJECTIONS`
+    rejections_total: [AtomicU64; 3],
+    /// Indexed by the position of the reason in `REAP_REASONS`
+    reaped_total: [AtomicU64; 4],
+    conn_timeouts_total: AtomicU64,
+    conn_dispatch_failures_total: AtomicU64,
+    bytes_forwarded_total: AtomicU64,
+    listeners: AtomicU64,
+    half_tunnels: AtomicU64,
+    tunnels: AtomicU64,
+}
+
+/// Counters and gauges describing the health of a relay server.
+///
+/// Cloning is cheap: all the clones update the same metrics, so a clone may be handed to every
+/// task of the relay server, and another clone may be used to render the metrics.
+#[derive(Clone, Default)]
+pub struct RelayMetrics {
+    inner: Arc<RelayMetricsInner>,
+}
+
+fn conn_kind_index(conn_kind: ConnKind) -> usize {
+    CONN_KINDS
+        .iter()
+        .position(|other| *other == conn_kind)
+        .unwrap()
+}
+
+fn quota_rejection_index(quota_rejection: QuotaRejection) -> usize {
+    QUOTA_REJECTIONS
+        .iter()
+        .position(|other| *other == quota_rejection)
+        .unwrap()
+}
+
+fn reap_reason_index(reap_reason: ReapReason) -> usize {
+    REAP_REASONS
+        .iter()
+        .position(|other| *other == reap_reason)
+        .unwrap()
+}
+
+impl RelayMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A connection declared its purpose
+    pub fn incoming_conn(&self, conn_kind: ConnKind) {
+        self.inner.conns_total[conn_kind_index(conn_kind)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A connection did not declare its purpose in time
+    pub fn conn_timeout(&self) {
+        self.inner
+            .conn_timeouts_total
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A connection closed or sent an invalid first message before declaring its purpose
+    pub fn conn_dispatch_failure(&self) {
+        self.inner
+            .conn_dispatch_failures_total
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn quota_rejection(&self, quota_rejection: QuotaRejection) {
+        self.inner.rejections_total[quota_rejection_index(quota_rejection)]
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A connection was closed for outliving its limits
+    pub fn reaped(&self, reap_reason: ReapReason) {
+        self.inner.reaped_total[reap_reason_index(reap_reason)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Data was forwarded from one side of a tunnel to the other side
+    pub fn bytes_forwarded(&self, num_bytes: usize) {
+        self.inner
+            .bytes_forwarded_total
+            .fetch_add(num_bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Update the amount of active listeners and pending half tunnels
+    pub fn set_listeners(&self, listeners: usize, half_tunnels: usize) {
+        self.inner
+            .listeners
+            .store(listeners as u64, Ordering::Relaxed);
+        self.inner
+            .half_tunnels
+            .store(half_tunnels as u64, Ordering::Relaxed);
+    }
+
+    pub fn tunnel_opened(&self) {
+        self.inner.tunnels.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn tunnel_closed(&self) {
+        self.inner.tunnels.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Render all the metrics in the Prometheus text exposition format
+    pub fn render_prometheus(&self) -> String {
+        let inner = &self.inner;
+        let mut output = String::new();
+
+        let _ = writeln!(
+            output,
+            "# HELP relay_conns_total Connections that declared their purpose."
+        );
+        let _ = writeln!(output, "# TYPE relay_conns_total counter");
+        for (conn_kind, counter) in CONN_KINDS.iter().zip(inner.conns_total.iter()) {
+            let _ = writeln!(
+                output,
+                "relay_conns_total{{kind=\"{}\"}} {}",
+                conn_kind.label(),
+                counter.load(Ordering::Relaxed)
+            );
+        }
+
+        let _ = writeln!(
+            output,
+            "# HELP relay_quota_rejections_total Connections refused or dropped because of relay limits."
+        );
+        let _ = writeln!(output, "# TYPE relay_quota_rejections_total counter");
+        for (quota_rejection, counter) in QUOTA_REJECTIONS.iter().zip(inner.rejections_total.iter())
+        {
+            let _ = writeln!(
+                output,
+                "relay_quota_rejections_total{{reason=\"{}\"}} {}",
+                quota_rejection.label(),
+                counter.load(Ordering::Relaxed)
+            );
+        }
+
+        let _ = writeln!(
+            output,
+            "# HELP relay_reaped_total Connections closed for outliving their limits."
+        );
+        let _ = writeln!(output, "# TYPE relay_reaped_total counter");
+        for (reap_reason, counter) in REAP_REASONS.iter().zip(inner.reaped_total.iter()) {
+            let _ = writeln!(
+                output,
+                "relay_reaped_total{{reason=\"{}\"}} {}",
+                reap_reason.label(),
+                counter.load(Ordering::Relaxed)
+            );
+        }
+
+        let simple_metrics: [(&str, &str, &str, &AtomicU64); 6] = [
+            (
+                "relay_conn_timeouts_total",
+                "counter",
+                "Connections that did not declare their purpose in time.",
+                &inner.conn_timeouts_total,
+            ),
+            (
+                "relay_conn_dispatch_failures_total",
+                "counter",
+                "Connections that closed or sent an invalid first message.",
+                &inner.conn_dispatch_failures_total,
+            ),
+            (
+                "relay_bytes_forwarded_total",
+                "counter",
+                "Bytes forwarded through tunnels.",
+                &inner.bytes_forwarded_total,
+            ),
+            (
+                "relay_listeners",
+                "gauge",
+                "Public keys currently listening.",
+                &inner.listeners,
+            ),
+            (
+                "relay_half_tunnels",
+                "gauge",
+                "Connect requests waiting to be accepted.",
+                &inner.half_tunnels,
+            ),
+            ("relay_tunnels", "gauge", "Open tunnels.", &inner.tunnels),
+        ];
+        for (name, metric_type, help, value) in simple_metrics.iter() {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} {}", name, metric_type);
+            let _ = writeln!(output, "{} {}", name, value.load(Ordering::Relaxed));
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relay_metrics_render_prometheus() {
+        let metrics = RelayMetrics::new();
+        let c_metrics = metrics.clone();
+
+        c_metrics.incoming_conn(ConnKind::Connect);
+        c_metrics.incoming_conn(ConnKind::Connect);
+        c_metrics.incoming_conn(ConnKind::ListenMux);
+        c_metrics.quota_rejection(QuotaRejection::TooManyListeners);
+        c_metrics.conn_timeout();
+        c_metrics.reaped(ReapReason::HalfOpen);
+        c_metrics.bytes_forwarded(100);
+        c_metrics.bytes_forwarded(23);
+        c_metrics.set_listeners(3, 5);
+        c_metrics.tunnel_opened();
+        c_metrics.tunnel_opened();
+        c_metrics.tunnel_closed();
+
+        let output = metrics.render_prometheus();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert!(lines.contains(&"# TYPE relay_conns_total counter"));
+        assert!(lines.contains(&"relay_conns_total{kind=\"listen\"} 0"));
+        assert!(lines.contains(&"relay_conns_total{kind=\"listen_mux\"} 1"));
+        assert!(lines.contains(&"relay_conns_total{kind=\"connect\"} 2"));
+        assert!(lines.contains(&"relay_quota_rejections_total{reason=\"too_many_listeners\"} 1"));
+        assert!(lines.contains(&"relay_quota_rejections_total{reason=\"slow_tunnel_side\"} 0"));
+        assert!(lines.contains(&"relay_reaped_total{reason=\"half_open\"} 1"));
+        assert!(lines.contains(&"relay_reaped_total{reason=\"tunnel_idle\"} 0"));
+        assert!(lines.contains(&"relay_conn_timeouts_total 1"));
+        assert!(lines.contains(&"relay_conn_dispatch_failures_total 0"));
+        assert!(lines.contains(&"relay_bytes_forwarded_total 123"));
+        assert!(lines.contains(&"# TYPE relay_listeners gauge"));
+        assert!(lines.contains(&"relay_listeners 3"));
+        assert!(lines.contains(&"relay_half_tunnels 5"));
+        assert!(lines.contains(&"relay_tunnels 1"));
+    }
+}
@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+use std::marker::Unpin;
+
+use futures::channel::mpsc;
+use futures::task::{Spawn, SpawnExt};
+use futures::{future, stream, Sink, SinkExt, Stream, StreamExt};
+
+use common::conn::{BoxSink, BoxStream, ConnPairVec, SinkError};
+use common::select_streams::select_streams;
+
+use proto::crypto::PublicKey;
+use proto::proto_ser::{ProtoDeserialize, ProtoSerialize};
+use proto::relay::messages::{MuxMessage, OpenStream, RejectListen, StreamData};
+
+/// Amount of messages buffered for a stream whose user does not read its incoming data.
+/// A stream that exceeds its buffer is closed, so that it will not delay the other streams.
+const STREAM_BUFFER_LEN: usize = 16;
+
+#[derive(Debug)]
+pub enum MuxError {
+    DeserializeError,
+    InvalidOpenStream,
+    StreamIdOverflow,
+    SendToRemoteError,
+    SendStreamError,
+    SpawnError,
+    /// The relay did not accept the listen connection
+    ListenRejected(RejectListen),
+    /// A listen rejection was sent by a client
+    InvalidRejectListen,
+}
+
+#[derive(Debug)]
+enum MuxEvent {
+    RemoteData(Vec<u8>),
+    RemoteClosed,
+    OpenRequest(PublicKey),
+    StreamData((u64, Vec<u8>)),
+    StreamClosed(u64),
+}
+
+async fn send_mux_message(
+    sender: &mut BoxSink<'static, Vec<u8>, SinkError>,
+    mux_message: MuxMessage,
+) -> Result<(), MuxError> {
+    sender
+        .send(mux_message.proto_serialize())
+        .await
+        .map_err(|_| MuxError::SendToRemoteError)
+}
+
+/// Create a new stream.
+/// Returns a sender for data arriving from the remote side, and a connection pair for the user of
+/// the stream. Data sent by the user is forwarded to `event_sender`.
+fn create_stream<S>(
+    stream_id: u64,
+    event_sender: mpsc::Sender<MuxEvent>,
+    spawner: &S,
+) -> Result<(mpsc::Sender<Vec<u8>>, ConnPairVec), MuxError>
+where
+    S: Spawn,
+{
+    let (remote_sender, user_receiver) = mpsc::channel::<Vec<u8>>(STREAM_BUFFER_LEN);
+    let (user_sender, remote_receiver) = mpsc::channel::<Vec<u8>>(0);
+
+    let mut stream_events = remote_receiver
+        .map(move |data| MuxEvent::StreamData((stream_id, data)))
+        .chain(stream::once(future::ready(MuxEvent::StreamClosed(
+            stream_id,
+        ))));
+
+    spawner
+        .spawn(async move {
+            let mut event_sender = event_sender;
+            let _ = event_sender.send_all(&mut stream_events.map(Ok)).await;
+        })
+        .map_err(|_| MuxError::SpawnError)?;
+
+    Ok((
+        remote_sender,
+        ConnPairVec::from_raw(user_sender, user_receiver),
+    ))
+}
+
+/// Multiplex many streams over a single connection.
+///
+/// Streams are opened locally (For every public key received from `open_requests`), or by the
+/// remote side (If `accept_remote_open` is set). Only one side of the connection may open
+/// streams. Every opened stream is sent through `streams_sender`, together with the public key
+/// of its remote side. Dropping a stream closes it on both sides.
+///
+/// A stream that does not read its incoming data is closed once its buffer is full
+/// (See `STREAM_BUFFER_LEN`).
+async fn mux_loop<SS, S>(
+    conn_pair: ConnPairVec,
+    open_requests: BoxStream<'static, PublicKey>,
+    accept_remote_open: bool,
+    mut streams_sender: SS,
+    spawner: S,
+) -> Result<(), MuxError>
+where
+    SS: Sink<(PublicKey, ConnPairVec)> + Unpin,
+    S: Spawn,
+{
+    let (mut sender, receiver) = conn_pair.split();
+    let (event_sender, event_receiver) = mpsc::channel::<MuxEvent>(0);
+
+    let receiver = receiver
+        .map(MuxEvent::RemoteData)
+        .chain(stream::once(future::ready(MuxEvent::RemoteClosed)));
+    let open_requests = open_requests.map(MuxEvent::OpenRequest);
+
+    let mut events = select_streams![receiver, open_requests, event_receiver];
+
+    // Senders of data arriving from the remote side, for every open stream:
+    let mut streams: HashMap<u64, mpsc::Sender<Vec<u8>>> = HashMap::new();
+    // Stream ids are never reused. This makes sure that data sent by a closed stream
+    // is never attributed to a new stream.
+    let mut next_stream_id: u64 = 0;
+
+    while let Some(event) = events.next().await {
+        match event {
+            MuxEvent::RemoteData(data) => {
+                let mux_message =
+                    MuxMessage::proto_deserialize(&data).map_err(|_| MuxError::DeserializeError)?;
+                match mux_message {
+                    MuxMessage::OpenStream(OpenStream {
+                        stream_id,
+                        public_key,
+                    }) => {
+                        if !accept_remote_open || stream_id < next_stream_id {
+                            return Err(MuxError::InvalidOpenStream);
+                        }
+                        next_stream_id =
+                            stream_id.checked_add(1).ok_or(MuxError::StreamIdOverflow)?;
+                        let (stream_sender, stream_conn_pair) =
+                            create_stream(stream_id, event_sender.clone(), &spawner)?;
+                        streams.insert(stream_id, stream_sender);
+                        streams_sender
+                            .send((public_key, stream_conn_pair))
+                            .await
+                            .map_err(|_| MuxError::SendStreamError)?;
+                    }
+                    MuxMessage::Data(StreamData { stream_id, data }) => {
+                        // Data for streams that were already closed is discarded:
+                        if let Some(stream_sender) = streams.get_mut(&stream_id) {
+                            // We never wait for a single stream, as this would stall all the
+                            // other streams:
+                            if stream_sender.try_send(data).is_err() {
+                                // The user of the stream is gone, or does not read its data:
+                                streams.remove(&stream_id);
+                                send_mux_message(&mut sender, MuxMessage::CloseStream(stream_id))
+                                    .await?;
+                            }
+                        }
+                    }
+                    MuxMessage::CloseStream(stream_id) => {
+                        // Dropping the sender closes the stream for its user:
+                        streams.remove(&stream_id);
+                    }
+                    MuxMessage::RejectListen(reject_listen) => {
+                        // Only the relay (The side that opens streams) may reject:
+                        if !accept_remote_open {
+                            return Err(MuxError::InvalidRejectListen);
+                        }
+                        return Err(MuxError::ListenRejected(reject_listen));
+                    }
+                }
+            }
+            MuxEvent::RemoteClosed => break,
+            MuxEvent::OpenRequest(public_key) => {
+                let stream_id = next_stream_id;
+                next_stream_id = stream_id.checked_add(1).ok_or(MuxError::StreamIdOverflow)?;
+                let open_stream = OpenStream {
+                    stream_id,
+                    public_key: public_key.clone(),
+                };
+                send_mux_message(&mut sender, MuxMessage::OpenStream(open_stream)).await?;
+                let (stream_sender, stream_conn_pair) =
+                    create_stream(stream_id, event_sender.clone(), &spawner)?;
+                streams.insert(stream_id, stream_sender);
+                streams_sender
+                    .send((public_key, stream_conn_pair))
+                    .await
+                    .map_err(|_| MuxError::SendStreamError)?;
+            }
+            MuxEvent::StreamData((stream_id, data)) => {
+                if streams.contains_key(&stream_id) {
+                    let stream_data = StreamData { stream_id, data };
+                    send_mux_message(&mut sender, MuxMessage::Data(stream_data)).await?;
+                }
+            }
+            MuxEvent::StreamClosed(stream_id) => {
+                if streams.remove(&stream_id).is_some() {
+                    send_mux_message(&mut sender, MuxMessage::CloseStream(stream_id)).await?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Relay side of a multiplexed listen connection.
+/// A new stream is opened for every public key received from `open_requests`.
+pub async fn mux_relay_loop<OR, SS, S>(
+    conn_pair: ConnPairVec,
+    open_requests: OR,
+    streams_sender: SS,
+    spawner: S,
+) -> Result<(), MuxError>
+where
+    OR: Stream<Item = PublicKey> + Send + 'static,
+    SS: Sink<(PublicKey, ConnPairVec)> + Unpin,
+    S: Spawn,
+{
+    mux_loop(
+        conn_pair,
+        open_requests.boxed(),
+        false,
+        streams_sender,
+        spawner,
+    )
+    .await
+}
+
+/// Client side of a multiplexed listen connection.
+/// Streams are opened only by the relay.
+pub async fn mux_client_loop<SS, S>(
+    conn_pair: ConnPairVec,
+    streams_sender: SS,
+    spawner: S,
+) -> Result<(), MuxError>
+where
+    SS: Sink<(PublicKey, ConnPairVec)> + Unpin,
+    S: Spawn,
+{
+    mux_loop(
+        conn_pair,
+        stream::empty().boxed(),
+        true,
+        streams_sender,
+        spawner,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::{LocalPool, ThreadPool};
+    use futures::FutureExt;
+
+    async fn task_mux_basic(spawner: impl Spawn + Clone + Send + 'static) {
+        let (relay_sender, client_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (client_sender, relay_receiver) = mpsc::channel::<Vec<u8>>(0);
+
+        let (mut open_sender, open_receiver) = mpsc::channel::<PublicKey>(0);
+        let (relay_streams_sender, mut relay_streams) = mpsc::channel(0);
+        let (client_streams_sender, mut client_streams) = mpsc::channel(0);
+
+        spawner
+            .spawn(
+                mux_relay_loop(
+                    ConnPairVec::from_raw(relay_sender, relay_receiver),
+                    open_receiver,
+                    relay_streams_sender,
+                    spawner.clone(),
+                )
+                .map(|res| res.unwrap()),
+            )
+            .unwrap();
+
+        spawner
+            .spawn(
+                mux_client_loop(
+                    ConnPairVec::from_raw(client_sender, client_receiver),
+                    client_streams_sender,
+                    spawner.clone(),
+                )
+                .map(|res| res.unwrap()),
+            )
+            .unwrap();
+
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+
+        // Open two streams:
+        open_sender.send(pk_a.clone()).await.unwrap();
+        let (public_key, relay_stream_a) = relay_streams.next().await.unwrap();
+        assert_eq!(public_key, pk_a);
+        let (public_key, client_stream_a) = client_streams.next().await.unwrap();
+        assert_eq!(public_key, pk_a);
+
+        open_sender.send(pk_b.clone()).await.unwrap();
+        let (public_key, relay_stream_b) = relay_streams.next().await.unwrap();
+        assert_eq!(public_key, pk_b);
+        let (public_key, client_stream_b) = client_streams.next().await.unwrap();
+        assert_eq!(public_key, pk_b);
+
+        let (mut relay_sender_a, mut relay_receiver_a) = relay_stream_a.split();
+        let (mut client_sender_a, mut client_receiver_a) = client_stream_a.split();
+        let (mut relay_sender_b, _relay_receiver_b) = relay_stream_b.split();
+        let (_client_sender_b, mut client_receiver_b) = client_stream_b.split();
+
+        relay_sender_b.send(vec![4, 5]).await.unwrap();
+        relay_sender_a.send(vec![1, 2, 3]).await.unwrap();
+        assert_eq!(client_receiver_b.next().await.unwrap(), vec![4, 5]);
+        assert_eq!(client_receiver_a.next().await.unwrap(), vec![1, 2, 3]);
+
+        client_sender_a.send(vec![3, 2, 1]).await.unwrap();
+        assert_eq!(relay_receiver_a.next().await.unwrap(), vec![3, 2, 1]);
+
+        // Closing a stream on the client side closes it on the relay side:
+        drop(client_sender_a);
+        drop(client_receiver_a);
+        assert!(relay_receiver_a.next().await.is_none());
+
+        // The other stream keeps working:
+        relay_sender_b.send(vec![6]).await.unwrap();
+        assert_eq!(client_receiver_b.next().await.unwrap(), vec![6]);
+    }
+
+    #[test]
+    fn test_mux_basic() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new().run_until(task_mux_basic(thread_pool.clone()));
+    }
+
+    async fn task_mux_client_rejects_open(spawner: impl Spawn + Clone + Send + 'static) {
+        let (mut relay_sender, client_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (client_sender, _relay_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (client_streams_sender, _client_streams) = mpsc::channel(0);
+
+        let mux_handle = spawner
+            .spawn_with_handle(mux_client_loop(
+                ConnPairVec::from_raw(client_sender, client_receiver),
+                client_streams_sender,
+                spawner.clone(),
+            ))
+            .unwrap();
+
+        // The relay may not use the same stream id twice:
+        let open_stream = OpenStream {
+            stream_id: 3,
+            public_key: PublicKey::from(&[0xaa; PublicKey::len()]),
+        };
+        relay_sender
+            .send(MuxMessage::OpenStream(open_stream.clone()).proto_serialize())
+            .await
+            .unwrap();
+        relay_sender
+            .send(MuxMessage::OpenStream(open_stream).proto_serialize())
+            .await
+            .unwrap();
+
+        match mux_handle.await {
+            Err(MuxError::InvalidOpenStream) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    async fn task_mux_stalled_stream(spawner: impl Spawn + Clone + Send + 'static) {
+        let (relay_sender, client_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (client_sender, relay_receiver) = mpsc::channel::<Vec<u8>>(0);
+
+        let (mut open_sender, open_receiver) = mpsc::channel::<PublicKey>(0);
+        let (relay_streams_sender, mut relay_streams) = mpsc::channel(0);
+        let (client_streams_sender, mut client_streams) = mpsc::channel(0);
+
+        spawner
+            .spawn(
+                mux_relay_loop(
+                    ConnPairVec::from_raw(relay_sender, relay_receiver),
+                    open_receiver,
+                    relay_streams_sender,
+                    spawner.clone(),
+                )
+                .map(|res| res.unwrap()),
+            )
+            .unwrap();
+
+        spawner
+            .spawn(
+                mux_client_loop(
+                    ConnPairVec::from_raw(client_sender, client_receiver),
+                    client_streams_sender,
+                    spawner.clone(),
+                )
+                .map(|res| res.unwrap()),
+            )
+            .unwrap();
+
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+
+        open_sender.send(pk_a.clone()).await.unwrap();
+        let (_public_key, relay_stream_a) = relay_streams.next().await.unwrap();
+        let (_public_key, client_stream_a) = client_streams.next().await.unwrap();
+
+        open_sender.send(pk_b.clone()).await.unwrap();
+        let (_public_key, relay_stream_b) = relay_streams.next().await.unwrap();
+        let (_public_key, client_stream_b) = client_streams.next().await.unwrap();
+
+        let (mut relay_sender_a, mut relay_receiver_a) = relay_stream_a.split();
+        let (_client_sender_a, mut client_receiver_a) = client_stream_a.split();
+        let (mut relay_sender_b, _relay_receiver_b) = relay_stream_b.split();
+        let (_client_sender_b, mut client_receiver_b) = client_stream_b.split();
+
+        // The client does not read stream a. Sending more than its buffer does not block:
+        for i in 0..STREAM_BUFFER_LEN + 2 {
+            relay_sender_a.send(vec![i as u8]).await.unwrap();
+        }
+
+        // Stream b keeps working:
+        relay_sender_b.send(vec![4, 5]).await.unwrap();
+        assert_eq!(client_receiver_b.next().await.unwrap(), vec![4, 5]);
+
+        // Stream a was closed on both sides. The client can still read the buffered data:
+        assert!(relay_receiver_a.next().await.is_none());
+        let mut num_received = 0;
+        while let Some(data) = client_receiver_a.next().await {
+            assert_eq!(data, vec![num_received as u8]);
+            num_received += 1;
+        }
+        assert!(num_received <= STREAM_BUFFER_LEN + 1);
+    }
+
+    #[test]
+    fn test_mux_stalled_stream() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new().run_until(task_mux_stalled_stream(thread_pool.clone()));
+    }
+
+    #[test]
+    fn test_mux_client_rejects_open() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new().run_until(task_mux_client_rejects_open(thread_pool.clone()));
+    }
+}
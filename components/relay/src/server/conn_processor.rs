@@ -7,13 +7,16 @@ use common::conn::{ConnPair, ConnPairVec, SinkError};
 use timer::utils::future_timeout;
 use timer::TimerClient;
 
+use crate::metrics::{ConnKind, RelayMetrics};
+
 use super::types::{
-    IncomingAccept, IncomingConn, IncomingConnInner, IncomingConnect, IncomingListen,
+    IncomingAccept, IncomingConn, IncomingConnInner, IncomingConnect, IncomingForwardConnect,
+    IncomingListen, IncomingListenMux, IncomingPeer, IncomingPing,
 };
 
 use proto::crypto::PublicKey;
 use proto::proto_ser::{ProtoDeserialize, ProtoSerialize};
-use proto::relay::messages::{IncomingConnection, InitConnection, RejectConnection};
+use proto::relay::messages::{IncomingConnection, InitConnection, RejectConnection, RelayPing};
 
 async fn dispatch_conn(
     conn_pair_vec: ConnPairVec,
@@ -38,6 +41,9 @@ async fn dispatch_conn(
             );
             IncomingConnInner::Listen(IncomingListen { conn_pair })
         }
+        InitConnection::ListenMux => IncomingConnInner::ListenMux(IncomingListenMux {
+            conn_pair: ConnPairVec::from_raw(sender, receiver),
+        }),
         InitConnection::Accept(accept_public_key) => IncomingConnInner::Accept(IncomingAccept {
             accept_public_key,
             conn_pair: ConnPairVec::from_raw(sender, receiver),
@@ -48,6 +54,28 @@ async fn dispatch_conn(
                 conn_pair: ConnPairVec::from_raw(sender, receiver),
             })
         }
+        InitConnection::Peer => IncomingConnInner::Peer(IncomingPeer {
+            conn_pair: ConnPairVec::from_raw(sender, receiver),
+        }),
+        InitConnection::ForwardConnect(forward_connect) => {
+            IncomingConnInner::ForwardConnect(IncomingForwardConnect {
+                init_public_key: forward_connect.init_public_key,
+                connect_public_key: forward_connect.connect_public_key,
+                conn_pair: ConnPairVec::from_raw(sender, receiver),
+            })
+        }
+        InitConnection::Ping => {
+            let conn_pair = ConnPair::from_raw(
+                sender.sink_map_err(|_| SinkError).with(|msg: RelayPing| {
+                    future::ready::<Result<_, SinkError>>(Ok(msg.proto_serialize()))
+                }),
+                receiver
+                    .map(|data| RelayPing::proto_deserialize(&data))
+                    .take_while(|res| future::ready(res.is_ok()))
+                    .map(Result::unwrap),
+            );
+            IncomingConnInner::Ping(IncomingPing { conn_pair })
+        }
     };
 
     Some(IncomingConn { public_key, inner })
@@ -58,6 +86,7 @@ async fn process_conn(
     public_key: PublicKey,
     mut timer_client: TimerClient,
     conn_timeout_ticks: usize,
+    metrics: RelayMetrics,
 ) -> Option<IncomingConn> {
     let fut_receiver = Box::pin(async move {
         if let Some(first_msg) = conn_pair_vec.receiver.next().await {
@@ -76,11 +105,28 @@ async fn process_conn(
     });
 
     let timer_stream = timer_client.request_timer_stream().await.unwrap();
-    let res = future_timeout(fut_receiver, timer_stream, conn_timeout_ticks).await?;
-    if res.is_none() {
-        warn!("process_conn(): timeout occurred");
-    }
-    res
+    let incoming_conn = match future_timeout(fut_receiver, timer_stream, conn_timeout_ticks).await {
+        Some(Some(incoming_conn)) => incoming_conn,
+        Some(None) => {
+            metrics.conn_dispatch_failure();
+            return None;
+        }
+        None => {
+            warn!("process_conn(): timeout occurred");
+            metrics.conn_timeout();
+            return None;
+        }
+    };
+    metrics.incoming_conn(match &incoming_conn.inner {
+        IncomingConnInner::Listen(_) => ConnKind::Listen,
+        IncomingConnInner::ListenMux(_) => ConnKind::ListenMux,
+        IncomingConnInner::Accept(_) => ConnKind::Accept,
+        IncomingConnInner::Connect(_) => ConnKind::Connect,
+        IncomingConnInner::Peer(_) => ConnKind::Peer,
+        IncomingConnInner::ForwardConnect(_) => ConnKind::ForwardConnect,
+        IncomingConnInner::Ping(_) => ConnKind::Ping,
+    });
+    Some(incoming_conn)
 }
 
 /// Process incoming connections
@@ -91,6 +137,7 @@ pub fn conn_processor<T>(
     incoming_conns: T,
     timer_client: TimerClient,
     conn_timeout_ticks: usize,
+    metrics: RelayMetrics,
 ) -> impl Stream<Item = IncomingConn>
 where
     T: Stream<Item = (PublicKey, ConnPairVec)> + Unpin + Send + 'static,
@@ -102,6 +149,7 @@ where
                 public_key,
                 timer_client.clone(),
                 conn_timeout_ticks,
+                metrics.clone(),
             )
         })
         .filter_map(|opt_conn| opt_conn)
@@ -146,6 +194,24 @@ mod tests {
             _ => panic!("Wrong IncomingConnInner"),
         };
 
+        let (sender, receiver) = mpsc::channel::<Vec<u8>>(0);
+        let first_msg = InitConnection::ListenMux;
+        let ser_first_msg = first_msg.proto_serialize();
+        let public_key = PublicKey::from(&[0x77; PublicKey::len()]);
+        let incoming_conn = dispatch_conn(
+            ConnPairVec::from_raw(sender, receiver),
+            public_key.clone(),
+            ser_first_msg,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(incoming_conn.public_key, public_key);
+        match incoming_conn.inner {
+            IncomingConnInner::ListenMux(_incoming_listen_mux) => {}
+            _ => panic!("Wrong IncomingConnInner"),
+        };
+
         let (sender, receiver) = mpsc::channel::<Vec<u8>>(0);
         let accept_public_key = PublicKey::from(&[0x22; PublicKey::len()]);
         let first_msg = InitConnection::Accept(accept_public_key.clone());
@@ -187,6 +253,24 @@ mod tests {
             }
             _ => panic!("Wrong IncomingConnInner"),
         };
+
+        let (sender, receiver) = mpsc::channel::<Vec<u8>>(0);
+        let first_msg = InitConnection::Ping;
+        let ser_first_msg = first_msg.proto_serialize();
+        let public_key = PublicKey::from(&[0x77; PublicKey::len()]);
+        let incoming_conn = dispatch_conn(
+            ConnPairVec::from_raw(sender, receiver),
+            public_key.clone(),
+            ser_first_msg,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(incoming_conn.public_key, public_key);
+        match incoming_conn.inner {
+            IncomingConnInner::Ping(_incoming_ping) => {}
+            _ => panic!("Wrong IncomingConnInner"),
+        };
     }
 
     #[test]
@@ -236,9 +320,15 @@ mod tests {
         )]);
 
         let conn_timeout_ticks = 16;
+        let metrics = RelayMetrics::new();
 
-        let processed_conns =
-            conn_processor(incoming_conns, timer_client, conn_timeout_ticks).boxed();
+        let processed_conns = conn_processor(
+            incoming_conns,
+            timer_client,
+            conn_timeout_ticks,
+            metrics.clone(),
+        )
+        .boxed();
 
         let processed_conns = Box::pin(processed_conns);
 
@@ -264,6 +354,10 @@ mod tests {
             IncomingConnInner::Listen(_incoming_listen) => {}
             _ => panic!("Incorrect processed conn"),
         };
+        assert!(metrics
+            .render_prometheus()
+            .lines()
+            .any(|line| line == "relay_conns_total{kind=\"listen\"} 1"));
 
         assert!(LocalPool::new()
             .run_until(receive(processed_conns))
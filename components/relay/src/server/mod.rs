@@ -1,9 +1,17 @@
 mod conn_limiter;
 mod conn_processor;
+mod peers;
+mod ping;
+mod relay_announcer;
 // pub mod net_server;
 mod server;
 mod server_loop;
+mod tunnel;
 mod types;
+mod ws_listener;
 
+pub use relay_announcer::{announce_relay, AnnounceRelayError};
 pub use server::relay_server;
 pub use server_loop::RelayServerError;
+pub use types::RelayTimeouts;
+pub use ws_listener::{ws_accept, ws_listener};
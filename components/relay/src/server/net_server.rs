@@ -9,7 +9,9 @@ use derive_more::*;
 use common::conn::{BoxFuture, ConnPairVec, FutTransform};
 use common::transform_pool::transform_pool_loop;
 
-use proto::consts::{CONN_TIMEOUT_TICKS, KEEPALIVE_TICKS, PROTOCOL_VERSION, TICKS_TO_REKEY};
+use proto::consts::{
+    CONN_TIMEOUT_TICKS, KEEPALIVE_TICKS, PROTOCOL_VERSION, TICKS_TO_REKEY, TICKS_TO_RESUME,
+};
 use proto::crypto::PublicKey;
 
 use crypto::rand::CryptoRandom;
@@ -119,6 +121,7 @@ where
         rng,
         timer_client.clone(),
         TICKS_TO_REKEY,
+        TICKS_TO_RESUME,
         spawner.clone(),
     );
 
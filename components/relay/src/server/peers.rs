@@ -0,0 +1,134 @@
+use futures::channel::mpsc;
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::{ConnPairVec, FutTransform};
+
+use proto::crypto::PublicKey;
+use proto::proto_ser::ProtoSerialize;
+use proto::relay::messages::{ForwardConnect, InitConnection, PeerListeners};
+
+use timer::TimerClient;
+
+use crate::metrics::RelayMetrics;
+
+use super::tunnel::{tunnel_loop, TunnelEnd};
+use super::types::RelayTimeouts;
+
+#[derive(Debug)]
+pub enum ForwardConnectError {
+    ConnectPeerFailed,
+}
+
+/// Open a connection to a peer relay, and declare its purpose.
+async fn connect_peer<A, PC>(
+    peer_public_key: PublicKey,
+    peer_address: A,
+    peer_connector: &mut PC,
+    init_connection: InitConnection,
+) -> Option<ConnPairVec>
+where
+    PC: FutTransform<Input = (PublicKey, A), Output = Option<ConnPairVec>>,
+{
+    let conn_pair = peer_connector
+        .transform((peer_public_key, peer_address))
+        .await?;
+    let (mut sender, receiver) = conn_pair.split();
+    sender.send(init_connection.proto_serialize()).await.ok()?;
+    Some(ConnPairVec::from_raw(sender, receiver))
+}
+
+/// Announce the public keys listening on this relay to a peer relay.
+///
+/// Every announcement received from `announcements` is sent to the peer relay. A connection to
+/// the peer relay is opened when an announcement is due and we are not connected, so a failed
+/// connection is retried on the next announcement.
+pub async fn peer_announcer_loop<A, PC>(
+    peer_public_key: PublicKey,
+    peer_address: A,
+    mut peer_connector: PC,
+    mut announcements: mpsc::Receiver<PeerListeners>,
+) where
+    A: Clone,
+    PC: FutTransform<Input = (PublicKey, A), Output = Option<ConnPairVec>>,
+{
+    let mut opt_conn_pair: Option<ConnPairVec> = None;
+    while let Some(peer_listeners) = announcements.next().await {
+        let conn_pair = match opt_conn_pair.take() {
+            Some(conn_pair) => conn_pair,
+            None => match connect_peer(
+                peer_public_key.clone(),
+                peer_address.clone(),
+                &mut peer_connector,
+                InitConnection::Peer,
+            )
+            .await
+            {
+                Some(conn_pair) => conn_pair,
+                None => {
+                    warn!("Failed connecting to peer relay {:?}", peer_public_key);
+                    continue;
+                }
+            },
+        };
+
+        let (mut sender, receiver) = conn_pair.split();
+        if sender.send(peer_listeners.proto_serialize()).await.is_ok() {
+            opt_conn_pair = Some(ConnPairVec::from_raw(sender, receiver));
+        }
+    }
+}
+
+/// Forward a connection from a client of this relay to a peer relay, where `connect_public_key`
+/// is listening.
+///
+/// The peer relay handles the forwarded connection as if the client connected to it directly.
+/// Data is forwarded in both directions until both sides close the connection, or until the
+/// tunnel outlives the limits given in `timeouts`.
+pub async fn forward_connect<A, PC>(
+    init_public_key: PublicKey,
+    connect_public_key: PublicKey,
+    conn_pair: ConnPairVec,
+    peer_public_key: PublicKey,
+    peer_address: A,
+    mut peer_connector: PC,
+    timer_client: TimerClient,
+    timeouts: RelayTimeouts,
+    max_tunnel_buffered_bytes: usize,
+    metrics: RelayMetrics,
+    spawner: impl Spawn,
+) -> Result<(), ForwardConnectError>
+where
+    PC: FutTransform<Input = (PublicKey, A), Output = Option<ConnPairVec>>,
+{
+    let forward_connect = ForwardConnect {
+        init_public_key,
+        connect_public_key,
+    };
+    let peer_conn_pair = connect_peer(
+        peer_public_key,
+        peer_address,
+        &mut peer_connector,
+        InitConnection::ForwardConnect(forward_connect),
+    )
+    .await
+    .ok_or(ForwardConnectError::ConnectPeerFailed)?;
+
+    metrics.tunnel_opened();
+    let tunnel_end = tunnel_loop(
+        conn_pair,
+        peer_conn_pair,
+        timer_client,
+        timeouts,
+        max_tunnel_buffered_bytes,
+        metrics.clone(),
+        spawner,
+    )
+    .await;
+    metrics.tunnel_closed();
+
+    if tunnel_end != TunnelEnd::Closed {
+        warn!("Forwarded tunnel closed: {:?}", tunnel_end);
+    }
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use futures::{SinkExt, StreamExt};
+
+use timer::utils::future_timeout;
+use timer::TimerClient;
+
+use super::types::IncomingPing;
+
+/// The reason a ping connection was closed
+#[derive(Debug, PartialEq, Eq)]
+pub enum PingEnd {
+    /// The remote side closed the connection
+    Closed,
+    /// The remote side did not send a ping in time
+    Timeout,
+    /// The maximum amount of pings was echoed
+    MaxPings,
+    RequestTimerStreamError,
+}
+
+/// Echo back every ping received over a ping connection, so that the remote side can measure
+/// the round trip time to the relay.
+///
+/// Every ping must arrive within `ping_timeout_ticks` of the previous one, and at most
+/// `max_pings` pings are echoed. This way a ping connection can not be held open forever.
+pub async fn ping_loop(
+    incoming_ping: IncomingPing,
+    mut timer_client: TimerClient,
+    ping_timeout_ticks: usize,
+    max_pings: usize,
+) -> PingEnd {
+    let (mut sender, mut receiver) = incoming_ping.conn_pair.split();
+
+    for _ in 0..max_pings {
+        let timer_stream = match timer_client.request_timer_stream().await {
+            Ok(timer_stream) => timer_stream,
+            Err(_) => return PingEnd::RequestTimerStreamError,
+        };
+        let relay_ping =
+            match future_timeout(receiver.next(), timer_stream, ping_timeout_ticks).await {
+                Some(Some(relay_ping)) => relay_ping,
+                Some(None) => return PingEnd::Closed,
+                None => return PingEnd::Timeout,
+            };
+        if sender.send(relay_ping).await.is_err() {
+            return PingEnd::Closed;
+        }
+    }
+    PingEnd::MaxPings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::channel::mpsc;
+    use futures::executor::{LocalPool, ThreadPool};
+    use futures::task::{Spawn, SpawnExt};
+
+    use common::conn::ConnPair;
+
+    use proto::relay::messages::RelayPing;
+
+    use timer::create_timer_incoming;
+
+    async fn task_ping_loop_basic(spawner: impl Spawn + Clone + Send + 'static) {
+        let (_tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (mut remote_sender, local_receiver) = mpsc::channel::<RelayPing>(0);
+        let (local_sender, mut remote_receiver) = mpsc::channel::<RelayPing>(0);
+        let incoming_ping = IncomingPing {
+            conn_pair: ConnPair::from_raw(local_sender, local_receiver),
+        };
+
+        let ping_handle = spawner
+            .spawn_with_handle(ping_loop(incoming_ping, timer_client, 4, 2))
+            .unwrap();
+
+        // Pings are echoed back:
+        for nonce in 0..2u64 {
+            remote_sender.send(RelayPing { nonce }).await.unwrap();
+            assert_eq!(remote_receiver.next().await.unwrap(), RelayPing { nonce });
+        }
+
+        // The connection is closed after the maximum amount of pings:
+        assert_eq!(ping_handle.await, PingEnd::MaxPings);
+        assert!(remote_receiver.next().await.is_none());
+    }
+
+    #[test]
+    fn test_ping_loop_basic() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new().run_until(task_ping_loop_basic(thread_pool.clone()));
+    }
+
+    async fn task_ping_loop_timeout(spawner: impl Spawn + Clone + Send + 'static) {
+        let (mut tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (mut remote_sender, local_receiver) = mpsc::channel::<RelayPing>(0);
+        let (local_sender, mut remote_receiver) = mpsc::channel::<RelayPing>(0);
+        let incoming_ping = IncomingPing {
+            conn_pair: ConnPair::from_raw(local_sender, local_receiver),
+        };
+
+        let ping_handle = spawner
+            .spawn_with_handle(ping_loop(incoming_ping, timer_client, 4, 16))
+            .unwrap();
+
+        remote_sender.send(RelayPing { nonce: 0 }).await.unwrap();
+        assert_eq!(
+            remote_receiver.next().await.unwrap(),
+            RelayPing { nonce: 0 }
+        );
+
+        // The remote side stays silent, so the connection is eventually closed:
+        for _ in 0..8 {
+            tick_sender.send(()).await.unwrap();
+        }
+        assert_eq!(ping_handle.await, PingEnd::Timeout);
+    }
+
+    #[test]
+    fn test_ping_loop_timeout() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new().run_until(task_ping_loop_timeout(thread_pool.clone()));
+    }
+}
@@ -0,0 +1,89 @@
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPairVec;
+
+use proto::index_server::messages::IndexClientToServer;
+use proto::net::messages::NetAddress;
+use proto::proto_ser::ProtoSerialize;
+
+#[derive(Debug)]
+pub enum AnnounceRelayError {
+    SendAnnounceError,
+}
+
+/// Register this relay as a public relay with an index server, so that new nodes can find it
+/// without knowing its address in advance.
+/// `conn_pair` is an established connection to the index server, authenticated with the identity
+/// of the relay. `address` is the public address of the relay.
+///
+/// The index server lists the relay only while the connection is open, so this function returns
+/// only once the index server closes the connection.
+pub async fn announce_relay(
+    conn_pair: ConnPairVec,
+    address: NetAddress,
+) -> Result<(), AnnounceRelayError> {
+    let (mut sender, mut receiver) = conn_pair.split();
+
+    sender
+        .send(IndexClientToServer::AnnounceRelay(address).proto_serialize())
+        .await
+        .map_err(|_| AnnounceRelayError::SendAnnounceError)?;
+
+    // The index server sends time hashes to all of its clients. Those are not relevant for a
+    // relay:
+    while let Some(_data) = receiver.next().await {}
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use futures::channel::mpsc;
+    use futures::executor::{block_on, ThreadPool};
+    use futures::task::{Spawn, SpawnExt};
+
+    use proto::crypto::HashResult;
+    use proto::index_server::messages::IndexServerToClient;
+    use proto::proto_ser::ProtoDeserialize;
+
+    async fn task_announce_relay<S>(spawner: S)
+    where
+        S: Spawn,
+    {
+        let (relay_sender, mut server_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (mut server_sender, relay_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let conn_pair = ConnPairVec::from_raw(relay_sender, relay_receiver);
+
+        let address = NetAddress::try_from("relay:1337".to_owned()).unwrap();
+        let announce_fut = spawner
+            .spawn_with_handle(announce_relay(conn_pair, address.clone()))
+            .unwrap();
+
+        let data = server_receiver.next().await.unwrap();
+        match IndexClientToServer::proto_deserialize(&data).unwrap() {
+            IndexClientToServer::AnnounceRelay(announced_address) => {
+                assert_eq!(announced_address, address)
+            }
+            _ => unreachable!(),
+        };
+
+        // The announcement lasts until the server closes the connection:
+        let time_hash = HashResult::from(&[1; HashResult::len()]);
+        server_sender
+            .send(IndexServerToClient::TimeHash(time_hash).proto_serialize())
+            .await
+            .unwrap();
+        drop(server_sender);
+
+        announce_fut.await.unwrap();
+    }
+
+    #[test]
+    fn test_announce_relay() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_announce_relay(thread_pool.clone()));
+    }
+}
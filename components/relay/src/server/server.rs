@@ -1,39 +1,68 @@
+use std::collections::HashMap;
 use std::marker::Unpin;
 
 use futures::task::Spawn;
 use futures::Stream;
 
-use common::conn::ConnPairVec;
+use common::conn::{ConnPairVec, FutTransform};
 
 use proto::crypto::PublicKey;
 
 use timer::TimerClient;
 
+use crate::metrics::RelayMetrics;
+
 use crate::server::conn_processor::conn_processor;
 use crate::server::server_loop::{relay_server_loop, RelayServerError};
+use crate::server::types::RelayTimeouts;
 
 /// A relay server loop. Incoming connections should contain both (sender, receiver) and a
 /// public_key of the remote side (Should be obtained after authentication).
 ///
-/// `conn_timeout_ticks` is the amount of time we are willing to wait for a connection to identify
-/// its purpose.
-pub async fn relay_server<IC, S>(
+/// `timeouts` limit the lifetime of the connections: The time we are willing to wait for a
+/// connection to identify its purpose, the time listen connections and tunnels may stay idle, and
+/// the maximum lifetime of tunnels.
+/// `max_listeners` is the maximum amount of nodes that may listen at the same time.
+/// `max_tunnel_buffered_bytes` is the maximum amount of bytes buffered for one side of a tunnel.
+/// A side that does not keep up with the other side is disconnected.
+/// `peers` are the trusted peer relays (and their addresses), and `peer_connector` is used to
+/// connect to them. Connections to nodes listening on a peer relay are forwarded to the peer relay.
+/// `metrics` is updated with the activity of the relay server.
+pub async fn relay_server<IC, A, PC, S>(
     incoming_conns: IC,
     timer_client: TimerClient,
-    conn_timeout_ticks: usize,
-    half_tunnel_ticks: usize,
+    timeouts: RelayTimeouts,
+    max_listeners: usize,
+    max_tunnel_buffered_bytes: usize,
+    peers: HashMap<PublicKey, A>,
+    peer_connector: PC,
+    metrics: RelayMetrics,
     spawner: S,
 ) -> Result<(), RelayServerError>
 where
     S: Spawn + Clone + Send + 'static,
     IC: Stream<Item = (PublicKey, ConnPairVec)> + Unpin + Send + 'static,
+    A: Clone + Send + Sync + 'static,
+    PC: FutTransform<Input = (PublicKey, A), Output = Option<ConnPairVec>> + Clone + Send + 'static,
 {
     // TODO: How to get rid of the Box::pin here?
     let processed_conns = Box::pin(conn_processor(
         incoming_conns,
         timer_client.clone(),
-        conn_timeout_ticks,
+        timeouts.conn_timeout_ticks,
+        metrics.clone(),
     ));
 
-    relay_server_loop(timer_client, processed_conns, half_tunnel_ticks, spawner).await
+    relay_server_loop(
+        timer_client,
+        processed_conns,
+        timeouts,
+        max_listeners,
+        max_tunnel_buffered_bytes,
+        peers,
+        peer_connector,
+        metrics,
+        spawner,
+    )
+    .await
 }
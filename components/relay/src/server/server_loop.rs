@@ -2,20 +2,32 @@ use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::marker::Unpin;
 
-use futures::channel::mpsc;
+use futures::channel::{mpsc, oneshot};
 use futures::task::{Spawn, SpawnExt};
 use futures::{future, stream, FutureExt, Sink, SinkExt, Stream, StreamExt, TryFutureExt};
 
-use common::conn::{BoxStream, ConnPairVec};
+use common::conn::{BoxStream, ConnPair, ConnPairVec, FutTransform};
 use common::futures_compat::send_to_sink;
 use common::select_streams::select_streams;
 
 use timer::TimerClient;
 
+use proto::consts::{MAX_RELAY_PINGS, RELAY_PEER_ANNOUNCE_TICKS};
 use proto::crypto::PublicKey;
-use proto::relay::messages::{IncomingConnection, RejectConnection};
+use proto::proto_ser::{ProtoDeserializeChecked, ProtoSerialize};
+use proto::relay::messages::{
+    IncomingConnection, MuxMessage, PeerListeners, RejectConnection, RejectListen,
+};
 
-use super::types::{IncomingAccept, IncomingConn, IncomingConnInner};
+use crate::metrics::{QuotaRejection, ReapReason, RelayMetrics};
+use crate::mux::mux_relay_loop;
+
+use super::peers::{forward_connect, peer_announcer_loop};
+use super::ping::{ping_loop, PingEnd};
+use super::tunnel::{tunnel_loop, TunnelEnd};
+use super::types::{
+    IncomingAccept, IncomingConn, IncomingConnInner, IncomingListenMux, IncomingPeer, RelayTimeouts,
+};
 
 struct HalfTunnel {
     conn_pair: ConnPairVec,
@@ -26,14 +38,20 @@ struct Listener {
     half_tunnels: HashMap<PublicKey, HalfTunnel>,
     tunnels: HashSet<PublicKey>,
     opt_sender: Option<mpsc::Sender<IncomingConnection>>,
+    /// Dropping this sender stops receiving messages from the listen connection
+    opt_close_sender: Option<oneshot::Sender<()>>,
+    /// Ticks passed since the last incoming connection, accepted connection or rejection
+    idle_ticks: usize,
 }
 
 impl Listener {
-    fn new(sender: mpsc::Sender<IncomingConnection>) -> Self {
+    fn new(sender: mpsc::Sender<IncomingConnection>, close_sender: oneshot::Sender<()>) -> Self {
         Listener {
             half_tunnels: HashMap::new(),
             tunnels: HashSet::new(),
             opt_sender: Some(sender),
+            opt_close_sender: Some(close_sender),
+            idle_ticks: 0,
         }
     }
 }
@@ -49,6 +67,8 @@ enum RelayServerEvent {
     TunnelClosed(TunnelClosed),
     ListenerMessage((PublicKey, RejectConnection)),
     ListenerClosed(PublicKey),
+    PeerListeners((PublicKey, PeerListeners)),
+    PeerClosed(PublicKey),
     TimerTick,
     TimerClosed,
 }
@@ -63,12 +83,64 @@ impl fmt::Debug for RelayServerEvent {
             RelayServerEvent::TunnelClosed(_) => write!(f, "RelayServerEvent::TunnelClosed"),
             RelayServerEvent::ListenerMessage(_) => write!(f, "RelayServerEvent::ListenerMessage"),
             RelayServerEvent::ListenerClosed(_) => write!(f, "RelayServerEvent::ListenerClosed"),
+            RelayServerEvent::PeerListeners(_) => write!(f, "RelayServerEvent::PeerListeners"),
+            RelayServerEvent::PeerClosed(_) => write!(f, "RelayServerEvent::PeerClosed"),
             RelayServerEvent::TimerTick => write!(f, "RelayServerEvent::TimerTick"),
             RelayServerEvent::TimerClosed => write!(f, "RelayServerEvent::TimerClosed"),
         }
     }
 }
 
+impl From<&RejectListen> for QuotaRejection {
+    fn from(reject_listen: &RejectListen) -> Self {
+        match reject_listen {
+            RejectListen::AlreadyListening => QuotaRejection::AlreadyListening,
+            RejectListen::TooManyListeners => QuotaRejection::TooManyListeners,
+        }
+    }
+}
+
+/// Check whether a new listen connection from `public_key` may be registered.
+/// A public key whose previous listen connection was closed (While some of its tunnels are still
+/// open) may listen again, but only if the amount of listeners is below `max_listeners`.
+fn check_listen(
+    listeners: &HashMap<PublicKey, Listener>,
+    public_key: &PublicKey,
+    max_listeners: usize,
+) -> Result<(), RejectListen> {
+    if let Some(listener) = listeners.get(public_key) {
+        if listener.opt_sender.is_some() {
+            return Err(RejectListen::AlreadyListening);
+        }
+    }
+
+    let num_listening = listeners
+        .values()
+        .filter(|listener| listener.opt_sender.is_some())
+        .count();
+    if num_listening >= max_listeners {
+        return Err(RejectListen::TooManyListeners);
+    }
+    Ok(())
+}
+
+/// Tell a multiplexed listener why its listen connection was not accepted, and close the
+/// connection.
+fn reject_listen_mux(
+    incoming_listen_mux: IncomingListenMux,
+    reject_listen: RejectListen,
+    spawner: &impl Spawn,
+) -> Result<(), RelayServerError> {
+    let (mut sender, _receiver) = incoming_listen_mux.conn_pair.split();
+    let ser_reject_listen = MuxMessage::RejectListen(reject_listen).proto_serialize();
+    // We don't wait for the listener to receive the rejection:
+    spawner
+        .spawn(async move {
+            let _ = sender.send(ser_reject_listen).await;
+        })
+        .map_err(|_| RelayServerError::SpawnError)
+}
+
 #[derive(Debug)]
 pub enum RelayServerError {
     IncomingConnsError,
@@ -79,6 +151,7 @@ pub enum RelayServerError {
     NoPendingHalfTunnel,
     AlreadyListening,
     EventReceiverError,
+    SpawnError,
 }
 
 fn handle_accept<TCL>(
@@ -87,7 +160,11 @@ fn handle_accept<TCL>(
     incoming_accept: IncomingAccept,
     // TODO: This should be a oneshot:
     tunnel_closed_sender: TCL,
-    spawner: impl Spawn,
+    timer_client: TimerClient,
+    timeouts: RelayTimeouts,
+    max_tunnel_buffered_bytes: usize,
+    metrics: RelayMetrics,
+    spawner: impl Spawn + Clone + Send + 'static,
 ) -> Result<(), RelayServerError>
 where
     TCL: Sink<TunnelClosed, Error = ()> + Unpin + Send + 'static,
@@ -100,50 +177,279 @@ where
         accept_public_key,
         conn_pair,
     } = incoming_accept;
-    let (mut sender, receiver) = conn_pair.split();
-    let conn_pair = match listener.half_tunnels.remove(&accept_public_key) {
+    let init_conn_pair = match listener.half_tunnels.remove(&accept_public_key) {
         Some(HalfTunnel { conn_pair, .. }) => conn_pair,
         None => return Err(RelayServerError::NoPendingHalfTunnel),
     };
-    let c_accept_public_key = accept_public_key;
+    listener.idle_ticks = 0;
+
+    let c_spawner = spawner.clone();
+    let c_metrics = metrics.clone();
+    let tunnel_fut = async move {
+        let tunnel_end = tunnel_loop(
+            init_conn_pair,
+            conn_pair,
+            timer_client,
+            timeouts,
+            max_tunnel_buffered_bytes,
+            c_metrics,
+            c_spawner,
+        )
+        .await;
+        if tunnel_end != TunnelEnd::Closed {
+            warn!(
+                "Tunnel {:?} -> {:?} closed: {:?}",
+                accept_public_key, acceptor_public_key, tunnel_end
+            );
+        }
+        let tunnel_closed = TunnelClosed {
+            init_public_key: accept_public_key,
+            listen_public_key: acceptor_public_key,
+        };
+        let _ = send_to_sink(tunnel_closed_sender, tunnel_closed).await;
+    };
 
-    let (mut remote_sender, remote_receiver) = conn_pair.split();
+    spawner
+        .spawn(tunnel_fut)
+        .map_err(|_| RelayServerError::SpawnError)?;
+    metrics.tunnel_opened();
 
-    let send_fut1 = async move {
-        remote_sender
-            .send_all(&mut receiver.map(Ok))
-            .map_err(|e| error!("send_fut1 error: {:?}", e))
-            .then(|_| future::ready(()))
-            .await
+    Ok(())
+}
+
+/// Handle a connection from `init_public_key` to `connect_public_key`, if `connect_public_key`
+/// is listening on this relay.
+/// Returns the connection back if `connect_public_key` is not listening on this relay.
+fn try_connect_local(
+    listeners: &mut HashMap<PublicKey, Listener>,
+    init_public_key: PublicKey,
+    connect_public_key: &PublicKey,
+    conn_pair: ConnPairVec,
+    half_tunnel_ticks: usize,
+) -> Result<(), ConnPairVec> {
+    let listener = match listeners.get_mut(connect_public_key) {
+        Some(listener) => listener,
+        None => return Err(conn_pair),
     };
-    let send_fut2 = async move {
-        sender
-            .send_all(&mut remote_receiver.map(Ok))
-            .map_err(|e| error!("send_fut2 error: {:?}", e))
-            .then(move |_| {
-                let tunnel_closed = TunnelClosed {
-                    init_public_key: c_accept_public_key,
-                    listen_public_key: acceptor_public_key,
-                };
-                send_to_sink(tunnel_closed_sender, tunnel_closed).then(|_| future::ready(()))
-            })
-            .await
+    if listener.half_tunnels.contains_key(&init_public_key)
+        || listener.tunnels.contains(&init_public_key)
+    {
+        // Discard Connect connection
+        return Ok(());
+    }
+
+    let half_tunnel = HalfTunnel {
+        conn_pair,
+        ticks_to_close: half_tunnel_ticks,
     };
+    if let Some(sender) = &mut listener.opt_sender {
+        // Try to send a message to listener about new pending connection:
+        if let Ok(()) = sender.try_send(IncomingConnection {
+            public_key: init_public_key.clone(),
+        }) {
+            listener.half_tunnels.insert(init_public_key, half_tunnel);
+            listener.idle_ticks = 0;
+        }
+    }
+    Ok(())
+}
 
-    spawner.spawn(send_fut1).unwrap();
-    spawner.spawn(send_fut2).unwrap();
+/// Receive announcements of the public keys listening on a peer relay.
+/// Announcements are forwarded to the relay server loop as events.
+fn register_peer<ES>(
+    peer_public_key: PublicKey,
+    incoming_peer: IncomingPeer,
+    event_sender: ES,
+    spawner: &impl Spawn,
+) -> Result<(), RelayServerError>
+where
+    ES: Sink<RelayServerEvent, Error = ()> + Unpin + Send + 'static,
+{
+    let (sender, receiver) = incoming_peer.conn_pair.split();
+    let c_peer_public_key = peer_public_key.clone();
+    let receiver = receiver
+        .map(|data| PeerListeners::proto_deserialize_checked(&data))
+        .take_while(|res| future::ready(res.is_ok()))
+        .map(Result::unwrap)
+        .map(move |peer_listeners| {
+            RelayServerEvent::PeerListeners((c_peer_public_key.clone(), peer_listeners))
+        })
+        .chain(stream::once(future::ready(RelayServerEvent::PeerClosed(
+            peer_public_key,
+        ))));
+    spawner
+        .spawn(async move {
+            // Keep the connection to the peer relay open while we receive announcements:
+            let _sender = sender;
+            let mut event_sender = event_sender;
+            event_sender
+                .send_all(&mut receiver.map(Ok))
+                .then(|_| future::ready(()))
+                .await
+        })
+        .map_err(|_| RelayServerError::SpawnError)
+}
 
-    Ok(())
+/// Register a listen connection from `public_key`.
+/// Incoming connection notifications are sent to the listener, and reject messages from the
+/// listener are forwarded to the relay server loop as events.
+fn register_listener<ES>(
+    listeners: &mut HashMap<PublicKey, Listener>,
+    public_key: PublicKey,
+    conn_pair: ConnPair<IncomingConnection, RejectConnection>,
+    event_sender: ES,
+    spawner: &impl Spawn,
+) where
+    ES: Sink<RelayServerEvent, Error = ()> + Unpin + Send + 'static,
+{
+    let (sender, receiver) = conn_pair.split();
+
+    // Change the sender to be an mpsc::Sender, so that we can use the
+    // try_send() function.
+    let (mpsc_sender, mpsc_receiver) = mpsc::channel::<IncomingConnection>(0);
+    spawner
+        .spawn(async move {
+            let mut sender = sender.sink_map_err(|_| ());
+            sender
+                .send_all(&mut mpsc_receiver.map(Ok))
+                .then(|_| future::ready(()))
+                .await
+        })
+        .unwrap();
+    let (close_sender, close_receiver) = oneshot::channel::<()>();
+    match listeners.get_mut(&public_key) {
+        // Listening again, while old tunnels are still open:
+        Some(listener) => {
+            listener.opt_sender = Some(mpsc_sender);
+            listener.opt_close_sender = Some(close_sender);
+            listener.idle_ticks = 0;
+        }
+        None => {
+            listeners.insert(public_key.clone(), Listener::new(mpsc_sender, close_sender));
+        }
+    }
+    let c_public_key = public_key.clone();
+    let receiver = receiver
+        .map(move |reject_connection| {
+            RelayServerEvent::ListenerMessage((c_public_key.clone(), reject_connection))
+        })
+        .chain(stream::once(future::ready(
+            RelayServerEvent::ListenerClosed(public_key),
+        )));
+    spawner
+        .spawn(async move {
+            let mut event_sender = event_sender;
+            let forward_fut = event_sender
+                .send_all(&mut receiver.map(Ok))
+                .then(|_| future::ready(()));
+            // Stop forwarding (And drop the listen connection) when the listener is closed by the
+            // relay server:
+            let _ = future::select(Box::pin(forward_fut), close_receiver).await;
+        })
+        .unwrap();
 }
 
-pub async fn relay_server_loop<S>(
+/// Close the listen connection of `public_key`. Pending half tunnels are discarded.
+/// The listener is forgotten once all of its tunnels are closed.
+fn close_listener(listeners: &mut HashMap<PublicKey, Listener>, public_key: &PublicKey) {
+    let listener = match listeners.get_mut(public_key) {
+        Some(listener) => listener,
+        None => return,
+    };
+    listener.opt_sender = None;
+    listener.opt_close_sender = None;
+    listener.half_tunnels = HashMap::new();
+    if listener.tunnels.is_empty() {
+        listeners.remove(public_key);
+    }
+}
+
+/// Serve a multiplexed listen connection from `public_key`.
+///
+/// Returns a plain listen connection pair, to be registered as a listener. Every incoming
+/// connection is accepted immediately, by opening a new stream over the multiplexed connection.
+/// The listening client rejects a connection by closing its stream.
+fn spawn_listen_mux<ES, S>(
+    public_key: PublicKey,
+    incoming_listen_mux: IncomingListenMux,
+    event_sender: ES,
+    spawner: S,
+) -> Result<ConnPair<IncomingConnection, RejectConnection>, RelayServerError>
+where
+    ES: Sink<RelayServerEvent, Error = ()> + Unpin + Send + 'static,
+    S: Spawn + Clone + Send + 'static,
+{
+    let (incoming_sender, incoming_receiver) = mpsc::channel::<IncomingConnection>(0);
+    let (reject_sender, reject_receiver) = mpsc::channel::<RejectConnection>(0);
+
+    let open_requests = incoming_receiver.map(|incoming_connection| incoming_connection.public_key);
+    // Every opened stream is handled as an Accept connection from the listener:
+    let accept_sender = event_sender.with(
+        move |(accept_public_key, conn_pair): (PublicKey, ConnPairVec)| {
+            future::ready(Ok::<_, ()>(RelayServerEvent::IncomingConn(IncomingConn {
+                public_key: public_key.clone(),
+                inner: IncomingConnInner::Accept(IncomingAccept {
+                    accept_public_key,
+                    conn_pair,
+                }),
+            })))
+        },
+    );
+
+    let c_spawner = spawner.clone();
+    spawner
+        .spawn(async move {
+            let _ = mux_relay_loop(
+                incoming_listen_mux.conn_pair,
+                open_requests,
+                accept_sender,
+                c_spawner,
+            )
+            .await
+            .map_err(|e| warn!("mux_relay_loop() error: {:?}", e));
+            // Closing the listen connection:
+            drop(reject_sender);
+        })
+        .map_err(|_| RelayServerError::SpawnError)?;
+
+    Ok(ConnPair::from_raw(incoming_sender, reject_receiver))
+}
+
+/// Collect the public keys currently listening on this relay.
+fn local_listeners(listeners: &HashMap<PublicKey, Listener>) -> PeerListeners {
+    PeerListeners {
+        public_keys: listeners
+            .iter()
+            .filter(|(_public_key, listener)| listener.opt_sender.is_some())
+            .map(|(public_key, _listener)| public_key.clone())
+            .collect(),
+    }
+}
+
+/// `timeouts` limit the lifetime of listen connections, pending connections and tunnels.
+/// `max_listeners` is the maximum amount of remote public keys that may listen at the same time.
+/// Every public key may have at most one listen connection.
+/// `max_tunnel_buffered_bytes` is the maximum amount of bytes buffered for one side of a tunnel.
+/// `peers` are the trusted peer relays, and their addresses. Connections to public keys that are
+/// not listening on this relay are forwarded to a peer relay they are listening on.
+/// Only public keys listening locally are announced to peer relays, and forwarded connections are
+/// never forwarded again, so a connection passes through at most two relays.
+/// `metrics` is updated with the state of the listeners and tunnels.
+pub async fn relay_server_loop<S, A, PC>(
     mut timer_client: TimerClient,
     incoming_conns: S,
-    half_tunnel_ticks: usize,
-    spawner: impl Spawn + Clone,
+    timeouts: RelayTimeouts,
+    max_listeners: usize,
+    max_tunnel_buffered_bytes: usize,
+    peers: HashMap<PublicKey, A>,
+    peer_connector: PC,
+    metrics: RelayMetrics,
+    spawner: impl Spawn + Clone + Send + 'static,
 ) -> Result<(), RelayServerError>
 where
     S: Stream<Item = IncomingConn> + Unpin + Send,
+    A: Clone + Send + Sync + 'static,
+    PC: FutTransform<Input = (PublicKey, A), Output = Option<ConnPairVec>> + Clone + Send + 'static,
 {
     let timer_stream = timer_client
         .request_timer_stream()
@@ -166,6 +472,24 @@ where
     let mut incoming_conns_closed = false;
     let mut listeners: HashMap<PublicKey, Listener> = HashMap::new();
 
+    // Announcements of our listeners to every peer relay:
+    let mut announcers: HashMap<PublicKey, mpsc::Sender<PeerListeners>> = HashMap::new();
+    for (peer_public_key, peer_address) in &peers {
+        let (announce_sender, announce_receiver) = mpsc::channel::<PeerListeners>(1);
+        spawner
+            .spawn(peer_announcer_loop(
+                peer_public_key.clone(),
+                peer_address.clone(),
+                peer_connector.clone(),
+                announce_receiver,
+            ))
+            .map_err(|_| RelayServerError::SpawnError)?;
+        announcers.insert(peer_public_key.clone(), announce_sender);
+    }
+    let mut announce_ticks: usize = 0;
+    // The public keys listening on every peer relay, as announced by the peer relay:
+    let mut peer_listeners: HashMap<PublicKey, HashSet<PublicKey>> = HashMap::new();
+
     while let Some(relay_server_event) = relay_server_events.next().await {
         let c_event_sender = event_sender.clone().sink_map_err(|_| ());
         match relay_server_event {
@@ -173,46 +497,52 @@ where
                 let IncomingConn { public_key, inner } = incoming_conn;
                 match inner {
                     IncomingConnInner::Listen(incoming_listen) => {
-                        if listeners.contains_key(&public_key) {
-                            continue; // Discard Listen connection
+                        if let Err(reject_listen) =
+                            check_listen(&listeners, &public_key, max_listeners)
+                        {
+                            // Discard Listen connection. Plain listen connections only carry
+                            // incoming connections, so the remote side only notices that the
+                            // connection was closed.
+                            warn!(
+                                "Listen connection from {:?} rejected: {:?}",
+                                public_key, reject_listen
+                            );
+                            metrics.quota_rejection(QuotaRejection::from(&reject_listen));
+                            continue;
                         }
-
-                        let (sender, receiver) = incoming_listen.conn_pair.split();
-
-                        // Change the sender to be an mpsc::Sender, so that we can use the
-                        // try_send() function.
-                        let (mpsc_sender, mpsc_receiver) = mpsc::channel::<IncomingConnection>(0);
-                        spawner
-                            .spawn(async move {
-                                let mut sender = sender.sink_map_err(|_| ());
-                                sender
-                                    .send_all(&mut mpsc_receiver.map(Ok))
-                                    .then(|_| future::ready(()))
-                                    .await
-                            })
-                            .unwrap();
-                        let listener = Listener::new(mpsc_sender);
-                        listeners.insert(public_key.clone(), listener);
-                        let c_public_key = public_key.clone();
-                        let receiver = receiver
-                            .map(move |reject_connection| {
-                                RelayServerEvent::ListenerMessage((
-                                    c_public_key.clone(),
-                                    reject_connection,
-                                ))
-                            })
-                            .chain(stream::once(future::ready(
-                                RelayServerEvent::ListenerClosed(public_key.clone()),
-                            )));
-                        spawner
-                            .spawn(async move {
-                                let mut c_event_sender = c_event_sender.sink_map_err(|_| ());
-                                c_event_sender
-                                    .send_all(&mut receiver.map(Ok))
-                                    .then(|_| future::ready(()))
-                                    .await
-                            })
-                            .unwrap();
+                        register_listener(
+                            &mut listeners,
+                            public_key,
+                            incoming_listen.conn_pair,
+                            c_event_sender,
+                            &spawner,
+                        );
+                    }
+                    IncomingConnInner::ListenMux(incoming_listen_mux) => {
+                        if let Err(reject_listen) =
+                            check_listen(&listeners, &public_key, max_listeners)
+                        {
+                            warn!(
+                                "ListenMux connection from {:?} rejected: {:?}",
+                                public_key, reject_listen
+                            );
+                            metrics.quota_rejection(QuotaRejection::from(&reject_listen));
+                            reject_listen_mux(incoming_listen_mux, reject_listen, &spawner)?;
+                            continue;
+                        }
+                        let conn_pair = spawn_listen_mux(
+                            public_key.clone(),
+                            incoming_listen_mux,
+                            c_event_sender.clone(),
+                            spawner.clone(),
+                        )?;
+                        register_listener(
+                            &mut listeners,
+                            public_key,
+                            conn_pair,
+                            c_event_sender,
+                            &spawner,
+                        );
                     }
                     IncomingConnInner::Accept(incoming_accept) => {
                         let tunnel_closed_sender = c_event_sender.with(|tunnel_closed| {
@@ -223,41 +553,106 @@ where
                             public_key.clone(),
                             incoming_accept,
                             tunnel_closed_sender,
+                            timer_client.clone(),
+                            timeouts.clone(),
+                            max_tunnel_buffered_bytes,
+                            metrics.clone(),
                             spawner.clone(),
                         )
                         .map_err(|e| warn!("handle_accept() error: {:?}", e));
                     }
                     IncomingConnInner::Connect(incoming_connect) => {
-                        let listener = match listeners.get_mut(&incoming_connect.connect_public_key)
+                        let connect_public_key = incoming_connect.connect_public_key;
+                        let conn_pair = match try_connect_local(
+                            &mut listeners,
+                            public_key.clone(),
+                            &connect_public_key,
+                            incoming_connect.conn_pair,
+                            timeouts.half_tunnel_ticks,
+                        ) {
+                            Ok(()) => continue,
+                            Err(conn_pair) => conn_pair,
+                        };
+
+                        // Not listening on this relay. Try to find a peer relay it listens on:
+                        let peer_public_key = match peer_listeners
+                            .iter()
+                            .find(|(_peer_public_key, public_keys)| {
+                                public_keys.contains(&connect_public_key)
+                            })
+                            .map(|(peer_public_key, _public_keys)| peer_public_key.clone())
                         {
-                            Some(listener) => listener,
+                            Some(peer_public_key) => peer_public_key,
                             None => continue, // Discard Connect connection
                         };
-                        if listener.half_tunnels.contains_key(&public_key)
-                            || listener.tunnels.contains(&public_key)
-                        {
+                        let peer_address = peers[&peer_public_key].clone();
+                        let fut = forward_connect(
+                            public_key,
+                            connect_public_key,
+                            conn_pair,
+                            peer_public_key,
+                            peer_address,
+                            peer_connector.clone(),
+                            timer_client.clone(),
+                            timeouts.clone(),
+                            max_tunnel_buffered_bytes,
+                            metrics.clone(),
+                            spawner.clone(),
+                        )
+                        .map_err(|e| warn!("forward_connect() error: {:?}", e))
+                        .map(|_| ());
+                        spawner
+                            .spawn(fut)
+                            .map_err(|_| RelayServerError::SpawnError)?;
+                    }
+                    IncomingConnInner::Peer(incoming_peer) => {
+                        if !peers.contains_key(&public_key) {
+                            // Discard connections from unknown relays:
+                            warn!("Peer connection from untrusted {:?} rejected", public_key);
                             continue;
                         }
-
-                        let half_tunnel = HalfTunnel {
-                            conn_pair: incoming_connect.conn_pair,
-                            ticks_to_close: half_tunnel_ticks,
-                        };
-                        if let Some(sender) = &mut listener.opt_sender {
-                            // Try to send a message to listener about new pending connection:
-                            if let Ok(()) = sender.try_send(IncomingConnection {
-                                public_key: public_key.clone(),
-                            }) {
-                                listener
-                                    .half_tunnels
-                                    .insert(public_key.clone(), half_tunnel);
-                            }
+                        register_peer(public_key, incoming_peer, c_event_sender, &spawner)?;
+                    }
+                    IncomingConnInner::ForwardConnect(incoming_forward_connect) => {
+                        if !peers.contains_key(&public_key) {
+                            warn!(
+                                "ForwardConnect connection from untrusted {:?} rejected",
+                                public_key
+                            );
+                            continue;
                         }
+                        // Forwarded connections are only handled locally, to avoid loops.
+                        let _ = try_connect_local(
+                            &mut listeners,
+                            incoming_forward_connect.init_public_key,
+                            &incoming_forward_connect.connect_public_key,
+                            incoming_forward_connect.conn_pair,
+                            timeouts.half_tunnel_ticks,
+                        );
+                    }
+                    IncomingConnInner::Ping(incoming_ping) => {
+                        let fut = ping_loop(
+                            incoming_ping,
+                            timer_client.clone(),
+                            timeouts.conn_timeout_ticks,
+                            MAX_RELAY_PINGS,
+                        )
+                        .map(move |ping_end| match ping_end {
+                            PingEnd::Closed | PingEnd::MaxPings => {}
+                            ping_end => warn!(
+                                "Ping connection from {:?} closed: {:?}",
+                                public_key, ping_end
+                            ),
+                        });
+                        spawner
+                            .spawn(fut)
+                            .map_err(|_| RelayServerError::SpawnError)?;
                     }
                 }
             }
             RelayServerEvent::IncomingConnsClosed => incoming_conns_closed = true,
             RelayServerEvent::TunnelClosed(tunnel_closed) => {
+                metrics.tunnel_closed();
                 let listener = match listeners.get_mut(&tunnel_closed.listen_public_key) {
                     Some(listener) => listener,
                     None => continue,
@@ -278,19 +673,32 @@ where
                     None => continue,
                 };
                 let _ = listener.half_tunnels.remove(&rejected_public_key);
+                listener.idle_ticks = 0;
             }
             RelayServerEvent::ListenerClosed(public_key) => {
-                let listener = match listeners.get_mut(&public_key) {
-                    Some(listener) => listener,
-                    None => continue,
-                };
-                listener.opt_sender = None;
-                listener.half_tunnels = HashMap::new();
-                if listener.tunnels.is_empty() {
-                    listeners.remove(&public_key);
-                }
+                close_listener(&mut listeners, &public_key)
+            }
+            RelayServerEvent::PeerListeners((peer_public_key, announced_listeners)) => {
+                let _ = peer_listeners.insert(
+                    peer_public_key,
+                    announced_listeners.public_keys.into_iter().collect(),
+                );
+            }
+            RelayServerEvent::PeerClosed(peer_public_key) => {
+                let _ = peer_listeners.remove(&peer_public_key);
             }
             RelayServerEvent::TimerTick => {
+                // Announce our listeners to peer relays:
+                announce_ticks = announce_ticks.saturating_sub(1);
+                if announce_ticks == 0 {
+                    announce_ticks = RELAY_PEER_ANNOUNCE_TICKS;
+                    let local_listeners = local_listeners(&listeners);
+                    for announce_sender in announcers.values_mut() {
+                        // If the previous announcement was not sent yet, we skip this one:
+                        let _ = announce_sender.try_send(local_listeners.clone());
+                    }
+                }
+
                 // Remove old half tunnels:
                 for listener in listeners.values_mut() {
                     listener
@@ -301,9 +709,38 @@ where
                             half_tunnel.ticks_to_close > 0
                         });
                 }
+
+                // Close idle listen connections:
+                if let Some(listen_idle_ticks) = timeouts.opt_listen_idle_ticks {
+                    let mut idle_listeners = Vec::new();
+                    for (public_key, listener) in &mut listeners {
+                        if listener.opt_sender.is_none() {
+                            continue;
+                        }
+                        listener.idle_ticks = listener.idle_ticks.saturating_add(1);
+                        if listener.idle_ticks >= listen_idle_ticks {
+                            idle_listeners.push(public_key.clone());
+                        }
+                    }
+                    for public_key in idle_listeners {
+                        warn!("Closing idle listen connection from {:?}", public_key);
+                        metrics.reaped(ReapReason::ListenIdle);
+                        close_listener(&mut listeners, &public_key);
+                    }
+                }
             }
             RelayServerEvent::TimerClosed => break,
         }
+        let num_listening = listeners
+            .values()
+            .filter(|listener| listener.opt_sender.is_some())
+            .count();
+        let num_half_tunnels = listeners
+            .values()
+            .map(|listener| listener.half_tunnels.len())
+            .sum();
+        metrics.set_listeners(num_listening, num_half_tunnels);
+
         if incoming_conns_closed && listeners.is_empty() {
             break;
         }
@@ -319,13 +756,25 @@ mod tests {
     use futures::executor::{LocalPool, ThreadPool};
     use futures::task::{Spawn, SpawnExt};
 
-    use crate::server::types::{IncomingAccept, IncomingConnect, IncomingListen};
+    use crate::mux::{mux_client_loop, MuxError};
+    use crate::server::types::{
+        IncomingAccept, IncomingConnect, IncomingForwardConnect, IncomingListen, IncomingPeer,
+    };
 
     use common::conn::ConnPair;
+    use common::dummy_connector::DummyConnector;
 
     use proto::crypto::PublicKey;
+    use proto::proto_ser::{ProtoDeserialize, ProtoSerialize};
+    use proto::relay::messages::{ForwardConnect, InitConnection};
     use timer::create_timer_incoming;
 
+    /// A peer connector for relay servers without peer relays. Never used.
+    fn dummy_peer_connector() -> DummyConnector<(PublicKey, u32), Option<ConnPairVec>> {
+        let (req_sender, _req_receiver) = mpsc::channel(0);
+        DummyConnector::new(req_sender)
+    }
+
     async fn task_relay_server_connect(
         spawner: impl Spawn + Clone + Send + 'static,
     ) -> Result<(), ()> {
@@ -335,12 +784,23 @@ mod tests {
 
         let (mut outgoing_conns, incoming_conns) = mpsc::channel::<_>(0);
 
-        let half_tunnel_ticks: usize = 16;
+        let timeouts = RelayTimeouts {
+            half_tunnel_ticks: 16,
+            ..RelayTimeouts::default()
+        };
+
+        let max_listeners: usize = 16;
+        let max_tunnel_buffered_bytes: usize = 0x10000;
 
         let fut_relay_server = relay_server_loop(
             timer_client,
             incoming_conns,
-            half_tunnel_ticks,
+            timeouts,
+            max_listeners,
+            max_tunnel_buffered_bytes,
+            HashMap::new(),
+            dummy_peer_connector(),
+            RelayMetrics::new(),
             spawner.clone(),
         );
 
@@ -440,6 +900,110 @@ mod tests {
             .unwrap();
     }
 
+    async fn task_relay_server_slow_tunnel_side(
+        spawner: impl Spawn + Clone + Send + 'static,
+    ) -> Result<(), ()> {
+        // Create a mock time service:
+        let (_tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (mut outgoing_conns, incoming_conns) = mpsc::channel::<_>(0);
+
+        let timeouts = RelayTimeouts {
+            half_tunnel_ticks: 16,
+            ..RelayTimeouts::default()
+        };
+
+        let max_listeners: usize = 16;
+        let max_tunnel_buffered_bytes: usize = 8;
+
+        let fut_relay_server = relay_server_loop(
+            timer_client,
+            incoming_conns,
+            timeouts,
+            max_listeners,
+            max_tunnel_buffered_bytes,
+            HashMap::new(),
+            dummy_peer_connector(),
+            RelayMetrics::new(),
+            spawner.clone(),
+        );
+
+        spawner
+            .spawn(fut_relay_server.map_err(|_e| ()).map(|_| ()))
+            .unwrap();
+
+        let (_a_ac, c_ac) = mpsc::channel::<RejectConnection>(0);
+        let (c_ca, mut a_ca) = mpsc::channel::<IncomingConnection>(0);
+        let (mut b_bc, c_bc) = mpsc::channel::<Vec<u8>>(0);
+        let (c_cb, _b_cb) = mpsc::channel::<Vec<u8>>(0);
+
+        let a_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let b_public_key = PublicKey::from(&[0xbb; PublicKey::len()]);
+
+        outgoing_conns
+            .send(IncomingConn {
+                public_key: a_public_key.clone(),
+                inner: IncomingConnInner::Listen(IncomingListen {
+                    conn_pair: ConnPair::from_raw(c_ca.sink_map_err(|_| ()), c_ac),
+                }),
+            })
+            .await
+            .unwrap();
+
+        outgoing_conns
+            .send(IncomingConn {
+                public_key: b_public_key.clone(),
+                inner: IncomingConnInner::Connect(IncomingConnect {
+                    connect_public_key: a_public_key.clone(),
+                    conn_pair: ConnPairVec::from_raw(c_cb.sink_map_err(|_| ()), c_bc),
+                }),
+            })
+            .await
+            .unwrap();
+
+        let msg = a_ca.next().await.unwrap();
+        assert_eq!(msg.public_key, b_public_key);
+
+        let (_a_ac1, c_ac1) = mpsc::channel::<Vec<u8>>(0);
+        let (c_ca1, mut a_ca1) = mpsc::channel::<Vec<u8>>(0);
+
+        outgoing_conns
+            .send(IncomingConn {
+                public_key: a_public_key.clone(),
+                inner: IncomingConnInner::Accept(IncomingAccept {
+                    accept_public_key: b_public_key.clone(),
+                    conn_pair: ConnPairVec::from_raw(c_ca1.sink_map_err(|_| ()), c_ac1),
+                }),
+            })
+            .await
+            .unwrap();
+
+        // a does not read the data sent by b. Eventually a is disconnected, and b can not send
+        // any more data:
+        let mut num_sent = 0;
+        while b_bc.send(vec![0u8; 4]).await.is_ok() {
+            num_sent += 1;
+        }
+
+        // a only receives the data that was delivered before it was disconnected:
+        let mut num_received = 0;
+        while a_ca1.next().await.is_some() {
+            num_received += 1;
+        }
+        assert!(num_received < num_sent);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_relay_server_slow_tunnel_side() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new()
+            .run_until(task_relay_server_slow_tunnel_side(thread_pool.clone()))
+            .unwrap();
+    }
+
     async fn task_relay_server_reject(
         spawner: impl Spawn + Clone + Send + 'static,
     ) -> Result<(), ()> {
@@ -449,12 +1013,23 @@ mod tests {
 
         let (mut outgoing_conns, incoming_conns) = mpsc::channel::<_>(0);
 
-        let half_tunnel_ticks: usize = 16;
+        let timeouts = RelayTimeouts {
+            half_tunnel_ticks: 16,
+            ..RelayTimeouts::default()
+        };
+
+        let max_listeners: usize = 16;
+        let max_tunnel_buffered_bytes: usize = 0x10000;
 
         let fut_relay_server = relay_server_loop(
             timer_client,
             incoming_conns,
-            half_tunnel_ticks,
+            timeouts,
+            max_listeners,
+            max_tunnel_buffered_bytes,
+            HashMap::new(),
+            dummy_peer_connector(),
+            RelayMetrics::new(),
             spawner.clone(),
         );
 
@@ -556,10 +1131,553 @@ mod tests {
             .unwrap();
     }
 
+    async fn task_relay_server_listen_limits(
+        spawner: impl Spawn + Clone + Send + 'static,
+    ) -> Result<(), ()> {
+        // Create a mock time service:
+        let (_tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (mut outgoing_conns, incoming_conns) = mpsc::channel::<_>(0);
+
+        let timeouts = RelayTimeouts {
+            half_tunnel_ticks: 16,
+            ..RelayTimeouts::default()
+        };
+        let max_listeners: usize = 1;
+        let max_tunnel_buffered_bytes: usize = 0x10000;
+        let metrics = RelayMetrics::new();
+
+        let fut_relay_server = relay_server_loop(
+            timer_client,
+            incoming_conns,
+            timeouts,
+            max_listeners,
+            max_tunnel_buffered_bytes,
+            HashMap::new(),
+            dummy_peer_connector(),
+            metrics.clone(),
+            spawner.clone(),
+        );
+
+        spawner
+            .spawn(fut_relay_server.map_err(|_e| ()).map(|_| ()))
+            .unwrap();
+
+        let a_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let b_public_key = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let c_public_key = PublicKey::from(&[0xcc; PublicKey::len()]);
+
+        // A listens:
+        let (_a_ac, c_ac) = mpsc::channel::<RejectConnection>(0);
+        let (c_ca, mut a_ca) = mpsc::channel::<IncomingConnection>(0);
+        let incoming_conn = IncomingConn {
+            public_key: a_public_key.clone(),
+            inner: IncomingConnInner::Listen(IncomingListen {
+                conn_pair: ConnPair::from_raw(c_ca.sink_map_err(|_| ()), c_ac),
+            }),
+        };
+        outgoing_conns.send(incoming_conn).await.unwrap();
+
+        // A attempts to listen again. The second listen connection is closed:
+        let (_a_ac2, c_ac2) = mpsc::channel::<RejectConnection>(0);
+        let (c_ca2, mut a_ca2) = mpsc::channel::<IncomingConnection>(0);
+        let incoming_conn = IncomingConn {
+            public_key: a_public_key.clone(),
+            inner: IncomingConnInner::Listen(IncomingListen {
+                conn_pair: ConnPair::from_raw(c_ca2.sink_map_err(|_| ()), c_ac2),
+            }),
+        };
+        outgoing_conns.send(incoming_conn).await.unwrap();
+        assert!(a_ca2.next().await.is_none());
+
+        // B attempts to listen, but there are already too many listeners:
+        let (_b_bc, c_bc) = mpsc::channel::<RejectConnection>(0);
+        let (c_cb, mut b_cb) = mpsc::channel::<IncomingConnection>(0);
+        let incoming_conn = IncomingConn {
+            public_key: b_public_key.clone(),
+            inner: IncomingConnInner::Listen(IncomingListen {
+                conn_pair: ConnPair::from_raw(c_cb.sink_map_err(|_| ()), c_bc),
+            }),
+        };
+        outgoing_conns.send(incoming_conn).await.unwrap();
+        assert!(b_cb.next().await.is_none());
+
+        // The first listen connection of A still works:
+        let (_c_c, c_c_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (c_c_sender, _c_c) = mpsc::channel::<Vec<u8>>(0);
+        let incoming_conn = IncomingConn {
+            public_key: c_public_key.clone(),
+            inner: IncomingConnInner::Connect(IncomingConnect {
+                connect_public_key: a_public_key.clone(),
+                conn_pair: ConnPairVec::from_raw(c_c_sender.sink_map_err(|_| ()), c_c_receiver),
+            }),
+        };
+        outgoing_conns.send(incoming_conn).await.unwrap();
+        assert_eq!(
+            a_ca.next().await.unwrap(),
+            IncomingConnection {
+                public_key: c_public_key
+            }
+        );
+
+        let rendered = metrics.render_prometheus();
+        let lines = rendered.lines().collect::<Vec<_>>();
+        assert!(lines.contains(&"relay_quota_rejections_total{reason=\"already_listening\"} 1"));
+        assert!(lines.contains(&"relay_quota_rejections_total{reason=\"too_many_listeners\"} 1"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_relay_server_listen_limits() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new()
+            .run_until(task_relay_server_listen_limits(thread_pool.clone()))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_check_listen_relisten() {
+        let a_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let b_public_key = PublicKey::from(&[0xbb; PublicKey::len()]);
+
+        let mut listeners = HashMap::new();
+        // The listen connection of A was closed, but one of its tunnels is still open:
+        let (sender, _receiver) = mpsc::channel(0);
+        let (close_sender, _close_receiver) = oneshot::channel();
+        let mut listener = Listener::new(sender, close_sender);
+        listener.opt_sender = None;
+        listener.opt_close_sender = None;
+        listener.tunnels.insert(b_public_key.clone());
+        listeners.insert(a_public_key.clone(), listener);
+
+        assert_eq!(check_listen(&listeners, &a_public_key, 1), Ok(()));
+
+        // B listens:
+        let (sender, _receiver) = mpsc::channel(0);
+        let (close_sender, _close_receiver) = oneshot::channel();
+        listeners.insert(b_public_key.clone(), Listener::new(sender, close_sender));
+
+        // A may listen again only while there is room for another listener:
+        assert_eq!(
+            check_listen(&listeners, &a_public_key, 1),
+            Err(RejectListen::TooManyListeners)
+        );
+        assert_eq!(check_listen(&listeners, &a_public_key, 2), Ok(()));
+        assert_eq!(
+            check_listen(&listeners, &b_public_key, 2),
+            Err(RejectListen::AlreadyListening)
+        );
+    }
+
+    async fn task_relay_server_reject_listen_mux(
+        spawner: impl Spawn + Clone + Send + 'static,
+    ) -> Result<(), ()> {
+        // Create a mock time service:
+        let (_tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (mut outgoing_conns, incoming_conns) = mpsc::channel::<_>(0);
+
+        let max_listeners: usize = 1;
+        let max_tunnel_buffered_bytes: usize = 0x10000;
+
+        let fut_relay_server = relay_server_loop(
+            timer_client,
+            incoming_conns,
+            RelayTimeouts::default(),
+            max_listeners,
+            max_tunnel_buffered_bytes,
+            HashMap::new(),
+            dummy_peer_connector(),
+            RelayMetrics::new(),
+            spawner.clone(),
+        );
+
+        spawner
+            .spawn(fut_relay_server.map_err(|_e| ()).map(|_| ()))
+            .unwrap();
+
+        let a_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let b_public_key = PublicKey::from(&[0xbb; PublicKey::len()]);
+
+        // A listens:
+        let (_a_ac, c_ac) = mpsc::channel::<RejectConnection>(0);
+        let (c_ca, _a_ca) = mpsc::channel::<IncomingConnection>(0);
+        let incoming_conn = IncomingConn {
+            public_key: a_public_key.clone(),
+            inner: IncomingConnInner::Listen(IncomingListen {
+                conn_pair: ConnPair::from_raw(c_ca.sink_map_err(|_| ()), c_ac),
+            }),
+        };
+        outgoing_conns.send(incoming_conn).await.unwrap();
+
+        // B attempts to listen using a multiplexed connection, but there are already too many
+        // listeners:
+        let (b_bc, c_bc) = mpsc::channel::<Vec<u8>>(0);
+        let (c_cb, b_cb) = mpsc::channel::<Vec<u8>>(0);
+        let incoming_conn = IncomingConn {
+            public_key: b_public_key.clone(),
+            inner: IncomingConnInner::ListenMux(IncomingListenMux {
+                conn_pair: ConnPairVec::from_raw(c_cb, c_bc),
+            }),
+        };
+        outgoing_conns.send(incoming_conn).await.unwrap();
+
+        // B is told why it was rejected:
+        let (streams_sender, _b_streams) = mpsc::channel(0);
+        let res = mux_client_loop(
+            ConnPairVec::from_raw(b_bc, b_cb),
+            streams_sender,
+            spawner.clone(),
+        )
+        .await;
+        match res {
+            Err(MuxError::ListenRejected(RejectListen::TooManyListeners)) => {}
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_relay_server_reject_listen_mux() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new()
+            .run_until(task_relay_server_reject_listen_mux(thread_pool.clone()))
+            .unwrap();
+    }
+
+    async fn task_relay_server_listen_mux(
+        spawner: impl Spawn + Clone + Send + 'static,
+    ) -> Result<(), ()> {
+        // Create a mock time service:
+        let (_tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (mut outgoing_conns, incoming_conns) = mpsc::channel::<_>(0);
+
+        let timeouts = RelayTimeouts {
+            half_tunnel_ticks: 16,
+            ..RelayTimeouts::default()
+        };
+        let max_listeners: usize = 16;
+        let max_tunnel_buffered_bytes: usize = 0x10000;
+
+        let fut_relay_server = relay_server_loop(
+            timer_client,
+            incoming_conns,
+            timeouts,
+            max_listeners,
+            max_tunnel_buffered_bytes,
+            HashMap::new(),
+            dummy_peer_connector(),
+            RelayMetrics::new(),
+            spawner.clone(),
+        );
+
+        spawner
+            .spawn(fut_relay_server.map_err(|_e| ()).map(|_| ()))
+            .unwrap();
+
+        let a_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let b_public_key = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let c_public_key = PublicKey::from(&[0xcc; PublicKey::len()]);
+
+        // A listens using a multiplexed connection:
+        let (a_ac, c_ac) = mpsc::channel::<Vec<u8>>(0);
+        let (c_ca, a_ca) = mpsc::channel::<Vec<u8>>(0);
+        let incoming_conn = IncomingConn {
+            public_key: a_public_key.clone(),
+            inner: IncomingConnInner::ListenMux(IncomingListenMux {
+                conn_pair: ConnPairVec::from_raw(c_ca, c_ac),
+            }),
+        };
+        outgoing_conns.send(incoming_conn).await.unwrap();
+
+        let (streams_sender, mut a_streams) = mpsc::channel(0);
+        spawner
+            .spawn(
+                mux_client_loop(
+                    ConnPairVec::from_raw(a_ac, a_ca),
+                    streams_sender,
+                    spawner.clone(),
+                )
+                .map(|_| ()),
+            )
+            .unwrap();
+
+        // B and C connect to A:
+        let (mut b_bc, c_bc) = mpsc::channel::<Vec<u8>>(0);
+        let (c_cb, mut b_cb) = mpsc::channel::<Vec<u8>>(0);
+        let incoming_conn = IncomingConn {
+            public_key: b_public_key.clone(),
+            inner: IncomingConnInner::Connect(IncomingConnect {
+                connect_public_key: a_public_key.clone(),
+                conn_pair: ConnPairVec::from_raw(c_cb, c_bc),
+            }),
+        };
+        outgoing_conns.send(incoming_conn).await.unwrap();
+
+        let (public_key, a_stream_b) = a_streams.next().await.unwrap();
+        assert_eq!(public_key, b_public_key);
+
+        let (mut d_dc, c_dc) = mpsc::channel::<Vec<u8>>(0);
+        let (c_cd, mut d_cd) = mpsc::channel::<Vec<u8>>(0);
+        let incoming_conn = IncomingConn {
+            public_key: c_public_key.clone(),
+            inner: IncomingConnInner::Connect(IncomingConnect {
+                connect_public_key: a_public_key.clone(),
+                conn_pair: ConnPairVec::from_raw(c_cd, c_dc),
+            }),
+        };
+        outgoing_conns.send(incoming_conn).await.unwrap();
+
+        let (public_key, a_stream_c) = a_streams.next().await.unwrap();
+        assert_eq!(public_key, c_public_key);
+
+        // Both connections are tunneled over the single listen connection of A:
+        let (mut a_sender_b, mut a_receiver_b) = a_stream_b.split();
+        let (mut a_sender_c, mut a_receiver_c) = a_stream_c.split();
+
+        a_sender_b.send(vec![1, 2, 3]).await.unwrap();
+        assert_eq!(b_cb.next().await.unwrap(), vec![1, 2, 3]);
+
+        d_dc.send(vec![4, 5]).await.unwrap();
+        assert_eq!(a_receiver_c.next().await.unwrap(), vec![4, 5]);
+
+        a_sender_c.send(vec![6]).await.unwrap();
+        assert_eq!(d_cd.next().await.unwrap(), vec![6]);
+
+        b_bc.send(vec![3, 2, 1]).await.unwrap();
+        assert_eq!(a_receiver_b.next().await.unwrap(), vec![3, 2, 1]);
+
+        // If B disconnects, A's stream is closed:
+        drop(b_bc);
+        assert!(a_receiver_b.next().await.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_relay_server_listen_mux() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new()
+            .run_until(task_relay_server_listen_mux(thread_pool.clone()))
+            .unwrap();
+    }
+
     // TODO: Add tests:
     // - Timeout of half tunnels
     //      (Do some action first, to make sure timer_stream was already obtained).
     // - Graceful shutdown if incoming_conns is closed.
     // - Duplicate connections should be denied. (Same (initiator_pk, listener_pk) pair).
     // - Tunnel keeps working even if listener is disconnected.
+
+    async fn task_relay_server_forward_connect(
+        spawner: impl Spawn + Clone + Send + 'static,
+    ) -> Result<(), ()> {
+        /*
+         * a --> relay1 --> relay2 <-- b
+         *
+         * b listens on relay2. relay2 announces b to relay1, and a connects to b through relay1.
+         */
+        let a_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let b_public_key = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let relay1_public_key = PublicKey::from(&[0x11; PublicKey::len()]);
+        let relay2_public_key = PublicKey::from(&[0x22; PublicKey::len()]);
+
+        let timeouts = RelayTimeouts {
+            half_tunnel_ticks: 16,
+            ..RelayTimeouts::default()
+        };
+        let max_listeners: usize = 16;
+        let max_tunnel_buffered_bytes: usize = 0x10000;
+
+        // relay1:
+        let (_tick_sender1, tick_receiver1) = mpsc::channel::<()>(0);
+        let timer_client1 = create_timer_incoming(tick_receiver1, spawner.clone()).unwrap();
+        let (mut outgoing_conns1, incoming_conns1) = mpsc::channel::<_>(0);
+        let (req_sender1, mut req_receiver1) = mpsc::channel(0);
+        let mut peers1 = HashMap::new();
+        peers1.insert(relay2_public_key.clone(), 2u32);
+
+        let fut_relay_server1 = relay_server_loop(
+            timer_client1,
+            incoming_conns1,
+            timeouts.clone(),
+            max_listeners,
+            max_tunnel_buffered_bytes,
+            peers1,
+            DummyConnector::new(req_sender1),
+            RelayMetrics::new(),
+            spawner.clone(),
+        );
+        spawner
+            .spawn(fut_relay_server1.map_err(|_e| ()).map(|_| ()))
+            .unwrap();
+
+        // relay2:
+        let (_tick_sender2, tick_receiver2) = mpsc::channel::<()>(0);
+        let timer_client2 = create_timer_incoming(tick_receiver2, spawner.clone()).unwrap();
+        let (mut outgoing_conns2, incoming_conns2) = mpsc::channel::<_>(0);
+        let mut peers2 = HashMap::new();
+        peers2.insert(relay1_public_key.clone(), 1u32);
+
+        let fut_relay_server2 = relay_server_loop(
+            timer_client2,
+            incoming_conns2,
+            timeouts,
+            max_listeners,
+            max_tunnel_buffered_bytes,
+            peers2,
+            dummy_peer_connector(),
+            RelayMetrics::new(),
+            spawner.clone(),
+        );
+        spawner
+            .spawn(fut_relay_server2.map_err(|_e| ()).map(|_| ()))
+            .unwrap();
+
+        // b listens on relay2:
+        let (_b_reject_sender, c_b_reject_receiver) = mpsc::channel::<RejectConnection>(0);
+        let (c_b_incoming_sender, mut b_incoming_receiver) = mpsc::channel::<IncomingConnection>(0);
+        outgoing_conns2
+            .send(IncomingConn {
+                public_key: b_public_key.clone(),
+                inner: IncomingConnInner::Listen(IncomingListen {
+                    conn_pair: ConnPair::from_raw(
+                        c_b_incoming_sender.sink_map_err(|_| ()),
+                        c_b_reject_receiver,
+                    ),
+                }),
+            })
+            .await
+            .unwrap();
+
+        // relay2 announces b to relay1:
+        let (mut announce_sender, c_announce_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (c_announce_sender, _announce_receiver) = mpsc::channel::<Vec<u8>>(0);
+        outgoing_conns1
+            .send(IncomingConn {
+                public_key: relay2_public_key.clone(),
+                inner: IncomingConnInner::Peer(IncomingPeer {
+                    conn_pair: ConnPairVec::from_raw(
+                        c_announce_sender.sink_map_err(|_| ()),
+                        c_announce_receiver,
+                    ),
+                }),
+            })
+            .await
+            .unwrap();
+        let peer_listeners = PeerListeners {
+            public_keys: vec![b_public_key.clone()],
+        };
+        announce_sender
+            .send(peer_listeners.proto_serialize())
+            .await
+            .unwrap();
+
+        // a connects to b through relay1. Connections are discarded until relay1 processes the
+        // announcement:
+        let (mut a_sender, mut a_receiver, conn_request) = loop {
+            let (a_sender, c_a_receiver) = mpsc::channel::<Vec<u8>>(0);
+            let (c_a_sender, mut a_receiver) = mpsc::channel::<Vec<u8>>(0);
+            outgoing_conns1
+                .send(IncomingConn {
+                    public_key: a_public_key.clone(),
+                    inner: IncomingConnInner::Connect(IncomingConnect {
+                        connect_public_key: b_public_key.clone(),
+                        conn_pair: ConnPairVec::from_raw(
+                            c_a_sender.sink_map_err(|_| ()),
+                            c_a_receiver,
+                        ),
+                    }),
+                })
+                .await
+                .unwrap();
+
+            let opt_conn_request =
+                match future::select(a_receiver.next(), req_receiver1.next()).await {
+                    future::Either::Left((None, _)) => None,
+                    future::Either::Right((Some(conn_request), _)) => Some(conn_request),
+                    _ => unreachable!(),
+                };
+            if let Some(conn_request) = opt_conn_request {
+                break (a_sender, a_receiver, conn_request);
+            }
+        };
+
+        // relay1 connects to relay2:
+        assert_eq!(conn_request.address, (relay2_public_key.clone(), 2u32));
+        let (c1_sender, mut c2_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (c2_sender, c1_receiver) = mpsc::channel::<Vec<u8>>(0);
+        conn_request.reply(Some(ConnPairVec::from_raw(
+            c1_sender.sink_map_err(|_| ()),
+            c1_receiver,
+        )));
+
+        let data = c2_receiver.next().await.unwrap();
+        assert_eq!(
+            InitConnection::proto_deserialize(&data).unwrap(),
+            InitConnection::ForwardConnect(ForwardConnect {
+                init_public_key: a_public_key.clone(),
+                connect_public_key: b_public_key.clone(),
+            })
+        );
+        outgoing_conns2
+            .send(IncomingConn {
+                public_key: relay1_public_key.clone(),
+                inner: IncomingConnInner::ForwardConnect(IncomingForwardConnect {
+                    init_public_key: a_public_key.clone(),
+                    connect_public_key: b_public_key.clone(),
+                    conn_pair: ConnPairVec::from_raw(c2_sender.sink_map_err(|_| ()), c2_receiver),
+                }),
+            })
+            .await
+            .unwrap();
+
+        // b is notified about a's connection, and accepts it:
+        assert_eq!(
+            b_incoming_receiver.next().await.unwrap(),
+            IncomingConnection {
+                public_key: a_public_key.clone()
+            }
+        );
+        let (mut b_sender, c_b_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (c_b_sender, mut b_receiver) = mpsc::channel::<Vec<u8>>(0);
+        outgoing_conns2
+            .send(IncomingConn {
+                public_key: b_public_key.clone(),
+                inner: IncomingConnInner::Accept(IncomingAccept {
+                    accept_public_key: a_public_key.clone(),
+                    conn_pair: ConnPairVec::from_raw(c_b_sender.sink_map_err(|_| ()), c_b_receiver),
+                }),
+            })
+            .await
+            .unwrap();
+
+        a_sender.send(vec![1, 2, 3]).await.unwrap();
+        assert_eq!(b_receiver.next().await.unwrap(), vec![1, 2, 3]);
+
+        b_sender.send(vec![4, 3, 2, 1]).await.unwrap();
+        assert_eq!(a_receiver.next().await.unwrap(), vec![4, 3, 2, 1]);
+
+        // Closing one side closes the other side:
+        drop(b_sender);
+        assert!(a_receiver.next().await.is_none());
+
+        drop(a_sender);
+        drop(b_receiver);
+        Ok(())
+    }
+
+    #[test]
+    fn test_relay_server_forward_connect() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new()
+            .run_until(task_relay_server_forward_connect(thread_pool.clone()))
+            .unwrap();
+    }
 }
@@ -0,0 +1,246 @@
+use futures::task::Spawn;
+use futures::{future, stream, StreamExt};
+
+use common::budget::{budget_sender, BudgetError};
+use common::conn::{BoxStream, ConnPairVec};
+use common::select_streams::select_streams;
+
+use timer::TimerClient;
+
+use crate::metrics::{QuotaRejection, ReapReason, RelayMetrics};
+
+use super::types::RelayTimeouts;
+
+/// One of the two sides of a tunnel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunnelSide {
+    /// The side that initiated the connection
+    Init,
+    /// The listening side, that accepted the connection
+    Listen,
+}
+
+/// The reason a tunnel was closed
+#[derive(Debug, PartialEq, Eq)]
+pub enum TunnelEnd {
+    /// Both sides closed the tunnel
+    Closed,
+    /// A side did not keep up with the other side, and was disconnected
+    SlowSide((TunnelSide, BudgetError)),
+    /// The tunnel outlived its limits
+    Reaped(ReapReason),
+    RequestTimerStreamError,
+    TimerClosed,
+}
+
+#[derive(Debug)]
+enum TunnelEvent {
+    Data((TunnelSide, Vec<u8>)),
+    SideClosed(TunnelSide),
+    TimerTick,
+    TimerClosed,
+}
+
+/// Forward data between the two sides of a tunnel, until both sides are closed or the tunnel
+/// outlives the limits given in `timeouts`.
+///
+/// Data is forwarded without waiting for the receiving side. A side that does not keep up
+/// with the other side is disconnected, so that a stalled peer can not make us buffer an
+/// unbounded amount of data.
+pub async fn tunnel_loop(
+    init_conn_pair: ConnPairVec,
+    listen_conn_pair: ConnPairVec,
+    mut timer_client: TimerClient,
+    timeouts: RelayTimeouts,
+    max_tunnel_buffered_bytes: usize,
+    metrics: RelayMetrics,
+    spawner: impl Spawn,
+) -> TunnelEnd {
+    let timer_stream = match timer_client.request_timer_stream().await {
+        Ok(timer_stream) => timer_stream,
+        Err(_) => return TunnelEnd::RequestTimerStreamError,
+    };
+    let timer_stream = timer_stream
+        .map(|_| TunnelEvent::TimerTick)
+        .chain(stream::once(future::ready(TunnelEvent::TimerClosed)));
+
+    let (init_sender, init_receiver) = init_conn_pair.split();
+    let (listen_sender, listen_receiver) = listen_conn_pair.split();
+
+    // Dropping a sender lets its side know that no more data will arrive:
+    let mut opt_init_sender = Some(budget_sender(
+        init_sender,
+        max_tunnel_buffered_bytes,
+        Vec::len,
+        &spawner,
+    ));
+    let mut opt_listen_sender = Some(budget_sender(
+        listen_sender,
+        max_tunnel_buffered_bytes,
+        Vec::len,
+        &spawner,
+    ));
+
+    let init_receiver = init_receiver
+        .map(|data| TunnelEvent::Data((TunnelSide::Init, data)))
+        .chain(stream::once(future::ready(TunnelEvent::SideClosed(
+            TunnelSide::Init,
+        ))));
+    let listen_receiver = listen_receiver
+        .map(|data| TunnelEvent::Data((TunnelSide::Listen, data)))
+        .chain(stream::once(future::ready(TunnelEvent::SideClosed(
+            TunnelSide::Listen,
+        ))));
+
+    let mut events = select_streams![init_receiver, listen_receiver, timer_stream];
+
+    let mut idle_ticks: usize = 0;
+    let mut lifetime_ticks: usize = 0;
+    // Ticks passed since one of the sides was closed:
+    let mut opt_half_open_ticks: Option<usize> = None;
+
+    while let Some(event) = events.next().await {
+        match event {
+            TunnelEvent::Data((side, data)) => {
+                idle_ticks = 0;
+                let (opt_sender, dest_side) = match side {
+                    TunnelSide::Init => (&mut opt_listen_sender, TunnelSide::Listen),
+                    TunnelSide::Listen => (&mut opt_init_sender, TunnelSide::Init),
+                };
+                let sender = match opt_sender {
+                    Some(sender) => sender,
+                    None => continue, // The receiving side was already closed
+                };
+                let data_len = data.len();
+                match sender.try_send(data) {
+                    Ok(()) => metrics.bytes_forwarded(data_len),
+                    Err(BudgetError::Closed) => *opt_sender = None,
+                    Err(budget_error) => {
+                        metrics.quota_rejection(QuotaRejection::SlowTunnelSide);
+                        return TunnelEnd::SlowSide((dest_side, budget_error));
+                    }
+                }
+            }
+            TunnelEvent::SideClosed(side) => {
+                match side {
+                    TunnelSide::Init => opt_listen_sender = None,
+                    TunnelSide::Listen => opt_init_sender = None,
+                }
+                if opt_half_open_ticks.is_some() {
+                    // Both sides are closed:
+                    return TunnelEnd::Closed;
+                }
+                opt_half_open_ticks = Some(0);
+            }
+            TunnelEvent::TimerTick => {
+                idle_ticks = idle_ticks.saturating_add(1);
+                lifetime_ticks = lifetime_ticks.saturating_add(1);
+
+                if let Some(half_open_ticks) = &mut opt_half_open_ticks {
+                    *half_open_ticks = half_open_ticks.saturating_add(1);
+                }
+
+                let opt_reap_reason = if opt_half_open_ticks.map_or(false, |half_open_ticks| {
+                    half_open_ticks >= timeouts.half_open_ticks
+                }) {
+                    Some(ReapReason::HalfOpen)
+                } else if idle_ticks >= timeouts.tunnel_idle_ticks {
+                    Some(ReapReason::TunnelIdle)
+                } else if timeouts
+                    .opt_tunnel_max_ticks
+                    .map_or(false, |tunnel_max_ticks| lifetime_ticks >= tunnel_max_ticks)
+                {
+                    Some(ReapReason::TunnelLifetime)
+                } else {
+                    None
+                };
+
+                if let Some(reap_reason) = opt_reap_reason {
+                    metrics.reaped(reap_reason);
+                    return TunnelEnd::Reaped(reap_reason);
+                }
+            }
+            TunnelEvent::TimerClosed => return TunnelEnd::TimerClosed,
+        }
+    }
+    // We should never get here, because the timer stream ends with a `TimerClosed` event:
+    TunnelEnd::TimerClosed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::channel::mpsc;
+    use futures::executor::{LocalPool, ThreadPool};
+    use futures::task::SpawnExt;
+    use futures::{FutureExt, SinkExt};
+
+    use timer::create_timer_incoming;
+
+    async fn task_tunnel_loop_reap(spawner: impl Spawn + Clone + Send + 'static) {
+        let (mut tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (mut a_sender, c_from_a) = mpsc::channel::<Vec<u8>>(0);
+        let (c_to_a, mut a_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (b_sender, c_from_b) = mpsc::channel::<Vec<u8>>(0);
+        let (c_to_b, mut b_receiver) = mpsc::channel::<Vec<u8>>(0);
+
+        let timeouts = RelayTimeouts {
+            tunnel_idle_ticks: 16,
+            opt_tunnel_max_ticks: Some(100),
+            half_open_ticks: 4,
+            ..RelayTimeouts::default()
+        };
+        let metrics = RelayMetrics::new();
+
+        let (tunnel_end_sender, tunnel_end_receiver) = futures::channel::oneshot::channel();
+        let tunnel_fut = tunnel_loop(
+            ConnPairVec::from_raw(c_to_a.sink_map_err(|_| ()), c_from_a),
+            ConnPairVec::from_raw(c_to_b.sink_map_err(|_| ()), c_from_b),
+            timer_client,
+            timeouts,
+            0x100,
+            metrics.clone(),
+            spawner.clone(),
+        );
+        spawner
+            .spawn(tunnel_fut.map(|tunnel_end| {
+                let _ = tunnel_end_sender.send(tunnel_end);
+            }))
+            .unwrap();
+
+        // Data is forwarded, and resets the idle ticks:
+        for _ in 0..6 {
+            tick_sender.send(()).await.unwrap();
+        }
+        a_sender.send(vec![1, 2, 3]).await.unwrap();
+        assert_eq!(b_receiver.next().await.unwrap(), vec![1, 2, 3]);
+        for _ in 0..6 {
+            tick_sender.send(()).await.unwrap();
+        }
+
+        // b closes its side. a is notified, but keeps the tunnel half open:
+        drop(b_sender);
+        assert!(a_receiver.next().await.is_none());
+
+        for _ in 0..4 {
+            tick_sender.send(()).await.unwrap();
+        }
+        assert_eq!(
+            tunnel_end_receiver.await.unwrap(),
+            TunnelEnd::Reaped(ReapReason::HalfOpen)
+        );
+
+        // The whole tunnel was closed:
+        assert!(b_receiver.next().await.is_none());
+        assert!(a_sender.send(vec![4, 5]).await.is_err());
+    }
+
+    #[test]
+    fn test_tunnel_loop_reap() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new().run_until(task_tunnel_loop_reap(thread_pool.clone()));
+    }
+}
@@ -1,12 +1,52 @@
 use common::conn::{ConnPair, ConnPairVec};
 
+use proto::consts::{
+    CONN_TIMEOUT_TICKS, KEEPALIVE_TICKS, RELAY_HALF_OPEN_TICKS, RELAY_TUNNEL_IDLE_TICKS,
+};
 use proto::crypto::PublicKey;
-use proto::relay::messages::{IncomingConnection, RejectConnection};
+use proto::relay::messages::{IncomingConnection, RejectConnection, RelayPing};
+
+/// Lifetime limits for the connections of a relay server, measured in timer ticks.
+#[derive(Debug, Clone)]
+pub struct RelayTimeouts {
+    /// Maximum time a connection may take to identify its purpose. Also the maximum time
+    /// between two pings of a ping connection.
+    pub conn_timeout_ticks: usize,
+    /// Maximum time a Connect connection waits for the listening side to accept it
+    pub half_tunnel_ticks: usize,
+    /// Maximum time a listen connection may go without any incoming connection, accepted
+    /// connection or rejection. `None` means that listen connections are never idle.
+    pub opt_listen_idle_ticks: Option<usize>,
+    /// Maximum time no data may pass through a tunnel, in either direction
+    pub tunnel_idle_ticks: usize,
+    /// Maximum lifetime of a tunnel. `None` means that a tunnel may live forever.
+    pub opt_tunnel_max_ticks: Option<usize>,
+    /// Maximum time a tunnel stays open after one of its sides was closed
+    pub half_open_ticks: usize,
+}
+
+impl Default for RelayTimeouts {
+    fn default() -> Self {
+        RelayTimeouts {
+            conn_timeout_ticks: CONN_TIMEOUT_TICKS,
+            half_tunnel_ticks: KEEPALIVE_TICKS,
+            opt_listen_idle_ticks: None,
+            tunnel_idle_ticks: RELAY_TUNNEL_IDLE_TICKS,
+            opt_tunnel_max_ticks: None,
+            half_open_ticks: RELAY_HALF_OPEN_TICKS,
+        }
+    }
+}
 
 pub struct IncomingListen {
     pub conn_pair: ConnPair<IncomingConnection, RejectConnection>,
 }
 
+/// A listen connection that multiplexes all the accepted connections over itself.
+pub struct IncomingListenMux {
+    pub conn_pair: ConnPairVec,
+}
+
 pub struct IncomingAccept {
     pub accept_public_key: PublicKey,
     pub conn_pair: ConnPairVec,
@@ -17,10 +57,31 @@ pub struct IncomingConnect {
     pub conn_pair: ConnPairVec,
 }
 
+/// A connection from a peer relay, announcing the public keys listening on it.
+pub struct IncomingPeer {
+    pub conn_pair: ConnPairVec,
+}
+
+/// A connection forwarded by a peer relay on behalf of one of its clients.
+pub struct IncomingForwardConnect {
+    pub init_public_key: PublicKey,
+    pub connect_public_key: PublicKey,
+    pub conn_pair: ConnPairVec,
+}
+
+/// A connection measuring the round trip time to the relay.
+pub struct IncomingPing {
+    pub conn_pair: ConnPair<RelayPing, RelayPing>,
+}
+
 pub enum IncomingConnInner {
     Listen(IncomingListen),
+    ListenMux(IncomingListenMux),
     Accept(IncomingAccept),
     Connect(IncomingConnect),
+    Peer(IncomingPeer),
+    ForwardConnect(IncomingForwardConnect),
+    Ping(IncomingPing),
 }
 
 pub struct IncomingConn {
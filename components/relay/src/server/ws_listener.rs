@@ -0,0 +1,574 @@
+use std::marker::Unpin;
+
+use futures::channel::mpsc;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use futures::task::{Spawn, SpawnExt};
+use futures::{SinkExt, Stream, StreamExt};
+
+use common::conn::ConnPairVec;
+
+use crypto::hash::sha1_legacy;
+
+use timer::utils::future_timeout;
+use timer::TimerClient;
+
+/// Magic value used to calculate Sec-WebSocket-Accept (RFC 6455, section 1.3)
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Maximum size of the HTTP upgrade request we are willing to read
+const MAX_HANDSHAKE_LEN: usize = 0x2000;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+#[derive(Debug)]
+enum WsError {
+    IoError(std::io::Error),
+    HandshakeTooLong,
+    InvalidHandshake,
+    UnmaskedFrame,
+    FrameTooLong,
+    UnexpectedOpcode(u8),
+}
+
+impl From<std::io::Error> for WsError {
+    fn from(e: std::io::Error) -> Self {
+        WsError::IoError(e)
+    }
+}
+
+/// Frames sent from the server to the client
+enum OutFrame {
+    Binary(Vec<u8>),
+    Pong(Vec<u8>),
+    Close,
+}
+
+/// Read the HTTP upgrade request, byte by byte, until an empty line is found.
+async fn read_handshake<R>(reader: &mut R) -> Result<String, WsError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut request = Vec::new();
+    let mut byte = [0u8; 1];
+    while !request.ends_with(b"\r\n\r\n") {
+        if request.len() >= MAX_HANDSHAKE_LEN {
+            return Err(WsError::HandshakeTooLong);
+        }
+        reader.read_exact(&mut byte).await?;
+        request.push(byte[0]);
+    }
+    String::from_utf8(request).map_err(|_| WsError::InvalidHandshake)
+}
+
+/// Calculate the value of the Sec-WebSocket-Accept header
+fn ws_accept_key(ws_key: &str) -> String {
+    let mut data = ws_key.as_bytes().to_vec();
+    data.extend_from_slice(WS_GUID.as_bytes());
+    base64::encode(&sha1_legacy(&data))
+}
+
+/// Verify an HTTP upgrade request. On success, returns the Sec-WebSocket-Accept value.
+fn check_handshake(request: &str) -> Result<String, WsError> {
+    let mut lines = request.split("\r\n");
+    let request_line = lines.next().ok_or(WsError::InvalidHandshake)?;
+    if !request_line.starts_with("GET ") {
+        return Err(WsError::InvalidHandshake);
+    }
+
+    let mut is_upgrade = false;
+    let mut is_connection_upgrade = false;
+    let mut is_version_13 = false;
+    let mut opt_ws_key = None;
+
+    for line in lines {
+        let mut split = line.splitn(2, ':');
+        let name = split.next().unwrap_or("").trim().to_ascii_lowercase();
+        let value = match split.next() {
+            Some(value) => value.trim(),
+            None => continue,
+        };
+        match name.as_str() {
+            "upgrade" => is_upgrade = value.eq_ignore_ascii_case("websocket"),
+            "connection" => {
+                is_connection_upgrade = value
+                    .split(',')
+                    .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+            }
+            "sec-websocket-version" => is_version_13 = value == "13",
+            "sec-websocket-key" => opt_ws_key = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+
+    match opt_ws_key {
+        Some(ws_key) if is_upgrade && is_connection_upgrade && is_version_13 => {
+            Ok(ws_accept_key(&ws_key))
+        }
+        _ => Err(WsError::InvalidHandshake),
+    }
+}
+
+/// Read one frame sent by the client. Returns (fin, opcode, payload)
+async fn read_frame<R>(
+    reader: &mut R,
+    max_frame_length: usize,
+) -> Result<(bool, u8, Vec<u8>), WsError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0u8; 2];
+    reader.read_exact(&mut header).await?;
+
+    let fin = header[0] & 0x80 != 0;
+    let opcode = header[0] & 0x0f;
+    // Frames sent from the client to the server must be masked (RFC 6455, section 5.1):
+    if header[1] & 0x80 == 0 {
+        return Err(WsError::UnmaskedFrame);
+    }
+
+    let payload_len = match header[1] & 0x7f {
+        126 => {
+            let mut len_buff = [0u8; 2];
+            reader.read_exact(&mut len_buff).await?;
+            u64::from(u16::from_be_bytes(len_buff))
+        }
+        127 => {
+            let mut len_buff = [0u8; 8];
+            reader.read_exact(&mut len_buff).await?;
+            u64::from_be_bytes(len_buff)
+        }
+        len => u64::from(len),
+    };
+    if payload_len > max_frame_length as u64 {
+        return Err(WsError::FrameTooLong);
+    }
+
+    let mut mask = [0u8; 4];
+    reader.read_exact(&mut mask).await?;
+
+    let mut payload = vec![0u8; payload_len as usize];
+    reader.read_exact(&mut payload).await?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+
+    Ok((fin, opcode, payload))
+}
+
+/// Serialize a frame sent from the server to the client. Server frames are not masked.
+fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    if payload.len() < 126 {
+        frame.push(payload.len() as u8);
+    } else if payload.len() <= usize::from(u16::max_value()) {
+        frame.push(126);
+        frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    } else {
+        frame.push(127);
+        frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Read messages from the client, until the connection is closed.
+/// Fragmented messages are reassembled. Every binary message becomes one `Vec<u8>`.
+async fn ws_reader_loop<T>(
+    mut reader: ReadHalf<T>,
+    mut user_receiver_sender: mpsc::Sender<Vec<u8>>,
+    mut out_sender: mpsc::Sender<OutFrame>,
+    max_frame_length: usize,
+) -> Result<(), WsError>
+where
+    T: AsyncRead,
+{
+    let mut message = Vec::new();
+    loop {
+        let (fin, opcode, payload) = read_frame(&mut reader, max_frame_length).await?;
+        match opcode {
+            OPCODE_BINARY | OPCODE_CONTINUATION => {
+                if message.len() + payload.len() > max_frame_length {
+                    return Err(WsError::FrameTooLong);
+                }
+                message.extend_from_slice(&payload);
+                if fin {
+                    let complete = std::mem::replace(&mut message, Vec::new());
+                    if user_receiver_sender.send(complete).await.is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+            OPCODE_PING => {
+                let _ = out_sender.send(OutFrame::Pong(payload)).await;
+            }
+            OPCODE_PONG => {}
+            OPCODE_CLOSE => {
+                let _ = out_sender.send(OutFrame::Close).await;
+                return Ok(());
+            }
+            // We only carry binary data (Text frames are not allowed):
+            _ => return Err(WsError::UnexpectedOpcode(opcode)),
+        }
+    }
+}
+
+/// Write frames to the client, until the user sender is dropped (Or the client closes the
+/// connection)
+async fn ws_writer_loop<T, OS>(mut writer: WriteHalf<T>, mut out_frames: OS) -> Result<(), WsError>
+where
+    T: AsyncWrite,
+    OS: Stream<Item = OutFrame> + Unpin,
+{
+    while let Some(out_frame) = out_frames.next().await {
+        let frame = match out_frame {
+            OutFrame::Binary(data) => encode_frame(OPCODE_BINARY, &data),
+            OutFrame::Pong(data) => encode_frame(OPCODE_PONG, &data),
+            OutFrame::Close => break,
+        };
+        writer.write_all(&frame).await?;
+        writer.flush().await?;
+    }
+    writer.write_all(&encode_frame(OPCODE_CLOSE, &[])).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Perform the WebSocket opening handshake (server side) over a raw byte stream, and turn the
+/// stream into a `ConnPairVec`. Every binary WebSocket message corresponds to one `Vec<u8>`
+/// message of the `ConnPairVec`.
+///
+/// Returns None if the handshake failed.
+pub async fn ws_accept<T, S>(
+    io_stream: T,
+    max_frame_length: usize,
+    spawner: S,
+) -> Option<ConnPairVec>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+    S: Spawn,
+{
+    let (mut reader, mut writer) = io_stream.split();
+
+    let request = match read_handshake(&mut reader).await {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("ws_accept(): Failed reading handshake: {:?}", e);
+            return None;
+        }
+    };
+
+    let response = match check_handshake(&request) {
+        Ok(accept_key) => format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key
+        ),
+        Err(e) => {
+            warn!("ws_accept(): Invalid handshake: {:?}", e);
+            let _ = writer.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n").await;
+            return None;
+        }
+    };
+    writer.write_all(response.as_bytes()).await.ok()?;
+    writer.flush().await.ok()?;
+
+    let (user_sender, user_sender_receiver) = mpsc::channel::<Vec<u8>>(0);
+    let (user_receiver_sender, user_receiver) = mpsc::channel::<Vec<u8>>(0);
+    let (out_sender, out_receiver) = mpsc::channel::<OutFrame>(0);
+
+    let reader_fut = ws_reader_loop(reader, user_receiver_sender, out_sender, max_frame_length);
+    spawner
+        .spawn(async move {
+            if let Err(e) = reader_fut.await {
+                warn!("ws_reader_loop() error: {:?}", e);
+            }
+        })
+        .ok()?;
+
+    // Frames originating from the user and frames originating from the reader (Pong, Close) are
+    // merged. Once the user drops its sender, we close the connection.
+    let out_frames = futures::stream::select(
+        user_sender_receiver
+            .map(OutFrame::Binary)
+            .chain(futures::stream::once(futures::future::ready(
+                OutFrame::Close,
+            ))),
+        out_receiver,
+    );
+    spawner
+        .spawn(async move {
+            if let Err(e) = ws_writer_loop(writer, out_frames).await {
+                warn!("ws_writer_loop() error: {:?}", e);
+            }
+        })
+        .ok()?;
+
+    Some(ConnPairVec::from_raw(user_sender, user_receiver))
+}
+
+/// Accept WebSocket connections over incoming raw byte streams (For example, TCP connections).
+/// Up to `max_concurrent_handshakes` handshakes are performed concurrently, and a handshake that
+/// does not complete within `handshake_timeout_ticks` is aborted. This way clients that never
+/// finish their handshake can not exhaust the server.
+/// Every successfully upgraded connection is returned as a `ConnPairVec`, ready to be processed
+/// like any other incoming relay connection.
+pub fn ws_listener<IS, T, S>(
+    incoming_streams: IS,
+    max_frame_length: usize,
+    timer_client: TimerClient,
+    handshake_timeout_ticks: usize,
+    max_concurrent_handshakes: usize,
+    spawner: S,
+) -> mpsc::Receiver<ConnPairVec>
+where
+    IS: Stream<Item = T> + Unpin + Send + 'static,
+    T: AsyncRead + AsyncWrite + Send + 'static,
+    S: Spawn + Clone + Send + 'static,
+{
+    let (conn_sender, conn_receiver) = mpsc::channel(0);
+
+    let c_spawner = spawner.clone();
+    let listen_fut =
+        incoming_streams.for_each_concurrent(max_concurrent_handshakes, move |io_stream| {
+            let mut c_conn_sender = conn_sender.clone();
+            let c_spawner = c_spawner.clone();
+            let mut c_timer_client = timer_client.clone();
+            async move {
+                let timer_stream = match c_timer_client.request_timer_stream().await {
+                    Ok(timer_stream) => timer_stream,
+                    Err(e) => {
+                        error!("ws_listener(): request_timer_stream() error: {:?}", e);
+                        return;
+                    }
+                };
+                let accept_fut = Box::pin(ws_accept(io_stream, max_frame_length, c_spawner));
+                match future_timeout(accept_fut, timer_stream, handshake_timeout_ticks).await {
+                    Some(Some(conn_pair)) => {
+                        let _ = c_conn_sender.send(conn_pair).await;
+                    }
+                    Some(None) => {}
+                    None => warn!("ws_listener(): handshake timeout"),
+                }
+            }
+        });
+
+    if let Err(e) = spawner.spawn(listen_fut) {
+        error!("ws_listener(): spawn error: {:?}", e);
+    }
+
+    conn_receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io;
+    use std::pin::Pin;
+
+    use futures::executor::{block_on, ThreadPool};
+    use futures::task::{Context, Poll};
+    use futures::TryStreamExt;
+
+    use timer::{dummy_timer_multi_sender, TimerTick};
+
+    /// An in memory byte stream, used to simulate a TCP connection.
+    struct MemStream {
+        reader: Box<dyn AsyncRead + Unpin + Send>,
+        writer: mpsc::UnboundedSender<Vec<u8>>,
+    }
+
+    impl AsyncRead for MemStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.reader).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for MemStream {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let res = self
+                .writer
+                .unbounded_send(buf.to_vec())
+                .map(|_| buf.len())
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe));
+            Poll::Ready(res)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Create a server side MemStream, together with the client's (sender, receiver)
+    fn create_mem_stream() -> (
+        MemStream,
+        mpsc::UnboundedSender<Vec<u8>>,
+        mpsc::UnboundedReceiver<Vec<u8>>,
+    ) {
+        let (client_sender, server_receiver) = mpsc::unbounded::<Vec<u8>>();
+        let (server_sender, client_receiver) = mpsc::unbounded::<Vec<u8>>();
+        let reader = server_receiver.map(Ok::<_, io::Error>).into_async_read();
+        let mem_stream = MemStream {
+            reader: Box::new(reader),
+            writer: server_sender,
+        };
+        (mem_stream, client_sender, client_receiver)
+    }
+
+    /// Create a masked frame, as sent by a client
+    fn client_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        assert!(payload.len() < 126);
+        let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    /// Read exactly `len` bytes sent by the server
+    async fn client_read(
+        client_receiver: &mut mpsc::UnboundedReceiver<Vec<u8>>,
+        len: usize,
+    ) -> Vec<u8> {
+        let mut data = Vec::new();
+        while data.len() < len {
+            data.extend(client_receiver.next().await.unwrap());
+        }
+        assert_eq!(data.len(), len);
+        data
+    }
+
+    #[test]
+    fn test_ws_accept_key() {
+        // Example from RFC 6455, section 1.3:
+        assert_eq!(
+            ws_accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_check_handshake_invalid() {
+        let request = "GET /chat HTTP/1.1\r\nHost: relay.example\r\n\r\n";
+        assert!(check_handshake(request).is_err());
+    }
+
+    async fn task_ws_accept_basic<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (mem_stream, client_sender, mut client_receiver) = create_mem_stream();
+
+        let request = "GET /relay HTTP/1.1\r\n\
+                       Host: relay.example\r\n\
+                       Upgrade: websocket\r\n\
+                       Connection: keep-alive, Upgrade\r\n\
+                       Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                       Sec-WebSocket-Version: 13\r\n\r\n";
+        client_sender
+            .unbounded_send(request.as_bytes().to_vec())
+            .unwrap();
+
+        let mut conn_pair = ws_accept(mem_stream, 0x100, spawner.clone()).await.unwrap();
+
+        let expected_response = "HTTP/1.1 101 Switching Protocols\r\n\
+                                 Upgrade: websocket\r\n\
+                                 Connection: Upgrade\r\n\
+                                 Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+        let response = client_read(&mut client_receiver, expected_response.len()).await;
+        assert_eq!(response, expected_response.as_bytes());
+
+        // Client -> Server, a fragmented message:
+        let mut first = client_frame(OPCODE_BINARY, &[1, 2]);
+        // Clear FIN bit:
+        first[0] &= 0x7f;
+        client_sender.unbounded_send(first).unwrap();
+        client_sender
+            .unbounded_send(client_frame(OPCODE_CONTINUATION, &[3]))
+            .unwrap();
+        assert_eq!(conn_pair.receiver.next().await.unwrap(), vec![1, 2, 3]);
+
+        // Ping is answered with a Pong:
+        client_sender
+            .unbounded_send(client_frame(OPCODE_PING, &[9]))
+            .unwrap();
+        assert_eq!(
+            client_read(&mut client_receiver, 3).await,
+            vec![0x80 | OPCODE_PONG, 1, 9]
+        );
+
+        // Server -> Client:
+        conn_pair.sender.send(vec![4, 5, 6]).await.unwrap();
+        assert_eq!(
+            client_read(&mut client_receiver, 5).await,
+            vec![0x80 | OPCODE_BINARY, 3, 4, 5, 6]
+        );
+
+        // Dropping the sender closes the connection:
+        drop(conn_pair.sender);
+        assert_eq!(
+            client_read(&mut client_receiver, 2).await,
+            vec![0x80 | OPCODE_CLOSE, 0]
+        );
+    }
+
+    #[test]
+    fn test_ws_accept_basic() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_ws_accept_basic(thread_pool.clone()));
+    }
+
+    async fn task_ws_listener_handshake_timeout<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (mut tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
+        let (mut stream_sender, stream_receiver) = mpsc::channel(0);
+        let handshake_timeout_ticks = 4;
+        let _conn_receiver = ws_listener(
+            stream_receiver,
+            0x100,
+            timer_client,
+            handshake_timeout_ticks,
+            8,
+            spawner.clone(),
+        );
+
+        // A client that never sends its handshake:
+        let (mem_stream, _client_sender, mut client_receiver) = create_mem_stream();
+        stream_sender.send(mem_stream).await.unwrap();
+
+        let mut tick_sender = tick_sender_receiver.next().await.unwrap();
+        for _ in 0..handshake_timeout_ticks {
+            tick_sender.send(TimerTick).await.unwrap();
+        }
+
+        // The server gives up the handshake, and drops the connection:
+        assert!(client_receiver.next().await.is_none());
+    }
+
+    #[test]
+    fn test_ws_listener_handshake_timeout() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_ws_listener_handshake_timeout(thread_pool.clone()));
+    }
+}
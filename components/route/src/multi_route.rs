@@ -40,7 +40,12 @@ fn fill_amount(amount: u128, sorted_routes: &[(Option<usize>, u128)]) -> Option<
 /// Returns a vector representing how many credits to push through every chosen route (if successful).
 /// For example: (5usize, 100u128) means: push 100 credits through the route that was given in
 /// index 5.
-fn safe_multi_route_amounts(multi_route: &MultiRoute, amount: u128) -> Option<MultiRouteChoice> {
+/// At most `max_routes` routes are used (The routes with the largest capacity).
+fn safe_multi_route_amounts(
+    multi_route: &MultiRoute,
+    amount: u128,
+    max_routes: usize,
+) -> Option<MultiRouteChoice> {
     let routes = &multi_route.routes;
     let sorted_routes = {
         let mut sorted_routes: Vec<_> = routes
@@ -51,6 +56,8 @@ fn safe_multi_route_amounts(multi_route: &MultiRoute, amount: u128) -> Option<Mu
 
         // Reverse sort: (Largest is first)
         sorted_routes.sort_by(|(_, a), (_, b)| b.cmp(a));
+        // Only the largest routes may be used:
+        sorted_routes.truncate(max_routes);
         // Add a zero entry in the end:
         sorted_routes.push((None, 0u128));
         sorted_routes
@@ -87,14 +94,19 @@ fn safe_multi_route_amounts(multi_route: &MultiRoute, amount: u128) -> Option<Mu
 }
 
 /// Choose a route for pushing `amount` credits
+/// If no single route has enough capacity, the amount is split between up to `max_routes` routes
+/// of the same multi route. All the resulting transactions belong to one payment, and therefore
+/// either all of them are committed or none.
 pub fn choose_multi_route(
     multi_routes: &[MultiRoute],
     amount: u128,
+    max_routes: usize,
 ) -> Option<(usize, MultiRouteChoice)> {
     // We naively select the first multi-route we find suitable:
     // TODO: Possibly improve this later:
     for (i, multi_route) in multi_routes.iter().enumerate() {
-        if let Some(multi_route_choice) = safe_multi_route_amounts(multi_route, amount) {
+        if let Some(multi_route_choice) = safe_multi_route_amounts(multi_route, amount, max_routes)
+        {
             return Some((i, multi_route_choice));
         }
     }
@@ -149,9 +161,9 @@ mod tests {
                 mul: 0x20000000,
             },
         });
-        assert!(safe_multi_route_amounts(&multi_route, 601).is_none());
+        assert!(safe_multi_route_amounts(&multi_route, 601, 3).is_none());
 
-        let multi_route_choice = safe_multi_route_amounts(&multi_route, 300).unwrap();
+        let multi_route_choice = safe_multi_route_amounts(&multi_route, 300, 3).unwrap();
 
         let mut total_credits = 0u128;
         for route_choice in &multi_route_choice {
@@ -173,6 +185,55 @@ mod tests {
             capacity: 10u128,
             rate: Rate { add: 0, mul: 0 },
         });
-        assert!(safe_multi_route_amounts(&multi_route, 10u128).is_some());
+        assert!(safe_multi_route_amounts(&multi_route, 10u128, 1).is_some());
+    }
+
+    #[test]
+    fn test_safe_multi_route_amounts_max_routes() {
+        let mut multi_route = MultiRoute { routes: vec![] };
+        for (i, capacity) in [40u128, 30u128, 20u128].iter().enumerate() {
+            multi_route.routes.push(RouteCapacityRate {
+                route: FriendsRoute {
+                    public_keys: vec![pk(0), pk(1 + i as u8), pk(9)],
+                },
+                capacity: *capacity,
+                rate: Rate { add: 0, mul: 0 },
+            });
+        }
+
+        // No single route can carry 60 credits:
+        assert!(safe_multi_route_amounts(&multi_route, 60, 1).is_none());
+
+        // Two routes are enough. The largest routes are used:
+        let multi_route_choice = safe_multi_route_amounts(&multi_route, 60, 2).unwrap();
+        assert_eq!(multi_route_choice.len(), 2);
+        assert!(multi_route_choice
+            .iter()
+            .all(|(route_index, _)| *route_index < 2));
+        let total_credits: u128 = multi_route_choice.iter().map(|(_, credits)| credits).sum();
+        assert_eq!(total_credits, 60);
+
+        // The amount can not be split into more than `max_routes` routes:
+        assert!(safe_multi_route_amounts(&multi_route, 80, 2).is_none());
+        assert!(safe_multi_route_amounts(&multi_route, 80, 3).is_some());
+    }
+
+    #[test]
+    fn test_choose_multi_route_max_routes() {
+        let mut multi_route = MultiRoute { routes: vec![] };
+        for i in 0..4u8 {
+            multi_route.routes.push(RouteCapacityRate {
+                route: FriendsRoute {
+                    public_keys: vec![pk(0), pk(1 + i), pk(9)],
+                },
+                capacity: 10u128,
+                rate: Rate { add: 0, mul: 0 },
+            });
+        }
+        let multi_routes = vec![multi_route];
+        assert!(choose_multi_route(&multi_routes, 35, 3).is_none());
+        let (route_index, multi_route_choice) = choose_multi_route(&multi_routes, 35, 4).unwrap();
+        assert_eq!(route_index, 0);
+        assert_eq!(multi_route_choice.len(), 4);
     }
 }
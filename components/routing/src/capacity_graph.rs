@@ -43,6 +43,30 @@ pub struct CapacityMultiRoute<N, C, T> {
     pub routes: Vec<CapacityRoute<N, C, T>>,
 }
 
+/// Additional constraints on the routes returned from a graph search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteConstraints<N, C> {
+    /// Nodes that must not show up as intermediate nodes in any returned route.
+    pub blacklist: Vec<N>,
+    /// Maximum amount of nodes in a returned route (Including the source and destination).
+    pub opt_max_route_len: Option<usize>,
+    /// Every returned route must be able to carry this capacity on top of the requested capacity.
+    pub capacity_margin: C,
+}
+
+impl<N, C> Default for RouteConstraints<N, C>
+where
+    C: Default,
+{
+    fn default() -> Self {
+        RouteConstraints {
+            blacklist: Vec::new(),
+            opt_max_route_len: None,
+            capacity_margin: C::default(),
+        }
+    }
+}
+
 pub trait CapacityGraph {
     type Node; // Node type
     type Capacity; // Directed capacity between two neighboring nodes
@@ -59,6 +83,15 @@ pub trait CapacityGraph {
         capacity_edge: CapacityEdge<Self::Capacity, Self::Rate>,
     ) -> Option<CapacityEdge<Self::Capacity, Self::Rate>>;
 
+    /// Change the receive capacity of an existing edge, keeping everything else about the edge.
+    /// Returns the previous receive capacity, or None if the edge does not exist.
+    fn set_recv_capacity(
+        &mut self,
+        a: &Self::Node,
+        b: &Self::Node,
+        recv_capacity: Self::Capacity,
+    ) -> Option<Self::Capacity>;
+
     /// Remove an edge from the graph
     fn remove_edge(
         &mut self,
@@ -78,14 +111,46 @@ pub trait CapacityGraph {
     ///
     /// opt_exclude is an optional edge to exclude (All of the returned routes must not go through this
     /// edge). This can be useful for finding non trivial loops.
+    /// All the returned routes must satisfy `constraints`.
     fn get_multi_routes(
         &self,
         a: &Self::Node,
         b: &Self::Node,
         capacity: Self::Capacity,
         opt_exclude: Option<(&Self::Node, &Self::Node)>,
+        constraints: &RouteConstraints<Self::Node, Self::Capacity>,
+    ) -> Vec<CapacityMultiRoute<Self::Node, Self::Capacity, Self::Rate>>;
+
+    /// Get up to `max_routes` routes with capacity at least `capacity`, ranked by their residual
+    /// capacity and by how recently their capacity was reported. Best ranked routes come first.
+    /// Every returned multi route contains a single route.
+    ///
+    /// `opt_exclude` and `constraints` have the same meaning as in `get_multi_routes()`.
+    fn get_ranked_routes(
+        &self,
+        a: &Self::Node,
+        b: &Self::Node,
+        capacity: Self::Capacity,
+        opt_exclude: Option<(&Self::Node, &Self::Node)>,
+        max_routes: usize,
+        constraints: &RouteConstraints<Self::Node, Self::Capacity>,
     ) -> Vec<CapacityMultiRoute<Self::Node, Self::Capacity, Self::Rate>>;
 
     /// Simulate advancement of time. Used to remove old edges.
     fn tick(&mut self, a: &Self::Node);
+
+    /// Returns the amount of nodes with outgoing edges, and the amount of directed edges in the
+    /// graph.
+    fn size(&self) -> (usize, usize);
+
+    /// Get the directed edges of the graph.
+    /// If `opt_node` is provided, only edges from or to this node are returned.
+    fn get_edges(
+        &self,
+        opt_node: Option<&Self::Node>,
+    ) -> Vec<(
+        Self::Node,
+        Self::Node,
+        CapacityEdge<Self::Capacity, Self::Rate>,
+    )>;
 }
@@ -0,0 +1,22 @@
+#![crate_type = "lib"]
+#![deny(trivial_numeric_casts, warnings)]
+#![allow(intra_doc_link_resolution_failure)]
+#![allow(
+    clippy::too_many_arguments,
+    clippy::implicit_hasher,
+    clippy::module_inception,
+    clippy::new_without_default
+)]
+
+//! Capacity graphs and route planning.
+//!
+//! Used by the index server to answer routes requests. Can also be used by external tools to
+//! plan routes over exported graphs, outside of the index server.
+
+mod bfs;
+pub mod capacity_graph;
+pub mod planner;
+mod rate;
+pub mod simple_capacity_graph;
+pub mod test_utils;
+mod utils;
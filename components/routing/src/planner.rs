@@ -0,0 +1,705 @@
+//! Route planning queries over a capacity graph.
+//!
+//! Unlike `CapacityGraph::get_multi_routes()`, which only finds a shortest route (in hops),
+//! the queries here rank routes using a pluggable cost function, and may return multiple routes.
+//! `capacity_ranked_routes()` ranks routes by how much capacity they have left, and how recently
+//! their capacity was reported.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::{cmp, hash};
+
+use crate::capacity_graph::{
+    CapacityEdge, CapacityGraph, CapacityRoute, LinearRate, RouteConstraints,
+};
+use crate::simple_capacity_graph::SimpleCapacityGraph;
+
+/// Amount of candidate routes considered by `capacity_ranked_routes()` for every returned route.
+const RANKING_CANDIDATES_FACTOR: usize = 4;
+
+/// The cost of using a directed edge of a route.
+pub trait CostFunction<N, T> {
+    /// The cost of sending credits from `a` to its direct neighbor `b`, where `rate` is the rate
+    /// of the edge from `a` to `b`.
+    /// Returns None if the edge must not be used.
+    fn edge_cost(&self, a: &N, b: &N, rate: &T) -> Option<u128>;
+}
+
+/// Every edge costs the same: Routes with less hops are preferred.
+#[derive(Debug, Clone)]
+pub struct HopCost;
+
+impl<N, T> CostFunction<N, T> for HopCost {
+    fn edge_cost(&self, _a: &N, _b: &N, _rate: &T) -> Option<u128> {
+        Some(1)
+    }
+}
+
+/// The cost of an edge is the fee for forwarding `amount` credits along the edge:
+/// Cheaper routes are preferred.
+///
+/// Note that the fee is counted for every edge, including the last one.
+#[derive(Debug, Clone)]
+pub struct FeeCost {
+    pub amount: u128,
+}
+
+impl<N, T> CostFunction<N, T> for FeeCost
+where
+    T: LinearRate<K = u128>,
+{
+    fn edge_cost(&self, _a: &N, _b: &N, rate: &T) -> Option<u128> {
+        rate.calc_fee(self.amount)
+    }
+}
+
+/// Build a capacity graph from a stream of directed edges, for example edges exported from
+/// another graph using `SimpleCapacityGraph::edges()`.
+/// A later edge between the same two nodes replaces an earlier one.
+pub fn build_capacity_graph<G, I>(edges: I) -> G
+where
+    G: CapacityGraph,
+    I: IntoIterator<Item = (G::Node, G::Node, CapacityEdge<G::Capacity, G::Rate>)>,
+{
+    let mut graph = G::new();
+    for (a, b, capacity_edge) in edges {
+        graph.update_edge(a, b, capacity_edge);
+    }
+    graph
+}
+
+/// Nodes and edges that a search must avoid
+struct Avoid<N> {
+    nodes: HashSet<N>,
+    edges: HashSet<(N, N)>,
+}
+
+impl<N> Avoid<N>
+where
+    N: cmp::Eq + hash::Hash + Clone,
+{
+    fn new(constraints: &RouteConstraints<N, u128>, opt_exclude: Option<(&N, &N)>) -> Self {
+        Avoid {
+            nodes: constraints.blacklist.iter().cloned().collect(),
+            edges: opt_exclude
+                .map(|(e_start, e_end)| (e_start.clone(), e_end.clone()))
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+/// Find a cheapest route from `a` to `b` (Dijkstra), using only edges that can carry
+/// `min_capacity` credits.
+/// Avoided nodes may still be used as the destination.
+/// Returns the cost of the route, together with the route.
+fn cheapest_route<N, T, F>(
+    graph: &SimpleCapacityGraph<N, T>,
+    a: &N,
+    b: &N,
+    min_capacity: u128,
+    cost_function: &F,
+    avoid: &Avoid<N>,
+) -> Option<(u128, Vec<N>)>
+where
+    N: cmp::Eq + hash::Hash + Clone + std::fmt::Debug,
+    T: LinearRate + Clone,
+    F: CostFunction<N, T>,
+{
+    // Nodes waiting in the heap are kept aside, as nodes are not required to be ordered:
+    let mut queued_nodes: Vec<N> = vec![a.clone()];
+    let mut heap = BinaryHeap::new();
+    heap.push(Reverse((0u128, 0usize)));
+
+    let mut costs: HashMap<N, u128> = HashMap::new();
+    let mut backtrack: HashMap<N, Option<N>> = HashMap::new();
+    costs.insert(a.clone(), 0);
+    backtrack.insert(a.clone(), None);
+    let mut visited: HashSet<N> = HashSet::new();
+
+    while let Some(Reverse((cost, index))) = heap.pop() {
+        let node = queued_nodes[index].clone();
+        if !visited.insert(node.clone()) {
+            continue;
+        }
+        if &node == b {
+            let mut route = vec![node];
+            while let Some(Some(prev)) = backtrack.get(route.last().unwrap()) {
+                route.push(prev.clone());
+            }
+            route.reverse();
+            return Some((cost, route));
+        }
+
+        for (next_node, rate) in graph.send_edges(&node, min_capacity) {
+            if visited.contains(next_node)
+                || (next_node != b && avoid.nodes.contains(next_node))
+                || avoid.edges.contains(&(node.clone(), next_node.clone()))
+            {
+                continue;
+            }
+            let edge_cost = match cost_function.edge_cost(&node, next_node, rate) {
+                Some(edge_cost) => edge_cost,
+                None => continue,
+            };
+            let next_cost = match cost.checked_add(edge_cost) {
+                Some(next_cost) => next_cost,
+                None => continue,
+            };
+            if let Some(&known_cost) = costs.get(next_node) {
+                if known_cost <= next_cost {
+                    continue;
+                }
+            }
+            costs.insert(next_node.clone(), next_cost);
+            backtrack.insert(next_node.clone(), Some(node.clone()));
+            heap.push(Reverse((next_cost, queued_nodes.len())));
+            queued_nodes.push(next_node.clone());
+        }
+    }
+    None
+}
+
+/// Calculate the total cost of a route
+fn route_cost<N, T, F>(
+    graph: &SimpleCapacityGraph<N, T>,
+    route: &[N],
+    min_capacity: u128,
+    cost_function: &F,
+) -> Option<u128>
+where
+    N: cmp::Eq + hash::Hash + Clone + std::fmt::Debug,
+    T: LinearRate + Clone,
+    F: CostFunction<N, T>,
+{
+    let mut total_cost = 0u128;
+    for i in 0..route.len().checked_sub(1)? {
+        let (_, rate) = graph
+            .send_edges(&route[i], min_capacity)
+            .find(|(next_node, _)| *next_node == &route[i + 1])?;
+        let edge_cost = cost_function.edge_cost(&route[i], &route[i + 1], rate)?;
+        total_cost = total_cost.checked_add(edge_cost)?;
+    }
+    Some(total_cost)
+}
+
+/// Add capacity and rate information to a route
+fn capacity_route<N, T>(
+    graph: &SimpleCapacityGraph<N, T>,
+    route: Vec<N>,
+) -> Option<CapacityRoute<N, u128, T>>
+where
+    N: cmp::Eq + hash::Hash + Clone + std::fmt::Debug,
+    T: LinearRate + Clone,
+{
+    let capacity = graph.get_route_capacity(&route)?;
+    let rate = graph.get_route_rate(&route)?;
+    Some(CapacityRoute {
+        route,
+        capacity,
+        rate,
+    })
+}
+
+fn is_route_len_valid<N>(route: &[N], constraints: &RouteConstraints<N, u128>) -> bool {
+    match constraints.opt_max_route_len {
+        Some(max_route_len) => route.len() <= max_route_len,
+        None => true,
+    }
+}
+
+/// Find up to `k` cheapest routes from `a` to `b` that can carry `min_capacity` credits,
+/// ordered by their cost (Yen's algorithm).
+/// The returned routes do not go through the directed edge `opt_exclude`.
+fn yen_routes<N, T, F>(
+    graph: &SimpleCapacityGraph<N, T>,
+    a: &N,
+    b: &N,
+    min_capacity: u128,
+    k: usize,
+    cost_function: &F,
+    constraints: &RouteConstraints<N, u128>,
+    opt_exclude: Option<(&N, &N)>,
+) -> Vec<Vec<N>>
+where
+    N: cmp::Eq + hash::Hash + Clone + std::fmt::Debug,
+    T: LinearRate + Clone,
+    F: CostFunction<N, T>,
+{
+    if k == 0 {
+        return Vec::new();
+    }
+
+    let mut found_routes: Vec<Vec<N>> = Vec::new();
+    // Candidate routes: (cost, route)
+    let mut candidates: Vec<(u128, Vec<N>)> = Vec::new();
+
+    match cheapest_route(
+        graph,
+        a,
+        b,
+        min_capacity,
+        cost_function,
+        &Avoid::new(constraints, opt_exclude),
+    ) {
+        Some((_cost, route)) if is_route_len_valid(&route, constraints) => found_routes.push(route),
+        _ => return Vec::new(),
+    }
+
+    while found_routes.len() < k {
+        let prev_route = found_routes.last().unwrap().clone();
+        for spur_index in 0..prev_route.len().saturating_sub(1) {
+            let spur_node = &prev_route[spur_index];
+            let root = &prev_route[..=spur_index];
+
+            let mut avoid = Avoid::new(constraints, opt_exclude);
+            // Avoid the edges leaving the root that were already used by found routes:
+            for route in &found_routes {
+                if route.len() > spur_index + 1 && &route[..=spur_index] == root {
+                    avoid
+                        .edges
+                        .insert((route[spur_index].clone(), route[spur_index + 1].clone()));
+                }
+            }
+            // The rest of the route must not go through the root again:
+            avoid.nodes.extend(root[..spur_index].iter().cloned());
+
+            let spur_route =
+                match cheapest_route(graph, spur_node, b, min_capacity, cost_function, &avoid) {
+                    Some((_cost, spur_route)) => spur_route,
+                    None => continue,
+                };
+
+            let mut route = root[..spur_index].to_vec();
+            route.extend(spur_route);
+
+            if !is_route_len_valid(&route, constraints)
+                || found_routes.contains(&route)
+                || candidates.iter().any(|(_, candidate)| candidate == &route)
+            {
+                continue;
+            }
+            if let Some(cost) = route_cost(graph, &route, min_capacity, cost_function) {
+                candidates.push((cost, route));
+            }
+        }
+
+        // Pick the cheapest candidate. Prefer shorter routes (And then older candidates) for
+        // candidates of the same cost:
+        let opt_best_index = (0..candidates.len()).min_by_key(|&i| {
+            let (cost, route) = &candidates[i];
+            (*cost, route.len(), i)
+        });
+        match opt_best_index {
+            Some(best_index) => found_routes.push(candidates.remove(best_index).1),
+            None => break,
+        }
+    }
+
+    found_routes
+}
+
+/// Find up to `k` cheapest routes from `a` to `b` that can carry `capacity` credits, ordered by
+/// their cost (Yen's algorithm). The returned routes are loopless, but may share nodes and
+/// edges.
+///
+/// All the returned routes satisfy `constraints`.
+pub fn k_cheapest_routes<N, T, F>(
+    graph: &SimpleCapacityGraph<N, T>,
+    a: &N,
+    b: &N,
+    capacity: u128,
+    k: usize,
+    cost_function: &F,
+    constraints: &RouteConstraints<N, u128>,
+) -> Vec<CapacityRoute<N, u128, T>>
+where
+    N: cmp::Eq + hash::Hash + Clone + std::fmt::Debug,
+    T: LinearRate + Clone,
+    F: CostFunction<N, T>,
+{
+    let min_capacity = match capacity.checked_add(constraints.capacity_margin) {
+        Some(min_capacity) => min_capacity,
+        None => return Vec::new(),
+    };
+
+    yen_routes(
+        graph,
+        a,
+        b,
+        min_capacity,
+        k,
+        cost_function,
+        constraints,
+        None,
+    )
+    .into_iter()
+    .filter_map(|route| capacity_route(graph, route))
+    .collect()
+}
+
+/// The capacity score of a route: The minimum over the edges of the route of the residual
+/// capacity of the edge (Capacity left after sending `min_capacity` credits), divided by the
+/// amount of ticks since the capacity of the edge was reported (Plus one).
+/// Returns None if the route can not carry `min_capacity` credits.
+fn capacity_score<N, T>(
+    graph: &SimpleCapacityGraph<N, T>,
+    route: &[N],
+    min_capacity: u128,
+) -> Option<u128>
+where
+    N: cmp::Eq + hash::Hash + Clone + std::fmt::Debug,
+    T: LinearRate + Clone,
+{
+    let mut score = u128::max_value();
+    for i in 0..route.len().checked_sub(1)? {
+        let residual_capacity = graph
+            .get_send_capacity(&route[i], &route[i + 1])
+            .checked_sub(min_capacity)?;
+        let age = graph.get_send_capacity_age(&route[i], &route[i + 1])?;
+        score = cmp::min(score, residual_capacity / age.saturating_add(1));
+    }
+    Some(score)
+}
+
+/// Find up to `max_routes` routes from `a` to `b` that can carry `capacity` credits, ranked by
+/// their capacity score: Routes with more residual capacity, that was reported more recently,
+/// come first. Between routes with the same score, shorter routes come first.
+///
+/// The returned routes do not go through the directed edge `opt_exclude`, and satisfy
+/// `constraints`.
+pub fn capacity_ranked_routes<N, T>(
+    graph: &SimpleCapacityGraph<N, T>,
+    a: &N,
+    b: &N,
+    capacity: u128,
+    opt_exclude: Option<(&N, &N)>,
+    max_routes: usize,
+    constraints: &RouteConstraints<N, u128>,
+) -> Vec<CapacityRoute<N, u128, T>>
+where
+    N: cmp::Eq + hash::Hash + Clone + std::fmt::Debug,
+    T: LinearRate + Clone,
+{
+    let min_capacity = match capacity.checked_add(constraints.capacity_margin) {
+        Some(min_capacity) => min_capacity,
+        None => return Vec::new(),
+    };
+
+    // Candidates are ordered by their amount of hops:
+    let candidates = yen_routes(
+        graph,
+        a,
+        b,
+        min_capacity,
+        max_routes.saturating_mul(RANKING_CANDIDATES_FACTOR),
+        &HopCost,
+        constraints,
+        opt_exclude,
+    );
+
+    let mut scored_routes = candidates
+        .into_iter()
+        .filter_map(|route| Some((capacity_score(graph, &route, min_capacity)?, route)))
+        .collect::<Vec<_>>();
+    // Highest score first. The sort is stable, so shorter routes win ties:
+    scored_routes.sort_by(|(score_a, _), (score_b, _)| score_b.cmp(score_a));
+
+    scored_routes
+        .into_iter()
+        .take(max_routes)
+        .filter_map(|(_score, route)| capacity_route(graph, route))
+        .collect()
+}
+
+/// Find up to `max_routes` routes from `a` to `b` that can each carry `capacity` credits,
+/// where no two routes share an intermediate node.
+///
+/// Routes are picked greedily: Every route is the cheapest route that avoids the intermediate
+/// nodes of the previously picked routes. This might return less routes than the maximum amount
+/// of disjoint routes in the graph.
+///
+/// All the returned routes satisfy `constraints`.
+pub fn disjoint_routes<N, T, F>(
+    graph: &SimpleCapacityGraph<N, T>,
+    a: &N,
+    b: &N,
+    capacity: u128,
+    max_routes: usize,
+    cost_function: &F,
+    constraints: &RouteConstraints<N, u128>,
+) -> Vec<CapacityRoute<N, u128, T>>
+where
+    N: cmp::Eq + hash::Hash + Clone + std::fmt::Debug,
+    T: LinearRate + Clone,
+    F: CostFunction<N, T>,
+{
+    let min_capacity = match capacity.checked_add(constraints.capacity_margin) {
+        Some(min_capacity) => min_capacity,
+        None => return Vec::new(),
+    };
+    if a == b {
+        return Vec::new();
+    }
+
+    let mut avoid = Avoid::new(constraints, None);
+    let mut routes = Vec::new();
+    while routes.len() < max_routes {
+        let route = match cheapest_route(graph, a, b, min_capacity, cost_function, &avoid) {
+            Some((_cost, route)) => route,
+            None => break,
+        };
+        if !is_route_len_valid(&route, constraints) {
+            break;
+        }
+
+        // Intermediate nodes can not be used by the next routes:
+        avoid
+            .nodes
+            .extend(route[1..route.len() - 1].iter().cloned());
+        // A direct edge can only be used once:
+        if route.len() == 2 {
+            avoid.edges.insert((a.clone(), b.clone()));
+        }
+        routes.extend(capacity_route(graph, route));
+    }
+    routes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_utils::ConstRate;
+
+    /// A rate that charges a fee proportional to the amount of credits
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct MulRate(u128);
+
+    impl LinearRate for MulRate {
+        type K = u128;
+
+        fn zero() -> Self {
+            MulRate(0)
+        }
+
+        fn calc_fee(&self, k: Self::K) -> Option<Self::K> {
+            self.0.checked_mul(k)
+        }
+
+        fn checked_add(&self, other: &Self) -> Option<Self> {
+            Some(MulRate(self.0.checked_add(other.0)?))
+        }
+    }
+
+    /// Add an edge of capacity 30 in both directions
+    fn add_channel<T: LinearRate + Clone>(
+        cg: &mut SimpleCapacityGraph<u32, T>,
+        a: u32,
+        b: u32,
+        rate: T,
+    ) {
+        cg.update_edge(a, b, CapacityEdge::new(30, rate.clone()));
+        cg.update_edge(b, a, CapacityEdge::new(30, rate));
+    }
+
+    fn example_capacity_graph() -> SimpleCapacityGraph<u32, ConstRate> {
+        /*
+         * Example graph:
+         *
+         *      1 --- 2
+         *     /       \
+         *    0 --- 3 --- 5
+         *     \         /
+         *      4 ------
+         *
+         */
+        let mut cg = SimpleCapacityGraph::<u32, ConstRate>::new();
+        add_channel(&mut cg, 0, 1, ConstRate(1));
+        add_channel(&mut cg, 1, 2, ConstRate(1));
+        add_channel(&mut cg, 2, 5, ConstRate(1));
+        add_channel(&mut cg, 0, 3, ConstRate(1));
+        add_channel(&mut cg, 3, 5, ConstRate(1));
+        add_channel(&mut cg, 0, 4, ConstRate(1));
+        add_channel(&mut cg, 4, 5, ConstRate(1));
+        add_channel(&mut cg, 3, 2, ConstRate(1));
+        cg
+    }
+
+    fn routes_of<C, T>(capacity_routes: &[CapacityRoute<u32, C, T>]) -> Vec<Vec<u32>> {
+        capacity_routes
+            .iter()
+            .map(|capacity_route| capacity_route.route.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_build_capacity_graph_from_edges() {
+        let cg = example_capacity_graph();
+        let edges: Vec<_> = cg
+            .edges()
+            .map(|(a, b, capacity_edge)| (*a, *b, capacity_edge.clone()))
+            .collect();
+        assert_eq!(edges.len(), 16);
+
+        let rebuilt_cg: SimpleCapacityGraph<u32, ConstRate> = build_capacity_graph(edges);
+        let no_constraints = RouteConstraints::default();
+        assert_eq!(
+            routes_of(&k_cheapest_routes(
+                &rebuilt_cg,
+                &0,
+                &5,
+                10,
+                8,
+                &HopCost,
+                &no_constraints
+            )),
+            routes_of(&k_cheapest_routes(
+                &cg,
+                &0,
+                &5,
+                10,
+                8,
+                &HopCost,
+                &no_constraints
+            ))
+        );
+    }
+
+    #[test]
+    fn test_k_cheapest_routes_hops() {
+        let cg = example_capacity_graph();
+        let no_constraints = RouteConstraints::default();
+
+        let routes = k_cheapest_routes(&cg, &0, &5, 10, 8, &HopCost, &no_constraints);
+        let routes = routes_of(&routes);
+        assert_eq!(routes.len(), 5);
+        // Shorter routes first:
+        assert!(routes[..2].contains(&vec![0, 3, 5]));
+        assert!(routes[..2].contains(&vec![0, 4, 5]));
+        assert!(routes[2..].contains(&vec![0, 1, 2, 5]));
+        assert!(routes[2..].contains(&vec![0, 3, 2, 5]));
+        assert!(routes[2..].contains(&vec![0, 1, 2, 3, 5]));
+
+        let routes = k_cheapest_routes(&cg, &0, &5, 10, 2, &HopCost, &no_constraints);
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].capacity, 30);
+        assert_eq!(routes[0].rate, ConstRate(1));
+
+        // Not enough capacity:
+        assert!(k_cheapest_routes(&cg, &0, &5, 31, 2, &HopCost, &no_constraints).is_empty());
+        assert!(k_cheapest_routes(&cg, &0, &5, 10, 0, &HopCost, &no_constraints).is_empty());
+    }
+
+    #[test]
+    fn test_k_cheapest_routes_constraints() {
+        let cg = example_capacity_graph();
+
+        let constraints = RouteConstraints {
+            blacklist: vec![3],
+            opt_max_route_len: Some(4),
+            capacity_margin: 0,
+        };
+        let routes = k_cheapest_routes(&cg, &0, &5, 10, 8, &HopCost, &constraints);
+        assert_eq!(routes_of(&routes), vec![vec![0, 4, 5], vec![0, 1, 2, 5]]);
+    }
+
+    #[test]
+    fn test_k_cheapest_routes_fees() {
+        /*
+         *    0 --- 1 --- 3
+         *     \         /
+         *      2 ------
+         */
+        let mut cg = SimpleCapacityGraph::<u32, MulRate>::new();
+        add_channel(&mut cg, 0, 1, MulRate(1));
+        add_channel(&mut cg, 1, 3, MulRate(1));
+        add_channel(&mut cg, 0, 2, MulRate(5));
+        add_channel(&mut cg, 2, 3, MulRate(5));
+        let no_constraints = RouteConstraints::default();
+
+        let routes =
+            k_cheapest_routes(&cg, &0, &3, 10, 2, &FeeCost { amount: 10 }, &no_constraints);
+        assert_eq!(routes_of(&routes), vec![vec![0, 1, 3], vec![0, 2, 3]]);
+
+        // Make the route through 1 expensive:
+        add_channel(&mut cg, 1, 3, MulRate(20));
+        let routes =
+            k_cheapest_routes(&cg, &0, &3, 10, 2, &FeeCost { amount: 10 }, &no_constraints);
+        assert_eq!(routes_of(&routes), vec![vec![0, 2, 3], vec![0, 1, 3]]);
+    }
+
+    #[test]
+    fn test_capacity_ranked_routes() {
+        /*
+         *      1 ------
+         *     /        \
+         *    0 --- 2 --- 5
+         *     \        /
+         *      3 --- 4
+         */
+        let mut cg = SimpleCapacityGraph::<u32, ConstRate>::new();
+        let mut add_wide_channel = |a: u32, b: u32, capacity: u128| {
+            cg.update_edge(a, b, CapacityEdge::new(capacity, ConstRate(1)));
+            cg.update_edge(b, a, CapacityEdge::new(capacity, ConstRate(1)));
+        };
+        add_wide_channel(0, 1, 30);
+        add_wide_channel(1, 5, 30);
+        add_wide_channel(0, 2, 100);
+        add_wide_channel(2, 5, 100);
+        add_wide_channel(0, 3, 200);
+        add_wide_channel(3, 4, 200);
+        add_wide_channel(4, 5, 200);
+        let no_constraints = RouteConstraints::default();
+
+        // More residual capacity first, even for longer routes:
+        let routes = capacity_ranked_routes(&cg, &0, &5, 10, None, 3, &no_constraints);
+        assert_eq!(
+            routes_of(&routes),
+            vec![vec![0, 3, 4, 5], vec![0, 2, 5], vec![0, 1, 5]]
+        );
+        assert_eq!(routes[0].capacity, 200);
+
+        let routes = capacity_ranked_routes(&cg, &0, &5, 10, None, 2, &no_constraints);
+        assert_eq!(routes_of(&routes), vec![vec![0, 3, 4, 5], vec![0, 2, 5]]);
+
+        // The capacity of node 3 was not reported for a while:
+        for _ in 0..9 {
+            cg.tick(&3);
+        }
+        let routes = capacity_ranked_routes(&cg, &0, &5, 10, None, 3, &no_constraints);
+        assert_eq!(
+            routes_of(&routes),
+            vec![vec![0, 2, 5], vec![0, 1, 5], vec![0, 3, 4, 5]]
+        );
+
+        let routes = capacity_ranked_routes(&cg, &0, &5, 10, Some((&0, &2)), 3, &no_constraints);
+        assert_eq!(routes_of(&routes), vec![vec![0, 1, 5], vec![0, 3, 4, 5]]);
+
+        // Only one route can carry the requested capacity:
+        let routes = capacity_ranked_routes(&cg, &0, &5, 150, None, 3, &no_constraints);
+        assert_eq!(routes_of(&routes), vec![vec![0, 3, 4, 5]]);
+
+        assert!(capacity_ranked_routes(&cg, &0, &5, 10, None, 0, &no_constraints).is_empty());
+    }
+
+    #[test]
+    fn test_disjoint_routes() {
+        let cg = example_capacity_graph();
+        let no_constraints = RouteConstraints::default();
+
+        let routes = disjoint_routes(&cg, &0, &5, 10, 8, &HopCost, &no_constraints);
+        let routes = routes_of(&routes);
+        assert_eq!(routes.len(), 3);
+        assert!(routes.contains(&vec![0, 3, 5]));
+        assert!(routes.contains(&vec![0, 4, 5]));
+        assert!(routes.contains(&vec![0, 1, 2, 5]));
+
+        let routes = disjoint_routes(&cg, &0, &5, 10, 1, &HopCost, &no_constraints);
+        assert_eq!(routes.len(), 1);
+
+        // A direct edge is used only once. 3 and 2 are intermediate nodes of the second route:
+        let routes = disjoint_routes(&cg, &0, &1, 10, 8, &HopCost, &no_constraints);
+        assert_eq!(routes_of(&routes), vec![vec![0, 1], vec![0, 3, 2, 1]]);
+    }
+}
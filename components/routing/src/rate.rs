@@ -0,0 +1,24 @@
+use proto::funder::messages::Rate;
+
+use crate::capacity_graph::LinearRate;
+
+impl LinearRate for Rate {
+    /// Type used to count credits
+    type K = u128;
+
+    fn zero() -> Self {
+        Rate { mul: 0, add: 0 }
+    }
+
+    fn calc_fee(&self, k: Self::K) -> Option<Self::K> {
+        self.calc_fee(k)
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        // Entry wise addition:
+        Some(Rate {
+            mul: self.mul.checked_add(other.mul)?,
+            add: self.add.checked_add(other.add)?,
+        })
+    }
+}
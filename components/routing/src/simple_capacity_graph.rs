@@ -1,11 +1,12 @@
 use std::collections::HashMap;
-use std::{cmp, hash};
+use std::{cmp, hash, mem};
 
-use super::bfs::bfs;
-use super::capacity_graph::{
-    CapacityEdge, CapacityGraph, CapacityMultiRoute, CapacityRoute, LinearRate,
+use crate::bfs::bfs;
+use crate::capacity_graph::{
+    CapacityEdge, CapacityGraph, CapacityMultiRoute, CapacityRoute, LinearRate, RouteConstraints,
 };
-use super::utils::{option_to_vec, OptionIterator};
+use crate::planner::capacity_ranked_routes;
+use crate::utils::{option_to_vec, OptionIterator};
 
 /// Amount of ticks an edge could live regardless of coupon collector's approximation.
 /// This is useful to allow the first edges build (n*log(n) is very small for small n).
@@ -92,7 +93,7 @@ where
     }
 
     /// Get the send capacity from `a` to a direct neighbor `b`.
-    fn get_send_capacity(&self, a: &N, b: &N) -> u128 {
+    pub fn get_send_capacity(&self, a: &N, b: &N) -> u128 {
         if self.get_edge(&a, &b).is_none() {
             return 0;
         }
@@ -120,16 +121,49 @@ where
         OptionIterator::new(Some(iter))
     }
 
+    /// Iterate over all the directed edges of the graph.
+    /// Useful for exporting the graph, to be rebuilt later using `build_capacity_graph()`.
+    pub fn edges(&self) -> impl Iterator<Item = (&N, &N, &CapacityEdge<u128, T>)> {
+        self.nodes.iter().flat_map(|(a, a_edges)| {
+            a_edges
+                .edges
+                .iter()
+                .map(move |(b, edge)| (a, b, &edge.capacity_edge))
+        })
+    }
+
+    /// Iterate over the neighbors `b` of `a` that `a` can send at least `capacity` credits to,
+    /// together with the rate of the edge from `a` to `b`.
+    pub(crate) fn send_edges(&self, a: &N, capacity: u128) -> impl Iterator<Item = (&N, &T)> {
+        let a = a.clone();
+        self.neighbors_with_send_capacity(a.clone(), capacity)
+            .map(move |b| (b, &self.get_edge_ref(&a, b).unwrap().capacity_edge.rate))
+    }
+
+    /// Amount of ticks since the send capacity from `a` to a direct neighbor `b` was last
+    /// reported. The send capacity depends on the edges in both directions, so the older edge
+    /// counts.
+    pub(crate) fn get_send_capacity_age(&self, a: &N, b: &N) -> Option<u128> {
+        let a_b_age = self.get_edge_ref(a, b)?.age;
+        let b_a_age = self.get_edge_ref(b, a)?.age;
+        Some(cmp::max(a_b_age, b_a_age))
+    }
+
+    /// Get a reference to a directed edge (if exists)
+    fn get_edge_ref(&self, a: &N, b: &N) -> Option<&Edge<T>> {
+        self.nodes.get(a)?.edges.get(b)
+    }
+
     /// Calculate the amount of capacity we can send through a route.
     /// This amount if the minimum of all edge capacities of the route.
-    fn get_route_capacity(&self, route: &[N]) -> Option<u128> {
+    pub(crate) fn get_route_capacity(&self, route: &[N]) -> Option<u128> {
         (0..route.len().checked_sub(1)?)
             .map(|i| self.get_send_capacity(&route[i], &route[i + 1]))
             .min()
     }
 
     /// Calculate the total rate of sending credits along a given route
-    fn get_route_rate(&self, route: &[N]) -> Option<T> {
+    pub(crate) fn get_route_rate(&self, route: &[N]) -> Option<T> {
         let mut total_rate = T::zero();
         // If the route is only of length 2, the rate will be 0.
         // No fees are paid for the last hop. TODO: Is this the right behaviour?
@@ -145,12 +179,14 @@ where
     ///
     /// opt_exclude is an optional edge to exclude (The returned route must not go through this
     /// edge). This can be useful for finding non trivial loops.
+    /// The returned route must satisfy `constraints`.
     fn get_multi_route(
         &self,
         a: &N,
         b: &N,
         capacity: u128,
         opt_exclude: Option<(&N, &N)>,
+        constraints: &RouteConstraints<N, u128>,
     ) -> Option<CapacityMultiRoute<N, u128, T>> {
         // TODO: Update this implementation:
         // Currently get_route does not attemp to find the cheapest route (according to rate)
@@ -160,12 +196,23 @@ where
             Some((e_start, e_end)) => (Some(e_start), Some(e_end)),
             None => (None, None),
         };
+        let min_capacity = capacity.checked_add(constraints.capacity_margin)?;
         let get_neighbors = |cur_node: &N| {
             let cur_node_is_e_start = Some(cur_node) == opt_e_start;
-            self.neighbors_with_send_capacity(cur_node.clone(), capacity)
+            self.neighbors_with_send_capacity(cur_node.clone(), min_capacity)
                 .filter(move |&next_node| !cur_node_is_e_start || Some(next_node) != opt_e_end)
+                .filter(move |&next_node| {
+                    next_node == b || !constraints.blacklist.contains(next_node)
+                })
         };
         let route = bfs(a, b, get_neighbors)?;
+
+        // bfs always finds a shortest route, so no other route can satisfy the length constraint:
+        if let Some(max_route_len) = constraints.opt_max_route_len {
+            if route.len() > max_route_len {
+                return None;
+            }
+        }
         // We assert that we will always have valid capacity here:
         let capacity = self.get_route_capacity(&route).unwrap();
 
@@ -210,6 +257,14 @@ where
             .map(|edge| edge.capacity_edge)
     }
 
+    fn set_recv_capacity(&mut self, a: &N, b: &N, recv_capacity: u128) -> Option<u128> {
+        let edge = self.nodes.get_mut(a)?.edges.get_mut(b)?;
+        Some(mem::replace(
+            &mut edge.capacity_edge.recv_capacity,
+            recv_capacity,
+        ))
+    }
+
     /// Remove an edge from the graph
     fn remove_edge(&mut self, a: &N, b: &N) -> Option<CapacityEdge<u128, T>> {
         let a_edges = match self.nodes.get_mut(a) {
@@ -243,8 +298,26 @@ where
         b: &N,
         capacity: u128,
         opt_exclude: Option<(&N, &N)>,
+        constraints: &RouteConstraints<N, u128>,
+    ) -> Vec<CapacityMultiRoute<N, u128, T>> {
+        option_to_vec(self.get_multi_route(a, b, capacity, opt_exclude, constraints))
+    }
+
+    fn get_ranked_routes(
+        &self,
+        a: &N,
+        b: &N,
+        capacity: u128,
+        opt_exclude: Option<(&N, &N)>,
+        max_routes: usize,
+        constraints: &RouteConstraints<N, u128>,
     ) -> Vec<CapacityMultiRoute<N, u128, T>> {
-        option_to_vec(self.get_multi_route(a, b, capacity, opt_exclude))
+        capacity_ranked_routes(self, a, b, capacity, opt_exclude, max_routes, constraints)
+            .into_iter()
+            .map(|capacity_route| CapacityMultiRoute {
+                routes: vec![capacity_route],
+            })
+            .collect()
     }
 
     fn tick(&mut self, a: &N) {
@@ -252,13 +325,32 @@ where
             node_edges.tick();
         }
     }
+
+    fn size(&self) -> (usize, usize) {
+        let num_edges = self
+            .nodes
+            .values()
+            .map(|node_edges| node_edges.edges.len())
+            .sum();
+        (self.nodes.len(), num_edges)
+    }
+
+    fn get_edges(&self, opt_node: Option<&N>) -> Vec<(N, N, CapacityEdge<u128, T>)> {
+        self.edges()
+            .filter(|(a, b, _capacity_edge)| match opt_node {
+                Some(node) => *a == node || *b == node,
+                None => true,
+            })
+            .map(|(a, b, capacity_edge)| (a.clone(), b.clone(), capacity_edge.clone()))
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use super::super::test_utils::ConstRate;
+    use crate::test_utils::ConstRate;
 
     #[test]
     fn test_get_send_capacity_basic() {
@@ -298,6 +390,65 @@ mod tests {
         assert_eq!(cg.nodes.len(), 1);
     }
 
+    #[test]
+    fn test_set_recv_capacity() {
+        let mut cg = SimpleCapacityGraph::<u32, ConstRate>::new();
+        assert_eq!(cg.set_recv_capacity(&0, &1, 10), None);
+        // Setting the capacity does not create the edge:
+        assert_eq!(cg.size(), (0, 0));
+
+        cg.update_edge(0, 1, CapacityEdge::new(20, ConstRate(1)));
+        assert_eq!(cg.set_recv_capacity(&0, &1, 10), Some(20));
+        assert_eq!(
+            cg.remove_edge(&0, &1),
+            Some(CapacityEdge::new(10, ConstRate(1)))
+        );
+    }
+
+    #[test]
+    fn test_size() {
+        let mut cg = SimpleCapacityGraph::<u32, ConstRate>::new();
+        assert_eq!(cg.size(), (0, 0));
+        cg.update_edge(0, 1, CapacityEdge::new(20, ConstRate(1)));
+        cg.update_edge(0, 2, CapacityEdge::new(20, ConstRate(1)));
+        cg.update_edge(1, 0, CapacityEdge::new(20, ConstRate(1)));
+        assert_eq!(cg.size(), (2, 3));
+
+        cg.remove_node(&0);
+        assert_eq!(cg.size(), (1, 1));
+    }
+
+    #[test]
+    fn test_get_edges() {
+        let mut cg = SimpleCapacityGraph::<u32, ConstRate>::new();
+        cg.update_edge(0, 1, CapacityEdge::new(20, ConstRate(1)));
+        cg.update_edge(1, 2, CapacityEdge::new(30, ConstRate(2)));
+        cg.update_edge(3, 4, CapacityEdge::new(40, ConstRate(3)));
+
+        let mut edges = cg.get_edges(None);
+        edges.sort_by_key(|(a, b, _capacity_edge)| (*a, *b));
+        assert_eq!(
+            edges,
+            vec![
+                (0, 1, CapacityEdge::new(20, ConstRate(1))),
+                (1, 2, CapacityEdge::new(30, ConstRate(2))),
+                (3, 4, CapacityEdge::new(40, ConstRate(3))),
+            ]
+        );
+
+        // Edges from or to node 1:
+        let mut edges = cg.get_edges(Some(&1));
+        edges.sort_by_key(|(a, b, _capacity_edge)| (*a, *b));
+        assert_eq!(
+            edges,
+            vec![
+                (0, 1, CapacityEdge::new(20, ConstRate(1))),
+                (1, 2, CapacityEdge::new(30, ConstRate(2))),
+            ]
+        );
+        assert!(cg.get_edges(Some(&5)).is_empty());
+    }
+
     fn example_capacity_graph() -> SimpleCapacityGraph<u32, ConstRate> {
         /*
          * Example graph:
@@ -335,51 +486,129 @@ mod tests {
     #[test]
     fn test_get_multi_route() {
         let cg = example_capacity_graph();
+        let no_constraints = RouteConstraints::default();
 
-        let multi_route = cg.get_multi_route(&2, &5, 29, None).unwrap();
+        let multi_route = cg
+            .get_multi_route(&2, &5, 29, None, &no_constraints)
+            .unwrap();
         assert_eq!(multi_route.routes[0].route, vec![2, 5]);
         assert_eq!(multi_route.routes[0].capacity, 30);
 
-        let multi_route = cg.get_multi_route(&2, &5, 30, None).unwrap();
+        let multi_route = cg
+            .get_multi_route(&2, &5, 30, None, &no_constraints)
+            .unwrap();
         assert_eq!(multi_route.routes[0].route, vec![2, 5]);
         assert_eq!(multi_route.routes[0].capacity, 30);
 
-        assert!(cg.get_multi_route(&2, &5, 31, None).is_none());
+        assert!(cg
+            .get_multi_route(&2, &5, 31, None, &no_constraints)
+            .is_none());
 
-        let multi_route = cg.get_multi_route(&0, &5, 25, None).unwrap();
+        let multi_route = cg
+            .get_multi_route(&0, &5, 25, None, &no_constraints)
+            .unwrap();
         assert_eq!(multi_route.routes[0].route, vec![0, 1, 3, 4, 2, 5]);
         assert_eq!(multi_route.routes[0].capacity, 30);
 
-        let multi_route = cg.get_multi_route(&0, &5, 29, None).unwrap();
+        let multi_route = cg
+            .get_multi_route(&0, &5, 29, None, &no_constraints)
+            .unwrap();
         assert_eq!(multi_route.routes[0].route, vec![0, 1, 3, 4, 2, 5]);
         assert_eq!(multi_route.routes[0].capacity, 30);
 
-        let multi_route = cg.get_multi_route(&0, &5, 30, None).unwrap();
+        let multi_route = cg
+            .get_multi_route(&0, &5, 30, None, &no_constraints)
+            .unwrap();
         assert_eq!(multi_route.routes[0].route, vec![0, 1, 3, 4, 2, 5]);
         assert_eq!(multi_route.routes[0].capacity, 30);
 
-        assert!(cg.get_multi_route(&0, &5, 31, None).is_none());
+        assert!(cg
+            .get_multi_route(&0, &5, 31, None, &no_constraints)
+            .is_none());
 
         // Block an essential edge:
-        assert!(cg.get_multi_route(&0, &5, 25, Some((&3, &4))).is_none());
+        assert!(cg
+            .get_multi_route(&0, &5, 25, Some((&3, &4)), &no_constraints)
+            .is_none());
 
         // Block an essential edge but the at the reversed direction:
-        let multi_route = cg.get_multi_route(&0, &5, 25, Some((&4, &3))).unwrap();
+        let multi_route = cg
+            .get_multi_route(&0, &5, 25, Some((&4, &3)), &no_constraints)
+            .unwrap();
         assert_eq!(multi_route.routes[0].route, vec![0, 1, 3, 4, 2, 5]);
         assert_eq!(multi_route.routes[0].capacity, 30);
 
         // Block an edge not used for the route:
-        let multi_route = cg.get_multi_route(&0, &5, 25, Some((&1, &2))).unwrap();
+        let multi_route = cg
+            .get_multi_route(&0, &5, 25, Some((&1, &2)), &no_constraints)
+            .unwrap();
         assert_eq!(multi_route.routes[0].route, vec![0, 1, 3, 4, 2, 5]);
         assert_eq!(multi_route.routes[0].capacity, 30);
 
         // Use excluded edge to find a loop from 1 to 1:
-        let multi_route = cg.get_multi_route(&2, &1, 6, Some((&2, &1))).unwrap();
+        let multi_route = cg
+            .get_multi_route(&2, &1, 6, Some((&2, &1)), &no_constraints)
+            .unwrap();
         assert_eq!(multi_route.routes[0].route, vec![2, 4, 3, 1]);
         assert_eq!(multi_route.routes[0].capacity, 6);
 
         // Request for too much capacity:
-        assert!(cg.get_multi_route(&2, &1, 7, Some((&2, &1))).is_none());
+        assert!(cg
+            .get_multi_route(&2, &1, 7, Some((&2, &1)), &no_constraints)
+            .is_none());
+    }
+
+    #[test]
+    fn test_get_multi_route_constraints() {
+        let cg = example_capacity_graph();
+
+        // The only route from 0 to 5 is [0, 1, 3, 4, 2, 5]:
+        let constraints = RouteConstraints {
+            blacklist: vec![1, 3],
+            opt_max_route_len: None,
+            capacity_margin: 0,
+        };
+        assert!(cg.get_multi_route(&0, &5, 25, None, &constraints).is_none());
+
+        // Source and destination can not be blacklisted:
+        let constraints = RouteConstraints {
+            blacklist: vec![0, 5],
+            opt_max_route_len: None,
+            capacity_margin: 0,
+        };
+        let multi_route = cg.get_multi_route(&0, &5, 25, None, &constraints).unwrap();
+        assert_eq!(multi_route.routes[0].route, vec![0, 1, 3, 4, 2, 5]);
+
+        let constraints = RouteConstraints {
+            blacklist: Vec::new(),
+            opt_max_route_len: Some(5),
+            capacity_margin: 0,
+        };
+        assert!(cg.get_multi_route(&0, &5, 25, None, &constraints).is_none());
+
+        let constraints = RouteConstraints {
+            blacklist: Vec::new(),
+            opt_max_route_len: Some(6),
+            capacity_margin: 0,
+        };
+        let multi_route = cg.get_multi_route(&0, &5, 25, None, &constraints).unwrap();
+        assert_eq!(multi_route.routes[0].route, vec![0, 1, 3, 4, 2, 5]);
+
+        // The route can carry 30 credits:
+        let constraints = RouteConstraints {
+            blacklist: Vec::new(),
+            opt_max_route_len: None,
+            capacity_margin: 5,
+        };
+        let multi_route = cg.get_multi_route(&0, &5, 25, None, &constraints).unwrap();
+        assert_eq!(multi_route.routes[0].capacity, 30);
+
+        let constraints = RouteConstraints {
+            blacklist: Vec::new(),
+            opt_max_route_len: None,
+            capacity_margin: 6,
+        };
+        assert!(cg.get_multi_route(&0, &5, 25, None, &constraints).is_none());
     }
 
     #[test]
@@ -392,11 +621,17 @@ mod tests {
         cg.update_edge(2, 3, CapacityEdge::new(10, ConstRate(1)));
         cg.update_edge(3, 2, CapacityEdge::new(30, ConstRate(1)));
 
-        let multi_route = cg.get_multi_route(&0, &1, 30, None).unwrap();
+        let no_constraints = RouteConstraints::default();
+
+        let multi_route = cg
+            .get_multi_route(&0, &1, 30, None, &no_constraints)
+            .unwrap();
         assert_eq!(multi_route.routes[0].route, vec![0, 1]);
         assert_eq!(multi_route.routes[0].capacity, 30);
 
-        let multi_route = cg.get_multi_route(&2, &3, 30, None).unwrap();
+        let multi_route = cg
+            .get_multi_route(&2, &3, 30, None, &no_constraints)
+            .unwrap();
         assert_eq!(multi_route.routes[0].route, vec![2, 3]);
         assert_eq!(multi_route.routes[0].capacity, 30);
 
@@ -404,20 +639,28 @@ mod tests {
         for _ in 0..max_edge_age - 1 {
             cg.tick(&0);
 
-            let multi_route = cg.get_multi_route(&0, &1, 30, None).unwrap();
+            let multi_route = cg
+                .get_multi_route(&0, &1, 30, None, &no_constraints)
+                .unwrap();
             assert_eq!(multi_route.routes[0].route, vec![0, 1]);
             assert_eq!(multi_route.routes[0].capacity, 30);
 
-            let multi_route = cg.get_multi_route(&2, &3, 30, None).unwrap();
+            let multi_route = cg
+                .get_multi_route(&2, &3, 30, None, &no_constraints)
+                .unwrap();
             assert_eq!(multi_route.routes[0].route, vec![2, 3]);
             assert_eq!(multi_route.routes[0].capacity, 30);
         }
 
         // At this point 0->1 and 1->0 should expire, but 2->3 and 3->2 don't expire:
         cg.tick(&0);
-        assert!(cg.get_multi_route(&0, &1, 30, None).is_none());
+        assert!(cg
+            .get_multi_route(&0, &1, 30, None, &no_constraints)
+            .is_none());
 
-        let multi_route = cg.get_multi_route(&2, &3, 30, None).unwrap();
+        let multi_route = cg
+            .get_multi_route(&2, &3, 30, None, &no_constraints)
+            .unwrap();
         assert_eq!(multi_route.routes[0].route, vec![2, 3]);
         assert_eq!(multi_route.routes[0].capacity, 30);
     }
@@ -1,10 +1,9 @@
-use crate::graph::capacity_graph::LinearRate;
+use crate::capacity_graph::LinearRate;
 
 /// A contant rate, used for testing
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ConstRate(pub u32);
 
-#[cfg(test)]
 impl LinearRate for ConstRate {
     type K = u32;
 
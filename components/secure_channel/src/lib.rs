@@ -15,6 +15,7 @@ extern crate common;
 #[macro_use]
 extern crate log;
 
+mod resumption;
 mod secure_channel;
 mod state;
 mod types;
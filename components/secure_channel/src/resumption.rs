@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+use futures::channel::{mpsc, oneshot};
+use futures::task::{Spawn, SpawnExt};
+use futures::{future, stream, SinkExt, Stream, StreamExt};
+
+use common::select_streams::select_streams;
+
+use crypto::hash::sha_512_256;
+use crypto::sym_encrypt::{SymmetricKey, SYMMETRIC_KEY_LEN};
+
+use proto::crypto::{HashResult, PublicKey, RandValue};
+
+use timer::TimerClient;
+
+const RESUMPTION_SECRET_PREFIX: &[u8] = b"RESUMPTION_SECRET";
+const RESUMPTION_TICKET_ID_PREFIX: &[u8] = b"RESUMPTION_TICKET_ID";
+const RESUMPTION_KEY_PREFIX: &[u8] = b"RESUMPTION_KEY";
+
+/// A secret shared by the two sides of a secure channel.
+/// Allows to resume the channel later without performing a full handshake.
+#[derive(Clone)]
+pub struct ResumptionTicket {
+    pub remote_public_key: PublicKey,
+    pub ticket_id: HashResult,
+    /// The protocol version of the channel this ticket was taken from.
+    pub version: u32,
+    /// Amount of resumptions that occurred since the last full handshake.
+    pub resumptions: usize,
+    secret: HashResult,
+}
+
+impl ResumptionTicket {
+    /// Derive a ticket from the symmetric keys of an established channel.
+    /// Both sides of the channel derive the same ticket, because the sending key of one side is
+    /// the receiving key of the other side.
+    pub fn from_keys(
+        remote_public_key: PublicKey,
+        send_key: &SymmetricKey,
+        recv_key: &SymmetricKey,
+        version: u32,
+        resumptions: usize,
+    ) -> Self {
+        let (first_key, second_key) = if send_key <= recv_key {
+            (send_key, recv_key)
+        } else {
+            (recv_key, send_key)
+        };
+
+        let mut secret_data = Vec::new();
+        secret_data.extend_from_slice(RESUMPTION_SECRET_PREFIX);
+        secret_data.extend_from_slice(first_key);
+        secret_data.extend_from_slice(second_key);
+        let secret = sha_512_256(&secret_data);
+
+        let mut ticket_id_data = Vec::new();
+        ticket_id_data.extend_from_slice(RESUMPTION_TICKET_ID_PREFIX);
+        ticket_id_data.extend_from_slice(&secret);
+        let ticket_id = sha_512_256(&ticket_id_data);
+
+        ResumptionTicket {
+            remote_public_key,
+            ticket_id,
+            version,
+            resumptions,
+            secret,
+        }
+    }
+
+    /// Derive a symmetric key for the messages sent by the side that chose `sender_rand_nonce`.
+    fn derive_key(
+        &self,
+        version: u32,
+        sender_rand_nonce: &RandValue,
+        receiver_rand_nonce: &RandValue,
+    ) -> SymmetricKey {
+        let mut key_data = Vec::new();
+        key_data.extend_from_slice(RESUMPTION_KEY_PREFIX);
+        key_data.extend_from_slice(&self.secret);
+        key_data.write_u32::<BigEndian>(version).unwrap();
+        key_data.extend_from_slice(sender_rand_nonce);
+        key_data.extend_from_slice(receiver_rand_nonce);
+
+        let mut key = [0u8; SYMMETRIC_KEY_LEN];
+        key.copy_from_slice(&sha_512_256(&key_data)[..SYMMETRIC_KEY_LEN]);
+        SymmetricKey::from(&key)
+    }
+
+    /// Derive fresh (send_key, recv_key) for a resumed channel.
+    /// The random nonces of both sides make sure that a resumed channel never reuses old keys.
+    /// The negotiated protocol version is mixed into the keys, so that the two sides can only
+    /// communicate if they agree on the version.
+    pub fn derive_keys(
+        &self,
+        version: u32,
+        local_rand_nonce: &RandValue,
+        remote_rand_nonce: &RandValue,
+    ) -> (SymmetricKey, SymmetricKey) {
+        (
+            self.derive_key(version, local_rand_nonce, remote_rand_nonce),
+            self.derive_key(version, remote_rand_nonce, local_rand_nonce),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum TicketsError {
+    SpawnError,
+}
+
+pub enum TicketsRequest {
+    /// Take the ticket for a remote side (If exists).
+    /// If a ticket id is specified, only a ticket with a matching id will be taken.
+    Take(
+        PublicKey,
+        Option<HashResult>,
+        oneshot::Sender<Option<ResumptionTicket>>,
+    ),
+    /// Store a ticket, replacing any previous ticket for the same remote side.
+    Store(ResumptionTicket),
+}
+
+/// A client to the tickets service.
+/// Keeps resumption tickets of recently closed secure channels.
+#[derive(Clone)]
+pub struct TicketsClient {
+    requests_sender: mpsc::Sender<TicketsRequest>,
+}
+
+impl TicketsClient {
+    pub fn new(requests_sender: mpsc::Sender<TicketsRequest>) -> Self {
+        TicketsClient { requests_sender }
+    }
+
+    /// Take a ticket out of the tickets service. Every ticket may be used only once.
+    pub async fn take(
+        &mut self,
+        remote_public_key: PublicKey,
+        opt_ticket_id: Option<HashResult>,
+    ) -> Option<ResumptionTicket> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        let request = TicketsRequest::Take(remote_public_key, opt_ticket_id, response_sender);
+        self.requests_sender.send(request).await.ok()?;
+        response_receiver.await.ok()?
+    }
+
+    pub async fn store(&mut self, ticket: ResumptionTicket) {
+        let _ = self
+            .requests_sender
+            .send(TicketsRequest::Store(ticket))
+            .await;
+    }
+}
+
+enum TicketsEvent {
+    Request(TicketsRequest),
+    TimerTick,
+    /// Any of the receivers was closed:
+    ReceiverClosed,
+}
+
+async fn tickets_loop<TS, RS>(timer_stream: TS, incoming_requests: RS, ticks_to_resume: usize)
+where
+    TS: Stream + Unpin,
+    RS: Stream<Item = TicketsRequest> + Unpin,
+{
+    let timer_stream = timer_stream
+        .map(|_| TicketsEvent::TimerTick)
+        .chain(stream::once(future::ready(TicketsEvent::ReceiverClosed)));
+    let incoming_requests = incoming_requests
+        .map(TicketsEvent::Request)
+        .chain(stream::once(future::ready(TicketsEvent::ReceiverClosed)));
+
+    // remote_public_key -> (ticket, ticks_left)
+    let mut tickets: HashMap<PublicKey, (ResumptionTicket, usize)> = HashMap::new();
+    let mut events = select_streams![timer_stream, incoming_requests];
+
+    while let Some(event) = events.next().await {
+        match event {
+            TicketsEvent::Request(TicketsRequest::Take(
+                remote_public_key,
+                opt_ticket_id,
+                response_sender,
+            )) => {
+                let is_match = match (tickets.get(&remote_public_key), &opt_ticket_id) {
+                    (Some((ticket, _)), Some(ticket_id)) => &ticket.ticket_id == ticket_id,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                };
+                let opt_ticket = if is_match {
+                    tickets
+                        .remove(&remote_public_key)
+                        .map(|(ticket, _ticks_left)| ticket)
+                } else {
+                    None
+                };
+                let _ = response_sender.send(opt_ticket);
+            }
+            TicketsEvent::Request(TicketsRequest::Store(ticket)) => {
+                tickets.insert(ticket.remote_public_key.clone(), (ticket, ticks_to_resume));
+            }
+            TicketsEvent::TimerTick => {
+                for (_ticket, ticks_left) in tickets.values_mut() {
+                    *ticks_left = ticks_left.saturating_sub(1);
+                }
+                tickets.retain(|_, (_ticket, ticks_left)| *ticks_left > 0);
+            }
+            TicketsEvent::ReceiverClosed => {
+                info!("tickets_loop(): ReceiverClosed");
+                break;
+            }
+        }
+    }
+}
+
+/// Spawn a tickets service. Tickets stored in the service expire after `ticks_to_resume` time
+/// ticks.
+pub fn create_tickets_service<S>(
+    mut timer_client: TimerClient,
+    ticks_to_resume: usize,
+    spawner: S,
+) -> Result<TicketsClient, TicketsError>
+where
+    S: Spawn,
+{
+    let (requests_sender, incoming_requests) = mpsc::channel(0);
+
+    let tickets_fut = async move {
+        let timer_stream = match timer_client.request_timer_stream().await {
+            Ok(timer_stream) => timer_stream,
+            Err(e) => {
+                warn!(
+                    "create_tickets_service(): request_timer_stream() error: {:?}",
+                    e
+                );
+                return;
+            }
+        };
+        tickets_loop(timer_stream, incoming_requests, ticks_to_resume).await;
+    };
+
+    spawner
+        .spawn(tickets_fut)
+        .map_err(|_| TicketsError::SpawnError)?;
+
+    Ok(TicketsClient::new(requests_sender))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::{block_on, ThreadPool};
+
+    fn dummy_ticket(public_key_seed: u8) -> ResumptionTicket {
+        ResumptionTicket::from_keys(
+            PublicKey::from(&[public_key_seed; PublicKey::len()]),
+            &SymmetricKey::from(&[1; SYMMETRIC_KEY_LEN]),
+            &SymmetricKey::from(&[2; SYMMETRIC_KEY_LEN]),
+            1,
+            0,
+        )
+    }
+
+    #[test]
+    fn test_resumption_ticket_symmetric() {
+        let public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let key1 = SymmetricKey::from(&[1; SYMMETRIC_KEY_LEN]);
+        let key2 = SymmetricKey::from(&[2; SYMMETRIC_KEY_LEN]);
+
+        let ticket1 = ResumptionTicket::from_keys(public_key.clone(), &key1, &key2, 1, 0);
+        let ticket2 = ResumptionTicket::from_keys(public_key.clone(), &key2, &key1, 1, 0);
+        assert_eq!(ticket1.ticket_id, ticket2.ticket_id);
+        assert_ne!(ticket1.ticket_id, ticket1.secret);
+
+        let rand_nonce1 = RandValue::from(&[3; RandValue::len()]);
+        let rand_nonce2 = RandValue::from(&[4; RandValue::len()]);
+        let (send_key1, recv_key1) = ticket1.derive_keys(1, &rand_nonce1, &rand_nonce2);
+        let (send_key2, recv_key2) = ticket2.derive_keys(1, &rand_nonce2, &rand_nonce1);
+        assert_eq!(send_key1, recv_key2);
+        assert_eq!(recv_key1, send_key2);
+        assert_ne!(send_key1, recv_key1);
+
+        // The keys of a resumed channel depend on the random nonces:
+        let rand_nonce3 = RandValue::from(&[5; RandValue::len()]);
+        let (send_key3, _recv_key3) = ticket1.derive_keys(1, &rand_nonce1, &rand_nonce3);
+        assert_ne!(send_key1, send_key3);
+
+        // The keys also depend on the protocol version:
+        let (send_key4, _recv_key4) = ticket1.derive_keys(2, &rand_nonce1, &rand_nonce2);
+        assert_ne!(send_key1, send_key4);
+    }
+
+    async fn task_tickets_loop<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (mut tick_sender, timer_stream) = mpsc::channel::<()>(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+
+        let ticks_to_resume = 3;
+        spawner
+            .spawn(tickets_loop(
+                timer_stream,
+                incoming_requests,
+                ticks_to_resume,
+            ))
+            .unwrap();
+        let mut tickets_client = TicketsClient::new(requests_sender);
+
+        let ticket = dummy_ticket(0xaa);
+        let remote_public_key = ticket.remote_public_key.clone();
+        let other_ticket_id = HashResult::from(&[0xbb; HashResult::len()]);
+
+        // Tickets are single use:
+        tickets_client.store(ticket.clone()).await;
+        let taken = tickets_client
+            .take(remote_public_key.clone(), None)
+            .await
+            .unwrap();
+        assert_eq!(taken.ticket_id, ticket.ticket_id);
+        assert!(tickets_client
+            .take(remote_public_key.clone(), None)
+            .await
+            .is_none());
+
+        // A ticket is only taken if the requested ticket id matches:
+        tickets_client.store(ticket.clone()).await;
+        assert!(tickets_client
+            .take(remote_public_key.clone(), Some(other_ticket_id.clone()))
+            .await
+            .is_none());
+        assert!(tickets_client
+            .take(remote_public_key.clone(), Some(ticket.ticket_id.clone()))
+            .await
+            .is_some());
+
+        // A ticket is still valid before ticks_to_resume ticks have passed.
+        // (A failed take() makes sure the ticket was stored before we send ticks)
+        tickets_client.store(ticket.clone()).await;
+        assert!(tickets_client
+            .take(remote_public_key.clone(), Some(other_ticket_id.clone()))
+            .await
+            .is_none());
+        for _ in 0..ticks_to_resume - 1 {
+            tick_sender.send(()).await.unwrap();
+        }
+        assert!(tickets_client
+            .take(remote_public_key.clone(), None)
+            .await
+            .is_some());
+
+        // Tickets expire after ticks_to_resume ticks.
+        // The last tick makes sure all the previous ticks were processed:
+        tickets_client.store(ticket.clone()).await;
+        assert!(tickets_client
+            .take(remote_public_key.clone(), Some(other_ticket_id.clone()))
+            .await
+            .is_none());
+        for _ in 0..=ticks_to_resume {
+            tick_sender.send(()).await.unwrap();
+        }
+        assert!(tickets_client
+            .take(remote_public_key.clone(), None)
+            .await
+            .is_none());
+    }
+
+    #[test]
+    fn test_tickets_loop() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_tickets_loop(thread_pool.clone()));
+    }
+}
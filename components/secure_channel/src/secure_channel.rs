@@ -1,7 +1,7 @@
 use std::marker::Unpin;
 
 use futures::task::{Spawn, SpawnExt};
-use futures::{future, stream, FutureExt, Sink, SinkExt, Stream, StreamExt};
+use futures::{future, stream, Sink, SinkExt, Stream, StreamExt};
 
 use futures::channel::mpsc;
 
@@ -16,8 +16,9 @@ use timer::TimerClient;
 
 use proto::crypto::PublicKey;
 use proto::proto_ser::{ProtoDeserialize, ProtoSerialize, ProtoSerializeError};
-use proto::secure_channel::messages::{ExchangeDh, ExchangeRandNonce};
+use proto::secure_channel::messages::{ExchangeDh, ExchangeRandNonce, SecureChannelReport};
 
+use crate::resumption::{create_tickets_service, TicketsClient};
 use crate::state::{ScState, ScStateError, ScStateInitial};
 use crate::types::{EncryptedData, PlainData};
 
@@ -28,6 +29,7 @@ enum SecureChannelError {
     ReaderClosed,
     ProtoSerializeError(ProtoSerializeError),
     HandleExchangeRandNonceError(ScStateError),
+    ResumeError(ScStateError),
     HandleExchangeScStateError(ScStateError),
     UnexpectedRemotePublicKey,
     RequestTimerStreamError,
@@ -40,6 +42,7 @@ async fn initial_exchange<EK, M: 'static, K: 'static, R: CryptoRandom + 'static>
     mut reader: M,
     identity_client: IdentityClient,
     opt_expected_remote: Option<PublicKey>,
+    mut opt_tickets_client: Option<TicketsClient>,
     rng: R,
 ) -> Result<(ScState, K, M), SecureChannelError>
 where
@@ -52,20 +55,69 @@ where
         .await
         .map_err(|_| SecureChannelError::IdentityFailure)?;
 
-    let (dh_state_initial, exchange_rand_nonce) =
-        ScStateInitial::new(local_public_key, opt_expected_remote.clone(), &rng);
-    let ser_exchange_rand_nonce = exchange_rand_nonce.proto_serialize();
-    writer
-        .send(ser_exchange_rand_nonce)
-        .await
-        .map_err(|_| SecureChannelError::WriterError)?;
-
-    let reader_message = reader
-        .next()
-        .await
-        .ok_or(SecureChannelError::ReaderClosed)?;
+    let (dh_state_initial, exchange_rand_nonce) = match &opt_expected_remote {
+        Some(expected_remote) => {
+            // We know who the remote side is. We offer to resume a previous channel, if possible:
+            let opt_ticket = match &mut opt_tickets_client {
+                Some(tickets_client) => tickets_client.take(expected_remote.clone(), None).await,
+                None => None,
+            };
+            let (dh_state_initial, local_exchange_rand_nonce) = ScStateInitial::new(
+                local_public_key,
+                opt_expected_remote.clone(),
+                opt_ticket,
+                &rng,
+            );
+            writer
+                .send(local_exchange_rand_nonce.proto_serialize())
+                .await
+                .map_err(|_| SecureChannelError::WriterError)?;
+
+            let reader_message = reader
+                .next()
+                .await
+                .ok_or(SecureChannelError::ReaderClosed)?;
+            let exchange_rand_nonce = ExchangeRandNonce::proto_deserialize(&reader_message)?;
+            (dh_state_initial, exchange_rand_nonce)
+        }
+        None => {
+            // We don't know who the remote side is. We first wait for the remote side
+            // to find out if it wants to resume a previous channel:
+            let reader_message = reader
+                .next()
+                .await
+                .ok_or(SecureChannelError::ReaderClosed)?;
+            let exchange_rand_nonce = ExchangeRandNonce::proto_deserialize(&reader_message)?;
+
+            let opt_ticket = match (&mut opt_tickets_client, &exchange_rand_nonce.opt_ticket_id) {
+                (Some(tickets_client), Some(ticket_id)) => {
+                    tickets_client
+                        .take(
+                            exchange_rand_nonce.src_public_key.clone(),
+                            Some(ticket_id.clone()),
+                        )
+                        .await
+                }
+                _ => None,
+            };
+            let (dh_state_initial, local_exchange_rand_nonce) =
+                ScStateInitial::new(local_public_key, None, opt_ticket, &rng);
+            writer
+                .send(local_exchange_rand_nonce.proto_serialize())
+                .await
+                .map_err(|_| SecureChannelError::WriterError)?;
+            (dh_state_initial, exchange_rand_nonce)
+        }
+    };
+
+    // Both sides offered the same ticket. We can skip the Diffie-Hellman exchange:
+    if let Some(dh_state) = dh_state_initial
+        .try_resume(&exchange_rand_nonce)
+        .map_err(SecureChannelError::ResumeError)?
+    {
+        return Ok((dh_state, writer, reader));
+    }
 
-    let exchange_rand_nonce = ExchangeRandNonce::proto_deserialize(&reader_message)?;
     let (dh_state_half, exchange_dh) = dh_state_initial
         .handle_exchange_rand_nonce(exchange_rand_nonce, identity_client.clone(), rng.clone())
         .await
@@ -103,6 +155,20 @@ enum SecureChannelEvent {
     ReceiverClosed,
 }
 
+/// Send a diagnostic report about the channel with `remote_public_key`, if anyone listens.
+async fn send_report(
+    opt_report_sender: &mut Option<mpsc::Sender<(PublicKey, SecureChannelReport)>>,
+    remote_public_key: &PublicKey,
+    report: SecureChannelReport,
+) {
+    if let Some(report_sender) = opt_report_sender {
+        // Losing a report should never break the channel:
+        let _ = report_sender
+            .send((remote_public_key.clone(), report))
+            .await;
+    }
+}
+
 async fn secure_channel_loop<EK, M: 'static, K: 'static, R: CryptoRandom + 'static>(
     mut dh_state: ScState,
     mut writer: K,
@@ -112,6 +178,7 @@ async fn secure_channel_loop<EK, M: 'static, K: 'static, R: CryptoRandom + 'stat
     rng: R,
     ticks_to_rekey: usize,
     mut timer_client: TimerClient,
+    mut opt_report_sender: Option<mpsc::Sender<(PublicKey, SecureChannelReport)>>,
 ) -> Result<(), SecureChannelError>
 where
     R: CryptoRandom,
@@ -142,17 +209,45 @@ where
             SecureChannelEvent::ReceiverClosed,
         )));
 
+    let remote_public_key = dh_state.get_remote_public_key().clone();
+    // Let the users of the channel know which protocol features they may use:
+    send_report(
+        &mut opt_report_sender,
+        &remote_public_key,
+        SecureChannelReport::Established(dh_state.get_version()),
+    )
+    .await;
+
     let mut cur_ticks_to_rekey = ticks_to_rekey;
     let mut events = select_streams![reader, from_user, timer_stream];
 
     while let Some(event) = events.next().await {
         match event {
             SecureChannelEvent::Reader(data) => {
-                let hi_output = dh_state
-                    .handle_incoming(&EncryptedData(data), &rng)
-                    .map_err(|_| SecureChannelError::HandleIncomingError)?;
+                let hi_output = match dh_state.handle_incoming(&EncryptedData(data), &rng) {
+                    Ok(hi_output) => hi_output,
+                    Err(e) => {
+                        let opt_report = match e {
+                            ScStateError::DecryptionFailure => {
+                                Some(SecureChannelReport::DecryptionFailure)
+                            }
+                            ScStateError::NonceReplay => Some(SecureChannelReport::NonceReplay),
+                            _ => None,
+                        };
+                        if let Some(report) = opt_report {
+                            send_report(&mut opt_report_sender, &remote_public_key, report).await;
+                        }
+                        return Err(SecureChannelError::HandleIncomingError);
+                    }
+                };
                 if hi_output.rekey_occurred {
                     cur_ticks_to_rekey = ticks_to_rekey;
+                    send_report(
+                        &mut opt_report_sender,
+                        &remote_public_key,
+                        SecureChannelReport::Rekey,
+                    )
+                    .await;
                 }
                 if let Some(send_message) = hi_output.opt_send_message {
                     writer
@@ -208,14 +303,22 @@ where
 ///
 /// `ticks_to_rekey` is the amount of time ticks it takes to issue a rekey, changing the symmetric
 /// key used for the encryption.
+///
+/// `opt_tickets_client` keeps resumption tickets of closed channels. If both sides still hold
+/// the ticket of a previous channel, the channel is resumed without a full handshake.
+///
+/// Decryption failures, nonce replays and rekeys of the channel are reported through
+/// `opt_report_sender`.
 async fn create_secure_channel<EK, M, K, R, S>(
     writer: K,
     reader: M,
     identity_client: IdentityClient,
     opt_expected_remote: Option<PublicKey>,
+    opt_tickets_client: Option<TicketsClient>,
     rng: R,
     timer_client: TimerClient,
     ticks_to_rekey: usize,
+    opt_report_sender: Option<mpsc::Sender<(PublicKey, SecureChannelReport)>>,
     spawner: S,
 ) -> Result<(PublicKey, ConnPairVec), SecureChannelError>
 where
@@ -230,11 +333,13 @@ where
         reader,
         identity_client,
         opt_expected_remote,
+        opt_tickets_client.clone(),
         rng.clone(),
     )
     .await?;
 
     let remote_public_key = dh_state.get_remote_public_key().clone();
+    let opt_resumption_ticket = dh_state.get_resumption_ticket().cloned();
 
     let (user_sender, from_user) = mpsc::channel::<Vec<u8>>(1);
     let (to_user, user_receiver) = mpsc::channel::<Vec<u8>>(1);
@@ -248,13 +353,20 @@ where
         rng.clone(),
         ticks_to_rekey,
         timer_client,
+        opt_report_sender,
     );
 
-    let sc_loop_report_error = sc_loop.map(|res| {
-        if let Err(e) = res {
+    let sc_loop_report_error = async move {
+        if let Err(e) = sc_loop.await {
             warn!("Secure Channel error: {:?}", e);
         }
-    });
+        // Allow the remote side to resume this channel for a while:
+        if let (Some(mut tickets_client), Some(resumption_ticket)) =
+            (opt_tickets_client, opt_resumption_ticket)
+        {
+            tickets_client.store(resumption_ticket).await;
+        }
+    };
     spawner
         .spawn(sc_loop_report_error)
         .map_err(|_| SecureChannelError::SpawnError)?;
@@ -271,25 +383,73 @@ pub struct SecureChannel<R, S> {
     rng: R,
     timer_client: TimerClient,
     ticks_to_rekey: usize,
+    opt_tickets_client: Option<TicketsClient>,
+    opt_report_sender: Option<mpsc::Sender<(PublicKey, SecureChannelReport)>>,
     spawner: S,
 }
 
-impl<R, S> SecureChannel<R, S> {
+impl<R, S> SecureChannel<R, S>
+where
+    S: Spawn + Clone,
+{
+    /// `ticks_to_resume` is the amount of time ticks a closed channel can be resumed without a
+    /// full handshake. A value of 0 disables resumption.
     pub fn new(
         identity_client: IdentityClient,
         rng: R,
         timer_client: TimerClient,
         ticks_to_rekey: usize,
+        ticks_to_resume: usize,
         spawner: S,
     ) -> SecureChannel<R, S> {
+        let opt_tickets_client = if ticks_to_resume > 0 {
+            match create_tickets_service(timer_client.clone(), ticks_to_resume, spawner.clone()) {
+                Ok(tickets_client) => Some(tickets_client),
+                Err(e) => {
+                    warn!(
+                        "SecureChannel::new(): Failed to create tickets service: {:?}",
+                        e
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
         SecureChannel {
             identity_client,
             rng,
             timer_client,
             ticks_to_rekey,
+            opt_tickets_client,
+            opt_report_sender: None,
             spawner,
         }
     }
+
+    /// Like `new`, but decryption failures, nonce replays and rekeys of every resulting channel
+    /// are reported through `report_sender`, together with the public key of the remote side.
+    pub fn new_with_reports(
+        identity_client: IdentityClient,
+        rng: R,
+        timer_client: TimerClient,
+        ticks_to_rekey: usize,
+        ticks_to_resume: usize,
+        report_sender: mpsc::Sender<(PublicKey, SecureChannelReport)>,
+        spawner: S,
+    ) -> SecureChannel<R, S> {
+        let mut secure_channel = SecureChannel::new(
+            identity_client,
+            rng,
+            timer_client,
+            ticks_to_rekey,
+            ticks_to_resume,
+            spawner,
+        );
+        secure_channel.opt_report_sender = Some(report_sender);
+        secure_channel
+    }
 }
 
 impl<R, S> FutTransform for SecureChannel<R, S>
@@ -321,9 +481,11 @@ where
                 receiver,
                 self.identity_client.clone(),
                 opt_expected_remote.clone(),
+                self.opt_tickets_client.clone(),
                 self.rng.clone(),
                 self.timer_client.clone(),
                 self.ticks_to_rekey,
+                self.opt_report_sender.clone(),
                 c_spawner,
             )
             .await
@@ -339,7 +501,7 @@ mod tests {
     use futures::channel::oneshot;
     use futures::executor::{LocalPool, ThreadPool};
     use futures::task::SpawnExt;
-    use futures::Future;
+    use futures::{Future, FutureExt};
 
     use timer::create_timer_incoming;
 
@@ -350,6 +512,8 @@ mod tests {
 
     use proto::crypto::PrivateKey;
 
+    use crate::state::SC_MAX_VERSION;
+
     async fn secure_channel1(
         fut_sc: impl Future<Output = Result<(PublicKey, ConnPairVec), SecureChannelError>> + 'static,
         mut tick_sender: mpsc::Sender<()>,
@@ -367,15 +531,19 @@ mod tests {
         }
         sender.send(vec![0, 1, 2]).await.unwrap();
 
+        // Keep the channel open (So that rekeying can complete) until the remote side closes it:
+        assert!(receiver.next().await.is_none());
+
         output_sender.send(true).unwrap();
     }
 
     async fn secure_channel2(
         fut_sc: impl Future<Output = Result<(PublicKey, ConnPairVec), SecureChannelError>> + 'static,
         _tick_sender: mpsc::Sender<()>,
+        mut report_receiver: mpsc::Receiver<(PublicKey, SecureChannelReport)>,
         output_sender: oneshot::Sender<bool>,
     ) {
-        let (_public_key, conn_pair_vec) = fut_sc.await.unwrap();
+        let (remote_public_key, conn_pair_vec) = fut_sc.await.unwrap();
         let (mut sender, mut receiver) = conn_pair_vec.split();
         let data = receiver.next().await.unwrap();
         assert_eq!(data, vec![0, 1, 2, 3, 4, 5]);
@@ -384,6 +552,16 @@ mod tests {
         let data = receiver.next().await.unwrap();
         assert_eq!(data, vec![0, 1, 2]);
 
+        // The negotiated version is reported first:
+        let (report_public_key, report) = report_receiver.next().await.unwrap();
+        assert_eq!(report_public_key, remote_public_key);
+        assert_eq!(report, SecureChannelReport::Established(SC_MAX_VERSION));
+
+        // Rekeying is reported:
+        let (report_public_key, report) = report_receiver.next().await.unwrap();
+        assert_eq!(report_public_key, remote_public_key);
+        assert_eq!(report, SecureChannelReport::Rekey);
+
         output_sender.send(true).unwrap();
     }
 
@@ -421,15 +599,19 @@ mod tests {
         let (sender2, receiver1) = mpsc::channel::<Vec<u8>>(1);
 
         let ticks_to_rekey: usize = 16;
+        // Reports must never block the channel, even before we read them:
+        let (report_sender2, report_receiver2) = mpsc::channel(8);
 
         let fut_sc1 = create_secure_channel(
             sender1.sink_map_err(|_| ()),
             receiver1,
             identity_client1,
             Some(public_key2),
+            None,
             rng1.clone(),
             timer_client.clone(),
             ticks_to_rekey,
+            None,
             thread_pool.clone(),
         );
 
@@ -438,9 +620,11 @@ mod tests {
             receiver2,
             identity_client2,
             Some(public_key1),
+            None,
             rng2.clone(),
             timer_client.clone(),
             ticks_to_rekey,
+            Some(report_sender2),
             thread_pool.clone(),
         );
 
@@ -458,6 +642,7 @@ mod tests {
             .spawn(secure_channel2(
                 fut_sc2,
                 tick_sender.clone(),
+                report_receiver2,
                 output_sender2,
             ))
             .unwrap();
@@ -1,4 +1,4 @@
-use std::mem;
+use std::{cmp, mem};
 
 use byteorder::{BigEndian, ByteOrder};
 
@@ -7,20 +7,74 @@ use derive_more::From;
 use crypto::dh::DhPrivateKey;
 use crypto::identity::verify_signature;
 use crypto::rand::{CryptoRandom, RandGen};
-use crypto::sym_encrypt::{Decryptor, Encryptor};
+use crypto::sym_encrypt::{Decryptor, Encryptor, SymmetricKey};
 
+use proto::consts::{MAX_SC_RESUMPTIONS, SC_COMPACT_WIRE_VERSION};
 use proto::crypto::{PublicKey, RandValue, Salt, Signature};
 use proto::proto_ser::{ProtoDeserialize, ProtoSerialize, ProtoSerializeError};
 
 use identity::IdentityClient;
+use signature::signature_buff::exchange_dh_signature_buff;
+
 use proto::secure_channel::messages::{
     ChannelContent, ChannelMessage, ExchangeDh, ExchangeRandNonce, Rekey,
 };
 
+use crate::resumption::ResumptionTicket;
 use crate::types::{EncryptedData, PlainData};
 
 const MAX_RAND_PADDING: u16 = 0x100;
 
+/// The secure channel protocol of nodes that predate version negotiation.
+/// Such nodes do not advertise a range of supported versions (It is read as 0 to 0), and sign
+/// over a legacy ExchangeDh buffer. The encryption and framing are the same as in version 1.
+pub const SC_LEGACY_VERSION: u32 = 0;
+/// Lowest version of the secure channel protocol (Encryption and framing) we support.
+pub const SC_MIN_VERSION: u32 = SC_LEGACY_VERSION;
+/// Highest version of the secure channel protocol we support.
+/// A new version can be rolled out gradually: Nodes that support both the old and the new
+/// versions will use the new version only with nodes that support it too.
+///
+/// Version 2: The users of the channel may send friend messages in the compact wire format.
+/// The encryption and framing are the same as in version 1.
+pub const SC_MAX_VERSION: u32 = SC_COMPACT_WIRE_VERSION;
+
+/// Written at the end of our `key_salt` whenever we support versions newer than the legacy
+/// version, but the legacy version was negotiated. The salt is signed also at the legacy
+/// ExchangeDh buffer, and the remote side rejects a legacy handshake that carries this sentinel.
+/// This prevents someone on the way from forcing two new nodes to the legacy version, by
+/// rewriting both versions ranges to (0, 0). (Similar to the downgrade protection of TLS 1.3)
+/// Nodes that predate version negotiation treat the salt as opaque.
+const SC_DOWNGRADE_SENTINEL: &[u8] = b"SCDOWNGR";
+
+/// Does a legacy `key_salt` contain the downgrade sentinel?
+fn has_downgrade_sentinel(key_salt: &Salt) -> bool {
+    key_salt.ends_with(SC_DOWNGRADE_SENTINEL)
+}
+
+/// Write the downgrade sentinel at the end of a salt.
+fn add_downgrade_sentinel(key_salt: &Salt) -> Salt {
+    let mut salt_bytes = [0u8; Salt::len()];
+    salt_bytes.copy_from_slice(key_salt);
+    salt_bytes[Salt::len() - SC_DOWNGRADE_SENTINEL.len()..].copy_from_slice(SC_DOWNGRADE_SENTINEL);
+    Salt::from(&salt_bytes)
+}
+
+/// Pick the highest version supported by both sides.
+/// Returns None if the two ranges of supported versions do not intersect.
+fn negotiate_version(
+    local_min_version: u32,
+    local_max_version: u32,
+    remote_min_version: u32,
+    remote_max_version: u32,
+) -> Option<u32> {
+    let version = cmp::min(local_max_version, remote_max_version);
+    if version < cmp::max(local_min_version, remote_min_version) {
+        return None;
+    }
+    Some(version)
+}
+
 #[derive(Debug, From)]
 pub enum ScStateError {
     UnexpectedRemotePublicKey,
@@ -32,15 +86,29 @@ pub enum ScStateError {
     CreateEncryptorFailure,
     CreateDecryptorFailure,
     DecryptionFailure,
+    /// An incoming message carried a nonce that was already used (Or was reordered)
+    NonceReplay,
     ProtoSerializeError(ProtoSerializeError),
     // DeserializeError,
     RekeyInProgress,
+    /// No protocol version is supported by both sides
+    NoCommonVersion,
+    /// The remote side chose a different protocol version
+    VersionMismatch,
+    /// The legacy version was negotiated, although the remote side supports newer versions.
+    /// Someone on the way tampered with the versions ranges.
+    VersionDowngrade,
 }
 
 pub struct ScStateInitial {
     local_public_key: PublicKey,
     opt_remote_public_key: Option<PublicKey>,
     local_rand_nonce: RandValue,
+    /// The range of versions (min, max) we support
+    local_versions: (u32, u32),
+    /// A ticket from a previous channel, used to resume the channel
+    /// without a full handshake.
+    opt_ticket: Option<ResumptionTicket>,
 }
 
 pub struct ScStateHalf {
@@ -49,6 +117,12 @@ pub struct ScStateHalf {
     local_rand_nonce: RandValue,
     dh_private_key: DhPrivateKey,
     local_salt: Salt,
+    /// The protocol version we chose
+    version: u32,
+    /// The range of versions (min, max) we support
+    local_versions: (u32, u32),
+    /// The range of versions (min, max) advertised by the remote side
+    remote_versions: (u32, u32),
 }
 
 struct PendingRekey {
@@ -60,6 +134,9 @@ pub struct ScState {
     #[allow(unused)]
     local_public_key: PublicKey,
     remote_public_key: PublicKey,
+    /// The negotiated protocol version.
+    /// No version changed the encryption or framing yet.
+    version: u32,
     sender: Encryptor,
     receiver: Decryptor,
     /// We might have an old receiver from the last rekeying.
@@ -67,29 +144,120 @@ pub struct ScState {
     /// messages for the new receiver.
     opt_old_receiver: Option<Decryptor>,
     opt_pending_rekey: Option<PendingRekey>,
+    /// A ticket allowing to resume this channel after it is closed.
+    /// None if the channel was resumed too many times, and a full handshake is required.
+    opt_resumption_ticket: Option<ResumptionTicket>,
+}
+
+/// Pick the highest version supported by us and by the sender of `exchange_rand_nonce`.
+fn negotiate_remote_version(
+    local_versions: (u32, u32),
+    exchange_rand_nonce: &ExchangeRandNonce,
+) -> Result<u32, ScStateError> {
+    let (local_min_version, local_max_version) = local_versions;
+    negotiate_version(
+        local_min_version,
+        local_max_version,
+        exchange_rand_nonce.min_version,
+        exchange_rand_nonce.max_version,
+    )
+    .ok_or(ScStateError::NoCommonVersion)
 }
 
 impl ScStateInitial {
     pub fn new<R: CryptoRandom>(
         local_public_key: PublicKey,
         opt_remote_public_key: Option<PublicKey>,
+        opt_ticket: Option<ResumptionTicket>,
+        rng: &R,
+    ) -> (ScStateInitial, ExchangeRandNonce) {
+        ScStateInitial::with_versions(
+            local_public_key,
+            opt_remote_public_key,
+            opt_ticket,
+            (SC_MIN_VERSION, SC_MAX_VERSION),
+            rng,
+        )
+    }
+
+    /// Start a handshake, supporting only the given range of versions (min, max).
+    fn with_versions<R: CryptoRandom>(
+        local_public_key: PublicKey,
+        opt_remote_public_key: Option<PublicKey>,
+        opt_ticket: Option<ResumptionTicket>,
+        local_versions: (u32, u32),
         rng: &R,
     ) -> (ScStateInitial, ExchangeRandNonce) {
         let local_rand_nonce = RandValue::rand_gen(rng);
+        let opt_ticket_id = opt_ticket.as_ref().map(|ticket| ticket.ticket_id.clone());
+        let (min_version, max_version) = local_versions;
 
         let sc_state_initial = ScStateInitial {
             local_public_key: local_public_key.clone(),
             opt_remote_public_key: opt_remote_public_key.clone(),
             local_rand_nonce: local_rand_nonce.clone(),
+            local_versions,
+            opt_ticket,
         };
         let exchange_rand_nonce = ExchangeRandNonce {
             rand_nonce: local_rand_nonce,
             src_public_key: local_public_key,
             opt_dest_public_key: opt_remote_public_key,
+            opt_ticket_id,
+            min_version,
+            max_version,
         };
         (sc_state_initial, exchange_rand_nonce)
     }
 
+    /// Attempt to resume a previous channel.
+    /// Resumption happens only if both sides offered the same resumption ticket.
+    /// Returns `None` if a full handshake is required.
+    pub fn try_resume(
+        &self,
+        exchange_rand_nonce: &ExchangeRandNonce,
+    ) -> Result<Option<ScState>, ScStateError> {
+        let ticket = match &self.opt_ticket {
+            Some(ticket) => ticket,
+            None => return Ok(None),
+        };
+
+        if exchange_rand_nonce.opt_ticket_id.as_ref() != Some(&ticket.ticket_id)
+            || exchange_rand_nonce.src_public_key != ticket.remote_public_key
+        {
+            return Ok(None);
+        }
+
+        if let Some(expected_remote_public_key) = &self.opt_remote_public_key {
+            if expected_remote_public_key != &ticket.remote_public_key {
+                return Err(ScStateError::UnexpectedRemotePublicKey);
+            }
+        }
+
+        let version = negotiate_remote_version(self.local_versions, exchange_rand_nonce)?;
+        // A resumed channel never uses a lower version than the channel it resumes. If both
+        // versions ranges were lowered on the way, both sides fall back to a full handshake, which
+        // detects the downgrade:
+        if version < ticket.version {
+            return Ok(None);
+        }
+        // If the versions ranges were tampered with, the two sides derive different keys:
+        let (send_key, recv_key) = ticket.derive_keys(
+            version,
+            &self.local_rand_nonce,
+            &exchange_rand_nonce.rand_nonce,
+        );
+
+        Ok(Some(ScState::new(
+            self.local_public_key.clone(),
+            ticket.remote_public_key.clone(),
+            version,
+            &send_key,
+            &recv_key,
+            ticket.resumptions + 1,
+        )?))
+    }
+
     pub async fn handle_exchange_rand_nonce<R: CryptoRandom + 'static>(
         self,
         exchange_rand_nonce: ExchangeRandNonce,
@@ -103,12 +271,22 @@ impl ScStateInitial {
             }
         }
 
+        let version = negotiate_remote_version(self.local_versions, &exchange_rand_nonce)?;
+        let remote_versions = (
+            exchange_rand_nonce.min_version,
+            exchange_rand_nonce.max_version,
+        );
+
         let dh_private_key =
             DhPrivateKey::new(&rng).map_err(|_| ScStateError::PrivateKeyGenFailure)?;
         let dh_public_key = dh_private_key
             .compute_public_key()
             .map_err(|_| ScStateError::DhPublicKeyComputeFailure)?;
-        let local_salt = Salt::rand_gen(&rng);
+        let mut local_salt = Salt::rand_gen(&rng);
+        let (_local_min_version, local_max_version) = self.local_versions;
+        if version == SC_LEGACY_VERSION && local_max_version > SC_LEGACY_VERSION {
+            local_salt = add_downgrade_sentinel(&local_salt);
+        }
 
         let sc_state_half = ScStateHalf {
             remote_public_key: exchange_rand_nonce.src_public_key,
@@ -116,16 +294,24 @@ impl ScStateInitial {
             local_rand_nonce: self.local_rand_nonce,
             dh_private_key,
             local_salt: local_salt.clone(),
+            version,
+            local_versions: self.local_versions,
+            remote_versions,
         };
 
         let mut exchange_dh = ExchangeDh {
             dh_public_key,
             rand_nonce: exchange_rand_nonce.rand_nonce,
             key_salt: local_salt,
+            version,
             signature: Signature::default(),
         };
         exchange_dh.signature = identity_client
-            .request_signature(exchange_dh.signature_buffer())
+            .request_signature(exchange_dh_signature_buff(
+                &exchange_dh,
+                self.local_versions,
+                remote_versions,
+            ))
             .await
             .unwrap();
 
@@ -140,11 +326,27 @@ impl ScStateHalf {
         if self.local_rand_nonce != exchange_dh.rand_nonce {
             return Err(ScStateError::IncorrectRandNonce);
         }
-        // Verify signature:
-        let sbuffer = exchange_dh.signature_buffer();
+        // Verify signature. The remote side signs over the versions ranges as it received them,
+        // so any tampering with the ranges invalidates the signature:
+        let sbuffer =
+            exchange_dh_signature_buff(exchange_dh, self.remote_versions, self.local_versions);
         if !verify_signature(&sbuffer, &self.remote_public_key, &exchange_dh.signature) {
             return Err(ScStateError::InvalidSignature);
         }
+        // Both sides must choose the same version. Otherwise, someone tampered with the
+        // ExchangeRandNonce messages:
+        if self.version != exchange_dh.version {
+            return Err(ScStateError::VersionMismatch);
+        }
+        // The remote side supports newer versions, but only saw the legacy version in our
+        // ExchangeRandNonce (The legacy ExchangeDh buffer does not cover the versions ranges):
+        let (_local_min_version, local_max_version) = self.local_versions;
+        if exchange_dh.version == SC_LEGACY_VERSION
+            && local_max_version > SC_LEGACY_VERSION
+            && has_downgrade_sentinel(&exchange_dh.key_salt)
+        {
+            return Err(ScStateError::VersionDowngrade);
+        }
         Ok(())
     }
 
@@ -160,15 +362,14 @@ impl ScStateHalf {
             )
             .map_err(|_| ScStateError::KeyDerivationFailure)?;
 
-        Ok(ScState {
-            local_public_key: self.local_public_key,
-            remote_public_key: self.remote_public_key,
-            sender: Encryptor::new(&send_key).map_err(|_| ScStateError::CreateEncryptorFailure)?,
-            receiver: Decryptor::new(&recv_key)
-                .map_err(|_| ScStateError::CreateDecryptorFailure)?,
-            opt_old_receiver: None,
-            opt_pending_rekey: None,
-        })
+        ScState::new(
+            self.local_public_key,
+            self.remote_public_key,
+            self.version,
+            &send_key,
+            &recv_key,
+            0,
+        )
     }
 }
 
@@ -179,6 +380,38 @@ pub struct HandleIncomingOutput {
 }
 
 impl ScState {
+    fn new(
+        local_public_key: PublicKey,
+        remote_public_key: PublicKey,
+        version: u32,
+        send_key: &SymmetricKey,
+        recv_key: &SymmetricKey,
+        resumptions: usize,
+    ) -> Result<ScState, ScStateError> {
+        let opt_resumption_ticket = if resumptions < MAX_SC_RESUMPTIONS {
+            Some(ResumptionTicket::from_keys(
+                remote_public_key.clone(),
+                send_key,
+                recv_key,
+                version,
+                resumptions,
+            ))
+        } else {
+            None
+        };
+
+        Ok(ScState {
+            local_public_key,
+            remote_public_key,
+            version,
+            sender: Encryptor::new(send_key).map_err(|_| ScStateError::CreateEncryptorFailure)?,
+            receiver: Decryptor::new(recv_key).map_err(|_| ScStateError::CreateDecryptorFailure)?,
+            opt_old_receiver: None,
+            opt_pending_rekey: None,
+            opt_resumption_ticket,
+        })
+    }
+
     fn encrypt_outgoing<R: CryptoRandom>(
         &mut self,
         channel_content: ChannelContent,
@@ -206,11 +439,25 @@ impl ScState {
         let data = self
             .receiver
             .decrypt(&enc_data.0)
-            .map_err(|_| ScStateError::DecryptionFailure)?;
+            .map_err(|_| self.classify_decrypt_error(enc_data))?;
         self.opt_old_receiver = None;
         Ok(PlainData(data))
     }
 
+    /// Find out why an incoming message could not be decrypted.
+    /// A message that does not carry the nonce expected by any of our receivers was replayed (Or
+    /// reordered). Any other message was malformed or tampered with.
+    fn classify_decrypt_error(&self, enc_data: &EncryptedData) -> ScStateError {
+        let old_expected = match &self.opt_old_receiver {
+            Some(old_receiver) => old_receiver.has_expected_nonce(&enc_data.0),
+            None => Some(false),
+        };
+        match (old_expected, self.receiver.has_expected_nonce(&enc_data.0)) {
+            (Some(false), Some(false)) => ScStateError::NonceReplay,
+            _ => ScStateError::DecryptionFailure,
+        }
+    }
+
     /// Decrypt an incoming message
     fn decrypt_incoming(
         &mut self,
@@ -352,11 +599,23 @@ impl ScState {
     pub fn get_remote_public_key(&self) -> &PublicKey {
         &self.remote_public_key
     }
+
+    /// The negotiated protocol version
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+
+    /// Get a ticket that allows to resume this channel later.
+    /// Returns None if a full handshake is required to establish the next channel.
+    pub fn get_resumption_ticket(&self) -> Option<&ResumptionTicket> {
+        self.opt_resumption_ticket.as_ref()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use futures::channel::mpsc;
     use futures::executor::{LocalPool, ThreadPool};
     use futures::task::SpawnExt;
     use futures::{future, FutureExt};
@@ -370,20 +629,38 @@ mod tests {
     use identity::create_identity;
     use identity::IdentityClient;
 
-    async fn run_basic_sc_state(
+    /// Perform a full handshake.
+    /// The second side supports only the versions in `local_versions2`.
+    /// If `opt_versions` is given, the versions ranges of both ExchangeRandNonce messages are
+    /// replaced on the way.
+    async fn run_sc_handshake(
         identity_client1: IdentityClient,
         identity_client2: IdentityClient,
-    ) -> Result<(ScState, ScState), ()> {
+        local_versions2: (u32, u32),
+        opt_versions: Option<(u32, u32)>,
+    ) -> Result<(ScState, ScState), ScStateError> {
         let rng1 = DummyRandom::new(&[1u8]);
         let rng2 = DummyRandom::new(&[2u8]);
         let local_public_key1 = identity_client1.request_public_key().await.unwrap();
         let local_public_key2 = identity_client2.request_public_key().await.unwrap();
         let opt_dest_public_key1 = Some(local_public_key2.clone());
         let opt_dest_public_key2 = None;
-        let (sc_state_initial1, exchange_rand_nonce1) =
-            ScStateInitial::new(local_public_key1.clone(), opt_dest_public_key1, &rng1);
-        let (sc_state_initial2, exchange_rand_nonce2) =
-            ScStateInitial::new(local_public_key2.clone(), opt_dest_public_key2, &rng2);
+        let (sc_state_initial1, mut exchange_rand_nonce1) =
+            ScStateInitial::new(local_public_key1.clone(), opt_dest_public_key1, None, &rng1);
+        let (sc_state_initial2, mut exchange_rand_nonce2) = ScStateInitial::with_versions(
+            local_public_key2.clone(),
+            opt_dest_public_key2,
+            None,
+            local_versions2,
+            &rng2,
+        );
+
+        if let Some((min_version, max_version)) = opt_versions {
+            exchange_rand_nonce1.min_version = min_version;
+            exchange_rand_nonce1.max_version = max_version;
+            exchange_rand_nonce2.min_version = min_version;
+            exchange_rand_nonce2.max_version = max_version;
+        }
 
         let (sc_state_half1, exchange_dh1) = sc_state_initial1
             .handle_exchange_rand_nonce(
@@ -391,19 +668,17 @@ mod tests {
                 identity_client1.clone(),
                 rng1.clone(),
             )
-            .await
-            .unwrap();
+            .await?;
         let (sc_state_half2, exchange_dh2) = sc_state_initial2
             .handle_exchange_rand_nonce(
                 exchange_rand_nonce1,
                 identity_client2.clone(),
                 rng2.clone(),
             )
-            .await
-            .unwrap();
+            .await?;
 
-        let sc_state1 = sc_state_half1.handle_exchange_dh(exchange_dh2).unwrap();
-        let sc_state2 = sc_state_half2.handle_exchange_dh(exchange_dh1).unwrap();
+        let sc_state1 = sc_state_half1.handle_exchange_dh(exchange_dh2)?;
+        let sc_state2 = sc_state_half2.handle_exchange_dh(exchange_dh1)?;
         Ok((sc_state1, sc_state2))
     }
 
@@ -473,7 +748,10 @@ mod tests {
         assert_eq!(incoming_output2.opt_incoming_message, None);
     }
 
-    fn prepare_dh_test() -> (ScState, ScState, DummyRandom, DummyRandom) {
+    fn try_prepare_dh_test(
+        local_versions2: (u32, u32),
+        opt_versions: Option<(u32, u32)>,
+    ) -> Result<(ScState, ScState, DummyRandom, DummyRandom), ScStateError> {
         let rng1 = DummyRandom::new(&[1u8]);
         let private_key = PrivateKey::rand_gen(&rng1);
         let identity1 = SoftwareEd25519Identity::from_private_key(&private_key).unwrap();
@@ -495,22 +773,282 @@ mod tests {
             .spawn(identity_server2.then(|_| future::ready(())))
             .unwrap();
 
-        let (sc_state1, sc_state2) = LocalPool::new()
-            .run_until(run_basic_sc_state(identity_client1, identity_client2))
-            .unwrap();
+        let (sc_state1, sc_state2) = LocalPool::new().run_until(run_sc_handshake(
+            identity_client1,
+            identity_client2,
+            local_versions2,
+            opt_versions,
+        ))?;
+
+        Ok((sc_state1, sc_state2, rng1, rng2))
+    }
+
+    fn prepare_dh_test() -> (ScState, ScState, DummyRandom, DummyRandom) {
+        try_prepare_dh_test((SC_MIN_VERSION, SC_MAX_VERSION), None).unwrap()
+    }
+
+    #[test]
+    fn test_negotiate_version() {
+        assert_eq!(negotiate_version(1, 1, 1, 1), Some(1));
+        // The highest common version is picked:
+        assert_eq!(negotiate_version(1, 3, 2, 5), Some(3));
+        assert_eq!(negotiate_version(2, 5, 1, 3), Some(3));
+        assert_eq!(negotiate_version(1, 5, 2, 2), Some(2));
+        // No common version:
+        assert_eq!(negotiate_version(1, 2, 3, 4), None);
+        assert_eq!(negotiate_version(3, 4, 1, 2), None);
+        // Invalid remote range:
+        assert_eq!(negotiate_version(1, 5, 3, 2), None);
+    }
 
-        (sc_state1, sc_state2, rng1, rng2)
+    #[test]
+    fn test_sc_state_no_common_version() {
+        let rng1 = DummyRandom::new(&[1u8]);
+        let rng2 = DummyRandom::new(&[2u8]);
+        let public_key1 = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let public_key2 = PublicKey::from(&[0xbb; PublicKey::len()]);
+
+        let (sc_state_initial1, _exchange_rand_nonce1) =
+            ScStateInitial::new(public_key1, None, None, &rng1);
+        let (_sc_state_initial2, mut exchange_rand_nonce2) =
+            ScStateInitial::new(public_key2, None, None, &rng2);
+
+        // The remote side supports only future versions:
+        exchange_rand_nonce2.min_version = SC_MAX_VERSION + 1;
+        exchange_rand_nonce2.max_version = SC_MAX_VERSION + 2;
+
+        // No signature is requested before the version is negotiated:
+        let (requests_sender, _requests_receiver) = mpsc::channel(0);
+        let identity_client = IdentityClient::new(requests_sender);
+        let res = LocalPool::new().run_until(sc_state_initial1.handle_exchange_rand_nonce(
+            exchange_rand_nonce2,
+            identity_client,
+            rng1.clone(),
+        ));
+        match res {
+            Err(ScStateError::NoCommonVersion) => {}
+            _ => unreachable!(),
+        }
     }
 
     #[test]
     fn test_basic_sc_state() {
         let (mut sc_state1, mut sc_state2, rng1, rng2) = prepare_dh_test();
+        assert_eq!(sc_state1.version, SC_MAX_VERSION);
+        assert_eq!(sc_state2.version, SC_MAX_VERSION);
         send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
         rekey_sequential(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
         send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
         rekey_simultaneous(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
         send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
     }
+
+    #[test]
+    fn test_sc_state_legacy_version() {
+        // Nodes that predate version negotiation do not advertise a versions range (It is read as
+        // 0 to 0), and ignore the downgrade sentinel we put in our salt:
+        let legacy_versions = (SC_LEGACY_VERSION, SC_LEGACY_VERSION);
+        let (mut sc_state1, mut sc_state2, rng1, rng2) =
+            try_prepare_dh_test(legacy_versions, None).unwrap();
+        assert_eq!(sc_state1.version, SC_LEGACY_VERSION);
+        assert_eq!(sc_state2.version, SC_LEGACY_VERSION);
+        send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
+    }
+
+    #[test]
+    fn test_sc_state_versions_downgrade() {
+        // Someone on the way forces a lower version on both sides:
+        match try_prepare_dh_test((SC_MIN_VERSION, SC_MAX_VERSION), Some((1, 1))) {
+            Err(ScStateError::InvalidSignature) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_sc_state_legacy_downgrade() {
+        // Someone on the way makes both sides look like nodes that predate version negotiation.
+        // The legacy ExchangeDh buffer does not cover the versions ranges, but it covers the
+        // downgrade sentinel in the salt:
+        match try_prepare_dh_test((SC_MIN_VERSION, SC_MAX_VERSION), Some((0, 0))) {
+            Err(ScStateError::VersionDowngrade) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_downgrade_sentinel() {
+        let rng = DummyRandom::new(&[3u8]);
+        let key_salt = Salt::rand_gen(&rng);
+        assert!(!has_downgrade_sentinel(&key_salt));
+        let key_salt = add_downgrade_sentinel(&key_salt);
+        assert!(has_downgrade_sentinel(&key_salt));
+    }
+
+    #[test]
+    fn test_sc_state_replay_and_tamper() {
+        let (mut sc_state1, mut sc_state2, rng1, rng2) = prepare_dh_test();
+
+        let plain_data = PlainData(vec![0, 1, 2, 3]);
+        let enc_data = sc_state1.create_outgoing(&plain_data, &rng1);
+        let incoming_output = sc_state2.handle_incoming(&enc_data, &rng2).unwrap();
+        assert_eq!(incoming_output.opt_incoming_message.unwrap(), plain_data);
+
+        // The same message is sent again:
+        match sc_state2.handle_incoming(&enc_data, &rng2) {
+            Err(ScStateError::NonceReplay) => {}
+            _ => unreachable!(),
+        }
+
+        // A message with the expected nonce, but a modified content:
+        let mut enc_data = sc_state1.create_outgoing(&plain_data, &rng1);
+        let last = enc_data.0.len() - 1;
+        enc_data.0[last] ^= 1;
+        match sc_state2.handle_incoming(&enc_data, &rng2) {
+            Err(ScStateError::DecryptionFailure) => {}
+            _ => unreachable!(),
+        }
+
+        // A truncated message:
+        match sc_state2.handle_incoming(&EncryptedData(vec![0u8; 4]), &rng2) {
+            Err(ScStateError::DecryptionFailure) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn resume_sc_state<R: CryptoRandom>(
+        sc_state1: &ScState,
+        sc_state2: &ScState,
+        rng1: &R,
+        rng2: &R,
+    ) -> (ScState, ScState) {
+        let ticket1 = sc_state1.get_resumption_ticket().unwrap().clone();
+        let ticket2 = sc_state2.get_resumption_ticket().unwrap().clone();
+        assert_eq!(ticket1.ticket_id, ticket2.ticket_id);
+
+        let (sc_state_initial1, exchange_rand_nonce1) = ScStateInitial::new(
+            sc_state1.local_public_key.clone(),
+            Some(sc_state1.remote_public_key.clone()),
+            Some(ticket1),
+            rng1,
+        );
+        let (sc_state_initial2, exchange_rand_nonce2) = ScStateInitial::new(
+            sc_state2.local_public_key.clone(),
+            None,
+            Some(ticket2),
+            rng2,
+        );
+
+        let new_sc_state1 = sc_state_initial1
+            .try_resume(&exchange_rand_nonce2)
+            .unwrap()
+            .unwrap();
+        let new_sc_state2 = sc_state_initial2
+            .try_resume(&exchange_rand_nonce1)
+            .unwrap()
+            .unwrap();
+
+        // A resumed channel has a new resumption ticket (Unless too many resumptions occurred):
+        let opt_new_ticket_id1 = new_sc_state1
+            .get_resumption_ticket()
+            .map(|ticket| ticket.ticket_id.clone());
+        let opt_new_ticket_id2 = new_sc_state2
+            .get_resumption_ticket()
+            .map(|ticket| ticket.ticket_id.clone());
+        assert_eq!(opt_new_ticket_id1, opt_new_ticket_id2);
+        assert_ne!(opt_new_ticket_id1, Some(ticket1.ticket_id.clone()));
+
+        (new_sc_state1, new_sc_state2)
+    }
+
+    #[test]
+    fn test_resume_sc_state() {
+        let (sc_state1, sc_state2, rng1, rng2) = prepare_dh_test();
+        let (mut sc_state1, mut sc_state2) = resume_sc_state(&sc_state1, &sc_state2, &rng1, &rng2);
+        send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
+        rekey_sequential(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
+        send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
+
+        // Resume a resumed channel:
+        let (mut sc_state1, mut sc_state2) = resume_sc_state(&sc_state1, &sc_state2, &rng1, &rng2);
+        send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
+    }
+
+    #[test]
+    fn test_resume_sc_state_max_resumptions() {
+        let (mut sc_state1, mut sc_state2, rng1, rng2) = prepare_dh_test();
+        for _ in 0..MAX_SC_RESUMPTIONS {
+            let (new_sc_state1, new_sc_state2) =
+                resume_sc_state(&sc_state1, &sc_state2, &rng1, &rng2);
+            sc_state1 = new_sc_state1;
+            sc_state2 = new_sc_state2;
+            send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
+        }
+
+        // A full handshake is required to establish the next channel:
+        assert!(sc_state1.get_resumption_ticket().is_none());
+        assert!(sc_state2.get_resumption_ticket().is_none());
+    }
+
+    #[test]
+    fn test_resume_sc_state_missing_ticket() {
+        let (sc_state1, sc_state2, rng1, rng2) = prepare_dh_test();
+        let ticket1 = sc_state1.get_resumption_ticket().unwrap().clone();
+
+        // Only one side has a ticket. A full handshake is required:
+        let (sc_state_initial1, exchange_rand_nonce1) = ScStateInitial::new(
+            sc_state1.local_public_key.clone(),
+            Some(sc_state1.remote_public_key.clone()),
+            Some(ticket1),
+            &rng1,
+        );
+        let (sc_state_initial2, exchange_rand_nonce2) =
+            ScStateInitial::new(sc_state2.local_public_key.clone(), None, None, &rng2);
+
+        assert!(sc_state_initial1
+            .try_resume(&exchange_rand_nonce2)
+            .unwrap()
+            .is_none());
+        assert!(sc_state_initial2
+            .try_resume(&exchange_rand_nonce1)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_resume_sc_state_legacy_downgrade() {
+        let (sc_state1, sc_state2, rng1, rng2) = prepare_dh_test();
+        let ticket1 = sc_state1.get_resumption_ticket().unwrap().clone();
+        let ticket2 = sc_state2.get_resumption_ticket().unwrap().clone();
+
+        let (sc_state_initial1, mut exchange_rand_nonce1) = ScStateInitial::new(
+            sc_state1.local_public_key.clone(),
+            Some(sc_state1.remote_public_key.clone()),
+            Some(ticket1),
+            &rng1,
+        );
+        let (sc_state_initial2, mut exchange_rand_nonce2) = ScStateInitial::new(
+            sc_state2.local_public_key.clone(),
+            None,
+            Some(ticket2),
+            &rng2,
+        );
+
+        // Someone on the way rewrites both versions ranges to (0, 0).
+        // Both sides refuse to resume, and perform a full handshake instead:
+        for exchange_rand_nonce in &mut [&mut exchange_rand_nonce1, &mut exchange_rand_nonce2] {
+            exchange_rand_nonce.min_version = SC_LEGACY_VERSION;
+            exchange_rand_nonce.max_version = SC_LEGACY_VERSION;
+        }
+        assert!(sc_state_initial1
+            .try_resume(&exchange_rand_nonce2)
+            .unwrap()
+            .is_none());
+        assert!(sc_state_initial2
+            .try_resume(&exchange_rand_nonce1)
+            .unwrap()
+            .is_none());
+    }
+
     // TODO: Add tests:
     // - Test the usage of old receiver
     // - Test error cases
@@ -0,0 +1,97 @@
+use std::convert::TryFrom;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use proto::crypto::{HashResult, HashedLock, InvoiceId, PublicKey, RandValue, Signature, Uid};
+use proto::funder::messages::{
+    Currency, CurrencyOperations, FriendTcOp, FriendsRoute, RequestSendFundsOp, UnsignedMoveToken,
+};
+use proto::net::messages::NetAddress;
+
+use signature::canonical::CanonicalSerialize;
+use signature::signature_buff::{
+    move_token_signature_buff, move_token_signature_buff_into, SIGNATURE_BUFF_VERSION,
+};
+
+/// A move token similar to the ones sent by a busy forwarding node:
+/// Many request operations, each with a long route.
+fn busy_move_token() -> UnsignedMoveToken<NetAddress> {
+    let route = FriendsRoute {
+        public_keys: (0..8u8)
+            .map(|i| PublicKey::from(&[i; PublicKey::len()]))
+            .collect(),
+    };
+    let operations = (0..32u8)
+        .map(|i| {
+            FriendTcOp::RequestSendFunds(RequestSendFundsOp {
+                request_id: Uid::from(&[i; Uid::len()]),
+                src_hashed_lock: HashedLock::from(&[i; HashedLock::len()]),
+                route: route.clone(),
+                dest_payment: 100,
+                total_dest_payment: 200,
+                invoice_id: InvoiceId::from(&[i; InvoiceId::len()]),
+                left_fees: 10,
+                expiry_ticks: 0,
+                refund_ticks: 0,
+                opt_exchange: None,
+            })
+        })
+        .collect();
+
+    UnsignedMoveToken {
+        old_token: Signature::from(&[0x11; Signature::len()]),
+        currencies_operations: vec![CurrencyOperations {
+            currency: Currency::try_from("FST".to_owned()).unwrap(),
+            operations,
+        }],
+        opt_local_relays: None,
+        opt_active_currencies: None,
+        info_hash: HashResult::from(&[0x22; HashResult::len()]),
+        rand_nonce: RandValue::from(&[0x33; RandValue::len()]),
+        signature_version: SIGNATURE_BUFF_VERSION,
+    }
+}
+
+fn bench_canonical_serialize(c: &mut Criterion) {
+    let move_token = busy_move_token();
+    let currencies_operations = &move_token.currencies_operations;
+
+    c.bench_function("canonical_serialize", |b| {
+        b.iter(|| black_box(currencies_operations.canonical_serialize()))
+    });
+
+    let mut buff = Vec::new();
+    c.bench_function("canonical_serialize_into (reused buffer)", |b| {
+        b.iter(|| {
+            buff.clear();
+            currencies_operations.canonical_serialize_into(&mut buff);
+            black_box(&buff);
+        })
+    });
+}
+
+fn bench_move_token_signature_buff(c: &mut Criterion) {
+    let move_token = busy_move_token();
+
+    // move_token_signature_buff() takes ownership of the move token, so the move token is cloned
+    // on every iteration:
+    c.bench_function("move_token_signature_buff", |b| {
+        b.iter(|| black_box(move_token_signature_buff(move_token.clone())))
+    });
+
+    let mut sig_buffer = Vec::new();
+    c.bench_function("move_token_signature_buff_into (reused buffer)", |b| {
+        b.iter(|| {
+            sig_buffer.clear();
+            move_token_signature_buff_into(&move_token, &mut sig_buffer);
+            black_box(&sig_buffer);
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_canonical_serialize,
+    bench_move_token_signature_buff
+);
+criterion_main!(benches);
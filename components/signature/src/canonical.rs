@@ -4,10 +4,12 @@ use byteorder::{BigEndian, WriteBytesExt};
 use proto::app_server::messages::RelayAddress;
 use proto::funder::messages::{
     BalanceInfo, CancelSendFundsOp, CollectSendFundsOp, CountersInfo, Currency,
-    CurrencyBalanceInfo, CurrencyOperations, FriendTcOp, FriendsRoute, McInfo, OptLocalRelays,
-    Receipt, RequestSendFundsOp, ResponseSendFundsOp, TokenInfo,
+    CurrencyBalanceInfo, CurrencyExchange, CurrencyOperations, FriendTcOp, FriendsRoute, McInfo,
+    OptLocalRelays, Receipt, RefundSendFundsOp, RequestSendFundsOp, ResponseSendFundsOp, TokenInfo,
+};
+use proto::index_server::messages::{
+    IndexMutation, NamedIndexServerAddress, RemoveFriendCurrency, UpdateFriendCurrency,
 };
-use proto::index_server::messages::{IndexMutation, RemoveFriendCurrency, UpdateFriendCurrency};
 use proto::net::messages::NetAddress;
 
 use common::int_convert::usize_to_u64;
@@ -15,26 +17,34 @@ use common::int_convert::usize_to_u64;
 /// Canonically serialize an object
 /// This serialization is used for security related applications (For example, signatures and
 /// hashing), therefore the serialization result must be the same on any system.
+///
+/// Serialization is done by appending to a caller provided buffer, so that a whole structure
+/// (Or a whole signature buffer) can be serialized without allocating for every field.
 pub trait CanonicalSerialize {
-    fn canonical_serialize(&self) -> Vec<u8>;
+    /// Append the canonical serialization of this object to `buff`
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>);
+
+    fn canonical_serialize(&self) -> Vec<u8> {
+        let mut buff = Vec::new();
+        self.canonical_serialize_into(&mut buff);
+        buff
+    }
 }
 
 impl<T> CanonicalSerialize for Option<T>
 where
     T: CanonicalSerialize,
 {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_data = Vec::new();
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
         match &self {
             None => {
-                res_data.push(0);
+                buff.push(0);
             }
             Some(t) => {
-                res_data.push(1);
-                res_data.extend_from_slice(&t.canonical_serialize());
+                buff.push(1);
+                t.canonical_serialize_into(buff);
             }
         };
-        res_data
     }
 }
 
@@ -42,71 +52,56 @@ impl<T> CanonicalSerialize for Vec<T>
 where
     T: CanonicalSerialize,
 {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_data = Vec::new();
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
         // Write length:
-        res_data
-            .write_u64::<BigEndian>(usize_to_u64(self.len()).unwrap())
+        buff.write_u64::<BigEndian>(usize_to_u64(self.len()).unwrap())
             .unwrap();
         // Write all items:
         for t in self.iter() {
-            res_data.extend_from_slice(&t.canonical_serialize());
+            t.canonical_serialize_into(buff);
         }
-        res_data
     }
 }
 
 impl CanonicalSerialize for String {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        self.as_bytes().to_vec()
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.extend_from_slice(self.as_bytes());
     }
 }
 
 impl CanonicalSerialize for &str {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        self.as_bytes().to_vec()
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.extend_from_slice(self.as_bytes());
     }
 }
 
 impl CanonicalSerialize for bool {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        if *self {
-            vec![1]
-        } else {
-            vec![0]
-        }
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.push(if *self { 1 } else { 0 });
     }
 }
 
 impl CanonicalSerialize for u32 {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_data = Vec::new();
-        res_data.write_u32::<BigEndian>(*self).unwrap();
-        res_data
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.write_u32::<BigEndian>(*self).unwrap();
     }
 }
 
 impl CanonicalSerialize for u64 {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_data = Vec::new();
-        res_data.write_u64::<BigEndian>(*self).unwrap();
-        res_data
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.write_u64::<BigEndian>(*self).unwrap();
     }
 }
 
 impl CanonicalSerialize for u128 {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_data = Vec::new();
-        res_data.write_u128::<BigEndian>(*self).unwrap();
-        res_data
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.write_u128::<BigEndian>(*self).unwrap();
     }
 }
 
 impl CanonicalSerialize for i128 {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_data = Vec::new();
-        res_data.write_i128::<BigEndian>(*self).unwrap();
-        res_data
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.write_i128::<BigEndian>(*self).unwrap();
     }
 }
 
@@ -115,12 +110,10 @@ where
     T: CanonicalSerialize,
     W: CanonicalSerialize,
 {
-    fn canonical_serialize(&self) -> Vec<u8> {
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
         let (t, w) = self;
-        let mut res_data = Vec::new();
-        res_data.extend_from_slice(&t.canonical_serialize());
-        res_data.extend_from_slice(&w.canonical_serialize());
-        res_data
+        t.canonical_serialize_into(buff);
+        w.canonical_serialize_into(buff);
     }
 }
 
@@ -139,122 +132,124 @@ where
 */
 
 impl CanonicalSerialize for Currency {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        self.as_str().canonical_serialize()
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        self.as_str().canonical_serialize_into(buff);
     }
 }
 
 impl CanonicalSerialize for CurrencyOperations {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.currency.canonical_serialize());
-        res_bytes.extend_from_slice(&self.operations.canonical_serialize());
-        res_bytes
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        self.currency.canonical_serialize_into(buff);
+        self.operations.canonical_serialize_into(buff);
     }
 }
 
 impl CanonicalSerialize for RequestSendFundsOp {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.request_id);
-        res_bytes.extend_from_slice(&self.src_hashed_lock);
-        res_bytes.extend_from_slice(&self.route.canonical_serialize());
-        res_bytes
-            .write_u128::<BigEndian>(self.dest_payment)
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.extend_from_slice(&self.request_id);
+        buff.extend_from_slice(&self.src_hashed_lock);
+        self.route.canonical_serialize_into(buff);
+        buff.write_u128::<BigEndian>(self.dest_payment).unwrap();
+        buff.write_u128::<BigEndian>(self.total_dest_payment)
             .unwrap();
-        res_bytes
-            .write_u128::<BigEndian>(self.total_dest_payment)
+        buff.extend_from_slice(&self.invoice_id);
+        buff.write_u128::<BigEndian>(self.left_fees).unwrap();
+        buff.write_u64::<BigEndian>(self.expiry_ticks).unwrap();
+        buff.write_u64::<BigEndian>(self.refund_ticks).unwrap();
+        self.opt_exchange.canonical_serialize_into(buff);
+    }
+}
+
+impl CanonicalSerialize for CurrencyExchange {
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        self.dest_currency.canonical_serialize_into(buff);
+        buff.write_u128::<BigEndian>(self.dest_payment).unwrap();
+        buff.write_u128::<BigEndian>(self.total_dest_payment)
             .unwrap();
-        res_bytes.extend_from_slice(&self.invoice_id);
-        res_bytes.write_u128::<BigEndian>(self.left_fees).unwrap();
-        res_bytes
     }
 }
 
 impl CanonicalSerialize for ResponseSendFundsOp {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.request_id);
-        res_bytes.extend_from_slice(&self.dest_hashed_lock);
-        res_bytes.extend_from_slice(&self.is_complete.canonical_serialize());
-        res_bytes.extend_from_slice(&self.rand_nonce);
-        res_bytes.extend_from_slice(&self.signature);
-        res_bytes
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.extend_from_slice(&self.request_id);
+        buff.extend_from_slice(&self.dest_hashed_lock);
+        self.is_complete.canonical_serialize_into(buff);
+        buff.write_u128::<BigEndian>(self.change).unwrap();
+        buff.extend_from_slice(&self.rand_nonce);
+        buff.extend_from_slice(&self.signature);
     }
 }
 
 impl CanonicalSerialize for CancelSendFundsOp {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.request_id);
-        res_bytes
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.extend_from_slice(&self.request_id);
     }
 }
 
 impl CanonicalSerialize for CollectSendFundsOp {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.request_id);
-        res_bytes.extend_from_slice(&self.src_plain_lock);
-        res_bytes.extend_from_slice(&self.dest_plain_lock);
-        res_bytes
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.extend_from_slice(&self.request_id);
+        buff.extend_from_slice(&self.src_plain_lock);
+        buff.extend_from_slice(&self.dest_plain_lock);
+    }
+}
+
+impl CanonicalSerialize for RefundSendFundsOp {
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.extend_from_slice(&self.request_id);
     }
 }
 
 impl CanonicalSerialize for FriendTcOp {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
         match self {
             FriendTcOp::RequestSendFunds(request_send_funds) => {
-                res_bytes.push(0u8);
-                res_bytes.append(&mut request_send_funds.canonical_serialize())
+                buff.push(0u8);
+                request_send_funds.canonical_serialize_into(buff);
             }
             FriendTcOp::ResponseSendFunds(response_send_funds) => {
-                res_bytes.push(1u8);
-                res_bytes.append(&mut response_send_funds.canonical_serialize())
+                buff.push(1u8);
+                response_send_funds.canonical_serialize_into(buff);
             }
             FriendTcOp::CancelSendFunds(cancel_send_funds) => {
-                res_bytes.push(2u8);
-                res_bytes.append(&mut cancel_send_funds.canonical_serialize())
+                buff.push(2u8);
+                cancel_send_funds.canonical_serialize_into(buff);
             }
             FriendTcOp::CollectSendFunds(commit_send_funds) => {
-                res_bytes.push(3u8);
-                res_bytes.append(&mut commit_send_funds.canonical_serialize())
+                buff.push(3u8);
+                commit_send_funds.canonical_serialize_into(buff);
+            }
+            FriendTcOp::RefundSendFunds(refund_send_funds) => {
+                buff.push(4u8);
+                refund_send_funds.canonical_serialize_into(buff);
             }
         }
-        res_bytes
     }
 }
 
 impl CanonicalSerialize for FriendsRoute {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes
-            .write_u64::<BigEndian>(usize_to_u64(self.public_keys.len()).unwrap())
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.write_u64::<BigEndian>(usize_to_u64(self.public_keys.len()).unwrap())
             .unwrap();
         for public_key in &self.public_keys {
-            res_bytes.extend_from_slice(public_key);
+            buff.extend_from_slice(public_key);
         }
-        res_bytes
     }
 }
 
 impl CanonicalSerialize for Receipt {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.response_hash);
-        res_bytes.extend_from_slice(&self.invoice_id);
-        res_bytes
-            .write_u128::<BigEndian>(self.dest_payment)
-            .unwrap();
-        res_bytes.extend_from_slice(&self.signature);
-        res_bytes
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.extend_from_slice(&self.response_hash);
+        buff.extend_from_slice(&self.invoice_id);
+        buff.write_u128::<BigEndian>(self.dest_payment).unwrap();
+        buff.write_u128::<BigEndian>(self.change).unwrap();
+        buff.extend_from_slice(&self.signature);
     }
 }
 
 impl CanonicalSerialize for NetAddress {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        self.as_str().canonical_serialize()
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        self.as_str().canonical_serialize_into(buff);
     }
 }
 
@@ -262,11 +257,20 @@ impl<B> CanonicalSerialize for RelayAddress<B>
 where
     B: CanonicalSerialize,
 {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.public_key);
-        res_bytes.extend_from_slice(&self.address.canonical_serialize());
-        res_bytes
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.extend_from_slice(&self.public_key);
+        self.address.canonical_serialize_into(buff);
+    }
+}
+
+impl<ISA> CanonicalSerialize for NamedIndexServerAddress<ISA>
+where
+    ISA: CanonicalSerialize,
+{
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.extend_from_slice(&self.public_key);
+        self.address.canonical_serialize_into(buff);
+        self.name.canonical_serialize_into(buff);
     }
 }
 
@@ -274,100 +278,113 @@ impl<B> CanonicalSerialize for OptLocalRelays<B>
 where
     B: CanonicalSerialize,
 {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
         match self {
-            OptLocalRelays::Empty => res_bytes.push(0u8),
+            OptLocalRelays::Empty => buff.push(0u8),
             OptLocalRelays::Relays(relays) => {
-                res_bytes.push(1u8);
-                res_bytes.append(&mut relays.canonical_serialize());
+                buff.push(1u8);
+                relays.canonical_serialize_into(buff);
             }
         };
-        res_bytes
     }
 }
 
 impl CanonicalSerialize for UpdateFriendCurrency {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.public_key);
-        res_bytes.extend_from_slice(&self.currency.canonical_serialize());
-        res_bytes
-            .write_u128::<BigEndian>(self.recv_capacity)
-            .unwrap();
-        res_bytes
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.extend_from_slice(&self.public_key);
+        self.currency.canonical_serialize_into(buff);
+        buff.write_u128::<BigEndian>(self.recv_capacity).unwrap();
     }
 }
 
 impl CanonicalSerialize for RemoveFriendCurrency {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.public_key);
-        res_bytes.extend_from_slice(&self.currency.canonical_serialize());
-        res_bytes
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.extend_from_slice(&self.public_key);
+        self.currency.canonical_serialize_into(buff);
     }
 }
 
 impl CanonicalSerialize for IndexMutation {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
         match self {
             IndexMutation::UpdateFriendCurrency(update_friend_currency) => {
-                res_bytes.push(0);
-                res_bytes.extend(update_friend_currency.canonical_serialize());
+                buff.push(0);
+                update_friend_currency.canonical_serialize_into(buff);
             }
             IndexMutation::RemoveFriendCurrency(remove_friend_currency) => {
-                res_bytes.push(1);
-                res_bytes.extend(remove_friend_currency.canonical_serialize());
+                buff.push(1);
+                remove_friend_currency.canonical_serialize_into(buff);
             }
         };
-        res_bytes
     }
 }
 
 impl CanonicalSerialize for BalanceInfo {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.balance.canonical_serialize());
-        res_bytes.extend_from_slice(&self.local_pending_debt.canonical_serialize());
-        res_bytes.extend_from_slice(&self.remote_pending_debt.canonical_serialize());
-        res_bytes
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        self.balance.canonical_serialize_into(buff);
+        self.local_pending_debt.canonical_serialize_into(buff);
+        self.remote_pending_debt.canonical_serialize_into(buff);
     }
 }
 
 impl CanonicalSerialize for CurrencyBalanceInfo {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.currency.canonical_serialize());
-        res_bytes.extend_from_slice(&self.balance_info.canonical_serialize());
-        res_bytes
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        self.currency.canonical_serialize_into(buff);
+        self.balance_info.canonical_serialize_into(buff);
     }
 }
 
 impl CanonicalSerialize for McInfo {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.local_public_key);
-        res_bytes.extend_from_slice(&self.remote_public_key);
-        res_bytes.extend_from_slice(&self.balances.canonical_serialize());
-        res_bytes
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        buff.extend_from_slice(&self.local_public_key);
+        buff.extend_from_slice(&self.remote_public_key);
+        self.balances.canonical_serialize_into(buff);
     }
 }
 
 impl CanonicalSerialize for CountersInfo {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.inconsistency_counter.canonical_serialize());
-        res_bytes.extend_from_slice(&self.move_token_counter.canonical_serialize());
-        res_bytes
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        self.inconsistency_counter.canonical_serialize_into(buff);
+        self.move_token_counter.canonical_serialize_into(buff);
     }
 }
 
 impl CanonicalSerialize for TokenInfo {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.mc.canonical_serialize());
-        res_bytes.extend_from_slice(&self.counters.canonical_serialize());
-        res_bytes
+    fn canonical_serialize_into(&self, buff: &mut Vec<u8>) {
+        self.mc.canonical_serialize_into(buff);
+        self.counters.canonical_serialize_into(buff);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proto::crypto::PublicKey;
+
+    #[test]
+    fn test_canonical_serialize_into_appends() {
+        let route = FriendsRoute {
+            public_keys: vec![
+                PublicKey::from(&[0xaa; PublicKey::len()]),
+                PublicKey::from(&[0xbb; PublicKey::len()]),
+            ],
+        };
+        let opt_value = Some((true, 0x1234u64));
+
+        let mut buff = vec![0xff];
+        route.canonical_serialize_into(&mut buff);
+        opt_value.canonical_serialize_into(&mut buff);
+
+        // Serializing into a buffer appends, without touching the existing contents:
+        let mut expected = vec![0xff];
+        expected.extend_from_slice(&route.canonical_serialize());
+        expected.extend_from_slice(&opt_value.canonical_serialize());
+        assert_eq!(buff, expected);
+
+        assert_eq!(
+            opt_value.canonical_serialize(),
+            vec![1, 1, 0, 0, 0, 0, 0, 0, 0x12, 0x34]
+        );
     }
 }
@@ -0,0 +1,293 @@
+//! Offline verification of channel proofs.
+//!
+//! A channel proof contains the latest move tokens of a token channel, as exported by one side of
+//! the channel. Every move token is signed by the side that sent it, and commits to the balances
+//! of the channel. Anyone can verify a channel proof without trusting the exporting node, which
+//! allows to prove balances out of band when a channel becomes inconsistent.
+
+use proto::crypto::PublicKey;
+use proto::funder::messages::TokenInfo;
+use proto::proto_ser::ProtoDeserialize;
+use proto::report::messages::{ChannelProof, MoveTokenHashedReport};
+
+use crate::verify::verify_move_token_hashed_report;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChannelProofError {
+    /// The blob is not a serialized channel proof.
+    DeserializeError,
+    /// The proof contains no move tokens.
+    Empty,
+    /// A move token does not belong to the channel between the two sides of the proof.
+    PublicKeyMismatch,
+    /// The move token received from the friend is not signed by the friend.
+    InvalidIncomingSignature,
+    /// The move token sent to the friend is not signed by the exporting node.
+    InvalidOutgoingSignature,
+    /// The move token sent to the friend does not follow the move token received from the
+    /// friend.
+    CountersMismatch,
+}
+
+/// Verify a single move token, signed by `signer` and sent to `receiver`.
+fn verify_signed_move_token(
+    move_token_hashed_report: &MoveTokenHashedReport,
+    signer: &PublicKey,
+    receiver: &PublicKey,
+) -> Result<bool, ChannelProofError> {
+    let mc = &move_token_hashed_report.token_info.mc;
+    if &mc.local_public_key != signer || &mc.remote_public_key != receiver {
+        return Err(ChannelProofError::PublicKeyMismatch);
+    }
+    Ok(verify_move_token_hashed_report(
+        move_token_hashed_report,
+        signer,
+    ))
+}
+
+/// Verify that all the move tokens in a channel proof are signed by the side that sent them, and
+/// that they belong to the same channel.
+pub fn verify_channel_proof(channel_proof: &ChannelProof) -> Result<(), ChannelProofError> {
+    if channel_proof.opt_last_incoming_move_token.is_none()
+        && channel_proof.opt_last_outgoing_move_token.is_none()
+    {
+        return Err(ChannelProofError::Empty);
+    }
+
+    if let Some(incoming) = &channel_proof.opt_last_incoming_move_token {
+        if !verify_signed_move_token(
+            incoming,
+            &channel_proof.friend_public_key,
+            &channel_proof.local_public_key,
+        )? {
+            return Err(ChannelProofError::InvalidIncomingSignature);
+        }
+    }
+
+    if let Some(outgoing) = &channel_proof.opt_last_outgoing_move_token {
+        if !verify_signed_move_token(
+            outgoing,
+            &channel_proof.local_public_key,
+            &channel_proof.friend_public_key,
+        )? {
+            return Err(ChannelProofError::InvalidOutgoingSignature);
+        }
+    }
+
+    if let (Some(incoming), Some(outgoing)) = (
+        &channel_proof.opt_last_incoming_move_token,
+        &channel_proof.opt_last_outgoing_move_token,
+    ) {
+        let incoming_counters = &incoming.token_info.counters;
+        let outgoing_counters = &outgoing.token_info.counters;
+        let is_next = outgoing_counters.inconsistency_counter
+            == incoming_counters.inconsistency_counter
+            && outgoing_counters.move_token_counter
+                == incoming_counters.move_token_counter.wrapping_add(1);
+        // A reset move token may follow the last move token received before the channel became
+        // inconsistent:
+        let is_reset =
+            outgoing_counters.inconsistency_counter > incoming_counters.inconsistency_counter;
+        if !is_next && !is_reset {
+            return Err(ChannelProofError::CountersMismatch);
+        }
+    }
+
+    Ok(())
+}
+
+/// Deserialize a channel proof blob (As exported by a node), and verify it.
+pub fn verify_channel_proof_blob(blob: &[u8]) -> Result<ChannelProof, ChannelProofError> {
+    let channel_proof =
+        ChannelProof::proto_deserialize(blob).map_err(|_| ChannelProofError::DeserializeError)?;
+    verify_channel_proof(&channel_proof)?;
+    Ok(channel_proof)
+}
+
+/// The balances committed to by the latest move token of a channel proof, from the point of view
+/// of the exporting node.
+/// Returns None if the proof contains no move tokens.
+pub fn latest_token_info(channel_proof: &ChannelProof) -> Option<TokenInfo> {
+    if let Some(outgoing) = &channel_proof.opt_last_outgoing_move_token {
+        return Some(outgoing.token_info.clone());
+    }
+    channel_proof
+        .opt_last_incoming_move_token
+        .as_ref()
+        .map(|incoming| incoming.token_info.clone().flip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use crypto::identity::{Identity, SoftwareEd25519Identity};
+    use crypto::rand::RandGen;
+    use crypto::test_utils::DummyRandom;
+
+    use proto::crypto::{HashResult, PrivateKey, RandValue, Signature};
+    use proto::funder::messages::{
+        BalanceInfo, CountersInfo, Currency, CurrencyBalanceInfo, McInfo,
+    };
+    use proto::proto_ser::ProtoSerialize;
+
+    use crate::signature_buff::{move_token_hashed_report_signature_buff, SIGNATURE_BUFF_VERSION};
+
+    fn create_identity(seed: u8) -> SoftwareEd25519Identity {
+        let rng = DummyRandom::new(&[seed]);
+        let private_key = PrivateKey::rand_gen(&rng);
+        SoftwareEd25519Identity::from_private_key(&private_key).unwrap()
+    }
+
+    /// A move token sent by `sender` to `receiver`, where `sender` has the given balance.
+    fn create_signed_move_token(
+        sender: &impl Identity,
+        receiver: &PublicKey,
+        balance: i128,
+        move_token_counter: u128,
+    ) -> MoveTokenHashedReport {
+        let mut move_token_hashed_report = MoveTokenHashedReport {
+            prefix_hash: HashResult::from(&[1u8; HashResult::len()]),
+            token_info: TokenInfo {
+                mc: McInfo {
+                    local_public_key: sender.get_public_key(),
+                    remote_public_key: receiver.clone(),
+                    balances: vec![CurrencyBalanceInfo {
+                        currency: Currency::try_from("FST".to_owned()).unwrap(),
+                        balance_info: BalanceInfo {
+                            balance,
+                            local_pending_debt: 0,
+                            remote_pending_debt: 0,
+                        },
+                    }],
+                },
+                counters: CountersInfo {
+                    inconsistency_counter: 2,
+                    move_token_counter,
+                },
+            },
+            rand_nonce: RandValue::from(&[2u8; RandValue::len()]),
+            new_token: Signature::from(&[0u8; Signature::len()]),
+        };
+        move_token_hashed_report.new_token = sender.sign(&move_token_hashed_report_signature_buff(
+            &move_token_hashed_report,
+            SIGNATURE_BUFF_VERSION,
+        ));
+        move_token_hashed_report
+    }
+
+    #[test]
+    fn test_verify_channel_proof() {
+        let local_identity = create_identity(1);
+        let friend_identity = create_identity(2);
+        let local_public_key = local_identity.get_public_key();
+        let friend_public_key = friend_identity.get_public_key();
+
+        let channel_proof = ChannelProof {
+            local_public_key: local_public_key.clone(),
+            friend_public_key: friend_public_key.clone(),
+            opt_last_incoming_move_token: Some(create_signed_move_token(
+                &friend_identity,
+                &local_public_key,
+                -5,
+                6,
+            )),
+            opt_last_outgoing_move_token: Some(create_signed_move_token(
+                &local_identity,
+                &friend_public_key,
+                5,
+                7,
+            )),
+        };
+        assert_eq!(verify_channel_proof(&channel_proof), Ok(()));
+
+        // The blob is portable:
+        let blob = channel_proof.proto_serialize();
+        assert_eq!(verify_channel_proof_blob(&blob), Ok(channel_proof.clone()));
+        assert_eq!(
+            verify_channel_proof_blob(&blob[1..]),
+            Err(ChannelProofError::DeserializeError)
+        );
+
+        let token_info = latest_token_info(&channel_proof).unwrap();
+        assert_eq!(token_info.mc.local_public_key, local_public_key);
+        assert_eq!(token_info.mc.balances[0].balance_info.balance, 5);
+
+        // Only the incoming move token is known. Balances are seen from the local side:
+        let mut incoming_only = channel_proof.clone();
+        incoming_only.opt_last_outgoing_move_token = None;
+        assert_eq!(verify_channel_proof(&incoming_only), Ok(()));
+        let token_info = latest_token_info(&incoming_only).unwrap();
+        assert_eq!(token_info.mc.local_public_key, local_public_key);
+        assert_eq!(token_info.mc.balances[0].balance_info.balance, 5);
+
+        let mut empty = channel_proof.clone();
+        empty.opt_last_incoming_move_token = None;
+        empty.opt_last_outgoing_move_token = None;
+        assert_eq!(verify_channel_proof(&empty), Err(ChannelProofError::Empty));
+        assert!(latest_token_info(&empty).is_none());
+    }
+
+    #[test]
+    fn test_verify_channel_proof_forged() {
+        let local_identity = create_identity(1);
+        let friend_identity = create_identity(2);
+        let local_public_key = local_identity.get_public_key();
+        let friend_public_key = friend_identity.get_public_key();
+
+        let channel_proof = ChannelProof {
+            local_public_key: local_public_key.clone(),
+            friend_public_key: friend_public_key.clone(),
+            opt_last_incoming_move_token: Some(create_signed_move_token(
+                &friend_identity,
+                &local_public_key,
+                -5,
+                6,
+            )),
+            opt_last_outgoing_move_token: Some(create_signed_move_token(
+                &local_identity,
+                &friend_public_key,
+                5,
+                7,
+            )),
+        };
+
+        // Changing a signed balance invalidates the signature:
+        let mut forged = channel_proof.clone();
+        if let Some(incoming) = &mut forged.opt_last_incoming_move_token {
+            incoming.token_info.mc.balances[0].balance_info.balance = -50;
+        }
+        assert_eq!(
+            verify_channel_proof(&forged),
+            Err(ChannelProofError::InvalidIncomingSignature)
+        );
+
+        // A move token signed by the exporting node can not pass as a move token of the friend:
+        let mut forged = channel_proof.clone();
+        forged.opt_last_incoming_move_token = Some(create_signed_move_token(
+            &local_identity,
+            &local_public_key,
+            -5,
+            6,
+        ));
+        assert_eq!(
+            verify_channel_proof(&forged),
+            Err(ChannelProofError::PublicKeyMismatch)
+        );
+
+        // Move tokens from different points in time:
+        let mut forged = channel_proof.clone();
+        forged.opt_last_outgoing_move_token = Some(create_signed_move_token(
+            &local_identity,
+            &friend_public_key,
+            5,
+            9,
+        ));
+        assert_eq!(
+            verify_channel_proof(&forged),
+            Err(ChannelProofError::CountersMismatch)
+        );
+    }
+}
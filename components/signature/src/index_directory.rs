@@ -0,0 +1,85 @@
+use crypto::identity::Identity;
+
+use proto::index_server::messages::{IndexServerDirectory, NamedIndexServerAddress};
+
+use crate::signature_buff::index_server_directory_signature_buff;
+
+/// Create an index server directory listing `index_servers`, signed by the directory key
+/// `directory_identity`.
+pub fn create_index_server_directory<I>(
+    directory_identity: &I,
+    version: u64,
+    index_servers: Vec<NamedIndexServerAddress>,
+) -> IndexServerDirectory
+where
+    I: Identity,
+{
+    let signature_buff = index_server_directory_signature_buff(version, &index_servers);
+
+    IndexServerDirectory {
+        version,
+        index_servers,
+        signature: directory_identity.sign(&signature_buff),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use crypto::identity::SoftwareEd25519Identity;
+    use crypto::rand::RandGen;
+    use crypto::test_utils::DummyRandom;
+
+    use proto::crypto::{PrivateKey, PublicKey};
+    use proto::net::messages::NetAddress;
+
+    use crate::verify::verify_index_server_directory;
+
+    fn create_identity(seed: u8) -> SoftwareEd25519Identity {
+        let rng = DummyRandom::new(&[seed]);
+        let private_key = PrivateKey::rand_gen(&rng);
+        SoftwareEd25519Identity::from_private_key(&private_key).unwrap()
+    }
+
+    #[test]
+    fn test_verify_index_server_directory() {
+        let directory_identity = create_identity(1);
+        let other_identity = create_identity(2);
+
+        let index_servers = vec![NamedIndexServerAddress {
+            public_key: PublicKey::from(&[0xaa; PublicKey::len()]),
+            address: NetAddress::try_from("index.example.com:1339".to_owned()).unwrap(),
+            name: "index".to_owned(),
+        }];
+        let directory = create_index_server_directory(&directory_identity, 3, index_servers);
+        assert!(verify_index_server_directory(
+            &directory,
+            &directory_identity.get_public_key()
+        ));
+
+        // Signed by a different key:
+        assert!(!verify_index_server_directory(
+            &directory,
+            &other_identity.get_public_key()
+        ));
+
+        // The version is signed:
+        let mut tampered = directory.clone();
+        tampered.version += 1;
+        assert!(!verify_index_server_directory(
+            &tampered,
+            &directory_identity.get_public_key()
+        ));
+
+        // The listed index servers are signed:
+        let mut tampered = directory.clone();
+        tampered.index_servers[0].name = "evil".to_owned();
+        assert!(!verify_index_server_directory(
+            &tampered,
+            &directory_identity.get_public_key()
+        ));
+    }
+}
@@ -0,0 +1,67 @@
+use crypto::identity::Identity;
+
+use proto::funder::messages::KeyRotation;
+
+use crate::signature_buff::key_rotation_signature_buff;
+
+/// Create a statement that the node with `old_identity` has replaced its key with the key of
+/// `new_identity`, signed by both keys.
+pub fn create_key_rotation<OI, NI>(old_identity: &OI, new_identity: &NI) -> KeyRotation
+where
+    OI: Identity,
+    NI: Identity,
+{
+    let old_public_key = old_identity.get_public_key();
+    let new_public_key = new_identity.get_public_key();
+    let signature_buff = key_rotation_signature_buff(&old_public_key, &new_public_key);
+
+    KeyRotation {
+        old_public_key,
+        new_public_key,
+        old_signature: old_identity.sign(&signature_buff),
+        new_signature: new_identity.sign(&signature_buff),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::identity::SoftwareEd25519Identity;
+    use crypto::rand::RandGen;
+    use crypto::test_utils::DummyRandom;
+
+    use proto::crypto::PrivateKey;
+
+    use crate::verify::verify_key_rotation;
+
+    fn create_identity(seed: u8) -> SoftwareEd25519Identity {
+        let rng = DummyRandom::new(&[seed]);
+        let private_key = PrivateKey::rand_gen(&rng);
+        SoftwareEd25519Identity::from_private_key(&private_key).unwrap()
+    }
+
+    #[test]
+    fn test_verify_key_rotation() {
+        let old_identity = create_identity(1);
+        let new_identity = create_identity(2);
+        let other_identity = create_identity(3);
+
+        let key_rotation = create_key_rotation(&old_identity, &new_identity);
+        assert!(verify_key_rotation(&key_rotation));
+
+        // Both signatures are required:
+        let mut tampered = key_rotation.clone();
+        tampered.old_signature = tampered.new_signature.clone();
+        assert!(!verify_key_rotation(&tampered));
+
+        // The new key must agree to the rotation:
+        let mut tampered = key_rotation.clone();
+        tampered.new_public_key = other_identity.get_public_key();
+        assert!(!verify_key_rotation(&tampered));
+
+        // Rotating to the same key is meaningless:
+        let key_rotation = create_key_rotation(&old_identity, &old_identity);
+        assert!(!verify_key_rotation(&key_rotation));
+    }
+}
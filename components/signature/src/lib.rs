@@ -9,5 +9,9 @@
 )]
 
 pub mod canonical;
+pub mod channel_proof;
+pub mod index_directory;
+pub mod key_rotation;
+pub mod receipt;
 pub mod signature_buff;
 pub mod verify;
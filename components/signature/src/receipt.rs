@@ -0,0 +1,232 @@
+//! Offline verification of receipts.
+//!
+//! A seller can verify a receipt presented by a buyer without running a node.
+//! This module only uses `core` and `alloc` facilities (No I/O and no std collections), so that it
+//! can be used in constrained environments, like a point of sale device.
+
+use crypto::hash::sha_512_256;
+use crypto::hash_lock::HashLock;
+use crypto::identity::verify_signature;
+
+use proto::crypto::{InvoiceId, PublicKey};
+use proto::funder::messages::Receipt;
+
+use crate::canonical::CanonicalSerialize;
+use crate::signature_buff::{
+    FUNDS_RESPONSE_PREFIX, LEGACY_SIGNATURE_BUFF_VERSION, SIGNATURE_BUFF_VERSION,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReceiptError {
+    /// The receipt belongs to a different invoice.
+    InvoiceIdMismatch,
+    /// The receipt was issued for a different total amount.
+    AmountMismatch,
+    /// The amount paid by the receipt's transaction is larger than the total amount.
+    InvalidDestPayment,
+    /// The change given back by the seller is larger than the amount paid by the receipt's
+    /// transaction.
+    InvalidChange,
+    /// The seller did not mark the invoice as fully paid.
+    Incomplete,
+    /// The receipt is not signed by the seller.
+    InvalidSignature,
+}
+
+/// Create the buffer signed by the seller when issuing a receipt, in the layout
+/// `signature_version`.
+/// This is the same buffer the seller signs over when responding to a funds request.
+pub fn receipt_signature_buff(receipt: &Receipt, signature_version: u32) -> Vec<u8> {
+    let mut sbuffer = Vec::new();
+    sbuffer.extend_from_slice(&sha_512_256(FUNDS_RESPONSE_PREFIX));
+    if signature_version != LEGACY_SIGNATURE_BUFF_VERSION {
+        sbuffer.extend_from_slice(&signature_version.to_be_bytes());
+    }
+    sbuffer.extend_from_slice(&receipt.response_hash);
+    sbuffer.extend_from_slice(&receipt.src_plain_lock.hash_lock());
+    sbuffer.extend_from_slice(&receipt.dest_plain_lock.hash_lock());
+    receipt.is_complete.canonical_serialize_into(&mut sbuffer);
+    if signature_version != LEGACY_SIGNATURE_BUFF_VERSION {
+        sbuffer.extend_from_slice(&receipt.change.to_be_bytes());
+    }
+    sbuffer.extend_from_slice(&receipt.dest_payment.to_be_bytes());
+    sbuffer.extend_from_slice(&receipt.total_dest_payment.to_be_bytes());
+    sbuffer.extend_from_slice(&receipt.invoice_id);
+    receipt.currency.canonical_serialize_into(&mut sbuffer);
+    sbuffer
+}
+
+/// Verify that a receipt is signed by the seller (`public_key`)
+/// A receipt without change may be signed in the legacy layout (See
+/// `response_signature_version`).
+pub fn verify_receipt_signature(receipt: &Receipt, public_key: &PublicKey) -> bool {
+    let verify_version = |signature_version| {
+        verify_signature(
+            &receipt_signature_buff(receipt, signature_version),
+            public_key,
+            &receipt.signature,
+        )
+    };
+    verify_version(SIGNATURE_BUFF_VERSION)
+        || (receipt.change == 0 && verify_version(LEGACY_SIGNATURE_BUFF_VERSION))
+}
+
+/// Verify that a receipt proves the full payment of an invoice:
+/// The receipt matches the invoice id and the total amount (`total_dest_payment`) of the
+/// invoice, the invoice was marked as fully paid, and the receipt is signed by the seller
+/// (`public_key`).
+pub fn verify_receipt(
+    receipt: &Receipt,
+    invoice_id: &InvoiceId,
+    total_dest_payment: u128,
+    public_key: &PublicKey,
+) -> Result<(), ReceiptError> {
+    if &receipt.invoice_id != invoice_id {
+        return Err(ReceiptError::InvoiceIdMismatch);
+    }
+    if receipt.total_dest_payment != total_dest_payment {
+        return Err(ReceiptError::AmountMismatch);
+    }
+    if receipt.dest_payment > receipt.total_dest_payment {
+        return Err(ReceiptError::InvalidDestPayment);
+    }
+    if receipt.change > receipt.dest_payment {
+        return Err(ReceiptError::InvalidChange);
+    }
+    if !receipt.is_complete {
+        return Err(ReceiptError::Incomplete);
+    }
+    if !verify_receipt_signature(receipt, public_key) {
+        return Err(ReceiptError::InvalidSignature);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use crypto::identity::{Identity, SoftwareEd25519Identity};
+    use crypto::rand::RandGen;
+    use crypto::test_utils::DummyRandom;
+
+    use proto::crypto::{HashResult, PlainLock, PrivateKey, Signature};
+    use proto::funder::messages::Currency;
+
+    use crate::signature_buff::signature_buff_header;
+
+    fn create_signed_receipt(identity: &impl Identity, signature_version: u32) -> Receipt {
+        let mut receipt = Receipt {
+            response_hash: HashResult::from(&[1u8; HashResult::len()]),
+            invoice_id: InvoiceId::from(&[2u8; InvoiceId::len()]),
+            currency: Currency::try_from("FST".to_owned()).unwrap(),
+            src_plain_lock: PlainLock::from(&[3u8; PlainLock::len()]),
+            dest_plain_lock: PlainLock::from(&[4u8; PlainLock::len()]),
+            is_complete: true,
+            dest_payment: 10,
+            total_dest_payment: 15,
+            change: 0,
+            signature: Signature::from(&[0u8; Signature::len()]),
+        };
+        receipt.signature = identity.sign(&receipt_signature_buff(&receipt, signature_version));
+        receipt
+    }
+
+    #[test]
+    fn test_receipt_signature_buff_header() {
+        let rng = DummyRandom::new(&[1u8]);
+        let private_key = PrivateKey::rand_gen(&rng);
+        let identity = SoftwareEd25519Identity::from_private_key(&private_key).unwrap();
+        let receipt = create_signed_receipt(&identity, SIGNATURE_BUFF_VERSION);
+
+        // The header is built without byteorder, but must match the header of all the other
+        // signed buffers:
+        let header = signature_buff_header(FUNDS_RESPONSE_PREFIX);
+        assert!(receipt_signature_buff(&receipt, SIGNATURE_BUFF_VERSION).starts_with(&header));
+    }
+
+    #[test]
+    fn test_verify_receipt() {
+        let rng = DummyRandom::new(&[1u8]);
+        let private_key = PrivateKey::rand_gen(&rng);
+        let identity = SoftwareEd25519Identity::from_private_key(&private_key).unwrap();
+        let public_key = identity.get_public_key();
+
+        let receipt = create_signed_receipt(&identity, SIGNATURE_BUFF_VERSION);
+        let invoice_id = receipt.invoice_id.clone();
+
+        assert_eq!(
+            verify_receipt(&receipt, &invoice_id, 15, &public_key),
+            Ok(())
+        );
+
+        assert_eq!(
+            verify_receipt(
+                &receipt,
+                &InvoiceId::from(&[5u8; InvoiceId::len()]),
+                15,
+                &public_key
+            ),
+            Err(ReceiptError::InvoiceIdMismatch)
+        );
+        assert_eq!(
+            verify_receipt(&receipt, &invoice_id, 16, &public_key),
+            Err(ReceiptError::AmountMismatch)
+        );
+
+        let mut incomplete_receipt = receipt.clone();
+        incomplete_receipt.is_complete = false;
+        assert_eq!(
+            verify_receipt(&incomplete_receipt, &invoice_id, 15, &public_key),
+            Err(ReceiptError::Incomplete)
+        );
+
+        let mut invalid_receipt = receipt.clone();
+        invalid_receipt.change = 11;
+        assert_eq!(
+            verify_receipt(&invalid_receipt, &invoice_id, 15, &public_key),
+            Err(ReceiptError::InvalidChange)
+        );
+
+        // Changing a signed field invalidates the signature:
+        let mut forged_receipt = receipt.clone();
+        forged_receipt.dest_payment = 15;
+        assert_eq!(
+            verify_receipt(&forged_receipt, &invoice_id, 15, &public_key),
+            Err(ReceiptError::InvalidSignature)
+        );
+        let mut forged_receipt = receipt.clone();
+        forged_receipt.change = 1;
+        assert_eq!(
+            verify_receipt(&forged_receipt, &invoice_id, 15, &public_key),
+            Err(ReceiptError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_verify_legacy_receipt() {
+        let rng = DummyRandom::new(&[1u8]);
+        let private_key = PrivateKey::rand_gen(&rng);
+        let identity = SoftwareEd25519Identity::from_private_key(&private_key).unwrap();
+        let public_key = identity.get_public_key();
+
+        let receipt = create_signed_receipt(&identity, LEGACY_SIGNATURE_BUFF_VERSION);
+        let invoice_id = receipt.invoice_id.clone();
+
+        assert_eq!(
+            verify_receipt(&receipt, &invoice_id, 15, &public_key),
+            Ok(())
+        );
+
+        // The legacy layout does not contain the change, so a legacy receipt can only be valid
+        // without change:
+        let mut forged_receipt = receipt.clone();
+        forged_receipt.change = 1;
+        assert_eq!(
+            verify_receipt(&forged_receipt, &invoice_id, 15, &public_key),
+            Err(ReceiptError::InvalidSignature)
+        );
+    }
+}
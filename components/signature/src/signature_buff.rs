@@ -2,16 +2,40 @@ use byteorder::{BigEndian, WriteBytesExt};
 
 use crypto::hash::{self, sha_512_256};
 
-use proto::crypto::HashResult;
+use proto::crypto::{HashResult, PublicKey, RandValue, Uid};
 
 use common::int_convert::usize_to_u64;
 
 use crate::canonical::CanonicalSerialize;
 use proto::funder::messages::{
-    Currency, PendingTransaction, TokenInfo, UnsignedMoveToken, UnsignedResponseSendFundsOp,
+    Currency, CurrencyExchange, CurrencyOperations, FriendTcOp, PendingTransaction, TokenInfo,
+    UnsignedMoveToken, UnsignedResponseSendFundsOp,
 };
-use proto::index_server::messages::MutationsUpdate;
+use proto::index_server::messages::{FriendProposal, MutationsUpdate, NamedIndexServerAddress};
 use proto::report::messages::MoveTokenHashedReport;
+use proto::secure_channel::messages::ExchangeDh;
+
+/// Version of the signed buffers layout.
+/// Must be increased whenever the layout of any signed buffer changes.
+pub const SIGNATURE_BUFF_VERSION: u32 = 1;
+
+/// The layout of the signed buffers used by nodes that predate signature buffer versions.
+/// Legacy buffers begin with the hash of the tag alone, and do not cover any of the fields added
+/// to the signed structures since. They are still created when talking to such nodes, and
+/// verified when received from them.
+pub const LEGACY_SIGNATURE_BUFF_VERSION: u32 = 0;
+
+// Domain separation tags.
+// Every signed buffer begins with the hash of a tag unique to the signed structure, followed by
+// SIGNATURE_BUFF_VERSION (Except for legacy buffers). This makes sure that a signature over one
+// structure can never be presented as a valid signature over another structure (Or over another
+// version of the same structure).
+//
+// A new signed structure must get a new tag, and the tag must be added to SIGNATURE_TAGS.
+//
+// Every signature buffer can also be written into a caller provided buffer (The `_into`
+// variants), allowing a busy node to reuse a single buffer for all the signatures it creates and
+// verifies.
 
 pub const FUNDS_RESPONSE_PREFIX: &[u8] = b"FUND_RESPONSE";
 pub const FUNDS_CANCEL_PREFIX: &[u8] = b"FUND_CANCEL";
@@ -19,6 +43,11 @@ pub const FUNDS_CANCEL_PREFIX: &[u8] = b"FUND_CANCEL";
 /// Create the buffer we sign over at the Response funds.
 /// Note that the signature is not just over the Response funds bytes. The signed buffer also
 /// contains information from the Request funds.
+///
+/// If the request was exchanged to another currency on the way, the signature is over the terms of
+/// the request in the currency of the destination.
+///
+/// The layout is chosen according to `response_signature_version`.
 pub fn create_response_signature_buffer<RSF>(
     currency: &Currency,
     response_send_funds: RSF,
@@ -27,29 +56,74 @@ pub fn create_response_signature_buffer<RSF>(
 where
     RSF: Into<UnsignedResponseSendFundsOp>,
 {
-    let response_send_funds: UnsignedResponseSendFundsOp = response_send_funds.into();
-    let mut sbuffer = Vec::new();
+    let mut sbuffer = Vec::with_capacity(SIGNATURE_BUFF_CAPACITY);
+    create_response_signature_buffer_into(
+        currency,
+        response_send_funds,
+        pending_transaction,
+        &mut sbuffer,
+    );
+    sbuffer
+}
 
-    sbuffer.extend_from_slice(&hash::sha_512_256(FUNDS_RESPONSE_PREFIX));
+/// Append the buffer we sign over at the Response funds to `sbuffer`.
+/// See `create_response_signature_buffer`.
+pub fn create_response_signature_buffer_into<RSF>(
+    currency: &Currency,
+    response_send_funds: RSF,
+    pending_transaction: &PendingTransaction,
+    sbuffer: &mut Vec<u8>,
+) where
+    RSF: Into<UnsignedResponseSendFundsOp>,
+{
+    let response_send_funds: UnsignedResponseSendFundsOp = response_send_funds.into();
+    let signature_version = response_signature_version(
+        response_send_funds.change,
+        &pending_transaction.opt_exchange,
+    );
+    signature_buff_header_version_into(FUNDS_RESPONSE_PREFIX, signature_version, sbuffer);
 
-    let mut inner_blob = Vec::new();
-    inner_blob.extend_from_slice(&pending_transaction.request_id);
-    inner_blob.extend_from_slice(&response_send_funds.rand_nonce);
+    let mut inner_blob = [0u8; Uid::len() + RandValue::len()];
+    inner_blob[..Uid::len()].copy_from_slice(&pending_transaction.request_id);
+    inner_blob[Uid::len()..].copy_from_slice(&response_send_funds.rand_nonce);
 
     sbuffer.extend_from_slice(&hash::sha_512_256(&inner_blob));
     sbuffer.extend_from_slice(&pending_transaction.src_hashed_lock);
     sbuffer.extend_from_slice(&response_send_funds.dest_hashed_lock);
     sbuffer.extend_from_slice(&response_send_funds.is_complete.canonical_serialize());
-    sbuffer
-        .write_u128::<BigEndian>(pending_transaction.dest_payment)
-        .unwrap();
-    sbuffer
-        .write_u128::<BigEndian>(pending_transaction.total_dest_payment)
-        .unwrap();
+    if signature_version != LEGACY_SIGNATURE_BUFF_VERSION {
+        sbuffer
+            .write_u128::<BigEndian>(response_send_funds.change)
+            .unwrap();
+    }
+    let (currency, dest_payment, total_dest_payment) = match &pending_transaction.opt_exchange {
+        Some(currency_exchange) => (
+            &currency_exchange.dest_currency,
+            currency_exchange.dest_payment,
+            currency_exchange.total_dest_payment,
+        ),
+        None => (
+            currency,
+            pending_transaction.dest_payment,
+            pending_transaction.total_dest_payment,
+        ),
+    };
+    sbuffer.write_u128::<BigEndian>(dest_payment).unwrap();
+    sbuffer.write_u128::<BigEndian>(total_dest_payment).unwrap();
     sbuffer.extend_from_slice(&pending_transaction.invoice_id);
-    sbuffer.extend_from_slice(&currency.canonical_serialize());
+    currency.canonical_serialize_into(sbuffer);
+}
 
-    sbuffer
+/// The layout of the buffer signed over at a response.
+/// A response is verified by every node along the route, some of which may predate signature
+/// buffer versions. Therefore the legacy layout is used whenever it can represent the response:
+/// If no change is given back, and the request was not exchanged to another currency.
+pub fn response_signature_version(change: u128, opt_exchange: &Option<CurrencyExchange>) -> u32 {
+    if change == 0 && opt_exchange.is_none() {
+        LEGACY_SIGNATURE_BUFF_VERSION
+    } else {
+        SIGNATURE_BUFF_VERSION
+    }
 }
 
 // Prefix used for chain hashing of token channel funds.
@@ -86,42 +160,132 @@ where
     MT: Into<UnsignedMoveToken<B>>,
 {
     let move_token: UnsignedMoveToken<B> = move_token.into();
+    unsigned_prefix_hash(&move_token)
+}
+
+fn unsigned_prefix_hash<B>(move_token: &UnsignedMoveToken<B>) -> HashResult
+where
+    B: CanonicalSerialize,
+{
     let mut hash_buff = Vec::new();
 
     hash_buff.extend_from_slice(&move_token.old_token);
-    hash_buff.extend_from_slice(&move_token.currencies_operations.canonical_serialize());
-    hash_buff.extend_from_slice(&move_token.opt_local_relays.canonical_serialize());
-    hash_buff.extend_from_slice(&move_token.opt_active_currencies.canonical_serialize());
+    if move_token.signature_version == LEGACY_SIGNATURE_BUFF_VERSION {
+        legacy_operations_serialize_into(&move_token.currencies_operations, &mut hash_buff);
+    } else {
+        move_token
+            .currencies_operations
+            .canonical_serialize_into(&mut hash_buff);
+    }
+    move_token
+        .opt_local_relays
+        .canonical_serialize_into(&mut hash_buff);
+    move_token
+        .opt_active_currencies
+        .canonical_serialize_into(&mut hash_buff);
 
     sha_512_256(&hash_buff)
 }
 
+/// Serialize operations the way nodes that predate signature buffer versions do.
+/// Fields added to the operations since are left out. Refunds were not yet supported by such
+/// nodes, and are serialized canonically.
+fn legacy_operations_serialize_into(
+    currencies_operations: &[CurrencyOperations],
+    buff: &mut Vec<u8>,
+) {
+    buff.write_u64::<BigEndian>(usize_to_u64(currencies_operations.len()).unwrap())
+        .unwrap();
+    for currency_operations in currencies_operations {
+        currency_operations.currency.canonical_serialize_into(buff);
+        buff.write_u64::<BigEndian>(usize_to_u64(currency_operations.operations.len()).unwrap())
+            .unwrap();
+        for operation in &currency_operations.operations {
+            match operation {
+                FriendTcOp::RequestSendFunds(request_send_funds) => {
+                    buff.push(0u8);
+                    buff.extend_from_slice(&request_send_funds.request_id);
+                    buff.extend_from_slice(&request_send_funds.src_hashed_lock);
+                    request_send_funds.route.canonical_serialize_into(buff);
+                    buff.write_u128::<BigEndian>(request_send_funds.dest_payment)
+                        .unwrap();
+                    buff.write_u128::<BigEndian>(request_send_funds.total_dest_payment)
+                        .unwrap();
+                    buff.extend_from_slice(&request_send_funds.invoice_id);
+                    buff.write_u128::<BigEndian>(request_send_funds.left_fees)
+                        .unwrap();
+                }
+                FriendTcOp::ResponseSendFunds(response_send_funds) => {
+                    buff.push(1u8);
+                    buff.extend_from_slice(&response_send_funds.request_id);
+                    buff.extend_from_slice(&response_send_funds.dest_hashed_lock);
+                    response_send_funds
+                        .is_complete
+                        .canonical_serialize_into(buff);
+                    buff.extend_from_slice(&response_send_funds.rand_nonce);
+                    buff.extend_from_slice(&response_send_funds.signature);
+                }
+                FriendTcOp::CancelSendFunds(_)
+                | FriendTcOp::CollectSendFunds(_)
+                | FriendTcOp::RefundSendFunds(_) => operation.canonical_serialize_into(buff),
+            }
+        }
+    }
+}
+
 pub fn move_token_signature_buff<B, MT>(move_token: MT) -> Vec<u8>
 where
     B: CanonicalSerialize + Clone,
     MT: Into<UnsignedMoveToken<B>>,
 {
     let move_token: UnsignedMoveToken<B> = move_token.into();
-    let mut sig_buffer = Vec::new();
-    sig_buffer.extend_from_slice(&sha_512_256(TOKEN_NEXT));
-    sig_buffer.extend_from_slice(&prefix_hash(move_token.clone()));
+    let mut sig_buffer = Vec::with_capacity(SIGNATURE_BUFF_CAPACITY);
+    move_token_signature_buff_into(&move_token, &mut sig_buffer);
+    sig_buffer
+}
+
+pub fn move_token_signature_buff_into<B>(
+    move_token: &UnsignedMoveToken<B>,
+    sig_buffer: &mut Vec<u8>,
+) where
+    B: CanonicalSerialize,
+{
+    signature_buff_header_version_into(TOKEN_NEXT, move_token.signature_version, sig_buffer);
+    sig_buffer.extend_from_slice(&unsigned_prefix_hash(move_token));
     sig_buffer.extend_from_slice(&move_token.info_hash);
     sig_buffer.extend_from_slice(&move_token.rand_nonce);
-    sig_buffer
 }
 
 pub const MUTATIONS_UPDATE_PREFIX: &[u8] = b"MUTATIONS_UPDATE";
 
-pub fn create_mutations_update_signature_buff(mutations_update: &MutationsUpdate) -> Vec<u8> {
-    let mut res_bytes = Vec::new();
-    res_bytes.extend_from_slice(&hash::sha_512_256(MUTATIONS_UPDATE_PREFIX));
+/// Create the buffer signed over at a `MutationsUpdate`.
+/// Index servers that predate signature buffer versions only verify the legacy layout.
+pub fn create_mutations_update_signature_buff(
+    mutations_update: &MutationsUpdate,
+    signature_version: u32,
+) -> Vec<u8> {
+    let mut res_bytes = Vec::with_capacity(SIGNATURE_BUFF_CAPACITY);
+    create_mutations_update_signature_buff_into(
+        mutations_update,
+        signature_version,
+        &mut res_bytes,
+    );
+    res_bytes
+}
+
+pub fn create_mutations_update_signature_buff_into(
+    mutations_update: &MutationsUpdate,
+    signature_version: u32,
+    res_bytes: &mut Vec<u8>,
+) {
+    signature_buff_header_version_into(MUTATIONS_UPDATE_PREFIX, signature_version, res_bytes);
     res_bytes.extend_from_slice(&mutations_update.node_public_key);
 
     res_bytes
         .write_u64::<BigEndian>(usize_to_u64(mutations_update.index_mutations.len()).unwrap())
         .unwrap();
     for mutation in &mutations_update.index_mutations {
-        res_bytes.extend(mutation.canonical_serialize());
+        mutation.canonical_serialize_into(res_bytes);
     }
 
     res_bytes.extend_from_slice(&mutations_update.time_hash);
@@ -130,17 +294,374 @@ pub fn create_mutations_update_signature_buff(mutations_update: &MutationsUpdate
         .write_u64::<BigEndian>(mutations_update.counter)
         .unwrap();
     res_bytes.extend_from_slice(&mutations_update.rand_nonce);
+}
 
-    res_bytes
+pub const MUTATIONS_UPDATE_POW_PREFIX: &[u8] = b"MUTATIONS_UPDATE_POW";
+
+/// Create the buffer we calculate the proof of work over at the MutationsUpdate structure.
+/// The buffer contains the hash of the signature buffer, so that a proof of work can not be
+/// reused for a different message.
+/// Only index servers that use the current signature buffer layout require a proof of work.
+pub fn create_mutations_update_pow_buff(mutations_update: &MutationsUpdate) -> Vec<u8> {
+    let mut pow_buff = signature_buff_header(MUTATIONS_UPDATE_POW_PREFIX);
+    pow_buff.extend_from_slice(&sha_512_256(&create_mutations_update_signature_buff(
+        mutations_update,
+        SIGNATURE_BUFF_VERSION,
+    )));
+    pow_buff
 }
 
+/// Create the buffer signed over at a move token, given its hashed report.
+/// The report does not state the layout the move token was signed with, hence `signature_version`.
 pub fn move_token_hashed_report_signature_buff(
     move_token_hashed_report: &MoveTokenHashedReport,
+    signature_version: u32,
 ) -> Vec<u8> {
-    let mut sig_buffer = Vec::new();
-    sig_buffer.extend_from_slice(&sha_512_256(TOKEN_NEXT));
+    let mut sig_buffer = Vec::with_capacity(SIGNATURE_BUFF_CAPACITY);
+    move_token_hashed_report_signature_buff_into(
+        move_token_hashed_report,
+        signature_version,
+        &mut sig_buffer,
+    );
+    sig_buffer
+}
+
+pub fn move_token_hashed_report_signature_buff_into(
+    move_token_hashed_report: &MoveTokenHashedReport,
+    signature_version: u32,
+    sig_buffer: &mut Vec<u8>,
+) {
+    signature_buff_header_version_into(TOKEN_NEXT, signature_version, sig_buffer);
     sig_buffer.extend_from_slice(&move_token_hashed_report.prefix_hash);
     sig_buffer.extend_from_slice(&hash_token_info(&move_token_hashed_report.token_info));
     sig_buffer.extend_from_slice(&move_token_hashed_report.rand_nonce);
-    sig_buffer
+}
+
+pub const FRIEND_PROPOSAL_PREFIX: &[u8] = b"FRIEND_PROPOSAL";
+
+/// Create the buffer the proposing node signs over at a `FriendProposal`
+pub fn friend_proposal_signature_buff(friend_proposal: &FriendProposal) -> Vec<u8> {
+    let mut sbuffer = Vec::with_capacity(SIGNATURE_BUFF_CAPACITY);
+    friend_proposal_signature_buff_into(friend_proposal, &mut sbuffer);
+    sbuffer
+}
+
+pub fn friend_proposal_signature_buff_into(
+    friend_proposal: &FriendProposal,
+    sbuffer: &mut Vec<u8>,
+) {
+    signature_buff_header_into(FRIEND_PROPOSAL_PREFIX, sbuffer);
+    sbuffer.extend_from_slice(&friend_proposal.src_public_key);
+    sbuffer.extend_from_slice(&friend_proposal.dest_public_key);
+    friend_proposal.relays.canonical_serialize_into(sbuffer);
+    friend_proposal.currency.canonical_serialize_into(sbuffer);
+    sbuffer
+        .write_u128::<BigEndian>(friend_proposal.proposed_max_debt)
+        .unwrap();
+    sbuffer.extend_from_slice(&friend_proposal.rand_nonce);
+}
+
+pub const EXCHANGE_DH_PREFIX: &[u8] = b"EXCHANGE_DH";
+
+/// Create the buffer we sign over at the ExchangeDh message (Secure channel handshake).
+/// The ranges of versions (min, max) advertised by the signer and by the receiver of the message
+/// are signed too, so that tampering with the advertised ranges (For example, to force a lower
+/// version) is detected.
+///
+/// Nodes that predate version negotiation (Secure channel version 0) sign over a legacy buffer,
+/// containing neither the version nor the ranges.
+pub fn exchange_dh_signature_buff(
+    exchange_dh: &ExchangeDh,
+    signer_versions: (u32, u32),
+    receiver_versions: (u32, u32),
+) -> Vec<u8> {
+    let mut sbuffer = Vec::with_capacity(SIGNATURE_BUFF_CAPACITY);
+    exchange_dh_signature_buff_into(
+        exchange_dh,
+        signer_versions,
+        receiver_versions,
+        &mut sbuffer,
+    );
+    sbuffer
+}
+
+pub fn exchange_dh_signature_buff_into(
+    exchange_dh: &ExchangeDh,
+    signer_versions: (u32, u32),
+    receiver_versions: (u32, u32),
+    sbuffer: &mut Vec<u8>,
+) {
+    if exchange_dh.version == 0 {
+        sbuffer.extend_from_slice(&exchange_dh.dh_public_key);
+        sbuffer.extend_from_slice(&exchange_dh.rand_nonce);
+        sbuffer.extend_from_slice(&exchange_dh.key_salt);
+        return;
+    }
+
+    signature_buff_header_into(EXCHANGE_DH_PREFIX, sbuffer);
+    sbuffer.extend_from_slice(&exchange_dh.dh_public_key);
+    sbuffer.extend_from_slice(&exchange_dh.rand_nonce);
+    sbuffer.extend_from_slice(&exchange_dh.key_salt);
+    sbuffer.write_u32::<BigEndian>(exchange_dh.version).unwrap();
+    for &(min_version, max_version) in &[signer_versions, receiver_versions] {
+        sbuffer.write_u32::<BigEndian>(min_version).unwrap();
+        sbuffer.write_u32::<BigEndian>(max_version).unwrap();
+    }
+}
+
+pub const KEY_ROTATION_PREFIX: &[u8] = b"KEY_ROTATION";
+
+/// Create the buffer signed by both the old and the new keys at a `KeyRotation`
+pub fn key_rotation_signature_buff(
+    old_public_key: &PublicKey,
+    new_public_key: &PublicKey,
+) -> Vec<u8> {
+    let mut sbuffer = Vec::with_capacity(SIGNATURE_BUFF_CAPACITY);
+    key_rotation_signature_buff_into(old_public_key, new_public_key, &mut sbuffer);
+    sbuffer
+}
+
+pub fn key_rotation_signature_buff_into(
+    old_public_key: &PublicKey,
+    new_public_key: &PublicKey,
+    sbuffer: &mut Vec<u8>,
+) {
+    signature_buff_header_into(KEY_ROTATION_PREFIX, sbuffer);
+    sbuffer.extend_from_slice(old_public_key);
+    sbuffer.extend_from_slice(new_public_key);
+}
+
+pub const INDEX_SERVER_DIRECTORY_PREFIX: &[u8] = b"INDEX_SERVER_DIRECTORY";
+
+/// Create the buffer the directory key signs over at an `IndexServerDirectory`
+pub fn index_server_directory_signature_buff(
+    version: u64,
+    index_servers: &[NamedIndexServerAddress],
+) -> Vec<u8> {
+    let mut sbuffer = Vec::with_capacity(SIGNATURE_BUFF_CAPACITY);
+    index_server_directory_signature_buff_into(version, index_servers, &mut sbuffer);
+    sbuffer
+}
+
+pub fn index_server_directory_signature_buff_into(
+    version: u64,
+    index_servers: &[NamedIndexServerAddress],
+    sbuffer: &mut Vec<u8>,
+) {
+    signature_buff_header_into(INDEX_SERVER_DIRECTORY_PREFIX, sbuffer);
+    sbuffer.write_u64::<BigEndian>(version).unwrap();
+    sbuffer
+        .write_u64::<BigEndian>(usize_to_u64(index_servers.len()).unwrap())
+        .unwrap();
+    for named_index_server_address in index_servers {
+        named_index_server_address.canonical_serialize_into(sbuffer);
+    }
+}
+
+/// All the domain separation tags in use.
+pub const SIGNATURE_TAGS: &[&[u8]] = &[
+    FUNDS_RESPONSE_PREFIX,
+    TOKEN_NEXT,
+    MUTATIONS_UPDATE_PREFIX,
+    MUTATIONS_UPDATE_POW_PREFIX,
+    EXCHANGE_DH_PREFIX,
+    FRIEND_PROPOSAL_PREFIX,
+    KEY_ROTATION_PREFIX,
+    INDEX_SERVER_DIRECTORY_PREFIX,
+];
+
+/// Initial capacity of a newly allocated signature buffer.
+/// Large enough to contain all the fixed size signature buffers without reallocating.
+const SIGNATURE_BUFF_CAPACITY: usize = 0x100;
+
+/// The beginning of every signed buffer: The hash of the domain separation tag, and the
+/// version of the signed buffers layout.
+pub fn signature_buff_header(tag: &[u8]) -> Vec<u8> {
+    let mut sbuffer = Vec::with_capacity(SIGNATURE_BUFF_CAPACITY);
+    signature_buff_header_into(tag, &mut sbuffer);
+    sbuffer
+}
+
+pub fn signature_buff_header_into(tag: &[u8], sbuffer: &mut Vec<u8>) {
+    signature_buff_header_version_into(tag, SIGNATURE_BUFF_VERSION, sbuffer);
+}
+
+/// The beginning of a signed buffer of a given layout version.
+/// A legacy header contains only the hash of the domain separation tag.
+pub fn signature_buff_header_version_into(
+    tag: &[u8],
+    signature_version: u32,
+    sbuffer: &mut Vec<u8>,
+) {
+    sbuffer.extend_from_slice(&hash::sha_512_256(tag));
+    if signature_version != LEGACY_SIGNATURE_BUFF_VERSION {
+        sbuffer.write_u32::<BigEndian>(signature_version).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+    use std::convert::TryFrom;
+
+    use proto::crypto::{DhPublicKey, HashedLock, InvoiceId, RandValue, Salt, Signature};
+    use proto::funder::messages::{FriendsRoute, RequestSendFundsOp};
+
+    #[test]
+    fn test_signature_tags_unique() {
+        let tag_hashes = SIGNATURE_TAGS
+            .iter()
+            .map(|tag| sha_512_256(tag))
+            .collect::<HashSet<_>>();
+        assert_eq!(tag_hashes.len(), SIGNATURE_TAGS.len());
+    }
+
+    #[test]
+    fn test_signature_buff_header() {
+        let header = signature_buff_header(TOKEN_NEXT);
+        assert!(header.starts_with(&sha_512_256(TOKEN_NEXT)));
+        assert_ne!(header, signature_buff_header(MUTATIONS_UPDATE_PREFIX));
+    }
+
+    #[test]
+    fn test_legacy_signature_buff_header() {
+        let mut sbuffer = Vec::new();
+        signature_buff_header_version_into(TOKEN_NEXT, LEGACY_SIGNATURE_BUFF_VERSION, &mut sbuffer);
+        assert_eq!(sbuffer, sha_512_256(TOKEN_NEXT).to_vec());
+    }
+
+    #[test]
+    fn test_legacy_exchange_dh_signature_buff() {
+        let exchange_dh = ExchangeDh {
+            dh_public_key: DhPublicKey::from(&[0x11; DhPublicKey::len()]),
+            rand_nonce: RandValue::from(&[0x22; RandValue::len()]),
+            key_salt: Salt::from(&[0x33; Salt::len()]),
+            version: 0,
+            signature: Signature::from(&[0; Signature::len()]),
+        };
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&exchange_dh.dh_public_key);
+        expected.extend_from_slice(&exchange_dh.rand_nonce);
+        expected.extend_from_slice(&exchange_dh.key_salt);
+        assert_eq!(
+            exchange_dh_signature_buff(&exchange_dh, (0, 0), (0, 2)),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_legacy_prefix_hash() {
+        let request_send_funds = RequestSendFundsOp {
+            request_id: Uid::from(&[0x11; Uid::len()]),
+            src_hashed_lock: HashedLock::from(&[0x22; HashedLock::len()]),
+            route: FriendsRoute {
+                public_keys: vec![PublicKey::from(&[0x33; PublicKey::len()])],
+            },
+            dest_payment: 10,
+            total_dest_payment: 20,
+            invoice_id: InvoiceId::from(&[0x44; InvoiceId::len()]),
+            left_fees: 5,
+            expiry_ticks: 0,
+            refund_ticks: 0,
+            opt_exchange: None,
+        };
+        let move_token = UnsignedMoveToken::<u32> {
+            old_token: Signature::from(&[0x55; Signature::len()]),
+            currencies_operations: vec![CurrencyOperations {
+                currency: Currency::try_from("FST".to_owned()).unwrap(),
+                operations: vec![FriendTcOp::RequestSendFunds(request_send_funds.clone())],
+            }],
+            opt_local_relays: None,
+            opt_active_currencies: None,
+            info_hash: HashResult::from(&[0x66; HashResult::len()]),
+            rand_nonce: RandValue::from(&[0x77; RandValue::len()]),
+            signature_version: LEGACY_SIGNATURE_BUFF_VERSION,
+        };
+
+        // Fields added to the operations since the legacy layout are not signed over:
+        let mut expiring_move_token = move_token.clone();
+        expiring_move_token.currencies_operations[0].operations =
+            vec![FriendTcOp::RequestSendFunds(RequestSendFundsOp {
+                expiry_ticks: 8,
+                ..request_send_funds
+            })];
+        assert_eq!(
+            prefix_hash(move_token.clone()),
+            prefix_hash(expiring_move_token.clone())
+        );
+
+        // The current layout signs over all the fields:
+        expiring_move_token.signature_version = SIGNATURE_BUFF_VERSION;
+        let mut versioned_move_token = move_token.clone();
+        versioned_move_token.signature_version = SIGNATURE_BUFF_VERSION;
+        assert_ne!(
+            prefix_hash(versioned_move_token.clone()),
+            prefix_hash(expiring_move_token)
+        );
+
+        // A legacy move token can not be presented as a move token of the current layout:
+        assert_ne!(
+            move_token_signature_buff(move_token),
+            move_token_signature_buff(versioned_move_token)
+        );
+    }
+
+    #[test]
+    fn test_signature_buff_into_reuse() {
+        let exchange_dh = ExchangeDh {
+            dh_public_key: DhPublicKey::from(&[0x11; DhPublicKey::len()]),
+            rand_nonce: RandValue::from(&[0x22; RandValue::len()]),
+            key_salt: Salt::from(&[0x33; Salt::len()]),
+            version: 1,
+            signature: Signature::from(&[0; Signature::len()]),
+        };
+
+        // A single buffer may be reused for many signature buffers:
+        let mut sbuffer = Vec::new();
+        for _ in 0..3 {
+            sbuffer.clear();
+            exchange_dh_signature_buff_into(&exchange_dh, (1, 2), (1, 3), &mut sbuffer);
+            assert_eq!(
+                sbuffer,
+                exchange_dh_signature_buff(&exchange_dh, (1, 2), (1, 3))
+            );
+        }
+    }
+
+    #[test]
+    fn test_signature_buffs_domain_separated() {
+        let exchange_dh = ExchangeDh {
+            dh_public_key: DhPublicKey::from(&[0x11; DhPublicKey::len()]),
+            rand_nonce: RandValue::from(&[0x22; RandValue::len()]),
+            key_salt: Salt::from(&[0x33; Salt::len()]),
+            version: 1,
+            signature: Signature::from(&[0; Signature::len()]),
+        };
+        let sbuffer = exchange_dh_signature_buff(&exchange_dh, (1, 2), (1, 3));
+        assert!(sbuffer.starts_with(&signature_buff_header(EXCHANGE_DH_PREFIX)));
+
+        // The advertised versions ranges are signed:
+        assert_ne!(
+            sbuffer,
+            exchange_dh_signature_buff(&exchange_dh, (1, 1), (1, 3))
+        );
+        assert_ne!(
+            sbuffer,
+            exchange_dh_signature_buff(&exchange_dh, (1, 2), (1, 1))
+        );
+
+        // The same content signed under any other tag results in a different buffer:
+        let content = &sbuffer[signature_buff_header(EXCHANGE_DH_PREFIX).len()..];
+        for tag in SIGNATURE_TAGS {
+            if *tag == EXCHANGE_DH_PREFIX {
+                continue;
+            }
+            let mut other_sbuffer = signature_buff_header(tag);
+            other_sbuffer.extend_from_slice(content);
+            assert_ne!(other_sbuffer, sbuffer);
+        }
+    }
 }
@@ -1,55 +1,64 @@
 use byteorder::{BigEndian, WriteBytesExt};
 
-use crypto::hash;
 use crypto::hash_lock::HashLock;
 use crypto::identity::verify_signature;
+use crypto::pow::{leading_zero_bits, pow_hash};
 
 use proto::crypto::PublicKey;
 
-use proto::funder::messages::{Commit, MoveToken, Receipt};
-use proto::index_server::messages::MutationsUpdate;
+use proto::funder::messages::{Commit, KeyRotation, MoveToken, Receipt};
+use proto::index_server::messages::{FriendProposal, IndexServerDirectory, MutationsUpdate};
 use proto::report::messages::MoveTokenHashedReport;
 
 use crate::canonical::CanonicalSerialize;
+use crate::receipt::verify_receipt_signature;
 use crate::signature_buff::{
-    create_mutations_update_signature_buff, move_token_hashed_report_signature_buff,
-    move_token_signature_buff, FUNDS_RESPONSE_PREFIX,
+    create_mutations_update_pow_buff, create_mutations_update_signature_buff,
+    friend_proposal_signature_buff, index_server_directory_signature_buff,
+    key_rotation_signature_buff, move_token_hashed_report_signature_buff,
+    move_token_signature_buff, signature_buff_header_version_into, FUNDS_RESPONSE_PREFIX,
+    LEGACY_SIGNATURE_BUFF_VERSION, SIGNATURE_BUFF_VERSION,
 };
 
 // TODO: Add a local test that makes sure verify_receipt is in sync with verify_commit_signature
 /// Verify that a given receipt's signature is valid
 pub fn verify_receipt(receipt: &Receipt, public_key: &PublicKey) -> bool {
-    let mut data = Vec::new();
-
-    data.extend_from_slice(&hash::sha_512_256(FUNDS_RESPONSE_PREFIX));
-    data.extend(receipt.response_hash.as_ref());
-    data.extend_from_slice(&receipt.src_plain_lock.hash_lock());
-    data.extend_from_slice(&receipt.dest_plain_lock.hash_lock());
-    data.extend_from_slice(&receipt.is_complete.canonical_serialize());
-    data.write_u128::<BigEndian>(receipt.dest_payment).unwrap();
-    data.write_u128::<BigEndian>(receipt.total_dest_payment)
-        .unwrap();
-    data.extend(receipt.invoice_id.as_ref());
-    data.extend_from_slice(&receipt.currency.canonical_serialize());
-    verify_signature(&data, public_key, &receipt.signature)
+    verify_receipt_signature(receipt, public_key)
 }
 
-/// Verify that a given Commit signature is valid
-fn verify_commit_signature(commit: &Commit, local_public_key: &PublicKey) -> bool {
+/// Create the buffer signed over at the response a Commit was created from
+fn commit_signature_buff(commit: &Commit, signature_version: u32) -> Vec<u8> {
     let mut data = Vec::new();
-
-    data.extend_from_slice(&hash::sha_512_256(FUNDS_RESPONSE_PREFIX));
+    signature_buff_header_version_into(FUNDS_RESPONSE_PREFIX, signature_version, &mut data);
     data.extend(commit.response_hash.as_ref());
     data.extend_from_slice(&commit.src_plain_lock.hash_lock());
     data.extend_from_slice(&commit.dest_hashed_lock);
     let is_complete = true;
     data.extend_from_slice(&is_complete.canonical_serialize());
+    if signature_version != LEGACY_SIGNATURE_BUFF_VERSION {
+        data.write_u128::<BigEndian>(commit.change).unwrap();
+    }
     data.write_u128::<BigEndian>(commit.dest_payment).unwrap();
     data.write_u128::<BigEndian>(commit.total_dest_payment)
         .unwrap();
     data.extend(commit.invoice_id.as_ref());
     data.extend_from_slice(&commit.currency.canonical_serialize());
-    verify_signature(&data, local_public_key, &commit.signature)
+    data
+}
+
+/// Verify that a given Commit signature is valid
+/// A Commit without change may be signed in the legacy layout (See
+/// `response_signature_version`).
+fn verify_commit_signature(commit: &Commit, local_public_key: &PublicKey) -> bool {
+    let verify_version = |signature_version| {
+        verify_signature(
+            &commit_signature_buff(commit, signature_version),
+            local_public_key,
+            &commit.signature,
+        )
+    };
+    verify_version(SIGNATURE_BUFF_VERSION)
+        || (commit.change == 0 && verify_version(LEGACY_SIGNATURE_BUFF_VERSION))
 }
 
 /// Verify a Commit message
@@ -58,39 +67,108 @@ pub fn verify_commit(commit: &Commit, local_public_key: &PublicKey) -> bool {
     if commit.total_dest_payment < commit.dest_payment {
         return false;
     }
+    // The seller can not give back more than what was paid:
+    if commit.dest_payment < commit.change {
+        return false;
+    }
 
     // Verify signature:
     verify_commit_signature(commit, local_public_key)
 }
 
 /// Verify that new_token is a valid signature over the rest of the fields.
+/// Both the legacy and the current layouts are accepted, as stated by the move token.
 pub fn verify_move_token<B>(move_token: MoveToken<B>, public_key: &PublicKey) -> bool
 where
     B: CanonicalSerialize + Clone,
 {
-    let sig_buffer = move_token_signature_buff(move_token.clone());
-    verify_signature(&sig_buffer, public_key, &move_token.new_token)
+    if move_token.signature_version != LEGACY_SIGNATURE_BUFF_VERSION
+        && move_token.signature_version != SIGNATURE_BUFF_VERSION
+    {
+        return false;
+    }
+    let new_token = move_token.new_token.clone();
+    let sig_buffer = move_token_signature_buff(move_token);
+    verify_signature(&sig_buffer, public_key, &new_token)
 }
 
 /// Verify the signature at the MutationsUpdate structure.
 /// Note that this structure also contains the `node_public_key` field, which is the identity
 /// of the node who signed this struct.
+/// Index clients that predate signature buffer versions sign in the legacy layout.
 pub fn verify_mutations_update(mutations_update: &MutationsUpdate) -> bool {
-    let signature_buff = create_mutations_update_signature_buff(&mutations_update);
+    let verify_version = |signature_version| {
+        verify_signature(
+            &create_mutations_update_signature_buff(&mutations_update, signature_version),
+            &mutations_update.node_public_key,
+            &mutations_update.signature,
+        )
+    };
+    verify_version(SIGNATURE_BUFF_VERSION) || verify_version(LEGACY_SIGNATURE_BUFF_VERSION)
+}
+
+/// Amount of leading zero bits in the proof of work hash of a MutationsUpdate.
+/// An index server requires a minimal amount of zero bits (The difficulty) before accepting the
+/// message.
+pub fn mutations_update_pow_bits(mutations_update: &MutationsUpdate) -> u32 {
+    let pow_buff = create_mutations_update_pow_buff(&mutations_update);
+    leading_zero_bits(&pow_hash(&pow_buff, mutations_update.pow_nonce))
+}
+
+/// Verify the signature at the FriendProposal structure.
+/// The proposal is signed by the proposing node (`src_public_key`).
+pub fn verify_friend_proposal(friend_proposal: &FriendProposal) -> bool {
+    let signature_buff = friend_proposal_signature_buff(&friend_proposal);
     verify_signature(
         &signature_buff,
-        &mutations_update.node_public_key,
-        &mutations_update.signature,
+        &friend_proposal.src_public_key,
+        &friend_proposal.signature,
     )
 }
 
+/// Verify the signatures at the KeyRotation structure.
+/// The rotation is valid only if it is signed by both the old and the new keys.
+pub fn verify_key_rotation(key_rotation: &KeyRotation) -> bool {
+    if key_rotation.old_public_key == key_rotation.new_public_key {
+        return false;
+    }
+    let signature_buff =
+        key_rotation_signature_buff(&key_rotation.old_public_key, &key_rotation.new_public_key);
+    verify_signature(
+        &signature_buff,
+        &key_rotation.old_public_key,
+        &key_rotation.old_signature,
+    ) && verify_signature(
+        &signature_buff,
+        &key_rotation.new_public_key,
+        &key_rotation.new_signature,
+    )
+}
+
+/// Verify that an index server directory is signed by the directory key `directory_public_key`
+pub fn verify_index_server_directory(
+    directory: &IndexServerDirectory,
+    directory_public_key: &PublicKey,
+) -> bool {
+    let signature_buff =
+        index_server_directory_signature_buff(directory.version, &directory.index_servers);
+    verify_signature(&signature_buff, directory_public_key, &directory.signature)
+}
+
 // TODO: Is the public_key argument redundant now? (As it should be exactly the same
 // as move_token_hashed_report.local_public_key)
 /// Verify that new_token is a valid signature over the rest of the fields.
+/// The report does not state the layout of the signed buffer, therefore both the current and the
+/// legacy layouts are accepted. (Move tokens stored before signature buffer versions, and move
+/// tokens of older friends, are signed in the legacy layout)
 pub fn verify_move_token_hashed_report(
     move_token_hashed_report: &MoveTokenHashedReport,
     public_key: &PublicKey,
 ) -> bool {
-    let sig_buffer = move_token_hashed_report_signature_buff(move_token_hashed_report);
-    verify_signature(&sig_buffer, public_key, &move_token_hashed_report.new_token)
+    let verify_version = |signature_version| {
+        let sig_buffer =
+            move_token_hashed_report_signature_buff(move_token_hashed_report, signature_version);
+        verify_signature(&sig_buffer, public_key, &move_token_hashed_report.new_token)
+    };
+    verify_version(SIGNATURE_BUFF_VERSION) || verify_version(LEGACY_SIGNATURE_BUFF_VERSION)
 }
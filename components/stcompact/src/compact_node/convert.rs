@@ -2,9 +2,9 @@ use app::common::Currency;
 
 use crate::compact_node::messages::{
     BalanceInfo, ChannelConsistentReport, ChannelInconsistentReport, ChannelStatusReport, Commit,
-    CompactReport, ConfigReport, CountersInfo, CurrencyReport, FriendLivenessReport, FriendReport,
-    FriendStatusReport, McInfo, MoveTokenHashedReport, OpenInvoice, OpenPayment, OpenPaymentStatus,
-    RequestsStatusReport, ResetTermsReport, TokenInfo,
+    CompactReport, CompletedPayment, ConfigReport, CountersInfo, CurrencyReport,
+    FriendLivenessReport, FriendReport, FriendStatusReport, McInfo, MoveTokenHashedReport,
+    OpenInvoice, OpenPayment, OpenPaymentStatus, RequestsStatusReport, ResetTermsReport, TokenInfo,
 };
 
 use crate::compact_node::persist;
@@ -206,6 +206,8 @@ impl From<app::report::FriendReport> for FriendReport {
             liveness: friend_report.liveness.into(),
             channel_status: friend_report.channel_status.into(),
             status: friend_report.status.into(),
+            missed_beats: friend_report.missed_beats,
+            is_gated: friend_report.is_gated,
         }
     }
 }
@@ -220,6 +222,7 @@ impl From<app::common::Commit> for Commit {
             dest_hashed_lock: from.dest_hashed_lock,
             dest_payment: from.dest_payment,
             total_dest_payment: from.total_dest_payment,
+            change: from.change,
             invoice_id: from.invoice_id,
             currency: from.currency,
             signature: from.signature,
@@ -235,6 +238,7 @@ impl From<Commit> for app::common::Commit {
             dest_hashed_lock: from.dest_hashed_lock,
             dest_payment: from.dest_payment,
             total_dest_payment: from.total_dest_payment,
+            change: from.change,
             invoice_id: from.invoice_id,
             currency: from.currency,
             signature: from.signature,
@@ -276,6 +280,7 @@ impl From<persist::OpenPayment> for OpenPayment {
             dest_public_key: from.dest_public_key,
             dest_payment: from.dest_payment,
             description: from.description,
+            payer_note: from.payer_note,
             generation: from.generation,
             status: from.status.into(),
         }
@@ -294,6 +299,28 @@ impl From<persist::OpenInvoice> for OpenInvoice {
     }
 }
 
+/// The completed payments of the buyer, ordered by generation
+pub fn create_payment_history(compact_state: &CompactState) -> Vec<CompletedPayment> {
+    let mut completed_payments = compact_state
+        .completed_payments
+        .iter()
+        .map(|(payment_id, completed_payment)| CompletedPayment {
+            payment_id: payment_id.clone(),
+            invoice_id: completed_payment.invoice_id.clone(),
+            currency: completed_payment.currency.clone(),
+            dest_public_key: completed_payment.dest_public_key.clone(),
+            dest_payment: completed_payment.dest_payment,
+            description: completed_payment.description.clone(),
+            payer_note: completed_payment.payer_note.clone(),
+            receipt: completed_payment.receipt.clone(),
+            fees: completed_payment.fees,
+            generation: completed_payment.generation.clone(),
+        })
+        .collect::<Vec<_>>();
+    completed_payments.sort_by_key(|completed_payment| completed_payment.generation.0);
+    completed_payments
+}
+
 pub fn create_compact_report(
     compact_state: CompactState,
     node_report: app::report::NodeReport,
@@ -17,6 +17,10 @@ use crate::compact_node::persist::{OpenPaymentStatus, OpenPaymentStatusFoundRout
 use crate::compact_node::types::{CompactNodeError, CompactServerState};
 use crate::gen::GenUid;
 
+/// Maximum amount of routes a single payment may be split between, in case no single route has
+/// enough capacity.
+const MAX_PAYMENT_ROUTES: usize = 4;
+
 /// Calculate fees if we send credits through the given MultiRoute with the MultiRouteChoice
 /// strategy
 fn calc_multi_route_fees(
@@ -42,7 +46,8 @@ fn obtain_multi_route(
         ResponseRoutesResult::Failure => return None,
     };
 
-    let (route_index, multi_route_choice) = choose_multi_route(&multi_routes, dest_payment)?;
+    let (route_index, multi_route_choice) =
+        choose_multi_route(&multi_routes, dest_payment, MAX_PAYMENT_ROUTES)?;
     let multi_route = &multi_routes[route_index];
 
     // Make sure that fees can be calculated correctly:
@@ -150,7 +155,9 @@ where
                         .await
                         .map_err(|_| CompactNodeError::UserSenderError)?;
                 }
-                (RequestResult::Failure, _) | (RequestResult::Success, true) => {
+                (RequestResult::Failure, _)
+                | (RequestResult::Rejected(_), _)
+                | (RequestResult::Success, true) => {
                     // Set payment as failed:
                     let ack_uid = compact_gen.gen_uid();
                     open_payment.status = OpenPaymentStatus::Failure(ack_uid.clone());
@@ -310,6 +317,68 @@ where
                     .map_err(|_| CompactNodeError::UserSenderError)?;
             }
         }
+        AppServerToApp::PermissionDenied(permission_denied) => {
+            // The compact server connects to the node with all permissions, so this should never
+            // happen. We still acknowledge the request, so that the user will not wait forever:
+            warn!("handle_node(): Permission denied: {:?}", permission_denied);
+            user_sender
+                .send(CompactToUserAck::Ack(permission_denied.app_request_id))
+                .await
+                .map_err(|_| CompactNodeError::UserSenderError)?;
+        }
+        AppServerToApp::RequestError(request_error) => {
+            // The request is acknowledged separately, right after the error:
+            warn!("handle_node(): Request error: {:?}", request_error);
+        }
+        AppServerToApp::ResponseExposure(response_exposure) => {
+            // The compact server never requests exposure analysis:
+            warn!(
+                "handle_node(): Unexpected ResponseExposure: request_id {:?}",
+                response_exposure.request_id
+            );
+        }
+        AppServerToApp::ResponseChannelProof(response_channel_proof) => {
+            // The compact server never exports channel proofs:
+            warn!(
+                "handle_node(): Unexpected ResponseChannelProof: request_id {:?}",
+                response_channel_proof.request_id
+            );
+        }
+        AppServerToApp::ResponseBalanceHistory(response_balance_history) => {
+            // The compact server never requests balance history:
+            warn!(
+                "handle_node(): Unexpected ResponseBalanceHistory: request_id {:?}",
+                response_balance_history.request_id
+            );
+        }
+        AppServerToApp::ResponseReceipts(response_receipts) => {
+            // The compact server never requests archived receipts:
+            warn!(
+                "handle_node(): Unexpected ResponseReceipts: request_id {:?}",
+                response_receipts.request_id
+            );
+        }
+        AppServerToApp::ResponseQuotePayment(response_quote_payment) => {
+            // The compact server never requests payment quotes:
+            warn!(
+                "handle_node(): Unexpected ResponseQuotePayment: request_id {:?}",
+                response_quote_payment.request_id
+            );
+        }
+        AppServerToApp::AppMessage(app_message) => {
+            // The compact server never subscribes to topics of other apps:
+            warn!(
+                "handle_node(): Unexpected AppMessage: topic {:?}",
+                app_message.topic
+            );
+        }
+        AppServerToApp::ResponseFriendDetail(response_friend_detail) => {
+            // The compact server keeps a full report, and never requests friend details:
+            warn!(
+                "handle_node(): Unexpected ResponseFriendDetail: friend_public_key {:?}",
+                response_friend_detail.friend_public_key
+            );
+        }
         AppServerToApp::ResponseRoutes(mut client_response_routes) => {
             // Search for the corresponding OpenPayment:
             let mut compact_state = server_state.compact_state().clone();
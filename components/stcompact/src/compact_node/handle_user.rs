@@ -4,13 +4,15 @@ use app::common::Uid;
 use app::conn::{buyer, config, routes, seller, AppPermissions, AppToAppServer};
 use app::verify::verify_commit;
 
+use crate::compact_node::convert::create_payment_history;
 use crate::compact_node::create_compact_report;
 use crate::compact_node::messages::{
     CompactToUser, CompactToUserAck, PaymentDone, PaymentDoneStatus, PaymentFees,
-    PaymentFeesResponse, ResponseVerifyCommit, UserToCompact, UserToCompactAck, VerifyCommitStatus,
+    PaymentFeesResponse, ResponsePaymentHistory, ResponseVerifyCommit, UserToCompact,
+    UserToCompactAck, VerifyCommitStatus,
 };
 use crate::compact_node::persist::{
-    OpenInvoice, OpenPayment, OpenPaymentStatus, OpenPaymentStatusSending,
+    CompletedPayment, OpenInvoice, OpenPayment, OpenPaymentStatus, OpenPaymentStatusSending,
 };
 use crate::compact_node::types::{CompactNodeError, CompactServerState};
 use crate::gen::GenUid;
@@ -326,6 +328,7 @@ where
                 dest_public_key: init_payment.dest_public_key,
                 dest_payment: init_payment.dest_payment,
                 description: init_payment.description,
+                payer_note: init_payment.payer_note,
                 generation: compact_state.generation.advance(),
                 status: OpenPaymentStatus::SearchingRoute(request_routes_id),
             };
@@ -547,7 +550,22 @@ where
                 OpenPaymentStatus::Success(_, _, stored_ack_uid)
                 | OpenPaymentStatus::Failure(stored_ack_uid) => {
                     if stored_ack_uid == &ack_uid {
-                        let _ = compact_state.open_payments.remove(&payment_id).unwrap();
+                        let open_payment = compact_state.open_payments.remove(&payment_id).unwrap();
+                        // Keep successful payments (With their receipts) in the payment history:
+                        if let OpenPaymentStatus::Success(receipt, fees, _) = open_payment.status {
+                            let completed_payment = CompletedPayment {
+                                invoice_id: open_payment.invoice_id,
+                                currency: open_payment.currency,
+                                dest_public_key: open_payment.dest_public_key,
+                                dest_payment: open_payment.dest_payment,
+                                description: open_payment.description,
+                                payer_note: open_payment.payer_note,
+                                receipt,
+                                fees,
+                                generation: open_payment.generation,
+                            };
+                            compact_state.add_completed_payment(payment_id, completed_payment);
+                        }
                         server_state.update_compact_state(compact_state).await?;
                     }
                     return user_sender
@@ -557,6 +575,23 @@ where
                 }
             }
         }
+        UserToCompact::RequestPaymentHistory(request_payment_history) => {
+            // Send ack:
+            user_sender
+                .send(CompactToUserAck::Ack(user_request_id))
+                .await
+                .map_err(|_| CompactNodeError::UserSenderError)?;
+
+            let response_payment_history = ResponsePaymentHistory {
+                request_id: request_payment_history.request_id,
+                completed_payments: create_payment_history(server_state.compact_state()),
+            };
+            let compact_to_user = CompactToUser::ResponsePaymentHistory(response_payment_history);
+            user_sender
+                .send(CompactToUserAck::CompactToUser(compact_to_user))
+                .await
+                .map_err(|_| CompactNodeError::UserSenderError)?;
+        }
         // =======================[Seller]=======================================
         UserToCompact::AddInvoice(add_invoice) => {
             let mut compact_state = server_state.compact_state().clone();
@@ -41,6 +41,8 @@ pub struct Commit {
     pub dest_payment: u128,
     #[serde(with = "ser_string")]
     pub total_dest_payment: u128,
+    #[serde(with = "ser_string")]
+    pub change: u128,
     #[serde(with = "ser_b64")]
     pub invoice_id: InvoiceId,
     #[serde(with = "ser_string")]
@@ -107,6 +109,10 @@ pub struct InitPayment {
     pub dest_payment: u128,
     /// Short textual invoice description
     pub description: String,
+    /// A private note for the payer's own records.
+    /// Kept locally together with the payment (and its receipt), never sent to any remote party.
+    #[serde(default)]
+    pub payer_note: String,
 }
 
 #[derive(Arbitrary, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -190,6 +196,47 @@ pub struct ConfirmPaymentFees {
     pub confirm_id: Uid,
 }
 
+#[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestPaymentHistory {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+}
+
+#[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletedPayment {
+    #[serde(with = "ser_b64")]
+    pub payment_id: PaymentId,
+    #[serde(with = "ser_b64")]
+    pub invoice_id: InvoiceId,
+    #[serde(with = "ser_string")]
+    pub currency: Currency,
+    #[serde(with = "ser_b64")]
+    pub dest_public_key: PublicKey,
+    #[serde(with = "ser_string")]
+    pub dest_payment: u128,
+    /// Invoice description (Obtained from the corresponding invoice)
+    pub description: String,
+    /// Payer's private note (Given at payment initialization)
+    #[serde(default)]
+    pub payer_note: String,
+    pub receipt: Receipt,
+    #[serde(with = "ser_string")]
+    pub fees: u128,
+    /// Chronological counter
+    pub generation: Generation,
+}
+
+#[derive(Arbitrary, Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponsePaymentHistory {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    /// Completed payments, ordered by generation
+    pub completed_payments: Vec<CompletedPayment>,
+}
+
 #[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SetFriendCurrencyMaxDebt {
@@ -391,6 +438,12 @@ pub struct FriendReport {
     pub liveness: FriendLivenessReport, // is the friend online/offline?
     pub channel_status: ChannelStatusReport,
     pub status: FriendStatusReport,
+    /// Amount of consecutive keepalive beats the friend has missed.
+    #[serde(with = "ser_string")]
+    pub missed_beats: u64,
+    /// Is the friend gated? New requests are not sent through a gated friend, because its recent
+    /// uptime is too low.
+    pub is_gated: bool,
 }
 
 #[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -440,6 +493,9 @@ pub struct OpenPayment {
     pub dest_payment: u128,
     /// Invoice description (Obtained from the corresponding invoice)
     pub description: String,
+    /// Payer's private note (Given at payment initialization)
+    #[serde(default)]
+    pub payer_note: String,
     /// Chronological counter
     pub generation: Generation,
     /// Current status of open payment
@@ -493,6 +549,9 @@ pub enum CompactToUser {
     Report(CompactReport),
     // -------------[Verify]-------------------
     ResponseVerifyCommit(ResponseVerifyCommit),
+    // -------------[History]------------------
+    /// Completed payments, together with their receipts and payer notes
+    ResponsePaymentHistory(ResponsePaymentHistory),
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -535,6 +594,8 @@ pub enum UserToCompact {
         #[serde(with = "ser_b64")] PaymentId,
         #[serde(with = "ser_b64")] Uid,
     ), // (payment_id, ack_uid)
+    /// Request the completed payments (Sent after `AckPaymentDone`):
+    RequestPaymentHistory(RequestPaymentHistory),
     // ---------------[Seller]------------------------------
     AddInvoice(AddInvoice),
     #[serde(with = "ser_b64")]
@@ -24,7 +24,8 @@ pub fn check_permission(user_request: &UserToCompact, app_permissions: &AppPermi
         UserToCompact::InitPayment(_)
         | UserToCompact::ConfirmPaymentFees(_)
         | UserToCompact::CancelPayment(_)
-        | UserToCompact::AckPaymentDone(_, _) => app_permissions.buyer,
+        | UserToCompact::AckPaymentDone(_, _)
+        | UserToCompact::RequestPaymentHistory(_) => app_permissions.buyer,
         UserToCompact::AddInvoice(_)
         | UserToCompact::CancelInvoice(_)
         | UserToCompact::CommitInvoice(_) => app_permissions.seller,
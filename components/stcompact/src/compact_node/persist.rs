@@ -12,6 +12,10 @@ use route::MultiRouteChoice;
 
 use crate::compact_node::messages::Generation;
 
+/// Maximum amount of completed payments kept in the payment history.
+/// When the history is full, the oldest completed payment is removed.
+pub const MAX_COMPLETED_PAYMENTS: usize = 0x400;
+
 #[allow(clippy::large_enum_variant)]
 #[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct OpenInvoice {
@@ -72,12 +76,38 @@ pub struct OpenPayment {
     pub dest_payment: u128,
     /// Invoice description (Obtained from the corresponding invoice)
     pub description: String,
+    /// Payer's private note. Stored locally only, never transmitted.
+    #[serde(default)]
+    pub payer_note: String,
     /// A counter used to sort items chronologically.
     pub generation: Generation,
     /// Current status of open payment
     pub status: OpenPaymentStatus,
 }
 
+/// A successful payment, kept in the payer's local history after it was acked.
+#[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CompletedPayment {
+    #[serde(with = "ser_b64")]
+    pub invoice_id: InvoiceId,
+    #[serde(with = "ser_string")]
+    pub currency: Currency,
+    #[serde(with = "ser_b64")]
+    pub dest_public_key: PublicKey,
+    #[serde(with = "ser_string")]
+    pub dest_payment: u128,
+    /// Invoice description (Obtained from the corresponding invoice)
+    pub description: String,
+    /// Payer's private note. Stored locally only, never transmitted.
+    #[serde(default)]
+    pub payer_note: String,
+    pub receipt: Receipt,
+    #[serde(with = "ser_string")]
+    pub fees: u128,
+    /// A counter used to sort items chronologically.
+    pub generation: Generation,
+}
+
 #[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct CompactState {
     /// Seller's open invoices:
@@ -86,6 +116,9 @@ pub struct CompactState {
     /// Buyer's open payments:
     #[serde(with = "ser_map_b64_any")]
     pub open_payments: HashMap<PaymentId, OpenPayment>,
+    /// Buyer's completed payments (Payment history):
+    #[serde(default, with = "ser_map_b64_any")]
+    pub completed_payments: HashMap<PaymentId, CompletedPayment>,
     /// Next generation value for a newly created item.
     pub generation: Generation,
 }
@@ -95,9 +128,30 @@ impl CompactState {
         Self {
             open_invoices: HashMap::new(),
             open_payments: HashMap::new(),
+            completed_payments: HashMap::new(),
             generation: Generation(0),
         }
     }
+
+    /// Add a payment to the payment history.
+    /// Removes the oldest completed payments if the history grows over `MAX_COMPLETED_PAYMENTS`.
+    pub fn add_completed_payment(
+        &mut self,
+        payment_id: PaymentId,
+        completed_payment: CompletedPayment,
+    ) {
+        self.completed_payments
+            .insert(payment_id, completed_payment);
+        while self.completed_payments.len() > MAX_COMPLETED_PAYMENTS {
+            let oldest_payment_id = self
+                .completed_payments
+                .iter()
+                .min_by_key(|(_, completed_payment)| completed_payment.generation.0)
+                .map(|(payment_id, _)| payment_id.clone())
+                .unwrap();
+            self.completed_payments.remove(&oldest_payment_id);
+        }
+    }
 }
 
 impl MutableState for CompactState {
@@ -112,3 +166,102 @@ impl MutableState for CompactState {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use app::common::{HashResult, PlainLock, Signature};
+
+    #[test]
+    fn test_compact_state_deser_without_payer_notes() {
+        let payment_id = PaymentId::from(&[1; PaymentId::len()]);
+        let open_payment = OpenPayment {
+            invoice_id: InvoiceId::from(&[2; InvoiceId::len()]),
+            currency: Currency::try_from("FST".to_owned()).unwrap(),
+            dest_public_key: PublicKey::from(&[3; PublicKey::len()]),
+            dest_payment: 10,
+            description: "description".to_owned(),
+            payer_note: "payer note".to_owned(),
+            generation: Generation(0),
+            status: OpenPaymentStatus::SearchingRoute(Uid::from(&[4; Uid::len()])),
+        };
+        let mut compact_state = CompactState::new();
+        compact_state
+            .open_payments
+            .insert(payment_id.clone(), open_payment);
+
+        // A state saved before payer notes and the payment history were added:
+        let mut value = serde_json::to_value(&compact_state).unwrap();
+        let value_map = value.as_object_mut().unwrap();
+        assert!(value_map.remove("completed_payments").is_some());
+        for (_, open_payment_value) in value_map["open_payments"].as_object_mut().unwrap() {
+            assert!(open_payment_value
+                .as_object_mut()
+                .unwrap()
+                .remove("payer_note")
+                .is_some());
+        }
+
+        let compact_state2: CompactState = serde_json::from_value(value).unwrap();
+        assert!(compact_state2.completed_payments.is_empty());
+        assert_eq!(compact_state2.open_payments[&payment_id].payer_note, "");
+    }
+
+    fn create_completed_payment(generation: u64) -> CompletedPayment {
+        let invoice_id = InvoiceId::from(&[2; InvoiceId::len()]);
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let receipt = Receipt {
+            response_hash: HashResult::from(&[5; HashResult::len()]),
+            invoice_id: invoice_id.clone(),
+            currency: currency.clone(),
+            src_plain_lock: PlainLock::from(&[6; PlainLock::len()]),
+            dest_plain_lock: PlainLock::from(&[7; PlainLock::len()]),
+            is_complete: true,
+            dest_payment: 10,
+            total_dest_payment: 10,
+            change: 0,
+            signature: Signature::from(&[8; Signature::len()]),
+        };
+        CompletedPayment {
+            invoice_id,
+            currency,
+            dest_public_key: PublicKey::from(&[3; PublicKey::len()]),
+            dest_payment: 10,
+            description: "description".to_owned(),
+            payer_note: "payer note".to_owned(),
+            receipt,
+            fees: 1,
+            generation: Generation(generation),
+        }
+    }
+
+    #[test]
+    fn test_compact_state_completed_payments_bounded() {
+        let mut compact_state = CompactState::new();
+        let num_payments = MAX_COMPLETED_PAYMENTS + 2;
+        for i in 0..num_payments {
+            let mut payment_id_bytes = [0u8; PaymentId::len()];
+            payment_id_bytes[0..8].copy_from_slice(&(i as u64).to_be_bytes());
+            compact_state.add_completed_payment(
+                PaymentId::from(&payment_id_bytes),
+                create_completed_payment(i as u64),
+            );
+        }
+
+        // Only the most recent payments are kept:
+        assert_eq!(
+            compact_state.completed_payments.len(),
+            MAX_COMPLETED_PAYMENTS
+        );
+        let oldest_generation = compact_state
+            .completed_payments
+            .values()
+            .map(|completed_payment| completed_payment.generation.0)
+            .min()
+            .unwrap();
+        assert_eq!(oldest_generation, 2);
+    }
+}
@@ -20,10 +20,14 @@ use app::common::{NetAddress, Uid};
 use app::conn::ConnPairApp;
 use app_client::app_connect_to_node;
 
-use proto::consts::{KEEPALIVE_TICKS, MAX_NODE_RELAYS, MAX_OPERATIONS_IN_BATCH, TICKS_TO_REKEY};
+use proto::consts::{
+    KEEPALIVE_TICKS, MAX_NODE_RELAYS, MAX_OPERATIONS_IN_BATCH, MAX_ROUTE_LEN, TICKS_TO_REKEY,
+    TICKS_TO_RESUME,
+};
 
-use node::{node, ConnPairServer, IncomingAppConnection, NodeConfig};
-use proto::app_server::messages::{AppPermissions, NodeReport};
+use node::{node, ConnPairServer, IncomingAppConnection, NodeConfig, NodeRequest};
+use proto::app_server::messages::{AppPermissions, AppSubscription, NodeReport};
+use proto::crypto::PublicKey;
 
 use crate::messages::{
     CreateNode, CreateNodeLocal, CreateNodeRemote, NodeId, NodeMode, NodeName, NodeOpened,
@@ -37,22 +41,54 @@ use crate::store::{
     LoadedNode, LoadedNodeLocal, LoadedNodeRemote, Store, StoreError, StoredNodeConfig,
 };
 
-use connection::{create_encrypt_keepalive, create_secure_connector};
+use connection::{
+    create_adaptive_keepalive, create_encrypt_keepalive, create_secure_connector, KeepAliveBounds,
+};
 
 /// Memory allocated to a channel in memory (Used to connect two components)
 const CHANNEL_LEN: usize = 0x20;
 /// The amount of ticks we wait before attempting to reconnect
 const BACKOFF_TICKS: usize = 0x8;
+/// Minimal amount of ticks between keepalives sent to a friend
+const KEEPALIVE_MIN_TICKS: usize = 0x4;
 /// Maximum amount of encryption set ups (diffie hellman) that we allow to occur at the same
 /// time.
 const MAX_CONCURRENT_ENCRYPT: usize = 0x8;
 /// The size we allocate for the user send funds requests queue.
 const MAX_PENDING_USER_REQUESTS: usize = 0x20;
+/// Maximum amount of times a failed transaction is retried through an alternative route.
+const MAX_TRANSACTION_RETRIES: u64 = 0x2;
 /// Maximum amount of concurrent index client requests:
 const MAX_OPEN_INDEX_CLIENT_REQUESTS: usize = 0x8;
+/// The amount of ticks routes received from an index server are cached:
+const ROUTE_CACHE_TICKS: usize = 0x10;
+/// Rotate the routes returned for repeated requests to the same destination:
+const ROUTE_ROTATION: bool = true;
+/// Smooth the capacities advertised to the index servers during short capacity drops:
+const CAPACITY_SMOOTHING: bool = true;
+/// Amount of index servers we keep a session with at the same time:
+const MAX_INDEX_SESSIONS: usize = 2;
 /// The amount of ticks we are willing to wait until a connection is established (Through
 /// the relay)
 const CONN_TIMEOUT_TICKS: usize = 0x8;
+/// Tunnel accepted relay connections over a single connection to the relay.
+/// Disabled, to keep working with relays that do not support multiplexed listening.
+const RELAY_MULTIPLEX: bool = false;
+/// The amount of ticks between measurements of the latency to relays:
+const RELAY_PROBE_TICKS: usize = 0x40;
+/// Maximum amount of ticks a graceful shutdown waits for pending operations to settle:
+const SHUTDOWN_TICKS: usize = 0x20;
+/// Amount of ticks until a request originated by this node expires:
+const REQUEST_EXPIRY_TICKS: u64 = 0x40;
+/// New requests are not queued through a friend that was online less than this percentage of the
+/// recent ticks:
+const MIN_FRIEND_UPTIME_PERCENT: u8 = 50;
+/// Channel reset terms of a friend are accepted automatically if they match our own terms:
+const AUTO_RESET_TOLERANCE: Option<u128> = Some(0);
+/// Amount of ticks between two snapshots of the balances with every friend (10 minutes):
+const BALANCE_SNAPSHOT_TICKS: usize = 600;
+/// Maximum amount of balance snapshots kept for every friend (One day):
+const MAX_BALANCE_SNAPSHOTS: usize = 144;
 
 pub type ConnPairCompactServer = ConnPair<ServerToUserAck, UserToServerAck>;
 
@@ -466,22 +502,59 @@ const NODE_CONFIG: NodeConfig = NodeConfig {
     backoff_ticks: BACKOFF_TICKS,
     /// The amount of ticks we wait until we decide an idle connection has timed out.
     keepalive_ticks: KEEPALIVE_TICKS,
+    /// Minimal amount of ticks between keepalives sent to a friend.
+    keepalive_min_ticks: KEEPALIVE_MIN_TICKS,
     /// Amount of ticks to wait until the next rekeying (Channel encryption)
     ticks_to_rekey: TICKS_TO_REKEY,
+    /// Amount of ticks a closed channel may be resumed without a full handshake
+    ticks_to_resume: TICKS_TO_RESUME,
     /// Maximum amount of encryption set ups (diffie hellman) that we allow to occur at the same
     /// time.
     max_concurrent_encrypt: MAX_CONCURRENT_ENCRYPT,
     /// The amount of ticks we are willing to wait until a connection is established (Through
     /// the relay)
     conn_timeout_ticks: CONN_TIMEOUT_TICKS,
+    /// Tunnel accepted relay connections over a single connection to the relay.
+    relay_multiplex: RELAY_MULTIPLEX,
+    relay_probe_ticks: RELAY_PROBE_TICKS,
+    /// Connect to friends' relays, and not only wait for friends to connect.
+    listen_only: false,
+    /// Do not act as a relay for our friends.
+    opt_embedded_relay_address: None,
     /// Maximum amount of operations in one move token message
     max_operations_in_batch: MAX_OPERATIONS_IN_BATCH,
     /// The size we allocate for the user send funds requests queue.
     max_pending_user_requests: MAX_PENDING_USER_REQUESTS,
+    /// Maximum length of the route of a transaction originated by this node.
+    max_route_len: MAX_ROUTE_LEN,
+    /// Maximum amount of times a failed transaction is retried through an alternative route.
+    max_transaction_retries: MAX_TRANSACTION_RETRIES,
     /// Maximum amount of concurrent index client requests:
     max_open_index_client_requests: MAX_OPEN_INDEX_CLIENT_REQUESTS,
+    /// The amount of ticks routes received from an index server are cached:
+    route_cache_ticks: ROUTE_CACHE_TICKS,
+    /// Rotate the routes returned for repeated requests to the same destination:
+    route_rotation: ROUTE_ROTATION,
+    /// Smooth the capacities advertised to the index servers during short capacity drops:
+    capacity_smoothing: CAPACITY_SMOOTHING,
+    /// Amount of index servers we keep a session with at the same time:
+    max_index_sessions: MAX_INDEX_SESSIONS,
+    /// Do not sync the index servers with an index server directory.
+    opt_index_directory_key: None,
     /// Maximum amount of relays a node may use.
     max_node_relays: MAX_NODE_RELAYS,
+    /// Maximum amount of ticks a graceful shutdown waits for pending operations to settle.
+    shutdown_ticks: SHUTDOWN_TICKS,
+    /// Amount of ticks until a request originated by this node expires.
+    request_expiry_ticks: REQUEST_EXPIRY_TICKS,
+    /// Minimum recent uptime of a friend for new requests to be queued through it.
+    min_friend_uptime_percent: MIN_FRIEND_UPTIME_PERCENT,
+    /// Maximum difference between the reset terms of a friend and ours, for automatic resets.
+    opt_auto_reset_tolerance: AUTO_RESET_TOLERANCE,
+    /// Amount of ticks between two balance snapshots.
+    balance_snapshot_ticks: BALANCE_SNAPSHOT_TICKS,
+    /// Maximum amount of balance snapshots kept for every friend.
+    max_balance_snapshots: MAX_BALANCE_SNAPSHOTS,
 };
 
 async fn open_node_local<ST, R, C, S>(
@@ -502,11 +575,16 @@ where
         buyer: true,
         seller: true,
         config: true,
+        reports: true,
+        publish: true,
     };
     let (report_sender, report_receiver) =
         oneshot::channel::<(NodeReport, oneshot::Sender<ConnPairServer<NetAddress>>)>();
+    // The compact server connects to the node as the node itself:
     let incoming_app_connection = IncomingAppConnection {
+        app_public_key: local.node_state.funder_state.local_public_key.clone(),
         app_permissions: app_permissions.clone(),
+        app_subscriptions: AppSubscription::all(),
         report_sender,
     };
 
@@ -520,10 +598,24 @@ where
         server_state.spawner.clone(),
     );
 
+    let (keepalive_report_sender, keepalive_reports) = mpsc::channel(NODE_CONFIG.channel_len);
+    let (secure_channel_report_sender, secure_channel_reports) =
+        mpsc::channel(NODE_CONFIG.channel_len);
+    let keepalive_bounds = KeepAliveBounds {
+        min_ticks: NODE_CONFIG.keepalive_min_ticks,
+        max_ticks: NODE_CONFIG.keepalive_ticks / 2,
+    };
+    let adaptive_client = create_adaptive_keepalive(keepalive_bounds, server_state.spawner.clone())
+        .map_err(|_| ServerError::SpawnError)?;
     let encrypt_keepalive = create_encrypt_keepalive(
         server_state.timer_client.clone(),
         local.node_identity_client.clone(),
         server_state.rng.clone(),
+        NODE_CONFIG.ticks_to_rekey,
+        NODE_CONFIG.ticks_to_resume,
+        keepalive_report_sender,
+        secure_channel_report_sender,
+        adaptive_client,
         server_state.spawner.clone(),
     );
 
@@ -535,7 +627,12 @@ where
         local.node_db_client,
         secure_connector,
         encrypt_keepalive,
+        keepalive_reports,
+        secure_channel_reports,
         incoming_apps,
+        // The embedded relay is disabled:
+        stream::empty::<(PublicKey, ConnPairVec)>(),
+        stream::pending::<NodeRequest>(),
         server_state.rng.clone(),
         server_state.spawner.clone(),
     )
@@ -18,7 +18,7 @@ use crypto::rand::system_random;
 
 use timer::create_timer;
 
-use net::TcpConnector;
+use net::{create_quic_runtime, QuicConnector, TcpConnector, TransportConnector};
 
 use proto::consts::{MAX_FRAME_LENGTH, TICK_MS};
 
@@ -33,6 +33,7 @@ const TICKS_TO_CONNECT: usize = 8;
 #[derive(Debug, From)]
 pub enum StCompactError {
     CreateTimerError,
+    CreateQuicRuntimeError,
     OpenFileStoreError,
     ServerError(ServerError),
     SerializeConnError(SerializeConnError),
@@ -115,8 +116,18 @@ where
     let timer_client =
         create_timer(dur, spawner.clone()).map_err(|_| StCompactError::CreateTimerError)?;
 
-    // A tcp connector, Used to connect to remote servers:
-    let tcp_connector = TcpConnector::new(MAX_FRAME_LENGTH, spawner.clone());
+    // A connector used to connect to remote servers.
+    // Every address is reached using TCP or QUIC, according to its prefix.
+    // The QUIC runtime must stay alive for as long as the server runs:
+    let quic_runtime = create_quic_runtime().map_err(|_| StCompactError::CreateQuicRuntimeError)?;
+    let net_connector = TransportConnector::new(
+        TcpConnector::new(MAX_FRAME_LENGTH, spawner.clone()),
+        QuicConnector::new(
+            MAX_FRAME_LENGTH,
+            quic_runtime.handle().clone(),
+            spawner.clone(),
+        ),
+    );
 
     // Obtain secure cryptographic random:
     let rng = system_random();
@@ -137,7 +148,7 @@ where
         TICKS_TO_CONNECT,
         timer_client,
         rng,
-        tcp_connector,
+        net_connector,
         spawner.clone(),
     )
     .await?)
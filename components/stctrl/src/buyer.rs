@@ -15,6 +15,7 @@ use app::common::{
 };
 use app::conn::{
     self, AppServerToApp, AppToAppServer, ConnPairApp, RequestResult, ResponseRoutesResult,
+    TransactionRejection,
 };
 use app::gen::{gen_payment_id, gen_uid};
 use app::report::NodeReport;
@@ -24,6 +25,10 @@ use crate::file::{CommitFile, InvoiceFile, PaymentFile, ReceiptFile};
 
 use route::choose_multi_route;
 
+/// Maximum amount of routes a single payment may be split between, in case no single route has
+/// enough capacity.
+const MAX_PAYMENT_ROUTES: usize = 4;
+
 /// Pay an invoice
 #[derive(Clone, Debug, StructOpt)]
 pub struct PayInvoiceCmd {
@@ -78,6 +83,8 @@ pub enum BuyerError {
     WriteError,
     CreatePaymentFailed,
     CreateTransactionFailed,
+    /// The node rejected the transaction before sending it
+    TransactionRejected(TransactionRejection),
     StoreCommitError,
     RequestClosePaymentError,
     AckClosePaymentError,
@@ -312,7 +319,7 @@ async fn buyer_pay_invoice(
     .map_err(|_| BuyerError::AppRoutesError)?;
 
     let (route_index, multi_route_choice) =
-        choose_multi_route(&multi_routes, invoice_file.dest_payment)
+        choose_multi_route(&multi_routes, invoice_file.dest_payment, MAX_PAYMENT_ROUTES)
             .ok_or(BuyerError::NoSuitableRoute)?;
     let multi_route = &multi_routes[route_index];
 
@@ -397,6 +404,9 @@ async fn buyer_pay_invoice(
                 }
                 RequestResult::Success => {}
                 RequestResult::Failure => return Err(BuyerError::CreateTransactionFailed),
+                RequestResult::Rejected(transaction_rejection) => {
+                    return Err(BuyerError::TransactionRejected(transaction_rejection))
+                }
             }
         }
     }
@@ -9,7 +9,9 @@ use structopt::StructOpt;
 
 use derive_more::From;
 
-use app::common::{Currency, NamedIndexServerAddress, NamedRelayAddress, Rate, RelayAddress};
+use app::common::{
+    Currency, KeyRotation, NamedIndexServerAddress, NamedRelayAddress, Rate, RelayAddress,
+};
 use app::conn::{self, AppRequest, AppServerToApp, AppToAppServer, ConnPairApp};
 use app::gen::gen_uid;
 use app::report::{ChannelStatusReport, NodeReport};
@@ -182,6 +184,16 @@ pub struct ResetFriendCmd {
     pub friend_name: String,
 }
 
+/// Announce a new identity key to all friends.
+/// The proof is created using `stmgr rotate-ident`. After the announcement, the node should be
+/// restarted with the new identity file.
+#[derive(Clone, Debug, StructOpt)]
+pub struct RotateKeyCmd {
+    /// Path of key rotation proof file
+    #[structopt(parse(from_os_str), long = "proof", short = "p")]
+    pub proof_path: PathBuf,
+}
+
 #[derive(Clone, Debug, StructOpt)]
 pub enum ConfigCmd {
     /// Add a relay server
@@ -230,6 +242,9 @@ pub enum ConfigCmd {
     /// Reset mutual credit with a friend according to friend's terms
     #[structopt(name = "reset-friend")]
     ResetFriend(ResetFriendCmd),
+    /// Announce a new identity key to all friends
+    #[structopt(name = "rotate-key")]
+    RotateKey(RotateKeyCmd),
 }
 
 #[derive(Debug, From)]
@@ -257,6 +272,8 @@ pub enum ConfigError {
     IoError(std::io::Error),
     StringSerdeError(StringSerdeError),
     InvalidCurrencyName,
+    ProofFileNotFound,
+    KeyRotationMismatch,
 }
 
 async fn config_request(
@@ -632,6 +649,27 @@ async fn config_reset_friend(
     config_request(&mut conn_pair, app_request).await
 }
 
+async fn config_rotate_key(
+    rotate_key_cmd: RotateKeyCmd,
+    mut conn_pair: ConnPairApp,
+    node_report: &NodeReport,
+) -> Result<(), ConfigError> {
+    if !rotate_key_cmd.proof_path.exists() {
+        return Err(ConfigError::ProofFileNotFound);
+    }
+
+    let key_rotation: KeyRotation =
+        deserialize_from_string(&fs::read_to_string(&rotate_key_cmd.proof_path)?)?;
+
+    // Make sure that the proof was created for the identity of this node:
+    if key_rotation.old_public_key != node_report.funder_report.local_public_key {
+        return Err(ConfigError::KeyRotationMismatch);
+    }
+
+    let app_request = conn::config::rotate_key(key_rotation);
+    config_request(&mut conn_pair, app_request).await
+}
+
 pub async fn config(
     config_cmd: ConfigCmd,
     node_report: &NodeReport,
@@ -690,6 +728,9 @@ pub async fn config(
         ConfigCmd::ResetFriend(reset_friend_cmd) => {
             config_reset_friend(reset_friend_cmd, conn_pair, node_report).await?
         }
+        ConfigCmd::RotateKey(rotate_key_cmd) => {
+            config_rotate_key(rotate_key_cmd, conn_pair, node_report).await?
+        }
     }
 
     Ok(())
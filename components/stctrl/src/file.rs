@@ -34,6 +34,8 @@ pub struct CommitFile {
     pub dest_payment: u128,
     #[serde(with = "ser_string")]
     pub total_dest_payment: u128,
+    #[serde(with = "ser_string")]
+    pub change: u128,
     #[serde(with = "ser_b64")]
     pub invoice_id: InvoiceId,
     #[serde(with = "ser_string")]
@@ -68,6 +70,8 @@ pub struct ReceiptFile {
     pub dest_payment: u128,
     #[serde(with = "ser_string")]
     pub total_dest_payment: u128,
+    #[serde(with = "ser_string")]
+    pub change: u128,
     #[serde(with = "ser_b64")]
     pub signature: Signature,
 }
@@ -114,6 +118,7 @@ mod test {
             dest_hashed_lock: HashedLock::from(&[3u8; HashedLock::len()]),
             dest_payment: 4u128,
             total_dest_payment: 5u128,
+            change: 0u128,
             invoice_id: InvoiceId::from(&[6u8; InvoiceId::len()]),
             currency: Currency::try_from("FST".to_owned()).unwrap(),
             signature: Signature::from(&[7u8; Signature::len()]),
@@ -141,14 +141,27 @@ pub async fn info_relays(
 ) -> Result<(), InfoError> {
     let mut table = Table::new();
     // Add title:
-    table.set_titles(row!["relay name", "public key", "address"]);
+    table.set_titles(row!["relay name", "public key", "address", "latency"]);
 
     for named_relay_address in &node_report.funder_report.relays {
         let pk_string = public_key_to_string(&named_relay_address.public_key);
+        let opt_relay_latency = node_report
+            .funder_report
+            .relay_latencies
+            .iter()
+            .find(|relay_latency| relay_latency.public_key == named_relay_address.public_key);
+        let latency_string = match opt_relay_latency {
+            None => "-".to_owned(),
+            Some(relay_latency) => match relay_latency.opt_latency_ms {
+                Some(latency_ms) => format!("{}ms", latency_ms),
+                None => "unreachable".to_owned(),
+            },
+        };
         table.add_row(row![
             named_relay_address.name,
             pk_string,
-            named_relay_address.address
+            named_relay_address.address,
+            latency_string
         ]);
     }
     if !table.is_empty() {
@@ -11,7 +11,7 @@ use crate::file::{InvoiceFile, ReceiptFile, TokenFile};
 use app::common::Receipt;
 use app::report::MoveTokenHashedReport;
 use app::ser_utils::{deserialize_from_string, public_key_to_string, StringSerdeError};
-use app::verify::{verify_move_token_hashed_report, verify_receipt};
+use app::verify::{verify_move_token_hashed_report, verify_receipt, ReceiptError};
 
 #[derive(Debug, From)]
 pub enum StVerifyError {
@@ -142,22 +142,24 @@ fn stverify_verify_receipt(
         deserialize_from_string(&fs::read_to_string(&verify_receipt_cmd.receipt_path)?)?;
     let receipt = Receipt::from(receipt_file);
 
-    // Make sure that the invoice and receipt files match:
-    // Verify invoice_id match:
-    if invoice_file.invoice_id != receipt.invoice_id {
-        return Err(StVerifyError::InvoiceIdMismatch);
-    }
-    // Verify dest_payment match:
-    if invoice_file.dest_payment != receipt.total_dest_payment {
-        return Err(StVerifyError::DestPaymentMismatch);
-    }
-
-    if verify_receipt(&receipt, &invoice_file.dest_public_key) {
-        writeln!(writer, "Receipt is valid!").map_err(|_| StVerifyError::WriteError)?;
-        Ok(())
-    } else {
-        Err(StVerifyError::InvalidReceipt)
-    }
+    // Make sure that the invoice and receipt files match, and that the receipt is signed by the
+    // seller:
+    verify_receipt(
+        &receipt,
+        &invoice_file.invoice_id,
+        invoice_file.dest_payment,
+        &invoice_file.dest_public_key,
+    )
+    .map_err(|e| match e {
+        ReceiptError::InvoiceIdMismatch => StVerifyError::InvoiceIdMismatch,
+        ReceiptError::AmountMismatch => StVerifyError::DestPaymentMismatch,
+        ReceiptError::InvalidDestPayment
+        | ReceiptError::Incomplete
+        | ReceiptError::InvalidSignature => StVerifyError::InvalidReceipt,
+    })?;
+
+    writeln!(writer, "Receipt is valid!").map_err(|_| StVerifyError::WriteError)?;
+    Ok(())
 }
 
 pub fn stverify(
@@ -32,6 +32,14 @@ fn spawn_entities(stctrl_setup: &StCtrlSetup) {
         lclient: stctrl_setup.index0_client_addr.parse().unwrap(),
         lserver: stctrl_setup.index0_server_addr.parse().unwrap(),
         trusted: stctrl_setup.temp_dir_path.join("index0").join("trusted"),
+        stdin_ticks: false,
+        decay_half_life: None,
+        decay_horizon: None,
+        ladmin: None,
+        admins: None,
+        admin_db: None,
+        lquery: None,
+        directory: None,
     };
     // TODO: How can we close this thread?
     thread::spawn(move || {
@@ -48,6 +56,14 @@ fn spawn_entities(stctrl_setup: &StCtrlSetup) {
         lclient: stctrl_setup.index1_client_addr.parse().unwrap(),
         lserver: stctrl_setup.index1_server_addr.parse().unwrap(),
         trusted: stctrl_setup.temp_dir_path.join("index1").join("trusted"),
+        stdin_ticks: false,
+        decay_half_life: None,
+        decay_horizon: None,
+        ladmin: None,
+        admins: None,
+        admin_db: None,
+        lquery: None,
+        directory: None,
     };
     // TODO: How can we close this thread?
     thread::spawn(move || {
@@ -62,6 +78,16 @@ fn spawn_entities(stctrl_setup: &StCtrlSetup) {
             .join("relay0")
             .join("relay0.ident"),
         laddr: stctrl_setup.relay0_addr.parse().unwrap(),
+        ws_laddr: None,
+        quic_laddr: None,
+        metrics_laddr: None,
+        peers: None,
+        stdin_ticks: false,
+        max_client_conns: None,
+        listen_idle_ticks: None,
+        tunnel_idle_ticks: None,
+        tunnel_max_ticks: None,
+        half_open_ticks: None,
     };
     // TODO: How can we close this thread?
     thread::spawn(move || {
@@ -76,6 +102,16 @@ fn spawn_entities(stctrl_setup: &StCtrlSetup) {
             .join("relay1")
             .join("relay1.ident"),
         laddr: stctrl_setup.relay1_addr.parse().unwrap(),
+        ws_laddr: None,
+        quic_laddr: None,
+        metrics_laddr: None,
+        peers: None,
+        stdin_ticks: false,
+        max_client_conns: None,
+        listen_idle_ticks: None,
+        tunnel_idle_ticks: None,
+        tunnel_max_ticks: None,
+        half_open_ticks: None,
     };
     // TODO: How can we close this thread?
     thread::spawn(move || {
@@ -85,10 +121,14 @@ fn spawn_entities(stctrl_setup: &StCtrlSetup) {
 
     // Spawn node0:
     let st_node_cmd = StNodeCmd {
-        idfile: stctrl_setup.temp_dir_path.join("node0").join("node0.ident"),
-        laddr: stctrl_setup.node0_addr.clone().parse().unwrap(),
-        database: stctrl_setup.temp_dir_path.join("node0").join("node0.db"),
-        trusted: stctrl_setup.temp_dir_path.join("node0").join("trusted"),
+        idfile: vec![stctrl_setup.temp_dir_path.join("node0").join("node0.ident")],
+        laddr: vec![stctrl_setup.node0_addr.clone().parse().unwrap()],
+        database: vec![stctrl_setup.temp_dir_path.join("node0").join("node0.db")],
+        trusted: vec![stctrl_setup.temp_dir_path.join("node0").join("trusted")],
+        stdin_ticks: false,
+        encrypt_db: false,
+        opt_passphrase_path: None,
+        listen_only: false,
     };
     // TODO: How can we close this thread?
     thread::spawn(move || {
@@ -98,10 +138,14 @@ fn spawn_entities(stctrl_setup: &StCtrlSetup) {
 
     // Spawn node1:
     let st_node_cmd = StNodeCmd {
-        idfile: stctrl_setup.temp_dir_path.join("node1").join("node1.ident"),
-        laddr: stctrl_setup.node1_addr.clone().parse().unwrap(),
-        database: stctrl_setup.temp_dir_path.join("node1").join("node1.db"),
-        trusted: stctrl_setup.temp_dir_path.join("node1").join("trusted"),
+        idfile: vec![stctrl_setup.temp_dir_path.join("node1").join("node1.ident")],
+        laddr: vec![stctrl_setup.node1_addr.clone().parse().unwrap()],
+        database: vec![stctrl_setup.temp_dir_path.join("node1").join("node1.db")],
+        trusted: vec![stctrl_setup.temp_dir_path.join("node1").join("trusted")],
+        stdin_ticks: false,
+        encrypt_db: true,
+        opt_passphrase_path: None,
+        listen_only: false,
     };
     // TODO: How can we close this thread?
     thread::spawn(move || {
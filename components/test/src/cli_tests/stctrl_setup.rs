@@ -124,6 +124,9 @@ pub fn create_stctrl_setup(temp_dir_path: &Path) -> StCtrlSetup {
         let init_node_db_cmd = InitNodeDbCmd {
             idfile_path: temp_dir_path.join(node).join(format!("{}.ident", node)),
             output_path: temp_dir_path.join(node).join(format!("{}.db", node)),
+            // node1 uses an encrypted database:
+            encrypt: *node == "node1",
+            opt_passphrase_path: None,
         };
         stmgr(StMgrCmd::InitNodeDb(init_node_db_cmd)).unwrap();
     }
@@ -210,6 +213,8 @@ pub fn create_stctrl_setup(temp_dir_path: &Path) -> StCtrlSetup {
         pbuyer: true,
         pseller: true,
         pconfig: true,
+        preports: true,
+        ppublish: false,
     };
     stmgr(StMgrCmd::AppTicket(app_ticket_cmd)).unwrap();
 
@@ -223,6 +228,8 @@ pub fn create_stctrl_setup(temp_dir_path: &Path) -> StCtrlSetup {
         pbuyer: true,
         pseller: true,
         pconfig: true,
+        preports: true,
+        ppublish: false,
     };
     stmgr(StMgrCmd::AppTicket(app_ticket_cmd)).unwrap();
 
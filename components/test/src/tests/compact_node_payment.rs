@@ -1,7 +1,6 @@
 use std::collections::HashMap;
 use std::convert::TryFrom;
 
-use futures::channel::mpsc;
 use futures::StreamExt;
 
 use tempfile::tempdir;
@@ -13,15 +12,15 @@ use proto::app_server::messages::AppPermissions;
 use proto::crypto::{InvoiceId, PaymentId, PublicKey};
 use proto::funder::messages::{Currency, Rate, Receipt};
 
-use timer::create_timer_incoming;
+use timer::virtual_clock::{create_virtual_clock, VirtualClock};
 
 use app::gen::gen_uid;
 
 use stcompact::compact_node::messages::{
     AddFriend, AddInvoice, CompactToUser, CompactToUserAck, ConfirmPaymentFees,
     FriendLivenessReport, InitPayment, OpenFriendCurrency, PaymentDoneStatus, PaymentFeesResponse,
-    RequestVerifyCommit, SetFriendCurrencyMaxDebt, SetFriendCurrencyRate, UserToCompact,
-    UserToCompactAck, VerifyCommitStatus,
+    RequestPaymentHistory, RequestVerifyCommit, SetFriendCurrencyMaxDebt, SetFriendCurrencyRate,
+    UserToCompact, UserToCompactAck, VerifyCommitStatus,
 };
 
 use crate::compact_node_wrapper::send_request;
@@ -33,8 +32,6 @@ use crate::utils::{
 
 use crate::compact_report_service::compact_report_service;
 
-const TIMER_CHANNEL_LEN: usize = 0;
-
 /// Perform a basic payment between a buyer and a seller.
 /// Node0 sends credits to Node1
 async fn make_test_payment(
@@ -44,7 +41,7 @@ async fn make_test_payment(
     seller_public_key: PublicKey,
     currency: Currency,
     total_dest_payment: u128,
-    mut tick_sender: mpsc::Sender<()>,
+    mut virtual_clock: VirtualClock,
     test_executor: TestExecutor,
 ) -> Option<(Receipt, u128)> {
     let payment_id = PaymentId::from(&[4u8; PaymentId::len()]);
@@ -69,6 +66,7 @@ async fn make_test_payment(
         dest_public_key: seller_public_key.clone(),
         dest_payment: total_dest_payment,
         description: "Example payment".to_owned(),
+        payer_note: "Office supplies".to_owned(),
     };
     send_request(&mut conn_pair0, UserToCompact::InitPayment(init_payment))
         .await
@@ -176,19 +174,24 @@ async fn make_test_payment(
     .unwrap();
 
     // Wait some time:
-    advance_time(5, &mut tick_sender, &test_executor).await;
+    advance_time(5, &mut virtual_clock, &test_executor).await;
 
     // Node0: Wait for PaymentDone:
     let (opt_receipt_fees, ack_uid) = loop {
         let compact_to_user_ack = conn_pair0.receiver.next().await.unwrap();
-        let payment_done =
-            if let CompactToUserAck::CompactToUser(CompactToUser::PaymentDone(payment_done)) =
-                compact_to_user_ack
-            {
+        let payment_done = match compact_to_user_ack {
+            CompactToUserAck::CompactToUser(CompactToUser::PaymentDone(payment_done)) => {
                 payment_done
-            } else {
+            }
+            CompactToUserAck::CompactToUser(CompactToUser::Report(compact_report)) => {
+                // The payer's private note is kept along with the open payment:
+                if let Some(open_payment) = compact_report.open_payments.get(&payment_id) {
+                    assert_eq!(open_payment.payer_note, "Office supplies");
+                }
                 continue;
-            };
+            }
+            _ => continue,
+        };
         assert_eq!(payment_done.payment_id, payment_id);
         match payment_done.status {
             PaymentDoneStatus::Success(receipt, fees, ack_uid) => {
@@ -201,11 +204,48 @@ async fn make_test_payment(
     // Node0: AckPaymentDone:
     send_request(
         &mut conn_pair0,
-        UserToCompact::AckPaymentDone(payment_id, ack_uid),
+        UserToCompact::AckPaymentDone(payment_id.clone(), ack_uid),
     )
     .await
     .unwrap();
 
+    // Node0: A successful payment is kept in the payment history, with its receipt and the
+    // payer's note:
+    let history_request_id = gen_uid();
+    let request_payment_history = RequestPaymentHistory {
+        request_id: history_request_id.clone(),
+    };
+    send_request(
+        &mut conn_pair0,
+        UserToCompact::RequestPaymentHistory(request_payment_history),
+    )
+    .await
+    .unwrap();
+
+    loop {
+        let compact_to_user_ack = conn_pair0.receiver.next().await.unwrap();
+        let response_payment_history = if let CompactToUserAck::CompactToUser(
+            CompactToUser::ResponsePaymentHistory(response_payment_history),
+        ) = compact_to_user_ack
+        {
+            response_payment_history
+        } else {
+            continue;
+        };
+        assert_eq!(response_payment_history.request_id, history_request_id);
+        if let Some((receipt, fees)) = &opt_receipt_fees {
+            let completed_payment = response_payment_history
+                .completed_payments
+                .iter()
+                .find(|completed_payment| completed_payment.payment_id == payment_id)
+                .unwrap();
+            assert_eq!(&completed_payment.receipt, receipt);
+            assert_eq!(&completed_payment.fees, fees);
+            assert_eq!(completed_payment.payer_note, "Office supplies");
+        }
+        break;
+    }
+
     opt_receipt_fees
 }
 
@@ -215,8 +255,7 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
     let currency3 = Currency::try_from("FST3".to_owned()).unwrap();
 
     // Create timer_client:
-    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
-    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+    let (mut virtual_clock, timer_client) = create_virtual_clock(test_executor.clone()).unwrap();
 
     // Create a temporary directory.
     // Should be deleted when gets out of scope:
@@ -239,6 +278,8 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            reports: true,
+            publish: false,
         },
     );
 
@@ -275,6 +316,8 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            reports: true,
+            publish: false,
         },
     );
     create_node(
@@ -389,7 +432,7 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
     .unwrap();
 
     // Wait some time:
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Node0: Add Node1 as a friend:
     let add_friend = AddFriend {
@@ -427,7 +470,7 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
     .await
     .unwrap();
 
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     // Wait until both sides see each other as online:
     loop {
@@ -439,7 +482,7 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
         if friend_report.liveness == FriendLivenessReport::Online {
             break;
         }
-        advance_time(5, &mut tick_sender, &test_executor).await;
+        advance_time(5, &mut virtual_clock, &test_executor).await;
     }
 
     loop {
@@ -451,7 +494,7 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
         if friend_report.liveness == FriendLivenessReport::Online {
             break;
         }
-        advance_time(5, &mut tick_sender, &test_executor).await;
+        advance_time(5, &mut virtual_clock, &test_executor).await;
     }
 
     // Node0: Set active currencies for Node1:
@@ -485,7 +528,7 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
     }
 
     // Wait some time, to let the two nodes negotiate currencies:
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     for currency in [&currency1, &currency2].iter() {
         // Node0: Open currency
@@ -514,7 +557,7 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
     }
 
     // Wait some time, to let the index servers exchange information:
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     // Node1 allows node0 to have maximum debt of currency1=10
     let set_friend_currency_max_debt = SetFriendCurrencyMaxDebt {
@@ -543,7 +586,7 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
     .unwrap();
 
     // Wait until the max debt was set:
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     // Send 10 currency1 credits from node0 to node1:
     let opt_receipt_fees = make_test_payment(
@@ -553,7 +596,7 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
         node_public_key(1),
         currency1.clone(),
         10u128, // total_dest_payment
-        tick_sender.clone(),
+        virtual_clock.clone(),
         test_executor.clone(),
     )
     .await;
@@ -561,7 +604,7 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
     assert!(opt_receipt_fees.is_some());
 
     // Allow some time for the index servers to be updated about the new state:
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     // Send 11 currency2 credits from node0 to node1:
     let opt_receipt_fees = make_test_payment(
@@ -571,7 +614,7 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
         node_public_key(1),
         currency2.clone(),
         11u128, // total_dest_payment
-        tick_sender.clone(),
+        virtual_clock.clone(),
         test_executor.clone(),
     )
     .await;
@@ -579,7 +622,7 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
     assert!(opt_receipt_fees.is_some());
 
     // Allow some time for the index servers to be updated about the new state:
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     // Node1: Send 5 credits to Node0:
     let opt_receipt_fees = make_test_payment(
@@ -589,7 +632,7 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
         node_public_key(0),
         currency1.clone(),
         5u128, // total_dest_payment
-        tick_sender.clone(),
+        virtual_clock.clone(),
         test_executor.clone(),
     )
     .await;
@@ -597,7 +640,7 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
     assert!(opt_receipt_fees.is_some());
 
     // Allow some time for the index servers to be updated about the new state:
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     // Node1: Attempt to send 6 more credits to Node0:
     // This should not work, because 6 + 5 = 11 > 10
@@ -608,7 +651,7 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
         node_public_key(0),
         currency1.clone(),
         6u128, // total_dest_payment
-        tick_sender.clone(),
+        virtual_clock.clone(),
         test_executor.clone(),
     )
     .await;
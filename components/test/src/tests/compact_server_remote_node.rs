@@ -1,7 +1,6 @@
 use std::collections::HashMap;
 use std::convert::TryFrom;
 
-use futures::channel::mpsc;
 use futures::StreamExt;
 
 use tempfile::tempdir;
@@ -13,7 +12,7 @@ use proto::app_server::messages::AppPermissions;
 use proto::crypto::{InvoiceId, PaymentId, PublicKey};
 use proto::funder::messages::{Currency, Rate, Receipt};
 
-use timer::create_timer_incoming;
+use timer::virtual_clock::{create_virtual_clock, VirtualClock};
 
 use app::gen::gen_uid;
 
@@ -35,8 +34,6 @@ use crate::utils::{
     node_public_key, relay_address, SimDb,
 };
 
-const TIMER_CHANNEL_LEN: usize = 0;
-
 /// Helper function: Send a UserToCompact message to a specific node
 async fn node_request(
     compact: &mut ConnPair<UserToServerAck, ServerToUserAck>,
@@ -63,7 +60,7 @@ async fn make_test_payment(
     seller_public_key: PublicKey,
     currency: Currency,
     total_dest_payment: u128,
-    mut tick_sender: mpsc::Sender<()>,
+    mut virtual_clock: VirtualClock,
     test_executor: TestExecutor,
 ) -> Option<(Receipt, u128)> {
     let payment_id = PaymentId::from(&[4u8; PaymentId::len()]);
@@ -92,6 +89,7 @@ async fn make_test_payment(
         dest_public_key: seller_public_key.clone(),
         dest_payment: total_dest_payment,
         description: "Example payment".to_owned(),
+        payer_note: "Office supplies".to_owned(),
     };
     node_request(
         &mut compact0,
@@ -218,7 +216,7 @@ async fn make_test_payment(
     .await;
 
     // Wait some time:
-    advance_time(5, &mut tick_sender, &test_executor).await;
+    advance_time(5, &mut virtual_clock, &test_executor).await;
 
     // Node0: Wait for PaymentDone:
     let (opt_receipt_fees, ack_uid) = loop {
@@ -260,8 +258,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
     let currency3 = Currency::try_from("FST3".to_owned()).unwrap();
 
     // Create timer_client:
-    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
-    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+    let (mut virtual_clock, timer_client) = create_virtual_clock(test_executor.clone()).unwrap();
 
     // Create a temporary directory.
     // Should be deleted when gets out of scope:
@@ -305,6 +302,8 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            reports: true,
+            publish: false,
         },
     );
     let node1_handle = create_node(
@@ -464,7 +463,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
         .unwrap();
 
     // Wait some time for the node to be opened:
-    advance_time(1, &mut tick_sender, &test_executor).await;
+    advance_time(1, &mut virtual_clock, &test_executor).await;
 
     // Wait for response:
     let server_to_user_ack = compact1.receiver.next().await.unwrap();
@@ -513,7 +512,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
     .await;
 
     // Wait some time:
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Node0: Add Node1 as a friend:
     let add_friend = AddFriend {
@@ -561,7 +560,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
     )
     .await;
 
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     // TODO: Possibly add waiting for both sides to be online in the future.
     // This requires some mechanism for reading compact reports while passing time.
@@ -578,7 +577,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
         if friend_report.liveness == FriendLivenessReport::Online {
             break;
         }
-        advance_time(5, &mut tick_sender, &test_executor).await;
+        advance_time(5, &mut virtual_clock, &test_executor).await;
     }
 
     loop {
@@ -590,7 +589,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
         if friend_report.liveness == FriendLivenessReport::Online {
             break;
         }
-        advance_time(5, &mut tick_sender, &test_executor).await;
+        advance_time(5, &mut virtual_clock, &test_executor).await;
     }
 
     */
@@ -629,7 +628,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
     }
 
     // Wait some time, to let the two nodes negotiate currencies:
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     for currency in [&currency1, &currency2].iter() {
         // Node0: Open currency
@@ -660,7 +659,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
     }
 
     // Wait some time, to let the index servers exchange information:
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     // Node1 allows node0 to have maximum debt of currency1=10
     let set_friend_currency_max_debt = SetFriendCurrencyMaxDebt {
@@ -691,7 +690,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
     .await;
 
     // Wait until the max debt was set:
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     // Send 10 currency1 credits from node0 to node1:
     let opt_receipt_fees = make_test_payment(
@@ -705,7 +704,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
         pk1.clone(),
         currency1.clone(),
         10u128, // total_dest_payment
-        tick_sender.clone(),
+        virtual_clock.clone(),
         test_executor.clone(),
     )
     .await;
@@ -713,7 +712,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
     assert!(opt_receipt_fees.is_some());
 
     // Allow some time for the index servers to be updated about the new state:
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     // Send 11 currency2 credits from node0 to node1:
     let opt_receipt_fees = make_test_payment(
@@ -727,7 +726,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
         pk1.clone(),
         currency2.clone(),
         11u128, // total_dest_payment
-        tick_sender.clone(),
+        virtual_clock.clone(),
         test_executor.clone(),
     )
     .await;
@@ -735,7 +734,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
     assert!(opt_receipt_fees.is_some());
 
     // Allow some time for the index servers to be updated about the new state:
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     // Node1: Send 5 credits to Node0:
     let opt_receipt_fees = make_test_payment(
@@ -749,7 +748,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
         pk0.clone(),
         currency1.clone(),
         5u128, // total_dest_payment
-        tick_sender.clone(),
+        virtual_clock.clone(),
         test_executor.clone(),
     )
     .await;
@@ -757,7 +756,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
     assert!(opt_receipt_fees.is_some());
 
     // Allow some time for the index servers to be updated about the new state:
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     // compact0: close a local node:
     let user_to_server = UserToServer::DisableNode(node0_name.clone());
@@ -765,7 +764,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
         .await
         .unwrap();
 
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     // Close and open node0:
     // compact0: close a local node:
@@ -792,7 +791,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
         unreachable!();
     };
 
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     // Node1: Attempt to send 6 more credits to Node0:
     // This should not work, because 6 + 5 = 11 > 10
@@ -807,7 +806,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
         pk0.clone(),
         currency1.clone(),
         6u128, // total_dest_payment
-        tick_sender.clone(),
+        virtual_clock.clone(),
         test_executor.clone(),
     )
     .await;
@@ -818,7 +817,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
     // (node1) is lost:
     drop(node1_handle);
 
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     // Wait until we can see that node1 is offline:
     while let Some(server_to_user_ack) = compact1.receiver.next().await {
@@ -845,7 +844,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
     .forget();
 
     // Reconnect should happen during this period of time:
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     // Wait until we can see that node1 is online:
     while let Some(server_to_user_ack) = compact1.receiver.next().await {
@@ -1,14 +1,12 @@
 use std::collections::HashMap;
 
-use futures::channel::mpsc;
-
 use tempfile::tempdir;
 
 use common::test_executor::TestExecutor;
 
 use proto::app_server::messages::AppPermissions;
 
-use timer::create_timer_incoming;
+use timer::virtual_clock::create_virtual_clock;
 
 use app::conn::{self, ConnPairApp};
 
@@ -21,8 +19,6 @@ use crate::utils::{
 
 use crate::node_report_service::node_report_service;
 
-const TIMER_CHANNEL_LEN: usize = 0;
-
 async fn task_handle_error_command(mut test_executor: TestExecutor) {
     /*
     let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
@@ -31,8 +27,7 @@ async fn task_handle_error_command(mut test_executor: TestExecutor) {
     */
 
     // Create timer_client:
-    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
-    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+    let (mut virtual_clock, timer_client) = create_virtual_clock(test_executor.clone()).unwrap();
 
     // Create a temporary directory.
     // Should be deleted when gets out of scope:
@@ -55,6 +50,8 @@ async fn task_handle_error_command(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            reports: true,
+            publish: false,
         },
     );
 
@@ -101,6 +98,8 @@ async fn task_handle_error_command(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            reports: true,
+            publish: false,
         },
     );
     create_node(
@@ -169,7 +168,7 @@ async fn task_handle_error_command(mut test_executor: TestExecutor) {
     .unwrap();
 
     // Wait some time:
-    advance_time(20, &mut tick_sender, &test_executor).await;
+    advance_time(20, &mut virtual_clock, &test_executor).await;
 
     // Node0: Add node1 as a friend:
     send_request(
@@ -212,7 +211,7 @@ async fn task_handle_error_command(mut test_executor: TestExecutor) {
     .await
     .unwrap();
 
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 }
 
 #[test]
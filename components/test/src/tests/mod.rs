@@ -1,6 +1,7 @@
 mod compact_node_payment;
 mod compact_server_remote_node;
 mod handle_error_command;
+mod node_runner;
 mod nodes_chain;
 mod relay_migration;
 mod resolve_inconsistency;
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use crypto::test_utils::DummyRandom;
+
+use proto::app_server::messages::AppPermissions;
+
+use timer::virtual_clock::create_virtual_clock;
+
+use bin::stnode::{NetNodeError, NodeRunner};
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{create_app, create_node_instance, listen_node_address, node_public_key, SimDb};
+
+fn app_trusted_apps(app_index: u8) -> HashMap<u8, AppPermissions> {
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        app_index,
+        AppPermissions {
+            routes: true,
+            buyer: true,
+            seller: true,
+            config: true,
+            reports: true,
+            publish: false,
+        },
+    );
+    trusted_apps
+}
+
+async fn task_node_runner(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (_virtual_clock, timer_client) = create_virtual_clock(test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let mut sim_net_client = create_sim_network(&mut test_executor);
+
+    // Create initial databases for node 0 and node 1:
+    sim_db.init_node_db(0).unwrap();
+    sim_db.init_node_db(1).unwrap();
+
+    // Both nodes run in the same node runner:
+    let mut node_runner = NodeRunner::new(
+        sim_net_client.clone(),
+        timer_client.clone(),
+        DummyRandom::new(&[0xff, 0x13, 0x37]),
+        test_executor.clone(),
+    );
+
+    let incoming_app_raw_conns = sim_net_client.listen(listen_node_address(0)).await.unwrap();
+    let node_instance = create_node_instance(
+        0,
+        sim_db.clone(),
+        incoming_app_raw_conns,
+        app_trusted_apps(0),
+        test_executor.clone(),
+    );
+    let running_node0 = node_runner.spawn_node(node_instance).await.unwrap();
+    assert_eq!(running_node0.local_public_key, node_public_key(0));
+
+    let incoming_app_raw_conns = sim_net_client.listen(listen_node_address(1)).await.unwrap();
+    let node_instance = create_node_instance(
+        1,
+        sim_db.clone(),
+        incoming_app_raw_conns,
+        app_trusted_apps(1),
+        test_executor.clone(),
+    );
+    let mut running_node1 = node_runner.spawn_node(node_instance).await.unwrap();
+    assert_eq!(running_node1.local_public_key, node_public_key(1));
+
+    // Every node serves its own apps:
+    let opt_app0 = create_app(
+        0,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        0,
+        test_executor.clone(),
+    )
+    .await;
+    assert!(opt_app0.is_some());
+
+    let opt_app1 = create_app(
+        1,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        1,
+        test_executor.clone(),
+    )
+    .await;
+    assert!(opt_app1.is_some());
+
+    // A node with the identity of node 0 can not run while node 0 is running:
+    let (_sender, incoming_app_raw_conns) = mpsc::channel(0);
+    let node_instance = create_node_instance(
+        0,
+        sim_db.clone(),
+        incoming_app_raw_conns,
+        app_trusted_apps(0),
+        test_executor.clone(),
+    );
+    match node_runner.spawn_node(node_instance).await {
+        Err(NetNodeError::DuplicateIdentity) => {}
+        _ => unreachable!(),
+    }
+
+    // The state of a running node may be exported:
+    let node_state = running_node1.node_handle.export_state().await.unwrap();
+    assert_eq!(node_state.funder_state.local_public_key, node_public_key(1));
+
+    // Shutting down node 1 does not affect node 0:
+    assert!(running_node1.node_handle.shutdown().await.unwrap());
+    running_node1.node_done.await.unwrap();
+
+    let opt_app0 = create_app(
+        0,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        0,
+        test_executor.clone(),
+    )
+    .await;
+    assert!(opt_app0.is_some());
+
+    // The identity of node 1 may be used again:
+    let (_sender, incoming_app_raw_conns) = mpsc::channel(0);
+    let node_instance = create_node_instance(
+        1,
+        sim_db.clone(),
+        incoming_app_raw_conns,
+        app_trusted_apps(1),
+        test_executor.clone(),
+    );
+    let running_node1 = node_runner.spawn_node(node_instance).await.unwrap();
+    assert_eq!(running_node1.local_public_key, node_public_key(1));
+}
+
+#[test]
+fn test_node_runner() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_node_runner(test_executor.clone()));
+    assert!(res.is_output());
+}
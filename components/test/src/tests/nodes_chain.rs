@@ -1,8 +1,6 @@
 use std::collections::HashMap;
 use std::convert::TryFrom;
 
-use futures::channel::mpsc;
-
 use tempfile::tempdir;
 
 use common::test_executor::TestExecutor;
@@ -10,7 +8,7 @@ use common::test_executor::TestExecutor;
 use proto::app_server::messages::AppPermissions;
 use proto::funder::messages::{Currency, PaymentStatus, PaymentStatusSuccess, Rate};
 
-use timer::create_timer_incoming;
+use timer::virtual_clock::create_virtual_clock;
 
 use app::conn::{self, ConnPairApp, RequestResult};
 
@@ -27,8 +25,6 @@ use crate::utils::{
 
 use crate::node_report_service::{node_report_service, NodeReportClient};
 
-const TIMER_CHANNEL_LEN: usize = 0;
-
 struct AppControl {
     #[allow(unused)]
     permissions: AppPermissions,
@@ -51,8 +47,7 @@ async fn task_nodes_chain(mut test_executor: TestExecutor) {
     let sim_net_client = create_sim_network(&mut test_executor);
 
     // Create timer_client:
-    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
-    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+    let (mut virtual_clock, timer_client) = create_virtual_clock(test_executor.clone()).unwrap();
 
     let mut apps = Vec::new();
 
@@ -68,6 +63,8 @@ async fn task_nodes_chain(mut test_executor: TestExecutor) {
                 buyer: true,
                 seller: true,
                 config: true,
+                reports: true,
+                publish: false,
             },
         );
 
@@ -198,7 +195,7 @@ async fn task_nodes_chain(mut test_executor: TestExecutor) {
     }
 
     // Wait some time:
-    // advance_time(40, &mut tick_sender, &test_executor).await;
+    // advance_time(40, &mut virtual_clock, &test_executor).await;
     /*
                        5
                        |
@@ -245,7 +242,7 @@ async fn task_nodes_chain(mut test_executor: TestExecutor) {
     }
 
     // Wait until active currencies are negotiated:
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Open channels:
     for (i, j) in &[(0u8, 1u8), (1, 3), (1, 2), (2, 5), (2, 4)] {
@@ -348,7 +345,7 @@ async fn task_nodes_chain(mut test_executor: TestExecutor) {
     .unwrap();
 
     // Wait some time:
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Make sure that nodes see each other as online along the chain 0 -- 1 -- 2 -- 4:
     for (i, j) in &[(0u8, 1u8), (1, 2), (2, 4)] {
@@ -443,7 +440,7 @@ async fn task_nodes_chain(mut test_executor: TestExecutor) {
         .unwrap();
 
     // Wait some time:
-    advance_time(5, &mut tick_sender, &test_executor).await;
+    advance_time(5, &mut virtual_clock, &test_executor).await;
 
     // Node0: Check the payment's result:
     let payment_status = request_close_payment(&mut apps[0].conn_pair, payment_id.clone())
@@ -543,7 +540,7 @@ async fn task_nodes_chain(mut test_executor: TestExecutor) {
         .unwrap();
 
     // Wait some time:
-    advance_time(5, &mut tick_sender, &test_executor).await;
+    advance_time(5, &mut virtual_clock, &test_executor).await;
 
     // Node5: Check the payment's result:
     let payment_status = request_close_payment(&mut apps[5].conn_pair, payment_id.clone())
@@ -1,13 +1,11 @@
 use std::collections::HashMap;
 
-use futures::channel::mpsc;
-
 use tempfile::tempdir;
 
 use common::test_executor::TestExecutor;
 
 use proto::app_server::messages::AppPermissions;
-use timer::create_timer_incoming;
+use timer::virtual_clock::create_virtual_clock;
 
 use app::conn::{self, ConnPairApp};
 // use app::report::NodeReport;
@@ -22,8 +20,6 @@ use crate::node_report_service::{node_report_service, NodeReportClient};
 use crate::app_wrapper::send_request;
 use crate::sim_network::create_sim_network;
 
-const TIMER_CHANNEL_LEN: usize = 0;
-
 /// Checks if a friend is online
 /// panics if the friend does not exist.
 async fn wait_friend_online(report_client: &mut NodeReportClient, index: u8) {
@@ -64,8 +60,7 @@ async fn wait_friend_offline(report_client: &mut NodeReportClient, index: u8) {
 
 async fn task_relay_migration(mut test_executor: TestExecutor) {
     // Create timer_client:
-    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
-    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+    let (mut virtual_clock, timer_client) = create_virtual_clock(test_executor.clone()).unwrap();
 
     // Create a temporary directory.
     // Should be deleted when gets out of scope:
@@ -88,6 +83,8 @@ async fn task_relay_migration(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            reports: true,
+            publish: false,
         },
     );
 
@@ -122,6 +119,8 @@ async fn task_relay_migration(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            reports: true,
+            publish: false,
         },
     );
     let node1_handle = create_node(
@@ -213,7 +212,7 @@ async fn task_relay_migration(mut test_executor: TestExecutor) {
     .unwrap();
 
     // Wait some time:
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Node0: Add node1 as a friend:
     send_request(
@@ -252,7 +251,7 @@ async fn task_relay_migration(mut test_executor: TestExecutor) {
     .await
     .unwrap();
 
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     wait_friend_online(&mut report_client0, 1).await;
     wait_friend_online(&mut report_client1, 0).await;
@@ -271,12 +270,12 @@ async fn task_relay_migration(mut test_executor: TestExecutor) {
     .await
     .unwrap();
 
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Close node1:
     drop(node1_handle);
 
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Node0 should see Node1 as offline:
     wait_friend_offline(&mut report_client0, 1).await;
@@ -303,7 +302,7 @@ async fn task_relay_migration(mut test_executor: TestExecutor) {
     .await
     .unwrap();
 
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Reopen node1:
     let mut trusted_apps = HashMap::new();
@@ -314,6 +313,8 @@ async fn task_relay_migration(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            reports: true,
+            publish: false,
         },
     );
     let _node1_handle = create_node(
@@ -343,7 +344,7 @@ async fn task_relay_migration(mut test_executor: TestExecutor) {
         node_report_service(node_report1, receiver1, &test_executor);
     // let _conn_pair1 = ConnPairApp::from_raw(sender1, receiver1);
 
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Node1 should be able to achieve connectivity:
     wait_friend_online(&mut report_client0, 1).await;
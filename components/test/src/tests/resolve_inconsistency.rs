@@ -1,8 +1,6 @@
 use std::collections::HashMap;
 use std::convert::TryFrom;
 
-use futures::channel::mpsc;
-
 use tempfile::tempdir;
 
 use common::test_executor::TestExecutor;
@@ -14,7 +12,7 @@ use proto::report::messages::ChannelStatusReport;
 
 use app::conn::{self, ConnPairApp, RequestResult};
 
-use timer::create_timer_incoming;
+use timer::virtual_clock::{create_virtual_clock, VirtualClock};
 
 use crate::node_report_service::node_report_service;
 use crate::utils::{
@@ -28,8 +26,6 @@ use crate::app_wrapper::{
     ack_close_payment, create_transaction, request_close_payment, send_request,
 };
 
-const TIMER_CHANNEL_LEN: usize = 0;
-
 /// Perform a basic payment between a buyer and a seller.
 /// Use a trivial route of [buyer, seller] instead of using an index server.
 /// Node0 sends credits to Node1
@@ -41,7 +37,7 @@ pub async fn make_test_payment(
     currency: Currency,
     total_dest_payment: u128,
     fees: u128,
-    mut tick_sender: mpsc::Sender<()>,
+    mut virtual_clock: VirtualClock,
     test_executor: TestExecutor,
 ) -> PaymentStatus {
     let payment_id = PaymentId::from(&[4u8; PaymentId::len()]);
@@ -105,7 +101,7 @@ pub async fn make_test_payment(
         .unwrap();
 
     // Wait some time:
-    advance_time(5, &mut tick_sender, &test_executor).await;
+    advance_time(5, &mut virtual_clock, &test_executor).await;
 
     // Node0: Check the payment's result:
     let payment_status = request_close_payment(conn_pair0, payment_id.clone())
@@ -136,8 +132,7 @@ async fn task_resolve_inconsistency(mut test_executor: TestExecutor) {
     let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
 
     // Create timer_client:
-    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
-    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+    let (mut virtual_clock, timer_client) = create_virtual_clock(test_executor.clone()).unwrap();
 
     // Create a temporary directory.
     // Should be deleted when gets out of scope:
@@ -160,6 +155,8 @@ async fn task_resolve_inconsistency(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            reports: true,
+            publish: false,
         },
     );
 
@@ -195,6 +192,8 @@ async fn task_resolve_inconsistency(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            reports: true,
+            publish: false,
         },
     );
     create_node(
@@ -274,7 +273,7 @@ async fn task_resolve_inconsistency(mut test_executor: TestExecutor) {
     .unwrap();
 
     // Wait some time:
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Node0: Add node1 as a friend:
     send_request(
@@ -327,7 +326,7 @@ async fn task_resolve_inconsistency(mut test_executor: TestExecutor) {
     .await
     .unwrap();
 
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     send_request(
         &mut conn_pair0,
@@ -355,7 +354,7 @@ async fn task_resolve_inconsistency(mut test_executor: TestExecutor) {
     .await
     .unwrap();
 
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Send 10 currency1 credits from node0 to node1:
     let payment_status = make_test_payment(
@@ -366,7 +365,7 @@ async fn task_resolve_inconsistency(mut test_executor: TestExecutor) {
         currency1.clone(),
         8u128, // total_dest_payment
         2u128, // fees
-        tick_sender.clone(),
+        virtual_clock.clone(),
         test_executor.clone(),
     )
     .await;
@@ -406,7 +405,7 @@ async fn task_resolve_inconsistency(mut test_executor: TestExecutor) {
     .await
     .unwrap();
 
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Node1 should now perceive the mutual channel with node0 to be inconsistent:
     let incon_report = loop {
@@ -475,7 +474,7 @@ async fn task_resolve_inconsistency(mut test_executor: TestExecutor) {
     .await
     .unwrap();
 
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Node0: Channel should be consistent now:
     loop {
@@ -525,7 +524,7 @@ async fn task_resolve_inconsistency(mut test_executor: TestExecutor) {
     .await
     .unwrap();
 
-    advance_time(20, &mut tick_sender, &test_executor).await;
+    advance_time(20, &mut virtual_clock, &test_executor).await;
 
     /*
     // Make sure again that the channel stays consistent:
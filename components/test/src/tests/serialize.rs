@@ -1,9 +1,10 @@
 use rand::{self, rngs::StdRng};
 
 use funder::FunderState;
+use proto::crypto::PublicKey;
 use proto::file::{
-    FriendAddressFile, FriendFile, IdentityFile, IndexServerFile, NodeAddressFile,
-    RelayAddressFile, TrustedAppFile,
+    FriendAddressFile, FriendFile, IdentityFile, IndexAdminFile, IndexDirectoryKeyFile,
+    IndexServerFile, NodeAddressFile, RelayAddressFile, TrustedAppFile,
 };
 use proto::net::messages::NetAddress;
 use stcompact::compact_node::CompactState;
@@ -38,12 +39,42 @@ ser_de_test!(qc_ser_de_friend_address_file, FriendAddressFile);
 ser_de_test!(qc_ser_de_friend_file, FriendFile);
 ser_de_test!(qc_ser_de_identity_file, IdentityFile);
 ser_de_test!(qc_ser_de_index_server_file, IndexServerFile);
+ser_de_test!(qc_ser_de_index_admin_file, IndexAdminFile);
+ser_de_test!(qc_ser_de_index_directory_key_file, IndexDirectoryKeyFile);
 ser_de_test!(qc_ser_de_node_address_file, NodeAddressFile);
 ser_de_test!(qc_ser_de_relay_address_file, RelayAddressFile);
 ser_de_test!(qc_ser_de_trusted_app_file, TrustedAppFile);
 
 ser_de_test!(qc_ser_de_compact_state, CompactState);
 
+#[test]
+fn test_deserialize_trusted_app_file_old_permissions() {
+    // A trusted app file written before the `reports` and `publish` permissions existed:
+    let trusted_app_str = r#"{
+        "publicKey": "qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqo",
+        "permissions": {
+            "routes": true,
+            "buyer": true,
+            "seller": false,
+            "config": false
+        }
+    }"#;
+    let trusted_app_file: TrustedAppFile = serde_json::from_str(trusted_app_str).unwrap();
+    assert_eq!(
+        trusted_app_file.public_key,
+        PublicKey::from(&[0xaa; PublicKey::len()])
+    );
+
+    let permissions = trusted_app_file.permissions;
+    assert!(permissions.routes);
+    assert!(permissions.buyer);
+    assert!(!permissions.seller);
+    assert!(!permissions.config);
+    // Apps always received reports before the `reports` permission existed:
+    assert!(permissions.reports);
+    assert!(!permissions.publish);
+}
+
 /*
 #[test]
 fn qc_ser_de_funder_state_json() {
@@ -1,8 +1,6 @@
 use std::collections::HashMap;
 use std::convert::TryFrom;
 
-use futures::channel::mpsc;
-
 use tempfile::tempdir;
 
 use common::test_executor::TestExecutor;
@@ -11,7 +9,7 @@ use proto::app_server::messages::AppPermissions;
 use proto::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
 use proto::funder::messages::{Currency, FriendsRoute, PaymentStatus, PaymentStatusSuccess, Rate};
 
-use timer::create_timer_incoming;
+use timer::virtual_clock::{create_virtual_clock, VirtualClock};
 
 use app::conn::{self, ConnPairApp, RequestResult};
 
@@ -26,8 +24,6 @@ use crate::utils::{
 
 use crate::node_report_service::node_report_service;
 
-const TIMER_CHANNEL_LEN: usize = 0;
-
 /// Perform a basic payment between a buyer and a seller.
 /// Node0 sends credits to Node1
 async fn make_test_payment(
@@ -38,7 +34,7 @@ async fn make_test_payment(
     currency: Currency,
     total_dest_payment: u128,
     fees: u128,
-    mut tick_sender: mpsc::Sender<()>,
+    mut virtual_clock: VirtualClock,
     test_executor: TestExecutor,
 ) -> PaymentStatus {
     let payment_id = PaymentId::from(&[4u8; PaymentId::len()]);
@@ -112,7 +108,7 @@ async fn make_test_payment(
     // Node0 now passes the Commit to Node1 out of band.
 
     // Wait some time:
-    advance_time(5, &mut tick_sender, &test_executor).await;
+    advance_time(5, &mut virtual_clock, &test_executor).await;
 
     // Node0: Check the payment's result:
     let payment_status = request_close_payment(conn_pair0, payment_id.clone())
@@ -145,8 +141,7 @@ async fn task_two_nodes_payment(mut test_executor: TestExecutor) {
     let currency3 = Currency::try_from("FST3".to_owned()).unwrap();
 
     // Create timer_client:
-    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
-    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+    let (mut virtual_clock, timer_client) = create_virtual_clock(test_executor.clone()).unwrap();
 
     // Create a temporary directory.
     // Should be deleted when gets out of scope:
@@ -169,6 +164,8 @@ async fn task_two_nodes_payment(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            reports: true,
+            publish: false,
         },
     );
 
@@ -215,6 +212,8 @@ async fn task_two_nodes_payment(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            reports: true,
+            publish: false,
         },
     );
     create_node(
@@ -328,7 +327,7 @@ async fn task_two_nodes_payment(mut test_executor: TestExecutor) {
     .unwrap();
 
     // Wait some time:
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Node0: Add node1 as a friend:
     send_request(
@@ -361,21 +360,21 @@ async fn task_two_nodes_payment(mut test_executor: TestExecutor) {
     )
     .await
     .unwrap();
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
     send_request(
         &mut conn_pair0,
         conn::config::disable_friend(node_public_key(1)),
     )
     .await
     .unwrap();
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
     send_request(
         &mut conn_pair0,
         conn::config::enable_friend(node_public_key(1)),
     )
     .await
     .unwrap();
-    advance_time(10, &mut tick_sender, &test_executor).await;
+    advance_time(10, &mut virtual_clock, &test_executor).await;
 
     // Node1: Enable node0:
     send_request(
@@ -385,7 +384,7 @@ async fn task_two_nodes_payment(mut test_executor: TestExecutor) {
     .await
     .unwrap();
 
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     loop {
         let node_report0 = report_client0.request_report().await;
@@ -436,7 +435,7 @@ async fn task_two_nodes_payment(mut test_executor: TestExecutor) {
     }
 
     // Wait some time, to let the two nodes negotiate currencies:
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     send_request(
         &mut conn_pair0,
@@ -465,7 +464,7 @@ async fn task_two_nodes_payment(mut test_executor: TestExecutor) {
     .unwrap();
 
     // Wait some time, to let the index servers exchange information:
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Node1 allows node0 to have maximum debt of 10
     send_request(
@@ -482,7 +481,7 @@ async fn task_two_nodes_payment(mut test_executor: TestExecutor) {
     .unwrap();
 
     // Wait until the max debt was set:
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Send 10 currency1 credits from node0 to node1:
     let payment_status = make_test_payment(
@@ -493,7 +492,7 @@ async fn task_two_nodes_payment(mut test_executor: TestExecutor) {
         currency1.clone(),
         8u128, // total_dest_payment
         2u128, // fees
-        tick_sender.clone(),
+        virtual_clock.clone(),
         test_executor.clone(),
     )
     .await;
@@ -504,7 +503,7 @@ async fn task_two_nodes_payment(mut test_executor: TestExecutor) {
     };
 
     // Allow some time for the index servers to be updated about the new state:
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Send 11 currency2 credits from node0 to node1:
     let payment_status = make_test_payment(
@@ -515,7 +514,7 @@ async fn task_two_nodes_payment(mut test_executor: TestExecutor) {
         currency2.clone(),
         9u128, // total_dest_payment
         2u128, // fees
-        tick_sender.clone(),
+        virtual_clock.clone(),
         test_executor.clone(),
     )
     .await;
@@ -526,7 +525,7 @@ async fn task_two_nodes_payment(mut test_executor: TestExecutor) {
     };
 
     // Allow some time for the index servers to be updated about the new state:
-    advance_time(40, &mut tick_sender, &test_executor).await;
+    advance_time(40, &mut virtual_clock, &test_executor).await;
 
     // Node1: Send 5 = 3 + 2 credits to Node0:
     let payment_status = make_test_payment(
@@ -537,7 +536,7 @@ async fn task_two_nodes_payment(mut test_executor: TestExecutor) {
         currency1.clone(),
         3u128, // total_dest_payment
         2u128, // fees
-        tick_sender.clone(),
+        virtual_clock.clone(),
         test_executor.clone(),
     )
     .await;
@@ -1,10 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 
 use futures::channel::mpsc;
 use futures::future::RemoteHandle;
 use futures::task::{Spawn, SpawnExt};
-use futures::{future, FutureExt, SinkExt, TryFutureExt};
+use futures::{future, stream, FutureExt, TryFutureExt};
 
 use crypto::identity::{Identity, SoftwareEd25519Identity};
 
@@ -13,12 +13,15 @@ use crypto::test_utils::DummyRandom;
 
 use common::test_executor::TestExecutor;
 
-use common::conn::{BoxFuture, ConnPair};
+use common::conn::{BoxFuture, ConnPair, ConnPairVec};
 
 use proto::crypto::{PrivateKey, PublicKey};
 
 use proto::app_server::messages::{AppPermissions, NamedRelayAddress, RelayAddress};
-use proto::consts::{KEEPALIVE_TICKS, MAX_NODE_RELAYS, MAX_OPERATIONS_IN_BATCH, TICKS_TO_REKEY};
+use proto::consts::{
+    KEEPALIVE_TICKS, MAX_NODE_RELAYS, MAX_OPERATIONS_IN_BATCH, MAX_ROUTE_LEN, TICKS_TO_REKEY,
+    TICKS_TO_RESUME,
+};
 use proto::index_server::messages::NamedIndexServerAddress;
 use proto::net::messages::NetAddress;
 
@@ -28,14 +31,16 @@ use app::conn::AppConnTuple;
 use app_client::app_connect_to_node;
 use connection::create_secure_connector;
 
-use node::{NodeConfig, NodeState};
+use node::{NodeConfig, NodeRequest, NodeState};
 
 use database::file_db::FileDb;
 use database::{database_loop, AtomicDb, DatabaseClient};
 
 use bin::stindex::net_index_server;
-use bin::stnode::{net_node, TrustedApps};
+use bin::stnode::{net_node, NodeInstance, TrustedApps};
 use bin::strelay::net_relay_server;
+use index_server::{AdminState, CapacityDecay};
+use relay::{RelayMetrics, RelayTimeouts};
 
 use stcompact::compact_node::messages::{CompactReport, CompactToUserAck, UserToCompactAck};
 use stcompact::compact_node::{compact_node, create_compact_report, CompactState, ConnPairCompact};
@@ -45,6 +50,7 @@ use stcompact::messages::{ServerToUserAck, UserToServerAck};
 use stcompact::server_loop::compact_server_loop;
 use stcompact::store::open_file_store;
 
+use timer::virtual_clock::VirtualClock;
 use timer::TimerClient;
 
 use crate::sim_network::{net_address, SimNetworkClient};
@@ -53,16 +59,49 @@ use crate::sim_network::{net_address, SimNetworkClient};
 const CHANNEL_LEN: usize = 0x20;
 /// The amount of ticks we wait before attempting to reconnect
 const BACKOFF_TICKS: usize = 0x8;
+/// Minimal amount of ticks between keepalives sent to a friend
+const KEEPALIVE_MIN_TICKS: usize = 0x4;
+/// Proof of work difficulty required by the index servers (Kept low to keep the tests fast)
+const POW_DIFFICULTY: u8 = 2;
+/// Capacities reported to the index servers decay much slower than the duration of any test
+const DECAY_HALF_LIFE: u64 = 0x1000;
+const DECAY_HORIZON: u64 = 0x4000;
 /// Maximum amount of encryption set ups (diffie hellman) that we allow to occur at the same
 /// time.
 const MAX_CONCURRENT_ENCRYPT: usize = 0x8;
 /// The size we allocate for the user send funds requests queue.
 const MAX_PENDING_USER_REQUESTS: usize = 0x20;
+/// Maximum amount of times a failed transaction is retried through an alternative route.
+const MAX_TRANSACTION_RETRIES: u64 = 0;
 /// Maximum amount of concurrent index client requests:
 const MAX_OPEN_INDEX_CLIENT_REQUESTS: usize = 0x8;
+/// The amount of ticks routes received from an index server are cached (0 disables the cache):
+const ROUTE_CACHE_TICKS: usize = 0;
+/// Rotate the routes returned for repeated requests to the same destination:
+const ROUTE_ROTATION: bool = false;
+/// Smooth the capacities advertised to the index servers during short capacity drops:
+const CAPACITY_SMOOTHING: bool = false;
+/// Amount of index servers we keep a session with at the same time:
+const MAX_INDEX_SESSIONS: usize = 1;
 /// The amount of ticks we are willing to wait until a connection is established (Through
 /// the relay)
 const CONN_TIMEOUT_TICKS: usize = 0x8;
+/// Tunnel accepted relay connections over a single connection to the relay.
+const RELAY_MULTIPLEX: bool = true;
+/// The amount of ticks between measurements of the latency to relays (0 disables measurements):
+const RELAY_PROBE_TICKS: usize = 0;
+/// Maximum amount of ticks a graceful shutdown waits for pending operations to settle:
+const SHUTDOWN_TICKS: usize = 0x20;
+/// Amount of ticks until a request originated by this node expires (0 disables expiry):
+const REQUEST_EXPIRY_TICKS: u64 = 0;
+/// Minimum recent uptime of a friend for new requests to be queued through it (0 disables gating):
+const MIN_FRIEND_UPTIME_PERCENT: u8 = 0;
+/// Channel reset terms of a friend are never accepted automatically (None disables automatic resets):
+const AUTO_RESET_TOLERANCE: Option<u128> = None;
+/// Amount of ticks between two snapshots of the balances with every friend (0 disables snapshots):
+const BALANCE_SNAPSHOT_TICKS: usize = 0;
+/// Maximum amount of balance snapshots kept for every friend:
+const MAX_BALANCE_SNAPSHOTS: usize = 0;
 
 fn gen_identity<R>(rng: &R) -> impl Identity
 where
@@ -117,22 +156,59 @@ fn default_node_config() -> NodeConfig {
         backoff_ticks: BACKOFF_TICKS,
         /// The amount of ticks we wait until we decide an idle connection has timed out.
         keepalive_ticks: KEEPALIVE_TICKS,
+        /// Minimal amount of ticks between keepalives sent to a friend.
+        keepalive_min_ticks: KEEPALIVE_MIN_TICKS,
         /// Amount of ticks to wait until the next rekeying (Channel encryption)
         ticks_to_rekey: TICKS_TO_REKEY,
+        /// Amount of ticks a closed channel may be resumed without a full handshake
+        ticks_to_resume: TICKS_TO_RESUME,
         /// Maximum amount of encryption set ups (diffie hellman) that we allow to occur at the same
         /// time.
         max_concurrent_encrypt: MAX_CONCURRENT_ENCRYPT,
         /// The amount of ticks we are willing to wait until a connection is established (Through
         /// the relay)
         conn_timeout_ticks: CONN_TIMEOUT_TICKS,
+        /// Tunnel accepted relay connections over a single connection to the relay.
+        relay_multiplex: RELAY_MULTIPLEX,
+        relay_probe_ticks: RELAY_PROBE_TICKS,
+        /// Connect to friends' relays, and not only wait for friends to connect.
+        listen_only: false,
+        /// Do not act as a relay for our friends.
+        opt_embedded_relay_address: None,
         /// Maximum amount of operations in one move token message
         max_operations_in_batch: MAX_OPERATIONS_IN_BATCH,
         /// The size we allocate for the user send funds requests queue.
         max_pending_user_requests: MAX_PENDING_USER_REQUESTS,
+        /// Maximum length of the route of a transaction originated by this node.
+        max_route_len: MAX_ROUTE_LEN,
+        /// Maximum amount of times a failed transaction is retried through an alternative route.
+        max_transaction_retries: MAX_TRANSACTION_RETRIES,
         /// Maximum amount of concurrent index client requests:
         max_open_index_client_requests: MAX_OPEN_INDEX_CLIENT_REQUESTS,
+        /// The amount of ticks routes received from an index server are cached:
+        route_cache_ticks: ROUTE_CACHE_TICKS,
+        /// Rotate the routes returned for repeated requests to the same destination:
+        route_rotation: ROUTE_ROTATION,
+        /// Smooth the capacities advertised to the index servers during short capacity drops:
+        capacity_smoothing: CAPACITY_SMOOTHING,
+        /// Amount of index servers we keep a session with at the same time:
+        max_index_sessions: MAX_INDEX_SESSIONS,
+        /// Do not sync the index servers with an index server directory.
+        opt_index_directory_key: None,
         /// Maximum amount of relays a node may use.
         max_node_relays: MAX_NODE_RELAYS,
+        /// Maximum amount of ticks a graceful shutdown waits for pending operations to settle.
+        shutdown_ticks: SHUTDOWN_TICKS,
+        /// Amount of ticks until a request originated by this node expires.
+        request_expiry_ticks: REQUEST_EXPIRY_TICKS,
+        /// Minimum recent uptime of a friend for new requests to be queued through it.
+        min_friend_uptime_percent: MIN_FRIEND_UPTIME_PERCENT,
+        /// Maximum difference between the reset terms of a friend and ours, for automatic resets.
+        opt_auto_reset_tolerance: AUTO_RESET_TOLERANCE,
+        /// Amount of ticks between two balance snapshots.
+        balance_snapshot_ticks: BALANCE_SNAPSHOT_TICKS,
+        /// Maximum amount of balance snapshots kept for every friend.
+        max_balance_snapshots: MAX_BALANCE_SNAPSHOTS,
         /*
         /// Maximum amount of incoming app connections we set up at the same time
         max_concurrent_incoming_apps: MAX_CONCURRENT_INCOMING_APPS,
@@ -387,7 +463,7 @@ where
 }
 
 #[derive(Debug, Clone)]
-struct DummyTrustedApps {
+pub struct DummyTrustedApps {
     trusted_apps: HashMap<PublicKey, AppPermissions>,
 }
 
@@ -400,21 +476,20 @@ impl TrustedApps for DummyTrustedApps {
     }
 }
 
-pub async fn create_node<S>(
+/// Load everything a node needs in order to run (Identity, database and configuration).
+/// `incoming_app_raw_conns` are the raw connections from the apps of the node.
+pub fn create_node_instance<S>(
     index: u8,
     sim_db: SimDb,
-    timer_client: TimerClient,
-    mut sim_network_client: SimNetworkClient,
+    incoming_app_raw_conns: mpsc::Receiver<ConnPairVec>,
     trusted_apps: HashMap<u8, AppPermissions>,
     spawner: S,
-) -> RemoteHandle<()>
+) -> NodeInstance<mpsc::Receiver<ConnPairVec>, DummyTrustedApps>
 where
     S: Spawn + Send + Sync + Clone + 'static,
 {
     let identity = get_node_identity(index);
     let identity_client = create_identity_client(identity, spawner.clone());
-    let listen_address = listen_node_address(index);
-    let incoming_app_raw_conns = sim_network_client.listen(listen_address).await.unwrap();
 
     // Translate application index to application public key:
     let trusted_apps_map = trusted_apps
@@ -426,8 +501,6 @@ where
     };
     // let get_trusted_apps = move || Some(trusted_apps.clone());
 
-    let rng = DummyRandom::new(&[0xff, 0x13, 0x37, index]);
-
     let atomic_db = sim_db.load_node_db(index).unwrap();
 
     // Get initial node_state:
@@ -444,16 +517,61 @@ where
     // Obtain a client to the database service:
     let database_client = DatabaseClient::new(db_request_sender);
 
+    NodeInstance {
+        identity_client,
+        node_config: default_node_config(),
+        trusted_apps: dummy_trusted_apps,
+        node_state,
+        database_client,
+        incoming_app_raw_conns,
+        opt_incoming_relay_raw_conns: None,
+    }
+}
+
+pub async fn create_node<S>(
+    index: u8,
+    sim_db: SimDb,
+    timer_client: TimerClient,
+    mut sim_network_client: SimNetworkClient,
+    trusted_apps: HashMap<u8, AppPermissions>,
+    spawner: S,
+) -> RemoteHandle<()>
+where
+    S: Spawn + Send + Sync + Clone + 'static,
+{
+    let listen_address = listen_node_address(index);
+    let incoming_app_raw_conns = sim_network_client.listen(listen_address).await.unwrap();
+
+    let NodeInstance {
+        identity_client,
+        node_config,
+        trusted_apps,
+        node_state,
+        database_client,
+        incoming_app_raw_conns,
+        opt_incoming_relay_raw_conns,
+    } = create_node_instance(
+        index,
+        sim_db,
+        incoming_app_raw_conns,
+        trusted_apps,
+        spawner.clone(),
+    );
+
+    let rng = DummyRandom::new(&[0xff, 0x13, 0x37, index]);
+
     // Note: we use the same spawner for testing purposes.
     // Simulating the passage of time becomes more difficult if our code uses a few different executors.
     let net_node_fut = net_node(
         incoming_app_raw_conns,
+        opt_incoming_relay_raw_conns,
+        stream::pending::<NodeRequest>(),
         sim_network_client,
         timer_client,
         identity_client,
         rng,
-        default_node_config(),
-        dummy_trusted_apps,
+        node_config,
+        trusted_apps,
         node_state,
         database_client,
         spawner.clone(),
@@ -504,13 +622,25 @@ pub async fn create_index_server<S>(
     let net_index_server_fut = net_index_server(
         incoming_client_raw_conns,
         incoming_server_raw_conns,
+        // The admin and query interfaces are not used in tests:
+        stream::empty(),
+        stream::empty(),
         sim_network_client,
         identity_client,
         timer_client,
         rng,
         trusted_servers,
+        HashSet::new(),
+        AdminState::new(),
+        DatabaseClient::new(mpsc::channel(0).0),
+        None,
         MAX_CONCURRENT_ENCRYPT,
         BACKOFF_TICKS,
+        POW_DIFFICULTY,
+        CapacityDecay {
+            half_life: DECAY_HALF_LIFE,
+            horizon: DECAY_HORIZON,
+        },
         spawner.clone(),
         spawner.clone(),
     )
@@ -537,10 +667,14 @@ pub async fn create_relay<S>(
     let rng = DummyRandom::new(&[0xff, 0x13, 0x39, index]);
     let net_relay_server_fut = net_relay_server(
         incoming_raw_conns,
+        sim_network_client,
         identity_client,
         timer_client,
         rng,
+        HashMap::new(),
+        RelayTimeouts::default(),
         MAX_CONCURRENT_ENCRYPT,
+        RelayMetrics::new(),
         spawner.clone(),
     )
     .map_err(|e| error!("net_relay_server() error: {:?}", e))
@@ -549,14 +683,15 @@ pub async fn create_relay<S>(
     spawner.spawn(net_relay_server_fut).unwrap();
 }
 
+/// Advance the virtual time by `ticks` ticks.
+/// All the tasks of the test executor handle a tick before the next tick is sent.
 pub async fn advance_time<'a>(
     ticks: usize,
-    tick_sender: &'a mut mpsc::Sender<()>,
+    virtual_clock: &'a mut VirtualClock,
     test_executor: &'a TestExecutor,
 ) {
-    test_executor.wait().await;
-    for _ in 0..ticks {
-        tick_sender.send(()).await.unwrap();
-        test_executor.wait().await;
-    }
+    virtual_clock
+        .advance_settled(ticks, || test_executor.wait())
+        .await
+        .unwrap();
 }
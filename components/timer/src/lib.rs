@@ -16,7 +16,12 @@ extern crate common;
 
 mod timer;
 pub mod utils;
+pub mod virtual_clock;
+
+#[cfg(feature = "testing")]
+pub mod testing;
 
 pub use self::timer::{
-    create_timer, create_timer_incoming, dummy_timer_multi_sender, TimerClient, TimerTick,
+    create_timer, create_timer_incoming, dummy_timer_multi_sender, TimerClient, TimerError,
+    TimerTick,
 };
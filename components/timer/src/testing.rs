@@ -0,0 +1,158 @@
+//! A mock timer service, for deterministic tests of crates that use a `TimerClient`.
+//!
+//! Time only advances when the test asks for it, using `MockTimer::tick()` or
+//! `MockTimer::advance()`.
+
+use common::conn::BoxStream;
+use common::select_streams::select_streams;
+use common::testing::MockScript;
+
+use futures::channel::{mpsc, oneshot};
+use futures::task::{Spawn, SpawnExt};
+use futures::{SinkExt, StreamExt};
+
+use crate::timer::{TimerClient, TimerRequest, TimerTick};
+
+#[derive(Debug)]
+pub enum MockTimerError {
+    SpawnError,
+    SendFailure,
+    ResponseCanceled,
+}
+
+#[derive(Debug)]
+enum MockTimerControl {
+    /// Send a tick to all timer streams. Returns the amount of streams that received the tick.
+    Tick(oneshot::Sender<usize>),
+}
+
+#[derive(Debug)]
+enum MockTimerEvent {
+    Request(TimerRequest),
+    Control(MockTimerControl),
+}
+
+/// Control over the time of a mock timer service.
+#[derive(Debug, Clone)]
+pub struct MockTimer {
+    control_sender: mpsc::Sender<MockTimerControl>,
+}
+
+impl MockTimer {
+    /// Send a single tick to all open timer streams.
+    /// Returns after all the streams received the tick, with the amount of streams that received
+    /// it.
+    pub async fn tick(&mut self) -> Result<usize, MockTimerError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        self.control_sender
+            .send(MockTimerControl::Tick(response_sender))
+            .await
+            .map_err(|_| MockTimerError::SendFailure)?;
+        response_receiver
+            .await
+            .map_err(|_| MockTimerError::ResponseCanceled)
+    }
+
+    /// Send `ticks` ticks to all open timer streams.
+    pub async fn advance(&mut self, ticks: usize) -> Result<(), MockTimerError> {
+        for _ in 0..ticks {
+            self.tick().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Create a mock timer service that consults `script` before every request for a timer stream.
+///
+/// A failed request is reported to the client as `TimerClientError::ResponseCanceled`.
+/// Requests and ticks are handled one at a time, so a delayed request also delays the ticks and
+/// requests after it.
+pub fn create_mock_timer(
+    script: MockScript,
+    spawner: impl Spawn,
+) -> Result<(MockTimer, TimerClient), MockTimerError> {
+    let (request_sender, incoming_requests) = mpsc::channel::<TimerRequest>(0);
+    let (control_sender, incoming_control) = mpsc::channel::<MockTimerControl>(0);
+
+    let incoming_requests = incoming_requests.map(MockTimerEvent::Request);
+    let incoming_control = incoming_control.map(MockTimerEvent::Control);
+    let mut events = select_streams![incoming_requests, incoming_control];
+
+    let timer_fut = async move {
+        let mut tick_senders: Vec<mpsc::Sender<TimerTick>> = Vec::new();
+        while let Some(event) = events.next().await {
+            match event {
+                MockTimerEvent::Request(timer_request) => {
+                    if !script.next_action().perform().await {
+                        // Dropping the response sender fails the request.
+                        continue;
+                    }
+                    let (tick_sender, tick_receiver) = mpsc::channel(0);
+                    tick_senders.push(tick_sender);
+                    let _ = timer_request.response_sender.send(tick_receiver);
+                }
+                MockTimerEvent::Control(MockTimerControl::Tick(response_sender)) => {
+                    let mut temp_tick_senders = Vec::new();
+                    temp_tick_senders.append(&mut tick_senders);
+                    for mut tick_sender in temp_tick_senders {
+                        if let Ok(()) = tick_sender.send(TimerTick).await {
+                            tick_senders.push(tick_sender);
+                        }
+                    }
+                    let _ = response_sender.send(tick_senders.len());
+                }
+            }
+        }
+    };
+
+    spawner
+        .spawn(timer_fut)
+        .map_err(|_| MockTimerError::SpawnError)?;
+
+    Ok((
+        MockTimer { control_sender },
+        TimerClient::new(request_sender),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::LocalPool;
+    use futures::future::join;
+
+    async fn task_mock_timer(spawner: impl Spawn) {
+        let script = MockScript::new();
+        let (mut mock_timer, mut timer_client) =
+            create_mock_timer(script.clone(), spawner).unwrap();
+
+        // No streams yet:
+        assert_eq!(mock_timer.tick().await.unwrap(), 0);
+
+        let mut timer_stream = timer_client.request_timer_stream().await.unwrap();
+        assert_eq!(mock_timer.tick().await.unwrap(), 1);
+        assert_eq!(timer_stream.next().await, Some(TimerTick));
+
+        // A scripted failure:
+        script.fail(1);
+        assert!(timer_client.request_timer_stream().await.is_err());
+
+        // Closed streams do not receive ticks:
+        drop(timer_stream);
+        assert_eq!(mock_timer.tick().await.unwrap(), 0);
+
+        let deadline = timer_client.request_deadline(3).await.unwrap();
+        let advance_fut = async {
+            mock_timer.advance(3).await.unwrap();
+        };
+        join(deadline, advance_fut).await;
+    }
+
+    #[test]
+    fn test_mock_timer() {
+        let mut local_pool = LocalPool::new();
+        let spawner = local_pool.spawner();
+        local_pool.run_until(task_mock_timer(spawner));
+    }
+}
@@ -22,11 +22,15 @@
 
 // #![deny(warnings)]
 #![allow(intra_doc_link_resolution_failure)]
-#![allow(clippy::too_many_arguments, clippy::implicit_hasher, clippy::module_inception)]
+#![allow(
+    clippy::too_many_arguments,
+    clippy::implicit_hasher,
+    clippy::module_inception
+)]
 // TODO: disallow clippy::too_many_arguments
 
 // use common::futures_compat::create_interval;
-use common::conn::BoxStream;
+use common::conn::{BoxFuture, BoxStream};
 use common::select_streams::select_streams;
 
 use async_std::stream::interval;
@@ -54,8 +58,8 @@ pub enum TimerClientError {
     ResponseCanceled,
 }
 
-struct TimerRequest {
-    response_sender: oneshot::Sender<mpsc::Receiver<TimerTick>>,
+pub(crate) struct TimerRequest {
+    pub(crate) response_sender: oneshot::Sender<mpsc::Receiver<TimerTick>>,
 }
 
 impl std::fmt::Debug for TimerRequest {
@@ -70,7 +74,7 @@ pub struct TimerClient {
 }
 
 impl TimerClient {
-    fn new(sender: mpsc::Sender<TimerRequest>) -> TimerClient {
+    pub(crate) fn new(sender: mpsc::Sender<TimerRequest>) -> TimerClient {
         TimerClient { sender }
     }
 
@@ -89,6 +93,38 @@ impl TimerClient {
             Err(_) => Err(TimerClientError::ResponseCanceled),
         }
     }
+
+    /// Request a future that resolves once, after `ticks` time ticks have passed.
+    /// The future also resolves if the timer service is closed.
+    pub async fn request_deadline(
+        &mut self,
+        ticks: usize,
+    ) -> Result<BoxFuture<'static, ()>, TimerClientError> {
+        let timer_stream = self.request_timer_stream().await?;
+        Ok(Box::pin(
+            timer_stream.take(ticks).for_each(|_| future::ready(())),
+        ))
+    }
+
+    /// Request a stream that yields a single `TimerTick` every `ticks` time ticks.
+    /// Consumers that only act periodically are woken up once every period, instead of counting
+    /// every tick themselves.
+    pub async fn request_interval(
+        &mut self,
+        ticks: usize,
+    ) -> Result<BoxStream<'static, TimerTick>, TimerClientError> {
+        let ticks = std::cmp::max(ticks, 1);
+        let timer_stream = self.request_timer_stream().await?;
+        Ok(Box::pin(timer_stream.enumerate().filter_map(
+            move |(index, timer_tick)| {
+                future::ready(if (index + 1) % ticks == 0 {
+                    Some(timer_tick)
+                } else {
+                    None
+                })
+            },
+        )))
+    }
 }
 
 #[derive(Debug)]
@@ -384,4 +420,53 @@ mod tests {
         let thread_pool = ThreadPool::new().unwrap();
         LocalPool::new().run_until(task_dummy_timer_multi_sender(thread_pool.clone()));
     }
+
+    async fn task_timer_request_deadline(spawner: impl Spawn + Clone + Send + 'static) {
+        let (mut tick_sender_receiver, mut timer_client) =
+            dummy_timer_multi_sender(spawner.clone());
+
+        let deadline = timer_client.request_deadline(4).await.unwrap();
+        let (done_sender, mut done_receiver) = oneshot::channel::<()>();
+        spawner
+            .spawn(deadline.map(move |_| done_sender.send(()).unwrap()))
+            .unwrap();
+
+        let mut tick_sender = tick_sender_receiver.next().await.unwrap();
+        for _ in 0..3usize {
+            tick_sender.send(TimerTick).await.unwrap();
+        }
+        assert_eq!(done_receiver.try_recv().unwrap(), None);
+
+        tick_sender.send(TimerTick).await.unwrap();
+        done_receiver.await.unwrap();
+    }
+
+    #[test]
+    fn test_timer_request_deadline() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new().run_until(task_timer_request_deadline(thread_pool.clone()));
+    }
+
+    async fn task_timer_request_interval(spawner: impl Spawn) {
+        let (mut tick_sender_receiver, mut timer_client) = dummy_timer_multi_sender(spawner);
+
+        let interval_fut = async {
+            let interval = timer_client.request_interval(4).await.unwrap();
+            // 18 ticks contain 4 full intervals:
+            assert_eq!(interval.collect::<Vec<_>>().await.len(), 4);
+        };
+        let tick_sender_fut = async {
+            let mut tick_sender = tick_sender_receiver.next().await.unwrap();
+            for _ in 0..18usize {
+                tick_sender.send(TimerTick).await.unwrap();
+            }
+        };
+        let _ = join(interval_fut, tick_sender_fut).await;
+    }
+
+    #[test]
+    fn test_timer_request_interval() {
+        let thread_pool = ThreadPool::new().unwrap();
+        LocalPool::new().run_until(task_timer_request_interval(thread_pool.clone()));
+    }
 }
@@ -14,12 +14,11 @@ pub async fn sleep_ticks(
     ticks: usize,
     mut timer_client: TimerClient,
 ) -> Result<(), SleepTicksError> {
-    let timer_stream = timer_client
-        .request_timer_stream()
+    let deadline = timer_client
+        .request_deadline(ticks)
         .await
         .map_err(|_| SleepTicksError::RequestTimerStreamError)?;
-    let fut = timer_stream.take(ticks).for_each(|_| future::ready(()));
-    fut.await;
+    deadline.await;
     Ok(())
 }
 
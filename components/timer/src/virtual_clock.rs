@@ -0,0 +1,230 @@
+//! A virtual clock, for deterministic integration tests.
+//!
+//! Time only advances when the test asks for it. Between ticks, the test may let all the other
+//! tasks settle (For example, using `TestExecutor::wait()`), so that every component observes
+//! every tick in the same order on every run, without any real delays.
+
+use common::select_streams::select_streams;
+
+use futures::channel::{mpsc, oneshot};
+use futures::task::{Spawn, SpawnExt};
+use futures::{Future, SinkExt, StreamExt};
+
+use crate::timer::{TimerClient, TimerRequest, TimerTick};
+
+#[derive(Debug)]
+pub enum VirtualClockError {
+    SpawnError,
+    SendFailure,
+    ResponseCanceled,
+}
+
+/// The state of a virtual clock, as seen by the test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClockStatus {
+    /// Amount of ticks sent since the clock was created
+    pub elapsed_ticks: u64,
+    /// Amount of open timer streams, waiting for the next tick
+    pub subscribers: usize,
+}
+
+#[derive(Debug)]
+enum ClockControl {
+    /// Send a tick to all timer streams. Returns the amount of streams that received the tick.
+    Tick(oneshot::Sender<usize>),
+    Status(oneshot::Sender<ClockStatus>),
+}
+
+#[derive(Debug)]
+enum ClockEvent {
+    Request(TimerRequest),
+    Control(ClockControl),
+}
+
+/// Control over the time of a virtual clock.
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    control_sender: mpsc::Sender<ClockControl>,
+}
+
+impl VirtualClock {
+    /// Send a single tick to all open timer streams.
+    /// Returns the amount of streams that received the tick.
+    pub async fn tick(&mut self) -> Result<usize, VirtualClockError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        self.control_sender
+            .send(ClockControl::Tick(response_sender))
+            .await
+            .map_err(|_| VirtualClockError::SendFailure)?;
+        response_receiver
+            .await
+            .map_err(|_| VirtualClockError::ResponseCanceled)
+    }
+
+    /// Send `ticks` ticks to all open timer streams, one after the other.
+    pub async fn advance(&mut self, ticks: usize) -> Result<(), VirtualClockError> {
+        for _ in 0..ticks {
+            self.tick().await?;
+        }
+        Ok(())
+    }
+
+    /// Send `ticks` ticks to all open timer streams. `settle` is awaited before the first tick and
+    /// after every tick, allowing all components to handle a tick before the next one is sent.
+    pub async fn advance_settled<F, W>(
+        &mut self,
+        ticks: usize,
+        mut settle: F,
+    ) -> Result<(), VirtualClockError>
+    where
+        F: FnMut() -> W,
+        W: Future<Output = ()>,
+    {
+        settle().await;
+        for _ in 0..ticks {
+            self.tick().await?;
+            settle().await;
+        }
+        Ok(())
+    }
+
+    /// Get the amount of ticks sent so far, and the amount of open timer streams.
+    pub async fn status(&mut self) -> Result<ClockStatus, VirtualClockError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        self.control_sender
+            .send(ClockControl::Status(response_sender))
+            .await
+            .map_err(|_| VirtualClockError::SendFailure)?;
+        response_receiver
+            .await
+            .map_err(|_| VirtualClockError::ResponseCanceled)
+    }
+}
+
+/// Create a timer service driven by a virtual clock.
+/// The returned `TimerClient` may be handed to any component that expects a real timer.
+pub fn create_virtual_clock(
+    spawner: impl Spawn,
+) -> Result<(VirtualClock, TimerClient), VirtualClockError> {
+    let (request_sender, incoming_requests) = mpsc::channel::<TimerRequest>(0);
+    let (control_sender, incoming_control) = mpsc::channel::<ClockControl>(0);
+
+    let incoming_requests = incoming_requests.map(ClockEvent::Request);
+    let incoming_control = incoming_control.map(ClockEvent::Control);
+    let mut events = select_streams![incoming_requests, incoming_control];
+
+    let clock_fut = async move {
+        let mut elapsed_ticks: u64 = 0;
+        let mut tick_senders: Vec<mpsc::Sender<TimerTick>> = Vec::new();
+        while let Some(event) = events.next().await {
+            match event {
+                ClockEvent::Request(timer_request) => {
+                    let (tick_sender, tick_receiver) = mpsc::channel(0);
+                    tick_senders.push(tick_sender);
+                    let _ = timer_request.response_sender.send(tick_receiver);
+                }
+                ClockEvent::Control(ClockControl::Tick(response_sender)) => {
+                    elapsed_ticks = elapsed_ticks.saturating_add(1);
+                    let mut temp_tick_senders = Vec::new();
+                    temp_tick_senders.append(&mut tick_senders);
+                    for mut tick_sender in temp_tick_senders {
+                        if let Ok(()) = tick_sender.send(TimerTick).await {
+                            tick_senders.push(tick_sender);
+                        }
+                    }
+                    let _ = response_sender.send(tick_senders.len());
+                }
+                ClockEvent::Control(ClockControl::Status(response_sender)) => {
+                    // Forget about streams that were dropped since the last tick:
+                    tick_senders.retain(|tick_sender| !tick_sender.is_closed());
+                    let _ = response_sender.send(ClockStatus {
+                        elapsed_ticks,
+                        subscribers: tick_senders.len(),
+                    });
+                }
+            }
+        }
+    };
+
+    spawner
+        .spawn(clock_fut)
+        .map_err(|_| VirtualClockError::SpawnError)?;
+
+    Ok((
+        VirtualClock { control_sender },
+        TimerClient::new(request_sender),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use common::test_executor::TestExecutor;
+
+    async fn task_virtual_clock(test_executor: TestExecutor) {
+        let (mut virtual_clock, mut timer_client) =
+            create_virtual_clock(test_executor.clone()).unwrap();
+
+        assert_eq!(
+            virtual_clock.status().await.unwrap(),
+            ClockStatus {
+                elapsed_ticks: 0,
+                subscribers: 0,
+            }
+        );
+
+        // A component that counts the ticks it observes:
+        let counter = Arc::new(Mutex::new(0usize));
+        let c_counter = counter.clone();
+        let mut timer_stream = timer_client.request_timer_stream().await.unwrap();
+        test_executor
+            .spawn(async move {
+                while let Some(TimerTick) = timer_stream.next().await {
+                    *c_counter.lock().unwrap() += 1;
+                }
+            })
+            .unwrap();
+
+        let wait_executor = test_executor.clone();
+        virtual_clock
+            .advance_settled(5, || wait_executor.wait())
+            .await
+            .unwrap();
+        // Every tick was handled before the next tick was sent:
+        assert_eq!(*counter.lock().unwrap(), 5);
+        assert_eq!(
+            virtual_clock.status().await.unwrap(),
+            ClockStatus {
+                elapsed_ticks: 5,
+                subscribers: 1,
+            }
+        );
+
+        // Dropped streams are no longer subscribers:
+        let timer_stream = timer_client.request_timer_stream().await.unwrap();
+        assert_eq!(virtual_clock.status().await.unwrap().subscribers, 2);
+        drop(timer_stream);
+        assert_eq!(virtual_clock.status().await.unwrap().subscribers, 1);
+
+        let deadline = timer_client.request_deadline(3).await.unwrap();
+        test_executor.spawn(deadline).unwrap();
+        assert_eq!(virtual_clock.tick().await.unwrap(), 2);
+        virtual_clock
+            .advance_settled(2, || wait_executor.wait())
+            .await
+            .unwrap();
+        // The deadline has resolved, and its stream was dropped:
+        assert_eq!(virtual_clock.status().await.unwrap().subscribers, 1);
+        assert_eq!(*counter.lock().unwrap(), 8);
+    }
+
+    #[test]
+    fn test_virtual_clock() {
+        let test_executor = TestExecutor::new();
+        let res = test_executor.run(task_virtual_clock(test_executor.clone()));
+        assert!(res.is_output());
+    }
+}